@@ -0,0 +1,95 @@
+// The readers' hot path on a mixed batch of sends, socket states and drops,
+// against the buffer-per-sample, fold-per-event path it replaced.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ebpf_congestion_signals::bench::HotPath;
+use ebpf_congestion_signals::{
+    CongestionEvent, EventData, QdiscData, SendMsgData, SocketData, EVENT_QDISC_DROP,
+    EVENT_SOCKET_STATE, EVENT_UDP_SEND, IPPROTO_UDP, PACING_UNLIMITED, SAMPLER_PRIMARY,
+    TRAFFIC_CLASS_UNKNOWN,
+};
+
+const CPUS: u32 = 8;
+
+fn workload(count: usize) -> Vec<CongestionEvent> {
+    (0..count)
+        .map(|i| {
+            let (event_type, data) = match i % 8 {
+                0 => (
+                    EVENT_SOCKET_STATE,
+                    EventData {
+                        socket: SocketData {
+                            wmem_queued: 64_000,
+                            sndbuf: 212_992,
+                            socket_id: i as u64 % 64,
+                            protocol: IPPROTO_UDP,
+                            netns: 0,
+                            dscp: TRAFFIC_CLASS_UNKNOWN,
+                            priority: TRAFFIC_CLASS_UNKNOWN,
+                            pacing_rate: PACING_UNLIMITED,
+                            max_pacing_rate: PACING_UNLIMITED,
+                            samplers: SAMPLER_PRIMARY,
+                        },
+                    },
+                ),
+                1 => (
+                    EVENT_QDISC_DROP,
+                    EventData {
+                        qdisc: QdiscData {
+                            dropped: 1,
+                            backlog_bytes: 0,
+                            backlog_packets: 0,
+                            reason: 0,
+                        },
+                    },
+                ),
+                _ => (
+                    EVENT_UDP_SEND,
+                    EventData {
+                        sendmsg: SendMsgData {
+                            bytes: 1_200 + (i as u64 % 7) * 1_000,
+                            is_tcp: 0,
+                            loopback: 0,
+                            socket_id: i as u64 % 64,
+                            netns: 0,
+                            dscp: TRAFFIC_CLASS_UNKNOWN,
+                            priority: TRAFFIC_CLASS_UNKNOWN,
+                            samplers: SAMPLER_PRIMARY,
+                            flow_hash: 0,
+                        },
+                    },
+                ),
+            };
+            CongestionEvent {
+                timestamp_ns: 1_000_000 + i as u64 * 1_000,
+                event_type,
+                cpu_id: i as u32 % CPUS,
+                data,
+            }
+        })
+        .collect()
+}
+
+fn hot_path(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reader_batch");
+    for batch_size in [64, 512, 4096] {
+        let events = workload(batch_size);
+        group.throughput(Throughput::Elements(batch_size as u64));
+        let mut path = HotPath::new(CPUS, batch_size);
+        group.bench_with_input(
+            BenchmarkId::new("walk", batch_size),
+            &events,
+            |b, events| b.iter(|| path.walk(events)),
+        );
+        let mut path = HotPath::new(CPUS, batch_size);
+        group.bench_with_input(
+            BenchmarkId::new("per_event", batch_size),
+            &events,
+            |b, events| b.iter(|| path.per_event(events)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, hot_path);
+criterion_main!(benches);
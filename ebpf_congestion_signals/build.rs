@@ -0,0 +1,51 @@
+//Hash the kernel crate's shared types the same way its build.rs does, so the
+//collector knows which layout fingerprint its mirrored types correspond to.
+//Keep layout_hash() in sync with ebpf_congestion_signals_ebpf/build.rs.
+//With `grpc` it also generates the service from proto/signals.proto, with the
+//vendored protoc so hosts don't need one installed.
+
+use std::{env, fs, path::Path};
+
+fn layout_hash(src: &str) -> u64 {
+    // FNV-1a over the code with comments and whitespace removed
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for line in src.lines() {
+        let code = line.split("//").next().unwrap_or("");
+        for b in code.bytes().filter(|b| !b.is_ascii_whitespace()) {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+fn main() {
+    let types = "../ebpf_congestion_signals_ebpf/src/types.rs";
+    println!("cargo:rerun-if-changed={}", types);
+
+    let src = fs::read_to_string(types).expect("read kernel-side types.rs");
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("layout_hash.rs");
+    fs::write(
+        out,
+        format!("pub const LAYOUT_HASH: u64 = {:#x};\n", layout_hash(&src)),
+    )
+    .unwrap();
+
+    #[cfg(feature = "grpc")]
+    compile_proto();
+}
+
+#[cfg(feature = "grpc")]
+fn compile_proto() {
+    let proto = "proto/signals.proto";
+    println!("cargo:rerun-if-changed={}", proto);
+    let protoc = protoc_bin_vendored::protoc_bin_path().expect("vendored protoc");
+    // google/protobuf/field_mask.proto
+    let well_known = protoc_bin_vendored::include_path().expect("vendored protoc includes");
+    env::set_var("PROTOC", protoc);
+    let descriptor = Path::new(&env::var("OUT_DIR").unwrap()).join("signals_descriptor.bin");
+    tonic_build::configure()
+        .file_descriptor_set_path(descriptor)
+        .compile_protos(&[Path::new(proto)], &[Path::new("proto"), &well_known])
+        .expect("compile proto/signals.proto");
+}
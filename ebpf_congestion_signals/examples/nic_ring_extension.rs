@@ -0,0 +1,167 @@
+//! A custom event type on the collector's pipeline: a NIC driver's TX ring
+//! tracepoints, counted per queue and carried in
+//! `CongestionSignals::extensions["nic_ring"]`. The handler decodes two
+//! extension types, RING_FULL (queue, free descriptors) and RING_REFILL
+//! (queue, descriptors refilled), and snapshots one object per interval.
+//!
+//!     cargo run --example nic_ring_extension -- --offline
+//!     sudo target/debug/examples/nic_ring_extension /sys/fs/bpf/congestion_signals
+//!
+//! `--offline` runs the handler over scripted events through an
+//! `ExtensionRegistry`, no privileges needed. Otherwise the collector loads,
+//! pins its EVENTS perf array in the directory given (on a bpffs mount) and
+//! prints the snapshot every second. The driver-side program is yours: an
+//! aya-ebpf one declares
+//!
+//!     #[map]
+//!     static EVENTS: PerfEventArray<CongestionEvent> = PerfEventArray::pinned(0);
+//!
+//! fills a zeroed CongestionEvent with `event_type` 0x100 or 0x101, the
+//! timestamp, the CPU and the payload words below at the start of `data`, and
+//! outputs it with BPF_F_CURRENT_CPU. Load it with
+//! `EbpfLoader::new().map_pin_path(dir)` once this example has pinned the map.
+
+use ebpf_congestion_signals::{
+    CollectorConfig, CongestionCollector, CustomEventHandler, ExtensionEvent, ExtensionRegistry,
+    JsonValue, EXTENSION_EVENT_TYPES,
+};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::Duration;
+
+const RING_FULL: u32 = EXTENSION_EVENT_TYPES.start;
+const RING_REFILL: u32 = EXTENSION_EVENT_TYPES.start + 1;
+const INTERVAL: Duration = Duration::from_secs(1);
+const INTERVALS: usize = 30;
+
+enum RingEvent {
+    // Payload: u32 queue, u32 free descriptors
+    Full { queue: u32, free: u32 },
+    // Payload: u32 queue, u32 descriptors refilled
+    Refill { queue: u32, refilled: u32 },
+}
+
+#[derive(Default)]
+struct QueueCounts {
+    ring_full: u64,
+    refilled: u64,
+    min_free: Option<u32>,
+}
+
+struct NicRingHandler;
+
+impl CustomEventHandler for NicRingHandler {
+    type Event = RingEvent;
+    type Accumulator = BTreeMap<u32, QueueCounts>;
+
+    fn decode(&self, event: &ExtensionEvent) -> Option<RingEvent> {
+        let queue = event.u32_at(0)?;
+        let value = event.u32_at(4)?;
+        match event.event_type {
+            RING_FULL => Some(RingEvent::Full { queue, free: value }),
+            RING_REFILL => Some(RingEvent::Refill {
+                queue,
+                refilled: value,
+            }),
+            _ => None,
+        }
+    }
+
+    fn fold(&self, queues: &mut Self::Accumulator, event: RingEvent) {
+        match event {
+            RingEvent::Full { queue, free } => {
+                let counts = queues.entry(queue).or_default();
+                counts.ring_full += 1;
+                counts.min_free = Some(counts.min_free.map_or(free, |min| min.min(free)));
+            }
+            RingEvent::Refill { queue, refilled } => {
+                queues.entry(queue).or_default().refilled += refilled as u64;
+            }
+        }
+    }
+
+    fn snapshot(&self, queues: &Self::Accumulator) -> JsonValue {
+        let total: u64 = queues.values().map(|counts| counts.ring_full).sum();
+        let per_queue = queues
+            .iter()
+            .map(|(queue, counts)| {
+                let mut fields = vec![
+                    ("ring_full".to_string(), counts.ring_full.into()),
+                    ("refilled".to_string(), counts.refilled.into()),
+                ];
+                if let Some(min_free) = counts.min_free {
+                    fields.push(("min_free".to_string(), (min_free as u64).into()));
+                }
+                (queue.to_string(), JsonValue::Object(fields))
+            })
+            .collect();
+        JsonValue::Object(vec![
+            ("ring_full".to_string(), total.into()),
+            ("queues".to_string(), JsonValue::Object(per_queue)),
+        ])
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let arg = std::env::args().nth(1);
+    match arg.as_deref() {
+        Some("--offline") => offline(),
+        Some(dir) => tokio::runtime::Runtime::new()?.block_on(live(dir)),
+        None => Err("usage: nic_ring_extension --offline | <bpffs directory>".into()),
+    }
+}
+
+fn offline() -> Result<(), Box<dyn Error>> {
+    let mut registry = ExtensionRegistry::new();
+    registry.register("nic_ring", RING_FULL..RING_REFILL + 1, NicRingHandler)?;
+    let payload = |queue: u32, value: u32| {
+        let mut bytes = queue.to_ne_bytes().to_vec();
+        bytes.extend_from_slice(&value.to_ne_bytes());
+        bytes
+    };
+    for interval in 0..3u32 {
+        for i in 0..=interval {
+            let at = (interval * 1_000 + i) as u64 * 1_000_000;
+            registry.record(&ExtensionEvent::from_payload(
+                RING_FULL,
+                at,
+                0,
+                &payload(i % 2, 8 - i),
+            ));
+            registry.record(&ExtensionEvent::from_payload(
+                RING_REFILL,
+                at + 500,
+                0,
+                &payload(i % 2, 64),
+            ));
+        }
+        for (name, snapshot) in registry.take_interval() {
+            println!("{}: {}", name, snapshot.to_json());
+        }
+    }
+    Ok(())
+}
+
+async fn live(dir: &str) -> Result<(), Box<dyn Error>> {
+    let mut collector = CongestionCollector::load_with_config(CollectorConfig::default())?;
+    collector.register_extension("nic_ring", RING_FULL..RING_REFILL + 1, NicRingHandler)?;
+    let pinned = collector.pin_event_map(dir)?;
+    println!("EVENTS pinned at {}", pinned.display());
+    collector.start_collection().await?;
+    for _ in 0..INTERVALS {
+        tokio::time::sleep(INTERVAL).await;
+        let signals = collector.read_and_reset();
+        let stats = collector.reader_stats();
+        let snapshot = signals
+            .extensions
+            .get("nic_ring")
+            .map_or("null".to_string(), JsonValue::to_json);
+        println!(
+            "{} extension events ({} unknown): {}",
+            stats.extension_events, stats.unknown_events, snapshot
+        );
+    }
+    // A pin outlives the process, and the map with it
+    std::fs::remove_file(&pinned)?;
+    Ok(())
+}
@@ -0,0 +1,216 @@
+//! A loopback QUIC transfer with quiche, its packets leaving when
+//! `DeparturePacer` says rather than when quiche's own pacer would, and the
+//! rate set by a `Governor` once a second. Prints the governed target, the rate
+//! actually paced at after the confidence bound, and what the server received.
+//!
+//!     cargo run --release --example quiche_loopback --features quiche-example
+//!
+//! No collector here, so it needs no privileges: the governor is fed the bytes
+//! the transfer itself sent, plus drops scripted into seconds 4 and 5 so it has
+//! something to cut on. On a real host feed it `collector.snapshots()` instead.
+//!
+//! cert.crt and cert.key are quiche's example certificate.
+
+use ebpf_congestion_signals::{
+    CongestionSignals, DepartureConfig, DeparturePacer, Governor, GovernorPolicy, InterfaceEgress,
+    Limitation,
+};
+use quiche::{ConnectionId, RecvInfo};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+const CERT: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/examples/quiche_loopback/cert.crt"
+);
+const KEY: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/examples/quiche_loopback/cert.key"
+);
+const MAX_DATAGRAM: usize = 1350;
+const SECONDS: u64 = 10;
+// 32 Mbps to start from, bytes/sec
+const INITIAL_RATE: u64 = 4_000_000;
+// Scripted drops/s, enough for a cut at the default policy
+const DROPS_PER_SEC: u64 = 400;
+// Packets taken from quiche per departure batch
+const BATCH: usize = 16;
+
+fn config(server: bool) -> quiche::Result<quiche::Config> {
+    let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    config.set_application_protos(&[b"governed"])?;
+    config.set_max_idle_timeout(5_000);
+    config.set_max_recv_udp_payload_size(MAX_DATAGRAM);
+    config.set_max_send_udp_payload_size(MAX_DATAGRAM);
+    config.set_initial_max_data(1 << 30);
+    config.set_initial_max_stream_data_bidi_local(1 << 30);
+    config.set_initial_max_stream_data_bidi_remote(1 << 30);
+    config.set_initial_max_streams_bidi(1);
+    config.enable_pacing(true);
+    if server {
+        config.load_cert_chain_from_pem_file(CERT)?;
+        config.load_priv_key_from_pem_file(KEY)?;
+    } else {
+        config.verify_peer(false);
+    }
+    Ok(config)
+}
+
+fn mbps(rate: u64) -> f64 {
+    rate as f64 * 8.0 / 1e6
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let server_socket = UdpSocket::bind("127.0.0.1:0")?;
+    let client_socket = UdpSocket::bind("127.0.0.1:0")?;
+    server_socket.set_nonblocking(true)?;
+    client_socket.set_nonblocking(true)?;
+    let server_addr = server_socket.local_addr()?;
+    let client_addr = client_socket.local_addr()?;
+
+    let mut server_config = config(true)?;
+    let mut client_config = config(false)?;
+    let mut client = quiche::connect(
+        None,
+        &ConnectionId::from_ref(&[1; 16]),
+        client_addr,
+        server_addr,
+        &mut client_config,
+    )?;
+    let mut server: Option<quiche::Connection> = None;
+
+    let mut governor = Governor::new(GovernorPolicy::default(), INITIAL_RATE);
+    let mut pacer = DeparturePacer::new(DepartureConfig::default());
+    // Client packets in departure order
+    let mut queued: VecDeque<(Instant, Vec<u8>)> = VecDeque::new();
+    let payload = vec![0u8; 64 * 1024];
+    let mut buf = vec![0u8; 65_535];
+    let mut out = [0u8; MAX_DATAGRAM];
+
+    let start = Instant::now();
+    // Nothing sent yet: an app-limited interval holds the initial rate
+    let idle = CongestionSignals::builder()
+        .limitation(Limitation::AppLimited)
+        .build();
+    pacer.update(&governor.update(&idle), None, start);
+    let mut second = 0;
+    let mut second_start = start;
+    let (mut sent, mut sent_packets, mut received) = (0u64, 0u64, 0u64);
+    println!("second  target Mbps  paced Mbps  received Mbps");
+
+    while second < SECONDS {
+        // Server: accept, read the stream, answer unpaced
+        while let Ok((len, from)) = server_socket.recv_from(&mut buf) {
+            if server.is_none() {
+                let scid = ConnectionId::from_ref(&[2; 16]);
+                server = Some(quiche::accept(
+                    &scid,
+                    None,
+                    server_addr,
+                    from,
+                    &mut server_config,
+                )?);
+            }
+            let conn = server.as_mut().unwrap();
+            let _ = conn.recv(
+                &mut buf[..len],
+                RecvInfo {
+                    from,
+                    to: server_addr,
+                },
+            );
+        }
+        if let Some(conn) = server.as_mut() {
+            for stream in conn.readable() {
+                while let Ok((n, _)) = conn.stream_recv(stream, &mut buf) {
+                    received += n as u64;
+                }
+            }
+            if conn.timeout() == Some(Duration::ZERO) {
+                conn.on_timeout();
+            }
+            while let Ok((len, info)) = conn.send(&mut out) {
+                server_socket.send_to(&out[..len], info.to)?;
+            }
+        }
+
+        // Client: keep the stream full and stamp departures batch by batch, so
+        // each batch is scheduled at the rate current when it was taken
+        while let Ok((len, from)) = client_socket.recv_from(&mut buf) {
+            let _ = client.recv(
+                &mut buf[..len],
+                RecvInfo {
+                    from,
+                    to: client_addr,
+                },
+            );
+        }
+        if client.timeout() == Some(Duration::ZERO) {
+            client.on_timeout();
+        }
+        if client.is_established() {
+            let _ = client.stream_send(0, &payload, false);
+        }
+        if queued.is_empty() {
+            while queued.len() < BATCH {
+                let Ok((len, info)) = client.send(&mut out) else {
+                    break;
+                };
+                queued.push_back((pacer.departure(len, info.at), out[..len].to_vec()));
+            }
+        }
+        let now = Instant::now();
+        while queued.front().is_some_and(|(at, _)| *at <= now) {
+            let (_, packet) = queued.pop_front().unwrap();
+            client_socket.send_to(&packet, server_addr)?;
+            sent += packet.len() as u64;
+            sent_packets += 1;
+        }
+
+        let elapsed = now - second_start;
+        if elapsed >= Duration::from_secs(1) {
+            let per_sec = |bytes: u64| (bytes as f64 / elapsed.as_secs_f64()) as u64;
+            println!(
+                "{:>6}  {:>11.1}  {:>10}  {:>13.1}",
+                second + 1,
+                mbps(governor.rate()),
+                pacer
+                    .rate()
+                    .map_or("quiche".to_string(), |rate| format!("{:.1}", mbps(rate))),
+                mbps(per_sec(received)),
+            );
+            second += 1;
+            let signals = CongestionSignals::builder()
+                .interval_ns(elapsed.as_nanos() as u64)
+                .egress(vec![InterfaceEgress {
+                    interface: "lo".to_string(),
+                    egress_bytes_exact: sent,
+                    egress_packets_exact: sent_packets,
+                }])
+                .drops(if (4..6).contains(&second) {
+                    DROPS_PER_SEC
+                } else {
+                    0
+                })
+                .build();
+            let decision = governor.update(&signals);
+            // quiche's view of what the path delivers bounds how low a
+            // decision of low confidence may take it
+            let native = client.path_stats().next().map(|path| path.delivery_rate);
+            pacer.update(&decision, native, now);
+            second_start = now;
+            (sent, sent_packets, received) = (0, 0, 0);
+        }
+
+        let next = queued
+            .front()
+            .map_or(now + Duration::from_millis(1), |(at, _)| *at);
+        std::thread::sleep(
+            next.saturating_duration_since(Instant::now())
+                .min(Duration::from_millis(1)),
+        );
+    }
+    Ok(())
+}
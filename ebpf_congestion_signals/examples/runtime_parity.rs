@@ -0,0 +1,94 @@
+//! `StatsdExporter::run` on whichever runtime the crate was built for, flushing
+//! scripted intervals to a UDP socket standing in for the agent. Prints every
+//! metric line received; `cargo xtask runtimes` runs it on tokio and on smol
+//! and fails unless both print the same.
+//!
+//!     cargo run --example runtime_parity --no-default-features --features async,statsd
+//!     cargo run --example runtime_parity --no-default-features --features smol,statsd
+//!
+//! No collector here: loading it needs privileges and the eBPF object. What
+//! the run covers is the runtime's part, the interval the exporter ticks on and
+//! the task it runs in, against output that doesn't depend on timing.
+
+use ebpf_congestion_signals::{CongestionSignals, ReaderStats, StatsdExporter};
+use futures_util::future::select;
+use std::error::Error;
+use std::future::Future;
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+const TICKS: u64 = 5;
+const INTERVAL: Duration = Duration::from_millis(20);
+// Everything of a flush in one datagram, so a datagram is a tick
+const MAX_DATAGRAM: usize = 65_000;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let agent = UdpSocket::bind("127.0.0.1:0")?;
+    agent.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let exporter = StatsdExporter::new(agent.local_addr()?, "parity", INTERVAL, &[])?
+        .with_max_datagram(MAX_DATAGRAM);
+
+    // Tells the runtime side to stop once the agent has every tick, or gave up
+    let (done, stop) = tokio::sync::oneshot::channel::<()>();
+    let received = std::thread::spawn(move || -> std::io::Result<Vec<String>> {
+        let mut buf = vec![0; MAX_DATAGRAM];
+        let mut lines = Vec::new();
+        for _ in 0..TICKS {
+            let len = agent.recv(&mut buf)?;
+            let datagram = String::from_utf8_lossy(&buf[..len]);
+            lines.extend(datagram.lines().map(str::to_string));
+        }
+        let _ = done.send(());
+        Ok(lines)
+    });
+
+    let mut tick = 0;
+    let source = move || {
+        tick += 1;
+        (interval(tick), ReaderStats::default())
+    };
+    let started = Instant::now();
+    block_on(async {
+        select(std::pin::pin!(exporter.run(source)), stop).await;
+    });
+    let elapsed = started.elapsed();
+
+    let lines = received.join().expect("agent thread panicked")?;
+    // The first flush is an interval after the start
+    if elapsed < INTERVAL * TICKS as u32 {
+        return Err(format!("{} ticks of {:?} in {:?}", TICKS, INTERVAL, elapsed).into());
+    }
+    eprintln!("{}: {} ticks in {:?}", RUNTIME, TICKS, elapsed);
+    for line in lines {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn interval(tick: u64) -> CongestionSignals {
+    CongestionSignals::builder()
+        .interval_ns(INTERVAL.as_nanos() as u64)
+        .send_bytes(tick * 150_000)
+        .drops(tick * tick)
+        .avg_wmem_pressure(tick as f64 / 10.0)
+        .build()
+}
+
+#[cfg(feature = "async")]
+const RUNTIME: &str = "tokio";
+#[cfg(not(feature = "async"))]
+const RUNTIME: &str = "smol";
+
+#[cfg(feature = "async")]
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("tokio runtime")
+        .block_on(future)
+}
+
+#[cfg(not(feature = "async"))]
+fn block_on<F: Future>(future: F) -> F::Output {
+    smol::block_on(future)
+}
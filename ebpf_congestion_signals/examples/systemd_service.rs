@@ -0,0 +1,180 @@
+//! A `Type=notify` service around the collector, on the `systemd` helpers:
+//! READY=1 once the probes are attached and the calibration self-test passed,
+//! WATCHDOG=1 while snapshots keep coming, STOPPING=1 and the probes detached
+//! on SIGTERM, and a STATUS= plus an exit status from `ServiceLifecycle` when
+//! the probes don't attach. Each connection to the control socket gets the
+//! latest snapshot as one JSON line (`null` when there's none fresh), or with
+//! a `health` line sent first, the health report's verdict and the heartbeat's:
+//! whether events still come through the probes and readers, so a quiet
+//! snapshot can be told from a broken collector. It's the socket-activated one
+//! named `control`, or one bound at CONTROL_FALLBACK.
+//!
+//!     cargo build --release --example systemd_service --features systemd
+//!
+//! Unit files for it are in examples/systemd/. Outside systemd it runs the
+//! same with nothing to notify.
+
+use ebpf_congestion_signals::{
+    control_listener, listen_fds, watchdog_interval, CalibrationOutcome, CollectorConfig,
+    CongestionCollector, HealthReport, HeartbeatConfig, LatestSnapshot, ServiceLifecycle,
+    ServiceNotification, SystemdNotifier,
+};
+use futures_util::StreamExt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::signal::unix::{signal, SignalKind};
+
+const CONTROL_FALLBACK: &str = "/run/congestion-signals/control";
+const INTERVAL: Duration = Duration::from_secs(1);
+// How long a control client has to send a verb before it gets the snapshot
+const VERB_TIMEOUT: Duration = Duration::from_millis(100);
+
+fn main() {
+    // Socket activation's fds and variables are taken before the runtime
+    // starts threads that could read the environment
+    let mut passed = listen_fds();
+    let control = control_listener(&mut passed, Some("control"), Path::new(CONTROL_FALLBACK));
+    // This service listens on nothing else; the unit's other sockets go unused
+    for (name, _) in &passed {
+        eprintln!("ignoring passed fd {:?}", name);
+    }
+    let runtime = tokio::runtime::Runtime::new().expect("tokio runtime");
+    std::process::exit(runtime.block_on(run(control)));
+}
+
+async fn run(control: std::io::Result<std::os::unix::net::UnixListener>) -> i32 {
+    let notifier = SystemdNotifier::from_env().unwrap_or_else(|e| {
+        eprintln!("NOTIFY_SOCKET unusable, not notifying: {}", e);
+        None
+    });
+    let notify = |notifications: Vec<ServiceNotification>| {
+        if let Some(notifier) = &notifier {
+            if let Err(e) = notifier.notify(&notifications) {
+                eprintln!("sd_notify: {}", e);
+            }
+        }
+    };
+    let watchdog = watchdog_interval();
+    let mut lifecycle = ServiceLifecycle::new(watchdog);
+
+    let loaded: anyhow::Result<CongestionCollector> = async {
+        let config = CollectorConfig::default().with_heartbeat(HeartbeatConfig::default());
+        let mut collector = CongestionCollector::load_with_config(config)?;
+        collector.start_collection().await?;
+        Ok(collector)
+    }
+    .await;
+    let mut collector = match loaded {
+        Ok(collector) => collector,
+        Err(e) => {
+            eprintln!("probes failed to attach: {:#}", e);
+            notify(lifecycle.attach_failed(e.as_ref()));
+            return lifecycle.exit_code();
+        }
+    };
+    notify(lifecycle.probes_attached());
+    let result = self_test(&mut collector).await;
+    notify(lifecycle.self_test(result, Instant::now()));
+    if lifecycle.exit_code() != 0 {
+        // Dropping the collector detaches the probes
+        return lifecycle.exit_code();
+    }
+
+    let control = match control.and_then(|listener| {
+        listener.set_nonblocking(true)?;
+        tokio::net::UnixListener::from_std(listener)
+    }) {
+        Ok(control) => control,
+        Err(e) => {
+            eprintln!("control socket: {}", e);
+            notify(lifecycle.stopping());
+            return 1;
+        }
+    };
+    // The publisher only reads while a stream is consumed, and the watchdog
+    // pings on what it publishes
+    let snapshots = collector.snapshots(INTERVAL);
+    tokio::spawn(snapshots.for_each(|_| async {}));
+
+    let mut sigterm = signal(SignalKind::terminate()).expect("SIGTERM handler");
+    // Twice per watchdog interval at least, as ServiceLifecycle::tick wants
+    let mut ticker = tokio::time::interval(watchdog.map_or(INTERVAL, |w| (w / 4).min(INTERVAL)));
+    loop {
+        tokio::select! {
+            _ = sigterm.recv() => break,
+            _ = tokio::signal::ctrl_c() => break,
+            _ = ticker.tick() => {
+                let age = match collector.latest(INTERVAL * 2) {
+                    LatestSnapshot::Fresh(signals) => signals.age(),
+                    LatestSnapshot::Stale { age } => Some(age),
+                    LatestSnapshot::Unavailable => None,
+                };
+                notify(lifecycle.tick(age, Instant::now()));
+            }
+            accepted = control.accept() => {
+                let Ok((client, _)) = accepted else { continue };
+                let (reader, mut writer) = client.into_split();
+                let mut verb = String::new();
+                let mut reader = BufReader::new(reader);
+                let _ = tokio::time::timeout(VERB_TIMEOUT, reader.read_line(&mut verb)).await;
+                let line = match (verb.trim(), collector.latest(INTERVAL * 2)) {
+                    ("health", _) => health_json(&collector.health()),
+                    (_, LatestSnapshot::Fresh(signals)) => signals.to_json(),
+                    _ => "null".to_string(),
+                };
+                let _ = writer.write_all(format!("{}\n", line).as_bytes()).await;
+            }
+        }
+    }
+
+    notify(lifecycle.stopping());
+    if let Err(e) = collector.stop_collection() {
+        eprintln!("stopping collection: {}", e);
+    }
+    drop(collector);
+    0
+}
+
+/// The `health` verb's line. `pipeline_alive` is null without a heartbeat
+fn health_json(report: &HealthReport) -> String {
+    let heartbeat = report.heartbeat.as_ref();
+    let alive = heartbeat.map_or("null".to_string(), |status| {
+        status.pipeline_alive.to_string()
+    });
+    let round_trip = heartbeat
+        .and_then(|status| status.last_round_trip)
+        .map_or("null".to_string(), |round_trip| {
+            round_trip.as_micros().to_string()
+        });
+    format!(
+        "{{\"healthy\":{},\"warnings\":{},\"pipeline_alive\":{},\"heartbeat_round_trip_us\":{}}}",
+        report.is_healthy(),
+        report.warnings.len(),
+        alive,
+        round_trip
+    )
+}
+
+/// The socket-state offsets read right, and every program still attached
+async fn self_test(collector: &mut CongestionCollector) -> Result<(), String> {
+    match collector.health().calibration {
+        Some(CalibrationOutcome::Mismatch {
+            expected_sndbuf,
+            observed_sndbuf,
+            ..
+        }) => {
+            return Err(format!(
+                "calibration read sk_sndbuf {} for {}",
+                observed_sndbuf, expected_sndbuf
+            ))
+        }
+        Some(CalibrationOutcome::Failed(why)) => return Err(format!("calibration: {}", why)),
+        _ => {}
+    }
+    match collector.verify_attachments().await {
+        Ok(false) => Ok(()),
+        Ok(true) => Err("probes detached right after start".to_string()),
+        Err(e) => Err(format!("verifying attachments: {:#}", e)),
+    }
+}
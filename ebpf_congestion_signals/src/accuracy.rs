@@ -0,0 +1,536 @@
+//Ground truth for the sampled signals. The kernel keeps exact SNMP counters
+//for what several signals estimate: every interval read diffs /proc/net/snmp
+//and /proc/net/netstat against the previous read and compares the deltas with
+//the signals of the same interval, scaled by their sampling ratio.
+//
+//Both files are per network namespace, the collector's own, while the probes
+//see every namespace on the host; sends from containers, and loopback sends
+//left out by exclude_loopback, show up as disagreement.
+
+use crate::CongestionSignals;
+use std::collections::HashMap;
+use std::fmt;
+
+// IPv4 plus UDP header, added per datagram to compare payloads with IP octets
+const UDP_IPV4_HEADER_BYTES: u64 = 28;
+
+/// When a persistent disagreement becomes a health warning, see
+/// `CollectorConfig::without_accuracy_check`
+#[derive(Debug, Clone)]
+pub struct AccuracyConfig {
+    /// Relative error over which an interval counts as off
+    pub max_relative_error: f64,
+    /// Intervals in a row off before `health()` warns
+    pub persistent_intervals: u32,
+    /// Kernel count over the sampling ratio, the samples an estimate rests on,
+    /// below which an interval isn't judged either way
+    pub min_samples: u64,
+}
+
+impl Default for AccuracyConfig {
+    fn default() -> Self {
+        Self {
+            max_relative_error: 0.20,
+            persistent_intervals: 5,
+            min_samples: 20,
+        }
+    }
+}
+
+/// The SNMP counters the signals are compared with, None when the kernel
+/// doesn't have one. Cumulative when read, deltas in an `AccuracyReport`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SnmpCounters {
+    /// IpExt OutOctets: IP bytes sent, headers included, every protocol
+    pub ip_out_octets: Option<u64>,
+    /// Udp OutDatagrams (IPv4)
+    pub udp_out_datagrams: Option<u64>,
+    /// Udp RcvbufErrors
+    pub udp_rcvbuf_errors: Option<u64>,
+    /// Tcp OutSegs
+    pub tcp_out_segs: Option<u64>,
+    /// Tcp RetransSegs
+    pub tcp_retrans_segs: Option<u64>,
+    /// TcpExt TCPTimeouts
+    pub tcp_timeouts: Option<u64>,
+}
+
+impl SnmpCounters {
+    /// The collector's namespace's counters, None when /proc/net/snmp can't be
+    /// read. /proc/net/netstat is optional
+    pub fn read() -> Option<Self> {
+        let snmp = std::fs::read_to_string("/proc/net/snmp").ok()?;
+        let netstat = std::fs::read_to_string("/proc/net/netstat").unwrap_or_default();
+        Some(Self::parse(&snmp, &netstat))
+    }
+
+    /// From the text of /proc/net/snmp and /proc/net/netstat: per protocol a
+    /// line of names and a line of values, both led by `Proto:`
+    pub fn parse(snmp: &str, netstat: &str) -> Self {
+        let mut values = HashMap::new();
+        for text in [snmp, netstat] {
+            let lines: Vec<&str> = text.lines().collect();
+            for pair in lines.chunks(2) {
+                let [names, counts] = pair else {
+                    continue;
+                };
+                let (Some((proto, names)), Some((value_proto, counts))) =
+                    (names.split_once(':'), counts.split_once(':'))
+                else {
+                    continue;
+                };
+                if proto != value_proto {
+                    continue;
+                }
+                for (name, count) in names.split_whitespace().zip(counts.split_whitespace()) {
+                    // A few are signed (Tcp MaxConn is -1), none of those we use
+                    if let Ok(count) = count.parse::<u64>() {
+                        values.insert((proto.to_string(), name.to_string()), count);
+                    }
+                }
+            }
+        }
+        let get =
+            |proto: &str, name: &str| values.get(&(proto.to_string(), name.to_string())).copied();
+        Self {
+            ip_out_octets: get("IpExt", "OutOctets"),
+            udp_out_datagrams: get("Udp", "OutDatagrams"),
+            udp_rcvbuf_errors: get("Udp", "RcvbufErrors"),
+            tcp_out_segs: get("Tcp", "OutSegs"),
+            tcp_retrans_segs: get("Tcp", "RetransSegs"),
+            tcp_timeouts: get("TcpExt", "TCPTimeouts"),
+        }
+    }
+
+    /// Each counter's increase since `earlier`, None where either side lacks it
+    /// or the counter went backwards further than a 32-bit wrap explains
+    pub fn delta(&self, earlier: &SnmpCounters) -> SnmpCounters {
+        self.zip(earlier, |now, then| counter_delta(then?, now?))
+    }
+
+    fn zip(
+        &self,
+        other: &SnmpCounters,
+        f: impl Fn(Option<u64>, Option<u64>) -> Option<u64>,
+    ) -> Self {
+        Self {
+            ip_out_octets: f(self.ip_out_octets, other.ip_out_octets),
+            udp_out_datagrams: f(self.udp_out_datagrams, other.udp_out_datagrams),
+            udp_rcvbuf_errors: f(self.udp_rcvbuf_errors, other.udp_rcvbuf_errors),
+            tcp_out_segs: f(self.tcp_out_segs, other.tcp_out_segs),
+            tcp_retrans_segs: f(self.tcp_retrans_segs, other.tcp_retrans_segs),
+            tcp_timeouts: f(self.tcp_timeouts, other.tcp_timeouts),
+        }
+    }
+}
+
+/// Increase of a cumulative counter. Counters are unsigned long, 32 bits on
+/// 32-bit kernels, so a lower value from a counter that fit in 32 bits is a
+/// wrap; anything else is a reset (the namespace was recreated)
+fn counter_delta(then: u64, now: u64) -> Option<u64> {
+    if now >= then {
+        Some(now - then)
+    } else if then <= u32::MAX as u64 {
+        Some(now + (1 << 32) - then)
+    } else {
+        None
+    }
+}
+
+/// One signal against its kernel counter over the same period
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignalAccuracy {
+    pub signal: &'static str,
+    /// The SNMP counter it's compared with
+    pub reference: &'static str,
+    /// 1 in how many events the signal samples, the estimate is scaled by it.
+    /// Under per-socket sampling 1 in how many sockets, rounded
+    pub sample_ratio: u64,
+    pub estimate: f64,
+    pub actual: u64,
+}
+
+impl SignalAccuracy {
+    /// |estimate - actual| / actual, None when the kernel counted nothing
+    pub fn relative_error(&self) -> Option<f64> {
+        (self.actual > 0).then(|| (self.estimate - self.actual as f64).abs() / self.actual as f64)
+    }
+
+    /// Whether there's enough behind the estimate to judge it, see
+    /// `AccuracyConfig::min_samples`
+    fn judged(&self, config: &AccuracyConfig) -> bool {
+        self.actual / self.sample_ratio >= config.min_samples
+    }
+}
+
+impl fmt::Display for SignalAccuracy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.relative_error() {
+            Some(error) => write!(
+                f,
+                "{} estimate within {:.1}% of {} ({:.0} vs {})",
+                self.signal,
+                error * 100.0,
+                self.reference,
+                self.estimate,
+                self.actual
+            ),
+            None => write!(
+                f,
+                "{} estimate {:.0}, {} counted nothing",
+                self.signal, self.estimate, self.reference
+            ),
+        }
+    }
+}
+
+/// The signals against the kernel's SNMP counters, see
+/// `CongestionCollector::accuracy`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccuracyReport {
+    pub interval_ns: u64,
+    /// Increase of each counter over the period
+    pub reference: SnmpCounters,
+    pub signals: Vec<SignalAccuracy>,
+}
+
+impl AccuracyReport {
+    /// Compare the signals of an interval with the counter deltas of the same
+    /// interval. Signals whose counter is missing are left out
+    pub fn compare(signals: &CongestionSignals, reference: SnmpCounters) -> Self {
+        let udp_sends = signals
+            .send_size_stats
+            .udp
+            .as_ref()
+            .map_or(0, |udp| udp.samples);
+        // The configured factor, not the measured one: this checks the sampling
+        let factor = signals.estimated.scale.configured_factor();
+        let ratio = factor.round() as u64;
+        let mut compared = Vec::new();
+        let mut add = |signal, counter, sample_ratio, estimate: f64, actual: Option<u64>| {
+            if let Some(actual) = actual {
+                compared.push(SignalAccuracy {
+                    signal,
+                    reference: counter,
+                    sample_ratio,
+                    estimate,
+                    actual,
+                });
+            }
+        };
+        add(
+            "udp_sends",
+            "Udp OutDatagrams",
+            ratio,
+            udp_sends as f64 * factor,
+            reference.udp_out_datagrams,
+        );
+        // Includes TCP and every other protocol: on a host mostly sending TCP
+        // this is an underestimate however good the sampling
+        add(
+            "send_bytes",
+            "IpExt OutOctets",
+            ratio,
+            (signals.send_bytes + udp_sends * UDP_IPV4_HEADER_BYTES) as f64 * factor,
+            reference.ip_out_octets,
+        );
+        add(
+            "udp_rcv_drops",
+            "Udp RcvbufErrors",
+            1,
+            signals.udp_rcv_drops as f64,
+            reference.udp_rcvbuf_errors,
+        );
+        if let Some(rto_events) = signals.rto_events {
+            add(
+                "rto_events",
+                "TcpExt TCPTimeouts",
+                1,
+                rto_events as f64,
+                reference.tcp_timeouts,
+            );
+        }
+        Self {
+            interval_ns: signals.interval_ns,
+            reference,
+            signals: compared,
+        }
+    }
+
+    /// Add a later report, for totals over a longer period
+    pub fn merge(&mut self, next: &AccuracyReport) {
+        self.interval_ns += next.interval_ns;
+        self.reference = self
+            .reference
+            .zip(&next.reference, |total, next| match (total, next) {
+                (Some(total), Some(next)) => Some(total + next),
+                (total, next) => total.or(next),
+            });
+        for signal in &next.signals {
+            match self.signals.iter_mut().find(|s| s.signal == signal.signal) {
+                Some(total) => {
+                    total.estimate += signal.estimate;
+                    total.actual += signal.actual;
+                }
+                None => self.signals.push(signal.clone()),
+            }
+        }
+    }
+}
+
+/// Diffs the counters on every interval read and tracks how long each signal
+/// has been off
+pub(crate) struct AccuracyTracker {
+    config: AccuracyConfig,
+    last: Option<SnmpCounters>,
+    latest: Option<AccuracyReport>,
+    since_start: Option<AccuracyReport>,
+    // Intervals in a row each signal was off, with its latest error
+    off: HashMap<&'static str, (u32, f64, &'static str)>,
+}
+
+impl AccuracyTracker {
+    pub(crate) fn new(config: AccuracyConfig) -> Self {
+        Self {
+            config,
+            last: None,
+            latest: None,
+            since_start: None,
+            off: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn observe(&mut self, signals: &CongestionSignals) {
+        if let Some(now) = SnmpCounters::read() {
+            self.observe_counters(signals, now);
+        }
+    }
+
+    /// `observe` with the counters already read
+    fn observe_counters(&mut self, signals: &CongestionSignals, now: SnmpCounters) {
+        // The first read only sets the baseline
+        let Some(last) = self.last.replace(now) else {
+            return;
+        };
+        let report = AccuracyReport::compare(signals, now.delta(&last));
+        for signal in &report.signals {
+            if !signal.judged(&self.config) {
+                continue;
+            }
+            let error = signal.relative_error().unwrap_or(0.0);
+            if error > self.config.max_relative_error {
+                let off = self
+                    .off
+                    .entry(signal.signal)
+                    .or_insert((0, 0.0, signal.reference));
+                off.0 += 1;
+                off.1 = error;
+            } else {
+                self.off.remove(signal.signal);
+            }
+        }
+        match &mut self.since_start {
+            Some(total) => total.merge(&report),
+            None => self.since_start = Some(report.clone()),
+        }
+        self.latest = Some(report);
+    }
+
+    pub(crate) fn latest(&self) -> Option<AccuracyReport> {
+        self.latest.clone()
+    }
+
+    pub(crate) fn since_start(&self) -> Option<AccuracyReport> {
+        self.since_start.clone()
+    }
+
+    /// Health warnings for signals off for `persistent_intervals` in a row
+    pub(crate) fn warnings(&self) -> Vec<String> {
+        let mut off: Vec<_> = self
+            .off
+            .iter()
+            .filter(|(_, (intervals, _, _))| *intervals >= self.config.persistent_intervals)
+            .collect();
+        off.sort_by_key(|(signal, _)| **signal);
+        off.into_iter()
+            .map(|(signal, (intervals, error, reference))| {
+                let hint = if *signal == "udp_sends" || *signal == "send_bytes" {
+                    "1-in-100 send sampling may be too coarse for this traffic, \
+                     consider a lower sampling ratio (or check for sends from \
+                     other namespaces and excluded loopback)"
+                } else {
+                    "events may be lost or the probe misses a path"
+                };
+                format!(
+                    "{} disagrees with {} by {:.0}% for {} intervals in a row: {}",
+                    signal,
+                    reference,
+                    error * 100.0,
+                    intervals,
+                    hint
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SendSizeStats, SendSizes};
+
+    // /proc/net/snmp and /proc/net/netstat excerpts an interval apart: Udp
+    // OutDatagrams wraps past 2^32 in between and TCPTimeouts is reset
+    const SNMP_BEFORE: &str = "Ip: Forwarding DefaultTTL\nIp: 1 64\n\
+        Tcp: RtoAlgorithm MaxConn OutSegs RetransSegs\nTcp: 1 -1 500 7\n\
+        Udp: InDatagrams OutDatagrams RcvbufErrors\nUdp: 10 4294967000 5\n";
+    const SNMP_AFTER: &str = "Ip: Forwarding DefaultTTL\nIp: 1 64\n\
+        Tcp: RtoAlgorithm MaxConn OutSegs RetransSegs\nTcp: 1 -1 900 9\n\
+        Udp: InDatagrams OutDatagrams RcvbufErrors\nUdp: 20 704 55\n";
+    const NETSTAT_BEFORE: &str = "TcpExt: SyncookiesSent TCPTimeouts\nTcpExt: 0 1099511627776\n\
+        IpExt: InOctets OutOctets\nIpExt: 0 1000\n";
+    const NETSTAT_AFTER: &str = "TcpExt: SyncookiesSent TCPTimeouts\nTcpExt: 0 3\n\
+        IpExt: InOctets OutOctets\nIpExt: 0 1229000\n";
+
+    /// 10 sampled 1200-byte datagrams and 48 receive drops
+    fn scripted() -> CongestionSignals {
+        sampled(10)
+    }
+
+    fn sampled(datagrams: u64) -> CongestionSignals {
+        CongestionSignals::builder()
+            .send_bytes(datagrams * 1200)
+            .send_size_stats(SendSizeStats {
+                udp: Some(SendSizes {
+                    samples: datagrams,
+                    ..Default::default()
+                }),
+                tcp: None,
+            })
+            .udp_rcv_drops(48)
+            .rto_events(2)
+            .build()
+    }
+
+    fn error(report: &AccuracyReport, signal: &str) -> Option<f64> {
+        report
+            .signals
+            .iter()
+            .find(|s| s.signal == signal)
+            .and_then(|s| s.relative_error())
+    }
+
+    #[test]
+    fn counters_parse_by_name_and_skip_signed_ones() {
+        let counters = SnmpCounters::parse(SNMP_BEFORE, NETSTAT_BEFORE);
+        assert_eq!(counters.udp_out_datagrams, Some(4_294_967_000));
+        assert_eq!(counters.tcp_out_segs, Some(500));
+        assert_eq!(counters.tcp_retrans_segs, Some(7));
+        assert_eq!(counters.ip_out_octets, Some(1000));
+        assert_eq!(counters.tcp_timeouts, Some(1_099_511_627_776));
+        // No netstat: those counters are missing, not zero
+        assert_eq!(SnmpCounters::parse(SNMP_BEFORE, "").ip_out_octets, None);
+    }
+
+    #[test]
+    fn deltas_wrap_32_bit_counters_and_drop_resets() {
+        let before = SnmpCounters::parse(SNMP_BEFORE, NETSTAT_BEFORE);
+        let after = SnmpCounters::parse(SNMP_AFTER, NETSTAT_AFTER);
+        let delta = after.delta(&before);
+        assert_eq!(delta.udp_out_datagrams, Some(1000));
+        assert_eq!(delta.tcp_out_segs, Some(400));
+        assert_eq!(delta.udp_rcvbuf_errors, Some(50));
+        // Went back from past 32 bits
+        assert_eq!(delta.tcp_timeouts, None);
+    }
+
+    #[test]
+    fn estimates_are_compared_with_the_counter_deltas() {
+        let before = SnmpCounters::parse(SNMP_BEFORE, NETSTAT_BEFORE);
+        let after = SnmpCounters::parse(SNMP_AFTER, NETSTAT_AFTER);
+        let report = AccuracyReport::compare(&scripted(), after.delta(&before));
+        // 1 in 100 sampled: exactly the 1000 sent, headers included in the octets
+        assert!(error(&report, "udp_sends").unwrap().abs() < 1e-9);
+        assert!(error(&report, "send_bytes").unwrap().abs() < 1e-9);
+        assert!((error(&report, "udp_rcv_drops").unwrap() - 0.04).abs() < 1e-9);
+        // The reset counter leaves RTOs out
+        assert!(report.signals.iter().all(|s| s.signal != "rto_events"));
+        let drops = report
+            .signals
+            .iter()
+            .find(|s| s.signal == "udp_rcv_drops")
+            .unwrap();
+        assert_eq!(
+            drops.to_string(),
+            "udp_rcv_drops estimate within 4.0% of Udp RcvbufErrors (48 vs 50)"
+        );
+    }
+
+    #[test]
+    fn merged_reports_sum_estimates_and_counters() {
+        let reference = SnmpCounters {
+            udp_out_datagrams: Some(1000),
+            udp_rcvbuf_errors: Some(50),
+            ..Default::default()
+        };
+        let mut total = AccuracyReport::compare(&scripted(), reference);
+        total.merge(&AccuracyReport::compare(&scripted(), reference));
+        assert_eq!(total.reference.udp_out_datagrams, Some(2000));
+        assert_eq!(total.reference.ip_out_octets, None);
+        assert!((error(&total, "udp_rcv_drops").unwrap() - 0.04).abs() < 1e-9);
+    }
+
+    #[test]
+    fn only_a_persistent_disagreement_warns() {
+        let config = AccuracyConfig::default();
+        let mut tracker = AccuracyTracker::new(config.clone());
+        // Half the datagrams the kernel sent, every interval
+        let mut counters = SnmpCounters {
+            udp_out_datagrams: Some(0),
+            ..Default::default()
+        };
+        tracker.observe_counters(&scripted(), counters);
+        for interval in 1..=config.persistent_intervals {
+            counters.udp_out_datagrams = counters.udp_out_datagrams.map(|n| n + 2000);
+            tracker.observe_counters(&scripted(), counters);
+            let warned = !tracker.warnings().is_empty();
+            assert_eq!(warned, interval == config.persistent_intervals);
+        }
+        assert!(tracker.warnings()[0]
+            .starts_with("udp_sends disagrees with Udp OutDatagrams by 50% for 5 intervals"));
+        assert_eq!(
+            tracker.latest().unwrap().reference.udp_out_datagrams,
+            Some(2000)
+        );
+        assert_eq!(
+            tracker.since_start().unwrap().reference.udp_out_datagrams,
+            Some(10_000)
+        );
+
+        // One interval too thin to judge leaves the count, one back in line
+        // resets it
+        counters.udp_out_datagrams = counters.udp_out_datagrams.map(|n| n + 1000);
+        tracker.observe_counters(&scripted(), counters);
+        assert!(!tracker.warnings().is_empty());
+        counters.udp_out_datagrams = counters.udp_out_datagrams.map(|n| n + 2000);
+        tracker.observe_counters(&sampled(20), counters);
+        assert!(tracker.warnings().is_empty());
+    }
+
+    #[test]
+    fn too_few_samples_are_not_judged() {
+        let mut tracker = AccuracyTracker::new(AccuracyConfig {
+            persistent_intervals: 1,
+            ..Default::default()
+        });
+        let mut counters = SnmpCounters {
+            udp_out_datagrams: Some(0),
+            ..Default::default()
+        };
+        tracker.observe_counters(&scripted(), counters);
+        // 19 samples' worth at 1 in 100, the estimate is 1000
+        counters.udp_out_datagrams = Some(1900);
+        tracker.observe_counters(&scripted(), counters);
+        assert!(tracker.warnings().is_empty());
+    }
+}
@@ -0,0 +1,513 @@
+//IRQ steering advice from softirq imbalance. When one CPU does most of the
+//network softirq work, usually every NIC queue's interrupt is landing on it:
+//irqbalance off, a driver default affinity, or all queues steered to CPU 0.
+//The advisor takes an interval's per-CPU softirq time
+//(`read_and_reset_per_cpu`) with the /proc/interrupts counts over the same
+//interval. It finds the busiest CPU against the mean and the IRQs that fired
+//most on it. The biggest of those stays where it is and each of the others
+//gets a CPU, least loaded first, as the hex mask /proc/irq/N/smp_affinity
+//takes. It only reads: applying the masks is left to whoever runs the host.
+//Nothing is suggested below `min_imbalance`, or at all when the host was
+//booted with isolcpus, since that layout is deliberate and spreading IRQs
+//over it would undo it.
+
+use crate::cpus::parse_cpu_list;
+use crate::PerCpuSignals;
+
+/// When the advisor suggests moving IRQs, and which ones it considers
+#[derive(Debug, Clone, PartialEq)]
+pub struct IrqAdvisorConfig {
+    /// The busiest CPU's softirq fraction over the mean of all CPUs below
+    /// which the load counts as balanced, 2.0 = twice its share
+    pub min_imbalance: f64,
+    /// Its own softirq fraction below which it isn't worth moving anything,
+    /// however uneven, 0.3 = 30% of its time
+    pub min_busy_fraction: f64,
+    /// Share of the busiest CPU's interrupts an IRQ needs to be one of those
+    /// responsible
+    pub min_irq_share: f64,
+    /// Only IRQs whose name contains one of these, e.g. the interface: "eth0".
+    /// Empty considers every IRQ
+    pub irq_names: Vec<String>,
+}
+
+impl Default for IrqAdvisorConfig {
+    fn default() -> Self {
+        Self {
+            min_imbalance: 2.0,
+            min_busy_fraction: 0.3,
+            min_irq_share: 0.1,
+            irq_names: Vec::new(),
+        }
+    }
+}
+
+/// One numbered row of /proc/interrupts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrqCounts {
+    pub irq: u32,
+    /// Interrupts handled on each of the snapshot's `cpus`
+    pub per_cpu: Vec<u64>,
+    /// The last column: the action's name, e.g. "eth0-TxRx-3"
+    pub name: String,
+}
+
+/// The numbered IRQs of /proc/interrupts. Named rows (NMI, LOC, ...) are
+/// per-CPU events no affinity moves, and are left out
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InterruptSnapshot {
+    /// The header's CPU ids, online ones only and not necessarily 0..N
+    pub cpus: Vec<u32>,
+    pub irqs: Vec<IrqCounts>,
+}
+
+impl InterruptSnapshot {
+    /// Empty when /proc/interrupts can't be read
+    pub fn read() -> Self {
+        Self::parse(&std::fs::read_to_string("/proc/interrupts").unwrap_or_default())
+    }
+
+    /// From the text of /proc/interrupts. Rows that don't parse are skipped
+    pub fn parse(contents: &str) -> Self {
+        let mut lines = contents.lines();
+        let cpus: Vec<u32> = lines
+            .next()
+            .map(|header| {
+                header
+                    .split_whitespace()
+                    .filter_map(|cpu| cpu.strip_prefix("CPU")?.parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let irqs = lines
+            .filter_map(|line| {
+                let (label, rest) = line.split_once(':')?;
+                let irq = label.trim().parse().ok()?;
+                let mut fields = rest.split_whitespace();
+                let per_cpu = fields
+                    .by_ref()
+                    .take(cpus.len())
+                    .map(|count| count.parse().ok())
+                    .collect::<Option<Vec<u64>>>()?;
+                let name = fields.last().unwrap_or_default().to_string();
+                Some(IrqCounts { irq, per_cpu, name })
+            })
+            .collect();
+        Self { cpus, irqs }
+    }
+
+    /// Interrupts since `earlier`, matched by IRQ and CPU id. What it didn't
+    /// have counts from zero, and counters that went backwards from where
+    /// they are now
+    pub fn since(&self, earlier: &Self) -> Self {
+        let irqs = self
+            .irqs
+            .iter()
+            .map(|now| {
+                let before = earlier.irqs.iter().find(|irq| irq.irq == now.irq);
+                let per_cpu = now
+                    .per_cpu
+                    .iter()
+                    .enumerate()
+                    .map(|(column, &count)| {
+                        let was = before.and_then(|before| {
+                            let cpu = self.cpus.get(column)?;
+                            let column = earlier.cpus.iter().position(|c| c == cpu)?;
+                            before.per_cpu.get(column).copied()
+                        });
+                        count.checked_sub(was.unwrap_or(0)).unwrap_or(count)
+                    })
+                    .collect();
+                IrqCounts {
+                    irq: now.irq,
+                    per_cpu,
+                    name: now.name.clone(),
+                }
+            })
+            .collect();
+        Self {
+            cpus: self.cpus.clone(),
+            irqs,
+        }
+    }
+}
+
+/// CPUs isolated by `isolcpus=` on a kernel command line, sorted. Its flags
+/// (domain, nohz, managed_irq) are skipped
+pub fn isolated_cpus(cmdline: &str) -> Vec<u32> {
+    let list: Vec<&str> = cmdline
+        .split_whitespace()
+        .filter_map(|arg| arg.strip_prefix("isolcpus="))
+        .flat_map(|value| value.split(','))
+        .filter(|item| !item.starts_with(|c: char| c.is_ascii_alphabetic()))
+        .collect();
+    parse_cpu_list(&list.join(","))
+}
+
+/// The smp_affinity mask for CPU `cpu`, at least `width` CPUs wide: hex, in
+/// comma-separated 32-bit groups, the highest first
+pub fn affinity_mask(cpu: u32, width: u32) -> String {
+    let groups = width.max(cpu + 1).div_ceil(32);
+    (0..groups)
+        .rev()
+        .map(|group| {
+            let bits = if group == cpu / 32 {
+                1u32 << (cpu % 32)
+            } else {
+                0
+            };
+            format!("{:08x}", bits)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Why an advice has no steering in it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IrqSuppression {
+    /// Under `min_imbalance` or `min_busy_fraction`, or no softirq time at all
+    Balanced,
+    /// The host was booted with isolcpus, these CPUs
+    Isolated(Vec<u32>),
+    /// None of the considered IRQs fired on the busiest CPU: the softirq work
+    /// comes from elsewhere (RPS, loopback, another host's traffic in a VM)
+    NoInterrupts,
+    /// Only one IRQ is responsible, and splitting it is RSS's job, not
+    /// affinity's
+    SingleIrq,
+}
+
+/// One suggested move
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrqSteering {
+    pub irq: u32,
+    pub name: String,
+    /// The busiest CPU, where it fired, and how often over the interval
+    pub cpu: u32,
+    pub interrupts: u64,
+    pub suggested_cpu: u32,
+    /// For /proc/irq/`irq`/smp_affinity
+    pub affinity_mask: String,
+}
+
+impl IrqSteering {
+    /// The command that applies it, run as root. The advisor never does
+    pub fn command(&self) -> String {
+        format!(
+            "echo {} > /proc/irq/{}/smp_affinity",
+            self.affinity_mask, self.irq
+        )
+    }
+}
+
+/// One interval's advice, see [`advise`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct IrqAdvice {
+    /// The busiest CPU's softirq fraction over the mean of all CPUs, 1.0 when
+    /// even, 0.0 without softirq time
+    pub imbalance: f64,
+    pub busiest_cpu: Option<u32>,
+    pub busiest_fraction: f64,
+    /// The IRQs that fired most on the busiest CPU, most first
+    pub responsible: Vec<u32>,
+    /// Moves for all of them but the first; empty when suppressed
+    pub recommendations: Vec<IrqSteering>,
+    pub suppressed: Option<IrqSuppression>,
+}
+
+/// Advice from one interval's per-CPU softirq fractions and the interrupts
+/// over the same interval (`InterruptSnapshot::since`), given the isolated
+/// CPUs. Pure, so fixtures advise like a live host
+pub fn advise(
+    config: &IrqAdvisorConfig,
+    per_cpu: &PerCpuSignals,
+    interrupts: &InterruptSnapshot,
+    isolated: &[u32],
+) -> IrqAdvice {
+    let fractions = &per_cpu.softirq_fraction_by_cpu;
+    let total: f64 = fractions.iter().sum();
+    let busiest = fractions
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .filter(|_| total > 0.0);
+    let (busiest_cpu, busiest_fraction) =
+        busiest.map_or((None, 0.0), |(index, &f)| (Some(per_cpu.cpu_id(index)), f));
+    let imbalance = if total > 0.0 {
+        busiest_fraction / (total / fractions.len() as f64)
+    } else {
+        0.0
+    };
+
+    let considered = |irq: &&IrqCounts| {
+        config.irq_names.is_empty()
+            || config
+                .irq_names
+                .iter()
+                .any(|n| irq.name.contains(n.as_str()))
+    };
+    let column = busiest_cpu.and_then(|cpu| interrupts.cpus.iter().position(|&c| c == cpu));
+    let mut fired: Vec<(&IrqCounts, u64)> = column
+        .map(|column| {
+            interrupts
+                .irqs
+                .iter()
+                .filter(considered)
+                .filter_map(|irq| Some((irq, *irq.per_cpu.get(column)?)))
+                .filter(|&(_, count)| count > 0)
+                .collect()
+        })
+        .unwrap_or_default();
+    let on_busiest: u64 = fired.iter().map(|&(_, count)| count).sum();
+    fired.retain(|&(_, count)| count as f64 >= on_busiest as f64 * config.min_irq_share);
+    fired.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.irq.cmp(&b.0.irq)));
+    let responsible = fired.iter().map(|(irq, _)| irq.irq).collect();
+
+    let suppressed = if !isolated.is_empty() {
+        Some(IrqSuppression::Isolated(isolated.to_vec()))
+    } else if imbalance < config.min_imbalance || busiest_fraction < config.min_busy_fraction {
+        Some(IrqSuppression::Balanced)
+    } else if fired.is_empty() {
+        Some(IrqSuppression::NoInterrupts)
+    } else if fired.len() == 1 {
+        Some(IrqSuppression::SingleIrq)
+    } else {
+        None
+    };
+
+    let mut recommendations = Vec::new();
+    if let (None, Some(busiest_cpu)) = (&suppressed, busiest_cpu) {
+        // Online CPUs only, which the header lists
+        let mut targets: Vec<u32> = interrupts
+            .cpus
+            .iter()
+            .copied()
+            .filter(|&cpu| cpu != busiest_cpu)
+            .collect();
+        let width = targets.iter().max().map_or(0, |&highest| highest + 1);
+        let load = |cpu: u32| {
+            per_cpu
+                .position(cpu)
+                .and_then(|index| fractions.get(index))
+                .copied()
+                .unwrap_or(0.0)
+        };
+        targets.sort_by(|&a, &b| load(a).total_cmp(&load(b)).then(a.cmp(&b)));
+        if !targets.is_empty() {
+            for (i, &(irq, interrupts)) in fired.iter().skip(1).enumerate() {
+                let suggested_cpu = targets[i % targets.len()];
+                recommendations.push(IrqSteering {
+                    irq: irq.irq,
+                    name: irq.name.clone(),
+                    cpu: busiest_cpu,
+                    interrupts,
+                    suggested_cpu,
+                    affinity_mask: affinity_mask(suggested_cpu, width.max(busiest_cpu + 1)),
+                });
+            }
+        }
+    }
+
+    IrqAdvice {
+        imbalance,
+        busiest_cpu,
+        busiest_fraction,
+        responsible,
+        recommendations,
+        suppressed,
+    }
+}
+
+/// Advises on a live host: reads isolcpus from /proc/cmdline once, and
+/// /proc/interrupts at the start of each interval
+#[derive(Debug, Clone)]
+pub struct IrqAdvisor {
+    config: IrqAdvisorConfig,
+    isolated: Vec<u32>,
+    previous: InterruptSnapshot,
+}
+
+impl IrqAdvisor {
+    /// Takes the first /proc/interrupts snapshot: create it when the interval
+    /// the first per-CPU read covers starts
+    pub fn new(config: IrqAdvisorConfig) -> Self {
+        let cmdline = std::fs::read_to_string("/proc/cmdline").unwrap_or_default();
+        Self {
+            config,
+            isolated: isolated_cpus(&cmdline),
+            previous: InterruptSnapshot::read(),
+        }
+    }
+
+    pub fn isolated_cpus(&self) -> &[u32] {
+        &self.isolated
+    }
+
+    /// Advice for the interval since the last call, or since `new`. `per_cpu`
+    /// has to cover the same interval: `read_and_reset_per_cpu` right before
+    pub fn recommendations(&mut self, per_cpu: &PerCpuSignals) -> IrqAdvice {
+        let now = InterruptSnapshot::read();
+        let delta = now.since(&self.previous);
+        self.previous = now;
+        advise(&self.config, per_cpu, &delta, &self.isolated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // All four eth0 queues on CPU 0, 40%, 30%, 20% and 10% of its interrupts
+    // between the two
+    const EARLIER: &str = "           CPU0       CPU1       CPU2       CPU3\n  \
+           0:         45          0          0          0   IO-APIC   2-edge      timer\n \
+          24:     100000          0          0          0  PCI-MSI 524288-edge      eth0-TxRx-0\n \
+          25:      80000          0          0          0  PCI-MSI 524289-edge      eth0-TxRx-1\n \
+          26:      60000          0          0          0  PCI-MSI 524290-edge      eth0-TxRx-2\n \
+          27:      40000          0          0          0  PCI-MSI 524291-edge      eth0-TxRx-3\n \
+          30:       1000       1000       1000       1000  PCI-MSI 376832-edge      nvme0q0\n\
+         NMI:          3          3          3          3   Non-maskable interrupts\n\
+         LOC:    9000000    8000000    8000000    8000000   Local timer interrupts\n\
+         ERR:          0\n";
+    const LATER: &str = "           CPU0       CPU1       CPU2       CPU3\n  \
+           0:         45          0          0          0   IO-APIC   2-edge      timer\n \
+          24:     140000          0          0          0  PCI-MSI 524288-edge      eth0-TxRx-0\n \
+          25:     110000          0          0          0  PCI-MSI 524289-edge      eth0-TxRx-1\n \
+          26:      80000          0          0          0  PCI-MSI 524290-edge      eth0-TxRx-2\n \
+          27:      50000          0          0          0  PCI-MSI 524291-edge      eth0-TxRx-3\n \
+          30:       1100       1200       1050       1010  PCI-MSI 376832-edge      nvme0q0\n\
+         NMI:          3          3          3          3   Non-maskable interrupts\n\
+         LOC:    9100000    8100000    8100000    8100000   Local timer interrupts\n\
+         ERR:          0\n";
+    const SKEWED: [f64; 4] = [0.9, 0.04, 0.05, 0.01];
+
+    fn delta() -> InterruptSnapshot {
+        InterruptSnapshot::parse(LATER).since(&InterruptSnapshot::parse(EARLIER))
+    }
+
+    fn per_cpu(fractions: &[f64]) -> PerCpuSignals {
+        PerCpuSignals {
+            softirq_fraction_by_cpu: fractions.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    fn eth0() -> IrqAdvisorConfig {
+        IrqAdvisorConfig {
+            irq_names: vec!["eth0".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn numbered_irqs_are_parsed_and_differenced() {
+        let later = InterruptSnapshot::parse(LATER);
+        assert_eq!(later.cpus, [0, 1, 2, 3]);
+        assert_eq!(later.irqs.len(), 6);
+        assert_eq!(later.irqs[1].name, "eth0-TxRx-0");
+        assert_eq!(delta().irqs[1].per_cpu, [40000, 0, 0, 0]);
+    }
+
+    #[test]
+    fn a_skewed_cpu_gets_its_queues_moved_least_loaded_first() {
+        let advice = advise(&eth0(), &per_cpu(&SKEWED), &delta(), &[]);
+        // 0.9 over a mean of 0.25
+        assert!((advice.imbalance - 3.6).abs() < 1e-9);
+        assert_eq!(advice.busiest_cpu, Some(0));
+        assert_eq!(advice.responsible, [24, 25, 26, 27]);
+        assert_eq!(advice.suppressed, None);
+        // Queue 0 fired most and stays
+        let moves: Vec<(u32, u32, &str)> = advice
+            .recommendations
+            .iter()
+            .map(|s| (s.irq, s.suggested_cpu, s.affinity_mask.as_str()))
+            .collect();
+        assert_eq!(
+            moves,
+            [
+                (25, 3, "00000008"),
+                (26, 1, "00000002"),
+                (27, 2, "00000004")
+            ]
+        );
+        assert_eq!(
+            advice.recommendations[0].command(),
+            "echo 00000008 > /proc/irq/25/smp_affinity"
+        );
+    }
+
+    #[test]
+    fn even_load_is_left_alone() {
+        let advice = advise(&eth0(), &per_cpu(&[0.3, 0.25, 0.28, 0.27]), &delta(), &[]);
+        assert_eq!(advice.suppressed, Some(IrqSuppression::Balanced));
+        assert!(advice.recommendations.is_empty());
+        assert!(advice.imbalance < 2.0);
+    }
+
+    #[test]
+    fn one_irq_carrying_the_load_has_nowhere_to_spread() {
+        // At a 40% share only queue 0 is responsible
+        let config = IrqAdvisorConfig {
+            min_irq_share: 0.4,
+            ..eth0()
+        };
+        let advice = advise(&config, &per_cpu(&SKEWED), &delta(), &[]);
+        assert_eq!(advice.suppressed, Some(IrqSuppression::SingleIrq));
+        assert_eq!(advice.responsible, [24]);
+        assert!(advice.recommendations.is_empty());
+    }
+
+    #[test]
+    fn isolcpus_holds_back_any_advice() {
+        let cmdline = "BOOT_IMAGE=/vmlinuz root=/dev/sda1 isolcpus=managed_irq,domain,2-3,6 quiet";
+        let isolated = isolated_cpus(cmdline);
+        assert_eq!(isolated, [2, 3, 6]);
+        assert!(isolated_cpus("root=/dev/sda1 quiet").is_empty());
+        let advice = advise(&eth0(), &per_cpu(&SKEWED), &delta(), &isolated);
+        assert_eq!(
+            advice.suppressed,
+            Some(IrqSuppression::Isolated(vec![2, 3, 6]))
+        );
+        assert!(advice.recommendations.is_empty());
+        assert_eq!(advice.responsible, [24, 25, 26, 27]);
+    }
+
+    #[test]
+    fn affinity_masks_are_comma_separated_32_bit_words() {
+        assert_eq!(affinity_mask(0, 4), "00000001");
+        assert_eq!(affinity_mask(40, 64), "00000100,00000000");
+        assert_eq!(affinity_mask(31, 33), "00000000,80000000");
+    }
+
+    #[test]
+    fn advice_over_sparse_ids_names_real_cpus() {
+        // CPUs 2, 3, 129 and 131 offline; the NIC's queues all on CPU 128
+        let interrupts = InterruptSnapshot::parse(
+            "           CPU0       CPU1       CPU128     CPU130\n \
+              40:          0          0      50000          0  PCI-MSI 524288-edge      eth0-rx-0\n \
+              41:          0          0      30000          0  PCI-MSI 524289-edge      eth0-rx-1\n \
+              42:          0          0      20000          0  PCI-MSI 524290-edge      eth0-rx-2\n",
+        );
+        assert_eq!(interrupts.cpus, [0, 1, 128, 130]);
+        let signals = PerCpuSignals {
+            cpus: vec![0, 1, 128, 130],
+            softirq_fraction_by_cpu: vec![0.05, 0.02, 0.9, 0.03],
+            ..Default::default()
+        };
+        let advice = advise(&IrqAdvisorConfig::default(), &signals, &interrupts, &[]);
+        assert_eq!(advice.busiest_cpu, Some(128));
+        assert_eq!(advice.responsible, [40, 41, 42]);
+        let moves: Vec<(u32, u32, &str)> = advice
+            .recommendations
+            .iter()
+            .map(|s| (s.irq, s.suggested_cpu, s.affinity_mask.as_str()))
+            .collect();
+        assert_eq!(
+            moves,
+            [
+                (41, 1, "00000000,00000000,00000000,00000000,00000002"),
+                (42, 130, "00000004,00000000,00000000,00000000,00000000")
+            ]
+        );
+    }
+}
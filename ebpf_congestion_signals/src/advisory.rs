@@ -0,0 +1,132 @@
+//Receive-side advice for QUIC endpoints. Pacing tells a sender how fast to go;
+//this tells an endpoint how much ACK and flow-control work to ask of this host
+//when NET_RX softirq time or receive buffers are the bottleneck.
+
+use crate::CongestionSignals;
+use std::time::Duration;
+
+/// RFC 9000's default max_ack_delay
+pub const DEFAULT_ACK_DELAY: Duration = Duration::from_millis(25);
+/// RFC 9000 recommends an ACK at least every 2 ack-eliciting packets
+pub const DEFAULT_MAX_ACK_BATCH: u32 = 2;
+
+// Upper bounds we'll ever suggest. Longer delays start to hurt the peer's loss
+// detection and RTT estimate more than they save us
+const MAX_ACK_DELAY: Duration = Duration::from_millis(100);
+const MAX_ACK_BATCH: u32 = 10;
+// Never suggest shrinking the advertised window below half
+const MIN_WINDOW_SCALE: f32 = 0.5;
+
+// Receive pressure (0.0-1.0) below which the defaults are returned unchanged
+const PRESSURE_RELAXED: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EndpointAdvisory {
+    /// max_ack_delay to advertise / request from the peer
+    pub suggested_ack_delay: Duration,
+    /// Ack-eliciting packets to receive before sending an ACK
+    pub suggested_max_ack_batch: u32,
+    /// Multiplier for the advertised stream/connection receive windows.
+    /// 1.0 leaves them alone, values below shrink them so peers can't overrun rcvbuf
+    pub receive_window_scale_hint: f32,
+}
+
+impl Default for EndpointAdvisory {
+    /// Conservative RFC 9000 behaviour, also what you get when no receive-side
+    /// signal was observed in the interval
+    fn default() -> Self {
+        Self {
+            suggested_ack_delay: DEFAULT_ACK_DELAY,
+            suggested_max_ack_batch: DEFAULT_MAX_ACK_BATCH,
+            receive_window_scale_hint: 1.0,
+        }
+    }
+}
+
+impl EndpointAdvisory {
+    /// Derive advice from one interval of signals.
+    ///
+    /// ACK thinning follows the larger of rcvbuf pressure and the busiest CPU's
+    /// softirq fraction, ramping linearly from the defaults at 50% up to the caps
+    /// at 100%. The window hint only reacts to the receive buffer itself: it
+    /// shrinks with rcvbuf pressure and goes straight to the floor once datagrams
+    /// are being dropped on a full rcvbuf.
+    pub fn from_signals(signals: &CongestionSignals) -> Self {
+        let mut advisory = Self::default();
+
+        let pressure = signals.avg_rmem_pressure.max(signals.softirq_cpu_fraction);
+        let ramp = ((pressure - PRESSURE_RELAXED) / (1.0 - PRESSURE_RELAXED)).clamp(0.0, 1.0);
+        if ramp > 0.0 {
+            let extra_delay = (MAX_ACK_DELAY - DEFAULT_ACK_DELAY).mul_f64(ramp);
+            advisory.suggested_ack_delay = DEFAULT_ACK_DELAY + extra_delay;
+            advisory.suggested_max_ack_batch = DEFAULT_MAX_ACK_BATCH
+                + ((MAX_ACK_BATCH - DEFAULT_MAX_ACK_BATCH) as f64 * ramp).round() as u32;
+        }
+
+        if signals.udp_rcv_drops > 0 {
+            advisory.receive_window_scale_hint = MIN_WINDOW_SCALE;
+        } else {
+            let rmem_ramp = ((signals.avg_rmem_pressure - PRESSURE_RELAXED)
+                / (1.0 - PRESSURE_RELAXED))
+                .clamp(0.0, 1.0);
+            advisory.receive_window_scale_hint = 1.0 - (1.0 - MIN_WINDOW_SCALE) * rmem_ramp as f32;
+        }
+
+        advisory
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn advise(rmem: f64, softirq: f64, drops: u64) -> EndpointAdvisory {
+        EndpointAdvisory::from_signals(
+            &CongestionSignals::builder()
+                .avg_rmem_pressure(rmem)
+                .softirq_cpu_fraction(softirq)
+                .udp_rcv_drops(drops)
+                .build(),
+        )
+    }
+
+    #[test]
+    fn relaxed_receive_side_keeps_the_defaults() {
+        assert_eq!(
+            EndpointAdvisory::from_signals(&CongestionSignals::idle()),
+            EndpointAdvisory::default()
+        );
+        assert_eq!(advise(0.5, 0.3, 0), EndpointAdvisory::default());
+    }
+
+    #[test]
+    fn ack_thinning_ramps_with_the_larger_pressure() {
+        let halfway = advise(0.2, 0.75, 0);
+        assert_eq!(
+            halfway.suggested_ack_delay,
+            DEFAULT_ACK_DELAY + (MAX_ACK_DELAY - DEFAULT_ACK_DELAY) / 2
+        );
+        assert_eq!(halfway.suggested_max_ack_batch, 6);
+        // Softirq time alone leaves the windows be
+        assert_eq!(halfway.receive_window_scale_hint, 1.0);
+        assert_eq!(
+            advise(0.75, 0.2, 0),
+            EndpointAdvisory {
+                receive_window_scale_hint: 0.75,
+                ..halfway
+            }
+        );
+
+        let saturated = advise(1.0, 1.0, 0);
+        assert_eq!(saturated.suggested_ack_delay, MAX_ACK_DELAY);
+        assert_eq!(saturated.suggested_max_ack_batch, MAX_ACK_BATCH);
+        assert_eq!(saturated.receive_window_scale_hint, MIN_WINDOW_SCALE);
+    }
+
+    #[test]
+    fn receive_drops_floor_the_window_hint() {
+        let advisory = advise(0.1, 0.1, 3);
+        assert_eq!(advisory.receive_window_scale_hint, MIN_WINDOW_SCALE);
+        assert_eq!(advisory.suggested_ack_delay, DEFAULT_ACK_DELAY);
+    }
+}
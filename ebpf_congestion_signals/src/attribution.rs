@@ -0,0 +1,290 @@
+//How much of the NET_TX softirq time our own sends caused. NET_TX runs the
+//qdisc and frees transmitted skbs for whoever sent them: the part our
+//registered sockets' traffic drove is the cost of sending and no reason to slow
+//down, the part other tenants' traffic drove is contention. The governor backs
+//off on the second only.
+//
+//It's a heuristic, and these are its assumptions:
+//- A NET_TX round works on what was sent shortly before it or during it: the
+//  sends in [start - `lookback`, exit] of the round, on any CPU, since TX
+//  completion needn't run where the send did
+//- Its time splits by bytes: our registered sockets' share of the sampled send
+//  bytes in that window is the share of the round that's ours
+//- A round no sampled send precedes is external. Sends are sampled 1 in 100
+//  (per socket under socket sampling), so a quiet window may still have had
+//  some of ours, and the confidence says how much of the split rests on sends
+//  actually seen
+//
+//The registered sockets' exact egress counts (REGISTERED_SOCKETS) carry no
+//time, so they can't place a send in a window; the sampled sends in the event
+//stream do. Windows span CPUs, so the events need timestamp order, which
+//`with_softirq_attribution` gets by turning on the reordering stage.
+//
+//NET_RX is left out: it processes what peers send here, which none of our
+//sends on this host accounts for, so all of it stays in the back-off signal.
+
+use crate::{CongestionEvent, EVENT_SOFTIRQ_EXIT, EVENT_TCP_SEND, EVENT_UDP_SEND};
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
+
+// vec_nr of NET_TX_SOFTIRQ
+const NET_TX_SOFTIRQ: u32 = 2;
+// Sends held for the windows of rounds still to come; past it the oldest goes
+const MAX_SENDS: usize = 4096;
+// Longest round a send is held for past `lookback`. Longer ones only look this
+// far back
+const MAX_ROUND_NS: u64 = 10_000_000;
+
+/// Turns on `CongestionSignals::softirq_attribution`, see
+/// `CollectorConfig::with_softirq_attribution`
+#[derive(Debug, Clone)]
+pub struct SoftirqAttributionConfig {
+    /// Sends this long before a NET_TX round started count toward it
+    pub lookback: Duration,
+    /// Sampled sends in the rounds' windows for full confidence
+    pub min_samples: u64,
+}
+
+impl Default for SoftirqAttributionConfig {
+    fn default() -> Self {
+        Self {
+            lookback: Duration::from_millis(3),
+            min_samples: 20,
+        }
+    }
+}
+
+/// One interval's NET_TX softirq time split between our sends and others'
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SoftirqAttribution {
+    /// NET_TX time attributed to registered sockets' sends
+    pub self_induced_softirq_ns: u64,
+    /// The rest, other sockets' sends and rounds no send preceded
+    pub external_softirq_ns: u64,
+    /// NET_TX rounds split, and of those the ones a sampled send preceded
+    pub rounds: u64,
+    pub covered_rounds: u64,
+    /// 0.0-1.0: the share of NET_TX time in rounds a sampled send preceded,
+    /// scaled down while fewer than `min_samples` sends were in their windows
+    pub confidence: f64,
+}
+
+impl SoftirqAttribution {
+    /// Share of the NET_TX time that was ours. None without any
+    pub fn self_induced_fraction(&self) -> Option<f64> {
+        let total = self.self_induced_softirq_ns + self.external_softirq_ns;
+        (total > 0).then(|| self.self_induced_softirq_ns as f64 / total as f64)
+    }
+}
+
+struct Send {
+    at_ns: u64,
+    bytes: u64,
+    ours: bool,
+}
+
+/// The split on its own, e.g. over a recording's events. Feed it events in
+/// timestamp order with `record` and take the totals with `take_interval`
+pub struct SoftirqAttributionTracker {
+    lookback_ns: u64,
+    min_samples: u64,
+    registered: HashSet<u64>,
+    sends: VecDeque<Send>,
+    self_ns: f64,
+    total_ns: u64,
+    covered_ns: u64,
+    rounds: u64,
+    covered_rounds: u64,
+    window_samples: u64,
+}
+
+impl SoftirqAttributionTracker {
+    pub fn new(config: &SoftirqAttributionConfig) -> Self {
+        Self {
+            lookback_ns: config.lookback.as_nanos() as u64,
+            min_samples: config.min_samples,
+            registered: HashSet::new(),
+            sends: VecDeque::new(),
+            self_ns: 0.0,
+            total_ns: 0,
+            covered_ns: 0,
+            rounds: 0,
+            covered_rounds: 0,
+            window_samples: 0,
+        }
+    }
+
+    /// Count this socket's sends as ours from now on
+    pub fn register(&mut self, socket_id: u64) {
+        self.registered.insert(socket_id);
+    }
+
+    pub fn unregister(&mut self, socket_id: u64) {
+        self.registered.remove(&socket_id);
+    }
+
+    pub fn record(&mut self, event: &CongestionEvent) {
+        match event.event_type {
+            EVENT_UDP_SEND | EVENT_TCP_SEND => {
+                let send = unsafe { event.data.sendmsg };
+                if self.sends.len() == MAX_SENDS {
+                    self.sends.pop_front();
+                }
+                self.sends.push_back(Send {
+                    at_ns: event.timestamp_ns,
+                    bytes: send.bytes,
+                    ours: self.registered.contains(&send.socket_id),
+                });
+            }
+            EVENT_SOFTIRQ_EXIT => {
+                let softirq = unsafe { event.data.softirq };
+                if softirq.vec_nr == NET_TX_SOFTIRQ {
+                    self.round(event.timestamp_ns, softirq.duration_ns);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // A NET_TX round exiting at `exit_ns`: split it by the sends in its window
+    fn round(&mut self, exit_ns: u64, duration_ns: u64) {
+        let from_ns = exit_ns
+            .saturating_sub(duration_ns.min(MAX_ROUND_NS))
+            .saturating_sub(self.lookback_ns);
+        let horizon_ns = exit_ns.saturating_sub(MAX_ROUND_NS + self.lookback_ns);
+        let stale = self
+            .sends
+            .iter()
+            .take_while(|send| send.at_ns < horizon_ns)
+            .count();
+        self.sends.drain(..stale);
+        let (mut ours, mut all, mut samples) = (0u64, 0u64, 0u64);
+        for send in &self.sends {
+            if send.at_ns >= from_ns && send.at_ns <= exit_ns {
+                all += send.bytes;
+                samples += 1;
+                if send.ours {
+                    ours += send.bytes;
+                }
+            }
+        }
+        self.rounds += 1;
+        self.total_ns += duration_ns;
+        if samples > 0 {
+            self.covered_rounds += 1;
+            self.covered_ns += duration_ns;
+            self.window_samples += samples;
+            if all > 0 {
+                self.self_ns += duration_ns as f64 * ours as f64 / all as f64;
+            }
+        }
+    }
+
+    /// Sends held for rounds still to come
+    pub fn pending(&self) -> usize {
+        self.sends.len()
+    }
+
+    /// What was split since the last call
+    pub fn take_interval(&mut self) -> SoftirqAttribution {
+        let self_induced = (self.self_ns.round() as u64).min(self.total_ns);
+        let covered = if self.total_ns > 0 {
+            self.covered_ns as f64 / self.total_ns as f64
+        } else {
+            0.0
+        };
+        let sampled = if self.min_samples > 0 {
+            (self.window_samples as f64 / self.min_samples as f64).min(1.0)
+        } else {
+            1.0
+        };
+        let attribution = SoftirqAttribution {
+            self_induced_softirq_ns: self_induced,
+            external_softirq_ns: self.total_ns - self_induced,
+            rounds: self.rounds,
+            covered_rounds: self.covered_rounds,
+            confidence: covered * sampled,
+        };
+        self.self_ns = 0.0;
+        self.total_ns = 0;
+        self.covered_ns = 0;
+        self.rounds = 0;
+        self.covered_rounds = 0;
+        self.window_samples = 0;
+        attribution
+    }
+
+    /// Drop what's held, e.g. after the kernel clock jumped
+    pub fn reset(&mut self) {
+        self.sends.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    const NET_RX_SOFTIRQ: u32 = 3;
+    // A registered socket and one that isn't
+    const OURS: u64 = 7;
+    const THEIRS: u64 = 9;
+
+    /// 40 NET_TX rounds of 100 µs on a constructed timeline, each after four
+    /// sampled sends on another CPU by `sender`'s socket, or none
+    fn rounds(sender: impl Fn(u64) -> Option<u64>) -> SoftirqAttribution {
+        let mut tracker = SoftirqAttributionTracker::new(&SoftirqAttributionConfig::default());
+        tracker.register(OURS);
+        for round in 0..40u64 {
+            let start_ns = (round + 1) * 10_000_000;
+            let cpu = (round % 2) as u32;
+            if let Some(socket_id) = sender(round) {
+                for send in 0..4 {
+                    let at_ns = start_ns - 1_000_000 + send * 100_000;
+                    tracker.record(&fixtures::udp_send(at_ns, cpu, socket_id, 1200));
+                }
+            }
+            tracker.record(&fixtures::softirq_exit(
+                start_ns + 100_000,
+                cpu + 2,
+                NET_TX_SOFTIRQ,
+                100_000,
+            ));
+            // NET_RX isn't split
+            tracker.record(&fixtures::softirq_exit(
+                start_ns + 200_000,
+                0,
+                NET_RX_SOFTIRQ,
+                50_000,
+            ));
+        }
+        tracker.take_interval()
+    }
+
+    #[test]
+    fn net_tx_time_splits_by_whose_sends_came_before() {
+        let all = rounds(|_| Some(OURS));
+        let none = rounds(|_| Some(THEIRS));
+        let half = rounds(|round| Some(if round % 2 == 0 { OURS } else { THEIRS }));
+        assert!(all.self_induced_fraction().unwrap() >= 0.9);
+        assert!(none.self_induced_fraction().unwrap() <= 0.1);
+        assert!((0.4..=0.6).contains(&half.self_induced_fraction().unwrap()));
+        for split in [all, none, half] {
+            assert!(split.confidence >= 0.9, "{split:?}");
+            assert_eq!(split.rounds, 40);
+            assert_eq!(
+                split.self_induced_softirq_ns + split.external_softirq_ns,
+                4_000_000
+            );
+        }
+    }
+
+    #[test]
+    fn rounds_no_send_preceded_count_external() {
+        let quiet = rounds(|_| None);
+        assert_eq!(quiet.self_induced_softirq_ns, 0);
+        assert_eq!(quiet.external_softirq_ns, 4_000_000);
+        assert_eq!(quiet.covered_rounds, 0);
+        assert_eq!(quiet.confidence, 0.0);
+    }
+}
@@ -0,0 +1,68 @@
+//Entry points for the criterion benches in benches/, not part of the API. They
+//run the readers' hot path over events already in memory: copied into a batch
+//buffer the way a ring read does, walked, aggregated and folded.
+
+use crate::collector::{AtomicSignals, EventBatch};
+use crate::perf_ring::SampleBatch;
+use crate::reader;
+use crate::{CollectorConfig, CongestionEvent, CpuSlots};
+use std::mem::size_of;
+
+fn bytes(event: &CongestionEvent) -> &[u8] {
+    // SAFETY: reading the event's own bytes
+    unsafe {
+        std::slice::from_raw_parts(
+            event as *const CongestionEvent as *const u8,
+            size_of::<CongestionEvent>(),
+        )
+    }
+}
+
+fn decode(sample: &[u8]) -> CongestionEvent {
+    assert!(sample.len() >= size_of::<CongestionEvent>());
+    // SAFETY: long enough, and copied unaligned as the readers do
+    unsafe { std::ptr::read_unaligned(sample.as_ptr() as *const CongestionEvent) }
+}
+
+pub struct HotPath {
+    signals: AtomicSignals,
+    samples: SampleBatch,
+}
+
+impl HotPath {
+    /// Counting into a collector's signals on CPUs 0..cpus, `batch_size`
+    /// samples at a time
+    pub fn new(cpus: u32, batch_size: usize) -> Self {
+        Self {
+            signals: AtomicSignals::new(CpuSlots::new(0..cpus), &CollectorConfig::default()),
+            samples: SampleBatch::new(batch_size, size_of::<CongestionEvent>()),
+        }
+    }
+
+    /// A wakeup's worth of `events` the way the readers take it: one buffer,
+    /// one cursor walk, one fold
+    pub fn walk(&mut self, events: &[CongestionEvent]) {
+        self.samples.clear();
+        for event in events {
+            let sample = bytes(event);
+            self.samples
+                .push_with(sample.len(), |out| out.copy_from_slice(sample));
+        }
+        let mut batch = EventBatch::new(&self.signals);
+        for sample in self.samples.iter() {
+            reader::aggregate(&decode(sample), &mut batch, &self.signals);
+        }
+        self.signals.fold(&mut batch);
+    }
+
+    /// The same with a buffer per sample and a fold per event, what the
+    /// readers did before
+    pub fn per_event(&mut self, events: &[CongestionEvent]) {
+        let buffers: Vec<Vec<u8>> = events.iter().map(|event| bytes(event).to_vec()).collect();
+        for sample in &buffers {
+            let mut batch = EventBatch::new(&self.signals);
+            reader::aggregate(&decode(sample), &mut batch, &self.signals);
+            self.signals.fold(&mut batch);
+        }
+    }
+}
@@ -1,5 +1,5 @@
-/// Diagnostic tool to test eBPF probe attachment
-/// This bypasses Aya and uses bpftool to verify kernel support
+//! Diagnostic tool to test eBPF probe attachment
+//! This bypasses Aya and uses bpftool to verify kernel support
 
 use std::process::Command;
 use std::fs;
@@ -53,7 +53,12 @@ fn main() {
     let funcs = fs::read_to_string("/sys/kernel/debug/tracing/available_filter_functions")
         .unwrap_or_default();
     
-    for func in &["udp_sendmsg", "tcp_sendmsg", "tcp_write_xmit"] {
+    for func in &[
+        "udp_sendmsg",
+        "__udp_enqueue_schedule_skb",
+        "tcp_sendmsg",
+        "tcp_write_xmit",
+    ] {
         if funcs.contains(func) {
             println!("    {} is available", func);
         } else {
@@ -125,7 +130,7 @@ fn main() {
     
     // 8. Check currently loaded BPF programs
     println!("\n8. Checking loaded BPF programs...");
-    match Command::new("bpftool").args(&["prog", "list"]).output() {
+    match Command::new("bpftool").args(["prog", "list"]).output() {
         Ok(output) => {
             let progs = String::from_utf8_lossy(&output.stdout);
             if progs.is_empty() {
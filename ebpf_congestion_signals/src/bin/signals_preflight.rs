@@ -0,0 +1,34 @@
+//! Would the collector run on this host? `signals-preflight [--strict]
+//! [--egress <interface>]...` loads and verifies the probes without attaching
+//! any, prints a JSON report and exits 1 if the collector couldn't load with
+//! this profile (with `--strict`, also if it would run with signals left out).
+//! Run it with the collector's privileges.
+
+use ebpf_congestion_signals::{CollectorConfig, CongestionCollector};
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let usage = "usage: signals-preflight [--strict] [--egress <interface>]...";
+    let mut strict = false;
+    let mut egress = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--strict" => strict = true,
+            "--egress" => egress.push(args.next().ok_or_else(|| anyhow::anyhow!(usage))?),
+            _ => anyhow::bail!(usage),
+        }
+    }
+
+    let config = CollectorConfig {
+        egress_interfaces: egress,
+        ..Default::default()
+    };
+    let report = CongestionCollector::preflight_with_config(&config);
+    println!("{}", report.to_json());
+    if !report.can_run || (strict && report.degraded()) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
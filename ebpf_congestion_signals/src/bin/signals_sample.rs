@@ -0,0 +1,22 @@
+//! One interval of congestion signals as JSON, for runbooks:
+//! `signals-sample [seconds]` loads the probes, collects for that long
+//! (default 5), prints one JSON object and unloads. Needs root.
+
+use ebpf_congestion_signals::CongestionCollector;
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let seconds: u64 = match std::env::args().nth(1) {
+        Some(arg) => arg
+            .parse()
+            .map_err(|_| anyhow::anyhow!("usage: signals-sample [seconds]"))?,
+        None => 5,
+    };
+
+    let signals = CongestionCollector::sample(Duration::from_secs(seconds)).await?;
+    println!("{}", signals.to_json());
+    Ok(())
+}
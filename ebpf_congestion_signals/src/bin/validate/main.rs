@@ -0,0 +1,882 @@
+mod scenarios;
+
+use ebpf_congestion_signals::{
+    AccuracyReport, BpfStats, CoAttachmentScan, CollectorConfig, ComparisonBound,
+    ConfidenceInterval, ConflictCheck, CongestionCollector, CongestionSignals, CpuSample, CpuTimes,
+    IrqAdvisor, IrqAdvisorConfig, IrqSuppression, MemoryReport, OverheadBudget, OverheadReport,
+    ProcessUsage, ReaderStats, ReaderWakeup, Redaction, SampleStats, SamplerComparison,
+    SamplerComparisonReport, SignalAccuracy, SoakBounds, SoakReport, SoakSample, SoakTracker,
+    TalkerRanking, TopTalker, WakeupWatermark,
+};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+// Interval reads before --dump-diagnostics writes the bundle
+const DUMP_INTERVALS: usize = 3;
+
+// One-second CPU samples before the load starts
+const BASELINE_SAMPLES: usize = 10;
+// Percent of all CPUs the collector may cost
+const OVERHEAD_LIMIT: f64 = 2.0;
+// How long --reader-wakeup watermark:N waits for a quiet CPU
+const WATERMARK_MAX_DELAY: Duration = Duration::from_millis(10);
+// How long --advise watches softirq load by default
+const ADVISE_SECONDS: u64 = 10;
+// --soak samples this often unless --sample-every says otherwise
+const SOAK_SAMPLE_SECS: u64 = 3600;
+
+fn export_schema() -> anyhow::Result<()> {
+    use ebpf_congestion_signals::export_model::export_json_schema;
+    print!("{}", export_json_schema());
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let args: Vec<String> = std::env::args().collect();
+    // What `cargo xtask schema` writes to schema/export.schema.json
+    if args.iter().any(|a| a == "--export-schema") {
+        return export_schema();
+    }
+    if let Some(dir) = args
+        .iter()
+        .position(|a| a == "--dump-diagnostics")
+        .and_then(|i| args.get(i + 1))
+    {
+        let mut collector = CongestionCollector::load()?;
+        collector.start_collection().await?;
+        // A few intervals for the history, and events for the recent ring
+        for _ in 0..DUMP_INTERVALS {
+            sleep(Duration::from_secs(1)).await;
+            collector.read_and_reset();
+        }
+        let redaction = if args.iter().any(|a| a == "--no-redact") {
+            Redaction::none()
+        } else {
+            Redaction::hashed()
+        };
+        let files = collector.dump_diagnostics_redacted(Path::new(dir), &redaction)?;
+        println!("Diagnostic bundle written to {}:", dir);
+        for file in files {
+            println!("  {}", file.display());
+        }
+        return Ok(());
+    }
+
+    if args.iter().any(|a| a == "--scenarios") {
+        let window = args
+            .iter()
+            .position(|a| a == "--window")
+            .and_then(|i| args.get(i + 1))
+            .map(|w| w.parse())
+            .transpose()?
+            .unwrap_or(10);
+        let flag = |name: &str| {
+            args.iter()
+                .position(|a| a == name)
+                .and_then(|i| args.get(i + 1))
+        };
+        let defaults = scenarios::ReactionBound::default();
+        let reaction = scenarios::ReactionBound {
+            max_latency: flag("--max-reaction")
+                .map(|secs| secs.parse().map(Duration::from_secs_f64))
+                .transpose()?
+                .unwrap_or(defaults.max_latency),
+            factor: flag("--reaction-factor")
+                .map(|f| f.parse())
+                .transpose()?
+                .unwrap_or(defaults.factor),
+        };
+
+        println!("=== eBPF Congestion Signals Scenario Matrix ===\n");
+        // The checks load collectors of their own next to this one
+        let config = CollectorConfig::default().with_conflict_check(ConflictCheck::Off);
+        let mut collector = CongestionCollector::load_with_config(config)?;
+        collector.start_collection().await?;
+        println!("✓ Probes loaded successfully\n");
+
+        let passed =
+            scenarios::run_matrix(&collector, Duration::from_secs(window), &reaction).await?;
+        print_memory(&collector.memory_report());
+        print_accuracy(collector.accuracy_since_start());
+        if !passed {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(ratio) = args
+        .iter()
+        .position(|a| a == "--compare-sampling")
+        .and_then(|i| args.get(i + 1))
+    {
+        let flag = |name: &str| {
+            args.iter()
+                .position(|a| a == name)
+                .and_then(|i| args.get(i + 1))
+        };
+        let bound = ComparisonBound {
+            max_error: flag("--max-sampling-error")
+                .map(|e| e.parse())
+                .transpose()?
+                .unwrap_or(ComparisonBound::default().max_error),
+            ..Default::default()
+        };
+        let seconds = flag("--duration")
+            .map(|d| d.parse())
+            .transpose()?
+            .unwrap_or(60);
+        let report = compare_sampling(ratio.parse()?, bound, seconds).await?;
+        if !report.within_bound {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if args.iter().any(|a| a == "--advise") {
+        let flag = |name: &str| {
+            args.iter()
+                .position(|a| a == name)
+                .and_then(|i| args.get(i + 1))
+        };
+        let seconds = flag("--duration")
+            .map(|d| d.parse())
+            .transpose()?
+            .unwrap_or(ADVISE_SECONDS);
+        let config = IrqAdvisorConfig {
+            irq_names: flag("--irq-name").into_iter().cloned().collect(),
+            ..Default::default()
+        };
+        advise_irqs(config, seconds).await?;
+        return Ok(());
+    }
+
+    if let Some(hours) = args
+        .iter()
+        .position(|a| a == "--soak")
+        .and_then(|i| args.get(i + 1))
+    {
+        let flag = |name: &str| {
+            args.iter()
+                .position(|a| a == name)
+                .and_then(|i| args.get(i + 1))
+        };
+        let defaults = SoakBounds::default();
+        let sample_every = flag("--sample-every")
+            .map(|secs| secs.parse().map(Duration::from_secs))
+            .transpose()?
+            .unwrap_or(Duration::from_secs(SOAK_SAMPLE_SECS));
+        let bounds = SoakBounds {
+            // Never more than the first sample's worth
+            warmup: defaults.warmup.min(sample_every),
+            max_rss_growth_per_hour: flag("--max-rss-growth")
+                .map(|mb| mb.parse::<u64>().map(|mb| mb << 20))
+                .transpose()?
+                .unwrap_or(defaults.max_rss_growth_per_hour),
+            max_accuracy_drift: flag("--max-accuracy-drift")
+                .map(|d| d.parse())
+                .transpose()?
+                .unwrap_or(defaults.max_accuracy_drift),
+            ..defaults
+        };
+        let checkpoint = flag("--checkpoint").map_or("soak-checkpoint.json", |p| p.as_str());
+        let duration = Duration::from_secs_f64(hours.parse::<f64>()? * 3600.0);
+        let report = soak(duration, sample_every, bounds, Path::new(checkpoint)).await?;
+        if !report.passed() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    println!("=== eBPF Congestion Signals Validation ===\n");
+    println!("This test validates:");
+    println!("1. eBPF probes load and attach successfully");
+    println!("2. Events are collected from all probes");
+    println!("3. CPU overhead is <2% during iperf3 test (95% CI lower bound)\n");
+
+    // Run again with e.g. --reader-wakeup timer:10 to compare the overhead
+    let wakeup = args
+        .iter()
+        .position(|a| a == "--reader-wakeup")
+        .and_then(|i| args.get(i + 1))
+        .map(|mode| reader_wakeup(mode))
+        .transpose()?
+        .unwrap_or_default();
+
+    // --overhead-budget 1.0 degrades collection above 1% CPU, see the log
+    let budget = args
+        .iter()
+        .position(|a| a == "--overhead-budget")
+        .and_then(|i| args.get(i + 1))
+        .map(|percent| percent.parse())
+        .transpose()?
+        .map(|budget_percent| OverheadBudget {
+            budget_percent,
+            ..Default::default()
+        });
+
+    // --top 5 lists each interval's heaviest sockets, by --top-by send (the
+    // default), wmem, stalls or retransmits
+    let top: Option<usize> = args
+        .iter()
+        .position(|a| a == "--top")
+        .and_then(|i| args.get(i + 1))
+        .map(|n| n.parse())
+        .transpose()?;
+    let ranking = args
+        .iter()
+        .position(|a| a == "--top-by")
+        .and_then(|i| args.get(i + 1))
+        .map(|name| talker_ranking(name))
+        .transpose()?
+        .unwrap_or_default();
+
+    // Load eBPF probes
+    println!("Loading eBPF probes (readers wake {:?})...", wakeup);
+    let mut config = CollectorConfig::default().with_reader_wakeup(wakeup);
+    config.talker_ranking = ranking;
+    if let Some(budget) = budget {
+        config = config.with_overhead_budget(budget);
+    }
+    let mut collector = CongestionCollector::load_with_config(config)?;
+    collector.start_collection().await?;
+    println!("✓ Probes loaded successfully\n");
+    print_co_attachments(&collector.scan_co_attachments());
+
+    // Kept for the whole run, so the programs' run time is accounted
+    let _bpf_stats = match BpfStats::enable() {
+        Ok(stats) => Some(stats),
+        Err(e) => {
+            println!("BPF run-time stats unavailable ({e}), collector cost from /proc only");
+            None
+        }
+    };
+
+    // Baseline CPU measurement
+    println!(
+        "Measuring baseline CPU usage ({} x 1s samples)...",
+        BASELINE_SAMPLES
+    );
+    let mut cpu = CpuMeter::start(&collector)?;
+    let mut baseline = Vec::with_capacity(BASELINE_SAMPLES);
+    while baseline.len() < BASELINE_SAMPLES {
+        sleep(Duration::from_secs(1)).await;
+        baseline.extend(cpu.sample(&collector)?);
+    }
+    let baseline_system: Vec<f64> = baseline.iter().map(|s| s.system).collect();
+    if let Some(stats) = SampleStats::of(&baseline_system) {
+        println!(
+            "Baseline CPU: {:.2}% ± {:.2} (sample std dev)\n",
+            stats.mean, stats.std_dev
+        );
+    }
+    let mut loaded = Vec::new();
+
+    // Start monitoring
+    println!("Starting signal collection...");
+    println!("Run iperf3 test in another terminal:");
+    println!("  Server: iperf3 -s");
+    println!("  Client: iperf3 -c <server_ip> -t 30 -P 4");
+    println!("\nPress Ctrl+C when test completes\n");
+
+    let start = Instant::now();
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    let mut total_signals = CongestionSignals::default();
+    let mut failures = collector.failures();
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = tokio::signal::ctrl_c() => break,
+        }
+        loaded.extend(cpu.sample(&collector)?);
+        if let Some(action) = collector.enforce_overhead_budget() {
+            println!(
+                "  → Overhead budget: {:.2}% CPU, level {} -> {}",
+                action.percent, action.from, action.to
+            );
+        }
+        while let Ok(failure) = failures.try_recv() {
+            let restarted = if failure.restarted { ", restarted" } else { "" };
+            println!(
+                "  FAILURE: {}: {}{}",
+                failure.component, failure.error, restarted
+            );
+        }
+        
+        let signals = collector.read_and_reset();
+        
+        // Accumulate totals
+        total_signals.estimated.send_bytes += signals.estimated.send_bytes;
+        total_signals.drops += signals.drops;
+        total_signals.softirq_ns += signals.softirq_ns;
+        total_signals.observed.events_received += signals.observed.events_received;
+        total_signals.queue_depth_packets += signals.queue_depth_packets;
+        total_signals.queue_depth_bytes += signals.queue_depth_bytes;
+        total_signals.udp_rcv_drops += signals.udp_rcv_drops;
+        total_signals.new_connections_per_interval += signals.new_connections_per_interval;
+        total_signals.closed_connections_per_interval += signals.closed_connections_per_interval;
+        if let Some(squeeze) = signals.rx_time_squeeze {
+            *total_signals.rx_time_squeeze.get_or_insert(0) += squeeze;
+        }
+        if let Some(throttles) = signals.tsq_throttles {
+            *total_signals.tsq_throttles.get_or_insert(0) += throttles;
+        }
+        if let Some(stalls) = signals.txq_stalls {
+            *total_signals.txq_stalls.get_or_insert(0) += stalls;
+        }
+        if let Some(rtos) = signals.rto_events {
+            *total_signals.rto_events.get_or_insert(0) += rtos;
+        }
+        if let Some(episodes) = signals.loss_recovery_episodes {
+            *total_signals.loss_recovery_episodes.get_or_insert(0) += episodes;
+        }
+
+        // Print interval stats with NEW queue metrics
+        println!(
+            "[{:>3}s] Events: {:>6} | Send: ~{:>8} MB (x{:.0}) | Drops: {:>4} | Queue: {:>4}pkts/{:>6}KB | Softirq: {:>6} µs ({:>4.1}% max CPU) | Rcv drops: {:>4} | Rmem: {:>5.1}% | {:?}",
+            start.elapsed().as_secs(),
+            signals.observed.events_received,
+            signals.estimated.send_bytes / 1_000_000,
+            signals.estimated.scale.send_factor,
+            signals.drops,
+            signals.queue_depth_packets,
+            signals.queue_depth_bytes / 1024,
+            signals.softirq_ns / 1000,
+            signals.softirq_cpu_fraction * 100.0,
+            signals.udp_rcv_drops,
+            signals.avg_rmem_pressure * 100.0,
+            signals.limitation,
+        );
+        if let Some(n) = top {
+            print_top_talkers(&collector.top_talkers(n), ranking);
+        }
+
+        // Every 10 seconds, report CPU overhead over the samples so far
+        if start.elapsed().as_secs().is_multiple_of(10) && start.elapsed().as_secs() > 0 {
+            print_overhead(&OverheadReport::compare(&baseline, &loaded));
+
+            if collector.verify_attachments().await? {
+                println!("  → Probes or readers had stopped, eBPF object reloaded");
+            }
+            for warning in collector.health().warnings {
+                println!("  WARNING: {}", warning);
+            }
+
+            println!(
+                "  → Totals: {} events | ~{} MB sent | {} drops | {} rcv drops | {} ms softirq | {} rx squeezes | {} TSQ throttles | {} TX queue stalls | {} TCP RTOs | {} TCP recoveries | {} connections opened, {} closed",
+                total_signals.observed.events_received,
+                total_signals.estimated.send_bytes / 1_000_000,
+                total_signals.drops,
+                total_signals.udp_rcv_drops,
+                total_signals.softirq_ns / 1_000_000,
+                total_signals
+                    .rx_time_squeeze
+                    .map_or("n/a".to_string(), |squeeze| squeeze.to_string()),
+                total_signals
+                    .tsq_throttles
+                    .map_or("n/a".to_string(), |throttles| throttles.to_string()),
+                total_signals
+                    .txq_stalls
+                    .map_or("n/a".to_string(), |stalls| stalls.to_string()),
+                total_signals
+                    .rto_events
+                    .map_or("n/a".to_string(), |rtos| rtos.to_string()),
+                total_signals
+                    .loss_recovery_episodes
+                    .map_or("n/a".to_string(), |episodes| episodes.to_string()),
+                total_signals.new_connections_per_interval,
+                total_signals.closed_connections_per_interval,
+            );
+
+            let mut by_reason: Vec<_> = collector.drops_by_reason().into_iter().collect();
+            by_reason.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+            if !by_reason.is_empty() {
+                let top: Vec<String> = by_reason
+                    .iter()
+                    .take(5)
+                    .map(|(reason, count)| format!("{} {}", reason, count))
+                    .collect();
+                println!("  → Drop reasons: {}", top.join(" | "));
+            }
+
+            let stats = collector.reader_stats();
+            println!(
+                "  → Readers: {} read | {} perf lost | queue {}/{} | {} queue drops | {} clock jumps",
+                stats.events_read,
+                stats.perf_lost,
+                stats.queue_depth,
+                stats.queue_capacity,
+                stats.queue_dropped,
+                stats.clock_jumps,
+            );
+            print_wakeups(&stats);
+            print_memory(&collector.memory_report());
+            print_accuracy(collector.accuracy_since_start());
+        }
+    }
+
+    println!("\n=== Final report ({}s) ===", start.elapsed().as_secs());
+    let overhead = OverheadReport::compare(&baseline, &loaded);
+    print_overhead(&overhead);
+    println!("  → Readers woke {:?}:", wakeup);
+    print_wakeups(&collector.reader_stats());
+    print_accuracy(collector.accuracy_since_start());
+    if overhead.exceeds(OVERHEAD_LIMIT) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Run the 1-in-`ratio` sampler next to the primary one for `seconds` of
+/// one-second intervals, under whatever load the host has, and report
+async fn compare_sampling(
+    ratio: u64,
+    bound: ComparisonBound,
+    seconds: u64,
+) -> anyhow::Result<SamplerComparisonReport> {
+    println!("=== eBPF Congestion Signals Sampler Comparison ===\n");
+    let config = CollectorConfig::default().with_sampler_comparison(ratio);
+    let mut collector = CongestionCollector::load_with_config(config)?;
+    collector.start_collection().await?;
+    println!(
+        "Comparing 1-in-{} against 1-in-100 for {}s (run iperf3 or your workload now)...\n",
+        ratio, seconds
+    );
+
+    let mut comparison = SamplerComparison::new(bound);
+    collector.read_and_reset();
+    for second in 1..=seconds {
+        sleep(Duration::from_secs(1)).await;
+        let signals = collector.read_and_reset();
+        comparison.observe(&signals);
+        if let Some(estimates) = &signals.sampler_comparison {
+            if second % 10 == 0 {
+                println!(
+                    "[{:3}s] primary {} KB, candidate {} KB ({} samples), error {}",
+                    second,
+                    estimates.primary_send_bytes / 1024,
+                    estimates.candidate_send_bytes / 1024,
+                    estimates.candidate_samples,
+                    estimates
+                        .relative_error()
+                        .map_or("n/a".to_string(), |e| format!("{:.1}%", e * 100.0))
+                );
+            }
+        }
+    }
+
+    let report = comparison.report();
+    let percent = |e: Option<f64>| e.map_or("n/a".to_string(), |e| format!("{:.1}%", e * 100.0));
+    println!("\n=== Sampler comparison ===");
+    println!(
+        "  → {} intervals compared, {} too thin (under {} bytes)",
+        report.compared, report.skipped, bound.min_send_bytes
+    );
+    println!(
+        "  → Relative error: mean {}, p{:.0} {}, max {}, whole run {}",
+        percent(report.mean_error),
+        bound.percentile * 100.0,
+        percent(report.percentile_error),
+        percent(report.max_error),
+        percent(report.run_error)
+    );
+    println!(
+        "  {} {}",
+        if report.within_bound { "✓" } else { "✗" },
+        report
+    );
+    Ok(report)
+}
+
+/// Collect for `duration`, or until SIGINT/SIGTERM, sampling every
+/// `sample_every` and writing the report to `checkpoint` after each sample
+async fn soak(
+    duration: Duration,
+    sample_every: Duration,
+    bounds: SoakBounds,
+    checkpoint: &Path,
+) -> anyhow::Result<SoakReport> {
+    use tokio::signal::unix::{signal, SignalKind};
+    println!("=== eBPF Congestion Signals Soak ===\n");
+    let mut collector = CongestionCollector::load()?;
+    collector.start_collection().await?;
+    println!(
+        "Soaking for {:.1}h, a sample every {:?}, checkpoints in {}\n",
+        duration.as_secs_f64() / 3600.0,
+        sample_every,
+        checkpoint.display()
+    );
+
+    let mut tracker = SoakTracker::new(bounds);
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    let start = Instant::now();
+    let mut next_sample = Duration::ZERO;
+    loop {
+        let stopped = tokio::select! {
+            _ = interval.tick() => false,
+            _ = tokio::signal::ctrl_c() => true,
+            _ = terminate.recv() => true,
+        };
+        // Keeps the intervals, and the cumulative totals, moving
+        collector.read_and_reset();
+        let elapsed = start.elapsed();
+        let done = stopped || elapsed >= duration;
+        if elapsed < next_sample && !done {
+            continue;
+        }
+        let sample = soak_sample(&collector, elapsed)?;
+        println!(
+            "[{:6.2}h] rss {} KB, {} fds, memory {} KB",
+            elapsed.as_secs_f64() / 3600.0,
+            sample.rss_bytes / 1024,
+            sample.open_fds,
+            sample.memory_bytes / 1024
+        );
+        for regression in tracker.record(sample) {
+            println!(
+                "  ✗ {} went down from {} to {}",
+                regression.counter, regression.from, regression.to
+            );
+        }
+        // Beside it and renamed over it, so a crash mid-write leaves the last one
+        let report = tracker.report();
+        let written = checkpoint.with_extension("tmp");
+        std::fs::write(&written, report.to_json())?;
+        std::fs::rename(&written, checkpoint)?;
+        next_sample += sample_every;
+        if done {
+            print_soak(&report);
+            return Ok(report);
+        }
+    }
+}
+
+fn soak_sample(collector: &CongestionCollector, elapsed: Duration) -> anyhow::Result<SoakSample> {
+    let usage = ProcessUsage::read()?;
+    let memory = collector.memory_report();
+    let totals = collector.cumulative();
+    let counters = [
+        ("intervals", totals.intervals),
+        ("send_bytes", totals.send_bytes),
+        ("external_send_bytes", totals.external_send_bytes),
+        ("drops", totals.drops),
+        ("udp_rcv_drops", totals.udp_rcv_drops),
+        ("event_count", totals.event_count),
+        ("softirq_ns", totals.softirq_ns),
+        ("evictions", memory.evictions()),
+    ];
+    Ok(SoakSample {
+        elapsed,
+        rss_bytes: usage.rss_bytes,
+        open_fds: usage.open_fds,
+        memory_bytes: memory.total_bytes(),
+        tables: memory
+            .structures
+            .iter()
+            .map(|s| (s.name.to_string(), s.entries as u64))
+            .collect(),
+        counters: counters
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value))
+            .collect(),
+        accuracy_error: collector.accuracy_since_start().and_then(|report| {
+            report
+                .signals
+                .iter()
+                .filter_map(SignalAccuracy::relative_error)
+                .reduce(f64::max)
+        }),
+    })
+}
+
+fn print_soak(report: &SoakReport) {
+    let per_hour = |growth: Option<f64>| {
+        growth.map_or("n/a".to_string(), |g| format!("{:.0} KB/h", g / 1024.0))
+    };
+    println!("\n=== Soak report ===");
+    println!(
+        "  → {} samples over {:.2}h: rss {}, memory estimate {}, fds {}",
+        report.samples.len(),
+        report.elapsed.as_secs_f64() / 3600.0,
+        per_hour(report.rss_growth_per_hour),
+        per_hour(report.memory_growth_per_hour),
+        report
+            .fd_growth
+            .map_or("n/a".to_string(), |g| format!("{:+}", g))
+    );
+    if let Some(drift) = report.accuracy_drift {
+        println!(
+            "  → Cross-check error drifted by {:.1} points",
+            drift * 100.0
+        );
+    }
+    for failure in &report.failures {
+        println!("  ✗ {}", failure);
+    }
+    if report.passed() {
+        println!("  ✓ Soak passed");
+    }
+}
+
+/// Read-only: prints the suggested masks and the commands that would apply them
+async fn advise_irqs(config: IrqAdvisorConfig, seconds: u64) -> anyhow::Result<()> {
+    println!("=== eBPF Congestion Signals IRQ Steering Advice ===\n");
+    let mut collector = CongestionCollector::load()?;
+    collector.start_collection().await?;
+    println!(
+        "Watching softirq load for {}s (run iperf3 or your workload now)...\n",
+        seconds
+    );
+
+    collector.read_and_reset_per_cpu();
+    let mut advisor = IrqAdvisor::new(config);
+    sleep(Duration::from_secs(seconds)).await;
+    let (_, per_cpu) = collector.read_and_reset_per_cpu();
+    let advice = advisor.recommendations(&per_cpu);
+
+    match advice.busiest_cpu {
+        Some(cpu) => println!(
+            "  → Busiest CPU {} at {:.1}% softirq, {:.1}x the mean",
+            cpu,
+            advice.busiest_fraction * 100.0,
+            advice.imbalance
+        ),
+        None => println!("  → No network softirq time in the window"),
+    }
+    if !advice.responsible.is_empty() {
+        println!(
+            "  → IRQs firing on it, most first: {:?}",
+            advice.responsible
+        );
+    }
+    match &advice.suppressed {
+        Some(IrqSuppression::Balanced) => println!("  ✓ Softirq load is balanced, nothing to move"),
+        Some(IrqSuppression::Isolated(cpus)) => {
+            println!("  → isolcpus is set ({:?}), leaving the layout alone", cpus)
+        }
+        Some(IrqSuppression::NoInterrupts) => {
+            println!("  → No device IRQs fired on it, the load isn't interrupt-driven")
+        }
+        Some(IrqSuppression::SingleIrq) => {
+            println!("  → One IRQ carries it all, spread it with more RSS queues instead")
+        }
+        None => {
+            println!("  → Suggested affinity (not applied):");
+            for steering in &advice.recommendations {
+                println!(
+                    "      IRQ {} {} ({} interrupts on CPU {}) → CPU {}: {}",
+                    steering.irq,
+                    steering.name,
+                    steering.interrupts,
+                    steering.cpu,
+                    steering.suggested_cpu,
+                    steering.command()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Per-second CPU samples, taken from the interval loop
+struct CpuMeter {
+    last: CpuTimes,
+    last_bpf: Option<Duration>,
+}
+
+impl CpuMeter {
+    fn start(collector: &CongestionCollector) -> anyhow::Result<Self> {
+        Ok(Self {
+            last: CpuTimes::read()?,
+            last_bpf: collector.bpf_run_time(),
+        })
+    }
+
+    fn sample(&mut self, collector: &CongestionCollector) -> anyhow::Result<Option<CpuSample>> {
+        let now = CpuTimes::read()?;
+        let bpf = collector.bpf_run_time();
+        let sample = CpuSample::between(&self.last, &now, self.last_bpf.zip(bpf));
+        self.last = now;
+        self.last_bpf = bpf;
+        Ok(sample)
+    }
+}
+
+fn print_overhead(report: &OverheadReport) {
+    let show = |interval: Option<ConfidenceInterval>| {
+        interval.map_or("n/a".to_string(), |interval| interval.to_string())
+    };
+    println!("  → CPU overhead (target: <{:.1}%):", OVERHEAD_LIMIT);
+    println!("      system, loaded - baseline  {}", show(report.system));
+    println!("      collector process          {}", show(report.process));
+    println!("      BPF programs               {}", show(report.bpf));
+    println!("      collector total            {}", show(report.collector));
+    if report.exceeds(OVERHEAD_LIMIT) {
+        println!("  WARNING: CPU overhead exceeds 2% threshold!");
+    } else if report.system.is_some_and(|system| system.exceeds(OVERHEAD_LIMIT)) {
+        println!("  → System got busier under the load, the collector itself stays below it");
+    }
+}
+
+/// The readers' wakeup rate next to what one wakeup per event would have been
+fn print_wakeups(stats: &ReaderStats) {
+    let per_wakeup = match stats.reader_wakeups {
+        0 => 0.0,
+        wakeups => stats.events_read as f64 / wakeups as f64,
+    };
+    println!(
+        "  → Reader wakeups: {:.0}/s, {:.1} events each (up to {:.0}/s waking per event)",
+        stats.wakeups_per_sec,
+        per_wakeup,
+        stats.wakeups_per_sec * per_wakeup,
+    );
+}
+
+/// `per-event`, `timer:<ms>` or `watermark:<events>`
+fn reader_wakeup(mode: &str) -> anyhow::Result<ReaderWakeup> {
+    let (kind, value) = mode.split_once(':').unwrap_or((mode, ""));
+    Ok(match kind {
+        "per-event" => ReaderWakeup::PerEvent,
+        "timer" => ReaderWakeup::Timer(Duration::from_millis(value.parse()?)),
+        "watermark" => ReaderWakeup::Watermark {
+            watermark: WakeupWatermark::Events(value.parse()?),
+            max_delay: WATERMARK_MAX_DELAY,
+        },
+        _ => anyhow::bail!(
+            "--reader-wakeup takes per-event, timer:<ms> or watermark:<events>, not {}",
+            mode
+        ),
+    })
+}
+
+/// `send`, `wmem`, `stalls` or `retransmits`
+fn talker_ranking(name: &str) -> anyhow::Result<TalkerRanking> {
+    Ok(match name {
+        "send" => TalkerRanking::SendBytes,
+        "wmem" => TalkerRanking::WmemPressure,
+        "stalls" => TalkerRanking::Stalls,
+        "retransmits" => TalkerRanking::Retransmits,
+        _ => anyhow::bail!(
+            "--top-by takes send, wmem, stalls or retransmits, not {}",
+            name
+        ),
+    })
+}
+
+fn print_top_talkers(talkers: &[TopTalker], ranking: TalkerRanking) {
+    if talkers.is_empty() {
+        println!("  → Top talkers by {:?}: none", ranking);
+        return;
+    }
+    println!("  → Top talkers by {:?}:", ranking);
+    for talker in talkers {
+        let activity = &talker.activity;
+        let share = talker.share.map_or(String::new(), |share| {
+            format!("{:.1}% of host", share * 100.0)
+        });
+        println!(
+            "      {:>20} {:<22} {:>8} KB  wmem {:>5}  {} RTOs  {} recoveries  {}",
+            talker.socket_id,
+            talker.peer.map_or("-".to_string(), |peer| peer.to_string()),
+            activity.send_bytes / 1024,
+            activity
+                .wmem_pressure
+                .map_or("n/a".to_string(), |p| format!("{:.0}%", p * 100.0)),
+            activity.stalls,
+            activity.retransmits,
+            share,
+        );
+    }
+}
+
+fn print_co_attachments(scan: &CoAttachmentScan) {
+    if scan.co_attached.is_empty() {
+        println!("  → Co-attached programs: none");
+    } else {
+        println!("  → Co-attached programs:");
+        println!(
+            "      {:<32} {:>6}  {:<16} {:<12} {:>6}  pids",
+            "attach point", "id", "name", "type", "uid"
+        );
+    }
+    for co_attached in &scan.co_attached {
+        let program = &co_attached.program;
+        let uid = program
+            .owner_uid
+            .map_or("-".to_string(), |uid| uid.to_string());
+        let pids: Vec<String> = co_attached.owner_pids.iter().map(u32::to_string).collect();
+        let pids = if pids.is_empty() {
+            "-".to_string()
+        } else {
+            pids.join(",")
+        };
+        println!(
+            "      {:<32} {:>6}  {:<16} {:<12} {:>6}  {}",
+            co_attached.point.to_string(),
+            program.id,
+            program.name,
+            program.program_type,
+            uid,
+            pids,
+        );
+    }
+    for point in &scan.missing {
+        println!("  WARNING: nothing of ours attached at {}", point);
+    }
+    for (point, reason) in &scan.unscanned {
+        println!("  → Not scanned: {} ({})", point, reason);
+    }
+    println!();
+}
+
+fn print_accuracy(report: Option<AccuracyReport>) {
+    let Some(report) = report else {
+        println!("  → Accuracy: no SNMP comparison yet");
+        return;
+    };
+    println!(
+        "  → Accuracy against SNMP counters over {}s:",
+        report.interval_ns / 1_000_000_000
+    );
+    for signal in &report.signals {
+        println!("      {}", signal);
+    }
+}
+
+fn print_memory(report: &MemoryReport) {
+    println!(
+        "  → Memory: {} KB userspace | {} KB BPF maps | {} evictions",
+        report.userspace_bytes() / 1024,
+        report.bpf_bytes() / 1024,
+        report.evictions(),
+    );
+    for s in &report.structures {
+        println!(
+            "      {:<20} {:>7}/{:<7} entries {:>7} KB {:>6} evicted",
+            s.name,
+            s.entries,
+            s.cap,
+            s.estimated_bytes / 1024,
+            s.evictions,
+        );
+    }
+    let shards = &report.socket_shards;
+    if let (Some(min), Some(max)) = (
+        shards.iter().map(|s| s.entries).min(),
+        shards.iter().map(|s| s.entries).max(),
+    ) {
+        println!(
+            "      {} socket table shards, {}-{} entries each, {} eviction passes, {} contended locks",
+            shards.len(),
+            min,
+            max,
+            shards.iter().map(|s| s.eviction_passes).sum::<u64>(),
+            shards.iter().map(|s| s.contended).sum::<u64>(),
+        );
+    }
+}
@@ -0,0 +1,2130 @@
+//! netem scenario matrix for `validate --scenarios`
+//!
+//! Builds a veth pair with one end in a scratch network namespace, then for each
+//! scenario applies a tc netem qdisc on our end, pushes traffic into the namespace
+//! for a fixed window and checks the collected signals against what the impairment
+//! should produce. Needs root plus `ip` and `tc` (iproute2).
+
+use ebpf_congestion_signals::{
+    socket_cookie, CalibrationOutcome, CgroupAggregationConfig, CollectorConfig, CollectorError,
+    CongestionCollector, CongestionSignals, LatestSnapshot, LoadedCollector, NetnsConfig,
+    OnsetThreshold, ReaderWakeup, Redaction, SessionReport, SignalSeries, StateFileConfig,
+    TrafficClass, TrafficClassConfig, WakeupWatermark,
+};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::os::fd::AsRawFd;
+use std::process::Command;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
+
+const NETNS: &str = "cqgov_validate";
+const HOST_DEV: &str = "cqgov0";
+const PEER_DEV: &str = "cqgov1";
+const HOST_ADDR: &str = "10.203.0.1";
+const PEER_ADDR: &str = "10.203.0.2";
+const SINK_PORT: u16 = 5201;
+
+// Paced UDP load: 1200B x 4000pps ≈ 38 Mbit/s, enough for 1% loss to be visible
+const UDP_PAYLOAD: usize = 1200;
+const UDP_PPS: u64 = 4000;
+
+// Kernel noise allowance on top of the idle drop rate
+const DROP_SLACK: u64 = 20;
+// Agreement of the drop count with netem's own under coalescing, relative on
+// top of the idle rate and DROP_SLACK
+const DROP_TOLERANCE: f64 = 0.05;
+
+// netdev_budget during the rx-squeeze scenario: any NET_RX round that polls more
+// than one packet runs out of budget, which makes time_squeeze deterministic
+const SQUEEZE_BUDGET: &str = "1";
+const NETDEV_BUDGET: &str = "/proc/sys/net/core/netdev_budget";
+// Agreement with /proc/net/softnet_stat, relative plus absolute for the rounds
+// on either edge of the window
+const SQUEEZE_TOLERANCE: f64 = 0.10;
+const SQUEEZE_SLACK: u64 = 10;
+
+// buffered-bytes: paced UDP into a netem rate limit well below it, from a
+// registered socket whose sndbuf holds the whole backlog. A second of it leaves
+// ~2 MB in the qdisc, which drains within a second once sending stops
+const BUFFERED_SNDBUF: libc::c_int = 8 * 1024 * 1024;
+const BUFFERED_WINDOW: Duration = Duration::from_secs(1);
+const BUFFERED_MIN_PEAK: u64 = 512 * 1024;
+const BUFFERED_DRAINED: u64 = 64 * 1024;
+const BUFFERED_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// reaction: TCP bulk with netem loss applied halfway through the window, read
+// every REACTION_READ, which is what the latencies resolve to
+const REACTION_NETEM: &str = "loss 5%";
+const REACTION_READ: Duration = Duration::from_millis(100);
+// Reads in the rolling p95 of TCP send buffer pressure
+const REACTION_WMEM_WINDOW: usize = 5;
+
+#[derive(Clone, Copy)]
+enum Traffic {
+    Udp,
+    Tcp,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Clean,
+    Loss,
+    Delay,
+    RateLimit,
+    RxSqueeze,
+    Buffered,
+}
+
+struct Scenario {
+    name: &'static str,
+    netem: Option<&'static str>,
+    traffic: Traffic,
+    kind: Kind,
+}
+
+const SCENARIOS: &[Scenario] = &[
+    Scenario {
+        name: "clean",
+        netem: None,
+        traffic: Traffic::Udp,
+        kind: Kind::Clean,
+    },
+    Scenario {
+        name: "loss-1pct",
+        netem: Some("loss 1%"),
+        traffic: Traffic::Udp,
+        kind: Kind::Loss,
+    },
+    Scenario {
+        name: "delay-50ms",
+        netem: Some("delay 50ms"),
+        traffic: Traffic::Udp,
+        kind: Kind::Delay,
+    },
+    Scenario {
+        name: "rate-10mbit",
+        netem: Some("rate 10mbit limit 100"),
+        traffic: Traffic::Tcp,
+        kind: Kind::RateLimit,
+    },
+    Scenario {
+        name: "rx-squeeze",
+        netem: None,
+        traffic: Traffic::Udp,
+        kind: Kind::RxSqueeze,
+    },
+    Scenario {
+        name: "buffered-bytes",
+        netem: Some("rate 20mbit limit 100000"),
+        traffic: Traffic::Udp,
+        kind: Kind::Buffered,
+    },
+];
+
+/// What one traffic window produced, on both sides of the probes
+struct Measurement {
+    signals: CongestionSignals,
+    packets_sent: u64,
+    /// time_squeeze over the same window, from /proc/net/softnet_stat
+    softnet_time_squeeze: Option<u64>,
+    /// Drops on HOST_DEV's qdisc over the same window, from `tc -s qdisc`
+    tc_drops: Option<u64>,
+    /// kernel_buffered_bytes when sending stopped and once it drained, for the
+    /// buffered-bytes scenario. Err says why the socket couldn't be registered
+    buffered: Option<Result<(u64, u64), String>>,
+}
+
+enum Outcome {
+    Pass,
+    Fail,
+    Skip,
+}
+
+struct Check {
+    scenario: &'static str,
+    name: &'static str,
+    outcome: Outcome,
+    detail: String,
+}
+
+/// How quickly signals have to react to the impairment of the reaction run,
+/// `--max-reaction` and `--reaction-factor` on the command line
+pub struct ReactionBound {
+    pub max_latency: Duration,
+    /// Of the signal's baseline before the impairment, see `OnsetThreshold`
+    pub factor: f64,
+}
+
+impl Default for ReactionBound {
+    fn default() -> Self {
+        Self {
+            max_latency: Duration::from_secs(3),
+            factor: OnsetThreshold::default().factor,
+        }
+    }
+}
+
+/// Tears the namespace down however we leave, the veth pair goes with it
+struct Topology;
+
+impl Topology {
+    fn create() -> anyhow::Result<Self> {
+        // Leftovers from an interrupted run
+        let _ = run("ip", &["netns", "del", NETNS]);
+        let _ = run("ip", &["link", "del", HOST_DEV]);
+
+        let topology = Topology;
+        run("ip", &["netns", "add", NETNS])?;
+        run(
+            "ip",
+            &[
+                "link", "add", HOST_DEV, "type", "veth", "peer", "name", PEER_DEV,
+            ],
+        )?;
+        run("ip", &["link", "set", PEER_DEV, "netns", NETNS])?;
+        run(
+            "ip",
+            &["addr", "add", &format!("{}/30", HOST_ADDR), "dev", HOST_DEV],
+        )?;
+        run("ip", &["link", "set", HOST_DEV, "up"])?;
+        run_in_ns(&["addr", "add", &format!("{}/30", PEER_ADDR), "dev", PEER_DEV])?;
+        run_in_ns(&["link", "set", PEER_DEV, "up"])?;
+        run_in_ns(&["link", "set", "lo", "up"])?;
+        Ok(topology)
+    }
+
+    fn apply(&self, netem: Option<&str>) -> anyhow::Result<()> {
+        let _ = run("tc", &["qdisc", "del", "dev", HOST_DEV, "root"]);
+        if let Some(netem) = netem {
+            let mut args = vec!["qdisc", "replace", "dev", HOST_DEV, "root", "netem"];
+            args.extend(netem.split_whitespace());
+            run("tc", &args)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Topology {
+    fn drop(&mut self) {
+        let _ = run("ip", &["link", "del", HOST_DEV]);
+        let _ = run("ip", &["netns", "del", NETNS]);
+    }
+}
+
+/// Lowers netdev_budget for as long as it lives
+struct BudgetOverride {
+    original: String,
+}
+
+impl BudgetOverride {
+    fn set(budget: &str) -> anyhow::Result<Self> {
+        let original = std::fs::read_to_string(NETDEV_BUDGET)?;
+        std::fs::write(NETDEV_BUDGET, budget)?;
+        Ok(Self { original })
+    }
+}
+
+impl Drop for BudgetOverride {
+    fn drop(&mut self) {
+        let _ = std::fs::write(NETDEV_BUDGET, self.original.trim());
+    }
+}
+
+/// Sum of column 3 (time_squeeze, hex) over all CPUs
+fn softnet_time_squeeze() -> Option<u64> {
+    let stat = std::fs::read_to_string("/proc/net/softnet_stat").ok()?;
+    stat.lines()
+        .map(|line| u64::from_str_radix(line.split_whitespace().nth(2)?, 16).ok())
+        .sum()
+}
+
+/// Sum of the "dropped N" counters of HOST_DEV's qdiscs
+fn qdisc_drops() -> Option<u64> {
+    let output = Command::new("tc")
+        .args(["-s", "qdisc", "show", "dev", HOST_DEV])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stats = String::from_utf8_lossy(&output.stdout);
+    let words: Vec<&str> = stats.split_whitespace().collect();
+    let drops = words
+        .windows(2)
+        .filter(|pair| pair[0] == "dropped")
+        .filter_map(|pair| pair[1].trim_end_matches(',').parse::<u64>().ok());
+    Some(drops.sum())
+}
+
+fn run(cmd: &str, args: &[&str]) -> anyhow::Result<()> {
+    let output = Command::new(cmd).args(args).output()?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{} {} failed: {}",
+            cmd,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+fn run_in_ns(args: &[&str]) -> anyhow::Result<()> {
+    let mut full = vec!["-n", NETNS];
+    full.extend_from_slice(args);
+    run("ip", &full)
+}
+
+/// Spawn a thread that moves itself into the scratch namespace and discards
+/// whatever arrives, so deliveries don't show up as no-socket drops
+fn spawn_sink(
+    traffic: Traffic,
+    stop: Arc<AtomicBool>,
+) -> anyhow::Result<std::thread::JoinHandle<()>> {
+    let ns = std::fs::File::open(format!("/run/netns/{}", NETNS))?;
+    let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+
+    let handle = std::thread::spawn(move || {
+        if unsafe { libc::setns(ns.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+            let _ = ready_tx.send(Err(std::io::Error::last_os_error()));
+            return;
+        }
+
+        let mut buf = vec![0u8; 65536];
+        match traffic {
+            Traffic::Udp => {
+                let sock = match UdpSocket::bind((PEER_ADDR, SINK_PORT)) {
+                    Ok(sock) => sock,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = sock.set_read_timeout(Some(Duration::from_millis(100)));
+                let _ = ready_tx.send(Ok(()));
+                while !stop.load(Ordering::Relaxed) {
+                    let _ = sock.recv(&mut buf);
+                }
+            }
+            Traffic::Tcp => {
+                let listener = match TcpListener::bind((PEER_ADDR, SINK_PORT)) {
+                    Ok(listener) => listener,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = ready_tx.send(Ok(()));
+                if let Ok((mut stream, _)) = listener.accept() {
+                    while let Ok(n) = stream.read(&mut buf) {
+                        if n == 0 {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    });
+
+    ready_rx.recv()??;
+    Ok(handle)
+}
+
+/// Push traffic into the namespace for `window`, returns packets handed to the kernel
+fn generate(traffic: Traffic, window: Duration) -> anyhow::Result<u64> {
+    let deadline = Instant::now() + window;
+    let mut packets = 0u64;
+
+    match traffic {
+        Traffic::Udp => {
+            let sock = UdpSocket::bind((HOST_ADDR, 0))?;
+            packets = send_paced(&sock, deadline);
+        }
+        Traffic::Tcp => {
+            let mut stream = TcpStream::connect((PEER_ADDR, SINK_PORT))?;
+            stream.set_write_timeout(Some(Duration::from_millis(200)))?;
+            let chunk = [0u8; 64 * 1024];
+            while Instant::now() < deadline {
+                if let Ok(n) = stream.write(&chunk) {
+                    // Segments rather than writes, roughly MSS-sized
+                    packets += (n as u64).div_ceil(1448);
+                }
+            }
+        }
+    }
+
+    Ok(packets)
+}
+
+/// UDP_PPS datagrams per second until `deadline`, returns how many were accepted
+fn send_paced(sock: &UdpSocket, deadline: Instant) -> u64 {
+    let payload = [0u8; UDP_PAYLOAD];
+    let gap = Duration::from_nanos(1_000_000_000 / UDP_PPS);
+    let mut packets = 0;
+    let mut next = Instant::now();
+    while Instant::now() < deadline {
+        if sock.send_to(&payload, (PEER_ADDR, SINK_PORT)).is_ok() {
+            packets += 1;
+        }
+        next += gap;
+        if let Some(wait) = next.checked_duration_since(Instant::now()) {
+            std::thread::sleep(wait);
+        }
+    }
+    packets
+}
+
+/// Send from a registered socket for BUFFERED_WINDOW, then watch its gauge
+/// drain. Returns packets sent, sampled send bytes from the reads it made and
+/// the gauge at the end of sending and after draining
+async fn buffered_window(
+    collector: &CongestionCollector,
+) -> anyhow::Result<(u64, u64, Result<(u64, u64), String>)> {
+    let sock = UdpSocket::bind((HOST_ADDR, 0))?;
+    // SO_SNDBUFFORCE: past net.core.wmem_max, we're root anyway
+    let ret = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_SNDBUFFORCE,
+            &BUFFERED_SNDBUF as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let handle = match collector.register_socket(&sock) {
+        Ok(handle) => handle,
+        Err(e) => return Ok((0, 0, Err(e.to_string()))),
+    };
+
+    let (sock, packets) = tokio::task::spawn_blocking(move || {
+        let packets = send_paced(&sock, Instant::now() + BUFFERED_WINDOW);
+        (sock, packets)
+    })
+    .await?;
+
+    // The gauge moves on interval reads
+    let mut send_bytes = collector.read_and_reset().send_bytes;
+    let gauge = |collector: &CongestionCollector| {
+        collector
+            .signals_for(&handle)
+            .map_or(0, |s| s.kernel_buffered_bytes)
+    };
+    let peak = gauge(collector);
+
+    let deadline = Instant::now() + BUFFERED_DRAIN_TIMEOUT;
+    let mut drained = peak;
+    while drained > BUFFERED_DRAINED && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        send_bytes += collector.read_and_reset().send_bytes;
+        drained = gauge(collector);
+    }
+
+    collector.unregister_socket(handle);
+    drop(sock);
+    Ok((packets, send_bytes, Ok((peak, drained))))
+}
+
+async fn measure(
+    collector: &CongestionCollector,
+    topology: &Topology,
+    scenario: &Scenario,
+    window: Duration,
+) -> anyhow::Result<Measurement> {
+    topology.apply(scenario.netem)?;
+
+    let budget = match scenario.kind {
+        Kind::RxSqueeze => Some(BudgetOverride::set(SQUEEZE_BUDGET)?),
+        _ => None,
+    };
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let sink = spawn_sink(scenario.traffic, stop.clone())?;
+
+    // Settle, then start the window from zeroed counters. The collector picks
+    // up a changed budget on this read
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    collector.read_and_reset();
+    let squeeze_before = softnet_time_squeeze();
+    let tc_before = qdisc_drops();
+
+    let traffic = scenario.traffic;
+    let (packets_sent, earlier_send_bytes, buffered) = match scenario.kind {
+        Kind::Buffered => {
+            let (packets, send_bytes, buffered) = buffered_window(collector).await?;
+            (packets, send_bytes, Some(buffered))
+        }
+        _ => {
+            let packets =
+                tokio::task::spawn_blocking(move || generate(traffic, window)).await??;
+            (packets, 0, None)
+        }
+    };
+
+    // Let delayed packets drain before reading
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let squeeze_after = softnet_time_squeeze();
+    let tc_after = qdisc_drops();
+    let mut signals = collector.read_and_reset();
+    signals.send_bytes += earlier_send_bytes;
+    drop(budget);
+
+    stop.store(true, Ordering::Relaxed);
+    let _ = tokio::task::spawn_blocking(move || sink.join()).await;
+
+    Ok(Measurement {
+        signals,
+        packets_sent,
+        softnet_time_squeeze: squeeze_before
+            .zip(squeeze_after)
+            .map(|(before, after)| after.saturating_sub(before)),
+        tc_drops: tc_before
+            .zip(tc_after)
+            .map(|(before, after)| after.saturating_sub(before)),
+        buffered,
+    })
+}
+
+fn evaluate(scenario: &Scenario, m: &Measurement, idle: &CongestionSignals) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let drop_noise = idle.drops + DROP_SLACK;
+    let extra_drops = m.signals.drops.saturating_sub(idle.drops);
+
+    let mut check = |name: &'static str, outcome: Outcome, detail: String| {
+        checks.push(Check {
+            scenario: scenario.name,
+            name,
+            outcome,
+            detail,
+        })
+    };
+    let pass_if = |ok: bool| if ok { Outcome::Pass } else { Outcome::Fail };
+
+    if let Traffic::Udp = scenario.traffic {
+        check(
+            "send activity observed",
+            pass_if(m.signals.send_bytes > 0),
+            format!(
+                "{} sampled bytes for {} packets sent",
+                m.signals.send_bytes, m.packets_sent
+            ),
+        );
+    }
+
+    match scenario.kind {
+        Kind::Clean | Kind::Delay => {
+            check(
+                "drops near baseline",
+                pass_if(m.signals.drops <= drop_noise),
+                format!("{} drops (allowed ≤{})", m.signals.drops, drop_noise),
+            );
+        }
+        Kind::Loss => {
+            // netem drops 1% on average; accept anything above half of that
+            let expected = m.packets_sent / 200;
+            check(
+                "drops elevated",
+                pass_if(extra_drops >= expected.max(1)),
+                format!("{} drops over idle (expected ≥{})", extra_drops, expected),
+            );
+            // Drops arrive coalesced (CollectorConfig::drop_coalescing), so this
+            // checks the counts they carry add up to netem's
+            match m.tc_drops {
+                Some(tc_drops) => {
+                    let allowed = (tc_drops as f64 * DROP_TOLERANCE) as u64 + drop_noise;
+                    check(
+                        "drops match tc -s qdisc",
+                        pass_if(extra_drops.abs_diff(tc_drops) <= allowed),
+                        format!(
+                            "{} drops over idle, {} by tc (allowed ±{})",
+                            extra_drops, tc_drops, allowed
+                        ),
+                    );
+                }
+                None => check(
+                    "drops match tc -s qdisc",
+                    Outcome::Skip,
+                    "tc -s qdisc unreadable".to_string(),
+                ),
+            }
+            check(
+                "retransmits elevated",
+                Outcome::Skip,
+                "no retransmit signal collected yet".to_string(),
+            );
+        }
+        Kind::RateLimit => {
+            check(
+                "drops near zero",
+                pass_if(m.signals.drops <= drop_noise),
+                format!("{} drops (allowed ≤{})", m.signals.drops, drop_noise),
+            );
+            check(
+                "wmem pressure elevated",
+                Outcome::Skip,
+                "socket-state probe not attached".to_string(),
+            );
+            check(
+                "backlog elevated",
+                Outcome::Skip,
+                format!(
+                    "net_dev_queue counts enqueues, not backlog ({} pkts seen)",
+                    m.signals.queue_depth_packets
+                ),
+            );
+        }
+        Kind::RxSqueeze => match (m.signals.rx_time_squeeze, m.softnet_time_squeeze) {
+            (None, _) => check(
+                "squeeze matches softnet",
+                Outcome::Skip,
+                "napi_poll probe not attached".to_string(),
+            ),
+            (_, None) => check(
+                "squeeze matches softnet",
+                Outcome::Skip,
+                "/proc/net/softnet_stat unreadable".to_string(),
+            ),
+            (Some(ours), Some(kernel)) => {
+                let allowed = (kernel as f64 * SQUEEZE_TOLERANCE) as u64 + SQUEEZE_SLACK;
+                check(
+                    "rx squeeze observed",
+                    pass_if(ours > 0),
+                    format!("{} squeezes at netdev_budget={}", ours, SQUEEZE_BUDGET),
+                );
+                check(
+                    "squeeze matches softnet",
+                    pass_if(ours.abs_diff(kernel) <= allowed),
+                    format!("{} vs softnet_stat {} (allowed ±{})", ours, kernel, allowed),
+                );
+            }
+        },
+        Kind::Buffered => match &m.buffered {
+            Some(Ok((peak, drained))) => {
+                check(
+                    "buffered bytes rise",
+                    pass_if(*peak >= BUFFERED_MIN_PEAK),
+                    format!("{} KB buffered (expected ≥{})", peak / 1024, BUFFERED_MIN_PEAK / 1024),
+                );
+                check(
+                    "buffered bytes drain",
+                    pass_if(*drained <= BUFFERED_DRAINED),
+                    format!(
+                        "{} KB once sending stopped (allowed ≤{} within {}s)",
+                        drained / 1024,
+                        BUFFERED_DRAINED / 1024,
+                        BUFFERED_DRAIN_TIMEOUT.as_secs()
+                    ),
+                );
+            }
+            Some(Err(e)) => check("buffered bytes rise", Outcome::Skip, e.clone()),
+            None => {}
+        },
+    }
+
+    checks
+}
+
+/// TCP bulk into the namespace for `window`, with REACTION_NETEM applied
+/// halfway through and stamped on the clock interval reads are stamped with.
+/// Each signal's reaction latency is the time from there to the first of two
+/// reads past `bound.factor` times its baseline, and fails past
+/// `bound.max_latency`. Signals whose probes didn't attach are skipped
+async fn reaction_latency(
+    collector: &CongestionCollector,
+    topology: &Topology,
+    window: Duration,
+    bound: &ReactionBound,
+) -> anyhow::Result<Vec<Check>> {
+    topology.apply(None)?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let sink = spawn_sink(Traffic::Tcp, stop.clone())?;
+    let traffic = tokio::task::spawn_blocking(move || generate(Traffic::Tcp, window));
+
+    collector.read_and_reset();
+    let start = Instant::now();
+    let mut impaired = None;
+    let mut reads = Vec::new();
+    let mut ticker = tokio::time::interval(REACTION_READ);
+    ticker.tick().await;
+    while start.elapsed() < window {
+        ticker.tick().await;
+        if impaired.is_none() && start.elapsed() >= window / 2 {
+            topology.apply(Some(REACTION_NETEM))?;
+            // Once tc returned: the qdisc drops from here on
+            impaired = Some(Instant::now());
+        }
+        reads.push(collector.read_and_reset());
+    }
+    traffic.await??;
+    stop.store(true, Ordering::Relaxed);
+    let _ = tokio::task::spawn_blocking(move || sink.join()).await;
+    topology.apply(None)?;
+    let impaired = impaired.unwrap_or(start);
+
+    let mut drops = SignalSeries::new();
+    let mut retransmits = SignalSeries::new();
+    let mut wmem = SignalSeries::new();
+    for s in &reads {
+        let at = s.produced_at.unwrap_or(start);
+        let per_sec = |n: u64| n as f64 * 1e9 / s.interval_ns.max(1) as f64;
+        drops.push(at, per_sec(s.drops));
+        if s.loss_recovery_episodes.is_some() || s.rto_events.is_some() {
+            let episodes = s.loss_recovery_episodes.unwrap_or(0) + s.rto_events.unwrap_or(0);
+            retransmits.push(at, per_sec(episodes));
+        }
+        if let Some(pressure) = s.tcp_wmem_pressure {
+            wmem.push(at, pressure);
+        }
+    }
+    let threshold = |min_rise: f64, ceiling: Option<f64>| OnsetThreshold {
+        factor: bound.factor,
+        min_rise,
+        ceiling,
+        ..Default::default()
+    };
+    let signals = [
+        ("drops", "/s", drops, threshold(50.0, None)),
+        ("retransmits", "/s", retransmits, threshold(1.0, None)),
+        (
+            "wmem p95",
+            "",
+            wmem.rolling_quantile(REACTION_WMEM_WINDOW, 0.95),
+            threshold(0.05, Some(0.9)),
+        ),
+    ];
+
+    let mut summary = Vec::new();
+    let mut checks = Vec::new();
+    for (signal, unit, series, threshold) in signals {
+        let (outcome, detail) = if series.is_empty() {
+            summary.push(format!("{}: -", signal));
+            (Outcome::Skip, "not measured on this kernel".to_string())
+        } else {
+            match series.reaction(impaired, &threshold) {
+                Some(reaction) => {
+                    let secs = reaction.latency.as_secs_f64();
+                    summary.push(format!("{}: {:.1}s", signal, secs));
+                    let outcome = if reaction.latency <= bound.max_latency {
+                        Outcome::Pass
+                    } else {
+                        Outcome::Fail
+                    };
+                    let detail = format!(
+                        "{:.1}s (allowed ≤{:.1}s), {:.2}{} → {:.2}{}",
+                        secs,
+                        bound.max_latency.as_secs_f64(),
+                        reaction.baseline,
+                        unit,
+                        reaction.value,
+                        unit
+                    );
+                    (outcome, detail)
+                }
+                None => {
+                    summary.push(format!("{}: none", signal));
+                    let detail = format!(
+                        "no rise past {}x baseline {:.2}{} in {:.1}s, peak {:.2}{}",
+                        bound.factor,
+                        series.baseline(impaired).unwrap_or(0.0),
+                        unit,
+                        window.saturating_sub(impaired - start).as_secs_f64(),
+                        series.peak_after(impaired).unwrap_or(0.0),
+                        unit
+                    );
+                    (Outcome::Fail, detail)
+                }
+            }
+        };
+        checks.push(Check {
+            scenario: "reaction",
+            name: signal,
+            outcome,
+            detail,
+        });
+    }
+    println!("Reaction to {}: {}", REACTION_NETEM, summary.join(", "));
+    Ok(checks)
+}
+
+fn print_table(checks: &[Check]) {
+    println!(
+        "\n{:<14} {:<24} {:<6} Detail",
+        "Scenario", "Check", "Result"
+    );
+    println!("{}", "-".repeat(80));
+    for c in checks {
+        let result = match c.outcome {
+            Outcome::Pass => "PASS",
+            Outcome::Fail => "FAIL",
+            Outcome::Skip => "SKIP",
+        };
+        println!(
+            "{:<14} {:<24} {:<6} {}",
+            c.scenario, c.name, result, c.detail
+        );
+    }
+}
+
+// loopback: datagrams per socket, enough for several 1-in-100 samples each
+// even when the sends spread over CPUs
+const LOOPBACK_SENDS: usize = 2000;
+
+/// Sends to 127.0.0.1 from a connected and an unconnected socket. The collector
+/// runs with the default `exclude_loopback`, so neither may show up with send
+/// bytes in `read_per_socket()`
+async fn loopback_excluded(collector: &CongestionCollector) -> Check {
+    let check = |outcome, detail| Check {
+        scenario: "loopback",
+        name: "excluded by default",
+        outcome,
+        detail,
+    };
+    let sent = (|| -> std::io::Result<(u64, u64)> {
+        let sink = UdpSocket::bind("127.0.0.1:0")?;
+        let target = sink.local_addr()?;
+        let connected = UdpSocket::bind("127.0.0.1:0")?;
+        connected.connect(target)?;
+        let unconnected = UdpSocket::bind("127.0.0.1:0")?;
+        let ids = (socket_cookie(&connected)?, socket_cookie(&unconnected)?);
+        let payload = [0u8; 64];
+        for _ in 0..LOOPBACK_SENDS {
+            connected.send(&payload)?;
+            unconnected.send_to(&payload, target)?;
+        }
+        Ok(ids)
+    })();
+    let (connected, unconnected) = match sent {
+        Ok(ids) => ids,
+        Err(e) => return check(Outcome::Fail, format!("loopback sends failed: {}", e)),
+    };
+
+    // Let the readers catch up
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let sockets = collector.read_per_socket();
+    let sent_bytes = |id| sockets.get(&id).map_or(0, |s| s.send_bytes);
+    let (connected, unconnected) = (sent_bytes(connected), sent_bytes(unconnected));
+    let outcome = if connected == 0 && unconnected == 0 {
+        Outcome::Pass
+    } else {
+        Outcome::Fail
+    };
+    check(
+        outcome,
+        format!(
+            "sampled send bytes after {} sends each: connected {}, sendto {}",
+            LOOPBACK_SENDS, connected, unconnected
+        ),
+    )
+}
+
+/// A second collector with per-cgroup aggregation (and loopback counted) while
+/// we send to 127.0.0.1: our own cgroup, from /proc/self/cgroup, must show the
+/// sampled sends, all of them loopback
+fn own_cgroup_attributed() -> Check {
+    let check = |outcome, detail| Check {
+        scenario: "cgroups",
+        name: "own sends attributed",
+        outcome,
+        detail,
+    };
+    let own = std::fs::read_to_string("/proc/self/cgroup")
+        .ok()
+        .and_then(|cgroups| {
+            cgroups
+                .lines()
+                .find_map(|line| line.strip_prefix("0::").map(str::to_string))
+        });
+    let Some(own) = own else {
+        return check(
+            Outcome::Fail,
+            "no cgroup2 entry in /proc/self/cgroup".to_string(),
+        );
+    };
+    let config = CollectorConfig::default()
+        .with_loopback()
+        .with_cgroup_aggregation(CgroupAggregationConfig::default());
+    let collector = match CongestionCollector::load_with_config(config) {
+        Ok(collector) => collector,
+        Err(e) => return check(Outcome::Fail, format!("load failed: {}", e)),
+    };
+    collector.read_per_cgroup();
+
+    let sent = (|| -> std::io::Result<()> {
+        let sink = UdpSocket::bind("127.0.0.1:0")?;
+        let sender = UdpSocket::bind("127.0.0.1:0")?;
+        sender.connect(sink.local_addr()?)?;
+        for _ in 0..LOOPBACK_SENDS {
+            sender.send(&[0u8; 64])?;
+        }
+        Ok(())
+    })();
+    if let Err(e) = sent {
+        return check(Outcome::Fail, format!("loopback sends failed: {}", e));
+    }
+
+    let cgroups = collector.read_per_cgroup();
+    let ours = cgroups
+        .iter()
+        .find(|(&id, _)| collector.cgroup_path(id).as_deref() == Some(own.as_str()));
+    match ours {
+        Some((_, signals))
+            if signals.send_bytes > 0
+                && signals.loopback_send_bytes == Some(signals.send_bytes) =>
+        {
+            check(
+                Outcome::Pass,
+                format!("{}: {} sampled bytes", own, signals.send_bytes),
+            )
+        }
+        Some((_, signals)) => check(
+            Outcome::Fail,
+            format!(
+                "{}: {} sampled bytes, {:?} loopback",
+                own, signals.send_bytes, signals.loopback_send_bytes
+            ),
+        ),
+        None => check(
+            Outcome::Fail,
+            format!("{} not among {} cgroups read", own, cgroups.len()),
+        ),
+    }
+}
+
+/// The socket-state calibration `start_collection()` ran must have read the
+/// calibration socket's sndbuf right
+fn calibration_passed(collector: &CongestionCollector) -> Check {
+    let outcome = collector.health().calibration;
+    Check {
+        scenario: "calibration",
+        name: "socket-state offsets",
+        outcome: if matches!(outcome, Some(CalibrationOutcome::Passed { .. })) {
+            Outcome::Pass
+        } else {
+            Outcome::Fail
+        },
+        detail: format!("{:?}", outcome),
+    }
+}
+
+/// Two overlapping sessions over three reads, the inner one around the middle
+/// read: each report must merge exactly the reads it was open for, and each read
+/// must carry the labels of the sessions open for it
+fn session_arithmetic(collector: &CongestionCollector) -> Check {
+    let check = |outcome, detail| Check {
+        scenario: "sessions",
+        name: "overlapping sessions",
+        outcome,
+        detail,
+    };
+    collector.read_and_reset();
+    let outer = collector.begin_session("outer");
+    let first = collector.read_and_reset();
+    let inner = collector.begin_session("inner");
+    let second = collector.read_and_reset();
+    let inner = inner.end();
+    let third = collector.read_and_reset();
+    let outer = outer.end();
+
+    let sum = |reads: &[&CongestionSignals], field: fn(&CongestionSignals) -> u64| {
+        reads.iter().map(|s| field(s)).sum::<u64>()
+    };
+    let all = [&first, &second, &third];
+    let merged_right = |report: &SessionReport, reads: &[&CongestionSignals]| {
+        report.intervals == reads.len() as u64
+            && report.signals.interval_ns == sum(reads, |s| s.interval_ns)
+            && report.signals.send_bytes == sum(reads, |s| s.send_bytes)
+            && report.signals.event_count == sum(reads, |s| s.event_count)
+            && report.signals.estimated.send_bytes == sum(reads, |s| s.estimated.send_bytes)
+            && report.signals.observed.events_received == sum(reads, |s| s.observed.events_received)
+    };
+    let labels_right = first.sessions == ["outer"]
+        && second.sessions == ["outer", "inner"]
+        && third.sessions == ["outer"];
+
+    let outcome = if merged_right(&outer, &all) && merged_right(&inner, &[&second]) && labels_right
+    {
+        Outcome::Pass
+    } else {
+        Outcome::Fail
+    };
+    check(
+        outcome,
+        format!(
+            "outer {} intervals {} ns, inner {} intervals {} ns, labels {:?} {:?} {:?}",
+            outer.intervals,
+            outer.signals.interval_ns,
+            inner.intervals,
+            inner.signals.interval_ns,
+            first.sessions,
+            second.sessions,
+            third.sessions
+        ),
+    )
+}
+
+/// A second collector, attached next to ours with the conflict check off,
+/// is what the kernel scan finds at our tracepoints and kprobes, held by this
+/// process, and counted as a change; once it's dropped the scan is back where
+/// it was
+async fn co_attachment_detected(collector: &CongestionCollector) -> Check {
+    use ebpf_congestion_signals::ConflictCheck;
+    let check = |outcome, detail| Check {
+        scenario: "co-attachment",
+        name: "second collector found",
+        outcome,
+        detail,
+    };
+    let before = collector.scan_co_attachments();
+    let config = CollectorConfig::default()
+        .with_conflict_check(ConflictCheck::Off)
+        .without_calibration()
+        .without_co_attachment_scan();
+    let second = match CongestionCollector::load_with_config(config) {
+        Ok(second) => second,
+        Err(e) => {
+            return check(
+                Outcome::Skip,
+                format!("second collector didn't load: {}", e),
+            )
+        }
+    };
+    let during = collector.scan_co_attachments();
+    let pid = std::process::id();
+    let ours_at = |point: &str| {
+        during
+            .co_attached
+            .iter()
+            .filter(|c| c.point.to_string() == point && c.owner_pids.contains(&pid))
+            .count()
+    };
+    let (tracepoint, kprobe) = (
+        ours_at("tracepoint:skb:kfree_skb"),
+        ours_at("kprobe:udp_sendmsg"),
+    );
+    let kprobes_scanned = !during
+        .unscanned
+        .iter()
+        .any(|(point, _)| point.to_string().starts_with("kprobe:"));
+    drop(second);
+    let after = collector.scan_co_attachments();
+
+    let outcome = if tracepoint >= 1
+        && (kprobe >= 1 || !kprobes_scanned)
+        && during.missing.is_empty()
+        && during.changed_from == Some(before.co_attached.len())
+        && after.co_attached == before.co_attached
+        && after.changed_from == Some(during.co_attached.len())
+    {
+        Outcome::Pass
+    } else {
+        Outcome::Fail
+    };
+    check(
+        outcome,
+        format!(
+            "{} co-attached before, {} with the second collector ({} at kfree_skb, {} at \
+             udp_sendmsg{}), {} after; missing {:?}",
+            before.co_attached.len(),
+            during.co_attached.len(),
+            tracepoint,
+            kprobe,
+            if kprobes_scanned { "" } else { ", not scanned" },
+            after.co_attached.len(),
+            during.missing
+        ),
+    )
+}
+
+const CHURN_CONNECTIONS: u64 = 50;
+
+/// Loopback TCP connections opened and closed between two reads show up on both
+/// sides of the handshake, in the accept count and in the closes
+async fn connection_churn_live(collector: &CongestionCollector) -> Check {
+    let check = |outcome, detail| Check {
+        scenario: "churn",
+        name: "connections opened and closed",
+        outcome,
+        detail,
+    };
+    collector.read_and_reset();
+    let churned = (|| -> std::io::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let target = listener.local_addr()?;
+        for _ in 0..CHURN_CONNECTIONS {
+            let client = TcpStream::connect(target)?;
+            let (server, _) = listener.accept()?;
+            drop(client);
+            drop(server);
+        }
+        Ok(())
+    })();
+    if let Err(e) = churned {
+        return check(Outcome::Fail, format!("loopback connections failed: {}", e));
+    }
+
+    // Let the closes finish and the readers catch up
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let signals = collector.read_and_reset();
+    let churn = signals.connections;
+    let (Some(active), Some(passive), Some(closed)) = (
+        churn.tcp_active_established,
+        churn.tcp_passive_established,
+        churn.tcp_closed,
+    ) else {
+        return check(
+            Outcome::Skip,
+            "sock:inet_sock_set_state not attached".to_string(),
+        );
+    };
+    // Other connections on the host only add to these
+    let accepted = churn.tcp_accepts.is_none_or(|n| n >= CHURN_CONNECTIONS);
+    let outcome = if active >= CHURN_CONNECTIONS
+        && passive >= CHURN_CONNECTIONS
+        && closed >= CHURN_CONNECTIONS
+        && accepted
+        && signals.new_connections_per_interval >= 2 * CHURN_CONNECTIONS
+        && signals.closed_connections_per_interval == closed
+    {
+        Outcome::Pass
+    } else {
+        Outcome::Fail
+    };
+    check(
+        outcome,
+        format!(
+            "{} connections: {:?}, {} new, {} closed",
+            CHURN_CONNECTIONS,
+            churn,
+            signals.new_connections_per_interval,
+            signals.closed_connections_per_interval
+        ),
+    )
+}
+
+const LATEST_INTERVAL: Duration = Duration::from_millis(200);
+const LATEST_MAX_AGE: Duration = Duration::from_secs(1);
+
+/// `latest()` must hand out the snapshot a stream just got, and report it stale
+/// once no stream keeps the publisher reading
+async fn latest_staleness(collector: &CongestionCollector) -> Check {
+    use futures_util::StreamExt;
+    let check = |outcome, detail| Check {
+        scenario: "snapshots",
+        name: "latest() staleness",
+        outcome,
+        detail,
+    };
+    let mut stream = Box::pin(collector.snapshots(LATEST_INTERVAL));
+    if stream.next().await.is_none() {
+        return check(Outcome::Fail, "snapshot stream ended".to_string());
+    }
+    let fresh = collector.latest(LATEST_MAX_AGE);
+    drop(stream);
+    tokio::time::sleep(LATEST_MAX_AGE + LATEST_INTERVAL * 2).await;
+    let stale = collector.latest(LATEST_MAX_AGE);
+
+    let outcome = match (&fresh, &stale) {
+        (LatestSnapshot::Fresh(_), LatestSnapshot::Stale { .. }) => Outcome::Pass,
+        _ => Outcome::Fail,
+    };
+    let describe = |latest: &LatestSnapshot| match latest {
+        LatestSnapshot::Fresh(signals) => format!("fresh ({:?} old)", signals.age()),
+        LatestSnapshot::Stale { age } => format!("stale ({:?} old)", age),
+        LatestSnapshot::Unavailable => "unavailable".to_string(),
+    };
+    check(
+        outcome,
+        format!(
+            "with a stream: {}, without: {}",
+            describe(&fresh),
+            describe(&stale)
+        ),
+    )
+}
+
+// fast: stream cadence, quiet time around the sends, and how far the two sums
+// may differ, from events landing between the reads at either end
+const FAST_INTERVAL: Duration = Duration::from_millis(20);
+const FAST_QUIET: Duration = Duration::from_millis(300);
+const FAST_TOLERANCE: f64 = 0.05;
+
+/// Fast snapshots and interval reads side by side over the same span of UDP
+/// sends: the fast sums must match the interval read's send bytes and drops,
+/// which they don't if either cadence's reset takes counts from the other
+async fn fast_and_slow_cadences(collector: &CongestionCollector, window: Duration) -> Check {
+    use futures_util::StreamExt;
+    let check = |outcome, detail| Check {
+        scenario: "fast",
+        name: "cadences count apart",
+        outcome,
+        detail,
+    };
+    collector.read_and_reset();
+    let end = Instant::now() + FAST_QUIET * 2 + window;
+    let mut stream = Box::pin(collector.fast_snapshots(FAST_INTERVAL));
+    let summed = tokio::spawn(async move {
+        let (mut send_bytes, mut drops, mut snapshots) = (0u64, 0u64, 0u64);
+        while let Some(fast) = stream.next().await {
+            send_bytes += fast.send_bytes;
+            drops += fast.drops;
+            snapshots += 1;
+            if fast.produced_at.is_some_and(|at| at > end) {
+                break;
+            }
+        }
+        (send_bytes, drops, snapshots)
+    });
+
+    tokio::time::sleep(FAST_QUIET).await;
+    let sent = tokio::task::spawn_blocking(move || {
+        let sock = UdpSocket::bind((HOST_ADDR, 0))?;
+        Ok::<_, std::io::Error>(send_paced(&sock, Instant::now() + window))
+    })
+    .await;
+    if !matches!(sent, Ok(Ok(packets)) if packets > 0) {
+        summed.abort();
+        return check(Outcome::Fail, format!("sends failed: {:?}", sent));
+    }
+    tokio::time::sleep(end.saturating_duration_since(Instant::now())).await;
+    let slow = collector.read_and_reset();
+    let Ok((send_bytes, drops, snapshots)) = summed.await else {
+        return check(Outcome::Fail, "fast snapshot task failed".to_string());
+    };
+
+    let close =
+        send_bytes.abs_diff(slow.send_bytes) as f64 <= slow.send_bytes as f64 * FAST_TOLERANCE;
+    // Counted at full cadence, give or take a late tick
+    let expected = ((FAST_QUIET * 2 + window).as_millis() / FAST_INTERVAL.as_millis()) as u64;
+    let outcome = if slow.send_bytes > 0
+        && close
+        && drops.abs_diff(slow.drops) <= DROP_SLACK
+        && snapshots >= expected / 2
+    {
+        Outcome::Pass
+    } else {
+        Outcome::Fail
+    };
+    check(
+        outcome,
+        format!(
+            "{} fast snapshots: {} send bytes, {} drops; interval read: {}, {}",
+            snapshots, send_bytes, drops, slow.send_bytes, slow.drops
+        ),
+    )
+}
+
+// sample() each time around, long enough for readers to start on every CPU
+const SAMPLE_RUNS: usize = 2;
+const SAMPLE_WINDOW: Duration = Duration::from_secs(1);
+
+/// `CongestionCollector::sample()` twice in a row: both must load, and the
+/// kernel must hold as many BPF programs afterwards as before
+async fn repeated_sample() -> Check {
+    let check = |outcome, detail| Check {
+        scenario: "lifecycle",
+        name: "repeated sample()",
+        outcome,
+        detail,
+    };
+    let before = aya::programs::loaded_programs().count();
+    for run in 1..=SAMPLE_RUNS {
+        if let Err(e) = CongestionCollector::sample(SAMPLE_WINDOW).await {
+            return check(Outcome::Fail, format!("run {} failed: {}", run, e));
+        }
+    }
+    let after = aya::programs::loaded_programs().count();
+    let outcome = if after <= before {
+        Outcome::Pass
+    } else {
+        Outcome::Fail
+    };
+    check(
+        outcome,
+        format!(
+            "{} BPF programs loaded before, {} after {} runs",
+            before, after, SAMPLE_RUNS
+        ),
+    )
+}
+
+/// `preflight()` on this host: the profile the matrix runs must be able to
+/// load, and verifying every program must leave nothing loaded behind
+fn preflight_live() -> Check {
+    let check = |outcome, detail| Check {
+        scenario: "lifecycle",
+        name: "preflight",
+        outcome,
+        detail,
+    };
+    let before = aya::programs::loaded_programs().count();
+    let report = CongestionCollector::preflight();
+    let after = aya::programs::loaded_programs().count();
+    let verified = report
+        .probes
+        .iter()
+        .filter(|probe| probe.verified == Some(true))
+        .count();
+    let outcome = if report.can_run && report.map_memory_bytes.is_some() && after <= before {
+        Outcome::Pass
+    } else {
+        Outcome::Fail
+    };
+    check(
+        outcome,
+        format!(
+            "{} of {} programs verified, {} KB of maps, {} remediations; {} BPF programs before, {} after",
+            verified,
+            report.probes.len(),
+            report.map_memory_bytes.unwrap_or(0) / 1024,
+            report.remediations.len(),
+            before,
+            after
+        ),
+    )
+}
+
+const NETNS_SENDS: usize = 2000;
+
+/// Sends over the veth pair from both ends: ours, then the scratch namespace's
+/// from a thread moved into it
+fn send_from_both_namespaces() -> anyhow::Result<()> {
+    let payload = [0u8; 64];
+    let host = UdpSocket::bind((HOST_ADDR, 0))?;
+    for _ in 0..NETNS_SENDS {
+        host.send_to(&payload, (PEER_ADDR, SINK_PORT))?;
+    }
+    let ns = std::fs::File::open(format!("/run/netns/{}", NETNS))?;
+    std::thread::spawn(move || -> std::io::Result<()> {
+        if unsafe { libc::setns(ns.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let peer = UdpSocket::bind((PEER_ADDR, 0))?;
+        for _ in 0..NETNS_SENDS {
+            peer.send_to(&payload, (HOST_ADDR, SINK_PORT))?;
+        }
+        Ok(())
+    })
+    .join()
+    .map_err(|_| anyhow::anyhow!("sender thread panicked"))??;
+    Ok(())
+}
+
+/// A collector with per-namespace attribution, collecting while we send from
+/// both ends of the veth pair, then read once per namespace
+async fn per_netns_after_sends(config: NetnsConfig) -> anyhow::Result<Vec<(String, u64)>> {
+    let config = CollectorConfig::default()
+        .without_calibration()
+        .with_netns(config);
+    let mut collector = CongestionCollector::load_with_config(config)?;
+    collector.start_collection().await?;
+    collector.read_per_netns();
+    send_from_both_namespaces()?;
+    // Let the readers catch up
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let mut namespaces: Vec<(String, u64)> = collector
+        .read_per_netns()
+        .into_iter()
+        .filter(|(_, signals)| signals.send_bytes > 0)
+        .map(|(inode, signals)| {
+            let name = collector
+                .netns_name(inode)
+                .unwrap_or_else(|| inode.to_string());
+            (name, signals.send_bytes)
+        })
+        .collect();
+    namespaces.sort();
+    collector.stop_collection()?;
+    Ok(namespaces)
+}
+
+/// Sends from our namespace and the scratch one under a collector counting
+/// every namespace: both must show up, by name, with sampled send bytes
+async fn netns_breakdown() -> Check {
+    let check = |outcome, detail| Check {
+        scenario: "netns",
+        name: "per-namespace breakdown",
+        outcome,
+        detail,
+    };
+    let namespaces = match per_netns_after_sends(NetnsConfig::default()).await {
+        Ok(namespaces) => namespaces,
+        Err(e) => return check(Outcome::Fail, format!("{}", e)),
+    };
+    let found = |wanted: &str| namespaces.iter().any(|(name, _)| name == wanted);
+    let outcome = if found("host") && found(NETNS) {
+        Outcome::Pass
+    } else {
+        Outcome::Fail
+    };
+    check(outcome, format!("sampled send bytes {:?}", namespaces))
+}
+
+/// The same sends with only the scratch namespace allowed: ours must be left
+/// out of the breakdown
+async fn netns_allow_list() -> Check {
+    let check = |outcome, detail| Check {
+        scenario: "netns",
+        name: "allow-list",
+        outcome,
+        detail,
+    };
+    let config = NetnsConfig {
+        allow: Some(vec![NETNS.to_string()]),
+        ..Default::default()
+    };
+    let namespaces = match per_netns_after_sends(config).await {
+        Ok(namespaces) => namespaces,
+        Err(e) => return check(Outcome::Fail, format!("{}", e)),
+    };
+    let outcome = match namespaces.as_slice() {
+        [(name, _)] if name == NETNS => Outcome::Pass,
+        _ => Outcome::Fail,
+    };
+    check(
+        outcome,
+        format!("allowed {}, sampled send bytes {:?}", NETNS, namespaces),
+    )
+}
+
+// IP_TOS of the two classes' sockets: EF (DSCP 46) and CS1 (DSCP 8)
+const INTERACTIVE_TOS: libc::c_int = 46 << 2;
+const BULK_TOS: libc::c_int = 8 << 2;
+
+fn classes() -> TrafficClassConfig {
+    TrafficClassConfig::new(vec![
+        TrafficClass::dscp("interactive", &[46]),
+        TrafficClass::dscp("bulk", &[8]),
+    ])
+}
+
+/// Sends over the veth pair from a socket per class, marked with IP_TOS like a
+/// deployment would: each class must see its own sampled send bytes
+async fn traffic_class_split() -> Check {
+    let check = |outcome, detail| Check {
+        scenario: "classes",
+        name: "per-class breakdown",
+        outcome,
+        detail,
+    };
+    let result = async {
+        let config = CollectorConfig::default()
+            .without_calibration()
+            .with_traffic_classes(classes());
+        let mut collector = CongestionCollector::load_with_config(config)?;
+        collector.start_collection().await?;
+        collector.read_per_class();
+        let payload = [0u8; 64];
+        for tos in [INTERACTIVE_TOS, BULK_TOS] {
+            let sock = UdpSocket::bind((HOST_ADDR, 0))?;
+            let ret = unsafe {
+                libc::setsockopt(
+                    sock.as_raw_fd(),
+                    libc::IPPROTO_IP,
+                    libc::IP_TOS,
+                    &tos as *const libc::c_int as *const libc::c_void,
+                    std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+                )
+            };
+            if ret != 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            for _ in 0..NETNS_SENDS {
+                sock.send_to(&payload, (PEER_ADDR, SINK_PORT))?;
+            }
+        }
+        // Let the readers catch up
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let mut per_class: Vec<(String, u64)> = collector
+            .read_per_class()
+            .into_iter()
+            .map(|(class, signals)| (class, signals.send_bytes))
+            .collect();
+        per_class.sort();
+        collector.stop_collection()?;
+        anyhow::Ok(per_class)
+    };
+    let per_class = match result.await {
+        Ok(per_class) => per_class,
+        Err(e) => return check(Outcome::Fail, format!("{}", e)),
+    };
+    let sent = |wanted: &str| {
+        per_class
+            .iter()
+            .any(|(class, bytes)| class == wanted && *bytes > 0)
+    };
+    let outcome = if sent("interactive") && sent("bulk") {
+        Outcome::Pass
+    } else {
+        Outcome::Fail
+    };
+    check(outcome, format!("sampled send bytes {:?}", per_class))
+}
+
+const EGRESS_SENDS: u64 = 2000;
+const EGRESS_PAYLOAD: usize = 1000;
+// Ethernet, IPv4 and UDP headers: tc egress sees the whole frame
+const EGRESS_FRAME: u64 = EGRESS_PAYLOAD as u64 + 14 + 20 + 8;
+// ARP and IPv6 neighbour traffic the veth carries on its own
+const EGRESS_NOISE_PACKETS: u64 = 20;
+
+/// tc egress on our end of the veth pair against datagrams of a known size:
+/// every packet counted, each at its frame length, give or take the link's own
+/// chatter
+async fn veth_egress_counts() -> Check {
+    let check = |outcome, detail| Check {
+        scenario: "egress",
+        name: "exact veth egress",
+        outcome,
+        detail,
+    };
+    let result = async {
+        let config = CollectorConfig {
+            egress_interfaces: vec![HOST_DEV.to_string()],
+            ..CollectorConfig::default().without_calibration()
+        };
+        let mut collector = CongestionCollector::load_with_config(config)?;
+        collector.start_collection().await?;
+        collector.read_and_reset();
+        let sock = UdpSocket::bind((HOST_ADDR, 0))?;
+        let payload = [0u8; EGRESS_PAYLOAD];
+        for _ in 0..EGRESS_SENDS {
+            sock.send_to(&payload, (PEER_ADDR, SINK_PORT))?;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let egress = collector
+            .read_and_reset()
+            .egress
+            .into_iter()
+            .find(|egress| egress.interface == HOST_DEV);
+        collector.stop_collection()?;
+        anyhow::Ok(egress)
+    };
+    let egress = match result.await {
+        Ok(Some(egress)) => egress,
+        Ok(None) => return check(Outcome::Fail, format!("tc egress not on {}", HOST_DEV)),
+        Err(e) => return check(Outcome::Fail, format!("{}", e)),
+    };
+    let packets = egress.egress_packets_exact;
+    let bytes = egress.egress_bytes_exact;
+    let expected_bytes = EGRESS_SENDS * EGRESS_FRAME;
+    // Noise packets are small, count them at a full frame to be safe
+    let noise_bytes = EGRESS_NOISE_PACKETS * EGRESS_FRAME;
+    let outcome = if (EGRESS_SENDS..=EGRESS_SENDS + EGRESS_NOISE_PACKETS).contains(&packets)
+        && (expected_bytes..=expected_bytes + noise_bytes).contains(&bytes)
+    {
+        Outcome::Pass
+    } else {
+        Outcome::Fail
+    };
+    check(
+        outcome,
+        format!(
+            "{} packets / {} bytes for {} x {}B frames",
+            packets, bytes, EGRESS_SENDS, EGRESS_FRAME
+        ),
+    )
+}
+
+// Sends for the live comparison: 300 primary samples of 1200 bytes, and a
+// candidate ratio that isn't a multiple of 100, so some sends are its alone
+const COMPARE_SENDS: usize = 30_000;
+const COMPARE_PAYLOAD: usize = 1200;
+const COMPARE_RATIO: u64 = 150;
+// Per-CPU counter phase and whatever else the host sent in the window
+const COMPARE_TOLERANCE: f64 = 0.10;
+
+/// A known number of equal sends with the comparison sampler on: both
+/// estimates land near what was sent, and sends only the candidate took
+/// don't inflate the primary one
+async fn sampler_comparison_live() -> Check {
+    let check = |outcome, detail| Check {
+        scenario: "sampling",
+        name: "dual sampler estimates",
+        outcome,
+        detail,
+    };
+    let result = async {
+        let config = CollectorConfig::default()
+            .without_calibration()
+            .with_sampler_comparison(COMPARE_RATIO);
+        let mut collector = CongestionCollector::load_with_config(config)?;
+        collector.start_collection().await?;
+        collector.read_and_reset();
+        let sock = UdpSocket::bind((HOST_ADDR, 0))?;
+        let payload = [0u8; COMPARE_PAYLOAD];
+        for _ in 0..COMPARE_SENDS {
+            sock.send_to(&payload, (PEER_ADDR, SINK_PORT))?;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let estimates = collector.read_and_reset().sampler_comparison;
+        collector.stop_collection()?;
+        anyhow::Ok(estimates)
+    };
+    let estimates = match result.await {
+        Ok(Some(estimates)) => estimates,
+        Ok(None) => return check(Outcome::Fail, "no estimates in the interval".to_string()),
+        Err(e) => return check(Outcome::Fail, format!("{}", e)),
+    };
+    let sent = (COMPARE_SENDS * COMPARE_PAYLOAD) as f64;
+    let near = |bytes: u64| (bytes as f64 - sent).abs() / sent <= COMPARE_TOLERANCE;
+    let outcome = if estimates.candidate_ratio == COMPARE_RATIO
+        && estimates.candidate_samples > 0
+        && near(estimates.primary_send_bytes)
+        && near(estimates.candidate_send_bytes)
+    {
+        Outcome::Pass
+    } else {
+        Outcome::Fail
+    };
+    check(
+        outcome,
+        format!(
+            "sent {} KB, primary {} KB, 1-in-{} {} KB from {} samples",
+            sent as u64 / 1024,
+            estimates.primary_send_bytes / 1024,
+            estimates.candidate_ratio,
+            estimates.candidate_send_bytes / 1024,
+            estimates.candidate_samples
+        ),
+    )
+}
+
+/// A known number of equal sends: the interval's scale comes from the kernel
+/// sampler's count of sends seen, and the estimate lands near what was sent
+async fn sample_scale_live() -> Check {
+    let check = |outcome, detail| Check {
+        scenario: "sampling",
+        name: "measured scale",
+        outcome,
+        detail,
+    };
+    let result = async {
+        let config = CollectorConfig::default().without_calibration();
+        let mut collector = CongestionCollector::load_with_config(config)?;
+        collector.start_collection().await?;
+        collector.read_and_reset();
+        let sock = UdpSocket::bind((HOST_ADDR, 0))?;
+        let payload = [0u8; COMPARE_PAYLOAD];
+        for _ in 0..COMPARE_SENDS {
+            sock.send_to(&payload, (PEER_ADDR, SINK_PORT))?;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let signals = collector.read_and_reset();
+        collector.stop_collection()?;
+        anyhow::Ok(signals.estimated)
+    };
+    let estimated = match result.await {
+        Ok(estimated) => estimated,
+        Err(e) => return check(Outcome::Fail, format!("{}", e)),
+    };
+    let scale = estimated.scale;
+    let sent = (COMPARE_SENDS * COMPARE_PAYLOAD) as f64;
+    let from_counters = scale.sends_seen.is_some_and(|seen| {
+        seen >= COMPARE_SENDS as u64
+            && scale.send_factor == seen as f64 / scale.sends_sampled as f64
+    });
+    let outcome = if from_counters
+        && !scale.is_nominal()
+        && (estimated.send_bytes as f64 - sent).abs() / sent <= COMPARE_TOLERANCE
+    {
+        Outcome::Pass
+    } else {
+        Outcome::Fail
+    };
+    check(
+        outcome,
+        format!(
+            "sent {} KB, estimated {} KB: {:?} seen, {} sampled, x{:.1}",
+            sent as u64 / 1024,
+            estimated.send_bytes / 1024,
+            scale.sends_seen,
+            scale.sends_sampled,
+            scale.send_factor
+        ),
+    )
+}
+
+/// A collector loaded while another is: refused without `allow_shared`, and
+/// with it handed the first one's intervals. Once that one is dropped the
+/// handle goes inactive and a load owns its probes again
+fn second_collector() -> Check {
+    let check = |outcome, detail| Check {
+        scenario: "lifecycle",
+        name: "second collector",
+        outcome,
+        detail,
+    };
+    let config = || {
+        CollectorConfig::default()
+            .without_calibration()
+            .without_accuracy_check()
+    };
+    let first = match CongestionCollector::load_with_config(config()) {
+        Ok(collector) => collector,
+        Err(e) => return check(Outcome::Fail, format!("first load failed: {}", e)),
+    };
+    let first_read = first.read_and_reset();
+
+    let refused = match CongestionCollector::load_with_config(config()) {
+        Ok(_) => return check(Outcome::Fail, "second load attached".to_string()),
+        Err(e) => e.downcast_ref::<CollectorError>().cloned(),
+    };
+    let shared = match CongestionCollector::load_or_share(config().allow_shared()) {
+        Ok(LoadedCollector::Shared(shared)) => shared,
+        Ok(LoadedCollector::Owned(_)) => {
+            return check(Outcome::Fail, "allow_shared load attached".to_string())
+        }
+        Err(e) => return check(Outcome::Fail, format!("allow_shared load failed: {}", e)),
+    };
+    let seen = shared.recent_intervals(1);
+    let active_before = shared.is_active();
+    drop(first);
+    let active_after = shared.is_active();
+    let reloaded = matches!(
+        CongestionCollector::load_or_share(config().allow_shared()),
+        Ok(LoadedCollector::Owned(_))
+    );
+
+    let outcome = if refused == Some(CollectorError::AlreadyActive { system_wide: false })
+        && seen.last().map(|s| s.produced_at) == Some(first_read.produced_at)
+        && active_before
+        && !active_after
+        && reloaded
+    {
+        Outcome::Pass
+    } else {
+        Outcome::Fail
+    };
+    check(
+        outcome,
+        format!(
+            "refused with {:?}, shared {} interval(s), active {} then {}, reloaded owned {}",
+            refused,
+            seen.len(),
+            active_before,
+            active_after,
+            reloaded
+        ),
+    )
+}
+
+/// Drop a collector with a state file and load another: the second one
+/// continues the first one's totals
+fn state_restored_across_restart() -> Check {
+    let check = |outcome, detail| Check {
+        scenario: "lifecycle",
+        name: "state restored",
+        outcome,
+        detail,
+    };
+    let path = std::env::temp_dir().join(format!("{}_state", NETNS));
+    let _ = std::fs::remove_file(&path);
+    let config = || {
+        CollectorConfig::default()
+            .without_calibration()
+            .without_accuracy_check()
+            .with_state_file(StateFileConfig::new(&path))
+    };
+
+    let first = match CongestionCollector::load_with_config(config()) {
+        Ok(collector) => collector,
+        Err(e) => return check(Outcome::Fail, format!("first load failed: {}", e)),
+    };
+    let fresh = !first.health().restored_from_state;
+    first.read_and_reset();
+    first.read_and_reset();
+    let saved = first.cumulative();
+    drop(first);
+
+    let second = match CongestionCollector::load_with_config(config()) {
+        Ok(collector) => collector,
+        Err(e) => return check(Outcome::Fail, format!("second load failed: {}", e)),
+    };
+    let restored = second.health().restored_from_state;
+    second.read_and_reset();
+    let continued = second.cumulative();
+    drop(second);
+    let _ = std::fs::remove_file(&path);
+
+    let outcome = if fresh
+        && restored
+        && continued.intervals == saved.intervals + 1
+        && continued.event_count >= saved.event_count
+    {
+        Outcome::Pass
+    } else {
+        Outcome::Fail
+    };
+    check(
+        outcome,
+        format!(
+            "restored {}, intervals {} then {}, events {} then {}",
+            restored,
+            saved.intervals,
+            continued.intervals,
+            saved.event_count,
+            continued.event_count
+        ),
+    )
+}
+
+/// `probe_socket_now()` on a socket registered with the running collector
+/// names that socket and reads a send buffer; unregistered it fails
+fn registered_socket_probe(collector: &CongestionCollector) -> Check {
+    let check = |outcome, detail| Check {
+        scenario: "socket probes",
+        name: "probe_socket_now()",
+        outcome,
+        detail,
+    };
+    let socket = match UdpSocket::bind("127.0.0.1:0") {
+        Ok(socket) => socket,
+        Err(e) => return check(Outcome::Fail, format!("bind failed: {}", e)),
+    };
+    let handle = match collector.register_socket(&socket) {
+        Ok(handle) => handle,
+        Err(e) => return check(Outcome::Skip, format!("can't register: {}", e)),
+    };
+    let probed = collector.probe_socket_now(&handle);
+    let listed = collector.probe_registered().len();
+    collector.unregister_socket(handle);
+    let after = collector.probe_socket_now(&handle);
+
+    let (outcome, detail) = match probed {
+        Ok(sample) => (
+            if sample.socket_id == handle.socket_id() && sample.sndbuf > 0 && after.is_err() {
+                Outcome::Pass
+            } else {
+                Outcome::Fail
+            },
+            format!(
+                "sndbuf {}, wmem {}, {} in probe_registered(), unregistered: {}",
+                sample.sndbuf,
+                sample.wmem_alloc,
+                listed,
+                after.map_or_else(|e| e.to_string(), |_| "still probed".to_string())
+            ),
+        ),
+        Err(e) => (Outcome::Fail, format!("probe failed: {}", e)),
+    };
+    check(outcome, detail)
+}
+
+// reader wakeups: loopback datagrams per mode, and how long they get to arrive
+const WAKEUP_SENDS: usize = 20_000;
+const WAKEUP_SETTLE: Duration = Duration::from_millis(500);
+
+/// (events read, wakeups) of a collector whose readers wake as `wakeup` says,
+/// over a burst of loopback sends
+async fn wakeups_over_sends(wakeup: ReaderWakeup) -> anyhow::Result<(u64, u64)> {
+    let config = CollectorConfig::default()
+        .without_calibration()
+        .without_accuracy_check()
+        .with_loopback()
+        .with_reader_wakeup(wakeup);
+    let mut collector = CongestionCollector::load_with_config(config)?;
+    collector.start_collection().await?;
+    let sink = UdpSocket::bind("127.0.0.1:0")?;
+    let sender = UdpSocket::bind("127.0.0.1:0")?;
+    sender.connect(sink.local_addr()?)?;
+    let payload = [0u8; 64];
+    for _ in 0..WAKEUP_SENDS {
+        sender.send(&payload)?;
+    }
+    tokio::time::sleep(WAKEUP_SETTLE).await;
+    let stats = collector.reader_stats();
+    collector.stop_collection()?;
+    Ok((stats.events_read, stats.reader_wakeups))
+}
+
+/// The same sends under each `ReaderWakeup`: every mode must read events, and
+/// the batching ones must take more of them per wakeup than waking per event
+async fn reader_wakeups() -> Check {
+    let check = |outcome, detail| Check {
+        scenario: "readers",
+        name: "wakeup batching",
+        outcome,
+        detail,
+    };
+    let modes = [
+        ("per event", ReaderWakeup::PerEvent),
+        (
+            "watermark 32",
+            ReaderWakeup::Watermark {
+                watermark: WakeupWatermark::Events(32),
+                max_delay: Duration::from_millis(10),
+            },
+        ),
+        ("timer 10ms", ReaderWakeup::Timer(Duration::from_millis(10))),
+    ];
+    let mut per_wakeup = Vec::with_capacity(modes.len());
+    let mut details = Vec::with_capacity(modes.len());
+    for (name, wakeup) in modes {
+        let (events, wakeups) = match wakeups_over_sends(wakeup).await {
+            Ok(counts) => counts,
+            Err(e) => return check(Outcome::Fail, format!("{}: {}", name, e)),
+        };
+        per_wakeup.push((events > 0).then(|| events as f64 / wakeups.max(1) as f64));
+        details.push(format!("{} {} events/{} wakeups", name, events, wakeups));
+    }
+
+    let outcome = match per_wakeup[..] {
+        [Some(per_event), Some(watermark), Some(timer)]
+            if watermark > per_event && timer > per_event =>
+        {
+            Outcome::Pass
+        }
+        _ => Outcome::Fail,
+    };
+    check(outcome, details.join(", "))
+}
+
+/// `dump_diagnostics_redacted()` with everything per flow redacted: the manifest
+/// must match its model and every file it lists parse as JSON, and no socket id
+/// may be left
+fn diagnostics_bundle(collector: &CongestionCollector) -> Check {
+    use ebpf_congestion_signals::check_export;
+    let check = |outcome, detail| Check {
+        scenario: "diagnostics",
+        name: "bundle parses",
+        outcome,
+        detail,
+    };
+    let dir = std::env::temp_dir().join(format!("{}_diagnostics", NETNS));
+    let _ = std::fs::remove_dir_all(&dir);
+    let result = (|| -> anyhow::Result<String> {
+        // With a governor's file too where there is one
+        #[cfg(feature = "governor")]
+        let written = collector.dump_diagnostics_governed(
+            &dir,
+            &Redaction::all(),
+            &ebpf_congestion_signals::Governor::new(Default::default(), 12_500_000),
+        )?;
+        #[cfg(not(feature = "governor"))]
+        let written = collector.dump_diagnostics_redacted(&dir, &Redaction::all())?;
+        let manifest = std::fs::read_to_string(dir.join("manifest.json"))?;
+        if !json_parses(&manifest) {
+            anyhow::bail!("manifest.json doesn't parse");
+        }
+        if let Err(problems) = check_export("manifest", &manifest) {
+            anyhow::bail!("manifest.json: {}", problems.join(", "));
+        }
+        // Names as the manifest lists them, `"name":"health.json"`
+        let listed: Vec<&str> = manifest
+            .split("\"name\":\"")
+            .skip(1)
+            .filter_map(|rest| rest.split('"').next())
+            .collect();
+        if cfg!(feature = "governor") && !listed.contains(&"governor.json") {
+            anyhow::bail!("manifest doesn't list governor.json");
+        }
+        if listed.len() + 1 != written.len() {
+            anyhow::bail!(
+                "{} files written, manifest lists {}",
+                written.len(),
+                listed.len()
+            );
+        }
+        for name in &listed {
+            let contents = std::fs::read_to_string(dir.join(name))?;
+            if !json_parses(&contents) {
+                anyhow::bail!("{} doesn't parse", name);
+            }
+            if contents.contains("\"socket_id\":") && !redacted(&contents) {
+                anyhow::bail!("{} has socket ids despite redaction", name);
+            }
+        }
+        Ok(format!(
+            "manifest and {} files parse, socket ids redacted",
+            listed.len()
+        ))
+    })();
+    let _ = std::fs::remove_dir_all(&dir);
+    match result {
+        Ok(detail) => check(Outcome::Pass, detail),
+        Err(e) => check(Outcome::Fail, e.to_string()),
+    }
+}
+
+/// Every `"socket_id":` in `json` is followed by null
+fn redacted(json: &str) -> bool {
+    json.split("\"socket_id\":")
+        .skip(1)
+        .all(|rest| rest.starts_with("null"))
+}
+
+/// Whether `text` is exactly one JSON value, surrounding whitespace aside
+fn json_parses(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    let mut at = 0;
+    json_value(bytes, &mut at) && {
+        skip_whitespace(bytes, &mut at);
+        at == bytes.len()
+    }
+}
+
+fn skip_whitespace(bytes: &[u8], at: &mut usize) {
+    while bytes.get(*at).is_some_and(|b| b.is_ascii_whitespace()) {
+        *at += 1;
+    }
+}
+
+fn json_value(bytes: &[u8], at: &mut usize) -> bool {
+    skip_whitespace(bytes, at);
+    match bytes.get(*at) {
+        Some(b'{') => json_list(bytes, at, b'}', true),
+        Some(b'[') => json_list(bytes, at, b']', false),
+        Some(b'"') => json_str(bytes, at),
+        Some(b't') => json_literal(bytes, at, "true"),
+        Some(b'f') => json_literal(bytes, at, "false"),
+        Some(b'n') => json_literal(bytes, at, "null"),
+        Some(b'-' | b'0'..=b'9') => {
+            let start = *at;
+            while bytes
+                .get(*at)
+                .is_some_and(|b| b.is_ascii_digit() || b"+-.eE".contains(b))
+            {
+                *at += 1;
+            }
+            std::str::from_utf8(&bytes[start..*at]).is_ok_and(|n| n.parse::<f64>().is_ok())
+        }
+        _ => false,
+    }
+}
+
+/// An object (`keyed`) or array, `at` on its opening bracket
+fn json_list(bytes: &[u8], at: &mut usize, close: u8, keyed: bool) -> bool {
+    *at += 1;
+    skip_whitespace(bytes, at);
+    if bytes.get(*at) == Some(&close) {
+        *at += 1;
+        return true;
+    }
+    loop {
+        if keyed {
+            skip_whitespace(bytes, at);
+            if !json_str(bytes, at) {
+                return false;
+            }
+            skip_whitespace(bytes, at);
+            if bytes.get(*at) != Some(&b':') {
+                return false;
+            }
+            *at += 1;
+        }
+        if !json_value(bytes, at) {
+            return false;
+        }
+        skip_whitespace(bytes, at);
+        match bytes.get(*at) {
+            Some(b',') => *at += 1,
+            Some(&b) if b == close => {
+                *at += 1;
+                return true;
+            }
+            _ => return false,
+        }
+    }
+}
+
+fn json_str(bytes: &[u8], at: &mut usize) -> bool {
+    if bytes.get(*at) != Some(&b'"') {
+        return false;
+    }
+    *at += 1;
+    while let Some(&b) = bytes.get(*at) {
+        *at += 1;
+        match b {
+            b'"' => return true,
+            b'\\' => *at += 1,
+            b if b < 0x20 => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+fn json_literal(bytes: &[u8], at: &mut usize, literal: &str) -> bool {
+    let matched = bytes[*at..].starts_with(literal.as_bytes());
+    *at += literal.len();
+    matched
+}
+
+/// Run the whole matrix, returns whether every non-skipped check passed
+pub async fn run_matrix(
+    collector: &CongestionCollector,
+    window: Duration,
+    reaction: &ReactionBound,
+) -> anyhow::Result<bool> {
+    println!(
+        "Setting up veth pair {} <-> {} (netns {})...",
+        HOST_DEV, PEER_DEV, NETNS
+    );
+    let topology = Topology::create()?;
+
+    println!("Measuring idle baseline ({}s)...", window.as_secs());
+    collector.read_and_reset();
+    tokio::time::sleep(window).await;
+    let idle = collector.read_and_reset();
+
+    let mut checks = Vec::new();
+    for scenario in SCENARIOS {
+        println!(
+            "Running {} ({}) for {}s...",
+            scenario.name,
+            scenario.netem.unwrap_or("no qdisc"),
+            window.as_secs()
+        );
+        let m = measure(collector, &topology, scenario, window).await?;
+        checks.extend(evaluate(scenario, &m, &idle));
+    }
+    println!(
+        "Running reaction (TCP, {} from {}s) for {}s...",
+        REACTION_NETEM,
+        window.as_secs() / 2,
+        window.as_secs()
+    );
+    checks.extend(reaction_latency(collector, &topology, window, reaction).await?);
+
+    topology.apply(None)?;
+    checks.push(loopback_excluded(collector).await);
+    checks.push(diagnostics_bundle(collector));
+    checks.push(own_cgroup_attributed());
+    checks.push(calibration_passed(collector));
+    checks.push(session_arithmetic(collector));
+    checks.push(co_attachment_detected(collector).await);
+    checks.push(sampler_comparison_live().await);
+    checks.push(sample_scale_live().await);
+    checks.push(connection_churn_live(collector).await);
+    checks.push(repeated_sample().await);
+    checks.push(preflight_live());
+    checks.push(second_collector());
+    checks.push(state_restored_across_restart());
+    checks.push(reader_wakeups().await);
+    checks.push(registered_socket_probe(collector));
+    checks.push(latest_staleness(collector).await);
+    checks.push(fast_and_slow_cadences(collector, window).await);
+    checks.push(netns_breakdown().await);
+    checks.push(netns_allow_list().await);
+    checks.push(traffic_class_split().await);
+    checks.push(veth_egress_counts().await);
+    print_table(&checks);
+
+    let failed = checks
+        .iter()
+        .filter(|c| matches!(c.outcome, Outcome::Fail))
+        .count();
+    if failed == 0 {
+        println!("\n✓ All scenario checks passed");
+    } else {
+        println!("\n✗ {} scenario check(s) failed", failed);
+    }
+
+    Ok(failed == 0)
+}
@@ -0,0 +1,499 @@
+//Bonded and teamed links. The kernel reports NAPI polls, drops and link speed
+//against the physical slaves, while the qdisc and the traffic's route sit on
+//the master: per device, one logical link shows up as a row per slave and
+//none for itself. This finds which devices are slaves of which master, in
+//which mode, so `read_per_interface` can put their rows under it and
+//`interface_speed` can answer for the master.
+//
+//Bonds are in sysfs: `<bond>/bonding/{mode,slaves,active_slave}`. A team
+//device lists its ports as `lower_<port>` links like any stacked device
+//(VLANs, macvlans), so the candidates with lower links that are neither a
+//bond nor a bridge are asked over the team generic netlink family for their
+//`mode` and `activeport` options; devices that aren't teams answer with an
+//error and are left out. Without CAP_NET_ADMIN the team family refuses and
+//teams go unseen. The sysfs reads are a few files per device, cheap enough
+//for every per-interface read.
+
+use std::io;
+use std::path::Path;
+
+const SYS_CLASS_NET: &str = "/sys/class/net";
+
+/// How a master spreads traffic over its slaves, from bonding's `mode` or the
+/// team's `mode` option
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BondMode {
+    /// balance-rr, team roundrobin
+    RoundRobin,
+    /// active-backup, team activebackup: one slave carries everything
+    ActiveBackup,
+    /// balance-xor
+    Xor,
+    /// broadcast, every packet on every slave
+    Broadcast,
+    /// 802.3ad: flows hashed over the aggregator's slaves
+    Lacp,
+    /// balance-tlb
+    Tlb,
+    /// balance-alb
+    Alb,
+    /// team loadbalance
+    LoadBalance,
+    /// team random
+    Random,
+    Unknown,
+}
+
+impl BondMode {
+    /// From the first word of `bonding/mode`, e.g. "802.3ad 4"
+    pub fn from_bonding(mode: &str) -> Self {
+        match mode.split_whitespace().next().unwrap_or("") {
+            "balance-rr" => Self::RoundRobin,
+            "active-backup" => Self::ActiveBackup,
+            "balance-xor" => Self::Xor,
+            "broadcast" => Self::Broadcast,
+            "802.3ad" => Self::Lacp,
+            "balance-tlb" => Self::Tlb,
+            "balance-alb" => Self::Alb,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// From the team's `mode` option
+    pub fn from_team(mode: &str) -> Self {
+        match mode {
+            "roundrobin" => Self::RoundRobin,
+            "activebackup" => Self::ActiveBackup,
+            "broadcast" => Self::Broadcast,
+            "loadbalance" => Self::LoadBalance,
+            "random" => Self::Random,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BondDriver {
+    Bonding,
+    Team,
+}
+
+/// One slave (team port) of a master, as of the topology's read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BondSlave {
+    pub name: String,
+    /// Link speed in bytes/sec, None when the driver reports none
+    pub speed: Option<u64>,
+    /// operstate "up"
+    pub up: bool,
+    /// Carrying traffic: the active slave under active-backup, any slave
+    /// that's up otherwise
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BondMaster {
+    pub name: String,
+    pub driver: BondDriver,
+    pub mode: BondMode,
+    pub slaves: Vec<BondSlave>,
+}
+
+impl BondMaster {
+    /// What the master can send, bytes/sec: the active slave's speed under
+    /// active-backup, the slowest slave's under broadcast, the slaves' sum
+    /// under the balancing modes. Slaves that are down don't count. Under
+    /// 802.3ad and the hash modes it's the aggregate; one flow still gets one
+    /// slave's speed. None when no slave that counts reports a speed, or the
+    /// mode is unknown
+    pub fn aggregate_speed(&self) -> Option<u64> {
+        let mut up = self
+            .slaves
+            .iter()
+            .filter(|slave| slave.up)
+            .filter_map(|slave| slave.speed);
+        match self.mode {
+            BondMode::ActiveBackup => self
+                .slaves
+                .iter()
+                .find(|slave| slave.active && slave.up)
+                .and_then(|slave| slave.speed),
+            BondMode::Broadcast => up.min(),
+            BondMode::Unknown => None,
+            _ => up.next().map(|first| first + up.sum::<u64>()),
+        }
+    }
+
+    pub fn slave(&self, name: &str) -> Option<&BondSlave> {
+        self.slaves.iter().find(|slave| slave.name == name)
+    }
+}
+
+/// The host's bonds and teams, see `CongestionCollector::read_per_interface`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BondTopology {
+    /// By name
+    pub masters: Vec<BondMaster>,
+}
+
+impl BondTopology {
+    /// This network namespace's bonds and teams. Empty when sysfs can't be
+    /// read
+    pub fn read() -> Self {
+        Self::read_with(Path::new(SYS_CLASS_NET), team_options)
+    }
+
+    /// Bonds under a `/sys/class/net`-like directory, e.g. a test fixture.
+    /// Teams need netlink and are left out
+    pub fn read_from(root: &Path) -> Self {
+        Self::read_with(root, |_| None)
+    }
+
+    fn read_with(root: &Path, team: impl Fn(u32) -> Option<TeamOptions>) -> Self {
+        let Ok(entries) = std::fs::read_dir(root) else {
+            return Self::default();
+        };
+        let mut names: Vec<String> = entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect();
+        names.sort();
+        let read = |path: &Path| {
+            std::fs::read_to_string(path)
+                .map(|s| s.trim().to_string())
+                .unwrap_or_default()
+        };
+        let slave = |name: &str, active: bool| {
+            let dir = root.join(name);
+            BondSlave {
+                name: name.to_string(),
+                speed: sysfs_speed(root, name),
+                up: read(&dir.join("operstate")) == "up",
+                active,
+            }
+        };
+        let mut masters = Vec::new();
+        for name in &names {
+            let dir = root.join(name);
+            let bonding = dir.join("bonding");
+            if bonding.is_dir() {
+                let mode = BondMode::from_bonding(&read(&bonding.join("mode")));
+                let active = read(&bonding.join("active_slave"));
+                let mut bond = BondMaster {
+                    name: name.clone(),
+                    driver: BondDriver::Bonding,
+                    mode,
+                    slaves: read(&bonding.join("slaves"))
+                        .split_whitespace()
+                        .map(|port| slave(port, mode != BondMode::ActiveBackup || port == active))
+                        .collect(),
+                };
+                bond.slaves.iter_mut().for_each(|s| s.active &= s.up);
+                masters.push(bond);
+                continue;
+            }
+            if dir.join("bridge").is_dir() {
+                continue;
+            }
+            let ports = lower_devices(&dir);
+            if ports.is_empty() {
+                continue;
+            }
+            let Some(options) = read(&dir.join("ifindex")).parse().ok().and_then(&team) else {
+                continue;
+            };
+            let mode = BondMode::from_team(&options.mode);
+            let ifindex = |port: &str| read(&root.join(port).join("ifindex")).parse().ok();
+            let mut team = BondMaster {
+                name: name.clone(),
+                driver: BondDriver::Team,
+                mode,
+                slaves: ports
+                    .iter()
+                    .map(|port| {
+                        let active = mode != BondMode::ActiveBackup
+                            || options.active_port.is_some()
+                                && ifindex(port) == options.active_port;
+                        slave(port, active)
+                    })
+                    .collect(),
+            };
+            team.slaves.iter_mut().for_each(|s| s.active &= s.up);
+            masters.push(team);
+        }
+        Self { masters }
+    }
+
+    pub fn master(&self, name: &str) -> Option<&BondMaster> {
+        self.masters.iter().find(|master| master.name == name)
+    }
+
+    /// Link speed of `interface` in bytes/sec: a master's aggregate, otherwise
+    /// its own `speed`. The files are read again, the topology's slave
+    /// speeds are as of its read
+    pub fn interface_speed(&self, interface: &str) -> Option<u64> {
+        match self.master(interface) {
+            Some(master) => master.aggregate_speed(),
+            None => sysfs_speed(Path::new(SYS_CLASS_NET), interface),
+        }
+    }
+
+    /// The master `slave` is enslaved to
+    pub fn master_of(&self, slave: &str) -> Option<&BondMaster> {
+        self.masters
+            .iter()
+            .find(|master| master.slave(slave).is_some())
+    }
+}
+
+/// Link speed of `interface` under `root` in bytes/sec, None when the driver
+/// doesn't report one (veth, tun, a link that's down report -1)
+fn sysfs_speed(root: &Path, interface: &str) -> Option<u64> {
+    let path = root.join(interface).join("speed");
+    let mbps: i64 = std::fs::read_to_string(path).ok()?.trim().parse().ok()?;
+    (mbps > 0).then(|| mbps as u64 * 1_000_000 / 8)
+}
+
+// The `lower_<name>` links of a device, its ports
+fn lower_devices(dir: &Path) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut lower: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            name.strip_prefix("lower_").map(str::to_string)
+        })
+        .collect();
+    lower.sort();
+    lower
+}
+
+struct TeamOptions {
+    mode: String,
+    active_port: Option<u32>,
+}
+
+// linux/genetlink.h and linux/if_team.h
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+const TEAM_GENL_VERSION: u8 = 1;
+const TEAM_CMD_OPTIONS_GET: u8 = 2;
+const TEAM_ATTR_TEAM_IFINDEX: u16 = 1;
+const TEAM_ATTR_LIST_OPTION: u16 = 2;
+const TEAM_ATTR_ITEM_OPTION: u16 = 1;
+const TEAM_ATTR_OPTION_NAME: u16 = 1;
+const TEAM_ATTR_OPTION_DATA: u16 = 4;
+// Attribute type without NLA_F_NESTED and NLA_F_NET_BYTEORDER
+const NLA_TYPE_MASK: u16 = 0x3fff;
+
+// The team options of `ifindex`, None when it isn't a team or netlink refused
+fn team_options(ifindex: u32) -> Option<TeamOptions> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::NETLINK_GENERIC,
+        )
+    };
+    if fd < 0 {
+        return None;
+    }
+    let options = (|| {
+        let family = genl_request(
+            fd,
+            libc::GENL_ID_CTRL as u16,
+            CTRL_CMD_GETFAMILY,
+            1,
+            &attribute(CTRL_ATTR_FAMILY_NAME, b"team\0"),
+        )
+        .ok()?
+        .iter()
+        .flat_map(|payload| attributes(payload))
+        .find(|&(kind, data)| kind == CTRL_ATTR_FAMILY_ID && data.len() >= 2)
+        .map(|(_, data)| u16::from_ne_bytes([data[0], data[1]]))?;
+        let replies = genl_request(
+            fd,
+            family,
+            TEAM_CMD_OPTIONS_GET,
+            TEAM_GENL_VERSION,
+            &attribute(TEAM_ATTR_TEAM_IFINDEX, &ifindex.to_ne_bytes()),
+        )
+        .ok()?;
+        let mut options = TeamOptions {
+            mode: String::new(),
+            active_port: None,
+        };
+        let items = replies
+            .iter()
+            .flat_map(|payload| attributes(payload))
+            .filter(|&(kind, _)| kind == TEAM_ATTR_LIST_OPTION)
+            .flat_map(|(_, list)| attributes(list))
+            .filter(|&(kind, _)| kind == TEAM_ATTR_ITEM_OPTION);
+        for (_, item) in items {
+            let (mut name, mut data) = (&[][..], &[][..]);
+            for (kind, value) in attributes(item) {
+                match kind {
+                    TEAM_ATTR_OPTION_NAME => name = value,
+                    TEAM_ATTR_OPTION_DATA => data = value,
+                    _ => {}
+                }
+            }
+            match name.split(|&b| b == 0).next() {
+                Some(b"mode") => {
+                    let mode = data.split(|&b| b == 0).next().unwrap_or_default();
+                    options.mode = String::from_utf8_lossy(mode).into_owned();
+                }
+                Some(b"activeport") if data.len() >= 4 => {
+                    let port = u32::from_ne_bytes([data[0], data[1], data[2], data[3]]);
+                    options.active_port = (port != 0).then_some(port);
+                }
+                _ => {}
+            }
+        }
+        (!options.mode.is_empty()).then_some(options)
+    })();
+    unsafe { libc::close(fd) };
+    options
+}
+
+// One netlink attribute, padded to 4 bytes
+fn attribute(kind: u16, data: &[u8]) -> Vec<u8> {
+    let len = 4 + data.len();
+    let mut out = Vec::with_capacity((len + 3) & !3);
+    out.extend_from_slice(&(len as u16).to_ne_bytes());
+    out.extend_from_slice(&kind.to_ne_bytes());
+    out.extend_from_slice(data);
+    out.resize((len + 3) & !3, 0);
+    out
+}
+
+// The attributes in `buf`, type masked and payload without its header
+fn attributes(mut buf: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut out = Vec::new();
+    while buf.len() >= 4 {
+        let len = u16::from_ne_bytes([buf[0], buf[1]]) as usize;
+        let kind = u16::from_ne_bytes([buf[2], buf[3]]) & NLA_TYPE_MASK;
+        if len < 4 || len > buf.len() {
+            break;
+        }
+        out.push((kind, &buf[4..len]));
+        buf = &buf[((len + 3) & !3).min(buf.len())..];
+    }
+    out
+}
+
+// Send a generic netlink request and collect the replies' attributes, the
+// payload after each genlmsghdr, up to NLMSG_DONE or the last of a batch
+fn genl_request(
+    fd: i32,
+    family: u16,
+    cmd: u8,
+    version: u8,
+    attributes: &[u8],
+) -> io::Result<Vec<Vec<u8>>> {
+    const HEADER: usize = std::mem::size_of::<libc::nlmsghdr>();
+    // nlmsghdr: length, type, flags, seq 1, pid 0; then genlmsghdr
+    let len = HEADER + 4 + attributes.len();
+    let mut request = Vec::with_capacity(len);
+    request.extend_from_slice(&(len as u32).to_ne_bytes());
+    request.extend_from_slice(&family.to_ne_bytes());
+    request.extend_from_slice(&(libc::NLM_F_REQUEST as u16).to_ne_bytes());
+    request.extend_from_slice(&1u32.to_ne_bytes());
+    request.extend_from_slice(&0u32.to_ne_bytes());
+    request.extend_from_slice(&[cmd, version, 0, 0]);
+    request.extend_from_slice(attributes);
+    let sent = unsafe {
+        libc::send(
+            fd,
+            request.as_ptr() as *const libc::c_void,
+            request.len(),
+            0,
+        )
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut replies = Vec::new();
+    let mut buf = vec![0u8; 32 * 1024];
+    loop {
+        let received =
+            unsafe { libc::recv(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut rest = &buf[..received as usize];
+        let mut multipart = false;
+        while rest.len() >= HEADER {
+            let len = u32::from_ne_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let kind = u16::from_ne_bytes([rest[4], rest[5]]) as i32;
+            let flags = u16::from_ne_bytes([rest[6], rest[7]]) as i32;
+            if len < HEADER || len > rest.len() {
+                break;
+            }
+            match kind {
+                libc::NLMSG_DONE => return Ok(replies),
+                libc::NLMSG_ERROR => {
+                    // -errno, 0 for an ack
+                    let errno = rest
+                        .get(HEADER..HEADER + 4)
+                        .map_or(0, |e| i32::from_ne_bytes([e[0], e[1], e[2], e[3]]));
+                    if errno != 0 {
+                        return Err(io::Error::from_raw_os_error(-errno));
+                    }
+                }
+                _ if len >= HEADER + 4 => replies.push(rest[HEADER + 4..len].to_vec()),
+                _ => {}
+            }
+            multipart |= flags & libc::NLM_F_MULTI != 0;
+            rest = &rest[((len + 3) & !3).min(rest.len())..];
+        }
+        if !multipart {
+            return Ok(replies);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn masters_and_modes_are_read_from_sysfs() {
+        let root = fixtures::bond_sysfs("bond-topology");
+        let topology = BondTopology::read_from(&root);
+        assert_eq!(topology.masters.len(), 2);
+        assert_eq!(
+            topology.master("bond0").map(|m| m.mode),
+            Some(BondMode::ActiveBackup)
+        );
+        assert_eq!(
+            topology.master_of("eth3").map(|m| m.mode),
+            Some(BondMode::Lacp)
+        );
+        assert!(topology.master_of("eth4").is_none());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn a_bonds_speed_follows_its_mode_and_slaves() {
+        let root = fixtures::bond_sysfs("bond-speed");
+        let speed = |name: &str| {
+            BondTopology::read_from(&root)
+                .master(name)
+                .and_then(|master| master.aggregate_speed())
+        };
+        // In bytes per second: active-backup is the active slave's, 802.3ad
+        // every slave's
+        assert_eq!(speed("bond0"), Some(1_250_000_000));
+        assert_eq!(speed("bond1"), Some(6_250_000_000));
+        fixtures::write_file(&root, "bond1/bonding/slaves", "eth2\n");
+        assert_eq!(speed("bond1"), Some(3_125_000_000));
+        assert!(BondTopology::read_from(&root).master_of("eth3").is_none());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,522 @@
+//Minimal reader for the kernel's BTF (/sys/kernel/btf/vmlinux).
+//
+//aya-ebpf can't emit CO-RE relocations from Rust, so instead of hardcoding struct
+//offsets per kernel we resolve the few we need here and hand them to the probes
+//as globals before load. Only the pieces needed for member and enum lookup are parsed.
+
+use crate::{KernelOffsets, OFFSET_UNKNOWN};
+use std::collections::HashMap;
+
+const BTF_MAGIC: u16 = 0xeb9f;
+
+const KIND_INT: u32 = 1;
+const KIND_ARRAY: u32 = 3;
+const KIND_STRUCT: u32 = 4;
+const KIND_UNION: u32 = 5;
+const KIND_ENUM: u32 = 6;
+const KIND_TYPEDEF: u32 = 8;
+const KIND_VOLATILE: u32 = 9;
+const KIND_CONST: u32 = 10;
+const KIND_RESTRICT: u32 = 11;
+const KIND_FUNC_PROTO: u32 = 13;
+const KIND_VAR: u32 = 14;
+const KIND_DATASEC: u32 = 15;
+const KIND_DECL_TAG: u32 = 17;
+const KIND_TYPE_TAG: u32 = 18;
+const KIND_ENUM64: u32 = 19;
+
+struct Member {
+    name_off: u32,
+    type_id: u32,
+    bit_offset: u32,
+    is_bitfield: bool,
+}
+
+struct Type {
+    kind: u32,
+    name_off: u32,
+    // Referenced type for typedef/const/volatile/..., size otherwise
+    size_or_type: u32,
+    members: Vec<Member>,
+    // (name_off, value) for enums
+    enumerators: Vec<(u32, i64)>,
+}
+
+pub(crate) struct KernelBtf {
+    // Index 0 is the implicit void type
+    types: Vec<Type>,
+    strings: Vec<u8>,
+    structs_by_name: HashMap<String, u32>,
+    enums_by_name: HashMap<String, u32>,
+}
+
+impl KernelBtf {
+    pub(crate) fn from_sys_fs() -> anyhow::Result<Self> {
+        let data = std::fs::read("/sys/kernel/btf/vmlinux")?;
+        Self::parse(&data)
+    }
+
+    pub(crate) fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let u16_at = |off: usize| -> anyhow::Result<u16> {
+            let b = data
+                .get(off..off + 2)
+                .ok_or_else(|| anyhow::anyhow!("BTF truncated at {}", off))?;
+            Ok(u16::from_le_bytes([b[0], b[1]]))
+        };
+        let u32_at = |off: usize| -> anyhow::Result<u32> {
+            let b = data
+                .get(off..off + 4)
+                .ok_or_else(|| anyhow::anyhow!("BTF truncated at {}", off))?;
+            Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        };
+
+        if u16_at(0)? != BTF_MAGIC {
+            anyhow::bail!("not a little-endian BTF blob");
+        }
+        let hdr_len = u32_at(4)? as usize;
+        let type_off = hdr_len + u32_at(8)? as usize;
+        let type_len = u32_at(12)? as usize;
+        let str_off = hdr_len + u32_at(16)? as usize;
+        let str_len = u32_at(20)? as usize;
+
+        let strings = data
+            .get(str_off..str_off + str_len)
+            .ok_or_else(|| anyhow::anyhow!("BTF string section out of bounds"))?
+            .to_vec();
+
+        let mut types = vec![Type {
+            kind: 0,
+            name_off: 0,
+            size_or_type: 0,
+            members: Vec::new(),
+            enumerators: Vec::new(),
+        }];
+        let mut pos = type_off;
+        let end = type_off + type_len;
+        while pos < end {
+            let name_off = u32_at(pos)?;
+            let info = u32_at(pos + 4)?;
+            let size_or_type = u32_at(pos + 8)?;
+            pos += 12;
+
+            let vlen = (info & 0xffff) as usize;
+            let kind = (info >> 24) & 0x1f;
+            let kind_flag = info >> 31 == 1;
+
+            let mut members = Vec::new();
+            let mut enumerators = Vec::new();
+            match kind {
+                KIND_STRUCT | KIND_UNION => {
+                    for i in 0..vlen {
+                        let m = pos + i * 12;
+                        let offset = u32_at(m + 8)?;
+                        members.push(Member {
+                            name_off: u32_at(m)?,
+                            type_id: u32_at(m + 4)?,
+                            bit_offset: if kind_flag { offset & 0xff_ffff } else { offset },
+                            is_bitfield: kind_flag && offset >> 24 != 0,
+                        });
+                    }
+                    pos += vlen * 12;
+                }
+                KIND_INT | KIND_VAR | KIND_DECL_TAG => pos += 4,
+                KIND_ARRAY => pos += 12,
+                KIND_ENUM => {
+                    for i in 0..vlen {
+                        let e = pos + i * 8;
+                        // kind_flag set: signed values
+                        let raw = u32_at(e + 4)?;
+                        let value = if kind_flag { raw as i32 as i64 } else { raw as i64 };
+                        enumerators.push((u32_at(e)?, value));
+                    }
+                    pos += vlen * 8;
+                }
+                KIND_ENUM64 => {
+                    for i in 0..vlen {
+                        let e = pos + i * 12;
+                        let value = (u32_at(e + 8)? as u64) << 32 | u32_at(e + 4)? as u64;
+                        enumerators.push((u32_at(e)?, value as i64));
+                    }
+                    pos += vlen * 12;
+                }
+                KIND_FUNC_PROTO => pos += vlen * 8,
+                KIND_DATASEC => pos += vlen * 12,
+                _ => {}
+            }
+
+            types.push(Type {
+                kind,
+                name_off,
+                size_or_type,
+                members,
+                enumerators,
+            });
+        }
+
+        let mut btf = Self {
+            types,
+            strings,
+            structs_by_name: HashMap::new(),
+            enums_by_name: HashMap::new(),
+        };
+        for (id, ty) in btf.types.iter().enumerate() {
+            if ty.name_off == 0 {
+                continue;
+            }
+            let name = btf.string(ty.name_off).to_string();
+            let by_name = match ty.kind {
+                KIND_STRUCT => &mut btf.structs_by_name,
+                KIND_ENUM | KIND_ENUM64 => &mut btf.enums_by_name,
+                _ => continue,
+            };
+            by_name.entry(name).or_insert(id as u32);
+        }
+
+        Ok(btf)
+    }
+
+    fn string(&self, off: u32) -> &str {
+        let start = off as usize;
+        let Some(rest) = self.strings.get(start..) else {
+            return "";
+        };
+        let len = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+        std::str::from_utf8(&rest[..len]).unwrap_or("")
+    }
+
+    /// Follow typedef/const/volatile/... down to the underlying type
+    fn resolve(&self, mut id: u32) -> Option<&Type> {
+        for _ in 0..16 {
+            let ty = self.types.get(id as usize)?;
+            match ty.kind {
+                KIND_TYPEDEF | KIND_VOLATILE | KIND_CONST | KIND_RESTRICT | KIND_TYPE_TAG => {
+                    id = ty.size_or_type
+                }
+                _ => return Some(ty),
+            }
+        }
+        None
+    }
+
+    /// Byte offset of `member` inside `ty`, descending into anonymous struct/union
+    /// members the way C field access does
+    fn member_offset(&self, ty: &Type, member: &str) -> Option<(u32, u32)> {
+        for m in &ty.members {
+            if m.is_bitfield {
+                continue;
+            }
+            if m.name_off == 0 {
+                let inner = self.resolve(m.type_id)?;
+                if let Some((off, type_id)) = self.member_offset(inner, member) {
+                    return Some((m.bit_offset / 8 + off, type_id));
+                }
+            } else if self.string(m.name_off) == member {
+                return Some((m.bit_offset / 8, m.type_id));
+            }
+        }
+        None
+    }
+
+    /// Enumerator names and values of a named enum, in declaration order
+    pub(crate) fn enum_values(&self, enum_name: &str) -> Option<Vec<(String, i64)>> {
+        let ty = self.types.get(*self.enums_by_name.get(enum_name)? as usize)?;
+        Some(
+            ty.enumerators
+                .iter()
+                .map(|&(name_off, value)| (self.string(name_off).to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// Byte offset of a (possibly dotted) member path, e.g.
+    /// `field_offset("sock", "sk_backlog.rmem_alloc")`
+    pub(crate) fn field_offset(&self, struct_name: &str, path: &str) -> Option<u32> {
+        let mut ty = self.types.get(*self.structs_by_name.get(struct_name)? as usize)?;
+        let mut total = 0;
+        let mut parts = path.split('.').peekable();
+        while let Some(part) = parts.next() {
+            let (off, type_id) = self.member_offset(ty, part)?;
+            total += off;
+            if parts.peek().is_some() {
+                ty = self.resolve(type_id)?;
+                if ty.kind != KIND_STRUCT && ty.kind != KIND_UNION {
+                    return None;
+                }
+            }
+        }
+        Some(total)
+    }
+}
+
+/// Resolve everything the probes need, leaving OFFSET_UNKNOWN for whatever this
+/// kernel's BTF (or lack of it) can't tell us
+pub(crate) fn resolve_offsets() -> KernelOffsets {
+    let mut offsets = KernelOffsets {
+        tcp_snd_cwnd: OFFSET_UNKNOWN,
+        tcp_snd_ssthresh: OFFSET_UNKNOWN,
+        sk_pacing_rate: OFFSET_UNKNOWN,
+        skb_head: OFFSET_UNKNOWN,
+        skb_network_header: OFFSET_UNKNOWN,
+        sk_cookie: OFFSET_UNKNOWN,
+        kfree_skb_reason: OFFSET_UNKNOWN,
+        sk_wmem_alloc: OFFSET_UNKNOWN,
+        sk_wmem_queued: OFFSET_UNKNOWN,
+        sk_sndbuf: OFFSET_UNKNOWN,
+        skb_sk: OFFSET_UNKNOWN,
+        skb_mac_header: OFFSET_UNKNOWN,
+        skb_transport_header: OFFSET_UNKNOWN,
+        skb_dev: OFFSET_UNKNOWN,
+        netdev_queue_dev: OFFSET_UNKNOWN,
+        net_device_ifindex: OFFSET_UNKNOWN,
+        sk_family: OFFSET_UNKNOWN,
+        sk_daddr: OFFSET_UNKNOWN,
+        sk_v6_daddr: OFFSET_UNKNOWN,
+        sk_net: OFFSET_UNKNOWN,
+        net_ns_inum: OFFSET_UNKNOWN,
+        net_device_nd_net: OFFSET_UNKNOWN,
+        inet_tos: OFFSET_UNKNOWN,
+        sk_priority: OFFSET_UNKNOWN,
+        sk_max_pacing_rate: OFFSET_UNKNOWN,
+        skb_len: OFFSET_UNKNOWN,
+        inet_cork_gso_size: OFFSET_UNKNOWN,
+        sk_rmem_alloc: OFFSET_UNKNOWN,
+        sk_rcvbuf: OFFSET_UNKNOWN,
+        sk_dport: OFFSET_UNKNOWN,
+    };
+
+    let btf = match KernelBtf::from_sys_fs() {
+        Ok(btf) => btf,
+        Err(e) => {
+            log::warn!("kernel BTF unavailable ({}), BTF-dependent probes disabled", e);
+            return offsets;
+        }
+    };
+
+    let resolve = |struct_name: &str, path: &str| {
+        btf.field_offset(struct_name, path).unwrap_or_else(|| {
+            log::warn!("{}.{} not found in kernel BTF", struct_name, path);
+            OFFSET_UNKNOWN
+        })
+    };
+    offsets.tcp_snd_cwnd = resolve("tcp_sock", "snd_cwnd");
+    offsets.tcp_snd_ssthresh = resolve("tcp_sock", "snd_ssthresh");
+    offsets.sk_pacing_rate = resolve("sock", "sk_pacing_rate");
+    offsets.skb_head = resolve("sk_buff", "head");
+    offsets.skb_network_header = resolve("sk_buff", "network_header");
+    offsets.sk_cookie = resolve("sock", "__sk_common.skc_cookie");
+    offsets.kfree_skb_reason = resolve("trace_event_raw_kfree_skb", "reason");
+    offsets.sk_wmem_alloc = resolve("sock", "sk_wmem_alloc");
+    offsets.sk_wmem_queued = resolve("sock", "sk_wmem_queued");
+    offsets.sk_sndbuf = resolve("sock", "sk_sndbuf");
+    offsets.skb_sk = resolve("sk_buff", "sk");
+    offsets.skb_mac_header = resolve("sk_buff", "mac_header");
+    offsets.skb_transport_header = resolve("sk_buff", "transport_header");
+    offsets.skb_dev = resolve("sk_buff", "dev");
+    offsets.netdev_queue_dev = resolve("netdev_queue", "dev");
+    offsets.net_device_ifindex = resolve("net_device", "ifindex");
+    offsets.sk_family = resolve("sock", "__sk_common.skc_family");
+    offsets.sk_daddr = resolve("sock", "__sk_common.skc_daddr");
+    offsets.sk_v6_daddr = resolve("sock", "__sk_common.skc_v6_daddr");
+    offsets.sk_net = resolve("sock", "__sk_common.skc_net.net");
+    offsets.net_ns_inum = resolve("net", "ns.inum");
+    offsets.net_device_nd_net = resolve("net_device", "nd_net.net");
+    offsets.inet_tos = resolve("inet_sock", "tos");
+    offsets.sk_priority = resolve("sock", "sk_priority");
+    offsets.sk_max_pacing_rate = resolve("sock", "sk_max_pacing_rate");
+    offsets.skb_len = resolve("sk_buff", "len");
+    offsets.inet_cork_gso_size = resolve("inet_cork", "gso_size");
+    offsets.sk_rmem_alloc = resolve("sock", "sk_backlog.rmem_alloc");
+    offsets.sk_rcvbuf = resolve("sock", "sk_rcvbuf");
+    offsets.sk_dport = resolve("sock", "__sk_common.skc_dport");
+
+    log::debug!("resolved kernel offsets: {:?}", offsets);
+    offsets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a BTF blob type by type, ids from 1 in the order added
+    struct Builder {
+        types: Vec<u8>,
+        strings: Vec<u8>,
+        next_id: u32,
+    }
+
+    impl Builder {
+        fn new() -> Self {
+            Self {
+                types: Vec::new(),
+                strings: vec![0],
+                next_id: 1,
+            }
+        }
+
+        fn name(&mut self, name: &str) -> u32 {
+            if name.is_empty() {
+                return 0;
+            }
+            let off = self.strings.len() as u32;
+            self.strings.extend_from_slice(name.as_bytes());
+            self.strings.push(0);
+            off
+        }
+
+        fn words(&mut self, words: &[u32]) {
+            for word in words {
+                self.types.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+
+        fn header(
+            &mut self,
+            name: &str,
+            kind: u32,
+            kind_flag: bool,
+            vlen: usize,
+            size: u32,
+        ) -> u32 {
+            let name_off = self.name(name);
+            let info = (kind_flag as u32) << 31 | kind << 24 | vlen as u32;
+            self.words(&[name_off, info, size]);
+            self.next_id += 1;
+            self.next_id - 1
+        }
+
+        fn int(&mut self, name: &str, size: u32) -> u32 {
+            let id = self.header(name, KIND_INT, false, 0, size);
+            self.words(&[size * 8]);
+            id
+        }
+
+        fn typedef(&mut self, name: &str, target: u32) -> u32 {
+            self.header(name, KIND_TYPEDEF, false, 0, target)
+        }
+
+        /// Members as (name, type, bit offset), with bitfield sizes when
+        /// `kind_flag`
+        fn composite(
+            &mut self,
+            kind: u32,
+            name: &str,
+            size: u32,
+            kind_flag: bool,
+            members: &[(&str, u32, u32)],
+        ) -> u32 {
+            let id = self.header(name, kind, kind_flag, members.len(), size);
+            for &(member, type_id, offset) in members {
+                let name_off = self.name(member);
+                self.words(&[name_off, type_id, offset]);
+            }
+            id
+        }
+
+        fn enumeration(&mut self, name: &str, signed: bool, values: &[(&str, i32)]) -> u32 {
+            let id = self.header(name, KIND_ENUM, signed, values.len(), 4);
+            for &(value_name, value) in values {
+                let name_off = self.name(value_name);
+                self.words(&[name_off, value as u32]);
+            }
+            id
+        }
+
+        fn build(self) -> Vec<u8> {
+            let mut blob = Vec::new();
+            blob.extend_from_slice(&BTF_MAGIC.to_le_bytes());
+            blob.extend_from_slice(&[1, 0]);
+            let type_len = self.types.len() as u32;
+            for word in [24, 0, type_len, type_len, self.strings.len() as u32] {
+                blob.extend_from_slice(&word.to_le_bytes());
+            }
+            blob.extend_from_slice(&self.types);
+            blob.extend_from_slice(&self.strings);
+            blob
+        }
+    }
+
+    /// struct sock { int a; union { u64 cookie; }; inner_t backlog; u32 flags:3; }
+    /// with struct inner { u32 x; u32 y; } behind the typedef
+    fn sock_btf() -> KernelBtf {
+        let mut btf = Builder::new();
+        let int = btf.int("int", 4);
+        let u32_id = btf.int("u32", 4);
+        let u64_id = btf.int("u64", 8);
+        let inner = btf.composite(
+            KIND_STRUCT,
+            "inner",
+            8,
+            false,
+            &[("x", u32_id, 0), ("y", u32_id, 32)],
+        );
+        let inner_t = btf.typedef("inner_t", inner);
+        let anon = btf.composite(KIND_UNION, "", 8, false, &[("cookie", u64_id, 0)]);
+        btf.composite(
+            KIND_STRUCT,
+            "sock",
+            32,
+            true,
+            &[
+                ("a", int, 0),
+                ("", anon, 64),
+                ("backlog", inner_t, 128),
+                ("flags", u32_id, 3 << 24 | 192),
+            ],
+        );
+        btf.enumeration(
+            "skb_drop_reason",
+            false,
+            &[("NOT_SPECIFIED", 2), ("NO_SOCKET", 3)],
+        );
+        btf.enumeration("signed_thing", true, &[("MINUS_ONE", -1)]);
+        KernelBtf::parse(&btf.build()).unwrap()
+    }
+
+    #[test]
+    fn member_paths_resolve_through_typedefs_and_anonymous_members() {
+        let btf = sock_btf();
+        assert_eq!(btf.field_offset("sock", "a"), Some(0));
+        assert_eq!(btf.field_offset("sock", "cookie"), Some(8));
+        assert_eq!(btf.field_offset("sock", "backlog"), Some(16));
+        assert_eq!(btf.field_offset("sock", "backlog.y"), Some(20));
+        assert_eq!(btf.field_offset("inner", "y"), Some(4));
+    }
+
+    #[test]
+    fn unresolvable_members_are_none() {
+        let btf = sock_btf();
+        // Bitfields have no byte offset to read at
+        assert_eq!(btf.field_offset("sock", "flags"), None);
+        assert_eq!(btf.field_offset("sock", "a.x"), None);
+        assert_eq!(btf.field_offset("sock", "backlog.z"), None);
+        assert_eq!(btf.field_offset("tcp_sock", "snd_cwnd"), None);
+    }
+
+    #[test]
+    fn enums_list_their_values_in_order() {
+        let btf = sock_btf();
+        assert_eq!(
+            btf.enum_values("skb_drop_reason"),
+            Some(vec![
+                ("NOT_SPECIFIED".to_string(), 2),
+                ("NO_SOCKET".to_string(), 3)
+            ])
+        );
+        assert_eq!(
+            btf.enum_values("signed_thing"),
+            Some(vec![("MINUS_ONE".to_string(), -1)])
+        );
+        assert_eq!(btf.enum_values("sock"), None);
+    }
+
+    #[test]
+    fn foreign_and_truncated_blobs_are_errors() {
+        assert!(KernelBtf::parse(&[0x9f, 0xeb]).is_err());
+        assert!(KernelBtf::parse(&[0; 24]).is_err());
+        let mut blob = Builder::new();
+        blob.int("int", 4);
+        let mut blob = blob.build();
+        blob.truncate(blob.len() - 6);
+        assert!(KernelBtf::parse(&blob).is_err());
+    }
+}
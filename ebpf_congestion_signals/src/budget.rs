@@ -0,0 +1,471 @@
+//The collector's own CPU against a budget, with a ladder of ways to spend less.
+//Each reading is the process's CPU (/proc/self/stat) plus the run time the
+//kernel accounted to the BPF programs, in percent of all CPUs, as in
+//overhead.rs. Readings over budget several times in a row take one step down
+//the ladder, readings comfortably under several times in a row take one back,
+//so a level holds for at least that many readings before it moves again.
+//
+//Steps are cumulative: level 2 is steps 1 and 2 applied together. The state
+//machine here only decides levels from readings, so a scripted series always
+//lands on the same ones; the collector turns a level into sampler shifts,
+//detached probes and masked events (`CongestionCollector::enforce_overhead_budget`).
+
+#[cfg(feature = "collector-core")]
+use crate::{CpuSample, CpuTimes, DEGRADE_EVENT_MASK, DEGRADE_SAMPLE_SHIFT};
+use crate::{
+    Signal, DEGRADE_MASKABLE, DEGRADE_MAX_SHIFT, EVENT_NET_DEV_QUEUE, EVENT_SOCKET_RCV_STATE,
+    EVENT_SOFTIRQ_EXIT, EVENT_TCP_STATE,
+};
+#[cfg(feature = "collector-core")]
+use aya::{
+    maps::{Array, MapData},
+    Ebpf,
+};
+#[cfg(feature = "collector-core")]
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// One rung of `OverheadBudget::ladder`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DegradationStep {
+    /// The occupancy samplers (receive buffer, TCP state) take 1 in 100 << this.
+    /// Their signals are averages and stay unbiased, just noisier. The send
+    /// sampler is left alone, byte totals are scaled by its ratio
+    SampleShift(u32),
+    /// Detach this many more optional probes, the ones with the most BPF run
+    /// time since the previous reading first. Their signals read None while
+    /// detached
+    DisableProbes(usize),
+    /// Stop the kernel sending these event types, bits of `1 << EVENT_*`. Only
+    /// the ones in `DEGRADE_MASKABLE` are honored
+    MaskEvents(u32),
+}
+
+/// Budget and ladder for `CollectorConfig::with_overhead_budget`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverheadBudget {
+    /// Percent of all CPUs the collector may use, process and BPF programs
+    /// together
+    pub budget_percent: f64,
+    /// Readings over budget in a row before taking the next step
+    pub escalate_after: u32,
+    /// A reading at or below this fraction of the budget is comfortably under
+    pub restore_below: f64,
+    /// Readings comfortably under in a row before undoing the last step
+    pub restore_after: u32,
+    /// Steps in the order they are taken, undone in reverse
+    pub ladder: Vec<DegradationStep>,
+}
+
+impl Default for OverheadBudget {
+    fn default() -> Self {
+        Self {
+            budget_percent: 1.0,
+            escalate_after: 3,
+            restore_below: 0.5,
+            restore_after: 5,
+            ladder: vec![
+                DegradationStep::SampleShift(1),
+                DegradationStep::DisableProbes(2),
+                DegradationStep::MaskEvents(1 << EVENT_NET_DEV_QUEUE | 1 << EVENT_SOCKET_RCV_STATE),
+                DegradationStep::SampleShift(3),
+                DegradationStep::DisableProbes(4),
+                DegradationStep::MaskEvents(1 << EVENT_SOFTIRQ_EXIT | 1 << EVENT_TCP_STATE),
+            ],
+        }
+    }
+}
+
+impl OverheadBudget {
+    /// Everything the first `level` steps apply
+    pub fn degradation(&self, level: usize) -> Degradation {
+        let level = level.min(self.ladder.len());
+        let mut degradation = Degradation {
+            level,
+            ..Default::default()
+        };
+        for step in &self.ladder[..level] {
+            match *step {
+                DegradationStep::SampleShift(shift) => {
+                    degradation.sample_shift = degradation.sample_shift.max(shift)
+                }
+                DegradationStep::DisableProbes(probes) => degradation.disabled_probes += probes,
+                DegradationStep::MaskEvents(mask) => degradation.event_mask |= mask,
+            }
+        }
+        degradation.sample_shift = degradation.sample_shift.min(DEGRADE_MAX_SHIFT);
+        degradation.event_mask &= DEGRADE_MASKABLE;
+        degradation
+    }
+}
+
+/// What a level of the ladder amounts to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Degradation {
+    /// Steps applied, 0 when nothing is degraded
+    pub level: usize,
+    /// Highest `SampleShift` among them
+    pub sample_shift: u32,
+    /// Optional probes to have detached
+    pub disabled_probes: usize,
+    /// Event types masked, within `DEGRADE_MASKABLE`
+    pub event_mask: u32,
+}
+
+impl Degradation {
+    /// Core signals that read zero because their events are masked, for
+    /// `CongestionSignals::missing_signals`
+    pub fn missing_signals(&self) -> Vec<Signal> {
+        let masked = |event_type: u32| self.event_mask & (1 << event_type) != 0;
+        let mut missing = Vec::new();
+        if masked(EVENT_NET_DEV_QUEUE) {
+            missing.push(Signal::QueueDepth);
+        }
+        if masked(EVENT_SOFTIRQ_EXIT) {
+            missing.push(Signal::Softirq);
+        }
+        missing
+    }
+
+    /// Add `missing_signals()` to an interval's, without duplicates
+    #[cfg(feature = "collector-core")]
+    pub(crate) fn add_missing(&self, missing: &mut Vec<Signal>) {
+        for signal in self.missing_signals() {
+            if !missing.contains(&signal) {
+                missing.push(signal);
+            }
+        }
+    }
+}
+
+/// A level change, returned by `OverheadGovernor::observe`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BudgetAction {
+    pub from: usize,
+    pub to: usize,
+    /// The reading that made it
+    pub percent: f64,
+}
+
+impl BudgetAction {
+    pub fn is_escalation(&self) -> bool {
+        self.to > self.from
+    }
+}
+
+/// Levels from overhead readings, see the module comment. Readings between
+/// `restore_below` and the budget reset both counts
+#[derive(Debug, Clone)]
+pub struct OverheadGovernor {
+    budget: OverheadBudget,
+    level: usize,
+    over: u32,
+    under: u32,
+}
+
+impl OverheadGovernor {
+    pub fn new(budget: OverheadBudget) -> Self {
+        Self {
+            budget,
+            level: 0,
+            over: 0,
+            under: 0,
+        }
+    }
+
+    pub fn budget(&self) -> &OverheadBudget {
+        &self.budget
+    }
+
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    pub fn degradation(&self) -> Degradation {
+        self.budget.degradation(self.level)
+    }
+
+    /// Take one reading, percent of all CPUs. Some when it moved the level;
+    /// the counts start over after every move
+    pub fn observe(&mut self, percent: f64) -> Option<BudgetAction> {
+        let from = self.level;
+        if percent > self.budget.budget_percent {
+            self.over = self.over.saturating_add(1);
+            self.under = 0;
+            if self.over >= self.budget.escalate_after.max(1)
+                && self.level < self.budget.ladder.len()
+            {
+                self.level += 1;
+            }
+        } else if percent <= self.budget.budget_percent * self.budget.restore_below {
+            self.under = self.under.saturating_add(1);
+            self.over = 0;
+            if self.under >= self.budget.restore_after.max(1) && self.level > 0 {
+                self.level -= 1;
+            }
+        } else {
+            self.over = 0;
+            self.under = 0;
+        }
+        if self.level == from {
+            return None;
+        }
+        self.over = 0;
+        self.under = 0;
+        Some(BudgetAction {
+            from,
+            to: self.level,
+            percent,
+        })
+    }
+}
+
+/// Program names by run time, most first. Ties go by name, so without run
+/// time accounting (all zero) the order is still the same on every host
+pub fn by_run_time(mut run_times: Vec<(String, Duration)>) -> Vec<String> {
+    run_times.sort_by(|(a, a_time), (b, b_time)| b_time.cmp(a_time).then_with(|| a.cmp(b)));
+    run_times.into_iter().map(|(name, _)| name).collect()
+}
+
+/// The overhead budget's state for `HealthReport::overhead`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverheadStatus {
+    pub degradation: Degradation,
+    /// Steps on the ladder
+    pub levels: usize,
+    pub budget_percent: f64,
+    /// The latest reading, None before the second `enforce_overhead_budget`
+    pub last_percent: Option<f64>,
+    /// Whether BPF run time was in the latest reading. Without it
+    /// (kernel.bpf_stats_enabled off, see `BpfStats`) only the process counts
+    /// and probes are ranked by name
+    pub bpf_accounted: bool,
+    /// Optional programs detached, in the order they were
+    pub disabled_probes: Vec<String>,
+}
+
+/// The collector's side of the budget: readings, the DEGRADATION map and which
+/// probes it detached. Detaching and re-attaching is the collector's, it owns
+/// the programs
+#[cfg(feature = "collector-core")]
+pub(crate) struct OverheadEnforcer {
+    governor: OverheadGovernor,
+    // None in objects without the map: samplers and events stay as built
+    map: Option<Array<MapData, u32>>,
+    // CPU times and BPF run time at the previous reading
+    last: Option<(CpuTimes, Option<Duration>)>,
+    // Each optional program's cumulative run time at the previous reading
+    run_times: HashMap<String, Duration>,
+    // Programs detached by the budget, oldest first, with the probe flag each
+    // had before: re-attaching restores it
+    pub(crate) disabled: Vec<(String, bool)>,
+    last_percent: Option<f64>,
+    bpf_accounted: bool,
+}
+
+#[cfg(feature = "collector-core")]
+impl OverheadEnforcer {
+    pub(crate) fn new(budget: OverheadBudget, ebpf: &mut Ebpf) -> Self {
+        Self {
+            governor: OverheadGovernor::new(budget),
+            map: Self::take_map(ebpf),
+            last: None,
+            run_times: HashMap::new(),
+            disabled: Vec::new(),
+            last_percent: None,
+            bpf_accounted: false,
+        }
+    }
+
+    fn take_map(ebpf: &mut Ebpf) -> Option<Array<MapData, u32>> {
+        match ebpf.take_map("DEGRADATION").map(Array::try_from) {
+            Some(Ok(map)) => Some(map),
+            _ => {
+                log::warn!("DEGRADATION map unusable, the overhead budget can only detach probes");
+                None
+            }
+        }
+    }
+
+    /// After a reload: the new object's map, written with the current level
+    pub(crate) fn replace_map(&mut self, ebpf: &mut Ebpf) {
+        self.map = Self::take_map(ebpf);
+        self.run_times.clear();
+        self.write_map();
+    }
+
+    /// Drop a detached program's run time, it starts from zero once attached
+    /// again
+    pub(crate) fn forget(&mut self, program: &str) {
+        self.run_times.remove(program);
+    }
+
+    pub(crate) fn degradation(&self) -> Degradation {
+        self.governor.degradation()
+    }
+
+    /// One reading since the previous call, and the optional programs by run
+    /// time over the same period. None for the reading on the first call and
+    /// when /proc can't be read
+    pub(crate) fn read(
+        &mut self,
+        bpf_run_time: Option<Duration>,
+        program_run_times: Vec<(String, Duration)>,
+    ) -> (Option<f64>, Vec<String>) {
+        let deltas = program_run_times
+            .into_iter()
+            .map(|(name, total)| {
+                let before = self.run_times.get(&name).copied().unwrap_or_default();
+                let delta = total.saturating_sub(before);
+                self.run_times.insert(name.clone(), total);
+                (name, delta)
+            })
+            .collect();
+        let ranked = by_run_time(deltas);
+
+        let Ok(now) = CpuTimes::read() else {
+            return (None, ranked);
+        };
+        let percent = self.last.take().and_then(|(then, then_bpf)| {
+            let sample = CpuSample::between(&then, &now, then_bpf.zip(bpf_run_time))?;
+            self.bpf_accounted = sample.bpf.is_some();
+            Some(sample.process + sample.bpf.unwrap_or(0.0))
+        });
+        self.last = Some((now, bpf_run_time));
+        if percent.is_some() {
+            self.last_percent = percent;
+        }
+        (percent, ranked)
+    }
+
+    pub(crate) fn observe(&mut self, percent: f64) -> Option<BudgetAction> {
+        self.governor.observe(percent)
+    }
+
+    /// Write the current level's sampler shift and event mask to the kernel
+    pub(crate) fn write_map(&mut self) {
+        let degradation = self.governor.degradation();
+        let Some(map) = self.map.as_mut() else {
+            return;
+        };
+        for (slot, value) in [
+            (DEGRADE_SAMPLE_SHIFT, degradation.sample_shift),
+            (DEGRADE_EVENT_MASK, degradation.event_mask),
+        ] {
+            if let Err(e) = map.set(slot, value, 0) {
+                log::warn!("writing DEGRADATION slot {} failed: {}", slot, e);
+            }
+        }
+    }
+
+    pub(crate) fn status(&self) -> OverheadStatus {
+        OverheadStatus {
+            degradation: self.governor.degradation(),
+            levels: self.governor.budget().ladder.len(),
+            budget_percent: self.governor.budget().budget_percent,
+            last_percent: self.last_percent,
+            bpf_accounted: self.bpf_accounted,
+            disabled_probes: self.disabled.iter().map(|(name, _)| name.clone()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EVENT_UDP_SEND;
+
+    fn budget() -> OverheadBudget {
+        OverheadBudget {
+            budget_percent: 1.0,
+            escalate_after: 2,
+            restore_below: 0.5,
+            restore_after: 3,
+            ladder: vec![
+                DegradationStep::SampleShift(2),
+                DegradationStep::DisableProbes(1),
+                DegradationStep::MaskEvents(1 << EVENT_NET_DEV_QUEUE | 1 << EVENT_UDP_SEND),
+            ],
+        }
+    }
+
+    /// Two over in a row step down, a reading between the thresholds starts
+    /// the count over, the last rung holds, three comfortably under in a row
+    /// step back up one at a time
+    const READINGS: [f64; 23] = [
+        1.5, 1.5, 1.5, 0.8, 1.5, 1.5, 2.0, 2.0, 2.0, 2.0, 0.4, 0.4, 0.7, 0.4, 0.4, 0.4, 0.4, 0.4,
+        0.4, 0.4, 0.4, 0.4, 0.4,
+    ];
+
+    /// (reading, from, to) for every move, and the deepest degradation
+    fn run() -> (Vec<(usize, usize, usize)>, Degradation, usize) {
+        let mut governor = OverheadGovernor::new(budget());
+        let mut moves = Vec::new();
+        let mut peak = Degradation::default();
+        for (i, percent) in READINGS.iter().enumerate() {
+            if let Some(action) = governor.observe(*percent) {
+                moves.push((i, action.from, action.to));
+            }
+            if governor.level() > peak.level {
+                peak = governor.degradation();
+            }
+        }
+        (moves, peak, governor.level())
+    }
+
+    #[test]
+    fn readings_walk_the_ladder_down_and_back() {
+        let (moves, peak, last) = run();
+        assert_eq!(
+            moves,
+            [
+                (1, 0, 1),
+                (5, 1, 2),
+                (7, 2, 3),
+                (15, 3, 2),
+                (18, 2, 1),
+                (21, 1, 0)
+            ]
+        );
+        assert_eq!(last, 0);
+        // Unmaskable events are left out of the mask
+        assert_eq!(
+            peak,
+            Degradation {
+                level: 3,
+                sample_shift: 2,
+                disabled_probes: 1,
+                event_mask: 1 << EVENT_NET_DEV_QUEUE,
+            }
+        );
+    }
+
+    #[test]
+    fn the_same_readings_land_on_the_same_levels() {
+        assert_eq!(run().0, run().0);
+    }
+
+    #[test]
+    fn the_default_ladder_in_full() {
+        let full = OverheadBudget::default().degradation(usize::MAX);
+        assert_eq!(full.level, 6);
+        assert_eq!(full.sample_shift, 3);
+        assert_eq!(full.disabled_probes, 6);
+        assert_eq!(full.event_mask, DEGRADE_MASKABLE);
+        assert_eq!(
+            full.missing_signals(),
+            [Signal::QueueDepth, Signal::Softirq]
+        );
+    }
+
+    #[test]
+    fn programs_rank_by_run_time_then_name() {
+        let ranked = by_run_time(vec![
+            ("b".to_string(), Duration::from_millis(5)),
+            ("a".to_string(), Duration::from_millis(5)),
+            ("c".to_string(), Duration::from_millis(9)),
+            ("d".to_string(), Duration::ZERO),
+        ]);
+        assert_eq!(ranked, ["c", "a", "b", "d"]);
+    }
+}
@@ -0,0 +1,254 @@
+//Bufferbloat: a deep FIFO ahead of the bottleneck that never fills, so nothing
+//drops while the delay through it climbs into hundreds of milliseconds and a
+//drop-weighted score says all is well. The detector looks for the shape of it
+//over a window of intervals instead: the qdisc backlog (`queue_depth_bytes`, as
+//headroom.rs reads it) trending up and ending well above where it started, or
+//TX queues stopped long enough in every interval to make packets wait, while
+//drops stay near zero and the link, when its speed is known, is busy. A window
+//with real loss in it is left to the drop checks: that's loss, not bloat.
+
+use crate::CongestionSignals;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// When a window of intervals counts as bufferbloat, part of
+/// `SeverityThresholds` and `GovernorPolicy`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BufferbloatConfig {
+    /// Intervals the trend is taken over; nothing is judged before that many
+    pub window: usize,
+    /// Backlog at the end of the window over the backlog at its start that
+    /// counts as building, 2.0 = doubled
+    pub backlog_growth: f64,
+    /// Backlogs ending below this many bytes are ignored, whatever their trend
+    pub min_backlog_bytes: u64,
+    /// Average TX queue stop that counts as a standing queue on its own, when
+    /// every interval of the window has one at least this long. Needs the txq
+    /// probes
+    pub queue_delay: Duration,
+    /// Drops per second above which an interval is lossy; one in the window
+    /// and the window is loss rather than bloat
+    pub max_drops_per_sec: f64,
+    /// Link speed in bytes/sec, see `interface_speed`. None leaves utilization
+    /// out; a `Governor` falls back to `HeadroomConfig::link_rate`
+    pub link_rate: Option<u64>,
+    /// Send rate over `link_rate`, averaged over the window, the link has to
+    /// be at for a growing queue to be the link's
+    pub min_utilization: f64,
+}
+
+impl Default for BufferbloatConfig {
+    fn default() -> Self {
+        Self {
+            // A second at 200 ms intervals
+            window: 5,
+            backlog_growth: 2.0,
+            min_backlog_bytes: 256 * 1024,
+            queue_delay: Duration::from_millis(20),
+            max_drops_per_sec: 1.0,
+            link_rate: None,
+            min_utilization: 0.8,
+        }
+    }
+}
+
+/// A window that looked like bufferbloat, see [`BufferbloatDetector`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bufferbloat {
+    /// `queue_depth_bytes` of the window's last interval
+    pub backlog_bytes: u64,
+    /// That over the window's first interval's
+    pub growth: f64,
+    /// Average TX queue stop of the last interval, None without the txq probes
+    pub queue_delay: Option<Duration>,
+    /// Mean send rate over the link rate, None without a link rate
+    pub utilization: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    backlog: u64,
+    drops_per_sec: f64,
+    // bytes/sec
+    send_rate: f64,
+    queue_delay: Option<Duration>,
+}
+
+/// Judges each interval with the `window - 1` before it, see the module docs.
+/// `SeverityClassifier` and `Governor` run one each
+#[derive(Debug, Clone)]
+pub struct BufferbloatDetector {
+    config: BufferbloatConfig,
+    samples: VecDeque<Sample>,
+}
+
+impl BufferbloatDetector {
+    pub fn new(config: BufferbloatConfig) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(config.window),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &BufferbloatConfig {
+        &self.config
+    }
+
+    /// Fold in one interval; Some while the window ending with it looks like
+    /// bufferbloat. Intervals without a length are skipped
+    pub fn update(&mut self, signals: &CongestionSignals) -> Option<Bufferbloat> {
+        let secs = signals.interval_ns as f64 / 1e9;
+        if secs <= 0.0 {
+            return None;
+        }
+        let window = self.config.window.max(2);
+        if self.samples.len() == window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample {
+            backlog: signals.queue_depth_bytes,
+            drops_per_sec: signals.drops as f64 / secs,
+            send_rate: signals.send_rate(secs),
+            queue_delay: signals
+                .txq_stalls
+                .zip(signals.txq_stalled_ns)
+                .and_then(|(stalls, stalled_ns)| stalled_ns.checked_div(stalls))
+                .map(Duration::from_nanos),
+        });
+        if self.samples.len() < window {
+            return None;
+        }
+        self.judge()
+    }
+
+    fn judge(&self) -> Option<Bufferbloat> {
+        let config = &self.config;
+        let samples = &self.samples;
+        if samples
+            .iter()
+            .any(|s| s.drops_per_sec > config.max_drops_per_sec)
+        {
+            return None;
+        }
+
+        let utilization = config.link_rate.filter(|&link| link > 0).map(|link| {
+            samples.iter().map(|s| s.send_rate).sum::<f64>() / samples.len() as f64 / link as f64
+        });
+        if utilization.is_some_and(|u| u < config.min_utilization) {
+            return None;
+        }
+
+        let (first, last) = (samples[0], samples[samples.len() - 1]);
+        let growth = last.backlog as f64 / first.backlog.max(1) as f64;
+        let building = last.backlog >= config.min_backlog_bytes
+            && growth >= config.backlog_growth
+            && backlog_slope(samples) > 0.0;
+        let standing = samples.iter().all(|s| {
+            s.queue_delay
+                .is_some_and(|delay| delay >= config.queue_delay)
+        });
+        (building || standing).then_some(Bufferbloat {
+            backlog_bytes: last.backlog,
+            growth,
+            queue_delay: last.queue_delay,
+            utilization,
+        })
+    }
+}
+
+impl Default for BufferbloatDetector {
+    fn default() -> Self {
+        Self::new(BufferbloatConfig::default())
+    }
+}
+
+/// Least-squares slope of the backlog over the window, bytes per interval. An
+/// end above the start alone could be one spike
+fn backlog_slope(samples: &VecDeque<Sample>) -> f64 {
+    let n = samples.len() as f64;
+    let mean_x = (n - 1.0) / 2.0;
+    let mean_y = samples.iter().map(|s| s.backlog as f64).sum::<f64>() / n;
+    let (mut cov, mut var) = (0.0, 0.0);
+    for (x, s) in samples.iter().enumerate() {
+        let dx = x as f64 - mean_x;
+        cov += dx * (s.backlog as f64 - mean_y);
+        var += dx * dx;
+    }
+    if var > 0.0 {
+        cov / var
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::backlogged;
+
+    const INTERVALS: u64 = 8;
+
+    fn config() -> BufferbloatConfig {
+        BufferbloatConfig {
+            link_rate: Some(12_500_000),
+            ..Default::default()
+        }
+    }
+
+    /// 100 KB growing 80 KB a second, the link at 92%
+    fn rising(drops: u64) -> Vec<CongestionSignals> {
+        (0..INTERVALS)
+            .map(|i| backlogged(100_000 + i * 80_000, drops, 11_500_000, None))
+            .collect()
+    }
+
+    fn detect(trace: &[CongestionSignals]) -> Vec<Option<Bufferbloat>> {
+        let mut detector = BufferbloatDetector::new(config());
+        trace
+            .iter()
+            .map(|signals| detector.update(signals))
+            .collect()
+    }
+
+    #[test]
+    fn a_growing_queue_behind_a_busy_link_is_bufferbloat() {
+        let window = config().window;
+        let detected = detect(&rising(0));
+        assert!(detected[..window - 1].iter().all(Option::is_none));
+        let bloat = detected[window - 1].unwrap();
+        assert_eq!(bloat.backlog_bytes, 420_000);
+        assert!((bloat.growth - 4.2).abs() < 1e-9);
+        assert_eq!(bloat.queue_delay, None);
+    }
+
+    #[test]
+    fn the_same_queue_with_drops_is_loss() {
+        assert!(detect(&rising(400)).iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn a_flat_queue_or_an_idle_link_is_neither() {
+        let flat: Vec<_> = (0..INTERVALS)
+            .map(|_| backlogged(400_000, 0, 11_500_000, None))
+            .collect();
+        assert!(detect(&flat).iter().all(Option::is_none));
+        let idle: Vec<_> = (0..INTERVALS)
+            .map(|i| backlogged(100_000 + i * 80_000, 0, 2_000_000, None))
+            .collect();
+        assert!(detect(&idle).iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn long_queue_stops_are_a_standing_queue_without_a_trend() {
+        let standing: Vec<_> = (0..INTERVALS)
+            .map(|_| backlogged(400_000, 0, 11_500_000, Some(30)))
+            .collect();
+        let detected = detect(&standing);
+        let window = config().window;
+        assert!(detected[..window - 1].iter().all(Option::is_none));
+        assert_eq!(
+            detected[window - 1].unwrap().queue_delay,
+            Some(Duration::from_millis(30))
+        );
+    }
+}
@@ -0,0 +1,386 @@
+//Bytes a registered socket has handed to the kernel that the driver hasn't taken
+//yet: socket send path plus qdisc, the part of "in flight" a pacer controls on
+//this host. udp_sendmsg adds every registered send's length, net_dev_xmit the
+//payload of every skb the driver accepts, per CPU in REGISTERED_SOCKETS.
+//
+//The difference drifts. Datagrams dropped before the driver (qdisc limit,
+//ENOBUFS, failed sends still counted at the probe) push it up; header sizes
+//guessed wrong pull it down. Downward drift is absorbed by clamping at zero.
+//Upward drift is bounded by sk_wmem_alloc: every send records the socket's
+//wmem right before its datagram, and what's buffered can't exceed that plus
+//the datagram (wmem counts truesize, more than the payload), so the gauge is
+//pulled down to that bound whenever it's over.
+//
+//The fd a socket was registered with is kept, unowned, for `probe_socket_now()`.
+//Its cookie is checked before every use; if the fd was closed or reused, the
+//socket is looked for again among this process's fds.
+
+use crate::memory::{hash_table_bytes, StructureMemory};
+use crate::probe::{getsockopt, probe_socket};
+use crate::sockets::{socket_cookie, SocketTable};
+use crate::{SocketBytes, SocketStateSample, MAX_REGISTERED_SOCKETS, WMEM_UNREAD};
+use aya::maps::{MapData, PerCpuHashMap, PerCpuValues};
+use aya::util::nr_cpus;
+use aya::Ebpf;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::os::fd::{AsRawFd, BorrowedFd, RawFd};
+
+/// A socket registered with `CongestionCollector::register_socket()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SocketHandle {
+    cookie: u64,
+}
+
+impl SocketHandle {
+    /// The socket cookie, also its key in `read_per_socket()`
+    pub fn socket_id(&self) -> u64 {
+        self.cookie
+    }
+}
+
+/// See `CongestionCollector::signals_for()`
+#[derive(Debug, Clone, Default)]
+pub struct RegisteredSocketSignals {
+    /// Payload handed to the kernel and not yet taken by the driver, bytes.
+    /// An estimate, see the readme for where it's off
+    pub kernel_buffered_bytes: u64,
+    /// Payload handed to udp_sendmsg and taken by the driver since registration
+    pub sent_bytes: u64,
+    pub transmitted_bytes: u64,
+    /// Times the gauge was pulled down to the sk_wmem_alloc bound
+    pub wmem_resyncs: u64,
+    /// Datagrams dropped on its full rcvbuf and its rcvbuf occupancy (0.0-1.0)
+    /// on the latest receive sample, from its `read_per_socket()` entry
+    pub udp_rcv_drops: u64,
+    pub rmem_pressure: Option<f64>,
+}
+
+struct Gauge {
+    // sent - transmitted that counts as zero buffered
+    baseline: i64,
+    latest: RegisteredSocketSignals,
+    // Where the socket was last found, not owned
+    fd: RawFd,
+}
+
+impl Gauge {
+    fn new(fd: RawFd) -> Self {
+        Self {
+            baseline: 0,
+            latest: RegisteredSocketSignals::default(),
+            fd,
+        }
+    }
+}
+
+pub(crate) struct BufferedTracker {
+    // None if the map was unusable
+    map: Option<PerCpuHashMap<MapData, u64, SocketBytes>>,
+    sockets: HashMap<u64, Gauge>,
+}
+
+impl BufferedTracker {
+    pub(crate) fn take(ebpf: &mut Ebpf) -> Self {
+        let map = match ebpf.take_map("REGISTERED_SOCKETS").map(PerCpuHashMap::try_from) {
+            Some(Ok(map)) => Some(map),
+            _ => {
+                log::warn!("REGISTERED_SOCKETS map unusable, buffered bytes tracking disabled");
+                None
+            }
+        };
+        Self {
+            map,
+            sockets: HashMap::new(),
+        }
+    }
+
+    /// Without a map, as if it were unusable
+    #[cfg(test)]
+    pub(crate) fn detached() -> Self {
+        Self {
+            map: None,
+            sockets: HashMap::new(),
+        }
+    }
+
+    /// `next`, a reloaded object's tracker, with the registrations carried
+    /// over, to put in place once the rest of the reload has worked. Leaves
+    /// this one as it was. Its counters start from zero, so the gauges start
+    /// over too
+    pub(crate) fn carried_over(&self, mut next: BufferedTracker) -> anyhow::Result<Self> {
+        for &cookie in self.sockets.keys() {
+            next.insert(cookie)?;
+        }
+        next.sockets = self
+            .sockets
+            .iter()
+            .map(|(&cookie, gauge)| (cookie, Gauge::new(gauge.fd)))
+            .collect();
+        Ok(next)
+    }
+
+    pub(crate) fn register(&mut self, socket: &impl AsRawFd) -> anyhow::Result<SocketHandle> {
+        let protocol = socket_protocol(socket)?;
+        if protocol != libc::IPPROTO_UDP {
+            anyhow::bail!("only UDP sockets can be registered (protocol {})", protocol);
+        }
+        if self.sockets.len() >= MAX_REGISTERED_SOCKETS as usize {
+            anyhow::bail!("{} sockets registered already", MAX_REGISTERED_SOCKETS);
+        }
+        // Also pins the cookie, so the probes key this socket by it from now on
+        let cookie = socket_cookie(socket)?;
+        if !self.sockets.contains_key(&cookie) {
+            self.insert(cookie)?;
+            self.sockets.insert(cookie, Gauge::new(socket.as_raw_fd()));
+        }
+        Ok(SocketHandle { cookie })
+    }
+
+    fn insert(&mut self, cookie: u64) -> anyhow::Result<()> {
+        let map = self
+            .map
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("REGISTERED_SOCKETS map unavailable"))?;
+        let nr_cpus =
+            nr_cpus().map_err(|e| anyhow::anyhow!("Failed to get possible CPUs: {:?}", e))?;
+        let zeros = PerCpuValues::try_from(vec![SocketBytes::default(); nr_cpus])?;
+        map.insert(cookie, zeros, 0)?;
+        Ok(())
+    }
+
+    /// Whether `socket_id` is a registered socket's cookie; those see every
+    /// send, so they stay in a sampled per-socket view
+    pub(crate) fn is_registered(&self, socket_id: u64) -> bool {
+        self.sockets.contains_key(&socket_id)
+    }
+
+    /// Registration refuses sockets past the cap instead of evicting
+    pub(crate) fn memory(&self) -> StructureMemory {
+        StructureMemory {
+            name: "registered sockets",
+            entries: self.sockets.len(),
+            cap: MAX_REGISTERED_SOCKETS as usize,
+            estimated_bytes: hash_table_bytes::<u64, Gauge>(self.sockets.capacity()),
+            evictions: 0,
+        }
+    }
+
+    pub(crate) fn unregister(&mut self, handle: SocketHandle) {
+        if self.sockets.remove(&handle.cookie).is_some() {
+            if let Some(map) = self.map.as_mut() {
+                let _ = map.remove(&handle.cookie);
+            }
+        }
+    }
+
+    /// Re-read every registered socket's counters, once per interval read.
+    /// The receive side comes from the processing side's per-socket table
+    pub(crate) fn update(&mut self, sockets: &SocketTable) {
+        for (&cookie, gauge) in &mut self.sockets {
+            if let Some(socket) = sockets.get(cookie) {
+                gauge.latest.udp_rcv_drops = socket.udp_rcv_drops;
+                gauge.latest.rmem_pressure = socket.rmem_pressure;
+            }
+        }
+        let Some(map) = &self.map else {
+            return;
+        };
+        for (cookie, gauge) in &mut self.sockets {
+            let Ok(per_cpu) = map.get(cookie, 0) else {
+                continue;
+            };
+            let sent: u64 = per_cpu.iter().map(|b| b.sent).sum();
+            let transmitted: u64 = per_cpu.iter().map(|b| b.transmitted).sum();
+            // Every send writes its CPU's slot, so the newest slot is the last send
+            let last = per_cpu.iter().max_by_key(|b| b.last_send_ns).copied();
+
+            let raw = sent as i64 - transmitted as i64;
+            let mut buffered = raw - gauge.baseline;
+            if buffered < 0 {
+                gauge.baseline = raw;
+                buffered = 0;
+            }
+            if let Some(last) = last.filter(|b| b.last_send_ns > 0 && b.last_send_wmem != WMEM_UNREAD)
+            {
+                let bound = last.last_send_wmem as i64 + last.last_send_len as i64;
+                if buffered > bound {
+                    gauge.baseline += buffered - bound;
+                    buffered = bound;
+                    gauge.latest.wmem_resyncs += 1;
+                }
+            }
+
+            gauge.latest.kernel_buffered_bytes = buffered as u64;
+            gauge.latest.sent_bytes = sent;
+            gauge.latest.transmitted_bytes = transmitted;
+        }
+    }
+
+    pub(crate) fn get(&self, handle: &SocketHandle) -> Option<RegisteredSocketSignals> {
+        self.sockets
+            .get(&handle.cookie)
+            .map(|gauge| gauge.latest.clone())
+    }
+
+    /// A registered socket's buffers read now
+    pub(crate) fn probe(&mut self, handle: &SocketHandle) -> anyhow::Result<SocketStateSample> {
+        let gauge = self
+            .sockets
+            .get_mut(&handle.cookie)
+            .ok_or_else(|| anyhow::anyhow!("socket {} not registered", handle.cookie))?;
+        if !has_cookie(gauge.fd, handle.cookie) {
+            gauge.fd = find_fd(handle.cookie).ok_or_else(|| {
+                anyhow::anyhow!("socket {} no longer open in this process", handle.cookie)
+            })?;
+        }
+        let fd = unsafe { BorrowedFd::borrow_raw(gauge.fd) };
+        Ok(probe_socket(&fd)?)
+    }
+
+    /// A registered socket's peer, None if it isn't registered, can't be
+    /// found or isn't connected
+    pub(crate) fn peer(&mut self, socket_id: u64) -> Option<SocketAddr> {
+        let gauge = self.sockets.get_mut(&socket_id)?;
+        if !has_cookie(gauge.fd, socket_id) {
+            gauge.fd = find_fd(socket_id)?;
+        }
+        peer_addr(gauge.fd)
+    }
+
+    /// Every registered socket's buffers read now, leaving out ones that
+    /// can't be found
+    pub(crate) fn probe_all(&mut self) -> Vec<SocketStateSample> {
+        let cookies: Vec<u64> = self.sockets.keys().copied().collect();
+        cookies
+            .into_iter()
+            .filter_map(|cookie| match self.probe(&SocketHandle { cookie }) {
+                Ok(sample) => Some(sample),
+                Err(e) => {
+                    log::debug!("no probe of registered socket: {}", e);
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+fn has_cookie(fd: RawFd, cookie: u64) -> bool {
+    getsockopt::<u64>(fd, libc::SO_COOKIE).is_ok_and(|found| found == cookie)
+}
+
+/// getpeername, None for an unconnected socket
+fn peer_addr(fd: RawFd) -> Option<SocketAddr> {
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getpeername(
+            fd,
+            &mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return None;
+    }
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let addr = unsafe { *(&storage as *const _ as *const libc::sockaddr_in) };
+            let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+            Some(SocketAddr::from((ip, u16::from_be(addr.sin_port))))
+        }
+        libc::AF_INET6 => {
+            let addr = unsafe { *(&storage as *const _ as *const libc::sockaddr_in6) };
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                u16::from_be(addr.sin6_port),
+                addr.sin6_flowinfo,
+                addr.sin6_scope_id,
+            )))
+        }
+        _ => None,
+    }
+}
+
+/// This process's fd for the socket with `cookie`, if it still has one
+fn find_fd(cookie: u64) -> Option<RawFd> {
+    std::fs::read_dir("/proc/self/fd")
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .find(|&fd| has_cookie(fd, cookie))
+}
+
+fn socket_protocol(socket: &impl AsRawFd) -> std::io::Result<i32> {
+    getsockopt(socket.as_raw_fd(), libc::SO_PROTOCOL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures, CollectorConfig};
+
+    #[test]
+    fn receive_side_comes_from_the_socket_table() {
+        let table = SocketTable::from_config(&CollectorConfig::default());
+        let mut tracker = BufferedTracker::detached();
+        tracker.sockets.insert(42, Gauge::new(-1));
+        tracker.sockets.insert(43, Gauge::new(-1));
+
+        table.record(&fixtures::rcv_state(1_000, 0, 42, 53_248, 212_992));
+        table.record(&fixtures::rcv_drop(2_000, 0, 42));
+        table.record(&fixtures::rcv_drop(3_000, 1, 42));
+        // Not registered, stays out of every gauge
+        table.record(&fixtures::rcv_drop(3_000, 1, 44));
+        tracker.update(&table);
+
+        let registered = tracker.get(&SocketHandle { cookie: 42 }).unwrap();
+        assert_eq!(registered.udp_rcv_drops, 2);
+        assert_eq!(registered.rmem_pressure, Some(0.25));
+        // No receive events yet
+        let quiet = tracker.get(&SocketHandle { cookie: 43 }).unwrap();
+        assert_eq!((quiet.udp_rcv_drops, quiet.rmem_pressure), (0, None));
+        assert!(tracker.get(&SocketHandle { cookie: 44 }).is_none());
+    }
+
+    #[test]
+    fn only_udp_sockets_register() {
+        let mut tracker = BufferedTracker::detached();
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let err = tracker.register(&tcp).unwrap_err();
+        assert!(err.to_string().contains("only UDP sockets"));
+
+        // Without the map there's nowhere to count its sends
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let err = tracker.register(&udp).unwrap_err();
+        assert!(err.to_string().contains("REGISTERED_SOCKETS"));
+        assert!(!tracker.is_registered(socket_cookie(&udp).unwrap()));
+    }
+
+    #[test]
+    fn a_failed_carry_over_leaves_the_registrations_in_place() {
+        let mut tracker = BufferedTracker::detached();
+        tracker.sockets.insert(42, Gauge::new(7));
+        tracker.sockets.get_mut(&42).unwrap().latest.sent_bytes = 1200;
+
+        // The reloaded object's map is unusable
+        let err = tracker
+            .carried_over(BufferedTracker::detached())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("REGISTERED_SOCKETS"));
+        assert!(tracker.is_registered(42));
+        assert_eq!(
+            tracker
+                .get(&SocketHandle { cookie: 42 })
+                .unwrap()
+                .sent_bytes,
+            1200
+        );
+
+        // Nothing registered, nothing to insert: carried over as is
+        let empty = BufferedTracker::detached()
+            .carried_over(BufferedTracker::detached())
+            .unwrap();
+        assert_eq!(empty.sockets.len(), 0);
+    }
+}
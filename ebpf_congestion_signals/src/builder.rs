@@ -0,0 +1,465 @@
+//`CongestionSignals` for tests and fixtures outside this crate. The struct is
+//`#[non_exhaustive]`: it gains a field most releases, and a struct literal in
+//a downstream test would break on each. The builder starts from the default,
+//all zeros and None, and has a setter per field named after it. An Option
+//field's setter takes the value or an Option; leave it unset for None.
+//
+//Nothing checks that the fields agree with each other, as a real read's do:
+//`drops` and `observed.drops` are set apart, and `limitation` isn't derived
+//from the pressures. `with_split_from_flat` fills `observed` and `estimated`
+//from the flat counters when a test needs both.
+
+use crate::{
+    CaptureNotice, CongestionSignals, ConnectionChurn, DropInterarrival, EstimatedTotals,
+    FixedSignals, GsoSegments, InterfaceEgress, InterfaceTxq, JsonValue, Limitation,
+    ObservedCounts, ProtocolCounts, SamplerEstimates, SendConcentration, SendSizeStats, Signal,
+    SoftirqAttribution, SoftirqCoupling,
+};
+use std::collections::HashMap;
+use std::time::{Instant, SystemTime};
+
+// Interval of the presets
+const PRESET_INTERVAL_NS: u64 = 1_000_000_000;
+
+impl CongestionSignals {
+    /// Signals from the default up, see [`CongestionSignalsBuilder`]
+    pub fn builder() -> CongestionSignalsBuilder {
+        CongestionSignalsBuilder::default()
+    }
+
+    /// One second with nothing sent and nothing pushing back, app-limited
+    pub fn idle() -> Self {
+        Self::builder()
+            .interval_ns(PRESET_INTERVAL_NS)
+            .limitation(Limitation::AppLimited)
+            .build()
+    }
+
+    /// One second of a host sending 10 MB/s into full buffers: 2000 drops,
+    /// wmem pressure at 0.9 and the busiest CPU 30% in softirq. Past the
+    /// default policy's cut threshold on drops alone
+    #[cfg(not(feature = "no-float"))]
+    pub fn synthetic_congested() -> Self {
+        Self::builder()
+            .interval_ns(PRESET_INTERVAL_NS)
+            // Sampled 1 in 100
+            .send_bytes(100_000)
+            .external_send_bytes(100_000)
+            .drops(2_000)
+            .avg_wmem_pressure(0.9)
+            .udp_wmem_pressure(0.9)
+            .softirq_ns(300_000_000)
+            .softirq_cpu_fraction(0.3)
+            .queue_depth_packets(5_000)
+            .queue_depth_bytes(6_000_000)
+            .event_count(3_000)
+            .limitation(Limitation::NetworkLimited)
+            .build()
+            .with_split_from_flat()
+    }
+}
+
+/// Builds [`CongestionSignals`] field by field, starting from the default.
+/// Setters are named after the fields; an Option field's takes the value or
+/// an Option.
+///
+/// A downstream test of its own pacing against a drop-heavy interval:
+///
+/// ```
+/// use ebpf_congestion_signals::{CongestionSignals, Governor, GovernorPolicy, PacingAction};
+///
+/// let drop_heavy = CongestionSignals::builder()
+///     .interval_ns(1_000_000_000)
+///     .drops(5_000)
+///     .udp_wmem_pressure(0.8)
+///     .build();
+/// let mut governor = Governor::new(GovernorPolicy::default(), 12_500_000);
+/// let decision = governor.update(&drop_heavy);
+/// assert_eq!(decision.action, PacingAction::Cut);
+/// assert!(decision.rate < 12_500_000);
+///
+/// // Nothing to react to
+/// let mut governor = Governor::new(GovernorPolicy::default(), 12_500_000);
+/// assert_ne!(governor.update(&CongestionSignals::idle()).action, PacingAction::Cut);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CongestionSignalsBuilder {
+    signals: CongestionSignals,
+}
+
+impl CongestionSignalsBuilder {
+    /// Start from `signals` instead of the default, e.g. a preset
+    pub fn from_signals(signals: CongestionSignals) -> Self {
+        Self { signals }
+    }
+
+    pub fn build(self) -> CongestionSignals {
+        self.signals
+    }
+
+    pub fn interval_ns(mut self, interval_ns: u64) -> Self {
+        self.signals.interval_ns = interval_ns;
+        self
+    }
+
+    pub fn aligned_start(mut self, aligned_start: impl Into<Option<SystemTime>>) -> Self {
+        self.signals.aligned_start = aligned_start.into();
+        self
+    }
+
+    pub fn aligned_end(mut self, aligned_end: impl Into<Option<SystemTime>>) -> Self {
+        self.signals.aligned_end = aligned_end.into();
+        self
+    }
+
+    pub fn produced_at(mut self, produced_at: impl Into<Option<Instant>>) -> Self {
+        self.signals.produced_at = produced_at.into();
+        self
+    }
+
+    pub fn observed(mut self, observed: ObservedCounts) -> Self {
+        self.signals.observed = observed;
+        self
+    }
+
+    pub fn estimated(mut self, estimated: EstimatedTotals) -> Self {
+        self.signals.estimated = estimated;
+        self
+    }
+
+    pub fn send_bytes(mut self, send_bytes: u64) -> Self {
+        self.signals.send_bytes = send_bytes;
+        self
+    }
+
+    pub fn loopback_send_bytes(mut self, loopback_send_bytes: impl Into<Option<u64>>) -> Self {
+        self.signals.loopback_send_bytes = loopback_send_bytes.into();
+        self
+    }
+
+    pub fn external_send_bytes(mut self, external_send_bytes: u64) -> Self {
+        self.signals.external_send_bytes = external_send_bytes;
+        self
+    }
+
+    pub fn send_size_stats(mut self, send_size_stats: SendSizeStats) -> Self {
+        self.signals.send_size_stats = send_size_stats;
+        self
+    }
+
+    pub fn est_send_msgs(mut self, est_send_msgs: ProtocolCounts) -> Self {
+        self.signals.est_send_msgs = est_send_msgs;
+        self
+    }
+
+    pub fn est_wire_packets(mut self, est_wire_packets: ProtocolCounts) -> Self {
+        self.signals.est_wire_packets = est_wire_packets;
+        self
+    }
+
+    pub fn udp_gso(mut self, udp_gso: impl Into<Option<GsoSegments>>) -> Self {
+        self.signals.udp_gso = udp_gso.into();
+        self
+    }
+
+    pub fn drops(mut self, drops: u64) -> Self {
+        self.signals.drops = drops;
+        self
+    }
+
+    #[cfg(not(feature = "no-float"))]
+    pub fn avg_wmem_pressure(mut self, avg_wmem_pressure: f64) -> Self {
+        self.signals.avg_wmem_pressure = avg_wmem_pressure;
+        self
+    }
+
+    #[cfg(not(feature = "no-float"))]
+    pub fn udp_wmem_pressure(mut self, udp_wmem_pressure: impl Into<Option<f64>>) -> Self {
+        self.signals.udp_wmem_pressure = udp_wmem_pressure.into();
+        self
+    }
+
+    #[cfg(not(feature = "no-float"))]
+    pub fn tcp_wmem_pressure(mut self, tcp_wmem_pressure: impl Into<Option<f64>>) -> Self {
+        self.signals.tcp_wmem_pressure = tcp_wmem_pressure.into();
+        self
+    }
+
+    #[cfg(not(feature = "no-float"))]
+    pub fn net_memory_pressure(mut self, net_memory_pressure: impl Into<Option<f64>>) -> Self {
+        self.signals.net_memory_pressure = net_memory_pressure.into();
+        self
+    }
+
+    pub fn memory_pressure_events(
+        mut self,
+        memory_pressure_events: impl Into<Option<u64>>,
+    ) -> Self {
+        self.signals.memory_pressure_events = memory_pressure_events.into();
+        self
+    }
+
+    #[cfg(not(feature = "no-float"))]
+    pub fn avg_socket_pacing_rate(
+        mut self,
+        avg_socket_pacing_rate: impl Into<Option<f64>>,
+    ) -> Self {
+        self.signals.avg_socket_pacing_rate = avg_socket_pacing_rate.into();
+        self
+    }
+
+    #[cfg(not(feature = "no-float"))]
+    pub fn kernel_paced_send_share(
+        mut self,
+        kernel_paced_send_share: impl Into<Option<f64>>,
+    ) -> Self {
+        self.signals.kernel_paced_send_share = kernel_paced_send_share.into();
+        self
+    }
+
+    pub fn kernel_paced(mut self, kernel_paced: bool) -> Self {
+        self.signals.kernel_paced = kernel_paced;
+        self
+    }
+
+    pub fn softirq_ns(mut self, softirq_ns: u64) -> Self {
+        self.signals.softirq_ns = softirq_ns;
+        self
+    }
+
+    pub fn event_count(mut self, event_count: u64) -> Self {
+        self.signals.event_count = event_count;
+        self
+    }
+
+    pub fn active_sockets(mut self, active_sockets: u64) -> Self {
+        self.signals.active_sockets = active_sockets;
+        self
+    }
+
+    pub fn active_flows(mut self, active_flows: impl Into<Option<u64>>) -> Self {
+        self.signals.active_flows = active_flows.into();
+        self
+    }
+
+    pub fn new_connections_per_interval(mut self, new_connections_per_interval: u64) -> Self {
+        self.signals.new_connections_per_interval = new_connections_per_interval;
+        self
+    }
+
+    pub fn closed_connections_per_interval(mut self, closed_connections_per_interval: u64) -> Self {
+        self.signals.closed_connections_per_interval = closed_connections_per_interval;
+        self
+    }
+
+    pub fn connections(mut self, connections: ConnectionChurn) -> Self {
+        self.signals.connections = connections;
+        self
+    }
+
+    pub fn send_concentration(
+        mut self,
+        send_concentration: impl Into<Option<SendConcentration>>,
+    ) -> Self {
+        self.signals.send_concentration = send_concentration.into();
+        self
+    }
+
+    pub fn sampler_comparison(
+        mut self,
+        sampler_comparison: impl Into<Option<SamplerEstimates>>,
+    ) -> Self {
+        self.signals.sampler_comparison = sampler_comparison.into();
+        self
+    }
+
+    pub fn queue_depth_packets(mut self, queue_depth_packets: u64) -> Self {
+        self.signals.queue_depth_packets = queue_depth_packets;
+        self
+    }
+
+    pub fn queue_depth_bytes(mut self, queue_depth_bytes: u64) -> Self {
+        self.signals.queue_depth_bytes = queue_depth_bytes;
+        self
+    }
+
+    pub fn udp_rcv_drops(mut self, udp_rcv_drops: u64) -> Self {
+        self.signals.udp_rcv_drops = udp_rcv_drops;
+        self
+    }
+
+    #[cfg(not(feature = "no-float"))]
+    pub fn avg_rmem_pressure(mut self, avg_rmem_pressure: f64) -> Self {
+        self.signals.avg_rmem_pressure = avg_rmem_pressure;
+        self
+    }
+
+    #[cfg(not(feature = "no-float"))]
+    pub fn softirq_cpu_fraction(mut self, softirq_cpu_fraction: f64) -> Self {
+        self.signals.softirq_cpu_fraction = softirq_cpu_fraction;
+        self
+    }
+
+    pub fn fixed(mut self, fixed: impl Into<Option<FixedSignals>>) -> Self {
+        self.signals.fixed = fixed.into();
+        self
+    }
+
+    #[cfg(not(feature = "no-float"))]
+    pub fn tcp_avg_cwnd(mut self, tcp_avg_cwnd: impl Into<Option<f64>>) -> Self {
+        self.signals.tcp_avg_cwnd = tcp_avg_cwnd.into();
+        self
+    }
+
+    #[cfg(not(feature = "no-float"))]
+    pub fn tcp_avg_ssthresh(mut self, tcp_avg_ssthresh: impl Into<Option<f64>>) -> Self {
+        self.signals.tcp_avg_ssthresh = tcp_avg_ssthresh.into();
+        self
+    }
+
+    #[cfg(not(feature = "no-float"))]
+    pub fn tcp_avg_pacing_rate(mut self, tcp_avg_pacing_rate: impl Into<Option<f64>>) -> Self {
+        self.signals.tcp_avg_pacing_rate = tcp_avg_pacing_rate.into();
+        self
+    }
+
+    pub fn tcp_sockets_below_ssthresh(
+        mut self,
+        tcp_sockets_below_ssthresh: impl Into<Option<u64>>,
+    ) -> Self {
+        self.signals.tcp_sockets_below_ssthresh = tcp_sockets_below_ssthresh.into();
+        self
+    }
+
+    pub fn ce_marks(mut self, ce_marks: impl Into<Option<u64>>) -> Self {
+        self.signals.ce_marks = ce_marks.into();
+        self
+    }
+
+    pub fn ce_triggered_cwr(mut self, ce_triggered_cwr: impl Into<Option<u64>>) -> Self {
+        self.signals.ce_triggered_cwr = ce_triggered_cwr.into();
+        self
+    }
+
+    pub fn rto_events(mut self, rto_events: impl Into<Option<u64>>) -> Self {
+        self.signals.rto_events = rto_events.into();
+        self
+    }
+
+    pub fn loss_recovery_episodes(
+        mut self,
+        loss_recovery_episodes: impl Into<Option<u64>>,
+    ) -> Self {
+        self.signals.loss_recovery_episodes = loss_recovery_episodes.into();
+        self
+    }
+
+    pub fn rx_time_squeeze(mut self, rx_time_squeeze: impl Into<Option<u64>>) -> Self {
+        self.signals.rx_time_squeeze = rx_time_squeeze.into();
+        self
+    }
+
+    pub fn implausible_socket_samples(mut self, implausible_socket_samples: u64) -> Self {
+        self.signals.implausible_socket_samples = implausible_socket_samples;
+        self
+    }
+
+    #[cfg(not(feature = "no-float"))]
+    pub fn burst_drop_correlation(
+        mut self,
+        burst_drop_correlation: impl Into<Option<f64>>,
+    ) -> Self {
+        self.signals.burst_drop_correlation = burst_drop_correlation.into();
+        self
+    }
+
+    pub fn drop_interarrival(
+        mut self,
+        drop_interarrival: impl Into<Option<DropInterarrival>>,
+    ) -> Self {
+        self.signals.drop_interarrival = drop_interarrival.into();
+        self
+    }
+
+    pub fn softirq_coupling(
+        mut self,
+        softirq_coupling: impl Into<Option<SoftirqCoupling>>,
+    ) -> Self {
+        self.signals.softirq_coupling = softirq_coupling.into();
+        self
+    }
+
+    pub fn softirq_attribution(
+        mut self,
+        softirq_attribution: impl Into<Option<SoftirqAttribution>>,
+    ) -> Self {
+        self.signals.softirq_attribution = softirq_attribution.into();
+        self
+    }
+
+    pub fn tsq_throttles(mut self, tsq_throttles: impl Into<Option<u64>>) -> Self {
+        self.signals.tsq_throttles = tsq_throttles.into();
+        self
+    }
+
+    pub fn softirq_discarded(mut self, softirq_discarded: u64) -> Self {
+        self.signals.softirq_discarded = softirq_discarded;
+        self
+    }
+
+    pub fn missing_signals(mut self, missing_signals: Vec<Signal>) -> Self {
+        self.signals.missing_signals = missing_signals;
+        self
+    }
+
+    pub fn degradation_level(mut self, degradation_level: u32) -> Self {
+        self.signals.degradation_level = degradation_level;
+        self
+    }
+
+    pub fn limitation(mut self, limitation: Limitation) -> Self {
+        self.signals.limitation = limitation;
+        self
+    }
+
+    pub fn egress(mut self, egress: Vec<InterfaceEgress>) -> Self {
+        self.signals.egress = egress;
+        self
+    }
+
+    pub fn txq_stalls(mut self, txq_stalls: impl Into<Option<u64>>) -> Self {
+        self.signals.txq_stalls = txq_stalls.into();
+        self
+    }
+
+    pub fn txq_stalled_ns(mut self, txq_stalled_ns: impl Into<Option<u64>>) -> Self {
+        self.signals.txq_stalled_ns = txq_stalled_ns.into();
+        self
+    }
+
+    pub fn txq_by_interface(mut self, txq_by_interface: Vec<InterfaceTxq>) -> Self {
+        self.signals.txq_by_interface = txq_by_interface;
+        self
+    }
+
+    #[cfg(not(feature = "no-float"))]
+    pub fn egress_estimate_error(mut self, egress_estimate_error: impl Into<Option<f64>>) -> Self {
+        self.signals.egress_estimate_error = egress_estimate_error.into();
+        self
+    }
+
+    pub fn sessions(mut self, sessions: Vec<String>) -> Self {
+        self.signals.sessions = sessions;
+        self
+    }
+
+    pub fn captures(mut self, captures: Vec<CaptureNotice>) -> Self {
+        self.signals.captures = captures;
+        self
+    }
+
+    pub fn extensions(mut self, extensions: HashMap<String, JsonValue>) -> Self {
+        self.signals.extensions = extensions;
+        self
+    }
+}
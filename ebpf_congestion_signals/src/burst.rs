@@ -0,0 +1,280 @@
+//Send-burst -> drop correlation. A few ms of line-rate sending followed shortly
+//by a cluster of qdisc drops is invisible in interval totals, so this buckets
+//the raw send and drop events on the processing side, finds the buckets whose
+//send volume stands out from the interval, and reports how many of the
+//interval's drops landed shortly after one. The buckets live in a hash table
+//cleared in place after each read, so recording allocates nothing once it has
+//grown to an interval's worth.
+
+use crate::memory::{hash_table_bytes, StructureMemory};
+use crate::{CongestionEvent, EVENT_QDISC_DROP, EVENT_TCP_SEND, EVENT_UDP_SEND};
+use std::collections::HashMap;
+use std::time::Duration;
+
+// Bound on buckets held between two interval reads; ~100 s at 10 ms buckets
+const MAX_BUCKETS: usize = 10_000;
+
+/// Turns on `CongestionSignals::burst_drop_correlation`, see
+/// `CollectorConfig::with_burst_correlation`
+#[derive(Debug, Clone)]
+pub struct BurstCorrelationConfig {
+    /// Width of one bucket
+    pub bucket: Duration,
+    /// A bucket is a burst when its send bytes exceed the interval's mean bucket
+    /// by this many standard deviations
+    pub burst_sigma: f64,
+    /// Drops up to this long after the end of a burst bucket count as following it
+    pub drop_window: Duration,
+}
+
+impl Default for BurstCorrelationConfig {
+    fn default() -> Self {
+        Self {
+            bucket: Duration::from_millis(10),
+            burst_sigma: 2.0,
+            drop_window: Duration::from_millis(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Bucket {
+    send_bytes: u64,
+    drops: u64,
+}
+
+pub(crate) struct BurstCorrelator {
+    bucket_ns: u64,
+    window_buckets: u64,
+    burst_sigma: f64,
+    // Bucket index (timestamp_ns / bucket_ns) -> totals since the last read
+    buckets: HashMap<u64, Bucket>,
+    // Bursts from the end of the previous interval whose drop window runs into
+    // this one, and this interval's while it's being read
+    bursts: Vec<u64>,
+    // Oldest buckets dropped at MAX_BUCKETS
+    evictions: u64,
+}
+
+impl BurstCorrelator {
+    pub(crate) fn new(config: &BurstCorrelationConfig) -> Self {
+        let bucket_ns = (config.bucket.as_nanos() as u64).max(1);
+        Self {
+            bucket_ns,
+            window_buckets: (config.drop_window.as_nanos() as u64).div_ceil(bucket_ns),
+            burst_sigma: config.burst_sigma,
+            buckets: HashMap::new(),
+            bursts: Vec::new(),
+            evictions: 0,
+        }
+    }
+
+    pub(crate) fn record(&mut self, event: &CongestionEvent) {
+        let index = event.timestamp_ns / self.bucket_ns;
+        match event.event_type {
+            EVENT_UDP_SEND | EVENT_TCP_SEND => {
+                let bytes = unsafe { event.data.sendmsg.bytes };
+                self.bucket(index).send_bytes += bytes;
+            }
+            EVENT_QDISC_DROP => {
+                let dropped = unsafe { event.data.qdisc.dropped };
+                self.bucket(index).drops += dropped as u64;
+            }
+            _ => {}
+        }
+    }
+
+    fn bucket(&mut self, index: u64) -> &mut Bucket {
+        if self.buckets.len() >= MAX_BUCKETS && !self.buckets.contains_key(&index) {
+            // Only after ~100 s without a read, the scan is fine
+            if let Some(&oldest) = self.buckets.keys().min() {
+                self.buckets.remove(&oldest);
+            }
+            self.evictions += 1;
+        }
+        self.buckets.entry(index).or_default()
+    }
+
+    pub(crate) fn memory(&self) -> StructureMemory {
+        StructureMemory {
+            name: "burst buckets",
+            entries: self.buckets.len(),
+            cap: MAX_BUCKETS,
+            estimated_bytes: hash_table_bytes::<u64, Bucket>(self.buckets.capacity()),
+            evictions: self.evictions,
+        }
+    }
+
+    /// Drop everything bucketed so far, the next interval starts empty
+    pub(crate) fn reset(&mut self) {
+        self.buckets.clear();
+        self.bursts.clear();
+    }
+
+    /// Fraction of this interval's drops that fell within the drop window after a
+    /// burst bucket, then start over. None without drops to attribute.
+    ///
+    /// Mean and deviation are over every bucket from the first to the last one
+    /// with events, empty ones counting as zero
+    pub(crate) fn take_interval(&mut self) -> Option<f64> {
+        let correlation = self.correlate();
+        self.buckets.clear();
+        correlation
+    }
+
+    fn correlate(&mut self) -> Option<f64> {
+        let buckets = &self.buckets;
+        let (Some(&first), Some(&last)) = (buckets.keys().min(), buckets.keys().max()) else {
+            self.bursts.clear();
+            return None;
+        };
+
+        let span = (last - first + 1) as f64;
+        let total_send: u64 = buckets.values().map(|b| b.send_bytes).sum();
+        let mean = total_send as f64 / span;
+        let occupied_sq: f64 = buckets
+            .values()
+            .map(|b| (b.send_bytes as f64 - mean).powi(2))
+            .sum();
+        let empty = span - buckets.len() as f64;
+        let std_dev = ((occupied_sq + empty * mean * mean) / span).sqrt();
+        let threshold = mean + self.burst_sigma * std_dev;
+
+        // After the carried ones
+        let bursts = &mut self.bursts;
+        bursts.extend(
+            buckets
+                .iter()
+                .filter(|(_, b)| b.send_bytes > 0 && b.send_bytes as f64 > threshold)
+                .map(|(&index, _)| index),
+        );
+
+        let drops: u64 = buckets.values().map(|b| b.drops).sum();
+        let following: u64 = buckets
+            .iter()
+            .filter(|(&index, b)| {
+                b.drops > 0
+                    && bursts
+                        .iter()
+                        .any(|&burst| index > burst && index - burst <= self.window_buckets)
+            })
+            .map(|(_, b)| b.drops)
+            .sum();
+
+        // Their windows reach past what we've seen; events still in the queue
+        // land in the next interval
+        let window_buckets = self.window_buckets;
+        bursts.retain(|&burst| burst + window_buckets > last);
+
+        (drops > 0).then(|| following as f64 / drops as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, Replay};
+
+    const MS: u64 = 1_000_000;
+
+    fn correlator() -> BurstCorrelator {
+        BurstCorrelator::new(&BurstCorrelationConfig::default())
+    }
+
+    /// A steady 1000 bytes every 10 ms bucket over `0..buckets`, with `burst`
+    /// bytes more in bucket `at`
+    fn traffic(correlator: &mut BurstCorrelator, buckets: u64, at: u64, burst: u64) {
+        for bucket in 0..buckets {
+            correlator.record(&fixtures::udp_send(bucket * 10 * MS, 0, 7, 1000));
+        }
+        correlator.record(&fixtures::udp_send(at * 10 * MS + MS, 0, 7, burst));
+    }
+
+    #[test]
+    fn drops_right_after_a_burst_correlate() {
+        let mut correlator = correlator();
+        traffic(&mut correlator, 50, 20, 100_000);
+        // 5 ms after the burst bucket ends
+        correlator.record(&fixtures::qdisc_drop(215 * MS, 0, 8, 0));
+        // Nowhere near it
+        correlator.record(&fixtures::qdisc_drop(405 * MS, 0, 2, 0));
+        assert_eq!(correlator.take_interval(), Some(0.8));
+    }
+
+    #[test]
+    fn drops_outside_the_window_or_in_the_burst_itself_do_not() {
+        let mut correlator = correlator();
+        traffic(&mut correlator, 50, 20, 100_000);
+        // Same bucket as the burst, then past the 10 ms window
+        correlator.record(&fixtures::qdisc_drop(205 * MS, 0, 4, 0));
+        correlator.record(&fixtures::qdisc_drop(225 * MS, 0, 4, 0));
+        assert_eq!(correlator.take_interval(), Some(0.0));
+    }
+
+    #[test]
+    fn steady_sending_has_no_bursts() {
+        let mut correlator = correlator();
+        traffic(&mut correlator, 50, 20, 0);
+        correlator.record(&fixtures::qdisc_drop(215 * MS, 0, 8, 0));
+        assert_eq!(correlator.take_interval(), Some(0.0));
+    }
+
+    #[test]
+    fn no_drops_no_statistic() {
+        let mut correlator = correlator();
+        traffic(&mut correlator, 50, 20, 100_000);
+        assert_eq!(correlator.take_interval(), None);
+        assert_eq!(correlator.take_interval(), None);
+    }
+
+    #[test]
+    fn a_burst_at_the_end_of_an_interval_reaches_into_the_next() {
+        let mut correlator = correlator();
+        traffic(&mut correlator, 50, 49, 100_000);
+        assert_eq!(correlator.take_interval(), None);
+        // The drops land after the read, 3 ms past the burst bucket
+        correlator.record(&fixtures::qdisc_drop(503 * MS, 0, 5, 0));
+        correlator.record(&fixtures::udp_send(503 * MS, 0, 7, 1000));
+        assert_eq!(correlator.take_interval(), Some(1.0));
+    }
+
+    #[test]
+    fn buckets_are_bounded_between_reads() {
+        let mut correlator = correlator();
+        for bucket in 0..MAX_BUCKETS as u64 + 5 {
+            correlator.record(&fixtures::udp_send(bucket * 10 * MS, 0, 7, 1000));
+        }
+        let memory = correlator.memory();
+        assert_eq!(memory.entries, MAX_BUCKETS);
+        assert_eq!(memory.evictions, 5);
+        correlator.reset();
+        assert_eq!(correlator.memory().entries, 0);
+    }
+
+    #[test]
+    fn replayed_bursts_reach_the_interval_read() {
+        let config = crate::CollectorConfig::default()
+            .with_burst_correlation(BurstCorrelationConfig::default());
+        let replay = Replay::new(config, 1);
+        let mut events: Vec<_> = (0..50)
+            .map(|bucket| fixtures::udp_send(bucket * 10 * MS, 0, 7, 1000))
+            .collect();
+        events.push(fixtures::udp_send(201 * MS, 0, 7, 100_000));
+        events.push(fixtures::qdisc_drop(212 * MS, 0, 3, 0));
+        events.push(fixtures::qdisc_drop(460 * MS, 0, 1, 0));
+        events.sort_by_key(|event| event.timestamp_ns);
+        replay.feed(&events);
+
+        let signals = replay.read(Duration::from_millis(500));
+        assert_eq!(signals.burst_drop_correlation, Some(0.75));
+        // Off without the config
+        let replay = Replay::new(crate::CollectorConfig::default(), 1);
+        replay.feed(&events);
+        assert_eq!(
+            replay
+                .read(Duration::from_millis(500))
+                .burst_drop_correlation,
+            None
+        );
+    }
+}
@@ -0,0 +1,343 @@
+//Startup check of the socket-state decoding. The offsets into struct sock come
+//from BTF and a fallback table; on an unusual kernel they can be wrong in ways
+//that still pass the plausibility bounds, and the wmem pressures are quietly
+//nonsense. So start_collection opens a loopback TCP connection with a known
+//SO_SNDBUF, sends on it until the 1-in-100 TCP state sampling has picked it a
+//few times, and compares the sk_sndbuf the probe read with what getsockopt says.
+//
+//Samples are matched by the sender's cookie, armed before the first send. The
+//first one is also the wall-clock self-check's event: it's timestamped by the
+//kernel and seen here a pipeline's latency later.
+
+use crate::{socket_cookie, CongestionEvent, EVENT_SOCKET_STATE};
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+// Samples of the calibration socket to wait for before deciding
+const CALIBRATION_SAMPLES: usize = 3;
+// Sends between looks at what has arrived, about one sample's worth
+const SENDS_PER_CHECK: u64 = 100;
+
+/// How `start_collection` checks the socket-state offsets, see
+/// `CollectorConfig::without_calibration`
+#[derive(Debug, Clone)]
+pub struct CalibrationConfig {
+    /// SO_SNDBUF set on the calibration socket; the kernel doubles it
+    pub sndbuf: u32,
+    /// Relative difference between the sk_sndbuf read and the real one that
+    /// still counts as a match
+    pub tolerance: f64,
+    /// How long to keep sending before calling it inconclusive
+    pub timeout: Duration,
+    /// After a mismatch, leave socket-state samples out of the signals (the wmem
+    /// pressures read as missing) instead of only reporting it
+    pub disable_on_mismatch: bool,
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        Self {
+            sndbuf: 131_072,
+            tolerance: 0.10,
+            timeout: Duration::from_secs(2),
+            disable_on_mismatch: false,
+        }
+    }
+}
+
+/// What the socket-state calibration found, in `HealthReport::calibration`.
+/// sk_sndbuf values are bytes as the kernel holds them, SO_SNDBUF doubled
+#[derive(Debug, Clone, PartialEq)]
+pub enum CalibrationOutcome {
+    /// The probe read the calibration socket's sk_sndbuf right
+    Passed {
+        expected_sndbuf: u32,
+        observed_sndbuf: u32,
+    },
+    /// It read something else: the socket-state offsets are wrong on this kernel
+    Mismatch {
+        expected_sndbuf: u32,
+        observed_sndbuf: u32,
+        samples: usize,
+    },
+    /// No sample of the calibration socket arrived. The TCP state probe isn't
+    /// attached, its offsets are unknown, or every read was implausible
+    NoSamples { expected_sndbuf: u32, sends: u64 },
+    /// The calibration socket couldn't be set up, nothing was checked
+    Failed(String),
+}
+
+impl CalibrationOutcome {
+    /// The offsets are known to be wrong
+    pub fn is_mismatch(&self) -> bool {
+        matches!(self, Self::Mismatch { .. })
+    }
+}
+
+/// Collects the sndbuf of socket-state samples for one armed cookie. Lives in
+/// `AtomicSignals`, fed from the processing side
+#[derive(Default)]
+pub(crate) struct SndbufCalibration {
+    // 0 while not armed, cookies start at 1
+    cookie: AtomicU64,
+    observed: Mutex<Vec<u32>>,
+    // The first sample's timestamp_ns and when it was seen
+    first_seen: Mutex<Option<(u64, SystemTime)>>,
+}
+
+impl SndbufCalibration {
+    pub(crate) fn arm(&self, cookie: u64) {
+        self.observed.lock().unwrap().clear();
+        *self.first_seen.lock().unwrap() = None;
+        self.cookie.store(cookie, Ordering::Relaxed);
+    }
+
+    /// Everything observed since `arm`
+    pub(crate) fn disarm(&self) -> Vec<u32> {
+        self.cookie.store(0, Ordering::Relaxed);
+        std::mem::take(&mut *self.observed.lock().unwrap())
+    }
+
+    pub(crate) fn observe(&self, event: &CongestionEvent) {
+        if event.event_type != EVENT_SOCKET_STATE {
+            return;
+        }
+        let cookie = self.cookie.load(Ordering::Relaxed);
+        let socket = unsafe { event.data.socket };
+        if cookie != 0 && socket.socket_id == cookie {
+            self.observed.lock().unwrap().push(socket.sndbuf);
+            self.first_seen
+                .lock()
+                .unwrap()
+                .get_or_insert((event.timestamp_ns, SystemTime::now()));
+        }
+    }
+
+    /// Timestamp and arrival of the first sample since `arm`
+    pub(crate) fn first_seen(&self) -> Option<(u64, SystemTime)> {
+        *self.first_seen.lock().unwrap()
+    }
+
+    fn samples(&self) -> usize {
+        self.observed.lock().unwrap().len()
+    }
+}
+
+/// Judge the sndbuf samples of the calibration socket against the real value,
+/// by their median so a sample from mid-teardown can't decide it
+pub(crate) fn evaluate(
+    expected: u32,
+    observed: &[u32],
+    tolerance: f64,
+    sends: u64,
+) -> CalibrationOutcome {
+    if observed.is_empty() {
+        return CalibrationOutcome::NoSamples {
+            expected_sndbuf: expected,
+            sends,
+        };
+    }
+    let mut sorted = observed.to_vec();
+    sorted.sort_unstable();
+    let median = sorted[sorted.len() / 2];
+    let error = (median as f64 - expected as f64).abs() / expected.max(1) as f64;
+    if error <= tolerance {
+        CalibrationOutcome::Passed {
+            expected_sndbuf: expected,
+            observed_sndbuf: median,
+        }
+    } else {
+        CalibrationOutcome::Mismatch {
+            expected_sndbuf: expected,
+            observed_sndbuf: median,
+            samples: observed.len(),
+        }
+    }
+}
+
+/// Run the calibration against running readers. Blocks for up to `timeout`;
+/// both ends of the connection are closed before it returns
+pub(crate) fn run(
+    calibration: &SndbufCalibration,
+    config: &CalibrationConfig,
+) -> CalibrationOutcome {
+    let setup = (|| -> io::Result<(TcpStream, TcpStream, u32)> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let sender = TcpStream::connect(listener.local_addr()?)?;
+        let (receiver, _) = listener.accept()?;
+        set_sndbuf(&sender, config.sndbuf)?;
+        sender.set_nodelay(true)?;
+        let expected = sndbuf(&sender)?;
+        Ok((sender, receiver, expected))
+    })();
+    let (mut sender, mut receiver, expected) = match setup {
+        Ok(sockets) => sockets,
+        Err(e) => return CalibrationOutcome::Failed(format!("calibration socket: {}", e)),
+    };
+    let cookie = match socket_cookie(&sender) {
+        Ok(cookie) => cookie,
+        Err(e) => return CalibrationOutcome::Failed(format!("calibration socket cookie: {}", e)),
+    };
+
+    // Keep the receive side empty so sends never block
+    let drain = std::thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        while matches!(receiver.read(&mut buf), Ok(n) if n > 0) {}
+    });
+
+    calibration.arm(cookie);
+    let deadline = Instant::now() + config.timeout;
+    let mut sends = 0;
+    let payload = [0u8; 64];
+    while Instant::now() < deadline && calibration.samples() < CALIBRATION_SAMPLES {
+        for _ in 0..SENDS_PER_CHECK {
+            if sender.write_all(&payload).is_err() {
+                break;
+            }
+            sends += 1;
+        }
+        // Readers wake on their own; give the samples time to get through
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    let observed = calibration.disarm();
+
+    let _ = sender.shutdown(std::net::Shutdown::Both);
+    drop(sender);
+    let _ = drain.join();
+    evaluate(expected, &observed, config.tolerance, sends)
+}
+
+fn set_sndbuf(socket: &TcpStream, bytes: u32) -> io::Result<()> {
+    let value = bytes as libc::c_int;
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_SNDBUF,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// sk_sndbuf as the kernel holds it: twice SO_SNDBUF, capped by wmem_max
+fn sndbuf(socket: &TcpStream) -> io::Result<u32> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_SNDBUF,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, Replay};
+    use crate::CollectorConfig;
+
+    const COOKIE: u64 = 4_711;
+    // SO_SNDBUF 131072 as the kernel holds it
+    const EXPECTED: u32 = 262_144;
+
+    /// The samples the calibration collected from a replay of `sndbufs` on its
+    /// socket, between sends on others
+    fn replayed(sndbufs: &[u32]) -> Vec<u32> {
+        let replay = Replay::new(CollectorConfig::default(), 1);
+        // Seen before it's armed, so not collected
+        replay.feed(&[fixtures::socket_state(1, 0, COOKIE, 0, 1)]);
+        replay.signals.calibration.arm(COOKIE);
+        let mut events = Vec::new();
+        for (i, &sndbuf) in sndbufs.iter().enumerate() {
+            let timestamp_ns = 1_000 * (i as u64 + 1);
+            events.push(fixtures::socket_state(timestamp_ns, 0, COOKIE + 1, 0, 1));
+            events.push(fixtures::udp_send(timestamp_ns + 1, 0, COOKIE, 64));
+            events.push(fixtures::socket_state(
+                timestamp_ns + 2,
+                0,
+                COOKIE,
+                64,
+                sndbuf,
+            ));
+        }
+        replay.feed(&events);
+        assert_eq!(
+            replay.signals.calibration.first_seen().map(|(ts, _)| ts),
+            (!sndbufs.is_empty()).then_some(1_002)
+        );
+        let observed = replay.signals.calibration.disarm();
+        // Disarmed, later samples no longer count
+        replay.feed(&[fixtures::socket_state(9_000, 0, COOKIE, 64, 1)]);
+        assert!(replay.signals.calibration.disarm().is_empty());
+        observed
+    }
+
+    #[test]
+    fn the_right_sndbuf_passes() {
+        let observed = replayed(&[EXPECTED, EXPECTED - 4_096, EXPECTED]);
+        assert_eq!(observed.len(), 3);
+        assert_eq!(
+            evaluate(EXPECTED, &observed, 0.10, 300),
+            CalibrationOutcome::Passed {
+                expected_sndbuf: EXPECTED,
+                observed_sndbuf: EXPECTED,
+            }
+        );
+    }
+
+    #[test]
+    fn a_misread_sndbuf_is_a_mismatch() {
+        // What the neighbouring field would read as
+        let observed = replayed(&[87_380, 87_380, 87_380]);
+        let outcome = evaluate(EXPECTED, &observed, 0.10, 300);
+        assert_eq!(
+            outcome,
+            CalibrationOutcome::Mismatch {
+                expected_sndbuf: EXPECTED,
+                observed_sndbuf: 87_380,
+                samples: 3,
+            }
+        );
+        assert!(outcome.is_mismatch());
+    }
+
+    #[test]
+    fn the_median_outvotes_one_stray_sample() {
+        let observed = replayed(&[EXPECTED, 4_608, EXPECTED]);
+        assert!(matches!(
+            evaluate(EXPECTED, &observed, 0.10, 300),
+            CalibrationOutcome::Passed { .. }
+        ));
+        let observed = replayed(&[4_608, EXPECTED, 4_608]);
+        assert!(evaluate(EXPECTED, &observed, 0.10, 300).is_mismatch());
+    }
+
+    #[test]
+    fn no_samples_is_inconclusive() {
+        let observed = replayed(&[]);
+        assert_eq!(
+            evaluate(EXPECTED, &observed, 0.10, 1_000),
+            CalibrationOutcome::NoSamples {
+                expected_sndbuf: EXPECTED,
+                sends: 1_000,
+            }
+        );
+    }
+}
@@ -0,0 +1,755 @@
+//Raw events around an incident, for when interval totals are too coarse to say
+//what happened. With `CollectorConfig::with_triggered_capture` the processing
+//side keeps the last `before` of decoded events in memory, bounded by
+//`max_bytes`. The interval read runs a SeverityClassifier of its own; when it
+//enters Red, or on `CongestionCollector::capture_now`, what's buffered is
+//frozen into a capture that goes on collecting for `after`, and the whole
+//window is then written as a recording (recording.rs) named for the trigger
+//time. At most one capture is started per `cooldown`.
+//
+//Windows are in the events' own clock, kernel monotonic time, so a capture fed
+//from a recording cuts the same windows a live one would.
+//
+//A socket traced with `CongestionCollector::trace_socket` gets a capture of
+//its own: every event of that socket until the trace ends, from the buffer's
+//`before` on, written as `capture-<unix ms>-socket-<n>.rec`. Traces don't wait
+//out the cooldown or each other. The events only the trace admitted stay out
+//of the rolling buffer, a socket sent unsampled would crowd out the rest.
+
+use crate::json::{interarrival_json, json_string, unix_ms};
+use crate::{
+    CongestionEvent, DropInterarrival, DropInterarrivalTracker, RecordingWriter, EVENT_QDISC_DROP,
+};
+// The classifier that triggers captures, and the capture itself, are f64
+#[cfg(not(feature = "no-float"))]
+use crate::{
+    event_socket, traced_only, CongestionSignals, CongestionState, Reason, SeverityClassifier,
+    SeverityThresholds,
+};
+#[cfg(not(feature = "no-float"))]
+use std::collections::VecDeque;
+#[cfg(not(feature = "no-float"))]
+use std::mem::size_of;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Turns on triggered capture, see `CollectorConfig::with_triggered_capture`
+#[derive(Debug, Clone)]
+pub struct CaptureConfig {
+    /// Recordings go here, `capture-<unix ms>-<trigger>.rec`, and
+    /// `capture-<unix ms>-socket-<n>.rec` for the `n`th socket trace
+    pub dir: PathBuf,
+    /// Events kept from before the trigger
+    pub before: Duration,
+    /// How long a capture goes on collecting after it
+    pub after: Duration,
+    /// Bound on the rolling buffer, and separately on one capture's events
+    pub max_bytes: usize,
+    /// Triggers this soon after the last capture's are suppressed
+    pub cooldown: Duration,
+    /// What the capture's own classifier calls Red
+    #[cfg(not(feature = "no-float"))]
+    pub thresholds: SeverityThresholds,
+}
+
+impl CaptureConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            before: Duration::from_secs(10),
+            after: Duration::from_secs(5),
+            max_bytes: 32 * 1024 * 1024,
+            cooldown: Duration::from_secs(600),
+            #[cfg(not(feature = "no-float"))]
+            thresholds: SeverityThresholds::default(),
+        }
+    }
+}
+
+/// What started a capture
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaptureTrigger {
+    /// The classifier entered Red, for these reasons
+    #[cfg(not(feature = "no-float"))]
+    Red(Vec<Reason>),
+    /// `capture_now()`
+    Manual,
+    /// `CongestionCollector::trace_socket()` on the socket with this id
+    Socket(u64),
+}
+
+impl CaptureTrigger {
+    /// Short name, goes into the file name
+    pub fn label(&self) -> &'static str {
+        match self {
+            #[cfg(not(feature = "no-float"))]
+            Self::Red(_) => "red",
+            Self::Manual => "manual",
+            Self::Socket(_) => "socket",
+        }
+    }
+}
+
+/// A capture that was written
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureRecord {
+    pub path: PathBuf,
+    pub trigger: CaptureTrigger,
+    /// Wall-clock time of the trigger, the one in the file name
+    pub triggered_at: SystemTime,
+    /// Kernel monotonic time of the trigger, and of the window's first and
+    /// last events. 0 for the last two without events
+    pub trigger_ns: u64,
+    pub first_event_ns: u64,
+    pub last_event_ns: u64,
+    /// Events written, and how many of them came before the trigger
+    pub events: usize,
+    pub events_before_trigger: usize,
+    /// Events left out because the capture reached `max_bytes`
+    pub truncated: u64,
+    /// Gaps between the window's qdisc drops, in timestamp order: whether the
+    /// loss that triggered it came in bursts
+    pub drop_interarrival: DropInterarrival,
+}
+
+/// Something the capture did, carried in `CongestionSignals::captures` of the
+/// interval read it happened in
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaptureNotice {
+    /// The buffer was frozen; the capture collects until `until_ns`
+    Triggered {
+        trigger: CaptureTrigger,
+        trigger_ns: u64,
+        until_ns: u64,
+    },
+    /// Within `cooldown` of the last capture's trigger (`retry_in` until the
+    /// next one is allowed), or while one was still collecting (None)
+    Suppressed {
+        trigger: CaptureTrigger,
+        retry_in: Option<Duration>,
+    },
+    Written(Box<CaptureRecord>),
+    Failed {
+        path: PathBuf,
+        error: String,
+    },
+}
+
+impl CaptureNotice {
+    /// One JSON object, no trailing newline
+    pub fn to_json(&self) -> String {
+        match self {
+            Self::Triggered {
+                trigger,
+                trigger_ns,
+                until_ns,
+            } => format!(
+                "{{\"capture\":\"triggered\",\"trigger\":\"{}\",\"trigger_ns\":{},\"until_ns\":{}}}",
+                trigger.label(),
+                trigger_ns,
+                until_ns
+            ),
+            Self::Suppressed { trigger, retry_in } => format!(
+                "{{\"capture\":\"suppressed\",\"trigger\":\"{}\",\"retry_in_ms\":{}}}",
+                trigger.label(),
+                retry_in.map_or("null".to_string(), |d| d.as_millis().to_string())
+            ),
+            Self::Written(record) => format!(
+                "{{\"capture\":\"written\",\"trigger\":\"{}\",\"path\":{},\"triggered_at_ms\":{},\
+                 \"trigger_ns\":{},\"first_event_ns\":{},\"last_event_ns\":{},\"events\":{},\
+                 \"events_before_trigger\":{},\"truncated\":{},\"drop_interarrival\":{}}}",
+                record.trigger.label(),
+                json_string(&record.path.display().to_string()),
+                unix_ms(record.triggered_at),
+                record.trigger_ns,
+                record.first_event_ns,
+                record.last_event_ns,
+                record.events,
+                record.events_before_trigger,
+                record.truncated,
+                interarrival_json(&record.drop_interarrival)
+            ),
+            Self::Failed { path, error } => format!(
+                "{{\"capture\":\"failed\",\"path\":{},\"error\":{}}}",
+                json_string(&path.display().to_string()),
+                json_string(error)
+            ),
+        }
+    }
+}
+
+/// Where triggered capture stands, see `HealthReport::capture`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CaptureStatus {
+    pub written: u64,
+    pub suppressed: u64,
+    pub failed: u64,
+    /// A capture is collecting its after-trigger window
+    pub in_progress: bool,
+    /// Events in the rolling buffer
+    pub buffered_events: usize,
+    /// Sockets being traced
+    pub tracing: usize,
+    pub last_written: Option<CaptureRecord>,
+    pub last_error: Option<String>,
+}
+
+/// A capture whose window has passed, ready to write. Taken out of the
+/// [`TriggeredCapture`] so the file is written without holding it
+#[derive(Debug, Clone)]
+pub struct FinishedCapture {
+    pub path: PathBuf,
+    trigger: CaptureTrigger,
+    triggered_at: SystemTime,
+    trigger_ns: u64,
+    events: Vec<CongestionEvent>,
+    truncated: u64,
+}
+
+impl FinishedCapture {
+    /// Write the recording, `Written` or `Failed`
+    pub fn write(self) -> CaptureNotice {
+        let written = RecordingWriter::create(&self.path).and_then(|mut writer| {
+            for event in &self.events {
+                writer.write_event(event)?;
+            }
+            writer.flush()
+        });
+        match written {
+            Ok(()) => CaptureNotice::Written(Box::new(CaptureRecord {
+                first_event_ns: self.events.first().map_or(0, |e| e.timestamp_ns),
+                last_event_ns: self.events.last().map_or(0, |e| e.timestamp_ns),
+                events: self.events.len(),
+                events_before_trigger: self
+                    .events
+                    .iter()
+                    .filter(|e| e.timestamp_ns < self.trigger_ns)
+                    .count(),
+                truncated: self.truncated,
+                drop_interarrival: drop_interarrival(&self.events),
+                path: self.path,
+                trigger: self.trigger,
+                triggered_at: self.triggered_at,
+                trigger_ns: self.trigger_ns,
+            })),
+            Err(e) => CaptureNotice::Failed {
+                path: self.path,
+                error: e.to_string(),
+            },
+        }
+    }
+}
+
+// Without the reordering stage the window's drops come in per-CPU order
+fn drop_interarrival(events: &[CongestionEvent]) -> DropInterarrival {
+    let mut drops: Vec<&CongestionEvent> = events
+        .iter()
+        .filter(|e| e.event_type == EVENT_QDISC_DROP)
+        .collect();
+    drops.sort_by_key(|e| e.timestamp_ns);
+    let mut tracker = DropInterarrivalTracker::new();
+    for drop in drops {
+        tracker.record(drop);
+    }
+    tracker.take_interval()
+}
+
+#[cfg(not(feature = "no-float"))]
+struct Pending {
+    trigger: CaptureTrigger,
+    triggered_at: SystemTime,
+    trigger_ns: u64,
+    until_ns: u64,
+    events: Vec<CongestionEvent>,
+    truncated: u64,
+}
+
+#[cfg(not(feature = "no-float"))]
+impl Pending {
+    fn add(&mut self, event: &CongestionEvent, max_events: usize) {
+        if event.timestamp_ns > self.until_ns {
+            return;
+        }
+        if self.events.len() < max_events {
+            self.events.push(*event);
+        } else {
+            self.truncated += 1;
+        }
+    }
+}
+
+// A socket trace's capture, numbered in the order they were started
+#[cfg(not(feature = "no-float"))]
+struct Trace {
+    socket_id: u64,
+    number: u64,
+    pending: Pending,
+}
+
+/// The rolling buffer and the capture collecting from it. Feed it events with
+/// `record` and intervals with `observe`, or trigger it by hand
+#[cfg(not(feature = "no-float"))]
+pub struct TriggeredCapture {
+    config: CaptureConfig,
+    // Whole events, from max_bytes
+    max_events: usize,
+    ring: VecDeque<CongestionEvent>,
+    newest_ns: u64,
+    classifier: SeverityClassifier,
+    pending: Option<Pending>,
+    last_trigger_ns: Option<u64>,
+    traces: Vec<Trace>,
+    traces_started: u64,
+    status: CaptureStatus,
+    // Since the last take_notices
+    notices: Vec<CaptureNotice>,
+}
+
+#[cfg(not(feature = "no-float"))]
+impl TriggeredCapture {
+    pub fn new(config: CaptureConfig) -> Self {
+        Self {
+            max_events: (config.max_bytes / size_of::<CongestionEvent>()).max(1),
+            ring: VecDeque::new(),
+            newest_ns: 0,
+            classifier: SeverityClassifier::new(config.thresholds.clone()),
+            pending: None,
+            last_trigger_ns: None,
+            traces: Vec::new(),
+            traces_started: 0,
+            status: CaptureStatus::default(),
+            notices: Vec::new(),
+            config,
+        }
+    }
+
+    pub fn record(&mut self, event: &CongestionEvent) {
+        if !self.traces.is_empty() {
+            if let Some((socket_id, _)) = event_socket(event) {
+                for trace in self.traces.iter_mut().filter(|t| t.socket_id == socket_id) {
+                    trace.pending.add(event, self.max_events);
+                }
+            }
+        }
+        if traced_only(event) {
+            return;
+        }
+
+        let ts = event.timestamp_ns;
+        self.newest_ns = self.newest_ns.max(ts);
+        let oldest = self
+            .newest_ns
+            .saturating_sub(self.config.before.as_nanos() as u64);
+        while self
+            .ring
+            .front()
+            .is_some_and(|e| e.timestamp_ns < oldest || self.ring.len() >= self.max_events)
+        {
+            self.ring.pop_front();
+        }
+        self.ring.push_back(*event);
+
+        if let Some(pending) = &mut self.pending {
+            pending.add(event, self.max_events);
+        }
+    }
+
+    /// Trace socket `socket_id` from `now_ns` until `until_ns`, into a
+    /// capture of its own named for `at`. Tracing a socket already traced
+    /// moves the end of its trace instead
+    pub fn trace(
+        &mut self,
+        socket_id: u64,
+        now_ns: u64,
+        until_ns: u64,
+        at: SystemTime,
+    ) -> CaptureNotice {
+        let trigger = CaptureTrigger::Socket(socket_id);
+        if let Some(trace) = self.traces.iter_mut().find(|t| t.socket_id == socket_id) {
+            trace.pending.until_ns = until_ns;
+            let notice = CaptureNotice::Triggered {
+                trigger,
+                trigger_ns: trace.pending.trigger_ns,
+                until_ns,
+            };
+            self.notices.push(notice.clone());
+            return notice;
+        }
+
+        let start_ns = now_ns.saturating_sub(self.config.before.as_nanos() as u64);
+        let events = self
+            .ring
+            .iter()
+            .filter(|e| e.timestamp_ns >= start_ns)
+            .filter(|e| event_socket(e).is_some_and(|(id, _)| id == socket_id))
+            .copied()
+            .collect();
+        self.traces_started += 1;
+        self.traces.push(Trace {
+            socket_id,
+            number: self.traces_started,
+            pending: Pending {
+                trigger: trigger.clone(),
+                triggered_at: at,
+                trigger_ns: now_ns,
+                until_ns,
+                events,
+                truncated: 0,
+            },
+        });
+        let notice = CaptureNotice::Triggered {
+            trigger,
+            trigger_ns: now_ns,
+            until_ns,
+        };
+        self.notices.push(notice.clone());
+        notice
+    }
+
+    /// Start a capture at `now_ns` (kernel monotonic), named for `at`
+    pub fn trigger(
+        &mut self,
+        trigger: CaptureTrigger,
+        now_ns: u64,
+        at: SystemTime,
+    ) -> CaptureNotice {
+        let notice = self.try_trigger(trigger, now_ns, at);
+        self.notices.push(notice.clone());
+        notice
+    }
+
+    fn try_trigger(
+        &mut self,
+        trigger: CaptureTrigger,
+        now_ns: u64,
+        at: SystemTime,
+    ) -> CaptureNotice {
+        if self.pending.is_some() {
+            self.status.suppressed += 1;
+            return CaptureNotice::Suppressed {
+                trigger,
+                retry_in: None,
+            };
+        }
+        let cooldown_ns = self.config.cooldown.as_nanos() as u64;
+        if let Some(last) = self.last_trigger_ns {
+            let since = now_ns.saturating_sub(last);
+            if since < cooldown_ns {
+                self.status.suppressed += 1;
+                return CaptureNotice::Suppressed {
+                    trigger,
+                    retry_in: Some(Duration::from_nanos(cooldown_ns - since)),
+                };
+            }
+        }
+
+        let start_ns = now_ns.saturating_sub(self.config.before.as_nanos() as u64);
+        let until_ns = now_ns + self.config.after.as_nanos() as u64;
+        let events: Vec<CongestionEvent> = self
+            .ring
+            .iter()
+            .filter(|e| e.timestamp_ns >= start_ns)
+            .copied()
+            .collect();
+        self.last_trigger_ns = Some(now_ns);
+        self.pending = Some(Pending {
+            trigger: trigger.clone(),
+            triggered_at: at,
+            trigger_ns: now_ns,
+            until_ns,
+            events,
+            truncated: 0,
+        });
+        CaptureNotice::Triggered {
+            trigger,
+            trigger_ns: now_ns,
+            until_ns,
+        }
+    }
+
+    /// Fold in one interval: a capture is triggered when the classifier enters
+    /// Red. Returns the capture whose window `now_ns` has passed, if any
+    pub fn observe(
+        &mut self,
+        signals: &CongestionSignals,
+        now_ns: u64,
+        at: SystemTime,
+    ) -> Option<FinishedCapture> {
+        let was_red = self.classifier.state().level() == 2;
+        if let CongestionState::Red(reasons) = self.classifier.update(signals) {
+            if !was_red {
+                self.trigger(CaptureTrigger::Red(reasons), now_ns, at);
+            }
+        }
+        self.poll(now_ns)
+    }
+
+    /// The pending capture once `now_ns` is past its window
+    pub fn poll(&mut self, now_ns: u64) -> Option<FinishedCapture> {
+        if self.pending.as_ref().is_none_or(|p| now_ns <= p.until_ns) {
+            return None;
+        }
+        self.finish()
+    }
+
+    /// The pending capture as it stands, window passed or not, e.g. when
+    /// collection stops
+    pub fn finish(&mut self) -> Option<FinishedCapture> {
+        let pending = self.pending.take()?;
+        let name = format!(
+            "capture-{}-{}.rec",
+            unix_ms(pending.triggered_at),
+            pending.trigger.label()
+        );
+        Some(self.finished(pending, name))
+    }
+
+    /// The socket traces whose end `now_ns` is past
+    pub fn poll_traces(&mut self, now_ns: u64) -> Vec<FinishedCapture> {
+        let (done, open) = std::mem::take(&mut self.traces)
+            .into_iter()
+            .partition(|t| now_ns > t.pending.until_ns);
+        self.traces = open;
+        self.finish_each(done)
+    }
+
+    /// Socket `socket_id`'s trace as it stands, None if it isn't traced
+    pub fn end_trace(&mut self, socket_id: u64) -> Option<FinishedCapture> {
+        let at = self.traces.iter().position(|t| t.socket_id == socket_id)?;
+        let trace = self.traces.remove(at);
+        self.finish_each(vec![trace]).pop()
+    }
+
+    /// Every socket trace as it stands, e.g. when collection stops
+    pub fn finish_traces(&mut self) -> Vec<FinishedCapture> {
+        let traces = std::mem::take(&mut self.traces);
+        self.finish_each(traces)
+    }
+
+    fn finish_each(&self, traces: Vec<Trace>) -> Vec<FinishedCapture> {
+        traces
+            .into_iter()
+            .map(|trace| {
+                let name = format!(
+                    "capture-{}-socket-{}.rec",
+                    unix_ms(trace.pending.triggered_at),
+                    trace.number
+                );
+                self.finished(trace.pending, name)
+            })
+            .collect()
+    }
+
+    fn finished(&self, mut pending: Pending, name: String) -> FinishedCapture {
+        // Per-CPU readers interleave, the recording is in time order
+        pending.events.sort_by_key(|e| e.timestamp_ns);
+        FinishedCapture {
+            path: self.config.dir.join(name),
+            trigger: pending.trigger,
+            triggered_at: pending.triggered_at,
+            trigger_ns: pending.trigger_ns,
+            events: pending.events,
+            truncated: pending.truncated,
+        }
+    }
+
+    /// Count a written or failed capture into `status` and the notices
+    pub fn note(&mut self, notice: &CaptureNotice) {
+        self.notices.push(notice.clone());
+        match notice {
+            CaptureNotice::Written(record) => {
+                self.status.written += 1;
+                self.status.last_written = Some(CaptureRecord::clone(record));
+            }
+            CaptureNotice::Failed { error, .. } => {
+                self.status.failed += 1;
+                self.status.last_error = Some(error.clone());
+            }
+            _ => {}
+        }
+    }
+
+    /// Triggers, suppressions and notes since the last call, oldest first
+    pub fn take_notices(&mut self) -> Vec<CaptureNotice> {
+        std::mem::take(&mut self.notices)
+    }
+
+    pub fn status(&self) -> CaptureStatus {
+        CaptureStatus {
+            in_progress: self.pending.is_some(),
+            buffered_events: self.ring.len(),
+            tracing: self.traces.len(),
+            ..self.status.clone()
+        }
+    }
+
+    /// Drop the buffer, any capture in progress and the socket traces, e.g.
+    /// after the kernel clock jumped
+    pub fn reset(&mut self) {
+        self.ring.clear();
+        self.pending = None;
+        self.traces.clear();
+        self.newest_ns = 0;
+        self.last_trigger_ns = None;
+    }
+}
+
+#[cfg(all(test, feature = "collector-core"))]
+mod tests {
+    use super::*;
+    use crate::fixtures::{qdisc_drop, scratch_dir, traced_run, udp_send};
+    use crate::{traced_only, RecordingReader};
+
+    const SECOND_NS: u64 = 1_000_000_000;
+
+    /// A scripted run fed the way a recording would be: a send every 100 ms,
+    /// and one interval a second that's Red at 6 s and again at 11 s, with a
+    /// drop a second on the half second. A manual trigger at 12 s
+    fn replay(capture: &mut TriggeredCapture) -> Vec<CaptureNotice> {
+        let at = SystemTime::now();
+        let mut notices = Vec::new();
+        for step in 1..=140u64 {
+            let now_ns = step * SECOND_NS / 10;
+            capture.record(&udp_send(now_ns, (step % 2) as u32, 7, 1200));
+            if step % 10 == 5 {
+                capture.record(&qdisc_drop(now_ns, 0, 1, 0));
+            }
+            if step % 10 != 0 {
+                continue;
+            }
+            let second = step / 10;
+            let signals = CongestionSignals::builder()
+                .interval_ns(SECOND_NS)
+                .drops(if second == 6 || second == 11 { 500 } else { 0 })
+                .build();
+            if let Some(finished) = capture.observe(&signals, now_ns, at) {
+                let notice = finished.write();
+                capture.note(&notice);
+            }
+            if second == 12 {
+                capture.trigger(CaptureTrigger::Manual, now_ns, at);
+            }
+            notices.extend(capture.take_notices());
+        }
+        notices
+    }
+
+    fn capture(dir: &std::path::Path) -> TriggeredCapture {
+        let mut config = CaptureConfig::new(dir);
+        config.before = Duration::from_secs(2);
+        config.after = Duration::from_secs(1);
+        config.cooldown = Duration::from_secs(60);
+        TriggeredCapture::new(config)
+    }
+
+    #[test]
+    fn a_red_interval_writes_the_window_around_it() {
+        let dir = scratch_dir("capture-window");
+        let mut capture = capture(&dir);
+        let notices = replay(&mut capture);
+        let written: Vec<&CaptureRecord> = notices
+            .iter()
+            .filter_map(|notice| match notice {
+                CaptureNotice::Written(record) => Some(&**record),
+                _ => None,
+            })
+            .collect();
+        let [record] = written[..] else {
+            panic!("expected one capture: {:?}", notices);
+        };
+        assert!(matches!(record.trigger, CaptureTrigger::Red(_)));
+        assert_eq!(record.trigger_ns, 6 * SECOND_NS);
+        assert_eq!(record.events, 34);
+        assert_eq!(record.events_before_trigger, 22);
+        assert_eq!(record.drop_interarrival.gaps, 2);
+        assert_eq!(record.drop_interarrival.mean_gap_ns, SECOND_NS as f64);
+
+        let read: Vec<u64> = RecordingReader::open(&record.path)
+            .unwrap()
+            .map(|event| event.unwrap().timestamp_ns)
+            .collect();
+        assert_eq!(read.len(), 34);
+        assert_eq!(read.first(), Some(&(4 * SECOND_NS)));
+        assert_eq!(read.last(), Some(&(7 * SECOND_NS)));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn triggers_in_the_cooldown_are_suppressed() {
+        let dir = scratch_dir("capture-cooldown");
+        let mut capture = capture(&dir);
+        let notices = replay(&mut capture);
+        let suppressed = notices
+            .iter()
+            .filter(|notice| {
+                matches!(
+                    notice,
+                    CaptureNotice::Suppressed {
+                        retry_in: Some(_),
+                        ..
+                    }
+                )
+            })
+            .count();
+        // The second Red and the manual trigger
+        assert_eq!(suppressed, 2);
+        let status = capture.status();
+        assert_eq!(status.written, 1);
+        assert_eq!(status.suppressed, 2);
+        assert!(!status.in_progress);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// Socket 41 traced from 1 s to 2 s with 500 ms before: the capture holds
+    /// its sampled events from then and every one of the trace, and the events
+    /// only the trace admitted stay out of the rolling buffer
+    #[test]
+    fn a_socket_trace_holds_what_the_trace_admitted() {
+        const TRACED: u64 = 41;
+        let ms = |ms: u64| ms * 1_000_000;
+        let events = traced_run(TRACED, 42);
+        let dir = scratch_dir("capture-trace");
+        let mut config = CaptureConfig::new(&dir);
+        config.before = Duration::from_millis(500);
+        let mut capture = TriggeredCapture::new(config);
+        let at = SystemTime::now();
+        for event in &events {
+            if event.timestamp_ns >= ms(1_000) && capture.status().tracing == 0 {
+                capture.trace(TRACED, ms(1_000), ms(2_000), at);
+                assert_eq!(capture.status().tracing, 1);
+            }
+            capture.record(event);
+        }
+        let newest = events.last().unwrap().timestamp_ns;
+        let buffered = events
+            .iter()
+            .filter(|e| !traced_only(e) && e.timestamp_ns >= newest - ms(500))
+            .count();
+        assert_eq!(capture.status().buffered_events, buffered);
+
+        let finished = capture.poll_traces(ms(3_000));
+        assert_eq!(capture.status().tracing, 0);
+        let record = match finished.into_iter().next().map(|f| f.write()) {
+            Some(CaptureNotice::Written(record)) => record,
+            other => panic!("trace not written: {:?}", other),
+        };
+        assert_eq!(record.trigger, CaptureTrigger::Socket(TRACED));
+        let name = record.path.file_name().unwrap().to_str().unwrap();
+        assert!(name.ends_with("-socket-1.rec"), "{}", name);
+        let read: Vec<CongestionEvent> = RecordingReader::open(&record.path)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let _ = std::fs::remove_dir_all(&dir);
+        // Sends at 500-900 ms, a buffer sample at 500 ms, and 101 sends plus
+        // a buffer sample in the trace
+        assert_eq!(read.len(), 108);
+        assert_eq!(record.events_before_trigger, 6);
+        assert_eq!(
+            read.iter().filter(|e| traced_only(e)).count(),
+            events.iter().filter(|e| traced_only(e)).count()
+        );
+        assert_eq!(read.first().unwrap().timestamp_ns, ms(500));
+        assert_eq!(read.last().unwrap().timestamp_ns, ms(2_000));
+    }
+}
@@ -0,0 +1,472 @@
+//Per-cgroup signals, to find the noisy neighbour among every cgroup on the host
+//at once. With aggregation on, the send, drop and send-buffer probes also add
+//what they see to CGROUP_SIGNALS, keyed by bpf_get_current_cgroup_id() (the
+//inode of the task's cgroup2 directory). Only task context counts: a drop in a
+//softirq happened to whatever task was interrupted, so the drops attributed are
+//the ones in the sender's own syscall, a full qdisc at enqueue. The map is never
+//reset; each read diffs against the previous one like TxqStalls.
+//
+//Ids are resolved to paths by walking the cgroup2 mount, cached, and walked
+//again when an unknown id shows up. Cgroups that saw nothing for `stale_after`,
+//or whose directory is gone, are removed from the map to make room.
+
+use crate::clock::Clock;
+use crate::health;
+use crate::memory::{hash_table_bytes, StructureMemory};
+use crate::{
+    CgroupCounters, CongestionSignals, EstimatedTotals, ObservedCounts, SampleScale, Signal,
+};
+use aya::maps::{MapData, PerCpuHashMap};
+use aya::Ebpf;
+use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Turns on `CongestionCollector::read_per_cgroup()`, see
+/// `CollectorConfig::with_cgroup_aggregation`.
+///
+/// It costs a bpf_get_current_cgroup_id call and a per-CPU hash update on every
+/// sampled send, every drop outside softirq and every send buffer sample
+#[derive(Debug, Clone)]
+pub struct CgroupAggregationConfig {
+    /// Cgroups tracked at once, the kernel map's size. A full map leaves new
+    /// cgroups out until stale ones are removed
+    pub max_cgroups: u32,
+    /// A cgroup without anything counted for this long is removed at the next read
+    pub stale_after: Duration,
+    /// Where cgroup2 is mounted, to resolve ids to paths
+    pub cgroup_root: PathBuf,
+}
+
+impl Default for CgroupAggregationConfig {
+    fn default() -> Self {
+        Self {
+            max_cgroups: 1024,
+            stale_after: Duration::from_secs(300),
+            cgroup_root: PathBuf::from("/sys/fs/cgroup"),
+        }
+    }
+}
+
+/// How `read_per_cgroup_rolled_up()` groups cgroups. Paths are relative to the
+/// cgroup root, `/` being the root itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgroupRollup {
+    /// Every cgroup on its own
+    None,
+    /// By the first `n` path components, `Depth(1)` gives `/system.slice`,
+    /// `/kubepods.slice`, ...
+    Depth(usize),
+    /// By Kubernetes pod (a `pod<uid>` or `kubepods-...-pod<uid>.slice`
+    /// component); cgroups outside pods stay on their own
+    Pod,
+    /// By container: the component after the pod, or a `docker-`, `crio-`,
+    /// `cri-containerd-` or `libpod-` scope; anything else stays on its own
+    Container,
+}
+
+pub(crate) struct CgroupTracker {
+    // None if the map was unusable
+    counters: Option<PerCpuHashMap<MapData, u64, CgroupCounters>>,
+    config: CgroupAggregationConfig,
+    exclude_loopback: bool,
+    // cgroup id -> totals at the previous read
+    last_totals: HashMap<u64, CgroupCounters>,
+    last_read: Instant,
+    // Interval bounds and produced_at, CollectorConfig::clock
+    clock: Arc<dyn Clock>,
+    // cgroup id -> path under cgroup_root, from the last walk
+    paths: HashMap<u64, String>,
+    // Cgroups removed from the map, stale or gone
+    removed: u64,
+}
+
+impl CgroupTracker {
+    pub(crate) fn take(
+        ebpf: &mut Ebpf,
+        config: &CgroupAggregationConfig,
+        exclude_loopback: bool,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let counters = match ebpf.take_map("CGROUP_SIGNALS").map(PerCpuHashMap::try_from) {
+            Some(Ok(map)) => Some(map),
+            _ => {
+                log::warn!("CGROUP_SIGNALS map unusable, per-cgroup reads return nothing");
+                None
+            }
+        };
+        Self {
+            counters,
+            config: config.clone(),
+            exclude_loopback,
+            last_totals: HashMap::new(),
+            last_read: clock.now(),
+            clock,
+            paths: HashMap::new(),
+            removed: 0,
+        }
+    }
+
+    /// Signals per cgroup id since the previous call, idle cgroups left out
+    pub(crate) fn read_interval(&mut self) -> HashMap<u64, CongestionSignals> {
+        let (diffs, interval_ns) = self.read_diffs();
+        diffs
+            .into_iter()
+            .map(|(id, diff)| (id, self.signals(&diff, interval_ns)))
+            .collect()
+    }
+
+    /// `read_interval` with cgroups merged per `rollup`, keyed by path. Ids
+    /// without a path are keyed by the id in decimal
+    pub(crate) fn read_rolled_up(
+        &mut self,
+        rollup: CgroupRollup,
+    ) -> HashMap<String, CongestionSignals> {
+        let (diffs, interval_ns) = self.read_diffs();
+        let mut groups: HashMap<String, CgroupCounters> = HashMap::new();
+        for (id, diff) in diffs {
+            let key = match self.paths.get(&id) {
+                Some(path) => rolled_up(path, rollup),
+                None => id.to_string(),
+            };
+            add(groups.entry(key).or_default(), &diff);
+        }
+        groups
+            .into_iter()
+            .map(|(key, counters)| (key, self.signals(&counters, interval_ns)))
+            .collect()
+    }
+
+    /// Path of a cgroup id under the cgroup root, walking it again if the id
+    /// isn't known yet
+    pub(crate) fn path(&mut self, cgroup_id: u64) -> Option<String> {
+        if !self.paths.contains_key(&cgroup_id) {
+            self.walk();
+        }
+        self.paths.get(&cgroup_id).cloned()
+    }
+
+    /// Kernel totals per cgroup as of the last read, for
+    /// `CongestionCollector::cumulative`
+    pub(crate) fn totals(&self) -> impl Iterator<Item = (u64, &CgroupCounters)> + '_ {
+        self.last_totals
+            .iter()
+            .map(|(&id, counters)| (id, counters))
+    }
+
+    pub(crate) fn memory(&self) -> StructureMemory {
+        StructureMemory {
+            name: "per-cgroup totals",
+            entries: self.last_totals.len(),
+            cap: self.config.max_cgroups as usize,
+            estimated_bytes: hash_table_bytes::<u64, CgroupCounters>(self.last_totals.capacity())
+                + self
+                    .paths
+                    .values()
+                    .map(|path| (path.len() + size_of::<(u64, String)>()) as u64)
+                    .sum::<u64>(),
+            evictions: self.removed,
+        }
+    }
+
+    /// Counters per cgroup since the previous read and the ns it covers.
+    /// Removes stale and vanished cgroups from the map on the way
+    fn read_diffs(&mut self) -> (HashMap<u64, CgroupCounters>, u64) {
+        let now = self.clock.now();
+        let interval_ns = now.duration_since(self.last_read).as_nanos() as u64;
+        self.last_read = now;
+        let Some(map) = &self.counters else {
+            return (HashMap::new(), interval_ns);
+        };
+
+        let now_ns = health::monotonic_now_ns();
+        let stale_ns = u64::try_from(self.config.stale_after.as_nanos()).unwrap_or(u64::MAX);
+        let mut diffs = HashMap::new();
+        let mut stale = Vec::new();
+        for entry in map.iter() {
+            let Ok((id, per_cpu)) = entry else {
+                continue;
+            };
+            let mut total = CgroupCounters::default();
+            for counters in per_cpu.iter() {
+                add(&mut total, counters);
+                total.last_seen_ns = total.last_seen_ns.max(counters.last_seen_ns);
+            }
+            let last = self.last_totals.insert(id, total).unwrap_or_default();
+            let diff = CgroupCounters {
+                send_bytes: total.send_bytes.saturating_sub(last.send_bytes),
+                loopback_send_bytes: total
+                    .loopback_send_bytes
+                    .saturating_sub(last.loopback_send_bytes),
+                sends: total.sends.saturating_sub(last.sends),
+                drops: total.drops.saturating_sub(last.drops),
+                udp_wmem_total: total.udp_wmem_total.saturating_sub(last.udp_wmem_total),
+                udp_wmem_samples: total.udp_wmem_samples.saturating_sub(last.udp_wmem_samples),
+                tcp_wmem_total: total.tcp_wmem_total.saturating_sub(last.tcp_wmem_total),
+                tcp_wmem_samples: total.tcp_wmem_samples.saturating_sub(last.tcp_wmem_samples),
+                last_seen_ns: total.last_seen_ns,
+            };
+            if now_ns.is_some_and(|now_ns| now_ns.saturating_sub(total.last_seen_ns) > stale_ns) {
+                stale.push(id);
+            }
+            if diff.sends > 0
+                || diff.drops > 0
+                || diff.udp_wmem_samples > 0
+                || diff.tcp_wmem_samples > 0
+            {
+                diffs.insert(id, diff);
+            }
+        }
+
+        // Paths for the new ids; one walk per read at most
+        if diffs.keys().any(|id| !self.paths.contains_key(id)) && self.walk() {
+            stale.extend(
+                self.last_totals
+                    .keys()
+                    .filter(|id| !self.paths.contains_key(id)),
+            );
+        }
+        self.remove(&stale);
+        (diffs, interval_ns)
+    }
+
+    /// Refill the path cache from the cgroup root. False when the root can't be
+    /// read, so nothing can be called gone
+    fn walk(&mut self) -> bool {
+        let root = self.config.cgroup_root.clone();
+        let mut paths = HashMap::new();
+        if walk(&root, &root, &mut paths).is_err() {
+            log::debug!("can't read cgroup root {}", root.display());
+            return false;
+        }
+        self.paths = paths;
+        true
+    }
+
+    fn remove(&mut self, ids: &[u64]) {
+        let Some(map) = &mut self.counters else {
+            return;
+        };
+        for id in ids {
+            if self.last_totals.remove(id).is_some() {
+                let _ = map.remove(id);
+                self.paths.remove(id);
+                self.removed += 1;
+            }
+        }
+    }
+
+    fn signals(&self, counters: &CgroupCounters, interval_ns: u64) -> CongestionSignals {
+        let pressure = |total: u64, samples: u64| total as f64 / samples as f64 / 1000.0;
+        let wmem_samples = counters.udp_wmem_samples + counters.tcp_wmem_samples;
+        let mut signals = CongestionSignals {
+            interval_ns,
+            produced_at: Some(self.clock.now()),
+            send_bytes: counters.send_bytes,
+            loopback_send_bytes: (!self.exclude_loopback).then_some(counters.loopback_send_bytes),
+            external_send_bytes: counters.send_bytes - counters.loopback_send_bytes,
+            drops: counters.drops,
+            avg_wmem_pressure: if wmem_samples > 0 {
+                pressure(
+                    counters.udp_wmem_total + counters.tcp_wmem_total,
+                    wmem_samples,
+                )
+            } else {
+                0.0
+            },
+            udp_wmem_pressure: (counters.udp_wmem_samples > 0)
+                .then(|| pressure(counters.udp_wmem_total, counters.udp_wmem_samples)),
+            tcp_wmem_pressure: (counters.tcp_wmem_samples > 0)
+                .then(|| pressure(counters.tcp_wmem_total, counters.tcp_wmem_samples)),
+            event_count: counters.sends + counters.drops + wmem_samples,
+            // Host-wide by nature, nothing to attribute
+            missing_signals: vec![Signal::QueueDepth, Signal::Softirq],
+            ..Default::default()
+        };
+        // Per-cgroup coverage isn't counted, the estimates are at the configured ratio
+        signals.observed = ObservedCounts::from(&signals);
+        signals.estimated = EstimatedTotals::from_samples(
+            signals.send_bytes,
+            signals.external_send_bytes,
+            SampleScale::nominal(counters.sends),
+        );
+        signals
+    }
+}
+
+fn add(total: &mut CgroupCounters, counters: &CgroupCounters) {
+    total.send_bytes += counters.send_bytes;
+    total.loopback_send_bytes += counters.loopback_send_bytes;
+    total.sends += counters.sends;
+    total.drops += counters.drops;
+    total.udp_wmem_total += counters.udp_wmem_total;
+    total.udp_wmem_samples += counters.udp_wmem_samples;
+    total.tcp_wmem_total += counters.tcp_wmem_total;
+    total.tcp_wmem_samples += counters.tcp_wmem_samples;
+}
+
+/// Every directory under `dir` by inode, which is its cgroup id on cgroup2
+fn walk(root: &Path, dir: &Path, paths: &mut HashMap<u64, String>) -> std::io::Result<()> {
+    let relative = dir.strip_prefix(root).unwrap_or(dir);
+    paths.insert(
+        std::fs::metadata(dir)?.ino(),
+        format!("/{}", relative.display()),
+    );
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        // Cgroups come and go while we walk, skip what vanished
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            let _ = walk(root, &entry.path(), paths);
+        }
+    }
+    Ok(())
+}
+
+/// The group a cgroup path falls in for `rollup`
+fn rolled_up(path: &str, rollup: CgroupRollup) -> String {
+    let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+    let keep = match rollup {
+        CgroupRollup::None => components.len(),
+        CgroupRollup::Depth(depth) => depth.min(components.len()),
+        CgroupRollup::Pod => components
+            .iter()
+            .position(|c| is_pod(c))
+            .map_or(components.len(), |pod| pod + 1),
+        CgroupRollup::Container => match components.iter().position(|c| is_pod(c)) {
+            Some(pod) => (pod + 2).min(components.len()),
+            None => components
+                .iter()
+                .position(|c| is_container_scope(c))
+                .map_or(components.len(), |scope| scope + 1),
+        },
+    };
+    format!("/{}", components[..keep].join("/"))
+}
+
+/// `pod<uid>` (cgroupfs driver) or `kubepods-<qos>-pod<uid>.slice` (systemd)
+fn is_pod(component: &str) -> bool {
+    let name = component.strip_suffix(".slice").unwrap_or(component);
+    name.starts_with("pod") || name.contains("-pod")
+}
+
+fn is_container_scope(component: &str) -> bool {
+    component.ends_with(".scope")
+        && ["docker-", "crio-", "cri-containerd-", "libpod-"]
+            .iter()
+            .any(|runtime| component.starts_with(runtime))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::fixtures;
+
+    const CONTAINER: &str = "/kubepods.slice/kubepods-burstable.slice/\
+        kubepods-burstable-pod1a2b.slice/cri-containerd-9f8e.scope";
+
+    #[test]
+    fn paths_roll_up_to_their_pod_and_container() {
+        let path = format!("{CONTAINER}/init");
+        assert_eq!(rolled_up(&path, CgroupRollup::None), path);
+        assert_eq!(rolled_up(&path, CgroupRollup::Depth(1)), "/kubepods.slice");
+        assert_eq!(
+            rolled_up(&path, CgroupRollup::Pod),
+            "/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod1a2b.slice"
+        );
+        assert_eq!(rolled_up(&path, CgroupRollup::Container), CONTAINER);
+
+        // Outside a pod a runtime's scope is the container
+        let docker = "/system.slice/docker-3c4d.scope/app";
+        assert_eq!(
+            rolled_up(docker, CgroupRollup::Container),
+            "/system.slice/docker-3c4d.scope"
+        );
+        assert_eq!(rolled_up(docker, CgroupRollup::Pod), docker);
+        assert_eq!(rolled_up("/", CgroupRollup::Depth(2)), "/");
+    }
+
+    #[test]
+    fn a_walk_keys_every_directory_by_inode() {
+        let root = fixtures::scratch_dir("cgroups");
+        std::fs::create_dir_all(root.join("system.slice/sshd.service")).unwrap();
+        std::fs::write(root.join("system.slice/cgroup.procs"), "1\n").unwrap();
+
+        let mut paths = HashMap::new();
+        walk(&root, &root, &mut paths).unwrap();
+        let inode = |dir: &str| std::fs::metadata(root.join(dir)).unwrap().ino();
+        assert_eq!(paths.len(), 3);
+        assert_eq!(paths[&inode("")], "/");
+        assert_eq!(
+            paths[&inode("system.slice/sshd.service")],
+            "/system.slice/sshd.service"
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    // Without the kernel map, as when CGROUP_SIGNALS is unusable
+    fn tracker(exclude_loopback: bool, clock: &ManualClock) -> CgroupTracker {
+        CgroupTracker {
+            counters: None,
+            config: CgroupAggregationConfig::default(),
+            exclude_loopback,
+            last_totals: HashMap::new(),
+            last_read: clock.now(),
+            clock: Arc::new(clock.clone()),
+            paths: HashMap::new(),
+            removed: 0,
+        }
+    }
+
+    #[test]
+    fn reads_span_the_clocks_time_since_the_last() {
+        let clock = ManualClock::new();
+        let mut tracker = tracker(false, &clock);
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(tracker.read_diffs().1, 250_000_000);
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(tracker.read_diffs().1, 100_000_000);
+        assert_eq!(
+            tracker.signals(&CgroupCounters::default(), 1).produced_at,
+            Some(clock.now())
+        );
+    }
+
+    #[test]
+    fn counters_read_as_the_cgroups_signals() {
+        let clock = ManualClock::new();
+        let tracker = |exclude_loopback| tracker(exclude_loopback, &clock);
+        let mut counters = CgroupCounters {
+            send_bytes: 12_000,
+            loopback_send_bytes: 2_000,
+            sends: 10,
+            drops: 3,
+            // Pressure in thousandths, summed over samples
+            udp_wmem_total: 2 * 500,
+            udp_wmem_samples: 2,
+            ..CgroupCounters::default()
+        };
+        add(
+            &mut counters,
+            &CgroupCounters {
+                tcp_wmem_total: 250,
+                tcp_wmem_samples: 1,
+                ..CgroupCounters::default()
+            },
+        );
+
+        let signals = tracker(false).signals(&counters, 1_000_000_000);
+        assert_eq!(signals.external_send_bytes, 10_000);
+        assert_eq!(signals.loopback_send_bytes, Some(2_000));
+        assert_eq!(signals.udp_wmem_pressure, Some(0.5));
+        assert_eq!(signals.tcp_wmem_pressure, Some(0.25));
+        assert_eq!(signals.avg_wmem_pressure, 1_250.0 / 3.0 / 1000.0);
+        assert_eq!(signals.event_count, 16);
+        assert_eq!(
+            tracker(true).signals(&counters, 1).loopback_send_bytes,
+            None
+        );
+    }
+}
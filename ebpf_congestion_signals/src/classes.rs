@@ -0,0 +1,283 @@
+//Traffic classes. Sends and send buffer samples carry their socket's DSCP
+//(inet_sock.tos >> 2) and sk_priority, TRAFFIC_CLASS_UNKNOWN when the offsets
+//aren't in kernel BTF. A TrafficClassConfig names the classes those map to,
+//e.g. "interactive" for EF (46) and "bulk" for CS1 (8), and read_per_class()
+//breaks the send signals down by them.
+//
+//Only the socket's own setting is seen: a DSCP set per packet with an IP_TOS
+//control message isn't, and those sends count under the socket's class.
+
+use crate::clock::Clock;
+use crate::memory::{entry_bytes, StructureMemory};
+use crate::netns::SendCounters;
+use crate::{
+    CongestionEvent, CongestionSignals, EVENT_SOCKET_STATE, EVENT_TCP_SEND, EVENT_UDP_SEND,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// One named class: sockets with any of these DSCP values, or else any of
+/// these priorities
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrafficClass {
+    pub name: String,
+    pub dscp: Vec<u8>,
+    /// sk_priority, as set with SO_PRIORITY (IP_TOS sets it too, from the
+    /// TOS bits)
+    pub priority: Vec<u32>,
+}
+
+impl TrafficClass {
+    pub fn dscp(name: &str, dscp: &[u8]) -> Self {
+        Self {
+            name: name.to_string(),
+            dscp: dscp.to_vec(),
+            priority: Vec::new(),
+        }
+    }
+
+    pub fn priority(name: &str, priority: &[u32]) -> Self {
+        Self {
+            name: name.to_string(),
+            dscp: Vec::new(),
+            priority: priority.to_vec(),
+        }
+    }
+}
+
+/// Per-class attribution, see `CollectorConfig::with_traffic_classes`
+#[derive(Debug, Clone)]
+pub struct TrafficClassConfig {
+    /// Checked in order: the first class listing the socket's DSCP, then the
+    /// first listing its priority
+    pub classes: Vec<TrafficClass>,
+    /// What sockets no class matches count as
+    pub default_class: String,
+}
+
+impl Default for TrafficClassConfig {
+    fn default() -> Self {
+        Self {
+            classes: Vec::new(),
+            default_class: "default".to_string(),
+        }
+    }
+}
+
+impl TrafficClassConfig {
+    pub fn new(classes: Vec<TrafficClass>) -> Self {
+        Self {
+            classes,
+            ..Default::default()
+        }
+    }
+
+    /// Name of the class a socket with this DSCP and priority is in
+    pub fn class_of(&self, dscp: u32, priority: u32) -> &str {
+        match self.index_of(dscp, priority) {
+            Some(index) => &self.classes[index].name,
+            None => &self.default_class,
+        }
+    }
+
+    fn index_of(&self, dscp: u32, priority: u32) -> Option<usize> {
+        self.classes
+            .iter()
+            .position(|class| class.dscp.iter().any(|&d| d as u32 == dscp))
+            .or_else(|| {
+                self.classes
+                    .iter()
+                    .position(|class| class.priority.contains(&priority))
+            })
+    }
+}
+
+/// DSCP and priority of a send or send buffer sample
+fn event_class(event: &CongestionEvent) -> Option<(u32, u32)> {
+    match event.event_type {
+        EVENT_UDP_SEND | EVENT_TCP_SEND => {
+            let send = unsafe { event.data.sendmsg };
+            Some((send.dscp, send.priority))
+        }
+        EVENT_SOCKET_STATE => {
+            let socket = unsafe { event.data.socket };
+            Some((socket.dscp, socket.priority))
+        }
+        _ => None,
+    }
+}
+
+/// Sends and send buffer samples per class since the last read, fed from the
+/// processing side. One slot per class name, so classes listed twice under the
+/// same name (or as the default) count together
+pub(crate) struct ClassTable {
+    config: TrafficClassConfig,
+    names: Vec<String>,
+    // Slot of each configured class, then of the default
+    slots: Vec<usize>,
+    counters: Vec<SendCounters>,
+    last_take: Instant,
+    exclude_loopback: bool,
+    // Interval bounds and produced_at, CollectorConfig::clock
+    clock: Arc<dyn Clock>,
+}
+
+impl ClassTable {
+    pub(crate) fn new(
+        config: TrafficClassConfig,
+        exclude_loopback: bool,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let mut names: Vec<String> = Vec::new();
+        let slots = config
+            .classes
+            .iter()
+            .map(|class| &class.name)
+            .chain([&config.default_class])
+            .map(|name| match names.iter().position(|n| n == name) {
+                Some(slot) => slot,
+                None => {
+                    names.push(name.clone());
+                    names.len() - 1
+                }
+            })
+            .collect();
+        Self {
+            counters: vec![SendCounters::default(); names.len()],
+            names,
+            slots,
+            config,
+            last_take: clock.now(),
+            exclude_loopback,
+            clock,
+        }
+    }
+
+    pub(crate) fn record(&mut self, event: &CongestionEvent) {
+        let Some((dscp, priority)) = event_class(event) else {
+            return;
+        };
+        let index = self
+            .config
+            .index_of(dscp, priority)
+            .unwrap_or(self.config.classes.len());
+        self.counters[self.slots[index]].record(event);
+    }
+
+    /// Signals per class name since the previous call, classes with nothing
+    /// counted left out
+    pub(crate) fn take(&mut self) -> HashMap<String, CongestionSignals> {
+        let now = self.clock.now();
+        let interval_ns = now.duration_since(self.last_take).as_nanos() as u64;
+        self.last_take = now;
+        let exclude_loopback = self.exclude_loopback;
+        self.names
+            .iter()
+            .zip(&mut self.counters)
+            .map(|(name, counters)| {
+                let signals = std::mem::take(counters).signals(now, interval_ns, exclude_loopback);
+                (name.clone(), signals)
+            })
+            .filter(|(_, signals)| signals.event_count > 0)
+            .collect()
+    }
+
+    pub(crate) fn memory(&self) -> StructureMemory {
+        StructureMemory {
+            name: "per-class signals",
+            entries: self.counters.len(),
+            cap: self.counters.len(),
+            estimated_bytes: entry_bytes::<String, SendCounters>(self.counters.len()),
+            evictions: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, Replay};
+    use std::time::Duration;
+
+    const EF: u32 = 46;
+    const CS1: u32 = 8;
+
+    fn config() -> TrafficClassConfig {
+        TrafficClassConfig::new(vec![
+            TrafficClass::dscp("interactive", &[EF as u8]),
+            TrafficClass::priority("bulk", &[1]),
+            // Counts with the first bulk
+            TrafficClass::dscp("bulk", &[CS1 as u8]),
+        ])
+    }
+
+    fn send(socket: u64, bytes: u64, dscp: u32, priority: u32) -> CongestionEvent {
+        let mut event = fixtures::udp_send(1, 0, socket, bytes);
+        event.data.sendmsg.dscp = dscp;
+        event.data.sendmsg.priority = priority;
+        event
+    }
+
+    #[test]
+    fn dscp_matches_before_priority() {
+        let config = config();
+        // DSCP EF at priority 1 is interactive
+        assert_eq!(config.class_of(EF, 1), "interactive");
+        assert_eq!(config.class_of(0, 1), "bulk");
+        assert_eq!(config.class_of(CS1, 0), "bulk");
+        assert_eq!(config.class_of(0, 0), "default");
+        assert_eq!(
+            config.class_of(crate::TRAFFIC_CLASS_UNKNOWN, crate::TRAFFIC_CLASS_UNKNOWN),
+            "default"
+        );
+    }
+
+    #[test]
+    fn replayed_sends_split_by_class() {
+        let replay = Replay::new(
+            crate::CollectorConfig::default().with_traffic_classes(config()),
+            1,
+        );
+        let mut queued = fixtures::socket_state(2, 0, 2, 900, 1000);
+        queued.data.socket.priority = 1;
+        replay.feed(&[
+            send(1, 1200, EF, 0),
+            send(2, 800, 0, 1),
+            send(3, 400, CS1, 0),
+            queued,
+        ]);
+        replay.read(Duration::from_secs(1));
+
+        let per_class = replay
+            .signals
+            .classes
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .take();
+        // Nothing was sent outside the named classes
+        assert_eq!(per_class.len(), 2);
+        let interactive = &per_class["interactive"];
+        assert_eq!(interactive.send_bytes, 1200);
+        // Since the table was made, on the replay's clock
+        assert_eq!(interactive.interval_ns, 1_000_000_000);
+        assert_eq!(interactive.produced_at, Some(replay.clock.now()));
+        assert_eq!(interactive.udp_wmem_pressure, None);
+        let bulk = &per_class["bulk"];
+        assert_eq!(bulk.send_bytes, 1200);
+        assert_eq!(bulk.udp_wmem_pressure, Some(0.9));
+        assert_eq!(bulk.event_count, 3);
+        // And the table starts over
+        assert!(replay
+            .signals
+            .classes
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .take()
+            .is_empty());
+    }
+}
@@ -0,0 +1,185 @@
+//Where the time-based components read the time. The governor's cut gap, the
+//reordering watermark's idle release, the health checks' windows, the
+//smoother's age and the collector's interval bounds (per CPU, cgroup,
+//namespace and class) and session times all take an `Arc<dyn Clock>`,
+//`SystemClock` unless one is given, so tests can drive them with a
+//`ManualClock` and step time instead of sleeping through it. `Clock::interval`
+//ticks on the clock too. Loops that wait on a channel and the time at once
+//(the pipeline's reordering, blocking or async) block for `poll_after` of
+//real time and then read the clock, so on a `ManualClock` they release what
+//`advance` moved past the watermark rather than what real time did. Other
+//async sleeps and intervals stay the runtime's (`Rt`), whose tokio timer
+//pauses and advances on its own.
+//
+//Kernel timestamps aren't the clock's: events carry CLOCK_MONOTONIC from the
+//kernel, and a `ManualClock` only moves what userspace measures.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+// How often a loop waiting on something else too looks at a ManualClock
+const MANUAL_POLL: Duration = Duration::from_millis(1);
+
+/// A source of `Instant`s, see the module docs
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> Instant;
+    /// Block for `duration`. A `ManualClock` moves forward instead
+    fn sleep(&self, duration: Duration);
+    /// Wall-clock time, for interval boundaries that line up across hosts
+    /// (`CollectorConfig::align_to_wall_clock`) and session reports.
+    /// `SystemTime::now()` unless overridden
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+    /// Real time to block for, with something else able to end the wait (an
+    /// event on a channel), before looking at the clock again to wait out
+    /// `duration` on it. All of it unless overridden
+    fn poll_after(&self, duration: Duration) -> Duration {
+        duration
+    }
+}
+
+/// `Instant::now()` and `std::thread::sleep`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Stands still until advanced, its wall time along with it. Clones share
+/// the time
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    start: Instant,
+    start_wall: SystemTime,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            start_wall: SystemTime::now(),
+            elapsed: Arc::new(Mutex::new(Duration::ZERO)),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+
+    /// Since it was created
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.start_wall + self.elapsed()
+    }
+
+    /// Only moves when advanced, which waiting for real wouldn't see: look
+    /// every millisecond
+    fn poll_after(&self, duration: Duration) -> Duration {
+        duration.min(MANUAL_POLL)
+    }
+}
+
+impl dyn Clock {
+    /// An `Interval` of `period` on this clock
+    pub fn interval(self: Arc<Self>, period: Duration) -> Interval {
+        Interval::new(self, period)
+    }
+}
+
+/// The clock components use unless given one
+pub fn system_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// Every `period` on `clock`, first at once. A tick fallen behind by more
+/// than a period skips to the next multiple, as `MissedTicks::Skip`
+#[derive(Debug, Clone)]
+pub struct Interval {
+    clock: Arc<dyn Clock>,
+    period: Duration,
+    next: Instant,
+}
+
+impl Interval {
+    pub fn new(clock: Arc<dyn Clock>, period: Duration) -> Self {
+        Self {
+            next: clock.now(),
+            clock,
+            period: period.max(Duration::from_nanos(1)),
+        }
+    }
+
+    /// Sleep on the clock until the next tick; returns when it was due
+    pub fn tick(&mut self) -> Instant {
+        let due = self.next;
+        let now = self.clock.now();
+        if now < due {
+            self.clock.sleep(due - now);
+        }
+        let behind = self.clock.now().saturating_duration_since(due);
+        let periods = (behind.as_nanos() / self.period.as_nanos()) as u32 + 1;
+        self.next = due + self.period * periods;
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: Duration = Duration::from_millis(200);
+
+    #[test]
+    fn an_interval_on_a_manual_clock_moves_it_by_its_ticks() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let mut interval = (Arc::new(clock.clone()) as Arc<dyn Clock>).interval(PERIOD);
+        let mut ticks = vec![interval.tick(), interval.tick()];
+        // Fallen behind by a period and a half: the next tick is at once, then
+        // on the period
+        clock.advance(PERIOD * 5 / 2);
+        ticks.push(interval.tick());
+        ticks.push(interval.tick());
+        let ticks_ms: Vec<u128> = ticks.iter().map(|t| (*t - start).as_millis()).collect();
+        assert_eq!(ticks_ms, [0, 200, 400, 800]);
+        assert_eq!(clock.elapsed(), Duration::from_millis(800));
+    }
+
+    #[test]
+    fn a_manual_clocks_wall_time_moves_with_it() {
+        let clock = ManualClock::new();
+        let wall = clock.system_time();
+        clock.sleep(PERIOD);
+        assert_eq!(clock.system_time().duration_since(wall).unwrap(), PERIOD);
+        assert_eq!(clock.now() - PERIOD, clock.start);
+    }
+}
@@ -0,0 +1,815 @@
+//Other BPF programs where ours attach. A bpftrace session or a Cilium agent on
+//the same tracepoints adds its cost to every event there, which the overhead
+//budget then puts down to us; a tc setup that replaces the clsact qdisc we
+//created takes our egress classifier with it. A scan asks the kernel what is
+//attached at each of our attach points: programs that aren't ours are
+//co-attached, and a point without ours is missing. `verify_attachments()`
+//scans every time it runs. Co-attached programs go to
+//`HealthReport::co_attached` with a health warning, a change in their number
+//to a `Component::CoAttachment` warning on `failures()`, and a missing point
+//has the object reloaded, as a lost link does.
+//
+//The kernel is asked per kind of point. A tracepoint's programs come from
+//PERF_EVENT_IOC_QUERY_BPF on a perf event of the scan's own, however they were
+//attached, plus raw tracepoint links of its name. A kprobe's come from the
+//perf links on its function, which links say from 6.6, and from perf events
+//attached without a link, found in /proc. Before 6.6 kprobes aren't scanned,
+//and kprobe_multi links never are. tc egress is TCX's list and the
+//classifiers under clsact. A program's owners are the uid that loaded it, from
+//bpf_prog_info, and the pids holding a descriptor on it or on its link, from
+///proc's fdinfo; a pinned program nobody holds open has no pids.
+//
+//The scan sees the kernel only through `AttachEnumerator`, so what it reports
+//can be checked against a scripted one.
+use crate::ProgramAttach;
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+#[cfg(feature = "collector-core")]
+use {
+    crate::egress,
+    aya::programs::{loaded_programs, SchedClassifier, TcAttachType},
+    std::collections::HashMap,
+    std::mem::size_of,
+    std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    std::path::Path,
+};
+
+/// One of our attach points
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AttachPoint {
+    /// A kprobe's or tracepoint's, see `PROGRAM_GROUPS`
+    Program(ProgramAttach),
+    /// `CollectorConfig::egress_interfaces`' classifier on this interface
+    TcEgress(String),
+}
+
+impl fmt::Display for AttachPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttachPoint::Program(ProgramAttach::Kprobe(function)) => {
+                write!(f, "kprobe:{}", function)
+            }
+            AttachPoint::Program(ProgramAttach::Tracepoint(category, name)) => {
+                write!(f, "tracepoint:{}:{}", category, name)
+            }
+            AttachPoint::TcEgress(interface) => write!(f, "tc egress on {}", interface),
+        }
+    }
+}
+
+/// A loaded program, as the kernel describes it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BpfProgram {
+    pub id: u32,
+    /// Cut to 15 bytes by the kernel, empty if it was loaded without one
+    pub name: String,
+    /// e.g. "TracePoint", "KProbe"
+    pub program_type: String,
+    /// Who loaded it, None on kernels that don't say
+    pub owner_uid: Option<u32>,
+}
+
+/// What a scan asks of the kernel, see the module docs
+pub trait AttachEnumerator {
+    /// Ids of every program attached at `point`, ours among them. An error
+    /// where the kernel can't say
+    fn attached(&mut self, point: &AttachPoint) -> io::Result<Vec<u32>>;
+
+    /// Program `id`, None once it's unloaded
+    fn program(&mut self, id: u32) -> Option<BpfProgram>;
+
+    /// Pids holding a descriptor on program `id` or a link of it
+    fn holders(&mut self, id: u32) -> Vec<u32>;
+}
+
+/// A program that isn't ours where one of ours is attached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoAttachedProgram {
+    pub point: AttachPoint,
+    pub program: BpfProgram,
+    /// Sorted, empty when nothing holds it open
+    pub owner_pids: Vec<u32>,
+}
+
+impl fmt::Display for CoAttachedProgram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let program = &self.program;
+        write!(
+            f,
+            "{:?} (id {}, {}",
+            program.name, program.id, program.program_type
+        )?;
+        if let Some(uid) = program.owner_uid {
+            write!(f, ", uid {}", uid)?;
+        }
+        if !self.owner_pids.is_empty() {
+            let pids: Vec<String> = self.owner_pids.iter().map(u32::to_string).collect();
+            write!(f, ", pid {}", pids.join(","))?;
+        }
+        write!(f, ") at {}", self.point)
+    }
+}
+
+/// One scan's findings
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CoAttachmentScan {
+    /// By point in the order scanned, then by program id. A program at two of
+    /// our points is in twice
+    pub co_attached: Vec<CoAttachedProgram>,
+    /// Points where none of ours is attached any more
+    pub missing: Vec<AttachPoint>,
+    /// Points the kernel couldn't be asked about, and why
+    pub unscanned: Vec<(AttachPoint, String)>,
+    /// How many co-attached programs the scan before found, when this one
+    /// found a different number. The first scan counts from 0
+    pub changed_from: Option<usize>,
+}
+
+impl CoAttachmentScan {
+    /// The warning for a change in the number of co-attached programs, None
+    /// without one
+    #[cfg(feature = "collector-core")]
+    pub(crate) fn change_warning(&self) -> Option<String> {
+        let before = self.changed_from?;
+        let programs: Vec<String> = self.co_attached.iter().map(|p| p.to_string()).collect();
+        Some(format!(
+            "{} programs of others attached where ours are, {} before{}{}",
+            self.co_attached.len(),
+            before,
+            if programs.is_empty() { "" } else { ": " },
+            programs.join("; ")
+        ))
+    }
+}
+
+/// Runs scans and keeps the latest, to tell when the number of co-attached
+/// programs changes
+#[derive(Debug, Default)]
+pub struct CoAttachmentScanner {
+    last: Option<CoAttachmentScan>,
+}
+
+impl CoAttachmentScanner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Scan `ours`, each of our programs with the point it's attached at.
+    /// Any program at a point that isn't among them is co-attached
+    pub fn scan(
+        &mut self,
+        enumerator: &mut dyn AttachEnumerator,
+        ours: &[(AttachPoint, u32)],
+    ) -> &CoAttachmentScan {
+        let own_ids: HashSet<u32> = ours.iter().map(|(_, id)| *id).collect();
+        let mut points: Vec<(&AttachPoint, HashSet<u32>)> = Vec::new();
+        for (point, id) in ours {
+            match points.iter_mut().find(|(seen, _)| *seen == point) {
+                Some((_, ids)) => {
+                    ids.insert(*id);
+                }
+                None => points.push((point, HashSet::from([*id]))),
+            }
+        }
+
+        let mut scan = CoAttachmentScan::default();
+        for (point, ids) in points {
+            let attached = match enumerator.attached(point) {
+                Ok(attached) => attached,
+                Err(e) => {
+                    scan.unscanned.push((point.clone(), e.to_string()));
+                    continue;
+                }
+            };
+            if !attached.iter().any(|id| ids.contains(id)) {
+                scan.missing.push(point.clone());
+            }
+            let mut others: Vec<u32> = attached
+                .into_iter()
+                .filter(|id| !own_ids.contains(id))
+                .collect();
+            others.sort_unstable();
+            others.dedup();
+            for id in others {
+                // Unloaded since it was listed
+                let Some(program) = enumerator.program(id) else {
+                    continue;
+                };
+                let mut owner_pids = enumerator.holders(id);
+                owner_pids.sort_unstable();
+                owner_pids.dedup();
+                scan.co_attached.push(CoAttachedProgram {
+                    point: point.clone(),
+                    program,
+                    owner_pids,
+                });
+            }
+        }
+
+        let before = self.last.as_ref().map_or(0, |last| last.co_attached.len());
+        if scan.co_attached.len() != before {
+            scan.changed_from = Some(before);
+        }
+        self.last.insert(scan)
+    }
+
+    /// The latest scan, None before the first
+    pub fn last(&self) -> Option<&CoAttachmentScan> {
+        self.last.as_ref()
+    }
+}
+
+// linux/perf_event.h and linux/bpf.h
+#[cfg(feature = "collector-core")]
+const PERF_TYPE_TRACEPOINT: u32 = 2;
+#[cfg(feature = "collector-core")]
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+// _IOWR('$', 10, struct perf_event_query_bpf *)
+#[cfg(feature = "collector-core")]
+const PERF_EVENT_IOC_QUERY_BPF: libc::c_ulong = 0xC008_240A;
+#[cfg(feature = "collector-core")]
+const BPF_OBJ_GET_INFO_BY_FD: libc::c_long = 15;
+#[cfg(feature = "collector-core")]
+const BPF_TASK_FD_QUERY: libc::c_long = 20;
+#[cfg(feature = "collector-core")]
+const BPF_LINK_GET_FD_BY_ID: libc::c_long = 30;
+#[cfg(feature = "collector-core")]
+const BPF_LINK_GET_NEXT_ID: libc::c_long = 31;
+#[cfg(feature = "collector-core")]
+const BPF_LINK_TYPE_RAW_TRACEPOINT: u32 = 1;
+#[cfg(feature = "collector-core")]
+const BPF_LINK_TYPE_PERF_EVENT: u32 = 7;
+#[cfg(feature = "collector-core")]
+const BPF_PERF_EVENT_KPROBE: u32 = 3;
+#[cfg(feature = "collector-core")]
+const BPF_PERF_EVENT_KRETPROBE: u32 = 4;
+#[cfg(feature = "collector-core")]
+const BPF_FD_TYPE_KPROBE: u32 = 2;
+#[cfg(feature = "collector-core")]
+const BPF_FD_TYPE_KRETPROBE: u32 = 3;
+// Programs a tracepoint's query has room for
+#[cfg(feature = "collector-core")]
+const MAX_QUERIED: usize = 64;
+// Kernel function and tracepoint names the queries have room for
+#[cfg(feature = "collector-core")]
+const NAME_LEN: usize = 256;
+// bpf_link_info is 3 u32s and then a union; room for the kernel's and zeros
+#[cfg(feature = "collector-core")]
+const LINK_INFO_WORDS: usize = 16;
+
+// PERF_ATTR_SIZE_VER0, as in perf_ring.rs
+#[cfg(feature = "collector-core")]
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup: u32,
+    bp_type: u32,
+    config1: u64,
+}
+
+#[cfg(feature = "collector-core")]
+#[repr(C)]
+struct QueryBpf {
+    ids_len: u32,
+    prog_cnt: u32,
+    ids: [u32; MAX_QUERIED],
+}
+
+// bpf_attr for BPF_LINK_GET_NEXT_ID (start_id) and BPF_LINK_GET_FD_BY_ID (link_id)
+#[cfg(feature = "collector-core")]
+#[repr(C)]
+struct LinkIdAttr {
+    id: u32,
+    next_id: u32,
+    open_flags: u32,
+}
+
+#[cfg(feature = "collector-core")]
+#[repr(C)]
+struct InfoByFdAttr {
+    bpf_fd: u32,
+    info_len: u32,
+    info: u64,
+}
+
+#[cfg(feature = "collector-core")]
+#[repr(C)]
+struct TaskFdQueryAttr {
+    pid: u32,
+    fd: u32,
+    flags: u32,
+    buf_len: u32,
+    buf: u64,
+    prog_id: u32,
+    fd_type: u32,
+    probe_offset: u64,
+    probe_addr: u64,
+}
+
+// Program ids by kprobe function or raw tracepoint, pids by program id
+#[cfg(feature = "collector-core")]
+type Ids<K> = HashMap<K, Vec<u32>>;
+
+/// The kernel's answers, for one scan: links, /proc and the program list are
+/// each read once, when first needed
+#[cfg(feature = "collector-core")]
+pub(crate) struct KernelAttachments {
+    kernel: Option<(u32, u32)>,
+    tracefs: Option<&'static Path>,
+    // Program ids by kprobe function and by raw tracepoint
+    links: Option<(Ids<String>, Ids<String>)>,
+    // Pids by program id, and program ids by kprobe function of perf events
+    // attached without a link
+    proc_fds: Option<(Ids<u32>, Ids<String>)>,
+    programs: Option<HashMap<u32, BpfProgram>>,
+}
+
+#[cfg(feature = "collector-core")]
+impl KernelAttachments {
+    pub(crate) fn new(kernel: Option<(u32, u32)>, tracefs: Option<&'static Path>) -> Self {
+        Self {
+            kernel,
+            tracefs,
+            links: None,
+            proc_fds: None,
+            programs: None,
+        }
+    }
+
+    fn links(&mut self) -> &(Ids<String>, Ids<String>) {
+        self.links.get_or_insert_with(|| {
+            let mut kprobes: HashMap<String, Vec<u32>> = HashMap::new();
+            let mut raw_tracepoints: HashMap<String, Vec<u32>> = HashMap::new();
+            for (link_type, prog_id, name) in perf_and_raw_tracepoint_links() {
+                match link_type {
+                    BPF_LINK_TYPE_PERF_EVENT => kprobes.entry(name).or_default().push(prog_id),
+                    _ => raw_tracepoints.entry(name).or_default().push(prog_id),
+                }
+            }
+            (kprobes, raw_tracepoints)
+        })
+    }
+
+    fn proc_fds(&mut self) -> &(Ids<u32>, Ids<String>) {
+        self.proc_fds.get_or_insert_with(scan_proc)
+    }
+}
+
+#[cfg(feature = "collector-core")]
+impl AttachEnumerator for KernelAttachments {
+    fn attached(&mut self, point: &AttachPoint) -> io::Result<Vec<u32>> {
+        match point {
+            AttachPoint::Program(ProgramAttach::Tracepoint(category, name)) => {
+                let tracefs = self.tracefs.ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "tracefs not mounted")
+                })?;
+                let mut ids = tracepoint_programs(tracefs, category, name)?;
+                ids.extend(self.links().1.get(*name).into_iter().flatten());
+                Ok(ids)
+            }
+            AttachPoint::Program(ProgramAttach::Kprobe(function)) => {
+                if self.kernel.is_none_or(|version| version < (6, 6)) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "perf links don't say which function they're on before 6.6",
+                    ));
+                }
+                let mut ids: Vec<u32> = self.links().0.get(*function).cloned().unwrap_or_default();
+                ids.extend(self.proc_fds().1.get(*function).into_iter().flatten());
+                Ok(ids)
+            }
+            AttachPoint::TcEgress(interface) => {
+                let tcx = SchedClassifier::query_tcx(interface, TcAttachType::Egress);
+                let classifiers = egress::classifier_programs(interface);
+                if let (Err(_), Err(e)) = (&tcx, &classifiers) {
+                    return Err(io::Error::new(e.kind(), e.to_string()));
+                }
+                let mut ids: Vec<u32> = tcx
+                    .map(|(_, programs)| programs.iter().map(|program| program.id()).collect())
+                    .unwrap_or_default();
+                ids.extend(classifiers.unwrap_or_default());
+                Ok(ids)
+            }
+        }
+    }
+
+    fn program(&mut self, id: u32) -> Option<BpfProgram> {
+        let programs = self.programs.get_or_insert_with(|| {
+            loaded_programs()
+                .filter_map(Result::ok)
+                .map(|info| {
+                    let program = BpfProgram {
+                        id: info.id(),
+                        name: info.name_as_str().unwrap_or_default().to_string(),
+                        program_type: info
+                            .program_type()
+                            .map_or("unknown".to_string(), |t| format!("{:?}", t)),
+                        owner_uid: info.created_by_uid(),
+                    };
+                    (program.id, program)
+                })
+                .collect()
+        });
+        programs.get(&id).cloned()
+    }
+
+    fn holders(&mut self, id: u32) -> Vec<u32> {
+        self.proc_fds().0.get(&id).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(feature = "collector-core")]
+fn bpf(cmd: libc::c_long, attr: *const libc::c_void, size: usize) -> io::Result<libc::c_long> {
+    let result = unsafe { libc::syscall(libc::SYS_bpf, cmd, attr, size as libc::c_uint) };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(result)
+}
+
+/// Ids of the programs on a tracepoint's perf event, which every perf event of
+/// the tracepoint shares
+#[cfg(feature = "collector-core")]
+fn tracepoint_programs(tracefs: &Path, category: &str, name: &str) -> io::Result<Vec<u32>> {
+    let id = std::fs::read_to_string(tracefs.join("events").join(category).join(name).join("id"))?;
+    let attr = PerfEventAttr {
+        type_: PERF_TYPE_TRACEPOINT,
+        size: size_of::<PerfEventAttr>() as u32,
+        config: id
+            .trim()
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "tracepoint id"))?,
+        ..Default::default()
+    };
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_perf_event_open,
+            &attr as *const PerfEventAttr,
+            -1,
+            0,
+            -1,
+            PERF_FLAG_FD_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Closed on return, leaving the tracepoint as it was
+    let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+    let mut query = QueryBpf {
+        ids_len: MAX_QUERIED as u32,
+        prog_cnt: 0,
+        ids: [0; MAX_QUERIED],
+    };
+    if unsafe { libc::ioctl(fd.as_raw_fd(), PERF_EVENT_IOC_QUERY_BPF, &mut query) } < 0 {
+        let e = io::Error::last_os_error();
+        // More than there's room for: the first MAX_QUERIED are in
+        if e.raw_os_error() != Some(libc::ENOSPC) {
+            return Err(e);
+        }
+    }
+    Ok(query.ids[..(query.prog_cnt as usize).min(MAX_QUERIED)].to_vec())
+}
+
+/// Every kprobe perf link and raw tracepoint link: its type, program and the
+/// function or tracepoint it's on
+#[cfg(feature = "collector-core")]
+fn perf_and_raw_tracepoint_links() -> Vec<(u32, u32, String)> {
+    let mut links = Vec::new();
+    let mut next = LinkIdAttr {
+        id: 0,
+        next_id: 0,
+        open_flags: 0,
+    };
+    while bpf(
+        BPF_LINK_GET_NEXT_ID,
+        (&next as *const LinkIdAttr).cast(),
+        size_of::<LinkIdAttr>(),
+    )
+    .is_ok()
+    {
+        next.id = next.next_id;
+        let by_id = LinkIdAttr {
+            id: next.id,
+            next_id: 0,
+            open_flags: 0,
+        };
+        // Gone since it was listed
+        let Ok(fd) = bpf(
+            BPF_LINK_GET_FD_BY_ID,
+            (&by_id as *const LinkIdAttr).cast(),
+            size_of::<LinkIdAttr>(),
+        ) else {
+            continue;
+        };
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        if let Some(link) = link_target(&fd) {
+            links.push(link);
+        }
+    }
+    links
+}
+
+/// A link's type, program and target name, for kprobe perf links and raw
+/// tracepoint links. The type comes first, it says where the name goes
+#[cfg(feature = "collector-core")]
+fn link_target(fd: &OwnedFd) -> Option<(u32, u32, String)> {
+    let info_by_fd = |info: &mut [u64; LINK_INFO_WORDS]| {
+        let attr = InfoByFdAttr {
+            bpf_fd: fd.as_raw_fd() as u32,
+            info_len: size_of::<[u64; LINK_INFO_WORDS]>() as u32,
+            info: info.as_mut_ptr() as u64,
+        };
+        bpf(
+            BPF_OBJ_GET_INFO_BY_FD,
+            (&attr as *const InfoByFdAttr).cast(),
+            size_of::<InfoByFdAttr>(),
+        )
+    };
+    // type, id at 0; prog_id, then the union at 16
+    let mut info = [0u64; LINK_INFO_WORDS];
+    info_by_fd(&mut info).ok()?;
+    let link_type = info[0] as u32;
+    let prog_id = info[1] as u32;
+    let mut name = [0u8; NAME_LEN];
+    let mut info = [0u64; LINK_INFO_WORDS];
+    match link_type {
+        // raw_tracepoint: tp_name at 16, tp_name_len at 24
+        BPF_LINK_TYPE_RAW_TRACEPOINT => {
+            info[2] = name.as_mut_ptr() as u64;
+            info[3] = NAME_LEN as u64;
+        }
+        // perf_event: type at 16; kprobe: func_name at 24, name_len at 32
+        BPF_LINK_TYPE_PERF_EVENT => {
+            info[3] = name.as_mut_ptr() as u64;
+            info[4] = NAME_LEN as u64;
+        }
+        _ => return None,
+    }
+    info_by_fd(&mut info).ok()?;
+    if link_type == BPF_LINK_TYPE_PERF_EVENT
+        && !matches!(
+            info[2] as u32,
+            BPF_PERF_EVENT_KPROBE | BPF_PERF_EVENT_KRETPROBE
+        )
+    {
+        return None;
+    }
+    let name = std::ffi::CStr::from_bytes_until_nul(&name).ok()?;
+    Some((link_type, prog_id, name.to_string_lossy().into_owned()))
+}
+
+/// Every process's BPF descriptors: the pids holding each program, by its
+/// fdinfo's prog_id, and the programs of kprobe perf events attached without
+/// a link, by function
+#[cfg(feature = "collector-core")]
+fn scan_proc() -> (Ids<u32>, Ids<String>) {
+    let mut holders: HashMap<u32, Vec<u32>> = HashMap::new();
+    let mut kprobes: HashMap<String, Vec<u32>> = HashMap::new();
+    let Ok(processes) = std::fs::read_dir("/proc") else {
+        return (holders, kprobes);
+    };
+    for process in processes.flatten() {
+        let Some(pid) = process
+            .file_name()
+            .to_str()
+            .and_then(|pid| pid.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        // Exited, or not ours to look at
+        let Ok(fds) = std::fs::read_dir(process.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            let Ok(target) = std::fs::read_link(fd.path()) else {
+                continue;
+            };
+            let Some(fd) = fd
+                .file_name()
+                .to_str()
+                .and_then(|fd| fd.parse::<u32>().ok())
+            else {
+                continue;
+            };
+            match target.to_str() {
+                Some("anon_inode:bpf-prog" | "anon_inode:bpf_link") => {
+                    let fdinfo = process.path().join("fdinfo").join(fd.to_string());
+                    let prog_id = std::fs::read_to_string(fdinfo).ok().and_then(|fdinfo| {
+                        fdinfo
+                            .lines()
+                            .find_map(|line| line.strip_prefix("prog_id:"))
+                            .and_then(|id| id.trim().parse::<u32>().ok())
+                    });
+                    if let Some(prog_id) = prog_id {
+                        holders.entry(prog_id).or_default().push(pid);
+                    }
+                }
+                Some("anon_inode:[perf_event]") => {
+                    if let Some((prog_id, function)) = task_fd_kprobe(pid, fd) {
+                        holders.entry(prog_id).or_default().push(pid);
+                        kprobes.entry(function).or_default().push(prog_id);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    (holders, kprobes)
+}
+
+/// The program and function of a kprobe perf event with a program set on it,
+/// None for any other perf event
+#[cfg(feature = "collector-core")]
+fn task_fd_kprobe(pid: u32, fd: u32) -> Option<(u32, String)> {
+    let mut name = [0u8; NAME_LEN];
+    let mut query = TaskFdQueryAttr {
+        pid,
+        fd,
+        flags: 0,
+        buf_len: NAME_LEN as u32,
+        buf: name.as_mut_ptr() as u64,
+        prog_id: 0,
+        fd_type: 0,
+        probe_offset: 0,
+        probe_addr: 0,
+    };
+    bpf(
+        BPF_TASK_FD_QUERY,
+        (&mut query as *mut TaskFdQueryAttr).cast(),
+        size_of::<TaskFdQueryAttr>(),
+    )
+    .ok()?;
+    if !matches!(query.fd_type, BPF_FD_TYPE_KPROBE | BPF_FD_TYPE_KRETPROBE) {
+        return None;
+    }
+    let name = std::ffi::CStr::from_bytes_until_nul(&name).ok()?;
+    Some((query.prog_id, name.to_string_lossy().into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// A kernel answering from tables: points without an entry can't be
+    /// asked about
+    #[derive(Default)]
+    struct Scripted {
+        attached: HashMap<AttachPoint, Vec<u32>>,
+        programs: HashMap<u32, BpfProgram>,
+        holders: HashMap<u32, Vec<u32>>,
+    }
+
+    impl AttachEnumerator for Scripted {
+        fn attached(&mut self, point: &AttachPoint) -> io::Result<Vec<u32>> {
+            self.attached
+                .get(point)
+                .cloned()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "not scripted"))
+        }
+
+        fn program(&mut self, id: u32) -> Option<BpfProgram> {
+            self.programs.get(&id).cloned()
+        }
+
+        fn holders(&mut self, id: u32) -> Vec<u32> {
+            self.holders.get(&id).cloned().unwrap_or_default()
+        }
+    }
+
+    fn kfree_skb() -> AttachPoint {
+        AttachPoint::Program(ProgramAttach::Tracepoint("skb", "kfree_skb"))
+    }
+
+    fn enqueue() -> AttachPoint {
+        AttachPoint::Program(ProgramAttach::Kprobe("__udp_enqueue_schedule_skb"))
+    }
+
+    fn send() -> AttachPoint {
+        AttachPoint::Program(ProgramAttach::Kprobe("udp_sendmsg"))
+    }
+
+    fn egress() -> AttachPoint {
+        AttachPoint::TcEgress("eth0".to_string())
+    }
+
+    fn program(id: u32, name: &str, owner_uid: Option<u32>) -> BpfProgram {
+        BpfProgram {
+            id,
+            name: name.to_string(),
+            program_type: "TracePoint".to_string(),
+            owner_uid,
+        }
+    }
+
+    /// Ours are 10 to 14. At kfree_skb 20 and 21 are someone else's and 30 was
+    /// unloaded after the listing; both of enqueue's, entry and return, are
+    /// ours; at udp_sendmsg ours is gone and 20 is still there; tc egress
+    /// isn't scripted
+    fn kernel() -> (Scripted, Vec<(AttachPoint, u32)>) {
+        let kernel = Scripted {
+            attached: HashMap::from([
+                (kfree_skb(), vec![21, 10, 20, 30, 20]),
+                (enqueue(), vec![12, 11]),
+                (send(), vec![20]),
+            ]),
+            programs: HashMap::from([
+                (20, program(20, "kfree_skb_count", Some(0))),
+                (21, program(21, "", None)),
+            ]),
+            holders: HashMap::from([(20, vec![4242, 17, 4242])]),
+        };
+        let ours = vec![
+            (kfree_skb(), 10),
+            (enqueue(), 11),
+            (enqueue(), 12),
+            (send(), 13),
+            (egress(), 14),
+        ];
+        (kernel, ours)
+    }
+
+    #[test]
+    fn programs_that_arent_ours_are_co_attached_with_their_owners() {
+        let (mut kernel, ours) = kernel();
+        let scan = CoAttachmentScanner::new().scan(&mut kernel, &ours).clone();
+        let found: Vec<(String, u32, Vec<u32>)> = scan
+            .co_attached
+            .iter()
+            .map(|c| (c.point.to_string(), c.program.id, c.owner_pids.clone()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("tracepoint:skb:kfree_skb".to_string(), 20, vec![17, 4242]),
+                ("tracepoint:skb:kfree_skb".to_string(), 21, vec![]),
+                ("kprobe:udp_sendmsg".to_string(), 20, vec![17, 4242]),
+            ]
+        );
+        assert_eq!(
+            scan.co_attached[0].to_string(),
+            "\"kfree_skb_count\" (id 20, TracePoint, uid 0, pid 17,4242) at \
+             tracepoint:skb:kfree_skb"
+        );
+        assert_eq!(
+            scan.co_attached[1].to_string(),
+            "\"\" (id 21, TracePoint) at tracepoint:skb:kfree_skb"
+        );
+    }
+
+    #[test]
+    fn points_without_ours_are_missing_and_unanswered_ones_unscanned() {
+        let (mut kernel, ours) = kernel();
+        let scan = CoAttachmentScanner::new().scan(&mut kernel, &ours).clone();
+        assert_eq!(scan.missing, [send()]);
+        assert_eq!(scan.unscanned.len(), 1);
+        assert_eq!(scan.unscanned[0].0, egress());
+        assert_eq!(scan.unscanned[0].1, "not scripted");
+    }
+
+    #[test]
+    fn a_change_in_the_number_co_attached_is_told_once() {
+        let (mut kernel, ours) = kernel();
+        let mut scanner = CoAttachmentScanner::new();
+        assert_eq!(scanner.last(), None);
+        let first = scanner.scan(&mut kernel, &ours).clone();
+        assert_eq!(first.changed_from, Some(0));
+        let same = scanner.scan(&mut kernel, &ours).clone();
+        assert_eq!(same.changed_from, None);
+        assert_eq!(same.co_attached, first.co_attached);
+
+        kernel.attached.insert(kfree_skb(), vec![10, 20]);
+        let one_left = scanner.scan(&mut kernel, &ours).clone();
+        assert_eq!(one_left.co_attached.len(), 2);
+        assert_eq!(one_left.changed_from, Some(3));
+        assert_eq!(scanner.last(), Some(&one_left));
+    }
+
+    #[cfg(feature = "collector-core")]
+    #[test]
+    fn the_change_warning_lists_whats_co_attached() {
+        let (mut kernel, ours) = kernel();
+        let mut scanner = CoAttachmentScanner::new();
+        let warning = scanner.scan(&mut kernel, &ours).change_warning().unwrap();
+        assert!(warning.starts_with("3 programs of others attached where ours are, 0 before: "));
+        assert!(warning.contains("(id 21, TracePoint) at tracepoint:skb:kfree_skb"));
+        assert_eq!(scanner.scan(&mut kernel, &ours).change_warning(), None);
+
+        kernel.attached.clear();
+        kernel.attached.insert(enqueue(), vec![11, 12]);
+        let warning = scanner.scan(&mut kernel, &ours).change_warning();
+        assert_eq!(
+            warning.as_deref(),
+            Some("0 programs of others attached where ours are, 3 before")
+        );
+    }
+}
@@ -0,0 +1,4204 @@
+//The collector itself: loads and attaches the probes, owns the aggregated
+//atomics and hands out interval reads. Readers and the processing side live in
+//reader.rs and pipeline.rs, in whichever of the async/blocking flavours is built.
+
+use crate::accuracy::AccuracyTracker;
+use crate::budget::{Degradation, OverheadEnforcer};
+use crate::burst::BurstCorrelator;
+use crate::calibration::{self, SndbufCalibration};
+use crate::cgroups::CgroupTracker;
+use crate::cardinality::DistinctCounter;
+use crate::classes::ClassTable;
+use crate::coattach::{AttachPoint, CoAttachmentScan, CoAttachmentScanner, KernelAttachments};
+use crate::conflict::{self, Claim};
+use crate::diagnostics::{self, DiagnosticState};
+use crate::drop_reason::DropReasonNames;
+use crate::egress::EgressAccounting;
+use crate::extensions::ExtensionTypes;
+use crate::health::{self, SampleSanityCheck, SoftirqCrossCheck, StalenessCheck};
+#[cfg(feature = "async-runtime")]
+use crate::heartbeat::{self, HeartbeatMonitor};
+use crate::memlock::Memlock;
+use crate::memory::{self, entry_bytes, hash_table_bytes, BpfMapMemory};
+use crate::netmem::{NetMemory, NetMemoryTracker};
+use crate::netns::{NetnsFilter, NetnsResolver, NetnsTable};
+use crate::pipeline::Pipeline;
+#[cfg(feature = "async-runtime")]
+use crate::pipeline::RECENT_EVENTS;
+use crate::preflight::{self, PreflightHost, PreflightReport};
+#[cfg(feature = "async-runtime")]
+use crate::publisher::{self, FastPublisher, LatestSnapshot, Publisher};
+#[cfg(feature = "async-runtime")]
+use crate::runtime::{Rt, Runtime};
+#[cfg(feature = "async-runtime")]
+use crate::supervisor::{Component, Supervisor};
+use crate::buffered::BufferedTracker;
+use crate::clock::Clock;
+use crate::cpus::CpuSlots;
+use crate::reader::{self, ReadWindow, Readers};
+use crate::sessions::{SessionHandle, SessionReport, Sessions};
+use crate::sockets::SocketTable;
+use crate::state::{self, PersistedState};
+use crate::txq::TxqStalls;
+use crate::traced::SocketTracer;
+use crate::{
+    btf, rank_talkers, AccuracyReport, BondTopology, BudgetAction, CollectorConfig, CollectorError, CollectorState, CongestionEvent, CongestionSignals, EstimatedTotals, FastSignals, GsoCounters, GsoSegments, ObservedCounts, ProtocolCounts,
+    TimeNamespaceOffsets, Transition, WallClock, WALL_CLOCK_TOLERANCE, MemlockOutcome,
+    CalibrationOutcome, CgroupRollup, DropReason, HealthReport, MemoryReport, SendSizeStats, SendSizes, Signal, Limitation, LimitationThresholds, PerCpuSignals, ReaderStats, RxBudget, SchemaDescriptor,
+    RegisteredSocketSignals, SocketHandle, SocketSignals, SocketStateSample, StructureMemory, CumulativeTotals, StateFileConfig, EVENT_NET_DEV_QUEUE, EVENT_QDISC_DROP, EVENT_RX_TIME_SQUEEZE,
+    EVENT_SOCKET_RCV_STATE, EVENT_SOCKET_STATE, EVENT_SOFTIRQ_EXIT, EVENT_TCP_CWR, EVENT_TCP_RECOVERY, EVENT_TCP_RTO, EVENT_TCP_SEND,
+    EVENT_SOCKET_LIFECYCLE, EVENT_TCP_STATE, EVENT_TYPE_SLOTS, EVENT_UDP_RCV_CE, EVENT_UDP_RCV_DROP, EVENT_UDP_SEND,
+    IPPROTO_TCP, IPPROTO_UDP, KernelOffsets, LoadedCollector, OFFSET_UNKNOWN, Redaction, SampleScale, SamplerEstimates, SocketSampling, SoftirqAttributionTracker, DropInterarrivalTracker, SoftirqBreakdown, SoftirqBreakdownTracker, SoftirqCouplingTracker, TalkerRanking, TopTalker, TriggeredCapture, CaptureNotice, CaptureTrigger, FinishedCapture, SAMPLER_COMPARE, SAMPLER_PRIMARY, traced_only, SocketTrace, load_groups, GroupHost, ProgramAttach, ProgramLoadFailure, ProgramLoader, ProgramRejection, PROGRAM_GROUPS, FixedSignals, mean_permille, ratio_permille, PERMILLE, CustomEventHandler, ExtensionRegistry,
+};
+
+use aya::include_bytes_aligned;
+use aya::{
+    maps::{Array, MapData, PerCpuArray},
+    programs::{loaded_links, KProbe, Program, ProgramError, TracePoint},
+    Ebpf, EbpfLoader,
+};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+#[cfg(feature = "async-runtime")]
+use std::net::UdpSocket;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant, SystemTime};
+#[cfg(feature = "governor")]
+use crate::Governor;
+#[cfg(feature = "async-runtime")]
+use crate::{
+    ComponentFailure, SeverityClassifier, SeveritySnapshot, SeverityThresholds, SignalSmoother,
+    SmoothedSignals,
+};
+#[cfg(feature = "async-runtime")]
+use futures_core::Stream;
+#[cfg(feature = "async-runtime")]
+use tokio::sync::broadcast;
+
+// Drop reasons below this are counted with an atomic add on the readers;
+// enum skb_drop_reason is ~130 long, only subsystem reasons (high bits) go past
+pub(crate) const DROP_REASON_SLOTS: usize = 256;
+
+// How far ahead of now a reload's cutover is. Setting it on both reader sets
+// takes microseconds, the old readers count what's stamped in between
+const RELOAD_CUTOVER_LEAD_NS: u64 = 10_000_000;
+
+// SOFTIRQ_MAX_DURATION_NS in main.rs; also guards recordings and objects from
+// before the kernel-side check
+const SOFTIRQ_MAX_DURATION_NS: u64 = 100_000_000;
+
+/// A softirq exit longer than SOFTIRQ_MAX_DURATION_NS: counted as discarded,
+/// and kept from everything that times softirqs
+pub(crate) fn implausible_softirq(event: &CongestionEvent) -> bool {
+    event.event_type == EVENT_SOFTIRQ_EXIT
+        && unsafe { event.data.softirq.duration_ns } > SOFTIRQ_MAX_DURATION_NS
+}
+
+// ssthresh of a socket that hasn't seen loss yet
+const TCP_INFINITE_SSTHRESH: u32 = 0x7fff_ffff;
+
+// Interval reads kept for diagnostic bundles, a minute at one read per second
+const INTERVAL_HISTORY: usize = 60;
+
+/// Thread-safe atomic storage for signals
+#[derive(Default)]
+pub(crate) struct AtomicSignals {
+    send_bytes: AtomicU64,
+    loopback_send_bytes: AtomicU64,
+    // Sampled by the comparison sampler, see CollectorConfig::sampler_comparison
+    compare_send_bytes: AtomicU64,
+    compare_sends: AtomicU64,
+    compare_ratio: Option<u64>,
+    // Sends the primary sampler admitted, counted before any userspace filter
+    sends_sampled: AtomicU64,
+    udp_send_sizes: AtomicSendSizes,
+    tcp_send_sizes: AtomicSendSizes,
+    drops: AtomicU64,
+    wmem_samples: AtomicU64,
+    wmem_total: AtomicU64,
+    udp_wmem_samples: AtomicU64,
+    udp_wmem_total: AtomicU64,
+    tcp_wmem_samples: AtomicU64,
+    tcp_wmem_total: AtomicU64,
+    softirq_ns: AtomicU64,
+    softirq_exits: AtomicU64,
+    event_count: AtomicU64,
+    queue_depth_packets: AtomicU64,
+    queue_depth_bytes: AtomicU64,
+    udp_rcv_drops: AtomicU64,
+    rmem_samples: AtomicU64,
+    rmem_total: AtomicU64,
+    // Never reset, the plausible side of the sanity check in health(): every
+    // rmem and wmem sample that made it out of the kernel
+    pub(crate) socket_samples_total: AtomicU64,
+    // The possible CPUs; the per-CPU counters have a slot for each, and one
+    // more past them for ids outside the set
+    pub(crate) cpus: Arc<CpuSlots>,
+    softirq_ns_by_cpu: Vec<AtomicU64>,
+    // Never reset, feeds the /proc/stat cross-check in health()
+    softirq_ns_total: AtomicU64,
+    // Folded next to send_bytes and drops but swapped only by read_fast(), so
+    // fast snapshots and the interval read don't reset each other's counts
+    fast_send_bytes: AtomicU64,
+    fast_drops: AtomicU64,
+    // Highest wmem permille since the last fast read plus one, 0 = no sample
+    fast_wmem_max: AtomicU64,
+    fast_udp_wmem_max: AtomicU64,
+    fast_tcp_wmem_max: AtomicU64,
+    tcp_samples: AtomicU64,
+    tcp_cwnd_total: AtomicU64,
+    tcp_ssthresh_samples: AtomicU64,
+    tcp_ssthresh_total: AtomicU64,
+    tcp_pacing_total: AtomicU64,
+    // Send buffer samples of paced sockets
+    socket_pacing_total: AtomicU64,
+    socket_pacing_samples: AtomicU64,
+    ce_marks: AtomicU64,
+    tcp_cwr: AtomicU64,
+    tcp_rto: AtomicU64,
+    tcp_recovery: AtomicU64,
+    rx_time_squeeze: AtomicU64,
+    // Implausible softirq durations that got past the kernel (older objects)
+    softirq_discarded: AtomicU64,
+    rx_time_squeeze_by_cpu: Vec<AtomicU64>,
+    // Softirq exits and squeezes that went to the "other" slot
+    unknown_cpu_events: AtomicU64,
+    // socket_id -> cwnd < ssthresh on its latest sample this interval, at most
+    // max_tracked_sockets; refused past that
+    pub(crate) tcp_below_ssthresh: Mutex<HashMap<u64, bool>>,
+    pub(crate) max_tracked_sockets: usize,
+    pub(crate) tcp_state_evictions: AtomicU64,
+    // Per socket lifetime, never reset; entries retire on close or idle TTL
+    pub(crate) sockets: SocketTable,
+    // Distinct socket ids this interval
+    pub(crate) active_sockets: Mutex<DistinctCounter>,
+    // Distinct socket and flow_hash pairs, None unless CollectorConfig::track_flows
+    pub(crate) active_flows: Option<Mutex<DistinctCounter>>,
+    // Latest timestamp_ns per event type, 0 = never seen. Never reset
+    last_seen_ns: [AtomicU64; EVENT_TYPE_SLOTS],
+    // kfree_skb drops by raw reason, never reset; past DROP_REASON_SLOTS in the map
+    drops_by_reason: Vec<AtomicU64>,
+    pub(crate) drops_by_reason_high: Mutex<HashMap<u32, u64>>,
+    // Fed by the processing side, None unless enabled in the config
+    pub(crate) burst: Option<Mutex<BurstCorrelator>>,
+    // Likewise, for CollectorConfig::drop_interarrival
+    pub(crate) interarrival: Option<Mutex<DropInterarrivalTracker>>,
+    // Likewise, for CollectorConfig::softirq_coupling
+    pub(crate) coupling: Option<Mutex<SoftirqCouplingTracker>>,
+    // And CollectorConfig::softirq_attribution
+    pub(crate) attribution: Option<Mutex<SoftirqAttributionTracker>>,
+    // And CollectorConfig::softirq_breakdown
+    pub(crate) softirq_breakdown: Option<Mutex<SoftirqBreakdownTracker>>,
+    // And CollectorConfig::triggered_capture, triggered from the interval read
+    pub(crate) capture: Option<Mutex<TriggeredCapture>>,
+    // Checked by the readers, None without CollectorConfig::heartbeat
+    #[cfg(feature = "async-runtime")]
+    pub(crate) heartbeat: Option<Arc<HeartbeatMonitor>>,
+    // Handlers fed by the processing side, and their types for the readers
+    pub(crate) extensions: Mutex<ExtensionRegistry>,
+    pub(crate) extension_types: ExtensionTypes,
+    // Socket-state samples of the calibration socket while one runs
+    pub(crate) calibration: SndbufCalibration,
+    // Set after a failed calibration with disable_on_mismatch, the readers then
+    // drop socket-state events
+    pub(crate) socket_state_disabled: AtomicBool,
+    // Checked by the readers, None without an allow-list
+    pub(crate) netns_filter: Option<NetnsFilter>,
+    // Fed by the processing side, None unless CollectorConfig::netns is set
+    pub(crate) netns: Option<Mutex<NetnsTable>>,
+    // Likewise for CollectorConfig::traffic_classes
+    pub(crate) classes: Option<Mutex<ClassTable>>,
+}
+
+impl AtomicSignals {
+    pub(crate) fn new(cpus: CpuSlots, config: &CollectorConfig) -> Self {
+        let slots = cpus.len() + 1;
+        Self {
+            compare_ratio: config
+                .sampler_comparison
+                .filter(|_| config.socket_sampling.is_none()),
+            burst: config
+                .burst_correlation
+                .as_ref()
+                .map(|burst| Mutex::new(BurstCorrelator::new(burst))),
+            interarrival: config
+                .drop_interarrival
+                .then(|| Mutex::new(DropInterarrivalTracker::new())),
+            coupling: config
+                .softirq_coupling
+                .as_ref()
+                .map(|coupling| Mutex::new(SoftirqCouplingTracker::new(coupling))),
+            attribution: config
+                .softirq_attribution
+                .as_ref()
+                .map(|attribution| Mutex::new(SoftirqAttributionTracker::new(attribution))),
+            softirq_breakdown: config.softirq_breakdown.as_ref().map(|breakdown| {
+                let mut tracker = SoftirqBreakdownTracker::new(breakdown);
+                tracker.set_bonds(&BondTopology::read());
+                Mutex::new(tracker)
+            }),
+            capture: config
+                .triggered_capture
+                .clone()
+                .map(|capture| Mutex::new(TriggeredCapture::new(capture))),
+            #[cfg(feature = "async-runtime")]
+            heartbeat: config
+                .heartbeat
+                .as_ref()
+                .map(|heartbeat| Arc::new(HeartbeatMonitor::new(heartbeat, config.clock.clone()))),
+            netns_filter: config
+                .netns
+                .as_ref()
+                .and_then(|netns| netns.allow.clone())
+                .map(|allow| NetnsFilter::new(allow, &mut NetnsResolver::new())),
+            netns: config.netns.as_ref().map(|netns| {
+                Mutex::new(NetnsTable::new(
+                    netns.max_namespaces,
+                    config.exclude_loopback,
+                    config.clock.clone(),
+                ))
+            }),
+            classes: config.traffic_classes.clone().map(|classes| {
+                Mutex::new(ClassTable::new(
+                    classes,
+                    config.exclude_loopback,
+                    config.clock.clone(),
+                ))
+            }),
+            softirq_ns_by_cpu: (0..slots).map(|_| AtomicU64::new(0)).collect(),
+            rx_time_squeeze_by_cpu: (0..slots).map(|_| AtomicU64::new(0)).collect(),
+            cpus: Arc::new(cpus),
+            drops_by_reason: (0..DROP_REASON_SLOTS).map(|_| AtomicU64::new(0)).collect(),
+            active_sockets: Mutex::new(DistinctCounter::new(
+                "active socket ids",
+                config.exact_socket_count_limit,
+            )),
+            active_flows: config.track_flows.then(|| {
+                Mutex::new(DistinctCounter::new(
+                    "active flows",
+                    config.exact_socket_count_limit,
+                ))
+            }),
+            sockets: SocketTable::from_config(config),
+            tcp_below_ssthresh: Mutex::new(HashMap::with_capacity(
+                config.socket_table_capacity.min(config.max_tracked_sockets),
+            )),
+            max_tracked_sockets: config.max_tracked_sockets,
+            ..Default::default()
+        }
+    }
+
+    /// Add a reader's batch and zero it. Fields the batch didn't touch cost nothing
+    pub(crate) fn fold(&self, batch: &mut EventBatch) {
+        let add = |counter: &AtomicU64, value: &mut u64| {
+            if *value > 0 {
+                counter.fetch_add(std::mem::take(value), Ordering::Relaxed);
+            }
+        };
+        add(&self.event_count, &mut batch.event_count);
+        // Before the interval counters take them
+        if batch.send_bytes > 0 {
+            self.fast_send_bytes
+                .fetch_add(batch.send_bytes, Ordering::Relaxed);
+        }
+        if batch.drops > 0 {
+            self.fast_drops.fetch_add(batch.drops, Ordering::Relaxed);
+        }
+        let max = |counter: &AtomicU64, value: &mut u64| {
+            if *value > 0 {
+                counter.fetch_max(std::mem::take(value), Ordering::Relaxed);
+            }
+        };
+        max(&self.fast_wmem_max, &mut batch.wmem_max);
+        max(&self.fast_udp_wmem_max, &mut batch.udp_wmem_max);
+        max(&self.fast_tcp_wmem_max, &mut batch.tcp_wmem_max);
+        for (last_seen, ts) in self.last_seen_ns.iter().zip(&mut batch.last_seen_ns) {
+            if *ts > 0 {
+                // Per-CPU readers race, keep the newest
+                last_seen.fetch_max(std::mem::take(ts), Ordering::Relaxed);
+            }
+        }
+        add(&self.send_bytes, &mut batch.send_bytes);
+        add(&self.loopback_send_bytes, &mut batch.loopback_send_bytes);
+        add(&self.compare_send_bytes, &mut batch.compare_send_bytes);
+        add(&self.compare_sends, &mut batch.compare_sends);
+        add(&self.sends_sampled, &mut batch.sends_sampled);
+        self.udp_send_sizes.fold(&mut batch.udp_send_sizes);
+        self.tcp_send_sizes.fold(&mut batch.tcp_send_sizes);
+        add(&self.drops, &mut batch.drops);
+        for (count, n) in self.drops_by_reason.iter().zip(&mut batch.drops_by_reason) {
+            add(count, n);
+        }
+        add(&self.queue_depth_packets, &mut batch.queue_depth_packets);
+        add(&self.queue_depth_bytes, &mut batch.queue_depth_bytes);
+        add(&self.wmem_total, &mut batch.wmem_total);
+        add(&self.wmem_samples, &mut batch.wmem_samples);
+        add(&self.udp_wmem_total, &mut batch.udp_wmem_total);
+        add(&self.udp_wmem_samples, &mut batch.udp_wmem_samples);
+        add(&self.tcp_wmem_total, &mut batch.tcp_wmem_total);
+        add(&self.tcp_wmem_samples, &mut batch.tcp_wmem_samples);
+        add(&self.udp_rcv_drops, &mut batch.udp_rcv_drops);
+        add(&self.rmem_total, &mut batch.rmem_total);
+        add(&self.rmem_samples, &mut batch.rmem_samples);
+        add(&self.socket_samples_total, &mut batch.socket_samples_total);
+        add(&self.tcp_samples, &mut batch.tcp_samples);
+        add(&self.tcp_cwnd_total, &mut batch.tcp_cwnd_total);
+        add(&self.tcp_pacing_total, &mut batch.tcp_pacing_total);
+        add(&self.socket_pacing_total, &mut batch.socket_pacing_total);
+        add(
+            &self.socket_pacing_samples,
+            &mut batch.socket_pacing_samples,
+        );
+        add(&self.tcp_ssthresh_samples, &mut batch.tcp_ssthresh_samples);
+        add(&self.tcp_ssthresh_total, &mut batch.tcp_ssthresh_total);
+        add(&self.ce_marks, &mut batch.ce_marks);
+        add(&self.tcp_cwr, &mut batch.tcp_cwr);
+        add(&self.tcp_rto, &mut batch.tcp_rto);
+        add(&self.tcp_recovery, &mut batch.tcp_recovery);
+        add(&self.rx_time_squeeze, &mut batch.rx_time_squeeze);
+        let squeezes = self.rx_time_squeeze_by_cpu.iter();
+        for (cpu, n) in squeezes.zip(&mut batch.rx_time_squeeze_by_cpu) {
+            add(cpu, n);
+        }
+        add(&self.unknown_cpu_events, &mut batch.unknown_cpu_events);
+        add(&self.softirq_discarded, &mut batch.softirq_discarded);
+        add(&self.softirq_exits, &mut batch.softirq_exits);
+        if batch.softirq_ns > 0 {
+            self.softirq_ns_total
+                .fetch_add(batch.softirq_ns, Ordering::Relaxed);
+        }
+        add(&self.softirq_ns, &mut batch.softirq_ns);
+        for (cpu, ns) in self.softirq_ns_by_cpu.iter().zip(&mut batch.softirq_ns_by_cpu) {
+            add(cpu, ns);
+        }
+    }
+
+    /// Swap out the per-CPU counters, over `elapsed_ns` of wall time. Squeezes
+    /// are left empty when `rx_squeeze` isn't attached
+    pub(crate) fn take_per_cpu(&self, elapsed_ns: u64, rx_squeeze: bool) -> PerCpuSignals {
+        let take = |counters: &[AtomicU64]| -> Vec<u64> {
+            counters
+                .iter()
+                .map(|cpu| cpu.swap(0, Ordering::Relaxed))
+                .collect()
+        };
+        let mut softirq_ns_by_cpu = take(&self.softirq_ns_by_cpu);
+        let mut rx_time_squeeze_by_cpu = take(&self.rx_time_squeeze_by_cpu);
+        let other_softirq_ns = softirq_ns_by_cpu.pop().unwrap_or(0);
+        let other_rx_time_squeeze = rx_time_squeeze_by_cpu.pop().unwrap_or(0);
+        if !rx_squeeze {
+            rx_time_squeeze_by_cpu.clear();
+        }
+        let softirq_fraction_by_cpu = softirq_ns_by_cpu
+            .iter()
+            .map(|&ns| {
+                if elapsed_ns > 0 {
+                    (ns as f64 / elapsed_ns as f64).min(1.0)
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        PerCpuSignals {
+            cpus: self.cpus.ids().to_vec(),
+            softirq_ns_by_cpu,
+            softirq_fraction_by_cpu,
+            rx_time_squeeze_by_cpu,
+            unknown_cpu_events: self.unknown_cpu_events.swap(0, Ordering::Relaxed),
+            other_softirq_ns,
+            other_rx_time_squeeze: if rx_squeeze { other_rx_time_squeeze } else { 0 },
+        }
+    }
+
+    /// After the kernel clock jumped to `now_ns`: forget last-seen times, restart
+    /// socket idle ages from now and drop pending burst buckets and coupled
+    /// sends, all of which would otherwise mix timestamps from both sides of
+    /// the jump
+    pub(crate) fn reset_clock_state(&self, now_ns: u64) {
+        for last_seen in &self.last_seen_ns {
+            last_seen.store(0, Ordering::Relaxed);
+        }
+        self.sockets.rebase(now_ns);
+        if let Some(burst) = &self.burst {
+            burst.lock().unwrap().reset();
+        }
+        if let Some(interarrival) = &self.interarrival {
+            interarrival.lock().unwrap().reset();
+        }
+        if let Some(coupling) = &self.coupling {
+            coupling.lock().unwrap().reset();
+        }
+        if let Some(attribution) = &self.attribution {
+            attribution.lock().unwrap().reset();
+        }
+        if let Some(breakdown) = &self.softirq_breakdown {
+            breakdown.lock().unwrap().reset();
+        }
+        if let Some(capture) = &self.capture {
+            capture.lock().unwrap().reset();
+        }
+    }
+}
+
+/// Send size totals of one protocol, see `SendSizes`
+struct AtomicSendSizes {
+    samples: AtomicU64,
+    total: AtomicU64,
+    // u64::MAX until the interval's first sample
+    min: AtomicU64,
+    max: AtomicU64,
+    histogram: [AtomicU64; 4],
+}
+
+impl Default for AtomicSendSizes {
+    fn default() -> Self {
+        Self {
+            samples: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            min: AtomicU64::new(u64::MAX),
+            max: AtomicU64::new(0),
+            histogram: Default::default(),
+        }
+    }
+}
+
+impl AtomicSendSizes {
+    fn fold(&self, batch: &mut SendSizeBatch) {
+        if batch.samples == 0 {
+            return;
+        }
+        let batch = std::mem::take(batch);
+        self.samples.fetch_add(batch.samples, Ordering::Relaxed);
+        self.total.fetch_add(batch.total, Ordering::Relaxed);
+        self.min.fetch_min(batch.min, Ordering::Relaxed);
+        self.max.fetch_max(batch.max, Ordering::Relaxed);
+        for (bucket, n) in self.histogram.iter().zip(batch.histogram) {
+            if n > 0 {
+                bucket.fetch_add(n, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// The interval's sizes, None without samples. Resets
+    fn take(&self) -> Option<SendSizes> {
+        let samples = self.samples.swap(0, Ordering::Relaxed);
+        let total = self.total.swap(0, Ordering::Relaxed);
+        let min = self.min.swap(u64::MAX, Ordering::Relaxed);
+        let max = self.max.swap(0, Ordering::Relaxed);
+        let histogram = self
+            .histogram
+            .each_ref()
+            .map(|bucket| bucket.swap(0, Ordering::Relaxed));
+        (samples > 0).then(|| SendSizes {
+            samples,
+            min_bytes: min,
+            avg_bytes: total as f64 / samples as f64,
+            max_bytes: max,
+            histogram,
+        })
+    }
+}
+
+#[derive(Default)]
+struct SendSizeBatch {
+    samples: u64,
+    total: u64,
+    min: u64,
+    max: u64,
+    histogram: [u64; 4],
+}
+
+impl SendSizeBatch {
+    fn add(&mut self, bytes: u64) {
+        self.min = if self.samples == 0 { bytes } else { self.min.min(bytes) };
+        self.max = self.max.max(bytes);
+        self.samples += 1;
+        self.total += bytes;
+        self.histogram[size_bucket(bytes)] += 1;
+    }
+}
+
+/// `SendSizes::histogram` index of a send
+fn size_bucket(bytes: u64) -> usize {
+    let limits = SendSizes::BUCKET_LIMITS;
+    // 64 KB exactly is a full GSO batch, still the 16-64 KB bucket
+    match bytes {
+        b if b < limits[0] => 0,
+        b if b < limits[1] => 1,
+        b if b <= limits[2] => 2,
+        _ => 3,
+    }
+}
+
+/// The atomic-add part of aggregation for one perf read, in plain integers. A
+/// reader adds each event here and folds the lot into `AtomicSignals` at the
+/// end of the read, one atomic per touched field instead of several per event
+pub(crate) struct EventBatch {
+    event_count: u64,
+    last_seen_ns: [u64; EVENT_TYPE_SLOTS],
+    send_bytes: u64,
+    loopback_send_bytes: u64,
+    compare_send_bytes: u64,
+    compare_sends: u64,
+    sends_sampled: u64,
+    udp_send_sizes: SendSizeBatch,
+    tcp_send_sizes: SendSizeBatch,
+    drops: u64,
+    drops_by_reason: Vec<u64>,
+    queue_depth_packets: u64,
+    queue_depth_bytes: u64,
+    wmem_total: u64,
+    wmem_samples: u64,
+    // Like AtomicSignals::fast_wmem_max, plus one and 0 = none
+    wmem_max: u64,
+    udp_wmem_max: u64,
+    tcp_wmem_max: u64,
+    udp_wmem_total: u64,
+    udp_wmem_samples: u64,
+    tcp_wmem_total: u64,
+    tcp_wmem_samples: u64,
+    udp_rcv_drops: u64,
+    rmem_total: u64,
+    rmem_samples: u64,
+    socket_samples_total: u64,
+    tcp_samples: u64,
+    tcp_cwnd_total: u64,
+    tcp_pacing_total: u64,
+    socket_pacing_total: u64,
+    socket_pacing_samples: u64,
+    tcp_ssthresh_samples: u64,
+    tcp_ssthresh_total: u64,
+    ce_marks: u64,
+    tcp_cwr: u64,
+    tcp_rto: u64,
+    tcp_recovery: u64,
+    rx_time_squeeze: u64,
+    rx_time_squeeze_by_cpu: Vec<u64>,
+    softirq_discarded: u64,
+    softirq_exits: u64,
+    softirq_ns: u64,
+    softirq_ns_by_cpu: Vec<u64>,
+    unknown_cpu_events: u64,
+    cpus: Arc<CpuSlots>,
+}
+
+impl EventBatch {
+    /// Sized like `signals`, one per reader and reused across reads
+    pub(crate) fn new(signals: &AtomicSignals) -> Self {
+        Self {
+            event_count: 0,
+            last_seen_ns: [0; EVENT_TYPE_SLOTS],
+            send_bytes: 0,
+            loopback_send_bytes: 0,
+            compare_send_bytes: 0,
+            compare_sends: 0,
+            sends_sampled: 0,
+            udp_send_sizes: SendSizeBatch::default(),
+            tcp_send_sizes: SendSizeBatch::default(),
+            drops: 0,
+            drops_by_reason: vec![0; signals.drops_by_reason.len()],
+            queue_depth_packets: 0,
+            queue_depth_bytes: 0,
+            wmem_total: 0,
+            wmem_samples: 0,
+            wmem_max: 0,
+            udp_wmem_max: 0,
+            tcp_wmem_max: 0,
+            udp_wmem_total: 0,
+            udp_wmem_samples: 0,
+            tcp_wmem_total: 0,
+            tcp_wmem_samples: 0,
+            udp_rcv_drops: 0,
+            rmem_total: 0,
+            rmem_samples: 0,
+            socket_samples_total: 0,
+            tcp_samples: 0,
+            tcp_cwnd_total: 0,
+            tcp_pacing_total: 0,
+            socket_pacing_total: 0,
+            socket_pacing_samples: 0,
+            tcp_ssthresh_samples: 0,
+            tcp_ssthresh_total: 0,
+            ce_marks: 0,
+            tcp_cwr: 0,
+            tcp_rto: 0,
+            tcp_recovery: 0,
+            rx_time_squeeze: 0,
+            rx_time_squeeze_by_cpu: vec![0; signals.rx_time_squeeze_by_cpu.len()],
+            softirq_discarded: 0,
+            softirq_exits: 0,
+            softirq_ns: 0,
+            softirq_ns_by_cpu: vec![0; signals.softirq_ns_by_cpu.len()],
+            unknown_cpu_events: 0,
+            cpus: signals.cpus.clone(),
+        }
+    }
+
+    /// Count a send the primary sampler admitted, ahead of the netns filter:
+    /// the kernel's count of sends seen it's set against covers every
+    /// namespace, see `SampleScale`
+    pub(crate) fn count_sampled(&mut self, event: &CongestionEvent) {
+        if matches!(event.event_type, EVENT_UDP_SEND | EVENT_TCP_SEND)
+            && unsafe { event.data.sendmsg.samplers } & SAMPLER_PRIMARY != 0
+        {
+            self.sends_sampled += 1;
+        }
+    }
+
+    /// Count a send the comparison sampler admitted. True when only it did:
+    /// such a send goes no further, every other signal is the primary sampler's
+    pub(crate) fn add_compared(&mut self, event: &CongestionEvent) -> bool {
+        if !matches!(event.event_type, EVENT_UDP_SEND | EVENT_TCP_SEND) {
+            return false;
+        }
+        let send = unsafe { event.data.sendmsg };
+        if send.samplers & SAMPLER_COMPARE != 0 {
+            self.compare_send_bytes += send.bytes;
+            self.compare_sends += 1;
+        }
+        send.samplers & SAMPLER_PRIMARY == 0
+    }
+
+    pub(crate) fn add(&mut self, event: &CongestionEvent) {
+        if traced_only(event) {
+            return;
+        }
+        self.event_count += 1;
+        if let Some(last_seen) = self.last_seen_ns.get_mut(event.event_type as usize) {
+            *last_seen = (*last_seen).max(event.timestamp_ns);
+        }
+
+        match event.event_type {
+            EVENT_UDP_SEND | EVENT_TCP_SEND => {
+                let bytes = unsafe { event.data.sendmsg.bytes };
+                self.send_bytes += bytes;
+                if unsafe { event.data.sendmsg.loopback } != 0 {
+                    self.loopback_send_bytes += bytes;
+                }
+                if event.event_type == EVENT_UDP_SEND {
+                    self.udp_send_sizes.add(bytes);
+                } else {
+                    self.tcp_send_sizes.add(bytes);
+                }
+            }
+            EVENT_QDISC_DROP => {
+                // One event per run of drops, see CollectorConfig::drop_coalescing
+                let drop = unsafe { event.data.qdisc };
+                self.drops += drop.dropped as u64;
+                // Out of range ones are counted on the processing task
+                if let Some(count) = self.drops_by_reason.get_mut(drop.reason as usize) {
+                    *count += drop.dropped as u64;
+                }
+            }
+            EVENT_NET_DEV_QUEUE => {
+                let qdata = unsafe { event.data.qdisc };
+                self.queue_depth_packets += qdata.backlog_packets as u64;
+                self.queue_depth_bytes += qdata.backlog_bytes as u64;
+            }
+            EVENT_SOCKET_STATE => {
+                let socket = unsafe { event.data.socket };
+                if let Some(rate) = socket.pacing_limit() {
+                    self.socket_pacing_total += rate;
+                    self.socket_pacing_samples += 1;
+                }
+                if socket.sndbuf > 0 {
+                    let pressure = (socket.wmem_queued as u64 * 1000) / (socket.sndbuf as u64);
+                    self.wmem_total += pressure;
+                    self.wmem_samples += 1;
+                    self.wmem_max = self.wmem_max.max(pressure + 1);
+                    self.socket_samples_total += 1;
+                    match socket.protocol {
+                        IPPROTO_UDP => {
+                            self.udp_wmem_total += pressure;
+                            self.udp_wmem_samples += 1;
+                            self.udp_wmem_max = self.udp_wmem_max.max(pressure + 1);
+                        }
+                        IPPROTO_TCP => {
+                            self.tcp_wmem_total += pressure;
+                            self.tcp_wmem_samples += 1;
+                            self.tcp_wmem_max = self.tcp_wmem_max.max(pressure + 1);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            EVENT_UDP_RCV_DROP => self.udp_rcv_drops += 1,
+            EVENT_SOCKET_RCV_STATE => {
+                let rcv = unsafe { event.data.rcv };
+                if rcv.rcvbuf > 0 {
+                    self.rmem_total += (rcv.rmem_alloc as u64 * 1000) / (rcv.rcvbuf as u64);
+                    self.rmem_samples += 1;
+                    self.socket_samples_total += 1;
+                }
+            }
+            EVENT_TCP_STATE => {
+                let tcp = unsafe { event.data.tcp };
+                self.tcp_samples += 1;
+                self.tcp_cwnd_total += tcp.snd_cwnd as u64;
+                self.tcp_pacing_total += tcp.pacing_rate;
+                if tcp.snd_ssthresh < TCP_INFINITE_SSTHRESH {
+                    self.tcp_ssthresh_samples += 1;
+                    self.tcp_ssthresh_total += tcp.snd_ssthresh as u64;
+                }
+            }
+            EVENT_UDP_RCV_CE => self.ce_marks += 1,
+            EVENT_TCP_CWR => self.tcp_cwr += 1,
+            EVENT_TCP_RTO => self.tcp_rto += 1,
+            EVENT_TCP_RECOVERY => self.tcp_recovery += 1,
+            EVENT_RX_TIME_SQUEEZE => {
+                self.rx_time_squeeze += 1;
+                let slot = self.cpu_slot(event.cpu_id);
+                if let Some(cpu) = self.rx_time_squeeze_by_cpu.get_mut(slot) {
+                    *cpu += 1;
+                }
+            }
+            EVENT_SOFTIRQ_EXIT => {
+                if implausible_softirq(event) {
+                    self.softirq_discarded += 1;
+                    return;
+                }
+                let duration = unsafe { event.data.softirq.duration_ns };
+                self.softirq_exits += 1;
+                self.softirq_ns += duration;
+                let slot = self.cpu_slot(event.cpu_id);
+                if let Some(cpu) = self.softirq_ns_by_cpu.get_mut(slot) {
+                    *cpu += duration;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // The per-CPU slot an event from `cpu` counts in, the "other" one for an
+    // id outside the possible CPUs
+    fn cpu_slot(&mut self, cpu: u32) -> usize {
+        self.cpus.slot(cpu).unwrap_or_else(|| {
+            self.unknown_cpu_events += 1;
+            self.cpus.len()
+        })
+    }
+}
+
+/// The per-CPU breakdown of a run of events over `interval_ns`, e.g. a
+/// recording's, as `read_and_reset_per_cpu` would have it with `cpus` as the
+/// possible CPUs
+pub fn replay_per_cpu<'a>(
+    events: impl IntoIterator<Item = &'a CongestionEvent>,
+    cpus: &CpuSlots,
+    interval_ns: u64,
+) -> PerCpuSignals {
+    let signals = AtomicSignals::new(cpus.clone(), &CollectorConfig::default());
+    let mut batch = EventBatch::new(&signals);
+    for event in events {
+        batch.add(event);
+    }
+    signals.fold(&mut batch);
+    signals.take_per_cpu(interval_ns, true)
+}
+
+/// Loads the probes and aggregates their events.
+///
+/// Lifecycle is `Loaded → Collecting → Stopped`; calling a method in the wrong
+/// state fails with [`CollectorError::InvalidState`] instead of panicking:
+///
+/// ```no_run
+/// # async fn run() -> anyhow::Result<()> {
+/// use ebpf_congestion_signals::{CollectorState, CongestionCollector};
+///
+/// let mut collector = CongestionCollector::load()?;
+/// assert_eq!(collector.state(), CollectorState::Loaded);
+///
+/// collector.start_collection().await?;
+/// // Safe to call from anywhere that just needs collection running
+/// collector.ensure_collecting().await?;
+///
+/// let signals = collector.read_and_reset();
+/// # let _ = signals;
+///
+/// collector.stop_collection()?;
+/// assert!(collector.start_collection().await.is_err());
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Dropping a collector that is still collecting stops it the same way.
+/// Without a runtime (the `blocking` feature) use `start_collection_blocking()`.
+pub struct CongestionCollector {
+    ebpf: Ebpf,
+    // The running object, and which of its programs had a bpf link after
+    // attaching; verify_attachments() re-attaches from these
+    bytecode: Vec<u8>,
+    linked_programs: Vec<String>,
+    // Declared sizes, read before the maps are taken out of the object
+    bpf_maps: Vec<BpfMapMemory>,
+    // After ebpf: the programs detach before a clsact we added is deleted
+    interval: Arc<IntervalReader>,
+    state: CollectorState,
+    signals: Arc<AtomicSignals>,
+    readers: Option<Readers>,
+    // The ones a reload replaced, draining what their object stamped before
+    // the cutover until the next reload or stop
+    retiring: Option<Readers>,
+    softirq_check: Mutex<SoftirqCrossCheck>,
+    staleness_check: Mutex<StalenessCheck>,
+    sample_check: Mutex<SampleSanityCheck>,
+    drop_reasons: DropReasonNames,
+    config: CollectorConfig,
+    // Started with collection, shared by readers across reloads
+    pipeline: Option<Pipeline>,
+    #[cfg(feature = "async-runtime")]
+    subscribers: broadcast::Sender<CongestionEvent>,
+    // Interval reads for snapshots(), started by the first stream
+    #[cfg(feature = "async-runtime")]
+    publisher: Mutex<Option<Publisher>>,
+    // Likewise for fast_snapshots()
+    #[cfg(feature = "async-runtime")]
+    fast_publisher: Mutex<Option<FastPublisher>>,
+    // Every background task of the async build runs under it
+    #[cfg(feature = "async-runtime")]
+    supervisor: Arc<Supervisor>,
+    // CollectorConfig::heartbeat's socket and cookie, kept across reloads so
+    // the kernel's HEARTBEAT_SOCKET stays right
+    #[cfg(feature = "async-runtime")]
+    heartbeat: Option<(Arc<UdpSocket>, u64)>,
+    // From the first start_collection, kept across reloads
+    calibration: Option<CalibrationOutcome>,
+    // Data pages per CPU perf buffer, after any cut to fit RLIMIT_MEMLOCK at
+    // load. Reloads keep it
+    buffer_pages: usize,
+    memlock: MemlockOutcome,
+    // Program groups the kernel rejected at the latest load or reload
+    program_failures: Vec<ProgramLoadFailure>,
+    // Anchor of to_wallclock, re-read every WALL_CLOCK_REFRESH
+    wall_clock: Mutex<Option<WallClock>>,
+    // The startup self-check's, with offsets at the time
+    wall_clock_skew: Option<(Duration, TimeNamespaceOffsets)>,
+    // None without CollectorConfig::with_overhead_budget
+    overhead: Option<OverheadEnforcer>,
+    // Keeps the latest scan_co_attachments(), for health() and to tell changes
+    co_attachments: Mutex<CoAttachmentScanner>,
+    // Last: released once everything above has detached
+    _claim: Claim,
+}
+
+/// Everything an interval read touches. Shared with the snapshot publisher so
+/// it can read without borrowing the collector; reloads swap the maps in place
+pub(crate) struct IntervalReader {
+    signals: Arc<AtomicSignals>,
+    probes: Mutex<OptionalProbes>,
+    egress: Mutex<Option<EgressAccounting>>,
+    // None without the napi_poll probe
+    rx_budget: Mutex<Option<RxBudgetSync>>,
+    implausible: Mutex<CounterMap>,
+    tsq_throttles: Mutex<CounterMap>,
+    tcp_accepts: Mutex<CounterMap>,
+    udp_gso: Mutex<GsoCounter>,
+    softirq_discarded: Mutex<CounterMap>,
+    // Slot 0 of SEND_SAMPLE_STATE: every send the primary sampler was asked about
+    sends_seen: Mutex<CounterMap>,
+    buffered: Mutex<BufferedTracker>,
+    // Sockets sent at full rate, see CongestionCollector::trace_socket
+    tracer: Mutex<SocketTracer>,
+    txq: Mutex<TxqStalls>,
+    // Names namespace inodes, and interfaces in other namespaces
+    netns: Mutex<NetnsResolver>,
+    // None unless CollectorConfig::cgroup_aggregation is set
+    cgroups: Mutex<Option<CgroupTracker>>,
+    last_read: Mutex<Instant>,
+    // Fast snapshots' own clock, see read_fast()
+    last_fast_read: Mutex<Instant>,
+    limitation: LimitationThresholds,
+    // Loopback sends were left out in the kernel, nothing to report apart
+    exclude_loopback: bool,
+    // Sockets sampled instead of events, see CollectorConfig::with_socket_sampling
+    socket_sampling: Option<SocketSampling>,
+    // The latest INTERVAL_HISTORY reads, oldest first
+    history: Mutex<VecDeque<CongestionSignals>>,
+    sessions: Mutex<Sessions>,
+    // None with CollectorConfig::without_accuracy_check
+    accuracy: Mutex<Option<AccuracyTracker>>,
+    net_memory: Mutex<NetMemoryTracker>,
+    // The overhead budget's current level, all zero without one
+    degradation: Mutex<Degradation>,
+    // Offsets restored from the state file plus every interval read since
+    // load. Cgroups and drop reasons hold only the offsets, their live side is
+    // in the kernel map and the drop reason counters
+    totals: Mutex<CumulativeTotals>,
+    // None without CollectorConfig::with_state_file
+    state_file: Option<StateFileConfig>,
+    last_saved: Mutex<Instant>,
+    restored_from_state: bool,
+    // Interval bounds and history stamps, CollectorConfig::clock; the
+    // aligned publisher's boundaries too
+    pub(crate) clock: Arc<dyn Clock>,
+}
+
+/// Which of the probes that may legitimately be missing on a given kernel got attached
+#[derive(Debug, Clone, Copy, Default)]
+struct OptionalProbes {
+    // tracefs was found; without it the kprobes run alone
+    tracefs: bool,
+    // The core tracepoints, each group of them
+    drops: bool,
+    queue_depth: bool,
+    softirq: bool,
+    tcp_state: bool,
+    tcp_cwr: bool,
+    tcp_rto: bool,
+    tcp_recovery: bool,
+    socket_lifecycle: bool,
+    rx_squeeze: bool,
+    tsq: bool,
+    tcp_accept: bool,
+    // The GSO probe, udp_send_skb and udp_v6_send_skb
+    udp_gso: bool,
+    udp6_gso: bool,
+    // net_dev_xmit attached and able to attribute skbs to sockets
+    xmit: bool,
+    txq_stop: bool,
+    txq_wake: bool,
+    // Not a separate probe: CE is read on the rcv sample when skb offsets resolved
+    udp_ecn: bool,
+}
+
+impl OptionalProbes {
+    /// Each flag by name, for diagnostic bundles
+    fn attached(&self) -> Vec<(&'static str, bool)> {
+        vec![
+            ("tracefs", self.tracefs),
+            ("drops", self.drops),
+            ("queue_depth", self.queue_depth),
+            ("softirq", self.softirq),
+            ("tcp_state", self.tcp_state),
+            ("tcp_cwr", self.tcp_cwr),
+            ("tcp_rto", self.tcp_rto),
+            ("tcp_recovery", self.tcp_recovery),
+            ("socket_lifecycle", self.socket_lifecycle),
+            ("rx_squeeze", self.rx_squeeze),
+            ("tsq", self.tsq),
+            ("tcp_accept", self.tcp_accept),
+            ("udp_gso", self.udp_gso),
+            ("udp6_gso", self.udp6_gso),
+            ("xmit", self.xmit),
+            ("txq_stop", self.txq_stop),
+            ("txq_wake", self.txq_wake),
+            ("udp_ecn", self.udp_ecn),
+        ]
+    }
+
+    /// The flag of a program in `PROGRAM_GROUPS`, None for the ones every
+    /// load has
+    fn flag_mut(&mut self, program: &str) -> Option<&mut bool> {
+        Some(match program {
+            "skb_kfree" => &mut self.drops,
+            "net_dev_queue" => &mut self.queue_depth,
+            "softirq_entry" | "softirq_exit" => &mut self.softirq,
+            "tcp_rate_check_app_limited" => &mut self.tcp_state,
+            "tcp_enter_cwr" => &mut self.tcp_cwr,
+            "tcp_retransmit_timer" => &mut self.tcp_rto,
+            "tcp_enter_recovery" => &mut self.tcp_recovery,
+            "inet_sock_set_state" => &mut self.socket_lifecycle,
+            "napi_poll" => &mut self.rx_squeeze,
+            "tcp_tsq_handler" => &mut self.tsq,
+            "inet_csk_accept_ret" => &mut self.tcp_accept,
+            "udp_send_skb" => &mut self.udp_gso,
+            "udp_v6_send_skb" => &mut self.udp6_gso,
+            "net_dev_xmit" => &mut self.xmit,
+            "netif_tx_stop_queue" => &mut self.txq_stop,
+            "netif_tx_wake_queue" => &mut self.txq_wake,
+            _ => return None,
+        })
+    }
+}
+
+/// The programs the overhead budget may detach, with the event type each one
+/// sends (0 for counters only) so it isn't reported stale meanwhile. The core
+/// probes and the tracepoints tracefs brings are never detached
+const OPTIONAL_PROGRAMS: [(&str, ProgramAttach, u32); 13] = [
+    (
+        "tcp_rate_check_app_limited",
+        ProgramAttach::Kprobe("tcp_rate_check_app_limited"),
+        EVENT_TCP_STATE,
+    ),
+    (
+        "tcp_enter_cwr",
+        ProgramAttach::Kprobe("tcp_enter_cwr"),
+        EVENT_TCP_CWR,
+    ),
+    (
+        "tcp_retransmit_timer",
+        ProgramAttach::Kprobe("tcp_retransmit_timer"),
+        EVENT_TCP_RTO,
+    ),
+    (
+        "tcp_enter_recovery",
+        ProgramAttach::Kprobe("tcp_enter_recovery"),
+        EVENT_TCP_RECOVERY,
+    ),
+    (
+        "inet_sock_set_state",
+        ProgramAttach::Tracepoint("sock", "inet_sock_set_state"),
+        EVENT_SOCKET_LIFECYCLE,
+    ),
+    (
+        "napi_poll",
+        ProgramAttach::Tracepoint("napi", "napi_poll"),
+        EVENT_RX_TIME_SQUEEZE,
+    ),
+    (
+        "tcp_tsq_handler",
+        ProgramAttach::Kprobe("tcp_tsq_handler"),
+        0,
+    ),
+    (
+        "inet_csk_accept_ret",
+        ProgramAttach::Kprobe("inet_csk_accept"),
+        0,
+    ),
+    ("udp_send_skb", ProgramAttach::Kprobe("udp_send_skb"), 0),
+    (
+        "udp_v6_send_skb",
+        ProgramAttach::Kprobe("udp_v6_send_skb"),
+        0,
+    ),
+    (
+        "net_dev_xmit",
+        ProgramAttach::Tracepoint("net", "net_dev_xmit"),
+        0,
+    ),
+    (
+        "netif_tx_stop_queue",
+        ProgramAttach::Kprobe("netif_tx_stop_queue"),
+        0,
+    ),
+    (
+        "netif_tx_wake_queue",
+        ProgramAttach::Kprobe("netif_tx_wake_queue"),
+        0,
+    ),
+];
+
+/// The loaded object as `load_groups` sees it
+struct ObjectPrograms<'a>(&'a mut Ebpf);
+
+impl ProgramLoader for ObjectPrograms<'_> {
+    fn load(&mut self, program: &str) -> Result<(), ProgramRejection> {
+        let result = match self.0.program_mut(program) {
+            Some(Program::KProbe(prog)) => prog.load(),
+            Some(Program::TracePoint(prog)) => prog.load(),
+            Some(_) => {
+                return Err(ProgramRejection::new(format!(
+                    "{} is neither a kprobe nor a tracepoint",
+                    program
+                )))
+            }
+            None => {
+                return Err(ProgramRejection::new(format!(
+                    "program {} not in object",
+                    program
+                )))
+            }
+        };
+        result.map_err(|e| match e {
+            ProgramError::LoadError {
+                io_error,
+                verifier_log,
+            } => {
+                let log = verifier_log.to_string();
+                ProgramRejection {
+                    error: format!("rejected: {}", io_error),
+                    verifier_log: (!log.trim().is_empty()).then_some(log),
+                }
+            }
+            e => ProgramRejection::new(e.to_string()),
+        })
+    }
+
+    fn attach(&mut self, program: &str, attach: ProgramAttach) -> Result<(), ProgramRejection> {
+        let result = match (self.0.program_mut(program), attach) {
+            (Some(Program::KProbe(prog)), ProgramAttach::Kprobe(symbol)) => {
+                prog.attach(symbol, 0).map(drop)
+            }
+            (Some(Program::TracePoint(prog)), ProgramAttach::Tracepoint(category, name)) => {
+                prog.attach(category, name).map(drop)
+            }
+            _ => {
+                return Err(ProgramRejection::new(format!(
+                    "{} can't attach to {:?}",
+                    program, attach
+                )))
+            }
+        };
+        result.map_err(|e| ProgramRejection::new(e.to_string()))
+    }
+
+    fn unload(&mut self, program: &str) {
+        let result = match self.0.program_mut(program) {
+            Some(Program::KProbe(prog)) => prog.unload(),
+            Some(Program::TracePoint(prog)) => prog.unload(),
+            _ => return,
+        };
+        if let Err(e) = result {
+            log::warn!("unloading {} failed: {}", program, e);
+        }
+    }
+}
+
+impl CongestionCollector {
+    /// Load and attach eBPF probes
+    pub fn load() -> anyhow::Result<Self> {
+        Self::load_with_config(CollectorConfig::default())
+    }
+
+    /// Same as `load`, with non-default tunables
+    ///
+    /// Fails with [`CollectorError::AlreadyActive`] while another collector is
+    /// loaded in this process, or in another one holding the host-wide lock;
+    /// see `CollectorConfig::conflict_check`
+    pub fn load_with_config(config: CollectorConfig) -> anyhow::Result<Self> {
+        let claim = Claim::take(config.conflict_check)?;
+        let bytecode = bytecode();
+        let memlock = Memlock::prepare(&config.memlock);
+        // Opened ahead of the load, which hands the kernel its cookie
+        #[cfg(feature = "async-runtime")]
+        let heartbeat = config.heartbeat.as_ref().and_then(|_| match heartbeat::open_socket() {
+            Ok((socket, cookie)) => Some((Arc::new(socket), cookie)),
+            Err(e) => {
+                log::warn!("heartbeat socket: {}, running without a heartbeat", e);
+                None
+            }
+        });
+        #[cfg(feature = "async-runtime")]
+        let heartbeat_cookie = heartbeat.as_ref().map_or(0, |(_, cookie)| *cookie);
+        #[cfg(not(feature = "async-runtime"))]
+        let heartbeat_cookie = 0;
+        let (mut ebpf, probes, program_failures) =
+            Self::load_and_attach(bytecode, &config, heartbeat_cookie)
+                .map_err(|e| memlock.explain(e))?;
+        let linked_programs = linked_programs(&ebpf);
+        let (buffer_pages, memlock) = memlock.size_buffers(&ebpf, &config)?;
+        let bpf_maps = memory::bpf_maps(&ebpf, buffer_pages);
+        let egress = Self::attach_egress(&mut ebpf, &config, None)?;
+        let rx_budget = RxBudgetSync::take(&mut ebpf, &probes);
+        let implausible =
+            CounterMap::take(&mut ebpf, "IMPLAUSIBLE_SOCKET_SAMPLES", "offset sanity check");
+        let tsq_throttles =
+            CounterMap::take(&mut ebpf, "TSQ_THROTTLES", "TSQ throttle counting");
+        let tcp_accepts =
+            CounterMap::take(&mut ebpf, "TCP_ACCEPTS", "accept counting");
+        let softirq_discarded =
+            CounterMap::take(&mut ebpf, "SOFTIRQ_DISCARDED", "softirq pairing counter");
+        let sends_seen =
+            CounterMap::take(&mut ebpf, "SEND_SAMPLE_STATE", "measured send scaling");
+        let buffered = BufferedTracker::take(&mut ebpf);
+        let tracer = SocketTracer::take(&mut ebpf, config.max_traced_sockets);
+        let txq = TxqStalls::take(&mut ebpf);
+        let udp_gso = GsoCounter::take(&mut ebpf);
+        let cgroups = Self::take_cgroups(&mut ebpf, &config);
+        let overhead = config
+            .overhead_budget
+            .clone()
+            .map(|budget| OverheadEnforcer::new(budget, &mut ebpf));
+
+        // Verify kprobes are in kernel
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        Self::verify_kprobes_attached()?;
+
+        let cpus = CpuSlots::possible()
+            .map_err(|e| anyhow::anyhow!("Failed to get possible CPUs: {:?}", e))?;
+
+        let signals = Arc::new(AtomicSignals::new(cpus, &config));
+        #[cfg(feature = "async-runtime")]
+        if let (Some(monitor), Some((_, cookie))) = (&signals.heartbeat, &heartbeat) {
+            monitor.arm(*cookie);
+        }
+        let restored = config.state_file.as_ref().and_then(state::restore);
+        let interval = Arc::new(IntervalReader {
+            signals: signals.clone(),
+            probes: Mutex::new(probes),
+            egress: Mutex::new(egress),
+            rx_budget: Mutex::new(rx_budget),
+            implausible: Mutex::new(implausible),
+            tsq_throttles: Mutex::new(tsq_throttles),
+            tcp_accepts: Mutex::new(tcp_accepts),
+            udp_gso: Mutex::new(udp_gso),
+            softirq_discarded: Mutex::new(softirq_discarded),
+            sends_seen: Mutex::new(sends_seen),
+            buffered: Mutex::new(buffered),
+            tracer: Mutex::new(tracer),
+            txq: Mutex::new(txq),
+            netns: Mutex::new(NetnsResolver::new()),
+            cgroups: Mutex::new(cgroups),
+            last_read: Mutex::new(config.clock.now()),
+            last_fast_read: Mutex::new(config.clock.now()),
+            limitation: config.limitation.clone(),
+            exclude_loopback: config.exclude_loopback,
+            socket_sampling: config.socket_sampling,
+            history: Mutex::new(VecDeque::with_capacity(INTERVAL_HISTORY)),
+            sessions: Mutex::new(Sessions::default()),
+            accuracy: Mutex::new(config.accuracy.clone().map(AccuracyTracker::new)),
+            net_memory: Mutex::new(NetMemoryTracker::default()),
+            degradation: Mutex::new(Degradation::default()),
+            restored_from_state: restored.is_some(),
+            totals: Mutex::new(restored.unwrap_or_default()),
+            state_file: config.state_file.clone(),
+            last_saved: Mutex::new(config.clock.now()),
+            clock: config.clock.clone(),
+        });
+        claim.publish(&interval, config.socket_idle_ttl);
+
+        Ok(Self {
+            ebpf,
+            bytecode: bytecode.to_vec(),
+            linked_programs,
+            bpf_maps,
+            interval,
+            state: CollectorState::Loaded,
+            signals,
+            readers: None,
+            retiring: None,
+            softirq_check: Mutex::new(SoftirqCrossCheck::new(config.clock.clone())),
+            staleness_check: Mutex::new(StalenessCheck::new(config.clock.clone())),
+            sample_check: Mutex::new(SampleSanityCheck::new()),
+            drop_reasons: DropReasonNames::resolve(),
+            #[cfg(feature = "async-runtime")]
+            subscribers: broadcast::channel(config.subscriber_capacity).0,
+            #[cfg(feature = "async-runtime")]
+            publisher: Mutex::new(None),
+            #[cfg(feature = "async-runtime")]
+            fast_publisher: Mutex::new(None),
+            #[cfg(feature = "async-runtime")]
+            supervisor: Arc::new(Supervisor::new(config.restart_policies)),
+            #[cfg(feature = "async-runtime")]
+            heartbeat,
+            config,
+            pipeline: None,
+            calibration: None,
+            buffer_pages,
+            memlock,
+            program_failures,
+            wall_clock: Mutex::new(None),
+            wall_clock_skew: None,
+            overhead,
+            co_attachments: Mutex::new(CoAttachmentScanner::new()),
+            _claim: claim,
+        })
+    }
+
+    /// `load_with_config`, or with `CollectorConfig::allow_shared` and a
+    /// collector already active in this process, a [`SharedCollector`] on that
+    /// one's signals. Another process holding the host-wide lock still fails
+    pub fn load_or_share(config: CollectorConfig) -> anyhow::Result<LoadedCollector> {
+        let allow_shared = config.allow_shared;
+        match Self::load_with_config(config) {
+            Ok(collector) => Ok(LoadedCollector::Owned(Box::new(collector))),
+            Err(e)
+                if allow_shared
+                    && e.downcast_ref::<CollectorError>()
+                        == Some(&CollectorError::AlreadyActive { system_wide: false }) =>
+            {
+                conflict::active().map(LoadedCollector::Shared).ok_or(e)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Swap in a new eBPF object (new probe, fixed offset) without restarting.
+    ///
+    /// The new object is schema-checked, loaded and attached first; if collection
+    /// is running, readers are started on its EVENTS map before the old programs
+    /// are detached. Aggregated signals are kept as-is. While both objects are
+    /// attached every call is seen twice, so the switch happens at a cutover
+    /// timestamp: the old readers count what was stamped before it, and keep
+    /// draining their buffers for that until the next reload or stop, the new
+    /// ones count the rest.
+    ///
+    /// Fails with [`CollectorError::SchemaMismatch`] / [`CollectorError::MissingSchema`]
+    /// if the object wasn't built against the same shared types; the running object
+    /// is left untouched in that case.
+    #[cfg(feature = "async-runtime")]
+    pub async fn reload(&mut self, bytecode: &[u8]) -> anyhow::Result<()> {
+        self.swap_object(bytecode)
+    }
+
+    /// `reload` for `blocking` builds
+    #[cfg(feature = "blocking")]
+    pub fn reload_blocking(&mut self, bytecode: &[u8]) -> anyhow::Result<()> {
+        self.swap_object(bytecode)
+    }
+
+    /// Check that every program attached at load still has its bpf link and that
+    /// no CPU reader gave up on read errors, and reload the running object if
+    /// either went wrong. Call it periodically, say every few seconds; returns
+    /// whether it reloaded.
+    ///
+    /// It also runs `scan_co_attachments()`, unless
+    /// `CollectorConfig::without_co_attachment_scan`: an attach point ours are
+    /// gone from, such as tc egress after someone replaced the clsact qdisc,
+    /// is reloaded for too, and a change in the number of other programs
+    /// where ours are is a `Component::CoAttachment` warning on `failures()`.
+    ///
+    /// Kernels that attach kprobes without bpf links (before 5.15) leave nothing
+    /// to check programs against, there only readers and tc egress are checked
+    #[cfg(feature = "async-runtime")]
+    pub async fn verify_attachments(&mut self) -> anyhow::Result<bool> {
+        self.reattach_if_needed()
+    }
+
+    /// `verify_attachments` for `blocking` builds
+    #[cfg(feature = "blocking")]
+    pub fn verify_attachments_blocking(&mut self) -> anyhow::Result<bool> {
+        self.reattach_if_needed()
+    }
+
+    fn reattach_if_needed(&mut self) -> anyhow::Result<bool> {
+        let linked = linked_programs(&self.ebpf);
+        let detached: Vec<&String> = self
+            .linked_programs
+            .iter()
+            .filter(|name| !linked.contains(name))
+            .collect();
+        for name in &detached {
+            log::warn!("program {} is no longer attached", name);
+        }
+        let failed_readers = self.pipeline.as_ref().map_or(0, |pipeline| {
+            pipeline.counters.failed_readers.lock().unwrap().len()
+        });
+        if failed_readers > 0 {
+            log::warn!("{} perf readers gave up", failed_readers);
+        }
+        let mut missing = Vec::new();
+        if self.config.co_attachment_scan {
+            let scan = self.scan_co_attachments();
+            if let Some(warning) = scan.change_warning() {
+                self.warn_co_attachment(warning);
+            }
+            missing = scan.missing;
+        }
+        for point in &missing {
+            log::warn!("nothing of ours attached at {} any more", point);
+            if let AttachPoint::TcEgress(interface) = point {
+                if let Some(egress) = self.interval.egress.lock().unwrap().as_mut() {
+                    egress.lost(interface);
+                }
+            }
+        }
+        if detached.is_empty() && failed_readers == 0 && missing.is_empty() {
+            return Ok(false);
+        }
+
+        log::info!("re-attaching: reloading the running eBPF object");
+        let bytecode = self.bytecode.clone();
+        self.swap_object(&bytecode)?;
+        Ok(true)
+    }
+
+    /// Ask the kernel what else is attached where our programs are, and
+    /// whether ours still are, see `CoAttachmentScan`. Kept for `health()`.
+    /// Walks every process's descriptors in /proc, to name the programs'
+    /// owners
+    pub fn scan_co_attachments(&self) -> CoAttachmentScan {
+        let mut ours: Vec<(AttachPoint, u32)> = Vec::new();
+        let program_id = |name: &str| {
+            self.ebpf
+                .program(name)
+                .and_then(|program| program.info().ok())
+                .map(|info| info.id())
+        };
+        for name in &self.linked_programs {
+            let attach = PROGRAM_GROUPS
+                .iter()
+                .flat_map(|group| group.programs)
+                .find(|(program, _)| program == name)
+                .map(|(_, attach)| *attach);
+            if let (Some(attach), Some(id)) = (attach, program_id(name)) {
+                ours.push((AttachPoint::Program(attach), id));
+            }
+        }
+        if let (Some(egress), Some(id)) = (
+            self.interval.egress.lock().unwrap().as_ref(),
+            program_id("tc_egress"),
+        ) {
+            ours.extend(
+                egress
+                    .interfaces()
+                    .map(|interface| (AttachPoint::TcEgress(interface.to_string()), id)),
+            );
+        }
+        let mut kernel =
+            KernelAttachments::new(preflight::kernel_version(), health::tracefs_root());
+        self.co_attachments
+            .lock()
+            .unwrap()
+            .scan(&mut kernel, &ours)
+            .clone()
+    }
+
+    #[cfg(feature = "async-runtime")]
+    fn warn_co_attachment(&self, warning: String) {
+        self.supervisor.reporter().warn(ComponentFailure {
+            component: Component::CoAttachment,
+            error: warning,
+            panicked: false,
+            restarted: false,
+        });
+    }
+
+    // Blocking builds have no failures() to send it on
+    #[cfg(not(feature = "async-runtime"))]
+    fn warn_co_attachment(&self, warning: String) {
+        log::warn!("co-attached programs: {}", warning);
+    }
+
+    fn swap_object(&mut self, bytecode: &[u8]) -> anyhow::Result<()> {
+        let (mut ebpf, probes, program_failures) =
+            Self::load_and_attach(bytecode, &self.config, self.heartbeat_cookie())?;
+        let linked = linked_programs(&ebpf);
+        let bpf_maps = memory::bpf_maps(&ebpf, self.buffer_pages);
+        let egress = {
+            let mut current = self.interval.egress.lock().unwrap();
+            Self::attach_egress(&mut ebpf, &self.config, current.as_mut())?
+        };
+        let rx_budget = RxBudgetSync::take(&mut ebpf, &probes);
+        let implausible =
+            CounterMap::take(&mut ebpf, "IMPLAUSIBLE_SOCKET_SAMPLES", "offset sanity check");
+        let tsq_throttles =
+            CounterMap::take(&mut ebpf, "TSQ_THROTTLES", "TSQ throttle counting");
+        let tcp_accepts =
+            CounterMap::take(&mut ebpf, "TCP_ACCEPTS", "accept counting");
+        let softirq_discarded =
+            CounterMap::take(&mut ebpf, "SOFTIRQ_DISCARDED", "softirq pairing counter");
+        let sends_seen =
+            CounterMap::take(&mut ebpf, "SEND_SAMPLE_STATE", "measured send scaling");
+        // Held until the commit below, so no registration or trace lands on
+        // the old trackers after they were carried over
+        let mut buffered = self.interval.buffered.lock().unwrap();
+        let next_buffered = buffered.carried_over(BufferedTracker::take(&mut ebpf))?;
+        let mut tracer = self.interval.tracer.lock().unwrap();
+        let next_tracer = tracer.carried_over(SocketTracer::take(
+            &mut ebpf,
+            self.config.max_traced_sockets,
+        ))?;
+        let txq = TxqStalls::take(&mut ebpf);
+        let udp_gso = GsoCounter::take(&mut ebpf);
+        let cgroups = Self::take_cgroups(&mut ebpf, &self.config);
+
+        if let Some(pipeline) = &self.pipeline {
+            // Readers of the same kind as the ones they replace, the pipeline decides
+            let readers = Readers::spawn(
+                &mut ebpf,
+                &self.signals,
+                pipeline,
+                self.buffer_pages,
+                ReadWindow::pending(),
+            )?;
+            // Far enough ahead that no reader has seen an event stamped past
+            // it before both windows are set
+            let cutover = health::monotonic_now_ns().unwrap_or(0) + RELOAD_CUTOVER_LEAD_NS;
+            readers.window().open_at(cutover);
+            if let Some(old) = &self.readers {
+                old.window().close_at(cutover);
+            }
+            self.retiring = self.readers.replace(readers);
+        }
+
+        // Nothing fails from here on
+        *buffered = next_buffered;
+        drop(buffered);
+        *tracer = next_tracer;
+        drop(tracer);
+        // Dropping the old Ebpf detaches and unloads its programs
+        drop(std::mem::replace(&mut self.ebpf, ebpf));
+        self.bytecode = bytecode.to_vec();
+        self.linked_programs = linked;
+        self.bpf_maps = bpf_maps;
+        let interval = &self.interval;
+        *interval.egress.lock().unwrap() = egress;
+        *interval.rx_budget.lock().unwrap() = rx_budget;
+        *interval.implausible.lock().unwrap() = implausible;
+        *interval.tsq_throttles.lock().unwrap() = tsq_throttles;
+        *interval.tcp_accepts.lock().unwrap() = tcp_accepts;
+        *interval.udp_gso.lock().unwrap() = udp_gso;
+        *interval.softirq_discarded.lock().unwrap() = softirq_discarded;
+        *interval.sends_seen.lock().unwrap() = sends_seen;
+        *interval.txq.lock().unwrap() = txq;
+        *interval.cgroups.lock().unwrap() = cgroups;
+        *interval.probes.lock().unwrap() = probes;
+        self.program_failures = program_failures;
+        self.reapply_degradation();
+        log::info!("eBPF object reloaded, previous programs detached");
+
+        Ok(())
+    }
+
+    /// Check whether `load_with_config(config)` would work on this host, without
+    /// attaching anything: the object is loaded with the same settings and each
+    /// program verified, then all of it unloaded. Doesn't conflict with a
+    /// collector already running. Needs the same privileges as a load to say
+    /// more than that they're missing.
+    pub fn preflight_with_config(config: &CollectorConfig) -> PreflightReport {
+        let host = PreflightHost::current();
+        let mut probes = preflight::probes(config, &host);
+        let loaded = Self::load_object(bytecode(), config, 0).map(|(mut ebpf, _)| {
+            preflight::verify(&mut ebpf, &mut probes);
+            memory::bpf_maps(&ebpf, reader::wanted_buffer_pages(config))
+                .iter()
+                .map(|map| map.estimated_bytes)
+                .sum::<u64>()
+            // Dropped here: maps closed, programs unloaded
+        });
+        match loaded {
+            Ok(bytes) => PreflightReport::assess(host, probes, None, Some(bytes)),
+            Err(e) => PreflightReport::assess(host, probes, Some(format!("{:#}", e)), None),
+        }
+    }
+
+    /// `preflight_with_config` for the default config
+    pub fn preflight() -> PreflightReport {
+        Self::preflight_with_config(&CollectorConfig::default())
+    }
+
+    /// Schema-check and load the object with `config`'s globals, and
+    /// `heartbeat` as the heartbeat socket's cookie (0 for none), attaching
+    /// nothing
+    fn load_object(
+        bytecode: &[u8],
+        config: &CollectorConfig,
+        heartbeat: u64,
+    ) -> anyhow::Result<(Ebpf, KernelOffsets)> {
+        SchemaDescriptor::from_object(bytecode)?.check_compatibility(config.forward_compatible)?;
+
+        let offsets = btf::resolve_offsets();
+        let exclude_loopback = config.exclude_loopback as u32;
+        let track_flows = config.track_flows as u32;
+        let aggregate_cgroups = config.cgroup_aggregation.is_some() as u32;
+        // The comparison is against the 1-in-100 sampler, which per-socket
+        // sampling replaces
+        let compare_ratio = match config.socket_sampling {
+            Some(_) => 0,
+            None => config.sampler_comparison.unwrap_or(0),
+        };
+        let socket_threshold = config
+            .socket_sampling
+            .map_or(0, |sampling| sampling.threshold());
+        // A zero window sends every drop alone
+        let (coalesce_ns, coalesce_max) = config.drop_coalescing.map_or((0, 0), |coalescing| {
+            (
+                coalescing.max_span.as_nanos().clamp(1, u64::MAX as u128) as u64,
+                coalescing.max_drops.max(1),
+            )
+        });
+        let napi_sample_ns = config.softirq_breakdown.as_ref().map_or(0, |breakdown| {
+            breakdown.long_softirq.as_nanos().clamp(1, u64::MAX as u128) as u64
+        });
+        let mut loader = EbpfLoader::new();
+        loader
+            .set_global("KERNEL_OFFSETS", &offsets, true)
+            .set_global("EXCLUDE_LOOPBACK", &exclude_loopback, true)
+            .set_global("TRACK_FLOWS", &track_flows, true)
+            .set_global("AGGREGATE_CGROUPS", &aggregate_cgroups, true)
+            .set_global("DROP_COALESCE_NS", &coalesce_ns, true)
+            .set_global("DROP_COALESCE_MAX", &coalesce_max, true)
+            .set_global("COMPARE_SAMPLE_RATIO", &compare_ratio, true)
+            .set_global("SOCKET_SAMPLE_THRESHOLD", &socket_threshold, true)
+            .set_global("NAPI_SAMPLE_NS", &napi_sample_ns, true)
+            .set_global("HEARTBEAT_SOCKET", &heartbeat, true);
+        if let Some(cgroups) = &config.cgroup_aggregation {
+            loader.set_max_entries("CGROUP_SIGNALS", cgroups.max_cgroups);
+        }
+        let ebpf = loader.load(bytecode)?;
+        Ok((ebpf, offsets))
+    }
+
+    fn load_and_attach(
+        bytecode: &[u8],
+        config: &CollectorConfig,
+        heartbeat: u64,
+    ) -> anyhow::Result<(Ebpf, OptionalProbes, Vec<ProgramLoadFailure>)> {
+        let (mut ebpf, offsets) = Self::load_object(bytecode, config, heartbeat)?;
+        log::info!("eBPF bytecode loaded successfully");
+
+        // Without tracefs none of the tracepoints can attach, and the kprobes
+        // still work: run degraded rather than not at all
+        let tracefs = health::tracefs_root().is_some();
+        if !tracefs {
+            log::warn!(
+                "tracefs not mounted, running on kprobes only: drops, queue depth and softirq time unavailable"
+            );
+        }
+        let host = GroupHost::new(preflight::kernel_version(), tracefs, &offsets);
+        let load = load_groups(
+            &mut ObjectPrograms(&mut ebpf),
+            &PROGRAM_GROUPS,
+            &host,
+            config.strict,
+        )?;
+        let mut probes = OptionalProbes {
+            tracefs,
+            ..Default::default()
+        };
+        for program in &load.attached {
+            if let Some(flag) = probes.flag_mut(program) {
+                *flag = true;
+            }
+        }
+        // Attached for the per-device counts too, sockets drain only with skb->sk
+        probes.xmit &= offsets.skb_sk != OFFSET_UNKNOWN;
+        if offsets.skb_sk == OFFSET_UNKNOWN {
+            log::warn!("sk_buff.sk not found in kernel BTF, registered sockets never drain");
+        }
+        if offsets.sk_cookie == OFFSET_UNKNOWN {
+            log::warn!("sock.skc_cookie not found in kernel BTF, socket ids are addresses and may alias");
+        }
+        probes.udp_ecn =
+            offsets.skb_head != OFFSET_UNKNOWN && offsets.skb_network_header != OFFSET_UNKNOWN;
+        if !probes.udp_ecn {
+            log::warn!("sk_buff offsets not found in kernel BTF, UDP CE mark counting disabled");
+        }
+
+        Ok((ebpf, probes, load.failures))
+    }
+
+    /// Attach a kprobe the core signals don't depend on. Failure (symbol missing,
+    /// verifier rejection) is logged and reported back rather than aborting the load.
+    fn attach_optional_kprobe(ebpf: &mut Ebpf, program: &str, symbol: &str) -> bool {
+        let result = (|| -> anyhow::Result<()> {
+            let prog: &mut KProbe = ebpf
+                .program_mut(program)
+                .ok_or_else(|| anyhow::anyhow!("program {} not in object", program))?
+                .try_into()?;
+            prog.load()?;
+            prog.attach(symbol, 0)?;
+            Ok(())
+        })();
+
+        match result {
+            Ok(()) => {
+                log::info!("optional kprobe:{} attached", symbol);
+                true
+            }
+            Err(e) => {
+                log::warn!("optional kprobe:{} not attached: {}", symbol, e);
+                false
+            }
+        }
+    }
+
+    fn attach_egress(
+        ebpf: &mut Ebpf,
+        config: &CollectorConfig,
+        previous: Option<&mut EgressAccounting>,
+    ) -> anyhow::Result<Option<EgressAccounting>> {
+        if config.egress_interfaces.is_empty() {
+            return Ok(None);
+        }
+        EgressAccounting::attach(ebpf, &config.egress_interfaces, previous).map(Some)
+    }
+
+    fn take_cgroups(ebpf: &mut Ebpf, config: &CollectorConfig) -> Option<CgroupTracker> {
+        let cgroups = config.cgroup_aggregation.as_ref()?;
+        Some(CgroupTracker::take(
+            ebpf,
+            cgroups,
+            config.exclude_loopback,
+            config.clock.clone(),
+        ))
+    }
+
+    /// Tracepoint counterpart of `attach_optional_kprobe`
+    fn attach_optional_tracepoint(
+        ebpf: &mut Ebpf,
+        program: &str,
+        category: &str,
+        name: &str,
+    ) -> bool {
+        let result = (|| -> anyhow::Result<()> {
+            let prog: &mut TracePoint = ebpf
+                .program_mut(program)
+                .ok_or_else(|| anyhow::anyhow!("program {} not in object", program))?
+                .try_into()?;
+            prog.load()?;
+            prog.attach(category, name)?;
+            Ok(())
+        })();
+
+        match result {
+            Ok(()) => {
+                log::info!("optional tracepoint:{}:{} attached", category, name);
+                true
+            }
+            Err(e) => {
+                log::warn!("optional tracepoint:{}:{} not attached: {}", category, name, e);
+                false
+            }
+        }
+    }
+
+    fn verify_kprobes_attached() -> anyhow::Result<()> {
+        use std::fs;
+
+        // Perf-based kprobes don't need it, there's just nothing to verify against
+        let Some(tracefs) = health::tracefs_root() else {
+            return Ok(());
+        };
+        let kprobe_events =
+            fs::read_to_string(tracefs.join("kprobe_events")).unwrap_or_default();
+
+        log::info!("Verifying kprobe attachment...");
+        log::info!("kprobe_events content:\n{}", kprobe_events);
+
+        if kprobe_events.contains("udp_sendmsg") {
+            log::info!("udp_sendmsg found in kprobe_events");
+        } else {
+            log::warn!("udp_sendmsg NOT found in kprobe_events");
+        }
+
+        Ok(())
+    }
+    pub fn state(&self) -> CollectorState {
+        self.state
+    }
+
+    /// Hand extension events of `event_types`, a range within
+    /// EXTENSION_EVENT_TYPES, to `handler`, its snapshots going into
+    /// `CongestionSignals::extensions` under `name`. Only before collection
+    /// starts; see the extensions module
+    pub fn register_extension<H: CustomEventHandler>(
+        &mut self,
+        name: &str,
+        event_types: std::ops::Range<u32>,
+        handler: H,
+    ) -> Result<(), CollectorError> {
+        self.state.next(Transition::Configure)?;
+        self.signals
+            .extensions
+            .lock()
+            .unwrap()
+            .register(name, event_types.clone(), handler)?;
+        self.signals.extension_types.insert(event_types);
+        Ok(())
+    }
+
+    /// Pin the EVENTS perf array as `dir`/EVENTS, for an extension's program
+    /// to output into: one declaring `PerfEventArray::pinned` by that name,
+    /// loaded with `EbpfLoader::map_pin_path(dir)`. Only before collection
+    /// starts, the readers take the map. A reload makes a new one, unpinned
+    pub fn pin_event_map(&self, dir: impl AsRef<Path>) -> anyhow::Result<PathBuf> {
+        self.state.next(Transition::Configure)?;
+        let path = dir.as_ref().join("EVENTS");
+        let map = self
+            .ebpf
+            .map("EVENTS")
+            .ok_or_else(|| anyhow::anyhow!("EVENTS map not found"))?;
+        map.pin(&path)?;
+        Ok(path)
+    }
+
+    /// Start collecting events in background tasks. Only valid once, from `Loaded`
+    #[cfg(feature = "async-runtime")]
+    pub async fn start_collection(&mut self) -> anyhow::Result<()> {
+        self.state.next(Transition::Start)?;
+        let pipeline = Pipeline::start(
+            &self.config,
+            self.signals.clone(),
+            self.subscribers.clone(),
+            self.supervisor.clone(),
+        );
+        self.start_readers(pipeline)?;
+        self.start_heartbeat();
+        if let Some(config) = self.config.calibration.clone() {
+            let signals = self.signals.clone();
+            let outcome =
+                Rt::spawn_blocking(move || calibration::run(&signals.calibration, &config)).await?;
+            self.record_calibration(outcome);
+            self.check_wall_clock();
+        }
+        Ok(())
+    }
+
+    /// Start collection unless it's already running. Still an error once stopped
+    #[cfg(feature = "async-runtime")]
+    pub async fn ensure_collecting(&mut self) -> anyhow::Result<()> {
+        self.state.next(Transition::EnsureCollecting)?;
+        if self.state == CollectorState::Loaded {
+            self.start_collection().await?;
+        }
+        Ok(())
+    }
+
+    /// `start_collection` on plain threads: one reader per CPU and a processing
+    /// thread, no async runtime needed. There are no event subscribers in this mode
+    #[cfg(feature = "blocking")]
+    pub fn start_collection_blocking(&mut self) -> anyhow::Result<()> {
+        self.state.next(Transition::Start)?;
+        let pipeline = Pipeline::start_blocking(&self.config, self.signals.clone())?;
+        self.start_readers(pipeline)?;
+        if let Some(config) = &self.config.calibration {
+            let outcome = calibration::run(&self.signals.calibration, config);
+            self.record_calibration(outcome);
+            self.check_wall_clock();
+        }
+        Ok(())
+    }
+
+    /// `ensure_collecting` for `start_collection_blocking`
+    #[cfg(feature = "blocking")]
+    pub fn ensure_collecting_blocking(&mut self) -> anyhow::Result<()> {
+        self.state.next(Transition::EnsureCollecting)?;
+        if self.state == CollectorState::Loaded {
+            self.start_collection_blocking()?;
+        }
+        Ok(())
+    }
+
+    /// The heartbeat's task, with a socket and `CollectorConfig::heartbeat`
+    #[cfg(feature = "async-runtime")]
+    fn start_heartbeat(&self) {
+        let (Some((socket, _)), Some(monitor), Some(config)) = (
+            &self.heartbeat,
+            &self.signals.heartbeat,
+            &self.config.heartbeat,
+        ) else {
+            return;
+        };
+        let (socket, monitor, config) = (socket.clone(), monitor.clone(), config.clone());
+        let reporter = self.supervisor.reporter();
+        self.supervisor.spawn(Component::Heartbeat, move || {
+            heartbeat::run(
+                socket.clone(),
+                monitor.clone(),
+                config.clone(),
+                reporter.clone(),
+            )
+        });
+    }
+
+    /// Cookie of the heartbeat socket, 0 without one
+    fn heartbeat_cookie(&self) -> u64 {
+        #[cfg(feature = "async-runtime")]
+        if let Some((_, cookie)) = &self.heartbeat {
+            return *cookie;
+        }
+        0
+    }
+
+    fn start_readers(&mut self, pipeline: Pipeline) -> anyhow::Result<()> {
+        self.readers = Some(Readers::spawn(
+            &mut self.ebpf,
+            &self.signals,
+            &pipeline,
+            self.buffer_pages,
+            ReadWindow::open(),
+        )?);
+        self.pipeline = Some(pipeline);
+        self.state = CollectorState::Collecting;
+        log::info!("Event collection started on all CPUs");
+        Ok(())
+    }
+
+    fn record_calibration(&mut self, outcome: CalibrationOutcome) {
+        match &outcome {
+            CalibrationOutcome::Passed { .. } => log::info!("socket-state calibration passed"),
+            CalibrationOutcome::Failed(e) => log::warn!("socket-state calibration skipped: {}", e),
+            _ => log::warn!("socket-state calibration: {:?}", outcome),
+        }
+        let disable = outcome.is_mismatch()
+            && self
+                .config
+                .calibration
+                .as_ref()
+                .is_some_and(|config| config.disable_on_mismatch);
+        if disable {
+            self.signals
+                .socket_state_disabled
+                .store(true, Ordering::Relaxed);
+        }
+        self.calibration = Some(outcome);
+    }
+
+    /// Convert the calibration's first event and compare with when it was seen,
+    /// so a time namespace the offsets don't account for shows at start
+    fn check_wall_clock(&mut self) {
+        let Some((timestamp_ns, seen_at)) = self.signals.calibration.first_seen() else {
+            return;
+        };
+        let Some(clock) = self.refresh_wall_clock(true) else {
+            return;
+        };
+        let skew = clock.skew(timestamp_ns, seen_at);
+        if skew > WALL_CLOCK_TOLERANCE {
+            log::warn!(
+                "wall-clock self-check: an event converted {:.3}s from when it was seen \
+                 (time namespace offsets {:?})",
+                skew.as_secs_f64(),
+                clock.offsets()
+            );
+        }
+        self.wall_clock_skew = Some((skew, clock.offsets()));
+    }
+
+    /// The anchor, read again when due or `force`d. A realtime step since the
+    /// last one (NTP, settimeofday) past WALL_CLOCK_TOLERANCE is logged
+    fn refresh_wall_clock(&self, force: bool) -> Option<WallClock> {
+        let mut current = self.wall_clock.lock().unwrap();
+        if let Some(clock) = *current {
+            if !force && !clock.is_due() {
+                return Some(clock);
+            }
+        }
+        let Some(clock) = WallClock::now() else {
+            return *current;
+        };
+        if let Some(earlier) = *current {
+            let step_ns = clock.step_from(&earlier);
+            if step_ns.unsigned_abs() > WALL_CLOCK_TOLERANCE.as_nanos() as u64 {
+                log::warn!("wall clock stepped {:.3}s", step_ns as f64 / 1e9);
+            }
+            if clock.offsets() != earlier.offsets() {
+                log::info!("time namespace offsets now {:?}", clock.offsets());
+            }
+        }
+        *current = Some(clock);
+        Some(clock)
+    }
+
+    /// An event's `timestamp_ns` (the host's CLOCK_MONOTONIC, whatever time
+    /// namespace we're in) as wall-clock time. The anchor is read again every
+    /// WALL_CLOCK_REFRESH, so NTP steps and namespace offsets are followed.
+    /// None only when the clocks can't be read
+    pub fn to_wallclock(&self, timestamp_ns: u64) -> Option<SystemTime> {
+        self.refresh_wall_clock(false)
+            .map(|clock| clock.to_wallclock(timestamp_ns))
+    }
+
+    /// Stop the readers and the processing task. Probes stay attached until the
+    /// collector is dropped; aggregated signals can still be read
+    pub fn stop_collection(&mut self) -> Result<(), CollectorError> {
+        let stopped = self.state.next(Transition::Stop)?;
+        self.stop_readers();
+        self.interval.tracer.lock().unwrap().stop_all();
+        // Whatever a capture in progress and the traces have so far
+        if let Some(capture) = &self.signals.capture {
+            let finished = capture.lock().unwrap().finish();
+            if let Some(finished) = finished {
+                write_capture(capture, finished);
+            }
+            let traces = capture.lock().unwrap().finish_traces();
+            for finished in traces {
+                write_capture(capture, finished);
+            }
+        }
+        self.state = stopped;
+        log::info!("Event collection stopped");
+        Ok(())
+    }
+
+    /// Load, collect for `duration`, return that one interval and unload again,
+    /// probes detached before it returns. For scripts and one-off debugging;
+    /// calls one after another each get a fresh object
+    #[cfg(feature = "async-runtime")]
+    pub async fn sample(duration: Duration) -> anyhow::Result<CongestionSignals> {
+        let mut collector = Self::load()?;
+        collector.start_collection().await?;
+        // Whatever arrived while the readers started
+        collector.read_and_reset();
+        Rt::sleep(duration).await;
+        let signals = collector.read_and_reset();
+        collector.stop_collection()?;
+        Ok(signals)
+    }
+
+    /// `sample` on plain threads
+    #[cfg(feature = "blocking")]
+    pub fn sample_blocking(duration: Duration) -> anyhow::Result<CongestionSignals> {
+        let mut collector = Self::load()?;
+        collector.start_collection_blocking()?;
+        collector.read_and_reset();
+        std::thread::sleep(duration);
+        let signals = collector.read_and_reset();
+        collector.stop_collection()?;
+        Ok(signals)
+    }
+
+    fn stop_readers(&mut self) {
+        // Readers first, a blocking pipeline only winds down once they're gone
+        self.readers = None;
+        self.retiring = None;
+        self.pipeline = None;
+        #[cfg(feature = "async-runtime")]
+        {
+            // Ends the snapshot streams
+            *self.publisher.lock().unwrap() = None;
+            *self.fast_publisher.lock().unwrap() = None;
+            self.supervisor.shutdown();
+        }
+    }
+
+    /// Get current aggregated signals and reset counters
+    pub fn read_and_reset(&self) -> CongestionSignals {
+        self.read_and_reset_per_cpu().0
+    }
+
+    /// Same as `read_and_reset`, plus the per-CPU breakdown of the interval
+    pub fn read_and_reset_per_cpu(&self) -> (CongestionSignals, PerCpuSignals) {
+        self.interval.read_and_reset_per_cpu()
+    }
+
+    /// The fast counters since the previous fast read, for a caller running
+    /// its own fast timer instead of `fast_snapshots()`; calling both splits
+    /// the windows between them. Leaves the interval read's counters alone
+    pub fn read_fast(&self) -> FastSignals {
+        self.interval.read_fast()
+    }
+
+    /// Start a labelled measurement session. Every interval read from now on,
+    /// by `read_and_reset()` or the snapshot publisher, is merged into it and
+    /// carries the label in `CongestionSignals::sessions`, until
+    /// `SessionHandle::end()` returns the [`SessionReport`]. Sessions may
+    /// overlap and nest, and span more than the interval history holds.
+    /// Nothing is merged while nobody reads
+    pub fn begin_session(&self, label: &str) -> SessionHandle {
+        SessionHandle::begin(self.interval.clone(), label)
+    }
+
+    /// Reports of sessions whose handle was dropped instead of ended, oldest
+    /// first, at most the last 64. Each is also logged at info level
+    pub fn take_session_reports(&self) -> Vec<SessionReport> {
+        self.interval.sessions.lock().unwrap().take_ended()
+    }
+
+    /// Entries and estimated bytes of each userspace table and queue, with its
+    /// cap and how often the cap evicted, plus the running object's BPF map
+    /// sizes. Caps are set in `CollectorConfig`
+    pub fn memory_report(&self) -> MemoryReport {
+        let signals = &self.signals;
+        let mut structures = vec![signals.sockets.memory()];
+        {
+            let below = signals.tcp_below_ssthresh.lock().unwrap();
+            structures.push(StructureMemory {
+                name: "interval TCP state",
+                entries: below.len(),
+                cap: signals.max_tracked_sockets,
+                estimated_bytes: hash_table_bytes::<u64, bool>(below.capacity()),
+                evictions: signals.tcp_state_evictions.load(Ordering::Relaxed),
+            });
+        }
+        structures.push(signals.active_sockets.lock().unwrap().memory());
+        if let Some(flows) = &signals.active_flows {
+            structures.push(flows.lock().unwrap().memory());
+        }
+        if let Some(burst) = &signals.burst {
+            structures.push(burst.lock().unwrap().memory());
+        }
+        structures.push(self.interval.buffered.lock().unwrap().memory());
+        if let Some(cgroups) = &*self.interval.cgroups.lock().unwrap() {
+            structures.push(cgroups.memory());
+        }
+        if let Some(netns) = &signals.netns {
+            structures.push(netns.lock().unwrap().memory());
+        }
+        if let Some(classes) = &signals.classes {
+            structures.push(classes.lock().unwrap().memory());
+        }
+        {
+            let history = self.interval.history.lock().unwrap();
+            structures.push(StructureMemory {
+                name: "interval history",
+                entries: history.len(),
+                cap: INTERVAL_HISTORY,
+                estimated_bytes: entry_bytes::<CongestionSignals, ()>(history.len()),
+                evictions: 0,
+            });
+        }
+
+        let stats = self.reader_stats();
+        structures.push(StructureMemory {
+            name: "event queue",
+            entries: stats.queue_depth,
+            cap: stats.queue_capacity,
+            estimated_bytes: entry_bytes::<CongestionEvent, ()>(stats.queue_depth),
+            evictions: stats.queue_dropped,
+        });
+        if let (Some(reordering), Some(config)) = (stats.reordering, self.config.reordering()) {
+            structures.push(StructureMemory {
+                name: "reorder buffer",
+                entries: reordering.held,
+                cap: config.max_buffered,
+                estimated_bytes: entry_bytes::<CongestionEvent, (u64, Instant)>(reordering.held),
+                evictions: reordering.overflowed,
+            });
+        }
+        #[cfg(feature = "async-runtime")]
+        {
+            // The broadcast ring is allocated in full up front
+            let slots = self.config.subscriber_capacity.next_power_of_two();
+            structures.push(StructureMemory {
+                name: "subscriber backlog",
+                entries: self.subscribers.len(),
+                cap: slots,
+                estimated_bytes: entry_bytes::<CongestionEvent, ()>(slots),
+                evictions: 0,
+            });
+            if let Some(recent) = self.pipeline.as_ref().and_then(Pipeline::recent_events) {
+                structures.push(StructureMemory {
+                    name: "recent events",
+                    entries: recent.len(),
+                    cap: RECENT_EVENTS,
+                    estimated_bytes: entry_bytes::<CongestionEvent, ()>(RECENT_EVENTS),
+                    evictions: 0,
+                });
+            }
+        }
+
+        MemoryReport {
+            structures,
+            bpf_maps: self.bpf_maps.clone(),
+            socket_shards: signals.sockets.shards(),
+        }
+    }
+
+    /// Queue depth and drop counts of the readers -> processing task hand-off.
+    /// All zero until `start_collection()`
+    pub fn reader_stats(&self) -> ReaderStats {
+        let mut stats = match &self.pipeline {
+            Some(pipeline) => pipeline.stats(),
+            None => ReaderStats {
+                queue_capacity: self.config.event_queue_capacity,
+                ..Default::default()
+            },
+        };
+        stats.netns_filtered = self
+            .signals
+            .netns_filter
+            .as_ref()
+            .map_or(0, NetnsFilter::filtered);
+        stats
+    }
+
+    /// Raw events after aggregation. Subscribers that fall behind by more than
+    /// `subscriber_capacity` get `RecvError::Lagged` and skip ahead; they never
+    /// slow down the readers. A tokio::sync receiver, which works under smol
+    /// too; `event_stream()` is the same without tokio's types
+    #[cfg(feature = "async-runtime")]
+    pub fn subscribe_events(&self) -> broadcast::Receiver<CongestionEvent> {
+        self.subscribers.subscribe()
+    }
+
+    /// `subscribe_events()` as a Stream, for any runtime. One that falls behind
+    /// skips ahead without saying so; `subscribe_events()` reports how far
+    #[cfg(feature = "async-runtime")]
+    pub fn event_stream(&self) -> impl Stream<Item = CongestionEvent> + Send + 'static {
+        publisher::broadcast_stream(self.subscribers.subscribe())
+    }
+
+    /// Panics and errors of the background tasks (per-CPU readers, the
+    /// processing task, the snapshot publisher), each naming the component and
+    /// whether it was restarted under `CollectorConfig::restart_policies`. Only
+    /// failures after subscribing are received; `health().component_failures`
+    /// counts all of them
+    #[cfg(feature = "async-runtime")]
+    pub fn failures(&self) -> broadcast::Receiver<ComponentFailure> {
+        self.supervisor.subscribe()
+    }
+
+    /// `failures()` as a Stream, like `event_stream()`
+    #[cfg(feature = "async-runtime")]
+    pub fn failure_stream(&self) -> impl Stream<Item = ComponentFailure> + Send + 'static {
+        publisher::broadcast_stream(self.supervisor.subscribe())
+    }
+
+    /// One `CongestionSignals` per `interval`, for `while let Some(signals) =
+    /// stream.next().await`. Ends when collection stops (immediately if it
+    /// already has); dropping the stream unsubscribes. Needs the runtime
+    /// the crate was built for: tokio with `async`, smol with `smol`.
+    ///
+    /// All streams share one publisher that does the `read_and_reset()`, so any
+    /// number can run side by side without splitting intervals between them. They
+    /// tick together at the `interval` of the stream that started the publisher;
+    /// a later call asking for a different one gets the running cadence and a
+    /// warning. Don't also call `read_and_reset()` yourself while streams are live,
+    /// that takes the data from under them. A stream that isn't polled for a
+    /// whole interval skips ahead to the latest snapshot.
+    ///
+    /// With `CollectorConfig::align_to_wall_clock` the ticks fall on wall-clock
+    /// multiples of `interval` and every snapshot carries `aligned_start` and
+    /// `aligned_end`; the first one arrives a boundary later than unaligned.
+    #[cfg(feature = "async-runtime")]
+    pub fn snapshots(
+        &self,
+        interval: Duration,
+    ) -> impl Stream<Item = CongestionSignals> + Send + 'static {
+        let rx = (self.state != CollectorState::Stopped).then(|| {
+            let mut publisher = self.publisher.lock().unwrap();
+            let publisher = publisher.get_or_insert_with(|| {
+                Publisher::start(
+                    self.interval.clone(),
+                    interval,
+                    self.config.align_to_wall_clock,
+                    &self.supervisor,
+                )
+            });
+            if publisher.interval() != interval {
+                log::warn!(
+                    "snapshots({:?}) joins the running {:?} publisher",
+                    interval,
+                    publisher.interval()
+                );
+            }
+            publisher.subscribe()
+        });
+        publisher::stream(rx)
+    }
+
+    /// A [`FastSignals`] every `interval`, clamped to `FAST_INTERVAL_MIN` to
+    /// `FAST_INTERVAL_MAX`: send bytes, drops, TX queue stalls and the fullest
+    /// send buffer, for cutting a rate without waiting out a whole interval.
+    /// Ends when collection stops; needs the runtime, as `snapshots()` does.
+    ///
+    /// Counted apart from the interval read, so it runs next to `snapshots()`
+    /// or `read_and_reset()` and neither resets the other: both see every send
+    /// and drop. Streams share one publisher like `snapshots()` streams do
+    #[cfg(feature = "async-runtime")]
+    pub fn fast_snapshots(
+        &self,
+        interval: Duration,
+    ) -> impl Stream<Item = FastSignals> + Send + 'static {
+        let clamped = interval.clamp(crate::FAST_INTERVAL_MIN, crate::FAST_INTERVAL_MAX);
+        if clamped != interval {
+            log::warn!("fast_snapshots({:?}) runs every {:?}", interval, clamped);
+        }
+        let rx = (self.state != CollectorState::Stopped).then(|| {
+            let mut publisher = self.fast_publisher.lock().unwrap();
+            let publisher = publisher.get_or_insert_with(|| {
+                FastPublisher::start(self.interval.clone(), clamped, &self.supervisor)
+            });
+            if publisher.interval() != clamped {
+                log::warn!(
+                    "fast_snapshots({:?}) joins the running {:?} publisher",
+                    clamped,
+                    publisher.interval()
+                );
+            }
+            publisher.subscribe()
+        });
+        publisher::stream(rx)
+    }
+
+    /// The snapshot streams last got, if it was read no more than `max_age` ago,
+    /// for a caller that polls instead of holding a stream. Doesn't start the
+    /// publisher and takes nothing from the streams; with none running the last
+    /// snapshot goes stale
+    #[cfg(feature = "async-runtime")]
+    pub fn latest(&self, max_age: Duration) -> LatestSnapshot {
+        self.publisher
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(LatestSnapshot::Unavailable, |publisher| {
+                publisher.latest(max_age)
+            })
+    }
+
+    /// `snapshots()` run through a [`SignalSmoother`] with weight `alpha`
+    #[cfg(feature = "async-runtime")]
+    pub fn smoothed_snapshots(
+        &self,
+        interval: Duration,
+        alpha: f64,
+    ) -> impl Stream<Item = SmoothedSignals> + Send + 'static {
+        use futures_util::StreamExt;
+        let mut smoother = SignalSmoother::new(alpha);
+        self.snapshots(interval).map(move |signals| smoother.update(&signals))
+    }
+
+    /// `snapshots()` with the [`CongestionState`](crate::CongestionState) each
+    /// one leaves a [`SeverityClassifier`] in
+    #[cfg(feature = "async-runtime")]
+    pub fn severity_snapshots(
+        &self,
+        interval: Duration,
+        thresholds: SeverityThresholds,
+    ) -> impl Stream<Item = SeveritySnapshot> + Send + 'static {
+        use futures_util::StreamExt;
+        let mut classifier = SeverityClassifier::new(thresholds);
+        self.snapshots(interval)
+            .map(move |signals| SeveritySnapshot {
+                state: classifier.update(&signals),
+                signals,
+            })
+    }
+
+    /// Most recent `timestamp_ns` (kernel monotonic clock) seen per event type.
+    /// Types that never produced an event are absent
+    pub fn last_seen(&self) -> HashMap<u32, u64> {
+        self.interval.last_seen()
+    }
+
+    /// Per-socket totals, keyed by socket_id (the socket cookie). Unlike
+    /// `read_and_reset` these are cumulative over the socket's lifetime and
+    /// aren't reset by reading. A socket id that closes and reopens shows up as a
+    /// fresh entry, never as the sum of both lifetimes.
+    ///
+    /// TCP sockets are reported with `closed` set once after their close, then
+    /// dropped. UDP sockets are dropped after `CollectorConfig::socket_idle_ttl`
+    /// without events. Without the inet_sock_set_state tracepoint TCP sockets
+    /// fall back to the same TTL.
+    ///
+    /// Under `CollectorConfig::with_socket_sampling` only sockets in the sample
+    /// (`SocketSignals::fully_sampled`) and registered ones are reported. A
+    /// registered socket outside the sample has no sends here, its exact
+    /// bytes are in `signals_for`.
+    ///
+    /// The copy is of one instant across the table, taken without holding up
+    /// the processing task, which keeps recording meanwhile; `memory_report()`
+    /// has each shard's lock contention.
+    pub fn read_per_socket(&self) -> HashMap<u64, SocketSignals> {
+        self.interval.read_per_socket(self.config.socket_idle_ttl)
+    }
+
+    /// The `n` sockets that did the most of `CollectorConfig::talker_ranking`
+    /// over the latest interval read, highest first, with their share of the
+    /// host's total. Registered sockets that are connected carry their peer.
+    /// Sockets retired by `read_per_socket` since are missing
+    pub fn top_talkers(&self, n: usize) -> Vec<TopTalker> {
+        self.interval.top_talkers(n, self.config.talker_ranking)
+    }
+
+    /// Signals per cgroup id since the previous per-cgroup read, cgroups with
+    /// nothing counted left out. Only sends, drops and send buffer pressure are
+    /// attributed: `send_bytes` is sampled like `read_and_reset`'s, drops are the
+    /// ones in the sender's own syscall (a full qdisc at enqueue), and queue depth
+    /// and softirq time are host-wide, listed in `missing_signals`.
+    ///
+    /// Empty unless `CollectorConfig::with_cgroup_aggregation` was set. On its
+    /// own baseline, shared with `read_per_cgroup_rolled_up`, so it doesn't
+    /// disturb `read_and_reset`
+    pub fn read_per_cgroup(&self) -> HashMap<u64, CongestionSignals> {
+        self.interval
+            .cgroups
+            .lock()
+            .unwrap()
+            .as_mut()
+            .map(CgroupTracker::read_interval)
+            .map(|cgroups| self.socket_scaled(cgroups))
+            .unwrap_or_default()
+    }
+
+    /// `read_per_cgroup` keyed by cgroup path (`/system.slice/nginx.service`),
+    /// cgroups grouped per `rollup`, e.g. into pods or containers
+    pub fn read_per_cgroup_rolled_up(
+        &self,
+        rollup: CgroupRollup,
+    ) -> HashMap<String, CongestionSignals> {
+        self.interval
+            .cgroups
+            .lock()
+            .unwrap()
+            .as_mut()
+            .map(|cgroups| cgroups.read_rolled_up(rollup))
+            .map(|rolled_up| self.socket_scaled(rolled_up))
+            .unwrap_or_default()
+    }
+
+    /// Signals per network namespace inode since the previous per-namespace
+    /// read, namespaces with nothing counted left out. Only sends and send
+    /// buffer pressure carry a namespace; drops, queue depth and softirq time
+    /// are listed in `missing_signals`. `send_bytes` is sampled like
+    /// `read_and_reset`'s.
+    ///
+    /// Empty unless `CollectorConfig::with_netns` was set, and then only the
+    /// allowed namespaces. On its own baseline, so it doesn't disturb
+    /// `read_and_reset`
+    pub fn read_per_netns(&self) -> HashMap<u32, CongestionSignals> {
+        self.signals
+            .netns
+            .as_ref()
+            .map(|netns| netns.lock().unwrap().take())
+            .map(|netns| self.socket_scaled(netns))
+            .unwrap_or_default()
+    }
+
+    /// Signals per traffic class name since the previous per-class read,
+    /// classes with nothing counted left out. Like `read_per_netns`, only sends
+    /// and send buffer pressure carry a class, and `send_bytes` is sampled.
+    ///
+    /// Empty unless `CollectorConfig::with_traffic_classes` was set
+    pub fn read_per_class(&self) -> HashMap<String, CongestionSignals> {
+        self.signals
+            .classes
+            .as_ref()
+            .map(|classes| classes.lock().unwrap().take())
+            .map(|classes| self.socket_scaled(classes))
+            .unwrap_or_default()
+    }
+
+    /// Softirq time per vector since the previous per-interface read, and
+    /// NET_RX time split between the NAPI devices polled in the long rounds,
+    /// with each device's polls and packets. Exact rather than sampled: every
+    /// exit is timed and every long round sends its polls, the first 8.
+    /// Bond and team slaves are rolled up into their master, with their own
+    /// rows under it; the topology is read again at every call.
+    ///
+    /// Empty unless `CollectorConfig::with_softirq_breakdown` was set, and
+    /// without devices when the napi_poll probe isn't attached. On its own
+    /// baseline, so it doesn't disturb `read_and_reset`
+    pub fn read_per_interface(&self) -> SoftirqBreakdown {
+        let Some(breakdown) = &self.signals.softirq_breakdown else {
+            return SoftirqBreakdown::default();
+        };
+        let topology = BondTopology::read();
+        let mut breakdown = breakdown.lock().unwrap();
+        breakdown.set_bonds(&topology);
+        breakdown.take_interval()
+    }
+
+    /// A breakdown's estimates at the per-socket sample's scale, sockets being
+    /// sampled rather than 1 in 100 sends
+    fn socket_scaled<K>(
+        &self,
+        mut breakdown: HashMap<K, CongestionSignals>,
+    ) -> HashMap<K, CongestionSignals> {
+        if let Some(sampling) = self.config.socket_sampling {
+            breakdown
+                .values_mut()
+                .for_each(|signals| sampling.rescale(signals));
+        }
+        breakdown
+    }
+
+    /// Name of a namespace inode: its /run/netns name, `host` for the
+    /// collector's own or `pid:<pid>` of the lowest pid in it. None once
+    /// nothing holds it open
+    pub fn netns_name(&self, inode: u32) -> Option<String> {
+        self.interval.netns.lock().unwrap().name(inode)
+    }
+
+    /// Path of a `read_per_cgroup` id relative to the cgroup root, None when
+    /// it's gone or aggregation is off
+    pub fn cgroup_path(&self, cgroup_id: u64) -> Option<String> {
+        self.interval
+            .cgroups
+            .lock()
+            .unwrap()
+            .as_mut()?
+            .path(cgroup_id)
+    }
+
+    /// Start tracking `kernel_buffered_bytes` for one of your UDP sockets, see
+    /// `signals_for()`. Sets the socket's cookie like `socket_cookie()`, so the
+    /// handle's `socket_id()` is its key in `read_per_socket()` too. Registering
+    /// the same socket twice returns the same handle.
+    ///
+    /// Fails for non-UDP sockets, past MAX_REGISTERED_SOCKETS, or when this
+    /// kernel doesn't let the net_dev_xmit probe attribute skbs to sockets
+    pub fn register_socket(&self, socket: &impl AsRawFd) -> anyhow::Result<SocketHandle> {
+        if !self.interval.probes.lock().unwrap().xmit {
+            anyhow::bail!("net_dev_xmit probe not attached, buffered bytes unavailable");
+        }
+        let handle = self.interval.buffered.lock().unwrap().register(socket)?;
+        if let Some(coupling) = &self.signals.coupling {
+            coupling.lock().unwrap().register(handle.socket_id());
+        }
+        if let Some(attribution) = &self.signals.attribution {
+            attribution.lock().unwrap().register(handle.socket_id());
+        }
+        Ok(handle)
+    }
+
+    /// Stop tracking a registered socket. Do it before closing the socket; its
+    /// map slot is only freed here
+    pub fn unregister_socket(&self, handle: SocketHandle) {
+        self.interval.buffered.lock().unwrap().unregister(handle);
+        if let Some(coupling) = &self.signals.coupling {
+            coupling.lock().unwrap().unregister(handle.socket_id());
+        }
+        if let Some(attribution) = &self.signals.attribution {
+            attribution.lock().unwrap().unregister(handle.socket_id());
+        }
+    }
+
+    /// Latest gauge for a registered socket, None if it isn't. Recomputed on
+    /// every interval read (`read_and_reset()` or the snapshot publisher), so
+    /// it's as fresh as the last of those
+    pub fn signals_for(&self, handle: &SocketHandle) -> Option<RegisteredSocketSignals> {
+        self.interval.buffered.lock().unwrap().get(handle)
+    }
+
+    /// A registered socket's send buffer as it is now, read with
+    /// getsockopt(SO_MEMINFO) instead of waiting for a sampled send. Cheap
+    /// enough for every decision; see `Governor::update_probed`. Fails if the
+    /// socket isn't registered or this process no longer has it open
+    pub fn probe_socket_now(&self, handle: &SocketHandle) -> anyhow::Result<SocketStateSample> {
+        self.interval.buffered.lock().unwrap().probe(handle)
+    }
+
+    /// `probe_socket_now()` for every registered socket, leaving out those
+    /// that can't be found
+    pub fn probe_registered(&self) -> Vec<SocketStateSample> {
+        self.interval.buffered.lock().unwrap().probe_all()
+    }
+
+    /// kfree_skb drops per reason since load, plus those carried over by
+    /// `CollectorConfig::with_state_file`, named after the running kernel's
+    /// enum skb_drop_reason. Not reset by `read_and_reset`; diff two calls for
+    /// an interval. Everything is `DropReason::Unknown` when this kernel's
+    /// tracepoint record or reason names couldn't be resolved.
+    pub fn drops_by_reason(&self) -> BTreeMap<DropReason, u64> {
+        let mut by_reason = BTreeMap::new();
+        for (reason, count) in self.interval.drops_by_raw_reason() {
+            *by_reason.entry(self.drop_reasons.lookup(reason)).or_insert(0) += count;
+        }
+        by_reason
+    }
+
+    /// Totals since load, or since the first of the runs carried over by
+    /// `CollectorConfig::with_state_file`. Nothing here is reset: the interval
+    /// sums cover the interval reads made so far (`read_and_reset` and the
+    /// snapshot publisher), cgroups and drop reasons are read now
+    pub fn cumulative(&self) -> CumulativeTotals {
+        self.interval.cumulative()
+    }
+
+    /// Save `cumulative()` to the state file now, e.g. before a planned
+    /// restart. Does nothing without `CollectorConfig::with_state_file`
+    pub fn save_state(&self) -> anyhow::Result<()> {
+        self.interval.save_state()?;
+        Ok(())
+    }
+
+    /// Check whether the signals can be trusted. Each call cross-checks softirq
+    /// time against /proc/stat over the period since the previous call, so poll
+    /// this at a steady cadence (seconds, not milliseconds).
+    pub fn health(&self) -> HealthReport {
+        let mut report = HealthReport::default();
+        let degradation = *self.interval.degradation.lock().unwrap();
+        let probes = *self.interval.probes.lock().unwrap();
+        if degradation.missing_signals().contains(&Signal::Softirq) {
+            // Not sent, nothing to check
+        } else if probes.softirq {
+            let ours_ns = self.signals.softirq_ns_total.load(Ordering::Relaxed);
+            self.softirq_check.lock().unwrap().check(ours_ns, &mut report);
+        } else if !probes.tracefs {
+            health::tracefs_missing(&missing_signals(&probes, false), &mut report);
+        }
+        // Rejected with tracefs there, said by each failure
+        health::program_failures(&self.program_failures, &mut report);
+        report.program_failures = self.program_failures.clone();
+        // Quiet because the overhead budget silenced them
+        let mut last_seen = self.last_seen();
+        last_seen.retain(|&event_type, _| {
+            let detached = self.overhead.as_ref().is_some_and(|overhead| {
+                overhead.disabled.iter().any(|(program, _)| {
+                    OPTIONAL_PROGRAMS
+                        .iter()
+                        .any(|(name, _, sent)| name == program && *sent == event_type)
+                })
+            });
+            degradation.event_mask & (1 << event_type) == 0 && !detached
+        });
+        self.staleness_check.lock().unwrap().check(
+            &last_seen,
+            self.config.staleness_window,
+            &mut report,
+        );
+        let implausible = self.interval.implausible.lock().unwrap().total();
+        self.sample_check.lock().unwrap().check(
+            implausible,
+            self.signals.socket_samples_total.load(Ordering::Relaxed),
+            &mut report,
+        );
+        if let Some(pipeline) = &self.pipeline {
+            let counters = &pipeline.counters;
+            report.reader_restarts = counters.reader_restarts.lock().unwrap().clone();
+            let mut failed: Vec<u32> = counters
+                .failed_readers
+                .lock()
+                .unwrap()
+                .iter()
+                .copied()
+                .collect();
+            failed.sort_unstable();
+            health::failed_readers(&failed, self.config.reader_max_retries, &mut report);
+        }
+        #[cfg(feature = "async-runtime")]
+        {
+            report.component_failures = self.supervisor.failure_count();
+            if let (Some(_), Some(monitor)) = (&self.heartbeat, &self.signals.heartbeat) {
+                let status = monitor.status();
+                health::heartbeat(&status, &mut report);
+                report.heartbeat = Some(status);
+            }
+        }
+        if let Some(scan) = self.co_attachments.lock().unwrap().last() {
+            health::co_attachment(scan, &mut report);
+            report.co_attached = scan.co_attached.clone();
+        }
+        if let Some(outcome) = &self.calibration {
+            let disabled = self.signals.socket_state_disabled.load(Ordering::Relaxed);
+            health::calibration(outcome, disabled, &mut report);
+        }
+        report.calibration = self.calibration.clone();
+        health::memlock(&self.memlock, &mut report);
+        report.memlock = Some(self.memlock);
+        if let Some((skew, offsets)) = self.wall_clock_skew {
+            health::wall_clock(skew, offsets, &mut report);
+            report.wall_clock_skew = Some(skew);
+        }
+        report.restored_from_state = self.interval.restored_from_state;
+        if let Some(accuracy) = &*self.interval.accuracy.lock().unwrap() {
+            report.warnings.extend(accuracy.warnings());
+        }
+        if let Some(capture) = &self.signals.capture {
+            let status = capture.lock().unwrap().status();
+            health::capture(&status, &mut report);
+            report.capture = Some(status);
+        }
+        if let Some(overhead) = &self.overhead {
+            let status = overhead.status();
+            health::overhead(&status, &mut report);
+            report.overhead = Some(status);
+        }
+        report
+    }
+
+    /// Start a triggered capture now, as entering Red would: the last `before`
+    /// of events and the next `after`, written by the first interval read past
+    /// it. None without `CollectorConfig::with_triggered_capture`
+    pub fn capture_now(&self) -> Option<CaptureNotice> {
+        let capture = self.signals.capture.as_ref()?;
+        let now_ns = health::monotonic_now_ns()?;
+        Some(
+            capture
+                .lock()
+                .unwrap()
+                .trigger(CaptureTrigger::Manual, now_ns, SystemTime::now()),
+        )
+    }
+
+    /// Send every event of `handle`'s socket for `duration`, sampled or not,
+    /// while the rest stay sampled. With `CollectorConfig::with_triggered_capture`
+    /// they're also written to a capture of their own when the trace ends.
+    /// Events only the trace admitted are left out of the signals. Tracing a
+    /// socket already traced sets a new end; fails past
+    /// `CollectorConfig::max_traced_sockets`. See the readme for the cost
+    pub fn trace_socket(
+        &self,
+        handle: &SocketHandle,
+        duration: Duration,
+    ) -> anyhow::Result<SocketTrace> {
+        let now_ns = health::monotonic_now_ns()
+            .ok_or_else(|| anyhow::anyhow!("kernel monotonic clock unreadable"))?;
+        let trace = SocketTrace {
+            socket_id: handle.socket_id(),
+            started_ns: now_ns,
+            until_ns: now_ns.saturating_add(duration.as_nanos() as u64),
+            recorded: self.signals.capture.is_some(),
+        };
+        let trace = self.interval.tracer.lock().unwrap().start(trace)?;
+        if let Some(capture) = &self.signals.capture {
+            capture.lock().unwrap().trace(
+                trace.socket_id,
+                now_ns,
+                trace.until_ns,
+                SystemTime::now(),
+            );
+        }
+        Ok(trace)
+    }
+
+    /// End `handle`'s trace now and write its capture, if it has one. False
+    /// if the socket wasn't traced
+    pub fn stop_trace(&self, handle: &SocketHandle) -> bool {
+        let socket_id = handle.socket_id();
+        let stopped = self.interval.tracer.lock().unwrap().stop(socket_id);
+        if stopped.is_none() {
+            return false;
+        }
+        if let Some(capture) = &self.signals.capture {
+            let finished = capture.lock().unwrap().end_trace(socket_id);
+            if let Some(finished) = finished {
+                write_capture(capture, finished);
+            }
+        }
+        true
+    }
+
+    /// The sockets being traced, by socket id
+    pub fn traced_sockets(&self) -> Vec<SocketTrace> {
+        self.interval.tracer.lock().unwrap().traces()
+    }
+
+    /// The latest interval read against the kernel's SNMP counters (Udp
+    /// OutDatagrams, IpExt OutOctets, RcvbufErrors, TCPTimeouts) over the same
+    /// period, sampled signals scaled up. None before the second interval read
+    /// or with `CollectorConfig::without_accuracy_check`.
+    ///
+    /// The counters are the collector's network namespace's, and include
+    /// loopback; the probes see every namespace and by default leave loopback
+    /// sends out, either shows up as error
+    pub fn accuracy(&self) -> Option<AccuracyReport> {
+        self.interval.accuracy.lock().unwrap().as_ref()?.latest()
+    }
+
+    /// Run time the kernel accounted to the linked programs since they were
+    /// loaded, None unless kernel.bpf_stats_enabled is on (see `BpfStats`).
+    /// Differences between two calls are the programs' CPU cost
+    pub fn bpf_run_time(&self) -> Option<Duration> {
+        let enabled = std::fs::read_to_string("/proc/sys/kernel/bpf_stats_enabled").ok()?;
+        if enabled.trim() != "1" {
+            return None;
+        }
+        let total = self
+            .ebpf
+            .programs()
+            .filter(|(name, _)| self.linked_programs.iter().any(|linked| linked == name))
+            .filter_map(|(_, program)| program.info().ok())
+            .map(|info| info.run_time())
+            .sum();
+        Some(total)
+    }
+
+    /// Take a reading of the collector's own CPU and move along
+    /// `CollectorConfig::with_overhead_budget`'s ladder by it: write the
+    /// samplers' shift and the event mask to the kernel, detach or re-attach
+    /// optional probes. Call it at a steady cadence, say once a second; every
+    /// call is one reading towards `escalate_after` and `restore_after`.
+    /// Some when the level moved; None without a budget, on the first call
+    /// and while the level holds
+    pub fn enforce_overhead_budget(&mut self) -> Option<BudgetAction> {
+        let bpf_run_time = self.bpf_run_time();
+        let run_times = self.optional_run_times();
+        let overhead = self.overhead.as_mut()?;
+        let (percent, ranked) = overhead.read(bpf_run_time, run_times);
+        let action = overhead.observe(percent?)?;
+        let budget = overhead.status().budget_percent;
+        if action.is_escalation() {
+            log::warn!(
+                "collector at {:.2}% CPU, over its {:.2}% budget: degrading to level {}",
+                action.percent,
+                budget,
+                action.to
+            );
+        } else {
+            log::info!(
+                "collector at {:.2}% CPU, well under its {:.2}% budget: restoring to level {}",
+                action.percent,
+                budget,
+                action.to
+            );
+        }
+        self.apply_degradation(&ranked);
+        Some(action)
+    }
+
+    /// Cumulative run time of each optional program attached, zero without
+    /// run time accounting
+    fn optional_run_times(&self) -> Vec<(String, Duration)> {
+        let mut probes = *self.interval.probes.lock().unwrap();
+        OPTIONAL_PROGRAMS
+            .iter()
+            .filter(|(program, _, _)| probes.flag_mut(program).is_some_and(|attached| *attached))
+            .map(|(program, _, _)| {
+                let run_time = self
+                    .ebpf
+                    .program(program)
+                    .and_then(|p| p.info().ok())
+                    .map(|info| info.run_time())
+                    .unwrap_or_default();
+                (program.to_string(), run_time)
+            })
+            .collect()
+    }
+
+    /// Bring the kernel and the probes in line with the budget's level.
+    /// `ranked` is the attached optional programs, most expensive first
+    fn apply_degradation(&mut self, ranked: &[String]) {
+        let Some(overhead) = self.overhead.as_mut() else {
+            return;
+        };
+        overhead.write_map();
+        let degradation = overhead.degradation();
+        let mut probes = self.interval.probes.lock().unwrap();
+        while overhead.disabled.len() > degradation.disabled_probes {
+            let Some((program, flag)) = overhead.disabled.pop() else {
+                break;
+            };
+            let attached = Self::attach_optional(&mut self.ebpf, &program);
+            if let Some(slot) = probes.flag_mut(&program) {
+                *slot = flag && attached;
+            }
+            log::info!("overhead budget: {} attached again", program);
+        }
+        for program in ranked {
+            if overhead.disabled.len() >= degradation.disabled_probes {
+                break;
+            }
+            if let Some(flag) = Self::detach_optional(&mut self.ebpf, &mut probes, program) {
+                log::warn!("overhead budget: {} detached", program);
+                overhead.forget(program);
+                overhead.disabled.push((program.clone(), flag));
+            }
+        }
+        drop(probes);
+        self.linked_programs = linked_programs(&self.ebpf);
+        *self.interval.degradation.lock().unwrap() = degradation;
+    }
+
+    /// After a reload attached everything again: the new object's map, and
+    /// the same programs detached
+    fn reapply_degradation(&mut self) {
+        let Some(overhead) = self.overhead.as_mut() else {
+            return;
+        };
+        overhead.replace_map(&mut self.ebpf);
+        let mut probes = self.interval.probes.lock().unwrap();
+        for (program, flag) in overhead.disabled.iter_mut() {
+            if let Some(attached) = Self::detach_optional(&mut self.ebpf, &mut probes, program) {
+                *flag = attached;
+            }
+        }
+        drop(probes);
+        self.linked_programs = linked_programs(&self.ebpf);
+    }
+
+    /// Unload an optional program, which detaches it, and clear its flag.
+    /// The flag it had, None when it couldn't be unloaded
+    fn detach_optional(
+        ebpf: &mut Ebpf,
+        probes: &mut OptionalProbes,
+        program: &str,
+    ) -> Option<bool> {
+        let result = match ebpf.program_mut(program)? {
+            Program::KProbe(p) => p.unload(),
+            Program::TracePoint(p) => p.unload(),
+            _ => return None,
+        };
+        if let Err(e) = result {
+            log::warn!("overhead budget: detaching {} failed: {}", program, e);
+            return None;
+        }
+        Some(std::mem::replace(probes.flag_mut(program)?, false))
+    }
+
+    fn attach_optional(ebpf: &mut Ebpf, program: &str) -> bool {
+        match OPTIONAL_PROGRAMS
+            .iter()
+            .find(|(name, _, _)| *name == program)
+        {
+            Some((_, ProgramAttach::Kprobe(symbol), _)) => {
+                Self::attach_optional_kprobe(ebpf, program, symbol)
+            }
+            Some((_, ProgramAttach::Tracepoint(category, name), _)) => {
+                Self::attach_optional_tracepoint(ebpf, program, category, name)
+            }
+            None => false,
+        }
+    }
+
+    /// `accuracy()` totalled over every interval read since the first one
+    pub fn accuracy_since_start(&self) -> Option<AccuracyReport> {
+        self.interval
+            .accuracy
+            .lock()
+            .unwrap()
+            .as_ref()?
+            .since_start()
+    }
+
+    /// Write a diagnostic bundle into `dir` (created if missing): health,
+    /// config, probe coverage, programs, reader stats, memory, the last minute
+    /// of interval reads, the per-socket and per-interface tables, kernel and BTF
+    /// details, and with the event stream the last 100 events, each a JSON file
+    /// listed in manifest.json. Returns the files written.
+    ///
+    /// Nothing is reset: per-socket entries stay and intervals are only those
+    /// already read. It does call `health()`, which moves its cross-check window
+    pub fn dump_diagnostics(&self, dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        self.dump_diagnostics_redacted(dir, &Redaction::none())
+    }
+
+    /// Same as `dump_diagnostics`, socket ids in sockets.json and events.json
+    /// redacted first
+    pub fn dump_diagnostics_redacted(
+        &self,
+        dir: &Path,
+        redaction: &Redaction,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let state = self.diagnostic_state();
+        diagnostics::write_bundle(dir, &state, redaction)
+    }
+
+    /// `dump_diagnostics_redacted` with governor.json added: `governor`'s
+    /// policy, rate, effectiveness and latest decisions
+    #[cfg(feature = "governor")]
+    pub fn dump_diagnostics_governed(
+        &self,
+        dir: &Path,
+        redaction: &Redaction,
+        governor: &Governor,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let mut state = self.diagnostic_state();
+        state.governor = Some(diagnostics::governor_json(governor));
+        diagnostics::write_bundle(dir, &state, redaction)
+    }
+
+    fn diagnostic_state(&self) -> DiagnosticState<'_> {
+        let probes = *self.interval.probes.lock().unwrap();
+        let intervals = Vec::from(self.interval.history.lock().unwrap().clone());
+        DiagnosticState {
+            state: self.state,
+            config: &self.config,
+            health: self.health(),
+            probes: probes.attached(),
+            missing_signals: missing_signals(
+                &probes,
+                self.signals.socket_state_disabled.load(Ordering::Relaxed),
+            ),
+            suppressed: vec![
+                (
+                    "implausible_socket_samples",
+                    self.interval.implausible.lock().unwrap().total(),
+                ),
+                (
+                    "softirq_discarded",
+                    self.interval.softirq_discarded.lock().unwrap().total(),
+                ),
+                (
+                    "tcp_state_evictions",
+                    self.signals.tcp_state_evictions.load(Ordering::Relaxed),
+                ),
+            ],
+            programs: diagnostics::programs_json(&self.ebpf, &self.linked_programs),
+            reader_stats: self.reader_stats(),
+            memory: self.memory_report(),
+            intervals,
+            sockets: self.signals.sockets.peek(),
+            events: self.pipeline.as_ref().and_then(Pipeline::recent_events),
+            governor: None,
+        }
+    }
+}
+
+impl Drop for CongestionCollector {
+    fn drop(&mut self) {
+        if self.state == CollectorState::Collecting {
+            self.stop_readers();
+        }
+        if let Err(e) = self.interval.save_state() {
+            log::warn!("Failed to save cumulative totals: {}", e);
+        }
+    }
+}
+
+/// The eBPF object built alongside this crate
+fn bytecode() -> &'static [u8] {
+    // Load ebpf bytecode...just ignore the red, loads when the program compiles
+    #[cfg(debug_assertions)]
+    let bytecode = include_bytes_aligned!(
+        "../../target/bpfel-unknown-none/debug/congestion_signals"
+    );
+
+    #[cfg(not(debug_assertions))]
+    let bytecode = include_bytes_aligned!(
+        "../../target/bpfel-unknown-none/release/congestion_signals"
+    );
+    bytecode
+}
+
+/// Names of the programs in `ebpf` that have a bpf link right now
+fn linked_programs(ebpf: &Ebpf) -> Vec<String> {
+    let linked: HashSet<u32> = loaded_links()
+        .filter_map(Result::ok)
+        .map(|link| link.prog_id)
+        .collect();
+    ebpf.programs()
+        .filter(|(_, program)| program.info().is_ok_and(|info| linked.contains(&info.id())))
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+impl IntervalReader {
+    /// Reads of `signals` alone, every map missing as if the object had none
+    /// and every probe taken as attached
+    #[cfg(test)]
+    pub(crate) fn detached(signals: Arc<AtomicSignals>, config: &CollectorConfig) -> Self {
+        let probes = OptionalProbes {
+            tracefs: true,
+            drops: true,
+            queue_depth: true,
+            softirq: true,
+            tcp_state: true,
+            tcp_cwr: true,
+            tcp_rto: true,
+            tcp_recovery: true,
+            socket_lifecycle: true,
+            rx_squeeze: true,
+            tsq: true,
+            tcp_accept: true,
+            udp_gso: true,
+            udp6_gso: true,
+            xmit: true,
+            txq_stop: true,
+            txq_wake: true,
+            udp_ecn: true,
+        };
+        Self {
+            signals,
+            probes: Mutex::new(probes),
+            egress: Mutex::new(None),
+            rx_budget: Mutex::new(None),
+            implausible: Mutex::new(CounterMap::detached()),
+            tsq_throttles: Mutex::new(CounterMap::detached()),
+            tcp_accepts: Mutex::new(CounterMap::detached()),
+            udp_gso: Mutex::new(GsoCounter::detached()),
+            softirq_discarded: Mutex::new(CounterMap::detached()),
+            sends_seen: Mutex::new(CounterMap::detached()),
+            buffered: Mutex::new(BufferedTracker::detached()),
+            tracer: Mutex::new(SocketTracer::detached(config.max_traced_sockets)),
+            txq: Mutex::new(TxqStalls::detached()),
+            netns: Mutex::new(NetnsResolver::new()),
+            cgroups: Mutex::new(None),
+            last_read: Mutex::new(config.clock.now()),
+            last_fast_read: Mutex::new(config.clock.now()),
+            limitation: config.limitation.clone(),
+            exclude_loopback: config.exclude_loopback,
+            socket_sampling: config.socket_sampling,
+            history: Mutex::new(VecDeque::with_capacity(INTERVAL_HISTORY)),
+            sessions: Mutex::new(Sessions::default()),
+            accuracy: Mutex::new(None),
+            net_memory: Mutex::new(NetMemoryTracker::default()),
+            degradation: Mutex::new(Degradation::default()),
+            restored_from_state: false,
+            totals: Mutex::new(CumulativeTotals::default()),
+            state_file: None,
+            last_saved: Mutex::new(config.clock.now()),
+            clock: config.clock.clone(),
+        }
+    }
+
+    /// kfree_skb drops by raw reason value: the restored offsets plus the
+    /// counts since load, reasons without any left out
+    fn drops_by_raw_reason(&self) -> BTreeMap<u32, u64> {
+        let mut by_reason = self.totals.lock().unwrap().drops_by_reason.clone();
+        let low = self
+            .signals
+            .drops_by_reason
+            .iter()
+            .enumerate()
+            .map(|(reason, count)| (reason as u32, count.load(Ordering::Relaxed)));
+        let high: Vec<(u32, u64)> = self
+            .signals
+            .drops_by_reason_high
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&reason, &count)| (reason, count))
+            .collect();
+        for (reason, count) in low.chain(high) {
+            if count > 0 {
+                *by_reason.entry(reason).or_insert(0) += count;
+            }
+        }
+        by_reason
+    }
+
+    /// See `CongestionCollector::cumulative`
+    pub(crate) fn cumulative(&self) -> CumulativeTotals {
+        let drops_by_reason = self.drops_by_raw_reason();
+        let mut totals = self.totals.lock().unwrap().clone();
+        totals.drops_by_reason = drops_by_reason;
+        if let Some(cgroups) = &*self.cgroups.lock().unwrap() {
+            for (id, counters) in cgroups.totals() {
+                let cgroup = totals.cgroups.entry(id).or_default();
+                cgroup.send_bytes += counters.send_bytes;
+                cgroup.drops += counters.drops;
+            }
+        }
+        totals
+    }
+
+    /// Write `cumulative()` to the state file, if there is one
+    pub(crate) fn save_state(&self) -> std::io::Result<()> {
+        let Some(config) = &self.state_file else {
+            return Ok(());
+        };
+        *self.last_saved.lock().unwrap() = self.clock.now();
+        PersistedState::capture(self.cumulative())?.save(&config.path)
+    }
+
+    pub(crate) fn sessions(&self) -> &Mutex<Sessions> {
+        &self.sessions
+    }
+
+    pub(crate) fn limitation_thresholds(&self) -> &LimitationThresholds {
+        &self.limitation
+    }
+
+    /// See `CongestionCollector::last_seen`
+    pub(crate) fn last_seen(&self) -> HashMap<u32, u64> {
+        self.signals
+            .last_seen_ns
+            .iter()
+            .enumerate()
+            .filter_map(|(event_type, ts)| {
+                let ts = ts.load(Ordering::Relaxed);
+                (ts != 0).then_some((event_type as u32, ts))
+            })
+            .collect()
+    }
+
+    /// See `CongestionCollector::read_per_socket`
+    pub(crate) fn read_per_socket(&self, idle_ttl: Duration) -> HashMap<u64, SocketSignals> {
+        let mut snapshot = {
+            let sockets = &self.signals.sockets;
+            match health::monotonic_now_ns() {
+                Some(now_ns) => sockets.snapshot(now_ns, idle_ttl),
+                // Can't age anything without the clock, still retire closed sockets
+                None => sockets.snapshot(0, Duration::MAX),
+            }
+        };
+        if self.socket_sampling.is_some() {
+            let buffered = self.buffered.lock().unwrap();
+            snapshot.retain(|&socket_id, socket| {
+                socket.fully_sampled || buffered.is_registered(socket_id)
+            });
+        }
+        snapshot
+    }
+
+    /// See `CongestionCollector::top_talkers`
+    pub(crate) fn top_talkers(&self, n: usize, ranking: TalkerRanking) -> Vec<TopTalker> {
+        let mut talkers = rank_talkers(self.signals.sockets.last_intervals(), ranking, n);
+        let mut buffered = self.buffered.lock().unwrap();
+        for talker in &mut talkers {
+            talker.peer = buffered.peer(talker.socket_id);
+        }
+        talkers
+    }
+
+    /// Up to `n` of the latest interval reads, oldest first
+    pub(crate) fn recent_intervals(&self, n: usize) -> Vec<CongestionSignals> {
+        let history = self.history.lock().unwrap();
+        history
+            .iter()
+            .skip(history.len().saturating_sub(n))
+            .cloned()
+            .collect()
+    }
+
+    /// Send bytes, drops, TX queue stalls and the fullest send buffer since the
+    /// previous call, see `CongestionCollector::fast_snapshots`. Swaps only the
+    /// fast counters and the fast TXQ_STALLS cursor: the interval read still
+    /// gets everything
+    pub(crate) fn read_fast(&self) -> FastSignals {
+        let probes = *self.probes.lock().unwrap();
+        let now = self.clock.now();
+        let elapsed = {
+            let mut last_read = self.last_fast_read.lock().unwrap();
+            let elapsed = now.duration_since(*last_read);
+            *last_read = now;
+            elapsed
+        };
+        let txq = self.txq.lock().unwrap().read_fast();
+        let wmem_max = |counter: &AtomicU64| match counter.swap(0, Ordering::Relaxed) {
+            0 => None,
+            permille => Some((permille - 1) as f64 / 1000.0),
+        };
+        FastSignals {
+            interval_ns: elapsed.as_nanos() as u64,
+            produced_at: Some(now),
+            send_bytes: self.signals.fast_send_bytes.swap(0, Ordering::Relaxed),
+            drops: self.signals.fast_drops.swap(0, Ordering::Relaxed),
+            txq_stalls: probes.txq_stop.then_some(txq.stops),
+            txq_stalled_ns: (probes.txq_stop && probes.txq_wake).then_some(txq.stopped_ns),
+            wmem_max: wmem_max(&self.signals.fast_wmem_max),
+            udp_wmem_max: wmem_max(&self.signals.fast_udp_wmem_max),
+            tcp_wmem_max: wmem_max(&self.signals.fast_tcp_wmem_max),
+        }
+    }
+
+    /// See `CongestionCollector::read_and_reset_per_cpu`
+    pub(crate) fn read_and_reset_per_cpu(&self) -> (CongestionSignals, PerCpuSignals) {
+        let probes = *self.probes.lock().unwrap();
+        if let Some(rx_budget) = self.rx_budget.lock().unwrap().as_mut() {
+            rx_budget.sync();
+        }
+        self.buffered.lock().unwrap().update(&self.signals.sockets);
+        if let Some(now_ns) = health::monotonic_now_ns() {
+            self.tracer.lock().unwrap().expire(now_ns);
+        }
+
+        let now = self.clock.now();
+        let elapsed_ns = {
+            let mut last_read = self.last_read.lock().unwrap();
+            let elapsed = now.duration_since(*last_read);
+            *last_read = now;
+            elapsed.as_nanos() as u64
+        };
+
+        let send_bytes = self.signals.send_bytes.swap(0, Ordering::Relaxed);
+        let loopback_send_bytes = self.signals.loopback_send_bytes.swap(0, Ordering::Relaxed);
+        let external_send_bytes = send_bytes - loopback_send_bytes;
+        let sampler_comparison = {
+            let bytes = self.signals.compare_send_bytes.swap(0, Ordering::Relaxed);
+            let sends = self.signals.compare_sends.swap(0, Ordering::Relaxed);
+            self.signals
+                .compare_ratio
+                .map(|ratio| SamplerEstimates::from_sampled(send_bytes, bytes, sends, ratio))
+        };
+        let send_size_stats = SendSizeStats {
+            udp: self.signals.udp_send_sizes.take(),
+            tcp: self.signals.tcp_send_sizes.take(),
+        };
+        let drops = self.signals.drops.swap(0, Ordering::Relaxed);
+        let wmem_total = self.signals.wmem_total.swap(0, Ordering::Relaxed);
+        let wmem_samples = self.signals.wmem_samples.swap(0, Ordering::Relaxed);
+        let softirq_ns = self.signals.softirq_ns.swap(0, Ordering::Relaxed);
+        let event_count = self.signals.event_count.swap(0, Ordering::Relaxed);
+        let queue_depth_packets = self.signals.queue_depth_packets.swap(0, Ordering::Relaxed);
+        let queue_depth_bytes = self.signals.queue_depth_bytes.swap(0, Ordering::Relaxed);
+        let udp_rcv_drops = self.signals.udp_rcv_drops.swap(0, Ordering::Relaxed);
+        let rmem_total = self.signals.rmem_total.swap(0, Ordering::Relaxed);
+        let rmem_samples = self.signals.rmem_samples.swap(0, Ordering::Relaxed);
+        let observed = ObservedCounts {
+            events_received: event_count,
+            drops,
+            udp_rcv_drops,
+            softirq_exits: self.signals.softirq_exits.swap(0, Ordering::Relaxed),
+        };
+        let sends_seen = {
+            let mut seen = self.sends_seen.lock().unwrap();
+            seen.is_available().then(|| seen.interval_delta())
+        };
+        let sends_sampled = self.signals.sends_sampled.swap(0, Ordering::Relaxed);
+        let scale = match self.socket_sampling {
+            Some(sampling) => SampleScale::per_socket(sends_seen, sends_sampled, sampling),
+            None => SampleScale::from_coverage(sends_seen, sends_sampled),
+        };
+        let estimated = EstimatedTotals::from_samples(send_bytes, external_send_bytes, scale);
+        let udp_gso = self.udp_gso.lock().unwrap().interval_delta();
+        let udp_gso = (probes.udp_gso || probes.udp6_gso).then_some(udp_gso);
+        let est_send_msgs = ProtocolCounts::sampled(&send_size_stats).scaled(&scale);
+        let est_wire_packets = est_send_msgs.wire_packets(udp_gso.as_ref());
+
+        let avg_wmem_pressure = if wmem_samples > 0 {
+            (wmem_total as f64) / (wmem_samples as f64) / 1000.0
+        } else {
+            0.0
+        };
+        let wmem_sums = |total: &AtomicU64, samples: &AtomicU64| {
+            (
+                total.swap(0, Ordering::Relaxed),
+                samples.swap(0, Ordering::Relaxed),
+            )
+        };
+        let wmem_pressure = |(total, samples): (u64, u64)| {
+            (samples > 0).then(|| total as f64 / samples as f64 / 1000.0)
+        };
+        let udp_wmem = wmem_sums(&self.signals.udp_wmem_total, &self.signals.udp_wmem_samples);
+        let tcp_wmem = wmem_sums(&self.signals.tcp_wmem_total, &self.signals.tcp_wmem_samples);
+        let udp_wmem_pressure = wmem_pressure(udp_wmem);
+        let tcp_wmem_pressure = wmem_pressure(tcp_wmem);
+
+        let avg_rmem_pressure = if rmem_samples > 0 {
+            (rmem_total as f64) / (rmem_samples as f64) / 1000.0
+        } else {
+            0.0
+        };
+
+        let tcp_samples = self.signals.tcp_samples.swap(0, Ordering::Relaxed);
+        let tcp_cwnd_total = self.signals.tcp_cwnd_total.swap(0, Ordering::Relaxed);
+        let tcp_ssthresh_samples = self.signals.tcp_ssthresh_samples.swap(0, Ordering::Relaxed);
+        let tcp_ssthresh_total = self.signals.tcp_ssthresh_total.swap(0, Ordering::Relaxed);
+        let tcp_pacing_total = self.signals.tcp_pacing_total.swap(0, Ordering::Relaxed);
+        let socket_pacing_total = self.signals.socket_pacing_total.swap(0, Ordering::Relaxed);
+        let socket_pacing_samples = self
+            .signals
+            .socket_pacing_samples
+            .swap(0, Ordering::Relaxed);
+        let avg_socket_pacing_rate = (socket_pacing_samples > 0)
+            .then(|| socket_pacing_total as f64 / socket_pacing_samples as f64);
+        let sockets = &self.signals.sockets;
+        let (kernel_paced_send_share, send_concentration) =
+            (sockets.take_paced_share(), sockets.take_concentration());
+        let tcp_below_ssthresh = {
+            let mut below = self.signals.tcp_below_ssthresh.lock().unwrap();
+            let count = below.values().filter(|&&below| below).count() as u64;
+            // In place, the next interval reuses the map's allocation
+            below.clear();
+            count
+        };
+
+        let average = |total: u64, samples: u64| {
+            (probes.tcp_state && samples > 0).then(|| total as f64 / samples as f64)
+        };
+        let tcp_avg_cwnd = average(tcp_cwnd_total, tcp_samples);
+        let tcp_avg_ssthresh = average(tcp_ssthresh_total, tcp_ssthresh_samples);
+        let tcp_avg_pacing_rate = average(tcp_pacing_total, tcp_samples);
+        let tcp_sockets_below_ssthresh = probes.tcp_state.then_some(tcp_below_ssthresh);
+
+        let ce_marks = self.signals.ce_marks.swap(0, Ordering::Relaxed);
+        let tcp_cwr = self.signals.tcp_cwr.swap(0, Ordering::Relaxed);
+        let ce_marks = probes.udp_ecn.then_some(ce_marks);
+        let ce_triggered_cwr = probes.tcp_cwr.then_some(tcp_cwr);
+
+        let tcp_rto = self.signals.tcp_rto.swap(0, Ordering::Relaxed);
+        let tcp_recovery = self.signals.tcp_recovery.swap(0, Ordering::Relaxed);
+        let rto_events = probes.tcp_rto.then_some(tcp_rto);
+        let loss_recovery_episodes = probes.tcp_recovery.then_some(tcp_recovery);
+
+        let rx_time_squeeze = self.signals.rx_time_squeeze.swap(0, Ordering::Relaxed);
+        let rx_time_squeeze = probes.rx_squeeze.then_some(rx_time_squeeze);
+
+        let implausible_socket_samples = self.implausible.lock().unwrap().interval_delta();
+        let tsq_throttles = self.tsq_throttles.lock().unwrap().interval_delta();
+        let tsq_throttles = probes.tsq.then_some(tsq_throttles);
+        let tcp_accepts = self.tcp_accepts.lock().unwrap().interval_delta();
+        let connections = self.signals.sockets.take_churn(
+            probes.socket_lifecycle,
+            probes.tcp_accept.then_some(tcp_accepts),
+        );
+        let softirq_discarded = self.softirq_discarded.lock().unwrap().interval_delta()
+            + self.signals.softirq_discarded.swap(0, Ordering::Relaxed);
+        let burst_drop_correlation = self
+            .signals
+            .burst
+            .as_ref()
+            .and_then(|burst| burst.lock().unwrap().take_interval());
+        let drop_interarrival = self
+            .signals
+            .interarrival
+            .as_ref()
+            .map(|interarrival| interarrival.lock().unwrap().take_interval());
+        let softirq_coupling = self
+            .signals
+            .coupling
+            .as_ref()
+            .map(|coupling| coupling.lock().unwrap().take_interval());
+        let softirq_attribution = self
+            .signals
+            .attribution
+            .as_ref()
+            .map(|attribution| attribution.lock().unwrap().take_interval());
+
+        let per_cpu = self.signals.take_per_cpu(elapsed_ns, probes.rx_squeeze);
+        let softirq_cpu_fraction = per_cpu
+            .softirq_fraction_by_cpu
+            .iter()
+            .cloned()
+            .fold(0.0, f64::max);
+        // The same from the integer sums, for `CongestionSignals::fixed`
+        let fixed = FixedSignals {
+            interval_ns: elapsed_ns,
+            softirq_ns,
+            avg_wmem_pressure: mean_permille(wmem_total, wmem_samples).unwrap_or(0),
+            udp_wmem_pressure: mean_permille(udp_wmem.0, udp_wmem.1),
+            tcp_wmem_pressure: mean_permille(tcp_wmem.0, tcp_wmem.1),
+            avg_rmem_pressure: mean_permille(rmem_total, rmem_samples).unwrap_or(0),
+            softirq_cpu_fraction: per_cpu
+                .softirq_ns_by_cpu
+                .iter()
+                .filter_map(|&ns| ratio_permille(ns, elapsed_ns))
+                .fold(0, u32::max)
+                .min(PERMILLE),
+        };
+
+        let egress = self
+            .egress
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(EgressAccounting::read_interval)
+            .unwrap_or_default();
+        let egress_bytes: u64 = egress.iter().map(|e| e.egress_bytes_exact).sum();
+        let egress_estimate_error = (egress_bytes > 0)
+            .then(|| estimated.external_send_bytes as f64 / egress_bytes as f64 - 1.0);
+
+        let active_sockets = self.signals.active_sockets.lock().unwrap().take();
+        let active_flows = self
+            .signals
+            .active_flows
+            .as_ref()
+            .map(|flows| flows.lock().unwrap().take());
+        let txq_by_interface = {
+            let mut netns = self.netns.lock().unwrap();
+            if let Some(filter) = &self.signals.netns_filter {
+                filter.refresh_if_needed(&mut netns);
+            }
+            self.txq.lock().unwrap().read_interval(&mut netns)
+        };
+        let txq_stalls = probes
+            .txq_stop
+            .then(|| txq_by_interface.iter().map(|t| t.txq_stalls).sum());
+        let txq_stalled_ns = (probes.txq_stop && probes.txq_wake)
+            .then(|| txq_by_interface.iter().map(|t| t.txq_stalled_ns).sum());
+        let (net_memory_pressure, memory_pressure_events) =
+            self.net_memory.lock().unwrap().observe(&NetMemory::read());
+        let degradation = *self.degradation.lock().unwrap();
+
+        let mut signals = CongestionSignals {
+            interval_ns: elapsed_ns,
+            // Stamped by the publisher when it aligns
+            aligned_start: None,
+            aligned_end: None,
+            produced_at: Some(now),
+            observed,
+            estimated,
+            send_bytes,
+            loopback_send_bytes: (!self.exclude_loopback).then_some(loopback_send_bytes),
+            external_send_bytes,
+            send_size_stats,
+            est_send_msgs,
+            est_wire_packets,
+            udp_gso,
+            drops,
+            avg_wmem_pressure,
+            udp_wmem_pressure,
+            tcp_wmem_pressure,
+            net_memory_pressure,
+            memory_pressure_events,
+            avg_socket_pacing_rate,
+            kernel_paced_send_share,
+            kernel_paced: self
+                .limitation
+                .kernel_paced
+                .interval_paced(kernel_paced_send_share),
+            softirq_ns,
+            event_count,
+            active_sockets,
+            active_flows,
+            new_connections_per_interval: connections.opened(),
+            closed_connections_per_interval: connections.closed(),
+            connections,
+            send_concentration,
+            sampler_comparison,
+            queue_depth_packets,
+            queue_depth_bytes,
+            udp_rcv_drops,
+            avg_rmem_pressure,
+            softirq_cpu_fraction,
+            fixed: Some(fixed),
+            tcp_avg_cwnd,
+            tcp_avg_ssthresh,
+            tcp_avg_pacing_rate,
+            tcp_sockets_below_ssthresh,
+            ce_marks,
+            ce_triggered_cwr,
+            rto_events,
+            loss_recovery_episodes,
+            rx_time_squeeze,
+            implausible_socket_samples,
+            burst_drop_correlation,
+            drop_interarrival,
+            softirq_coupling,
+            softirq_attribution,
+            tsq_throttles,
+            softirq_discarded,
+            missing_signals: missing_signals(
+                &probes,
+                self.signals.socket_state_disabled.load(Ordering::Relaxed),
+            ),
+            degradation_level: degradation.level as u32,
+            limitation: Limitation::default(),
+            egress,
+            egress_estimate_error,
+            txq_stalls,
+            txq_stalled_ns,
+            txq_by_interface,
+            // Filled in once the interval is complete
+            sessions: Vec::new(),
+            captures: Vec::new(),
+            extensions: self.signals.extensions.lock().unwrap().take_interval(),
+        };
+        degradation.add_missing(&mut signals.missing_signals);
+        signals.limitation = Limitation::classify(&signals, &self.limitation);
+        signals.sessions = self
+            .sessions
+            .lock()
+            .unwrap()
+            .fold(&signals, self.clock.system_time());
+        if let (Some(capture), Some(now_ns)) = (&self.signals.capture, health::monotonic_now_ns()) {
+            let finished = capture
+                .lock()
+                .unwrap()
+                .observe(&signals, now_ns, SystemTime::now());
+            if let Some(finished) = finished {
+                write_capture(capture, finished);
+            }
+            let traces = capture.lock().unwrap().poll_traces(now_ns);
+            for finished in traces {
+                write_capture(capture, finished);
+            }
+            signals.captures = capture.lock().unwrap().take_notices();
+        }
+        if let Some(accuracy) = self.accuracy.lock().unwrap().as_mut() {
+            accuracy.observe(&signals);
+        }
+        {
+            let mut history = self.history.lock().unwrap();
+            if history.len() == INTERVAL_HISTORY {
+                history.pop_front();
+            }
+            history.push_back(signals.clone());
+        }
+        self.totals.lock().unwrap().add_interval(&signals);
+        if let Some(config) = &self.state_file {
+            if now.duration_since(*self.last_saved.lock().unwrap()) >= config.save_every {
+                if let Err(e) = self.save_state() {
+                    log::warn!("Failed to save cumulative totals: {}", e);
+                }
+            }
+        }
+
+        (signals, per_cpu)
+    }
+}
+
+/// Write a capture taken out of the lock, and count it back in
+fn write_capture(capture: &Mutex<TriggeredCapture>, finished: FinishedCapture) {
+    let notice = finished.write();
+    match &notice {
+        CaptureNotice::Written(record) => log::info!(
+            "Wrote {} events around a {} trigger to {}",
+            record.events,
+            record.trigger.label(),
+            record.path.display()
+        ),
+        CaptureNotice::Failed { path, error } => {
+            log::warn!("Failed to write capture {}: {}", path.display(), error)
+        }
+        _ => {}
+    }
+    capture.lock().unwrap().note(&notice);
+}
+
+/// Core signals lost when their tracepoints couldn't attach
+fn missing_signals(probes: &OptionalProbes, send_buffer_disabled: bool) -> Vec<Signal> {
+    let mut missing: Vec<Signal> = [
+        (Signal::Drops, probes.drops),
+        (Signal::QueueDepth, probes.queue_depth),
+        (Signal::Softirq, probes.softirq),
+    ]
+    .into_iter()
+    .filter(|&(_, attached)| !attached)
+    .map(|(signal, _)| signal)
+    .collect();
+    if send_buffer_disabled {
+        missing.push(Signal::SendBuffer);
+    }
+    missing
+}
+
+/// A never-reset per-CPU u64 counter map (IMPLAUSIBLE_SOCKET_SAMPLES,
+/// TSQ_THROTTLES), cumulative per object
+struct CounterMap {
+    map: Option<PerCpuArray<MapData, u64>>,
+    // total() at the previous interval read
+    last: u64,
+}
+
+impl CounterMap {
+    /// `disables` names what goes missing without the map, for the warning
+    fn take(ebpf: &mut Ebpf, name: &str, disables: &str) -> Self {
+        let map = match ebpf.take_map(name).map(PerCpuArray::try_from) {
+            Some(Ok(map)) => Some(map),
+            _ => {
+                log::warn!("{} map unusable, {} disabled", name, disables);
+                None
+            }
+        };
+        Self { map, last: 0 }
+    }
+
+    /// Without a map, as if it were unusable
+    #[cfg(test)]
+    fn detached() -> Self {
+        Self { map: None, last: 0 }
+    }
+
+    /// Summed over CPUs
+    fn total(&self) -> u64 {
+        self.map
+            .as_ref()
+            .and_then(|map| map.get(&0, 0).ok())
+            .map(|per_cpu| per_cpu.iter().sum())
+            .unwrap_or(0)
+    }
+
+    /// The map was there to take
+    fn is_available(&self) -> bool {
+        self.map.is_some()
+    }
+
+    fn interval_delta(&mut self) -> u64 {
+        let total = self.total();
+        total.saturating_sub(std::mem::replace(&mut self.last, total))
+    }
+}
+
+/// UDP_GSO summed over CPUs and diffed per interval like `CounterMap`
+struct GsoCounter {
+    map: Option<PerCpuArray<MapData, GsoCounters>>,
+    last: GsoCounters,
+}
+
+impl GsoCounter {
+    fn take(ebpf: &mut Ebpf) -> Self {
+        let map = match ebpf.take_map("UDP_GSO").map(PerCpuArray::try_from) {
+            Some(Ok(map)) => Some(map),
+            _ => {
+                log::warn!("UDP_GSO map unusable, wire packet estimates disabled");
+                None
+            }
+        };
+        Self {
+            map,
+            last: GsoCounters::default(),
+        }
+    }
+
+    #[cfg(test)]
+    fn detached() -> Self {
+        Self {
+            map: None,
+            last: GsoCounters::default(),
+        }
+    }
+
+    fn interval_delta(&mut self) -> GsoSegments {
+        let total = self
+            .map
+            .as_ref()
+            .and_then(|map| map.get(&0, 0).ok())
+            .map(|per_cpu| {
+                per_cpu
+                    .iter()
+                    .fold(GsoCounters::default(), |sum, cpu| GsoCounters {
+                        skbs: sum.skbs + cpu.skbs,
+                        gso_skbs: sum.gso_skbs + cpu.gso_skbs,
+                        segments: sum.segments + cpu.segments,
+                    })
+            })
+            .unwrap_or_default();
+        let last = std::mem::replace(&mut self.last, total);
+        GsoSegments {
+            skbs: total.skbs.saturating_sub(last.skbs),
+            gso_skbs: total.gso_skbs.saturating_sub(last.gso_skbs),
+            segments: total.segments.saturating_sub(last.segments),
+        }
+    }
+}
+
+/// Keeps the RX_BUDGET map in line with net.core.netdev_budget{,_usecs}, which
+/// are commonly tuned at runtime
+struct RxBudgetSync {
+    map: Array<MapData, RxBudget>,
+    current: Option<RxBudget>,
+}
+
+impl RxBudgetSync {
+    fn take(ebpf: &mut Ebpf, probes: &OptionalProbes) -> Option<Self> {
+        if !probes.rx_squeeze {
+            return None;
+        }
+        let map = ebpf.take_map("RX_BUDGET").map(Array::try_from);
+        let mut sync = match map {
+            Some(Ok(map)) => Self { map, current: None },
+            _ => {
+                log::warn!("RX_BUDGET map unusable, time squeeze tracking disabled");
+                return None;
+            }
+        };
+        sync.sync();
+        Some(sync)
+    }
+
+    /// Read the sysctls and write them through when they changed
+    fn sync(&mut self) {
+        let budget = rx_budget();
+        if self.current == Some(budget) {
+            return;
+        }
+        match self.map.set(0, budget, 0) {
+            Ok(()) => {
+                log::debug!("netdev budget now {:?}", budget);
+                self.current = Some(budget);
+            }
+            Err(e) => log::warn!("writing RX_BUDGET failed: {}", e),
+        }
+    }
+}
+
+/// net_rx_action's limits as the running kernel has them configured. The
+/// fallbacks are the kernel defaults at HZ=1000, usecs is 2 jiffies
+fn rx_budget() -> RxBudget {
+    let read = |name: &str, default: u32| {
+        std::fs::read_to_string(format!("/proc/sys/net/core/{}", name))
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(default)
+    };
+    RxBudget {
+        packets: read("netdev_budget", 300),
+        usecs: read("netdev_budget_usecs", 2000),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, Replay};
+    use crate::{TCP_CLOSE, TCP_ESTABLISHED};
+
+    #[test]
+    fn receive_path_aggregates() {
+        let replay = Replay::new(CollectorConfig::default(), 2);
+        replay.feed(&[
+            fixtures::rcv_state(1_000, 0, 7, 106_496, 212_992),
+            fixtures::rcv_state(2_000, 1, 8, 53_248, 212_992),
+            fixtures::rcv_drop(3_000, 0, 7),
+            fixtures::rcv_drop(4_000, 0, 7),
+            fixtures::rcv_drop(5_000, 1, 8),
+        ]);
+        let signals = replay.read(Duration::from_secs(1));
+        assert_eq!(signals.udp_rcv_drops, 3);
+        assert!((signals.avg_rmem_pressure - 0.375).abs() < 1e-9);
+
+        // Reset by the read
+        let signals = replay.read(Duration::from_secs(1));
+        assert_eq!(signals.udp_rcv_drops, 0);
+        assert_eq!(signals.avg_rmem_pressure, 0.0);
+
+        let per_socket = replay.signals.sockets.peek();
+        assert_eq!(per_socket[&7].udp_rcv_drops, 2);
+        assert_eq!(per_socket[&7].rmem_pressure, Some(0.5));
+        assert_eq!(per_socket[&8].udp_rcv_drops, 1);
+    }
+
+    #[test]
+    fn softirq_time_is_a_fraction_of_each_cpu() {
+        let replay = Replay::new(CollectorConfig::default(), 2);
+        let mut events = Vec::new();
+        for i in 0..4 {
+            events.push(fixtures::softirq_exit(i * 1_000, 0, 3, 50_000_000));
+        }
+        events.push(fixtures::softirq_exit(5_000, 1, 2, 50_000_000));
+        replay.feed(&events);
+        let (signals, per_cpu) = replay.read_per_cpu(Duration::from_secs(1));
+        assert_eq!(per_cpu.softirq_ns_by_cpu, [200_000_000, 50_000_000]);
+        assert_eq!(per_cpu.softirq_fraction_by_cpu, [0.2, 0.05]);
+        assert_eq!(signals.softirq_cpu_fraction, 0.2);
+    }
+
+    #[test]
+    fn implausible_softirq_durations_reach_nothing() {
+        let config = CollectorConfig::default()
+            .with_softirq_breakdown(crate::SoftirqBreakdownConfig::default())
+            .with_softirq_coupling(crate::SoftirqCouplingConfig::default());
+        let replay = Replay::new(config, 1);
+        replay.feed(&[
+            fixtures::softirq_exit(1_000, 0, 3, 40_000),
+            // Exactly the bound still counts
+            fixtures::softirq_exit(2_000, 0, 3, SOFTIRQ_MAX_DURATION_NS),
+            fixtures::softirq_exit(3_000, 0, 3, SOFTIRQ_MAX_DURATION_NS + 1),
+            // An entry and exit from different contexts, wrapped around
+            fixtures::softirq_exit(4_000, 0, 2, 2_000u64.wrapping_sub(3_000)),
+        ]);
+
+        let signals = replay.read(Duration::from_secs(1));
+        assert_eq!(signals.softirq_ns, SOFTIRQ_MAX_DURATION_NS + 40_000);
+        assert_eq!(signals.softirq_discarded, 2);
+        let breakdown = replay
+            .signals
+            .softirq_breakdown
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .take_interval();
+        assert!(breakdown.vector(2).is_none());
+        let rx = breakdown.vector(3).unwrap();
+        assert_eq!(rx.exits, 2);
+        assert!(rx.total_ns <= 2 * SOFTIRQ_MAX_DURATION_NS);
+    }
+
+    fn wmem_sample(timestamp_ns: u64, protocol: u32, wmem_queued: u32) -> CongestionEvent {
+        let mut event = fixtures::socket_state(timestamp_ns, 0, 7, wmem_queued, 100_000);
+        event.data.socket.protocol = protocol;
+        event
+    }
+
+    #[test]
+    fn wmem_pressure_splits_by_protocol() {
+        let replay = Replay::new(CollectorConfig::default(), 1);
+        replay.feed(&[
+            wmem_sample(1_000, IPPROTO_UDP, 80_000),
+            wmem_sample(2_000, IPPROTO_UDP, 60_000),
+            wmem_sample(3_000, IPPROTO_TCP, 10_000),
+            // In the average, neither side
+            wmem_sample(4_000, 132, 50_000),
+        ]);
+        let fast = replay.interval.read_fast();
+        assert_eq!(fast.udp_wmem_max, Some(0.8));
+        assert_eq!(fast.tcp_wmem_max, Some(0.1));
+        assert_eq!(fast.wmem_max, Some(0.8));
+
+        let signals = replay.read(Duration::from_secs(1));
+        assert_eq!(signals.udp_wmem_pressure, Some(0.7));
+        assert_eq!(signals.tcp_wmem_pressure, Some(0.1));
+        assert_eq!(signals.avg_wmem_pressure, 0.5);
+
+        // Only UDP this time: TCP has nothing to average
+        replay.feed(&[wmem_sample(5_000, IPPROTO_UDP, 25_000)]);
+        let signals = replay.read(Duration::from_secs(1));
+        assert_eq!(signals.udp_wmem_pressure, Some(0.25));
+        assert_eq!(signals.tcp_wmem_pressure, None);
+        assert_eq!(signals.avg_wmem_pressure, 0.25);
+    }
+
+    #[test]
+    fn tcp_state_samples_average_per_interval() {
+        let replay = Replay::new(CollectorConfig::default(), 1);
+        let mut paced = fixtures::tcp_state(3_000, 0, 2, 40, 20);
+        paced.data.tcp.pacing_rate = 3_000_000;
+        replay.feed(&[
+            fixtures::tcp_state(1_000, 0, 1, 10, 20),
+            fixtures::tcp_state(2_000, 0, 1, 12, 20),
+            paced,
+            // Not out of slow start yet: no ssthresh to average in
+            fixtures::tcp_state(4_000, 0, 3, 10, TCP_INFINITE_SSTHRESH),
+        ]);
+        let signals = replay.read(Duration::from_secs(1));
+        assert_eq!(signals.tcp_avg_cwnd, Some(18.0));
+        assert_eq!(signals.tcp_avg_ssthresh, Some(20.0));
+        assert_eq!(signals.tcp_avg_pacing_rate, Some(750_000.0));
+        // Sockets 1 and 3 by their last sample
+        assert_eq!(signals.tcp_sockets_below_ssthresh, Some(2));
+
+        // A closed flow leaves the count
+        replay.feed(&[
+            fixtures::tcp_state(5_000, 0, 1, 10, 20),
+            fixtures::tcp_state(6_000, 0, 3, 10, 20),
+            fixtures::lifecycle(7_000, 3, TCP_ESTABLISHED, TCP_CLOSE),
+        ]);
+        let signals = replay.read(Duration::from_secs(1));
+        assert_eq!(signals.tcp_sockets_below_ssthresh, Some(1));
+
+        let signals = replay.read(Duration::from_secs(1));
+        assert_eq!(signals.tcp_avg_cwnd, None);
+        assert_eq!(signals.tcp_sockets_below_ssthresh, Some(0));
+    }
+
+    #[test]
+    fn reload_counts_the_overlap_once() {
+        let replay = Replay::new(CollectorConfig::default(), 1);
+        let old = ReadWindow::open();
+        replay.feed_window(
+            &old,
+            &[
+                fixtures::rcv_state(1_000, 0, 7, 53_248, 212_992),
+                fixtures::rcv_drop(2_000, 0, 7),
+            ],
+        );
+        assert_eq!(replay.read(Duration::from_secs(1)).udp_rcv_drops, 1);
+
+        // The new object attached at 10us and the old one detached at 20us,
+        // both buffers have the drops in between
+        let new = ReadWindow::pending();
+        new.open_at(15_000);
+        old.close_at(15_000);
+        let overlap: Vec<_> = (10..20)
+            .map(|us| fixtures::rcv_drop(us * 1_000, 0, 7))
+            .collect();
+        let after: Vec<_> = (20..25)
+            .map(|us| fixtures::rcv_drop(us * 1_000, 0, 7))
+            .collect();
+        replay.feed_window(&new, overlap.iter().chain(&after));
+        // The old readers drain theirs after the new ones started
+        replay.feed_window(&old, &overlap);
+        assert_eq!(replay.read(Duration::from_secs(1)).udp_rcv_drops, 15);
+
+        // What was aggregated before the switch is kept
+        let socket = &replay.signals.sockets.peek()[&7];
+        assert_eq!(socket.udp_rcv_drops, 16);
+        assert_eq!(socket.first_seen_ns, 1_000);
+        assert_eq!(socket.rmem_pressure, Some(0.25));
+    }
+
+    #[test]
+    fn uneven_polls_carry_their_own_length() {
+        // 1000 sampled bytes per 10ms, read at whatever times the reads wake
+        let replay = Replay::new(CollectorConfig::default(), 1);
+        let mut timestamp_ns = 0;
+        let mut total_ns = 0;
+        for elapsed_ms in [900u64, 1_100, 1_000, 1_250, 750] {
+            let events: Vec<CongestionEvent> = (0..elapsed_ms / 10)
+                .map(|i| fixtures::udp_send(timestamp_ns + i * 10_000_000, 0, 7, 1_000))
+                .collect();
+            timestamp_ns += elapsed_ms * 1_000_000;
+            replay.feed(&events);
+
+            let signals = replay.read(Duration::from_millis(elapsed_ms));
+            assert_eq!(signals.interval_ns, elapsed_ms * 1_000_000);
+            let rate = signals.send_bytes as f64 * 1e9 / signals.interval_ns as f64;
+            assert!(
+                (rate - 100_000.0).abs() < 1e-6,
+                "{} after {}ms",
+                rate,
+                elapsed_ms
+            );
+            total_ns += signals.interval_ns;
+        }
+        assert_eq!(total_ns, timestamp_ns);
+    }
+
+    #[test]
+    fn time_squeezes_count_per_interval_and_cpu() {
+        let replay = Replay::new(CollectorConfig::default(), 4);
+        replay.feed(&[
+            fixtures::rx_squeeze(1_000, 0, 2_000_000, 300),
+            fixtures::rx_squeeze(2_000, 2, 2_000_000, 300),
+            fixtures::rx_squeeze(3_000, 2, 1_000_000, 64),
+            fixtures::rx_squeeze(4_000, 2, 2_000_000, 300),
+            // A CPU outside the set goes to the catch-all slot
+            fixtures::rx_squeeze(5_000, 9, 2_000_000, 300),
+            fixtures::napi_poll(6_000, 1, "eth0", 64),
+        ]);
+
+        let (signals, per_cpu) = replay.read_per_cpu(Duration::from_secs(1));
+        assert_eq!(signals.rx_time_squeeze, Some(5));
+        assert_eq!(per_cpu.rx_time_squeeze_by_cpu, vec![1, 0, 3, 0]);
+        assert_eq!(per_cpu.other_rx_time_squeeze, 1);
+
+        let (signals, per_cpu) = replay.read_per_cpu(Duration::from_secs(1));
+        assert_eq!(signals.rx_time_squeeze, Some(0));
+        assert_eq!(per_cpu.rx_time_squeeze_by_cpu, vec![0; 4]);
+    }
+
+    #[test]
+    fn kprobe_only_intervals_list_the_tracepoint_signals() {
+        let replay = Replay::new(CollectorConfig::default(), 1);
+        {
+            let mut probes = replay.interval.probes.lock().unwrap();
+            probes.tracefs = false;
+            probes.drops = false;
+            probes.queue_depth = false;
+            probes.softirq = false;
+        }
+        replay.feed(&[
+            fixtures::udp_send(1_000, 0, 7, 1_200),
+            fixtures::socket_state(2_000, 0, 7, 106_496, 212_992),
+        ]);
+        let signals = replay.read(Duration::from_secs(1));
+        let missing = [Signal::Drops, Signal::QueueDepth, Signal::Softirq];
+        assert_eq!(signals.missing_signals, missing);
+        // What the kprobes measure still comes through
+        assert_eq!(signals.send_bytes, 1_200);
+        assert!(signals.avg_wmem_pressure > 0.0);
+        assert!(signals
+            .to_json()
+            .contains("\"missing_signals\":[\"Drops\",\"QueueDepth\",\"Softirq\"]"));
+
+        // And the rest read as measured
+        let mut probes = replay.interval.probes.lock().unwrap();
+        probes.drops = true;
+        probes.queue_depth = true;
+        drop(probes);
+        let signals = replay.read(Duration::from_secs(1));
+        assert_eq!(signals.missing_signals, [Signal::Softirq]);
+    }
+
+    #[test]
+    fn send_sizes_bucket_at_their_limits() {
+        let buckets: Vec<usize> = [0, 1_499, 1_500, 16_383, 16_384, 65_536, 65_537, u64::MAX]
+            .into_iter()
+            .map(size_bucket)
+            .collect();
+        assert_eq!(buckets, [0, 0, 1, 1, 2, 2, 3, 3]);
+    }
+
+    #[test]
+    fn send_sizes_follow_the_replayed_sequence() {
+        let replay = Replay::new(CollectorConfig::default(), 2);
+        let udp = [1_200, 1_200, 1_472, 9_000, 65_536, 1_200];
+        let tcp = [100_000, 32_768];
+        let mut events = Vec::new();
+        for (i, &bytes) in udp.iter().enumerate() {
+            events.push(fixtures::udp_send(i as u64, i as u32 % 2, 7, bytes));
+        }
+        for (i, &bytes) in tcp.iter().enumerate() {
+            events.push(fixtures::tcp_send(100 + i as u64, 0, 8, bytes));
+        }
+        replay.feed(&events);
+        let signals = replay.read(Duration::from_secs(1));
+
+        let udp = signals.send_size_stats.udp.unwrap();
+        assert_eq!(udp.samples, 6);
+        assert_eq!((udp.min_bytes, udp.max_bytes), (1_200, 65_536));
+        assert!((udp.avg_bytes - 79_608.0 / 6.0).abs() < 1e-9);
+        assert_eq!(udp.histogram, [4, 1, 1, 0]);
+        let tcp = signals.send_size_stats.tcp.unwrap();
+        assert_eq!((tcp.min_bytes, tcp.max_bytes), (32_768, 100_000));
+        assert_eq!(tcp.histogram, [0, 0, 1, 1]);
+
+        // Nothing sent, nothing to report
+        let signals = replay.read(Duration::from_secs(1));
+        assert!(signals.send_size_stats.udp.is_none());
+        assert!(signals.send_size_stats.tcp.is_none());
+    }
+
+    #[test]
+    fn tcp_state_past_its_cap_is_refused_and_counted() {
+        let config = CollectorConfig {
+            max_tracked_sockets: 8,
+            ..CollectorConfig::default()
+        };
+        let replay = Replay::new(config, 1);
+        let events: Vec<_> = (1..=10)
+            .map(|socket_id| fixtures::tcp_state(socket_id, 0, socket_id, 10, 20))
+            .collect();
+        replay.feed(&events);
+        assert_eq!(replay.signals.tcp_below_ssthresh.lock().unwrap().len(), 8);
+        assert_eq!(
+            replay.signals.tcp_state_evictions.load(Ordering::Relaxed),
+            2
+        );
+
+        // A socket already held still updates
+        replay.feed(&[fixtures::tcp_state(20, 0, 1, 30, 20)]);
+        assert_eq!(
+            replay.signals.tcp_state_evictions.load(Ordering::Relaxed),
+            2
+        );
+        assert_eq!(
+            replay
+                .read(Duration::from_secs(1))
+                .tcp_sockets_below_ssthresh,
+            Some(7)
+        );
+    }
+
+    fn loopback_send(timestamp_ns: u64, socket_id: u64, bytes: u64) -> CongestionEvent {
+        let mut event = fixtures::udp_send(timestamp_ns, 0, socket_id, bytes);
+        event.data.sendmsg.loopback = 1;
+        event
+    }
+
+    #[test]
+    fn loopback_sends_are_reported_apart_when_kept() {
+        let replay = Replay::new(CollectorConfig::default().with_loopback(), 1);
+        replay.feed(&[
+            fixtures::udp_send(1_000, 0, 7, 1_200),
+            loopback_send(2_000, 8, 9_000),
+            fixtures::udp_send(3_000, 0, 7, 1_200),
+            loopback_send(4_000, 7, 600),
+        ]);
+        let signals = replay.read(Duration::from_secs(1));
+        assert_eq!(signals.send_bytes, 12_000);
+        assert_eq!(signals.loopback_send_bytes, Some(9_600));
+        assert_eq!(signals.external_send_bytes, 2_400);
+        assert_eq!(
+            signals.estimated.external_send_bytes,
+            2_400 * signals.estimated.scale.send_factor as u64
+        );
+
+        let sockets = replay.signals.sockets.peek();
+        assert_eq!(
+            (sockets[&7].send_bytes, sockets[&7].loopback_send_bytes),
+            (3_000, 600)
+        );
+        assert_eq!(
+            (sockets[&8].send_bytes, sockets[&8].loopback_send_bytes),
+            (9_000, 9_000)
+        );
+    }
+
+    #[test]
+    fn filtered_loopback_leaves_every_send_external() {
+        // The kernel drops loopback sends before they're sampled, so every
+        // send that reaches the readers is external
+        let config = CollectorConfig::default();
+        assert!(config.exclude_loopback);
+        let replay = Replay::new(config, 1);
+        replay.feed(&[
+            fixtures::udp_send(1_000, 0, 7, 1_200),
+            fixtures::udp_send(2_000, 0, 7, 1_200),
+        ]);
+        let signals = replay.read(Duration::from_secs(1));
+        assert_eq!(signals.loopback_send_bytes, None);
+        assert_eq!(signals.external_send_bytes, signals.send_bytes);
+        assert_eq!(signals.send_bytes, 2_400);
+    }
+
+    #[test]
+    fn rtos_and_recoveries_are_counted_apart_per_interval() {
+        let replay = Replay::new(CollectorConfig::default(), 2);
+        replay.feed(&[
+            fixtures::tcp_recovery(1_000, 0, 7, 40),
+            fixtures::tcp_recovery(2_000, 1, 8, 40),
+            fixtures::tcp_rto(3_000, 0, 7, 20),
+            fixtures::tcp_recovery(4_000, 1, 7, 10),
+        ]);
+        let signals = replay.read(Duration::from_secs(1));
+        assert_eq!(signals.rto_events, Some(1));
+        assert_eq!(signals.loss_recovery_episodes, Some(3));
+
+        // Counted afresh every interval
+        replay.feed(&[fixtures::tcp_rto(5_000, 0, 8, 10)]);
+        let signals = replay.read(Duration::from_secs(1));
+        assert_eq!(signals.rto_events, Some(1));
+        assert_eq!(signals.loss_recovery_episodes, Some(0));
+    }
+
+    #[test]
+    fn loss_episodes_without_their_probe_read_as_unmeasured() {
+        let replay = Replay::new(CollectorConfig::default(), 1);
+        replay.interval.probes.lock().unwrap().tcp_rto = false;
+        replay.feed(&[fixtures::tcp_recovery(1_000, 0, 7, 40)]);
+        let signals = replay.read(Duration::from_secs(1));
+        assert_eq!(signals.rto_events, None);
+        assert_eq!(signals.loss_recovery_episodes, Some(1));
+    }
+
+    /// Events from CPU 130 of "0-3,128-131" land in its own slot and come
+    /// back under its id; ids outside the set go to the other slot, counted
+    #[test]
+    fn per_cpu_replay_keeps_sparse_ids() {
+        let ids = crate::parse_cpu_list("0-3,128-131");
+        let cpus = CpuSlots::new(ids.clone());
+        let events = [
+            fixtures::softirq_exit(0, 0, 3, 10_000_000),
+            fixtures::softirq_exit(0, 130, 3, 60_000_000),
+            fixtures::softirq_exit(0, 130, 3, 40_000_000),
+            fixtures::softirq_exit(0, 131, 3, 50_000_000),
+            fixtures::rx_squeeze(0, 130, 2_000_000, 64),
+            fixtures::rx_squeeze(0, 130, 2_000_000, 64),
+            // Between the ranges, and past them all
+            fixtures::softirq_exit(0, 5, 3, 7_000_000),
+            fixtures::rx_squeeze(0, 4096, 2_000_000, 64),
+        ];
+        let per_cpu = replay_per_cpu(&events, &cpus, 1_000_000_000);
+        assert_eq!(per_cpu.cpus, ids);
+        assert_eq!(
+            per_cpu.softirq_ns_by_cpu,
+            [10_000_000, 0, 0, 0, 0, 0, 100_000_000, 50_000_000]
+        );
+        assert_eq!(per_cpu.rx_time_squeeze_by_cpu, [0, 0, 0, 0, 0, 0, 2, 0]);
+        assert_eq!(per_cpu.position(130), Some(6));
+        assert_eq!(per_cpu.cpu_id(6), 130);
+        assert!((per_cpu.softirq_fraction_by_cpu[6] - 0.1).abs() < 1e-9);
+        assert_eq!(per_cpu.unknown_cpu_events, 2);
+        assert_eq!(per_cpu.other_softirq_ns, 7_000_000);
+        assert_eq!(per_cpu.other_rx_time_squeeze, 1);
+
+        // No possible CPUs known at all: everything is other
+        let empty = replay_per_cpu(&events, &CpuSlots::default(), 1_000_000_000);
+        assert!(empty.softirq_ns_by_cpu.is_empty());
+        assert_eq!(empty.unknown_cpu_events, events.len() as u64);
+        assert_eq!(empty.other_softirq_ns, 167_000_000);
+    }
+}
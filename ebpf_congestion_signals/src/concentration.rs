@@ -0,0 +1,171 @@
+//How an interval's sends spread over sockets. When the host is congested one
+//flow hogging the egress wants that flow throttled, pain spread evenly wants
+//global pacing. Each interval read takes the sampled send bytes per socket from
+//the per-socket table and summarises them as top-1/top-5 shares, a Gini index
+//and the Herfindahl-Hirschman index, with the heaviest sockets by cookie.
+//
+//Sends are sampled 1-in-SEND_SAMPLE_RATIO, so a handful of samples would make
+//any flow look dominant: intervals below `min_send_bytes` report nothing.
+
+use crate::SEND_SAMPLE_RATIO;
+
+/// When and how `CongestionSignals::send_concentration` is reported, see
+/// `CollectorConfig::concentration`
+#[derive(Debug, Clone, Copy)]
+pub struct ConcentrationConfig {
+    /// Send bytes an interval needs, scaled up from the sampled ones, before
+    /// its concentration says anything. The default is ~17 sampled 1200-byte
+    /// datagrams
+    pub min_send_bytes: u64,
+    /// Heaviest sockets listed in `SendConcentration::top_sockets`
+    pub top_sockets: usize,
+}
+
+impl Default for ConcentrationConfig {
+    fn default() -> Self {
+        Self {
+            min_send_bytes: 2_000_000,
+            top_sockets: 5,
+        }
+    }
+}
+
+/// One of an interval's heaviest senders
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TopSocket {
+    /// The socket cookie, as from `socket_cookie()` on a registered socket
+    pub socket_id: u64,
+    /// Sampled send bytes in the interval
+    pub send_bytes: u64,
+    /// Of the interval's sampled send bytes
+    pub share: f64,
+}
+
+/// Distribution of an interval's send bytes across the sockets that sent
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SendConcentration {
+    /// Sockets with sampled sends in the interval
+    pub sockets: usize,
+    /// Share of the heaviest socket, and of the five heaviest
+    pub top1_share: f64,
+    pub top5_share: f64,
+    /// 0 when every socket sent the same, towards 1 as one socket takes it all.
+    /// 0 with a single socket, where `top1_share` says it instead
+    pub gini: f64,
+    /// Sum of squared shares, from 1/`sockets` (even) to 1 (one socket)
+    pub hhi: f64,
+    /// Heaviest first, `ConcentrationConfig::top_sockets` at most
+    pub top_sockets: Vec<TopSocket>,
+}
+
+impl SendConcentration {
+    /// From (socket_id, sampled send bytes) pairs. None below
+    /// `config.min_send_bytes`; sockets that sent nothing are left out
+    pub fn of(
+        per_socket: impl IntoIterator<Item = (u64, u64)>,
+        config: &ConcentrationConfig,
+    ) -> Option<Self> {
+        let mut sent: Vec<(u64, u64)> = per_socket
+            .into_iter()
+            .filter(|&(_, bytes)| bytes > 0)
+            .collect();
+        let total: u64 = sent.iter().map(|&(_, bytes)| bytes).sum();
+        if total == 0 || total.saturating_mul(SEND_SAMPLE_RATIO) < config.min_send_bytes {
+            return None;
+        }
+        // Heaviest first, socket id breaking ties so the listing is stable
+        sent.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let total_f = total as f64;
+        let share = |bytes: u64| bytes as f64 / total_f;
+        let top_share = |n: usize| share(sent.iter().take(n).map(|&(_, bytes)| bytes).sum());
+        let n = sent.len() as f64;
+        // Σ (2i - n - 1) x_i over ascending x, i from 1, divided by n Σ x. The
+        // sort is descending, so rank i there is n - i + 1 here
+        let weighted: f64 = sent
+            .iter()
+            .enumerate()
+            .map(|(i, &(_, bytes))| (n - 2.0 * i as f64 - 1.0) * bytes as f64)
+            .sum();
+        Some(Self {
+            sockets: sent.len(),
+            top1_share: top_share(1),
+            top5_share: top_share(5),
+            gini: weighted / (n * total_f),
+            hhi: sent.iter().map(|&(_, bytes)| share(bytes).powi(2)).sum(),
+            top_sockets: sent
+                .iter()
+                .take(config.top_sockets)
+                .map(|&(socket_id, send_bytes)| TopSocket {
+                    socket_id,
+                    send_bytes,
+                    share: share(send_bytes),
+                })
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ConcentrationConfig {
+        ConcentrationConfig {
+            top_sockets: 3,
+            ..Default::default()
+        }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn an_even_spread_has_no_concentration() {
+        let even = SendConcentration::of((1..=4).map(|id| (id, 10_000)), &config()).unwrap();
+        assert_eq!(even.sockets, 4);
+        assert!(close(even.top1_share, 0.25));
+        assert!(close(even.top5_share, 1.0));
+        assert!(close(even.gini, 0.0));
+        assert!(close(even.hhi, 0.25));
+    }
+
+    #[test]
+    fn one_hog_dominates() {
+        let hog = SendConcentration::of(
+            [(1, 10_000), (2, 70_000), (3, 10_000), (4, 10_000), (5, 0)],
+            &config(),
+        )
+        .unwrap();
+        // The idle socket is left out
+        assert_eq!(hog.sockets, 4);
+        assert!(close(hog.top1_share, 0.7));
+        // Ascending 10k, 10k, 10k, 70k: (-3 - 1 + 1) * 10k + 3 * 70k over 4 * 100k
+        assert!(close(hog.gini, 0.45));
+        assert!(close(hog.hhi, 0.52));
+        assert_eq!(hog.top_sockets[0].socket_id, 2);
+    }
+
+    #[test]
+    fn too_few_bytes_say_nothing() {
+        // 1000 sampled bytes is 100 KB sent, under the 2 MB minimum
+        assert!(SendConcentration::of([(1, 1_000)], &config()).is_none());
+        assert!(SendConcentration::of([], &config()).is_none());
+    }
+
+    #[test]
+    fn the_top_sockets_are_heaviest_first_ties_by_id() {
+        let concentration = SendConcentration::of(
+            [(7, 5_000), (3, 20_000), (9, 20_000), (1, 1_000), (4, 8_000)],
+            &config(),
+        )
+        .unwrap();
+        let top: Vec<u64> = concentration
+            .top_sockets
+            .iter()
+            .map(|t| t.socket_id)
+            .collect();
+        assert_eq!(top, [3, 9, 4]);
+    }
+}
@@ -0,0 +1,622 @@
+//Collector tunables. Everything has a default that works for a single QUIC host;
+//pass a modified copy to `CongestionCollector::load_with_config`.
+//
+//The capacities here also size what the readers and the processing side
+//allocate up front. Past warm-up (tables grown to their high-water mark) the
+//per-event path allocates nothing; interval reads still build their result.
+
+#[cfg(feature = "collector-core")]
+use crate::{system_clock, Clock};
+#[cfg(feature = "collector-core")]
+use crate::{
+    AccuracyConfig, BurstCorrelationConfig, CalibrationConfig, CaptureConfig,
+    CgroupAggregationConfig, ConflictCheck, MemlockConfig, NetnsConfig, OverheadBudget,
+    SocketSampling, SoftirqAttributionConfig, SoftirqBreakdownConfig, SoftirqCouplingConfig,
+    StateFileConfig, TrafficClassConfig,
+};
+use crate::{ConcentrationConfig, EventReordering, LimitationThresholds, TalkerRanking};
+#[cfg(feature = "async-runtime")]
+use crate::{HeartbeatConfig, RestartPolicies};
+#[cfg(feature = "collector-core")]
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct CollectorConfig {
+    /// Events buffered between the per-CPU readers and the processing task.
+    /// When full, readers drop (and count) rather than wait, so perf buffers keep draining
+    pub event_queue_capacity: usize,
+    /// Per-subscriber backlog for `subscribe_events()`; lagging subscribers skip ahead
+    pub subscriber_capacity: usize,
+    /// How long a previously active event type may stay silent, while others
+    /// keep arriving, before `health()` calls it stale
+    pub staleness_window: Duration,
+    /// When an interval counts as app- or network-limited
+    pub limitation: LimitationThresholds,
+    /// When `CongestionSignals::send_concentration` is reported, and how many
+    /// top sockets it lists
+    pub concentration: ConcentrationConfig,
+    /// What `top_talkers()` ranks sockets by
+    pub talker_ranking: TalkerRanking,
+    /// `read_per_socket()` forgets a socket after this long without events. UDP
+    /// sockets have no close event, so this is what retires them
+    pub socket_idle_ttl: Duration,
+    /// Sockets kept in the per-socket table (and the interval's TCP state). A
+    /// full table evicts its least recently seen eighth, counted in `memory_report()`
+    pub max_tracked_sockets: usize,
+    /// Entries the per-socket table and the TCP state map are allocated with.
+    /// They grow from there up to `max_tracked_sockets` and keep what they grew
+    /// to; set it to `max_tracked_sockets` to never rehash, at ~150 bytes per entry
+    pub socket_table_capacity: usize,
+    /// Interfaces to attach the tc egress counter to, e.g. `["eth0"]`. Empty
+    /// (the default) leaves tc alone
+    pub egress_interfaces: Vec<String>,
+    /// Distinct sockets per interval counted exactly before `active_sockets`
+    /// switches to a fixed-size estimate, and likewise flows for `active_flows`
+    pub exact_socket_count_limit: usize,
+    /// Hash the destination of sampled UDP sends in the kernel, for
+    /// `CongestionSignals::active_flows`. Off by default; see `with_flow_tracking`
+    pub track_flows: bool,
+    /// Perf buffer read errors in a row a CPU's reader retries, backing off up to
+    /// a second between tries, before it gives up and `health()` reports it
+    pub reader_max_retries: u32,
+    /// Perf samples a reader takes per read, and the events folded into the
+    /// atomics at once. Each CPU's reader keeps this many event-sized buffers
+    pub reader_batch_size: usize,
+    /// When a CPU's reader wakes to read its perf buffer: on every sample (the
+    /// default), at a watermark, or on a timer; see `with_reader_wakeup`
+    pub reader_wakeup: ReaderWakeup,
+    /// Data pages in each CPU's perf buffer, a power of two. None for the
+    /// reader wakeup's default: 2, or 16 under a watermark. Cut down to fit
+    /// RLIMIT_MEMLOCK where it's charged, see `memlock`
+    pub perf_buffer_pages: Option<usize>,
+    /// Leave sends to loopback addresses out of the sampled events in the kernel,
+    /// so local traffic (sidecar proxies, health checks) doesn't read as egress.
+    /// See `with_loopback`
+    pub exclude_loopback: bool,
+    /// Send runs of drops with the same reason on the same device as one event
+    /// from the kernel. None sends every drop alone; see `without_drop_coalescing`
+    pub drop_coalescing: Option<DropCoalescing>,
+    /// Hold events on the processing side to release them in timestamp order
+    /// across CPUs. None (the default) processes them as the readers hand them
+    /// over, unless burst correlation is on; see `with_event_reordering`
+    pub event_reordering: Option<EventReordering>,
+    /// Accept eBPF objects with a newer schema as long as the event types we know
+    /// are unchanged; see `allow_forward_compatible`
+    pub forward_compatible: bool,
+    /// Bucket send and drop events to fill `burst_drop_correlation`. None (the
+    /// default) skips the bucketing; see `with_burst_correlation`
+    #[cfg(feature = "collector-core")]
+    pub burst_correlation: Option<BurstCorrelationConfig>,
+    /// Time the gaps between drops for `CongestionSignals::drop_interarrival`.
+    /// Off by default; see `with_drop_interarrival`
+    #[cfg(feature = "collector-core")]
+    pub drop_interarrival: bool,
+    /// Add sends, drops and send buffer pressure up per cgroup in the kernel for
+    /// `read_per_cgroup()`. None (the default) leaves the map unwritten; see
+    /// `with_cgroup_aggregation`
+    #[cfg(feature = "collector-core")]
+    pub cgroup_aggregation: Option<CgroupAggregationConfig>,
+    /// Filter by network namespace and break signals down per namespace for
+    /// `read_per_netns()`. None (the default) counts every namespace together;
+    /// see `with_netns`
+    #[cfg(feature = "collector-core")]
+    pub netns: Option<NetnsConfig>,
+    /// Break sends and send buffer pressure down by the sockets' DSCP and
+    /// priority for `read_per_class()`. None (the default) skips it; see
+    /// `with_traffic_classes`
+    #[cfg(feature = "collector-core")]
+    pub traffic_classes: Option<TrafficClassConfig>,
+    /// Check the socket-state offsets against a socket of known sndbuf when
+    /// collection starts, reported in `health()`. On by default; see
+    /// `without_calibration`
+    #[cfg(feature = "collector-core")]
+    pub calibration: Option<CalibrationConfig>,
+    /// Compare each interval with the kernel's SNMP counters, reported by
+    /// `accuracy()` and warned about in `health()`. On by default; see
+    /// `without_accuracy_check`
+    #[cfg(feature = "collector-core")]
+    pub accuracy: Option<AccuracyConfig>,
+    /// Where `load()` looks for a collector already active, and refuses with
+    /// `CollectorError::AlreadyActive` if it finds one. Host-wide by default;
+    /// see `with_conflict_check`
+    #[cfg(feature = "collector-core")]
+    pub conflict_check: ConflictCheck,
+    /// Let `load_or_share()` hand out the collector already active in this
+    /// process instead of refusing; see `allow_shared`
+    #[cfg(feature = "collector-core")]
+    pub allow_shared: bool,
+    /// Have `verify_attachments()` scan our attach points for other programs
+    /// and for ours gone, see `CongestionCollector::scan_co_attachments`. On
+    /// by default; see `without_co_attachment_scan`
+    #[cfg(feature = "collector-core")]
+    pub co_attachment_scan: bool,
+    /// Save cumulative totals here and carry them over to the next load in the
+    /// same boot, see `CongestionCollector::cumulative`. None (the default)
+    /// starts from zero at every load; see `with_state_file`
+    #[cfg(feature = "collector-core")]
+    pub state_file: Option<StateFileConfig>,
+    /// 1-in-N ratio of a second send sampler run next to the primary one, its
+    /// estimates in `CongestionSignals::sampler_comparison`. None (the
+    /// default) runs the primary one alone; see `with_sampler_comparison`
+    #[cfg(feature = "collector-core")]
+    pub sampler_comparison: Option<u64>,
+    /// Sample sockets instead of events: every send and occupancy sample of
+    /// the sockets in the sample, none of the others. None (the default)
+    /// samples 1 in 100 events; see `with_socket_sampling`
+    #[cfg(feature = "collector-core")]
+    pub socket_sampling: Option<SocketSampling>,
+    /// Pair registered sockets' sends with the NET_RX rounds after them on the
+    /// same CPU, for `CongestionSignals::softirq_coupling`. None (the default)
+    /// skips it; see `with_softirq_coupling`
+    #[cfg(feature = "collector-core")]
+    pub softirq_coupling: Option<SoftirqCouplingConfig>,
+    /// Split NET_TX time between registered sockets' sends and other
+    /// traffic, for `CongestionSignals::softirq_attribution`. None (the
+    /// default) skips it; see `with_softirq_attribution`
+    #[cfg(feature = "collector-core")]
+    pub softirq_attribution: Option<SoftirqAttributionConfig>,
+    /// Send the NAPI polls of long NET_RX rounds and split softirq time per
+    /// device, for `CongestionCollector::read_per_interface`. None (the
+    /// default) sends none; see `with_softirq_breakdown`
+    #[cfg(feature = "collector-core")]
+    pub softirq_breakdown: Option<SoftirqBreakdownConfig>,
+    /// Keep the last seconds of raw events and write them out around a Red
+    /// state or a `capture_now()`. None (the default) keeps nothing; see
+    /// `with_triggered_capture`
+    #[cfg(feature = "collector-core")]
+    pub triggered_capture: Option<CaptureConfig>,
+    /// Sockets `CongestionCollector::trace_socket` traces at once, capped at
+    /// MAX_TRACED_SOCKETS. Each sends every event it has, not 1 in 100
+    #[cfg(feature = "collector-core")]
+    pub max_traced_sockets: usize,
+    /// Hold the collector's own CPU to a budget, stepping down a ladder of
+    /// cheaper collection while it's exceeded. None (the default) never
+    /// degrades; see `with_overhead_budget`
+    #[cfg(feature = "collector-core")]
+    pub overhead_budget: Option<OverheadBudget>,
+    /// Raise RLIMIT_MEMLOCK at load on kernels that charge eBPF memory to it
+    /// (before 5.11), and how far perf buffers may be cut when it can't be; see
+    /// `with_memlock`
+    #[cfg(feature = "collector-core")]
+    pub memlock: MemlockConfig,
+    /// Fail the load when the kernel rejects a core program group, rather
+    /// than run with the groups that verified; see `strict`
+    #[cfg(feature = "collector-core")]
+    pub strict: bool,
+    /// What interval bounds, history stamps and the health checks' windows
+    /// are measured on, the system's by default; see `with_clock`
+    #[cfg(feature = "collector-core")]
+    pub clock: Arc<dyn Clock>,
+    /// Tick `snapshots()` on wall-clock multiples of its interval (every :00.000,
+    /// :00.200, ... at 200 ms) and stamp each one with `aligned_start`/`aligned_end`;
+    /// see `with_wall_clock_alignment`
+    #[cfg(feature = "async-runtime")]
+    pub align_to_wall_clock: bool,
+    /// Whether readers, the processing task and the snapshot publisher are
+    /// started again after a panic or error, see `CongestionCollector::failures`
+    #[cfg(feature = "async-runtime")]
+    pub restart_policies: RestartPolicies,
+    /// Send a datagram through the probes and readers every period and mark
+    /// the pipeline dead when they stop coming through. None (the default)
+    /// sends none; see `with_heartbeat`
+    #[cfg(feature = "async-runtime")]
+    pub heartbeat: Option<HeartbeatConfig>,
+}
+
+impl Default for CollectorConfig {
+    fn default() -> Self {
+        Self {
+            event_queue_capacity: 8192,
+            subscriber_capacity: 1024,
+            staleness_window: Duration::from_secs(30),
+            limitation: LimitationThresholds::default(),
+            concentration: ConcentrationConfig::default(),
+            talker_ranking: TalkerRanking::default(),
+            socket_idle_ttl: Duration::from_secs(60),
+            max_tracked_sockets: 65_536,
+            socket_table_capacity: 4096,
+            egress_interfaces: Vec::new(),
+            exact_socket_count_limit: 4096,
+            track_flows: false,
+            reader_max_retries: 10,
+            reader_batch_size: 64,
+            reader_wakeup: ReaderWakeup::PerEvent,
+            perf_buffer_pages: None,
+            exclude_loopback: true,
+            drop_coalescing: Some(DropCoalescing::default()),
+            event_reordering: None,
+            forward_compatible: false,
+            #[cfg(feature = "collector-core")]
+            burst_correlation: None,
+            #[cfg(feature = "collector-core")]
+            drop_interarrival: false,
+            #[cfg(feature = "collector-core")]
+            cgroup_aggregation: None,
+            #[cfg(feature = "collector-core")]
+            netns: None,
+            #[cfg(feature = "collector-core")]
+            traffic_classes: None,
+            #[cfg(feature = "collector-core")]
+            calibration: Some(CalibrationConfig::default()),
+            #[cfg(feature = "collector-core")]
+            accuracy: Some(AccuracyConfig::default()),
+            #[cfg(feature = "collector-core")]
+            conflict_check: ConflictCheck::default(),
+            #[cfg(feature = "collector-core")]
+            allow_shared: false,
+            #[cfg(feature = "collector-core")]
+            co_attachment_scan: true,
+            #[cfg(feature = "collector-core")]
+            state_file: None,
+            #[cfg(feature = "collector-core")]
+            sampler_comparison: None,
+            #[cfg(feature = "collector-core")]
+            socket_sampling: None,
+            #[cfg(feature = "collector-core")]
+            softirq_coupling: None,
+            #[cfg(feature = "collector-core")]
+            softirq_attribution: None,
+            #[cfg(feature = "collector-core")]
+            softirq_breakdown: None,
+            #[cfg(feature = "collector-core")]
+            triggered_capture: None,
+            #[cfg(feature = "collector-core")]
+            max_traced_sockets: 4,
+            #[cfg(feature = "collector-core")]
+            overhead_budget: None,
+            #[cfg(feature = "collector-core")]
+            memlock: MemlockConfig::default(),
+            #[cfg(feature = "collector-core")]
+            strict: false,
+            #[cfg(feature = "collector-core")]
+            clock: system_clock(),
+            #[cfg(feature = "async-runtime")]
+            align_to_wall_clock: false,
+            #[cfg(feature = "async-runtime")]
+            restart_policies: RestartPolicies::default(),
+            #[cfg(feature = "async-runtime")]
+            heartbeat: None,
+        }
+    }
+}
+
+/// How long a run of drops is held on its CPU before it is sent. The event
+/// carries the count and the time from first to last drop, and the timestamp
+/// of the last
+#[derive(Debug, Clone, Copy)]
+pub struct DropCoalescing {
+    /// Send a run once this long has passed since its first drop, at the next
+    /// drop or softirq on that CPU
+    pub max_span: Duration,
+    /// Send a run once it has this many drops
+    pub max_drops: u32,
+}
+
+impl Default for DropCoalescing {
+    fn default() -> Self {
+        Self {
+            max_span: Duration::from_millis(1),
+            max_drops: 256,
+        }
+    }
+}
+
+/// When the per-CPU readers wake. Each wakeup is a task (or thread) scheduled
+/// for a read, so at tens of thousands of events a second waking per event is
+/// most of what the readers cost; batching trades that for event latency. The
+/// interval reads don't see the difference beyond a read's worth of events
+/// landing in the next interval
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReaderWakeup {
+    /// The kernel signals readiness on every sample. Lowest latency, one
+    /// wakeup per event at moderate rates
+    #[default]
+    PerEvent,
+    /// The kernel signals readiness once this much is in a CPU's buffer, and
+    /// the reader also reads every `max_delay` so a quiet CPU's last events
+    /// don't wait for more. Wakeups drop by about the watermark under load
+    Watermark {
+        watermark: WakeupWatermark,
+        max_delay: Duration,
+    },
+    /// Ignore the kernel's signal and read every CPU's buffer at this period:
+    /// a fixed wakeup rate whatever the load, events up to a period late. A
+    /// buffer must hold a period's events, or the kernel drops (and counts, in
+    /// `ReaderStats::perf_lost`) the rest
+    Timer(Duration),
+}
+
+/// How full a perf buffer gets before the kernel wakes its reader
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeupWatermark {
+    /// Samples written since the last wakeup
+    Events(u32),
+    /// Bytes waiting in the buffer, up to half of it
+    Bytes(u32),
+}
+
+impl CollectorConfig {
+    /// Load objects built against a newer schema instead of refusing them.
+    /// Events of types this build doesn't know are counted
+    /// (`ReaderStats::unknown_events`) and skipped
+    pub fn allow_forward_compatible(mut self) -> Self {
+        self.forward_compatible = true;
+        self
+    }
+
+    /// Count loopback sends too, reported apart as
+    /// `CongestionSignals::loopback_send_bytes`
+    pub fn with_loopback(mut self) -> Self {
+        self.exclude_loopback = false;
+        self
+    }
+
+    /// Count distinct flows, a socket and the address and port it sends to, as
+    /// `CongestionSignals::active_flows`: one unconnected QUIC server socket
+    /// talking to thousands of peers is one socket but thousands of flows.
+    /// Costs a few probe reads per sampled UDP send
+    pub fn with_flow_tracking(mut self) -> Self {
+        self.track_flows = true;
+        self
+    }
+
+    /// Send an event per drop, so each drop's timestamp is its own. A
+    /// microburst can then fill the perf buffers with thousands of drops
+    pub fn without_drop_coalescing(mut self) -> Self {
+        self.drop_coalescing = None;
+        self
+    }
+
+    /// Batch reader wakeups, e.g. `ReaderWakeup::Timer(Duration::from_millis(10))`
+    /// for at most 100 per second per CPU. `ReaderStats::wakeups_per_sec`
+    /// shows the rate either way
+    pub fn with_reader_wakeup(mut self, wakeup: ReaderWakeup) -> Self {
+        self.reader_wakeup = wakeup;
+        self
+    }
+
+    /// Release events to the processing side, subscribers included, in
+    /// timestamp order, holding each up to `config.watermark`. Ones later than
+    /// that are counted in `ReaderStats::reordering` and processed as they come
+    pub fn with_event_reordering(mut self, config: EventReordering) -> Self {
+        self.event_reordering = Some(config);
+        self
+    }
+
+    /// The reordering stage to run: the configured one, or the default when
+    /// burst correlation needs its buckets filled in order, the drop gaps their
+    /// drops, the softirq breakdown its polls or the softirq attribution its
+    /// sends
+    #[cfg(feature = "collector-core")]
+    pub(crate) fn reordering(&self) -> Option<EventReordering> {
+        self.event_reordering.or_else(|| {
+            (self.burst_correlation.is_some()
+                || self.drop_interarrival
+                || self.softirq_breakdown.is_some()
+                || self.softirq_attribution.is_some())
+            .then(EventReordering::default)
+        })
+    }
+
+    /// Correlate send bursts with the drops that follow them, reported per
+    /// interval as `CongestionSignals::burst_drop_correlation`. Costs a map
+    /// update per send sample and drop on the processing side, and turns on
+    /// the default event reordering unless it's configured
+    #[cfg(feature = "collector-core")]
+    pub fn with_burst_correlation(mut self, config: BurstCorrelationConfig) -> Self {
+        self.burst_correlation = Some(config);
+        self
+    }
+
+    /// Histogram the time between drops, with a burstiness index, per interval
+    /// as `CongestionSignals::drop_interarrival`. Costs a few operations per
+    /// drop event on the processing side, and turns on the default event
+    /// reordering unless it's configured
+    #[cfg(feature = "collector-core")]
+    pub fn with_drop_interarrival(mut self) -> Self {
+        self.drop_interarrival = true;
+        self
+    }
+
+    /// Attribute signals to the cgroup of the task sending, for
+    /// `CongestionCollector::read_per_cgroup`. Costs a helper call and a per-CPU
+    /// map update in the kernel per sampled send, drop and send buffer sample
+    #[cfg(feature = "collector-core")]
+    pub fn with_cgroup_aggregation(mut self, config: CgroupAggregationConfig) -> Self {
+        self.cgroup_aggregation = Some(config);
+        self
+    }
+
+    /// Attribute sends and send buffer samples to the network namespace of the
+    /// socket, for `CongestionCollector::read_per_netns`, and count only the
+    /// namespaces in `config.allow`. Costs a hash lookup per event on the
+    /// readers and the processing side
+    #[cfg(feature = "collector-core")]
+    pub fn with_netns(mut self, config: NetnsConfig) -> Self {
+        self.netns = Some(config);
+        self
+    }
+
+    /// Attribute sends and send buffer samples to the traffic classes in
+    /// `config`, for `CongestionCollector::read_per_class`. Costs a lookup
+    /// per event on the processing side
+    #[cfg(feature = "collector-core")]
+    pub fn with_traffic_classes(mut self, config: TrafficClassConfig) -> Self {
+        self.traffic_classes = Some(config);
+        self
+    }
+
+    /// Start collecting without the socket-state calibration, which opens a
+    /// loopback TCP connection and sends on it for a moment
+    #[cfg(feature = "collector-core")]
+    pub fn without_calibration(mut self) -> Self {
+        self.calibration = None;
+        self
+    }
+
+    /// Skip the SNMP comparison, which reads /proc/net/snmp and
+    /// /proc/net/netstat on every interval read
+    #[cfg(feature = "collector-core")]
+    pub fn without_accuracy_check(mut self) -> Self {
+        self.accuracy = None;
+        self
+    }
+
+    /// Look for an active collector in this process only, or not at all. Off,
+    /// the collector attaches next to any other and both count every event
+    #[cfg(feature = "collector-core")]
+    pub fn with_conflict_check(mut self, check: ConflictCheck) -> Self {
+        self.conflict_check = check;
+        self
+    }
+
+    /// With a collector already active in this process, have
+    /// `CongestionCollector::load_or_share` return a `SharedCollector` on its
+    /// signals rather than fail. `load()` can't return one and still fails
+    #[cfg(feature = "collector-core")]
+    pub fn allow_shared(mut self) -> Self {
+        self.allow_shared = true;
+        self
+    }
+
+    /// Leave the co-attachment scan out of `verify_attachments()`, which walks
+    /// every process's descriptors in /proc each time. Lost bpf links are
+    /// still found, a lost tc egress classifier isn't
+    #[cfg(feature = "collector-core")]
+    pub fn without_co_attachment_scan(mut self) -> Self {
+        self.co_attachment_scan = false;
+        self
+    }
+
+    /// Keep `CongestionCollector::cumulative` counting across restarts: totals
+    /// are saved to `config.path` every `save_every` and on drop, and restored
+    /// at load if the file is from this boot and no older than `max_age`
+    #[cfg(feature = "collector-core")]
+    pub fn with_state_file(mut self, config: StateFileConfig) -> Self {
+        self.state_file = Some(config);
+        self
+    }
+
+    /// Sample sends a second time, 1 in `ratio` on a counter of its own, to see
+    /// whether that ratio tracks the 1-in-100 one; see `SamplerComparison`.
+    /// Costs an extra map lookup per send in the kernel, and events for the
+    /// sends only the second sampler takes
+    #[cfg(feature = "collector-core")]
+    pub fn with_sampler_comparison(mut self, ratio: u64) -> Self {
+        self.sampler_comparison = Some(ratio.max(1));
+        self
+    }
+
+    /// Sample about `fraction` of sockets fully, chosen by a hash of the socket
+    /// id, rather than 1 in 100 of every socket's events: `read_per_socket`
+    /// then has every send of the sockets it reports, and totals are scaled
+    /// by `1 / fraction` (`SampleScale::per_socket`). 1/16 sends about six
+    /// times the events of 1-in-100 sampling. Turns off the sampler comparison
+    #[cfg(feature = "collector-core")]
+    pub fn with_socket_sampling(mut self, fraction: f64) -> Self {
+        self.socket_sampling = Some(SocketSampling::new(fraction));
+        self
+    }
+
+    /// Measure how often registered sockets' sends wait out a long NET_RX
+    /// softirq on their CPU, reported per interval as
+    /// `CongestionSignals::softirq_coupling`. Costs a set lookup per send
+    /// sample on the processing side
+    #[cfg(feature = "collector-core")]
+    pub fn with_softirq_coupling(mut self, config: SoftirqCouplingConfig) -> Self {
+        self.softirq_coupling = Some(config);
+        self
+    }
+
+    /// Split NET_TX softirq time between registered sockets' sends and other
+    /// traffic, reported per interval as `CongestionSignals::softirq_attribution`.
+    /// The governor then backs off on the external part only. Costs a set
+    /// lookup and a queue push per send sample on the processing side, and
+    /// turns on the default event reordering unless it's configured
+    #[cfg(feature = "collector-core")]
+    pub fn with_softirq_attribution(mut self, config: SoftirqAttributionConfig) -> Self {
+        self.softirq_attribution = Some(config);
+        self
+    }
+
+    /// Split NET_RX time between the NAPI devices polled in it, read with
+    /// `CongestionCollector::read_per_interface`. NET_RX rounds of
+    /// `config.long_softirq` or longer send up to 8 poll events each; turns on
+    /// the default event reordering unless it's configured
+    #[cfg(feature = "collector-core")]
+    pub fn with_softirq_breakdown(mut self, config: SoftirqBreakdownConfig) -> Self {
+        self.softirq_breakdown = Some(config);
+        self
+    }
+
+    /// Buffer decoded events for `config.before` and write a recording of the
+    /// window around each trigger, see `TriggeredCapture`. Costs a copy of each
+    /// event on the processing side and up to twice `config.max_bytes` of memory
+    #[cfg(feature = "collector-core")]
+    pub fn with_triggered_capture(mut self, config: CaptureConfig) -> Self {
+        self.triggered_capture = Some(config);
+        self
+    }
+
+    /// Enforce `budget` on the collector's own CPU, process and BPF programs
+    /// together. Readings are taken by `CongestionCollector::enforce_overhead_budget`,
+    /// call it at a steady cadence; BPF run time needs `BpfStats::enable`
+    #[cfg(feature = "collector-core")]
+    pub fn with_overhead_budget(mut self, budget: OverheadBudget) -> Self {
+        self.overhead_budget = Some(budget);
+        self
+    }
+
+    /// What load does about a low RLIMIT_MEMLOCK before 5.11. By default it's
+    /// raised to unlimited, which past the hard limit takes CAP_SYS_RESOURCE;
+    /// when it stays low the perf buffers are cut to fit, down to
+    /// `min_buffer_pages`, and `health()` says so
+    #[cfg(feature = "collector-core")]
+    pub fn with_memlock(mut self, config: MemlockConfig) -> Self {
+        self.memlock = config;
+        self
+    }
+
+    /// Fail the load at the first core program group the kernel rejects, with
+    /// `CollectorError::ProgramRejected` and its verifier log, the way every
+    /// load did before `PROGRAM_GROUPS`. By default the load goes on with the
+    /// groups that verified and `health()` reports the rest. Groups the host
+    /// lacks a requirement for are skipped either way
+    #[cfg(feature = "collector-core")]
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Measure userspace time on `clock`, e.g. a `ManualClock` to step
+    /// through staleness and state-save windows. Event timestamps stay the
+    /// kernel's
+    #[cfg(feature = "collector-core")]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Align snapshot intervals to the system clock so intervals from different
+    /// hosts cover the same wall-clock time, as far as their clocks agree. When
+    /// the clock steps, the interval spanning the step is dropped and ticking
+    /// realigns to the new time
+    #[cfg(feature = "async-runtime")]
+    pub fn with_wall_clock_alignment(mut self) -> Self {
+        self.align_to_wall_clock = true;
+        self
+    }
+
+    /// Tell a quiet interval from a broken collector: a tiny loopback datagram
+    /// every `config.period` whose send event has to come back through the
+    /// readers, `HealthReport::heartbeat` with what came of it, and a
+    /// `Component::Heartbeat` failure after `max_missed` in a row. Its events
+    /// count in none of the signals. Costs a socket, a send and a receive per
+    /// period, and a cookie compare per event in the readers
+    #[cfg(feature = "async-runtime")]
+    pub fn with_heartbeat(mut self, config: HeartbeatConfig) -> Self {
+        self.heartbeat = Some(config);
+        self
+    }
+}
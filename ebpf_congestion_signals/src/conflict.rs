@@ -0,0 +1,264 @@
+//One collector per process, and per host. A second one attaches every probe
+//again and counts every event twice, so `load()` claims a process-wide slot
+//first and, by default, a host-wide lock: flock on a directory in bpffs, or on
+//a file in /run where /sys/fs/bpf isn't mounted. The lock goes with the process
+//however it exits, so a crashed collector never leaves one behind. It's
+//best-effort: if neither can be created the collector loads with a warning.
+//
+//With `CollectorConfig::allow_shared` a second collector in the same process
+//gets a `SharedCollector` on the first one's signals instead. Nothing is
+//shared across processes.
+
+use crate::collector::IntervalReader;
+use crate::{CollectorError, CongestionSignals, SessionHandle, SocketSignals};
+use std::collections::HashMap;
+use std::fs::{DirBuilder, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+const BPFFS_LOCK: &str = "/sys/fs/bpf/ebpf_congestion_signals";
+const RUN_LOCK: &str = "/run/ebpf_congestion_signals.lock";
+
+/// How far `load()` looks for another active collector
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictCheck {
+    /// In this process, and on the host through the lock under /sys/fs/bpf
+    #[default]
+    Host,
+    /// In this process only
+    Process,
+    /// Attach regardless, and count every event as many times as there are
+    /// collectors. Such a collector isn't seen by later checks either; for
+    /// side-by-side comparisons of configurations
+    Off,
+}
+
+enum Slot {
+    Free,
+    // Claimed, probes still attaching
+    Loading,
+    Active {
+        reader: Weak<IntervalReader>,
+        socket_idle_ttl: Duration,
+    },
+}
+
+static ACTIVE: Mutex<Slot> = Mutex::new(Slot::Free);
+
+/// The slot (and host lock) held by a loaded collector, released on drop
+pub(crate) struct Claim {
+    holds_slot: bool,
+    _host_lock: Option<File>,
+}
+
+impl Claim {
+    pub(crate) fn take(check: ConflictCheck) -> Result<Self, CollectorError> {
+        if check == ConflictCheck::Off {
+            return Ok(Self {
+                holds_slot: false,
+                _host_lock: None,
+            });
+        }
+        {
+            let mut slot = ACTIVE.lock().unwrap();
+            if !matches!(*slot, Slot::Free) {
+                return Err(CollectorError::AlreadyActive { system_wide: false });
+            }
+            *slot = Slot::Loading;
+        }
+        // From here dropping the claim frees the slot again
+        let mut claim = Self {
+            holds_slot: true,
+            _host_lock: None,
+        };
+        if check == ConflictCheck::Host {
+            claim._host_lock = host_lock()?;
+        }
+        Ok(claim)
+    }
+
+    /// Let `allow_shared` loads find this collector
+    pub(crate) fn publish(&self, reader: &Arc<IntervalReader>, socket_idle_ttl: Duration) {
+        if self.holds_slot {
+            *ACTIVE.lock().unwrap() = Slot::Active {
+                reader: Arc::downgrade(reader),
+                socket_idle_ttl,
+            };
+        }
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        if self.holds_slot {
+            *ACTIVE.lock().unwrap() = Slot::Free;
+        }
+    }
+}
+
+/// The collector active in this process, if it finished loading
+pub(crate) fn active() -> Option<SharedCollector> {
+    match &*ACTIVE.lock().unwrap() {
+        Slot::Active {
+            reader,
+            socket_idle_ttl,
+        } => Some(SharedCollector {
+            reader: reader.clone(),
+            socket_idle_ttl: *socket_idle_ttl,
+        }),
+        _ => None,
+    }
+}
+
+/// Held host-wide lock, None where neither place could be used
+fn host_lock() -> Result<Option<File>, CollectorError> {
+    let opened = DirBuilder::new()
+        .mode(0o700)
+        .create(BPFFS_LOCK)
+        .or_else(|e| match e.kind() {
+            ErrorKind::AlreadyExists => Ok(()),
+            _ => Err(e),
+        })
+        .and_then(|_| File::open(BPFFS_LOCK))
+        .map(|dir| (dir, BPFFS_LOCK))
+        .or_else(|_| {
+            OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(RUN_LOCK)
+                .map(|file| (file, RUN_LOCK))
+        });
+    let (mut file, path) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            log::warn!(
+                "no host-wide collector lock ({}), only this process is checked",
+                e
+            );
+            return Ok(None);
+        }
+    };
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let e = std::io::Error::last_os_error();
+        if e.kind() == ErrorKind::WouldBlock {
+            return Err(CollectorError::AlreadyActive { system_wide: true });
+        }
+        log::warn!(
+            "couldn't lock {} ({}), only this process is checked",
+            path,
+            e
+        );
+        return Ok(None);
+    }
+    // Who holds it, for whoever finds it locked; a directory can't say
+    if !Path::new(path).is_dir() {
+        let _ = file
+            .set_len(0)
+            .and_then(|_| writeln!(file, "{}", std::process::id()));
+    }
+    Ok(Some(file))
+}
+
+/// A handle on the signals of the collector already active in this process,
+/// from `CongestionCollector::load_or_share`. It attaches nothing and resets
+/// nothing: the interval reads stay the owner's, and this sees what they
+/// produced. Everything comes back empty once the owner is dropped
+#[derive(Clone)]
+pub struct SharedCollector {
+    reader: Weak<IntervalReader>,
+    socket_idle_ttl: Duration,
+}
+
+impl SharedCollector {
+    /// Whether the collector this shares is still loaded
+    pub fn is_active(&self) -> bool {
+        self.reader.strong_count() > 0
+    }
+
+    /// Up to `n` of the owner's most recent interval reads, oldest first
+    pub fn recent_intervals(&self, n: usize) -> Vec<CongestionSignals> {
+        self.reader
+            .upgrade()
+            .map_or_else(Vec::new, |reader| reader.recent_intervals(n))
+    }
+
+    /// See `CongestionCollector::read_per_socket`
+    pub fn read_per_socket(&self) -> HashMap<u64, SocketSignals> {
+        self.reader.upgrade().map_or_else(HashMap::new, |reader| {
+            reader.read_per_socket(self.socket_idle_ttl)
+        })
+    }
+
+    /// See `CongestionCollector::last_seen`
+    pub fn last_seen(&self) -> HashMap<u32, u64> {
+        self.reader
+            .upgrade()
+            .map_or_else(HashMap::new, |reader| reader.last_seen())
+    }
+
+    /// See `CongestionCollector::begin_session`; the session is merged from
+    /// the owner's interval reads. None once the owner is gone
+    pub fn begin_session(&self, label: &str) -> Option<SessionHandle> {
+        self.reader
+            .upgrade()
+            .map(|reader| SessionHandle::begin(reader, label))
+    }
+}
+
+/// What `CongestionCollector::load_or_share` got
+pub enum LoadedCollector {
+    /// No other collector was active, this one attached its own probes
+    Owned(Box<crate::CongestionCollector>),
+    /// Another one in this process was, and `allow_shared` was set
+    Shared(SharedCollector),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, Replay};
+
+    // The slot is process-wide, so this is one test rather than several
+    // racing for it
+    #[test]
+    fn a_second_claim_is_refused_until_the_first_is_dropped() {
+        let first = Claim::take(ConflictCheck::Process).unwrap();
+        assert_eq!(
+            Claim::take(ConflictCheck::Process).err(),
+            Some(CollectorError::AlreadyActive { system_wide: false })
+        );
+        // Still loading: nothing to share yet
+        assert!(active().is_none());
+        // Off neither checks nor takes the slot
+        drop(Claim::take(ConflictCheck::Off).unwrap());
+        assert!(active().is_none());
+
+        let replay = Replay::new(crate::CollectorConfig::default(), 1);
+        first.publish(&replay.interval, Duration::from_secs(30));
+        let shared = active().unwrap();
+        replay.feed(&[fixtures::udp_send(1, 0, 7, 1200)]);
+        replay.read(Duration::from_secs(1));
+        // The owner's reads, not reset again
+        assert!(shared.is_active());
+        let recent = shared.recent_intervals(4);
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].send_bytes, 1200);
+        let session = shared.begin_session("shared").unwrap();
+        replay.read(Duration::from_secs(1));
+        assert_eq!(session.end().intervals, 1);
+
+        // The owner going away empties the handle
+        drop(replay);
+        assert!(!shared.is_active());
+        assert!(shared.recent_intervals(4).is_empty());
+        assert!(shared.begin_session("late").is_none());
+        drop(first);
+        assert!(active().is_none());
+        drop(Claim::take(ConflictCheck::Process).unwrap());
+    }
+}
@@ -0,0 +1,272 @@
+//How often a registered socket's sends ran into a long NET_RX softirq on the
+//same CPU. A send's completion and the ACKs it's waiting on are processed in
+//NET_RX; when a long round follows a send on its CPU, they wait it out. Interval
+//aggregates show softirq time and send volume apart; this pairs them per send.
+//
+//Each registered send is held per CPU until that CPU's next NET_RX exit. The
+//exit carries the round's duration, so its start is known too: a send followed
+//by a long round exiting within `max_delay` is delayed by it, by the time from
+//send to exit, and one the round's window covers was sent while it was in
+//progress. Only same-CPU pairs are compared, and each CPU's events reach the
+//processing side in order, reordering stage or not.
+//
+//Registered sockets' sends are sampled 1 in 100 in events like any other's, so
+//the counts are of sampled sends; the delayed share is the measure.
+
+use crate::{CongestionEvent, EVENT_SOFTIRQ_EXIT, EVENT_TCP_SEND, EVENT_UDP_SEND};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+// vec_nr of NET_RX_SOFTIRQ
+const NET_RX_SOFTIRQ: u32 = 3;
+// Sends held per CPU waiting for an exit; past it the oldest is checked as not
+// delayed
+const MAX_PENDING: usize = 1024;
+
+/// Turns on `CongestionSignals::softirq_coupling`, see
+/// `CollectorConfig::with_softirq_coupling`
+#[derive(Debug, Clone)]
+pub struct SoftirqCouplingConfig {
+    /// A NET_RX round at least this long counts as one that delays sends
+    pub long_softirq: Duration,
+    /// Rounds exiting later than this after a send don't count against it
+    pub max_delay: Duration,
+}
+
+impl Default for SoftirqCouplingConfig {
+    fn default() -> Self {
+        Self {
+            long_softirq: Duration::from_micros(50),
+            max_delay: Duration::from_millis(2),
+        }
+    }
+}
+
+/// One interval's registered sends paired with the NET_RX rounds after them
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SoftirqCoupling {
+    /// Sampled sends of registered sockets whose CPU's next round was seen, or
+    /// that waited past `max_delay` without one
+    pub sends_checked: u64,
+    /// Of those, followed by a long round that exited within `max_delay`
+    pub sends_delayed_by_softirq: u64,
+    /// Of the delayed ones, sent while the round was already in progress
+    pub sends_in_softirq: u64,
+    /// From send to the round's exit, over the delayed sends. 0 without any
+    pub avg_softirq_delay_ns: u64,
+}
+
+impl SoftirqCoupling {
+    /// Share of the checked sends that were delayed. None without any
+    pub fn delayed_fraction(&self) -> Option<f64> {
+        (self.sends_checked > 0)
+            .then(|| self.sends_delayed_by_softirq as f64 / self.sends_checked as f64)
+    }
+}
+
+/// The pairing on its own, e.g. over a recording's events. Feed it events in
+/// per-CPU order with `record` and take the totals with `take_interval`
+pub struct SoftirqCouplingTracker {
+    long_softirq_ns: u64,
+    max_delay_ns: u64,
+    registered: HashSet<u64>,
+    // cpu -> registered send timestamps since its last NET_RX exit
+    pending: HashMap<u32, VecDeque<u64>>,
+    checked: u64,
+    delayed: u64,
+    in_softirq: u64,
+    delay_ns: u64,
+}
+
+impl SoftirqCouplingTracker {
+    pub fn new(config: &SoftirqCouplingConfig) -> Self {
+        Self {
+            long_softirq_ns: config.long_softirq.as_nanos() as u64,
+            max_delay_ns: config.max_delay.as_nanos() as u64,
+            registered: HashSet::new(),
+            pending: HashMap::new(),
+            checked: 0,
+            delayed: 0,
+            in_softirq: 0,
+            delay_ns: 0,
+        }
+    }
+
+    /// Pair this socket's sends from now on
+    pub fn register(&mut self, socket_id: u64) {
+        self.registered.insert(socket_id);
+    }
+
+    pub fn unregister(&mut self, socket_id: u64) {
+        self.registered.remove(&socket_id);
+    }
+
+    pub fn record(&mut self, event: &CongestionEvent) {
+        match event.event_type {
+            EVENT_UDP_SEND | EVENT_TCP_SEND => {
+                let socket_id = unsafe { event.data.sendmsg.socket_id };
+                if !self.registered.contains(&socket_id) {
+                    return;
+                }
+                let ts = event.timestamp_ns;
+                let max_delay_ns = self.max_delay_ns;
+                let pending = self.pending.entry(event.cpu_id).or_default();
+                // No round came in time for these
+                while pending
+                    .front()
+                    .is_some_and(|&sent| sent + max_delay_ns < ts)
+                {
+                    pending.pop_front();
+                    self.checked += 1;
+                }
+                if pending.len() == MAX_PENDING {
+                    pending.pop_front();
+                    self.checked += 1;
+                }
+                pending.push_back(ts);
+            }
+            EVENT_SOFTIRQ_EXIT => {
+                let softirq = unsafe { event.data.softirq };
+                if softirq.vec_nr != NET_RX_SOFTIRQ {
+                    return;
+                }
+                let Some(pending) = self.pending.get_mut(&event.cpu_id) else {
+                    return;
+                };
+                let exit = event.timestamp_ns;
+                let start = exit.saturating_sub(softirq.duration_ns);
+                let long = softirq.duration_ns >= self.long_softirq_ns;
+                while let Some(&sent) = pending.front() {
+                    if sent > exit {
+                        break;
+                    }
+                    pending.pop_front();
+                    self.checked += 1;
+                    if long && exit - sent <= self.max_delay_ns {
+                        self.delayed += 1;
+                        self.delay_ns += exit - sent;
+                        if sent >= start {
+                            self.in_softirq += 1;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Sends held waiting for their CPU's next round
+    pub fn pending(&self) -> usize {
+        self.pending.values().map(VecDeque::len).sum()
+    }
+
+    /// What was paired since the last call. Sends still waiting for a round
+    /// are counted in the interval it comes in
+    pub fn take_interval(&mut self) -> SoftirqCoupling {
+        let coupling = SoftirqCoupling {
+            sends_checked: self.checked,
+            sends_delayed_by_softirq: self.delayed,
+            sends_in_softirq: self.in_softirq,
+            avg_softirq_delay_ns: self.delay_ns.checked_div(self.delayed).unwrap_or(0),
+        };
+        self.checked = 0;
+        self.delayed = 0;
+        self.in_softirq = 0;
+        self.delay_ns = 0;
+        coupling
+    }
+
+    /// Drop what's held, e.g. after the kernel clock jumped
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{softirq_exit, udp_send};
+
+    const COUPLED: u64 = 7;
+    const UNCOUPLED: u64 = 9;
+
+    fn send(timestamp_ns: u64, cpu_id: u32, socket_id: u64) -> CongestionEvent {
+        udp_send(timestamp_ns, cpu_id, socket_id, 1200)
+    }
+
+    fn tracker() -> SoftirqCouplingTracker {
+        let mut tracker = SoftirqCouplingTracker::new(&SoftirqCouplingConfig {
+            long_softirq: Duration::from_micros(50),
+            max_delay: Duration::from_millis(2),
+        });
+        tracker.register(COUPLED);
+        tracker
+    }
+
+    /// Registered sends before a long NET_RX round, inside one, before a short
+    /// one, and too long before one, on two CPUs. Rounds on another CPU,
+    /// NET_TX rounds and unregistered sockets don't pair
+    #[test]
+    fn sends_pair_with_the_next_long_round_on_their_cpu() {
+        let mut tracker = tracker();
+        let timeline = [
+            // CPU 0: 100 µs before a round that starts after it
+            send(1_000_000, 0, COUPLED),
+            // CPU 1: its send must wait for CPU 1's round, not CPU 0's
+            send(1_050_000, 1, COUPLED),
+            softirq_exit(1_060_000, 1, 2, 500_000),
+            softirq_exit(1_100_000, 0, 3, 80_000),
+            softirq_exit(1_500_000, 1, 3, 60_000),
+            // CPU 0: inside a 100 µs round
+            send(2_000_000, 0, COUPLED),
+            softirq_exit(2_050_000, 0, 3, 100_000),
+            // CPU 0: before a round too short to count
+            send(3_000_000, 0, COUPLED),
+            softirq_exit(3_010_000, 0, 3, 20_000),
+            // CPU 0: the next round is 3 ms later, past max_delay
+            send(4_000_000, 0, COUPLED),
+            send(4_500_000, 0, UNCOUPLED),
+            softirq_exit(7_000_000, 0, 3, 200_000),
+            // CPU 1: still waiting at the read
+            send(8_000_000, 1, COUPLED),
+        ];
+        for event in &timeline {
+            tracker.record(event);
+        }
+        assert_eq!(tracker.pending(), 1);
+        assert_eq!(
+            tracker.take_interval(),
+            SoftirqCoupling {
+                sends_checked: 5,
+                sends_delayed_by_softirq: 3,
+                sends_in_softirq: 1,
+                avg_softirq_delay_ns: 200_000,
+            }
+        );
+
+        // The held send times out behind a later one, which a round then covers
+        tracker.record(&send(20_000_000, 1, COUPLED));
+        tracker.record(&softirq_exit(20_100_000, 1, 3, 150_000));
+        tracker.unregister(COUPLED);
+        tracker.record(&send(21_000_000, 1, COUPLED));
+        assert_eq!(
+            tracker.take_interval(),
+            SoftirqCoupling {
+                sends_checked: 2,
+                sends_delayed_by_softirq: 1,
+                sends_in_softirq: 1,
+                avg_softirq_delay_ns: 100_000,
+            }
+        );
+        assert_eq!(tracker.pending(), 0);
+    }
+
+    #[test]
+    fn an_unregistered_host_checks_nothing() {
+        let mut tracker = tracker();
+        tracker.unregister(COUPLED);
+        tracker.record(&send(1_000_000, 0, COUPLED));
+        tracker.record(&softirq_exit(1_100_000, 0, 3, 80_000));
+        assert_eq!(tracker.take_interval(), SoftirqCoupling::default());
+    }
+}
@@ -0,0 +1,102 @@
+//CPU ids aren't 0..N. With CPUs offline, hotplug slots left empty or a
+//CPU-limited VM, /sys/devices/system/cpu/possible can read "0-3,128-131":
+//eight CPUs, and events from CPU 130. Per-CPU counters are kept in slots over
+//the possible CPUs' actual ids. `CpuSlots` maps an id to its slot through a
+//table as long as the highest id, bounds-checked. An id outside the set (a
+//CPU onlined past the possible mask, a corrupt event) lands in one extra
+//"other" slot and is counted there, instead of indexing past the end or
+//disappearing.
+
+pub(crate) const POSSIBLE_CPUS: &str = "/sys/devices/system/cpu/possible";
+
+/// CPU ids from a kernel cpu list ("0-3,8,10-11"), sorted, without duplicates.
+/// Items that don't parse are skipped
+pub fn parse_cpu_list(list: &str) -> Vec<u32> {
+    let mut cpus: Vec<u32> = list
+        .trim()
+        .split(',')
+        .flat_map(|item| match item.split_once('-') {
+            Some((first, last)) => match (first.parse::<u32>(), last.parse::<u32>()) {
+                (Ok(first), Ok(last)) => (first..=last).collect(),
+                _ => Vec::new(),
+            },
+            None => item.parse().into_iter().collect(),
+        })
+        .collect();
+    cpus.sort_unstable();
+    cpus.dedup();
+    cpus
+}
+
+/// Slot of each known CPU id, see the module docs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CpuSlots {
+    ids: Vec<u32>,
+    // By CPU id up to the highest known one, None for the gaps
+    slots: Vec<Option<u32>>,
+}
+
+impl CpuSlots {
+    pub fn new(ids: impl IntoIterator<Item = u32>) -> Self {
+        let mut ids: Vec<u32> = ids.into_iter().collect();
+        ids.sort_unstable();
+        ids.dedup();
+        let len = ids.last().map_or(0, |&highest| highest as usize + 1);
+        let mut slots = vec![None; len];
+        for (slot, &id) in ids.iter().enumerate() {
+            slots[id as usize] = Some(slot as u32);
+        }
+        Self { ids, slots }
+    }
+
+    /// The host's possible CPUs, from sysfs
+    pub fn possible() -> std::io::Result<Self> {
+        let list = std::fs::read_to_string(POSSIBLE_CPUS)?;
+        Ok(Self::new(parse_cpu_list(&list)))
+    }
+
+    /// The known ids, ascending: slot `i` is CPU `ids()[i]`
+    pub fn ids(&self) -> &[u32] {
+        &self.ids
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// The slot of CPU `cpu`, None when it isn't known
+    pub fn slot(&self, cpu: u32) -> Option<usize> {
+        self.slots
+            .get(cpu as usize)
+            .copied()
+            .flatten()
+            .map(|slot| slot as usize)
+    }
+
+    /// `cpu`'s slot, or the "other" one past the known CPUs'
+    /// (`len()`) when it isn't known
+    pub fn slot_or_other(&self, cpu: u32) -> usize {
+        self.slot(cpu).unwrap_or(self.ids.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sparse_ids_get_dense_slots() {
+        let ids = parse_cpu_list("0-3,128-131\n");
+        assert_eq!(ids, [0, 1, 2, 3, 128, 129, 130, 131]);
+        let cpus = CpuSlots::new(ids);
+        assert_eq!(cpus.slot(130), Some(6));
+        // A gap, and one far past the end
+        assert_eq!(cpus.slot(4), None);
+        assert_eq!(cpus.slot(u32::MAX), None);
+        assert_eq!(cpus.slot_or_other(4), 8);
+    }
+}
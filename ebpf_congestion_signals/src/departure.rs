@@ -0,0 +1,235 @@
+//Governed rate to per-packet departure times, for QUIC stacks that schedule
+//their own sends. quiche returns every packet with `send_info.at`, the earliest
+//time its pacer lets it go; the adapter pushes that back so the packets leave
+//at the governed rate instead, never earlier than quiche asked for.
+//
+//Departures come from a token bucket run on scheduled time, not wall time: a
+//sender that drains `conn.send()` in a tight loop gets each packet stamped
+//where the rate puts it, with up to `burst_bytes` going out back to back after
+//an idle stretch. A governed rate below quiche's own is only trusted as far as
+//the decision's headroom confidence goes, and a stale or missing decision
+//hands scheduling back to quiche. A smoothing decision (PacingAction::Smooth)
+//keeps the rate but shrinks the bucket, so what left in bursts leaves evenly.
+
+use crate::{PacingAction, PacingDecision};
+use std::time::{Duration, Instant};
+
+/// Tuning for [`DeparturePacer`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepartureConfig {
+    /// Bytes allowed out back to back after an idle stretch, ten 1350-byte
+    /// packets by default
+    pub burst_bytes: u64,
+    /// The bucket while the decision is to smooth, one packet by default
+    pub smoothed_burst_bytes: u64,
+    /// How far below the stack's own rate (`native_rate` in `update`) a fully
+    /// confident decision may go, 0.5 = down to half of it. Scaled by the
+    /// decision's `headroom.confidence`, so at zero confidence it's the stack's
+    /// rate or above
+    pub max_below_native: f64,
+    /// A decision older than this, or one made on stale input, leaves every
+    /// departure to the stack
+    pub max_decision_age: Duration,
+}
+
+impl Default for DepartureConfig {
+    fn default() -> Self {
+        Self {
+            burst_bytes: 13_500,
+            smoothed_burst_bytes: 1_350,
+            max_below_native: 0.5,
+            max_decision_age: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeparturePacer {
+    config: DepartureConfig,
+    // bytes/sec and when it was decided, None while the stack schedules
+    rate: Option<(f64, Instant)>,
+    tokens: f64,
+    smoothed: bool,
+    // Departure of the previous packet, None after falling back
+    last: Option<Instant>,
+}
+
+impl DeparturePacer {
+    pub fn new(config: DepartureConfig) -> Self {
+        Self {
+            config,
+            rate: None,
+            tokens: 0.0,
+            smoothed: false,
+            last: None,
+        }
+    }
+
+    pub fn config(&self) -> &DepartureConfig {
+        &self.config
+    }
+
+    /// Take `decision`'s rate from `now` on. `native_rate` is what the stack
+    /// would pace at by itself in bytes/sec (quiche: the path's delivery rate,
+    /// or cwnd over RTT), None when it doesn't say and the rate goes unbounded
+    pub fn update(&mut self, decision: &PacingDecision, native_rate: Option<u64>, now: Instant) {
+        if decision.stale_input.is_some() {
+            self.fall_back();
+            return;
+        }
+        let confidence = decision.headroom.confidence.clamp(0.0, 1.0);
+        let floor = native_rate.map_or(0.0, |native| {
+            native as f64 * (1.0 - self.config.max_below_native.clamp(0.0, 1.0) * confidence)
+        });
+        self.rate = Some(((decision.rate as f64).max(floor), now));
+        self.smoothed = decision.action == PacingAction::Smooth;
+    }
+
+    /// Bytes/sec departures are paced at, None while the stack schedules
+    pub fn rate(&self) -> Option<u64> {
+        self.rate.map(|(rate, _)| rate as u64)
+    }
+
+    /// When a packet of `len` bytes the stack wants sent at `at` should leave.
+    /// Never before `at`; exactly `at` without a fresh decision
+    pub fn departure(&mut self, len: usize, at: Instant) -> Instant {
+        let rate = match self.rate {
+            Some((rate, decided)) if rate > 0.0 => {
+                if at.saturating_duration_since(decided) > self.config.max_decision_age {
+                    self.fall_back();
+                    return at;
+                }
+                rate
+            }
+            _ => return at,
+        };
+        let burst = if self.smoothed {
+            self.config
+                .smoothed_burst_bytes
+                .min(self.config.burst_bytes)
+        } else {
+            self.config.burst_bytes
+        } as f64;
+        // Ready once the stack is and the previous packet has left
+        let start = self.last.map_or(at, |last| last.max(at));
+        let accrued = self.last.map_or(burst, |last| {
+            self.tokens + start.saturating_duration_since(last).as_secs_f64() * rate
+        });
+        self.tokens = accrued.min(burst);
+
+        let len = len as f64;
+        let departure = if self.tokens >= len {
+            self.tokens -= len;
+            start
+        } else {
+            let wait = Duration::from_secs_f64((len - self.tokens) / rate);
+            self.tokens = 0.0;
+            start + wait
+        };
+        self.last = Some(departure);
+        departure
+    }
+
+    fn fall_back(&mut self) {
+        self.rate = None;
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HeadroomEstimate;
+
+    fn config() -> DepartureConfig {
+        DepartureConfig {
+            burst_bytes: 10_000,
+            ..Default::default()
+        }
+    }
+
+    fn decision(rate: u64, confidence: f64, stale: bool) -> PacingDecision {
+        PacingDecision {
+            rate,
+            action: PacingAction::Hold,
+            score: 0.0,
+            headroom: HeadroomEstimate {
+                confidence,
+                ..Default::default()
+            },
+            stale_input: stale.then_some(Duration::from_secs(2)),
+            advisory: Default::default(),
+        }
+    }
+
+    fn ms(ms: f64) -> Duration {
+        Duration::from_secs_f64(ms / 1000.0)
+    }
+
+    #[test]
+    fn a_full_bucket_goes_at_once_and_the_rest_at_the_rate() {
+        let start = Instant::now();
+        // 1 MB/s and 1000-byte packets: ten at once, then one per millisecond
+        let mut pacer = DeparturePacer::new(config());
+        pacer.update(&decision(1_000_000, 1.0, false), None, start);
+        let departures: Vec<Duration> = (0..20)
+            .map(|_| pacer.departure(1000, start) - start)
+            .collect();
+        assert!(departures[..10].iter().all(|d| d.is_zero()));
+        for (i, departure) in departures[10..].iter().enumerate() {
+            assert!(
+                departure.abs_diff(ms(i as f64 + 1.0)) < ms(0.01),
+                "{:?}",
+                departures
+            );
+        }
+        // quiche holding a packet past the schedule
+        let later = start + ms(50.0);
+        assert_eq!(pacer.departure(1000, later), later);
+    }
+
+    #[test]
+    fn a_rate_below_the_stacks_is_floored_by_confidence() {
+        // 0.5 MB/s against a stack pacing at 2 MB/s, at half the default bound
+        let floor = |confidence: f64| {
+            let mut pacer = DeparturePacer::new(config());
+            pacer.update(
+                &decision(500_000, confidence, false),
+                Some(2_000_000),
+                Instant::now(),
+            );
+            pacer.rate()
+        };
+        assert_eq!(floor(1.0), Some(1_000_000));
+        assert_eq!(floor(0.0), Some(2_000_000));
+    }
+
+    #[test]
+    fn stale_or_old_decisions_hand_departures_back() {
+        let start = Instant::now();
+        let mut stale = DeparturePacer::new(config());
+        stale.update(&decision(500_000, 1.0, true), None, start);
+        assert_eq!(stale.rate(), None);
+        assert_eq!(stale.departure(1000, start), start);
+
+        let mut old = DeparturePacer::new(config());
+        old.update(&decision(500_000, 1.0, false), None, start);
+        let aged = start + config().max_decision_age + ms(1.0);
+        assert_eq!(old.departure(1000, aged), aged);
+        assert_eq!(old.rate(), None);
+    }
+
+    #[test]
+    fn a_smoothing_decision_shrinks_the_bucket_to_a_packet() {
+        let start = Instant::now();
+        let mut pacer = DeparturePacer::new(config());
+        let smoothing = PacingDecision {
+            action: PacingAction::Smooth,
+            ..decision(1_000_000, 1.0, false)
+        };
+        pacer.update(&smoothing, None, start);
+        // The same 1 MB/s: the 350 bytes left after the first, then paced
+        assert_eq!(pacer.departure(1000, start), start);
+        assert!((pacer.departure(1000, start) - start).abs_diff(ms(0.65)) < ms(0.01));
+    }
+}
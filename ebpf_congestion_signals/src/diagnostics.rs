@@ -0,0 +1,941 @@
+//Diagnostic bundles for "the signals look wrong" reports: one directory of JSON
+//files with everything the collector knows about itself (health, config, probe
+//coverage, programs, readers, recent intervals, per-socket and per-interface
+//tables, the kernel it runs on) plus a manifest listing them. Socket ids go
+//through the caller's Redaction like every other export. Handed a governor, it
+//adds what the governor decided and how its cuts have worked out.
+
+use crate::btf::resolve_offsets;
+use crate::export;
+use crate::json::{json_f64, json_opt, json_opt_f64, json_string};
+use crate::memory::MemoryReport;
+use crate::redact::Redaction;
+#[cfg(feature = "governor")]
+use crate::Governor;
+use crate::{
+    event_socket, event_type_name, CollectorConfig, CollectorState, CongestionEvent,
+    CongestionSignals, HealthReport, ReaderStats, Signal, SocketSignals, EVENT_NAPI_POLL,
+    EVENT_NET_DEV_QUEUE, EVENT_QDISC_DROP, EVENT_RX_TIME_SQUEEZE, EVENT_SOCKET_LIFECYCLE,
+    EVENT_SOCKET_RCV_STATE, EVENT_SOCKET_STATE, EVENT_SOFTIRQ_ENTER, EVENT_SOFTIRQ_EXIT,
+    EVENT_TCP_CWR, EVENT_TCP_RECOVERY, EVENT_TCP_RTO, EVENT_TCP_SEND, EVENT_TCP_STATE,
+    EVENT_UDP_RCV_CE, EVENT_UDP_RCV_DROP, EVENT_UDP_SEND, EXPORT_SCHEMA_VERSION, OFFSET_UNKNOWN,
+    SCHEMA_VERSION,
+};
+use aya::Ebpf;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Bumped when a file is added, removed or changes shape
+const BUNDLE_FORMAT: u32 = 3;
+
+/// Everything the collector hands over for one bundle
+pub(crate) struct DiagnosticState<'a> {
+    pub(crate) state: CollectorState,
+    pub(crate) config: &'a CollectorConfig,
+    pub(crate) health: HealthReport,
+    /// Optional probes by name, and whether each attached
+    pub(crate) probes: Vec<(&'static str, bool)>,
+    pub(crate) missing_signals: Vec<Signal>,
+    /// Never-reset counters of samples the collector threw away, by name
+    pub(crate) suppressed: Vec<(&'static str, u64)>,
+    /// programs.json, see `programs_json`
+    pub(crate) programs: String,
+    pub(crate) reader_stats: ReaderStats,
+    pub(crate) memory: MemoryReport,
+    /// Oldest first
+    pub(crate) intervals: Vec<CongestionSignals>,
+    pub(crate) sockets: HashMap<u64, SocketSignals>,
+    /// None without the event stream (blocking builds)
+    pub(crate) events: Option<Vec<CongestionEvent>>,
+    /// governor.json, see `dump_diagnostics_governed`
+    pub(crate) governor: Option<String>,
+}
+
+/// Write the bundle into `dir`, creating it. Returns the files written,
+/// manifest.json last
+pub(crate) fn write_bundle(
+    dir: &Path,
+    state: &DiagnosticState,
+    redaction: &Redaction,
+) -> anyhow::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir)?;
+
+    let mut files: Vec<(&str, &str, String)> = vec![
+        (
+            "health.json",
+            "health() at dump time",
+            health_json(&state.health),
+        ),
+        (
+            "config.json",
+            "CollectorConfig in use",
+            config_json(state.config),
+        ),
+        (
+            "coverage.json",
+            "optional probes, missing signals, suppressed samples",
+            coverage_json(state),
+        ),
+        (
+            "programs.json",
+            "BPF programs, whether linked, run counts",
+            state.programs.clone(),
+        ),
+        (
+            "readers.json",
+            "reader_stats() and per-CPU reader restarts",
+            readers_json(&state.reader_stats, &state.health),
+        ),
+        ("memory.json", "memory_report()", memory_json(&state.memory)),
+        (
+            "intervals.json",
+            "most recent interval reads, oldest first",
+            format!(
+                "[{}]",
+                state
+                    .intervals
+                    .iter()
+                    .map(|signals| signals.to_json_redacted(redaction))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+        ),
+        (
+            "sockets.json",
+            "per-socket table, not reset by the dump",
+            sockets_json(&state.sockets, redaction),
+        ),
+        (
+            "interfaces.json",
+            "egress and TX queue tables of the latest interval",
+            interfaces_json(state.config, state.intervals.last()),
+        ),
+        (
+            "system.json",
+            "kernel release, BTF and resolved struct offsets",
+            system_json(),
+        ),
+    ];
+    if let Some(events) = &state.events {
+        files.push((
+            "events.json",
+            "most recent decoded events, oldest first",
+            events_json(events, redaction),
+        ));
+    }
+    if let Some(governor) = &state.governor {
+        files.push((
+            "governor.json",
+            "governor policy, rate, effectiveness, latest decisions",
+            governor.clone(),
+        ));
+    }
+
+    let mut written = Vec::with_capacity(files.len() + 1);
+    for (name, _, contents) in &files {
+        let path = dir.join(name);
+        std::fs::write(&path, contents)?;
+        written.push(path);
+    }
+
+    let created_at_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis())
+        .to_string();
+    let listed: Vec<String> = files
+        .iter()
+        .map(|(name, description, _)| {
+            format!(
+                "{{\"name\":{},\"description\":{}}}",
+                json_string(name),
+                json_string(description)
+            )
+        })
+        .collect();
+    let manifest = format!(
+        "{{\"schema_version\":{},\"bundle_format\":{},\"created_at_ms\":{}{},\"collector_version\":{},\
+         \"event_schema_version\":{},\"state\":\"{:?}\",\"event_stream\":{},\"redaction\":{{\"socket_ids\":\"{:?}\",\
+         \"addresses\":\"{:?}\"}},\"files\":[{}]}}",
+        EXPORT_SCHEMA_VERSION,
+        BUNDLE_FORMAT,
+        created_at_ms,
+        export::aliases("manifest", "created_at_ms", &created_at_ms),
+        json_string(env!("CARGO_PKG_VERSION")),
+        SCHEMA_VERSION,
+        state.state,
+        state.events.is_some(),
+        redaction.socket_ids,
+        redaction.addresses,
+        listed.join(","),
+    );
+    let path = dir.join("manifest.json");
+    std::fs::write(&path, manifest)?;
+    written.push(path);
+    Ok(written)
+}
+
+// Decisions in governor.json
+#[cfg(feature = "governor")]
+const GOVERNOR_DECISIONS: usize = 20;
+
+#[cfg(feature = "governor")]
+pub(crate) fn governor_json(governor: &Governor) -> String {
+    let decisions: Vec<String> = governor
+        .recent_decisions(GOVERNOR_DECISIONS)
+        .iter()
+        .map(|record| record.to_json())
+        .collect();
+    format!(
+        "{{\"policy\":{},\"rate\":{},\"effectiveness\":{},\"decisions\":[{}]}}",
+        json_string(&governor.policy().name),
+        governor.rate(),
+        governor.effectiveness().to_json(),
+        decisions.join(",")
+    )
+}
+
+fn health_json(health: &HealthReport) -> String {
+    let warnings: Vec<String> = health.warnings.iter().map(|w| json_string(w)).collect();
+    let mut ages: Vec<(&u32, &f64)> = health.last_seen_age_secs.iter().collect();
+    ages.sort_by_key(|&(event_type, _)| *event_type);
+    let ages: Vec<String> = ages
+        .iter()
+        .map(|&(&event_type, &age)| {
+            format!(
+                "{}:{}",
+                json_string(event_type_name(event_type)),
+                json_f64(age)
+            )
+        })
+        .collect();
+    let failures: Vec<String> = health
+        .program_failures
+        .iter()
+        .map(|failure| {
+            format!(
+                "{{\"group\":{},\"program\":{},\"stage\":{},\"error\":{},\"verifier_log\":{}}}",
+                json_string(failure.group),
+                json_string(failure.program),
+                json_string(&failure.stage.to_string()),
+                json_string(&failure.error),
+                json_opt(failure.verifier_log.as_deref().map(json_string)),
+            )
+        })
+        .collect();
+    let co_attached: Vec<String> = health
+        .co_attached
+        .iter()
+        .map(|co_attached| {
+            let program = &co_attached.program;
+            let pids: Vec<String> = co_attached.owner_pids.iter().map(u32::to_string).collect();
+            format!(
+                "{{\"point\":{},\"id\":{},\"name\":{},\"program_type\":{},\"owner_uid\":{},\
+                 \"owner_pids\":[{}]}}",
+                json_string(&co_attached.point.to_string()),
+                program.id,
+                json_string(&program.name),
+                json_string(&program.program_type),
+                json_opt(program.owner_uid),
+                pids.join(","),
+            )
+        })
+        .collect();
+    format!(
+        "{{\"healthy\":{},\"warnings\":[{}],\"last_seen_age_secs\":{{{}}},\
+         \"component_failures\":{},\"calibration\":{},\
+         \"program_failures\":[{}],\"heartbeat\":{},\"co_attached\":[{}]}}",
+        health.is_healthy(),
+        warnings.join(","),
+        ages.join(","),
+        health.component_failures,
+        json_opt(
+            health
+                .calibration
+                .as_ref()
+                .map(|outcome| json_string(&format!("{:?}", outcome)))
+        ),
+        failures.join(","),
+        json_opt(health.heartbeat.as_ref().map(|status| {
+            format!(
+                "{{\"pipeline_alive\":{},\"last_round_trip_us\":{},\"sent\":{},\"seen\":{},\
+                 \"missed\":{},\"consecutive_missed\":{},\"excluded_events\":{}}}",
+                status.pipeline_alive,
+                json_opt(status.last_round_trip.map(|rtt| rtt.as_micros())),
+                status.sent,
+                status.seen,
+                status.missed,
+                status.consecutive_missed,
+                status.excluded_events
+            )
+        })),
+        co_attached.join(","),
+    )
+}
+
+fn config_json(config: &CollectorConfig) -> String {
+    let interfaces: Vec<String> = config
+        .egress_interfaces
+        .iter()
+        .map(|i| json_string(i))
+        .collect();
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "{{\"event_queue_capacity\":{},\"subscriber_capacity\":{},\"staleness_window_ms\":{},\
+         \"limitation\":{},\"concentration\":{},\"socket_idle_ttl_ms\":{},\"max_tracked_sockets\":{},\
+         \"socket_table_capacity\":{},\"egress_interfaces\":[{}],\"exact_socket_count_limit\":{},\
+         \"reader_max_retries\":{},\"reader_batch_size\":{},\"reader_wakeup\":{},\
+         \"exclude_loopback\":{},\"drop_coalescing\":{},\"event_reordering\":{},\
+         \"forward_compatible\":{},\
+         \"burst_correlation\":{},\"drop_interarrival\":{},\
+         \"cgroup_aggregation\":{},\"netns\":{},\"traffic_classes\":{},\
+         \"calibration\":{},\"accuracy\":{},\"conflict_check\":{},\"allow_shared\":{},\
+         \"co_attachment_scan\":{},\"state_file\":{},\
+         \"sampler_comparison\":{},\"softirq_coupling\":{},\
+         \"softirq_attribution\":{},\"triggered_capture\":{}",
+        config.event_queue_capacity,
+        config.subscriber_capacity,
+        config.staleness_window.as_millis(),
+        json_string(&format!("{:?}", config.limitation)),
+        json_string(&format!("{:?}", config.concentration)),
+        config.socket_idle_ttl.as_millis(),
+        config.max_tracked_sockets,
+        config.socket_table_capacity,
+        interfaces.join(","),
+        config.exact_socket_count_limit,
+        config.reader_max_retries,
+        config.reader_batch_size,
+        json_string(&format!("{:?}", config.reader_wakeup)),
+        config.exclude_loopback,
+        json_opt(
+            config
+                .drop_coalescing
+                .map(|coalescing| json_string(&format!("{:?}", coalescing)))
+        ),
+        // What runs, burst correlation turns it on
+        json_opt(
+            config
+                .reordering()
+                .map(|reordering| json_string(&format!("{:?}", reordering)))
+        ),
+        config.forward_compatible,
+        json_opt(
+            config
+                .burst_correlation
+                .as_ref()
+                .map(|burst| json_string(&format!("{:?}", burst)))
+        ),
+        config.drop_interarrival,
+        json_opt(
+            config
+                .cgroup_aggregation
+                .as_ref()
+                .map(|cgroups| json_string(&format!("{:?}", cgroups)))
+        ),
+        json_opt(
+            config
+                .netns
+                .as_ref()
+                .map(|netns| json_string(&format!("{:?}", netns)))
+        ),
+        json_opt(
+            config
+                .traffic_classes
+                .as_ref()
+                .map(|classes| json_string(&format!("{:?}", classes)))
+        ),
+        json_opt(
+            config
+                .calibration
+                .as_ref()
+                .map(|calibration| json_string(&format!("{:?}", calibration)))
+        ),
+        json_opt(
+            config
+                .accuracy
+                .as_ref()
+                .map(|accuracy| json_string(&format!("{:?}", accuracy)))
+        ),
+        json_string(&format!("{:?}", config.conflict_check)),
+        config.allow_shared,
+        config.co_attachment_scan,
+        json_opt(
+            config
+                .state_file
+                .as_ref()
+                .map(|state_file| json_string(&format!("{:?}", state_file)))
+        ),
+        json_opt(config.sampler_comparison),
+        json_opt(
+            config
+                .softirq_coupling
+                .as_ref()
+                .map(|coupling| json_string(&format!("{:?}", coupling)))
+        ),
+        json_opt(
+            config
+                .softirq_attribution
+                .as_ref()
+                .map(|attribution| json_string(&format!("{:?}", attribution)))
+        ),
+        json_opt(
+            config
+                .triggered_capture
+                .as_ref()
+                .map(|capture| json_string(&format!("{:?}", capture)))
+        ),
+    );
+    #[cfg(feature = "async-runtime")]
+    {
+        let _ = write!(
+            out,
+            ",\"align_to_wall_clock\":{},\"restart_policies\":{},\"heartbeat\":{}",
+            config.align_to_wall_clock,
+            json_string(&format!("{:?}", config.restart_policies)),
+            json_opt(
+                config
+                    .heartbeat
+                    .as_ref()
+                    .map(|heartbeat| json_string(&format!("{:?}", heartbeat)))
+            ),
+        );
+    }
+    out.push('}');
+    out
+}
+
+fn coverage_json(state: &DiagnosticState) -> String {
+    let probes: Vec<String> = state
+        .probes
+        .iter()
+        .map(|&(name, attached)| format!("{}:{}", json_string(name), attached))
+        .collect();
+    let missing: Vec<String> = state
+        .missing_signals
+        .iter()
+        .map(|signal| json_string(&format!("{:?}", signal)))
+        .collect();
+    let suppressed: Vec<String> = state
+        .suppressed
+        .iter()
+        .map(|&(name, count)| format!("{}:{}", json_string(name), count))
+        .collect();
+    format!(
+        "{{\"probes\":{{{}}},\"missing_signals\":[{}],\"suppressed\":{{{}}}}}",
+        probes.join(","),
+        missing.join(","),
+        suppressed.join(","),
+    )
+}
+
+/// Run counts and times stay 0 unless kernel.bpf_stats_enabled is set
+/// The programs in `ebpf`, whether each is in `linked`, and their run stats
+pub(crate) fn programs_json(ebpf: &Ebpf, linked: &[String]) -> String {
+    let mut programs: Vec<String> = ebpf
+        .programs()
+        .map(|(name, program)| {
+            let info = program.info().ok();
+            format!(
+                "{{\"name\":{},\"linked\":{},\"id\":{},\"run_count\":{},\"run_time_ns\":{}}}",
+                json_string(name),
+                linked.iter().any(|l| l == name),
+                json_opt(info.as_ref().map(|i| i.id())),
+                json_opt(info.as_ref().map(|i| i.run_count())),
+                json_opt(info.as_ref().map(|i| i.run_time().as_nanos())),
+            )
+        })
+        .collect();
+    programs.sort();
+    let stats_enabled = std::fs::read_to_string("/proc/sys/kernel/bpf_stats_enabled")
+        .ok()
+        .map(|s| s.trim() == "1");
+    format!(
+        "{{\"bpf_stats_enabled\":{},\"programs\":[{}]}}",
+        json_opt(stats_enabled),
+        programs.join(","),
+    )
+}
+
+fn readers_json(stats: &ReaderStats, health: &HealthReport) -> String {
+    let mut restarts: Vec<(&u32, &u64)> = health.reader_restarts.iter().collect();
+    restarts.sort();
+    let restarts: Vec<String> = restarts
+        .iter()
+        .map(|(cpu, count)| format!("\"{}\":{}", cpu, count))
+        .collect();
+    format!(
+        "{{\"events_read\":{},\"perf_lost\":{},\"queue_depth\":{},\"queue_capacity\":{},\
+         \"queue_dropped\":{},\"unknown_events\":{},\"extension_events\":{},\"clock_jumps\":{},\"netns_filtered\":{},\
+         \"reader_wakeups\":{},\"wakeups_per_sec\":{},\"reordering\":{},\"reader_restarts\":{{{}}}}}",
+        stats.events_read,
+        stats.perf_lost,
+        stats.queue_depth,
+        stats.queue_capacity,
+        stats.queue_dropped,
+        stats.unknown_events,
+        stats.extension_events,
+        stats.clock_jumps,
+        stats.netns_filtered,
+        stats.reader_wakeups,
+        json_f64(stats.wakeups_per_sec),
+        json_opt(stats.reordering.map(|r| format!(
+            "{{\"held\":{},\"released\":{},\"late_events\":{},\"overflowed\":{},\
+             \"mean_delay_us\":{},\"max_delay_us\":{}}}",
+            r.held,
+            r.released,
+            r.late_events,
+            r.overflowed,
+            r.mean_delay.as_micros(),
+            r.max_delay.as_micros(),
+        ))),
+        restarts.join(","),
+    )
+}
+
+fn memory_json(memory: &MemoryReport) -> String {
+    let structures: Vec<String> = memory
+        .structures
+        .iter()
+        .map(|s| {
+            format!(
+                "{{\"name\":{},\"entries\":{},\"cap\":{},\"estimated_bytes\":{},\"evictions\":{}}}",
+                json_string(s.name),
+                s.entries,
+                s.cap,
+                s.estimated_bytes,
+                s.evictions,
+            )
+        })
+        .collect();
+    let maps: Vec<String> = memory
+        .bpf_maps
+        .iter()
+        .map(|m| {
+            format!(
+                "{{\"name\":{},\"max_entries\":{},\"estimated_bytes\":{}}}",
+                json_string(&m.name),
+                m.max_entries,
+                m.estimated_bytes,
+            )
+        })
+        .collect();
+    let shards: Vec<String> = memory
+        .socket_shards
+        .iter()
+        .map(|s| {
+            format!(
+                "{{\"entries\":{},\"cap\":{},\"estimated_bytes\":{},\"evictions\":{},\
+                 \"eviction_passes\":{},\"contended\":{}}}",
+                s.entries, s.cap, s.estimated_bytes, s.evictions, s.eviction_passes, s.contended,
+            )
+        })
+        .collect();
+    format!(
+        "{{\"total_bytes\":{},\"structures\":[{}],\"bpf_maps\":[{}],\"socket_shards\":[{}]}}",
+        memory.total_bytes(),
+        structures.join(","),
+        maps.join(","),
+        shards.join(","),
+    )
+}
+
+fn sockets_json(sockets: &HashMap<u64, SocketSignals>, redaction: &Redaction) -> String {
+    let mut ids: Vec<&u64> = sockets.keys().collect();
+    ids.sort();
+    let rows: Vec<String> = ids
+        .into_iter()
+        .map(|id| {
+            let s = &sockets[id];
+            format!(
+                "{{\"socket_id\":{},\"is_tcp\":{},\"first_seen_ns\":{},\"last_seen_ns\":{},\
+                 \"send_bytes\":{},\"loopback_send_bytes\":{},\"udp_rcv_drops\":{},\"ce_marks\":{},\
+                 \"rmem_pressure\":{},\"wmem_pressure\":{},\"tcp_cwnd\":{},\"tcp_ssthresh\":{},\
+                 \"tcp_cwr\":{},\"rto_events\":{},\"loss_recovery_episodes\":{},\"closed\":{}}}",
+                json_opt(redaction.socket_id(*id)),
+                s.is_tcp,
+                s.first_seen_ns,
+                s.last_seen_ns,
+                s.send_bytes,
+                s.loopback_send_bytes,
+                s.udp_rcv_drops,
+                s.ce_marks,
+                json_opt_f64(s.rmem_pressure),
+                json_opt_f64(s.wmem_pressure),
+                json_opt(s.tcp_cwnd),
+                json_opt(s.tcp_ssthresh),
+                s.tcp_cwr,
+                s.rto_events,
+                s.loss_recovery_episodes,
+                s.closed,
+            )
+        })
+        .collect();
+    format!("[{}]", rows.join(","))
+}
+
+fn interfaces_json(config: &CollectorConfig, latest: Option<&CongestionSignals>) -> String {
+    let configured: Vec<String> = config
+        .egress_interfaces
+        .iter()
+        .map(|i| json_string(i))
+        .collect();
+    let egress: Vec<String> = latest
+        .map_or(&[][..], |s| &s.egress[..])
+        .iter()
+        .map(|e| {
+            format!(
+                "{{\"interface\":{},\"egress_bytes_exact\":{},\"egress_packets_exact\":{}}}",
+                json_string(&e.interface),
+                e.egress_bytes_exact,
+                e.egress_packets_exact,
+            )
+        })
+        .collect();
+    let txq: Vec<String> = latest
+        .map_or(&[][..], |s| &s.txq_by_interface[..])
+        .iter()
+        .map(|t| {
+            format!(
+                "{{\"interface\":{},\"ifindex\":{},\"netns\":{},\"txq_stalls\":{},\"txq_stalled_ns\":{},\
+                 \"xmit_busy\":{}}}",
+                json_string(&t.interface),
+                t.ifindex,
+                t.netns,
+                t.txq_stalls,
+                t.txq_stalled_ns,
+                t.xmit_busy,
+            )
+        })
+        .collect();
+    format!(
+        "{{\"egress_interfaces\":[{}],\"interval_ns\":{},\"egress\":[{}],\"txq_by_interface\":[{}]}}",
+        configured.join(","),
+        json_opt(latest.map(|s| s.interval_ns)),
+        egress.join(","),
+        txq.join(","),
+    )
+}
+
+fn system_json() -> String {
+    let read = |path: &str| {
+        std::fs::read_to_string(path)
+            .ok()
+            .map(|s| s.trim().to_string())
+    };
+    let btf = Path::new("/sys/kernel/btf/vmlinux").exists();
+    // Resolved again rather than kept from load; the kernel's BTF doesn't change
+    let o = resolve_offsets();
+    let offsets = [
+        ("tcp_snd_cwnd", o.tcp_snd_cwnd),
+        ("tcp_snd_ssthresh", o.tcp_snd_ssthresh),
+        ("sk_pacing_rate", o.sk_pacing_rate),
+        ("skb_head", o.skb_head),
+        ("skb_network_header", o.skb_network_header),
+        ("sk_cookie", o.sk_cookie),
+        ("kfree_skb_reason", o.kfree_skb_reason),
+        ("sk_wmem_alloc", o.sk_wmem_alloc),
+        ("sk_wmem_queued", o.sk_wmem_queued),
+        ("sk_sndbuf", o.sk_sndbuf),
+        ("sk_rmem_alloc", o.sk_rmem_alloc),
+        ("sk_rcvbuf", o.sk_rcvbuf),
+        ("skb_sk", o.skb_sk),
+        ("skb_mac_header", o.skb_mac_header),
+        ("skb_transport_header", o.skb_transport_header),
+        ("skb_dev", o.skb_dev),
+        ("netdev_queue_dev", o.netdev_queue_dev),
+        ("net_device_ifindex", o.net_device_ifindex),
+        ("sk_family", o.sk_family),
+        ("sk_daddr", o.sk_daddr),
+        ("sk_v6_daddr", o.sk_v6_daddr),
+        ("sk_dport", o.sk_dport),
+    ];
+    let offsets: Vec<String> = offsets
+        .iter()
+        .map(|&(name, offset)| {
+            format!(
+                "{}:{}",
+                json_string(name),
+                json_opt((offset != OFFSET_UNKNOWN).then_some(offset))
+            )
+        })
+        .collect();
+    format!(
+        "{{\"kernel_release\":{},\"kernel_version\":{},\"btf_vmlinux\":{},\"nr_cpus\":{},\
+         \"offsets\":{{{}}}}}",
+        json_opt(read("/proc/sys/kernel/osrelease").map(|s| json_string(&s))),
+        json_opt(read("/proc/sys/kernel/version").map(|s| json_string(&s))),
+        btf,
+        json_opt(aya::util::nr_cpus().ok()),
+        offsets.join(","),
+    )
+}
+
+fn events_json(events: &[CongestionEvent], redaction: &Redaction) -> String {
+    let rows: Vec<String> = events.iter().map(|e| event_json(e, redaction)).collect();
+    format!("[{}]", rows.join(","))
+}
+
+fn event_json(event: &CongestionEvent, redaction: &Redaction) -> String {
+    let socket_id = event_socket(event).and_then(|(id, _)| redaction.socket_id(id));
+    let payload = unsafe {
+        match event.event_type {
+            EVENT_UDP_SEND | EVENT_TCP_SEND => {
+                let d = event.data.sendmsg;
+                format!(
+                    "{{\"bytes\":{},\"is_tcp\":{},\"loopback\":{}}}",
+                    d.bytes,
+                    d.is_tcp != 0,
+                    d.loopback != 0
+                )
+            }
+            EVENT_QDISC_DROP => {
+                let d = event.data.qdisc;
+                format!(
+                    "{{\"dropped\":{},\"span_ns\":{},\"reason\":{}}}",
+                    d.dropped, d.backlog_bytes, d.reason
+                )
+            }
+            EVENT_NET_DEV_QUEUE => {
+                let d = event.data.qdisc;
+                format!(
+                    "{{\"dropped\":{},\"backlog_bytes\":{},\"backlog_packets\":{},\"reason\":{}}}",
+                    d.dropped, d.backlog_bytes, d.backlog_packets, d.reason
+                )
+            }
+            EVENT_SOCKET_STATE => {
+                let d = event.data.socket;
+                format!(
+                    "{{\"wmem_queued\":{},\"sndbuf\":{},\"protocol\":{},\"pacing_rate\":{}}}",
+                    d.wmem_queued,
+                    d.sndbuf,
+                    d.protocol,
+                    json_opt(d.pacing_limit())
+                )
+            }
+            EVENT_SOFTIRQ_ENTER | EVENT_SOFTIRQ_EXIT => {
+                let d = event.data.softirq;
+                format!(
+                    "{{\"vec_nr\":{},\"duration_ns\":{}}}",
+                    d.vec_nr, d.duration_ns
+                )
+            }
+            EVENT_UDP_RCV_DROP | EVENT_SOCKET_RCV_STATE | EVENT_UDP_RCV_CE => {
+                let d = event.data.rcv;
+                format!(
+                    "{{\"rmem_alloc\":{},\"rcvbuf\":{}}}",
+                    d.rmem_alloc, d.rcvbuf
+                )
+            }
+            EVENT_TCP_STATE | EVENT_TCP_CWR | EVENT_TCP_RTO | EVENT_TCP_RECOVERY => {
+                let d = event.data.tcp;
+                format!(
+                    "{{\"snd_cwnd\":{},\"snd_ssthresh\":{},\"pacing_rate\":{}}}",
+                    d.snd_cwnd, d.snd_ssthresh, d.pacing_rate
+                )
+            }
+            EVENT_SOCKET_LIFECYCLE => {
+                let d = event.data.lifecycle;
+                format!(
+                    "{{\"oldstate\":{},\"newstate\":{}}}",
+                    d.oldstate, d.newstate
+                )
+            }
+            EVENT_RX_TIME_SQUEEZE => {
+                let d = event.data.rx_squeeze;
+                format!(
+                    "{{\"elapsed_ns\":{},\"packets\":{},\"budget_exhausted\":{}}}",
+                    d.elapsed_ns,
+                    d.packets,
+                    d.budget_exhausted != 0
+                )
+            }
+            EVENT_NAPI_POLL => {
+                let d = event.data.napi;
+                format!(
+                    "{{\"device\":{},\"work\":{},\"budget\":{}}}",
+                    json_string(&d.device()),
+                    d.work,
+                    d.budget
+                )
+            }
+            _ => "null".to_string(),
+        }
+    };
+    format!(
+        "{{\"timestamp_ns\":{},\"event_type\":{},\"event_name\":{},\"cpu\":{},\"socket_id\":{},\
+         \"payload\":{}}}",
+        event.timestamp_ns,
+        event.event_type,
+        json_string(event_type_name(event.event_type)),
+        event.cpu_id,
+        json_opt(socket_id),
+        payload,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, Replay};
+    use crate::json::{parse_json, JsonValue};
+    use crate::redact::SocketIdRedaction;
+    use crate::replay_per_socket;
+    use std::time::Duration;
+
+    // Socket ids the way they fall back to struct sock addresses
+    const MARKERS: [u64; 2] = [0xffff_8881_0a2b_3c40, 0xffff_9e3f_c0de_0080];
+
+    fn capture() -> Vec<CongestionEvent> {
+        let mut events = Vec::new();
+        for (i, socket_id) in MARKERS.into_iter().enumerate() {
+            let cpu_id = i as u32;
+            for n in 0..40 {
+                let timestamp_ns = 1_000_000 + n * 10_000 + i as u64;
+                events.push(fixtures::udp_send(timestamp_ns, cpu_id, socket_id, 9_000));
+            }
+            events.push(fixtures::tcp_send(2_000_000, cpu_id, socket_id, 1_200));
+            events.push(fixtures::socket_state(
+                2_100_000, cpu_id, socket_id, 150_000, 212_992,
+            ));
+            events.push(fixtures::tcp_state(2_200_000, cpu_id, socket_id, 10, 64));
+            events.push(fixtures::rcv_drop(2_300_000, cpu_id, socket_id));
+        }
+        events
+    }
+
+    /// Everything a bundle serializes per socket and per event
+    fn serialized(redaction: &Redaction) -> String {
+        let config = CollectorConfig::default();
+        let events = capture();
+        let replay = Replay::new(config.clone(), 2);
+        replay.feed(&events);
+        let signals = replay.read(Duration::from_secs(1));
+        assert!(signals.send_concentration.is_some());
+        [
+            signals.to_json_redacted(redaction),
+            sockets_json(&replay_per_socket(&events, &config), redaction),
+            events_json(&events, redaction),
+        ]
+        .join("\n")
+    }
+
+    fn leaked(output: &str) -> Vec<String> {
+        MARKERS
+            .iter()
+            .flat_map(|marker| [marker.to_string(), format!("{marker:x}")])
+            .filter(|marker| output.contains(marker.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn the_markers_reach_an_unredacted_bundle() {
+        let output = serialized(&Redaction::none());
+        assert_eq!(leaked(&output).len(), MARKERS.len());
+    }
+
+    #[test]
+    fn redacted_bundles_carry_no_socket_addresses() {
+        let hashed = Redaction {
+            socket_ids: SocketIdRedaction::Hash,
+            ..Redaction::all()
+        };
+        for redaction in [Redaction::all(), hashed] {
+            let output = serialized(&redaction);
+            assert!(output.contains("\"socket_id\":"));
+            assert_eq!(leaked(&output), Vec::<String>::new(), "{redaction:?}");
+        }
+    }
+
+    /// A bundle of replayed `capture()` after three interval reads
+    fn replayed_bundle(dir: &Path, redaction: &Redaction) -> Vec<PathBuf> {
+        let config = CollectorConfig::default();
+        let events = capture();
+        let replay = Replay::new(config.clone(), 2);
+        replay.feed(&events);
+        for _ in 0..3 {
+            replay.read(Duration::from_secs(1));
+        }
+        let state = DiagnosticState {
+            state: CollectorState::Collecting,
+            config: &config,
+            health: HealthReport::default(),
+            probes: vec![("tracefs", true), ("tcp_rto", false)],
+            missing_signals: vec![Signal::Softirq],
+            suppressed: vec![("tcp_state_evictions", 0)],
+            programs: "{\"bpf_stats_enabled\":null,\"programs\":[]}".to_string(),
+            reader_stats: ReaderStats::default(),
+            memory: MemoryReport::default(),
+            intervals: replay.interval.recent_intervals(usize::MAX),
+            sockets: replay.signals.sockets.peek(),
+            events: Some(events),
+            governor: None,
+        };
+        write_bundle(dir, &state, redaction).unwrap()
+    }
+
+    fn parse(path: &Path) -> JsonValue {
+        let text = std::fs::read_to_string(path).unwrap();
+        parse_json(&text).unwrap_or_else(|e| panic!("{}: {e}", path.display()))
+    }
+
+    #[test]
+    fn every_file_of_a_replayed_bundle_parses_and_is_listed() {
+        let dir = fixtures::scratch_dir("bundle");
+        let written = replayed_bundle(&dir, &Redaction::none());
+        assert_eq!(written.last().unwrap(), &dir.join("manifest.json"));
+
+        let parsed: Vec<JsonValue> = written.iter().map(|path| parse(path)).collect();
+        let manifest = parsed.last().unwrap();
+        let JsonValue::Array(files) = manifest.get("files").unwrap() else {
+            panic!("manifest files is not an array");
+        };
+        let listed: Vec<PathBuf> = files
+            .iter()
+            .map(|file| match file.get("name") {
+                Some(JsonValue::String(name)) => dir.join(name),
+                other => panic!("file listed without a name: {other:?}"),
+            })
+            .collect();
+        assert_eq!(listed, written[..written.len() - 1]);
+        assert_eq!(manifest.get("event_stream"), Some(&JsonValue::Bool(true)));
+
+        let file = |name: &str| &parsed[written.iter().position(|p| p.ends_with(name)).unwrap()];
+        let JsonValue::Array(intervals) = file("intervals.json") else {
+            panic!("intervals.json is not an array");
+        };
+        assert_eq!(intervals.len(), 3);
+        let JsonValue::Array(events) = file("events.json") else {
+            panic!("events.json is not an array");
+        };
+        assert_eq!(events.len(), capture().len());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_redacted_bundle_leaks_no_socket_address_into_any_file() {
+        let dir = fixtures::scratch_dir("bundle-redacted");
+        for path in replayed_bundle(&dir, &Redaction::all()) {
+            let contents = std::fs::read_to_string(&path).unwrap();
+            assert_eq!(
+                leaked(&contents),
+                Vec::<String>::new(),
+                "{}",
+                path.display()
+            );
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn the_manifest_checks_against_the_export_model() {
+        let dir = fixtures::scratch_dir("manifest");
+        let written = replayed_bundle(&dir, &Redaction::none());
+        let manifest = std::fs::read_to_string(written.last().unwrap()).unwrap();
+        assert_eq!(crate::check_export("manifest", &manifest), Ok(()));
+        let read: crate::export_model::Manifest = serde_json::from_str(&manifest).unwrap();
+        assert_eq!(read.created_ms, Some(read.created_at_ms));
+    }
+}
@@ -0,0 +1,330 @@
+//Names for the kfree_skb drop reason. enum skb_drop_reason gets renumbered
+//between kernel versions (new reasons are inserted, not appended), so the
+//numeric value only means something together with the running kernel's own
+//table: BTF when available, the tracepoint's print format otherwise.
+
+use crate::btf::KernelBtf;
+use std::collections::HashMap;
+use std::fmt;
+
+const FORMAT_PATHS: [&str; 2] = [
+    "/sys/kernel/tracing/events/skb/kfree_skb/format",
+    "/sys/kernel/debug/tracing/events/skb/kfree_skb/format",
+];
+
+/// Why the kernel freed a packet without delivering it, see `drops_by_reason()`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DropReason {
+    NotSpecified,
+    NoSocket,
+    SocketClose,
+    SocketFilter,
+    SocketRcvbuff,
+    SocketBacklog,
+    ProtoMem,
+    PktTooSmall,
+    PktTooBig,
+    HdrTrunc,
+    IpCsum,
+    UdpCsum,
+    TcpCsum,
+    IpInhdr,
+    IpRpfilter,
+    IpNoproto,
+    IpOutnoroutes,
+    OtherHost,
+    NetfilterDrop,
+    XfrmPolicy,
+    BpfCgroupEgress,
+    NeighFailed,
+    NeighQueuefull,
+    TcIngress,
+    TcEgress,
+    QdiscDrop,
+    QdiscOverlimit,
+    QdiscCongested,
+    CpuBacklog,
+    Xdp,
+    UnhandledProto,
+    FullRing,
+    Nomem,
+    QueuePurge,
+    /// Named by the running kernel, but not a reason this build has a variant for
+    Other(String),
+    /// No name for this value on the running kernel, or no name table at all
+    Unknown(u32),
+}
+
+impl DropReason {
+    /// From the kernel's enumerator name, with or without the `SKB_DROP_REASON_` prefix
+    pub fn from_kernel_name(name: &str) -> Self {
+        let name = name.strip_prefix("SKB_DROP_REASON_").unwrap_or(name);
+        match name {
+            "NOT_SPECIFIED" => Self::NotSpecified,
+            "NO_SOCKET" => Self::NoSocket,
+            "SOCKET_CLOSE" => Self::SocketClose,
+            "SOCKET_FILTER" => Self::SocketFilter,
+            "SOCKET_RCVBUFF" => Self::SocketRcvbuff,
+            "SOCKET_BACKLOG" => Self::SocketBacklog,
+            "PROTO_MEM" => Self::ProtoMem,
+            "PKT_TOO_SMALL" => Self::PktTooSmall,
+            "PKT_TOO_BIG" => Self::PktTooBig,
+            "HDR_TRUNC" => Self::HdrTrunc,
+            "IP_CSUM" => Self::IpCsum,
+            "UDP_CSUM" => Self::UdpCsum,
+            "TCP_CSUM" => Self::TcpCsum,
+            "IP_INHDR" => Self::IpInhdr,
+            "IP_RPFILTER" => Self::IpRpfilter,
+            "IP_NOPROTO" => Self::IpNoproto,
+            "IP_OUTNOROUTES" => Self::IpOutnoroutes,
+            "OTHERHOST" => Self::OtherHost,
+            "NETFILTER_DROP" => Self::NetfilterDrop,
+            "XFRM_POLICY" => Self::XfrmPolicy,
+            "BPF_CGROUP_EGRESS" => Self::BpfCgroupEgress,
+            "NEIGH_FAILED" => Self::NeighFailed,
+            "NEIGH_QUEUEFULL" => Self::NeighQueuefull,
+            "TC_INGRESS" => Self::TcIngress,
+            "TC_EGRESS" => Self::TcEgress,
+            "QDISC_DROP" => Self::QdiscDrop,
+            "QDISC_OVERLIMIT" => Self::QdiscOverlimit,
+            "QDISC_CONGESTED" => Self::QdiscCongested,
+            "CPU_BACKLOG" => Self::CpuBacklog,
+            "XDP" => Self::Xdp,
+            "UNHANDLED_PROTO" => Self::UnhandledProto,
+            "FULL_RING" => Self::FullRing,
+            "NOMEM" => Self::Nomem,
+            "QUEUE_PURGE" => Self::QueuePurge,
+            other => Self::Other(other.to_string()),
+        }
+    }
+
+    /// Kernel spelling without the prefix, e.g. `SOCKET_RCVBUFF`
+    pub fn name(&self) -> &str {
+        match self {
+            Self::NotSpecified => "NOT_SPECIFIED",
+            Self::NoSocket => "NO_SOCKET",
+            Self::SocketClose => "SOCKET_CLOSE",
+            Self::SocketFilter => "SOCKET_FILTER",
+            Self::SocketRcvbuff => "SOCKET_RCVBUFF",
+            Self::SocketBacklog => "SOCKET_BACKLOG",
+            Self::ProtoMem => "PROTO_MEM",
+            Self::PktTooSmall => "PKT_TOO_SMALL",
+            Self::PktTooBig => "PKT_TOO_BIG",
+            Self::HdrTrunc => "HDR_TRUNC",
+            Self::IpCsum => "IP_CSUM",
+            Self::UdpCsum => "UDP_CSUM",
+            Self::TcpCsum => "TCP_CSUM",
+            Self::IpInhdr => "IP_INHDR",
+            Self::IpRpfilter => "IP_RPFILTER",
+            Self::IpNoproto => "IP_NOPROTO",
+            Self::IpOutnoroutes => "IP_OUTNOROUTES",
+            Self::OtherHost => "OTHERHOST",
+            Self::NetfilterDrop => "NETFILTER_DROP",
+            Self::XfrmPolicy => "XFRM_POLICY",
+            Self::BpfCgroupEgress => "BPF_CGROUP_EGRESS",
+            Self::NeighFailed => "NEIGH_FAILED",
+            Self::NeighQueuefull => "NEIGH_QUEUEFULL",
+            Self::TcIngress => "TC_INGRESS",
+            Self::TcEgress => "TC_EGRESS",
+            Self::QdiscDrop => "QDISC_DROP",
+            Self::QdiscOverlimit => "QDISC_OVERLIMIT",
+            Self::QdiscCongested => "QDISC_CONGESTED",
+            Self::CpuBacklog => "CPU_BACKLOG",
+            Self::Xdp => "XDP",
+            Self::UnhandledProto => "UNHANDLED_PROTO",
+            Self::FullRing => "FULL_RING",
+            Self::Nomem => "NOMEM",
+            Self::QueuePurge => "QUEUE_PURGE",
+            Self::Other(name) => name,
+            Self::Unknown(_) => "UNKNOWN",
+        }
+    }
+}
+
+impl fmt::Display for DropReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(value) => write!(f, "UNKNOWN({})", value),
+            other => f.write_str(other.name()),
+        }
+    }
+}
+
+/// Value -> reason for the running kernel
+#[derive(Debug, Default)]
+pub(crate) struct DropReasonNames {
+    by_value: HashMap<u32, DropReason>,
+}
+
+impl DropReasonNames {
+    /// BTF first, then the tracepoint format file. Empty (everything Unknown)
+    /// when neither is readable
+    pub(crate) fn resolve() -> Self {
+        if let Some(names) = KernelBtf::from_sys_fs()
+            .ok()
+            .and_then(|btf| btf.enum_values("skb_drop_reason"))
+        {
+            return Self::from_pairs(names.iter().map(|(name, value)| (name.as_str(), *value)));
+        }
+
+        for path in FORMAT_PATHS {
+            if let Ok(format) = std::fs::read_to_string(path) {
+                let names = Self::from_format(&format);
+                if !names.by_value.is_empty() {
+                    return names;
+                }
+            }
+        }
+
+        log::warn!(
+            "skb_drop_reason names unavailable (no BTF or tracefs), drop reasons stay numeric"
+        );
+        Self::default()
+    }
+
+    fn from_pairs<'a>(pairs: impl Iterator<Item = (&'a str, i64)>) -> Self {
+        let by_value = pairs
+            .filter(|(name, _)| {
+                // Markers, not reasons; 0 is also what the probe sends when it
+                // couldn't read the reason, so it must stay Unknown
+                !matches!(
+                    *name,
+                    "SKB_NOT_DROPPED_YET" | "SKB_DROP_REASON_MAX" | "SKB_DROP_REASON_SUBSYS_MASK"
+                )
+            })
+            .filter_map(|(name, value)| {
+                let value = u32::try_from(value).ok().filter(|&v| v != 0)?;
+                Some((value, DropReason::from_kernel_name(name)))
+            })
+            .collect();
+        Self { by_value }
+    }
+
+    /// Pairs from the print fmt line: `__print_symbolic(REC->reason, { 2, "NOT_SPECIFIED" }, ...)`
+    fn from_format(format: &str) -> Self {
+        let Some(symbols) = format
+            .lines()
+            .find(|line| line.trim_start().starts_with("print fmt:"))
+            .and_then(|line| line.split_once("__print_symbolic"))
+            .map(|(_, rest)| rest)
+        else {
+            return Self::default();
+        };
+
+        let pairs = symbols.split('{').skip(1).filter_map(|entry| {
+            let (value, rest) = entry.split_once(',')?;
+            let name = rest.split('"').nth(1)?;
+            Some((name, value.trim().parse::<i64>().ok()?))
+        });
+        Self::from_pairs(pairs)
+    }
+
+    pub(crate) fn lookup(&self, value: u32) -> DropReason {
+        self.by_value
+            .get(&value)
+            .cloned()
+            .unwrap_or(DropReason::Unknown(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // From a 6.1 kernel's kfree_skb format, trimmed
+    const FORMAT: &str = "name: kfree_skb
+ID: 1475
+format:
+\tfield:unsigned short common_type;\toffset:0;\tsize:2;\tsigned:0;
+\tfield:enum skb_drop_reason reason;\toffset:32;\tsize:4;\tsigned:0;
+
+print fmt: \"skbaddr=%p protocol=%u location=%p reason: %s\", REC->skbaddr, REC->protocol, \
+REC->location, __print_symbolic(REC->reason, { 1, \"NOT_DROPPED_YET\" }, { 2, \"NOT_SPECIFIED\" }, \
+{ 3, \"NO_SOCKET\" }, { 6, \"SOCKET_RCVBUFF\" }, { 42, \"QDISC_DROP\" }, { 77, \"DUMMY_NEW\" })
+";
+
+    #[test]
+    fn kernel_names_map_with_or_without_the_prefix() {
+        assert_eq!(
+            DropReason::from_kernel_name("SOCKET_RCVBUFF"),
+            DropReason::SocketRcvbuff
+        );
+        assert_eq!(
+            DropReason::from_kernel_name("SKB_DROP_REASON_QDISC_DROP"),
+            DropReason::QdiscDrop
+        );
+        assert_eq!(
+            DropReason::from_kernel_name("FULL_RING"),
+            DropReason::FullRing
+        );
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for reason in [
+            DropReason::NotSpecified,
+            DropReason::NoSocket,
+            DropReason::SocketRcvbuff,
+            DropReason::TcpCsum,
+            DropReason::OtherHost,
+            DropReason::NeighQueuefull,
+            DropReason::QdiscCongested,
+            DropReason::QueuePurge,
+        ] {
+            assert_eq!(DropReason::from_kernel_name(reason.name()), reason);
+            assert_eq!(reason.to_string(), reason.name());
+        }
+    }
+
+    #[test]
+    fn names_this_build_lacks_are_kept() {
+        let reason = DropReason::from_kernel_name("SKB_DROP_REASON_TCP_OLD_ACK");
+        assert_eq!(reason, DropReason::Other("TCP_OLD_ACK".to_string()));
+        assert_eq!(reason.to_string(), "TCP_OLD_ACK");
+    }
+
+    #[test]
+    fn values_without_a_name_are_unknown() {
+        let names = DropReasonNames::default();
+        assert_eq!(names.lookup(6), DropReason::Unknown(6));
+        assert_eq!(names.lookup(6).to_string(), "UNKNOWN(6)");
+        assert_eq!(names.lookup(6).name(), "UNKNOWN");
+    }
+
+    #[test]
+    fn the_tracepoint_format_names_values() {
+        let names = DropReasonNames::from_format(FORMAT);
+        assert_eq!(names.lookup(2), DropReason::NotSpecified);
+        assert_eq!(names.lookup(3), DropReason::NoSocket);
+        assert_eq!(names.lookup(6), DropReason::SocketRcvbuff);
+        assert_eq!(names.lookup(42), DropReason::QdiscDrop);
+        assert_eq!(names.lookup(77), DropReason::Other("DUMMY_NEW".to_string()));
+        assert_eq!(names.lookup(5), DropReason::Unknown(5));
+    }
+
+    #[test]
+    fn a_format_without_symbols_names_nothing() {
+        let names = DropReasonNames::from_format("print fmt: \"skbaddr=%p\", REC->skbaddr\n");
+        assert!(names.by_value.is_empty());
+        assert!(DropReasonNames::from_format("").by_value.is_empty());
+    }
+
+    #[test]
+    fn markers_and_zero_stay_unknown() {
+        let names = DropReasonNames::from_pairs(
+            [
+                ("SKB_NOT_DROPPED_YET", 0),
+                ("SKB_DROP_REASON_NOT_SPECIFIED", 2),
+                ("SKB_DROP_REASON_MAX", 90),
+                ("SKB_DROP_REASON_SUBSYS_MASK", 0xffff_0000),
+                ("SKB_DROP_REASON_NEGATIVE", -1),
+            ]
+            .into_iter(),
+        );
+        assert_eq!(names.lookup(0), DropReason::Unknown(0));
+        assert_eq!(names.lookup(2), DropReason::NotSpecified);
+        assert_eq!(names.lookup(90), DropReason::Unknown(90));
+        assert_eq!(names.lookup(0xffff_0000), DropReason::Unknown(0xffff_0000));
+        assert_eq!(names.by_value.len(), 1);
+    }
+}
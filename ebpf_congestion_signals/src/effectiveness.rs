@@ -0,0 +1,338 @@
+//Whether the governor's cuts work. Each interval's pressure is what a cut is
+//meant to relieve: the drops, wmem, TX queue and bufferbloat parts of the
+//score. A cut is judged by that pressure over the `window` intervals after it
+//against its mean over the `baseline` intervals up to it: improved when it fell
+//by `min_decline`, and the time to improvement is how long until the first
+//interval that low. A raise ends every window still open, since pressure after
+//it is the raise's doing. When few of the latest cuts improved, the congestion
+//isn't coming from us (cross traffic, a shared bottleneck, someone else's
+//queue) and cutting further only gives up rate: the tracker calls it
+//non-responsive, the governor takes `backoff` of each cut, and the severity
+//state says so. It's reconsidered with every cut judged after that.
+
+#[cfg(feature = "governor")]
+use crate::json::{json_opt, json_opt_f64};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// How cuts are judged, part of `GovernorPolicy`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EffectivenessConfig {
+    /// Intervals up to a cut whose pressure it's compared against
+    pub baseline: usize,
+    /// Intervals after a cut it's judged over, unless the rate is raised first
+    pub window: usize,
+    /// Share the pressure has to fall by for a cut to have improved things,
+    /// 0.2 = by a fifth
+    pub min_decline: f64,
+    /// Latest judged cuts the statistics are over
+    pub history: usize,
+    /// Judged cuts before the congestion can be called non-responsive
+    pub min_cuts: usize,
+    /// Share of cuts improved below which it's non-responsive
+    pub non_responsive_below: f64,
+    /// Share at or above which it's responsive again
+    pub responsive_above: f64,
+    /// Share of a cut the governor takes while it's non-responsive
+    pub backoff: f64,
+}
+
+impl Default for EffectivenessConfig {
+    fn default() -> Self {
+        Self {
+            baseline: 3,
+            // A second at 200 ms intervals
+            window: 5,
+            min_decline: 0.2,
+            history: 20,
+            min_cuts: 5,
+            non_responsive_below: 0.25,
+            responsive_above: 0.5,
+            backoff: 0.5,
+        }
+    }
+}
+
+/// How the governor's cuts have worked out, see [`EffectivenessTracker`]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Effectiveness {
+    /// Cuts judged since the governor was created, and how many improved
+    pub judged: u64,
+    pub improved: u64,
+    /// Share of the latest `history` judged cuts that improved, None before
+    /// any was judged
+    pub improved_fraction: Option<f64>,
+    /// Median time from cut to improvement of the latest improved ones
+    pub median_time_to_improvement: Option<Duration>,
+    /// Cuts whose window is still open
+    pub pending: usize,
+    /// Too few cuts improve: cuts are taken at `backoff`
+    pub non_responsive: bool,
+}
+
+impl Effectiveness {
+    #[cfg(feature = "governor")]
+    pub(crate) fn to_json(self) -> String {
+        format!(
+            "{{\"judged\":{},\"improved\":{},\"improved_fraction\":{},\
+             \"median_time_to_improvement_ms\":{},\"pending\":{},\"non_responsive\":{}}}",
+            self.judged,
+            self.improved,
+            json_opt_f64(self.improved_fraction),
+            json_opt(self.median_time_to_improvement.map(|t| t.as_millis())),
+            self.pending,
+            self.non_responsive,
+        )
+    }
+}
+
+// A cut being judged
+#[derive(Debug, Clone, Copy)]
+struct PendingCut {
+    before: f64,
+    after_sum: f64,
+    intervals: usize,
+    elapsed: Duration,
+    improved_after: Option<Duration>,
+}
+
+// A judged cut
+#[derive(Debug, Clone, Copy)]
+struct CutOutcome {
+    improved: bool,
+    time_to_improvement: Option<Duration>,
+}
+
+/// Judges each cut by the pressure after it, see the module docs. A
+/// `Governor` runs one for its policy
+#[derive(Debug, Clone)]
+pub struct EffectivenessTracker {
+    config: EffectivenessConfig,
+    recent: VecDeque<f64>,
+    pending: Vec<PendingCut>,
+    // Whether an interval was observed since the newest pending cut, so cuts
+    // between two intervals count once
+    observed_since_cut: bool,
+    outcomes: VecDeque<CutOutcome>,
+    judged: u64,
+    improved: u64,
+    non_responsive: bool,
+}
+
+impl EffectivenessTracker {
+    pub fn new(config: EffectivenessConfig) -> Self {
+        Self {
+            recent: VecDeque::with_capacity(config.baseline),
+            outcomes: VecDeque::with_capacity(config.history),
+            config,
+            pending: Vec::new(),
+            observed_since_cut: true,
+            judged: 0,
+            improved: 0,
+            non_responsive: false,
+        }
+    }
+
+    pub fn config(&self) -> &EffectivenessConfig {
+        &self.config
+    }
+
+    /// One interval's pressure, the score's parts a cut is meant to relieve,
+    /// and how long it was
+    pub fn observe(&mut self, pressure: f64, interval: Duration) {
+        if self.recent.len() == self.config.baseline.max(1) {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(pressure);
+        self.observed_since_cut = true;
+
+        let threshold = 1.0 - self.config.min_decline;
+        for cut in &mut self.pending {
+            cut.after_sum += pressure;
+            cut.intervals += 1;
+            cut.elapsed += interval;
+            if cut.improved_after.is_none() && pressure <= cut.before * threshold {
+                cut.improved_after = Some(cut.elapsed);
+            }
+        }
+        let window = self.config.window.max(1);
+        let (done, open): (Vec<_>, Vec<_>) = self
+            .pending
+            .drain(..)
+            .partition(|cut| cut.intervals >= window);
+        self.pending = open;
+        self.judge(done);
+    }
+
+    /// The rate was just cut, after the intervals observed so far. Cuts with
+    /// nothing to relieve, or another cut since the last interval, aren't judged
+    pub fn cut(&mut self) {
+        if !self.observed_since_cut || self.recent.is_empty() {
+            return;
+        }
+        let before = self.recent.iter().sum::<f64>() / self.recent.len() as f64;
+        if before <= 0.0 {
+            return;
+        }
+        self.observed_since_cut = false;
+        self.pending.push(PendingCut {
+            before,
+            after_sum: 0.0,
+            intervals: 0,
+            elapsed: Duration::ZERO,
+            improved_after: None,
+        });
+    }
+
+    /// The rate was just raised: judge every open window on what it saw
+    pub fn raised(&mut self) {
+        let open = std::mem::take(&mut self.pending);
+        self.judge(open.into_iter().filter(|cut| cut.intervals > 0).collect());
+    }
+
+    /// Whether too few of the latest cuts improved things
+    pub fn non_responsive(&self) -> bool {
+        self.non_responsive
+    }
+
+    pub fn effectiveness(&self) -> Effectiveness {
+        let improved = self.outcomes.iter().filter(|o| o.improved).count();
+        let mut times: Vec<Duration> = self
+            .outcomes
+            .iter()
+            .filter(|o| o.improved)
+            .filter_map(|o| o.time_to_improvement)
+            .collect();
+        times.sort();
+        Effectiveness {
+            judged: self.judged,
+            improved: self.improved,
+            improved_fraction: (!self.outcomes.is_empty())
+                .then(|| improved as f64 / self.outcomes.len() as f64),
+            median_time_to_improvement: times.get(times.len() / 2).copied(),
+            pending: self.pending.len(),
+            non_responsive: self.non_responsive,
+        }
+    }
+
+    fn judge(&mut self, cuts: Vec<PendingCut>) {
+        if cuts.is_empty() {
+            return;
+        }
+        let threshold = 1.0 - self.config.min_decline;
+        for cut in cuts {
+            let after = cut.after_sum / cut.intervals as f64;
+            let improved = after <= cut.before * threshold;
+            if self.outcomes.len() == self.config.history.max(1) {
+                self.outcomes.pop_front();
+            }
+            self.outcomes.push_back(CutOutcome {
+                improved,
+                time_to_improvement: cut.improved_after,
+            });
+            self.judged += 1;
+            self.improved += improved as u64;
+        }
+        if self.outcomes.len() < self.config.min_cuts {
+            return;
+        }
+        let fraction =
+            self.outcomes.iter().filter(|o| o.improved).count() as f64 / self.outcomes.len() as f64;
+        if fraction < self.config.non_responsive_below {
+            self.non_responsive = true;
+        } else if fraction >= self.config.responsive_above {
+            self.non_responsive = false;
+        }
+    }
+}
+
+impl Default for EffectivenessTracker {
+    fn default() -> Self {
+        Self::new(EffectivenessConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_millis(200);
+
+    /// A tracker that saw `pressure` for a baseline, then cut
+    fn cut_at(tracker: &mut EffectivenessTracker, pressure: f64) {
+        for _ in 0..tracker.config().baseline {
+            tracker.observe(pressure, INTERVAL);
+        }
+        tracker.cut();
+    }
+
+    #[test]
+    fn a_cut_is_judged_over_its_window() {
+        let mut tracker = EffectivenessTracker::default();
+        cut_at(&mut tracker, 1.0);
+        tracker.observe(0.9, INTERVAL);
+        tracker.observe(0.5, INTERVAL);
+        assert_eq!(tracker.effectiveness().pending, 1);
+        for _ in 0..3 {
+            tracker.observe(0.5, INTERVAL);
+        }
+        let effectiveness = tracker.effectiveness();
+        assert_eq!((effectiveness.judged, effectiveness.improved), (1, 1));
+        assert_eq!(effectiveness.pending, 0);
+        assert_eq!(effectiveness.improved_fraction, Some(1.0));
+        assert_eq!(effectiveness.median_time_to_improvement, Some(2 * INTERVAL));
+    }
+
+    #[test]
+    fn a_raise_judges_the_open_windows_on_what_they_saw() {
+        let mut tracker = EffectivenessTracker::default();
+        cut_at(&mut tracker, 1.0);
+        tracker.observe(1.0, INTERVAL);
+        tracker.raised();
+        let effectiveness = tracker.effectiveness();
+        assert_eq!((effectiveness.judged, effectiveness.improved), (1, 0));
+        assert_eq!(effectiveness.median_time_to_improvement, None);
+
+        // Nothing seen after this one yet
+        cut_at(&mut tracker, 1.0);
+        tracker.raised();
+        assert_eq!(tracker.effectiveness().judged, 1);
+    }
+
+    #[test]
+    fn cuts_with_nothing_to_relieve_or_back_to_back_are_not_judged() {
+        let mut tracker = EffectivenessTracker::default();
+        tracker.cut();
+        cut_at(&mut tracker, 0.0);
+        assert_eq!(tracker.effectiveness().pending, 0);
+        cut_at(&mut tracker, 1.0);
+        tracker.cut();
+        assert_eq!(tracker.effectiveness().pending, 1);
+    }
+
+    #[test]
+    fn cuts_that_never_help_are_non_responsive_until_they_do() {
+        // A cut, one interval of `after` and a raise
+        let judged = |tracker: &mut EffectivenessTracker, after| {
+            cut_at(tracker, 1.0);
+            tracker.observe(after, INTERVAL);
+            tracker.raised();
+        };
+        let mut tracker = EffectivenessTracker::default();
+        for _ in 0..4 {
+            judged(&mut tracker, 1.0);
+            assert!(!tracker.non_responsive());
+        }
+        judged(&mut tracker, 1.0);
+        assert!(tracker.non_responsive());
+        assert_eq!(tracker.effectiveness().improved_fraction, Some(0.0));
+
+        // Four of nine isn't yet responsive_above, five of ten is
+        for _ in 0..4 {
+            judged(&mut tracker, 0.1);
+        }
+        assert!(tracker.non_responsive());
+        judged(&mut tracker, 0.1);
+        assert!(!tracker.non_responsive());
+    }
+}
@@ -0,0 +1,491 @@
+//Exact egress accounting from the tc_egress classifier. The sendmsg probes only
+//see 1 in 100 sends at the socket layer; this counts every packet the interface
+//is handed, after GSO is decided and before the qdisc, in per-CPU counters.
+//
+//On kernels with TCX (6.6+) the program attaches without a qdisc. Older kernels
+//need clsact: we add it when it's missing and delete it again on drop, but never
+//touch a clsact someone else created.
+
+use crate::{EgressCount, InterfaceEgress, MAX_EGRESS_INTERFACES};
+use aya::maps::{MapData, PerCpuHashMap};
+use aya::programs::{tc, SchedClassifier, TcAttachType};
+use aya::Ebpf;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::io;
+use std::sync::Mutex;
+
+// include/uapi/linux/pkt_sched.h
+const TC_H_CLSACT: u32 = 0xFFFF_FFF1;
+const TC_H_CLSACT_HANDLE: u32 = 0xFFFF_0000;
+// TC_H_MAKE(TC_H_CLSACT, TC_H_MIN_EGRESS)
+const TC_H_CLSACT_EGRESS: u32 = 0xFFFF_FFF3;
+// include/uapi/linux/rtnetlink.h and pkt_cls.h
+const TCA_KIND: u16 = 1;
+const TCA_OPTIONS: u16 = 2;
+const TCA_BPF_ID: u16 = 11;
+const NLA_TYPE_MASK: u16 = 0x3FFF;
+const NLMSG_HEADER_LEN: usize = std::mem::size_of::<libc::nlmsghdr>();
+// struct tcmsg
+const TCMSG_LEN: usize = 20;
+
+struct Interface {
+    name: String,
+    ifindex: u32,
+    created_clsact: bool,
+}
+
+pub(crate) struct EgressAccounting {
+    interfaces: Vec<Interface>,
+    // Taken out of the Ebpf so reads don't need it; None if the map was unusable
+    counters: Option<PerCpuHashMap<MapData, u32, EgressCount>>,
+    // ifindex -> (bytes, packets) at the previous read
+    last_totals: Mutex<HashMap<u32, (u64, u64)>>,
+}
+
+impl EgressAccounting {
+    /// Attach tc_egress to every interface that allows it. An interface that fails
+    /// is logged and left out, like the other optional probes. `previous` is the
+    /// accounting being replaced by a reload: clsact qdiscs it created are taken
+    /// over so they still get cleaned up, by us instead of it.
+    pub(crate) fn attach(
+        ebpf: &mut Ebpf,
+        names: &[String],
+        previous: Option<&mut EgressAccounting>,
+    ) -> anyhow::Result<Self> {
+        if names.len() > MAX_EGRESS_INTERFACES as usize {
+            anyhow::bail!(
+                "{} egress interfaces configured, at most {} supported",
+                names.len(),
+                MAX_EGRESS_INTERFACES
+            );
+        }
+
+        let prog: &mut SchedClassifier = ebpf
+            .program_mut("tc_egress")
+            .ok_or_else(|| anyhow::anyhow!("program tc_egress not in object"))?
+            .try_into()?;
+        prog.load()?;
+
+        let mut inherited: HashMap<u32, bool> = HashMap::new();
+        if let Some(previous) = previous {
+            for interface in &mut previous.interfaces {
+                inherited.insert(interface.ifindex, interface.created_clsact);
+                interface.created_clsact = false;
+            }
+        }
+
+        let mut interfaces = Vec::with_capacity(names.len());
+        for name in names {
+            let ifindex = match ifindex(name) {
+                Ok(ifindex) => ifindex,
+                Err(e) => {
+                    log::warn!("egress accounting on {} disabled: {}", name, e);
+                    continue;
+                }
+            };
+            let mut created_clsact = inherited.get(&ifindex).copied().unwrap_or(false);
+
+            let mut attached = prog.attach(name, TcAttachType::Egress);
+            if attached.is_err() && !created_clsact {
+                // Legacy netlink attach, only works with a clsact qdisc in place
+                match tc::qdisc_add_clsact(name) {
+                    Ok(()) => {
+                        created_clsact = true;
+                        attached = prog.attach(name, TcAttachType::Egress);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                    Err(e) => log::warn!("adding clsact qdisc to {} failed: {}", name, e),
+                }
+            }
+
+            let iface = Interface {
+                name: name.clone(),
+                ifindex,
+                created_clsact,
+            };
+            match attached {
+                Ok(_) => {
+                    log::info!("tc egress accounting attached to {}", name);
+                    interfaces.push(iface);
+                }
+                Err(e) => {
+                    log::warn!("egress accounting on {} disabled: {}", name, e);
+                    // Dropping it removes the clsact we just added
+                    drop(iface);
+                }
+            }
+        }
+
+        let counters = match ebpf.take_map("EGRESS_COUNTERS").map(PerCpuHashMap::try_from) {
+            Some(Ok(map)) => Some(map),
+            _ => {
+                log::warn!("EGRESS_COUNTERS map unusable, egress accounting reads nothing");
+                None
+            }
+        };
+
+        Ok(Self {
+            interfaces,
+            counters,
+            last_totals: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Egress per interface since the previous call
+    pub(crate) fn read_interval(&self) -> Vec<InterfaceEgress> {
+        let Some(map) = &self.counters else {
+            return Vec::new();
+        };
+
+        let mut last_totals = self.last_totals.lock().unwrap();
+        self.interfaces
+            .iter()
+            .map(|interface| {
+                // No entry yet just means nothing was sent
+                let (bytes, packets) = map
+                    .get(&interface.ifindex, 0)
+                    .map(|per_cpu| {
+                        per_cpu
+                            .iter()
+                            .fold((0, 0), |(b, p), c| (b + c.bytes, p + c.packets))
+                    })
+                    .unwrap_or((0, 0));
+                let (last_bytes, last_packets) = last_totals
+                    .insert(interface.ifindex, (bytes, packets))
+                    .unwrap_or((0, 0));
+                InterfaceEgress {
+                    interface: interface.name.clone(),
+                    egress_bytes_exact: bytes.saturating_sub(last_bytes),
+                    egress_packets_exact: packets.saturating_sub(last_packets),
+                }
+            })
+            .collect()
+    }
+
+    /// The interfaces tc_egress attached to
+    pub(crate) fn interfaces(&self) -> impl Iterator<Item = &str> {
+        self.interfaces
+            .iter()
+            .map(|interface| interface.name.as_str())
+    }
+
+    /// `interface` lost our classifier. A clsact we created went with it, or
+    /// isn't the one there now: it's no longer ours to delete
+    pub(crate) fn lost(&mut self, interface: &str) {
+        for lost in self.interfaces.iter_mut().filter(|i| i.name == interface) {
+            lost.created_clsact = false;
+        }
+    }
+}
+
+impl Drop for Interface {
+    fn drop(&mut self) {
+        if !self.created_clsact {
+            return;
+        }
+        match delete_clsact(self.ifindex) {
+            Ok(()) => log::info!("removed clsact qdisc from {}", self.name),
+            // Interface gone (veth peer deleted, ...) takes the qdisc with it
+            Err(e) if e.raw_os_error() == Some(libc::ENODEV) => {}
+            Err(e) => log::warn!("removing clsact qdisc from {} failed: {}", self.name, e),
+        }
+    }
+}
+
+fn ifindex(name: &str) -> io::Result<u32> {
+    let c_name = CString::new(name)?;
+    match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        ifindex => Ok(ifindex),
+    }
+}
+
+/// RTM_DELQDISC for the clsact qdisc; aya can add one but not remove it
+fn delete_clsact(ifindex: u32) -> io::Result<()> {
+    #[repr(C)]
+    struct Request {
+        header: libc::nlmsghdr,
+        // struct tcmsg
+        family: u8,
+        pad1: u8,
+        pad2: u16,
+        ifindex: i32,
+        handle: u32,
+        parent: u32,
+        info: u32,
+    }
+
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::NETLINK_ROUTE,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let result = (|| {
+        let request = Request {
+            header: libc::nlmsghdr {
+                nlmsg_len: std::mem::size_of::<Request>() as u32,
+                nlmsg_type: libc::RTM_DELQDISC,
+                nlmsg_flags: (libc::NLM_F_REQUEST | libc::NLM_F_ACK) as u16,
+                nlmsg_seq: 1,
+                nlmsg_pid: 0,
+            },
+            family: libc::AF_UNSPEC as u8,
+            pad1: 0,
+            pad2: 0,
+            ifindex: ifindex as i32,
+            handle: TC_H_CLSACT_HANDLE,
+            parent: TC_H_CLSACT,
+            info: 0,
+        };
+        let sent = unsafe {
+            libc::send(
+                fd,
+                &request as *const Request as *const libc::c_void,
+                std::mem::size_of::<Request>(),
+                0,
+            )
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // The ack is an NLMSG_ERROR carrying 0 or -errno
+        let mut buf = [0u8; 256];
+        let received =
+            unsafe { libc::recv(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let header_len = std::mem::size_of::<libc::nlmsghdr>();
+        if (received as usize) < header_len + 4 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let msg_type = u16::from_ne_bytes([buf[4], buf[5]]);
+        if msg_type == libc::NLMSG_ERROR as u16 {
+            let errno = i32::from_ne_bytes(buf[header_len..header_len + 4].try_into().unwrap());
+            if errno != 0 {
+                return Err(io::Error::from_raw_os_error(-errno));
+            }
+        }
+        Ok(())
+    })();
+    unsafe { libc::close(fd) };
+    result
+}
+
+/// Ids of the bpf classifiers on `interface`'s clsact egress, by an RTM_GETTFILTER
+/// dump. Empty without a clsact qdisc
+pub(crate) fn classifier_programs(interface: &str) -> io::Result<Vec<u32>> {
+    #[repr(C)]
+    struct Request {
+        header: libc::nlmsghdr,
+        // struct tcmsg
+        family: u8,
+        pad1: u8,
+        pad2: u16,
+        ifindex: i32,
+        handle: u32,
+        parent: u32,
+        info: u32,
+    }
+
+    let ifindex = ifindex(interface)?;
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::NETLINK_ROUTE,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let result = (|| {
+        let request = Request {
+            header: libc::nlmsghdr {
+                nlmsg_len: std::mem::size_of::<Request>() as u32,
+                nlmsg_type: libc::RTM_GETTFILTER,
+                nlmsg_flags: (libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16,
+                nlmsg_seq: 1,
+                nlmsg_pid: 0,
+            },
+            family: libc::AF_UNSPEC as u8,
+            pad1: 0,
+            pad2: 0,
+            ifindex: ifindex as i32,
+            handle: 0,
+            parent: TC_H_CLSACT_EGRESS,
+            info: 0,
+        };
+        let sent = unsafe {
+            libc::send(
+                fd,
+                &request as *const Request as *const libc::c_void,
+                std::mem::size_of::<Request>(),
+                0,
+            )
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut ids = Vec::new();
+        let mut buf = vec![0u8; 32 * 1024];
+        loop {
+            let received =
+                unsafe { libc::recv(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+            if received < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut messages = &buf[..received as usize];
+            while messages.len() >= NLMSG_HEADER_LEN {
+                let len = u32::from_ne_bytes(messages[0..4].try_into().unwrap()) as usize;
+                let msg_type = u16::from_ne_bytes([messages[4], messages[5]]);
+                if len < NLMSG_HEADER_LEN || len > messages.len() {
+                    return Err(io::ErrorKind::InvalidData.into());
+                }
+                match msg_type as libc::c_int {
+                    libc::NLMSG_DONE => return Ok(ids),
+                    libc::NLMSG_ERROR => {
+                        let errno = messages
+                            .get(NLMSG_HEADER_LEN..NLMSG_HEADER_LEN + 4)
+                            .map_or(0, |errno| i32::from_ne_bytes(errno.try_into().unwrap()));
+                        return match errno {
+                            0 => Ok(ids),
+                            // Nothing to dump under a parent that isn't there
+                            errno if -errno == libc::EINVAL || -errno == libc::ENOENT => Ok(ids),
+                            errno => Err(io::Error::from_raw_os_error(-errno)),
+                        };
+                    }
+                    _ if msg_type == libc::RTM_NEWTFILTER => {
+                        let attributes = messages
+                            .get(NLMSG_HEADER_LEN + TCMSG_LEN..len)
+                            .unwrap_or_default();
+                        ids.extend(bpf_classifier_id(attributes));
+                    }
+                    _ => {}
+                }
+                messages = &messages[align4(len).min(messages.len())..];
+            }
+        }
+    })();
+    unsafe { libc::close(fd) };
+    result
+}
+
+fn align4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Netlink attributes as (type, payload)
+fn attributes(mut bytes: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        if bytes.len() < 4 {
+            return None;
+        }
+        let len = u16::from_ne_bytes([bytes[0], bytes[1]]) as usize;
+        let kind = u16::from_ne_bytes([bytes[2], bytes[3]]) & NLA_TYPE_MASK;
+        if len < 4 || len > bytes.len() {
+            return None;
+        }
+        let payload = &bytes[4..len];
+        bytes = &bytes[align4(len).min(bytes.len())..];
+        Some((kind, payload))
+    })
+}
+
+/// The program id of a filter message's attributes, if it's a bpf classifier
+fn bpf_classifier_id(filter: &[u8]) -> Option<u32> {
+    let mut is_bpf = false;
+    let mut id = None;
+    for (kind, payload) in attributes(filter) {
+        match kind {
+            TCA_KIND => is_bpf = payload.starts_with(b"bpf\0"),
+            TCA_OPTIONS => {
+                id = attributes(payload)
+                    .find(|&(kind, payload)| kind == TCA_BPF_ID && payload.len() >= 4)
+                    .map(|(_, payload)| u32::from_ne_bytes(payload[..4].try_into().unwrap()));
+            }
+            _ => {}
+        }
+    }
+    id.filter(|_| is_bpf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One netlink attribute, padded to 4 bytes
+    fn attribute(kind: u16, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = ((4 + payload.len()) as u16).to_ne_bytes().to_vec();
+        bytes.extend(kind.to_ne_bytes());
+        bytes.extend(payload);
+        bytes.resize(align4(bytes.len()), 0);
+        bytes
+    }
+
+    // A filter message's attributes: its kind, then its options
+    fn filter(kind: &[u8], options: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = attribute(TCA_KIND, kind);
+        bytes.extend(attribute(TCA_OPTIONS, &options.concat()));
+        bytes
+    }
+
+    #[test]
+    fn attributes_step_over_their_padding() {
+        let mut bytes = attribute(TCA_KIND, b"bpf\0");
+        bytes.extend(attribute(7, &[1, 2, 3, 4, 5]));
+        // Nested, which sets NLA_F_NESTED
+        bytes.extend(attribute(TCA_OPTIONS | 0x8000, &[]));
+        let found: Vec<(u16, Vec<u8>)> = attributes(&bytes)
+            .map(|(kind, payload)| (kind, payload.to_vec()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (TCA_KIND, b"bpf\0".to_vec()),
+                (7, vec![1, 2, 3, 4, 5]),
+                (TCA_OPTIONS, vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn attributes_stop_at_a_truncated_one() {
+        let mut bytes = attribute(TCA_KIND, b"bpf\0");
+        bytes.extend(attribute(7, &[0; 8]));
+        let cut = bytes.len() - 4;
+        assert_eq!(attributes(&bytes[..cut]).count(), 1);
+        // Shorter than its own header
+        assert_eq!(attributes(&[2, 0, 1, 0, 0, 0]).count(), 0);
+        assert_eq!(attributes(&[8, 0, 1]).count(), 0);
+    }
+
+    #[test]
+    fn a_bpf_classifiers_program_id_is_read_from_its_options() {
+        let id = attribute(TCA_BPF_ID, &42u32.to_ne_bytes());
+        let name = attribute(3, b"tc_egress\0");
+        assert_eq!(
+            bpf_classifier_id(&filter(b"bpf\0", &[name.clone(), id.clone()])),
+            Some(42)
+        );
+        // Another classifier kind, or a bpf one without an id
+        assert_eq!(
+            bpf_classifier_id(&filter(b"u32\0", std::slice::from_ref(&id))),
+            None
+        );
+        assert_eq!(bpf_classifier_id(&filter(b"bpf\0", &[name])), None);
+        assert_eq!(bpf_classifier_id(&filter(b"bpfx", &[id])), None);
+        // An id too short to hold one
+        let short = attribute(TCA_BPF_ID, &[1, 2]);
+        assert_eq!(bpf_classifier_id(&filter(b"bpf\0", &[short])), None);
+        assert_eq!(bpf_classifier_id(&[]), None);
+    }
+}
@@ -0,0 +1,94 @@
+//Typed errors for the failure modes callers are expected to handle.
+//Everything else still goes through anyhow, so match on these with
+//`err.downcast_ref::<CollectorError>()`.
+
+use crate::{CollectorState, ProgramLoadFailure};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CollectorError {
+    /// The object has no CONGESTION_SCHEMA marker (not ours, or too old to carry one)
+    MissingSchema,
+    /// The object was built against a different shared-types layout
+    SchemaMismatch { expected: u32, found: u32 },
+    /// The object's event layout can't be decoded by this build even though the
+    /// version would be acceptable
+    IncompatibleLayout { version: u32, reason: String },
+    /// A lifecycle method was called in the wrong state, e.g. `start_collection` twice
+    InvalidState {
+        expected: CollectorState,
+        actual: CollectorState,
+    },
+    /// Another collector is loaded in this process, or holds the host-wide lock
+    /// (`system_wide`); see `CollectorConfig::conflict_check`
+    AlreadyActive { system_wide: bool },
+    /// RLIMIT_MEMLOCK (`limit` bytes) is charged for eBPF memory on this kernel
+    /// and couldn't be raised to the `needed` bytes that the maps and the
+    /// smallest perf buffers take; see `CollectorConfig::memlock`
+    MemlockTooLow { limit: u64, needed: u64 },
+    /// A core program group was rejected under `CollectorConfig::strict`
+    ProgramRejected(ProgramLoadFailure),
+    /// An extension's name or event types are taken, or the types are outside
+    /// EXTENSION_EVENT_TYPES; see `CongestionCollector::register_extension`
+    ExtensionRejected { name: String, reason: String },
+}
+
+impl fmt::Display for CollectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CollectorError::MissingSchema => {
+                write!(f, "eBPF object has no CONGESTION_SCHEMA marker")
+            }
+            CollectorError::SchemaMismatch { expected, found } => write!(
+                f,
+                "eBPF object schema version {} does not match userspace version {}",
+                found, expected
+            ),
+            CollectorError::IncompatibleLayout { version, reason } => write!(
+                f,
+                "eBPF object schema version {} has an incompatible layout: {}",
+                version, reason
+            ),
+            CollectorError::InvalidState { expected, actual } => write!(
+                f,
+                "collector is {:?}, operation requires {:?}",
+                actual, expected
+            ),
+            CollectorError::AlreadyActive { system_wide: false } => {
+                write!(
+                    f,
+                    "a congestion collector is already loaded in this process"
+                )
+            }
+            CollectorError::AlreadyActive { system_wide: true } => write!(
+                f,
+                "another process holds the host-wide congestion collector lock"
+            ),
+            CollectorError::MemlockTooLow { limit, needed } => write!(
+                f,
+                "RLIMIT_MEMLOCK is {} KB but the eBPF maps and smallest perf buffers need {} KB \
+                 on this kernel; raise it with `ulimit -l {}` (or LimitMEMLOCK=infinity in the \
+                 unit), or run with CAP_SYS_RESOURCE so it's raised at load",
+                limit / 1024,
+                needed.div_ceil(1024),
+                needed.div_ceil(1024)
+            ),
+            CollectorError::ProgramRejected(failure) => {
+                write!(
+                    f,
+                    "core program group {} rejected: {} failed to {}: {}",
+                    failure.group, failure.program, failure.stage, failure.error
+                )?;
+                match &failure.verifier_log {
+                    Some(log) => write!(f, "\nverifier log:\n{}", log),
+                    None => Ok(()),
+                }
+            }
+            CollectorError::ExtensionRejected { name, reason } => {
+                write!(f, "extension {:?} rejected: {}", name, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CollectorError {}
@@ -0,0 +1,86 @@
+//The JSON the crate writes, as one versioned model. Every document (interval
+//signals, decision log lines, preflight reports, session reports, the
+//diagnostic bundle's manifest, soak reports) starts with `schema_version`.
+//Their field names and types, nested objects included, are the serde types of
+//export_model, and nothing else: schemars generates the JSON Schema in
+//schema/export.schema.json from them (`cargo xtask schema`), and
+//`check_export` reads a document back against that schema. The emitters stay
+//hand-written (json.rs); the tests check what they write against the model,
+//and the checked-in documents of the previous version against the current one.
+//
+//Bump EXPORT_SCHEMA_VERSION when a field is renamed, retired or changes
+//meaning; adding one doesn't need it. A rename or retirement goes into
+//FIELD_DEPRECATIONS for the version it happens in: the old name is written
+//next to the new one and is a field of the model for that version, then
+//removed from both.
+
+/// Version of the exported JSON documents, their `schema_version`. Documents
+/// from before there was one count as version 0
+pub const EXPORT_SCHEMA_VERSION: u32 = 1;
+
+/// A field renamed (`new` Some) or retired (None) in schema version `since`.
+/// For that version the old name is still written and read; the next drops it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldDeprecation {
+    /// Name of the definition the field is in
+    pub definition: &'static str,
+    pub old: &'static str,
+    pub new: Option<&'static str>,
+    pub since: u32,
+}
+
+impl FieldDeprecation {
+    /// Still written and read at EXPORT_SCHEMA_VERSION
+    pub fn is_live(&self) -> bool {
+        EXPORT_SCHEMA_VERSION <= self.since
+    }
+}
+
+/// Renamed and retired fields, see the module docs
+pub const FIELD_DEPRECATIONS: &[FieldDeprecation] = &[
+    FieldDeprecation {
+        definition: "preflight",
+        old: "memlock_limit",
+        new: Some("memlock_limit_bytes"),
+        since: 1,
+    },
+    FieldDeprecation {
+        definition: "manifest",
+        old: "created_ms",
+        new: Some("created_at_ms"),
+        since: 1,
+    },
+];
+
+/// `,"<old>":<value>` for each live deprecation renaming a field to `field` of
+/// `definition`, for emitters to write right after it
+#[cfg(feature = "collector-core")]
+pub(crate) fn aliases(definition: &str, field: &str, value: &str) -> String {
+    FIELD_DEPRECATIONS
+        .iter()
+        .filter(|d| d.definition == definition && d.new == Some(field) && d.is_live())
+        .map(|d| format!(",{}:{}", crate::json::json_string(d.old), value))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deprecations_stay_for_one_version() {
+        assert!(FIELD_DEPRECATIONS.iter().all(FieldDeprecation::is_live));
+    }
+
+    #[cfg(feature = "collector-core")]
+    #[test]
+    fn a_renamed_field_is_written_under_both_names() {
+        let preflight = crate::fixtures::documents()
+            .into_iter()
+            .find(|(document, _)| *document == "preflight")
+            .unwrap()
+            .1;
+        assert!(preflight.contains("\"memlock_limit_bytes\":65536"));
+        assert!(preflight.contains("\"memlock_limit\":65536"));
+    }
+}
@@ -0,0 +1,811 @@
+//The exported JSON documents as serde types, the one description of them (see
+//export.rs): for readers, for schemars, which generates
+//schema/export.schema.json from these (`cargo xtask schema`), and for
+//`check_export`, which walks that schema. An integer is u64, a number
+//Option<f64> because one that isn't finite is written as null, a nullable
+//field an Option. Fields added since version 0 default, so the previous
+//version's documents read; a renamed field's old name is a field of its own
+//while its deprecation is live, since the emitters write both. Every struct
+//denies unknown fields, so a rename without a deprecation fails to read.
+
+use crate::FIELD_DEPRECATIONS;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+/// Any one of the documents, told apart by their fields
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+#[schemars(title = "ebpf_congestion_signals exported JSON")]
+pub enum ExportDocument {
+    Signals(Box<Signals>),
+    Decision(Box<Decision>),
+    Preflight(Box<Preflight>),
+    Session(Box<Session>),
+    Manifest(Box<Manifest>),
+    Soak(Box<Soak>),
+}
+
+/// The JSON Schema of [`ExportDocument`], as checked in at
+/// schema/export.schema.json
+pub fn export_json_schema() -> String {
+    let schema = schemars::schema_for!(ExportDocument);
+    let mut out = serde_json::to_string_pretty(&schema).expect("a schema serializes");
+    out.push('\n');
+    out
+}
+
+// The schema as JSON, for check_export to walk
+fn schema_value() -> &'static Value {
+    static SCHEMA: OnceLock<Value> = OnceLock::new();
+    SCHEMA.get_or_init(|| {
+        serde_json::to_value(schemars::schema_for!(ExportDocument)).expect("a schema serializes")
+    })
+}
+
+/// Check `json` against the model of `document` (`"signals"`, `"manifest"`,
+/// any `$defs` name of the schema) the way the model reads it: unknown fields,
+/// missing ones it has no default for and values of the wrong type are
+/// problems, and so is a deprecated name after its deprecation. Every problem
+/// found, by path
+pub fn check_export(document: &str, json: &str) -> Result<(), Vec<String>> {
+    if schema_value()["$defs"].get(document).is_none() {
+        return Err(vec![format!("no definition {:?}", document)]);
+    }
+    let value: Value = serde_json::from_str(json).map_err(|e| vec![e.to_string()])?;
+    let mut problems = Vec::new();
+    check_object(document, &value, document, &mut problems);
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
+}
+
+fn check_object(name: &str, value: &Value, path: &str, problems: &mut Vec<String>) {
+    let definition = &schema_value()["$defs"][name];
+    let Value::Object(entries) = value else {
+        problems.push(format!("{}: not an object", path));
+        return;
+    };
+    let required = definition["required"].as_array().into_iter().flatten();
+    for field in required.filter_map(Value::as_str) {
+        if !entries.contains_key(field) {
+            problems.push(format!("{}.{}: missing", path, field));
+        }
+    }
+    for (key, value) in entries {
+        let path = format!("{}.{}", path, key);
+        if let Some(field) = definition["properties"].get(key) {
+            check_value(field, value, &path, problems);
+            continue;
+        }
+        let deprecated = FIELD_DEPRECATIONS
+            .iter()
+            .find(|d| d.definition == name && d.old == key);
+        match deprecated {
+            Some(d) => problems.push(format!("{}: removed after version {}", path, d.since)),
+            None => problems.push(format!("{}: unknown field", path)),
+        }
+    }
+}
+
+fn check_value(schema: &Value, value: &Value, path: &str, problems: &mut Vec<String>) {
+    // `true` or `{}`, any JSON (an extension's snapshot)
+    let Value::Object(schema) = schema else {
+        return;
+    };
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        let name = reference.trim_start_matches("#/$defs/");
+        check_object(name, value, path, problems);
+        return;
+    }
+    if let Some(Value::Array(variants)) = schema.get("anyOf") {
+        // An Option of an object: null, or the object
+        if value.is_null() && variants.iter().any(|variant| variant["type"] == "null") {
+            return;
+        }
+        if let Some(variant) = variants.iter().find(|variant| variant["type"] != "null") {
+            check_value(variant, value, path, problems);
+        }
+        return;
+    }
+    let types: Vec<&str> = match schema.get("type") {
+        Some(Value::String(ty)) => vec![ty],
+        Some(Value::Array(types)) => types.iter().filter_map(Value::as_str).collect(),
+        _ => return,
+    };
+    if value.is_null() && types.contains(&"null") {
+        return;
+    }
+    let Some(&ty) = types.iter().find(|&&ty| ty != "null") else {
+        return;
+    };
+    let fits = match (ty, value) {
+        ("integer", value) => value.is_u64() || value.is_i64(),
+        ("number", value) => value.is_number(),
+        ("boolean", value) => value.is_boolean(),
+        ("string", value) => value.is_string(),
+        ("array", Value::Array(values)) => {
+            let items = schema.get("items").unwrap_or(&Value::Bool(true));
+            for (i, value) in values.iter().enumerate() {
+                check_value(items, value, &format!("{}[{}]", path, i), problems);
+            }
+            true
+        }
+        ("object", Value::Object(entries)) => {
+            let values = schema
+                .get("additionalProperties")
+                .unwrap_or(&Value::Bool(true));
+            for (key, value) in entries {
+                check_value(values, value, &format!("{}.{}", path, key), problems);
+            }
+            true
+        }
+        _ => false,
+    };
+    if !fits {
+        problems.push(format!("{}: not {}", path, type_name(ty)));
+    }
+}
+
+fn type_name(ty: &str) -> &'static str {
+    match ty {
+        "integer" => "an integer",
+        "number" => "a number",
+        "boolean" => "a boolean",
+        "string" => "a string",
+        "array" => "an array",
+        _ => "an object",
+    }
+}
+
+/// One interval read, CongestionSignals::to_json; fields as on CongestionSignals, wall-clock bounds in ms since the epoch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "signals", deny_unknown_fields)]
+pub struct Signals {
+    #[serde(default)]
+    pub schema_version: u32,
+    pub interval_ns: u64,
+    pub aligned_start_ms: Option<u64>,
+    pub aligned_end_ms: Option<u64>,
+    pub observed: Observed,
+    pub estimated: Estimated,
+    pub send_bytes: u64,
+    pub loopback_send_bytes: Option<u64>,
+    pub external_send_bytes: u64,
+    pub udp_send_sizes: Option<SendSizes>,
+    pub tcp_send_sizes: Option<SendSizes>,
+    pub est_send_msgs: ProtocolCounts,
+    pub est_wire_packets: ProtocolCounts,
+    pub udp_gso: Option<Gso>,
+    pub drops: u64,
+    pub avg_wmem_pressure: Option<f64>,
+    pub udp_wmem_pressure: Option<f64>,
+    pub tcp_wmem_pressure: Option<f64>,
+    pub net_memory_pressure: Option<f64>,
+    pub memory_pressure_events: Option<u64>,
+    pub avg_socket_pacing_rate: Option<f64>,
+    pub kernel_paced_send_share: Option<f64>,
+    pub kernel_paced: bool,
+    pub softirq_ns: u64,
+    pub event_count: u64,
+    pub active_sockets: u64,
+    pub active_flows: Option<u64>,
+    pub new_connections_per_interval: u64,
+    pub closed_connections_per_interval: u64,
+    pub connections: Connections,
+    pub send_concentration: Option<Concentration>,
+    pub sampler_comparison: Option<Sampler>,
+    pub queue_depth_packets: u64,
+    pub queue_depth_bytes: u64,
+    pub udp_rcv_drops: u64,
+    pub avg_rmem_pressure: Option<f64>,
+    pub softirq_cpu_fraction: Option<f64>,
+    pub tcp_avg_cwnd: Option<f64>,
+    pub tcp_avg_ssthresh: Option<f64>,
+    pub tcp_avg_pacing_rate: Option<f64>,
+    pub tcp_sockets_below_ssthresh: Option<u64>,
+    pub ce_marks: Option<u64>,
+    pub ce_triggered_cwr: Option<u64>,
+    pub rto_events: Option<u64>,
+    pub loss_recovery_episodes: Option<u64>,
+    pub rx_time_squeeze: Option<u64>,
+    pub implausible_socket_samples: u64,
+    pub burst_drop_correlation: Option<f64>,
+    pub drop_interarrival: Option<Interarrival>,
+    pub softirq_coupling: Option<Coupling>,
+    pub softirq_attribution: Option<Attribution>,
+    pub tsq_throttles: Option<u64>,
+    pub softirq_discarded: u64,
+    pub txq_stalls: Option<u64>,
+    pub txq_stalled_ns: Option<u64>,
+    pub egress_estimate_error: Option<f64>,
+    pub limitation: String,
+    pub missing_signals: Vec<String>,
+    pub degradation_level: u64,
+    pub egress: Vec<Egress>,
+    pub txq_by_interface: Vec<Txq>,
+    pub sessions: Vec<String>,
+    pub captures: Vec<Capture>,
+    #[serde(default)]
+    pub extensions: BTreeMap<String, Value>,
+}
+
+/// One governor decision, DecisionRecord::to_json: a decision log line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "decision", deny_unknown_fields)]
+pub struct Decision {
+    #[serde(default)]
+    pub schema_version: u32,
+    pub at_ms: u64,
+    pub policy: String,
+    pub action: String,
+    pub previous_rate: u64,
+    pub rate: u64,
+    pub score: Option<f64>,
+    pub stale_input_ms: Option<u64>,
+    pub softirq_cause: Option<String>,
+    pub fast: bool,
+    pub host_memory_pressure: bool,
+    pub bufferbloat: Option<Bufferbloat>,
+    pub burst: Option<Burst>,
+    #[serde(default)]
+    pub effectiveness: Effectiveness,
+    pub receiver_overwhelmed: Option<ReceivePressure>,
+    pub estimated_headroom_bps: Option<u64>,
+    pub headroom_confidence: Option<f64>,
+    pub ceiling_bps: Option<u64>,
+    pub link_utilization: Option<f64>,
+    pub components: Vec<Component>,
+    pub signals: DecisionSignals,
+    pub sessions: Vec<String>,
+    pub captures: Vec<Capture>,
+    pub explain: String,
+}
+
+/// PreflightReport::to_json, what signals-preflight prints
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "preflight", deny_unknown_fields)]
+pub struct Preflight {
+    #[serde(default)]
+    pub schema_version: u32,
+    pub can_run: bool,
+    pub degraded: bool,
+    pub kernel_release: String,
+    pub btf: bool,
+    pub tracefs: bool,
+    pub capabilities: Option<Vec<String>>,
+    pub memlock_limit_bytes: Option<u64>,
+    pub load_error: Option<String>,
+    pub map_memory_bytes: Option<u64>,
+    pub probes: Vec<Probe>,
+    pub remediations: Vec<Remediation>,
+    /// `memlock_limit_bytes` under its name before version 1, written next to
+    /// it through version 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(extend("deprecated" = true))]
+    pub memlock_limit: Option<u64>,
+}
+
+/// SessionReport::to_json
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "session", deny_unknown_fields)]
+pub struct Session {
+    #[serde(default)]
+    pub schema_version: u32,
+    pub label: String,
+    pub started_at_ms: u64,
+    pub ended_at_ms: u64,
+    pub intervals: u64,
+    pub transitions: Vec<Transition>,
+    pub signals: Signals,
+}
+
+/// manifest.json of a diagnostic bundle, which versions the bundle's other files with it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "manifest", deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default)]
+    pub schema_version: u32,
+    pub bundle_format: u64,
+    #[serde(default)]
+    pub created_at_ms: u64,
+    pub collector_version: String,
+    #[serde(default)]
+    pub event_schema_version: u64,
+    pub state: String,
+    pub event_stream: bool,
+    pub redaction: Redaction,
+    pub files: Vec<File>,
+    /// `created_at_ms` under its name before version 1, written next to it
+    /// through version 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(extend("deprecated" = true))]
+    pub created_ms: Option<u64>,
+}
+
+/// ObservedCounts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "observed", deny_unknown_fields)]
+pub struct Observed {
+    pub events_received: u64,
+    pub drops: u64,
+    pub udp_rcv_drops: u64,
+    pub softirq_exits: u64,
+}
+
+/// EstimatedTotals
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "estimated", deny_unknown_fields)]
+pub struct Estimated {
+    pub send_bytes: u64,
+    pub external_send_bytes: u64,
+    pub send_packets: u64,
+    pub scale: Scale,
+}
+
+/// SampleScale
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "scale", deny_unknown_fields)]
+pub struct Scale {
+    pub sends_seen: Option<u64>,
+    pub sends_sampled: u64,
+    pub send_factor: Option<f64>,
+    pub socket_fraction: Option<f64>,
+}
+
+/// SendSizes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "send_sizes", deny_unknown_fields)]
+pub struct SendSizes {
+    pub samples: u64,
+    pub min_bytes: u64,
+    pub avg_bytes: Option<f64>,
+    pub max_bytes: u64,
+    pub histogram: Vec<u64>,
+}
+
+/// ProtocolCounts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "protocol_counts", deny_unknown_fields)]
+pub struct ProtocolCounts {
+    pub udp: u64,
+    pub tcp: u64,
+}
+
+/// GsoSegments
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "gso", deny_unknown_fields)]
+pub struct Gso {
+    pub skbs: u64,
+    pub gso_skbs: u64,
+    pub segments: u64,
+}
+
+/// ConnectionChurn
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "connections", deny_unknown_fields)]
+pub struct Connections {
+    pub tcp_active_established: Option<u64>,
+    pub tcp_passive_established: Option<u64>,
+    pub tcp_accepts: Option<u64>,
+    pub tcp_closed: Option<u64>,
+    pub udp_first_seen: u64,
+}
+
+/// SendConcentration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "concentration", deny_unknown_fields)]
+pub struct Concentration {
+    pub sockets: u64,
+    pub top1_share: Option<f64>,
+    pub top5_share: Option<f64>,
+    pub gini: Option<f64>,
+    pub hhi: Option<f64>,
+    pub top_sockets: Vec<TopSocket>,
+}
+
+/// SendConcentration::top_sockets; socket_id is null when redacted out
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "top_socket", deny_unknown_fields)]
+pub struct TopSocket {
+    pub socket_id: Option<u64>,
+    pub send_bytes: u64,
+    pub share: Option<f64>,
+}
+
+/// SamplerEstimates
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "sampler", deny_unknown_fields)]
+pub struct Sampler {
+    pub candidate_ratio: u64,
+    pub primary_send_bytes: u64,
+    pub candidate_send_bytes: u64,
+    pub candidate_samples: u64,
+    pub relative_error: Option<f64>,
+}
+
+/// SoftirqAttribution
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "attribution", deny_unknown_fields)]
+pub struct Attribution {
+    pub self_induced_softirq_ns: u64,
+    pub external_softirq_ns: u64,
+    pub rounds: u64,
+    pub covered_rounds: u64,
+    pub confidence: Option<f64>,
+}
+
+/// DropInterarrival
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "interarrival", deny_unknown_fields)]
+pub struct Interarrival {
+    pub gaps: u64,
+    pub histogram: Vec<u64>,
+    pub mean_gap_ns: Option<f64>,
+    pub burstiness: Option<f64>,
+}
+
+/// SoftirqCoupling
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "coupling", deny_unknown_fields)]
+pub struct Coupling {
+    pub sends_checked: u64,
+    pub sends_delayed_by_softirq: u64,
+    pub sends_in_softirq: u64,
+    pub avg_softirq_delay_ns: u64,
+}
+
+/// InterfaceEgress
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "egress", deny_unknown_fields)]
+pub struct Egress {
+    pub interface: String,
+    pub egress_bytes_exact: u64,
+    pub egress_packets_exact: u64,
+}
+
+/// InterfaceTxq
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "txq", deny_unknown_fields)]
+pub struct Txq {
+    pub interface: String,
+    pub ifindex: u64,
+    pub netns: u64,
+    pub txq_stalls: u64,
+    pub txq_stalled_ns: u64,
+    pub xmit_busy: u64,
+}
+
+/// CaptureNotice; `capture` names the variant, which has the fields it's documented with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "capture", deny_unknown_fields)]
+pub struct Capture {
+    pub capture: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trigger: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trigger_ns: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until_ns: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_in_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub triggered_at_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_event_ns: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_event_ns: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub events: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub events_before_trigger: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drop_interarrival: Option<Interarrival>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// ScoreComponent
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "component", deny_unknown_fields)]
+pub struct Component {
+    pub name: String,
+    pub value: Option<f64>,
+    pub normalized: Option<f64>,
+    pub weight: Option<f64>,
+}
+
+/// Bufferbloat
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "bufferbloat", deny_unknown_fields)]
+pub struct Bufferbloat {
+    pub backlog_bytes: u64,
+    pub growth: Option<f64>,
+    pub queue_delay_ms: Option<u64>,
+    pub utilization: Option<f64>,
+}
+
+/// ReceivePressure
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "receive_pressure", deny_unknown_fields)]
+pub struct ReceivePressure {
+    pub rcv_drops_per_sec: Option<f64>,
+    pub rmem_pressure: Option<f64>,
+}
+
+/// BurstEpisode; period_ms only for periodic ones
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "burst", deny_unknown_fields)]
+pub struct Burst {
+    pub class: String,
+    pub pressured_fraction: Option<f64>,
+    pub bursts: u64,
+    pub longest_ms: u64,
+    pub period_ms: Option<u64>,
+}
+
+/// Effectiveness of the governor's cuts as of a decision
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "effectiveness", deny_unknown_fields)]
+pub struct Effectiveness {
+    pub judged: u64,
+    pub improved: u64,
+    pub improved_fraction: Option<f64>,
+    pub median_time_to_improvement_ms: Option<u64>,
+    pub pending: u64,
+    pub non_responsive: bool,
+}
+
+/// The scalar fields of the interval a decision was made on; the average send sizes are null without samples
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "decision_signals", deny_unknown_fields)]
+pub struct DecisionSignals {
+    pub interval_ns: u64,
+    pub send_bytes: u64,
+    pub drops: u64,
+    pub avg_wmem_pressure: Option<f64>,
+    pub udp_avg_send_bytes: Option<f64>,
+    pub tcp_avg_send_bytes: Option<f64>,
+    pub softirq_cpu_fraction: Option<f64>,
+    pub udp_rcv_drops: u64,
+    pub avg_rmem_pressure: Option<f64>,
+    pub rto_events: Option<u64>,
+    pub loss_recovery_episodes: Option<u64>,
+    pub limitation: String,
+    pub kernel_paced: bool,
+    pub new_connections_per_interval: u64,
+    pub closed_connections_per_interval: u64,
+    pub net_memory_pressure: Option<f64>,
+    pub memory_pressure_events: Option<u64>,
+}
+
+/// ProbePreflight
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "probe", deny_unknown_fields)]
+pub struct Probe {
+    pub program: String,
+    pub kind: String,
+    pub target: String,
+    pub required: bool,
+    pub available: bool,
+    pub verified: Option<bool>,
+    pub feasible: bool,
+    pub error: Option<String>,
+}
+
+/// Remediation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "remediation", deny_unknown_fields)]
+pub struct Remediation {
+    pub blocking: bool,
+    pub action: String,
+}
+
+/// StateTransition; from and to are CongestionState levels, 0-2
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "transition", deny_unknown_fields)]
+pub struct Transition {
+    pub at_ms: u64,
+    pub from: u64,
+    pub to: u64,
+    pub reasons: Vec<String>,
+}
+
+/// The Redaction a bundle was written with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "redaction", deny_unknown_fields)]
+pub struct Redaction {
+    pub socket_ids: String,
+    pub addresses: String,
+}
+
+/// One file of a diagnostic bundle
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "file", deny_unknown_fields)]
+pub struct File {
+    pub name: String,
+    pub description: String,
+}
+
+/// SoakReport::to_json, the checkpoint and report of validate --soak
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "soak", deny_unknown_fields)]
+pub struct Soak {
+    #[serde(default)]
+    pub schema_version: u32,
+    pub elapsed_ms: u64,
+    pub passed: bool,
+    pub rss_growth_per_hour: Option<f64>,
+    pub memory_growth_per_hour: Option<f64>,
+    pub fd_growth: Option<u64>,
+    pub accuracy_drift: Option<f64>,
+    pub table_growth: Vec<SoakTable>,
+    pub counter_regressions: Vec<SoakRegression>,
+    pub failures: Vec<String>,
+    pub samples: Vec<SoakSample>,
+}
+
+/// A table's growth over a soak, entries per hour
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "soak_table", deny_unknown_fields)]
+pub struct SoakTable {
+    pub name: String,
+    pub growth_per_hour: Option<f64>,
+}
+
+/// CounterRegression
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "soak_regression", deny_unknown_fields)]
+pub struct SoakRegression {
+    pub counter: String,
+    pub at_ms: u64,
+    pub from: u64,
+    pub to: u64,
+}
+
+/// SoakSample; tables and counters as name/value pairs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "soak_sample", deny_unknown_fields)]
+pub struct SoakSample {
+    pub elapsed_ms: u64,
+    pub rss_bytes: u64,
+    pub open_fds: u64,
+    pub memory_bytes: u64,
+    pub tables: Vec<SoakCount>,
+    pub counters: Vec<SoakCount>,
+    pub accuracy_error: Option<f64>,
+}
+
+/// One named table size or counter of a SoakSample
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename = "soak_count", deny_unknown_fields)]
+pub struct SoakCount {
+    pub name: String,
+    pub value: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{documents, V0_DOCUMENTS};
+    use crate::EXPORT_SCHEMA_VERSION;
+
+    fn read(document: &str, json: &str) -> Result<ExportDocument, serde_json::Error> {
+        fn boxed<T: serde::de::DeserializeOwned>(json: &str) -> serde_json::Result<Box<T>> {
+            serde_json::from_str(json)
+        }
+        Ok(match document {
+            "signals" => ExportDocument::Signals(boxed(json)?),
+            "decision" => ExportDocument::Decision(boxed(json)?),
+            "preflight" => ExportDocument::Preflight(boxed(json)?),
+            "session" => ExportDocument::Session(boxed(json)?),
+            "manifest" => ExportDocument::Manifest(boxed(json)?),
+            "soak" => ExportDocument::Soak(boxed(json)?),
+            other => panic!("no document {:?}", other),
+        })
+    }
+
+    #[test]
+    fn the_checked_in_schema_is_the_models() {
+        assert!(
+            export_json_schema() == include_str!("../schema/export.schema.json"),
+            "schema/export.schema.json is stale, run cargo xtask schema"
+        );
+    }
+
+    #[test]
+    fn every_emitters_document_reads_into_the_model() {
+        for (document, json) in documents() {
+            let parsed = read(document, &json).unwrap_or_else(|e| panic!("{}: {}", document, e));
+            match &parsed {
+                ExportDocument::Signals(signals) => {
+                    assert_eq!(signals.schema_version, EXPORT_SCHEMA_VERSION);
+                    assert_eq!(signals.drops, 12);
+                }
+                ExportDocument::Preflight(preflight) => {
+                    assert_eq!(preflight.memlock_limit_bytes, Some(64 << 10));
+                    assert_eq!(preflight.memlock_limit, preflight.memlock_limit_bytes);
+                }
+                _ => {}
+            }
+            // And the untagged enum tells it apart
+            let untagged: ExportDocument = serde_json::from_str(&json).unwrap();
+            assert_eq!(untagged, parsed);
+        }
+    }
+
+    #[test]
+    fn the_previous_versions_fixtures_read_into_the_model() {
+        for (document, json) in V0_DOCUMENTS {
+            read(document, json).unwrap_or_else(|e| panic!("{}: {}", document, e));
+        }
+        let Ok(ExportDocument::Manifest(manifest)) = read("manifest", V0_DOCUMENTS[4].1) else {
+            unreachable!()
+        };
+        assert_eq!(manifest.created_ms, Some(1_760_000_000_000));
+        assert_eq!(manifest.created_at_ms, 0);
+    }
+
+    #[test]
+    fn a_rename_without_an_alias_fails_to_read() {
+        let renamed = V0_DOCUMENTS[0].1.replace("\"drops\":", "\"drop_count\":");
+        let error = read("signals", &renamed).unwrap_err().to_string();
+        assert!(error.contains("unknown field `drop_count`"), "{}", error);
+    }
+
+    #[test]
+    fn every_emitters_document_checks_clean() {
+        for (document, json) in documents() {
+            assert_eq!(check_export(document, &json), Ok(()), "{}", json);
+        }
+    }
+
+    #[test]
+    fn the_previous_versions_fixtures_still_check() {
+        for (document, json) in V0_DOCUMENTS {
+            assert_eq!(check_export(document, json), Ok(()), "{}", document);
+        }
+    }
+
+    #[test]
+    fn a_rename_without_an_alias_is_caught() {
+        let renamed = V0_DOCUMENTS[0].1.replace("\"drops\":", "\"drop_count\":");
+        let problems = check_export("signals", &renamed).unwrap_err();
+        assert!(
+            problems.contains(&"signals.drop_count: unknown field".to_string()),
+            "{:?}",
+            problems
+        );
+        assert!(problems.contains(&"signals.drops: missing".to_string()));
+    }
+
+    #[test]
+    fn wrong_types_are_reported_by_path() {
+        let json = V0_DOCUMENTS[0]
+            .1
+            .replace("\"drops\":", "\"drops\":\"many\",\"x\":");
+        let problems = check_export("signals", &json).unwrap_err();
+        assert!(problems.contains(&"signals.drops: not an integer".to_string()));
+        assert!(problems.contains(&"signals.x: unknown field".to_string()));
+        let nested = V0_DOCUMENTS[0]
+            .1
+            .replace("\"observed\":{", "\"observed\":{\"y\":1,");
+        let problems = check_export("signals", &nested).unwrap_err();
+        assert_eq!(
+            problems,
+            vec!["signals.observed.y: unknown field".to_string()]
+        );
+        assert_eq!(
+            check_export("nothing", "{}"),
+            Err(vec!["no definition \"nothing\"".to_string()])
+        );
+    }
+}
@@ -0,0 +1,476 @@
+//Event types from outside the crate. An eBPF program of the caller's own, on a
+//driver's tracepoints say, outputs records in the CongestionEvent layout into
+//the collector's EVENTS perf array (`CongestionCollector::pin_event_map`), with
+//an event type in EXTENSION_EVENT_TYPES and up to EXTENSION_PAYLOAD_SIZE bytes
+//of its own in `data`. From there they take the crate's own events' way: the
+//readers, the processing queue, subscribers and recordings.
+//
+//A `CustomEventHandler`, registered for its types before collection starts,
+//decodes each on the processing side and folds it into an accumulator of its
+//own. Each interval read takes the accumulator's snapshot into
+//`CongestionSignals::extensions` under the handler's name, then resets it, so
+//the JSON, statsd, parquet and gRPC exports carry it too.
+//
+//The readers count extension events in `event_count` and
+//`ReaderStats::extension_events`. A type in the range nobody registered for is
+//unknown (`ReaderStats::unknown_events`), like a newer object's. None of the
+//crate's own signals look at them.
+//
+//A session (`CongestionCollector::begin_session`) knows nothing of what a
+//snapshot means: it sums integers key by key, objects within objects, and
+//takes the latest interval's value of anything else.
+
+use crate::{CollectorError, CongestionEvent, EventData, JsonValue};
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::ops::Range;
+#[cfg(feature = "collector-core")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Event types reserved for extensions, the crate's own stay below
+pub const EXTENSION_EVENT_TYPES: Range<u32> = 0x100..0x200;
+
+/// Bytes of `CongestionEvent::data` an extension's record has for itself
+pub const EXTENSION_PAYLOAD_SIZE: usize = size_of::<EventData>();
+
+/// An extension's event as a handler gets it
+#[derive(Debug, Clone, Copy)]
+pub struct ExtensionEvent {
+    pub timestamp_ns: u64,
+    pub event_type: u32,
+    pub cpu_id: u32,
+    /// `CongestionEvent::data` as it came, native endian
+    pub payload: [u8; EXTENSION_PAYLOAD_SIZE],
+}
+
+impl ExtensionEvent {
+    /// An event decoded from a perf sample or a recording, whose bytes are
+    /// all initialized
+    pub(crate) fn new(event: &CongestionEvent) -> Self {
+        Self {
+            timestamp_ns: event.timestamp_ns,
+            event_type: event.event_type,
+            cpu_id: event.cpu_id,
+            // SAFETY: `raw` spans the union and decoded events came from
+            // initialized bytes, which copies of them keep
+            payload: unsafe { event.data.raw },
+        }
+    }
+
+    /// An event with `payload` at the start of its data, zeroed after. Payload
+    /// past EXTENSION_PAYLOAD_SIZE is cut off
+    pub fn from_payload(event_type: u32, timestamp_ns: u64, cpu_id: u32, payload: &[u8]) -> Self {
+        let mut padded = [0u8; EXTENSION_PAYLOAD_SIZE];
+        let len = payload.len().min(EXTENSION_PAYLOAD_SIZE);
+        padded[..len].copy_from_slice(&payload[..len]);
+        Self {
+            timestamp_ns,
+            event_type,
+            cpu_id,
+            payload: padded,
+        }
+    }
+
+    /// The u32 at byte `offset` of the payload, None past its end
+    pub fn u32_at(&self, offset: usize) -> Option<u32> {
+        let bytes = self.payload.get(offset..offset.checked_add(4)?)?;
+        Some(u32::from_ne_bytes(bytes.try_into().ok()?))
+    }
+
+    pub fn u64_at(&self, offset: usize) -> Option<u64> {
+        let bytes = self.payload.get(offset..offset.checked_add(8)?)?;
+        Some(u64::from_ne_bytes(bytes.try_into().ok()?))
+    }
+}
+
+/// A record of `event_type` with `payload` at the start of `data`, as an
+/// extension's program would output it, e.g. to replay or test a handler.
+/// Payload past EXTENSION_PAYLOAD_SIZE is cut off
+pub fn extension_event(
+    event_type: u32,
+    timestamp_ns: u64,
+    cpu_id: u32,
+    payload: &[u8],
+) -> CongestionEvent {
+    let event = ExtensionEvent::from_payload(event_type, timestamp_ns, cpu_id, payload);
+    CongestionEvent {
+        timestamp_ns,
+        event_type,
+        cpu_id,
+        data: EventData { raw: event.payload },
+    }
+}
+
+/// Decodes an extension's events and aggregates them over an interval, see
+/// the module docs. Runs on the processing side, one event at a time
+pub trait CustomEventHandler: Send + 'static {
+    /// One event, decoded
+    type Event;
+    /// What an interval's events fold into
+    type Accumulator: Default + Send + 'static;
+
+    /// The event, or None for a record it can't make sense of, which is
+    /// skipped
+    fn decode(&self, event: &ExtensionEvent) -> Option<Self::Event>;
+
+    fn fold(&self, accumulator: &mut Self::Accumulator, event: Self::Event);
+
+    /// The interval's part of `CongestionSignals::extensions`
+    fn snapshot(&self, accumulator: &Self::Accumulator) -> JsonValue;
+
+    /// After each snapshot. Back to the default unless overridden, e.g. to
+    /// keep a gauge's last value across intervals
+    fn reset(&self, accumulator: &mut Self::Accumulator) {
+        *accumulator = Self::Accumulator::default();
+    }
+}
+
+// A handler with its accumulator, behind one object-safe face
+trait Dispatch: Send {
+    fn record(&mut self, event: &ExtensionEvent);
+    fn take_interval(&mut self) -> JsonValue;
+}
+
+struct Registered<H: CustomEventHandler> {
+    handler: H,
+    accumulator: H::Accumulator,
+}
+
+impl<H: CustomEventHandler> Dispatch for Registered<H> {
+    fn record(&mut self, event: &ExtensionEvent) {
+        if let Some(decoded) = self.handler.decode(event) {
+            self.handler.fold(&mut self.accumulator, decoded);
+        }
+    }
+
+    fn take_interval(&mut self) -> JsonValue {
+        let snapshot = self.handler.snapshot(&self.accumulator);
+        self.handler.reset(&mut self.accumulator);
+        snapshot
+    }
+}
+
+/// Registered handlers by event type. The collector keeps one
+/// (`CongestionCollector::register_extension`); on its own it runs handlers
+/// over a recording's events
+#[derive(Default)]
+pub struct ExtensionRegistry {
+    handlers: Vec<(String, Box<dyn Dispatch>)>,
+    // Event type -> index into handlers
+    by_type: HashMap<u32, usize>,
+}
+
+impl ExtensionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hand `event_types` to `handler`, its snapshots going under `name`. The
+    /// types must be in EXTENSION_EVENT_TYPES and not taken, the name unique
+    pub fn register<H: CustomEventHandler>(
+        &mut self,
+        name: &str,
+        event_types: Range<u32>,
+        handler: H,
+    ) -> Result<(), CollectorError> {
+        let rejected = |reason: String| CollectorError::ExtensionRejected {
+            name: name.to_string(),
+            reason,
+        };
+        if name.is_empty() {
+            return Err(rejected("empty name".to_string()));
+        }
+        if self
+            .handlers
+            .iter()
+            .any(|(registered, _)| registered == name)
+        {
+            return Err(rejected("name already registered".to_string()));
+        }
+        if event_types.is_empty()
+            || event_types.start < EXTENSION_EVENT_TYPES.start
+            || event_types.end > EXTENSION_EVENT_TYPES.end
+        {
+            return Err(rejected(format!(
+                "event types {:#x}..{:#x} not within {:#x}..{:#x}",
+                event_types.start,
+                event_types.end,
+                EXTENSION_EVENT_TYPES.start,
+                EXTENSION_EVENT_TYPES.end
+            )));
+        }
+        if let Some(taken) = event_types.clone().find(|t| self.by_type.contains_key(t)) {
+            return Err(rejected(format!(
+                "event type {:#x} already registered",
+                taken
+            )));
+        }
+        let index = self.handlers.len();
+        self.handlers.push((
+            name.to_string(),
+            Box::new(Registered {
+                handler,
+                accumulator: H::Accumulator::default(),
+            }),
+        ));
+        self.by_type.extend(event_types.map(|t| (t, index)));
+        Ok(())
+    }
+
+    /// Whether a handler takes this event type
+    pub fn handles(&self, event_type: u32) -> bool {
+        self.by_type.contains_key(&event_type)
+    }
+
+    /// Hand the event to its handler. False when none takes its type
+    pub fn record(&mut self, event: &ExtensionEvent) -> bool {
+        let Some(&index) = self.by_type.get(&event.event_type) else {
+            return false;
+        };
+        self.handlers[index].1.record(event);
+        true
+    }
+
+    /// `record` for an event off the perf buffers
+    #[cfg(feature = "collector-core")]
+    pub(crate) fn record_event(&mut self, event: &CongestionEvent) -> bool {
+        self.record(&ExtensionEvent::new(event))
+    }
+
+    /// Every handler's snapshot since the last call, by name, each reset after
+    pub fn take_interval(&mut self) -> HashMap<String, JsonValue> {
+        self.handlers
+            .iter_mut()
+            .map(|(name, handler)| (name.clone(), handler.take_interval()))
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+}
+
+/// Fold `next`, a later interval's snapshot, into `total` for a session: see
+/// the module docs
+#[cfg(feature = "collector-core")]
+pub(crate) fn merge_snapshot(total: &mut JsonValue, next: &JsonValue) {
+    match (&mut *total, next) {
+        (
+            JsonValue::Number {
+                value,
+                integer: true,
+            },
+            JsonValue::Number {
+                value: more,
+                integer: true,
+            },
+        ) => *value += more,
+        (JsonValue::Object(fields), JsonValue::Object(next_fields)) => {
+            for (key, next_value) in next_fields {
+                match fields.iter_mut().find(|(k, _)| k == key) {
+                    Some((_, value)) => merge_snapshot(value, next_value),
+                    None => fields.push((key.clone(), next_value.clone())),
+                }
+            }
+        }
+        _ => *total = next.clone(),
+    }
+}
+
+/// Registered extension types as bits, for the readers to check without the
+/// registry's lock
+#[cfg(feature = "collector-core")]
+#[derive(Default)]
+pub(crate) struct ExtensionTypes {
+    bits: [AtomicU64; (EXTENSION_EVENT_TYPES.end - EXTENSION_EVENT_TYPES.start) as usize / 64],
+}
+
+#[cfg(feature = "collector-core")]
+impl ExtensionTypes {
+    pub(crate) fn insert(&self, event_types: Range<u32>) {
+        for event_type in event_types {
+            let bit = (event_type - EXTENSION_EVENT_TYPES.start) as usize;
+            self.bits[bit / 64].fetch_or(1 << (bit % 64), Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn contains(&self, event_type: u32) -> bool {
+        if !EXTENSION_EVENT_TYPES.contains(&event_type) {
+            return false;
+        }
+        let bit = (event_type - EXTENSION_EVENT_TYPES.start) as usize;
+        self.bits[bit / 64].load(Ordering::Relaxed) & (1 << (bit % 64)) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EVENT_UDP_SEND;
+
+    const BASE: u32 = EXTENSION_EVENT_TYPES.start;
+
+    // Sums the u64 at the start of the payload, keeping the last one as a gauge
+    // across intervals if `keep_last`
+    struct Summing {
+        keep_last: bool,
+    }
+
+    impl CustomEventHandler for Summing {
+        type Event = u64;
+        type Accumulator = (u64, u64);
+
+        fn decode(&self, event: &ExtensionEvent) -> Option<u64> {
+            event.u64_at(0).filter(|&value| value != u64::MAX)
+        }
+
+        fn fold(&self, (sum, last): &mut (u64, u64), value: u64) {
+            *sum += value;
+            *last = value;
+        }
+
+        fn snapshot(&self, &(sum, last): &(u64, u64)) -> JsonValue {
+            JsonValue::Object(vec![
+                ("sum".to_string(), sum.into()),
+                ("last".to_string(), last.into()),
+            ])
+        }
+
+        fn reset(&self, accumulator: &mut (u64, u64)) {
+            *accumulator = (0, if self.keep_last { accumulator.1 } else { 0 });
+        }
+    }
+
+    fn summing() -> Summing {
+        Summing { keep_last: false }
+    }
+
+    fn event(event_type: u32, value: u64) -> ExtensionEvent {
+        ExtensionEvent::from_payload(event_type, 1_000, 0, &value.to_ne_bytes())
+    }
+
+    fn field(snapshots: &HashMap<String, JsonValue>, name: &str, key: &str) -> Option<f64> {
+        snapshots
+            .get(name)
+            .and_then(|snapshot| snapshot.get(key))
+            .and_then(JsonValue::as_f64)
+    }
+
+    #[test]
+    fn bad_registrations_are_rejected() {
+        let mut registry = ExtensionRegistry::new();
+        registry.register("a", BASE..BASE + 2, summing()).unwrap();
+        let rejected = [
+            registry.register("below", BASE - 1..BASE + 5, summing()),
+            registry.register(
+                "past",
+                EXTENSION_EVENT_TYPES.end - 1..EXTENSION_EVENT_TYPES.end + 1,
+                summing(),
+            ),
+            registry.register("empty range", BASE + 9..BASE + 9, summing()),
+            registry.register("taken", BASE + 1..BASE + 4, summing()),
+            registry.register("a", BASE + 10..BASE + 11, summing()),
+            registry.register("", BASE + 10..BASE + 11, summing()),
+        ];
+        for result in &rejected {
+            assert!(matches!(
+                result,
+                Err(CollectorError::ExtensionRejected { .. })
+            ));
+        }
+        let taken = rejected[3].as_ref().unwrap_err().to_string();
+        assert!(taken.contains(&format!("{:#x} already registered", BASE + 1)));
+        // None of them took a type
+        assert!(!registry.handles(BASE + 2));
+        assert!(!registry.handles(BASE + 10));
+    }
+
+    #[test]
+    fn events_go_to_their_types_handler_only() {
+        let mut registry = ExtensionRegistry::new();
+        registry.register("a", BASE..BASE + 2, summing()).unwrap();
+        let gauge = Summing { keep_last: true };
+        registry.register("b", BASE + 2..BASE + 3, gauge).unwrap();
+        assert!(registry.handles(BASE + 2));
+        assert!(!registry.handles(BASE + 3));
+
+        let recorded = [
+            registry.record(&event(BASE, 3)),
+            registry.record(&event(BASE + 1, 4)),
+            // Decoded to None: skipped, but still this handler's type
+            registry.record(&event(BASE + 1, u64::MAX)),
+            registry.record(&event(BASE + 2, 7)),
+            registry.record(&event(BASE + 2, 5)),
+            registry.record(&event(BASE + 3, 100)),
+            registry.record(&event(EVENT_UDP_SEND, 100)),
+        ];
+        assert_eq!(recorded, [true, true, true, true, true, false, false]);
+        let first = registry.take_interval();
+        assert_eq!(field(&first, "a", "sum"), Some(7.0));
+        assert_eq!(field(&first, "b", "sum"), Some(12.0));
+        assert_eq!(field(&first, "b", "last"), Some(5.0));
+    }
+
+    #[test]
+    fn each_interval_starts_over_unless_reset_keeps_something() {
+        let mut registry = ExtensionRegistry::new();
+        registry.register("a", BASE..BASE + 1, summing()).unwrap();
+        let gauge = Summing { keep_last: true };
+        registry.register("b", BASE + 1..BASE + 2, gauge).unwrap();
+        registry.record(&event(BASE, 3));
+        registry.record(&event(BASE + 1, 5));
+        registry.take_interval();
+
+        registry.record(&event(BASE, 1));
+        let second = registry.take_interval();
+        assert_eq!(field(&second, "a", "sum"), Some(1.0));
+        assert_eq!(field(&second, "a", "last"), Some(1.0));
+        assert_eq!(field(&second, "b", "sum"), Some(0.0));
+        assert_eq!(field(&second, "b", "last"), Some(5.0));
+        let third = registry.take_interval();
+        assert_eq!(field(&third, "a", "last"), Some(0.0));
+    }
+
+    #[test]
+    fn payload_past_the_data_is_cut_off() {
+        let long = ExtensionEvent::from_payload(BASE, 0, 0, &[0xff; 4096]);
+        assert_eq!(long.u64_at(EXTENSION_PAYLOAD_SIZE - 8), Some(u64::MAX));
+        assert_eq!(long.u32_at(EXTENSION_PAYLOAD_SIZE - 2), None);
+        let record = extension_event(BASE, 0, 0, &[0xff; 4096]);
+        assert_eq!(
+            ExtensionEvent::new(&record).payload,
+            [0xff; EXTENSION_PAYLOAD_SIZE]
+        );
+    }
+
+    #[cfg(not(feature = "no-float"))]
+    #[test]
+    fn snapshots_export_under_their_names() {
+        use crate::CongestionSignals;
+        let snapshot = JsonValue::Object(vec![
+            ("ring_full".to_string(), 3u64.into()),
+            (
+                "queues".to_string(),
+                JsonValue::Object(vec![(
+                    "0".to_string(),
+                    JsonValue::Object(vec![("min_free".to_string(), 2u64.into())]),
+                )]),
+            ),
+            ("driver".to_string(), "ixgbe".into()),
+        ]);
+        let with = CongestionSignals::builder()
+            .extensions([("nic_ring".to_string(), snapshot)].into_iter().collect())
+            .build()
+            .to_json();
+        let without = CongestionSignals::builder().build().to_json();
+        #[cfg(feature = "schema")]
+        for json in [&with, &without] {
+            assert_eq!(crate::check_export("signals", json), Ok(()));
+        }
+        assert!(with.contains(concat!(
+            r#""extensions":{"nic_ring":{"ring_full":3,"#,
+            r#""queues":{"0":{"min_free":2}},"driver":"ixgbe"}}"#
+        )));
+        assert!(without.contains(r#""extensions":{}"#));
+    }
+}
@@ -0,0 +1,67 @@
+//Freshness over completeness. The interval read carries everything, on a
+//cadence set by its heaviest features (socket tables, egress diffs, cgroup
+//rollups); a governor cutting on it reacts up to an interval late. Fast
+//snapshots carry the few signals a cut needs, read every 10-50 ms from
+//counters of their own, so they run next to the interval read without either
+//resetting what the other counts.
+
+use crate::{CongestionSignals, ObservedCounts};
+use std::time::{Duration, Instant};
+
+/// Fast snapshots' cadence is clamped to this range
+pub const FAST_INTERVAL_MIN: Duration = Duration::from_millis(10);
+pub const FAST_INTERVAL_MAX: Duration = Duration::from_millis(50);
+
+/// One fast snapshot, see `CongestionCollector::fast_snapshots`. Everything
+/// counts since the previous fast snapshot, whatever read the interval since
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FastSignals {
+    /// Measured between fast reads, like `CongestionSignals::interval_ns`
+    pub interval_ns: u64,
+    /// None for signals that weren't read from a collector
+    pub produced_at: Option<Instant>,
+    /// Sampled sendmsg bytes, as `CongestionSignals::send_bytes`; not scaled
+    pub send_bytes: u64,
+    pub drops: u64,
+    /// Summed over every interface; None without the netif_tx_stop_queue probe
+    pub txq_stalls: Option<u64>,
+    /// Likewise, also None without netif_tx_wake_queue
+    pub txq_stalled_ns: Option<u64>,
+    /// The fullest send buffer sampled (0.0-1.0), not an average: a single
+    /// filling socket is what a cut should catch early. None without a sample
+    pub wmem_max: Option<f64>,
+    pub udp_wmem_max: Option<f64>,
+    pub tcp_wmem_max: Option<f64>,
+}
+
+impl FastSignals {
+    /// Time since `produced_at`, None when it isn't set
+    pub fn age(&self) -> Option<Duration> {
+        self.produced_at.map(|at| at.elapsed())
+    }
+
+    /// As an interval read the governor can score, the buffer maxima standing
+    /// in for the average pressures and everything a fast snapshot doesn't
+    /// carry at its default
+    pub fn to_signals(&self) -> CongestionSignals {
+        CongestionSignals {
+            interval_ns: self.interval_ns,
+            produced_at: self.produced_at,
+            observed: ObservedCounts {
+                drops: self.drops,
+                ..Default::default()
+            },
+            send_bytes: self.send_bytes,
+            drops: self.drops,
+            #[cfg(not(feature = "no-float"))]
+            avg_wmem_pressure: self.wmem_max.unwrap_or(0.0),
+            #[cfg(not(feature = "no-float"))]
+            udp_wmem_pressure: self.udp_wmem_max,
+            #[cfg(not(feature = "no-float"))]
+            tcp_wmem_pressure: self.tcp_wmem_max,
+            txq_stalls: self.txq_stalls,
+            txq_stalled_ns: self.txq_stalled_ns,
+            ..Default::default()
+        }
+    }
+}
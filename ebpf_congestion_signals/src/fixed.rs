@@ -0,0 +1,163 @@
+//Fixed-point forms of the pressures, fractions and the score, for consumers
+//that don't do floats: an embedded pacer, a kernel-side map, a wire format
+//with integer fields. Pressures and fractions are per-mille (1000 = 1.0), times
+//nanoseconds. The collector computes them from the interval's integer sums,
+//the same totals and sample counts the f64 fields are divided from, so they
+//aren't the floats rounded: `CongestionSignals::fixed` holds what the read
+//computed, `fixed_point` falls back to the floats for signals built otherwise.
+//
+//Rounding is half up, in both directions. A float field and its per-mille
+//agree to within half a per-mille: `permille_to_f64(fixed)` is off the float
+//by at most 0.0005, and `permille_from_f64(float)` is the integer unless the
+//exact ratio sits on a half, which the float's own rounding may put on
+//either side.
+//
+//Nothing here needs std. With the `no-float` feature the f64 fields and the
+//conversions to and from them are left out, and this is all there is of the
+//signals' pressures. The governor's weights, the smoother's EWMA and the
+//exporters are f64 throughout, so that build has only the types
+//(--no-default-features).
+
+/// 1.0 in per-mille
+pub const PERMILLE: u32 = 1000;
+
+/// `numerator / denominator` in per-mille, rounded half up. None for a zero
+/// denominator, u32::MAX past it
+pub fn ratio_permille(numerator: u64, denominator: u64) -> Option<u32> {
+    (denominator > 0).then(|| {
+        let scaled = numerator as u128 * PERMILLE as u128 * 2 + denominator as u128;
+        (scaled / (denominator as u128 * 2)).min(u32::MAX as u128) as u32
+    })
+}
+
+/// Mean of `samples` values that are per-mille already, as the kernel's
+/// occupancy samples are, rounded half up. None without samples
+pub fn mean_permille(total_permille: u64, samples: u64) -> Option<u32> {
+    (samples > 0).then(|| {
+        let scaled = total_permille as u128 * 2 + samples as u128;
+        (scaled / (samples as u128 * 2)).min(u32::MAX as u128) as u32
+    })
+}
+
+/// A float fraction in per-mille, rounded half up. Negative and NaN are 0
+#[cfg(not(feature = "no-float"))]
+pub fn permille_from_f64(value: f64) -> u32 {
+    // Saturating casts: no `f64::round`, which needs std
+    (value * PERMILLE as f64 + 0.5) as u32
+}
+
+#[cfg(not(feature = "no-float"))]
+pub fn permille_to_f64(permille: u32) -> f64 {
+    permille as f64 / PERMILLE as f64
+}
+
+/// `CongestionSignals`' pressures and fractions in per-mille, times in ns.
+/// Each field is the f64 field of the same name
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FixedSignals {
+    pub interval_ns: u64,
+    pub softirq_ns: u64,
+    pub avg_wmem_pressure: u32,
+    pub udp_wmem_pressure: Option<u32>,
+    pub tcp_wmem_pressure: Option<u32>,
+    pub avg_rmem_pressure: u32,
+    /// The busiest CPU's softirq ns over `interval_ns`, capped at 1000
+    pub softirq_cpu_fraction: u32,
+}
+
+#[cfg(all(test, not(feature = "no-float")))]
+mod tests {
+    use super::*;
+    use crate::CongestionSignals;
+
+    const INTERVAL_NS: u64 = 200_000_000;
+
+    /// Within half a per-mille of `float`, and equal to it rounded unless the
+    /// exact value is on a half
+    fn assert_agrees(fixed: u32, float: f64, on_half: bool, what: &str) {
+        assert!(
+            (fixed as f64 - float * 1000.0).abs() <= 0.5 + 1e-9,
+            "{what}: {fixed} against {float}"
+        );
+        assert!((permille_to_f64(fixed) - float).abs() <= 0.0005 + 1e-12);
+        if !on_half {
+            assert_eq!(permille_from_f64(float), fixed, "{what}");
+        }
+    }
+
+    #[test]
+    fn means_agree_with_the_floats() {
+        for samples in 1..=120u64 {
+            for total in (0..=samples * 1000).step_by(13) {
+                // As the collector divides its occupancy sums
+                let float = total as f64 / samples as f64 / 1000.0;
+                let on_half = (total * 2) % samples == 0 && (total * 2 / samples) % 2 == 1;
+                let fixed = mean_permille(total, samples).unwrap();
+                assert_agrees(fixed, float, on_half, &format!("mean {total}/{samples}"));
+            }
+        }
+        assert_eq!(mean_permille(5, 0), None);
+    }
+
+    #[test]
+    fn ratios_agree_with_the_floats() {
+        for ns in (0..=INTERVAL_NS).step_by(99_991) {
+            let float = ns as f64 / INTERVAL_NS as f64;
+            let on_half = (ns * 2000) % INTERVAL_NS == 0 && (ns * 2000 / INTERVAL_NS) % 2 == 1;
+            let fixed = ratio_permille(ns, INTERVAL_NS).unwrap();
+            assert_agrees(fixed, float, on_half, &format!("ratio {ns}"));
+        }
+        assert_eq!(ratio_permille(5, 0), None);
+    }
+
+    #[test]
+    fn signals_built_by_hand_fall_back_to_the_floats() {
+        let built = CongestionSignals::builder()
+            .interval_ns(INTERVAL_NS)
+            .avg_wmem_pressure(0.4375)
+            .tcp_wmem_pressure(0.9)
+            .softirq_cpu_fraction(0.25)
+            .build();
+        assert!(built.fixed.is_none());
+        let fallback = built.fixed_point();
+        assert_eq!(fallback.interval_ns, INTERVAL_NS);
+        // 437.5 rounds up
+        assert_eq!(fallback.avg_wmem_pressure, 438);
+        assert_eq!(fallback.udp_wmem_pressure, None);
+        assert_eq!(fallback.tcp_wmem_pressure, Some(900));
+        assert_eq!(fallback.softirq_cpu_fraction, 250);
+    }
+}
+
+#[cfg(all(test, feature = "no-float"))]
+mod tests {
+    use super::*;
+    use crate::CongestionSignals;
+
+    #[test]
+    fn without_floats_the_fixed_point_is_what_was_set() {
+        let set = FixedSignals {
+            interval_ns: 200_000_000,
+            avg_wmem_pressure: ratio_permille(7, 16).unwrap(),
+            softirq_cpu_fraction: mean_permille(750, 3).unwrap(),
+            ..Default::default()
+        };
+        let signals = CongestionSignals::builder().fixed(set).build();
+        assert_eq!(signals.fixed_point(), set);
+        assert_eq!(
+            (set.avg_wmem_pressure, set.softirq_cpu_fraction),
+            (438, 250)
+        );
+
+        let unset = CongestionSignals::builder()
+            .interval_ns(200_000_000)
+            .softirq_ns(50_000_000)
+            .build()
+            .fixed_point();
+        assert_eq!(
+            (unset.interval_ns, unset.softirq_ns),
+            (200_000_000, 50_000_000)
+        );
+        assert_eq!(unset.avg_wmem_pressure, 0);
+    }
+}
@@ -0,0 +1,688 @@
+//Events as the readers decode them, for the unit tests. Each takes what its
+//tests vary and fills the rest the way a plain sample would have it.
+
+use crate::{
+    CongestionEvent, EventData, NapiPollData, QdiscData, RcvSocketData, RxSqueezeData, SendMsgData,
+    SocketData, SocketLifecycleData, SoftirqData, TcpStateData, EVENT_NAPI_POLL, EVENT_QDISC_DROP,
+    EVENT_RX_TIME_SQUEEZE, EVENT_SOCKET_LIFECYCLE, EVENT_SOCKET_RCV_STATE, EVENT_SOCKET_STATE,
+    EVENT_SOFTIRQ_ENTER, EVENT_SOFTIRQ_EXIT, EVENT_TCP_SEND, EVENT_TCP_STATE, EVENT_UDP_SEND,
+    IPPROTO_UDP, PACING_UNLIMITED, SAMPLER_PRIMARY, TRAFFIC_CLASS_UNKNOWN,
+};
+#[cfg(feature = "collector-core")]
+use crate::{EVENT_TCP_RECOVERY, EVENT_TCP_RTO, EVENT_UDP_RCV_DROP, SAMPLER_TRACE};
+
+pub(crate) const NAPI_WEIGHT: u32 = 64;
+
+fn event(timestamp_ns: u64, event_type: u32, cpu_id: u32, data: EventData) -> CongestionEvent {
+    CongestionEvent {
+        timestamp_ns,
+        event_type,
+        cpu_id,
+        data,
+    }
+}
+
+/// A sampled UDP send of `bytes`
+pub(crate) fn udp_send(
+    timestamp_ns: u64,
+    cpu_id: u32,
+    socket_id: u64,
+    bytes: u64,
+) -> CongestionEvent {
+    event(
+        timestamp_ns,
+        EVENT_UDP_SEND,
+        cpu_id,
+        EventData {
+            sendmsg: SendMsgData {
+                bytes,
+                is_tcp: 0,
+                loopback: 0,
+                socket_id,
+                netns: 0,
+                dscp: TRAFFIC_CLASS_UNKNOWN,
+                priority: TRAFFIC_CLASS_UNKNOWN,
+                samplers: SAMPLER_PRIMARY,
+                flow_hash: 0,
+            },
+        },
+    )
+}
+
+pub(crate) fn tcp_send(
+    timestamp_ns: u64,
+    cpu_id: u32,
+    socket_id: u64,
+    bytes: u64,
+) -> CongestionEvent {
+    let mut event = udp_send(timestamp_ns, cpu_id, socket_id, bytes);
+    event.event_type = EVENT_TCP_SEND;
+    event.data.sendmsg.is_tcp = 1;
+    event
+}
+
+/// `dropped` coalesced drops, the first `span_ns` before the last
+pub(crate) fn qdisc_drop(
+    timestamp_ns: u64,
+    cpu_id: u32,
+    dropped: u32,
+    span_ns: u32,
+) -> CongestionEvent {
+    event(
+        timestamp_ns,
+        EVENT_QDISC_DROP,
+        cpu_id,
+        EventData {
+            qdisc: QdiscData {
+                dropped,
+                backlog_bytes: span_ns,
+                backlog_packets: 0,
+                reason: 0,
+            },
+        },
+    )
+}
+
+/// A UDP socket's send buffer, `wmem_queued` of `sndbuf`
+pub(crate) fn socket_state(
+    timestamp_ns: u64,
+    cpu_id: u32,
+    socket_id: u64,
+    wmem_queued: u32,
+    sndbuf: u32,
+) -> CongestionEvent {
+    event(
+        timestamp_ns,
+        EVENT_SOCKET_STATE,
+        cpu_id,
+        EventData {
+            socket: SocketData {
+                wmem_queued,
+                sndbuf,
+                socket_id,
+                protocol: IPPROTO_UDP,
+                netns: 0,
+                dscp: TRAFFIC_CLASS_UNKNOWN,
+                priority: TRAFFIC_CLASS_UNKNOWN,
+                pacing_rate: PACING_UNLIMITED,
+                max_pacing_rate: PACING_UNLIMITED,
+                samplers: SAMPLER_PRIMARY,
+            },
+        },
+    )
+}
+
+pub(crate) fn softirq_enter(timestamp_ns: u64, cpu_id: u32, vec_nr: u32) -> CongestionEvent {
+    let mut event = softirq_exit(timestamp_ns, cpu_id, vec_nr, 0);
+    event.event_type = EVENT_SOFTIRQ_ENTER;
+    event
+}
+
+pub(crate) fn softirq_exit(
+    timestamp_ns: u64,
+    cpu_id: u32,
+    vec_nr: u32,
+    duration_ns: u64,
+) -> CongestionEvent {
+    event(
+        timestamp_ns,
+        EVENT_SOFTIRQ_EXIT,
+        cpu_id,
+        EventData {
+            softirq: SoftirqData {
+                vec_nr,
+                duration_ns,
+            },
+        },
+    )
+}
+
+pub(crate) fn rcv_state(
+    timestamp_ns: u64,
+    cpu_id: u32,
+    socket_id: u64,
+    rmem_alloc: u32,
+    rcvbuf: u32,
+) -> CongestionEvent {
+    event(
+        timestamp_ns,
+        EVENT_SOCKET_RCV_STATE,
+        cpu_id,
+        EventData {
+            rcv: RcvSocketData {
+                rmem_alloc,
+                rcvbuf,
+                socket_id,
+                samplers: SAMPLER_PRIMARY,
+            },
+        },
+    )
+}
+
+/// A datagram dropped on `socket_id`'s full rcvbuf
+#[cfg(feature = "collector-core")]
+pub(crate) fn rcv_drop(timestamp_ns: u64, cpu_id: u32, socket_id: u64) -> CongestionEvent {
+    let mut event = rcv_state(timestamp_ns, cpu_id, socket_id, 212_992, 212_992);
+    event.event_type = EVENT_UDP_RCV_DROP;
+    event
+}
+
+pub(crate) fn tcp_state(
+    timestamp_ns: u64,
+    cpu_id: u32,
+    socket_id: u64,
+    snd_cwnd: u32,
+    snd_ssthresh: u32,
+) -> CongestionEvent {
+    event(
+        timestamp_ns,
+        EVENT_TCP_STATE,
+        cpu_id,
+        EventData {
+            tcp: TcpStateData {
+                snd_cwnd,
+                snd_ssthresh,
+                pacing_rate: 0,
+                socket_id,
+                samplers: SAMPLER_PRIMARY,
+            },
+        },
+    )
+}
+
+/// An RTO firing on a socket whose window was `snd_cwnd` before the cut
+#[cfg(feature = "collector-core")]
+pub(crate) fn tcp_rto(
+    timestamp_ns: u64,
+    cpu_id: u32,
+    socket_id: u64,
+    snd_cwnd: u32,
+) -> CongestionEvent {
+    let mut event = tcp_state(timestamp_ns, cpu_id, socket_id, snd_cwnd, snd_cwnd / 2);
+    event.event_type = EVENT_TCP_RTO;
+    event
+}
+
+/// A fast recovery entry, `snd_cwnd` the window before the cut
+#[cfg(feature = "collector-core")]
+pub(crate) fn tcp_recovery(
+    timestamp_ns: u64,
+    cpu_id: u32,
+    socket_id: u64,
+    snd_cwnd: u32,
+) -> CongestionEvent {
+    let mut event = tcp_state(timestamp_ns, cpu_id, socket_id, snd_cwnd, snd_cwnd / 2);
+    event.event_type = EVENT_TCP_RECOVERY;
+    event
+}
+
+pub(crate) fn lifecycle(
+    timestamp_ns: u64,
+    socket_id: u64,
+    oldstate: u32,
+    newstate: u32,
+) -> CongestionEvent {
+    event(
+        timestamp_ns,
+        EVENT_SOCKET_LIFECYCLE,
+        0,
+        EventData {
+            lifecycle: SocketLifecycleData {
+                socket_id,
+                oldstate,
+                newstate,
+            },
+        },
+    )
+}
+
+pub(crate) fn rx_squeeze(
+    timestamp_ns: u64,
+    cpu_id: u32,
+    elapsed_ns: u64,
+    packets: u32,
+) -> CongestionEvent {
+    event(
+        timestamp_ns,
+        EVENT_RX_TIME_SQUEEZE,
+        cpu_id,
+        EventData {
+            rx_squeeze: RxSqueezeData {
+                elapsed_ns,
+                packets,
+                budget_exhausted: 0,
+            },
+        },
+    )
+}
+
+pub(crate) fn napi_poll(
+    timestamp_ns: u64,
+    cpu_id: u32,
+    device: &str,
+    work: u32,
+) -> CongestionEvent {
+    let mut dev_name = [0u8; 16];
+    dev_name[..device.len()].copy_from_slice(device.as_bytes());
+    event(
+        timestamp_ns,
+        EVENT_NAPI_POLL,
+        cpu_id,
+        EventData {
+            napi: NapiPollData {
+                work,
+                budget: NAPI_WEIGHT,
+                dev_name,
+            },
+        },
+    )
+}
+
+/// A second of signals with `backlog` bytes queued and `sent` bytes out of
+/// eth0, and with `stalled_ms`, ten TX queue stops that long
+#[cfg(not(feature = "no-float"))]
+pub(crate) fn backlogged(
+    backlog: u64,
+    drops: u64,
+    sent: u64,
+    stalled_ms: Option<u64>,
+) -> crate::CongestionSignals {
+    crate::CongestionSignals::builder()
+        .interval_ns(1_000_000_000)
+        .queue_depth_bytes(backlog)
+        .drops(drops)
+        .egress(vec![crate::InterfaceEgress {
+            interface: "eth0".to_string(),
+            egress_bytes_exact: sent,
+            egress_packets_exact: sent / 1200,
+        }])
+        .txq_stalls(stalled_ms.map(|_| 10))
+        .txq_stalled_ns(stalled_ms.map(|ms| 10 * ms * 1_000_000))
+        .build()
+}
+
+/// A second of fast snapshots, 50 buckets of 20 ms with `drops(i)` in bucket i
+pub(crate) fn burst_buckets(drops: impl Fn(u64) -> u64) -> Vec<crate::FastSignals> {
+    (0..50)
+        .map(|i| crate::FastSignals {
+            interval_ns: 20_000_000,
+            drops: drops(i),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// What `burst_buckets` dropped in all
+#[cfg(not(feature = "no-float"))]
+pub(crate) fn bucket_drops(buckets: &[crate::FastSignals]) -> crate::CongestionSignals {
+    crate::CongestionSignals::builder()
+        .interval_ns(1_000_000_000)
+        .drops(buckets.iter().map(|fast| fast.drops).sum())
+        .build()
+}
+
+/// 3 s of sends every 10 ms from `traced` and every 100 ms from `other`, the
+/// first traced from 1 s to 2 s: its unsampled sends there come with
+/// SAMPLER_TRACE alone, as the kernel sends them. Plus a send buffer sample
+/// of it at 500 ms and one only the trace took at 1.5 s
+#[cfg(feature = "collector-core")]
+pub(crate) fn traced_run(traced: u64, other: u64) -> Vec<CongestionEvent> {
+    let ms = |ms: u64| ms * 1_000_000;
+    let mut events = Vec::new();
+    for send in 0..300 {
+        let ts = ms(send * 10);
+        let mut event = udp_send(ts, 0, traced, 1200);
+        match (send % 10 == 0, (ms(1_000)..=ms(2_000)).contains(&ts)) {
+            (true, true) => event.data.sendmsg.samplers = SAMPLER_PRIMARY | SAMPLER_TRACE,
+            (false, true) => event.data.sendmsg.samplers = SAMPLER_TRACE,
+            (true, false) => {}
+            (false, false) => continue,
+        }
+        events.push(event);
+        if send % 10 == 0 {
+            events.push(udp_send(ts + ms(5), 1, other, 1200));
+        }
+    }
+    events.push(socket_state(ms(500) + 1, 0, traced, 100, 1000));
+    let mut traced_only = socket_state(ms(1_500) + 1, 0, traced, 900, 1000);
+    traced_only.data.socket.samplers = SAMPLER_TRACE;
+    events.push(traced_only);
+    events.sort_by_key(|e| e.timestamp_ns);
+    events
+}
+
+/// The previous schema version's documents, checked in under schema/fixtures
+#[cfg(feature = "schema")]
+pub(crate) const V0_DOCUMENTS: [(&str, &str); 5] = [
+    (
+        "signals",
+        include_str!("../schema/fixtures/v0/signals.json"),
+    ),
+    (
+        "decision",
+        include_str!("../schema/fixtures/v0/decision.json"),
+    ),
+    (
+        "preflight",
+        include_str!("../schema/fixtures/v0/preflight.json"),
+    ),
+    (
+        "session",
+        include_str!("../schema/fixtures/v0/session.json"),
+    ),
+    (
+        "manifest",
+        include_str!("../schema/fixtures/v0/manifest.json"),
+    ),
+];
+
+/// One document from each emitter the build has, by definition name. The
+/// bundle's manifest needs a bundle written, see the diagnostics tests
+#[cfg(any(feature = "schema", feature = "collector-core"))]
+pub(crate) fn documents() -> Vec<(&'static str, String)> {
+    use std::time::Duration;
+    let signals = crate::CongestionSignals::builder()
+        .interval_ns(1_000_000_000)
+        .drops(12)
+        .avg_wmem_pressure(0.5)
+        .build();
+    let mut documents = vec![("signals", signals.to_json())];
+    #[cfg(feature = "governor")]
+    {
+        let mut governor = crate::Governor::new(crate::GovernorPolicy::default(), 12_500_000);
+        governor.update(&signals);
+        documents.push(("decision", governor.recent_decisions(1)[0].to_json()));
+    }
+    #[cfg(feature = "collector-core")]
+    {
+        use crate::{CongestionState, Reason, SessionReport, StateTransition};
+        use std::time::SystemTime;
+        let host = crate::PreflightHost {
+            kernel_release: "5.10.0".to_string(),
+            kernel_version: Some((5, 10)),
+            btf: true,
+            tracefs: true,
+            capabilities: Some(crate::Capabilities {
+                effective: u64::MAX,
+                memlock_limit: Some(64 << 10),
+            }),
+        };
+        let preflight = crate::PreflightReport::assess(host, Vec::new(), None, Some(4 << 20));
+        documents.push(("preflight", preflight.to_json()));
+        let started_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_760_000_000);
+        let session = SessionReport {
+            label: "bulk".to_string(),
+            started_at,
+            ended_at: started_at + Duration::from_secs(5),
+            intervals: 5,
+            signals: signals.clone(),
+            transitions: vec![StateTransition {
+                at: started_at + Duration::from_secs(2),
+                from: CongestionState::Green,
+                to: CongestionState::Red(vec![Reason::DropRate { per_sec: 12.0 }]),
+            }],
+        };
+        documents.push(("session", session.to_json()));
+    }
+    let mut soak = crate::SoakTracker::new(crate::SoakBounds::default());
+    for hour in 0..3 {
+        soak.record(crate::SoakSample {
+            elapsed: Duration::from_secs(hour * 3600),
+            rss_bytes: 48 << 20,
+            tables: [("sockets".to_string(), 4000)].into(),
+            counters: [("drops".to_string(), 7 - hour)].into(),
+            accuracy_error: Some(0.02),
+            ..Default::default()
+        });
+    }
+    documents.push(("soak", soak.report().to_json()));
+    documents
+}
+
+/// A fresh directory under the system temp dir for one test's files
+pub(crate) fn scratch_dir(test: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "congestion-signals-{}-{}",
+        test,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Writes `contents` to `path` under `root`, making its directories
+pub(crate) fn write_file(root: &std::path::Path, path: &str, contents: &str) {
+    let path = root.join(path);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, contents).unwrap();
+}
+
+/// A /sys/class/net for one test: bond0 active-backup over two 10G slaves
+/// with eth0 active, bond1 802.3ad over two 25G slaves, and a plain 1G eth4
+pub(crate) fn bond_sysfs(test: &str) -> std::path::PathBuf {
+    let root = scratch_dir(test);
+    write_file(&root, "bond0/bonding/mode", "active-backup 1\n");
+    write_file(&root, "bond0/bonding/slaves", "eth0 eth1\n");
+    write_file(&root, "bond0/bonding/active_slave", "eth0\n");
+    write_file(&root, "bond1/bonding/mode", "802.3ad 4\n");
+    write_file(&root, "bond1/bonding/slaves", "eth2 eth3\n");
+    write_file(&root, "bond1/bonding/active_slave", "\n");
+    for (device, mbps) in [
+        ("eth0", 10_000),
+        ("eth1", 10_000),
+        ("eth2", 25_000),
+        ("eth3", 25_000),
+        ("eth4", 1_000),
+    ] {
+        write_file(&root, &format!("{device}/speed"), &format!("{mbps}\n"));
+        write_file(&root, &format!("{device}/operstate"), "up\n");
+    }
+    root
+}
+
+/// Events through the readers' aggregation and the processing side into
+/// interval reads, the way a running collector takes them, minus the kernel
+#[cfg(feature = "collector-core")]
+pub(crate) struct Replay {
+    pub(crate) signals: std::sync::Arc<crate::collector::AtomicSignals>,
+    pub(crate) interval: std::sync::Arc<crate::collector::IntervalReader>,
+    pub(crate) clock: crate::ManualClock,
+}
+
+#[cfg(feature = "collector-core")]
+impl Replay {
+    /// `config` on CPUs 0..cpus, its clock replaced by one the replay advances
+    pub(crate) fn new(config: crate::CollectorConfig, cpus: u32) -> Self {
+        let clock = crate::ManualClock::new();
+        let config = config.with_clock(std::sync::Arc::new(clock.clone()));
+        let signals = std::sync::Arc::new(crate::collector::AtomicSignals::new(
+            crate::CpuSlots::new(0..cpus),
+            &config,
+        ));
+        let interval = std::sync::Arc::new(crate::collector::IntervalReader::detached(
+            signals.clone(),
+            &config,
+        ));
+        Self {
+            signals,
+            interval,
+            clock,
+        }
+    }
+
+    /// One reader batch of `events`, each processed as soon as it's queued
+    pub(crate) fn feed<'a>(&self, events: impl IntoIterator<Item = &'a CongestionEvent>) {
+        self.feed_window(&crate::reader::ReadWindow::open(), events)
+    }
+
+    /// `feed` for a reader set counting what `window` admits
+    pub(crate) fn feed_window<'a>(
+        &self,
+        window: &crate::reader::ReadWindow,
+        events: impl IntoIterator<Item = &'a CongestionEvent>,
+    ) {
+        let mut batch = crate::collector::EventBatch::new(&self.signals);
+        for event in events {
+            if !window.admits(event.timestamp_ns) {
+                continue;
+            }
+            if crate::extensions::EXTENSION_EVENT_TYPES.contains(&event.event_type) {
+                batch.add(event);
+                crate::pipeline::process_slow(&self.signals, event);
+            } else if crate::reader::aggregate(event, &mut batch, &self.signals) {
+                crate::pipeline::process_slow(&self.signals, event);
+            }
+        }
+        self.signals.fold(&mut batch);
+    }
+
+    /// The interval read after `elapsed`
+    pub(crate) fn read(&self, elapsed: std::time::Duration) -> crate::CongestionSignals {
+        self.read_per_cpu(elapsed).0
+    }
+
+    pub(crate) fn read_per_cpu(
+        &self,
+        elapsed: std::time::Duration,
+    ) -> (crate::CongestionSignals, crate::PerCpuSignals) {
+        self.clock.advance(elapsed);
+        self.interval.read_and_reset_per_cpu()
+    }
+}
+
+/// A queue of each kind the build has, nothing ever draining them. The
+/// receivers come along to keep the queues open
+#[cfg(feature = "collector-core")]
+pub(crate) fn stalled_queues(
+    capacity: usize,
+) -> Vec<(
+    crate::pipeline::Queue,
+    std::sync::mpsc::Receiver<CongestionEvent>,
+)> {
+    let mut queues = Vec::new();
+    #[cfg(feature = "async-runtime")]
+    {
+        let (tx, rx) = std::sync::mpsc::sync_channel(capacity);
+        let queue = crate::pipeline::Queue::Async {
+            tx,
+            depth: std::sync::Arc::default(),
+            capacity,
+            ready: std::sync::Arc::default(),
+        };
+        queues.push((queue, rx));
+    }
+    #[cfg(feature = "blocking")]
+    {
+        let (tx, rx) = std::sync::mpsc::sync_channel(capacity);
+        let queue = crate::pipeline::Queue::Blocking {
+            tx,
+            depth: std::sync::Arc::default(),
+            capacity,
+        };
+        queues.push((queue, rx));
+    }
+    queues
+}
+
+/// What a queue from `stalled_queues` holds, in the order it was queued
+#[cfg(feature = "collector-core")]
+pub(crate) fn queued(rx: &std::sync::mpsc::Receiver<CongestionEvent>) -> Vec<CongestionEvent> {
+    rx.try_iter().collect()
+}
+
+/// A deterministic mix of every kind of event across CPUs 0..cpus, `count`
+/// of them in timestamp order with each softirq exit after its entry
+#[cfg(feature = "collector-core")]
+pub(crate) fn mixed_workload(count: usize, cpus: u32) -> Vec<CongestionEvent> {
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+    let mut events = Vec::with_capacity(count);
+    let mut timestamp_ns = 1_000_000;
+    while events.len() < count {
+        let roll = next();
+        timestamp_ns += 1_000 + roll % 50_000;
+        let cpu_id = (roll >> 8) as u32 % cpus;
+        let socket_id = 1 + (roll >> 16) % 8;
+        let size = 64 + (roll >> 24) % 9_000;
+        let event = match (roll >> 40) % 11 {
+            0 | 1 => udp_send(timestamp_ns, cpu_id, socket_id, size),
+            2 => tcp_send(timestamp_ns, cpu_id, socket_id, size),
+            3 => qdisc_drop(timestamp_ns, cpu_id, 1 + (roll >> 48) as u32 % 4, 2_000),
+            4 => socket_state(timestamp_ns, cpu_id, socket_id, size as u32 * 20, 212_992),
+            5 => {
+                events.push(softirq_enter(timestamp_ns, cpu_id, 3));
+                timestamp_ns += 5_000;
+                softirq_exit(timestamp_ns, cpu_id, 3, 5_000)
+            }
+            6 => rcv_state(timestamp_ns, cpu_id, socket_id, size as u32 * 10, 212_992),
+            7 => rcv_drop(timestamp_ns, cpu_id, socket_id),
+            8 => tcp_state(timestamp_ns, cpu_id, socket_id, 10 + size as u32 % 90, 64),
+            9 => rx_squeeze(timestamp_ns, cpu_id, 2_000_000, 300),
+            _ => napi_poll(timestamp_ns, cpu_id, "eth0", (roll >> 48) as u32 % 65),
+        };
+        events.push(event);
+    }
+    events.truncate(count);
+    events
+}
+
+/// The test binary's allocator: the system's, counting what the calling
+/// thread allocates inside `allocations`. Other tests' threads go uncounted
+struct CountingAllocator;
+
+std::thread_local! {
+    static COUNTING: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+    static ALLOCATED: std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+}
+
+impl CountingAllocator {
+    fn count(&self) {
+        if COUNTING
+            .try_with(|counting| counting.get())
+            .unwrap_or(false)
+        {
+            let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get() + 1));
+        }
+    }
+}
+
+// SAFETY: everything is handed to System as is
+unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        self.count();
+        std::alloc::System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: std::alloc::Layout) -> *mut u8 {
+        self.count();
+        std::alloc::System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: std::alloc::Layout, new_size: usize) -> *mut u8 {
+        self.count();
+        std::alloc::System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        std::alloc::System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// What `f` returns, and how many allocations and reallocations it made
+#[cfg(feature = "collector-core")]
+pub(crate) fn allocations<T>(f: impl FnOnce() -> T) -> (T, u64) {
+    ALLOCATED.with(|allocated| allocated.set(0));
+    COUNTING.with(|counting| counting.set(true));
+    let out = f();
+    COUNTING.with(|counting| counting.set(false));
+    (out, ALLOCATED.with(|allocated| allocated.get()))
+}
@@ -0,0 +1,2239 @@
+//Pacing-rate control on top of CongestionSignals. Each interval is scored from
+//weighted pressure components (drop rate, wmem pressure, softirq load, stopped
+//TX queues, TCP loss episodes); the rate
+//is cut in proportion to the score once it crosses the policy's threshold, probed
+//upward while the score stays low, and held on app-limited intervals. Intervals
+//the kernel already paced (CongestionSignals::kernel_paced) are never probed
+//upward and only cut, gently, when they saw loss. Every
+//decision goes into a DecisionLog so a rate change can be explained after the fact,
+//together with the headroom estimate (headroom.rs) for the interval. Signals read
+//longer ago than the policy's max input age freeze the rate instead: a stalled
+//publisher shouldn't have the governor keep cutting or probing on old numbers.
+//With traffic classes, update_per_class() also paces each class on its own,
+//taking a cut in proportion to the class's weight. update_probed() scores wmem
+//from the paced sockets' buffers read right before the decision. When softirq
+//load rises, the previous interval tells whether new connections or sent
+//packets grew faster, so the explanation can blame churn or throughput.
+//Host memory pressure on socket buffers shrinks every buffer at once; while
+//it's elevated the rate isn't raised and full buffers are blamed on the host.
+//Bounded-latency mode splits the two directions: update_fast() cuts on fast
+//snapshots as soon as they show pressure, update_slow() raises on full
+//intervals, and neither does the other's job. A queue building with nothing
+//dropped (bufferbloat.rs) adds a component of its own, weighted to cut on its
+//own: the loss that would otherwise score never comes. Fast snapshots fed in
+//with observe_fast() classify each interval's pressure (microburst.rs); a cut
+//for a microburst is taken as smoothing instead, the same rate with the
+//bursts the pacer lets out capped, and the record says what kind it was.
+//Every cut is judged by whether the pressure it was for declined after it
+//(effectiveness.rs); while few do, the congestion isn't ours to fix and cuts
+//are taken at a fraction of their size. Datagrams our own receivers dropped on
+//full rcvbufs, and rcvbufs filling up, are the receiver falling behind rather
+//than the path: they score nothing and never cut, the record says the
+//receiver was overwhelmed, for ACK frequency and flow control to act on.
+
+use crate::clock::{system_clock, Clock};
+use crate::headroom::{HeadroomConfig, HeadroomEstimate, HeadroomEstimator};
+use crate::json::{json_f64, json_opt, json_opt_f64, json_string};
+use crate::{
+    permille_from_f64, Bufferbloat, BufferbloatConfig, BufferbloatDetector, BurstClass,
+    BurstClassifier, BurstConfig, BurstEpisode, CaptureNotice, CongestionSignals, Effectiveness,
+    EffectivenessConfig, EffectivenessTracker, EndpointAdvisory, FastSignals, Limitation,
+    SendSizes, SocketStateSample, EXPORT_SCHEMA_VERSION,
+};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Decisions kept in memory by default, ~3 minutes at 200 ms intervals
+pub const DEFAULT_DECISION_LOG_CAPACITY: usize = 1024;
+
+// Rise of softirq_cpu_fraction over the previous interval worth explaining
+const SOFTIRQ_RISE: f64 = 0.05;
+// Below this many connections opened and closed per second churn isn't blamed,
+// however fast it grew
+const MIN_CHURN_PER_SEC: f64 = 100.0;
+// NET_RX rounds cut short per second from which the softirq time is blamed on
+// receive starvation
+const MIN_SQUEEZES_PER_SEC: f64 = 10.0;
+
+/// How signals turn into a score and the score into a rate, see [`Governor`]
+#[derive(Debug, Clone)]
+pub struct GovernorPolicy {
+    /// Shows up in every logged decision, so A/B'd policies can be told apart
+    pub name: String,
+    pub drops_weight: f64,
+    pub wmem_weight: f64,
+    pub softirq_weight: f64,
+    /// Weight of the fraction of the interval TX queues were stopped for
+    pub txq_weight: f64,
+    /// Weight of loss episodes on coexisting TCP flows
+    pub loss_weight: f64,
+    /// Fast recovery entries one RTO counts as: a timeout means everything in
+    /// flight was lost, recovery only that some packets were
+    pub rto_severity: f64,
+    /// Loss episodes per second (recoveries plus `rto_severity` per RTO) that
+    /// count as full pressure
+    pub loss_full_scale: f64,
+    /// Weight of ECN CE feedback, below `loss_weight`: a mark says a queue is
+    /// building before anything was dropped
+    pub ce_weight: f64,
+    /// CE marks plus CE-triggered TCP window reductions per second that count
+    /// as full pressure
+    pub ce_full_scale: f64,
+    /// Which send buffers the wmem component looks at
+    pub wmem_source: WmemSource,
+    /// Drops per second that count as full pressure (component 1.0)
+    pub drops_full_scale: f64,
+    /// Score (0.0-1.0) at or above which the rate is cut
+    pub cut_threshold: f64,
+    /// Fraction of the rate cut at score 1.0, scaled down linearly below it
+    pub max_cut: f64,
+    /// Share of a cut taken on kernel-paced intervals, which are only cut when
+    /// they saw drops or TCP loss and never raised
+    pub kernel_paced_cut: f64,
+    /// Score at or below which the rate is raised
+    pub increase_threshold: f64,
+    /// Fraction of the rate added per low-pressure interval
+    pub increase_step: f64,
+    /// Bounds for the pacing rate, bytes/sec
+    pub min_rate: u64,
+    pub max_rate: u64,
+    /// How the per-interval headroom estimate tracks the send-rate ceiling
+    pub headroom: HeadroomConfig,
+    /// Signals older than this, by the governor's clock, hold the rate and
+    /// leave the headroom estimate alone. None acts on any age
+    pub max_input_age: Option<Duration>,
+    /// Share of a cut each traffic class takes in `update_per_class`, by class
+    /// name: 0.0 holds the class steady, classes not listed take all of it
+    pub class_weights: HashMap<String, f64>,
+    /// Least time between two cuts of `update_fast`. A full buffer stays full
+    /// for several fast snapshots after a cut, until the lower rate drains it;
+    /// cutting on each would take the rate to the floor
+    pub min_fast_cut_gap: Duration,
+    /// `CongestionSignals::net_memory_pressure` at or above which the host is
+    /// short of socket buffer memory, as it is on any interval TCP entered
+    /// memory pressure in. The rate isn't raised then: more sends only take
+    /// more of what's short
+    pub net_memory_threshold: f64,
+    /// When intervals count as bufferbloat. `link_rate` None takes
+    /// `headroom.link_rate`
+    pub bufferbloat: BufferbloatConfig,
+    /// Weight of the bufferbloat component, 1.0 while the detector sees it.
+    /// At `cut_threshold` or above it cuts without any loss
+    pub bufferbloat_weight: f64,
+    /// How the fast snapshots of an interval classify its episode
+    pub burst: BurstConfig,
+    /// Smooth instead of cutting on an interval whose pressure was a
+    /// microburst: a few packets lost to one burst say the bursts are too
+    /// big, not the rate too high
+    pub smooth_microbursts: bool,
+    /// How cuts are judged to have worked, and how much of one is taken once
+    /// they mostly don't
+    pub effectiveness: EffectivenessConfig,
+    /// `avg_rmem_pressure` at or above which our receivers count as
+    /// overwhelmed, as they do on any interval with receive drops
+    pub rmem_threshold: f64,
+}
+
+impl Default for GovernorPolicy {
+    fn default() -> Self {
+        Self {
+            name: "default".to_string(),
+            drops_weight: 0.5,
+            wmem_weight: 0.3,
+            softirq_weight: 0.1,
+            txq_weight: 0.1,
+            loss_weight: 0.2,
+            rto_severity: 4.0,
+            loss_full_scale: 20.0,
+            ce_weight: 0.15,
+            ce_full_scale: 20.0,
+            wmem_source: WmemSource::Udp,
+            drops_full_scale: 500.0,
+            cut_threshold: 0.3,
+            max_cut: 0.5,
+            kernel_paced_cut: 0.5,
+            increase_threshold: 0.1,
+            increase_step: 0.05,
+            // 1 Mbps .. 10 Gbps
+            min_rate: 125_000,
+            max_rate: 1_250_000_000,
+            headroom: HeadroomConfig::default(),
+            // Five 200 ms intervals
+            max_input_age: Some(Duration::from_secs(1)),
+            class_weights: HashMap::new(),
+            min_fast_cut_gap: Duration::from_millis(100),
+            // Within a tenth of where the kernel starts shrinking buffers
+            net_memory_threshold: 0.9,
+            bufferbloat: BufferbloatConfig::default(),
+            bufferbloat_weight: 0.3,
+            burst: BurstConfig::default(),
+            smooth_microbursts: true,
+            effectiveness: EffectivenessConfig::default(),
+            rmem_threshold: 0.8,
+        }
+    }
+}
+
+/// Send buffer pressure fed to the governor's wmem component
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WmemSource {
+    /// `udp_wmem_pressure`: the QUIC sockets being paced. Backpressure on
+    /// coexisting TCP flows says little about our own queueing
+    #[default]
+    Udp,
+    /// `tcp_wmem_pressure`
+    Tcp,
+    /// `avg_wmem_pressure`, every sampled socket
+    All,
+}
+
+/// One weighted input of a decision's score
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreComponent {
+    /// "drops", "wmem", "softirq", "txq", "tcp_loss", "ecn_ce" or "bufferbloat"
+    pub name: &'static str,
+    /// The signal as measured: drops/sec, loss episodes/sec, CE marks/sec, a 0.0-1.0
+    /// pressure / fraction, or for bufferbloat the backlog's growth (0 without)
+    pub value: f64,
+    /// `value` mapped to 0.0-1.0
+    pub normalized: f64,
+    pub weight: f64,
+}
+
+impl ScoreComponent {
+    /// What this component added to the score
+    pub fn contribution(&self) -> f64 {
+        self.normalized * self.weight
+    }
+
+    fn describe(&self) -> String {
+        match self.name {
+            "drops" => format!("drops {:.0}/s (weight {})", self.value, self.weight),
+            "txq" => format!(
+                "NIC saturation: TX queue stopped {:.0}% (weight {})",
+                self.value * 100.0,
+                self.weight
+            ),
+            "tcp_loss" => format!(
+                "TCP loss {:.1} episodes/s (weight {})",
+                self.value, self.weight
+            ),
+            "ecn_ce" => format!("ECN CE {:.1} marks/s (weight {})", self.value, self.weight),
+            "bufferbloat" => format!(
+                "bufferbloat: backlog up {:.1}x without drops (weight {})",
+                self.value, self.weight
+            ),
+            name => format!(
+                "{} {:.0}% (weight {})",
+                name,
+                self.value * 100.0,
+                self.weight
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacingAction {
+    Cut,
+    Hold,
+    Increase,
+    /// The rate held, but sent more evenly: a pacer caps its bursts until the
+    /// next decision. For microbursts, see `GovernorPolicy::smooth_microbursts`
+    Smooth,
+}
+
+impl PacingAction {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Cut => "cut",
+            Self::Hold => "hold",
+            Self::Increase => "increase",
+            Self::Smooth => "smooth",
+        }
+    }
+}
+
+/// What [`Governor::update`] returns for one interval
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PacingDecision {
+    /// Pacing rate to apply until the next interval, bytes/sec
+    pub rate: u64,
+    pub action: PacingAction,
+    /// Sum of the weighted components, 0.0-1.0
+    pub score: f64,
+    /// How much faster than this interval the host could have sent
+    pub headroom: HeadroomEstimate,
+    /// Age of the signals when they were too old to act on: the rate was held
+    /// and the headroom has zero confidence. None for a normal decision
+    pub stale_input: Option<Duration>,
+    /// ACK and receive-window advice for the local endpoint, from the same
+    /// interval's receive side. The defaults for stale input, and the latest
+    /// interval's for `update_fast`, whose snapshots carry no receive side
+    pub advisory: EndpointAdvisory,
+}
+
+impl PacingDecision {
+    /// `score` in per-mille, rounded half up
+    pub fn score_permille(&self) -> u32 {
+        permille_from_f64(self.score)
+    }
+}
+
+/// A decision with everything that went into it, see [`DecisionLog`]
+#[derive(Debug, Clone)]
+pub struct DecisionRecord {
+    /// When the decision was made, or the end of the interval it was made on
+    /// for wall-clock aligned snapshots
+    pub at: SystemTime,
+    pub policy: String,
+    /// Rate before this decision, bytes/sec
+    pub previous_rate: u64,
+    pub decision: PacingDecision,
+    pub components: Vec<ScoreComponent>,
+    /// The interval the decision was made on
+    pub signals: CongestionSignals,
+    /// What drove softirq load up since the previous interval, None when it
+    /// didn't rise or there was no previous interval to compare with
+    pub softirq_cause: Option<SoftirqCause>,
+    /// Made by `update_fast`, `signals` then holds the fast snapshot
+    /// (`FastSignals::to_signals`)
+    pub fast: bool,
+    /// The host was short of socket buffer memory
+    /// (`GovernorPolicy::host_memory_pressure`): full send buffers are the
+    /// host's doing, not the path's, and the rate wasn't raised
+    pub host_memory_pressure: bool,
+    /// What the bufferbloat detector saw, None when it didn't. Fast decisions
+    /// carry no backlog and leave it None
+    pub bufferbloat: Option<Bufferbloat>,
+    /// What kind of episode the interval's fast snapshots showed, None
+    /// without pressure in them or without any (`Governor::observe_fast`)
+    pub burst: Option<BurstEpisode>,
+    /// How the policy's cuts had worked out, this one not yet judged. While
+    /// `non_responsive` a cut is taken at `EffectivenessConfig::backoff`
+    pub effectiveness: Effectiveness,
+    /// Our receive side was falling behind (`GovernorPolicy::receiver_overwhelmed`).
+    /// That's for the endpoint's ACKs and flow control, the rate wasn't cut for it
+    pub receiver_overwhelmed: Option<ReceivePressure>,
+}
+
+/// Our own receivers falling behind, see [`GovernorPolicy::receiver_overwhelmed`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReceivePressure {
+    /// `udp_rcv_drops` per second
+    pub rcv_drops_per_sec: f64,
+    /// `avg_rmem_pressure`
+    pub rmem_pressure: f64,
+}
+
+/// Whether softirq load came with more connections or more packets, or NET_RX
+/// ran out of budget, see [`SoftirqCause::between`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoftirqCause {
+    /// Connections opened and closed per second grew faster than packets sent
+    Churn,
+    /// Packets sent grew at least as fast, or churn stayed too low to matter
+    Throughput,
+    /// `rx_time_squeeze`: NET_RX rounds kept running out of budget, so receive
+    /// processing, our ACKs with it, is starved on this host
+    ReceiveStarvation,
+}
+
+impl SoftirqCause {
+    /// Attribute the softirq load of `current`. At 10 or more time squeezes a
+    /// second it's receive starvation, risen or not. Otherwise None unless
+    /// `softirq_cpu_fraction` rose by at least 5 points from `previous`, then
+    /// compares how much the per-second rates of new plus closed connections
+    /// and of estimated sends grew; churn is blamed only above 100 connections
+    /// a second
+    pub fn between(previous: &CongestionSignals, current: &CongestionSignals) -> Option<Self> {
+        LoadSample::of(previous)?.cause(&LoadSample::of(current)?)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Churn => "churn",
+            Self::Throughput => "throughput",
+            Self::ReceiveStarvation => "receive_starvation",
+        }
+    }
+}
+
+/// The parts of an interval [`SoftirqCause`] compares, per second
+#[derive(Debug, Clone, Copy)]
+struct LoadSample {
+    softirq: f64,
+    packets_per_sec: f64,
+    churn_per_sec: f64,
+    squeezes_per_sec: f64,
+}
+
+impl LoadSample {
+    fn of(signals: &CongestionSignals) -> Option<Self> {
+        let secs = signals.interval_ns as f64 / 1e9;
+        (secs > 0.0).then(|| Self {
+            softirq: signals.softirq_cpu_fraction,
+            packets_per_sec: signals.est_wire_packets.total() as f64 / secs,
+            churn_per_sec: (signals.new_connections_per_interval
+                + signals.closed_connections_per_interval) as f64
+                / secs,
+            squeezes_per_sec: signals.rx_time_squeeze.unwrap_or(0) as f64 / secs,
+        })
+    }
+
+    fn cause(&self, current: &Self) -> Option<SoftirqCause> {
+        if current.squeezes_per_sec >= MIN_SQUEEZES_PER_SEC {
+            return Some(SoftirqCause::ReceiveStarvation);
+        }
+        if current.softirq - self.softirq < SOFTIRQ_RISE {
+            return None;
+        }
+        // One per second added to both sides, so growth from nothing is finite
+        let growth = |before: f64, after: f64| (after + 1.0) / (before + 1.0);
+        let churn = growth(self.churn_per_sec, current.churn_per_sec);
+        let packets = growth(self.packets_per_sec, current.packets_per_sec);
+        Some(
+            if current.churn_per_sec >= MIN_CHURN_PER_SEC && churn > packets {
+                SoftirqCause::Churn
+            } else {
+                SoftirqCause::Throughput
+            },
+        )
+    }
+}
+
+impl DecisionRecord {
+    /// One-line justification, e.g.
+    /// `rate cut 120→80 Mbps: drops 340/s (weight 0.5), wmem 87% (weight 0.3)`.
+    /// Lists the components that contributed, largest first
+    pub fn explain(&self) -> String {
+        let previous = mbps(self.previous_rate);
+        let rate = mbps(self.decision.rate);
+        let head = match self.decision.action {
+            PacingAction::Cut if self.fast => format!("rate cut {}→{} Mbps (fast)", previous, rate),
+            PacingAction::Cut => format!("rate cut {}→{} Mbps", previous, rate),
+            PacingAction::Increase => format!("rate raised {}→{} Mbps", previous, rate),
+            PacingAction::Hold => format!("rate held at {} Mbps", rate),
+            PacingAction::Smooth => format!("rate held at {} Mbps, smoothed", rate),
+        };
+
+        if let Some(age) = self.decision.stale_input {
+            return format!("{}: input stale ({:.1} s old)", head, age.as_secs_f64());
+        }
+        if self.signals.limitation == Limitation::AppLimited {
+            return format!("{}: app-limited", head);
+        }
+        if self.signals.kernel_paced
+            && !matches!(
+                self.decision.action,
+                PacingAction::Cut | PacingAction::Smooth
+            )
+        {
+            return format!("{}: kernel-paced", head);
+        }
+
+        let mut contributing: Vec<&ScoreComponent> = self
+            .components
+            .iter()
+            .filter(|c| c.contribution() > 0.0)
+            .collect();
+        contributing.sort_by(|a, b| b.contribution().total_cmp(&a.contribution()));
+        let host_memory = self.host_memory_pressure.then(|| self.host_memory());
+        if contributing.is_empty() {
+            let reasons: Vec<String> = host_memory.into_iter().chain(self.receiver()).collect();
+            return if reasons.is_empty() {
+                format!("{}: no pressure", head)
+            } else {
+                format!("{}: {}", head, reasons.join(", "))
+            };
+        }
+        let mut reasons: Vec<String> = contributing
+            .iter()
+            .map(|c| match (c.name, self.softirq_cause) {
+                ("softirq", Some(cause)) => format!("{} {}", c.describe(), self.blame(cause)),
+                ("wmem", _) if self.host_memory_pressure => {
+                    format!("{} from host memory", c.describe())
+                }
+                _ => c.describe(),
+            })
+            .collect();
+        if let Some(host_memory) = host_memory {
+            reasons.insert(0, host_memory);
+        }
+        reasons.extend(self.receiver());
+        if let Some(burst) = &self.burst {
+            reasons.push(describe_burst(burst));
+        }
+        if self.effectiveness.non_responsive {
+            reasons.push(format!(
+                "non-responsive congestion ({:.0}% of cuts helped)",
+                self.effectiveness.improved_fraction.unwrap_or(0.0) * 100.0
+            ));
+        }
+        if self.signals.kernel_paced {
+            reasons.insert(0, "kernel-paced".to_string());
+        }
+        format!("{}: {}", head, reasons.join(", "))
+    }
+
+    fn blame(&self, cause: SoftirqCause) -> String {
+        match cause {
+            SoftirqCause::Churn => {
+                let secs = (self.signals.interval_ns as f64 / 1e9).max(1e-9);
+                format!(
+                    "from connection churn ({:.0} new/s)",
+                    self.signals.new_connections_per_interval as f64 / secs
+                )
+            }
+            SoftirqCause::Throughput => "from throughput".to_string(),
+            SoftirqCause::ReceiveStarvation => {
+                let secs = (self.signals.interval_ns as f64 / 1e9).max(1e-9);
+                format!(
+                    "from receive starvation (local), {:.0} time squeezes/s",
+                    self.signals.rx_time_squeeze.unwrap_or(0) as f64 / secs
+                )
+            }
+        }
+    }
+
+    fn receiver(&self) -> Option<String> {
+        let receive = self.receiver_overwhelmed?;
+        Some(format!(
+            "receiver overwhelmed: {:.0} rcv drops/s, rmem {:.0}% (not cut for)",
+            receive.rcv_drops_per_sec,
+            receive.rmem_pressure * 100.0
+        ))
+    }
+
+    fn host_memory(&self) -> String {
+        let s = &self.signals;
+        let mut out = "host memory pressure".to_string();
+        if let Some(pressure) = s.net_memory_pressure {
+            let _ = write!(
+                out,
+                ", socket buffers at {:.0}% of tcp/udp_mem",
+                pressure * 100.0
+            );
+        }
+        if let Some(events) = s.memory_pressure_events.filter(|&n| n > 0) {
+            let _ = write!(out, ", TCP entered it {} times", events);
+        }
+        out
+    }
+
+    /// The record as one JSON object, no trailing newline. Signals are reduced to
+    /// their scalar fields
+    pub fn to_json(&self) -> String {
+        let at_ms = self
+            .at
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let s = &self.signals;
+
+        let mut out = String::new();
+        let _ = write!(
+            out,
+            "{{\"schema_version\":{},\"at_ms\":{},\"policy\":{},\"action\":\"{}\",\"previous_rate\":{},\"rate\":{},\"score\":{},\
+             \"stale_input_ms\":{},\"softirq_cause\":{},\"fast\":{},\"host_memory_pressure\":{},\
+             \"bufferbloat\":{},\"burst\":{},\"effectiveness\":{},\"receiver_overwhelmed\":{}",
+            EXPORT_SCHEMA_VERSION,
+            at_ms,
+            json_string(&self.policy),
+            self.decision.action.name(),
+            self.previous_rate,
+            self.decision.rate,
+            json_f64(self.decision.score),
+            json_opt(self.decision.stale_input.map(|age| age.as_millis())),
+            self.softirq_cause
+                .map_or("null".to_string(), |cause| json_string(cause.name())),
+            self.fast,
+            self.host_memory_pressure,
+            self.bufferbloat.map_or("null".to_string(), |bloat| bufferbloat_json(&bloat)),
+            self.burst.map_or("null".to_string(), |burst| burst_json(&burst)),
+            self.effectiveness.to_json(),
+            self.receiver_overwhelmed
+                .map_or("null".to_string(), |receive| receive_json(&receive)),
+        );
+        let headroom = &self.decision.headroom;
+        let _ = write!(
+            out,
+            ",\"estimated_headroom_bps\":{},\"headroom_confidence\":{},\"ceiling_bps\":{},\
+             \"link_utilization\":{}",
+            json_opt(headroom.estimated_headroom_bps),
+            json_f64(headroom.confidence),
+            json_opt(headroom.ceiling_bps),
+            json_opt_f64(headroom.link_utilization),
+        );
+        out.push_str(",\"components\":[");
+        for (i, c) in self.components.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(
+                out,
+                "{{\"name\":\"{}\",\"value\":{},\"normalized\":{},\"weight\":{}}}",
+                c.name,
+                json_f64(c.value),
+                json_f64(c.normalized),
+                json_f64(c.weight),
+            );
+        }
+        let _ = write!(
+            out,
+            "],\"signals\":{{\"interval_ns\":{},\"send_bytes\":{},\"drops\":{},\"avg_wmem_pressure\":{},\
+             \"udp_avg_send_bytes\":{},\"tcp_avg_send_bytes\":{},\
+             \"softirq_cpu_fraction\":{},\"udp_rcv_drops\":{},\"avg_rmem_pressure\":{},\
+             \"rto_events\":{},\"loss_recovery_episodes\":{},\"limitation\":\"{:?}\",\
+             \"kernel_paced\":{},\"new_connections_per_interval\":{},\
+             \"closed_connections_per_interval\":{},\"net_memory_pressure\":{},\
+             \"memory_pressure_events\":{}}}",
+            s.interval_ns,
+            s.send_bytes,
+            s.drops,
+            json_f64(s.avg_wmem_pressure),
+            json_f64(avg_send_bytes(&s.send_size_stats.udp)),
+            json_f64(avg_send_bytes(&s.send_size_stats.tcp)),
+            json_f64(s.softirq_cpu_fraction),
+            s.udp_rcv_drops,
+            json_f64(s.avg_rmem_pressure),
+            json_opt(s.rto_events),
+            json_opt(s.loss_recovery_episodes),
+            s.limitation,
+            s.kernel_paced,
+            s.new_connections_per_interval,
+            s.closed_connections_per_interval,
+            json_opt_f64(s.net_memory_pressure),
+            json_opt(s.memory_pressure_events),
+        );
+        let sessions: Vec<String> = s.sessions.iter().map(|l| json_string(l)).collect();
+        let captures: Vec<String> = s.captures.iter().map(CaptureNotice::to_json).collect();
+        let _ = write!(
+            out,
+            ",\"sessions\":[{}],\"captures\":[{}],\"explain\":{}}}",
+            sessions.join(","),
+            captures.join(","),
+            json_string(&self.explain())
+        );
+        out
+    }
+}
+
+/// Bounded ring of the most recent decisions, optionally also appended as JSON
+/// lines to a writer (a file, stderr, a pipe to the log shipper)
+pub struct DecisionLog {
+    records: VecDeque<DecisionRecord>,
+    capacity: usize,
+    sink: Option<Box<dyn Write + Send>>,
+    sink_errors: u64,
+}
+
+impl DecisionLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: VecDeque::with_capacity(capacity.min(DEFAULT_DECISION_LOG_CAPACITY)),
+            capacity,
+            sink: None,
+            sink_errors: 0,
+        }
+    }
+
+    /// Also write every record to `sink`, one JSON object per line
+    pub fn with_json_lines(mut self, sink: impl Write + Send + 'static) -> Self {
+        self.sink = Some(Box::new(sink));
+        self
+    }
+
+    pub fn record(&mut self, record: DecisionRecord) {
+        if let Some(sink) = &mut self.sink {
+            let line = record.to_json();
+            if let Err(e) = writeln!(sink, "{}", line).and_then(|_| sink.flush()) {
+                self.sink_errors += 1;
+                // First failure and then every 100th, don't flood the log about the log
+                if self.sink_errors % 100 == 1 {
+                    log::warn!(
+                        "decision log write failed ({} so far): {}",
+                        self.sink_errors,
+                        e
+                    );
+                }
+            }
+        }
+
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    /// Up to `n` most recent records, oldest first
+    pub fn recent(&self, n: usize) -> impl Iterator<Item = &DecisionRecord> {
+        self.records
+            .iter()
+            .skip(self.records.len().saturating_sub(n))
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// JSON lines that failed to write since creation
+    pub fn sink_errors(&self) -> u64 {
+        self.sink_errors
+    }
+}
+
+impl Default for DecisionLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_DECISION_LOG_CAPACITY)
+    }
+}
+
+impl std::fmt::Debug for DecisionLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DecisionLog")
+            .field("len", &self.records.len())
+            .field("capacity", &self.capacity)
+            .field("json_lines", &self.sink.is_some())
+            .finish()
+    }
+}
+
+/// Turns each interval's signals into a pacing rate for our sender
+#[derive(Debug)]
+pub struct Governor {
+    policy: GovernorPolicy,
+    rate: u64,
+    log: DecisionLog,
+    headroom: HeadroomEstimator,
+    bufferbloat: BufferbloatDetector,
+    // What it saw on the latest fresh interval, for update_per_class
+    bloat: Option<Bufferbloat>,
+    burst: BurstClassifier,
+    effectiveness: EffectivenessTracker,
+    class_rates: HashMap<String, u64>,
+    // The latest fresh interval, for SoftirqCause
+    load: Option<LoadSample>,
+    // The latest fresh interval's, which fast decisions carry on
+    advisory: EndpointAdvisory,
+    // Of update_fast: the last cut, and whether one came since update_slow
+    last_fast_cut: Option<Instant>,
+    fast_cut_since_slow: bool,
+    clock: Arc<dyn Clock>,
+}
+
+impl Governor {
+    /// `initial_rate` in bytes/sec, clamped to the policy's bounds
+    pub fn new(policy: GovernorPolicy, initial_rate: u64) -> Self {
+        let rate = initial_rate.clamp(policy.min_rate, policy.max_rate);
+        let bufferbloat = BufferbloatConfig {
+            link_rate: policy.bufferbloat.link_rate.or(policy.headroom.link_rate),
+            ..policy.bufferbloat
+        };
+        Self {
+            headroom: HeadroomEstimator::new(policy.headroom),
+            bufferbloat: BufferbloatDetector::new(bufferbloat),
+            bloat: None,
+            burst: BurstClassifier::new(policy.burst),
+            effectiveness: EffectivenessTracker::new(policy.effectiveness),
+            policy,
+            rate,
+            log: DecisionLog::default(),
+            class_rates: HashMap::new(),
+            load: None,
+            advisory: EndpointAdvisory::default(),
+            last_fast_cut: None,
+            fast_cut_since_slow: false,
+            clock: system_clock(),
+        }
+    }
+
+    /// Time `min_fast_cut_gap` and the input age on `clock` rather than the system's
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Replace the default in-memory log, e.g. to add JSON lines output
+    pub fn with_decision_log(mut self, log: DecisionLog) -> Self {
+        self.log = log;
+        self
+    }
+
+    /// Current pacing rate, bytes/sec
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    pub fn policy(&self) -> &GovernorPolicy {
+        &self.policy
+    }
+
+    /// How this policy's cuts have worked out: how many relieved the pressure
+    /// they were for, how soon, and whether the congestion has turned out
+    /// non-responsive
+    pub fn effectiveness(&self) -> Effectiveness {
+        self.effectiveness.effectiveness()
+    }
+
+    /// Fold in a fast snapshot taken during the interval the next `update` is
+    /// for, without deciding on it: its pressure, with the others', tells a
+    /// microburst from sustained congestion. `update_fast` does this itself
+    pub fn observe_fast(&mut self, fast: &FastSignals) {
+        self.burst.observe(fast);
+    }
+
+    /// Decide the rate for the next interval and log why
+    pub fn update(&mut self, signals: &CongestionSignals) -> PacingDecision {
+        self.step(signals, Cadence::Both)
+    }
+
+    /// The cutting half of bounded-latency mode, for
+    /// `CongestionCollector::fast_snapshots`: scored like an interval, with the
+    /// fullest send buffer as the wmem component, and cut when it crosses
+    /// `cut_threshold` but never raised. At most one cut per `min_fast_cut_gap`.
+    /// Cuts come before a burst can be classified, so it isn't smoothed here:
+    /// the episode goes into `update_slow`'s record.
+    /// Holds carry no headroom estimate and only cuts go into the log, a hold
+    /// every few milliseconds would push the interval decisions out of it
+    pub fn update_fast(&mut self, fast: &FastSignals) -> PacingDecision {
+        self.burst.observe(fast);
+        self.step(&fast.to_signals(), Cadence::Fast)
+    }
+
+    /// The raising half, for full intervals next to `update_fast`: `update`
+    /// without the cut, held instead when `update_fast` cut since the last
+    /// call, so a rate just cut isn't probed upward on an interval that mostly
+    /// predates the cut. Cuts for bufferbloat after all, which fast snapshots
+    /// have no backlog to see
+    pub fn update_slow(&mut self, signals: &CongestionSignals) -> PacingDecision {
+        self.step(signals, Cadence::Slow)
+    }
+
+    fn step(&mut self, signals: &CongestionSignals, cadence: Cadence) -> PacingDecision {
+        let stale_input = self.stale_input(signals);
+        let fast = cadence == Cadence::Fast;
+        // Fast snapshots carry no backlog, and stale intervals aren't now
+        if stale_input.is_none() && !fast {
+            self.bloat = self.bufferbloat.update(signals);
+        }
+        let bufferbloat = self.bloat.filter(|_| stale_input.is_none() && !fast);
+        // A fast snapshot is one bucket, the interval closes an episode
+        let burst = if fast { None } else { self.burst.classify() };
+        let smooth = self.policy.smooth_microbursts
+            && stale_input.is_none()
+            && burst.is_some_and(|burst| burst.class == BurstClass::Microburst);
+        let mut components = self.policy.score_components(signals);
+        components.push(self.policy.bufferbloat_component(bufferbloat));
+        let score = components
+            .iter()
+            .map(ScoreComponent::contribution)
+            .sum::<f64>()
+            .clamp(0.0, 1.0);
+        // Fast snapshots are a bucket of the interval a cut is judged over
+        if stale_input.is_none() && !fast {
+            self.effectiveness.observe(
+                relieved_pressure(&components),
+                Duration::from_nanos(signals.interval_ns),
+            );
+        }
+        let cut_weight = self.cut_weight();
+
+        let policy = &self.policy;
+        let previous_rate = self.rate;
+        let held = match cadence {
+            Cadence::Both => false,
+            Cadence::Fast => self.last_fast_cut.is_some_and(|at| {
+                self.clock.now().saturating_duration_since(at) < policy.min_fast_cut_gap
+            }),
+            Cadence::Slow => std::mem::take(&mut self.fast_cut_since_slow),
+        };
+        let host_memory_pressure = policy.host_memory_pressure(signals);
+        let leeway = if held {
+            Leeway::Hold
+        } else {
+            leeway(
+                signals,
+                stale_input,
+                host_memory_pressure,
+                bufferbloat.is_some(),
+            )
+        };
+        let (action, rate) = match (
+            cadence,
+            self.decide(score, previous_rate, cut_weight, leeway),
+        ) {
+            (Cadence::Fast, (PacingAction::Increase, _)) => (PacingAction::Hold, previous_rate),
+            // Fast snapshots can't see bufferbloat, so that cut is left to here
+            (Cadence::Slow, (PacingAction::Cut, _)) if bufferbloat.is_none() => {
+                (PacingAction::Hold, previous_rate)
+            }
+            (_, (PacingAction::Cut, _)) if smooth => (PacingAction::Smooth, previous_rate),
+            (_, decided) => decided,
+        };
+        if fast && action == PacingAction::Cut {
+            self.last_fast_cut = Some(self.clock.now());
+            self.fast_cut_since_slow = true;
+        }
+        match action {
+            PacingAction::Cut => self.effectiveness.cut(),
+            PacingAction::Increase => self.effectiveness.raised(),
+            _ => {}
+        }
+        let mut softirq_cause = None;
+        // Fast snapshots carry no softirq time or connections
+        if stale_input.is_none() && !fast {
+            let load = LoadSample::of(signals);
+            if let (Some(previous), Some(current)) = (self.load, load) {
+                softirq_cause = previous.cause(&current);
+            }
+            self.load = load;
+        }
+        if !fast {
+            self.advisory = match stale_input {
+                Some(_) => EndpointAdvisory::default(),
+                None => EndpointAdvisory::from_signals(signals),
+            };
+        }
+
+        let decision = PacingDecision {
+            rate,
+            action,
+            score,
+            headroom: if stale_input.is_some() || fast {
+                HeadroomEstimate::default()
+            } else {
+                self.headroom.update(signals)
+            },
+            stale_input,
+            advisory: self.advisory,
+        };
+        self.rate = rate;
+        if fast && action != PacingAction::Cut {
+            return decision;
+        }
+        self.log.record(DecisionRecord {
+            // Aligned snapshots carry the boundary, which lines up across hosts
+            at: signals.aligned_end.unwrap_or_else(SystemTime::now),
+            policy: policy.name.clone(),
+            previous_rate,
+            decision,
+            components,
+            signals: signals.clone(),
+            softirq_cause,
+            fast,
+            host_memory_pressure,
+            bufferbloat,
+            burst,
+            effectiveness: self.effectiveness.effectiveness(),
+            receiver_overwhelmed: policy.receiver_overwhelmed(signals),
+        });
+        decision
+    }
+
+    /// `update` with the wmem component taken from `probes`, the sockets this
+    /// governor paces as they are right now (`CongestionCollector::probe_registered`),
+    /// instead of from sampled sends: the fullest one's `occupancy`, in place of
+    /// whichever pressure `wmem_source` picks. With no probes it's `update`
+    pub fn update_probed(
+        &mut self,
+        signals: &CongestionSignals,
+        probes: &[SocketStateSample],
+    ) -> PacingDecision {
+        let Some(occupancy) = probes.iter().map(|probe| probe.occupancy).reduce(f64::max) else {
+            return self.update(signals);
+        };
+        let mut probed = signals.clone();
+        probed.fixed = None;
+        match self.policy.wmem_source {
+            WmemSource::Udp => probed.udp_wmem_pressure = Some(occupancy),
+            WmemSource::Tcp => probed.tcp_wmem_pressure = Some(occupancy),
+            WmemSource::All => probed.avg_wmem_pressure = occupancy,
+        }
+        self.update(&probed)
+    }
+
+    /// `update` for the host, then a decision per traffic class from
+    /// `CongestionCollector::read_per_class`. A class is scored like the host
+    /// but with its own send buffer pressure in the wmem component, and a cut
+    /// is scaled by its `class_weights` entry, so a weight of 0.0 holds it while
+    /// the others are cut. Bufferbloat is the host's and counts for every class.
+    /// Each class's rate starts from the host's and is kept by name. Only the
+    /// host decision goes into the log
+    pub fn update_per_class(
+        &mut self,
+        signals: &CongestionSignals,
+        per_class: &HashMap<String, CongestionSignals>,
+    ) -> (PacingDecision, HashMap<String, PacingDecision>) {
+        let host_rate = self.rate;
+        let host = self.update(signals);
+        let host_memory = self.policy.host_memory_pressure(signals);
+        let bufferbloat = self.bloat.filter(|_| host.stale_input.is_none());
+        let leeway = leeway(
+            signals,
+            host.stale_input,
+            host_memory,
+            bufferbloat.is_some(),
+        );
+        let bloat_score = self
+            .policy
+            .bufferbloat_component(bufferbloat)
+            .contribution();
+        let mut decisions = HashMap::new();
+        for (name, class) in per_class {
+            // Host-wide everything else: drops and softirq time carry no class
+            let scored = CongestionSignals {
+                avg_wmem_pressure: class.avg_wmem_pressure,
+                udp_wmem_pressure: class.udp_wmem_pressure,
+                tcp_wmem_pressure: class.tcp_wmem_pressure,
+                fixed: None,
+                ..signals.clone()
+            };
+            let score = (self.policy.score(&scored) + bloat_score).clamp(0.0, 1.0);
+            let weight =
+                self.policy.class_weights.get(name).copied().unwrap_or(1.0) * self.cut_weight();
+            let previous_rate = *self.class_rates.get(name).unwrap_or(&host_rate);
+            let (action, rate) = match self.decide(score, previous_rate, weight, leeway) {
+                // The host's microburst is every class's
+                (PacingAction::Cut, _) if host.action == PacingAction::Smooth => {
+                    (PacingAction::Smooth, previous_rate)
+                }
+                decided => decided,
+            };
+            self.class_rates.insert(name.clone(), rate);
+            decisions.insert(
+                name.clone(),
+                PacingDecision {
+                    rate,
+                    action,
+                    score,
+                    ..host
+                },
+            );
+        }
+        (host, decisions)
+    }
+
+    /// Pacing rate of a traffic class as of the last `update_per_class`
+    pub fn class_rate(&self, class: &str) -> Option<u64> {
+        self.class_rates.get(class).copied()
+    }
+
+    /// Share of a cut taken: `EffectivenessConfig::backoff` while the
+    /// congestion is non-responsive
+    fn cut_weight(&self) -> f64 {
+        if self.effectiveness.non_responsive() {
+            self.policy.effectiveness.backoff
+        } else {
+            1.0
+        }
+    }
+
+    fn stale_input(&self, signals: &CongestionSignals) -> Option<Duration> {
+        self.policy.max_input_age.and_then(|max_age| {
+            signals
+                .age_at(self.clock.now())
+                .filter(|age| *age > max_age)
+        })
+    }
+
+    /// Action and rate from a score, with the cut scaled by `cut_weight`
+    fn decide(
+        &self,
+        score: f64,
+        previous_rate: u64,
+        cut_weight: f64,
+        leeway: Leeway,
+    ) -> (PacingAction, u64) {
+        let policy = &self.policy;
+        let (action, rate) = if leeway == Leeway::Hold {
+            (PacingAction::Hold, previous_rate)
+        } else if score >= policy.cut_threshold {
+            let cut_weight = match leeway {
+                Leeway::CutOnly => cut_weight * policy.kernel_paced_cut,
+                _ => cut_weight,
+            };
+            let cut = previous_rate as f64 * (1.0 - policy.max_cut * score * cut_weight);
+            (PacingAction::Cut, cut as u64)
+        } else if leeway == Leeway::Full && score <= policy.increase_threshold {
+            let raised = previous_rate as f64 * (1.0 + policy.increase_step);
+            (PacingAction::Increase, raised as u64)
+        } else {
+            (PacingAction::Hold, previous_rate)
+        };
+        let rate = rate.clamp(policy.min_rate, policy.max_rate);
+        // Pinned at a bound, or a class weighted out of cuts: report what
+        // actually happened
+        let action = match action {
+            PacingAction::Cut | PacingAction::Increase if rate == previous_rate => {
+                PacingAction::Hold
+            }
+            action => action,
+        };
+        (action, rate)
+    }
+
+    /// Up to `n` most recent decisions, oldest first
+    pub fn recent_decisions(&self, n: usize) -> Vec<&DecisionRecord> {
+        self.log.recent(n).collect()
+    }
+
+    pub fn decision_log(&self) -> &DecisionLog {
+        &self.log
+    }
+}
+
+impl GovernorPolicy {
+    /// The score `Governor::update` would act on, without a governor's state:
+    /// bufferbloat takes a window of intervals, so it isn't in it
+    pub fn score(&self, signals: &CongestionSignals) -> f64 {
+        self.score_components(signals)
+            .iter()
+            .map(ScoreComponent::contribution)
+            .sum::<f64>()
+            .clamp(0.0, 1.0)
+    }
+
+    /// `score` in per-mille, rounded half up. The weights are f64, so this is
+    /// the float score rounded, not an integer sum
+    pub fn score_permille(&self, signals: &CongestionSignals) -> u32 {
+        permille_from_f64(self.score(signals))
+    }
+
+    /// Whether `signals` show the host short of socket buffer memory:
+    /// `net_memory_pressure` at `net_memory_threshold` or TCP entering memory
+    /// pressure during the interval
+    pub fn host_memory_pressure(&self, signals: &CongestionSignals) -> bool {
+        signals
+            .net_memory_pressure
+            .is_some_and(|pressure| pressure >= self.net_memory_threshold)
+            || signals.memory_pressure_events.unwrap_or(0) > 0
+    }
+
+    /// Whether `signals` show our receivers falling behind rather than the
+    /// path: datagrams dropped on full rcvbufs, or `avg_rmem_pressure` at
+    /// `rmem_threshold`. Pacing our sends slower doesn't help a receiver, so
+    /// none of it is in the score
+    pub fn receiver_overwhelmed(&self, signals: &CongestionSignals) -> Option<ReceivePressure> {
+        let secs = signals.interval_ns as f64 / 1e9;
+        (signals.udp_rcv_drops > 0 || signals.avg_rmem_pressure >= self.rmem_threshold).then(|| {
+            ReceivePressure {
+                rcv_drops_per_sec: if secs > 0.0 {
+                    signals.udp_rcv_drops as f64 / secs
+                } else {
+                    0.0
+                },
+                rmem_pressure: signals.avg_rmem_pressure,
+            }
+        })
+    }
+
+    fn bufferbloat_component(&self, bufferbloat: Option<Bufferbloat>) -> ScoreComponent {
+        ScoreComponent {
+            name: "bufferbloat",
+            value: bufferbloat.map_or(0.0, |bloat| bloat.growth),
+            normalized: if bufferbloat.is_some() { 1.0 } else { 0.0 },
+            weight: self.bufferbloat_weight,
+        }
+    }
+
+    fn score_components(&self, signals: &CongestionSignals) -> Vec<ScoreComponent> {
+        let policy = self;
+        let secs = signals.interval_ns as f64 / 1e9;
+        let drops_per_sec = if secs > 0.0 {
+            signals.drops as f64 / secs
+        } else {
+            0.0
+        };
+        let drops_normalized = if policy.drops_full_scale > 0.0 {
+            drops_per_sec / policy.drops_full_scale
+        } else {
+            0.0
+        };
+
+        // Nothing sampled counts as no pressure
+        let wmem = match policy.wmem_source {
+            WmemSource::Udp => signals.udp_wmem_pressure.unwrap_or(0.0),
+            WmemSource::Tcp => signals.tcp_wmem_pressure.unwrap_or(0.0),
+            WmemSource::All => signals.avg_wmem_pressure,
+        };
+        // Summed over queues, so a multiqueue NIC can pass 1.0
+        let txq_stopped = match signals.txq_stalled_ns {
+            Some(ns) if signals.interval_ns > 0 => ns as f64 / signals.interval_ns as f64,
+            _ => 0.0,
+        };
+        // An RTO weighs rto_severity recoveries; probes that didn't attach count as none
+        let loss_episodes = signals.loss_recovery_episodes.unwrap_or(0) as f64
+            + signals.rto_events.unwrap_or(0) as f64 * policy.rto_severity;
+        let loss_per_sec = if secs > 0.0 {
+            loss_episodes / secs
+        } else {
+            0.0
+        };
+        let loss_normalized = if policy.loss_full_scale > 0.0 {
+            loss_per_sec / policy.loss_full_scale
+        } else {
+            0.0
+        };
+        // Marks on our receives plus TCP's reactions to them; unattached
+        // probes count as none
+        let ce =
+            signals.ce_marks.unwrap_or(0) as f64 + signals.ce_triggered_cwr.unwrap_or(0) as f64;
+        let ce_per_sec = if secs > 0.0 { ce / secs } else { 0.0 };
+        let ce_normalized = if policy.ce_full_scale > 0.0 {
+            ce_per_sec / policy.ce_full_scale
+        } else {
+            0.0
+        };
+        // Softirq work our own sends caused is the cost of sending, only the
+        // rest is contention. The attribution covers NET_TX, so its share of
+        // all softirq time, discounted by how much of it rests on sends seen,
+        // comes off the busiest CPU's fraction
+        let softirq = match signals.softirq_attribution {
+            Some(attribution) if signals.softirq_ns > 0 => {
+                let own = attribution.self_induced_softirq_ns as f64 / signals.softirq_ns as f64;
+                signals.softirq_cpu_fraction
+                    * (1.0 - own.clamp(0.0, 1.0) * attribution.confidence.clamp(0.0, 1.0))
+            }
+            _ => signals.softirq_cpu_fraction,
+        };
+
+        vec![
+            ScoreComponent {
+                name: "drops",
+                value: drops_per_sec,
+                normalized: drops_normalized.clamp(0.0, 1.0),
+                weight: policy.drops_weight,
+            },
+            ScoreComponent {
+                name: "wmem",
+                value: wmem,
+                normalized: wmem.clamp(0.0, 1.0),
+                weight: policy.wmem_weight,
+            },
+            ScoreComponent {
+                name: "softirq",
+                value: softirq,
+                normalized: softirq.clamp(0.0, 1.0),
+                weight: policy.softirq_weight,
+            },
+            ScoreComponent {
+                name: "txq",
+                value: txq_stopped,
+                normalized: txq_stopped.clamp(0.0, 1.0),
+                weight: policy.txq_weight,
+            },
+            ScoreComponent {
+                name: "tcp_loss",
+                value: loss_per_sec,
+                normalized: loss_normalized.clamp(0.0, 1.0),
+                weight: policy.loss_weight,
+            },
+            ScoreComponent {
+                name: "ecn_ce",
+                value: ce_per_sec,
+                normalized: ce_normalized.clamp(0.0, 1.0),
+                weight: policy.ce_weight,
+            },
+        ]
+    }
+}
+
+/// Which update a decision is made in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cadence {
+    /// `update`: cuts and raises
+    Both,
+    /// `update_fast`: cuts only
+    Fast,
+    /// `update_slow`: raises only
+    Slow,
+}
+
+/// What an interval lets the rate do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Leeway {
+    Full,
+    /// Host memory pressure: cut as usual, never raised
+    NoRaise,
+    /// Kernel-paced with loss: cut by `kernel_paced_cut` of the usual, never raised
+    CutOnly,
+    Hold,
+}
+
+fn leeway(
+    signals: &CongestionSignals,
+    stale_input: Option<Duration>,
+    host_memory_pressure: bool,
+    bufferbloat: bool,
+) -> Leeway {
+    // App-limited intervals say nothing about the path, like BBR's app-limited
+    // bandwidth samples; stale ones say nothing about now. Kernel-paced ones
+    // only say the kernel is governing already: don't fight it, but back off
+    // when it still loses packets, or queues them up without losing any
+    if stale_input.is_some() || signals.limitation == Limitation::AppLimited {
+        return Leeway::Hold;
+    }
+    if !signals.kernel_paced {
+        return if host_memory_pressure {
+            Leeway::NoRaise
+        } else {
+            Leeway::Full
+        };
+    }
+    let lossy = signals.drops > 0
+        || signals.rto_events.unwrap_or(0) > 0
+        || signals.loss_recovery_episodes.unwrap_or(0) > 0;
+    if lossy || bufferbloat {
+        Leeway::CutOnly
+    } else {
+        Leeway::Hold
+    }
+}
+
+/// What of the score a cut is meant to relieve: drops, send buffers, queues
+/// and the CE marks queues set
+fn relieved_pressure(components: &[ScoreComponent]) -> f64 {
+    components
+        .iter()
+        .filter(|c| matches!(c.name, "drops" | "wmem" | "txq" | "ecn_ce" | "bufferbloat"))
+        .map(ScoreComponent::contribution)
+        .sum()
+}
+
+fn describe_burst(burst: &BurstEpisode) -> String {
+    let share = burst.pressured_fraction * 100.0;
+    match burst.class {
+        BurstClass::Microburst => format!(
+            "microburst ({:.0} ms, {:.0}% of the interval)",
+            burst.longest.as_secs_f64() * 1e3,
+            share
+        ),
+        BurstClass::Sustained => format!("sustained ({:.0}% of the interval)", share),
+        BurstClass::Periodic { period } => format!(
+            "periodic bursts every {:.0} ms ({:.0}% of the interval)",
+            period.as_secs_f64() * 1e3,
+            share
+        ),
+    }
+}
+
+fn burst_json(burst: &BurstEpisode) -> String {
+    let period = match burst.class {
+        BurstClass::Periodic { period } => Some(period.as_millis()),
+        _ => None,
+    };
+    format!(
+        "{{\"class\":\"{}\",\"pressured_fraction\":{},\"bursts\":{},\"longest_ms\":{},\"period_ms\":{}}}",
+        burst.class.label(),
+        json_f64(burst.pressured_fraction),
+        burst.bursts,
+        burst.longest.as_millis(),
+        json_opt(period),
+    )
+}
+
+fn receive_json(receive: &ReceivePressure) -> String {
+    format!(
+        "{{\"rcv_drops_per_sec\":{},\"rmem_pressure\":{}}}",
+        json_f64(receive.rcv_drops_per_sec),
+        json_f64(receive.rmem_pressure),
+    )
+}
+
+fn bufferbloat_json(bloat: &Bufferbloat) -> String {
+    format!(
+        "{{\"backlog_bytes\":{},\"growth\":{},\"queue_delay_ms\":{},\"utilization\":{}}}",
+        bloat.backlog_bytes,
+        json_f64(bloat.growth),
+        json_opt(bloat.queue_delay.map(|delay| delay.as_millis())),
+        json_opt_f64(bloat.utilization),
+    )
+}
+
+/// bytes/sec as Mbps, whole numbers once it's big enough not to need decimals
+fn mbps(rate: u64) -> String {
+    let mbps = rate as f64 * 8.0 / 1e6;
+    if mbps >= 10.0 {
+        format!("{:.0}", mbps)
+    } else {
+        format!("{:.1}", mbps)
+    }
+}
+
+/// NaN when nothing was sampled, null in JSON
+fn avg_send_bytes(sizes: &Option<SendSizes>) -> f64 {
+    sizes.as_ref().map_or(f64::NAN, |sizes| sizes.avg_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::EstimatedTotals;
+
+    const RATE: u64 = 10_000_000;
+
+    fn relaxed(limitation: Limitation) -> CongestionSignals {
+        CongestionSignals::builder()
+            .interval_ns(1_000_000_000)
+            .send_bytes(50_000)
+            .external_send_bytes(50_000)
+            .limitation(limitation)
+            .build()
+    }
+
+    #[test]
+    fn app_limited_intervals_freeze_the_rate() {
+        let mut governor = Governor::new(GovernorPolicy::default(), RATE);
+        for _ in 0..5 {
+            let decision = governor.update(&CongestionSignals::idle());
+            assert_eq!(decision.action, PacingAction::Hold);
+            assert_eq!(decision.rate, RATE);
+        }
+        let record = governor.recent_decisions(1)[0];
+        assert_eq!(record.explain(), "rate held at 80 Mbps: app-limited");
+    }
+
+    #[test]
+    fn the_same_quiet_interval_raises_when_not_app_limited() {
+        let mut governor = Governor::new(GovernorPolicy::default(), RATE);
+        let decision = governor.update(&relaxed(Limitation::Unconstrained));
+        assert_eq!(decision.action, PacingAction::Increase);
+        assert!(decision.rate > RATE);
+
+        let mut governor = Governor::new(GovernorPolicy::default(), RATE);
+        let decision = governor.update(&relaxed(Limitation::AppLimited));
+        assert_eq!(decision.action, PacingAction::Hold);
+        assert_eq!(decision.rate, RATE);
+    }
+
+    #[test]
+    fn app_limited_intervals_are_not_cut_either() {
+        let mut congested = CongestionSignals::synthetic_congested();
+        let mut governor = Governor::new(GovernorPolicy::default(), RATE);
+        assert_eq!(governor.update(&congested).action, PacingAction::Cut);
+        let cut = governor.rate();
+
+        congested.limitation = Limitation::AppLimited;
+        let decision = governor.update(&congested);
+        assert_eq!(decision.action, PacingAction::Hold);
+        assert_eq!(decision.rate, cut);
+        // Scored all the same, only the action is frozen
+        assert!(decision.score >= governor.policy().cut_threshold);
+    }
+
+    #[test]
+    fn rate_changes_resume_after_an_app_limited_stretch() {
+        let mut governor = Governor::new(GovernorPolicy::default(), RATE);
+        governor.update(&CongestionSignals::idle());
+        governor.update(&CongestionSignals::idle());
+        let decision = governor.update(&relaxed(Limitation::Unconstrained));
+        assert_eq!(decision.action, PacingAction::Increase);
+    }
+
+    /// 340 drops and 87% of the UDP send buffers in one second
+    fn pressured() -> CongestionSignals {
+        CongestionSignals::builder()
+            .interval_ns(1_000_000_000)
+            .send_bytes(50_000)
+            .external_send_bytes(50_000)
+            .drops(340)
+            .udp_wmem_pressure(0.87)
+            .limitation(Limitation::NetworkLimited)
+            .build()
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedSink(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for SharedSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct BrokenSink;
+
+    impl Write for BrokenSink {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn a_cut_explains_its_components_largest_first() {
+        let mut governor = Governor::new(GovernorPolicy::default(), 15_000_000);
+        let decision = governor.update(&pressured());
+        assert_eq!(decision.action, PacingAction::Cut);
+
+        let record = governor.recent_decisions(1)[0];
+        assert_eq!(record.policy, "default");
+        assert_eq!(record.previous_rate, 15_000_000);
+        assert_eq!(record.decision.rate, decision.rate);
+        assert_eq!(record.signals.drops, 340);
+        let names: Vec<&str> = record.components.iter().map(|c| c.name).collect();
+        assert!(names.starts_with(&["drops", "wmem", "softirq", "txq", "tcp_loss", "ecn_ce"]));
+        assert_eq!(
+            record.explain(),
+            format!(
+                "rate cut 120→{} Mbps: drops 340/s (weight 0.5), wmem 87% (weight 0.3)",
+                mbps(decision.rate)
+            )
+        );
+    }
+
+    #[test]
+    fn quiet_and_raised_intervals_say_so() {
+        let mut governor = Governor::new(GovernorPolicy::default(), RATE);
+        governor.update(&relaxed(Limitation::Unconstrained));
+        let record = governor.recent_decisions(1)[0];
+        assert_eq!(record.explain(), "rate raised 80→84 Mbps: no pressure");
+
+        let stale = CongestionSignals::builder()
+            .interval_ns(1_000_000_000)
+            .produced_at(Instant::now() - Duration::from_secs(5))
+            .build();
+        governor.update(&stale);
+        let explain = governor.recent_decisions(1)[0].explain();
+        assert!(
+            explain.starts_with("rate held at 84 Mbps: input stale (5."),
+            "{}",
+            explain
+        );
+    }
+
+    #[test]
+    fn input_ages_on_the_governors_clock() {
+        let clock = ManualClock::new();
+        let mut governor =
+            Governor::new(GovernorPolicy::default(), RATE).with_clock(Arc::new(clock.clone()));
+        let signals = CongestionSignals::builder()
+            .interval_ns(1_000_000_000)
+            .drops(500)
+            .produced_at(clock.now())
+            .build();
+        // Five seconds on the clock, none on the system's: the drops are too old
+        // to cut for
+        clock.advance(Duration::from_secs(5));
+        let decision = governor.update(&signals);
+        assert_eq!(decision.action, PacingAction::Hold);
+        assert_eq!(decision.rate, RATE);
+        assert_eq!(decision.stale_input, Some(Duration::from_secs(5)));
+        assert_eq!(decision.headroom.confidence, 0.0);
+
+        let fresh = CongestionSignals {
+            produced_at: Some(clock.now()),
+            ..signals
+        };
+        let decision = governor.update(&fresh);
+        assert_eq!(decision.stale_input, None);
+        assert_eq!(decision.action, PacingAction::Cut);
+    }
+
+    #[test]
+    fn a_red_interval_cuts_bulk_and_holds_a_weighted_out_class() {
+        let policy = GovernorPolicy {
+            class_weights: HashMap::from([("interactive".to_string(), 0.0)]),
+            ..Default::default()
+        };
+        let mut governor = Governor::new(policy, RATE);
+        let signals = CongestionSignals::builder()
+            .interval_ns(1_000_000_000)
+            .drops(1000)
+            .build();
+        let class = |wmem: f64| CongestionSignals::builder().udp_wmem_pressure(wmem).build();
+        let per_class = HashMap::from([
+            ("interactive".to_string(), class(0.1)),
+            ("bulk".to_string(), class(0.9)),
+        ]);
+        let (host, decisions) = governor.update_per_class(&signals, &per_class);
+        assert_eq!(host.action, PacingAction::Cut);
+        let interactive = decisions["interactive"];
+        assert_eq!(
+            (interactive.action, interactive.rate),
+            (PacingAction::Hold, RATE)
+        );
+        let bulk = decisions["bulk"];
+        assert_eq!(bulk.action, PacingAction::Cut);
+        assert!(bulk.rate < RATE);
+    }
+
+    #[test]
+    fn kernel_paced_intervals_hold_without_loss_and_cut_gently_with_it() {
+        let paced = |drops| {
+            CongestionSignals::builder()
+                .interval_ns(1_000_000_000)
+                .external_send_bytes(10_000_000)
+                .drops(drops)
+                .kernel_paced(true)
+                .build()
+        };
+        let unpaced = crate::CongestionSignalsBuilder::from_signals(paced(1000))
+            .kernel_paced(false)
+            .build();
+        let quiet = Governor::new(GovernorPolicy::default(), RATE).update(&paced(0));
+        assert_eq!((quiet.action, quiet.rate), (PacingAction::Hold, RATE));
+        let gentle = Governor::new(GovernorPolicy::default(), RATE).update(&paced(1000));
+        let usual = Governor::new(GovernorPolicy::default(), RATE).update(&unpaced);
+        assert_eq!(gentle.action, PacingAction::Cut);
+        assert_eq!(usual.action, PacingAction::Cut);
+        assert!(RATE - gentle.rate < RATE - usual.rate);
+    }
+
+    #[test]
+    fn stopped_tx_queues_explain_as_nic_saturation() {
+        let mut governor = Governor::new(GovernorPolicy::default(), RATE);
+        let signals = CongestionSignals::builder()
+            .interval_ns(1_000_000_000)
+            .send_bytes(50_000)
+            .external_send_bytes(50_000)
+            .drops(500)
+            .txq_stalls(12)
+            .txq_stalled_ns(400_000_000)
+            .limitation(Limitation::NetworkLimited)
+            .build();
+        assert_eq!(governor.update(&signals).action, PacingAction::Cut);
+        let explain = governor.recent_decisions(1)[0].explain();
+        assert!(
+            explain.ends_with(
+                ": drops 500/s (weight 0.5), NIC saturation: TX queue stopped 40% (weight 0.1)"
+            ),
+            "{}",
+            explain
+        );
+    }
+
+    #[test]
+    fn the_ring_keeps_the_latest_decisions_oldest_first() {
+        let mut governor =
+            Governor::new(GovernorPolicy::default(), RATE).with_decision_log(DecisionLog::new(3));
+        governor.update(&pressured());
+        for _ in 0..3 {
+            governor.update(&relaxed(Limitation::Unconstrained));
+        }
+        assert_eq!(governor.decision_log().len(), 3);
+
+        let recent = governor.recent_decisions(10);
+        assert_eq!(recent.len(), 3);
+        assert!(recent
+            .iter()
+            .all(|record| record.decision.action == PacingAction::Increase));
+        assert!(recent
+            .windows(2)
+            .all(|pair| pair[0].decision.rate < pair[1].decision.rate));
+        assert_eq!(
+            governor.recent_decisions(1)[0].decision.rate,
+            governor.rate()
+        );
+
+        let mut governor =
+            Governor::new(GovernorPolicy::default(), RATE).with_decision_log(DecisionLog::new(0));
+        governor.update(&pressured());
+        assert!(governor.decision_log().is_empty());
+    }
+
+    #[test]
+    fn every_decision_is_appended_as_a_json_line() {
+        let sink = SharedSink::default();
+        let log = DecisionLog::new(8).with_json_lines(sink.clone());
+        let mut governor = Governor::new(GovernorPolicy::default(), RATE).with_decision_log(log);
+        governor.update(&pressured());
+        governor.update(&relaxed(Limitation::Unconstrained));
+
+        let written = String::from_utf8(sink.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("\"policy\":\"default\""));
+        assert!(lines[0].contains("\"action\":\"cut\""));
+        assert!(lines[0].contains("\"previous_rate\":10000000"));
+        assert!(lines[0].contains("\"drops\":340"));
+        assert!(lines[1].contains("\"action\":\"increase\""));
+        assert!(lines[1].contains("no pressure"));
+        assert_eq!(governor.decision_log().sink_errors(), 0);
+    }
+
+    #[test]
+    fn a_failing_sink_is_counted_and_the_ring_still_fills() {
+        let log = DecisionLog::new(8).with_json_lines(BrokenSink);
+        let mut governor = Governor::new(GovernorPolicy::default(), RATE).with_decision_log(log);
+        governor.update(&pressured());
+        governor.update(&pressured());
+        assert_eq!(governor.decision_log().sink_errors(), 2);
+        assert_eq!(governor.decision_log().len(), 2);
+    }
+
+    fn loss_component(signals: &CongestionSignals) -> f64 {
+        let mut governor = Governor::new(GovernorPolicy::default(), RATE);
+        governor.update(signals);
+        let record = governor.recent_decisions(1)[0];
+        record
+            .components
+            .iter()
+            .find(|c| c.name == "tcp_loss")
+            .unwrap()
+            .value
+    }
+
+    #[test]
+    fn an_rto_weighs_more_than_a_fast_recovery() {
+        let interval = CongestionSignals::builder().interval_ns(1_000_000_000);
+        let recovery = loss_component(&interval.clone().loss_recovery_episodes(1).build());
+        let rto = loss_component(&interval.clone().rto_events(1).build());
+        assert_eq!(recovery, 1.0);
+        assert_eq!(rto, GovernorPolicy::default().rto_severity);
+
+        // Probes that didn't attach score as no loss
+        assert_eq!(loss_component(&interval.build()), 0.0);
+    }
+
+    #[test]
+    fn ce_marks_alone_cut_less_than_as_much_loss() {
+        let policy = GovernorPolicy {
+            cut_threshold: 0.05,
+            ..Default::default()
+        };
+        let interval = CongestionSignals::builder().interval_ns(1_000_000_000);
+        let cut = |signals: CongestionSignals| {
+            let mut governor = Governor::new(policy.clone(), RATE);
+            let decision = governor.update(&signals);
+            assert_eq!(decision.action, PacingAction::Cut);
+            decision.rate
+        };
+        let marked = cut(interval.clone().ce_marks(6).ce_triggered_cwr(4).build());
+        let lossy = cut(interval.clone().loss_recovery_episodes(10).build());
+        assert!(lossy < marked && marked < RATE, "{} {}", lossy, marked);
+
+        // At the default thresholds marks on their own hold the rate, no more
+        let mut governor = Governor::new(GovernorPolicy::default(), RATE);
+        let decision = governor.update(&interval.ce_marks(40).build());
+        assert_eq!(decision.action, PacingAction::Hold);
+        assert!(governor.recent_decisions(1)[0]
+            .explain()
+            .contains("ECN CE 40.0 marks/s (weight 0.15)"));
+    }
+
+    fn churning(softirq: f64, send_packets: u64, new_connections: u64) -> CongestionSignals {
+        CongestionSignals::builder()
+            .interval_ns(1_000_000_000)
+            .softirq_cpu_fraction(softirq)
+            .estimated(EstimatedTotals {
+                send_packets,
+                ..Default::default()
+            })
+            .new_connections_per_interval(new_connections)
+            .closed_connections_per_interval(new_connections)
+            .build()
+    }
+
+    #[test]
+    fn softirq_rising_with_churn_is_blamed_on_churn() {
+        let script = [
+            churning(0.10, 10_000, 50),
+            // Connection storm at about the same packet rate
+            churning(0.45, 11_000, 4_000),
+            // The same churn, four times the packets
+            churning(0.80, 44_000, 4_000),
+            // Flat softirq
+            churning(0.80, 44_000, 4_000),
+        ];
+        let mut governor = Governor::new(GovernorPolicy::default(), 12_500_000);
+        for signals in &script {
+            governor.update(signals);
+        }
+        let records = governor.recent_decisions(script.len());
+        let causes: Vec<Option<SoftirqCause>> = records.iter().map(|r| r.softirq_cause).collect();
+        assert_eq!(
+            causes,
+            [
+                None,
+                Some(SoftirqCause::Churn),
+                Some(SoftirqCause::Throughput),
+                None
+            ]
+        );
+        assert!(records[1]
+            .explain()
+            .contains("from connection churn (4000 new/s)"));
+    }
+
+    #[test]
+    fn churn_growing_from_next_to_nothing_is_not_blamed() {
+        let trivial =
+            SoftirqCause::between(&churning(0.10, 10_000, 5), &churning(0.30, 10_000, 40));
+        assert_eq!(trivial, Some(SoftirqCause::Throughput));
+        let no_previous = SoftirqCause::between(
+            &CongestionSignals::default(),
+            &churning(0.45, 11_000, 4_000),
+        );
+        assert_eq!(no_previous, None);
+    }
+
+    #[test]
+    fn time_squeezes_are_blamed_as_receive_starvation() {
+        let squeezed = |softirq, squeezes| {
+            CongestionSignals::builder()
+                .interval_ns(1_000_000_000)
+                .softirq_cpu_fraction(softirq)
+                .rx_time_squeeze(squeezes)
+                .build()
+        };
+        let mut governor = Governor::new(GovernorPolicy::default(), RATE);
+        governor.update(&squeezed(0.6, 0));
+        // Flat softirq, but NET_RX kept running out of budget
+        governor.update(&squeezed(0.6, 250));
+        let record = governor.recent_decisions(1)[0];
+        assert_eq!(record.softirq_cause, Some(SoftirqCause::ReceiveStarvation));
+        assert_eq!(
+            record.explain(),
+            "rate raised 84→88 Mbps: softirq 60% (weight 0.1) from receive starvation (local), \
+             250 time squeezes/s"
+        );
+
+        // A few squeezes on a flat load say nothing
+        assert_eq!(
+            SoftirqCause::between(&squeezed(0.6, 0), &squeezed(0.6, 5)),
+            None
+        );
+    }
+
+    fn fast(drops: u64) -> FastSignals {
+        // Over 20 ms, so 20 drops is 1000/s
+        FastSignals {
+            interval_ns: 20_000_000,
+            drops,
+            ..Default::default()
+        }
+    }
+
+    fn dropping(drops: u64) -> CongestionSignals {
+        CongestionSignals::builder()
+            .interval_ns(1_000_000_000)
+            .drops(drops)
+            .build()
+    }
+
+    /// Fast snapshots cut but never raise and respect the cut gap, full
+    /// intervals raise but never cut and hold the first one after a fast cut
+    #[test]
+    fn fast_snapshots_cut_and_full_intervals_raise() {
+        let gap = Duration::from_millis(20);
+        let policy = GovernorPolicy {
+            min_fast_cut_gap: gap,
+            ..Default::default()
+        };
+        let clock = ManualClock::new();
+        let mut governor = Governor::new(policy, RATE).with_clock(Arc::new(clock.clone()));
+        let mut actions = vec![
+            governor.update_fast(&fast(20)).action,
+            // Within the gap
+            governor.update_fast(&fast(20)).action,
+            governor.update_fast(&fast(0)).action,
+            // Right after the cut, then quiet again
+            governor.update_slow(&dropping(0)).action,
+            governor.update_slow(&dropping(0)).action,
+            governor.update_slow(&dropping(1000)).action,
+        ];
+        clock.advance(gap);
+        actions.push(governor.update_fast(&fast(20)).action);
+
+        use PacingAction::{Cut, Hold, Increase};
+        assert_eq!(actions, [Cut, Hold, Hold, Hold, Increase, Hold, Cut]);
+        assert!(governor.rate() < RATE);
+    }
+
+    #[test]
+    fn only_fast_cuts_are_logged_from_the_fast_side() {
+        let mut governor = Governor::new(GovernorPolicy::default(), RATE);
+        governor.update_fast(&fast(20));
+        governor.update_fast(&fast(0));
+        governor.update_slow(&dropping(0));
+        governor.update_slow(&dropping(0));
+        let logged: Vec<bool> = governor
+            .recent_decisions(10)
+            .iter()
+            .map(|record| record.fast)
+            .collect();
+        assert_eq!(logged, [true, false, false]);
+        assert!(governor.recent_decisions(3)[0].explain().contains("(fast)"));
+    }
+
+    fn memory_pressured(
+        drops: u64,
+        wmem: f64,
+        pressure: Option<f64>,
+        events: Option<u64>,
+    ) -> CongestionSignals {
+        CongestionSignals::builder()
+            .interval_ns(1_000_000_000)
+            .drops(drops)
+            .udp_wmem_pressure(wmem)
+            .net_memory_pressure(pressure)
+            .memory_pressure_events(events)
+            .build()
+    }
+
+    /// An interval that would have raised holds, its full send buffers are
+    /// explained as the host's, and a cut still cuts
+    #[test]
+    fn host_memory_pressure_holds_a_raise_and_explains_a_cut() {
+        let mut governor = Governor::new(GovernorPolicy::default(), 12_500_000);
+        let raised = governor.update(&memory_pressured(0, 0.0, Some(0.4), Some(0)));
+        let held = governor.update(&memory_pressured(0, 0.1, Some(0.95), Some(0)));
+        let cut = governor.update(&memory_pressured(400, 0.9, Some(1.1), Some(3)));
+        assert_eq!(raised.action, PacingAction::Increase);
+        assert_eq!(held.action, PacingAction::Hold);
+        assert_eq!(cut.action, PacingAction::Cut);
+
+        let records = governor.recent_decisions(3);
+        let flags: Vec<bool> = records.iter().map(|r| r.host_memory_pressure).collect();
+        assert_eq!(flags, [false, true, true]);
+        assert!(!records[0].explain().contains("host memory"));
+        assert!(records[1]
+            .explain()
+            .contains("host memory pressure, socket buffers at 95% of tcp/udp_mem"));
+        let cut_why = records[2].explain();
+        assert!(
+            cut_why.contains("wmem 90% (weight 0.3) from host memory"),
+            "{}",
+            cut_why
+        );
+        assert!(cut_why.contains("TCP entered it 3 times"), "{}", cut_why);
+        assert!(records[2]
+            .to_json()
+            .contains("\"host_memory_pressure\":true"));
+    }
+
+    #[test]
+    fn tcp_entering_pressure_counts_on_its_own() {
+        let policy = GovernorPolicy::default();
+        assert!(policy.host_memory_pressure(&memory_pressured(0, 0.0, Some(0.2), Some(1))));
+        assert!(!policy.host_memory_pressure(&memory_pressured(0, 0.0, None, None)));
+    }
+
+    #[test]
+    fn an_overwhelmed_receiver_is_told_from_the_path_and_not_cut_for() {
+        let receiving = CongestionSignals::builder()
+            .interval_ns(1_000_000_000)
+            .send_bytes(50_000)
+            .external_send_bytes(50_000)
+            .udp_rcv_drops(340)
+            .avg_rmem_pressure(0.95)
+            .build();
+        let mut governor = Governor::new(GovernorPolicy::default(), RATE);
+        let decision = governor.update(&receiving);
+        assert_ne!(decision.action, PacingAction::Cut);
+        assert_eq!(decision.rate, RATE * 105 / 100);
+        let record = governor.recent_decisions(1)[0];
+        assert_eq!(
+            record.receiver_overwhelmed,
+            Some(ReceivePressure {
+                rcv_drops_per_sec: 340.0,
+                rmem_pressure: 0.95,
+            })
+        );
+        assert_eq!(
+            record.explain(),
+            "rate raised 80→84 Mbps: receiver overwhelmed: 340 rcv drops/s, rmem 95% (not cut for)"
+        );
+
+        // The same count dropped on the way out is the path's, and cut for
+        let mut governor = Governor::new(GovernorPolicy::default(), RATE);
+        let sending = CongestionSignals::builder()
+            .interval_ns(1_000_000_000)
+            .drops(340)
+            .build();
+        assert_eq!(governor.update(&sending).action, PacingAction::Cut);
+        assert_eq!(governor.recent_decisions(1)[0].receiver_overwhelmed, None);
+
+        // Full rcvbufs alone count, below the threshold they don't
+        let policy = GovernorPolicy::default();
+        let filling = |rmem| {
+            CongestionSignals::builder()
+                .interval_ns(1_000_000_000)
+                .avg_rmem_pressure(rmem)
+                .build()
+        };
+        assert!(policy.receiver_overwhelmed(&filling(0.8)).is_some());
+        assert!(policy.receiver_overwhelmed(&filling(0.5)).is_none());
+    }
+
+    /// Receive pressure scripted up and back down: each decision carries its
+    /// interval's advice, fast decisions the interval before's, stale ones the
+    /// defaults
+    #[test]
+    fn decisions_carry_the_advisory_for_their_receive_side() {
+        let receiving = |rmem, softirq, rcv_drops| {
+            CongestionSignals::builder()
+                .interval_ns(1_000_000_000)
+                .avg_rmem_pressure(rmem)
+                .softirq_cpu_fraction(softirq)
+                .udp_rcv_drops(rcv_drops)
+                .build()
+        };
+        let mut governor = Governor::new(GovernorPolicy::default(), RATE);
+        let relaxed = governor.update(&receiving(0.3, 0.2, 0)).advisory;
+        assert_eq!(relaxed, EndpointAdvisory::default());
+
+        let busy = governor.update(&receiving(0.3, 0.75, 0)).advisory;
+        assert!(busy.suggested_ack_delay > relaxed.suggested_ack_delay);
+        assert!(busy.suggested_max_ack_batch > relaxed.suggested_max_ack_batch);
+        assert_eq!(busy.receive_window_scale_hint, 1.0);
+        assert_eq!(governor.update_fast(&fast(0)).advisory, busy);
+
+        let overrun = governor.update(&receiving(0.95, 0.75, 40)).advisory;
+        assert_eq!(overrun.suggested_ack_delay, Duration::from_micros(92_500));
+        assert_eq!(overrun.receive_window_scale_hint, 0.5);
+        assert_eq!(
+            overrun,
+            EndpointAdvisory::from_signals(&receiving(0.95, 0.75, 40))
+        );
+
+        let stale = CongestionSignals::builder()
+            .avg_rmem_pressure(0.95)
+            .produced_at(Instant::now() - Duration::from_secs(5))
+            .build();
+        assert_eq!(
+            governor.update(&stale).advisory,
+            EndpointAdvisory::default()
+        );
+        assert_eq!(
+            governor.update(&receiving(0.3, 0.2, 0)).advisory,
+            EndpointAdvisory::default()
+        );
+    }
+
+    fn bufferbloat_decisions(drops: u64) -> Vec<DecisionRecord> {
+        let policy = GovernorPolicy {
+            bufferbloat: crate::BufferbloatConfig {
+                link_rate: Some(12_500_000),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut governor = Governor::new(policy, RATE);
+        // 100 KB growing 80 KB a second, the link at 92%
+        for i in 0..8 {
+            governor.update(&crate::fixtures::backlogged(
+                100_000 + i * 80_000,
+                drops,
+                11_500_000,
+                None,
+            ));
+        }
+        governor.recent_decisions(8).into_iter().cloned().collect()
+    }
+
+    fn first_cut(records: &[DecisionRecord]) -> Option<usize> {
+        records
+            .iter()
+            .position(|record| record.decision.action == PacingAction::Cut)
+    }
+
+    #[test]
+    fn bufferbloat_is_cut_on_and_explained() {
+        let records = bufferbloat_decisions(0);
+        let window = crate::BufferbloatConfig::default().window;
+        assert_eq!(first_cut(&records), Some(window - 1));
+        assert!(records[..window - 1]
+            .iter()
+            .all(|r| r.bufferbloat.is_none()));
+        let bloated = &records[window - 1];
+        assert!(bloated
+            .explain()
+            .contains("bufferbloat: backlog up 4.2x without drops"));
+        assert!(bloated
+            .to_json()
+            .contains("\"bufferbloat\":{\"backlog_bytes\":420000"));
+    }
+
+    #[test]
+    fn a_growing_queue_with_drops_is_cut_for_the_drops() {
+        let records = bufferbloat_decisions(400);
+        assert_eq!(first_cut(&records), Some(0));
+        assert!(records[0].explain().contains("drops 400/s"));
+        assert!(records.iter().all(|r| r.bufferbloat.is_none()));
+        assert!(!records.iter().any(|r| r.explain().contains("bufferbloat")));
+    }
+
+    fn after_buckets(buckets: &[FastSignals]) -> (PacingDecision, DecisionRecord) {
+        let mut governor = Governor::new(GovernorPolicy::default(), RATE);
+        for fast in buckets {
+            governor.observe_fast(fast);
+        }
+        let decision = governor.update(&crate::fixtures::bucket_drops(buckets));
+        (decision, governor.recent_decisions(1)[0].clone())
+    }
+
+    #[test]
+    fn a_microburst_is_smoothed_and_sustained_drops_are_cut() {
+        use crate::fixtures::burst_buckets;
+        let (micro, record) = after_buckets(&burst_buckets(|i| if i == 25 { 400 } else { 0 }));
+        assert_eq!(micro.action, PacingAction::Smooth);
+        assert_eq!(micro.rate, RATE);
+        assert!(
+            record.explain().contains("smoothed"),
+            "{}",
+            record.explain()
+        );
+        assert!(
+            record
+                .explain()
+                .contains("microburst (20 ms, 2% of the interval)"),
+            "{}",
+            record.explain()
+        );
+        assert!(record
+            .to_json()
+            .contains("\"burst\":{\"class\":\"microburst\""));
+
+        let (sustained, _) = after_buckets(&burst_buckets(|i| if i < 40 { 10 } else { 0 }));
+        assert_eq!(sustained.action, PacingAction::Cut);
+        assert!(sustained.rate < RATE);
+    }
+
+    #[test]
+    fn periodic_bursts_are_cut_and_recorded_with_their_period() {
+        let buckets = crate::fixtures::burst_buckets(|i| if i % 10 < 2 { 40 } else { 0 });
+        let (periodic, record) = after_buckets(&buckets);
+        assert_eq!(periodic.action, PacingAction::Cut);
+        assert!(record
+            .to_json()
+            .contains("\"bursts\":5,\"longest_ms\":40,\"period_ms\":200"));
+    }
+
+    /// 40 intervals of 200 ms with `drops(rate)` in each: the effectiveness,
+    /// the share of the rate each cut took, whether the state ever said
+    /// non-responsive, and the last record
+    fn closed_loop(drops: impl Fn(u64) -> u64) -> (Effectiveness, Vec<f64>, bool, DecisionRecord) {
+        let mut governor = Governor::new(GovernorPolicy::default(), 12_500_000);
+        let mut classifier = crate::SeverityClassifier::default();
+        let mut cuts = Vec::new();
+        let mut flagged = false;
+        for _ in 0..40 {
+            let rate = governor.rate();
+            let signals = CongestionSignals::builder()
+                .interval_ns(200_000_000)
+                .send_bytes(rate / 5)
+                .drops(drops(rate))
+                .build();
+            let decision = governor.update(&signals);
+            if decision.action == PacingAction::Cut {
+                cuts.push(1.0 - decision.rate as f64 / rate as f64);
+            }
+            classifier.observe_effectiveness(&governor.effectiveness());
+            let state = classifier.update(&signals);
+            flagged |= state
+                .reasons()
+                .iter()
+                .any(|reason| matches!(reason, crate::Reason::NonResponsive { .. }));
+        }
+        let record = governor.recent_decisions(1)[0].clone();
+        (governor.effectiveness(), cuts, flagged, record)
+    }
+
+    /// Drops from our own excess over a 10 MB/s bottleneck, one per 1000
+    /// bytes/sec over it: every cut relieves them within an interval
+    #[test]
+    fn cuts_that_relieve_our_own_excess_stay_responsive() {
+        let (effectiveness, cuts, flagged, _) =
+            closed_loop(|rate| (rate.saturating_sub(10_000_000)) / 1000);
+        assert!(effectiveness.judged >= 3, "{:?}", effectiveness);
+        assert_eq!(effectiveness.improved_fraction, Some(1.0));
+        assert_eq!(
+            effectiveness.median_time_to_improvement,
+            Some(Duration::from_millis(200))
+        );
+        assert!(!effectiveness.non_responsive && !flagged);
+        assert!(
+            cuts.iter().all(|&cut| (cut - 0.25).abs() < 1e-3),
+            "{:?}",
+            cuts
+        );
+    }
+
+    /// The same drops whatever the rate, as cross traffic's would be
+    #[test]
+    fn cross_traffic_drops_back_the_cuts_off_to_half() {
+        let (effectiveness, cuts, flagged, record) = closed_loop(|_| 200);
+        assert!(effectiveness.non_responsive && flagged);
+        assert_eq!(effectiveness.improved, 0);
+        assert!(effectiveness.judged >= 5);
+        assert!((cuts[0] - 0.25).abs() < 1e-3);
+        assert!((cuts[12] - 0.125).abs() < 1e-3, "{:?}", cuts);
+        assert!(
+            record
+                .explain()
+                .contains("non-responsive congestion (0% of cuts helped)"),
+            "{}",
+            record.explain()
+        );
+        assert!(record.to_json().contains("\"non_responsive\":true}"));
+    }
+
+    #[test]
+    fn the_per_mille_score_is_the_score_rounded() {
+        let signals = CongestionSignals::builder()
+            .interval_ns(200_000_000)
+            .avg_wmem_pressure(0.4375)
+            .tcp_wmem_pressure(0.9)
+            .softirq_cpu_fraction(0.25)
+            .build();
+        let policy = GovernorPolicy::default();
+        assert_eq!(
+            policy.score_permille(&signals),
+            (policy.score(&signals) * 1000.0).round() as u32
+        );
+    }
+
+    /// The softirq component counts the external NET_TX time only: softirq
+    /// time our sends caused scores below the same time caused by others,
+    /// which scores as if there were no attribution
+    #[test]
+    fn self_induced_softirq_time_scores_lower() {
+        let unattributed = CongestionSignals::builder()
+            .interval_ns(1_000_000_000)
+            .softirq_ns(400_000_000)
+            .softirq_cpu_fraction(0.4)
+            .build();
+        let split = |self_induced_ns: u64, confidence: f64| {
+            crate::CongestionSignalsBuilder::from_signals(unattributed.clone())
+                .softirq_attribution(crate::SoftirqAttribution {
+                    self_induced_softirq_ns: self_induced_ns,
+                    external_softirq_ns: 400_000_000 - self_induced_ns,
+                    rounds: 1000,
+                    covered_rounds: 1000,
+                    confidence,
+                })
+                .build()
+        };
+        let policy = GovernorPolicy::default();
+        let without = policy.score(&unattributed);
+        let ours = policy.score(&split(400_000_000, 1.0));
+        let half = policy.score(&split(200_000_000, 1.0));
+        let unsure = policy.score(&split(400_000_000, 0.25));
+        let external = policy.score(&split(0, 1.0));
+        assert!(without > 0.0);
+        assert_eq!(ours, 0.0);
+        assert!(ours < half && half < unsure && unsure < external);
+        assert!((external - without).abs() < 1e-12);
+    }
+}
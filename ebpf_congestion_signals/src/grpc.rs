@@ -0,0 +1,877 @@
+//gRPC streaming for control planes that subscribe to hosts rather than scrape
+//them. `Subscribe` sends a Snapshot per interval: the read itself, the busiest
+//sockets, per-interface egress and TX queue stalls, and the severity state and
+//governor score of the stream so far. Each subscription picks its interval and
+//a field mask; masked-out fields are pruned on the encoded message, so the
+//mask works at any depth without a per-field list here. `GetHealth` is
+//`health()`. TLS and a shared secret in the `authorization` metadata are both
+//optional, see GrpcConfig.
+//
+//What's served comes from a SnapshotSource, `CongestionCollector` or anything
+//standing in for one, such as ScriptedSource in a consumer's tests.
+
+// tonic's Status is every handler's error type, large or not
+#![allow(clippy::result_large_err)]
+
+use crate::json::unix_ms;
+use crate::{
+    CongestionCollector, CongestionSignals, CongestionState, GovernorPolicy, HealthReport,
+    ProtocolCounts, Reason, SeverityClassifier, SeverityThresholds, SocketSignals,
+};
+use futures_core::Stream;
+use futures_util::StreamExt;
+use prost::bytes::Buf;
+use prost::encoding::{decode_varint, encode_varint};
+use prost::Message;
+use prost_types::{field_descriptor_proto, DescriptorProto, FileDescriptorSet};
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+use tonic::{Request, Response, Status};
+
+/// Generated from proto/signals.proto: messages, `signals_server` and the
+/// `signals_client::SignalsClient` to subscribe with
+pub mod proto {
+    tonic::include_proto!("congestion_signals.v1");
+
+    pub(crate) const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("signals_descriptor");
+}
+
+use proto::signals_server::{Signals, SignalsServer};
+
+const SNAPSHOT_MESSAGE: &str = ".congestion_signals.v1.Snapshot";
+
+/// Interval snapshots every `interval`, see [`SnapshotSource::snapshots`]
+pub type SnapshotStream = Pin<Box<dyn Stream<Item = CongestionSignals> + Send>>;
+
+/// What [`GrpcServer`] serves
+pub trait SnapshotSource: Send + Sync + 'static {
+    /// One interval read every `interval`, for as long as the stream is held
+    fn snapshots(&self, interval: Duration) -> SnapshotStream;
+    /// Per-socket signals to go with the latest snapshot
+    fn per_socket(&self) -> HashMap<u64, SocketSignals>;
+    fn health(&self) -> HealthReport;
+}
+
+/// Subscriptions join the collector's snapshot publisher, see
+/// `CongestionCollector::snapshots`. Per-socket reads are `read_per_socket`'s,
+/// which report a closed TCP socket once: with sockets in a subscription's
+/// mask, a caller of its own may miss some closes
+impl SnapshotSource for CongestionCollector {
+    fn snapshots(&self, interval: Duration) -> SnapshotStream {
+        Box::pin(CongestionCollector::snapshots(self, interval))
+    }
+
+    fn per_socket(&self) -> HashMap<u64, SocketSignals> {
+        self.read_per_socket()
+    }
+
+    fn health(&self) -> HealthReport {
+        CongestionCollector::health(self)
+    }
+}
+
+/// A SnapshotSource that plays back scripted intervals, for testing what
+/// consumes one without a kernel. Intervals repeat from the first once the
+/// script runs out; sockets and health are fixed
+///
+/// ```
+/// use ebpf_congestion_signals::{CongestionSignals, ScriptedSource};
+///
+/// let source = ScriptedSource::new([
+///     CongestionSignals::idle(),
+///     CongestionSignals::builder().drops(1000).build(),
+/// ]);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedSource {
+    intervals: Arc<[CongestionSignals]>,
+    sockets: HashMap<u64, SocketSignals>,
+    health: HealthReport,
+}
+
+impl ScriptedSource {
+    /// An empty script streams nothing
+    pub fn new(intervals: impl IntoIterator<Item = CongestionSignals>) -> Self {
+        Self {
+            intervals: intervals.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    /// What `per_socket` returns
+    pub fn with_sockets(mut self, sockets: HashMap<u64, SocketSignals>) -> Self {
+        self.sockets = sockets;
+        self
+    }
+
+    /// What `health` returns
+    pub fn with_health(mut self, health: HealthReport) -> Self {
+        self.health = health;
+        self
+    }
+}
+
+impl SnapshotSource for ScriptedSource {
+    fn snapshots(&self, interval: Duration) -> SnapshotStream {
+        let intervals = self.intervals.clone();
+        Box::pin(futures_util::stream::unfold(0usize, move |n| {
+            let intervals = intervals.clone();
+            async move {
+                let signals = intervals.get(n % intervals.len().max(1))?.clone();
+                tokio::time::sleep(interval).await;
+                Some((signals, n + 1))
+            }
+        }))
+    }
+
+    fn per_socket(&self) -> HashMap<u64, SocketSignals> {
+        self.sockets.clone()
+    }
+
+    fn health(&self) -> HealthReport {
+        self.health.clone()
+    }
+}
+
+/// Server certificate and key, PEM, and optionally the CA client certificates
+/// must chain to
+#[derive(Clone)]
+pub struct GrpcTls {
+    pub cert_pem: Vec<u8>,
+    pub key_pem: Vec<u8>,
+    pub client_ca_pem: Option<Vec<u8>>,
+}
+
+impl GrpcTls {
+    fn server_config(&self) -> ServerTlsConfig {
+        let config =
+            ServerTlsConfig::new().identity(Identity::from_pem(&self.cert_pem, &self.key_pem));
+        match &self.client_ca_pem {
+            Some(ca) => config.client_ca_root(Certificate::from_pem(ca)),
+            None => config,
+        }
+    }
+}
+
+/// How [`GrpcServer`] listens and what it sends
+#[derive(Clone)]
+pub struct GrpcConfig {
+    pub addr: SocketAddr,
+    /// For subscriptions that ask for interval 0
+    pub default_interval: Duration,
+    /// Shorter intervals are raised to this
+    pub min_interval: Duration,
+    /// Sockets per snapshot, the most sending first
+    pub max_sockets: usize,
+    /// What each subscription's state is judged against
+    pub thresholds: SeverityThresholds,
+    /// What its score is computed with
+    pub policy: GovernorPolicy,
+    /// None serves plaintext
+    pub tls: Option<GrpcTls>,
+    /// Required as `authorization: Bearer <secret>` on every call when set
+    pub shared_secret: Option<String>,
+}
+
+impl GrpcConfig {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            default_interval: Duration::from_secs(1),
+            min_interval: Duration::from_millis(100),
+            max_sockets: 256,
+            thresholds: SeverityThresholds::default(),
+            policy: GovernorPolicy::default(),
+            tls: None,
+            shared_secret: None,
+        }
+    }
+
+    pub fn with_tls(mut self, cert_pem: impl Into<Vec<u8>>, key_pem: impl Into<Vec<u8>>) -> Self {
+        self.tls = Some(GrpcTls {
+            cert_pem: cert_pem.into(),
+            key_pem: key_pem.into(),
+            client_ca_pem: None,
+        });
+        self
+    }
+
+    /// Ask clients for certificates chaining to `ca_pem`. Needs `with_tls`
+    pub fn with_client_ca(mut self, ca_pem: impl Into<Vec<u8>>) -> Self {
+        if let Some(tls) = &mut self.tls {
+            tls.client_ca_pem = Some(ca_pem.into());
+        }
+        self
+    }
+
+    pub fn with_shared_secret(mut self, secret: impl Into<String>) -> Self {
+        self.shared_secret = Some(secret.into());
+        self
+    }
+
+    fn interval(&self, interval_ms: u32) -> Duration {
+        match interval_ms {
+            0 => self.default_interval,
+            ms => Duration::from_millis(ms as u64),
+        }
+        .max(self.min_interval)
+    }
+}
+
+/// `Signals` over gRPC, see proto/signals.proto
+pub struct GrpcServer<S> {
+    source: Arc<S>,
+    config: GrpcConfig,
+}
+
+impl<S: SnapshotSource> GrpcServer<S> {
+    pub fn new(source: Arc<S>, config: GrpcConfig) -> Self {
+        Self { source, config }
+    }
+
+    /// Serve on `config.addr` until `shutdown` completes
+    pub async fn serve(self, shutdown: impl Future<Output = ()>) -> anyhow::Result<()> {
+        let listener = tokio::net::TcpListener::bind(self.config.addr).await?;
+        self.serve_on(listener, shutdown).await
+    }
+
+    /// Serve on a listener already bound, e.g. to port 0
+    pub async fn serve_on(
+        self,
+        listener: tokio::net::TcpListener,
+        shutdown: impl Future<Output = ()>,
+    ) -> anyhow::Result<()> {
+        let incoming = TcpIncoming::from_listener(listener, true, None)
+            .map_err(|e| anyhow::anyhow!("gRPC listener: {}", e))?;
+        let mut builder = Server::builder();
+        if let Some(tls) = &self.config.tls {
+            builder = builder.tls_config(tls.server_config())?;
+        }
+        let service = Service {
+            source: self.source,
+            mask_root: MaskRoot::load()?,
+            config: self.config,
+        };
+        builder
+            .add_service(SignalsServer::new(service))
+            .serve_with_incoming_shutdown(incoming, shutdown)
+            .await?;
+        Ok(())
+    }
+}
+
+struct Service<S> {
+    source: Arc<S>,
+    config: GrpcConfig,
+    mask_root: MaskRoot,
+}
+
+impl<S> Service<S> {
+    fn authorize<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let Some(secret) = &self.config.shared_secret else {
+            return Ok(());
+        };
+        let expected = format!("Bearer {}", secret);
+        let given = request
+            .metadata()
+            .get("authorization")
+            .map(|value| value.as_bytes())
+            .unwrap_or_default();
+        // Same time whichever byte differs
+        let matches = given.len() == expected.len()
+            && given
+                .iter()
+                .zip(expected.as_bytes())
+                .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+                == 0;
+        if matches {
+            Ok(())
+        } else {
+            Err(Status::unauthenticated("missing or wrong shared secret"))
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl<S: SnapshotSource> Signals for Service<S> {
+    type SubscribeStream = Pin<Box<dyn Stream<Item = Result<proto::Snapshot, Status>> + Send>>;
+
+    async fn subscribe(
+        &self,
+        request: Request<proto::SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        self.authorize(&request)?;
+        let request = request.into_inner();
+        let interval = self.config.interval(request.interval_ms);
+        let mask = match request.field_mask {
+            Some(mask) if !mask.paths.is_empty() => Some(
+                self.mask_root
+                    .resolve(&mask.paths)
+                    .map_err(Status::invalid_argument)?,
+            ),
+            _ => None,
+        };
+        let wants_sockets = mask.as_ref().is_none_or(|mask| mask.keeps(4));
+
+        let source = self.source.clone();
+        let max_sockets = self.config.max_sockets;
+        let policy = self.config.policy.clone();
+        let mut classifier = SeverityClassifier::new(self.config.thresholds.clone());
+        let stream = self.source.snapshots(interval).map(move |signals| {
+            let state = classifier.update(&signals);
+            let sockets = if wants_sockets {
+                busiest_sockets(source.per_socket(), max_sockets)
+            } else {
+                Vec::new()
+            };
+            let snapshot = proto::Snapshot {
+                produced_at_unix_ms: unix_ms(SystemTime::now()) as u64,
+                interval_ms: interval.as_millis() as u32,
+                signals: Some(proto::CongestionSignals::from(&signals)),
+                sockets,
+                interfaces: interfaces(&signals),
+                state: Some(proto::CongestionState::from(&state)),
+                score: policy.score(&signals),
+            };
+            match &mask {
+                Some(mask) => mask.apply(&snapshot),
+                None => Ok(snapshot),
+            }
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn get_health(
+        &self,
+        request: Request<proto::GetHealthRequest>,
+    ) -> Result<Response<proto::Health>, Status> {
+        self.authorize(&request)?;
+        let report = self.source.health();
+        let heartbeat = report.heartbeat;
+        Ok(Response::new(proto::Health {
+            healthy: report.is_healthy(),
+            warnings: report.warnings,
+            last_seen_age_secs: report.last_seen_age_secs,
+            reader_restarts: report.reader_restarts,
+            component_failures: report.component_failures,
+            restored_from_state: report.restored_from_state,
+            pipeline_alive: heartbeat.map(|status| status.pipeline_alive),
+            heartbeat_round_trip_us: heartbeat
+                .and_then(|status| status.last_round_trip)
+                .map(|round_trip| round_trip.as_micros() as u64),
+        }))
+    }
+}
+
+fn busiest_sockets(sockets: HashMap<u64, SocketSignals>, max: usize) -> Vec<proto::SocketSnapshot> {
+    let mut sockets: Vec<(u64, SocketSignals)> = sockets.into_iter().collect();
+    sockets.sort_by(|a, b| b.1.send_bytes.cmp(&a.1.send_bytes).then(a.0.cmp(&b.0)));
+    sockets.truncate(max);
+    sockets
+        .into_iter()
+        .map(|(socket_id, s)| proto::SocketSnapshot {
+            socket_id,
+            is_tcp: s.is_tcp,
+            first_seen_ns: s.first_seen_ns,
+            last_seen_ns: s.last_seen_ns,
+            send_bytes: s.send_bytes,
+            loopback_send_bytes: s.loopback_send_bytes,
+            udp_rcv_drops: s.udp_rcv_drops,
+            ce_marks: s.ce_marks,
+            rmem_pressure: s.rmem_pressure,
+            wmem_pressure: s.wmem_pressure,
+            tcp_cwnd: s.tcp_cwnd,
+            tcp_ssthresh: s.tcp_ssthresh,
+            tcp_cwr: s.tcp_cwr,
+            pacing_rate: s.pacing_rate,
+            max_pacing_rate: s.max_pacing_rate,
+            send_rate: s.send_rate,
+            kernel_paced: s.kernel_paced,
+            rto_events: s.rto_events,
+            loss_recovery_episodes: s.loss_recovery_episodes,
+            closed: s.closed,
+        })
+        .collect()
+}
+
+/// Exact egress and TX queue stalls joined by interface name
+fn interfaces(signals: &CongestionSignals) -> Vec<proto::InterfaceSnapshot> {
+    let mut interfaces: Vec<proto::InterfaceSnapshot> = signals
+        .egress
+        .iter()
+        .map(|egress| proto::InterfaceSnapshot {
+            interface: egress.interface.clone(),
+            egress_bytes_exact: Some(egress.egress_bytes_exact),
+            egress_packets_exact: Some(egress.egress_packets_exact),
+            ..Default::default()
+        })
+        .collect();
+    for txq in &signals.txq_by_interface {
+        let index = match interfaces.iter().position(|i| i.interface == txq.interface) {
+            Some(index) => index,
+            None => {
+                interfaces.push(proto::InterfaceSnapshot {
+                    interface: txq.interface.clone(),
+                    ..Default::default()
+                });
+                interfaces.len() - 1
+            }
+        };
+        let interface = &mut interfaces[index];
+        // The same name in two namespaces adds up
+        interface.txq_stalls = Some(interface.txq_stalls.unwrap_or(0) + txq.txq_stalls);
+        interface.txq_stalled_ns = Some(interface.txq_stalled_ns.unwrap_or(0) + txq.txq_stalled_ns);
+        interface.xmit_busy = Some(interface.xmit_busy.unwrap_or(0) + txq.xmit_busy);
+    }
+    interfaces
+}
+
+fn protocol_counts(counts: &ProtocolCounts) -> proto::ProtocolCounts {
+    proto::ProtocolCounts {
+        udp: counts.udp,
+        tcp: counts.tcp,
+    }
+}
+
+impl From<&CongestionSignals> for proto::CongestionSignals {
+    fn from(s: &CongestionSignals) -> Self {
+        let scale = &s.estimated.scale;
+        Self {
+            interval_ns: s.interval_ns,
+            aligned_start_unix_ms: s.aligned_start.map(|t| unix_ms(t) as u64),
+            aligned_end_unix_ms: s.aligned_end.map(|t| unix_ms(t) as u64),
+            observed: Some(proto::ObservedCounts {
+                events_received: s.observed.events_received,
+                drops: s.observed.drops,
+                udp_rcv_drops: s.observed.udp_rcv_drops,
+                softirq_exits: s.observed.softirq_exits,
+            }),
+            estimated: Some(proto::EstimatedTotals {
+                send_bytes: s.estimated.send_bytes,
+                external_send_bytes: s.estimated.external_send_bytes,
+                send_packets: s.estimated.send_packets,
+                send_factor: scale.send_factor,
+                sends_seen: scale.sends_seen,
+                sends_sampled: scale.sends_sampled,
+                socket_fraction: scale.socket_fraction,
+            }),
+            est_send_msgs: Some(protocol_counts(&s.est_send_msgs)),
+            est_wire_packets: Some(protocol_counts(&s.est_wire_packets)),
+            udp_gso: s.udp_gso.map(|gso| proto::GsoSegments {
+                skbs: gso.skbs,
+                gso_skbs: gso.gso_skbs,
+                segments: gso.segments,
+            }),
+            send_bytes: s.send_bytes,
+            loopback_send_bytes: s.loopback_send_bytes,
+            external_send_bytes: s.external_send_bytes,
+            drops: s.drops,
+            avg_wmem_pressure: s.avg_wmem_pressure,
+            udp_wmem_pressure: s.udp_wmem_pressure,
+            tcp_wmem_pressure: s.tcp_wmem_pressure,
+            net_memory_pressure: s.net_memory_pressure,
+            memory_pressure_events: s.memory_pressure_events,
+            avg_socket_pacing_rate: s.avg_socket_pacing_rate,
+            kernel_paced_send_share: s.kernel_paced_send_share,
+            kernel_paced: s.kernel_paced,
+            softirq_ns: s.softirq_ns,
+            event_count: s.event_count,
+            active_sockets: s.active_sockets,
+            active_flows: s.active_flows,
+            new_connections_per_interval: s.new_connections_per_interval,
+            closed_connections_per_interval: s.closed_connections_per_interval,
+            connections: Some(proto::ConnectionChurn {
+                tcp_active_established: s.connections.tcp_active_established,
+                tcp_passive_established: s.connections.tcp_passive_established,
+                tcp_accepts: s.connections.tcp_accepts,
+                tcp_closed: s.connections.tcp_closed,
+                udp_first_seen: s.connections.udp_first_seen,
+            }),
+            queue_depth_packets: s.queue_depth_packets,
+            queue_depth_bytes: s.queue_depth_bytes,
+            udp_rcv_drops: s.udp_rcv_drops,
+            avg_rmem_pressure: s.avg_rmem_pressure,
+            softirq_cpu_fraction: s.softirq_cpu_fraction,
+            tcp_avg_cwnd: s.tcp_avg_cwnd,
+            tcp_avg_ssthresh: s.tcp_avg_ssthresh,
+            tcp_avg_pacing_rate: s.tcp_avg_pacing_rate,
+            tcp_sockets_below_ssthresh: s.tcp_sockets_below_ssthresh,
+            ce_marks: s.ce_marks,
+            ce_triggered_cwr: s.ce_triggered_cwr,
+            rto_events: s.rto_events,
+            loss_recovery_episodes: s.loss_recovery_episodes,
+            rx_time_squeeze: s.rx_time_squeeze,
+            implausible_socket_samples: s.implausible_socket_samples,
+            burst_drop_correlation: s.burst_drop_correlation,
+            drop_interarrival: s.drop_interarrival.map(|d| proto::DropInterarrival {
+                gaps: d.gaps,
+                histogram: d.histogram.to_vec(),
+                mean_gap_ns: d.mean_gap_ns,
+                burstiness: d.burstiness,
+            }),
+            softirq_coupling: s.softirq_coupling.map(|c| proto::SoftirqCoupling {
+                sends_checked: c.sends_checked,
+                sends_delayed_by_softirq: c.sends_delayed_by_softirq,
+                sends_in_softirq: c.sends_in_softirq,
+                avg_softirq_delay_ns: c.avg_softirq_delay_ns,
+            }),
+            softirq_attribution: s.softirq_attribution.map(|a| proto::SoftirqAttribution {
+                self_induced_softirq_ns: a.self_induced_softirq_ns,
+                external_softirq_ns: a.external_softirq_ns,
+                rounds: a.rounds,
+                covered_rounds: a.covered_rounds,
+                confidence: a.confidence,
+            }),
+            tsq_throttles: s.tsq_throttles,
+            softirq_discarded: s.softirq_discarded,
+            missing_signals: s
+                .missing_signals
+                .iter()
+                .map(|m| format!("{:?}", m))
+                .collect(),
+            degradation_level: s.degradation_level,
+            limitation: format!("{:?}", s.limitation),
+            txq_stalls: s.txq_stalls,
+            txq_stalled_ns: s.txq_stalled_ns,
+            egress_estimate_error: s.egress_estimate_error,
+            sessions: s.sessions.clone(),
+            extensions: s
+                .extensions
+                .iter()
+                .map(|(name, snapshot)| (name.clone(), snapshot.to_json()))
+                .collect(),
+        }
+    }
+}
+
+impl From<&CongestionState> for proto::CongestionState {
+    fn from(state: &CongestionState) -> Self {
+        use proto::congestion_state::Level;
+        let level = match state {
+            CongestionState::Green => Level::Green,
+            CongestionState::Yellow(_) => Level::Yellow,
+            CongestionState::Red(_) => Level::Red,
+        };
+        Self {
+            level: level as i32,
+            reasons: state
+                .reasons()
+                .iter()
+                .map(|reason| proto::Reason {
+                    label: reason.label().to_string(),
+                    value: match *reason {
+                        Reason::WmemPressure { p95 } => p95,
+                        Reason::QueueDelay { ms } => ms,
+                        Reason::DropRate { per_sec } => per_sec,
+                        Reason::SndbufStall { pressure } => pressure,
+                        Reason::Bufferbloat { growth, .. } => growth,
+                        Reason::Burst {
+                            pressured_fraction, ..
+                        } => pressured_fraction,
+                        Reason::NonResponsive { improved_fraction } => improved_fraction,
+                    },
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The proto's messages by full name, to resolve field mask paths against
+struct MaskRoot {
+    messages: HashMap<String, DescriptorProto>,
+}
+
+impl MaskRoot {
+    fn load() -> anyhow::Result<Self> {
+        let set = FileDescriptorSet::decode(proto::FILE_DESCRIPTOR_SET)?;
+        let mut messages = HashMap::new();
+        for file in set.file {
+            let package = file.package.unwrap_or_default();
+            for message in file.message_type {
+                add_message(&format!(".{}", package), message, &mut messages);
+            }
+        }
+        Ok(Self { messages })
+    }
+
+    fn resolve(&self, paths: &[String]) -> Result<FieldMask, String> {
+        let mut mask = FieldMask::default();
+        for path in paths {
+            let mut message = SNAPSHOT_MESSAGE.to_string();
+            let mut at = &mut mask;
+            let mut names = path.split('.').peekable();
+            while let Some(name) = names.next() {
+                let field = self
+                    .messages
+                    .get(&message)
+                    .and_then(|m| m.field.iter().find(|f| f.name() == name))
+                    .ok_or_else(|| format!("no field {:?} in field mask path {:?}", name, path))?;
+                let number = field.number() as u32;
+                let last = names.peek().is_none();
+                if last {
+                    at.fields.insert(number, None);
+                    break;
+                }
+                if field.r#type() != field_descriptor_proto::Type::Message {
+                    return Err(format!("{:?} in {:?} has no fields", name, path));
+                }
+                message = field.type_name().to_string();
+                // A whole field already kept stays whole
+                let Some(sub) = at
+                    .fields
+                    .entry(number)
+                    .or_insert_with(|| Some(FieldMask::default()))
+                else {
+                    break;
+                };
+                at = sub;
+            }
+        }
+        Ok(mask)
+    }
+}
+
+fn add_message(
+    scope: &str,
+    message: DescriptorProto,
+    messages: &mut HashMap<String, DescriptorProto>,
+) {
+    let name = format!("{}.{}", scope, message.name());
+    for nested in message.nested_type.clone() {
+        add_message(&name, nested, messages);
+    }
+    messages.insert(name, message);
+}
+
+/// Field numbers kept at one level; None keeps the whole field
+#[derive(Debug, Default)]
+struct FieldMask {
+    fields: HashMap<u32, Option<FieldMask>>,
+}
+
+impl FieldMask {
+    fn keeps(&self, number: u32) -> bool {
+        self.fields.contains_key(&number)
+    }
+
+    fn apply(&self, snapshot: &proto::Snapshot) -> Result<proto::Snapshot, Status> {
+        let mut pruned = Vec::new();
+        self.prune(&snapshot.encode_to_vec(), &mut pruned)
+            .and_then(|()| proto::Snapshot::decode(pruned.as_slice()))
+            .map_err(|e| Status::internal(format!("field mask: {}", e)))
+    }
+
+    /// Copy the fields kept from one encoded message to `out`
+    fn prune(&self, mut bytes: &[u8], out: &mut Vec<u8>) -> Result<(), prost::DecodeError> {
+        while bytes.has_remaining() {
+            let start = bytes;
+            let key = decode_varint(&mut bytes)?;
+            let (number, wire_type) = ((key >> 3) as u32, key & 7);
+            let key_len = start.len() - bytes.len();
+            let value_len = match wire_type {
+                0 => {
+                    decode_varint(&mut bytes)?;
+                    0
+                }
+                1 => 8,
+                2 => decode_varint(&mut bytes)? as usize,
+                5 => 4,
+                _ => return Err(prost::DecodeError::new("unexpected wire type")),
+            };
+            if bytes.len() < value_len {
+                return Err(prost::DecodeError::new("buffer underflow"));
+            }
+            let header_len = start.len() - bytes.len();
+            let (value, rest) = bytes.split_at(value_len);
+            bytes = rest;
+            match self.fields.get(&number) {
+                Some(None) => out.extend_from_slice(&start[..header_len + value_len]),
+                Some(Some(sub)) if wire_type == 2 => {
+                    let mut inner = Vec::new();
+                    sub.prune(value, &mut inner)?;
+                    out.extend_from_slice(&start[..key_len]);
+                    encode_varint(inner.len() as u64, out);
+                    out.extend_from_slice(&inner);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::signals_client::SignalsClient;
+    use proto::{GetHealthRequest, Snapshot, SubscribeRequest};
+    use tonic::transport::Channel;
+
+    // The scripted source's sockets, the second one the busier
+    const QUIET_SOCKET: u64 = 7;
+    const BUSY_SOCKET: u64 = 9;
+    const SECRET: &str = "s3cret";
+
+    /// Scripted intervals: drops on the second one, two sockets, and a health
+    /// warning
+    fn scripted() -> ScriptedSource {
+        let interval = |drops| {
+            CongestionSignals::builder()
+                .interval_ns(20_000_000)
+                .drops(drops)
+                .external_send_bytes(1_000_000)
+                .build()
+        };
+        let sockets = [(QUIET_SOCKET, 5_000), (BUSY_SOCKET, 9_000)]
+            .into_iter()
+            .map(|(socket_id, send_bytes)| {
+                let mut socket = SocketSignals::default();
+                socket.send_bytes = send_bytes;
+                (socket_id, socket)
+            })
+            .collect();
+        ScriptedSource::new([interval(0), interval(1000), interval(0)])
+            .with_sockets(sockets)
+            .with_health(HealthReport {
+                warnings: vec!["scripted".to_string()],
+                ..Default::default()
+            })
+    }
+
+    /// Serve the scripted source on loopback with SECRET until `test` is
+    /// done with its client
+    async fn with_client<F, T>(test: impl FnOnce(SignalsClient<Channel>) -> F) -> T
+    where
+        F: Future<Output = T>,
+    {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut config = GrpcConfig::new(addr).with_shared_secret(SECRET);
+        config.min_interval = Duration::from_millis(20);
+        let server = GrpcServer::new(Arc::new(scripted()), config);
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(server.serve_on(listener, async {
+            let _ = stopped.await;
+        }));
+        let client = SignalsClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+        let result = test(client).await;
+        let _ = stop.send(());
+        let _ = server.await;
+        result
+    }
+
+    fn authed<T>(message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        let bearer = format!("Bearer {}", SECRET);
+        request
+            .metadata_mut()
+            .insert("authorization", bearer.parse().unwrap());
+        request
+    }
+
+    fn subscribe(paths: &[&str]) -> SubscribeRequest {
+        SubscribeRequest {
+            interval_ms: 20,
+            field_mask: Some(prost_types::FieldMask {
+                paths: paths.iter().map(|path| path.to_string()).collect(),
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn the_shared_secret_is_enforced() {
+        with_client(|mut client| async move {
+            let anonymous = client.get_health(GetHealthRequest {}).await.unwrap_err();
+            assert_eq!(anonymous.code(), tonic::Code::Unauthenticated);
+            let health = client
+                .get_health(authed(GetHealthRequest {}))
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(!health.healthy);
+            assert_eq!(health.warnings, ["scripted"]);
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn a_bad_mask_path_is_refused() {
+        with_client(|mut client| async move {
+            let bad_mask = client
+                .subscribe(authed(subscribe(&["signals.no_such_field"])))
+                .await
+                .unwrap_err();
+            assert_eq!(bad_mask.code(), tonic::Code::InvalidArgument);
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn a_masked_subscription_gets_only_the_masked_fields() {
+        let snapshots = with_client(|mut client| async move {
+            let request = subscribe(&["state", "signals.drops", "sockets.socket_id"]);
+            let mut stream = client
+                .subscribe(authed(request))
+                .await
+                .unwrap()
+                .into_inner();
+            let mut snapshots: Vec<Snapshot> = Vec::new();
+            while snapshots.len() < 3 {
+                let next = tokio::time::timeout(Duration::from_secs(2), stream.message());
+                snapshots.extend(next.await.unwrap().unwrap());
+            }
+            snapshots
+        })
+        .await;
+
+        let levels: Vec<i32> = snapshots
+            .iter()
+            .map(|s| s.state.as_ref().map_or(-1, |state| state.level))
+            .collect();
+        assert_eq!(levels, [0, 2, 2]);
+        let drops: Vec<u64> = snapshots
+            .iter()
+            .map(|s| s.signals.as_ref().map_or(u64::MAX, |signals| signals.drops))
+            .collect();
+        assert_eq!(drops, [0, 1000, 0]);
+        for snapshot in &snapshots {
+            assert_eq!(snapshot.produced_at_unix_ms, 0);
+            assert_eq!(snapshot.score, 0.0);
+            assert!(snapshot.interfaces.is_empty());
+            assert_eq!(snapshot.signals.as_ref().unwrap().external_send_bytes, 0);
+            // Busiest first, with only their ids
+            let sockets: Vec<(u64, u64)> = snapshot
+                .sockets
+                .iter()
+                .map(|socket| (socket.socket_id, socket.send_bytes))
+                .collect();
+            assert_eq!(sockets, [(BUSY_SOCKET, 0), (QUIET_SOCKET, 0)]);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_script_plays_round_and_an_empty_one_ends() {
+        let script = scripted();
+        let drops: Vec<u64> = script
+            .snapshots(Duration::from_millis(1))
+            .take(5)
+            .map(|signals| signals.drops)
+            .collect()
+            .await;
+        assert_eq!(drops, [0, 1000, 0, 0, 1000]);
+        assert_eq!(script.per_socket().len(), 2);
+        assert!(!script.health().is_healthy());
+
+        let empty = ScriptedSource::new([]);
+        assert_eq!(empty.snapshots(Duration::from_millis(1)).count().await, 0);
+    }
+}
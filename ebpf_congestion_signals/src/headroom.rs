@@ -0,0 +1,315 @@
+//How much faster this host could send before the path pushes back. Like BBR's
+//bandwidth filter the ceiling is the highest send rate seen without pressure,
+//decayed every interval so an old peak ages out, but the pressure is what the
+//host sees: drop onset, a growing qdisc backlog, and the link speed when known.
+//An interval with pressure caps the ceiling at what was being sent then.
+
+use crate::{BondTopology, CongestionSignals, Limitation};
+
+/// Tuning for [`HeadroomEstimator`], part of `GovernorPolicy`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeadroomConfig {
+    /// Link speed in bytes/sec. The ceiling never goes past it, and without
+    /// other pressure sending at `saturated_utilization` of it counts as a
+    /// sample of the ceiling. See [`HeadroomConfig::with_interface_speed`]
+    pub link_rate: Option<u64>,
+    /// Fraction of the link rate at which the link itself is the bottleneck
+    pub saturated_utilization: f64,
+    /// Multiplier applied to the ceiling every interval, 0.98 halves an
+    /// unrefreshed ceiling in ~35 intervals
+    pub decay: f64,
+    /// Drops per second that mark the onset of loss
+    pub onset_drops_per_sec: f64,
+    /// qdisc backlog growth between intervals that counts as queue building,
+    /// 1.5 = half again as many bytes queued
+    pub backlog_growth: f64,
+    /// Backlogs below this many bytes are ignored, whatever their trend
+    pub min_backlog_bytes: u64,
+    /// Samples of the ceiling before confidence can reach 1.0
+    pub full_confidence_samples: u32,
+    /// Intervals without a sample after which confidence has halved
+    pub confidence_half_life: u32,
+}
+
+impl Default for HeadroomConfig {
+    fn default() -> Self {
+        Self {
+            link_rate: None,
+            saturated_utilization: 0.95,
+            decay: 0.98,
+            onset_drops_per_sec: 10.0,
+            backlog_growth: 1.5,
+            min_backlog_bytes: 64 * 1024,
+            full_confidence_samples: 8,
+            confidence_half_life: 25,
+        }
+    }
+}
+
+impl HeadroomConfig {
+    /// Take `link_rate` from `/sys/class/net/<interface>/speed`. Left as is for
+    /// interfaces without a speed (veth, tun, a link that's down report -1)
+    pub fn with_interface_speed(mut self, interface: &str) -> Self {
+        if let Some(rate) = interface_speed(interface) {
+            self.link_rate = Some(rate);
+        }
+        self
+    }
+}
+
+/// Link speed of a network interface in bytes/sec, None when the driver
+/// doesn't report one. A bond's or team's is its
+/// `BondMaster::aggregate_speed`, which follows the mode
+pub fn interface_speed(interface: &str) -> Option<u64> {
+    BondTopology::read().interface_speed(interface)
+}
+
+/// One interval's estimate, carried by `PacingDecision::headroom`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HeadroomEstimate {
+    /// Ceiling minus the interval's send rate, bits/sec. None until the ceiling
+    /// has been sampled once
+    pub estimated_headroom_bps: Option<u64>,
+    /// 0.0-1.0, from how many samples the ceiling has had and how long ago the
+    /// last one was
+    pub confidence: f64,
+    /// The ceiling itself, bits/sec
+    pub ceiling_bps: Option<u64>,
+    /// Send rate over `link_rate`, None without a link rate
+    pub link_utilization: Option<f64>,
+}
+
+/// Tracks the send-rate ceiling across intervals, see the module docs.
+/// `Governor` runs one; it can also be fed directly
+#[derive(Debug, Clone)]
+pub struct HeadroomEstimator {
+    config: HeadroomConfig,
+    // bytes/sec
+    ceiling: Option<f64>,
+    samples: u32,
+    intervals_since_sample: u32,
+    previous_backlog: u64,
+}
+
+impl HeadroomEstimator {
+    pub fn new(config: HeadroomConfig) -> Self {
+        Self {
+            config,
+            ceiling: None,
+            samples: 0,
+            intervals_since_sample: 0,
+            previous_backlog: 0,
+        }
+    }
+
+    pub fn config(&self) -> &HeadroomConfig {
+        &self.config
+    }
+
+    /// Fold in one interval and estimate the headroom left at its send rate
+    pub fn update(&mut self, signals: &CongestionSignals) -> HeadroomEstimate {
+        let config = &self.config;
+        let secs = signals.interval_ns as f64 / 1e9;
+        let backlog = signals.queue_depth_bytes;
+        let backlog_rising = backlog >= config.min_backlog_bytes
+            && backlog as f64 > self.previous_backlog as f64 * config.backlog_growth;
+        self.previous_backlog = backlog;
+        if secs <= 0.0 {
+            return self.estimate(0.0);
+        }
+
+        let rate = signals.send_rate(secs);
+        let drop_onset = signals.drops as f64 / secs >= config.onset_drops_per_sec;
+        let utilization = config
+            .link_rate
+            .filter(|&link| link > 0)
+            .map(|link| rate / link as f64);
+        let saturated = utilization.is_some_and(|u| u >= config.saturated_utilization);
+
+        let decayed = self.ceiling.map(|ceiling| ceiling * config.decay);
+        // App-limited intervals say nothing about the path: age the ceiling only
+        let sampled = if signals.limitation == Limitation::AppLimited {
+            self.ceiling = decayed;
+            false
+        } else if drop_onset || backlog_rising {
+            // Whatever the path carries, it's no more than what pushed it over
+            self.ceiling = Some(decayed.map_or(rate, |ceiling| ceiling.min(rate)));
+            true
+        } else if saturated {
+            self.ceiling = config.link_rate.map(|link| link as f64);
+            true
+        } else if rate > 0.0 && decayed.is_none_or(|ceiling| rate >= ceiling) {
+            self.ceiling = Some(rate);
+            true
+        } else {
+            self.ceiling = decayed;
+            false
+        };
+        if let (Some(ceiling), Some(link)) = (self.ceiling, config.link_rate) {
+            self.ceiling = Some(ceiling.min(link as f64));
+        }
+
+        if sampled {
+            self.samples = self.samples.saturating_add(1);
+            self.intervals_since_sample = 0;
+        } else {
+            self.intervals_since_sample = self.intervals_since_sample.saturating_add(1);
+        }
+        let mut estimate = self.estimate(rate);
+        estimate.link_utilization = utilization;
+        estimate
+    }
+
+    fn estimate(&self, rate: f64) -> HeadroomEstimate {
+        let Some(ceiling) = self.ceiling else {
+            return HeadroomEstimate::default();
+        };
+        let config = &self.config;
+        let sample_factor = if config.full_confidence_samples > 0 {
+            (self.samples as f64 / config.full_confidence_samples as f64).min(1.0)
+        } else {
+            1.0
+        };
+        let age_factor = if config.confidence_half_life > 0 {
+            0.5f64.powf(self.intervals_since_sample as f64 / config.confidence_half_life as f64)
+        } else {
+            1.0
+        };
+        HeadroomEstimate {
+            estimated_headroom_bps: Some(((ceiling - rate).max(0.0) * 8.0) as u64),
+            confidence: sample_factor * age_factor,
+            ceiling_bps: Some((ceiling * 8.0) as u64),
+            link_utilization: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "collector-core")]
+    const MB: f64 = 1_000_000.0;
+
+    /// An interval of `rate` bytes/sec, in sampled sends
+    fn one_second(rate: u64) -> crate::CongestionSignalsBuilder {
+        CongestionSignals::builder()
+            .interval_ns(1_000_000_000)
+            .external_send_bytes(rate / crate::SEND_SAMPLE_RATIO)
+    }
+
+    #[test]
+    fn the_ceiling_is_the_best_rate_seen_without_pressure() {
+        let mut estimator = HeadroomEstimator::new(HeadroomConfig::default());
+        assert_eq!(
+            estimator.update(&CongestionSignals::idle()),
+            HeadroomEstimate::default()
+        );
+
+        let fast = one_second(50_000_000).build();
+        let slow = one_second(20_000_000).build();
+        estimator.update(&fast);
+        let estimate = estimator.update(&slow);
+        // 50 MB/s decayed once, less the 20 MB/s sent this interval
+        assert_eq!(estimate.ceiling_bps, Some(392_000_000));
+        assert_eq!(estimate.estimated_headroom_bps, Some(232_000_000));
+        // One sample of the 8 for full confidence, one interval old
+        assert_eq!(estimate.confidence, 0.125 * 0.5f64.powf(1.0 / 25.0));
+    }
+
+    #[test]
+    fn drop_onset_caps_the_ceiling_at_its_rate() {
+        let mut estimator = HeadroomEstimator::new(HeadroomConfig::default());
+        estimator.update(&one_second(50_000_000).build());
+        let dropping = one_second(30_000_000).drops(100).build();
+        let estimate = estimator.update(&dropping);
+        assert_eq!(estimate.ceiling_bps, Some(240_000_000));
+        assert_eq!(estimate.estimated_headroom_bps, Some(0));
+    }
+
+    #[test]
+    fn app_limited_intervals_only_age_the_ceiling() {
+        let mut estimator = HeadroomEstimator::new(HeadroomConfig::default());
+        estimator.update(&one_second(50_000_000).build());
+        let quiet = one_second(0).limitation(Limitation::AppLimited).build();
+        let estimate = estimator.update(&quiet);
+        assert_eq!(estimate.ceiling_bps, Some(392_000_000));
+    }
+
+    #[test]
+    fn a_saturated_link_is_its_own_ceiling() {
+        let config = HeadroomConfig {
+            link_rate: Some(125_000_000),
+            ..HeadroomConfig::default()
+        };
+        let mut estimator = HeadroomEstimator::new(config);
+        let estimate = estimator.update(&one_second(120_000_000).build());
+        assert_eq!(estimate.ceiling_bps, Some(1_000_000_000));
+        assert_eq!(estimate.link_utilization, Some(0.96));
+    }
+
+    /// A sender raising its rate 10% per interval until the path drops, then
+    /// cutting 30%, over a bottleneck of `capacity` bytes/sec per interval.
+    /// Intervals go through the replay harness; yields each one's estimate
+    #[cfg(feature = "collector-core")]
+    fn over_bottleneck(capacities: &[f64]) -> Vec<HeadroomEstimate> {
+        use crate::fixtures::{self, Replay};
+        use crate::{CollectorConfig, SEND_SAMPLE_RATIO};
+        use std::time::Duration;
+
+        let replay = Replay::new(CollectorConfig::default(), 1);
+        let mut estimator = HeadroomEstimator::new(HeadroomConfig::default());
+        let mut rate = 10.0 * MB;
+        let mut estimates = Vec::new();
+        for (interval, &capacity) in capacities.iter().enumerate() {
+            let start = interval as u64 * 1_000_000_000;
+            // Sampled sends, scaled back up by the interval read
+            let sampled = (rate / SEND_SAMPLE_RATIO as f64) as u64;
+            let mut events: Vec<_> = (0..10)
+                .map(|n| fixtures::udp_send(start + n * 100_000_000, 0, 7, sampled / 10))
+                .collect();
+            let excess = rate - capacity;
+            if excess > 0.0 {
+                let dropped = (excess / 1_500.0) as u32;
+                events.push(fixtures::qdisc_drop(start + 999_000_000, 0, dropped, 0));
+            }
+            replay.feed(&events);
+            let signals = replay.read(Duration::from_secs(1));
+            estimates.push(estimator.update(&signals));
+            rate = if excess > 0.0 { rate * 0.7 } else { rate * 1.1 };
+        }
+        estimates
+    }
+
+    #[cfg(feature = "collector-core")]
+    fn ceiling(estimate: &HeadroomEstimate) -> f64 {
+        estimate.ceiling_bps.unwrap() as f64 / 8.0
+    }
+
+    #[test]
+    #[cfg(feature = "collector-core")]
+    fn the_estimate_follows_a_bottleneck_down_and_up() {
+        let capacities: Vec<f64> = [(40, 100.0), (40, 40.0), (40, 100.0)]
+            .iter()
+            .flat_map(|&(intervals, capacity)| std::iter::repeat_n(capacity * MB, intervals))
+            .collect();
+        let estimates = over_bottleneck(&capacities);
+
+        // Converged on the first bottleneck, within the sender's probing
+        let settled = |range: std::ops::Range<usize>, capacity: f64| {
+            for (i, estimate) in estimates[range].iter().enumerate() {
+                let ceiling = ceiling(estimate);
+                assert!(
+                    ceiling > 0.6 * capacity * MB && ceiling < 1.25 * capacity * MB,
+                    "interval {i}: ceiling {ceiling} for a {capacity} MB/s bottleneck"
+                );
+            }
+        };
+        settled(30..40, 100.0);
+        // Down within 5 intervals of the bottleneck halving
+        settled(45..80, 40.0);
+        // Up within 15 intervals of it coming back
+        settled(95..120, 100.0);
+        assert!(estimates[119].confidence > 0.5);
+    }
+}
@@ -0,0 +1,699 @@
+//Health reporting: things that suggest the signals themselves can't be trusted
+//(lost events, broken probes) as opposed to the network being congested.
+
+use crate::clock::Clock;
+use crate::wallclock::{TimeNamespaceOffsets, WALL_CLOCK_TOLERANCE};
+use crate::{
+    CoAttachedProgram, CoAttachmentScan, HeartbeatStatus, MemlockOutcome, ProgramLoadFailure,
+};
+use crate::{
+    event_type_name, CalibrationOutcome, CaptureStatus, OverheadStatus, EVENT_NAPI_POLL, EVENT_QDISC_DROP, EVENT_RX_TIME_SQUEEZE, EVENT_SOCKET_LIFECYCLE,
+    EVENT_UDP_RCV_DROP, Signal,
+};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Point-in-time view of collector health, built by `CongestionCollector::health()`
+#[derive(Debug, Clone, Default)]
+pub struct HealthReport {
+    /// Human-readable problems found while building the report
+    pub warnings: Vec<String>,
+    /// Seconds since the last event of each type that has produced one
+    pub last_seen_age_secs: HashMap<u32, f64>,
+    /// Per CPU, how often its perf reader recovered from read errors. CPUs whose
+    /// reader never had one are absent
+    pub reader_restarts: HashMap<u32, u64>,
+    /// Background task panics and errors since load, see
+    /// `CongestionCollector::failures()`. Always 0 in blocking builds
+    pub component_failures: u64,
+    /// Result of the socket-state calibration at `start_collection()`, None
+    /// before it or with `CollectorConfig::without_calibration`
+    pub calibration: Option<CalibrationOutcome>,
+    /// Cumulative totals were carried over from `CollectorConfig::state_file`
+    /// at load, rather than starting from zero
+    pub restored_from_state: bool,
+    /// Triggered capture's counts and last recording, None without
+    /// `CollectorConfig::with_triggered_capture`
+    pub capture: Option<CaptureStatus>,
+    /// The overhead budget's level and latest reading, None without
+    /// `CollectorConfig::with_overhead_budget`
+    pub overhead: Option<OverheadStatus>,
+    /// How far the startup self-check's event converted to wall-clock time
+    /// from when it was seen, see `CongestionCollector::to_wallclock`. None
+    /// before `start_collection()` or without a calibration sample to check
+    pub wall_clock_skew: Option<Duration>,
+    /// What load found and did about RLIMIT_MEMLOCK
+    pub memlock: Option<MemlockOutcome>,
+    /// Program groups the kernel rejected at the latest load or reload, with
+    /// the verifier's log; their signals are left out. See `PROGRAM_GROUPS`
+    pub program_failures: Vec<ProgramLoadFailure>,
+    /// Whether the heartbeat's datagrams come through the probes and readers,
+    /// and how fast. None without `CollectorConfig::with_heartbeat` or its
+    /// socket, and in blocking builds
+    pub heartbeat: Option<HeartbeatStatus>,
+    /// Other programs where ours are attached, as of the latest
+    /// `verify_attachments()` or `scan_co_attachments()`. Empty before one
+    pub co_attached: Vec<CoAttachedProgram>,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.warnings.is_empty()
+    }
+}
+
+// Relative disagreement between our softirq time and /proc/stat before we warn
+const SOFTIRQ_DISAGREEMENT: f64 = 0.20;
+// Below this much /proc/stat softirq time (2 ticks at 100Hz) the jiffy rounding dominates
+const SOFTIRQ_MIN_PROC_NS: u64 = 20_000_000;
+
+/// Cross-checks our NET_TX/NET_RX softirq time against the kernel's own per-CPU
+/// softirq accounting in /proc/stat. Both sides are cumulative; each call compares
+/// the deltas since the previous call.
+///
+/// /proc/stat counts every softirq vector (timers, RCU, ...), not just the network
+/// ones, and is tick-sampled unless the kernel has IRQ_TIME_ACCOUNTING, so this
+/// is a coarse check meant to catch lost events, not a precise one.
+pub(crate) struct SoftirqCrossCheck {
+    last: Option<(Instant, u64, u64)>,
+    clock: Arc<dyn Clock>,
+}
+
+impl SoftirqCrossCheck {
+    pub(crate) fn new(clock: Arc<dyn Clock>) -> Self {
+        Self { last: None, clock }
+    }
+
+    /// `ours_ns` is the collector's cumulative softirq time across all CPUs
+    pub(crate) fn check(&mut self, ours_ns: u64, report: &mut HealthReport) {
+        if let Some(proc_ns) = read_proc_stat_softirq_ns() {
+            self.compare(ours_ns, proc_ns, report);
+        }
+    }
+
+    /// `check` against `proc_ns`, /proc/stat's cumulative softirq time
+    fn compare(&mut self, ours_ns: u64, proc_ns: u64, report: &mut HealthReport) {
+        let now = self.clock.now();
+        if let Some((_, last_ours, last_proc)) = self.last.replace((now, ours_ns, proc_ns)) {
+            let ours = ours_ns.saturating_sub(last_ours);
+            let procd = proc_ns.saturating_sub(last_proc);
+            if procd < SOFTIRQ_MIN_PROC_NS {
+                return;
+            }
+
+            let disagreement = (ours as f64 - procd as f64).abs() / procd as f64;
+            if disagreement > SOFTIRQ_DISAGREEMENT {
+                report.warnings.push(format!(
+                    "softirq time disagrees with /proc/stat by {:.0}% ({} µs vs {} µs), possible lost events",
+                    disagreement * 100.0,
+                    ours / 1000,
+                    procd / 1000
+                ));
+            }
+        }
+    }
+}
+
+/// Sum of the softirq column over the per-CPU lines of /proc/stat, in ns
+fn read_proc_stat_softirq_ns() -> Option<u64> {
+    let content = std::fs::read_to_string("/proc/stat").ok()?;
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks_per_sec <= 0 {
+        return None;
+    }
+    Some(softirq_jiffies(&content) * 1_000_000_000 / ticks_per_sec as u64)
+}
+
+fn softirq_jiffies(proc_stat: &str) -> u64 {
+    proc_stat
+        .lines()
+        .filter(|line| line.starts_with("cpu") && !line.starts_with("cpu "))
+        // cpuN user nice system idle iowait irq softirq ...
+        .filter_map(|line| line.split_whitespace().nth(7)?.parse::<u64>().ok())
+        .sum()
+}
+
+// Share of discarded socket buffer samples above which the offsets are suspect
+const IMPLAUSIBLE_RATIO: f64 = 0.5;
+// Too few samples to call it either way
+const IMPLAUSIBLE_MIN_SAMPLES: u64 = 20;
+
+/// Compares the socket buffer samples the kernel discarded as impossible
+/// against the ones it sent, over the period since the previous call. Now and
+/// then a socket mid-teardown reads oddly; most of them failing means the
+/// struct sock offsets from BTF point at the wrong fields, a BTF that doesn't
+/// describe the running kernel.
+pub(crate) struct SampleSanityCheck {
+    last: Option<(u64, u64)>,
+}
+
+impl SampleSanityCheck {
+    pub(crate) fn new() -> Self {
+        Self { last: None }
+    }
+
+    /// Both counts are cumulative: discarded in the kernel, and decoded here
+    pub(crate) fn check(&mut self, implausible: u64, plausible: u64, report: &mut HealthReport) {
+        let Some((last_implausible, last_plausible)) = self.last.replace((implausible, plausible))
+        else {
+            return;
+        };
+        let implausible = implausible.saturating_sub(last_implausible);
+        let total = implausible + plausible.saturating_sub(last_plausible);
+        if total < IMPLAUSIBLE_MIN_SAMPLES {
+            return;
+        }
+
+        let ratio = implausible as f64 / total as f64;
+        if ratio > IMPLAUSIBLE_RATIO {
+            report.warnings.push(format!(
+                "probable offset mismatch: {} of {} socket buffer samples implausible ({:.0}%), check that kernel BTF matches the running kernel",
+                implausible,
+                total,
+                ratio * 100.0
+            ));
+        }
+    }
+}
+
+// Only fire when something goes wrong (or, for lifecycle, when TCP connections
+// come and go, and for NAPI polls, when a NET_RX round runs long), so silence
+// from them is the normal case
+const CONDITION_EVENTS: [u32; 5] = [
+    EVENT_QDISC_DROP,
+    EVENT_UDP_RCV_DROP,
+    EVENT_SOCKET_LIFECYCLE,
+    EVENT_RX_TIME_SQUEEZE,
+    EVENT_NAPI_POLL,
+];
+
+/// Flags event types that were active and went quiet while other types kept
+/// arriving, which points at a lost probe rather than an idle host. Warnings
+/// land in every report while the condition holds; the log line is limited to
+/// once per staleness window per type.
+pub(crate) struct StalenessCheck {
+    last_logged: HashMap<u32, Instant>,
+    clock: Arc<dyn Clock>,
+}
+
+impl StalenessCheck {
+    pub(crate) fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            last_logged: HashMap::new(),
+            clock,
+        }
+    }
+
+    pub(crate) fn check(
+        &mut self,
+        last_seen_ns: &HashMap<u32, u64>,
+        window: Duration,
+        report: &mut HealthReport,
+    ) {
+        if let Some(now_ns) = monotonic_now_ns() {
+            self.check_at(now_ns, last_seen_ns, window, report);
+        }
+    }
+
+    /// `check` with the kernel clock at `now_ns`
+    fn check_at(
+        &mut self,
+        now_ns: u64,
+        last_seen_ns: &HashMap<u32, u64>,
+        window: Duration,
+        report: &mut HealthReport,
+    ) {
+        report.last_seen_age_secs = last_seen_ns
+            .iter()
+            .map(|(&event_type, &ts)| (event_type, now_ns.saturating_sub(ts) as f64 / 1e9))
+            .collect();
+
+        let window_secs = window.as_secs_f64();
+        let any_fresh = report
+            .last_seen_age_secs
+            .values()
+            .any(|&age| age < window_secs);
+
+        let mut stale: Vec<_> = report
+            .last_seen_age_secs
+            .iter()
+            .filter(|(event_type, &age)| {
+                any_fresh && age >= window_secs && !CONDITION_EVENTS.contains(event_type)
+            })
+            .map(|(&event_type, &age)| (event_type, age))
+            .collect();
+        stale.sort_by_key(|&(event_type, _)| event_type);
+
+        for &(event_type, age) in &stale {
+            let warning = format!(
+                "no {} events for {:.0}s while other probes are active, probe may be detached",
+                event_type_name(event_type),
+                age
+            );
+
+            let now = self.clock.now();
+            let log_due = self
+                .last_logged
+                .get(&event_type)
+                .is_none_or(|&at| now.duration_since(at) >= window);
+            if log_due {
+                log::warn!("{}", warning);
+                self.last_logged.insert(event_type, now);
+            }
+            report.warnings.push(warning);
+        }
+
+        self.last_logged.retain(|event_type, _| {
+            let still_stale = stale.iter().any(|&(t, _)| t == *event_type);
+            if !still_stale {
+                log::info!("{} events are flowing again", event_type_name(*event_type));
+            }
+            still_stale
+        });
+    }
+}
+
+/// Same clock as bpf_ktime_get_ns()
+pub(crate) fn monotonic_now_ns() -> Option<u64> {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) } != 0 {
+        return None;
+    }
+    Some(ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64)
+}
+
+// Standalone tracefs on 4.1+, the debugfs one only where tracefs isn't mounted
+// on its own
+const TRACEFS_ROOTS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+/// Where tracefs is mounted, None when neither place has it (common in
+/// containers). Tracepoints attach through its events/ directory
+pub(crate) fn tracefs_root() -> Option<&'static Path> {
+    first_tracefs(TRACEFS_ROOTS.iter().map(Path::new))
+}
+
+/// The first of `roots` with tracefs mounted on it. An empty mount point
+/// doesn't count
+fn first_tracefs<'a>(mut roots: impl Iterator<Item = &'a Path>) -> Option<&'a Path> {
+    roots.find(|root| root.join("events").is_dir())
+}
+
+/// The health warning for a collector running without tracepoints
+pub(crate) fn tracefs_missing(missing: &[Signal], report: &mut HealthReport) {
+    let names: Vec<String> = missing.iter().map(|s| format!("{:?}", s)).collect();
+    report.warnings.push(format!(
+        "tracefs not found at {}, tracepoint signals unavailable ({}): mount tracefs \
+         (mount -t tracefs nodev /sys/kernel/tracing) or run the container with --privileged",
+        TRACEFS_ROOTS.join(" or "),
+        names.join(", ")
+    ));
+}
+
+/// Warning for a calibration that shows the socket-state offsets wrong, or
+/// couldn't find out
+pub(crate) fn calibration(outcome: &CalibrationOutcome, disabled: bool, report: &mut HealthReport) {
+    let warning = match outcome {
+        CalibrationOutcome::Passed { .. } | CalibrationOutcome::Failed(_) => return,
+        CalibrationOutcome::Mismatch {
+            expected_sndbuf,
+            observed_sndbuf,
+            samples,
+        } => format!(
+            "socket-state calibration failed: sk_sndbuf of the calibration socket read as {} \
+             across {} samples, expected {}; wmem pressures are {}, check sk_sndbuf / \
+             sk_wmem_queued offsets in kernel BTF",
+            observed_sndbuf,
+            samples,
+            expected_sndbuf,
+            if disabled { "left out" } else { "unreliable" }
+        ),
+        CalibrationOutcome::NoSamples {
+            expected_sndbuf,
+            sends,
+        } => format!(
+            "socket-state calibration inconclusive: no sample of the calibration socket \
+             (sk_sndbuf {}) in {} sends; the TCP state probe may be missing or its offsets unknown",
+            expected_sndbuf, sends
+        ),
+    };
+    report.warnings.push(warning);
+}
+
+/// A dead event path: quiet signals are the collector's, not the network's
+#[cfg(feature = "async-runtime")]
+pub(crate) fn heartbeat(status: &HeartbeatStatus, report: &mut HealthReport) {
+    if !status.pipeline_alive {
+        report.warnings.push(format!(
+            "the last {} heartbeats didn't come through the readers; the event path is broken \
+             and zeros in the signals mean nothing",
+            status.consecutive_missed
+        ));
+    }
+}
+
+/// Warnings for other programs where ours attach, each event there costs
+/// theirs too, and for points ours were gone from at the scan
+pub(crate) fn co_attachment(scan: &CoAttachmentScan, report: &mut HealthReport) {
+    if !scan.co_attached.is_empty() {
+        let programs: Vec<String> = scan.co_attached.iter().map(|p| p.to_string()).collect();
+        report.warnings.push(format!(
+            "{} programs of others attached where ours are, their cost adds to ours in the \
+             overhead budget: {}",
+            programs.len(),
+            programs.join("; ")
+        ));
+    }
+    for point in &scan.missing {
+        report.warnings.push(format!(
+            "nothing of ours attached at {} at the last scan; verify_attachments() reloads for it",
+            point
+        ));
+    }
+}
+
+/// Warning for perf buffers cut down to fit RLIMIT_MEMLOCK
+pub(crate) fn memlock(outcome: &MemlockOutcome, report: &mut HealthReport) {
+    if let MemlockOutcome::Reduced {
+        limit,
+        wanted_pages,
+        pages,
+    } = outcome
+    {
+        report.warnings.push(format!(
+            "perf buffers cut to {} pages per CPU from {} to fit RLIMIT_MEMLOCK of {} KB; \
+             bursts overflow them sooner, raise it with `ulimit -l unlimited` or \
+             LimitMEMLOCK=infinity in the unit",
+            pages,
+            wanted_pages,
+            limit / 1024
+        ));
+    }
+}
+
+/// Warnings for program groups the kernel rejected, the logs are in
+/// `program_failures`
+pub(crate) fn program_failures(failures: &[ProgramLoadFailure], report: &mut HealthReport) {
+    for failure in failures {
+        report.warnings.push(failure.to_string());
+    }
+}
+
+/// Warning for a startup wall-clock self-check off by more than WALL_CLOCK_TOLERANCE
+pub(crate) fn wall_clock(skew: Duration, offsets: TimeNamespaceOffsets, report: &mut HealthReport) {
+    if skew <= WALL_CLOCK_TOLERANCE {
+        return;
+    }
+    report.warnings.push(format!(
+        "event timestamps convert to wall-clock time {:.3}s off (time namespace monotonic \
+         offset {}ns); wall-clock times from to_wallclock are unreliable",
+        skew.as_secs_f64(),
+        offsets.monotonic_ns
+    ));
+}
+
+/// Warnings for CPU readers that gave up, see `CollectorConfig::reader_max_retries`
+pub(crate) fn failed_readers(cpus: &[u32], max_retries: u32, report: &mut HealthReport) {
+    for cpu in cpus {
+        report.warnings.push(format!(
+            "perf reader on CPU {} gave up after {} read errors in a row, its events are \
+             no longer counted; verify_attachments() or a reload restarts it",
+            cpu, max_retries
+        ));
+    }
+}
+
+pub(crate) fn capture(status: &CaptureStatus, report: &mut HealthReport) {
+    if let Some(error) = &status.last_error {
+        report.warnings.push(format!(
+            "{} triggered capture(s) could not be written, the last: {}",
+            status.failed, error
+        ));
+    }
+}
+
+pub(crate) fn overhead(status: &OverheadStatus, report: &mut HealthReport) {
+    let degradation = &status.degradation;
+    if degradation.level == 0 {
+        return;
+    }
+    let mut degraded = Vec::new();
+    if degradation.sample_shift > 0 {
+        degraded.push(format!(
+            "occupancy samplers at 1 in {}",
+            100u64 << degradation.sample_shift
+        ));
+    }
+    if !status.disabled_probes.is_empty() {
+        degraded.push(format!("detached {}", status.disabled_probes.join(", ")));
+    }
+    let masked: Vec<&str> = (0..32)
+        .filter(|event_type| degradation.event_mask & (1 << event_type) != 0)
+        .map(event_type_name)
+        .collect();
+    if !masked.is_empty() {
+        degraded.push(format!("not sending {} events", masked.join(", ")));
+    }
+    report.warnings.push(format!(
+        "collector over its {:.2}% CPU budget, degraded to level {} of {}: {}",
+        status.budget_percent,
+        degradation.level,
+        status.levels,
+        degraded.join("; ")
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, Replay};
+    use crate::{ManualClock, EVENT_SOCKET_STATE, EVENT_UDP_SEND};
+
+    const PROC_STAT: &str = "\
+cpu  100 0 50 1000 0 0 30 0 0 0
+cpu0 50 0 25 500 0 0 10 0 0 0
+cpu1 50 0 25 500 0 0 20 0 0 0
+intr 12345
+softirq 999 1 2 3
+";
+
+    #[test]
+    fn proc_stat_softirq_sums_the_per_cpu_lines() {
+        assert_eq!(softirq_jiffies(PROC_STAT), 30);
+        assert_eq!(softirq_jiffies("cpu0 1 2 3\n"), 0);
+    }
+
+    #[test]
+    fn softirq_disagreement_past_a_fifth_warns() {
+        let mut check = SoftirqCrossCheck::new(Arc::new(ManualClock::new()));
+        let mut report = HealthReport::default();
+        // The first call only takes the baseline
+        check.compare(0, 1_000_000_000, &mut report);
+        check.compare(90_000_000, 1_100_000_000, &mut report);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+
+        check.compare(140_000_000, 1_200_000_000, &mut report);
+        assert_eq!(report.warnings.len(), 1);
+        assert!(
+            report.warnings[0].contains("by 50%"),
+            "{}",
+            report.warnings[0]
+        );
+    }
+
+    #[test]
+    fn too_little_proc_stat_time_is_not_compared() {
+        let mut check = SoftirqCrossCheck::new(Arc::new(ManualClock::new()));
+        let mut report = HealthReport::default();
+        check.compare(0, 0, &mut report);
+        check.compare(0, SOFTIRQ_MIN_PROC_NS - 1, &mut report);
+        assert!(report.warnings.is_empty());
+    }
+
+    // Replayed events are kernel timestamps in whole seconds here
+    const SECOND: u64 = 1_000_000_000;
+
+    fn staleness_report(
+        check: &mut StalenessCheck,
+        replay: &Replay,
+        now_secs: u64,
+    ) -> HealthReport {
+        let mut report = HealthReport::default();
+        let last_seen = replay.interval.last_seen();
+        check.check_at(
+            now_secs * SECOND,
+            &last_seen,
+            Duration::from_secs(5),
+            &mut report,
+        );
+        report
+    }
+
+    #[test]
+    fn a_gap_in_one_probe_is_flagged_until_it_recovers() {
+        let replay = Replay::new(crate::CollectorConfig::default(), 1);
+        let clock = ManualClock::new();
+        let mut check = StalenessCheck::new(Arc::new(clock.clone()));
+        replay.feed(&[
+            fixtures::udp_send(SECOND, 0, 7, 1200),
+            fixtures::socket_state(SECOND, 0, 7, 1000, 212_992),
+        ]);
+        let report = staleness_report(&mut check, &replay, 2);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        assert_eq!(report.last_seen_age_secs[&EVENT_UDP_SEND], 1.0);
+
+        // Sends go on, socket state samples stop
+        replay.feed(&[fixtures::udp_send(9 * SECOND, 0, 7, 1200)]);
+        let report = staleness_report(&mut check, &replay, 10);
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].starts_with("no socket_state events for 9s"));
+        assert!(check.last_logged.contains_key(&EVENT_SOCKET_STATE));
+
+        // Still reported, though logged once per window
+        clock.advance(Duration::from_secs(1));
+        let logged_at = check.last_logged[&EVENT_SOCKET_STATE];
+        assert_eq!(staleness_report(&mut check, &replay, 11).warnings.len(), 1);
+        assert_eq!(check.last_logged[&EVENT_SOCKET_STATE], logged_at);
+
+        replay.feed(&[fixtures::socket_state(11 * SECOND, 0, 7, 1000, 212_992)]);
+        assert!(staleness_report(&mut check, &replay, 12)
+            .warnings
+            .is_empty());
+        assert!(check.last_logged.is_empty());
+    }
+
+    #[test]
+    fn conditions_and_a_quiet_host_are_not_stale() {
+        let replay = Replay::new(crate::CollectorConfig::default(), 1);
+        let mut check = StalenessCheck::new(Arc::new(ManualClock::new()));
+        replay.feed(&[
+            fixtures::qdisc_drop(SECOND, 0, 1, 0),
+            fixtures::udp_send(SECOND, 0, 7, 1200),
+        ]);
+        // Drops stopping is good news
+        replay.feed(&[fixtures::udp_send(20 * SECOND, 0, 7, 1200)]);
+        assert!(staleness_report(&mut check, &replay, 21)
+            .warnings
+            .is_empty());
+        // Nothing at all for a while is idle, not a detached probe
+        assert!(staleness_report(&mut check, &replay, 60)
+            .warnings
+            .is_empty());
+    }
+
+    fn sanity(check: &mut SampleSanityCheck, implausible: u64, plausible: u64) -> Vec<String> {
+        let mut report = HealthReport::default();
+        check.check(implausible, plausible, &mut report);
+        report.warnings
+    }
+
+    #[test]
+    fn mostly_implausible_samples_flag_an_offset_mismatch() {
+        let mut check = SampleSanityCheck::new();
+        // The first call only takes the baseline, however bad
+        assert!(sanity(&mut check, 1000, 0).is_empty());
+        let warnings = sanity(&mut check, 1060, 40);
+        assert_eq!(warnings.len(), 1);
+        assert!(
+            warnings[0].starts_with(
+                "probable offset mismatch: 60 of 100 socket buffer samples implausible (60%)"
+            ),
+            "{}",
+            warnings[0]
+        );
+    }
+
+    #[test]
+    fn the_ratio_is_taken_over_the_period_not_since_load() {
+        let mut check = SampleSanityCheck::new();
+        sanity(&mut check, 0, 0);
+        assert_eq!(sanity(&mut check, 900, 100).len(), 1);
+        // Cumulatively still 90% implausible, but this period was clean
+        assert!(sanity(&mut check, 900, 1100).is_empty());
+        // Exactly half is not above it
+        assert!(sanity(&mut check, 1000, 1200).is_empty());
+        assert_eq!(sanity(&mut check, 1051, 1249).len(), 1);
+    }
+
+    #[test]
+    fn too_few_samples_say_nothing() {
+        let mut check = SampleSanityCheck::new();
+        sanity(&mut check, 0, 0);
+        assert!(sanity(&mut check, IMPLAUSIBLE_MIN_SAMPLES - 1, 0).is_empty());
+        // A counter reset (reload) doesn't wrap into a huge delta
+        assert!(sanity(&mut check, 0, 0).is_empty());
+    }
+
+    #[test]
+    fn replayed_socket_samples_count_as_plausible() {
+        let replay = Replay::new(crate::CollectorConfig::default(), 1);
+        let mut check = SampleSanityCheck::new();
+        let plausible = || {
+            replay
+                .signals
+                .socket_samples_total
+                .load(std::sync::atomic::Ordering::Relaxed)
+        };
+        sanity(&mut check, 0, plausible());
+
+        let samples: Vec<_> = (0..30)
+            .map(|i| fixtures::socket_state(SECOND + i, 0, 7, 50_000, 212_992))
+            .collect();
+        replay.feed(&samples);
+        assert_eq!(plausible(), 30);
+        // 20 discarded in the kernel next to 30 decoded
+        assert!(sanity(&mut check, 20, plausible()).is_empty());
+
+        replay.feed(&samples[..10]);
+        // 40 more discarded next to 10 decoded
+        let warnings = sanity(&mut check, 60, plausible());
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("40 of 50"), "{}", warnings[0]);
+    }
+
+    /// A sysfs with tracing directories made as `mounted` says, tracefs
+    /// showing through as the events/ directory
+    fn mock_sysfs(test: &str, mounted: [bool; 2]) -> Vec<std::path::PathBuf> {
+        let dir = fixtures::scratch_dir(test);
+        let roots = [dir.join("tracing"), dir.join("debug/tracing")];
+        for (root, mounted) in roots.iter().zip(mounted) {
+            std::fs::create_dir_all(root).unwrap();
+            if mounted {
+                std::fs::create_dir(root.join("events")).unwrap();
+            }
+        }
+        roots.into()
+    }
+
+    #[test]
+    fn tracefs_is_looked_for_before_debugfs() {
+        let found = |test, mounted| {
+            let roots = mock_sysfs(test, mounted);
+            first_tracefs(roots.iter().map(|root| root.as_path()))
+                .map(|root| roots.iter().position(|r| r == root).unwrap())
+        };
+        assert_eq!(found("tracefs-both", [true, true]), Some(0));
+        assert_eq!(found("tracefs-debugfs", [false, true]), Some(1));
+        assert_eq!(found("tracefs-standalone", [true, false]), Some(0));
+        // Mount points alone, as in a container with neither mounted
+        assert_eq!(found("tracefs-none", [false, false]), None);
+    }
+
+    #[test]
+    fn a_missing_tracefs_says_how_to_get_it() {
+        let mut report = HealthReport::default();
+        tracefs_missing(&[Signal::Drops, Signal::Softirq], &mut report);
+        assert_eq!(report.warnings.len(), 1);
+        let warning = &report.warnings[0];
+        assert!(warning.contains("(Drops, Softirq)"), "{warning}");
+        assert!(warning.contains("mount -t tracefs"), "{warning}");
+        assert!(warning.contains("--privileged"), "{warning}");
+        for root in TRACEFS_ROOTS {
+            assert!(warning.contains(root), "{warning}");
+        }
+    }
+}
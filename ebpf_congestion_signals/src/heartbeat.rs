@@ -0,0 +1,431 @@
+//Self-monitoring of the event path. An interval of zeros reads the same
+//whether nothing was sent or the collector broke: a reader wedged, a probe
+//detached, the processing side stuck. With `CollectorConfig::with_heartbeat`
+//the collector sends itself a tiny UDP datagram every period, on a loopback
+//socket of its own, and expects the probe's send event for it back from the
+//readers within the deadline. The round trip, from just before the send to a
+//reader taking the event, is the event path's latency.
+//
+//The kernel knows the socket by its cookie (HEARTBEAT_SOCKET, set at load)
+//and outputs all of its sends, unsampled and ahead of the loopback exclusion,
+//as trace-only samples, which nothing counts. The readers take every event of
+//the cookie out first, before a counter, the pipeline, subscribers or capture
+//see it. What's left of the heartbeat elsewhere is the few µs of NET_RX its
+//loopback delivery takes and its samples in `ReaderStats::events_read`, the
+//perf buffers' own count.
+//
+//Beats are told apart by their length, 1 to 32 bytes by sequence, so a late
+//one doesn't pass for the next. `max_missed` beats in a row unseen mark the
+//pipeline dead and raise a `Component::Heartbeat` failure on
+//`CongestionCollector::failures()`; the next one seen marks it alive again.
+//
+//The beats run as a supervised task, so all of it but `HeartbeatStatus` is
+//async-runtime only: blocking builds have no knob to set and leave
+//`HealthReport::heartbeat` at None.
+
+#[cfg(feature = "async-runtime")]
+use crate::runtime::{MissedTicks, Rt, Runtime, Ticker};
+#[cfg(feature = "async-runtime")]
+use crate::supervisor::{Component, ComponentFailure, FailureReporter};
+#[cfg(feature = "async-runtime")]
+use crate::{event_socket, socket_cookie, Clock, CongestionEvent, EVENT_UDP_SEND};
+#[cfg(feature = "async-runtime")]
+use std::io;
+#[cfg(feature = "async-runtime")]
+use std::net::{Ipv4Addr, UdpSocket};
+#[cfg(feature = "async-runtime")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "async-runtime")]
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+#[cfg(feature = "async-runtime")]
+use std::time::Instant;
+
+// Beat lengths cycle through 1..=BEAT_LENGTHS bytes
+#[cfg(feature = "async-runtime")]
+const BEAT_LENGTHS: u64 = 32;
+
+/// Turns on the heartbeat, see `CollectorConfig::with_heartbeat`
+#[cfg(feature = "async-runtime")]
+#[derive(Debug, Clone)]
+pub struct HeartbeatConfig {
+    /// Between beats
+    pub period: Duration,
+    /// How long a beat has to come through, at most `period`
+    pub deadline: Duration,
+    /// Beats in a row unseen before the pipeline counts as dead
+    pub max_missed: u32,
+}
+
+#[cfg(feature = "async-runtime")]
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            period: Duration::from_secs(1),
+            deadline: Duration::from_millis(500),
+            max_missed: 3,
+        }
+    }
+}
+
+/// What the heartbeat saw, in `HealthReport::heartbeat`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HeartbeatStatus {
+    /// False once `max_missed` beats in a row didn't come through, true again
+    /// at the next one that does
+    pub pipeline_alive: bool,
+    /// From the latest beat that came through being sent to a reader taking
+    /// its event. None before one has
+    pub last_round_trip: Option<Duration>,
+    pub sent: u64,
+    pub seen: u64,
+    /// Beats that didn't come through by their deadline, and of those the
+    /// ones since the last that did
+    pub missed: u64,
+    pub consecutive_missed: u64,
+    /// Events of the heartbeat socket kept out of the signals: its sends,
+    /// late ones included, and its receive and lifecycle samples
+    pub excluded_events: u64,
+}
+
+#[cfg(feature = "async-runtime")]
+struct Beat {
+    length: u64,
+    sent_at: Instant,
+}
+
+#[cfg(feature = "async-runtime")]
+struct State {
+    outstanding: Option<Beat>,
+    status: HeartbeatStatus,
+    // The current run of misses was raised already
+    escalated: bool,
+}
+
+/// Matches the heartbeat socket's events to its beats and decides when the
+/// pipeline is dead, on `clock`: `sent` as each beat goes out, `observe` for
+/// every event read, `check` once the deadline passed
+#[cfg(feature = "async-runtime")]
+pub struct HeartbeatMonitor {
+    // 0 until armed, cookies start at 1
+    cookie: AtomicU64,
+    deadline: Duration,
+    max_missed: u64,
+    clock: Arc<dyn Clock>,
+    state: Mutex<State>,
+}
+
+#[cfg(feature = "async-runtime")]
+impl HeartbeatMonitor {
+    pub fn new(config: &HeartbeatConfig, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            cookie: AtomicU64::new(0),
+            deadline: config.deadline.min(config.period),
+            max_missed: config.max_missed.max(1) as u64,
+            clock,
+            state: Mutex::new(State {
+                outstanding: None,
+                status: HeartbeatStatus {
+                    pipeline_alive: true,
+                    ..Default::default()
+                },
+                escalated: false,
+            }),
+        }
+    }
+
+    /// Take socket `cookie`'s events as the heartbeat's
+    pub fn arm(&self, cookie: u64) {
+        self.cookie.store(cookie, Ordering::Relaxed);
+    }
+
+    /// A beat goes out now: the length to send it with. One still
+    /// outstanding counts as missed
+    pub fn sent(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        if state.outstanding.take().is_some() {
+            self.miss(&mut state);
+        }
+        let length = state.status.sent % BEAT_LENGTHS + 1;
+        state.status.sent += 1;
+        state.outstanding = Some(Beat {
+            length,
+            sent_at: self.clock.now(),
+        });
+        length as usize
+    }
+
+    /// Whether `event` is the heartbeat socket's, to be left out of
+    /// everything else. A send of the outstanding beat's length is that beat
+    /// coming through
+    pub fn observe(&self, event: &CongestionEvent) -> bool {
+        let cookie = self.cookie.load(Ordering::Relaxed);
+        if cookie == 0 || event_socket(event).map(|(id, _)| id) != Some(cookie) {
+            return false;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.status.excluded_events += 1;
+        let bytes = match event.event_type {
+            EVENT_UDP_SEND => unsafe { event.data.sendmsg.bytes },
+            _ => return true,
+        };
+        if let Some(beat) = state.outstanding.take_if(|beat| beat.length == bytes) {
+            let status = &mut state.status;
+            status.seen += 1;
+            status.last_round_trip = Some(self.clock.now().saturating_duration_since(beat.sent_at));
+            status.consecutive_missed = 0;
+            status.pipeline_alive = true;
+            state.escalated = false;
+        }
+        true
+    }
+
+    /// Count the outstanding beat missed once its deadline passed. The
+    /// failure to raise the first time `max_missed` were missed in a row,
+    /// None otherwise
+    pub fn check(&self) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        let now = self.clock.now();
+        if let Some(beat) = &state.outstanding {
+            if now.saturating_duration_since(beat.sent_at) >= self.deadline {
+                state.outstanding = None;
+                self.miss(&mut state);
+            }
+        }
+        if state.status.pipeline_alive || state.escalated {
+            return None;
+        }
+        state.escalated = true;
+        Some(format!(
+            "{} heartbeats in a row didn't come through the readers within {:?}",
+            state.status.consecutive_missed, self.deadline
+        ))
+    }
+
+    fn miss(&self, state: &mut State) {
+        state.status.missed += 1;
+        state.status.consecutive_missed += 1;
+        if state.status.consecutive_missed >= self.max_missed {
+            state.status.pipeline_alive = false;
+        }
+    }
+
+    pub fn status(&self) -> HeartbeatStatus {
+        self.state.lock().unwrap().status
+    }
+}
+
+/// The heartbeat's loopback socket and its cookie. Connected to itself: what
+/// it sends it receives, and drains before each beat
+#[cfg(feature = "async-runtime")]
+pub(crate) fn open_socket() -> io::Result<(UdpSocket, u64)> {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+    socket.connect(socket.local_addr()?)?;
+    socket.set_nonblocking(true)?;
+    let cookie = socket_cookie(&socket)?;
+    Ok((socket, cookie))
+}
+
+/// Beat every `config.period` until cancelled, raising what `check` returns
+/// as a `Component::Heartbeat` failure. Runs under the supervisor
+#[cfg(feature = "async-runtime")]
+pub(crate) async fn run(
+    socket: Arc<UdpSocket>,
+    monitor: Arc<HeartbeatMonitor>,
+    config: HeartbeatConfig,
+    reporter: FailureReporter,
+) -> anyhow::Result<()> {
+    let mut ticker = Rt::interval(config.period, MissedTicks::Delay);
+    let mut buf = [0u8; BEAT_LENGTHS as usize];
+    loop {
+        ticker.tick().await;
+        while socket.recv(&mut buf).is_ok() {}
+        let length = monitor.sent();
+        if let Err(e) = socket.send(&[0; BEAT_LENGTHS as usize][..length]) {
+            // The beat counts as missed, which is what to report
+            log::debug!("heartbeat send: {}", e);
+        }
+        Rt::sleep(monitor.deadline).await;
+        if let Some(error) = monitor.check() {
+            reporter.report(ComponentFailure {
+                component: Component::Heartbeat,
+                error,
+                panicked: false,
+                restarted: false,
+            });
+        }
+    }
+}
+
+#[cfg(all(test, feature = "async-runtime"))]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures, replay_per_socket, CollectorConfig, ManualClock, SoftirqAttributionConfig,
+        SoftirqAttributionTracker, SAMPLER_TRACE,
+    };
+
+    // The heartbeat socket's cookie, and two others, only the first
+    // registered for the NET_TX split
+    const HEARTBEAT: u64 = 11;
+    const OURS: u64 = 7;
+    const THEIRS: u64 = 9;
+    const NET_TX_SOFTIRQ: u32 = 2;
+
+    // A heartbeat send as the kernel outputs it: trace-only, ahead of sampling
+    fn beat(timestamp_ns: u64, length: usize) -> CongestionEvent {
+        let mut event = fixtures::udp_send(timestamp_ns, 0, HEARTBEAT, length as u64);
+        event.data.sendmsg.loopback = 1;
+        event.data.sendmsg.samplers = SAMPLER_TRACE;
+        event
+    }
+
+    fn split(events: &[CongestionEvent]) -> crate::SoftirqAttribution {
+        let mut tracker = SoftirqAttributionTracker::new(&SoftirqAttributionConfig::default());
+        tracker.register(OURS);
+        for event in events {
+            tracker.record(event);
+        }
+        tracker.take_interval()
+    }
+
+    #[test]
+    fn only_the_heartbeats_events_are_taken_out() {
+        let clock = Arc::new(ManualClock::new());
+        let monitor = HeartbeatMonitor::new(&HeartbeatConfig::default(), clock.clone());
+        monitor.arm(HEARTBEAT);
+        let (mut traffic, mut mixed, mut kept) = (Vec::new(), Vec::new(), Vec::new());
+        for i in 0..200u64 {
+            let ts = 1_000_000_000 + i * 1_000_000;
+            let socket = if i % 3 == 0 { OURS } else { THEIRS };
+            let send = fixtures::udp_send(ts, (i % 4) as u32, socket, 1200);
+            let round = (i % 10 == 9)
+                .then(|| fixtures::softirq_exit(ts + 500_000, 0, NET_TX_SOFTIRQ, 200_000));
+            traffic.push(send);
+            traffic.extend(round);
+            let mut events = vec![send];
+            if i % 50 == 49 {
+                // A beat ahead of the NET_TX round: its send and its receive
+                let length = monitor.sent();
+                clock.advance(Duration::from_micros(150));
+                events.push(beat(ts + 100_000, length));
+                events.push(fixtures::rcv_state(
+                    ts + 120_000,
+                    0,
+                    HEARTBEAT,
+                    768,
+                    212_992,
+                ));
+            }
+            events.extend(round);
+            for event in events {
+                mixed.push(event);
+                if !monitor.observe(&event) {
+                    kept.push(event);
+                }
+            }
+        }
+
+        assert_eq!(kept.len(), traffic.len());
+        assert!(kept
+            .iter()
+            .zip(&traffic)
+            .all(|(a, b)| a.timestamp_ns == b.timestamp_ns && a.event_type == b.event_type));
+        let config = CollectorConfig::default();
+        let sockets = replay_per_socket(&kept, &config);
+        let alone = replay_per_socket(&traffic, &config);
+        assert!(!sockets.contains_key(&HEARTBEAT));
+        assert_eq!(sockets.len(), alone.len());
+        for (id, socket) in &sockets {
+            assert_eq!(socket.send_bytes, alone[id].send_bytes);
+        }
+        // Left in, the beats' bytes would shift the split
+        assert_eq!(split(&kept), split(&traffic));
+        assert_ne!(split(&mixed), split(&traffic));
+
+        let status = monitor.status();
+        assert_eq!((status.sent, status.seen, status.missed), (4, 4, 0));
+        assert_eq!(status.excluded_events, 8);
+        assert!(status.pipeline_alive);
+        assert_eq!(status.last_round_trip, Some(Duration::from_micros(150)));
+    }
+
+    #[test]
+    fn a_stall_is_raised_once_and_the_next_beat_through_clears_it() {
+        let config = HeartbeatConfig {
+            period: Duration::from_secs(1),
+            deadline: Duration::from_millis(300),
+            max_missed: 3,
+        };
+        let clock = Arc::new(ManualClock::new());
+        let monitor = HeartbeatMonitor::new(&config, clock.clone());
+        monitor.arm(HEARTBEAT);
+        let mut failures = Vec::new();
+        // One period: a beat, its event back from the mocked readers `delay`
+        // later (never with None) after the event of beat `stale` if given,
+        // and the check at the deadline
+        let mut period = |delay: Option<Duration>, stale: Option<usize>| {
+            let length = monitor.sent();
+            let now_ns = || clock.elapsed().as_nanos() as u64;
+            if let Some(stale) = stale {
+                monitor.observe(&beat(now_ns(), stale));
+            }
+            let delay = delay.unwrap_or(config.period);
+            if delay < config.deadline {
+                clock.advance(delay);
+                monitor.observe(&beat(now_ns(), length));
+                clock.advance(config.deadline - delay);
+                failures.extend(monitor.check());
+            } else {
+                clock.advance(config.deadline);
+                failures.extend(monitor.check());
+                if delay < config.period {
+                    clock.advance(delay - config.deadline);
+                    monitor.observe(&beat(now_ns(), length));
+                }
+            }
+            clock.advance(config.period.saturating_sub(delay.max(config.deadline)));
+            (monitor.status(), failures.len(), length)
+        };
+        let fast = Some(Duration::from_millis(40));
+        period(fast, None);
+        let (flowing, ..) = period(fast, None);
+        assert!(flowing.pipeline_alive);
+        assert_eq!(flowing.seen, 2);
+        assert_eq!(flowing.last_round_trip, Some(Duration::from_millis(40)));
+
+        period(None, None);
+        let (two_missed, ..) = period(None, None);
+        assert!(two_missed.pipeline_alive);
+        assert_eq!(two_missed.consecutive_missed, 2);
+        let (dead, raised, _) = period(None, None);
+        assert!(!dead.pipeline_alive);
+        assert_eq!(raised, 1);
+        let (still_dead, raised, _) = period(None, None);
+        assert_eq!(still_dead.consecutive_missed, 4);
+        assert_eq!(raised, 1);
+
+        // Past the deadline, within the period, then again a period late
+        let (late, _, late_length) = period(Some(Duration::from_millis(700)), None);
+        assert!(!late.pipeline_alive);
+        assert_eq!((late.seen, late.consecutive_missed), (2, 5));
+        let (stale, ..) = period(None, Some(late_length));
+        assert_eq!((stale.seen, stale.consecutive_missed), (2, 6));
+        assert_eq!(stale.excluded_events, 4);
+
+        let (back, ..) = period(Some(Duration::from_millis(10)), None);
+        assert!(back.pipeline_alive);
+        assert_eq!((back.seen, back.consecutive_missed), (3, 0));
+        assert_eq!(back.last_round_trip, Some(Duration::from_millis(10)));
+
+        period(None, None);
+        period(None, None);
+        let (stalled_again, raised, _) = period(None, None);
+        assert!(!stalled_again.pipeline_alive);
+        assert_eq!(raised, 2);
+        assert_eq!((stalled_again.sent, stalled_again.missed), (12, 9));
+        assert!(failures
+            .iter()
+            .all(|message| message.starts_with("3 heartbeats in a row")));
+    }
+}
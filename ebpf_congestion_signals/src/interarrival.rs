@@ -0,0 +1,271 @@
+//Time between drops. Interval totals say how many packets were lost, not
+//whether they went one every so often, as random loss does, or thirty back to
+//back and then nothing for a while: FEC answers the first, smoothing the
+//bursts the second. The processing side takes the gap from each qdisc drop to
+//the one before it, on any CPU, into a histogram of 1-2-5 buckets from 10 µs
+//to 10 s, and the gaps' coefficient of variation as a burstiness index: 0 for
+//evenly spaced drops, about 1 for drops at random (exponential gaps), well
+//above 1 for bursts with quiet in between.
+//
+//A coalesced event (`CollectorConfig::drop_coalescing`) stands for `dropped`
+//drops over the span before its timestamp, which is the last one's. The gaps
+//inside a run are taken as even, span / (dropped - 1), so a run's gaps land
+//together in a bucket under `max_span`; `without_drop_coalescing` gives each
+//drop its own time.
+//
+//Gaps across CPUs need timestamp order, which `with_drop_interarrival` gets by
+//turning on the reordering stage. A drop that still comes in behind the last
+//one counts a gap of 0. A gap across an interval read counts in the interval
+//of the later drop. rcvbuf drops are left out, they're the receive side's.
+
+use crate::{CongestionEvent, EVENT_QDISC_DROP};
+
+const BUCKETS: usize = 20;
+
+/// Gaps between the qdisc drops of one interval, see
+/// `CollectorConfig::with_drop_interarrival`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DropInterarrival {
+    /// Gaps measured: one per drop after the first the collector saw
+    pub gaps: u64,
+    /// Gaps under 10 µs, then between consecutive `BUCKET_LIMITS_NS`, then
+    /// 10 s and over
+    pub histogram: [u64; BUCKETS],
+    pub mean_gap_ns: f64,
+    /// Coefficient of variation of the gaps, standard deviation over mean: 0
+    /// for evenly spaced drops, about 1 for random ones, above that for
+    /// bursts. 0 with fewer than two gaps
+    pub burstiness: f64,
+}
+
+impl DropInterarrival {
+    /// Upper bounds of the histogram buckets but the last, ns
+    pub const BUCKET_LIMITS_NS: [u64; BUCKETS - 1] = [
+        10_000,
+        20_000,
+        50_000,
+        100_000,
+        200_000,
+        500_000,
+        1_000_000,
+        2_000_000,
+        5_000_000,
+        10_000_000,
+        20_000_000,
+        50_000_000,
+        100_000_000,
+        200_000_000,
+        500_000_000,
+        1_000_000_000,
+        2_000_000_000,
+        5_000_000_000,
+        10_000_000_000,
+    ];
+
+    /// Index of the histogram bucket a gap of `gap_ns` counts in
+    pub fn bucket(gap_ns: u64) -> usize {
+        Self::BUCKET_LIMITS_NS.partition_point(|&limit| limit <= gap_ns)
+    }
+
+    /// Fold in another interval's gaps, as a session's merge does
+    pub fn add(&mut self, other: &DropInterarrival) {
+        let (m2, m2_other) = (self.m2(), other.m2());
+        let mut moments = Moments {
+            count: self.gaps,
+            mean: self.mean_gap_ns,
+            m2,
+        };
+        moments.merge(other.gaps, other.mean_gap_ns, m2_other);
+        for (bucket, n) in self.histogram.iter_mut().zip(other.histogram) {
+            *bucket += n;
+        }
+        *self = moments.finish(self.histogram);
+    }
+
+    // Sum of squared deviations, back from the mean and burstiness
+    fn m2(&self) -> f64 {
+        let deviation = self.burstiness * self.mean_gap_ns;
+        deviation * deviation * self.gaps as f64
+    }
+}
+
+// Running mean and sum of squared deviations, merged a batch of equal gaps or
+// another interval at a time
+#[derive(Default)]
+struct Moments {
+    count: u64,
+    mean: f64,
+    m2: f64,
+}
+
+impl Moments {
+    fn merge(&mut self, count: u64, mean: f64, m2: f64) {
+        if count == 0 {
+            return;
+        }
+        let total = self.count + count;
+        let delta = mean - self.mean;
+        self.mean += delta * count as f64 / total as f64;
+        self.m2 += m2 + delta * delta * self.count as f64 * count as f64 / total as f64;
+        self.count = total;
+    }
+
+    fn finish(&self, histogram: [u64; BUCKETS]) -> DropInterarrival {
+        let burstiness = if self.count >= 2 && self.mean > 0.0 {
+            (self.m2 / self.count as f64).sqrt() / self.mean
+        } else {
+            0.0
+        };
+        DropInterarrival {
+            gaps: self.count,
+            histogram,
+            mean_gap_ns: self.mean,
+            burstiness,
+        }
+    }
+}
+
+/// The gaps on their own, e.g. over a recording's drops. Feed it events in
+/// timestamp order with `record` and take the interval with `take_interval`
+#[derive(Default)]
+pub struct DropInterarrivalTracker {
+    last_drop_ns: Option<u64>,
+    histogram: [u64; BUCKETS],
+    moments: Moments,
+}
+
+impl DropInterarrivalTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, event: &CongestionEvent) {
+        if event.event_type != EVENT_QDISC_DROP {
+            return;
+        }
+        let qdisc = unsafe { event.data.qdisc };
+        let dropped = qdisc.dropped.max(1) as u64;
+        let span_ns = if dropped > 1 {
+            qdisc.backlog_bytes as u64
+        } else {
+            0
+        };
+        let first_ns = event.timestamp_ns.saturating_sub(span_ns);
+        if let Some(last_ns) = self.last_drop_ns {
+            self.gaps(first_ns.saturating_sub(last_ns), 1);
+        }
+        if dropped > 1 {
+            self.gaps(span_ns / (dropped - 1), dropped - 1);
+        }
+        self.last_drop_ns = Some(self.last_drop_ns.unwrap_or(0).max(event.timestamp_ns));
+    }
+
+    // `count` gaps of `gap_ns` each
+    fn gaps(&mut self, gap_ns: u64, count: u64) {
+        self.histogram[DropInterarrival::bucket(gap_ns)] += count;
+        self.moments.merge(count, gap_ns as f64, 0.0);
+    }
+
+    /// The gaps since the last call. The last drop stays, the next interval's
+    /// first gap is from it
+    pub fn take_interval(&mut self) -> DropInterarrival {
+        let interval = self.moments.finish(self.histogram);
+        self.histogram = [0; BUCKETS];
+        self.moments = Moments::default();
+        interval
+    }
+
+    /// Forget the last drop, e.g. after the kernel clock jumped
+    pub fn reset(&mut self) {
+        self.last_drop_ns = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    fn replay(events: &[CongestionEvent]) -> DropInterarrival {
+        let mut tracker = DropInterarrivalTracker::new();
+        for event in events {
+            tracker.record(event);
+        }
+        tracker.take_interval()
+    }
+
+    /// 100 drops 1 ms apart
+    fn uniform() -> Vec<CongestionEvent> {
+        (1..=100u64)
+            .map(|n| fixtures::qdisc_drop(n * 1_000_000, (n % 2) as u32, 1, 0))
+            .collect()
+    }
+
+    fn burst_start(burst: u64) -> u64 {
+        (burst + 1) * 100_000_000
+    }
+
+    /// 4 bursts of 25 drops 20 µs apart, 100 ms between them
+    fn bursts() -> Vec<CongestionEvent> {
+        (0..4u64)
+            .flat_map(|burst| {
+                (0..25u64)
+                    .map(move |n| fixtures::qdisc_drop(burst_start(burst) + n * 20_000, 0, 1, 0))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn burstiness_tells_uniform_random_and_bursty_trains_apart() {
+        let even = replay(&uniform());
+        assert_eq!(even.gaps, 99);
+        assert_eq!(even.histogram[DropInterarrival::bucket(1_000_000)], 99);
+        assert!(even.burstiness < 1e-9);
+
+        // 2000 exponential gaps with a 1 ms mean from a fixed xorshift, so
+        // every run replays the same
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut at_ns = 0u64;
+        let random: Vec<CongestionEvent> = (0..2000)
+            .map(|n| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                let u = (state >> 11) as f64 / (1u64 << 53) as f64;
+                at_ns += (-(1.0 - u).ln() * 1_000_000.0) as u64;
+                fixtures::qdisc_drop(at_ns, n % 4, 1, 0)
+            })
+            .collect();
+        let random = replay(&random);
+        assert!((0.85..=1.15).contains(&random.burstiness), "{random:?}");
+
+        let bursty = replay(&bursts());
+        assert_eq!(bursty.gaps, 99);
+        assert_eq!(bursty.histogram[DropInterarrival::bucket(20_000)], 96);
+        assert!(bursty.burstiness > 3.0);
+    }
+
+    #[test]
+    fn a_coalesced_burst_reads_as_its_single_drops() {
+        // One event a burst, spanning its 24 gaps
+        let coalesced: Vec<CongestionEvent> = (0..4u64)
+            .map(|burst| fixtures::qdisc_drop(burst_start(burst) + 24 * 20_000, 0, 25, 24 * 20_000))
+            .collect();
+        let (single, coalesced) = (replay(&bursts()), replay(&coalesced));
+        assert_eq!(coalesced.gaps, single.gaps);
+        assert_eq!(coalesced.histogram, single.histogram);
+        assert!((coalesced.burstiness - single.burstiness).abs() < 1e-9);
+    }
+
+    #[test]
+    fn two_halves_added_give_the_whole() {
+        let uniform = uniform();
+        let mut tracker = DropInterarrivalTracker::new();
+        uniform[..50].iter().for_each(|event| tracker.record(event));
+        let mut halves = tracker.take_interval();
+        // The gap across the read counts in the second half
+        uniform[50..].iter().for_each(|event| tracker.record(event));
+        halves.add(&tracker.take_interval());
+        assert_eq!(halves, replay(&uniform));
+    }
+}
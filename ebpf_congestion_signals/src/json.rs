@@ -0,0 +1,653 @@
+//JSON by hand, the crate has no serde: one object per record, numbers that
+//aren't finite and absent optionals as null. What each document holds is
+//the serde model of export_model; parse_json here is for tests that read a
+//document back without it.
+
+#[cfg(not(feature = "no-float"))]
+use crate::redact::Redaction;
+use crate::DropInterarrival;
+// What the signals' writer reads, left out with their f64 fields
+#[cfg(not(feature = "no-float"))]
+use crate::{
+    CaptureNotice, CongestionSignals, ConnectionChurn, EstimatedTotals, GsoSegments,
+    ObservedCounts, ProtocolCounts, SamplerEstimates, SendConcentration, SendSizes,
+    SoftirqAttribution, SoftirqCoupling, EXPORT_SCHEMA_VERSION,
+};
+#[cfg(not(feature = "no-float"))]
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(not(feature = "no-float"))]
+impl CongestionSignals {
+    /// Every field as one JSON object, no trailing newline. Wall-clock bounds
+    /// are milliseconds since the epoch
+    pub fn to_json(&self) -> String {
+        self.to_json_redacted(&Redaction::none())
+    }
+
+    /// `to_json` with the socket ids in `send_concentration` redacted
+    pub fn to_json_redacted(&self, redaction: &Redaction) -> String {
+        let mut out = String::new();
+        let _ = write!(
+            out,
+            "{{\"schema_version\":{},\"interval_ns\":{},\"aligned_start_ms\":{},\"aligned_end_ms\":{},\
+             \"observed\":{},\"estimated\":{},\"send_bytes\":{},\"loopback_send_bytes\":{},\"external_send_bytes\":{},\
+             \"udp_send_sizes\":{},\"tcp_send_sizes\":{},\
+             \"est_send_msgs\":{},\"est_wire_packets\":{},\"udp_gso\":{},\"drops\":{},\
+             \"avg_wmem_pressure\":{},\"udp_wmem_pressure\":{},\"tcp_wmem_pressure\":{},\
+             \"net_memory_pressure\":{},\"memory_pressure_events\":{},\
+             \"avg_socket_pacing_rate\":{},\"kernel_paced_send_share\":{},\"kernel_paced\":{},\
+             \"softirq_ns\":{},\"event_count\":{},\"active_sockets\":{},\"active_flows\":{},\
+             \"new_connections_per_interval\":{},\"closed_connections_per_interval\":{},\"connections\":{},\
+             \"send_concentration\":{},\
+             \"sampler_comparison\":{},\"queue_depth_packets\":{},\"queue_depth_bytes\":{},\"udp_rcv_drops\":{},\
+             \"avg_rmem_pressure\":{},\"softirq_cpu_fraction\":{}",
+            EXPORT_SCHEMA_VERSION,
+            self.interval_ns,
+            json_opt(self.aligned_start.map(unix_ms)),
+            json_opt(self.aligned_end.map(unix_ms)),
+            observed_json(&self.observed),
+            estimated_json(&self.estimated),
+            self.send_bytes,
+            json_opt(self.loopback_send_bytes),
+            self.external_send_bytes,
+            send_sizes_json(&self.send_size_stats.udp),
+            send_sizes_json(&self.send_size_stats.tcp),
+            protocol_counts_json(&self.est_send_msgs),
+            protocol_counts_json(&self.est_wire_packets),
+            gso_json(&self.udp_gso),
+            self.drops,
+            json_f64(self.avg_wmem_pressure),
+            json_opt_f64(self.udp_wmem_pressure),
+            json_opt_f64(self.tcp_wmem_pressure),
+            json_opt_f64(self.net_memory_pressure),
+            json_opt(self.memory_pressure_events),
+            json_opt_f64(self.avg_socket_pacing_rate),
+            json_opt_f64(self.kernel_paced_send_share),
+            self.kernel_paced,
+            self.softirq_ns,
+            self.event_count,
+            self.active_sockets,
+            json_opt(self.active_flows),
+            self.new_connections_per_interval,
+            self.closed_connections_per_interval,
+            connections_json(&self.connections),
+            concentration_json(&self.send_concentration, redaction),
+            sampler_json(&self.sampler_comparison),
+            self.queue_depth_packets,
+            self.queue_depth_bytes,
+            self.udp_rcv_drops,
+            json_f64(self.avg_rmem_pressure),
+            json_f64(self.softirq_cpu_fraction),
+        );
+        let _ = write!(
+            out,
+            ",\"tcp_avg_cwnd\":{},\"tcp_avg_ssthresh\":{},\"tcp_avg_pacing_rate\":{},\
+             \"tcp_sockets_below_ssthresh\":{},\"ce_marks\":{},\"ce_triggered_cwr\":{},\
+             \"rto_events\":{},\"loss_recovery_episodes\":{},\"rx_time_squeeze\":{},\"implausible_socket_samples\":{},\
+             \"burst_drop_correlation\":{},\"drop_interarrival\":{},\"softirq_coupling\":{},\
+             \"softirq_attribution\":{},\"tsq_throttles\":{},\"softirq_discarded\":{},\
+             \"txq_stalls\":{},\"txq_stalled_ns\":{},\"egress_estimate_error\":{},\
+             \"limitation\":\"{:?}\"",
+            json_opt_f64(self.tcp_avg_cwnd),
+            json_opt_f64(self.tcp_avg_ssthresh),
+            json_opt_f64(self.tcp_avg_pacing_rate),
+            json_opt(self.tcp_sockets_below_ssthresh),
+            json_opt(self.ce_marks),
+            json_opt(self.ce_triggered_cwr),
+            json_opt(self.rto_events),
+            json_opt(self.loss_recovery_episodes),
+            json_opt(self.rx_time_squeeze),
+            self.implausible_socket_samples,
+            json_opt_f64(self.burst_drop_correlation),
+            json_opt(self.drop_interarrival.as_ref().map(interarrival_json)),
+            coupling_json(&self.softirq_coupling),
+            attribution_json(&self.softirq_attribution),
+            json_opt(self.tsq_throttles),
+            self.softirq_discarded,
+            json_opt(self.txq_stalls),
+            json_opt(self.txq_stalled_ns),
+            json_opt_f64(self.egress_estimate_error),
+            self.limitation,
+        );
+
+        let missing: Vec<String> = self
+            .missing_signals
+            .iter()
+            .map(|signal| json_string(&format!("{:?}", signal)))
+            .collect();
+        let _ = write!(out, ",\"missing_signals\":[{}]", missing.join(","));
+        let _ = write!(out, ",\"degradation_level\":{}", self.degradation_level);
+
+        let egress: Vec<String> = self
+            .egress
+            .iter()
+            .map(|e| {
+                format!(
+                    "{{\"interface\":{},\"egress_bytes_exact\":{},\"egress_packets_exact\":{}}}",
+                    json_string(&e.interface),
+                    e.egress_bytes_exact,
+                    e.egress_packets_exact,
+                )
+            })
+            .collect();
+        let _ = write!(out, ",\"egress\":[{}]", egress.join(","));
+
+        let txq: Vec<String> = self
+            .txq_by_interface
+            .iter()
+            .map(|t| {
+                format!(
+                    "{{\"interface\":{},\"ifindex\":{},\"netns\":{},\"txq_stalls\":{},\"txq_stalled_ns\":{},\
+                     \"xmit_busy\":{}}}",
+                    json_string(&t.interface),
+                    t.ifindex,
+                    t.netns,
+                    t.txq_stalls,
+                    t.txq_stalled_ns,
+                    t.xmit_busy,
+                )
+            })
+            .collect();
+        let _ = write!(out, ",\"txq_by_interface\":[{}]", txq.join(","));
+
+        let sessions: Vec<String> = self.sessions.iter().map(|s| json_string(s)).collect();
+        let _ = write!(out, ",\"sessions\":[{}]", sessions.join(","));
+
+        let captures: Vec<String> = self.captures.iter().map(CaptureNotice::to_json).collect();
+        let _ = write!(out, ",\"captures\":[{}]", captures.join(","));
+        let _ = write!(
+            out,
+            ",\"extensions\":{}}}",
+            extensions_json(&self.extensions)
+        );
+        out
+    }
+}
+
+#[cfg(not(feature = "no-float"))]
+fn send_sizes_json(sizes: &Option<SendSizes>) -> String {
+    match sizes {
+        Some(s) => format!(
+            "{{\"samples\":{},\"min_bytes\":{},\"avg_bytes\":{},\"max_bytes\":{},\"histogram\":[{}]}}",
+            s.samples,
+            s.min_bytes,
+            json_f64(s.avg_bytes),
+            s.max_bytes,
+            s.histogram.map(|n| n.to_string()).join(","),
+        ),
+        None => "null".to_string(),
+    }
+}
+
+#[cfg(not(feature = "no-float"))]
+fn protocol_counts_json(counts: &ProtocolCounts) -> String {
+    format!("{{\"udp\":{},\"tcp\":{}}}", counts.udp, counts.tcp)
+}
+
+#[cfg(not(feature = "no-float"))]
+fn gso_json(gso: &Option<GsoSegments>) -> String {
+    match gso {
+        Some(g) => format!(
+            "{{\"skbs\":{},\"gso_skbs\":{},\"segments\":{}}}",
+            g.skbs, g.gso_skbs, g.segments
+        ),
+        None => "null".to_string(),
+    }
+}
+
+#[cfg(not(feature = "no-float"))]
+fn sampler_json(estimates: &Option<SamplerEstimates>) -> String {
+    match estimates {
+        Some(e) => format!(
+            "{{\"candidate_ratio\":{},\"primary_send_bytes\":{},\"candidate_send_bytes\":{},\
+             \"candidate_samples\":{},\"relative_error\":{}}}",
+            e.candidate_ratio,
+            e.primary_send_bytes,
+            e.candidate_send_bytes,
+            e.candidate_samples,
+            json_opt_f64(e.relative_error()),
+        ),
+        None => "null".to_string(),
+    }
+}
+
+#[cfg(not(feature = "no-float"))]
+fn observed_json(o: &ObservedCounts) -> String {
+    format!(
+        "{{\"events_received\":{},\"drops\":{},\"udp_rcv_drops\":{},\"softirq_exits\":{}}}",
+        o.events_received, o.drops, o.udp_rcv_drops, o.softirq_exits,
+    )
+}
+
+#[cfg(not(feature = "no-float"))]
+fn estimated_json(e: &EstimatedTotals) -> String {
+    format!(
+        "{{\"send_bytes\":{},\"external_send_bytes\":{},\"send_packets\":{},\
+         \"scale\":{{\"sends_seen\":{},\"sends_sampled\":{},\"send_factor\":{},\
+         \"socket_fraction\":{}}}}}",
+        e.send_bytes,
+        e.external_send_bytes,
+        e.send_packets,
+        json_opt(e.scale.sends_seen),
+        e.scale.sends_sampled,
+        json_f64(e.scale.send_factor),
+        json_opt_f64(e.scale.socket_fraction),
+    )
+}
+
+#[cfg(not(feature = "no-float"))]
+fn connections_json(c: &ConnectionChurn) -> String {
+    format!(
+        "{{\"tcp_active_established\":{},\"tcp_passive_established\":{},\"tcp_accepts\":{},\
+         \"tcp_closed\":{},\"udp_first_seen\":{}}}",
+        json_opt(c.tcp_active_established),
+        json_opt(c.tcp_passive_established),
+        json_opt(c.tcp_accepts),
+        json_opt(c.tcp_closed),
+        c.udp_first_seen,
+    )
+}
+
+pub(crate) fn interarrival_json(interarrival: &DropInterarrival) -> String {
+    format!(
+        "{{\"gaps\":{},\"histogram\":[{}],\"mean_gap_ns\":{},\"burstiness\":{}}}",
+        interarrival.gaps,
+        interarrival.histogram.map(|n| n.to_string()).join(","),
+        json_f64(interarrival.mean_gap_ns),
+        json_f64(interarrival.burstiness),
+    )
+}
+
+#[cfg(not(feature = "no-float"))]
+fn coupling_json(coupling: &Option<SoftirqCoupling>) -> String {
+    match coupling {
+        Some(c) => format!(
+            "{{\"sends_checked\":{},\"sends_delayed_by_softirq\":{},\"sends_in_softirq\":{},\
+             \"avg_softirq_delay_ns\":{}}}",
+            c.sends_checked, c.sends_delayed_by_softirq, c.sends_in_softirq, c.avg_softirq_delay_ns,
+        ),
+        None => "null".to_string(),
+    }
+}
+
+#[cfg(not(feature = "no-float"))]
+fn attribution_json(attribution: &Option<SoftirqAttribution>) -> String {
+    match attribution {
+        Some(a) => format!(
+            "{{\"self_induced_softirq_ns\":{},\"external_softirq_ns\":{},\"rounds\":{},\
+             \"covered_rounds\":{},\"confidence\":{}}}",
+            a.self_induced_softirq_ns,
+            a.external_softirq_ns,
+            a.rounds,
+            a.covered_rounds,
+            json_f64(a.confidence),
+        ),
+        None => "null".to_string(),
+    }
+}
+
+#[cfg(not(feature = "no-float"))]
+fn concentration_json(concentration: &Option<SendConcentration>, redaction: &Redaction) -> String {
+    let Some(c) = concentration else {
+        return "null".to_string();
+    };
+    let top: Vec<String> = c
+        .top_sockets
+        .iter()
+        .map(|t| {
+            format!(
+                "{{\"socket_id\":{},\"send_bytes\":{},\"share\":{}}}",
+                json_opt(redaction.socket_id(t.socket_id)),
+                t.send_bytes,
+                json_f64(t.share),
+            )
+        })
+        .collect();
+    format!(
+        "{{\"sockets\":{},\"top1_share\":{},\"top5_share\":{},\"gini\":{},\"hhi\":{},\
+         \"top_sockets\":[{}]}}",
+        c.sockets,
+        json_f64(c.top1_share),
+        json_f64(c.top5_share),
+        json_f64(c.gini),
+        json_f64(c.hhi),
+        top.join(","),
+    )
+}
+
+pub(crate) fn unix_ms(at: SystemTime) -> u128 {
+    at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis())
+}
+
+pub(crate) fn json_opt<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| "null".to_string(), |v| v.to_string())
+}
+
+pub(crate) fn json_opt_f64(value: Option<f64>) -> String {
+    value.map_or_else(|| "null".to_string(), json_f64)
+}
+
+pub(crate) fn json_f64(value: f64) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+pub(crate) fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A JSON value: a document read back against the export model (export.rs),
+/// or an extension's part of `CongestionSignals::extensions`. Objects keep
+/// their keys in order
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    /// `integer` when written without a fraction or exponent
+    Number {
+        value: f64,
+        integer: bool,
+    },
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    /// The value written out, no whitespace
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out);
+        out
+    }
+
+    fn write(&self, out: &mut String) {
+        match self {
+            JsonValue::Null => out.push_str("null"),
+            JsonValue::Bool(value) => {
+                let _ = write!(out, "{}", value);
+            }
+            JsonValue::Number { value, integer } if *integer && value.is_finite() => {
+                let _ = write!(out, "{}", value);
+            }
+            JsonValue::Number { value, .. } => out.push_str(&json_f64(*value)),
+            JsonValue::String(value) => out.push_str(&json_string(value)),
+            JsonValue::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    item.write(out);
+                }
+                out.push(']');
+            }
+            JsonValue::Object(fields) => {
+                out.push('{');
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    out.push_str(&json_string(key));
+                    out.push(':');
+                    value.write(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+
+    /// An object's field of that name
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            JsonValue::Number { value, .. } => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl From<u64> for JsonValue {
+    fn from(value: u64) -> Self {
+        JsonValue::Number {
+            value: value as f64,
+            integer: true,
+        }
+    }
+}
+
+impl From<f64> for JsonValue {
+    fn from(value: f64) -> Self {
+        JsonValue::Number {
+            value,
+            integer: false,
+        }
+    }
+}
+
+impl From<bool> for JsonValue {
+    fn from(value: bool) -> Self {
+        JsonValue::Bool(value)
+    }
+}
+
+impl From<&str> for JsonValue {
+    fn from(value: &str) -> Self {
+        JsonValue::String(value.to_string())
+    }
+}
+
+impl From<String> for JsonValue {
+    fn from(value: String) -> Self {
+        JsonValue::String(value)
+    }
+}
+
+/// `CongestionSignals::extensions` as one object, by name
+#[cfg(not(feature = "no-float"))]
+pub(crate) fn extensions_json(extensions: &HashMap<String, JsonValue>) -> String {
+    let mut names: Vec<&String> = extensions.keys().collect();
+    names.sort();
+    let fields: Vec<String> = names
+        .into_iter()
+        .map(|name| format!("{}:{}", json_string(name), extensions[name].to_json()))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+/// One JSON value making up all of `text`, whitespace aside
+#[cfg(all(test, feature = "collector-core"))]
+pub(crate) fn parse_json(text: &str) -> Result<JsonValue, String> {
+    let mut parser = JsonParser {
+        bytes: text.as_bytes(),
+        at: 0,
+    };
+    let value = parser.value(0)?;
+    parser.skip_whitespace();
+    if parser.at != parser.bytes.len() {
+        return Err(format!("trailing characters at {}", parser.at));
+    }
+    Ok(value)
+}
+
+// Deeper than any document the crate writes
+#[cfg(all(test, feature = "collector-core"))]
+const MAX_JSON_DEPTH: usize = 64;
+
+#[cfg(all(test, feature = "collector-core"))]
+struct JsonParser<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+#[cfg(all(test, feature = "collector-core"))]
+impl JsonParser<'_> {
+    fn value(&mut self, depth: usize) -> Result<JsonValue, String> {
+        if depth > MAX_JSON_DEPTH {
+            return Err(format!("nested deeper than {}", MAX_JSON_DEPTH));
+        }
+        self.skip_whitespace();
+        match self.bytes.get(self.at) {
+            Some(b'{') => {
+                self.at += 1;
+                let mut fields = Vec::new();
+                if self.eat(b'}') {
+                    return Ok(JsonValue::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    fields.push((key, self.value(depth + 1)?));
+                    if self.eat(b'}') {
+                        return Ok(JsonValue::Object(fields));
+                    }
+                    self.expect(b',')?;
+                }
+            }
+            Some(b'[') => {
+                self.at += 1;
+                let mut items = Vec::new();
+                if self.eat(b']') {
+                    return Ok(JsonValue::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    if self.eat(b']') {
+                        return Ok(JsonValue::Array(items));
+                    }
+                    self.expect(b',')?;
+                }
+            }
+            Some(b'"') => self.string().map(JsonValue::String),
+            Some(b't') => self.literal("true", JsonValue::Bool(true)),
+            Some(b'f') => self.literal("false", JsonValue::Bool(false)),
+            Some(b'n') => self.literal("null", JsonValue::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(&c) => Err(format!("unexpected {:?} at {}", c as char, self.at)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut out = String::new();
+        loop {
+            let start = self.at;
+            while !matches!(self.bytes.get(self.at), Some(b'"' | b'\\') | None) {
+                self.at += 1;
+            }
+            out.push_str(
+                std::str::from_utf8(&self.bytes[start..self.at]).map_err(|e| e.to_string())?,
+            );
+            match self.bytes.get(self.at) {
+                Some(b'"') => {
+                    self.at += 1;
+                    return Ok(out);
+                }
+                Some(b'\\') => {
+                    let escape = self.bytes.get(self.at + 1).copied();
+                    self.at += 2;
+                    match escape {
+                        Some(b'"') => out.push('"'),
+                        Some(b'\\') => out.push('\\'),
+                        Some(b'/') => out.push('/'),
+                        Some(b'n') => out.push('\n'),
+                        Some(b't') => out.push('\t'),
+                        Some(b'r') => out.push('\r'),
+                        Some(b'b') => out.push('\u{8}'),
+                        Some(b'f') => out.push('\u{c}'),
+                        Some(b'u') => {
+                            let hex = self
+                                .bytes
+                                .get(self.at..self.at + 4)
+                                .and_then(|hex| std::str::from_utf8(hex).ok())
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .ok_or_else(|| format!("bad \\u escape at {}", self.at))?;
+                            self.at += 4;
+                            // Surrogate pairs come out as U+FFFD, nothing we write has them
+                            out.push(char::from_u32(hex).unwrap_or('\u{fffd}'));
+                        }
+                        _ => return Err(format!("bad escape at {}", self.at - 1)),
+                    }
+                }
+                _ => return Err("unterminated string".to_string()),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<JsonValue, String> {
+        let start = self.at;
+        while matches!(
+            self.bytes.get(self.at),
+            Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')
+        ) {
+            self.at += 1;
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.at]).map_err(|e| e.to_string())?;
+        let value = text
+            .parse::<f64>()
+            .map_err(|e| format!("number {:?}: {}", text, e))?;
+        Ok(JsonValue::Number {
+            value,
+            integer: !text.contains(['.', 'e', 'E']),
+        })
+    }
+
+    fn literal(&mut self, word: &str, value: JsonValue) -> Result<JsonValue, String> {
+        if self.bytes[self.at..].starts_with(word.as_bytes()) {
+            self.at += word.len();
+            Ok(value)
+        } else {
+            Err(format!("unexpected literal at {}", self.at))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.bytes.get(self.at), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.at += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.bytes.get(self.at) == Some(&byte);
+        if found {
+            self.at += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.eat(byte) {
+            Ok(())
+        } else {
+            Err(format!("expected {:?} at {}", byte as char, self.at))
+        }
+    }
+}
@@ -1,19 +1,310 @@
 //Define the library for collecting congestion signals/events from eBPF and aggregates them
 
-use aya::include_bytes_aligned;
-use aya::{
-    maps::perf::AsyncPerfEventArray,
-    programs::{KProbe, TracePoint},
-    util::online_cpus,
-    Ebpf,
+// Module layout follows the cargo features: the types, recordings and schema
+// below are always built; `collector-core` adds the probes and aggregation,
+// `async`/`blocking` pick how they're read, the rest are add-ons on top.
+
+#[cfg(feature = "collector-core")]
+mod accuracy;
+mod advisor;
+#[cfg(feature = "governor")]
+mod advisory;
+mod attribution;
+#[cfg(feature = "collector-core")]
+#[doc(hidden)]
+pub mod bench;
+mod bonding;
+#[cfg(feature = "collector-core")]
+mod btf;
+mod budget;
+#[cfg(not(feature = "no-float"))]
+mod bufferbloat;
+#[cfg(feature = "collector-core")]
+mod buffered;
+mod builder;
+#[cfg(feature = "collector-core")]
+mod burst;
+#[cfg(feature = "collector-core")]
+mod calibration;
+mod capture;
+#[cfg(feature = "collector-core")]
+mod cardinality;
+#[cfg(feature = "collector-core")]
+mod cgroups;
+#[cfg(feature = "collector-core")]
+mod classes;
+mod clock;
+mod coattach;
+#[cfg(feature = "collector-core")]
+mod collector;
+mod concentration;
+#[cfg(feature = "collector-core")]
+mod conflict;
+mod config;
+mod coupling;
+mod cpus;
+#[cfg(feature = "pacing-adapter")]
+mod departure;
+#[cfg(feature = "collector-core")]
+mod diagnostics;
+#[cfg(feature = "collector-core")]
+mod drop_reason;
+mod effectiveness;
+#[cfg(feature = "collector-core")]
+mod egress;
+mod error;
+mod export;
+#[cfg(feature = "schema")]
+pub mod export_model;
+mod extensions;
+mod fast;
+mod fixed;
+#[cfg(test)]
+mod fixtures;
+#[cfg(feature = "governor")]
+mod governor;
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(feature = "governor")]
+mod headroom;
+#[cfg(feature = "collector-core")]
+mod health;
+#[cfg(feature = "collector-core")]
+mod heartbeat;
+mod interarrival;
+mod json;
+mod limitation;
+#[cfg(feature = "collector-core")]
+mod memlock;
+#[cfg(feature = "collector-core")]
+mod memory;
+mod microburst;
+mod napi;
+#[cfg(feature = "collector-core")]
+mod netmem;
+#[cfg(feature = "collector-core")]
+mod netns;
+mod overhead;
+mod packets;
+#[cfg(feature = "parquet")]
+mod parquet_export;
+#[cfg(feature = "collector-core")]
+mod perf_ring;
+#[cfg(feature = "collector-core")]
+mod pipeline;
+#[cfg(feature = "collector-core")]
+mod preflight;
+mod probe;
+mod program_groups;
+#[cfg(feature = "async-runtime")]
+mod publisher;
+mod reaction;
+#[cfg(feature = "collector-core")]
+mod reader;
+mod recording;
+mod redact;
+mod reorder;
+#[cfg(feature = "async-runtime")]
+mod runtime;
+mod sampling;
+mod schema;
+#[cfg(feature = "collector-core")]
+mod sessions;
+#[cfg(not(feature = "no-float"))]
+mod severity;
+#[cfg(not(feature = "no-float"))]
+mod smoothing;
+mod soak;
+#[cfg(feature = "collector-core")]
+mod sockets;
+mod state;
+#[cfg(feature = "statsd")]
+mod statsd;
+#[cfg(feature = "async-runtime")]
+mod supervisor;
+#[cfg(feature = "systemd")]
+mod systemd;
+mod talkers;
+#[cfg(feature = "collector-core")]
+mod traced;
+#[cfg(feature = "collector-core")]
+mod txq;
+#[cfg(feature = "collector-core")]
+mod wallclock;
+
+#[cfg(all(feature = "collector-core", not(any(feature = "async-runtime", feature = "blocking"))))]
+compile_error!("collector-core needs a reader mode, enable `async`, `smol` or `blocking` too");
+
+#[cfg(all(feature = "async-runtime", not(any(feature = "async", feature = "smol"))))]
+compile_error!("async-runtime is shared by `async` and `smol`, enable one of those instead");
+
+#[cfg(all(
+    feature = "no-float",
+    any(
+        feature = "collector-core",
+        feature = "governor",
+        feature = "statsd",
+        feature = "parquet",
+        feature = "schema"
+    )
+))]
+compile_error!("no-float leaves out the f64 fields the collector, governor and exporters fill in or read, enable it with --no-default-features only");
+
+#[cfg(feature = "collector-core")]
+pub use accuracy::{AccuracyConfig, AccuracyReport, SignalAccuracy, SnmpCounters};
+pub use advisor::{
+    advise, affinity_mask, isolated_cpus, InterruptSnapshot, IrqAdvice, IrqAdvisor,
+    IrqAdvisorConfig, IrqCounts, IrqSteering, IrqSuppression,
+};
+#[cfg(feature = "governor")]
+pub use advisory::EndpointAdvisory;
+pub use attribution::{SoftirqAttribution, SoftirqAttributionConfig, SoftirqAttributionTracker};
+pub use bonding::{BondDriver, BondMaster, BondMode, BondSlave, BondTopology};
+pub use budget::{
+    by_run_time, BudgetAction, Degradation, DegradationStep, OverheadBudget, OverheadGovernor,
+    OverheadStatus,
+};
+#[cfg(not(feature = "no-float"))]
+pub use bufferbloat::{Bufferbloat, BufferbloatConfig, BufferbloatDetector};
+#[cfg(feature = "collector-core")]
+pub use buffered::{RegisteredSocketSignals, SocketHandle};
+pub use builder::CongestionSignalsBuilder;
+#[cfg(feature = "collector-core")]
+pub use burst::BurstCorrelationConfig;
+#[cfg(feature = "collector-core")]
+pub use calibration::{CalibrationConfig, CalibrationOutcome};
+#[cfg(not(feature = "no-float"))]
+pub use capture::TriggeredCapture;
+pub use capture::{
+    CaptureConfig, CaptureNotice, CaptureRecord, CaptureStatus, CaptureTrigger, FinishedCapture,
+};
+#[cfg(feature = "collector-core")]
+pub use cgroups::{CgroupAggregationConfig, CgroupRollup};
+#[cfg(feature = "collector-core")]
+pub use classes::{TrafficClass, TrafficClassConfig};
+pub use clock::{system_clock, Clock, Interval, ManualClock, SystemClock};
+pub use coattach::{
+    AttachEnumerator, AttachPoint, BpfProgram, CoAttachedProgram, CoAttachmentScan,
+    CoAttachmentScanner,
+};
+#[cfg(feature = "collector-core")]
+pub use collector::{replay_per_cpu, CongestionCollector};
+pub use concentration::{ConcentrationConfig, SendConcentration, TopSocket};
+#[cfg(feature = "collector-core")]
+pub use conflict::{ConflictCheck, LoadedCollector, SharedCollector};
+pub use config::{CollectorConfig, DropCoalescing, ReaderWakeup, WakeupWatermark};
+pub use coupling::{SoftirqCoupling, SoftirqCouplingConfig, SoftirqCouplingTracker};
+pub use cpus::{parse_cpu_list, CpuSlots};
+#[cfg(feature = "pacing-adapter")]
+pub use departure::{DepartureConfig, DeparturePacer};
+#[cfg(feature = "collector-core")]
+pub use drop_reason::DropReason;
+pub use effectiveness::{Effectiveness, EffectivenessConfig, EffectivenessTracker};
+pub use error::CollectorError;
+pub use export::{FieldDeprecation, EXPORT_SCHEMA_VERSION, FIELD_DEPRECATIONS};
+#[cfg(feature = "schema")]
+pub use export_model::check_export;
+pub use extensions::{
+    extension_event, CustomEventHandler, ExtensionEvent, ExtensionRegistry, EXTENSION_EVENT_TYPES,
+    EXTENSION_PAYLOAD_SIZE,
+};
+pub use fast::{FastSignals, FAST_INTERVAL_MAX, FAST_INTERVAL_MIN};
+pub use fixed::{mean_permille, ratio_permille, FixedSignals, PERMILLE};
+#[cfg(not(feature = "no-float"))]
+pub use fixed::{permille_from_f64, permille_to_f64};
+#[cfg(feature = "governor")]
+pub use governor::{
+    DecisionLog, DecisionRecord, Governor, GovernorPolicy, PacingAction, PacingDecision,
+    ReceivePressure, ScoreComponent, SoftirqCause, WmemSource, DEFAULT_DECISION_LOG_CAPACITY,
+};
+#[cfg(feature = "grpc")]
+pub use grpc::{
+    proto as grpc_proto, GrpcConfig, GrpcServer, GrpcTls, ScriptedSource, SnapshotSource,
+    SnapshotStream,
+};
+#[cfg(feature = "governor")]
+pub use headroom::{interface_speed, HeadroomConfig, HeadroomEstimate, HeadroomEstimator};
+#[cfg(feature = "collector-core")]
+pub use health::HealthReport;
+#[cfg(feature = "collector-core")]
+pub use heartbeat::HeartbeatStatus;
+#[cfg(feature = "async-runtime")]
+pub use heartbeat::{HeartbeatConfig, HeartbeatMonitor};
+pub use interarrival::{DropInterarrival, DropInterarrivalTracker};
+pub use json::JsonValue;
+pub use limitation::{KernelPacedThresholds, Limitation, LimitationThresholds};
+#[cfg(feature = "collector-core")]
+pub use memlock::{plan_buffers, BufferSizing, MemlockConfig, MemlockLimit, MemlockOutcome};
+#[cfg(feature = "collector-core")]
+pub use memory::{BpfMapMemory, MemoryReport, ShardMemory, StructureMemory};
+pub use microburst::{BurstClass, BurstClassifier, BurstConfig, BurstEpisode};
+pub use napi::{
+    InterfaceSoftirq, SoftirqBreakdown, SoftirqBreakdownConfig, SoftirqBreakdownTracker,
+    SoftirqVectorTime,
+};
+#[cfg(feature = "collector-core")]
+pub use netmem::{NetMemory, ProtoMemory};
+#[cfg(feature = "collector-core")]
+pub use netns::NetnsConfig;
+#[cfg(feature = "collector-core")]
+pub use overhead::BpfStats;
+pub use overhead::{ConfidenceInterval, CpuSample, CpuTimes, OverheadReport, SampleStats};
+pub use packets::{replay_send_packets, GsoSegments, ProtocolCounts};
+#[cfg(feature = "parquet")]
+pub use parquet_export::{
+    event_schema, recording_to_parquet, recording_to_parquet_redacted, signals_to_parquet,
+    ParquetSink,
+};
+#[cfg(feature = "collector-core")]
+pub use preflight::{
+    parse_kernel_version, Capabilities, Capability, PreflightHost, PreflightReport, ProbeKind,
+    ProbePreflight, Remediation,
 };
-use bytes::BytesMut;
-use std::mem::size_of;
-use std::sync::{
-    atomic::{AtomicU64, Ordering},
-    Arc,
+pub use probe::{probe_socket, SocketStateSample};
+pub use program_groups::{
+    load_groups, GroupHost, GroupLoad, HostFeature, LoadStage, ProgramAttach, ProgramGroup,
+    ProgramLoadFailure, ProgramLoader, ProgramRejection, PROGRAM_GROUPS,
 };
-use tokio::task;
+#[cfg(feature = "async-runtime")]
+pub use publisher::LatestSnapshot;
+pub use reaction::{OnsetThreshold, Reaction, SignalSeries};
+pub use recording::{RecordingReader, RecordingWriter};
+pub use redact::{AddressRedaction, Redaction, SocketIdRedaction};
+pub use reorder::{EventReorderer, EventReordering, ReorderStats};
+pub use sampling::{
+    ComparisonBound, SamplerComparison, SamplerComparisonReport, SamplerEstimates, SocketSampling,
+};
+pub use schema::SchemaDescriptor;
+#[cfg(feature = "collector-core")]
+pub use sessions::{SessionHandle, SessionReport, StateTransition};
+#[cfg(not(feature = "no-float"))]
+pub use severity::{
+    CongestionState, Reason, SeverityClassifier, SeveritySnapshot, SeverityThresholds,
+};
+#[cfg(not(feature = "no-float"))]
+pub use smoothing::{SignalSmoother, SmoothedSignals};
+pub use soak::{CounterRegression, ProcessUsage, SoakBounds, SoakReport, SoakSample, SoakTracker};
+#[cfg(feature = "collector-core")]
+pub use sockets::{replay_per_socket, socket_cookie, SocketSignals, SocketTable};
+pub use state::{
+    boot_id, CgroupTotals, CumulativeTotals, InterfaceTotals, PersistedState, StateError,
+    StateFileConfig, STATE_FILE_VERSION,
+};
+#[cfg(feature = "statsd")]
+pub use statsd::StatsdExporter;
+#[cfg(feature = "async-runtime")]
+pub use supervisor::{Component, ComponentFailure, RestartPolicies, RestartPolicy};
+#[cfg(feature = "systemd")]
+pub use systemd::{
+    control_listener, listen_fds, watchdog_interval, ServiceLifecycle, ServiceNotification,
+    ServiceState, SystemdNotifier, EXIT_NO_PERMISSION, EXIT_PROBES_UNAVAILABLE,
+    EXIT_SELF_TEST_FAILED,
+};
+pub use talkers::{rank_talkers, IntervalActivity, TalkerRanking, TopTalker};
+#[cfg(feature = "collector-core")]
+pub use traced::SocketTrace;
+#[cfg(feature = "collector-core")]
+pub use wallclock::{TimeNamespaceOffsets, WallClock, WALL_CLOCK_REFRESH, WALL_CLOCK_TOLERANCE};
 
 // Mirror kernel-side types. I am defining them here again instead of sharing
 // via a common crate because plain::from_bytes requires the types to implement
@@ -37,30 +328,73 @@ pub union EventData {
     pub qdisc: QdiscData,
     pub socket: SocketData,
     pub softirq: SoftirqData,
+    pub rcv: RcvSocketData,
+    pub tcp: TcpStateData,
+    pub lifecycle: SocketLifecycleData,
+    pub rx_squeeze: RxSqueezeData,
+    pub napi: NapiPollData,
+    /// What an extension's event (EXTENSION_EVENT_TYPES) carries: every byte
+    /// of the union, none of it padding
+    pub raw: [u8; EVENT_DATA_SIZE],
 }
 
+// The largest variant, SocketData, rounded up to its alignment
+const EVENT_DATA_SIZE: usize = 56;
+const _: () = assert!(std::mem::size_of::<EventData>() == EVENT_DATA_SIZE);
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct SendMsgData {
     pub bytes: u64,
     pub is_tcp: u32,
+    pub loopback: u32,
     pub socket_id: u64,
+    /// Network namespace inode, 0 when unknown
+    pub netns: u32,
+    /// The socket's DSCP and sk_priority, TRAFFIC_CLASS_UNKNOWN when unread.
+    /// See `CollectorConfig::with_traffic_classes`
+    pub dscp: u32,
+    pub priority: u32,
+    /// SAMPLER_PRIMARY and/or SAMPLER_COMPARE, see
+    /// `CollectorConfig::with_sampler_comparison`, plus SAMPLER_SOCKET from a
+    /// socket in the per-socket sample
+    pub samplers: u32,
+    /// Destination address and port packed into one value, 0 unless
+    /// `CollectorConfig::track_flows` is on and they were readable
+    pub flow_hash: u64,
 }
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct QdiscData {
+    /// Drops this event stands for, see `CollectorConfig::drop_coalescing`
     pub dropped: u32,
+    /// For EVENT_QDISC_DROP, where there's no backlog to report: ns from the
+    /// first coalesced drop to the last, saturating
     pub backlog_bytes: u32,
     pub backlog_packets: u32,
+    pub reason: u32,
 }
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct SocketData {
+    /// sk_wmem_alloc for UDP, sk_wmem_queued for TCP
     pub wmem_queued: u32,
     pub sndbuf: u32,
     pub socket_id: u64,
+    /// IPPROTO_UDP or IPPROTO_TCP
+    pub protocol: u32,
+    pub netns: u32,
+    pub dscp: u32,
+    pub priority: u32,
+    /// sock.sk_pacing_rate and sk_max_pacing_rate, bytes/sec. PACING_UNLIMITED
+    /// until the stack or SO_MAX_PACING_RATE sets one, 0 when the offsets
+    /// aren't in kernel BTF
+    pub pacing_rate: u64,
+    pub max_pacing_rate: u64,
+    /// Those of the send that carried it
+    pub samplers: u32,
 }
 
 #[repr(C)]
@@ -70,6 +404,248 @@ pub struct SoftirqData {
     pub duration_ns: u64,
 }
 
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct RcvSocketData {
+    pub rmem_alloc: u32,
+    pub rcvbuf: u32,
+    pub socket_id: u64,
+    /// SAMPLER_PRIMARY on receive samples; SAMPLER_SOCKET on any event of a
+    /// socket in the per-socket sample
+    pub samplers: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct TcpStateData {
+    pub snd_cwnd: u32,
+    pub snd_ssthresh: u32,
+    pub pacing_rate: u64,
+    pub socket_id: u64,
+    /// As in `RcvSocketData`
+    pub samplers: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SocketLifecycleData {
+    pub socket_id: u64,
+    pub oldstate: u32,
+    pub newstate: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct RxSqueezeData {
+    pub elapsed_ns: u64,
+    pub packets: u32,
+    pub budget_exhausted: u32,
+}
+
+/// One NAPI poll of a long NET_RX round, see `SoftirqBreakdown`
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct NapiPollData {
+    pub work: u32,
+    pub budget: u32,
+    pub dev_name: [u8; 16],
+}
+
+impl NapiPollData {
+    /// The polled device's name, empty when the kernel couldn't read it
+    pub fn device(&self) -> String {
+        let len = self
+            .dev_name
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(self.dev_name.len());
+        String::from_utf8_lossy(&self.dev_name[..len]).into_owned()
+    }
+}
+
+/// Written into the object's KERNEL_OFFSETS global before load, see btf.rs
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct KernelOffsets {
+    pub tcp_snd_cwnd: u32,
+    pub tcp_snd_ssthresh: u32,
+    pub sk_pacing_rate: u32,
+    pub skb_head: u32,
+    pub skb_network_header: u32,
+    pub sk_cookie: u32,
+    pub kfree_skb_reason: u32,
+    pub sk_wmem_alloc: u32,
+    pub sk_wmem_queued: u32,
+    pub sk_sndbuf: u32,
+    pub skb_sk: u32,
+    pub skb_mac_header: u32,
+    pub skb_transport_header: u32,
+    pub skb_dev: u32,
+    pub netdev_queue_dev: u32,
+    pub net_device_ifindex: u32,
+    pub sk_family: u32,
+    pub sk_daddr: u32,
+    pub sk_v6_daddr: u32,
+    pub sk_net: u32,
+    pub net_ns_inum: u32,
+    pub net_device_nd_net: u32,
+    pub inet_tos: u32,
+    pub sk_priority: u32,
+    /// sock.sk_max_pacing_rate, next to sk_pacing_rate above
+    pub sk_max_pacing_rate: u32,
+    /// sk_buff.len and inet_cork.gso_size, for the GSO probe
+    pub skb_len: u32,
+    pub inet_cork_gso_size: u32,
+    /// sock.sk_backlog.rmem_alloc and sock.sk_rcvbuf, for rmem pressure
+    pub sk_rmem_alloc: u32,
+    pub sk_rcvbuf: u32,
+    /// sock.__sk_common.skc_dport, for flow hashes of connected sockets
+    pub sk_dport: u32,
+}
+
+// SAFETY: KernelOffsets is repr(C), only u32 fields, no padding
+#[cfg(feature = "collector-core")]
+unsafe impl aya::Pod for KernelOffsets {}
+
+pub const OFFSET_UNKNOWN: u32 = u32::MAX;
+pub const TRAFFIC_CLASS_UNKNOWN: u32 = u32::MAX;
+pub const SAMPLER_PRIMARY: u32 = 1;
+pub const SAMPLER_COMPARE: u32 = 2;
+/// The event's socket is in the per-socket sample, see `SocketSampling`
+pub const SAMPLER_SOCKET: u32 = 4;
+/// The event's socket is traced, see `CongestionCollector::trace_socket`
+pub const SAMPLER_TRACE: u32 = 8;
+/// ~0UL in sk_pacing_rate and sk_max_pacing_rate: not paced
+pub const PACING_UNLIMITED: u64 = u64::MAX;
+
+/// Value of the RX_BUDGET map, from /proc/sys/net/core
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RxBudget {
+    pub packets: u32,
+    pub usecs: u32,
+}
+
+// SAFETY: RxBudget is repr(C), two u32s
+#[cfg(feature = "collector-core")]
+unsafe impl aya::Pod for RxBudget {}
+
+/// Slots of the DEGRADATION map, see `OverheadBudget`
+pub const DEGRADE_SAMPLE_SHIFT: u32 = 0;
+pub const DEGRADE_EVENT_MASK: u32 = 1;
+pub const DEGRADE_MAX_SHIFT: u32 = 7;
+/// Event types the kernel stops sending on a `DegradationStep::MaskEvents`
+pub const DEGRADE_MASKABLE: u32 = 1 << EVENT_NET_DEV_QUEUE
+    | 1 << EVENT_SOFTIRQ_EXIT
+    | 1 << EVENT_SOCKET_RCV_STATE
+    | 1 << EVENT_TCP_STATE;
+
+/// Per-CPU value of the EGRESS_COUNTERS map
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct EgressCount {
+    pub bytes: u64,
+    pub packets: u64,
+}
+
+// SAFETY: EgressCount is repr(C), two u64s
+#[cfg(feature = "collector-core")]
+unsafe impl aya::Pod for EgressCount {}
+
+pub const MAX_EGRESS_INTERFACES: u32 = 64;
+
+/// Value of REGISTERED_SOCKETS, per CPU and socket cookie
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SocketBytes {
+    pub sent: u64,
+    pub transmitted: u64,
+    pub last_send_ns: u64,
+    /// sk_wmem_alloc before the latest send's datagram, WMEM_UNREAD if unknown
+    pub last_send_wmem: u32,
+    pub last_send_len: u32,
+}
+
+// SAFETY: SocketBytes is repr(C), three u64s then two u32s, no padding
+#[cfg(feature = "collector-core")]
+unsafe impl aya::Pod for SocketBytes {}
+
+pub const WMEM_UNREAD: u32 = u32::MAX;
+
+/// Key of TXQ_STALLS
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TxqKey {
+    pub ifindex: u32,
+    pub netns: u32,
+}
+
+// SAFETY: TxqKey is repr(C), two u32s
+#[cfg(feature = "collector-core")]
+unsafe impl aya::Pod for TxqKey {}
+
+/// Value of TXQ_STALLS, per CPU and interface
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TxqStall {
+    pub stops: u64,
+    pub stopped_ns: u64,
+    pub xmit_busy: u64,
+}
+
+// SAFETY: TxqStall is repr(C), three u64s
+#[cfg(feature = "collector-core")]
+unsafe impl aya::Pod for TxqStall {}
+
+/// Value of UDP_GSO, per CPU, see `GsoSegments`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GsoCounters {
+    pub skbs: u64,
+    pub gso_skbs: u64,
+    pub segments: u64,
+}
+
+// SAFETY: GsoCounters is repr(C), three u64s
+#[cfg(feature = "collector-core")]
+unsafe impl aya::Pod for GsoCounters {}
+
+pub const MAX_TXQ_INTERFACES: u32 = 64;
+pub const MAX_REGISTERED_SOCKETS: u32 = 1024;
+pub const MAX_TRACED_SOCKETS: u32 = 16;
+
+/// Value of CGROUP_SIGNALS, per CPU and cgroup id. Send buffer totals are
+/// permille of sk_sndbuf
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CgroupCounters {
+    pub send_bytes: u64,
+    pub loopback_send_bytes: u64,
+    pub sends: u64,
+    pub drops: u64,
+    pub udp_wmem_total: u64,
+    pub udp_wmem_samples: u64,
+    pub tcp_wmem_total: u64,
+    pub tcp_wmem_samples: u64,
+    pub last_seen_ns: u64,
+}
+
+// SAFETY: CgroupCounters is repr(C), nine u64s
+#[cfg(feature = "collector-core")]
+unsafe impl aya::Pod for CgroupCounters {}
+
+pub const MAX_CGROUPS: u32 = 1024;
+
+impl SocketData {
+    /// What the kernel paces this socket at, sk_pacing_rate capped by
+    /// sk_max_pacing_rate. None when neither is set or they weren't read
+    pub fn pacing_limit(&self) -> Option<u64> {
+        let known = |rate: u64| if rate == 0 { PACING_UNLIMITED } else { rate };
+        let rate = known(self.pacing_rate).min(known(self.max_pacing_rate));
+        (rate != PACING_UNLIMITED).then_some(rate)
+    }
+}
+
 impl std::fmt::Debug for EventData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "EventData {{ ... }}")
@@ -83,238 +659,916 @@ pub const EVENT_SOCKET_STATE: u32 = 4;
 pub const EVENT_SOFTIRQ_ENTER: u32 = 5;
 pub const EVENT_SOFTIRQ_EXIT: u32 = 6;
 pub const EVENT_NET_DEV_QUEUE: u32 = 7;
+pub const EVENT_UDP_RCV_DROP: u32 = 8;
+pub const EVENT_SOCKET_RCV_STATE: u32 = 9;
+pub const EVENT_TCP_STATE: u32 = 10;
+pub const EVENT_TCP_CWR: u32 = 11;
+pub const EVENT_UDP_RCV_CE: u32 = 12;
+pub const EVENT_SOCKET_LIFECYCLE: u32 = 13;
+pub const EVENT_RX_TIME_SQUEEZE: u32 = 14;
+pub const EVENT_TCP_RTO: u32 = 15;
+pub const EVENT_TCP_RECOVERY: u32 = 16;
+pub const EVENT_NAPI_POLL: u32 = 17;
+
+// One past the highest event type, sizes the per-type tables below
+const EVENT_TYPE_SLOTS: usize = 18;
+
+// TCP states carried by EVENT_SOCKET_LIFECYCLE
+pub const TCP_ESTABLISHED: u32 = 1;
+pub const TCP_SYN_SENT: u32 = 2;
+pub const TCP_SYN_RECV: u32 = 3;
+pub const TCP_CLOSE: u32 = 7;
+
+// The sendmsg probe emits 1 in this many sends, see should_sample_send in main.rs
+pub(crate) const SEND_SAMPLE_RATIO: u64 = 100;
+
+// SocketData::protocol
+pub const IPPROTO_TCP: u32 = 6;
+pub const IPPROTO_UDP: u32 = 17;
+
+/// Short name of an event type for logs and reports
+pub fn event_type_name(event_type: u32) -> &'static str {
+    match event_type {
+        EVENT_UDP_SEND => "udp_send",
+        EVENT_TCP_SEND => "tcp_send",
+        EVENT_QDISC_DROP => "qdisc_drop",
+        EVENT_SOCKET_STATE => "socket_state",
+        EVENT_SOFTIRQ_ENTER => "softirq_enter",
+        EVENT_SOFTIRQ_EXIT => "softirq_exit",
+        EVENT_NET_DEV_QUEUE => "net_dev_queue",
+        EVENT_UDP_RCV_DROP => "udp_rcv_drop",
+        EVENT_SOCKET_RCV_STATE => "socket_rcv_state",
+        EVENT_TCP_STATE => "tcp_state",
+        EVENT_TCP_CWR => "tcp_cwr",
+        EVENT_UDP_RCV_CE => "udp_rcv_ce",
+        EVENT_SOCKET_LIFECYCLE => "socket_lifecycle",
+        EVENT_RX_TIME_SQUEEZE => "rx_time_squeeze",
+        EVENT_TCP_RTO => "tcp_rto",
+        EVENT_TCP_RECOVERY => "tcp_recovery",
+        EVENT_NAPI_POLL => "napi_poll",
+        t if EXTENSION_EVENT_TYPES.contains(&t) => "extension",
+        _ => "unknown",
+    }
+}
+
+/// socket_id of the events that carry one, and whether they say it's TCP
+#[cfg(not(feature = "no-float"))]
+pub(crate) fn event_socket(event: &CongestionEvent) -> Option<(u64, bool)> {
+    let socket = match event.event_type {
+        EVENT_UDP_SEND | EVENT_TCP_SEND => unsafe {
+            (event.data.sendmsg.socket_id, event.data.sendmsg.is_tcp != 0)
+        },
+        EVENT_UDP_RCV_DROP | EVENT_SOCKET_RCV_STATE | EVENT_UDP_RCV_CE => unsafe {
+            (event.data.rcv.socket_id, false)
+        },
+        EVENT_SOCKET_STATE => unsafe {
+            (
+                event.data.socket.socket_id,
+                event.data.socket.protocol == IPPROTO_TCP,
+            )
+        },
+        EVENT_TCP_STATE | EVENT_TCP_CWR | EVENT_TCP_RTO | EVENT_TCP_RECOVERY => unsafe {
+            (event.data.tcp.socket_id, true)
+        },
+        EVENT_SOCKET_LIFECYCLE => unsafe { (event.data.lifecycle.socket_id, true) },
+        _ => return None,
+    };
+    Some(socket)
+}
+
+/// SAMPLER_* bits of the events that carry them, 0 for the others
+pub(crate) fn event_samplers(event: &CongestionEvent) -> u32 {
+    match event.event_type {
+        EVENT_UDP_SEND | EVENT_TCP_SEND => unsafe { event.data.sendmsg.samplers },
+        EVENT_UDP_RCV_DROP | EVENT_SOCKET_RCV_STATE | EVENT_UDP_RCV_CE => unsafe {
+            event.data.rcv.samplers
+        },
+        EVENT_SOCKET_STATE => unsafe { event.data.socket.samplers },
+        EVENT_TCP_STATE | EVENT_TCP_CWR | EVENT_TCP_RTO | EVENT_TCP_RECOVERY => unsafe {
+            event.data.tcp.samplers
+        },
+        _ => 0,
+    }
+}
+
+/// Whether only a trace admitted this event (SAMPLER_TRACE without
+/// SAMPLER_PRIMARY), see `CongestionCollector::trace_socket`. Such events go
+/// to the trace and subscribers, every signal leaves them out
+pub fn traced_only(event: &CongestionEvent) -> bool {
+    event_samplers(event) & (SAMPLER_TRACE | SAMPLER_PRIMARY) == SAMPLER_TRACE
+}
+
+// Must match the kernel-side types.rs, checked against CONGESTION_SCHEMA on load
+pub const SCHEMA_MAGIC: u32 = 0x4353_4947;
+pub const SCHEMA_VERSION: u32 = 26;
+const SCHEMA_SYMBOL: &str = "CONGESTION_SCHEMA";
 
-/// Aggregated statistics from eBPF probes
+/// Aggregated statistics from eBPF probes. Outside this crate, build one with
+/// [`CongestionSignals::builder`]: it gains fields between releases
 #[derive(Debug, Clone, Default)]
+#[non_exhaustive]
 pub struct CongestionSignals {
+    /// Exact length of the interval these totals cover, measured between reads.
+    /// Divide by this rather than the nominal poll period, timer wakeups jitter
+    pub interval_ns: u64,
+    /// Wall-clock bounds of the interval when a snapshot publisher aligned it to
+    /// multiples of its interval since the epoch, so hosts' intervals line up
+    /// (`CollectorConfig::align_to_wall_clock`). None for any other read
+    pub aligned_start: Option<std::time::SystemTime>,
+    pub aligned_end: Option<std::time::SystemTime>,
+    /// When the interval was read, by `read_and_reset()` or the snapshot
+    /// publisher, see `is_stale`. None for signals that weren't read from a
+    /// collector (built by hand, from a recording)
+    pub produced_at: Option<std::time::Instant>,
+    /// What the probes count one event per occurrence of: drops, softirq exits,
+    /// events received. Exact, but for events lost on the way
+    pub observed: ObservedCounts,
+    /// Send totals scaled up from the 1-in-100 send samples, and the factor
+    /// they were scaled by
+    pub estimated: EstimatedTotals,
+    /// Sampled sendmsg bytes, loopback included when the collector counts it.
+    /// Not scaled: `estimated.send_bytes` is what was sent. This and the other
+    /// flat counters are kept as before the split for one release
     pub send_bytes: u64,
+    /// The part of `send_bytes` sent to loopback addresses. None when loopback
+    /// is left out in the kernel (`CollectorConfig::exclude_loopback`, the default)
+    pub loopback_send_bytes: Option<u64>,
+    /// `send_bytes` that can reach a NIC; what the limitation, smoothing and
+    /// egress comparison go by
+    pub external_send_bytes: u64,
+    /// Sizes of the sampled sends, per protocol: 200-byte QUIC packets and 64 KB
+    /// GSO batches pace very differently at the same `send_bytes`
+    pub send_size_stats: SendSizeStats,
+    /// sendmsg calls per protocol, the sampled ones in `send_size_stats`
+    /// scaled like `estimated`. Zero in per-cgroup breakdowns, which don't
+    /// split sends by protocol
+    pub est_send_msgs: ProtocolCounts,
+    /// `est_send_msgs` as packets on the wire: UDP messages at the GSO probe's
+    /// segments per skb, so a 64 KB UDP_SEGMENT send counts as the 45 packets
+    /// it leaves as. Equal to `est_send_msgs` without the probe (`udp_gso`
+    /// None), and TCP's always is: its segmentation by MSS isn't counted
+    pub est_wire_packets: ProtocolCounts,
+    /// Every UDP skb the host sent and the segments they left as, host-wide.
+    /// None without the udp_send_skb probe or the inet_cork offsets
+    pub udp_gso: Option<GsoSegments>,
     pub drops: u64,
+    /// Send buffer occupancy (0.0-1.0) averaged over every sampled socket, UDP
+    /// and TCP alike
+    #[cfg(not(feature = "no-float"))]
     pub avg_wmem_pressure: f64,
+    /// The same split by protocol. UDP is sk_wmem_alloc (what QUIC has sitting in
+    /// the qdisc/NIC) per sampled send, TCP is sk_wmem_queued per sampled TCP
+    /// state. None when the offsets aren't in kernel BTF or nothing was sampled
+    #[cfg(not(feature = "no-float"))]
+    pub udp_wmem_pressure: Option<f64>,
+    #[cfg(not(feature = "no-float"))]
+    pub tcp_wmem_pressure: Option<f64>,
+    /// Pages held by socket buffers host-wide over the pressure threshold of
+    /// tcp_mem or udp_mem (their middle value), whichever protocol is higher.
+    /// From 1.0 the kernel shrinks every socket's buffers at once: throughput
+    /// drops on every path, which `wmem` pressure alone would pass off as
+    /// congestion. Read from /proc when the interval is, None where it can't be
+    #[cfg(not(feature = "no-float"))]
+    pub net_memory_pressure: Option<f64>,
+    /// Times TCP entered memory pressure since the previous read (TcpExt
+    /// TCPMemoryPressures, the collector's namespace). None on the first read
+    /// and when the kernel doesn't count it
+    pub memory_pressure_events: Option<u64>,
+    /// `SocketData::pacing_limit` averaged over the send buffer samples of
+    /// sockets the kernel paces, bytes/sec. None when none was
+    #[cfg(not(feature = "no-float"))]
+    pub avg_socket_pacing_rate: Option<f64>,
+    /// Share of the sampled send bytes from sockets `SocketSignals::kernel_paced`
+    /// marks. None without sends
+    #[cfg(not(feature = "no-float"))]
+    pub kernel_paced_send_share: Option<f64>,
+    /// The share reached `KernelPacedThresholds::send_share`: the kernel's pacing
+    /// held the sockets back, so their rate says nothing about the path.
+    /// `Governor` neither raises the rate nor cuts it without loss
+    pub kernel_paced: bool,
     pub softirq_ns: u64,
+    /// Events received of every type, `observed.events_received`. Sampled sends
+    /// and unsampled drops and softirqs summed together: collector load, not
+    /// a packet rate
     pub event_count: u64,
+    /// Distinct sockets with events this interval: one bulk flow and thousands
+    /// of small ones look the same in `send_bytes`. Exact up to
+    /// `CollectorConfig::exact_socket_count_limit`, an estimate within a few
+    /// percent past it. Sends are sampled 1 in 100, so sockets sending fewer
+    /// than that in an interval may be missed; counted on the processing side,
+    /// so `ReaderStats::queue_dropped` events are missing too
+    pub active_sockets: u64,
+    /// Distinct flows, a socket and a destination address and port, seen on
+    /// sampled UDP sends this interval. Counted like `active_sockets`, exact up
+    /// to the same limit. None without `CollectorConfig::with_flow_tracking`
+    pub active_flows: Option<u64>,
+    /// Connections that started this interval: TCP handshakes completed on
+    /// either side plus UDP sockets seen for the first time. Thousands a second
+    /// load softirq and socket allocation like throughput does, at any
+    /// `send_bytes`. The TCP part is missing without sock:inet_sock_set_state,
+    /// see `connections`
+    pub new_connections_per_interval: u64,
+    /// TCP connections that closed this interval
+    pub closed_connections_per_interval: u64,
+    /// Both broken down by protocol and side, with the accept count
+    pub connections: ConnectionChurn,
+    /// How the sampled send bytes spread over the sockets that sent, from the
+    /// per-socket table. None below `ConcentrationConfig::min_send_bytes`, and
+    /// in session reports, which can't merge it
+    pub send_concentration: Option<SendConcentration>,
+    /// Both send samplers' estimates of the interval's send bytes. None unless
+    /// `CollectorConfig::with_sampler_comparison` was set
+    pub sampler_comparison: Option<SamplerEstimates>,
     pub queue_depth_packets: u64,
     pub queue_depth_bytes: u64,
+    /// Datagrams dropped because a receiver's rcvbuf was full (RcvbufErrors)
+    pub udp_rcv_drops: u64,
+    /// Receive buffer occupancy (0.0-1.0) averaged over sampled enqueues
+    #[cfg(not(feature = "no-float"))]
+    pub avg_rmem_pressure: f64,
+    /// Network softirq time over interval wall time on the busiest CPU (0.0-1.0).
+    /// One saturated core is enough to delay our traffic, so this is the max, not the mean
+    #[cfg(not(feature = "no-float"))]
+    pub softirq_cpu_fraction: f64,
+    /// The pressures and fractions above in per-mille, computed by the read
+    /// from the integer sums they're divided from. None for signals built
+    /// otherwise and once a float field is changed after the read, see
+    /// `fixed_point`
+    pub fixed: Option<FixedSignals>,
+    // Coexisting TCP flows, sampled on the send path. These need struct offsets
+    // from kernel BTF; None when unavailable on this kernel or nothing was sampled.
+    #[cfg(not(feature = "no-float"))]
+    pub tcp_avg_cwnd: Option<f64>,
+    /// Averaged over sockets that have left initial slow start
+    #[cfg(not(feature = "no-float"))]
+    pub tcp_avg_ssthresh: Option<f64>,
+    /// Average sk_pacing_rate, bytes/sec
+    #[cfg(not(feature = "no-float"))]
+    pub tcp_avg_pacing_rate: Option<f64>,
+    /// Sampled sockets whose last sample had cwnd < ssthresh (slow start / post-loss)
+    pub tcp_sockets_below_ssthresh: Option<u64>,
+    /// UDP receives carrying ECN CE, counted on the sampled receive path (same
+    /// 1-in-100 as rmem). None when the skb offsets aren't in kernel BTF
+    pub ce_marks: Option<u64>,
+    /// TCP window reductions on ECE feedback (tcp_enter_cwr, which local
+    /// NET_XMIT_CN also triggers). None when the probe couldn't attach
+    pub ce_triggered_cwr: Option<u64>,
+    /// TCP retransmission timeouts (tcp_retransmit_timer, once per backoff): a
+    /// coexisting flow lost everything in flight, the severe loss response.
+    /// None when the probe couldn't attach
+    pub rto_events: Option<u64>,
+    /// TCP fast recovery entries (tcp_enter_recovery): loss repaired from
+    /// duplicate acks, the mild response. None where the function is inlined
+    pub loss_recovery_episodes: Option<u64>,
+    /// NET_RX softirq rounds that hit netdev_budget or netdev_budget_usecs with
+    /// packets still queued (softnet_stat's time_squeeze): receive starvation on
+    /// this host, not the path. Deferred packets, our ACKs among them, get
+    /// processed late and in bursts. None when the napi_poll probe isn't attached
+    pub rx_time_squeeze: Option<u64>,
+    /// rcvbuf samples the kernel discarded because the reads were impossible
+    /// (buffer outside 4 KB..1 GB, occupancy over 4x the buffer). They're kept out
+    /// of `avg_rmem_pressure`; a large share of them means the struct sock
+    /// offsets from BTF don't match this kernel, which `health()` flags
+    pub implausible_socket_samples: u64,
+    /// Share (0.0-1.0) of this interval's drops that came within the drop window
+    /// after a send burst, see `CollectorConfig::with_burst_correlation`. Send
+    /// volume per bucket comes from the 1-in-100 send samples. None when not
+    /// enabled or nothing was dropped
+    #[cfg(not(feature = "no-float"))]
+    pub burst_drop_correlation: Option<f64>,
+    /// Gaps between this interval's qdisc drops, log-bucketed, and how bursty
+    /// they were, see `CollectorConfig::with_drop_interarrival`. None when not
+    /// enabled
+    pub drop_interarrival: Option<DropInterarrival>,
+    /// Registered sockets' sampled sends that waited out a long NET_RX round
+    /// on their CPU, see `CollectorConfig::with_softirq_coupling`. None when
+    /// not enabled
+    pub softirq_coupling: Option<SoftirqCoupling>,
+    /// NET_TX softirq time split between registered sockets' sends and
+    /// everyone else's, see `CollectorConfig::with_softirq_attribution`. None
+    /// when not enabled
+    pub softirq_attribution: Option<SoftirqAttribution>,
+    /// Times TCP Small Queues released a coexisting flow it had throttled for
+    /// having too much queued in the qdisc/NIC. Local egress saturation even
+    /// without drops. None when tcp_tsq_handler can't be probed on this kernel
+    pub tsq_throttles: Option<u64>,
+    /// softirq exits thrown away instead of timed: no matching entry, or a
+    /// duration over 100 ms that can only come from a mispaired entry/exit.
+    /// A steady trickle after attach is normal, a rate suggests lost events
+    pub softirq_discarded: u64,
+    /// Signals whose zeros in this interval mean "not measured": their
+    /// tracepoints couldn't be attached (no tracefs in the container, see
+    /// `health()`). Empty normally
+    pub missing_signals: Vec<Signal>,
+    /// Steps of `CollectorConfig::with_overhead_budget`'s ladder in force
+    /// when the interval was read, 0 when nothing is degraded. Above 0 the
+    /// occupancy averages are noisier and detached probes' signals are None,
+    /// see `HealthReport::overhead` for which
+    pub degradation_level: u32,
+    /// App- vs network-limited classification of this interval; rate control
+    /// should hold still when `AppLimited`
+    pub limitation: Limitation,
+    /// Exact tc egress per interface in `CollectorConfig::egress_interfaces`,
+    /// empty when none are configured or none could be attached
+    pub egress: Vec<InterfaceEgress>,
+    /// Times a driver stopped a TX queue (out of descriptors), summed over
+    /// interfaces: NIC saturation, the qdisc backs up behind it. None when
+    /// netif_tx_stop_queue can't be probed on this kernel
+    pub txq_stalls: Option<u64>,
+    /// Time TX queues spent stopped, counted when they're woken and summed over
+    /// queues, so it can exceed `interval_ns` on multiqueue NICs. None without
+    /// both the stop and wake probes
+    pub txq_stalled_ns: Option<u64>,
+    /// The same per interface, only interfaces with any this interval
+    pub txq_by_interface: Vec<InterfaceTxq>,
+    /// Sampling error of the sendmsg estimate: external_send_bytes scaled by the sample
+    /// ratio, over exact egress bytes of all interfaces, minus 1. Egress also counts
+    /// headers and traffic that never passed sendmsg, so expect it somewhat negative.
+    /// None without egress accounting or egress traffic
+    #[cfg(not(feature = "no-float"))]
+    pub egress_estimate_error: Option<f64>,
+    /// Labels of the measurement sessions open when the interval was read, in
+    /// the order they were begun (`CongestionCollector::begin_session`)
+    pub sessions: Vec<String>,
+    /// Triggered capture's triggers, suppressions and writes during this
+    /// interval read, see `CollectorConfig::with_triggered_capture`. Empty
+    /// normally
+    pub captures: Vec<CaptureNotice>,
+    /// Each registered extension's snapshot of the interval, by name, see
+    /// `CongestionCollector::register_extension`. Empty without any
+    pub extensions: std::collections::HashMap<String, JsonValue>,
 }
 
-/// Thread-safe atomic storage for signals
-#[derive(Default)]
-struct AtomicSignals {
-    send_bytes: AtomicU64,
-    drops: AtomicU64,
-    wmem_samples: AtomicU64,
-    wmem_total: AtomicU64,
-    softirq_ns: AtomicU64,
-    event_count: AtomicU64,
-    queue_depth_packets: AtomicU64,
-    queue_depth_bytes: AtomicU64,
-}
-
-pub struct CongestionCollector {
-    ebpf: Ebpf,
-    signals: Arc<AtomicSignals>,
-}
-
-impl CongestionCollector {
-    /// Load and attach eBPF probes
-    pub fn load() -> anyhow::Result<Self> {
-        // Load ebpf bytecode...just ignore the red, loads when the program compiles
-        #[cfg(debug_assertions)]
-        let mut ebpf = Ebpf::load(include_bytes_aligned!(
-            "../../target/bpfel-unknown-none/debug/congestion_signals"
-        ))?;
-
-        #[cfg(not(debug_assertions))]
-        let mut ebpf = Ebpf::load(include_bytes_aligned!(
-            "../../target/bpfel-unknown-none/release/congestion_signals"
-        ))?;
-
-        log::info!("eBPF bytecode loaded successfully");
-        // Attach kprobes
-        log::info!("attaching kprobe:udp_sendmsg");
-        let prog: &mut KProbe = ebpf.program_mut("udp_sendmsg").unwrap().try_into()?;
-        prog.load()?;
-        prog.attach("udp_sendmsg", 0)?;
-        log::info!("udp_sendmsg attached");
-
-        // Skip tcp_write_xmit for now - focus should be on getting basic probes working
-        // let prog: &mut KProbe = ebpf.program_mut("tcp_write_xmit").unwrap().try_into()?;
-        // prog.load()?;
-        // prog.attach("tcp_write_xmit", 0)?;
-
-        // Attach tracepoints
-        log::info!("Attaching tracepoint: skb:kfree_skb");
-        let prog: &mut TracePoint = ebpf.program_mut("skb_kfree").unwrap().try_into()?;
-        prog.load()?;
-        prog.attach("skb", "kfree_skb")?;
-        log::info!("skb:kfree_skb attached");
-
-        log::info!("Attaching tracepoint: net:net_dev_queue");
-        let prog: &mut TracePoint = ebpf.program_mut("net_dev_queue").unwrap().try_into()?;
-        prog.load()?;
-        prog.attach("net", "net_dev_queue")?;
-        log::info!("net:net_dev_queue tracepoint attached");
-
-        log::info!("Attaching tracepoint: irq:softirq_entry");
-        let prog: &mut TracePoint = ebpf.program_mut("softirq_entry").unwrap().try_into()?;
-        prog.load()?;
-        prog.attach("irq", "softirq_entry")?;
-        
-        log::info!("Attaching tracepoint: irq:softirq_exit");
-        let prog: &mut TracePoint = ebpf.program_mut("softirq_exit").unwrap().try_into()?;
-        prog.load()?;
-        prog.attach("irq", "softirq_exit")?;
-        log::info!("eBPF probes loaded and attached");
-
-        // Verify kprobes are in kernel
-        std::thread::sleep(std::time::Duration::from_millis(100));
-        Self::verify_kprobes_attached()?;
-
-        Ok(Self {
-            ebpf,
-            signals: Arc::new(AtomicSignals::default()),
+impl CongestionSignals {
+    /// Time since `produced_at`, None when it isn't set
+    pub fn age(&self) -> Option<std::time::Duration> {
+        self.age_at(std::time::Instant::now())
+    }
+
+    /// `age` as of `now`, a reading of the clock that stamped `produced_at`
+    pub(crate) fn age_at(&self, now: std::time::Instant) -> Option<std::time::Duration> {
+        self.produced_at.map(|at| now.saturating_duration_since(at))
+    }
+
+    /// Read more than `max_age` ago. A consumer whose publisher stalls (an
+    /// overloaded runtime) keeps getting the last snapshot it saw; this tells it
+    /// the numbers are old. Signals without `produced_at` are never stale
+    pub fn is_stale(&self, max_age: std::time::Duration) -> bool {
+        self.age().is_some_and(|age| age > max_age)
+    }
+
+    /// bytes/sec leaving the host over `secs`: exact tc egress counts when
+    /// attached, otherwise the sampled sendmsg bytes scaled back up
+    #[cfg(not(feature = "no-float"))]
+    pub(crate) fn send_rate(&self, secs: f64) -> f64 {
+        let bytes = if self.egress.is_empty() {
+            self.external_send_bytes as f64 * self.estimated.scale.configured_factor()
+        } else {
+            self.egress
+                .iter()
+                .map(|e| e.egress_bytes_exact)
+                .sum::<u64>() as f64
+        };
+        bytes / secs
+    }
+
+    /// `fixed`, or the float fields in per-mille when it's None
+    #[cfg(not(feature = "no-float"))]
+    pub fn fixed_point(&self) -> FixedSignals {
+        self.fixed.unwrap_or(FixedSignals {
+            interval_ns: self.interval_ns,
+            softirq_ns: self.softirq_ns,
+            avg_wmem_pressure: permille_from_f64(self.avg_wmem_pressure),
+            udp_wmem_pressure: self.udp_wmem_pressure.map(permille_from_f64),
+            tcp_wmem_pressure: self.tcp_wmem_pressure.map(permille_from_f64),
+            avg_rmem_pressure: permille_from_f64(self.avg_rmem_pressure),
+            softirq_cpu_fraction: permille_from_f64(self.softirq_cpu_fraction),
         })
     }
 
-    fn verify_kprobes_attached() -> anyhow::Result<()> {
-        use std::fs;
+    /// `fixed`; without the float fields to fall back to, just the times when
+    /// it's None
+    #[cfg(feature = "no-float")]
+    pub fn fixed_point(&self) -> FixedSignals {
+        self.fixed.unwrap_or(FixedSignals {
+            interval_ns: self.interval_ns,
+            softirq_ns: self.softirq_ns,
+            ..Default::default()
+        })
+    }
 
-        let kprobe_events =
-            fs::read_to_string("/sys/kernel/debug/tracing/kprobe_events").unwrap_or_default();
+    /// Fill `observed` and `estimated` from the flat counters, for signals
+    /// built the way they were before the split. The estimates are at the
+    /// configured ratio
+    pub fn with_split_from_flat(mut self) -> Self {
+        self.observed = ObservedCounts::from(&self);
+        self.estimated = EstimatedTotals::from(&self);
+        self
+    }
+}
 
-        log::info!("Verifying kprobe attachment...");
-        log::info!("kprobe_events content:\n{}", kprobe_events);
+/// See `CongestionSignals::observed`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ObservedCounts {
+    /// Events aggregated this interval, of every type
+    pub events_received: u64,
+    /// Packets dropped in the qdisc/NIC path, `CongestionSignals::drops`
+    pub drops: u64,
+    /// Datagrams dropped for a full rcvbuf, `CongestionSignals::udp_rcv_drops`
+    pub udp_rcv_drops: u64,
+    /// softirq exits timed into `softirq_ns`, every vector
+    pub softirq_exits: u64,
+}
 
-        if kprobe_events.contains("udp_sendmsg") {
-            log::info!("udp_sendmsg found in kprobe_events");
-        } else {
-            log::warn!("udp_sendmsg NOT found in kprobe_events");
+/// Connections opened and closed over one interval, see
+/// `CongestionSignals::new_connections_per_interval`. The TCP counts are None
+/// without the sock:inet_sock_set_state tracepoint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionChurn {
+    /// Handshakes completed by connect(), SYN_SENT→ESTABLISHED
+    pub tcp_active_established: Option<u64>,
+    /// Handshakes completed for a listener, SYN_RECV→ESTABLISHED
+    pub tcp_passive_established: Option<u64>,
+    /// inet_csk_accept returning a connection to the application. Lags the
+    /// passive handshakes by the accept backlog. None without the kretprobe
+    pub tcp_accepts: Option<u64>,
+    pub tcp_closed: Option<u64>,
+    /// UDP sockets whose first event the per-socket table saw. Sockets open
+    /// before collection started count in the first interval, and ones evicted
+    /// or retired idle count again when they come back
+    pub udp_first_seen: u64,
+}
+
+impl ConnectionChurn {
+    /// Handshakes completed on either side plus new UDP sockets
+    pub fn opened(&self) -> u64 {
+        self.tcp_active_established.unwrap_or(0)
+            + self.tcp_passive_established.unwrap_or(0)
+            + self.udp_first_seen
+    }
+
+    /// TCP closes; UDP sockets have no close the probes see
+    pub fn closed(&self) -> u64 {
+        self.tcp_closed.unwrap_or(0)
+    }
+}
+
+/// See `CongestionSignals::estimated`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EstimatedTotals {
+    /// sendmsg bytes, loopback included when the collector counts it
+    pub send_bytes: u64,
+    /// The part of them that can reach a NIC
+    pub external_send_bytes: u64,
+    /// sendmsg calls. A GSO batch is one call, so this is below the packets
+    /// on the wire whenever the application batches
+    pub send_packets: u64,
+    pub scale: SampleScale,
+}
+
+impl EstimatedTotals {
+    /// Sampled byte totals, and the sampled sends in `scale`, times its factor
+    pub fn from_samples(send_bytes: u64, external_send_bytes: u64, scale: SampleScale) -> Self {
+        let scaled = |sampled: u64| (sampled as f64 * scale.send_factor).round() as u64;
+        Self {
+            send_bytes: scaled(send_bytes),
+            external_send_bytes: scaled(external_send_bytes),
+            send_packets: scaled(scale.sends_sampled),
+            scale,
         }
+    }
+}
 
-        Ok(())
-    }
-    /// Start collecting events in background tasks
-    pub async fn start_collection(&mut self) -> anyhow::Result<()> {
-        let mut perf_array = AsyncPerfEventArray::try_from(self.ebpf.take_map("EVENTS").unwrap())?;
-
-        // fixed online_cpus() should return Vec<32>
-        let cpus =
-            online_cpus().map_err(|e| anyhow::anyhow!("Failed to get online CPUs: {:?}", e))?;
-
-        log::info!("Starting event collection on {} CPUs", cpus.len());
-
-        for cpu_id in cpus {
-            let mut buf = perf_array.open(cpu_id, None)?;
-            let signals = self.signals.clone();
-
-            task::spawn(async move {
-                let mut buffers = vec![BytesMut::with_capacity(4096); 10];
-
-                loop {
-                    for buf in &mut buffers {
-                        buf.clear();
-                    }
-
-                    match buf.read_events(&mut buffers).await {
-                        Ok(events) => {
-                            if events.lost > 0 {
-                                log::warn!("Lost {} perf events on CPU {}", events.lost, cpu_id);
-                            }
-
-                            for buf in buffers.iter_mut().take(events.read) {
-                                if buf.len() < size_of::<CongestionEvent>() {
-                                    log::warn!(
-                                        "Short perf sample on CPU {}: {} bytes (expected at least {})",
-                                        cpu_id,
-                                        buf.len(),
-                                        size_of::<CongestionEvent>()
-                                    );
-                                    continue;
-                                }
-
-                                let event = unsafe {
-                                    // Perf buffers don't guarantee alignment; copy unaligned.
-                                    std::ptr::read_unaligned(buf.as_ptr() as *const CongestionEvent)
-                                };
-
-                                Self::process_event(&signals, &event);
-                            }
-                        }
-                        Err(e) => {
-                            log::error!("Error reading events from CPU {}: {}", cpu_id, e);
-                        }
-                    }
-                }
-            });
+/// How the send samples were scaled into `EstimatedTotals`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleScale {
+    /// Sends the kernel's sampler counted this interval, admitted or not,
+    /// summed over CPUs. None where its counters couldn't be read
+    pub sends_seen: Option<u64>,
+    /// Of those, the ones the primary sampler admitted that reached userspace
+    pub sends_sampled: u64,
+    /// What sampled totals are multiplied by: `sends_seen / sends_sampled`,
+    /// 1-in-100 as configured without either. Lost samples raise it, so the
+    /// estimates make up for them. Under per-socket sampling the inverse of
+    /// `socket_fraction`
+    pub send_factor: f64,
+    /// Share of sockets in the per-socket sample, None when sends are
+    /// sampled 1 in 100. See `SocketSampling`
+    pub socket_fraction: Option<f64>,
+}
+
+impl SampleScale {
+    /// From the sampler's coverage counters
+    pub fn from_coverage(sends_seen: Option<u64>, sends_sampled: u64) -> Self {
+        let send_factor = match sends_seen {
+            Some(seen) if seen > 0 && sends_sampled > 0 => seen as f64 / sends_sampled as f64,
+            _ => SEND_SAMPLE_RATIO as f64,
+        };
+        Self {
+            sends_seen,
+            sends_sampled,
+            send_factor,
+            socket_fraction: None,
         }
+    }
 
-        log::info!("Event collection started on all CPUs");
-        Ok(())
+    /// Under per-socket sampling the sampled sockets are a random share of
+    /// all of them, so totals are the sample's over that share. Not
+    /// `sends_seen / sends_sampled`: sends per socket vary, and that ratio
+    /// would weigh the sample's sockets by how often they send. Samples lost
+    /// in the perf buffer aren't made up for
+    pub fn per_socket(
+        sends_seen: Option<u64>,
+        sends_sampled: u64,
+        sampling: SocketSampling,
+    ) -> Self {
+        Self {
+            sends_seen,
+            sends_sampled,
+            send_factor: 1.0 / sampling.fraction(),
+            socket_fraction: Some(sampling.fraction()),
+        }
     }
 
-    fn process_event(signals: &AtomicSignals, event: &CongestionEvent) {
-        signals.event_count.fetch_add(1, Ordering::Relaxed);
+    /// The factor the sampler is set up for, whatever coverage was measured:
+    /// the 1-in-100 ratio or the inverse of `socket_fraction`
+    pub fn configured_factor(&self) -> f64 {
+        self.socket_fraction
+            .map_or(SEND_SAMPLE_RATIO as f64, |fraction| 1.0 / fraction)
+    }
 
-        match event.event_type {
-            EVENT_UDP_SEND | EVENT_TCP_SEND => unsafe {
-                let bytes = event.data.sendmsg.bytes;
-                signals.send_bytes.fetch_add(bytes, Ordering::Relaxed);
-            },
-            EVENT_QDISC_DROP => {
-                signals.drops.fetch_add(1, Ordering::Relaxed);
-            }
-            EVENT_NET_DEV_QUEUE => unsafe {
-                // NEW: Track queue depth
-                let qdata = event.data.qdisc;
-                signals
-                    .queue_depth_packets
-                    .fetch_add(qdata.backlog_packets as u64, Ordering::Relaxed);
-                signals
-                    .queue_depth_bytes
-                    .fetch_add(qdata.backlog_bytes as u64, Ordering::Relaxed);
-            },
-            EVENT_SOCKET_STATE => unsafe {
-                //mind ya: this is deprecated, just here for compactability
-                let wmem = event.data.socket.wmem_queued;
-                let sndbuf = event.data.socket.sndbuf;
-                if sndbuf > 0 {
-                    let pressure = (wmem as u64 * 1000) / (sndbuf as u64);
-                    signals.wmem_total.fetch_add(pressure, Ordering::Relaxed);
-                    signals.wmem_samples.fetch_add(1, Ordering::Relaxed);
-                }
-            },
-            EVENT_SOFTIRQ_EXIT => unsafe {
-                let duration = event.data.softirq.duration_ns;
-                signals.softirq_ns.fetch_add(duration, Ordering::Relaxed);
-            },
-            _ => {}
+    /// The configured ratio, for totals without coverage counters
+    pub fn nominal(sends_sampled: u64) -> Self {
+        Self::from_coverage(None, sends_sampled)
+    }
+
+    /// The factor is `configured_factor` rather than measured coverage
+    pub fn is_nominal(&self) -> bool {
+        self.socket_fraction.is_some()
+            || self.sends_seen.is_none_or(|seen| seen == 0)
+            || self.sends_sampled == 0
+    }
+}
+
+impl Default for SampleScale {
+    fn default() -> Self {
+        Self::nominal(0)
+    }
+}
+
+impl From<&CongestionSignals> for ObservedCounts {
+    /// From the flat counters. They carry no softirq exit count, left 0
+    fn from(signals: &CongestionSignals) -> Self {
+        Self {
+            events_received: signals.event_count,
+            drops: signals.drops,
+            udp_rcv_drops: signals.udp_rcv_drops,
+            softirq_exits: 0,
         }
     }
+}
 
-    /// Get current aggregated signals and reset counters
-    pub fn read_and_reset(&self) -> CongestionSignals {
-        let send_bytes = self.signals.send_bytes.swap(0, Ordering::Relaxed);
-        let drops = self.signals.drops.swap(0, Ordering::Relaxed);
-        let wmem_total = self.signals.wmem_total.swap(0, Ordering::Relaxed);
-        let wmem_samples = self.signals.wmem_samples.swap(0, Ordering::Relaxed);
-        let softirq_ns = self.signals.softirq_ns.swap(0, Ordering::Relaxed);
-        let event_count = self.signals.event_count.swap(0, Ordering::Relaxed);
-        let queue_depth_packets = self.signals.queue_depth_packets.swap(0, Ordering::Relaxed);
-        let queue_depth_bytes = self.signals.queue_depth_bytes.swap(0, Ordering::Relaxed);
+impl From<&CongestionSignals> for EstimatedTotals {
+    /// From the flat sampled totals at the configured ratio
+    fn from(signals: &CongestionSignals) -> Self {
+        let sampled = ProtocolCounts::sampled(&signals.send_size_stats).total();
+        Self::from_samples(
+            signals.send_bytes,
+            signals.external_send_bytes,
+            SampleScale::nominal(sampled),
+        )
+    }
+}
 
-        let avg_wmem_pressure = if wmem_samples > 0 {
-            (wmem_total as f64) / (wmem_samples as f64) / 1000.0
-        } else {
-            0.0
-        };
+/// See `CongestionSignals::send_size_stats`
+#[derive(Debug, Clone, Default)]
+pub struct SendSizeStats {
+    /// None when no send of that protocol was sampled
+    pub udp: Option<SendSizes>,
+    pub tcp: Option<SendSizes>,
+}
 
-        CongestionSignals {
-            send_bytes,
-            drops,
-            avg_wmem_pressure,
-            softirq_ns,
-            event_count,
-            queue_depth_packets,
-            queue_depth_bytes,
+/// sendmsg lengths of one protocol over one interval, from the 1-in-100 samples
+#[derive(Debug, Clone, Default)]
+pub struct SendSizes {
+    pub samples: u64,
+    pub min_bytes: u64,
+    pub avg_bytes: f64,
+    pub max_bytes: u64,
+    /// Sends below 1500 bytes, 1500 bytes-16 KB, 16-64 KB and over 64 KB
+    pub histogram: [u64; 4],
+}
+
+impl SendSizes {
+    /// Upper bounds of the histogram buckets but the last, bytes
+    pub const BUCKET_LIMITS: [u64; 3] = [1500, 16 * 1024, 64 * 1024];
+
+    /// A bytes/sec pacing rate in sends/sec at this interval's average size
+    pub fn sends_per_sec(&self, bytes_per_sec: u64) -> Option<f64> {
+        (self.avg_bytes > 0.0).then(|| bytes_per_sec as f64 / self.avg_bytes)
+    }
+}
+
+/// A core signal that can be missing on some hosts, see
+/// `CongestionSignals::missing_signals`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Signal {
+    /// `drops` (skb:kfree_skb)
+    Drops,
+    /// `queue_depth_packets` and `queue_depth_bytes` (net:net_dev_queue)
+    QueueDepth,
+    /// `softirq_ns` and `softirq_cpu_fraction` (irq:softirq_entry/exit)
+    Softirq,
+    /// `avg_wmem_pressure`, `udp_wmem_pressure` and `tcp_wmem_pressure`, left
+    /// out after a failed calibration, see `CalibrationConfig::disable_on_mismatch`
+    SendBuffer,
+}
+
+/// One interval of exact egress on one interface
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InterfaceEgress {
+    pub interface: String,
+    /// skb length at tc egress: L2 and up, headers counted once per GSO batch
+    pub egress_bytes_exact: u64,
+    /// Wire packets, GSO batches counted as their segment count
+    pub egress_packets_exact: u64,
+}
+
+/// TX queue stalls on one interface over one interval, see
+/// `CongestionSignals::txq_stalls`
+#[derive(Debug, Clone, Default)]
+pub struct InterfaceTxq {
+    pub interface: String,
+    pub ifindex: u32,
+    /// Namespace inode of the interface, 0 when unknown
+    pub netns: u32,
+    pub txq_stalls: u64,
+    pub txq_stalled_ns: u64,
+    /// Driver refused an skb with NETDEV_TX_BUSY, a queue it should have
+    /// stopped earlier; the qdisc requeues it
+    pub xmit_busy: u64,
+}
+
+/// Per-CPU breakdown of the same interval as the CongestionSignals read with it.
+/// The vectors follow `cpus`, the possible CPUs' ids, which needn't be 0..N
+/// (see `CpuSlots`); `cpu_id` and `position` convert
+#[derive(Debug, Clone, Default)]
+pub struct PerCpuSignals {
+    /// CPU id of each entry, ascending. Empty reads as 0..N, e.g. in a
+    /// hand-built fixture
+    pub cpus: Vec<u32>,
+    pub softirq_ns_by_cpu: Vec<u64>,
+    /// softirq_ns_by_cpu over interval wall time (0.0-1.0)
+    pub softirq_fraction_by_cpu: Vec<f64>,
+    /// rx_time_squeeze per CPU, empty when it's None
+    pub rx_time_squeeze_by_cpu: Vec<u64>,
+    /// Softirq exits and squeezes from CPU ids outside `cpus`, counted here
+    /// instead of per CPU, and their totals
+    pub unknown_cpu_events: u64,
+    pub other_softirq_ns: u64,
+    pub other_rx_time_squeeze: u64,
+}
+
+impl PerCpuSignals {
+    /// The CPU id of entry `index`
+    pub fn cpu_id(&self, index: usize) -> u32 {
+        self.cpus.get(index).copied().unwrap_or(index as u32)
+    }
+
+    /// The entry of CPU `cpu`, None when it isn't one of `cpus`
+    pub fn position(&self, cpu: u32) -> Option<usize> {
+        if self.cpus.is_empty() {
+            let len = self
+                .softirq_ns_by_cpu
+                .len()
+                .max(self.softirq_fraction_by_cpu.len());
+            return ((cpu as usize) < len).then_some(cpu as usize);
         }
+        self.cpus.binary_search(&cpu).ok()
+    }
+}
+
+/// Reader-side health counters, see `CongestionCollector::reader_stats()`
+#[derive(Debug, Clone, Default)]
+pub struct ReaderStats {
+    /// Events decoded from the perf buffers
+    pub events_read: u64,
+    /// Samples the kernel had to discard because a perf buffer was full
+    pub perf_lost: u64,
+    /// Events waiting for the processing task right now
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+    /// Events dropped because the processing queue was full
+    pub queue_dropped: u64,
+    /// Events of types this build doesn't know, skipped (forward-compatible
+    /// objects, or extension types no handler is registered for)
+    pub unknown_events: u64,
+    /// Events of registered extension types, passed on to their handlers
+    pub extension_events: u64,
+    /// Jumps in the kernel clock (VM migration, some resumes) between consecutive
+    /// events on a CPU. Each one resets last-seen times, socket ages and burst buckets
+    pub clock_jumps: u64,
+    /// Events from namespaces outside `NetnsConfig::allow`, left out
+    pub netns_filtered: u64,
+    /// Times a CPU's reader woke to read its buffer, summed over CPUs, since
+    /// collection started; see `CollectorConfig::with_reader_wakeup`
+    pub reader_wakeups: u64,
+    /// The same per second of collection. Against `events_read` over that
+    /// time it shows how many events each wakeup took
+    pub wakeups_per_sec: f64,
+    /// Held, late and delayed events of the processing side's reordering
+    /// stage. None when it's off, see `CollectorConfig::with_event_reordering`
+    pub reordering: Option<ReorderStats>,
+}
+
+/// Collector lifecycle, see [`CongestionCollector`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollectorState {
+    /// Probes attached, no readers yet
+    Loaded,
+    /// Readers and processing task running
+    Collecting,
+    /// Readers stopped; the EVENTS map was consumed, so this is terminal
+    Stopped,
+}
+
+/// What a lifecycle method asks of the collector's state
+#[cfg(feature = "collector-core")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Transition {
+    /// Changes only valid before collection, such as registering an extension
+    Configure,
+    Start,
+    /// Start unless already collecting
+    EnsureCollecting,
+    Stop,
+}
+
+#[cfg(feature = "collector-core")]
+impl CollectorState {
+    /// The state after `transition`, or why it isn't allowed from this one
+    pub(crate) fn next(self, transition: Transition) -> Result<Self, CollectorError> {
+        use CollectorState::*;
+        match (self, transition) {
+            (Loaded, Transition::Configure) => Ok(Loaded),
+            (Loaded, Transition::Start | Transition::EnsureCollecting) => Ok(Collecting),
+            (Collecting, Transition::EnsureCollecting) => Ok(Collecting),
+            (Collecting, Transition::Stop) => Ok(Stopped),
+            (actual, Transition::Stop) => Err(CollectorError::InvalidState {
+                expected: Collecting,
+                actual,
+            }),
+            (actual, _) => Err(CollectorError::InvalidState {
+                expected: Loaded,
+                actual,
+            }),
+        }
+    }
+}
+
+
+#[cfg(all(test, feature = "collector-core"))]
+mod tests {
+    use super::*;
+
+    const STATES: [CollectorState; 3] = [
+        CollectorState::Loaded,
+        CollectorState::Collecting,
+        CollectorState::Stopped,
+    ];
+    const TRANSITIONS: [Transition; 4] = [
+        Transition::Configure,
+        Transition::Start,
+        Transition::EnsureCollecting,
+        Transition::Stop,
+    ];
+
+    #[test]
+    fn the_lifecycle_runs_loaded_collecting_stopped() {
+        let state = CollectorState::Loaded;
+        assert_eq!(
+            state.next(Transition::Configure),
+            Ok(CollectorState::Loaded)
+        );
+        let state = state.next(Transition::Start).unwrap();
+        assert_eq!(state, CollectorState::Collecting);
+        assert_eq!(state.next(Transition::EnsureCollecting), Ok(state));
+        assert_eq!(state.next(Transition::Stop), Ok(CollectorState::Stopped));
+        assert_eq!(
+            CollectorState::Loaded.next(Transition::EnsureCollecting),
+            Ok(CollectorState::Collecting)
+        );
+    }
+
+    #[test]
+    fn every_illegal_transition_names_the_state_it_needed() {
+        let legal = [
+            (CollectorState::Loaded, Transition::Configure),
+            (CollectorState::Loaded, Transition::Start),
+            (CollectorState::Loaded, Transition::EnsureCollecting),
+            (CollectorState::Collecting, Transition::EnsureCollecting),
+            (CollectorState::Collecting, Transition::Stop),
+        ];
+        let mut illegal = 0;
+        for state in STATES {
+            for transition in TRANSITIONS {
+                if legal.contains(&(state, transition)) {
+                    continue;
+                }
+                let expected = match transition {
+                    Transition::Stop => CollectorState::Collecting,
+                    _ => CollectorState::Loaded,
+                };
+                assert_eq!(
+                    state.next(transition),
+                    Err(CollectorError::InvalidState {
+                        expected,
+                        actual: state
+                    }),
+                    "{:?} from {:?}",
+                    transition,
+                    state
+                );
+                illegal += 1;
+            }
+        }
+        assert_eq!(illegal, 7);
+    }
+
+    #[test]
+    fn stopped_is_terminal() {
+        for transition in TRANSITIONS {
+            assert!(CollectorState::Stopped.next(transition).is_err());
+        }
+    }
+
+    #[test]
+    fn a_second_start_is_refused() {
+        let collecting = CollectorState::Loaded.next(Transition::Start).unwrap();
+        let error = collecting.next(Transition::Start).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "collector is Collecting, operation requires Loaded"
+        );
+        assert!(collecting.next(Transition::Configure).is_err());
+    }
+
+    #[test]
+    fn send_estimates_scale_by_measured_coverage() {
+        // 250,000 seen for 2,000 sampled is 125, not the configured 100
+        let measured = EstimatedTotals::from_samples(
+            2_400_000,
+            2_000_000,
+            SampleScale::from_coverage(Some(250_000), 2_000),
+        );
+        assert_eq!(measured.scale.send_factor, 125.0);
+        assert!(!measured.scale.is_nominal());
+        assert_eq!(measured.send_bytes, 300_000_000);
+        assert_eq!(measured.external_send_bytes, 250_000_000);
+        assert_eq!(measured.send_packets, 250_000);
+        let json = CongestionSignals::builder()
+            .estimated(measured)
+            .build()
+            .to_json();
+        assert!(json.contains(
+            "\"scale\":{\"sends_seen\":250000,\"sends_sampled\":2000,\"send_factor\":125"
+        ));
+    }
+
+    #[test]
+    fn without_coverage_the_scale_is_nominal() {
+        let unread = SampleScale::from_coverage(None, 2_000);
+        assert_eq!(unread.send_factor, 100.0);
+        assert!(unread.is_nominal());
+        assert!(SampleScale::from_coverage(Some(0), 0).is_nominal());
+    }
+
+    #[test]
+    fn flat_signals_split_at_the_configured_ratio() {
+        let flat = CongestionSignals::builder()
+            .send_bytes(120_000)
+            .external_send_bytes(120_000)
+            .drops(7)
+            .event_count(150)
+            .send_size_stats(SendSizeStats {
+                udp: Some(SendSizes {
+                    samples: 100,
+                    ..Default::default()
+                }),
+                tcp: None,
+            })
+            .build()
+            .with_split_from_flat();
+        assert!(flat.estimated.scale.is_nominal());
+        assert_eq!(flat.estimated.send_bytes, 12_000_000);
+        assert_eq!(flat.estimated.send_packets, 10_000);
+        assert_eq!(
+            flat.observed,
+            ObservedCounts {
+                events_received: 150,
+                drops: 7,
+                ..Default::default()
+            }
+        );
     }
 }
@@ -0,0 +1,276 @@
+//Was the interval limited by the network or by the application? Low send_bytes
+//only means congestion when something below the socket is pushing back, so
+//consumers should freeze rate changes on app-limited intervals, the way BBR
+//ignores app-limited bandwidth samples. The same goes for sockets the kernel
+//already paces (fq, TCP pacing, SO_MAX_PACING_RATE): sending at their pacing
+//rate with send buffer to spare, it's the pacing that holds them back.
+
+#[cfg(feature = "collector-core")]
+use crate::CongestionSignals;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Limitation {
+    /// Sending with no sign of pushback
+    #[default]
+    Unconstrained,
+    /// Little was sent and nothing pushed back: the app had nothing to send
+    AppLimited,
+    /// Sending while buffers filled up or packets were dropped
+    NetworkLimited,
+}
+
+/// Thresholds for [`Limitation`], part of `CollectorConfig`
+#[derive(Debug, Clone)]
+pub struct LimitationThresholds {
+    /// Intervals sending less than this are candidates for app-limited. In the same
+    /// sampled units as `external_send_bytes`, per second
+    pub app_limited_send_rate: f64,
+    /// wmem pressure at or below which an interval counts as pushback-free
+    pub app_limited_max_wmem_pressure: f64,
+    /// wmem pressure at or above which sending counts as network-limited
+    pub network_limited_wmem_pressure: f64,
+    /// Drops in one interval at or above which sending counts as network-limited
+    pub network_limited_drops: u64,
+    /// When sockets and intervals count as kernel-paced
+    pub kernel_paced: KernelPacedThresholds,
+}
+
+/// Thresholds for `SocketSignals::kernel_paced` and `CongestionSignals::kernel_paced`
+#[derive(Debug, Clone, Copy)]
+pub struct KernelPacedThresholds {
+    /// A send rate within this factor of the pacing rate, either way, is sending
+    /// at it. Loose, the send rate is estimated from sampled sends
+    pub rate_match: f64,
+    /// The pacing rate must be below this share of what the socket's sndbuf
+    /// could carry per `round_trip`, or the buffer is just as much the limit
+    pub max_capability_share: f64,
+    pub round_trip: Duration,
+    /// Share of an interval's sampled send bytes from kernel-paced sockets at or
+    /// above which the interval counts as kernel-paced
+    pub send_share: f64,
+}
+
+impl Default for KernelPacedThresholds {
+    fn default() -> Self {
+        Self {
+            rate_match: 0.8,
+            max_capability_share: 0.5,
+            round_trip: Duration::from_millis(10),
+            send_share: 0.5,
+        }
+    }
+}
+
+impl KernelPacedThresholds {
+    /// Whether a socket sending `send_rate` bytes/sec is held back by its
+    /// `pacing_limit` rather than by its `sndbuf`
+    pub fn socket_paced(&self, send_rate: f64, pacing_limit: u64, sndbuf: u32) -> bool {
+        let limit = pacing_limit as f64;
+        let capability = sndbuf as f64 / self.round_trip.as_secs_f64();
+        limit > 0.0
+            && send_rate >= limit * self.rate_match
+            && send_rate * self.rate_match <= limit
+            && limit <= capability * self.max_capability_share
+    }
+
+    /// Whether an interval with this share of kernel-paced send bytes counts as
+    /// kernel-paced
+    pub fn interval_paced(&self, send_share: Option<f64>) -> bool {
+        send_share.is_some_and(|share| share >= self.send_share)
+    }
+}
+
+impl Default for LimitationThresholds {
+    fn default() -> Self {
+        Self {
+            // ~100 KB/s of real traffic at the default 1-in-100 send sampling
+            app_limited_send_rate: 1_000.0,
+            app_limited_max_wmem_pressure: 0.05,
+            network_limited_wmem_pressure: 0.5,
+            network_limited_drops: 1,
+            kernel_paced: KernelPacedThresholds::default(),
+        }
+    }
+}
+
+impl Limitation {
+    #[cfg(feature = "collector-core")]
+    pub(crate) fn classify(
+        signals: &CongestionSignals,
+        thresholds: &LimitationThresholds,
+    ) -> Self {
+        let pushback = signals.avg_wmem_pressure >= thresholds.network_limited_wmem_pressure
+            || signals.drops >= thresholds.network_limited_drops;
+        if signals.external_send_bytes > 0 && pushback {
+            return Limitation::NetworkLimited;
+        }
+
+        let send_rate = if signals.interval_ns > 0 {
+            signals.external_send_bytes as f64 * 1e9 / signals.interval_ns as f64
+        } else {
+            0.0
+        };
+        if send_rate < thresholds.app_limited_send_rate
+            && signals.avg_wmem_pressure <= thresholds.app_limited_max_wmem_pressure
+            && signals.drops == 0
+        {
+            return Limitation::AppLimited;
+        }
+
+        Limitation::Unconstrained
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "collector-core")]
+    fn one_second(send_bytes: u64, wmem_pressure: f64, drops: u64) -> CongestionSignals {
+        CongestionSignals::builder()
+            .interval_ns(1_000_000_000)
+            .send_bytes(send_bytes)
+            .external_send_bytes(send_bytes)
+            .avg_wmem_pressure(wmem_pressure)
+            .drops(drops)
+            .build()
+    }
+
+    #[cfg(feature = "collector-core")]
+    #[test]
+    fn intervals_classify_by_sending_and_pushback() {
+        let thresholds = LimitationThresholds::default();
+        let classify = |signals| Limitation::classify(&signals, &thresholds);
+        // Quiet and nothing pushing back
+        assert_eq!(classify(one_second(0, 0.0, 0)), Limitation::AppLimited);
+        assert_eq!(classify(one_second(500, 0.05, 0)), Limitation::AppLimited);
+        // Sending into full buffers, or losing packets
+        assert_eq!(
+            classify(one_second(500, 0.5, 0)),
+            Limitation::NetworkLimited
+        );
+        assert_eq!(
+            classify(one_second(50_000, 0.0, 1)),
+            Limitation::NetworkLimited
+        );
+        // Sending freely
+        assert_eq!(
+            classify(one_second(50_000, 0.1, 0)),
+            Limitation::Unconstrained
+        );
+        // Pressure in between is neither, however little was sent
+        assert_eq!(classify(one_second(500, 0.2, 0)), Limitation::Unconstrained);
+    }
+
+    #[cfg(feature = "collector-core")]
+    #[test]
+    fn pushback_without_sending_is_not_network_limited() {
+        let thresholds = LimitationThresholds::default();
+        let signals = one_second(0, 0.9, 0);
+        assert_eq!(
+            Limitation::classify(&signals, &thresholds),
+            Limitation::Unconstrained
+        );
+        // Drops with nothing sent point at someone else's traffic
+        let signals = one_second(0, 0.0, 10);
+        assert_eq!(
+            Limitation::classify(&signals, &thresholds),
+            Limitation::Unconstrained
+        );
+    }
+
+    #[cfg(feature = "collector-core")]
+    #[test]
+    fn thresholds_move_the_boundaries() {
+        let signals = one_second(5_000, 0.3, 2);
+        let defaults = LimitationThresholds::default();
+        assert_eq!(
+            Limitation::classify(&signals, &defaults),
+            Limitation::NetworkLimited
+        );
+
+        let tolerant = LimitationThresholds {
+            network_limited_drops: 3,
+            ..Default::default()
+        };
+        assert_eq!(
+            Limitation::classify(&signals, &tolerant),
+            Limitation::Unconstrained
+        );
+
+        let signals = one_second(5_000, 0.0, 0);
+        let busy = LimitationThresholds {
+            app_limited_send_rate: 10_000.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            Limitation::classify(&signals, &defaults),
+            Limitation::Unconstrained
+        );
+        assert_eq!(
+            Limitation::classify(&signals, &busy),
+            Limitation::AppLimited
+        );
+    }
+
+    #[cfg(feature = "collector-core")]
+    #[test]
+    fn the_send_rate_scales_with_the_interval() {
+        let thresholds = LimitationThresholds::default();
+        // 500 bytes in 100ms is 5000/s, well above the app-limited rate
+        let signals = CongestionSignals::builder()
+            .interval_ns(100_000_000)
+            .external_send_bytes(500)
+            .build();
+        assert_eq!(
+            Limitation::classify(&signals, &thresholds),
+            Limitation::Unconstrained
+        );
+    }
+
+    #[test]
+    fn a_socket_at_its_pacing_rate_is_kernel_paced() {
+        let thresholds = KernelPacedThresholds::default();
+        // 1 MB/s pacing with a 1 MB sndbuf, which could carry 100 MB/s
+        assert!(thresholds.socket_paced(1_000_000.0, 1_000_000, 1 << 20));
+        assert!(thresholds.socket_paced(850_000.0, 1_000_000, 1 << 20));
+        // Well below the rate: the app is the limit
+        assert!(!thresholds.socket_paced(500_000.0, 1_000_000, 1 << 20));
+        // No pacing rate
+        assert!(!thresholds.socket_paced(1_000_000.0, 0, 1 << 20));
+        // A sndbuf that carries barely more than the pacing rate limits just as much
+        assert!(!thresholds.socket_paced(1_000_000.0, 1_000_000, 12_000));
+    }
+
+    #[test]
+    fn the_pacing_limit_is_the_lower_rate_set() {
+        let socket = |pacing_rate, max_pacing_rate| crate::SocketData {
+            wmem_queued: 0,
+            sndbuf: 212_992,
+            socket_id: 1,
+            protocol: crate::IPPROTO_TCP,
+            netns: 0,
+            dscp: 0,
+            priority: 0,
+            pacing_rate,
+            max_pacing_rate,
+            samplers: 0,
+        };
+        let unlimited = crate::PACING_UNLIMITED;
+        assert_eq!(socket(unlimited, unlimited).pacing_limit(), None);
+        // 0 is what an unread field holds
+        assert_eq!(socket(0, 0).pacing_limit(), None);
+        assert_eq!(socket(2_000_000, unlimited).pacing_limit(), Some(2_000_000));
+        assert_eq!(socket(2_000_000, 1_000_000).pacing_limit(), Some(1_000_000));
+        assert_eq!(socket(0, 1_000_000).pacing_limit(), Some(1_000_000));
+    }
+
+    #[test]
+    fn intervals_are_kernel_paced_by_send_share() {
+        let thresholds = KernelPacedThresholds::default();
+        assert!(thresholds.interval_paced(Some(0.5)));
+        assert!(!thresholds.interval_paced(Some(0.4)));
+        assert!(!thresholds.interval_paced(None));
+    }
+}
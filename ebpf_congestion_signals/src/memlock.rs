@@ -0,0 +1,384 @@
+//RLIMIT_MEMLOCK on kernels that charge eBPF memory to it. Before 5.11 (no
+//memcg accounting) every map and each CPU's perf buffer pages count against
+//the locked-memory limit, and the usual default of 64 KB makes the load or the
+//first perf buffer open fail with EPERM, which says nothing about why. So load
+//checks the limit first and tries to raise it (`MemlockConfig::raise_to`); an
+//unprivileged process can go up to the hard limit, past it takes
+//CAP_SYS_RESOURCE. Whatever it ends up at, once the maps are loaded the perf
+//buffers are sized to the rest: the largest power of two pages per CPU, down
+//to `min_buffer_pages`, that fits. Smaller buffers overflow sooner under a
+//burst, so a cut is a health warning. Only when the smallest ones don't fit
+//does load fail, with the exact limit and what to raise it to.
+
+use crate::{memory, parse_kernel_version, reader, CollectorConfig, CollectorError};
+use aya::util::online_cpus;
+use aya::Ebpf;
+use std::io;
+
+/// What load does about RLIMIT_MEMLOCK where eBPF memory is charged to it,
+/// see `CollectorConfig::memlock`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemlockConfig {
+    /// Raise the soft limit to this many bytes, u64::MAX for unlimited, when
+    /// it's lower. None leaves it as it is
+    pub raise_to: Option<u64>,
+    /// Fewest data pages per CPU perf buffer to cut down to, a power of two
+    pub min_buffer_pages: usize,
+}
+
+impl Default for MemlockConfig {
+    fn default() -> Self {
+        Self {
+            raise_to: Some(u64::MAX),
+            min_buffer_pages: 1,
+        }
+    }
+}
+
+/// The soft and hard RLIMIT_MEMLOCK in bytes, None for unlimited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemlockLimit {
+    pub soft: Option<u64>,
+    pub hard: Option<u64>,
+}
+
+impl MemlockLimit {
+    pub fn current() -> io::Result<Self> {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let bytes = |value| (value != libc::RLIM_INFINITY).then_some(value);
+        Ok(Self {
+            soft: bytes(limit.rlim_cur),
+            hard: bytes(limit.rlim_max),
+        })
+    }
+
+    /// Set both limits to `bytes` (u64::MAX for unlimited), or only the soft
+    /// one when that's as far as the hard one allows
+    fn raise(&self, bytes: u64) -> io::Result<Self> {
+        let value = if bytes == u64::MAX {
+            libc::RLIM_INFINITY
+        } else {
+            bytes
+        };
+        let set = |rlim_cur, rlim_max| {
+            let limit = libc::rlimit { rlim_cur, rlim_max };
+            if unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &limit) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        };
+        // Raising the hard limit needs CAP_SYS_RESOURCE; without it, what the
+        // hard limit already allows
+        if let Err(e) = set(value, value.max(self.hard.unwrap_or(libc::RLIM_INFINITY))) {
+            match self.hard {
+                Some(hard) if hard > self.soft.unwrap_or(u64::MAX) => set(value.min(hard), hard)?,
+                _ => return Err(e),
+            }
+        }
+        Self::current()
+    }
+}
+
+/// How the per-CPU perf buffers are sized against the limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferSizing {
+    pub cpus: u64,
+    pub page_size: u64,
+    /// Data pages per CPU wanted, a power of two, see
+    /// `CollectorConfig::perf_buffer_pages`
+    pub wanted_pages: usize,
+    pub min_pages: usize,
+}
+
+impl BufferSizing {
+    /// Every CPU's buffer of `pages` data pages, plus its metadata page
+    pub fn bytes(&self, pages: usize) -> u64 {
+        self.cpus * (pages as u64 + 1) * self.page_size
+    }
+
+    /// The most data pages per CPU, a power of two between `min_pages` and
+    /// `wanted_pages`, whose buffers fit in `available` bytes
+    pub fn fit(&self, available: u64) -> Option<usize> {
+        let mut pages = self.wanted_pages;
+        while pages >= self.min_pages.max(1) {
+            if self.bytes(pages) <= available {
+                return Some(pages);
+            }
+            pages /= 2;
+        }
+        None
+    }
+}
+
+/// What load found and did about RLIMIT_MEMLOCK, in `HealthReport::memlock`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemlockOutcome {
+    /// This kernel doesn't charge eBPF memory to it (5.11 and later)
+    NotCharged,
+    /// Everything fit at the limit as it was
+    Sufficient { limit: Option<u64> },
+    /// Raised from `from` to `to` bytes (None for unlimited) and it fit
+    Raised { from: u64, to: Option<u64> },
+    /// Perf buffers cut from `wanted_pages` to `pages` data pages per CPU to
+    /// fit `limit` bytes, the most it could be raised to
+    Reduced {
+        limit: u64,
+        wanted_pages: usize,
+        pages: usize,
+    },
+}
+
+/// Data pages per CPU for the perf buffers, and how it was decided. `before`
+/// and `after` are the soft limit before and after trying to raise it;
+/// `charged_bytes` the loaded maps' share of it. Fails when even `min_pages`
+/// doesn't fit
+pub fn plan_buffers(
+    charged: bool,
+    before: Option<u64>,
+    after: Option<u64>,
+    charged_bytes: u64,
+    sizing: &BufferSizing,
+) -> Result<(usize, MemlockOutcome), CollectorError> {
+    let wanted = sizing.wanted_pages;
+    let Some(limit) = after.filter(|_| charged) else {
+        let outcome = match (charged, before) {
+            (false, _) => MemlockOutcome::NotCharged,
+            (true, Some(from)) => MemlockOutcome::Raised { from, to: None },
+            (true, None) => MemlockOutcome::Sufficient { limit: None },
+        };
+        return Ok((wanted, outcome));
+    };
+    let available = limit.saturating_sub(charged_bytes);
+    match sizing.fit(available) {
+        Some(pages) if pages == wanted => Ok((
+            pages,
+            match before {
+                Some(from) if from < limit => MemlockOutcome::Raised {
+                    from,
+                    to: Some(limit),
+                },
+                _ => MemlockOutcome::Sufficient { limit: Some(limit) },
+            },
+        )),
+        Some(pages) => Ok((
+            pages,
+            MemlockOutcome::Reduced {
+                limit,
+                wanted_pages: wanted,
+                pages,
+            },
+        )),
+        None => Err(CollectorError::MemlockTooLow {
+            limit,
+            needed: charged_bytes + sizing.bytes(sizing.min_pages.max(1)),
+        }),
+    }
+}
+
+/// Where load stands with RLIMIT_MEMLOCK, from before the object is loaded
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Memlock {
+    // Whether this kernel charges eBPF memory to the limit
+    charged: bool,
+    before: Option<u64>,
+    after: Option<u64>,
+}
+
+impl Memlock {
+    /// Read the limit and, where it's charged, raise it as `config` says
+    pub(crate) fn prepare(config: &MemlockConfig) -> Self {
+        let release = std::fs::read_to_string("/proc/sys/kernel/osrelease").unwrap_or_default();
+        // An unknown version is taken as old: checking costs nothing where
+        // nothing is charged
+        let charged = parse_kernel_version(release.trim()).is_none_or(|v| v < (5, 11));
+        let current = match MemlockLimit::current() {
+            Ok(limit) => limit,
+            Err(e) => {
+                log::warn!("RLIMIT_MEMLOCK unreadable: {}", e);
+                return Self {
+                    charged: false,
+                    before: None,
+                    after: None,
+                };
+            }
+        };
+        let mut after = current.soft;
+        if let (true, Some(target), Some(soft)) = (charged, config.raise_to, current.soft) {
+            if target > soft {
+                match current.raise(target) {
+                    Ok(raised) => after = raised.soft,
+                    Err(e) => log::warn!(
+                        "couldn't raise RLIMIT_MEMLOCK from {} KB: {}",
+                        soft / 1024,
+                        e
+                    ),
+                }
+            }
+        }
+        Self {
+            charged,
+            before: current.soft,
+            after,
+        }
+    }
+
+    /// Perf buffer pages per CPU that fit next to `ebpf`'s loaded maps
+    pub(crate) fn size_buffers(
+        &self,
+        ebpf: &Ebpf,
+        config: &CollectorConfig,
+    ) -> Result<(usize, MemlockOutcome), CollectorError> {
+        let maps = memory::bpf_maps(ebpf, 0)
+            .iter()
+            .filter(|map| map.name != "EVENTS")
+            .map(|map| map.estimated_bytes)
+            .sum();
+        let sizing = BufferSizing {
+            cpus: online_cpus().map_or(1, |cpus| cpus.len()) as u64,
+            page_size: unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(4096) as u64,
+            wanted_pages: reader::wanted_buffer_pages(config),
+            min_pages: config.memlock.min_buffer_pages,
+        };
+        let planned = plan_buffers(self.charged, self.before, self.after, maps, &sizing)?;
+        match planned.1 {
+            MemlockOutcome::Raised { from, to } => log::info!(
+                "RLIMIT_MEMLOCK raised from {} KB to {}",
+                from / 1024,
+                to.map_or("unlimited".to_string(), |to| format!("{} KB", to / 1024))
+            ),
+            MemlockOutcome::Reduced {
+                limit,
+                wanted_pages,
+                pages,
+            } => log::warn!(
+                "RLIMIT_MEMLOCK is {} KB: perf buffers cut to {} pages per CPU from {}",
+                limit / 1024,
+                pages,
+                wanted_pages
+            ),
+            _ => {}
+        }
+        Ok(planned)
+    }
+
+    /// A load error, with the limit when it could be the cause
+    pub(crate) fn explain(&self, error: anyhow::Error) -> anyhow::Error {
+        match (self.charged, self.after) {
+            (true, Some(limit)) => error.context(format!(
+                "loading with RLIMIT_MEMLOCK at {} KB, which eBPF maps are charged to \
+                 before 5.11; raise it with `ulimit -l unlimited` or LimitMEMLOCK=infinity \
+                 in the unit",
+                limit / 1024
+            )),
+            _ => error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4 CPUs of 4 KB pages wanting 16 pages each, 278,528 bytes with the
+    // metadata pages
+    const SIZING: BufferSizing = BufferSizing {
+        cpus: 4,
+        page_size: 4096,
+        wanted_pages: 16,
+        min_pages: 1,
+    };
+    const DEFAULT: u64 = 64 << 10;
+    const MAPS: u64 = 300 << 10;
+
+    #[test]
+    fn buffers_fit_in_halving_page_counts() {
+        assert_eq!(SIZING.bytes(16), 278_528);
+        assert_eq!(SIZING.fit(278_528), Some(16));
+        assert_eq!(SIZING.fit(278_527), Some(8));
+        assert_eq!(SIZING.fit(32_767), None);
+    }
+
+    #[test]
+    fn a_limit_that_fits_keeps_the_wanted_pages() {
+        assert_eq!(
+            plan_buffers(false, Some(DEFAULT), Some(DEFAULT), MAPS, &SIZING),
+            Ok((16, MemlockOutcome::NotCharged))
+        );
+        assert_eq!(
+            plan_buffers(true, Some(DEFAULT), None, MAPS, &SIZING),
+            Ok((
+                16,
+                MemlockOutcome::Raised {
+                    from: DEFAULT,
+                    to: None
+                }
+            ))
+        );
+        assert_eq!(
+            plan_buffers(true, Some(8 << 20), Some(8 << 20), MAPS, &SIZING),
+            Ok((
+                16,
+                MemlockOutcome::Sufficient {
+                    limit: Some(8 << 20)
+                }
+            ))
+        );
+        assert_eq!(
+            plan_buffers(true, Some(DEFAULT), Some(2 << 20), MAPS, &SIZING),
+            Ok((
+                16,
+                MemlockOutcome::Raised {
+                    from: DEFAULT,
+                    to: Some(2 << 20)
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn a_tight_limit_cuts_the_pages() {
+        // 512 KB next to 300 KB of maps
+        assert_eq!(
+            plan_buffers(true, Some(512 << 10), Some(512 << 10), MAPS, &SIZING),
+            Ok((
+                8,
+                MemlockOutcome::Reduced {
+                    limit: 512 << 10,
+                    wanted_pages: 16,
+                    pages: 8
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn too_low_a_limit_names_what_to_raise_it_to() {
+        let too_low = plan_buffers(true, Some(DEFAULT), Some(DEFAULT), MAPS, &SIZING);
+        assert_eq!(
+            too_low,
+            Err(CollectorError::MemlockTooLow {
+                limit: DEFAULT,
+                needed: MAPS + 32_768
+            })
+        );
+        let message = too_low.unwrap_err().to_string();
+        assert!(message.contains("RLIMIT_MEMLOCK is 64 KB"), "{}", message);
+        assert!(message.contains("ulimit -l 332"), "{}", message);
+
+        // A fit only below min_pages
+        let min_four = BufferSizing {
+            min_pages: 4,
+            ..SIZING
+        };
+        assert!(matches!(
+            plan_buffers(true, None, Some(MAPS + 60_000), MAPS, &min_four),
+            Err(CollectorError::MemlockTooLow { .. })
+        ));
+    }
+}
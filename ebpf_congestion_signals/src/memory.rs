@@ -0,0 +1,127 @@
+//What the collector's state costs in memory, for sidecars with a fixed budget.
+//Userspace figures are estimates: entries times key and value size (plus a
+//control byte per slot for hash tables, counted at allocated capacity), no
+//allocator overhead. BPF map figures are what the object declares, most of
+//which the kernel allocates up front whatever is in use.
+
+use aya::maps::Map;
+use aya::util::{nr_cpus, online_cpus};
+use aya::Ebpf;
+use std::mem::size_of;
+
+/// Built by `CongestionCollector::memory_report()`
+#[derive(Debug, Clone, Default)]
+pub struct MemoryReport {
+    /// Userspace tables and queues
+    pub structures: Vec<StructureMemory>,
+    /// Maps of the running eBPF object
+    pub bpf_maps: Vec<BpfMapMemory>,
+    /// Each shard of the per-socket table, which `structures` totals
+    pub socket_shards: Vec<ShardMemory>,
+}
+
+/// One userspace structure
+#[derive(Debug, Clone)]
+pub struct StructureMemory {
+    pub name: &'static str,
+    pub entries: usize,
+    /// Most entries it holds before evicting (or refusing new ones)
+    pub cap: usize,
+    pub estimated_bytes: u64,
+    /// Entries evicted or refused at the cap since load
+    pub evictions: u64,
+}
+
+/// One shard of the per-socket table
+#[derive(Debug, Clone)]
+pub struct ShardMemory {
+    pub entries: usize,
+    /// Entries before the shard evicts
+    pub cap: usize,
+    pub estimated_bytes: u64,
+    /// Entries evicted at the cap since load, and the eviction passes that
+    /// dropped them, an eighth of the shard each
+    pub evictions: u64,
+    pub eviction_passes: u64,
+    /// Locks of the shard that waited on another holder: the processing task
+    /// on a snapshot's copy, or the other way round
+    pub contended: u64,
+}
+
+/// One BPF map; the perf event array includes its per-CPU ring buffers
+#[derive(Debug, Clone)]
+pub struct BpfMapMemory {
+    pub name: String,
+    pub max_entries: u32,
+    pub estimated_bytes: u64,
+}
+
+impl MemoryReport {
+    pub fn userspace_bytes(&self) -> u64 {
+        self.structures.iter().map(|s| s.estimated_bytes).sum()
+    }
+
+    pub fn bpf_bytes(&self) -> u64 {
+        self.bpf_maps.iter().map(|m| m.estimated_bytes).sum()
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.userspace_bytes() + self.bpf_bytes()
+    }
+
+    /// Total evictions across structures; non-zero means some cap was hit
+    pub fn evictions(&self) -> u64 {
+        self.structures.iter().map(|s| s.evictions).sum()
+    }
+}
+
+/// A hash table with room for `capacity` entries
+pub(crate) fn hash_table_bytes<K, V>(capacity: usize) -> u64 {
+    (capacity * (size_of::<K>() + size_of::<V>() + 1)) as u64
+}
+
+/// `entries` stored a key and value each, trees and queues
+pub(crate) fn entry_bytes<K, V>(entries: usize) -> u64 {
+    (entries * (size_of::<K>() + size_of::<V>())) as u64
+}
+
+/// Declared sizes of every map in a freshly loaded object, before any is taken
+pub(crate) fn bpf_maps(ebpf: &Ebpf, buffer_pages: usize) -> Vec<BpfMapMemory> {
+    let possible_cpus = nr_cpus().unwrap_or(1) as u64;
+    let online_cpus = online_cpus().map_or(1, |cpus| cpus.len()) as u64;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(4096) as u64;
+
+    let mut maps: Vec<BpfMapMemory> = ebpf
+        .maps()
+        .filter_map(|(name, map)| {
+            // The kinds this object declares
+            let (data, per_cpu) = match map {
+                Map::PerCpuArray(data) | Map::PerCpuHashMap(data) | Map::PerCpuLruHashMap(data) => {
+                    (data, true)
+                }
+                Map::Array(data)
+                | Map::HashMap(data)
+                | Map::LruHashMap(data)
+                | Map::PerfEventArray(data) => (data, false),
+                _ => return None,
+            };
+            let info = data.info().ok()?;
+            let entries = info.max_entries() as u64;
+            let key = info.key_size() as u64;
+            let value = info.value_size() as u64;
+            let estimated_bytes = match map {
+                // Each CPU's buffer has a metadata page over its data pages
+                Map::PerfEventArray(_) => online_cpus * (buffer_pages as u64 + 1) * page_size,
+                _ if per_cpu => entries * (key + value * possible_cpus),
+                _ => entries * (key + value),
+            };
+            Some(BpfMapMemory {
+                name: name.to_string(),
+                max_entries: info.max_entries(),
+                estimated_bytes,
+            })
+        })
+        .collect();
+    maps.sort_by_key(|map| std::cmp::Reverse(map.estimated_bytes));
+    maps
+}
@@ -0,0 +1,255 @@
+//Microbursts against sustained congestion. Aggregated per interval, one 20 ms
+//burst that dropped five packets and a second of steady loss both read as
+//drops > 0. Fast snapshots (fast.rs) split the interval into buckets; the
+//classifier marks each bucket that dropped or filled a send buffer, and when
+//the interval closes judges the episode by how much of it was pressured: under
+//`microburst_fraction` it was confined to a burst, over it sustained. Bursts
+//that keep starting at the same spacing, `periodic_bursts` of them within
+//`period_tolerance`, are periodic whatever their share; an application
+//flushing a batch every 100 ms looks like that. Time is the buckets' own
+//`interval_ns` summed, so a recorded or scripted trace classifies the same.
+
+use crate::FastSignals;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// When a pressured stretch counts as a microburst or a periodic one, part of
+/// `SeverityThresholds` and `GovernorPolicy`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BurstConfig {
+    /// Share of the interval's buckets pressured below which the episode is a
+    /// microburst, 0.25 = under a quarter of it
+    pub microburst_fraction: f64,
+    /// A bucket whose fullest send buffer is at or above this is pressured,
+    /// as one that dropped anything is
+    pub wmem_pressure: f64,
+    /// Burst starts in a row with even spacing that make an episode periodic,
+    /// at least 3
+    pub periodic_bursts: usize,
+    /// How far each spacing may be from their mean and still be even, 0.2 =
+    /// within a fifth of it
+    pub period_tolerance: f64,
+}
+
+impl Default for BurstConfig {
+    fn default() -> Self {
+        Self {
+            microburst_fraction: 0.25,
+            wmem_pressure: 0.9,
+            periodic_bursts: 4,
+            period_tolerance: 0.2,
+        }
+    }
+}
+
+/// What kind of congestion episode an interval held, see [`BurstClassifier`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BurstClass {
+    /// Pressure in under `microburst_fraction` of the interval, with no period
+    Microburst,
+    Sustained,
+    /// Bursts starting every `period`
+    Periodic {
+        period: Duration,
+    },
+}
+
+impl BurstClass {
+    /// Short name, e.g. for a metric tag
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Microburst => "microburst",
+            Self::Sustained => "sustained",
+            Self::Periodic { .. } => "periodic",
+        }
+    }
+}
+
+/// One interval's episode, see [`BurstClassifier::classify`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BurstEpisode {
+    pub class: BurstClass,
+    /// Share of the interval's buckets that were pressured, by time
+    pub pressured_fraction: f64,
+    /// Bursts that started in the interval: runs of pressured buckets
+    pub bursts: u32,
+    /// The longest run of pressured buckets, one still going included
+    pub longest: Duration,
+}
+
+/// Classifies congestion episodes from fast snapshots, see the module docs.
+/// `SeverityClassifier` and `Governor` run one each
+#[derive(Debug, Clone)]
+pub struct BurstClassifier {
+    config: BurstConfig,
+    // Bucket time seen so far, ns
+    clock_ns: u64,
+    // Of the current interval
+    observed_ns: u64,
+    pressured_ns: u64,
+    bursts: u32,
+    longest_ns: u64,
+    // The run of pressured buckets in progress, ns; 0 outside one
+    run_ns: u64,
+    // Where the latest `periodic_bursts` bursts started on `clock_ns`
+    onsets: VecDeque<u64>,
+}
+
+impl BurstClassifier {
+    pub fn new(config: BurstConfig) -> Self {
+        Self {
+            onsets: VecDeque::with_capacity(config.periodic_bursts),
+            config,
+            clock_ns: 0,
+            observed_ns: 0,
+            pressured_ns: 0,
+            bursts: 0,
+            longest_ns: 0,
+            run_ns: 0,
+        }
+    }
+
+    pub fn config(&self) -> &BurstConfig {
+        &self.config
+    }
+
+    /// Fold in one fast snapshot as the next bucket of the current interval
+    pub fn observe(&mut self, fast: &FastSignals) {
+        let ns = fast.interval_ns;
+        let pressured = fast.drops > 0
+            || fast
+                .wmem_max
+                .is_some_and(|wmem| wmem >= self.config.wmem_pressure);
+        if pressured {
+            if self.run_ns == 0 {
+                self.bursts += 1;
+                if self.onsets.len() == self.config.periodic_bursts.max(3) {
+                    self.onsets.pop_front();
+                }
+                self.onsets.push_back(self.clock_ns);
+            }
+            self.run_ns += ns;
+            self.pressured_ns += ns;
+            self.longest_ns = self.longest_ns.max(self.run_ns);
+        } else {
+            self.run_ns = 0;
+        }
+        self.clock_ns += ns;
+        self.observed_ns += ns;
+    }
+
+    /// Close the interval: None when none of its buckets were pressured or
+    /// there were none. A burst running on carries over into the next one
+    pub fn classify(&mut self) -> Option<BurstEpisode> {
+        let episode = (self.pressured_ns > 0 && self.observed_ns > 0).then(|| {
+            let pressured_fraction = self.pressured_ns as f64 / self.observed_ns as f64;
+            let class = match self.period() {
+                // Bursts in a row, not one burst that never ended
+                Some(period) if self.run_ns < period.as_nanos() as u64 => {
+                    BurstClass::Periodic { period }
+                }
+                _ if pressured_fraction < self.config.microburst_fraction => BurstClass::Microburst,
+                _ => BurstClass::Sustained,
+            };
+            BurstEpisode {
+                class,
+                pressured_fraction,
+                bursts: self.bursts,
+                longest: Duration::from_nanos(self.longest_ns),
+            }
+        });
+        self.observed_ns = 0;
+        self.pressured_ns = 0;
+        self.bursts = 0;
+        self.longest_ns = self.run_ns;
+        episode
+    }
+
+    /// The mean spacing of the latest burst starts, when there are enough of
+    /// them and they're even
+    fn period(&self) -> Option<Duration> {
+        let wanted = self.config.periodic_bursts.max(3);
+        if self.onsets.len() < wanted {
+            return None;
+        }
+        let gaps: Vec<u64> = self
+            .onsets
+            .iter()
+            .zip(self.onsets.iter().skip(1))
+            .map(|(earlier, later)| later - earlier)
+            .collect();
+        let mean = gaps.iter().sum::<u64>() as f64 / gaps.len() as f64;
+        let tolerance = mean * self.config.period_tolerance;
+        gaps.iter()
+            .all(|&gap| (gap as f64 - mean).abs() <= tolerance)
+            .then(|| Duration::from_nanos(mean.round() as u64))
+    }
+}
+
+impl Default for BurstClassifier {
+    fn default() -> Self {
+        Self::new(BurstConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::burst_buckets;
+
+    fn classify(buckets: &[FastSignals]) -> Option<BurstEpisode> {
+        let mut classifier = BurstClassifier::default();
+        for fast in buckets {
+            classifier.observe(fast);
+        }
+        classifier.classify()
+    }
+
+    #[test]
+    fn one_short_burst_is_a_microburst() {
+        let episode = classify(&burst_buckets(|i| if i == 25 { 400 } else { 0 })).unwrap();
+        assert_eq!(episode.class, BurstClass::Microburst);
+        assert_eq!(episode.bursts, 1);
+        assert_eq!(episode.longest, Duration::from_millis(20));
+        assert!((episode.pressured_fraction - 0.02).abs() < 1e-9);
+    }
+
+    #[test]
+    fn pressure_through_most_of_the_interval_is_sustained() {
+        let episode = classify(&burst_buckets(|i| if i < 40 { 10 } else { 0 })).unwrap();
+        assert_eq!(episode.class, BurstClass::Sustained);
+        assert_eq!(episode.longest, Duration::from_millis(800));
+    }
+
+    #[test]
+    fn evenly_spaced_bursts_are_periodic_at_their_spacing() {
+        let episode = classify(&burst_buckets(|i| if i % 10 < 2 { 40 } else { 0 })).unwrap();
+        let period = Duration::from_millis(200);
+        assert_eq!(episode.class, BurstClass::Periodic { period });
+        assert_eq!(episode.bursts, 5);
+        assert_eq!(episode.longest, Duration::from_millis(40));
+    }
+
+    #[test]
+    fn full_send_buffers_are_pressure_and_a_quiet_interval_is_none() {
+        let mut buckets = burst_buckets(|_| 0);
+        assert_eq!(classify(&buckets), None);
+        buckets[3].wmem_max = Some(0.95);
+        assert_eq!(classify(&buckets).unwrap().class, BurstClass::Microburst);
+    }
+
+    #[test]
+    fn a_burst_running_on_carries_into_the_next_interval() {
+        let mut classifier = BurstClassifier::default();
+        for fast in burst_buckets(|i| u64::from(i >= 48)) {
+            classifier.observe(&fast);
+        }
+        classifier.classify();
+        for fast in burst_buckets(|i| u64::from(i < 2)) {
+            classifier.observe(&fast);
+        }
+        let episode = classifier.classify().unwrap();
+        assert_eq!(episode.bursts, 0);
+        assert_eq!(episode.longest, Duration::from_millis(80));
+    }
+}
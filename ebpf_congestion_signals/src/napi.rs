@@ -0,0 +1,603 @@
+//Where network softirq time goes: per vector, and for NET_RX per NAPI device.
+//`softirq_ns` says how much NET_RX ran, not whether the NIC's poll or a virtio
+//device's took it; on a host with several interfaces that's the question.
+//
+//The napi_poll probe keeps the first polls of every NET_RX round per CPU, and
+//the exit of a round at least `long_softirq` long sends them ahead of itself as
+//EVENT_NAPI_POLL, each stamped with its poll's end. The rounds short of that
+//send nothing and count in their vector's total only. Userspace puts each poll
+//in the round that covers it: same CPU and inside the window the exit's
+//duration gives, [exit - duration, exit]. Polls of one CPU run one after the
+//other, so a poll took the time from the previous poll's end (or the round's
+//start) to its own. Windows of different CPUs overlap freely and are kept apart
+//by CPU. What's left of a long round after its last poll is net_rx_action
+//itself, RPS and polls past the kernel's per-round limit, counted unattributed.
+//
+//The events pass the reordering stage, which `with_softirq_breakdown` turns
+//on, so polls that arrive late across CPUs still reach their round's exit in
+//time order.
+//
+//Bond and team slaves poll as themselves; the master has no NAPI of its own.
+//Under `set_bonds` a slave's polls also count toward its master, as of when
+//they ran, and the interval puts the slaves' rows under the master's. A slave
+//removed from its master mid-interval keeps its polls until then in the
+//master and is marked `departed`.
+
+use crate::{BondTopology, CongestionEvent, EVENT_NAPI_POLL, EVENT_SOFTIRQ_EXIT};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+// vec_nr of NET_TX_SOFTIRQ and NET_RX_SOFTIRQ, the two the probes time
+const NET_TX_SOFTIRQ: u32 = 2;
+const NET_RX_SOFTIRQ: u32 = 3;
+// Polls held per CPU waiting for their round's exit; the kernel sends at most
+// 8 per round. Past it the oldest counts unmatched
+const MAX_PENDING: usize = 64;
+// Name the devices past `max_interfaces` are counted under
+const OTHER_INTERFACES: &str = "[other]";
+// Frame of a long round's unattributed time in `SoftirqBreakdown::folded`
+const UNATTRIBUTED: &str = "[unattributed]";
+
+/// Turns on `CongestionCollector::read_per_interface`, see
+/// `CollectorConfig::with_softirq_breakdown`
+#[derive(Debug, Clone)]
+pub struct SoftirqBreakdownConfig {
+    /// NET_RX rounds at least this long have their polls sent and broken down
+    pub long_softirq: Duration,
+    /// Devices reported by name, the rest under "[other]"
+    pub max_interfaces: usize,
+}
+
+impl Default for SoftirqBreakdownConfig {
+    fn default() -> Self {
+        Self {
+            long_softirq: Duration::from_micros(100),
+            max_interfaces: 64,
+        }
+    }
+}
+
+/// One softirq vector over an interval
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SoftirqVectorTime {
+    /// 2 for NET_TX, 3 for NET_RX
+    pub vec_nr: u32,
+    pub exits: u64,
+    pub total_ns: u64,
+    /// NET_RX rounds broken down into polls, `long_softirq` or longer, and
+    /// their time. 0 for NET_TX
+    pub sampled_exits: u64,
+    pub sampled_ns: u64,
+}
+
+impl SoftirqVectorTime {
+    /// "net_tx" or "net_rx"
+    pub fn name(&self) -> &'static str {
+        match self.vec_nr {
+            NET_TX_SOFTIRQ => "net_tx",
+            NET_RX_SOFTIRQ => "net_rx",
+            _ => "softirq",
+        }
+    }
+}
+
+/// NET_RX time and NAPI work of one device over an interval, from the long
+/// rounds only
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InterfaceSoftirq {
+    pub interface: String,
+    /// Softirq time in this device's polls
+    pub softirq_ns: u64,
+    pub polls: u64,
+    /// Packets its polls processed
+    pub packets: u64,
+    /// Polls that used their whole budget: the device had more queued than
+    /// one poll may take
+    pub budget_exhausted_polls: u64,
+    pub max_poll_ns: u64,
+    /// Long rounds it was polled in
+    pub rounds: u64,
+    /// A bond's or team's slaves, the master's counts being their sums. Empty
+    /// for any other device
+    pub members: Vec<InterfaceSoftirq>,
+    /// A slave that left its master during the interval
+    pub departed: bool,
+}
+
+impl InterfaceSoftirq {
+    /// Packets per poll. None without polls
+    pub fn packets_per_poll(&self) -> Option<f64> {
+        (self.polls > 0).then(|| self.packets as f64 / self.polls as f64)
+    }
+}
+
+/// Softirq time per vector and NET_RX time per NAPI device since the previous
+/// `CongestionCollector::read_per_interface`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SoftirqBreakdown {
+    /// The vectors that exited, NET_TX before NET_RX
+    pub vectors: Vec<SoftirqVectorTime>,
+    /// Most softirq time first, bond and team slaves under their master
+    pub interfaces: Vec<InterfaceSoftirq>,
+    /// Long rounds' time outside the polls sent for them
+    pub unattributed_ns: u64,
+    /// Polls no round's window covered: its exit was masked, discarded or lost
+    pub unmatched_polls: u64,
+}
+
+impl SoftirqBreakdown {
+    pub fn vector(&self, vec_nr: u32) -> Option<&SoftirqVectorTime> {
+        self.vectors.iter().find(|vector| vector.vec_nr == vec_nr)
+    }
+
+    /// A device by name, a master's slaves too
+    pub fn interface(&self, name: &str) -> Option<&InterfaceSoftirq> {
+        self.interfaces
+            .iter()
+            .flat_map(|interface| std::iter::once(interface).chain(&interface.members))
+            .find(|interface| interface.interface == name)
+    }
+
+    /// Folded stacks for flamegraph.pl or inferno, ns as the sample count: a
+    /// `net_rx;eth0` line per device, `net_rx;[unattributed]`, and each
+    /// vector's time not broken down on a line of its own. A master's slaves
+    /// are `net_rx;bond0;eth0`
+    pub fn folded(&self) -> String {
+        let mut out = String::new();
+        for vector in &self.vectors {
+            let own = vector.total_ns.saturating_sub(vector.sampled_ns);
+            if own > 0 {
+                out.push_str(&format!("{} {}\n", vector.name(), own));
+            }
+            if vector.vec_nr != NET_RX_SOFTIRQ {
+                continue;
+            }
+            for interface in &self.interfaces {
+                if interface.members.is_empty() && interface.softirq_ns > 0 {
+                    out.push_str(&format!(
+                        "{};{} {}\n",
+                        vector.name(),
+                        interface.interface,
+                        interface.softirq_ns
+                    ));
+                }
+                for member in interface.members.iter().filter(|m| m.softirq_ns > 0) {
+                    out.push_str(&format!(
+                        "{};{};{} {}\n",
+                        vector.name(),
+                        interface.interface,
+                        member.interface,
+                        member.softirq_ns
+                    ));
+                }
+            }
+            if self.unattributed_ns > 0 {
+                out.push_str(&format!(
+                    "{};{} {}\n",
+                    vector.name(),
+                    UNATTRIBUTED,
+                    self.unattributed_ns
+                ));
+            }
+        }
+        out
+    }
+}
+
+struct Poll {
+    at_ns: u64,
+    device: String,
+    work: u32,
+    budget: u32,
+}
+
+/// The correlation on its own, e.g. over a recording's events. Feed it events
+/// in timestamp order, or at least per-CPU order, with `record` and take the
+/// totals with `take_interval`
+pub struct SoftirqBreakdownTracker {
+    long_softirq_ns: u64,
+    max_interfaces: usize,
+    // cpu -> polls not yet put in a round
+    pending: HashMap<u32, VecDeque<Poll>>,
+    // By vec_nr
+    vectors: HashMap<u32, SoftirqVectorTime>,
+    interfaces: HashMap<String, InterfaceSoftirq>,
+    unattributed_ns: u64,
+    unmatched_polls: u64,
+    // Slave -> master, from the last `set_bonds`
+    bonds: HashMap<String, String>,
+    // The interval's polled slaves -> their master when polled, and the long
+    // rounds each master was polled in
+    members: HashMap<String, String>,
+    master_rounds: HashMap<String, u64>,
+}
+
+impl SoftirqBreakdownTracker {
+    pub fn new(config: &SoftirqBreakdownConfig) -> Self {
+        Self {
+            long_softirq_ns: config.long_softirq.as_nanos() as u64,
+            max_interfaces: config.max_interfaces,
+            pending: HashMap::new(),
+            vectors: HashMap::new(),
+            interfaces: HashMap::new(),
+            unattributed_ns: 0,
+            unmatched_polls: 0,
+            bonds: HashMap::new(),
+            members: HashMap::new(),
+            master_rounds: HashMap::new(),
+        }
+    }
+
+    /// Roll slaves' polls up into their master from now on. The collector
+    /// reads the topology again at every `read_per_interface`, before taking
+    /// the interval
+    pub fn set_bonds(&mut self, topology: &BondTopology) {
+        self.bonds = topology
+            .masters
+            .iter()
+            .flat_map(|master| {
+                master
+                    .slaves
+                    .iter()
+                    .map(|slave| (slave.name.clone(), master.name.clone()))
+            })
+            .collect();
+    }
+
+    pub fn record(&mut self, event: &CongestionEvent) {
+        match event.event_type {
+            EVENT_NAPI_POLL => {
+                let napi = unsafe { event.data.napi };
+                let pending = self.pending.entry(event.cpu_id).or_default();
+                if pending.len() == MAX_PENDING {
+                    pending.pop_front();
+                    self.unmatched_polls += 1;
+                }
+                pending.push_back(Poll {
+                    at_ns: event.timestamp_ns,
+                    device: napi.device(),
+                    work: napi.work,
+                    budget: napi.budget,
+                });
+            }
+            EVENT_SOFTIRQ_EXIT => {
+                let softirq = unsafe { event.data.softirq };
+                if softirq.vec_nr != NET_TX_SOFTIRQ && softirq.vec_nr != NET_RX_SOFTIRQ {
+                    return;
+                }
+                let vector = self
+                    .vectors
+                    .entry(softirq.vec_nr)
+                    .or_insert(SoftirqVectorTime {
+                        vec_nr: softirq.vec_nr,
+                        ..Default::default()
+                    });
+                vector.exits += 1;
+                vector.total_ns += softirq.duration_ns;
+                if softirq.vec_nr == NET_RX_SOFTIRQ {
+                    self.round(event.cpu_id, event.timestamp_ns, softirq.duration_ns);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // A NET_RX round on `cpu` exiting at `exit_ns`: take the polls its window
+    // covers and split its time between them
+    fn round(&mut self, cpu: u32, exit_ns: u64, duration_ns: u64) {
+        let start_ns = exit_ns.saturating_sub(duration_ns);
+        let mut polls = Vec::new();
+        if let Some(pending) = self.pending.get_mut(&cpu) {
+            let mut later = VecDeque::new();
+            for poll in pending.drain(..) {
+                if poll.at_ns < start_ns {
+                    // An earlier round's, whose exit never came
+                    self.unmatched_polls += 1;
+                } else if poll.at_ns <= exit_ns {
+                    polls.push(poll);
+                } else {
+                    later.push_back(poll);
+                }
+            }
+            *pending = later;
+        }
+        if polls.is_empty() && duration_ns < self.long_softirq_ns {
+            return;
+        }
+        if let Some(vector) = self.vectors.get_mut(&NET_RX_SOFTIRQ) {
+            vector.sampled_exits += 1;
+            vector.sampled_ns += duration_ns;
+        }
+
+        // Late arrivals put back in poll order
+        polls.sort_by_key(|poll| poll.at_ns);
+        let mut polled: Vec<String> = Vec::new();
+        let mut polled_masters: Vec<String> = Vec::new();
+        let mut cursor = start_ns;
+        for poll in polls {
+            let poll_ns = poll.at_ns - cursor;
+            cursor = poll.at_ns;
+            let name = if self.interfaces.contains_key(&poll.device)
+                || self.interfaces.len() < self.max_interfaces
+            {
+                poll.device
+            } else {
+                OTHER_INTERFACES.to_string()
+            };
+            let interface =
+                self.interfaces
+                    .entry(name.clone())
+                    .or_insert_with(|| InterfaceSoftirq {
+                        interface: name.clone(),
+                        ..Default::default()
+                    });
+            interface.softirq_ns += poll_ns;
+            interface.polls += 1;
+            interface.packets += poll.work as u64;
+            if poll.budget > 0 && poll.work >= poll.budget {
+                interface.budget_exhausted_polls += 1;
+            }
+            interface.max_poll_ns = interface.max_poll_ns.max(poll_ns);
+            if let Some(master) = self.bonds.get(&name) {
+                self.members.insert(name.clone(), master.clone());
+                if !polled_masters.contains(master) {
+                    *self.master_rounds.entry(master.clone()).or_default() += 1;
+                    polled_masters.push(master.clone());
+                }
+            }
+            if !polled.contains(&name) {
+                interface.rounds += 1;
+                polled.push(name);
+            }
+        }
+        self.unattributed_ns += exit_ns - cursor;
+    }
+
+    /// Polls held waiting for their round's exit
+    pub fn pending(&self) -> usize {
+        self.pending.values().map(VecDeque::len).sum()
+    }
+
+    /// What was broken down since the last call. Polls still waiting for
+    /// their exit count in the interval it comes in
+    pub fn take_interval(&mut self) -> SoftirqBreakdown {
+        let mut vectors: Vec<SoftirqVectorTime> = self.vectors.drain().map(|(_, v)| v).collect();
+        vectors.sort_by_key(|vector| vector.vec_nr);
+        let mut interfaces = Vec::new();
+        let mut masters: HashMap<String, InterfaceSoftirq> = HashMap::new();
+        for (name, mut interface) in self.interfaces.drain() {
+            let Some(master) = self.members.remove(&name) else {
+                interfaces.push(interface);
+                continue;
+            };
+            interface.departed = self.bonds.get(&name) != Some(&master);
+            let row = masters
+                .entry(master.clone())
+                .or_insert_with(|| InterfaceSoftirq {
+                    rounds: self.master_rounds.remove(&master).unwrap_or(0),
+                    interface: master,
+                    ..Default::default()
+                });
+            row.softirq_ns += interface.softirq_ns;
+            row.polls += interface.polls;
+            row.packets += interface.packets;
+            row.budget_exhausted_polls += interface.budget_exhausted_polls;
+            row.max_poll_ns = row.max_poll_ns.max(interface.max_poll_ns);
+            row.members.push(interface);
+        }
+        for (_, mut master) in masters {
+            master.members.sort_by(by_softirq);
+            interfaces.push(master);
+        }
+        interfaces.sort_by(by_softirq);
+        self.members.clear();
+        self.master_rounds.clear();
+        SoftirqBreakdown {
+            vectors,
+            interfaces,
+            unattributed_ns: std::mem::take(&mut self.unattributed_ns),
+            unmatched_polls: std::mem::take(&mut self.unmatched_polls),
+        }
+    }
+
+    /// Drop what's held, e.g. after the kernel clock jumped
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+}
+
+// Most softirq time first, then by name
+fn by_softirq(a: &InterfaceSoftirq, b: &InterfaceSoftirq) -> std::cmp::Ordering {
+    b.softirq_ns
+        .cmp(&a.softirq_ns)
+        .then_with(|| a.interface.cmp(&b.interface))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::fixtures::{self, NAPI_WEIGHT};
+    use crate::{EventReorderer, EventReordering};
+    use std::sync::Arc;
+
+    /// Long NET_RX rounds on two CPUs whose windows overlap, each polling
+    /// devices the other also polls, fed through the reordering stage in the
+    /// interleaved order two CPUs' readers hand them over
+    #[test]
+    fn polls_land_in_their_own_cpus_overlapping_round() {
+        let mut reorderer = EventReorderer::new(EventReordering {
+            watermark: Duration::from_millis(5),
+            max_buffered: 1024,
+        })
+        .with_clock(Arc::new(ManualClock::new()));
+        let mut tracker = SoftirqBreakdownTracker::new(&SoftirqBreakdownConfig {
+            long_softirq: Duration::from_micros(100),
+            max_interfaces: 8,
+        });
+        let poll = fixtures::napi_poll;
+        let exit = fixtures::softirq_exit;
+        // CPU 0's round covers 1.0-1.3 ms, CPU 1's 1.15-1.4 ms. CPU 1's polls
+        // are read after CPU 0's exit, one of them inside CPU 0's window
+        let arrivals = [
+            poll(1_120_000, 0, "eth0", NAPI_WEIGHT),
+            poll(1_200_000, 0, "virtio0", 10),
+            poll(1_280_000, 0, "eth0", 30),
+            exit(1_300_000, 0, NET_RX_SOFTIRQ, 300_000),
+            poll(1_250_000, 1, "virtio0", NAPI_WEIGHT),
+            poll(1_390_000, 1, "eth1", 5),
+            exit(1_400_000, 1, NET_RX_SOFTIRQ, 250_000),
+            // Too short to break down, and a NET_TX round
+            exit(2_000_000, 0, NET_RX_SOFTIRQ, 20_000),
+            exit(2_100_000, 0, NET_TX_SOFTIRQ, 40_000),
+            // The exit of this poll's round was masked
+            poll(2_500_000, 1, "eth1", 3),
+            poll(3_150_000, 1, "eth1", 8),
+            exit(3_200_000, 1, NET_RX_SOFTIRQ, 200_000),
+        ];
+        for event in arrivals {
+            reorderer.push(event, |event| tracker.record(&event));
+        }
+        reorderer.flush(|event| tracker.record(&event));
+        let breakdown = tracker.take_interval();
+
+        // Each poll took the time since the previous one on its CPU
+        let device = |name: &str| {
+            breakdown.interface(name).map(|interface| {
+                (
+                    interface.softirq_ns,
+                    interface.polls,
+                    interface.packets,
+                    interface.budget_exhausted_polls,
+                    interface.rounds,
+                )
+            })
+        };
+        assert_eq!(device("eth0"), Some((200_000, 2, 94, 1, 1)));
+        assert_eq!(device("virtio0"), Some((180_000, 2, 74, 1, 2)));
+        assert_eq!(device("eth1"), Some((290_000, 2, 13, 0, 2)));
+        let order: Vec<&str> = breakdown
+            .interfaces
+            .iter()
+            .map(|interface| interface.interface.as_str())
+            .collect();
+        assert_eq!(order, ["eth1", "eth0", "virtio0"]);
+        assert_eq!(breakdown.unattributed_ns, 80_000);
+        assert_eq!(breakdown.unmatched_polls, 1);
+
+        // The short round counts in the total only
+        let net_rx = breakdown.vector(NET_RX_SOFTIRQ).copied().unwrap();
+        assert_eq!(
+            (
+                net_rx.exits,
+                net_rx.total_ns,
+                net_rx.sampled_exits,
+                net_rx.sampled_ns,
+            ),
+            (4, 770_000, 3, 750_000)
+        );
+        let net_tx = breakdown.vector(NET_TX_SOFTIRQ).copied().unwrap();
+        assert_eq!(
+            (net_tx.exits, net_tx.total_ns, net_tx.sampled_exits),
+            (1, 40_000, 0)
+        );
+        assert_eq!(
+            breakdown.folded(),
+            "net_tx 40000\nnet_rx 20000\nnet_rx;eth1 290000\nnet_rx;eth0 200000\n\
+             net_rx;virtio0 180000\nnet_rx;[unattributed] 80000\n"
+        );
+
+        assert_eq!(tracker.take_interval(), SoftirqBreakdown::default());
+        assert_eq!(tracker.pending(), 0);
+    }
+
+    fn row(interface: &InterfaceSoftirq) -> (&str, u64, u64, u64, u64, bool) {
+        (
+            interface.interface.as_str(),
+            interface.softirq_ns,
+            interface.polls,
+            interface.packets,
+            interface.rounds,
+            interface.departed,
+        )
+    }
+
+    /// NAPI polls on each slave of `fixtures::bond_sysfs`, and one 802.3ad
+    /// slave removed before the read
+    #[test]
+    fn slaves_roll_up_under_their_master() {
+        let root = fixtures::bond_sysfs("napi-bonds");
+        let mut tracker = SoftirqBreakdownTracker::new(&SoftirqBreakdownConfig::default());
+        tracker.set_bonds(&BondTopology::read_from(&root));
+        let poll = fixtures::napi_poll;
+        let exit = fixtures::softirq_exit;
+        // CPU 0's round covers 1.0-1.3 ms, CPU 1's 2.0-2.4 ms
+        for event in [
+            poll(1_100_000, 0, "eth0", 20),
+            poll(1_150_000, 0, "eth1", 2),
+            poll(1_250_000, 0, "eth2", 30),
+            exit(1_300_000, 0, NET_RX_SOFTIRQ, 300_000),
+            poll(2_200_000, 1, "eth3", NAPI_WEIGHT),
+            poll(2_300_000, 1, "eth4", 10),
+            poll(2_350_000, 1, "eth2", 5),
+            exit(2_400_000, 1, NET_RX_SOFTIRQ, 400_000),
+        ] {
+            tracker.record(&event);
+        }
+        // eth3 leaves bond1 before the read
+        fixtures::write_file(&root, "bond1/bonding/slaves", "eth2\n");
+        tracker.set_bonds(&BondTopology::read_from(&root));
+        let breakdown = tracker.take_interval();
+
+        let rows: Vec<_> = breakdown.interfaces.iter().map(row).collect();
+        assert_eq!(
+            rows,
+            [
+                ("bond1", 350_000, 3, 99, 2, false),
+                ("bond0", 150_000, 2, 22, 1, false),
+                ("eth4", 100_000, 1, 10, 1, false),
+            ]
+        );
+        let members = |name: &str| -> Vec<_> {
+            breakdown
+                .interfaces
+                .iter()
+                .find(|i| i.interface == name)
+                .unwrap()
+                .members
+                .iter()
+                .map(row)
+                .collect()
+        };
+        // The removed slave's polls until then stay in the master
+        assert_eq!(
+            members("bond1"),
+            [
+                ("eth3", 200_000, 1, 64, 1, true),
+                ("eth2", 150_000, 2, 35, 2, false),
+            ]
+        );
+        assert_eq!(
+            members("bond0"),
+            [
+                ("eth0", 100_000, 1, 20, 1, false),
+                ("eth1", 50_000, 1, 2, 1, false),
+            ]
+        );
+        assert_eq!(breakdown.interface("eth3").map(|i| i.departed), Some(true));
+        assert_eq!(
+            breakdown.folded(),
+            "net_rx;bond1;eth3 200000\nnet_rx;bond1;eth2 150000\n\
+             net_rx;bond0;eth0 100000\nnet_rx;bond0;eth1 50000\nnet_rx;eth4 100000\n\
+             net_rx;[unattributed] 100000\n"
+        );
+
+        // And on its own the interval after
+        tracker.record(&poll(3_100_000, 0, "eth3", 4));
+        tracker.record(&exit(3_200_000, 0, NET_RX_SOFTIRQ, 200_000));
+        let next = tracker.take_interval();
+        let rows: Vec<_> = next.interfaces.iter().map(row).collect();
+        assert_eq!(rows, [("eth3", 100_000, 1, 4, 1, false)]);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,200 @@
+//Host-wide memory pressure on socket buffers. TCP and UDP each have one page
+//counter for every socket buffer on the host (/proc/net/sockstat `mem`) and
+//three sysctl limits (/proc/sys/net/ipv4/tcp_mem, udp_mem: min, pressure,
+//max). Above the pressure threshold the kernel stops growing buffers and
+//shrinks them towards their minimum, every socket at once: sends back up and
+//throughput falls on every path, which is not congestion on any of them.
+//
+//Read from userspace on every interval read, along with the times TCP entered
+//pressure mode (tcp_enter_memory_pressure, TcpExt TCPMemoryPressures in
+//netstat). The page counters and limits are global, the netstat counter is
+//the collector's namespace's.
+
+/// One protocol's socket buffer pages and limits, from /proc/net/sockstat and
+/// its `*_mem` sysctl
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProtoMemory {
+    /// Pages held by the protocol's socket buffers, host-wide
+    pub allocated_pages: u64,
+    /// The sysctl's middle value: above it the kernel is under memory pressure
+    pub pressure_pages: u64,
+    /// The last value: above it buffer allocations fail
+    pub max_pages: u64,
+}
+
+impl ProtoMemory {
+    /// `allocated_pages` over `pressure_pages`, 1.0 and up under pressure.
+    /// None when the limit reads as zero
+    pub fn pressure(&self) -> Option<f64> {
+        (self.pressure_pages > 0).then(|| self.allocated_pages as f64 / self.pressure_pages as f64)
+    }
+}
+
+/// One read of the host's socket buffer memory, see
+/// `CongestionSignals::net_memory_pressure`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NetMemory {
+    /// None when sockstat or the sysctl couldn't be read or parsed
+    pub tcp: Option<ProtoMemory>,
+    pub udp: Option<ProtoMemory>,
+    /// TcpExt TCPMemoryPressures, cumulative. None when the kernel doesn't
+    /// count it
+    pub tcp_memory_pressures: Option<u64>,
+}
+
+impl NetMemory {
+    /// This host's, each part None where its file can't be read
+    pub fn read() -> Self {
+        let read = |path: &str| std::fs::read_to_string(path).unwrap_or_default();
+        Self::parse(
+            &read("/proc/net/sockstat"),
+            &read("/proc/sys/net/ipv4/tcp_mem"),
+            &read("/proc/sys/net/ipv4/udp_mem"),
+            &read("/proc/net/netstat"),
+        )
+    }
+
+    /// From the text of /proc/net/sockstat, the tcp_mem and udp_mem sysctls
+    /// (three page counts) and /proc/net/netstat
+    pub fn parse(sockstat: &str, tcp_mem: &str, udp_mem: &str, netstat: &str) -> Self {
+        let proto = |name: &str, limits: &str| {
+            let allocated_pages = sockstat_mem(sockstat, name)?;
+            let limits: Vec<u64> = limits
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .ok()?;
+            let [_, pressure_pages, max_pages] = limits[..] else {
+                return None;
+            };
+            Some(ProtoMemory {
+                allocated_pages,
+                pressure_pages,
+                max_pages,
+            })
+        };
+        Self {
+            tcp: proto("TCP", tcp_mem),
+            udp: proto("UDP", udp_mem),
+            tcp_memory_pressures: netstat_counter(netstat, "TcpExt", "TCPMemoryPressures"),
+        }
+    }
+
+    /// The higher of TCP's and UDP's `ProtoMemory::pressure`, None when
+    /// neither could be read
+    pub fn pressure(&self) -> Option<f64> {
+        [self.tcp, self.udp]
+            .into_iter()
+            .flatten()
+            .filter_map(|proto| proto.pressure())
+            .reduce(f64::max)
+    }
+}
+
+/// Turns each interval read's `NetMemory` into the signals' pressure and
+/// pressure entries since the previous read
+#[derive(Debug, Default)]
+pub(crate) struct NetMemoryTracker {
+    last_pressures: Option<u64>,
+}
+
+impl NetMemoryTracker {
+    /// Pressure now and TCP pressure entries since the last call. None entries
+    /// on the first call, and when the counter is missing or went backwards
+    /// (the namespace was recreated)
+    pub(crate) fn observe(&mut self, memory: &NetMemory) -> (Option<f64>, Option<u64>) {
+        let events = match (self.last_pressures, memory.tcp_memory_pressures) {
+            (Some(then), Some(now)) => now.checked_sub(then),
+            _ => None,
+        };
+        self.last_pressures = memory.tcp_memory_pressures;
+        (memory.pressure(), events)
+    }
+}
+
+/// `mem` of a sockstat line, e.g. `TCP: inuse 10 orphan 0 tw 0 alloc 10 mem 45`
+fn sockstat_mem(sockstat: &str, proto: &str) -> Option<u64> {
+    sockstat.lines().find_map(|line| {
+        let (name, fields) = line.split_once(':')?;
+        if name != proto {
+            return None;
+        }
+        let mut fields = fields.split_whitespace();
+        while let Some(field) = fields.next() {
+            if field == "mem" {
+                return fields.next()?.parse().ok();
+            }
+        }
+        None
+    })
+}
+
+/// A counter of /proc/net/netstat: a line of names and a line of values, both
+/// led by `Proto:`
+fn netstat_counter(netstat: &str, proto: &str, counter: &str) -> Option<u64> {
+    let lines: Vec<&str> = netstat.lines().collect();
+    lines.chunks(2).find_map(|pair| {
+        let [names, values] = pair else {
+            return None;
+        };
+        let (names, values) = (
+            names.strip_prefix(proto)?.strip_prefix(':')?,
+            values.strip_prefix(proto)?.strip_prefix(':')?,
+        );
+        names
+            .split_whitespace()
+            .zip(values.split_whitespace())
+            .find(|(name, _)| *name == counter)
+            .and_then(|(_, value)| value.parse().ok())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOCKSTAT: &str = "sockets: used 812\n\
+                            TCP: inuse 640 orphan 2 tw 118 alloc 700 mem 90000\n\
+                            UDP: inuse 31 mem 1200\n\
+                            UDPLITE: inuse 0\n";
+    const NETSTAT: &str = "TcpExt: SyncookiesSent TCPMemoryPressures TCPMemoryPressuresChrono\n\
+                           TcpExt: 0 7 3120\n\
+                           IpExt: InNoRoutes OutOctets\n\
+                           IpExt: 0 123456\n";
+
+    #[test]
+    fn pages_are_taken_over_each_pressure_threshold() {
+        let memory = NetMemory::parse(
+            SOCKSTAT,
+            "70692 94258 141384\n",
+            "4096 8000 16000\n",
+            NETSTAT,
+        );
+        assert_eq!(
+            memory.tcp,
+            Some(ProtoMemory {
+                allocated_pages: 90_000,
+                pressure_pages: 94_258,
+                max_pages: 141_384,
+            })
+        );
+        assert_eq!(memory.udp.and_then(|udp| udp.pressure()), Some(0.15));
+        assert_eq!(memory.tcp_memory_pressures, Some(7));
+        let pressure = memory.pressure().unwrap();
+        assert!((pressure - 90_000.0 / 94_258.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn the_higher_protocol_wins() {
+        let udp_over = NetMemory::parse(SOCKSTAT, "70692 94258 141384", "100 1000 2000", "");
+        assert_eq!(udp_over.pressure(), Some(1.2));
+        assert_eq!(udp_over.tcp_memory_pressures, None);
+    }
+
+    #[test]
+    fn missing_or_malformed_files_read_as_none() {
+        assert_eq!(NetMemory::parse("", "", "", ""), NetMemory::default());
+        let malformed = NetMemory::parse(SOCKSTAT, "70692 94258", "a b c", NETSTAT);
+        assert_eq!(malformed.pressure(), None);
+    }
+}
@@ -0,0 +1,547 @@
+//Network namespaces. The probes fire for every namespace on the host; sends,
+//send buffer samples and TX queue stalls carry the inode of the namespace they
+//happened in (sock_net(sk)->ns.inum, the device's for stalls), 0 when the
+//offsets aren't in kernel BTF. This names those inodes, filters events by an
+//allow-list and keeps the per-namespace breakdown read_per_netns() returns.
+//
+//Names come from /run/netns (what `ip netns` creates) and otherwise from the
+//lowest pid in the namespace, as "pid:<pid>"; the collector's own is "host".
+//An inode nothing names yet triggers a rescan, at most once a second.
+
+use crate::clock::Clock;
+use crate::memory::{hash_table_bytes, StructureMemory};
+use crate::{
+    CongestionEvent, CongestionSignals, EstimatedTotals, ObservedCounts, ProtocolCounts,
+    SampleScale, Signal, EVENT_SOCKET_STATE, EVENT_TCP_SEND, EVENT_UDP_SEND, IPPROTO_TCP,
+    IPPROTO_UDP,
+};
+use std::collections::{HashMap, HashSet};
+use std::ffi::CStr;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+const RESCAN_AFTER: Duration = Duration::from_secs(1);
+
+/// Per-namespace attribution, see `CollectorConfig::with_netns`
+#[derive(Debug, Clone)]
+pub struct NetnsConfig {
+    /// Only count events from these namespaces: names under /run/netns,
+    /// `host` for the collector's own, `pid:<pid>` or inode numbers. Applies to
+    /// the events that carry a namespace (sends and send buffer samples), in
+    /// every signal; drops, queue depth and softirq time are host-wide and
+    /// stay unfiltered. None counts every namespace
+    pub allow: Option<Vec<String>>,
+    /// Namespaces `read_per_netns` keeps per interval, the rest are left out
+    pub max_namespaces: usize,
+}
+
+impl Default for NetnsConfig {
+    fn default() -> Self {
+        Self {
+            allow: None,
+            max_namespaces: 256,
+        }
+    }
+}
+
+/// Inode -> name and a path to open the namespace by
+pub(crate) struct NetnsResolver {
+    own: u32,
+    names: HashMap<u32, (String, PathBuf)>,
+    // (netns, ifindex) -> interface name in that namespace, cleared on rescan
+    interfaces: HashMap<(u32, u32), String>,
+    last_scan: Option<Instant>,
+}
+
+impl NetnsResolver {
+    /// Scans on first use
+    pub(crate) fn new() -> Self {
+        Self {
+            own: ns_inode(Path::new("/proc/self/ns/net")).unwrap_or(0),
+            names: HashMap::new(),
+            interfaces: HashMap::new(),
+            last_scan: None,
+        }
+    }
+
+    fn scan(&mut self) {
+        let mut names = HashMap::new();
+        if let Ok(entries) = std::fs::read_dir("/run/netns") {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if let Some(inode) = ns_inode(&path) {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    names.entry(inode).or_insert((name, path));
+                }
+            }
+        }
+        if self.own != 0 {
+            names
+                .entry(self.own)
+                .or_insert(("host".to_string(), PathBuf::from("/proc/self/ns/net")));
+        }
+        let mut pids: Vec<u32> = std::fs::read_dir("/proc")
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter_map(|e| e.file_name().to_str()?.parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        pids.sort_unstable();
+        for pid in pids {
+            let path = PathBuf::from(format!("/proc/{}/ns/net", pid));
+            if let Some(inode) = ns_inode(&path) {
+                names
+                    .entry(inode)
+                    .or_insert_with(|| (format!("pid:{}", pid), path));
+            }
+        }
+        self.names = names;
+        self.interfaces.clear();
+        self.last_scan = Some(Instant::now());
+    }
+
+    /// Rescan for an inode nothing named yet, unless one just ran
+    fn rescan_for(&mut self, inode: u32) {
+        if inode != 0
+            && !self.names.contains_key(&inode)
+            && self.last_scan.is_none_or(|at| at.elapsed() >= RESCAN_AFTER)
+        {
+            self.scan();
+        }
+    }
+
+    pub(crate) fn name(&mut self, inode: u32) -> Option<String> {
+        self.rescan_for(inode);
+        self.names.get(&inode).map(|(name, _)| name.clone())
+    }
+
+    /// Name of an interface in its namespace, the index itself when unknown.
+    /// Other namespaces are entered on a short-lived thread, setns() only
+    /// moves the calling one
+    pub(crate) fn interface_name(&mut self, netns: u32, ifindex: u32) -> String {
+        if netns == 0 || netns == self.own {
+            return interface_name(ifindex).unwrap_or_else(|| ifindex.to_string());
+        }
+        if let Some(name) = self.interfaces.get(&(netns, ifindex)) {
+            return name.clone();
+        }
+        self.rescan_for(netns);
+        let name = self
+            .names
+            .get(&netns)
+            .and_then(|(_, path)| interface_name_in(path, ifindex))
+            .unwrap_or_else(|| ifindex.to_string());
+        self.interfaces.insert((netns, ifindex), name.clone());
+        name
+    }
+
+    /// Inodes the allow-list entries stand for, the ones that exist right now
+    fn resolve_allowed(&self, allow: &[String]) -> HashSet<u32> {
+        allow
+            .iter()
+            .filter_map(|entry| {
+                entry.parse().ok().or_else(|| {
+                    self.names
+                        .iter()
+                        .find(|(_, (name, _))| name == entry)
+                        .map(|(inode, _)| *inode)
+                })
+            })
+            .collect()
+    }
+}
+
+fn ns_inode(path: &Path) -> Option<u32> {
+    std::fs::metadata(path).ok().map(|m| m.ino() as u32)
+}
+
+fn interface_name(ifindex: u32) -> Option<String> {
+    let mut buf = [0 as libc::c_char; libc::IF_NAMESIZE];
+    let name = unsafe { libc::if_indextoname(ifindex, buf.as_mut_ptr()) };
+    if name.is_null() {
+        return None;
+    }
+    Some(
+        unsafe { CStr::from_ptr(name) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+fn interface_name_in(ns: &Path, ifindex: u32) -> Option<String> {
+    let ns = std::fs::File::open(ns).ok()?;
+    std::thread::spawn(move || {
+        if unsafe { libc::setns(ns.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+            return None;
+        }
+        interface_name(ifindex)
+    })
+    .join()
+    .ok()
+    .flatten()
+}
+
+/// Namespace of an event, None for the ones that don't carry it
+pub(crate) fn event_netns(event: &CongestionEvent) -> Option<u32> {
+    match event.event_type {
+        EVENT_UDP_SEND | EVENT_TCP_SEND => Some(unsafe { event.data.sendmsg.netns }),
+        EVENT_SOCKET_STATE => Some(unsafe { event.data.socket.netns }),
+        _ => None,
+    }
+}
+
+/// The allow-list as inodes, checked by the readers before an event counts
+pub(crate) struct NetnsFilter {
+    allow: Vec<String>,
+    // Allowed inodes, and every inode the resolver could name when they were
+    // resolved
+    inodes: RwLock<(HashSet<u32>, HashSet<u32>)>,
+    // An event came from a namespace missing from both
+    unknown_seen: AtomicBool,
+    filtered: AtomicU64,
+}
+
+impl NetnsFilter {
+    pub(crate) fn new(allow: Vec<String>, resolver: &mut NetnsResolver) -> Self {
+        resolver.scan();
+        let filter = Self {
+            allow,
+            inodes: RwLock::default(),
+            unknown_seen: AtomicBool::new(false),
+            filtered: AtomicU64::new(0),
+        };
+        filter.refresh(resolver);
+        filter
+    }
+
+    /// Events without a namespace, or with it unread, always pass. One from a
+    /// namespace created since the last refresh is left out until the next
+    pub(crate) fn allows(&self, event: &CongestionEvent) -> bool {
+        let Some(netns) = event_netns(event).filter(|&netns| netns != 0) else {
+            return true;
+        };
+        let inodes = self.inodes.read().unwrap();
+        if inodes.0.contains(&netns) {
+            return true;
+        }
+        if !inodes.1.contains(&netns) {
+            self.unknown_seen.store(true, Ordering::Relaxed);
+        }
+        self.filtered.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+
+    fn refresh(&self, resolver: &NetnsResolver) {
+        let allowed = resolver.resolve_allowed(&self.allow);
+        let known = resolver.names.keys().copied().collect();
+        *self.inodes.write().unwrap() = (allowed, known);
+    }
+
+    /// Re-resolve the allow-list if an unnamed namespace showed up, from the
+    /// interval read
+    pub(crate) fn refresh_if_needed(&self, resolver: &mut NetnsResolver) {
+        if !self.unknown_seen.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        if resolver
+            .last_scan
+            .is_none_or(|at| at.elapsed() >= RESCAN_AFTER)
+        {
+            resolver.scan();
+        }
+        self.refresh(resolver);
+    }
+
+    /// Events left out since load
+    pub(crate) fn filtered(&self) -> u64 {
+        self.filtered.load(Ordering::Relaxed)
+    }
+}
+
+/// Sends and send buffer samples of one namespace, or one traffic class
+/// (classes.rs)
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SendCounters {
+    send_bytes: u64,
+    loopback_send_bytes: u64,
+    sends: u64,
+    // The part of sends that were TCP's
+    tcp_sends: u64,
+    udp_wmem_total: u64,
+    udp_wmem_samples: u64,
+    tcp_wmem_total: u64,
+    tcp_wmem_samples: u64,
+}
+
+/// Sends and send buffer samples per namespace since the last read, fed from
+/// the processing side
+pub(crate) struct NetnsTable {
+    counters: HashMap<u32, SendCounters>,
+    max_namespaces: usize,
+    evictions: u64,
+    last_take: Instant,
+    exclude_loopback: bool,
+    // Interval bounds and produced_at, CollectorConfig::clock
+    clock: Arc<dyn Clock>,
+}
+
+impl NetnsTable {
+    pub(crate) fn new(
+        max_namespaces: usize,
+        exclude_loopback: bool,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self {
+            counters: HashMap::new(),
+            max_namespaces,
+            evictions: 0,
+            last_take: clock.now(),
+            exclude_loopback,
+            clock,
+        }
+    }
+
+    pub(crate) fn record(&mut self, event: &CongestionEvent) {
+        let Some(netns) = event_netns(event) else {
+            return;
+        };
+        if self.counters.len() >= self.max_namespaces && !self.counters.contains_key(&netns) {
+            self.evictions += 1;
+            return;
+        }
+        self.counters.entry(netns).or_default().record(event);
+    }
+
+    /// Signals per namespace inode since the previous call
+    pub(crate) fn take(&mut self) -> HashMap<u32, CongestionSignals> {
+        let now = self.clock.now();
+        let interval_ns = now.duration_since(self.last_take).as_nanos() as u64;
+        self.last_take = now;
+        let exclude_loopback = self.exclude_loopback;
+        self.counters
+            .drain()
+            .map(|(netns, counters)| (netns, counters.signals(now, interval_ns, exclude_loopback)))
+            .collect()
+    }
+
+    pub(crate) fn memory(&self) -> StructureMemory {
+        StructureMemory {
+            name: "per-netns signals",
+            entries: self.counters.len(),
+            cap: self.max_namespaces,
+            estimated_bytes: hash_table_bytes::<u32, SendCounters>(self.counters.capacity()),
+            evictions: self.evictions,
+        }
+    }
+}
+
+impl SendCounters {
+    /// Sends and send buffer samples, other events are ignored
+    pub(crate) fn record(&mut self, event: &CongestionEvent) {
+        match event.event_type {
+            EVENT_UDP_SEND | EVENT_TCP_SEND => {
+                let send = unsafe { event.data.sendmsg };
+                self.send_bytes += send.bytes;
+                self.sends += 1;
+                self.tcp_sends += (event.event_type == EVENT_TCP_SEND) as u64;
+                if send.loopback != 0 {
+                    self.loopback_send_bytes += send.bytes;
+                }
+            }
+            EVENT_SOCKET_STATE => {
+                let socket = unsafe { event.data.socket };
+                if socket.sndbuf == 0 {
+                    return;
+                }
+                let pressure = socket.wmem_queued as u64 * 1000 / socket.sndbuf as u64;
+                match socket.protocol {
+                    IPPROTO_UDP => {
+                        self.udp_wmem_total += pressure;
+                        self.udp_wmem_samples += 1;
+                    }
+                    IPPROTO_TCP => {
+                        self.tcp_wmem_total += pressure;
+                        self.tcp_wmem_samples += 1;
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Read at `now`, covering the `interval_ns` before it
+    pub(crate) fn signals(
+        &self,
+        now: Instant,
+        interval_ns: u64,
+        exclude_loopback: bool,
+    ) -> CongestionSignals {
+        let pressure = |total: u64, samples: u64| total as f64 / samples as f64 / 1000.0;
+        let wmem_samples = self.udp_wmem_samples + self.tcp_wmem_samples;
+        let mut signals = CongestionSignals {
+            interval_ns,
+            produced_at: Some(now),
+            send_bytes: self.send_bytes,
+            loopback_send_bytes: (!exclude_loopback).then_some(self.loopback_send_bytes),
+            external_send_bytes: self.send_bytes - self.loopback_send_bytes,
+            avg_wmem_pressure: if wmem_samples > 0 {
+                pressure(self.udp_wmem_total + self.tcp_wmem_total, wmem_samples)
+            } else {
+                0.0
+            },
+            udp_wmem_pressure: (self.udp_wmem_samples > 0)
+                .then(|| pressure(self.udp_wmem_total, self.udp_wmem_samples)),
+            tcp_wmem_pressure: (self.tcp_wmem_samples > 0)
+                .then(|| pressure(self.tcp_wmem_total, self.tcp_wmem_samples)),
+            event_count: self.sends + wmem_samples,
+            // Host-wide, with no socket to attribute them to
+            missing_signals: vec![Signal::Drops, Signal::QueueDepth, Signal::Softirq],
+            ..Default::default()
+        };
+        // Per-namespace coverage isn't counted, the estimates are at the configured ratio
+        signals.observed = ObservedCounts::from(&signals);
+        signals.estimated = EstimatedTotals::from_samples(
+            signals.send_bytes,
+            signals.external_send_bytes,
+            SampleScale::nominal(self.sends),
+        );
+        // The GSO probe is host-wide, so a breakdown's packets are its messages
+        signals.est_send_msgs = ProtocolCounts {
+            udp: self.sends - self.tcp_sends,
+            tcp: self.tcp_sends,
+        }
+        .scaled(&signals.estimated.scale);
+        signals.est_wire_packets = signals.est_send_msgs;
+        signals
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::fixtures;
+
+    const HOST: u32 = 100;
+    const TENANT_A: u32 = 200;
+    const TENANT_B: u32 = 300;
+
+    /// Named without a scan, one that just ran so none is due
+    fn resolver() -> NetnsResolver {
+        let mut resolver = NetnsResolver::new();
+        resolver.own = HOST;
+        resolver.names = [
+            (HOST, "host"),
+            (TENANT_A, "tenant-a"),
+            (TENANT_B, "tenant-b"),
+        ]
+        .into_iter()
+        .map(|(inode, name)| (inode, (name.to_string(), PathBuf::from("/nonexistent"))))
+        .collect();
+        resolver.last_scan = Some(Instant::now());
+        resolver
+    }
+
+    fn filter(allow: &[&str], resolver: &NetnsResolver) -> NetnsFilter {
+        let filter = NetnsFilter {
+            allow: allow.iter().map(|entry| entry.to_string()).collect(),
+            inodes: RwLock::default(),
+            unknown_seen: AtomicBool::new(false),
+            filtered: AtomicU64::new(0),
+        };
+        filter.refresh(resolver);
+        filter
+    }
+
+    fn send_in(netns: u32, bytes: u64) -> CongestionEvent {
+        let mut event = fixtures::udp_send(1, 0, 7, bytes);
+        event.data.sendmsg.netns = netns;
+        event
+    }
+
+    #[test]
+    fn the_allow_list_takes_names_and_inodes() {
+        let resolver = resolver();
+        let filter = filter(&["tenant-a", "300", "gone"], &resolver);
+        assert!(filter.allows(&send_in(TENANT_A, 1200)));
+        assert!(filter.allows(&send_in(TENANT_B, 1200)));
+        assert!(!filter.allows(&send_in(HOST, 1200)));
+        // Unread namespaces and host-wide events aren't filtered
+        assert!(filter.allows(&send_in(0, 1200)));
+        assert!(filter.allows(&fixtures::qdisc_drop(1, 0, 3, 0)));
+        assert_eq!(filter.filtered(), 1);
+        assert!(!filter.unknown_seen.load(Ordering::Relaxed));
+
+        // A namespace created since: left out, and a refresh asked for
+        assert!(!filter.allows(&send_in(400, 1200)));
+        assert!(filter.unknown_seen.load(Ordering::Relaxed));
+        assert_eq!(filter.filtered(), 2);
+    }
+
+    #[test]
+    fn unnamed_inodes_rescan_at_most_once_a_second() {
+        let mut resolver = resolver();
+        assert_eq!(resolver.name(TENANT_A).as_deref(), Some("tenant-a"));
+        // Just scanned: the names stay as they were
+        assert_eq!(resolver.name(400), None);
+        assert_eq!(resolver.names.len(), 3);
+    }
+
+    #[test]
+    fn interface_names_are_per_namespace() {
+        let mut resolver = resolver();
+        // The same ifindex in two namespaces is two interfaces
+        resolver
+            .interfaces
+            .insert((TENANT_A, 3), "eth0".to_string());
+        resolver
+            .interfaces
+            .insert((TENANT_B, 3), "veth-b".to_string());
+        assert_eq!(resolver.interface_name(TENANT_A, 3), "eth0");
+        assert_eq!(resolver.interface_name(TENANT_B, 3), "veth-b");
+        // A namespace that can't be entered names it by index, once
+        assert_eq!(resolver.interface_name(TENANT_A, 9), "9");
+        assert_eq!(resolver.interfaces[&(TENANT_A, 9)], "9");
+    }
+
+    #[test]
+    fn the_table_splits_sends_by_namespace_up_to_its_cap() {
+        let clock = ManualClock::new();
+        let mut table = NetnsTable::new(2, false, Arc::new(clock.clone()));
+        table.record(&send_in(TENANT_A, 1200));
+        let mut tcp = fixtures::tcp_send(2, 0, 8, 800);
+        tcp.data.sendmsg.netns = TENANT_A;
+        table.record(&tcp);
+        let mut state = fixtures::socket_state(3, 0, 7, 500, 1000);
+        state.data.socket.netns = TENANT_A;
+        table.record(&state);
+        table.record(&send_in(TENANT_B, 300));
+        // Over the cap
+        table.record(&send_in(400, 9000));
+        // Carries no namespace
+        table.record(&fixtures::qdisc_drop(4, 0, 3, 0));
+        assert_eq!(table.memory().evictions, 1);
+
+        clock.advance(Duration::from_millis(200));
+        let per_netns = table.take();
+        assert_eq!(per_netns.len(), 2);
+        let a = &per_netns[&TENANT_A];
+        assert_eq!(a.send_bytes, 2000);
+        assert_eq!(a.external_send_bytes, 2000);
+        assert_eq!(a.udp_wmem_pressure, Some(0.5));
+        assert_eq!(a.tcp_wmem_pressure, None);
+        assert_eq!(a.event_count, 3);
+        assert_eq!(a.interval_ns, 200_000_000);
+        assert_eq!(a.produced_at, Some(clock.now()));
+        assert!(a.missing_signals.contains(&Signal::Drops));
+        assert_eq!(per_netns[&TENANT_B].send_bytes, 300);
+        assert!(table.take().is_empty());
+    }
+}
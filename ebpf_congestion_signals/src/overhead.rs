@@ -0,0 +1,366 @@
+//CPU overhead of the collector, seen three ways: whole-system busy time (from
+//`/proc/stat`, which also moves with whatever load runs next to it), this
+//process's own CPU from `/proc/self/stat`, and the run time the kernel accounts
+//to the BPF programs (kernel.bpf_stats_enabled, see `BpfStats::enable`).
+//
+//A single difference of two system samples is mostly noise, so these take
+//repeated samples and compare means with a 95% confidence interval.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Two-sided 95% critical values of Student's t for 1..=30 degrees of freedom
+const T_975: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+const Z_975: f64 = 1.960;
+
+fn t_critical(degrees_of_freedom: f64) -> f64 {
+    if degrees_of_freedom >= T_975.len() as f64 + 1.0 {
+        return Z_975;
+    }
+    // Rounding down is the conservative side, past float error in Welch's df
+    let whole = (degrees_of_freedom + 1e-9).floor() as usize;
+    T_975[whole.clamp(1, T_975.len()) - 1]
+}
+
+/// Mean and spread of repeated measurements
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampleStats {
+    pub samples: usize,
+    pub mean: f64,
+    /// Sample standard deviation (n - 1)
+    pub std_dev: f64,
+}
+
+impl SampleStats {
+    /// None below two samples, where there's no spread to go on
+    pub fn of(samples: &[f64]) -> Option<Self> {
+        if samples.len() < 2 {
+            return None;
+        }
+        let n = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / n;
+        let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (n - 1.0);
+        Some(Self {
+            samples: samples.len(),
+            mean,
+            std_dev: variance.sqrt(),
+        })
+    }
+
+    fn std_error(&self) -> f64 {
+        self.std_dev / (self.samples as f64).sqrt()
+    }
+
+    /// 95% confidence interval for the mean
+    pub fn interval(&self) -> ConfidenceInterval {
+        let margin = t_critical(self.samples as f64 - 1.0) * self.std_error();
+        ConfidenceInterval::around(self.mean, margin)
+    }
+
+    /// 95% confidence interval for `self.mean - baseline.mean`, not assuming
+    /// equal variances (Welch)
+    pub fn difference_from(&self, baseline: &SampleStats) -> ConfidenceInterval {
+        let (a, b) = (self.std_error().powi(2), baseline.std_error().powi(2));
+        let difference = self.mean - baseline.mean;
+        if a + b == 0.0 {
+            return ConfidenceInterval::around(difference, 0.0);
+        }
+        let degrees_of_freedom = (a + b).powi(2)
+            / (a.powi(2) / (self.samples as f64 - 1.0)
+                + b.powi(2) / (baseline.samples as f64 - 1.0));
+        let margin = t_critical(degrees_of_freedom) * (a + b).sqrt();
+        ConfidenceInterval::around(difference, margin)
+    }
+}
+
+/// An estimate in percent of all CPUs, with its 95% confidence interval
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConfidenceInterval {
+    pub estimate: f64,
+    pub low: f64,
+    pub high: f64,
+}
+
+impl ConfidenceInterval {
+    fn around(estimate: f64, margin: f64) -> Self {
+        Self {
+            estimate,
+            low: estimate - margin,
+            high: estimate + margin,
+        }
+    }
+
+    /// Whether even the low end is above `limit`, i.e. the samples show the
+    /// limit is exceeded rather than merely being noisy
+    pub fn exceeds(&self, limit: f64) -> bool {
+        self.low > limit
+    }
+}
+
+impl fmt::Display for ConfidenceInterval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.2}% (95% CI {:.2}..{:.2}%)",
+            self.estimate, self.low, self.high
+        )
+    }
+}
+
+/// Cumulative CPU time from /proc/stat and /proc/self/stat
+#[derive(Debug, Clone, Copy)]
+pub struct CpuTimes {
+    /// All CPUs' ticks outside idle and iowait
+    pub system_busy: u64,
+    pub system_total: u64,
+    /// utime + stime of this process
+    pub process: u64,
+    pub cpus: u32,
+    pub at: Instant,
+}
+
+impl CpuTimes {
+    pub fn read() -> std::io::Result<Self> {
+        let stat = std::fs::read_to_string("/proc/stat")?;
+        let invalid = |what| std::io::Error::new(std::io::ErrorKind::InvalidData, what);
+        let (system_busy, system_total) =
+            Self::parse_stat(&stat).ok_or_else(|| invalid("no cpu line in /proc/stat"))?;
+        let cpus = stat
+            .lines()
+            .filter(|line| line.starts_with("cpu") && !line.starts_with("cpu "))
+            .count() as u32;
+        let own = std::fs::read_to_string("/proc/self/stat")?;
+        let process = Self::parse_process_stat(&own)
+            .ok_or_else(|| invalid("unexpected /proc/self/stat format"))?;
+        Ok(Self {
+            system_busy,
+            system_total,
+            process,
+            cpus: cpus.max(1),
+            at: Instant::now(),
+        })
+    }
+
+    /// Busy and total ticks from the aggregate line: user nice system idle
+    /// iowait irq softirq steal. guest is already counted in user
+    pub fn parse_stat(stat: &str) -> Option<(u64, u64)> {
+        let line = stat.lines().find(|line| line.starts_with("cpu "))?;
+        let ticks: Vec<u64> = line
+            .split_whitespace()
+            .skip(1)
+            .take(8)
+            .map(|t| t.parse().ok())
+            .collect::<Option<_>>()?;
+        if ticks.len() < 4 {
+            return None;
+        }
+        let total: u64 = ticks.iter().sum();
+        let idle = ticks[3] + ticks.get(4).copied().unwrap_or(0);
+        Some((total - idle, total))
+    }
+
+    /// utime + stime, fields 14 and 15. The command name before them is in
+    /// parentheses and may contain spaces
+    pub fn parse_process_stat(stat: &str) -> Option<u64> {
+        let after_comm = &stat[stat.rfind(')')? + 1..];
+        let mut fields = after_comm.split_whitespace().skip(11);
+        let utime: u64 = fields.next()?.parse().ok()?;
+        let stime: u64 = fields.next()?.parse().ok()?;
+        Some(utime + stime)
+    }
+}
+
+/// One sample of each perspective over the time between two readings, in
+/// percent of all CPUs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CpuSample {
+    pub system: f64,
+    /// This process, readers and processing task included
+    pub process: f64,
+    /// None when BPF run time isn't being accounted
+    pub bpf: Option<f64>,
+}
+
+impl CpuSample {
+    /// `bpf_run_time` is the programs' cumulative run time at each reading,
+    /// e.g. `CongestionCollector::bpf_run_time`
+    pub fn between(
+        earlier: &CpuTimes,
+        later: &CpuTimes,
+        bpf_run_time: Option<(Duration, Duration)>,
+    ) -> Option<Self> {
+        let total = later.system_total.checked_sub(earlier.system_total)?;
+        let elapsed = later.at.checked_duration_since(earlier.at)?.as_secs_f64();
+        if total == 0 || elapsed <= 0.0 {
+            return None;
+        }
+        let busy = later.system_busy.saturating_sub(earlier.system_busy);
+        let capacity = elapsed * later.cpus as f64;
+        let process_secs =
+            later.process.saturating_sub(earlier.process) as f64 / ticks_per_second();
+        Some(Self {
+            system: 100.0 * busy as f64 / total as f64,
+            process: 100.0 * process_secs / capacity,
+            bpf: bpf_run_time.map(|(before, after)| {
+                100.0 * after.saturating_sub(before).as_secs_f64() / capacity
+            }),
+        })
+    }
+}
+
+fn ticks_per_second() -> f64 {
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as f64,
+        _ => 100.0,
+    }
+}
+
+/// Baseline and loaded samples compared three ways
+#[derive(Debug, Clone, Copy)]
+pub struct OverheadReport {
+    /// Loaded minus baseline system busy time: the collector plus everything
+    /// the load itself costs
+    pub system: Option<ConfidenceInterval>,
+    /// This process while loaded
+    pub process: Option<ConfidenceInterval>,
+    /// The BPF programs while loaded
+    pub bpf: Option<ConfidenceInterval>,
+    /// Process and BPF programs together, what the collector itself costs
+    pub collector: Option<ConfidenceInterval>,
+}
+
+impl OverheadReport {
+    pub fn compare(baseline: &[CpuSample], loaded: &[CpuSample]) -> Self {
+        let system = |samples: &[CpuSample]| {
+            SampleStats::of(&samples.iter().map(|s| s.system).collect::<Vec<_>>())
+        };
+        let process: Vec<f64> = loaded.iter().map(|s| s.process).collect();
+        let bpf: Option<Vec<f64>> = loaded.iter().map(|s| s.bpf).collect();
+        let collector: Option<Vec<f64>> = loaded
+            .iter()
+            .map(|s| s.bpf.map(|bpf| bpf + s.process))
+            .collect();
+        Self {
+            system: system(loaded)
+                .zip(system(baseline))
+                .map(|(loaded, baseline)| loaded.difference_from(&baseline)),
+            process: SampleStats::of(&process).map(|s| s.interval()),
+            bpf: bpf
+                .as_deref()
+                .and_then(SampleStats::of)
+                .map(|s| s.interval()),
+            collector: collector
+                .as_deref()
+                .and_then(SampleStats::of)
+                .map(|s| s.interval()),
+        }
+    }
+
+    /// Whether the collector's own cost is shown to exceed `limit` percent.
+    /// Without BPF run time this falls back to the system difference, which
+    /// can only overstate it
+    pub fn exceeds(&self, limit: f64) -> bool {
+        self.collector
+            .or(self.system)
+            .is_some_and(|interval| interval.exceeds(limit))
+    }
+}
+
+/// BPF run-time accounting (kernel.bpf_stats_enabled) for as long as this is
+/// kept. The accounting costs a clock read per program run
+#[cfg(feature = "collector-core")]
+pub struct BpfStats {
+    _fd: std::os::fd::OwnedFd,
+}
+
+#[cfg(feature = "collector-core")]
+impl BpfStats {
+    /// Needs CAP_SYS_ADMIN and a 5.8+ kernel; older ones only have the sysctl
+    pub fn enable() -> anyhow::Result<Self> {
+        let fd = aya::sys::enable_stats(aya::sys::Stats::RunTime)?;
+        Ok(Self { _fd: fd })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROC_STAT: &str = "cpu  100 0 50 700 100 0 0 0 0 0\ncpu0 100 0 50 700 100 0 0 0 0 0\n";
+    const PROCESS_STAT: &str = "1234 (validate (x)) S 1 1 1 0 -1 4194560 100 0 0 0 42 8 0 0 20 0 4";
+
+    fn close(value: f64, expected: f64) -> bool {
+        (value - expected).abs() < 1e-3
+    }
+
+    fn samples(system: [f64; 5], process: [f64; 5], bpf: Option<f64>) -> Vec<CpuSample> {
+        system
+            .iter()
+            .zip(process)
+            .map(|(&system, process)| CpuSample {
+                system,
+                process,
+                bpf,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stat_lines_parse_to_busy_and_process_ticks() {
+        // Idle and iowait aren't busy
+        assert_eq!(CpuTimes::parse_stat(PROC_STAT), Some((150, 950)));
+        // A command with spaces and parentheses in it
+        assert_eq!(CpuTimes::parse_process_stat(PROCESS_STAT), Some(50));
+    }
+
+    #[test]
+    fn intervals_widen_with_t_for_few_samples() {
+        assert!(SampleStats::of(&[1.0]).is_none());
+        let stats = SampleStats::of(&[10.0, 11.0, 9.0, 10.0, 10.0]).unwrap();
+        assert!(close(stats.mean, 10.0));
+        assert!(close(stats.std_dev, 0.5f64.sqrt()));
+        // 4 degrees of freedom, t = 2.776
+        let interval = stats.interval();
+        assert!(close(interval.high, 10.0 + 2.776 * 0.1f64.sqrt()));
+        // Past the table it's the normal's
+        assert_eq!(t_critical(40.0), Z_975);
+        assert_eq!(t_critical(8.7), 2.306);
+    }
+
+    #[test]
+    fn the_collector_is_judged_on_its_own_interval() {
+        // A 2-point system difference over noise of ±1: about 1% to 3%, while
+        // the collector's own 0.8% is much tighter
+        let baseline = samples([10.0, 11.0, 9.0, 10.0, 10.0], [0.0; 5], Some(0.2));
+        let loaded = samples(
+            [12.0, 13.0, 11.0, 12.0, 12.0],
+            [0.5, 0.7, 0.6, 0.6, 0.6],
+            Some(0.2),
+        );
+        let report = OverheadReport::compare(&baseline, &loaded);
+        let (system, collector) = (report.system.unwrap(), report.collector.unwrap());
+        // Welch with 8 degrees of freedom, t = 2.306
+        assert!(close(system.estimate, 2.0));
+        assert!(close(system.low, 2.0 - 2.306 * 0.2f64.sqrt()));
+        assert!(close(collector.estimate, 0.8));
+        assert!(close(collector.high, 0.8 + 2.776 * 0.001f64.sqrt()));
+        assert!(system.exceeds(0.8));
+        assert!(!report.exceeds(0.8));
+        assert!(report.exceeds(0.7));
+    }
+
+    #[test]
+    fn without_bpf_run_time_the_system_difference_stands_in() {
+        let baseline = samples([10.0, 11.0, 9.0, 10.0, 10.0], [0.0; 5], None);
+        let loaded = samples([12.0, 13.0, 11.0, 12.0, 12.0], [0.6; 5], None);
+        let report = OverheadReport::compare(&baseline, &loaded);
+        assert!(report.collector.is_none() && report.bpf.is_none());
+        assert!(report.process.is_some());
+        // Overstated: the whole 2% counts against the collector
+        assert!(report.exceeds(0.8));
+    }
+}
@@ -0,0 +1,218 @@
+//Sends counted in messages and packets rather than bytes. Per-packet costs
+//(crypto, syscalls, softirq work) dominate at small packet sizes, so a pacing
+//rate means little without the packet rate under it. The sampled sendmsg calls
+//of each protocol, scaled like the byte totals, are `est_send_msgs`; a
+//UDP_SEGMENT (GSO) send is one of them however many packets it leaves as. With
+//the GSO probe on udp_send_skb/udp_v6_send_skb the kernel also counts every
+//UDP skb and the segments its gso_size splits it into, and the UDP messages
+//are multiplied by that interval's segments per skb into `est_wire_packets`.
+//The ratio and not the probe's own count: the probe sees every UDP skb,
+//loopback and other namespaces included, where the sampled sends may not.
+//TCP segments by MSS below the socket and stays counted in messages.
+
+use crate::{
+    CongestionEvent, SampleScale, SendSizeStats, SendSizes, EVENT_TCP_SEND, EVENT_UDP_SEND,
+    SAMPLER_PRIMARY,
+};
+
+/// A send count split by protocol, see `CongestionSignals::est_send_msgs`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProtocolCounts {
+    pub udp: u64,
+    pub tcp: u64,
+}
+
+impl ProtocolCounts {
+    pub fn total(&self) -> u64 {
+        self.udp + self.tcp
+    }
+
+    /// The sampled sendmsg calls of each protocol `sizes` counted
+    pub fn sampled(sizes: &SendSizeStats) -> Self {
+        let samples = |sizes: &Option<SendSizes>| sizes.as_ref().map_or(0, |s| s.samples);
+        Self {
+            udp: samples(&sizes.udp),
+            tcp: samples(&sizes.tcp),
+        }
+    }
+
+    /// Sampled counts times the scale's factor, as the byte totals are
+    pub fn scaled(&self, scale: &SampleScale) -> Self {
+        let scaled = |sampled: u64| (sampled as f64 * scale.send_factor).round() as u64;
+        Self {
+            udp: scaled(self.udp),
+            tcp: scaled(self.tcp),
+        }
+    }
+
+    /// These messages as wire packets: the UDP ones at `gso`'s segments per
+    /// skb, TCP ones as they are. Unchanged without the GSO probe or UDP skbs
+    pub fn wire_packets(&self, gso: Option<&GsoSegments>) -> Self {
+        let Some(per_skb) = gso.and_then(GsoSegments::segments_per_skb) else {
+            return *self;
+        };
+        Self {
+            udp: (self.udp as f64 * per_skb).round() as u64,
+            tcp: self.tcp,
+        }
+    }
+
+    /// Fold in another interval's, as a session's merge does
+    #[cfg(feature = "collector-core")]
+    pub(crate) fn add(&mut self, other: &Self) {
+        self.udp += other.udp;
+        self.tcp += other.tcp;
+    }
+}
+
+/// What the GSO probe counted over one interval, see
+/// `CongestionSignals::udp_gso`. Exact: every UDP skb the host sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GsoSegments {
+    /// UDP skbs handed to IP
+    pub skbs: u64,
+    /// The ones with a gso_size, from UDP_SEGMENT on the socket or per call
+    pub gso_skbs: u64,
+    /// Wire packets they leave as: payload over gso_size, rounded up, for an
+    /// skb with one and over it; 1 for the rest
+    pub segments: u64,
+}
+
+impl GsoSegments {
+    /// Wire packets per UDP skb, 1.0 without GSO. None without skbs
+    pub fn segments_per_skb(&self) -> Option<f64> {
+        (self.skbs > 0).then(|| self.segments as f64 / self.skbs as f64)
+    }
+
+    /// Fold in another interval's, as a session's merge does
+    #[cfg(feature = "collector-core")]
+    pub(crate) fn add(&mut self, other: &Self) {
+        self.skbs += other.skbs;
+        self.gso_skbs += other.gso_skbs;
+        self.segments += other.segments;
+    }
+}
+
+/// `est_send_msgs` and `est_wire_packets` of a run of events, e.g. a
+/// recording's, as an interval read over them would have them. `scale` is the
+/// interval's, see `SampleScale::from_coverage`; `gso` what the probe counted
+/// over it
+pub fn replay_send_packets<'a>(
+    events: impl IntoIterator<Item = &'a CongestionEvent>,
+    scale: &SampleScale,
+    gso: Option<&GsoSegments>,
+) -> (ProtocolCounts, ProtocolCounts) {
+    let mut sampled = ProtocolCounts::default();
+    for event in events {
+        let primary = || unsafe { event.data.sendmsg.samplers } & SAMPLER_PRIMARY != 0;
+        match event.event_type {
+            EVENT_UDP_SEND if primary() => sampled.udp += 1,
+            EVENT_TCP_SEND if primary() => sampled.tcp += 1,
+            _ => {}
+        }
+    }
+    let msgs = sampled.scaled(scale);
+    (msgs, msgs.wire_packets(gso))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{tcp_send, udp_send};
+    use crate::SAMPLER_SOCKET;
+
+    // 75 plain skbs and 25 of four segments each
+    const GSO: GsoSegments = GsoSegments {
+        skbs: 100,
+        gso_skbs: 25,
+        segments: 175,
+    };
+
+    /// 50 UDP and 20 TCP sends the primary sampler took, and 20 only the
+    /// per-socket one did
+    fn sends() -> Vec<CongestionEvent> {
+        (0..90)
+            .map(|send| {
+                if send >= 70 {
+                    return tcp_send(send, 0, send % 4, 1200);
+                }
+                let mut event = udp_send(send, 0, send % 4, 1200);
+                if send >= 50 {
+                    event.data.sendmsg.samplers = SAMPLER_SOCKET;
+                }
+                event
+            })
+            .collect()
+    }
+
+    #[test]
+    fn messages_scale_by_the_samplers_coverage() {
+        // 7,000 seen for 70 sampled
+        let scale = SampleScale::from_coverage(Some(7_000), 70);
+        let (msgs, _) = replay_send_packets(&sends(), &scale, None);
+        assert_eq!(
+            msgs,
+            ProtocolCounts {
+                udp: 5_000,
+                tcp: 2_000
+            }
+        );
+    }
+
+    #[test]
+    fn gso_segments_multiply_only_the_udp_messages() {
+        let scale = SampleScale::from_coverage(Some(7_000), 70);
+        let (msgs, wire) = replay_send_packets(&sends(), &scale, Some(&GSO));
+        assert_eq!(GSO.segments_per_skb(), Some(1.75));
+        assert_eq!(
+            wire,
+            ProtocolCounts {
+                udp: 8_750,
+                tcp: msgs.tcp
+            }
+        );
+        #[cfg(not(feature = "no-float"))]
+        {
+            let json = crate::CongestionSignals::builder()
+                .est_send_msgs(msgs)
+                .est_wire_packets(wire)
+                .udp_gso(GSO)
+                .build()
+                .to_json();
+            assert!(json.contains("\"est_wire_packets\":{\"udp\":8750,\"tcp\":2000}"));
+            assert!(json.contains("\"udp_gso\":{\"skbs\":100,\"gso_skbs\":25,\"segments\":175}"));
+        }
+    }
+
+    #[test]
+    fn without_skbs_or_the_probe_packets_are_messages() {
+        let scale = SampleScale::from_coverage(Some(7_000), 70);
+        let (msgs, no_skbs) = replay_send_packets(&sends(), &scale, Some(&GsoSegments::default()));
+        assert_eq!(no_skbs, msgs);
+        let (_, no_probe) = replay_send_packets(&sends(), &scale, None);
+        assert_eq!(no_probe, msgs);
+        assert_eq!(GsoSegments::default().segments_per_skb(), None);
+    }
+
+    #[cfg(feature = "collector-core")]
+    #[test]
+    fn intervals_add_up() {
+        let mut total = GSO;
+        total.add(&GsoSegments {
+            skbs: 10,
+            gso_skbs: 0,
+            segments: 10,
+        });
+        assert_eq!(
+            total,
+            GsoSegments {
+                skbs: 110,
+                gso_skbs: 25,
+                segments: 185
+            }
+        );
+        let mut msgs = ProtocolCounts { udp: 1, tcp: 2 };
+        msgs.add(&ProtocolCounts { udp: 10, tcp: 20 });
+        assert_eq!(msgs, ProtocolCounts { udp: 11, tcp: 22 });
+    }
+}
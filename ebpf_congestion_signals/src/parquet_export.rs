@@ -0,0 +1,782 @@
+//Columnar export for offline analysis (DuckDB, pandas, ...). One row per event,
+//common columns first and one nullable column per payload field, so each event
+//type only fills the columns its payload has. Socket ids go through a Redaction
+//on the way out.
+
+use crate::json::extensions_json;
+use crate::recording::RecordingReader;
+use crate::redact::Redaction;
+use crate::{
+    event_type_name, CongestionEvent, CongestionSignals, EVENT_NAPI_POLL, EVENT_NET_DEV_QUEUE,
+    EVENT_QDISC_DROP, EVENT_RX_TIME_SQUEEZE, EVENT_SOCKET_LIFECYCLE, EVENT_SOCKET_RCV_STATE,
+    EVENT_SOCKET_STATE, EVENT_SOFTIRQ_ENTER, EVENT_SOFTIRQ_EXIT, EVENT_TCP_CWR, EVENT_TCP_RECOVERY,
+    EVENT_TCP_RTO, EVENT_TCP_SEND, EVENT_TCP_STATE, EVENT_UDP_RCV_CE, EVENT_UDP_RCV_DROP,
+    EVENT_UDP_SEND, IPPROTO_TCP,
+};
+use arrow_array::{
+    ArrayRef, BooleanArray, Float64Array, RecordBatch, StringArray, UInt32Array, UInt64Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_ROW_GROUP_SIZE: usize = 65_536;
+
+/// Payload fields of one event, None where its type doesn't carry them
+#[derive(Default)]
+struct Row {
+    socket_cookie: Option<u64>,
+    bytes: Option<u64>,
+    is_tcp: Option<bool>,
+    dropped: Option<u32>,
+    backlog_bytes: Option<u32>,
+    backlog_packets: Option<u32>,
+    wmem_queued: Option<u32>,
+    sndbuf: Option<u32>,
+    vec_nr: Option<u32>,
+    duration_ns: Option<u64>,
+    rmem_alloc: Option<u32>,
+    rcvbuf: Option<u32>,
+    snd_cwnd: Option<u32>,
+    snd_ssthresh: Option<u32>,
+    pacing_rate: Option<u64>,
+    drop_reason: Option<u32>,
+    oldstate: Option<u32>,
+    newstate: Option<u32>,
+    rx_packets: Option<u32>,
+    rx_budget_exhausted: Option<bool>,
+    interface: Option<String>,
+}
+
+impl Row {
+    fn from_event(event: &CongestionEvent, redaction: &Redaction) -> Self {
+        let mut row = Self::unredacted(event);
+        row.socket_cookie = row.socket_cookie.and_then(|id| redaction.socket_id(id));
+        row
+    }
+
+    fn unredacted(event: &CongestionEvent) -> Self {
+        unsafe {
+            match event.event_type {
+                EVENT_UDP_SEND | EVENT_TCP_SEND => {
+                    let d = event.data.sendmsg;
+                    Row {
+                        socket_cookie: Some(d.socket_id),
+                        bytes: Some(d.bytes),
+                        is_tcp: Some(d.is_tcp != 0),
+                        ..Default::default()
+                    }
+                }
+                EVENT_QDISC_DROP => {
+                    let d = event.data.qdisc;
+                    Row {
+                        dropped: Some(d.dropped),
+                        // First to last of the coalesced drops
+                        duration_ns: Some(d.backlog_bytes as u64),
+                        // Raw value, only meaningful with the capturing kernel's enum
+                        drop_reason: Some(d.reason),
+                        ..Default::default()
+                    }
+                }
+                EVENT_NET_DEV_QUEUE => {
+                    let d = event.data.qdisc;
+                    Row {
+                        dropped: Some(d.dropped),
+                        backlog_bytes: Some(d.backlog_bytes),
+                        backlog_packets: Some(d.backlog_packets),
+                        ..Default::default()
+                    }
+                }
+                EVENT_SOCKET_STATE => {
+                    let d = event.data.socket;
+                    Row {
+                        socket_cookie: Some(d.socket_id),
+                        is_tcp: Some(d.protocol == IPPROTO_TCP),
+                        wmem_queued: Some(d.wmem_queued),
+                        sndbuf: Some(d.sndbuf),
+                        pacing_rate: d.pacing_limit(),
+                        ..Default::default()
+                    }
+                }
+                EVENT_SOFTIRQ_ENTER | EVENT_SOFTIRQ_EXIT => {
+                    let d = event.data.softirq;
+                    Row {
+                        vec_nr: Some(d.vec_nr),
+                        duration_ns: Some(d.duration_ns),
+                        ..Default::default()
+                    }
+                }
+                EVENT_UDP_RCV_DROP | EVENT_SOCKET_RCV_STATE | EVENT_UDP_RCV_CE => {
+                    let d = event.data.rcv;
+                    Row {
+                        socket_cookie: Some(d.socket_id),
+                        rmem_alloc: Some(d.rmem_alloc),
+                        rcvbuf: Some(d.rcvbuf),
+                        ..Default::default()
+                    }
+                }
+                EVENT_TCP_STATE | EVENT_TCP_CWR | EVENT_TCP_RTO | EVENT_TCP_RECOVERY => {
+                    let d = event.data.tcp;
+                    Row {
+                        socket_cookie: Some(d.socket_id),
+                        snd_cwnd: Some(d.snd_cwnd),
+                        snd_ssthresh: Some(d.snd_ssthresh),
+                        pacing_rate: Some(d.pacing_rate),
+                        ..Default::default()
+                    }
+                }
+                EVENT_SOCKET_LIFECYCLE => {
+                    let d = event.data.lifecycle;
+                    Row {
+                        socket_cookie: Some(d.socket_id),
+                        is_tcp: Some(true),
+                        oldstate: Some(d.oldstate),
+                        newstate: Some(d.newstate),
+                        ..Default::default()
+                    }
+                }
+                EVENT_RX_TIME_SQUEEZE => {
+                    let d = event.data.rx_squeeze;
+                    Row {
+                        duration_ns: Some(d.elapsed_ns),
+                        rx_packets: Some(d.packets),
+                        rx_budget_exhausted: Some(d.budget_exhausted != 0),
+                        ..Default::default()
+                    }
+                }
+                EVENT_NAPI_POLL => {
+                    let d = event.data.napi;
+                    Row {
+                        rx_packets: Some(d.work),
+                        rx_budget_exhausted: Some(d.budget > 0 && d.work >= d.budget),
+                        interface: Some(d.device()),
+                        ..Default::default()
+                    }
+                }
+                _ => Row::default(),
+            }
+        }
+    }
+}
+
+/// Arrow schema of event exports
+pub fn event_schema() -> SchemaRef {
+    let nullable_u32 = |name| Field::new(name, DataType::UInt32, true);
+    let nullable_u64 = |name| Field::new(name, DataType::UInt64, true);
+    Arc::new(Schema::new(vec![
+        Field::new("timestamp_ns", DataType::UInt64, false),
+        Field::new("event_type", DataType::UInt32, false),
+        Field::new("event_name", DataType::Utf8, false),
+        Field::new("cpu", DataType::UInt32, false),
+        nullable_u64("socket_cookie"),
+        nullable_u64("bytes"),
+        Field::new("is_tcp", DataType::Boolean, true),
+        nullable_u32("dropped"),
+        nullable_u32("backlog_bytes"),
+        nullable_u32("backlog_packets"),
+        nullable_u32("wmem_queued"),
+        nullable_u32("sndbuf"),
+        nullable_u32("vec_nr"),
+        nullable_u64("duration_ns"),
+        nullable_u32("rmem_alloc"),
+        nullable_u32("rcvbuf"),
+        nullable_u32("snd_cwnd"),
+        nullable_u32("snd_ssthresh"),
+        nullable_u64("pacing_rate"),
+        nullable_u32("drop_reason"),
+        nullable_u32("oldstate"),
+        nullable_u32("newstate"),
+        nullable_u32("rx_packets"),
+        Field::new("rx_budget_exhausted", DataType::Boolean, true),
+        Field::new("interface", DataType::Utf8, true),
+    ]))
+}
+
+fn events_batch(
+    schema: &SchemaRef,
+    events: &[CongestionEvent],
+    redaction: &Redaction,
+) -> anyhow::Result<RecordBatch> {
+    let rows: Vec<Row> = events
+        .iter()
+        .map(|event| Row::from_event(event, redaction))
+        .collect();
+    let u32_col = |f: fn(&Row) -> Option<u32>| -> ArrayRef {
+        Arc::new(rows.iter().map(f).collect::<UInt32Array>())
+    };
+    let u64_col = |f: fn(&Row) -> Option<u64>| -> ArrayRef {
+        Arc::new(rows.iter().map(f).collect::<UInt64Array>())
+    };
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(
+            events
+                .iter()
+                .map(|e| e.timestamp_ns)
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(events.iter().map(|e| e.event_type).collect::<UInt32Array>()),
+        Arc::new(
+            events
+                .iter()
+                .map(|e| Some(event_type_name(e.event_type)))
+                .collect::<StringArray>(),
+        ),
+        Arc::new(events.iter().map(|e| e.cpu_id).collect::<UInt32Array>()),
+        u64_col(|r| r.socket_cookie),
+        u64_col(|r| r.bytes),
+        Arc::new(rows.iter().map(|r| r.is_tcp).collect::<BooleanArray>()),
+        u32_col(|r| r.dropped),
+        u32_col(|r| r.backlog_bytes),
+        u32_col(|r| r.backlog_packets),
+        u32_col(|r| r.wmem_queued),
+        u32_col(|r| r.sndbuf),
+        u32_col(|r| r.vec_nr),
+        u64_col(|r| r.duration_ns),
+        u32_col(|r| r.rmem_alloc),
+        u32_col(|r| r.rcvbuf),
+        u32_col(|r| r.snd_cwnd),
+        u32_col(|r| r.snd_ssthresh),
+        u64_col(|r| r.pacing_rate),
+        u32_col(|r| r.drop_reason),
+        u32_col(|r| r.oldstate),
+        u32_col(|r| r.newstate),
+        u32_col(|r| r.rx_packets),
+        Arc::new(
+            rows.iter()
+                .map(|r| r.rx_budget_exhausted)
+                .collect::<BooleanArray>(),
+        ),
+        Arc::new(
+            rows.iter()
+                .map(|r| r.interface.as_deref())
+                .collect::<StringArray>(),
+        ),
+    ];
+
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+fn writer_props(row_group_size: usize) -> WriterProperties {
+    WriterProperties::builder()
+        .set_max_row_group_size(row_group_size)
+        .build()
+}
+
+/// Convert a recording (see `RecordingWriter`) into a single Parquet file
+pub fn recording_to_parquet(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+) -> anyhow::Result<u64> {
+    recording_to_parquet_redacted(input, output, &Redaction::none())
+}
+
+/// `recording_to_parquet` with socket ids redacted
+pub fn recording_to_parquet_redacted(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    redaction: &Redaction,
+) -> anyhow::Result<u64> {
+    let schema = event_schema();
+    let mut writer = ArrowWriter::try_new(
+        File::create(output)?,
+        schema.clone(),
+        Some(writer_props(DEFAULT_ROW_GROUP_SIZE)),
+    )?;
+
+    let mut rows = 0;
+    let mut pending = Vec::with_capacity(DEFAULT_ROW_GROUP_SIZE);
+    for event in RecordingReader::open(input)? {
+        pending.push(event?);
+        if pending.len() == DEFAULT_ROW_GROUP_SIZE {
+            writer.write(&events_batch(&schema, &pending, redaction)?)?;
+            rows += pending.len() as u64;
+            pending.clear();
+        }
+    }
+    if !pending.is_empty() {
+        writer.write(&events_batch(&schema, &pending, redaction)?)?;
+        rows += pending.len() as u64;
+    }
+
+    writer.close()?;
+    Ok(rows)
+}
+
+/// Streaming event writer for long captures.
+///
+/// A Parquet file is only readable once its footer is written, so instead of one
+/// growing file the sink rolls over to `{prefix}-{NNNNN}.parquet` every
+/// `flush_interval`; completed parts can be queried (e.g.
+/// `read_parquet('capture-*.parquet')`) while later ones are still being written.
+pub struct ParquetSink {
+    prefix: PathBuf,
+    schema: SchemaRef,
+    row_group_size: usize,
+    flush_interval: Duration,
+    writer: Option<ArrowWriter<File>>,
+    part: u32,
+    part_started: Instant,
+    pending: Vec<CongestionEvent>,
+    redaction: Redaction,
+}
+
+impl ParquetSink {
+    pub fn create(
+        prefix: impl Into<PathBuf>,
+        row_group_size: usize,
+        flush_interval: Duration,
+    ) -> anyhow::Result<Self> {
+        let row_group_size = row_group_size.max(1);
+        Ok(Self {
+            prefix: prefix.into(),
+            schema: event_schema(),
+            row_group_size,
+            flush_interval,
+            writer: None,
+            part: 0,
+            part_started: Instant::now(),
+            pending: Vec::with_capacity(row_group_size),
+            redaction: Redaction::none(),
+        })
+    }
+
+    /// Redact socket ids in everything written from now on
+    pub fn with_redaction(mut self, redaction: Redaction) -> Self {
+        self.redaction = redaction;
+        self
+    }
+
+    pub fn push(&mut self, event: &CongestionEvent) -> anyhow::Result<()> {
+        self.pending.push(*event);
+        if self.pending.len() >= self.row_group_size {
+            self.write_pending()?;
+        }
+        if self.part_started.elapsed() >= self.flush_interval {
+            self.finish_part()?;
+        }
+        Ok(())
+    }
+
+    fn write_pending(&mut self) -> anyhow::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        if self.writer.is_none() {
+            let path = PathBuf::from(format!(
+                "{}-{:05}.parquet",
+                self.prefix.display(),
+                self.part
+            ));
+            self.writer = Some(ArrowWriter::try_new(
+                File::create(path)?,
+                self.schema.clone(),
+                Some(writer_props(self.row_group_size)),
+            )?);
+        }
+        let batch = events_batch(&self.schema, &self.pending, &self.redaction)?;
+        self.writer.as_mut().unwrap().write(&batch)?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Write out buffered rows and close the current part so it becomes readable
+    pub fn finish_part(&mut self) -> anyhow::Result<()> {
+        self.write_pending()?;
+        if let Some(writer) = self.writer.take() {
+            writer.close()?;
+            self.part += 1;
+        }
+        self.part_started = Instant::now();
+        Ok(())
+    }
+
+    pub fn close(mut self) -> anyhow::Result<()> {
+        self.finish_part()
+    }
+}
+
+fn unix_ns(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// Write interval snapshots, one row per `read_and_reset()` result in order
+pub fn signals_to_parquet(
+    snapshots: &[CongestionSignals],
+    output: impl AsRef<Path>,
+) -> anyhow::Result<()> {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("interval", DataType::UInt64, false),
+        Field::new("interval_ns", DataType::UInt64, false),
+        Field::new("aligned_start_unix_ns", DataType::UInt64, true),
+        Field::new("aligned_end_unix_ns", DataType::UInt64, true),
+        Field::new("send_bytes", DataType::UInt64, false),
+        Field::new("loopback_send_bytes", DataType::UInt64, true),
+        Field::new("external_send_bytes", DataType::UInt64, false),
+        Field::new("drops", DataType::UInt64, false),
+        Field::new("avg_wmem_pressure", DataType::Float64, false),
+        Field::new("udp_wmem_pressure", DataType::Float64, true),
+        Field::new("tcp_wmem_pressure", DataType::Float64, true),
+        Field::new("net_memory_pressure", DataType::Float64, true),
+        Field::new("memory_pressure_events", DataType::UInt64, true),
+        Field::new("avg_socket_pacing_rate", DataType::Float64, true),
+        Field::new("kernel_paced_send_share", DataType::Float64, true),
+        Field::new("kernel_paced", DataType::Boolean, false),
+        Field::new("softirq_ns", DataType::UInt64, false),
+        Field::new("event_count", DataType::UInt64, false),
+        Field::new("observed_events_received", DataType::UInt64, false),
+        Field::new("observed_softirq_exits", DataType::UInt64, false),
+        Field::new("estimated_send_bytes", DataType::UInt64, false),
+        Field::new("estimated_external_send_bytes", DataType::UInt64, false),
+        Field::new("estimated_send_packets", DataType::UInt64, false),
+        Field::new("est_udp_send_msgs", DataType::UInt64, false),
+        Field::new("est_tcp_send_msgs", DataType::UInt64, false),
+        Field::new("est_udp_wire_packets", DataType::UInt64, false),
+        Field::new("est_tcp_wire_packets", DataType::UInt64, false),
+        Field::new("udp_gso_segments_per_skb", DataType::Float64, true),
+        Field::new("sends_seen", DataType::UInt64, true),
+        Field::new("sends_sampled", DataType::UInt64, false),
+        Field::new("send_scale_factor", DataType::Float64, false),
+        Field::new("socket_sample_fraction", DataType::Float64, true),
+        Field::new("active_sockets", DataType::UInt64, false),
+        Field::new("active_flows", DataType::UInt64, true),
+        Field::new("new_connections", DataType::UInt64, false),
+        Field::new("closed_connections", DataType::UInt64, false),
+        Field::new("tcp_accepts", DataType::UInt64, true),
+        Field::new("send_top1_share", DataType::Float64, true),
+        Field::new("send_top5_share", DataType::Float64, true),
+        Field::new("send_gini", DataType::Float64, true),
+        Field::new("send_hhi", DataType::Float64, true),
+        Field::new("queue_depth_packets", DataType::UInt64, false),
+        Field::new("queue_depth_bytes", DataType::UInt64, false),
+        Field::new("udp_rcv_drops", DataType::UInt64, false),
+        Field::new("avg_rmem_pressure", DataType::Float64, false),
+        Field::new("softirq_cpu_fraction", DataType::Float64, false),
+        Field::new("tcp_avg_cwnd", DataType::Float64, true),
+        Field::new("tcp_avg_ssthresh", DataType::Float64, true),
+        Field::new("tcp_avg_pacing_rate", DataType::Float64, true),
+        Field::new("tcp_sockets_below_ssthresh", DataType::UInt64, true),
+        Field::new("ce_marks", DataType::UInt64, true),
+        Field::new("ce_triggered_cwr", DataType::UInt64, true),
+        Field::new("rto_events", DataType::UInt64, true),
+        Field::new("loss_recovery_episodes", DataType::UInt64, true),
+        Field::new("rx_time_squeeze", DataType::UInt64, true),
+        Field::new("implausible_socket_samples", DataType::UInt64, false),
+        Field::new("burst_drop_correlation", DataType::Float64, true),
+        Field::new("tsq_throttles", DataType::UInt64, true),
+        Field::new("softirq_discarded", DataType::UInt64, false),
+        Field::new("degradation_level", DataType::UInt32, false),
+        Field::new("txq_stalls", DataType::UInt64, true),
+        Field::new("txq_stalled_ns", DataType::UInt64, true),
+        Field::new("limitation", DataType::Utf8, false),
+        // CongestionSignals::extensions as a JSON object, by extension name
+        Field::new("extensions", DataType::Utf8, false),
+    ]));
+
+    let u64_col = |f: fn(&CongestionSignals) -> u64| -> ArrayRef {
+        Arc::new(snapshots.iter().map(f).collect::<UInt64Array>())
+    };
+    let f64_col = |f: fn(&CongestionSignals) -> f64| -> ArrayRef {
+        Arc::new(snapshots.iter().map(f).collect::<Float64Array>())
+    };
+    let opt_f64_col = |f: fn(&CongestionSignals) -> Option<f64>| -> ArrayRef {
+        Arc::new(snapshots.iter().map(f).collect::<Float64Array>())
+    };
+    let opt_u64_col = |f: fn(&CongestionSignals) -> Option<u64>| -> ArrayRef {
+        Arc::new(snapshots.iter().map(f).collect::<UInt64Array>())
+    };
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new((0..snapshots.len() as u64).collect::<UInt64Array>()),
+        u64_col(|s| s.interval_ns),
+        opt_u64_col(|s| s.aligned_start.map(unix_ns)),
+        opt_u64_col(|s| s.aligned_end.map(unix_ns)),
+        u64_col(|s| s.send_bytes),
+        opt_u64_col(|s| s.loopback_send_bytes),
+        u64_col(|s| s.external_send_bytes),
+        u64_col(|s| s.drops),
+        f64_col(|s| s.avg_wmem_pressure),
+        opt_f64_col(|s| s.udp_wmem_pressure),
+        opt_f64_col(|s| s.tcp_wmem_pressure),
+        opt_f64_col(|s| s.net_memory_pressure),
+        opt_u64_col(|s| s.memory_pressure_events),
+        opt_f64_col(|s| s.avg_socket_pacing_rate),
+        opt_f64_col(|s| s.kernel_paced_send_share),
+        Arc::new(
+            snapshots
+                .iter()
+                .map(|s| Some(s.kernel_paced))
+                .collect::<BooleanArray>(),
+        ),
+        u64_col(|s| s.softirq_ns),
+        u64_col(|s| s.event_count),
+        u64_col(|s| s.observed.events_received),
+        u64_col(|s| s.observed.softirq_exits),
+        u64_col(|s| s.estimated.send_bytes),
+        u64_col(|s| s.estimated.external_send_bytes),
+        u64_col(|s| s.estimated.send_packets),
+        u64_col(|s| s.est_send_msgs.udp),
+        u64_col(|s| s.est_send_msgs.tcp),
+        u64_col(|s| s.est_wire_packets.udp),
+        u64_col(|s| s.est_wire_packets.tcp),
+        opt_f64_col(|s| s.udp_gso.as_ref().and_then(|g| g.segments_per_skb())),
+        opt_u64_col(|s| s.estimated.scale.sends_seen),
+        u64_col(|s| s.estimated.scale.sends_sampled),
+        f64_col(|s| s.estimated.scale.send_factor),
+        opt_f64_col(|s| s.estimated.scale.socket_fraction),
+        u64_col(|s| s.active_sockets),
+        opt_u64_col(|s| s.active_flows),
+        u64_col(|s| s.new_connections_per_interval),
+        u64_col(|s| s.closed_connections_per_interval),
+        Arc::new(
+            snapshots
+                .iter()
+                .map(|s| s.connections.tcp_accepts)
+                .collect::<UInt64Array>(),
+        ),
+        opt_f64_col(|s| s.send_concentration.as_ref().map(|c| c.top1_share)),
+        opt_f64_col(|s| s.send_concentration.as_ref().map(|c| c.top5_share)),
+        opt_f64_col(|s| s.send_concentration.as_ref().map(|c| c.gini)),
+        opt_f64_col(|s| s.send_concentration.as_ref().map(|c| c.hhi)),
+        u64_col(|s| s.queue_depth_packets),
+        u64_col(|s| s.queue_depth_bytes),
+        u64_col(|s| s.udp_rcv_drops),
+        f64_col(|s| s.avg_rmem_pressure),
+        f64_col(|s| s.softirq_cpu_fraction),
+        opt_f64_col(|s| s.tcp_avg_cwnd),
+        opt_f64_col(|s| s.tcp_avg_ssthresh),
+        opt_f64_col(|s| s.tcp_avg_pacing_rate),
+        Arc::new(
+            snapshots
+                .iter()
+                .map(|s| s.tcp_sockets_below_ssthresh)
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(
+            snapshots
+                .iter()
+                .map(|s| s.ce_marks)
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(
+            snapshots
+                .iter()
+                .map(|s| s.ce_triggered_cwr)
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(
+            snapshots
+                .iter()
+                .map(|s| s.rto_events)
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(
+            snapshots
+                .iter()
+                .map(|s| s.loss_recovery_episodes)
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(
+            snapshots
+                .iter()
+                .map(|s| s.rx_time_squeeze)
+                .collect::<UInt64Array>(),
+        ),
+        u64_col(|s| s.implausible_socket_samples),
+        opt_f64_col(|s| s.burst_drop_correlation),
+        Arc::new(
+            snapshots
+                .iter()
+                .map(|s| s.tsq_throttles)
+                .collect::<UInt64Array>(),
+        ),
+        u64_col(|s| s.softirq_discarded),
+        Arc::new(
+            snapshots
+                .iter()
+                .map(|s| s.degradation_level)
+                .collect::<UInt32Array>(),
+        ),
+        Arc::new(snapshots.iter().map(|s| s.txq_stalls).collect::<UInt64Array>()),
+        Arc::new(
+            snapshots
+                .iter()
+                .map(|s| s.txq_stalled_ns)
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(
+            snapshots
+                .iter()
+                .map(|s| Some(format!("{:?}", s.limitation)))
+                .collect::<StringArray>(),
+        ),
+        Arc::new(
+            snapshots
+                .iter()
+                .map(|s| Some(extensions_json(&s.extensions)))
+                .collect::<StringArray>(),
+        ),
+    ];
+
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+    let mut writer = ArrowWriter::try_new(
+        File::create(output)?,
+        schema,
+        Some(writer_props(DEFAULT_ROW_GROUP_SIZE)),
+    )?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+    use crate::recording::RecordingWriter;
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn fixture_events() -> Vec<CongestionEvent> {
+        vec![
+            fixtures::udp_send(1_000, 0, 7, 1200),
+            fixtures::qdisc_drop(2_000, 1, 3, 500),
+            fixtures::socket_state(3_000, 0, 7, 1_000, 212_992),
+            fixtures::softirq_exit(4_000, 1, 3, 150_000),
+            fixtures::tcp_state(5_000, 2, 8, 10, 7),
+            fixtures::napi_poll(6_000, 0, "eth0", 64),
+        ]
+    }
+
+    fn write_recording(path: &Path, events: &[CongestionEvent]) {
+        let mut writer = RecordingWriter::create(path).unwrap();
+        for event in events {
+            writer.write_event(event).unwrap();
+        }
+        writer.flush().unwrap();
+    }
+
+    // The file's schema and its batches, row groups counted
+    fn read_back(path: &Path) -> (SchemaRef, Vec<RecordBatch>, usize) {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap();
+        let schema = builder.schema().clone();
+        let row_groups = builder.metadata().num_row_groups();
+        let batches = builder
+            .build()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        (schema, batches, row_groups)
+    }
+
+    fn rows(batches: &[RecordBatch]) -> usize {
+        batches.iter().map(RecordBatch::num_rows).sum()
+    }
+
+    #[test]
+    fn recording_converts_with_the_event_schema() {
+        let dir = fixtures::scratch_dir("parquet-recording");
+        let (input, output) = (dir.join("capture.rec"), dir.join("capture.parquet"));
+        let events = fixture_events();
+        write_recording(&input, &events);
+
+        assert_eq!(recording_to_parquet(&input, &output).unwrap(), 6);
+        let (schema, batches, _) = read_back(&output);
+        assert_eq!(schema.fields(), event_schema().fields());
+        assert_eq!(rows(&batches), events.len());
+
+        let batch = &batches[0];
+        let column = |name| batch.column(schema.index_of(name).unwrap()).clone();
+        let cookies = column("socket_cookie");
+        let cookies = cookies.as_any().downcast_ref::<UInt64Array>().unwrap();
+        assert_eq!(cookies.value(0), 7);
+        assert!(cookies.is_null(1));
+        let durations = column("duration_ns");
+        let durations = durations.as_any().downcast_ref::<UInt64Array>().unwrap();
+        assert_eq!(durations.value(1), 500);
+        assert_eq!(durations.value(3), 150_000);
+        let interfaces = column("interface");
+        let interfaces = interfaces.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(interfaces.value(5), "eth0");
+        assert_eq!(interfaces.null_count(), 5);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn redaction_omits_socket_cookies() {
+        let dir = fixtures::scratch_dir("parquet-redacted");
+        let (input, output) = (dir.join("capture.rec"), dir.join("capture.parquet"));
+        write_recording(&input, &fixture_events());
+
+        recording_to_parquet_redacted(&input, &output, &Redaction::all()).unwrap();
+        let (schema, batches, _) = read_back(&output);
+        let cookies = batches[0].column(schema.index_of("socket_cookie").unwrap());
+        assert_eq!(cookies.null_count(), batches[0].num_rows());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sink_writes_row_groups_and_parts() {
+        let dir = fixtures::scratch_dir("parquet-sink");
+        let prefix = dir.join("capture");
+        let mut sink = ParquetSink::create(&prefix, 2, Duration::from_secs(3600)).unwrap();
+        for event in fixture_events().iter().take(5) {
+            sink.push(event).unwrap();
+        }
+        sink.finish_part().unwrap();
+        sink.push(&fixture_events()[5]).unwrap();
+        sink.close().unwrap();
+
+        let (schema, batches, row_groups) = read_back(&dir.join("capture-00000.parquet"));
+        assert_eq!(schema.fields(), event_schema().fields());
+        assert_eq!((rows(&batches), row_groups), (5, 3));
+        let (_, batches, row_groups) = read_back(&dir.join("capture-00001.parquet"));
+        assert_eq!((rows(&batches), row_groups), (1, 1));
+        assert!(!dir.join("capture-00002.parquet").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sink_rolls_over_on_the_flush_interval() {
+        let dir = fixtures::scratch_dir("parquet-flush");
+        let prefix = dir.join("capture");
+        let mut sink = ParquetSink::create(&prefix, 100, Duration::ZERO).unwrap();
+        sink.push(&fixture_events()[0]).unwrap();
+        // Readable before the sink is closed
+        let (_, batches, _) = read_back(&dir.join("capture-00000.parquet"));
+        assert_eq!(rows(&batches), 1);
+        sink.close().unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn snapshots_are_one_row_each() {
+        let dir = fixtures::scratch_dir("parquet-signals");
+        let output = dir.join("signals.parquet");
+        let snapshots = vec![
+            CongestionSignals {
+                send_bytes: 1_000,
+                drops: 2,
+                ..Default::default()
+            },
+            CongestionSignals {
+                send_bytes: 3_000,
+                ..Default::default()
+            },
+        ];
+        signals_to_parquet(&snapshots, &output).unwrap();
+
+        let (schema, batches, _) = read_back(&output);
+        assert_eq!(rows(&batches), 2);
+        let sent = batches[0].column(schema.index_of("send_bytes").unwrap());
+        let sent = sent.as_any().downcast_ref::<UInt64Array>().unwrap();
+        assert_eq!((sent.value(0), sent.value(1)), (1_000, 3_000));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
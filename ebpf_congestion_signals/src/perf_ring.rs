@@ -0,0 +1,363 @@
+//Per-CPU perf buffers opened here rather than through aya. aya 0.13 opens every
+//buffer with wakeup_events = 1 and copies each sample into a BytesMut of its
+//own; this sets the wakeup in perf_event_attr (a watermark for
+//`ReaderWakeup::Watermark`, every sample otherwise, as aya does), maps the
+//ring, and puts its fd in the EVENTS map itself. A read copies a whole batch of
+//samples into one `SampleBatch`, which the reader walks with a cursor.
+//Records are decoded the way aya does it.
+
+use crate::config::WakeupWatermark;
+use aya::maps::perf::Events;
+use aya::maps::MapData;
+use std::io;
+use std::mem::size_of;
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+
+/// Data pages per ring by default, what a watermark of a few hundred events
+/// has room for on top of a late reader. aya's own buffers have two
+pub(crate) const WATERMARK_PAGES: usize = 16;
+/// The same with a wakeup on every sample, what aya opens without a page count
+pub(crate) const PER_EVENT_PAGES: usize = 2;
+
+// perf_event_attr and friends, from linux/perf_event.h
+const PERF_TYPE_SOFTWARE: u32 = 1;
+const PERF_COUNT_SW_BPF_OUTPUT: u64 = 10;
+const PERF_SAMPLE_RAW: u64 = 1 << 10;
+// attr.watermark: wakeup_watermark (bytes) rather than wakeup_events
+const ATTR_FLAG_WATERMARK: u64 = 1 << 14;
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+const PERF_EVENT_IOC_ENABLE: libc::c_ulong = 0x2400;
+const PERF_RECORD_LOST: u32 = 2;
+const PERF_RECORD_SAMPLE: u32 = 9;
+// perf_event_mmap_page::data_head, data_tail
+const DATA_HEAD_OFFSET: usize = 1024;
+const DATA_TAIL_OFFSET: usize = 1032;
+const BPF_MAP_UPDATE_ELEM: libc::c_long = 2;
+
+// PERF_ATTR_SIZE_VER0, which every kernel with BPF output accepts
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    // wakeup_events, or wakeup_watermark with ATTR_FLAG_WATERMARK
+    wakeup: u32,
+    bp_type: u32,
+    config1: u64,
+}
+
+// bpf_attr for BPF_MAP_UPDATE_ELEM
+#[repr(C)]
+struct MapUpdateAttr {
+    map_fd: u32,
+    _pad: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+/// One CPU's ring, unmapped and closed on drop
+pub(crate) struct PerfRing {
+    fd: OwnedFd,
+    base: *mut u8,
+    page_size: usize,
+    // Data area, a power of two
+    size: usize,
+}
+
+// The mapping is only touched through &mut self, or read-only by readable()
+unsafe impl Send for PerfRing {}
+
+impl PerfRing {
+    /// Open a ring of `pages` data pages on `cpu_id` that signals readiness
+    /// at `watermark`, and route that CPU's samples in `map` to it
+    pub(crate) fn open(
+        map: &MapData,
+        cpu_id: u32,
+        watermark: WakeupWatermark,
+        pages: usize,
+    ) -> io::Result<Self> {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let size = page_size * pages;
+        let (flags, wakeup) = match watermark {
+            WakeupWatermark::Events(events) => (0, events.max(1)),
+            WakeupWatermark::Bytes(bytes) => (ATTR_FLAG_WATERMARK, bytes.clamp(1, size as u32 / 2)),
+        };
+        let attr = PerfEventAttr {
+            type_: PERF_TYPE_SOFTWARE,
+            size: size_of::<PerfEventAttr>() as u32,
+            config: PERF_COUNT_SW_BPF_OUTPUT,
+            sample_period: 1,
+            sample_type: PERF_SAMPLE_RAW,
+            flags,
+            wakeup,
+            ..Default::default()
+        };
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_perf_event_open,
+                &attr as *const PerfEventAttr,
+                -1,
+                cpu_id as libc::c_int,
+                -1,
+                PERF_FLAG_FD_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                page_size + size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // From here dropping the ring unmaps it again
+        let ring = Self {
+            fd,
+            base: base as *mut u8,
+            page_size,
+            size,
+        };
+
+        if unsafe { libc::ioctl(ring.fd.as_raw_fd(), PERF_EVENT_IOC_ENABLE, 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let key = cpu_id;
+        let value = ring.fd.as_raw_fd() as u32;
+        let update = MapUpdateAttr {
+            map_fd: map.fd().as_fd().as_raw_fd() as u32,
+            _pad: 0,
+            key: &key as *const u32 as u64,
+            value: &value as *const u32 as u64,
+            flags: 0,
+        };
+        let updated = unsafe {
+            libc::syscall(
+                libc::SYS_bpf,
+                BPF_MAP_UPDATE_ELEM,
+                &update as *const MapUpdateAttr,
+                size_of::<MapUpdateAttr>() as libc::c_uint,
+            )
+        };
+        if updated < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ring)
+    }
+
+    fn head(&self) -> &AtomicU64 {
+        unsafe { &*(self.base.add(DATA_HEAD_OFFSET) as *const AtomicU64) }
+    }
+
+    fn tail(&self) -> &AtomicU64 {
+        unsafe { &*(self.base.add(DATA_TAIL_OFFSET) as *const AtomicU64) }
+    }
+
+    pub(crate) fn readable(&self) -> bool {
+        self.head().load(Ordering::Acquire) != self.tail().load(Ordering::Relaxed)
+    }
+
+    /// Fill `out` from ring offset `at`, wrapping
+    fn copy(&self, at: usize, out: &mut [u8]) {
+        let data = unsafe { self.base.add(self.page_size) };
+        let start = at % self.size;
+        let first = out.len().min(self.size - start);
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.add(start), out.as_mut_ptr(), first);
+            std::ptr::copy_nonoverlapping(data, out.as_mut_ptr().add(first), out.len() - first);
+        }
+    }
+
+    fn read_u32(&self, at: usize) -> u32 {
+        let mut bytes = [0u8; 4];
+        self.copy(at, &mut bytes);
+        u32::from_ne_bytes(bytes)
+    }
+
+    /// A batch's worth of samples into `batch`, cleared first, counting lost
+    /// records
+    pub(crate) fn read_batch(&mut self, batch: &mut SampleBatch) -> io::Result<Events> {
+        let max = batch.max_samples;
+        if max == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no room for samples",
+            ));
+        }
+        batch.clear();
+        let head = self.head().load(Ordering::Acquire) as usize;
+        let mut tail = self.tail().load(Ordering::Relaxed) as usize;
+        let mut events = Events { read: 0, lost: 0 };
+
+        while tail != head && events.read < max {
+            // perf_event_header: type u32, misc u16, size u16. Records are
+            // 8-byte aligned, so a header never wraps
+            let record_type = self.read_u32(tail);
+            let record_size = {
+                let mut size = [0u8; 2];
+                self.copy(tail + 6, &mut size);
+                u16::from_ne_bytes(size) as usize
+            };
+            if record_size == 0 {
+                // Torn header, nothing sane to skip by
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "zero-sized perf record",
+                ));
+            }
+            match record_type {
+                PERF_RECORD_SAMPLE => {
+                    let len = self.read_u32(tail + 8) as usize;
+                    batch.push_with(len, |sample| self.copy(tail + 12, sample));
+                    events.read += 1;
+                }
+                PERF_RECORD_LOST => {
+                    // id u64, then lost u64
+                    let mut lost = [0u8; 8];
+                    self.copy(tail + 16, &mut lost);
+                    events.lost += u64::from_ne_bytes(lost) as usize;
+                }
+                _ => {}
+            }
+            tail += record_size;
+        }
+
+        fence(Ordering::Release);
+        self.tail().store(tail as u64, Ordering::Relaxed);
+        Ok(events)
+    }
+}
+
+impl AsRawFd for PerfRing {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl Drop for PerfRing {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.base as *mut libc::c_void, self.page_size + self.size);
+        }
+    }
+}
+
+/// One read's samples back to back in a single buffer, each behind its u32
+/// length. Allocated once for `max_samples` of `sample_bytes`, grows only for
+/// longer ones
+pub(crate) struct SampleBatch {
+    data: Vec<u8>,
+    max_samples: usize,
+}
+
+impl SampleBatch {
+    pub(crate) fn new(max_samples: usize, sample_bytes: usize) -> Self {
+        Self {
+            data: Vec::with_capacity(max_samples * (size_of::<u32>() + sample_bytes)),
+            max_samples,
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    /// Append a `len` byte sample written by `fill`
+    pub(crate) fn push_with(&mut self, len: usize, fill: impl FnOnce(&mut [u8])) {
+        self.data.extend_from_slice(&(len as u32).to_ne_bytes());
+        let start = self.data.len();
+        self.data.resize(start + len, 0);
+        fill(&mut self.data[start..]);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn push(&mut self, sample: &[u8]) {
+        self.push_with(sample.len(), |out| out.copy_from_slice(sample));
+    }
+
+    /// The samples in the order they were read
+    pub(crate) fn iter(&self) -> SampleCursor<'_> {
+        SampleCursor { rest: &self.data }
+    }
+}
+
+/// Walks a `SampleBatch`, checking each record's length once
+pub(crate) struct SampleCursor<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for SampleCursor<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let (len, rest) = self.rest.split_first_chunk::<4>()?;
+        let len = u32::from_ne_bytes(*len) as usize;
+        if rest.len() < len {
+            // push_with never leaves one cut short, but don't read past it
+            self.rest = &[];
+            return None;
+        }
+        let (sample, rest) = rest.split_at(len);
+        self.rest = rest;
+        Some(sample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_cursor_walks_every_sample_in_order() {
+        let samples: [&[u8]; 4] = [b"first", b"", &[7; 300], b"last"];
+        let mut batch = SampleBatch::new(samples.len(), 64);
+        for sample in samples {
+            batch.push(sample);
+        }
+        assert!(batch.iter().eq(samples));
+    }
+
+    #[test]
+    fn a_cleared_batch_keeps_its_buffer() {
+        let mut batch = SampleBatch::new(8, 64);
+        let buffer = batch.data.as_ptr();
+        for round in 0..3u8 {
+            batch.clear();
+            for sample in 0..8u8 {
+                batch.push(&[round ^ sample; 64]);
+            }
+            assert_eq!(batch.iter().count(), 8);
+            assert_eq!(batch.data.as_ptr(), buffer);
+        }
+        batch.clear();
+        assert_eq!(batch.iter().next(), None);
+    }
+
+    #[test]
+    fn a_record_cut_short_ends_the_walk() {
+        let mut batch = SampleBatch::new(2, 16);
+        batch.push(b"whole");
+        batch.push(b"cut short");
+        batch.data.truncate(batch.data.len() - 1);
+        let mut cursor = batch.iter();
+        assert_eq!(cursor.next(), Some(&b"whole"[..]));
+        assert_eq!(cursor.next(), None);
+        assert_eq!(cursor.next(), None);
+    }
+}
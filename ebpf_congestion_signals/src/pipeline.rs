@@ -0,0 +1,671 @@
+//Slow path between the per-CPU readers and everything that isn't a plain atomic
+//add: per-socket bookkeeping, burst correlation and subscriber fan-out. Readers only ever try_send
+//into the queue, so a slow consumer here costs queue drops, never perf-buffer loss.
+//The `async` and `smol` builds run this on a supervised task, `blocking` on a thread
+//of its own. With reordering on, events pass an EventReorderer on the way in.
+
+use crate::collector::{AtomicSignals, DROP_REASON_SLOTS};
+#[cfg(feature = "async-runtime")]
+use crate::runtime::{AbortHandle, MissedTicks, Rt, Runtime, TaskHandle, Ticker};
+#[cfg(feature = "async-runtime")]
+use crate::supervisor::{Component, Supervisor};
+use crate::{
+    event_socket, traced_only, CollectorConfig, CongestionEvent, EventReorderer, EventReordering,
+    ReaderStats, ReaderWakeup, EVENT_QDISC_DROP, EVENT_SOCKET_LIFECYCLE, EVENT_TCP_STATE,
+    EVENT_UDP_SEND, EXTENSION_EVENT_TYPES, TCP_CLOSE,
+};
+#[cfg(feature = "async-runtime")]
+use futures_util::future::{select, Either};
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "async-runtime")]
+use std::collections::VecDeque;
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    mpsc as std_mpsc, Arc, Mutex,
+};
+use std::time::{Duration, Instant};
+#[cfg(feature = "async-runtime")]
+use tokio::sync::{broadcast, Notify};
+
+/// Events kept for diagnostic bundles, most recent last
+#[cfg(feature = "async-runtime")]
+pub(crate) const RECENT_EVENTS: usize = 100;
+
+#[derive(Default)]
+pub(crate) struct PipelineCounters {
+    pub(crate) events_read: AtomicU64,
+    pub(crate) perf_lost: AtomicU64,
+    pub(crate) queue_dropped: AtomicU64,
+    pub(crate) unknown_events: AtomicU64,
+    pub(crate) extension_events: AtomicU64,
+    pub(crate) clock_jumps: AtomicU64,
+    pub(crate) reader_wakeups: AtomicU64,
+    // When the readers were last spawned, for the wakeup rate
+    pub(crate) readers_started: Mutex<Option<Instant>>,
+    // cpu -> times its reader recovered from read errors
+    pub(crate) reader_restarts: Mutex<HashMap<u32, u64>>,
+    // CPUs whose reader gave up, until readers are spawned again
+    pub(crate) failed_readers: Mutex<HashSet<u32>>,
+}
+
+/// Reader side of the hand-off. The variant also decides which kind of readers
+/// `reader::Readers::spawn` starts for it. Both are a std sync_channel, whose
+/// slots are allocated up front; std channels can't report their length, so
+/// depth is counted alongside
+#[derive(Clone)]
+pub(crate) enum Queue {
+    /// `ready` wakes the task draining it. Not tokio's mpsc, which allocates
+    /// a block of slots every 32 events
+    #[cfg(feature = "async-runtime")]
+    Async {
+        tx: std_mpsc::SyncSender<CongestionEvent>,
+        depth: Arc<AtomicUsize>,
+        capacity: usize,
+        ready: Arc<Notify>,
+    },
+    #[cfg(feature = "blocking")]
+    Blocking {
+        tx: std_mpsc::SyncSender<CongestionEvent>,
+        depth: Arc<AtomicUsize>,
+        capacity: usize,
+    },
+}
+
+impl Queue {
+    /// Events waiting, and how many fit
+    fn occupancy(&self) -> (usize, usize) {
+        match self {
+            #[cfg(feature = "async-runtime")]
+            Queue::Async {
+                depth, capacity, ..
+            } => (depth.load(Ordering::Relaxed), *capacity),
+            #[cfg(feature = "blocking")]
+            Queue::Blocking {
+                depth, capacity, ..
+            } => (depth.load(Ordering::Relaxed), *capacity),
+        }
+    }
+}
+
+enum Worker {
+    /// The readers run under the same supervisor
+    #[cfg(feature = "async-runtime")]
+    Task {
+        task: AbortHandle,
+        supervisor: Arc<Supervisor>,
+    },
+    // Detached, exits on its own once every sender (ours and the readers') is
+    // gone, after draining what was still queued
+    #[cfg(feature = "blocking")]
+    Thread,
+}
+
+pub(crate) struct Pipeline {
+    pub(crate) queue: Queue,
+    pub(crate) counters: Arc<PipelineCounters>,
+    pub(crate) reader_max_retries: u32,
+    pub(crate) reader_batch_size: usize,
+    pub(crate) reader_wakeup: ReaderWakeup,
+    worker: Worker,
+    // Outlives a restarted task like the queue, so held events aren't lost
+    reorder: Option<Arc<Mutex<EventReorderer>>>,
+    // What went out to subscribers last, for dump_diagnostics()
+    #[cfg(feature = "async-runtime")]
+    recent: Arc<Mutex<VecDeque<CongestionEvent>>>,
+}
+
+impl Pipeline {
+    /// Processing on a supervised task, fanning events out to subscribers
+    #[cfg(feature = "async-runtime")]
+    pub(crate) fn start(
+        config: &CollectorConfig,
+        signals: Arc<AtomicSignals>,
+        subscribers: broadcast::Sender<CongestionEvent>,
+        supervisor: Arc<Supervisor>,
+    ) -> Self {
+        let capacity = config.event_queue_capacity;
+        let (tx, rx) = std_mpsc::sync_channel(capacity);
+        let depth = Arc::new(AtomicUsize::new(0));
+        let ready = Arc::new(Notify::new());
+        // Outlives a panicking task, so a restart picks up where it left off
+        let rx = Arc::new(tokio::sync::Mutex::new(rx));
+        let recent = Arc::new(Mutex::new(VecDeque::with_capacity(RECENT_EVENTS)));
+        let reordering = config.reordering();
+        let clock = config.clock.clone();
+        let reorder = reordering.map(|reordering| {
+            let reorderer = EventReorderer::new(reordering).with_clock(clock.clone());
+            Arc::new(Mutex::new(reorderer))
+        });
+
+        let task_recent = recent.clone();
+        let task_reorder = reorder.clone();
+        let task_depth = depth.clone();
+        let task_ready = ready.clone();
+        let task = supervisor.spawn(Component::Pipeline, move || {
+            let clock = clock.clone();
+            let rx = rx.clone();
+            let depth = task_depth.clone();
+            let ready = task_ready.clone();
+            let signals = signals.clone();
+            let subscribers = subscribers.clone();
+            let recent = task_recent.clone();
+            let reorder = task_reorder.clone();
+            async move {
+                let mut rx = rx.lock().await;
+                let deliver = |event: CongestionEvent| {
+                    process_slow(&signals, &event);
+                    {
+                        let mut recent = recent.lock().unwrap();
+                        if recent.len() == RECENT_EVENTS {
+                            recent.pop_front();
+                        }
+                        recent.push_back(event);
+                    }
+                    // No receivers is fine, and lagging ones skip ahead on their own
+                    let _ = subscribers.send(event);
+                };
+                let (Some(reorder), Some(reordering)) = (&reorder, reordering) else {
+                    while let Some(event) = recv(&mut rx, &depth, &ready).await {
+                        deliver(event);
+                    }
+                    return Ok(());
+                };
+
+                // Releases what's past the watermark when events stop arriving
+                let period = clock.poll_after(idle_check(&reordering));
+                let mut tick = Rt::interval(period, MissedTicks::Skip);
+                loop {
+                    // Both cancel safe, the loser is dropped each round
+                    let next = recv(&mut rx, &depth, &ready);
+                    match select(std::pin::pin!(next), std::pin::pin!(tick.tick())).await {
+                        Either::Left((Some(event), _)) => {
+                            reorder.lock().unwrap().push(event, deliver)
+                        }
+                        Either::Left((None, _)) => {
+                            reorder.lock().unwrap().flush(deliver);
+                            return Ok(());
+                        }
+                        Either::Right(_) => {
+                            reorder.lock().unwrap().release_ready(clock.now(), deliver);
+                        }
+                    }
+                }
+            }
+        });
+
+        Self {
+            queue: Queue::Async {
+                tx,
+                depth,
+                capacity,
+                ready,
+            },
+            counters: Arc::new(PipelineCounters::default()),
+            reader_max_retries: config.reader_max_retries,
+            reader_batch_size: config.reader_batch_size.max(1),
+            reader_wakeup: config.reader_wakeup,
+            worker: Worker::Task { task, supervisor },
+            reorder,
+            recent,
+        }
+    }
+
+    /// Up to RECENT_EVENTS events the subscribers got last, oldest first. None
+    /// for a blocking pipeline, which has no event stream
+    pub(crate) fn recent_events(&self) -> Option<Vec<CongestionEvent>> {
+        match &self.worker {
+            #[cfg(feature = "async-runtime")]
+            Worker::Task { .. } => Some(self.recent.lock().unwrap().iter().copied().collect()),
+            #[cfg(feature = "blocking")]
+            Worker::Thread => None,
+        }
+    }
+
+    /// What async readers are spawned under, None for a blocking pipeline
+    #[cfg(feature = "async-runtime")]
+    pub(crate) fn supervisor(&self) -> Option<&Arc<Supervisor>> {
+        match &self.worker {
+            Worker::Task { supervisor, .. } => Some(supervisor),
+            #[cfg(feature = "blocking")]
+            Worker::Thread => None,
+        }
+    }
+
+    /// Processing on a plain thread, no runtime and no subscribers
+    #[cfg(feature = "blocking")]
+    pub(crate) fn start_blocking(
+        config: &CollectorConfig,
+        signals: Arc<AtomicSignals>,
+    ) -> std::io::Result<Self> {
+        let capacity = config.event_queue_capacity;
+        let (tx, rx) = std_mpsc::sync_channel(capacity);
+        let depth = Arc::new(AtomicUsize::new(0));
+        let reordering = config.reordering();
+        let clock = config.clock.clone();
+        let reorder = reordering.map(|reordering| {
+            let reorderer = EventReorderer::new(reordering).with_clock(clock.clone());
+            Arc::new(Mutex::new(reorderer))
+        });
+
+        let thread_depth = depth.clone();
+        let thread_reorder = reorder.clone();
+        std::thread::Builder::new()
+            .name("congestion-pipeline".into())
+            .spawn(move || {
+                let deliver = |event: CongestionEvent| process_slow(&signals, &event);
+                let (Some(reorder), Some(reordering)) = (thread_reorder, reordering) else {
+                    while let Ok(event) = rx.recv() {
+                        thread_depth.fetch_sub(1, Ordering::Relaxed);
+                        deliver(event);
+                    }
+                    return;
+                };
+                let timeout = clock.poll_after(idle_check(&reordering));
+                loop {
+                    match rx.recv_timeout(timeout) {
+                        Ok(event) => {
+                            thread_depth.fetch_sub(1, Ordering::Relaxed);
+                            reorder.lock().unwrap().push(event, deliver);
+                        }
+                        Err(std_mpsc::RecvTimeoutError::Timeout) => {
+                            reorder.lock().unwrap().release_ready(clock.now(), deliver);
+                        }
+                        Err(std_mpsc::RecvTimeoutError::Disconnected) => {
+                            reorder.lock().unwrap().flush(deliver);
+                            return;
+                        }
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            queue: Queue::Blocking {
+                tx,
+                depth,
+                capacity,
+            },
+            counters: Arc::new(PipelineCounters::default()),
+            reader_max_retries: config.reader_max_retries,
+            reader_batch_size: config.reader_batch_size.max(1),
+            reader_wakeup: config.reader_wakeup,
+            worker: Worker::Thread,
+            reorder,
+            #[cfg(feature = "async-runtime")]
+            recent: Arc::default(),
+        })
+    }
+
+    pub(crate) fn stats(&self) -> ReaderStats {
+        let (queue_depth, queue_capacity) = self.queue.occupancy();
+        let reader_wakeups = self.counters.reader_wakeups.load(Ordering::Relaxed);
+        let collecting = self
+            .counters
+            .readers_started
+            .lock()
+            .unwrap()
+            .map_or(0.0, |started| started.elapsed().as_secs_f64());
+        ReaderStats {
+            events_read: self.counters.events_read.load(Ordering::Relaxed),
+            perf_lost: self.counters.perf_lost.load(Ordering::Relaxed),
+            queue_depth,
+            queue_capacity,
+            queue_dropped: self.counters.queue_dropped.load(Ordering::Relaxed),
+            unknown_events: self.counters.unknown_events.load(Ordering::Relaxed),
+            extension_events: self.counters.extension_events.load(Ordering::Relaxed),
+            clock_jumps: self.counters.clock_jumps.load(Ordering::Relaxed),
+            // The filter lives with the signals, the collector fills it in
+            netns_filtered: 0,
+            reader_wakeups,
+            wakeups_per_sec: if collecting > 0.0 {
+                reader_wakeups as f64 / collecting
+            } else {
+                0.0
+            },
+            reordering: self
+                .reorder
+                .as_ref()
+                .map(|reorder| reorder.lock().unwrap().stats()),
+        }
+    }
+}
+
+/// The next event queued, None once every sender is gone. A send between
+/// try_recv and notified() leaves a permit behind, so isn't missed
+#[cfg(feature = "async-runtime")]
+async fn recv(
+    rx: &mut std_mpsc::Receiver<CongestionEvent>,
+    depth: &AtomicUsize,
+    ready: &Notify,
+) -> Option<CongestionEvent> {
+    loop {
+        match rx.try_recv() {
+            Ok(event) => {
+                depth.fetch_sub(1, Ordering::Relaxed);
+                return Some(event);
+            }
+            Err(std_mpsc::TryRecvError::Empty) => ready.notified().await,
+            Err(std_mpsc::TryRecvError::Disconnected) => return None,
+        }
+    }
+}
+
+/// How often a reordering stage with no new events checks for ones to release
+fn idle_check(reordering: &EventReordering) -> Duration {
+    (reordering.watermark / 2).max(Duration::from_millis(1))
+}
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        match &self.worker {
+            #[cfg(feature = "async-runtime")]
+            Worker::Task { task, .. } => task.abort(),
+            #[cfg(feature = "blocking")]
+            Worker::Thread => {}
+        }
+    }
+}
+
+/// Hand an event to the processing side without ever waiting on it
+pub(crate) fn enqueue(queue: &Queue, counters: &PipelineCounters, event: CongestionEvent) {
+    let sent = match queue {
+        #[cfg(feature = "async-runtime")]
+        Queue::Async {
+            tx, depth, ready, ..
+        } => {
+            let sent = try_send(tx, depth, event);
+            if sent.is_ok() {
+                ready.notify_one();
+            }
+            sent
+        }
+        #[cfg(feature = "blocking")]
+        Queue::Blocking { tx, depth, .. } => try_send(tx, depth, event),
+    };
+    // Disconnected is past the worker's end, nothing to count
+    if let Err(std_mpsc::TrySendError::Full(_)) = sent {
+        counters.queue_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn try_send(
+    tx: &std_mpsc::SyncSender<CongestionEvent>,
+    depth: &AtomicUsize,
+    event: CongestionEvent,
+) -> Result<(), std_mpsc::TrySendError<CongestionEvent>> {
+    // Counted before the send so the worker's decrement can't underflow
+    depth.fetch_add(1, Ordering::Relaxed);
+    tx.try_send(event).inspect_err(|_| {
+        depth.fetch_sub(1, Ordering::Relaxed);
+    })
+}
+
+/// Everything that needs more than an atomic add
+pub(crate) fn process_slow(signals: &AtomicSignals, event: &CongestionEvent) {
+    // The readers only pass on the registered ones
+    if EXTENSION_EVENT_TYPES.contains(&event.event_type) {
+        signals.extensions.lock().unwrap().record_event(event);
+        return;
+    }
+    // A trace's unsampled events are for the trace, not the signals
+    if traced_only(event) {
+        if let Some(capture) = &signals.capture {
+            capture.lock().unwrap().record(event);
+        }
+        return;
+    }
+    signals.sockets.record(event);
+    signals.calibration.observe(event);
+    if let Some((socket_id, _)) = event_socket(event) {
+        signals.active_sockets.lock().unwrap().insert(socket_id);
+    }
+    if let Some(burst) = &signals.burst {
+        burst.lock().unwrap().record(event);
+    }
+    if let Some(interarrival) = &signals.interarrival {
+        interarrival.lock().unwrap().record(event);
+    }
+    if let Some(coupling) = &signals.coupling {
+        coupling.lock().unwrap().record(event);
+    }
+    if let Some(attribution) = &signals.attribution {
+        attribution.lock().unwrap().record(event);
+    }
+    if let Some(breakdown) = &signals.softirq_breakdown {
+        breakdown.lock().unwrap().record(event);
+    }
+    if let Some(capture) = &signals.capture {
+        capture.lock().unwrap().record(event);
+    }
+    if let Some(netns) = &signals.netns {
+        netns.lock().unwrap().record(event);
+    }
+    if let Some(classes) = &signals.classes {
+        classes.lock().unwrap().record(event);
+    }
+
+    match event.event_type {
+        EVENT_UDP_SEND => {
+            let send = unsafe { event.data.sendmsg };
+            match &signals.active_flows {
+                // The same peer from two sockets is two flows
+                Some(flows) if send.flow_hash != 0 => {
+                    let flow = send.socket_id.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ send.flow_hash;
+                    flows.lock().unwrap().insert(flow);
+                }
+                _ => {}
+            }
+        }
+        EVENT_TCP_STATE => {
+            let tcp = unsafe { event.data.tcp };
+            let mut below = signals.tcp_below_ssthresh.lock().unwrap();
+            if below.len() < signals.max_tracked_sockets || below.contains_key(&tcp.socket_id) {
+                below.insert(tcp.socket_id, tcp.snd_cwnd < tcp.snd_ssthresh);
+            } else {
+                signals.tcp_state_evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        EVENT_QDISC_DROP => {
+            let drop = unsafe { event.data.qdisc };
+            // Subsystem reasons, the readers count the rest
+            if drop.reason as usize >= DROP_REASON_SLOTS {
+                *signals
+                    .drops_by_reason_high
+                    .lock()
+                    .unwrap()
+                    .entry(drop.reason)
+                    .or_insert(0) += drop.dropped as u64;
+            }
+        }
+        EVENT_SOCKET_LIFECYCLE => {
+            let lifecycle = unsafe { event.data.lifecycle };
+            // A closed flow no longer competes with us
+            if lifecycle.newstate == TCP_CLOSE {
+                signals
+                    .tcp_below_ssthresh
+                    .lock()
+                    .unwrap()
+                    .remove(&lifecycle.socket_id);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collector::EventBatch;
+    use crate::fixtures::{self, Replay};
+
+    const CAPACITY: usize = 8;
+
+    #[test]
+    fn a_stalled_consumer_costs_queue_drops_not_aggregation() {
+        const EVENTS: u64 = 100_000;
+        for (queue, _rx) in fixtures::stalled_queues(CAPACITY) {
+            let replay = Replay::new(CollectorConfig::default(), 1);
+            let counters = PipelineCounters::default();
+            let mut batch = EventBatch::new(&replay.signals);
+            for timestamp_ns in 0..EVENTS {
+                let event = fixtures::rcv_drop(timestamp_ns, 0, 7);
+                if crate::reader::aggregate(&event, &mut batch, &replay.signals) {
+                    enqueue(&queue, &counters, event);
+                }
+            }
+            replay.signals.fold(&mut batch);
+
+            assert_eq!(
+                counters.queue_dropped.load(Ordering::Relaxed),
+                EVENTS - CAPACITY as u64
+            );
+            // The readers' share is all there
+            assert_eq!(replay.read(Duration::from_secs(1)).udp_rcv_drops, EVENTS);
+            assert_eq!(queue.occupancy(), (CAPACITY, CAPACITY));
+        }
+    }
+
+    /// What one `sample()` does short of the kernel: a pipeline started on
+    /// fresh signals, a second of events through it, one read, and teardown.
+    /// The signals come back once the worker is gone
+    #[cfg(any(feature = "async", feature = "blocking"))]
+    fn sample_once(
+        start: impl FnOnce(&CollectorConfig, Arc<AtomicSignals>) -> Pipeline,
+    ) -> (crate::CongestionSignals, Arc<AtomicSignals>) {
+        let config = CollectorConfig::default();
+        let signals = Arc::new(AtomicSignals::new(crate::CpuSlots::new(0..2), &config));
+        let interval = crate::collector::IntervalReader::detached(signals.clone(), &config);
+        let pipeline = start(&config, signals.clone());
+        let mut batch = EventBatch::new(&signals);
+        for socket_id in 1..=4 {
+            let event = fixtures::udp_send(socket_id, 0, socket_id, 1_200);
+            if crate::reader::aggregate(&event, &mut batch, &signals) {
+                enqueue(&pipeline.queue, &pipeline.counters, event);
+            }
+        }
+        signals.fold(&mut batch);
+        while signals.sockets.peek().len() < 4 {
+            std::thread::yield_now();
+        }
+        let (read, _) = interval.read_and_reset_per_cpu();
+        drop(interval);
+        drop(pipeline);
+        (read, signals)
+    }
+
+    #[cfg(any(feature = "async", feature = "blocking"))]
+    fn wound_down(signals: &Arc<AtomicSignals>) -> bool {
+        for _ in 0..1_000_000 {
+            if Arc::strong_count(signals) == 1 {
+                return true;
+            }
+            std::thread::yield_now();
+        }
+        false
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn repeated_blocking_samples_leave_nothing_running() {
+        for round in 0..5 {
+            let (read, signals) =
+                sample_once(|config, signals| Pipeline::start_blocking(config, signals).unwrap());
+            assert_eq!(
+                (read.send_bytes, read.active_sockets),
+                (4_800, 4),
+                "round {round}"
+            );
+            assert!(wound_down(&signals), "round {round}");
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn repeated_async_samples_leave_nothing_running() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .unwrap();
+        let _guard = runtime.enter();
+        for round in 0..5 {
+            let supervisor = Arc::new(Supervisor::new(Default::default()));
+            let (read, signals) = sample_once(|config, signals| {
+                let (subscribers, _) = broadcast::channel(16);
+                Pipeline::start(config, signals, subscribers, supervisor.clone())
+            });
+            supervisor.shutdown();
+            assert_eq!(
+                (read.send_bytes, read.active_sockets),
+                (4_800, 4),
+                "round {round}"
+            );
+            assert!(wound_down(&signals), "round {round}");
+        }
+    }
+
+    /// Started with reordering on a ManualClock, an event is held until the
+    /// clock is advanced past the watermark, however long that takes for real
+    #[cfg(any(feature = "async", feature = "blocking"))]
+    fn released_on_the_clock(
+        start: impl FnOnce(&CollectorConfig, Arc<AtomicSignals>) -> Pipeline,
+    ) -> crate::ReorderStats {
+        const WATERMARK: Duration = Duration::from_secs(60);
+        let clock = crate::ManualClock::new();
+        let config = CollectorConfig::default()
+            .with_clock(Arc::new(clock.clone()))
+            .with_event_reordering(crate::EventReordering {
+                watermark: WATERMARK,
+                max_buffered: CAPACITY,
+            });
+        let signals = Arc::new(AtomicSignals::new(crate::CpuSlots::new(0..2), &config));
+        let pipeline = start(&config, signals.clone());
+        let stats = || pipeline.stats().reordering.unwrap();
+        enqueue(
+            &pipeline.queue,
+            &pipeline.counters,
+            fixtures::udp_send(1_000, 0, 7, 1_200),
+        );
+        while stats().held == 0 {
+            std::thread::yield_now();
+        }
+        clock.advance(WATERMARK - Duration::from_millis(1));
+        for _ in 0..1_000 {
+            std::thread::yield_now();
+        }
+        assert_eq!(stats().released, 0);
+        clock.advance(Duration::from_millis(1));
+        while stats().released == 0 {
+            std::thread::yield_now();
+        }
+        stats()
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn the_blocking_pipeline_releases_on_its_clock() {
+        let stats = released_on_the_clock(|config, signals| {
+            Pipeline::start_blocking(config, signals).unwrap()
+        });
+        assert_eq!((stats.held, stats.released), (0, 1));
+        assert_eq!(stats.max_delay, Duration::from_secs(60));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn the_async_pipeline_releases_on_its_clock() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .unwrap();
+        let _guard = runtime.enter();
+        let supervisor = Arc::new(Supervisor::new(Default::default()));
+        let stats = released_on_the_clock(|config, signals| {
+            let (subscribers, _) = broadcast::channel(16);
+            Pipeline::start(config, signals, subscribers, supervisor.clone())
+        });
+        supervisor.shutdown();
+        assert_eq!((stats.held, stats.released), (0, 1));
+        assert_eq!(stats.max_delay, Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,622 @@
+//"Would the collector work on this host?" without attaching anything. The
+//object is loaded with the same globals a real load sets and every program is
+//put through the verifier, then it's dropped: maps closed, programs unloaded,
+//nothing ever attached. Probe targets are looked up by name (kallsyms or
+//tracefs), kernel BTF and tracefs checked for, and the process's capabilities
+//and locked-memory limit held against what this kernel asks for.
+//
+//The report says per probe whether it could attach and what to change on the
+//host where it couldn't. `signals-preflight` prints it as JSON.
+
+use crate::export;
+use crate::health;
+use crate::json::{json_opt, json_string};
+use crate::{CollectorConfig, HostFeature, ProgramAttach, EXPORT_SCHEMA_VERSION, PROGRAM_GROUPS};
+use aya::programs::{KProbe, SchedClassifier, TracePoint};
+use aya::Ebpf;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::io;
+
+/// A capability the collector may need, see `Capabilities`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// tc egress accounting: clsact qdiscs and classifiers
+    NetAdmin,
+    /// Everything BPF before 5.8
+    SysAdmin,
+    /// kprobes and tracepoints, 5.8+
+    Perfmon,
+    /// Loading programs and creating maps, 5.8+
+    Bpf,
+}
+
+impl Capability {
+    /// Bit in the capability sets, linux/capability.h
+    pub fn bit(self) -> u32 {
+        match self {
+            Self::NetAdmin => 12,
+            Self::SysAdmin => 21,
+            Self::Perfmon => 38,
+            Self::Bpf => 39,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::NetAdmin => "CAP_NET_ADMIN",
+            Self::SysAdmin => "CAP_SYS_ADMIN",
+            Self::Perfmon => "CAP_PERFMON",
+            Self::Bpf => "CAP_BPF",
+        }
+    }
+}
+
+/// What this process may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The effective set, CapEff in /proc/self/status
+    pub effective: u64,
+    /// RLIMIT_MEMLOCK in bytes, None when unlimited
+    pub memlock_limit: Option<u64>,
+}
+
+impl Capabilities {
+    /// This process's
+    pub fn current() -> io::Result<Self> {
+        let status = std::fs::read_to_string("/proc/self/status")?;
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let memlock_limit = (limit.rlim_cur != libc::RLIM_INFINITY).then_some(limit.rlim_cur);
+        Self::from_status(&status, memlock_limit)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no CapEff in status"))
+    }
+
+    /// From the text of a /proc/<pid>/status
+    pub fn from_status(status: &str, memlock_limit: Option<u64>) -> Option<Self> {
+        let effective = status
+            .lines()
+            .find_map(|line| line.strip_prefix("CapEff:"))
+            .and_then(|hex| u64::from_str_radix(hex.trim(), 16).ok())?;
+        Some(Self {
+            effective,
+            memlock_limit,
+        })
+    }
+
+    pub fn has(&self, capability: Capability) -> bool {
+        self.effective & (1 << capability.bit()) != 0
+    }
+
+    /// Enough to load programs and attach probes on a kernel of `version`
+    /// (major, minor); CAP_BPF and CAP_PERFMON only exist from 5.8
+    pub fn can_trace(&self, version: Option<(u32, u32)>) -> bool {
+        self.has(Capability::SysAdmin)
+            || (version.is_some_and(|v| v >= (5, 8))
+                && self.has(Capability::Bpf)
+                && self.has(Capability::Perfmon))
+    }
+}
+
+/// The host as the preflight found it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreflightHost {
+    /// `uname -r`
+    pub kernel_release: String,
+    /// Major and minor from the release, None if it didn't parse
+    pub kernel_version: Option<(u32, u32)>,
+    /// /sys/kernel/btf/vmlinux exists
+    pub btf: bool,
+    /// tracefs is mounted with its events directory
+    pub tracefs: bool,
+    /// None if /proc/self/status couldn't be read
+    pub capabilities: Option<Capabilities>,
+}
+
+impl PreflightHost {
+    pub fn current() -> Self {
+        let kernel_release = kernel_release();
+        Self {
+            kernel_version: parse_kernel_version(&kernel_release),
+            kernel_release,
+            btf: std::path::Path::new("/sys/kernel/btf/vmlinux").exists(),
+            tracefs: health::tracefs_root().is_some(),
+            capabilities: Capabilities::current().ok(),
+        }
+    }
+}
+
+fn kernel_release() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/osrelease")
+        .map(|release| release.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// This kernel's major and minor, None if its release didn't parse
+pub(crate) fn kernel_version() -> Option<(u32, u32)> {
+    parse_kernel_version(&kernel_release())
+}
+
+/// "6.1.0-18-amd64" -> (6, 1)
+pub fn parse_kernel_version(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeKind {
+    Kprobe,
+    Tracepoint,
+    /// tc egress accounting, one per configured interface
+    Classifier,
+}
+
+/// One program and where it would attach
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbePreflight {
+    /// In the eBPF object
+    pub program: &'static str,
+    pub kind: ProbeKind,
+    /// Kernel symbol, "category:name" tracepoint, or interface
+    pub target: String,
+    /// The load fails without it, a core group's program under
+    /// `CollectorConfig::strict`; the rest only leave signals out
+    pub required: bool,
+    /// The symbol, tracepoint or interface exists
+    pub available: bool,
+    /// The verifier accepted the program. None when the object didn't load
+    pub verified: Option<bool>,
+    /// Why it couldn't load, if it didn't
+    pub error: Option<String>,
+}
+
+impl ProbePreflight {
+    /// Would attach on this host
+    pub fn feasible(&self) -> bool {
+        self.available && self.verified == Some(true)
+    }
+}
+
+/// Something to change on the host
+#[derive(Debug, Clone, PartialEq)]
+pub struct Remediation {
+    /// The collector won't load until it's done. Otherwise some signals are
+    /// left out
+    pub blocking: bool,
+    pub action: String,
+}
+
+/// Built by `CongestionCollector::preflight()`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreflightReport {
+    pub host: PreflightHost,
+    /// Why the object didn't load (schema mismatch, verifier, permissions).
+    /// None when it did
+    pub load_error: Option<String>,
+    pub probes: Vec<ProbePreflight>,
+    /// What the object's maps would take, per `MemoryReport::bpf_bytes`.
+    /// None when it didn't load
+    pub map_memory_bytes: Option<u64>,
+    pub remediations: Vec<Remediation>,
+    /// Nothing blocking: `load_with_config` with the same config would succeed
+    pub can_run: bool,
+}
+
+/// Every probe `config` would attach, with whether its target exists here
+pub(crate) fn probes(config: &CollectorConfig, host: &PreflightHost) -> Vec<ProbePreflight> {
+    let symbols = kernel_symbols();
+    let tracepoint = |category: &str, name: &str| {
+        health::tracefs_root()
+            .is_some_and(|root| root.join("events").join(category).join(name).is_dir())
+    };
+    let probe = |program, kind, target: String, required, available| ProbePreflight {
+        program,
+        kind,
+        target,
+        required,
+        available,
+        verified: None,
+        error: None,
+    };
+
+    // Kprobes first, then tracepoints, each in load order
+    let mut probes = Vec::new();
+    for kprobes in [true, false] {
+        for group in &PROGRAM_GROUPS {
+            // Only a strict load fails without a core group, and one the host
+            // lacks tracefs for isn't tried
+            let required = config.strict
+                && group.core
+                && (host.tracefs || !group.requires.contains(&HostFeature::Tracefs));
+            for &(program, attach) in group.programs {
+                match attach {
+                    ProgramAttach::Kprobe(symbol) if kprobes => probes.push(probe(
+                        program,
+                        ProbeKind::Kprobe,
+                        symbol.to_string(),
+                        required,
+                        symbols.contains(symbol),
+                    )),
+                    ProgramAttach::Tracepoint(category, name) if !kprobes => probes.push(probe(
+                        program,
+                        ProbeKind::Tracepoint,
+                        format!("{}:{}", category, name),
+                        required,
+                        tracepoint(category, name),
+                    )),
+                    _ => {}
+                }
+            }
+        }
+    }
+    probes.extend(config.egress_interfaces.iter().map(|interface| {
+        let available = std::path::Path::new("/sys/class/net")
+            .join(interface)
+            .exists();
+        probe(
+            "tc_egress",
+            ProbeKind::Classifier,
+            interface.clone(),
+            false,
+            available,
+        )
+    }));
+    probes
+}
+
+/// Function names kprobes can attach to: tracefs' list where it's readable,
+/// /proc/kallsyms otherwise (names only, which unprivileged readers get too)
+fn kernel_symbols() -> HashSet<String> {
+    let filter_functions = health::tracefs_root()
+        .and_then(|root| std::fs::read_to_string(root.join("available_filter_functions")).ok());
+    if let Some(functions) = filter_functions {
+        return functions
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .map(str::to_string)
+            .collect();
+    }
+    std::fs::read_to_string("/proc/kallsyms")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(2))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Put every probe's program through the verifier; nothing is attached
+pub(crate) fn verify(ebpf: &mut Ebpf, probes: &mut [ProbePreflight]) {
+    // tc_egress loads once for every interface
+    let mut classifier: Option<Result<(), String>> = None;
+    for probe in probes {
+        let result = match probe.kind {
+            ProbeKind::Classifier => classifier
+                .get_or_insert_with(|| load_program(ebpf, probe.program, probe.kind))
+                .clone(),
+            kind => load_program(ebpf, probe.program, kind),
+        };
+        probe.verified = Some(result.is_ok());
+        probe.error = result.err();
+    }
+}
+
+fn load_program(ebpf: &mut Ebpf, program: &str, kind: ProbeKind) -> Result<(), String> {
+    let prog = ebpf
+        .program_mut(program)
+        .ok_or_else(|| format!("program {} not in object", program))?;
+    let loaded = match kind {
+        ProbeKind::Kprobe => <&mut KProbe>::try_from(prog).map(|prog| prog.load()),
+        ProbeKind::Tracepoint => <&mut TracePoint>::try_from(prog).map(|prog| prog.load()),
+        ProbeKind::Classifier => <&mut SchedClassifier>::try_from(prog).map(|prog| prog.load()),
+    };
+    match loaded {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+impl PreflightReport {
+    /// Weigh what was found: what blocks a load, what leaves signals out, and
+    /// what to do about each. `load_error` and `map_memory_bytes` are from
+    /// loading the object, None/None if it was never tried
+    pub fn assess(
+        host: PreflightHost,
+        probes: Vec<ProbePreflight>,
+        load_error: Option<String>,
+        map_memory_bytes: Option<u64>,
+    ) -> Self {
+        let mut remediations = Vec::new();
+        let mut remedy =
+            |blocking, action: String| remediations.push(Remediation { blocking, action });
+
+        match &host.capabilities {
+            Some(caps) if !caps.can_trace(host.kernel_version) => remedy(
+                true,
+                if host.kernel_version.is_some_and(|v| v >= (5, 8)) {
+                    "run as root, or grant CAP_BPF and CAP_PERFMON \
+                     (setcap cap_bpf,cap_perfmon+ep, or AmbientCapabilities= in the unit)"
+                        .to_string()
+                } else {
+                    "run as root or with CAP_SYS_ADMIN, this kernel predates CAP_BPF".to_string()
+                },
+            ),
+            Some(_) => {}
+            None => remedy(
+                false,
+                "capabilities couldn't be read from /proc/self/status".to_string(),
+            ),
+        }
+        // Before 5.11 map memory is charged against RLIMIT_MEMLOCK, not the memcg
+        let memlock_counts = host.kernel_version.is_some_and(|v| v < (5, 11));
+        if let (true, Some(Some(limit)), Some(needed)) = (
+            memlock_counts,
+            host.capabilities.map(|caps| caps.memlock_limit),
+            map_memory_bytes,
+        ) {
+            if limit < needed {
+                remedy(
+                    true,
+                    format!(
+                        "raise RLIMIT_MEMLOCK to at least {} KB (ulimit -l, or LimitMEMLOCK=infinity \
+                         in the unit); it's {} KB and maps are charged to it before 5.11",
+                        needed.div_ceil(1024),
+                        limit / 1024
+                    ),
+                );
+            }
+        }
+        if let Some(e) = &load_error {
+            remedy(true, format!("the eBPF object didn't load: {}", e));
+        }
+        if !host.btf {
+            remedy(
+                false,
+                "no kernel BTF at /sys/kernel/btf/vmlinux (CONFIG_DEBUG_INFO_BTF=y): \
+                 TCP state, socket cookies, CE marks and TX queue stalls are left out"
+                    .to_string(),
+            );
+        }
+        if !host.tracefs {
+            remedy(
+                false,
+                "tracefs isn't mounted (mount -t tracefs nodev /sys/kernel/tracing): \
+                 drops, queue depth and softirq time are left out"
+                    .to_string(),
+            );
+        }
+        for probe in probes.iter().filter(|probe| !probe.feasible()) {
+            let why = match (&probe.error, probe.available) {
+                (Some(e), _) => format!("rejected: {}", e),
+                (None, false) if probe.kind == ProbeKind::Classifier => {
+                    "no such interface".to_string()
+                }
+                // Covered by the tracefs remedy
+                (None, false) if probe.kind == ProbeKind::Tracepoint && !host.tracefs => continue,
+                (None, false) => "not in this kernel".to_string(),
+                // Not verified because the object didn't load, said above
+                (None, true) => continue,
+            };
+            remedy(
+                probe.required,
+                format!("{} ({}) {}", probe.program, probe.target, why),
+            );
+        }
+        if probes
+            .iter()
+            .any(|probe| probe.kind == ProbeKind::Classifier)
+            && host
+                .capabilities
+                .is_some_and(|caps| !caps.has(Capability::NetAdmin))
+        {
+            remedy(
+                false,
+                "egress accounting needs CAP_NET_ADMIN for its clsact qdisc".to_string(),
+            );
+        }
+
+        let can_run = !remediations.iter().any(|remediation| remediation.blocking);
+        Self {
+            host,
+            load_error,
+            probes,
+            map_memory_bytes,
+            remediations,
+            can_run,
+        }
+    }
+
+    /// Runs, but with some signals left out
+    pub fn degraded(&self) -> bool {
+        self.can_run && !self.remediations.is_empty()
+    }
+
+    /// One JSON object, no trailing newline
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        let host = &self.host;
+        let memlock_limit = json_opt(host.capabilities.and_then(|caps| caps.memlock_limit));
+        let _ = write!(
+            out,
+            "{{\"schema_version\":{},\"can_run\":{},\"degraded\":{},\"kernel_release\":{},\
+             \"btf\":{},\"tracefs\":{},\"capabilities\":{},\"memlock_limit_bytes\":{}{},\"load_error\":{},\
+             \"map_memory_bytes\":{}",
+            EXPORT_SCHEMA_VERSION,
+            self.can_run,
+            self.degraded(),
+            json_string(&host.kernel_release),
+            host.btf,
+            host.tracefs,
+            json_opt(host.capabilities.map(|caps| {
+                let held: Vec<String> = [
+                    Capability::Bpf,
+                    Capability::Perfmon,
+                    Capability::SysAdmin,
+                    Capability::NetAdmin,
+                ]
+                .into_iter()
+                .filter(|&capability| caps.has(capability))
+                .map(|capability| json_string(capability.name()))
+                .collect();
+                format!("[{}]", held.join(","))
+            })),
+            // null both when unlimited and when unknown, capabilities tells them apart
+            memlock_limit,
+            export::aliases("preflight", "memlock_limit_bytes", &memlock_limit),
+            json_opt(self.load_error.as_deref().map(json_string)),
+            json_opt(self.map_memory_bytes),
+        );
+        let probes: Vec<String> = self
+            .probes
+            .iter()
+            .map(|probe| {
+                format!(
+                    "{{\"program\":{},\"kind\":\"{:?}\",\"target\":{},\"required\":{},\
+                     \"available\":{},\"verified\":{},\"feasible\":{},\"error\":{}}}",
+                    json_string(probe.program),
+                    probe.kind,
+                    json_string(&probe.target),
+                    probe.required,
+                    probe.available,
+                    json_opt(probe.verified),
+                    probe.feasible(),
+                    json_opt(probe.error.as_deref().map(json_string)),
+                )
+            })
+            .collect();
+        let remediations: Vec<String> = self
+            .remediations
+            .iter()
+            .map(|remediation| {
+                format!(
+                    "{{\"blocking\":{},\"action\":{}}}",
+                    remediation.blocking,
+                    json_string(&remediation.action)
+                )
+            })
+            .collect();
+        let _ = write!(
+            out,
+            ",\"probes\":[{}],\"remediations\":[{}]}}",
+            probes.join(","),
+            remediations.join(",")
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // /proc/self/status lines: everything, CAP_BPF and CAP_PERFMON alone, nothing
+    const STATUS_ROOT: &str =
+        "Name:\tsignals\nCapInh:\t0000000000000000\nCapEff:\t000001ffffffffff\n";
+    const STATUS_BPF_PERFMON: &str = "CapEff:\t000000c000000000\n";
+    const STATUS_NONE: &str = "CapEff:\t0000000000000000\n";
+
+    fn host(status: &str, release: &str, memlock: Option<u64>) -> PreflightHost {
+        PreflightHost {
+            kernel_release: release.to_string(),
+            kernel_version: parse_kernel_version(release),
+            btf: true,
+            tracefs: true,
+            capabilities: Capabilities::from_status(status, memlock),
+        }
+    }
+
+    fn probe(
+        program: &'static str,
+        required: bool,
+        available: bool,
+        verified: bool,
+    ) -> ProbePreflight {
+        ProbePreflight {
+            program,
+            kind: ProbeKind::Kprobe,
+            target: program.to_string(),
+            required,
+            available,
+            verified: Some(verified),
+            error: (!verified).then(|| "invalid mem access".to_string()),
+        }
+    }
+
+    fn fine() -> Vec<ProbePreflight> {
+        vec![
+            probe("udp_sendmsg", true, true, true),
+            probe("tcp_tsq_handler", false, true, true),
+        ]
+    }
+
+    fn assess(host: PreflightHost, probes: Vec<ProbePreflight>) -> PreflightReport {
+        PreflightReport::assess(host, probes, None, Some(4 << 20))
+    }
+
+    #[test]
+    fn root_on_a_current_kernel_runs_clean() {
+        let root = assess(host(STATUS_ROOT, "6.1.0-18-amd64", None), fine());
+        assert!(root.can_run);
+        assert!(root.remediations.is_empty());
+        assert!(!root.degraded());
+        assert!(root
+            .to_json()
+            .starts_with("{\"schema_version\":1,\"can_run\":true,\"degraded\":false"));
+    }
+
+    #[test]
+    fn split_capabilities_need_a_kernel_that_knows_them() {
+        let split = assess(
+            host(STATUS_BPF_PERFMON, "5.15.0-91-generic", Some(64 << 10)),
+            fine(),
+        );
+        assert!(split.can_run);
+        let old_kernel = assess(host(STATUS_BPF_PERFMON, "5.4.0", None), fine());
+        assert!(!old_kernel.can_run);
+        let unprivileged = assess(host(STATUS_NONE, "6.8.0", None), fine());
+        assert!(!unprivileged.can_run);
+        assert!(unprivileged.remediations[0].action.contains("CAP_BPF"));
+    }
+
+    #[test]
+    fn memlock_only_binds_before_5_11() {
+        let memlock = assess(host(STATUS_ROOT, "5.10.0", Some(64 << 10)), fine());
+        assert!(!memlock.can_run);
+        assert!(memlock.remediations[0].action.contains("RLIMIT_MEMLOCK"));
+    }
+
+    #[test]
+    fn a_missing_optional_probe_degrades_and_a_rejected_required_one_blocks() {
+        let inlined = assess(
+            host(STATUS_ROOT, "6.1.0", None),
+            vec![
+                probe("udp_sendmsg", true, true, true),
+                probe("tcp_tsq_handler", false, false, true),
+            ],
+        );
+        assert!(inlined.can_run);
+        assert!(inlined.degraded());
+        let rejected = assess(
+            host(STATUS_ROOT, "6.1.0", None),
+            vec![probe("udp_sendmsg", true, true, false)],
+        );
+        assert!(!rejected.can_run);
+        assert_eq!(
+            rejected.remediations.iter().filter(|r| r.blocking).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn status_and_release_parsing() {
+        assert!(Capabilities::from_status("Name:\tx\n", None).is_none());
+        assert_eq!(parse_kernel_version("6.1.0-18-amd64"), Some((6, 1)));
+    }
+}
@@ -0,0 +1,143 @@
+//A socket's buffers read now, with getsockopt(SO_MEMINFO), for when sampled
+//send events are too sparse to say where one socket stands. 1-in-100 sampling
+//can go seconds without a sample from a given QUIC socket; this is a syscall
+//and needs no BPF, so it works without a collector too.
+
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+use std::time::Instant;
+
+// enum sk_meminfo_vars, linux/sock_diag.h
+const SK_MEMINFO_RMEM_ALLOC: usize = 0;
+const SK_MEMINFO_RCVBUF: usize = 1;
+const SK_MEMINFO_WMEM_ALLOC: usize = 2;
+const SK_MEMINFO_SNDBUF: usize = 3;
+const SK_MEMINFO_WMEM_QUEUED: usize = 5;
+const SK_MEMINFO_DROPS: usize = 8;
+const SK_MEMINFO_VARS: usize = 9;
+
+/// One socket's buffers at `sampled_at`, see `probe_socket()`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SocketStateSample {
+    /// The socket cookie, as from `socket_cookie()`
+    pub socket_id: u64,
+    /// sk_wmem_alloc: truesize of the socket's skbs not yet freed, queued in
+    /// the qdisc or the driver. What the sampled `wmem_pressure` is made of
+    pub wmem_alloc: u32,
+    /// sk_wmem_queued: TCP's write queue, sent or not. 0 for UDP
+    pub wmem_queued: u32,
+    /// sk_sndbuf, doubled by the kernel from what SO_SNDBUF set
+    pub sndbuf: u32,
+    /// The larger of `wmem_alloc` and `wmem_queued` over `sndbuf`
+    pub occupancy: f64,
+    pub rmem_alloc: u32,
+    pub rcvbuf: u32,
+    /// Datagrams the socket dropped since it was created, receive side
+    pub drops: u32,
+    pub sampled_at: Instant,
+}
+
+/// Read `socket`'s buffers now. Works for any socket, registered or not
+pub fn probe_socket(socket: &impl AsRawFd) -> io::Result<SocketStateSample> {
+    let fd = socket.as_raw_fd();
+    let socket_id = getsockopt::<u64>(fd, libc::SO_COOKIE)?;
+    let meminfo = getsockopt::<[u32; SK_MEMINFO_VARS]>(fd, libc::SO_MEMINFO)?;
+    let wmem_alloc = meminfo[SK_MEMINFO_WMEM_ALLOC];
+    let wmem_queued = meminfo[SK_MEMINFO_WMEM_QUEUED];
+    let sndbuf = meminfo[SK_MEMINFO_SNDBUF];
+    Ok(SocketStateSample {
+        socket_id,
+        wmem_alloc,
+        wmem_queued,
+        sndbuf,
+        occupancy: match sndbuf {
+            0 => 0.0,
+            sndbuf => wmem_alloc.max(wmem_queued) as f64 / sndbuf as f64,
+        },
+        rmem_alloc: meminfo[SK_MEMINFO_RMEM_ALLOC],
+        rcvbuf: meminfo[SK_MEMINFO_RCVBUF],
+        drops: meminfo[SK_MEMINFO_DROPS],
+        sampled_at: Instant::now(),
+    })
+}
+
+/// A SOL_SOCKET option of plain type `T`. Shorter answers (older kernels'
+/// meminfo) leave the rest zeroed
+pub(crate) fn getsockopt<T: Copy>(fd: RawFd, name: libc::c_int) -> io::Result<T> {
+    let mut value: T = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<T>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            name,
+            &mut value as *mut T as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream, UdpSocket};
+
+    // What a paused receiver's UDP socket is sent, and its rcvbuf
+    const DATAGRAMS: usize = 200;
+    const RCVBUF: libc::c_int = 16 * 1024;
+
+    #[test]
+    fn a_stalled_tcp_sender_shows_a_full_send_queue() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut sender = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        // Accepted, never read
+        let (receiver, _) = listener.accept().unwrap();
+        sender.set_nonblocking(true).unwrap();
+        let chunk = [0u8; 64 * 1024];
+        loop {
+            match sender.write(&chunk) {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => panic!("{}", e),
+            }
+        }
+
+        let probed = probe_socket(&sender).unwrap();
+        let cookie = getsockopt::<u64>(sender.as_raw_fd(), libc::SO_COOKIE).unwrap();
+        assert_eq!(probed.socket_id, cookie);
+        assert!(probed.wmem_queued > 0);
+        assert!(probed.occupancy > 0.5, "{:?}", probed);
+        assert!(probe_socket(&receiver).unwrap().rmem_alloc > 0);
+    }
+
+    #[test]
+    fn a_flooded_udp_receiver_shows_bytes_waiting_and_drops() {
+        let sink = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let ret = unsafe {
+            libc::setsockopt(
+                sink.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_RCVBUF,
+                &RCVBUF as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        assert_eq!(ret, 0);
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        udp.connect(sink.local_addr().unwrap()).unwrap();
+        for _ in 0..DATAGRAMS {
+            udp.send(&[0u8; 1200]).unwrap();
+        }
+
+        let probed = probe_socket(&sink).unwrap();
+        assert!(probed.rmem_alloc > 0);
+        assert!(probed.drops > 0, "{:?}", probed);
+        // Nothing sent from it
+        assert_eq!(probed.occupancy, 0.0);
+    }
+}
@@ -0,0 +1,584 @@
+//The object's programs, in groups that load and attach on their own, most
+//important first. A group is what makes sense alone: the enqueue kprobe and
+//its kretprobe, both softirq tracepoints, the TX queue's stop and wake. Each
+//declares what it needs of the host, a kernel version, tracefs or offsets from
+//kernel BTF, and a group the host lacks it for is skipped without trying.
+//
+//The rest are tried in order. When the verifier (or anything else) rejects a
+//program, the group's programs loaded so far are unloaded again and the
+//rejection, verifier log included, goes to `HealthReport::program_failures`
+//rather than stderr. The load goes on with whatever verified, the way the
+//optional probes always did: a kernel whose verifier refuses the softirq
+//programs still gets send and drop signals. `CollectorConfig::strict` fails
+//the load at the first core group rejected instead, which is what every load
+//did before groups.
+//
+//`load_groups` sees the object only through `ProgramLoader`, so what a partial
+//load keeps and reports can be checked without a kernel.
+
+use crate::{CollectorError, KernelOffsets, OFFSET_UNKNOWN};
+use std::fmt;
+use HostFeature::{GsoOffsets, SkbOwnerOffsets, TcpSockOffsets, Tracefs, TxqOffsets};
+use ProgramAttach::{Kprobe, Tracepoint};
+
+/// Where a program attaches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProgramAttach {
+    /// Kernel function; entry or return is the program's
+    Kprobe(&'static str),
+    /// Category and name
+    Tracepoint(&'static str, &'static str),
+}
+
+/// What a group needs of the host besides a kernel version
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostFeature {
+    /// Mounted, for tracepoints
+    Tracefs,
+    /// tcp_sock's cwnd, ssthresh and sock's pacing rate in kernel BTF
+    TcpSockOffsets,
+    /// sk_buff's length and headers and inet_cork's GSO size
+    GsoOffsets,
+    /// sk_buff's socket, or its device's ifindex
+    SkbOwnerOffsets,
+    /// netdev_queue's device and the device's ifindex
+    TxqOffsets,
+}
+
+impl fmt::Display for HostFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HostFeature::Tracefs => "tracefs",
+            HostFeature::TcpSockOffsets => "tcp_sock offsets in kernel BTF",
+            HostFeature::GsoOffsets => "sk_buff.len and inet_cork.gso_size in kernel BTF",
+            HostFeature::SkbOwnerOffsets => "sk_buff.sk or sk_buff.dev in kernel BTF",
+            HostFeature::TxqOffsets => "netdev_queue offsets in kernel BTF",
+        })
+    }
+}
+
+/// Programs that load together, see the module docs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramGroup {
+    pub name: &'static str,
+    /// A core signal depends on it. Under `CollectorConfig::strict` its
+    /// rejection fails the load
+    pub core: bool,
+    /// Major and minor, None for any kernel the object loads on
+    pub min_kernel: Option<(u32, u32)>,
+    pub requires: &'static [HostFeature],
+    /// In the object, and where each attaches
+    pub programs: &'static [(&'static str, ProgramAttach)],
+}
+
+const fn group(
+    name: &'static str,
+    requires: &'static [HostFeature],
+    programs: &'static [(&'static str, ProgramAttach)],
+) -> ProgramGroup {
+    ProgramGroup {
+        name,
+        core: false,
+        min_kernel: None,
+        requires,
+        programs,
+    }
+}
+
+const fn core(
+    name: &'static str,
+    requires: &'static [HostFeature],
+    programs: &'static [(&'static str, ProgramAttach)],
+) -> ProgramGroup {
+    ProgramGroup {
+        core: true,
+        ..group(name, requires, programs)
+    }
+}
+
+const fn since(group: ProgramGroup, version: (u32, u32)) -> ProgramGroup {
+    ProgramGroup {
+        min_kernel: Some(version),
+        ..group
+    }
+}
+
+/// Every group `load_and_attach` loads, in the order it tries them
+pub const PROGRAM_GROUPS: [ProgramGroup; 17] = [
+    core("udp_send", &[], &[("udp_sendmsg", Kprobe("udp_sendmsg"))]),
+    core(
+        "udp_enqueue",
+        &[],
+        &[
+            (
+                "udp_enqueue_schedule_skb",
+                Kprobe("__udp_enqueue_schedule_skb"),
+            ),
+            (
+                "udp_enqueue_schedule_skb_ret",
+                Kprobe("__udp_enqueue_schedule_skb"),
+            ),
+        ],
+    ),
+    core(
+        "drops",
+        &[Tracefs],
+        &[("skb_kfree", Tracepoint("skb", "kfree_skb"))],
+    ),
+    core(
+        "queue_depth",
+        &[Tracefs],
+        &[("net_dev_queue", Tracepoint("net", "net_dev_queue"))],
+    ),
+    core(
+        "softirq",
+        &[Tracefs],
+        &[
+            ("softirq_entry", Tracepoint("irq", "softirq_entry")),
+            ("softirq_exit", Tracepoint("irq", "softirq_exit")),
+        ],
+    ),
+    // Application-limited rate samples are 4.10's
+    since(
+        group(
+            "tcp_state",
+            &[TcpSockOffsets],
+            &[(
+                "tcp_rate_check_app_limited",
+                Kprobe("tcp_rate_check_app_limited"),
+            )],
+        ),
+        (4, 10),
+    ),
+    group(
+        "tcp_cwr",
+        &[],
+        &[("tcp_enter_cwr", Kprobe("tcp_enter_cwr"))],
+    ),
+    group(
+        "tcp_rto",
+        &[],
+        &[("tcp_retransmit_timer", Kprobe("tcp_retransmit_timer"))],
+    ),
+    // Static in tcp_input.c, often inlined into tcp_fastretrans_alert
+    group(
+        "tcp_recovery",
+        &[],
+        &[("tcp_enter_recovery", Kprobe("tcp_enter_recovery"))],
+    ),
+    since(
+        group(
+            "socket_lifecycle",
+            &[Tracefs],
+            &[(
+                "inet_sock_set_state",
+                Tracepoint("sock", "inet_sock_set_state"),
+            )],
+        ),
+        (4, 16),
+    ),
+    group(
+        "rx_squeeze",
+        &[Tracefs],
+        &[("napi_poll", Tracepoint("napi", "napi_poll"))],
+    ),
+    // Static in tcp_output.c; kernels that inline it have no symbol to probe
+    group(
+        "tsq",
+        &[],
+        &[("tcp_tsq_handler", Kprobe("tcp_tsq_handler"))],
+    ),
+    group(
+        "tcp_accept",
+        &[],
+        &[("inet_csk_accept_ret", Kprobe("inet_csk_accept"))],
+    ),
+    // Static in udp.c and ipv6/udp.c. The payload is the skb's length past
+    // its IP and UDP headers
+    group(
+        "udp_gso",
+        &[GsoOffsets],
+        &[("udp_send_skb", Kprobe("udp_send_skb"))],
+    ),
+    group(
+        "udp6_gso",
+        &[GsoOffsets],
+        &[("udp_v6_send_skb", Kprobe("udp_v6_send_skb"))],
+    ),
+    group(
+        "xmit",
+        &[Tracefs, SkbOwnerOffsets],
+        &[("net_dev_xmit", Tracepoint("net", "net_dev_xmit"))],
+    ),
+    // Exported, but inlined into the drivers before 6.3
+    group(
+        "txq",
+        &[TxqOffsets],
+        &[
+            ("netif_tx_stop_queue", Kprobe("netif_tx_stop_queue")),
+            ("netif_tx_wake_queue", Kprobe("netif_tx_wake_queue")),
+        ],
+    ),
+];
+
+/// What groups' requirements are held against
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GroupHost {
+    /// Major and minor, None when unknown: no group is skipped for its version
+    pub kernel_version: Option<(u32, u32)>,
+    pub features: Vec<HostFeature>,
+}
+
+impl GroupHost {
+    /// This host, from what BTF resolved and whether tracefs is mounted
+    pub fn new(kernel_version: Option<(u32, u32)>, tracefs: bool, offsets: &KernelOffsets) -> Self {
+        let known = |offsets: &[u32]| offsets.iter().all(|&offset| offset != OFFSET_UNKNOWN);
+        let features = [
+            (Tracefs, tracefs),
+            (
+                TcpSockOffsets,
+                known(&[
+                    offsets.tcp_snd_cwnd,
+                    offsets.tcp_snd_ssthresh,
+                    offsets.sk_pacing_rate,
+                ]),
+            ),
+            (
+                GsoOffsets,
+                known(&[
+                    offsets.skb_len,
+                    offsets.inet_cork_gso_size,
+                    offsets.skb_network_header,
+                    offsets.skb_transport_header,
+                ]),
+            ),
+            (
+                SkbOwnerOffsets,
+                known(&[offsets.skb_sk]) || known(&[offsets.skb_dev, offsets.net_device_ifindex]),
+            ),
+            (
+                TxqOffsets,
+                known(&[offsets.netdev_queue_dev, offsets.net_device_ifindex]),
+            ),
+        ];
+        Self {
+            kernel_version,
+            features: features
+                .into_iter()
+                .filter(|&(_, present)| present)
+                .map(|(feature, _)| feature)
+                .collect(),
+        }
+    }
+
+    /// Why `group` isn't tried here, None when it is
+    pub fn unmet(&self, group: &ProgramGroup) -> Option<String> {
+        if let (Some(version), Some(min)) = (self.kernel_version, group.min_kernel) {
+            if version < min {
+                return Some(format!("needs kernel {}.{}", min.0, min.1));
+            }
+        }
+        let missing: Vec<String> = group
+            .requires
+            .iter()
+            .filter(|feature| !self.features.contains(feature))
+            .map(|feature| feature.to_string())
+            .collect();
+        (!missing.is_empty()).then(|| format!("needs {}", missing.join(" and ")))
+    }
+}
+
+/// Why a program didn't load or attach
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramRejection {
+    pub error: String,
+    /// What the verifier said, when it was the verifier
+    pub verifier_log: Option<String>,
+}
+
+impl ProgramRejection {
+    pub fn new(error: impl Into<String>) -> Self {
+        Self {
+            error: error.into(),
+            verifier_log: None,
+        }
+    }
+}
+
+/// The object as `load_groups` sees it: the loaded aya object, or a stand-in
+pub trait ProgramLoader {
+    /// Put `program` through the verifier
+    fn load(&mut self, program: &str) -> Result<(), ProgramRejection>;
+    fn attach(&mut self, program: &str, attach: ProgramAttach) -> Result<(), ProgramRejection>;
+    /// Unload a loaded program, which detaches it
+    fn unload(&mut self, program: &str);
+}
+
+/// Which step a program failed at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStage {
+    Load,
+    Attach,
+}
+
+impl fmt::Display for LoadStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LoadStage::Load => "load",
+            LoadStage::Attach => "attach",
+        })
+    }
+}
+
+/// The program a group was rejected for, see `HealthReport::program_failures`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramLoadFailure {
+    pub group: &'static str,
+    pub program: &'static str,
+    pub stage: LoadStage,
+    pub error: String,
+    /// The verifier's log, when it rejected the program
+    pub verifier_log: Option<String>,
+}
+
+impl fmt::Display for ProgramLoadFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "program group {} left out: {} failed to {}: {}",
+            self.group, self.program, self.stage, self.error
+        )
+    }
+}
+
+/// What `load_groups` loaded and didn't
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GroupLoad {
+    /// Groups loaded and attached, in the order tried
+    pub loaded: Vec<&'static str>,
+    /// Their programs
+    pub attached: Vec<&'static str>,
+    /// Groups not tried, with what the host lacks for them
+    pub skipped: Vec<(&'static str, String)>,
+    /// One per group rejected
+    pub failures: Vec<ProgramLoadFailure>,
+}
+
+/// Load and attach each of `groups` in order, see the module docs. Fails when
+/// nothing attached, and with `strict` at the first core group rejected, with
+/// [`CollectorError::ProgramRejected`]
+pub fn load_groups(
+    loader: &mut impl ProgramLoader,
+    groups: &[ProgramGroup],
+    host: &GroupHost,
+    strict: bool,
+) -> anyhow::Result<GroupLoad> {
+    let mut load = GroupLoad::default();
+    for group in groups {
+        if let Some(unmet) = host.unmet(group) {
+            log::warn!("program group {} skipped: {}", group.name, unmet);
+            load.skipped.push((group.name, unmet));
+            continue;
+        }
+        match load_group(loader, group) {
+            Ok(()) => {
+                log::info!("program group {} attached", group.name);
+                load.loaded.push(group.name);
+                load.attached
+                    .extend(group.programs.iter().map(|&(program, _)| program));
+            }
+            Err(failure) if strict && group.core => {
+                return Err(CollectorError::ProgramRejected(failure).into());
+            }
+            Err(failure) => {
+                log::warn!("{}", failure);
+                load.failures.push(failure);
+            }
+        }
+    }
+    if load.attached.is_empty() {
+        anyhow::bail!(
+            "no program group attached, {} rejected and {} skipped",
+            load.failures.len(),
+            load.skipped.len()
+        );
+    }
+    Ok(load)
+}
+
+// All of the group or none of it
+fn load_group(
+    loader: &mut impl ProgramLoader,
+    group: &ProgramGroup,
+) -> Result<(), ProgramLoadFailure> {
+    for (i, &(program, attach)) in group.programs.iter().enumerate() {
+        let result = match loader.load(program) {
+            Ok(()) => loader
+                .attach(program, attach)
+                .map_err(|rejection| (LoadStage::Attach, rejection)),
+            Err(rejection) => Err((LoadStage::Load, rejection)),
+        };
+        let Err((stage, rejection)) = result else {
+            continue;
+        };
+        // This one too when it loaded and didn't attach
+        let loaded = if stage == LoadStage::Attach { i + 1 } else { i };
+        for &(program, _) in &group.programs[..loaded] {
+            loader.unload(program);
+        }
+        return Err(ProgramLoadFailure {
+            group: group.name,
+            program,
+            stage,
+            error: rejection.error,
+            verifier_log: rejection.verifier_log,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rejects some programs the way an older kernel's verifier does, and
+    /// keeps what's loaded and attached
+    #[derive(Default)]
+    struct Kernel {
+        rejected: Vec<&'static str>,
+        unattachable: Vec<&'static str>,
+        loaded: Vec<String>,
+        attached: Vec<String>,
+    }
+
+    impl Kernel {
+        fn new(rejected: &[&'static str], unattachable: &[&'static str]) -> Self {
+            Self {
+                rejected: rejected.to_vec(),
+                unattachable: unattachable.to_vec(),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl ProgramLoader for Kernel {
+        fn load(&mut self, program: &str) -> Result<(), ProgramRejection> {
+            if self.rejected.contains(&program) {
+                return Err(ProgramRejection {
+                    error: "rejected: Invalid argument (os error 22)".to_string(),
+                    verifier_log: Some(format!(
+                        "0: (79) r6 = *(u64 *)(r1 +104)\ninvalid bpf_context access off=104 size=8\n\
+                         processed 1 insns in {}",
+                        program
+                    )),
+                });
+            }
+            self.loaded.push(program.to_string());
+            Ok(())
+        }
+
+        fn attach(&mut self, program: &str, _: ProgramAttach) -> Result<(), ProgramRejection> {
+            if self.unattachable.contains(&program) {
+                return Err(ProgramRejection::new("tracepoint not found"));
+            }
+            self.attached.push(program.to_string());
+            Ok(())
+        }
+
+        fn unload(&mut self, program: &str) {
+            self.loaded.retain(|p| p != program);
+            self.attached.retain(|p| p != program);
+        }
+    }
+
+    /// A 4.14 kernel with tracefs and BTF, but no netdev_queue offsets
+    fn host() -> GroupHost {
+        GroupHost {
+            kernel_version: Some((4, 14)),
+            features: vec![Tracefs, TcpSockOffsets, GsoOffsets, SkbOwnerOffsets],
+        }
+    }
+
+    #[test]
+    fn rejected_groups_are_left_out_and_the_rest_load() {
+        let mut kernel = Kernel::new(&["softirq_exit", "tcp_enter_cwr"], &["net_dev_xmit"]);
+        let load = load_groups(&mut kernel, &PROGRAM_GROUPS, &host(), false).unwrap();
+        let rejected: Vec<(&str, &str, LoadStage)> = load
+            .failures
+            .iter()
+            .map(|f| (f.group, f.program, f.stage))
+            .collect();
+        assert_eq!(
+            rejected,
+            [
+                ("softirq", "softirq_exit", LoadStage::Load),
+                ("tcp_cwr", "tcp_enter_cwr", LoadStage::Load),
+                ("xmit", "net_dev_xmit", LoadStage::Attach),
+            ]
+        );
+        let logged: Vec<bool> = load
+            .failures
+            .iter()
+            .map(|f| f.verifier_log.is_some())
+            .collect();
+        assert_eq!(logged, [true, true, false]);
+        assert!(load.failures[0]
+            .verifier_log
+            .as_deref()
+            .unwrap()
+            .contains("invalid bpf_context access"));
+        assert_eq!(
+            load.failures[0].to_string(),
+            "program group softirq left out: softirq_exit failed to load: \
+             rejected: Invalid argument (os error 22)"
+        );
+        // Socket lifecycle is 4.16's
+        let skipped: Vec<&str> = load.skipped.iter().map(|&(group, _)| group).collect();
+        assert_eq!(skipped, ["socket_lifecycle", "txq"]);
+        assert_eq!(load.loaded.len(), 12);
+        assert!(load.attached.contains(&"udp_sendmsg"));
+        assert!(load.attached.contains(&"skb_kfree"));
+        assert!(!load.attached.contains(&"softirq_entry"));
+        // The group's other half and the program that didn't attach are unloaded
+        assert_eq!(kernel.loaded, load.attached);
+        assert_eq!(kernel.attached, kernel.loaded);
+    }
+
+    #[test]
+    fn a_strict_load_fails_on_a_required_group_only() {
+        let strict = load_groups(
+            &mut Kernel::new(&["softirq_exit"], &[]),
+            &PROGRAM_GROUPS,
+            &host(),
+            true,
+        );
+        let error = strict.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<CollectorError>(),
+            Some(CollectorError::ProgramRejected(failure)) if failure.group == "softirq"
+        ));
+        assert!(
+            error.to_string().contains("verifier log:\n0: (79)"),
+            "{}",
+            error
+        );
+
+        let optional = load_groups(
+            &mut Kernel::new(&["tcp_enter_cwr"], &[]),
+            &PROGRAM_GROUPS,
+            &host(),
+            true,
+        );
+        assert_eq!(optional.unwrap().failures.len(), 1);
+    }
+
+    #[test]
+    fn nothing_loading_is_an_error() {
+        let every_program: Vec<&'static str> = PROGRAM_GROUPS
+            .iter()
+            .flat_map(|group| group.programs.iter().map(|&(program, _)| program))
+            .collect();
+        let mut kernel = Kernel::new(&every_program, &[]);
+        assert!(load_groups(&mut kernel, &PROGRAM_GROUPS, &host(), false).is_err());
+    }
+}
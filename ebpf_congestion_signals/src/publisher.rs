@@ -0,0 +1,532 @@
+//One interval reader behind every snapshot stream. read_and_reset() hands the
+//interval to whoever calls it, so two consumers each on their own timer would
+//split the data between them; instead a single task reads on one cadence and
+//fans the result out through a watch channel.
+//
+//Every snapshot carries when it was read, so `latest()` can refuse to hand out
+//one the task hasn't replaced in time instead of serving it as current.
+//
+//Fast snapshots get a publisher of their own on their own cadence. It reads
+//the fast counters only, so it never takes an interval from the one above.
+//
+//Aligned ticks take the wall time from the reader's clock
+//(`CollectorConfig::clock`), so a test can step it backward under paused
+//runtime time.
+
+use crate::clock::Clock;
+use crate::collector::IntervalReader;
+use crate::runtime::{AbortHandle, MissedTicks, Rt, Runtime, TaskHandle, Ticker};
+use crate::supervisor::{Component, Supervisor};
+use crate::{CongestionSignals, FastSignals};
+use futures_core::Stream;
+use futures_util::FutureExt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, watch};
+
+pub(crate) struct Publisher {
+    tx: Arc<watch::Sender<CongestionSignals>>,
+    interval: Duration,
+    task: AbortHandle,
+    // The reader's, which stamps produced_at
+    clock: Arc<dyn Clock>,
+}
+
+impl Publisher {
+    /// Spawn the reading task under `supervisor`, on the runtime `Rt` is for.
+    /// `align_to_wall_clock` ticks on multiples of `interval` since the epoch
+    /// instead of from now
+    pub(crate) fn start(
+        reader: Arc<IntervalReader>,
+        interval: Duration,
+        align_to_wall_clock: bool,
+        supervisor: &Supervisor,
+    ) -> Self {
+        let tx = Arc::new(watch::channel(CongestionSignals::default()).0);
+        let task_tx = tx.clone();
+        let clock = reader.clock.clone();
+        // A restart keeps the sender, so streams carry on across it
+        let task = if align_to_wall_clock {
+            supervisor.spawn(Component::Publisher, move || {
+                run_aligned(reader.clone(), interval, task_tx.clone()).map(Ok)
+            })
+        } else {
+            supervisor.spawn(Component::Publisher, move || {
+                run(reader.clone(), interval, task_tx.clone()).map(Ok)
+            })
+        };
+        Self {
+            tx,
+            interval,
+            task,
+            clock,
+        }
+    }
+
+    pub(crate) fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<CongestionSignals> {
+        self.tx.subscribe()
+    }
+
+    /// The last published snapshot, without marking it seen for any stream
+    pub(crate) fn latest(&self, max_age: Duration) -> LatestSnapshot {
+        let signals = self.tx.borrow();
+        match signals.age_at(self.clock.now()) {
+            // The channel's initial value
+            None => LatestSnapshot::Unavailable,
+            Some(age) if age > max_age => LatestSnapshot::Stale { age },
+            Some(_) => LatestSnapshot::Fresh(Box::new(signals.clone())),
+        }
+    }
+}
+
+pub(crate) struct FastPublisher {
+    tx: Arc<watch::Sender<FastSignals>>,
+    interval: Duration,
+    task: AbortHandle,
+}
+
+impl FastPublisher {
+    /// Spawn the fast reading task under `supervisor`, on the runtime `Rt` is for
+    pub(crate) fn start(
+        reader: Arc<IntervalReader>,
+        interval: Duration,
+        supervisor: &Supervisor,
+    ) -> Self {
+        let tx = Arc::new(watch::channel(FastSignals::default()).0);
+        let task_tx = tx.clone();
+        let task = supervisor.spawn(Component::FastPublisher, move || {
+            run_fast(reader.clone(), interval, task_tx.clone()).map(Ok)
+        });
+        Self { tx, interval, task }
+    }
+
+    pub(crate) fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<FastSignals> {
+        self.tx.subscribe()
+    }
+}
+
+/// What `CongestionCollector::latest` found
+#[derive(Debug, Clone)]
+pub enum LatestSnapshot {
+    Fresh(Box<CongestionSignals>),
+    /// The last snapshot was read `age` ago, more than the max age asked for.
+    /// The publisher stalled (an overloaded runtime), or no stream is left to
+    /// consume it: it only reads while one is
+    Stale {
+        age: Duration,
+    },
+    /// No snapshot published yet, or no `snapshots()` stream ever started
+    Unavailable,
+}
+
+async fn run(
+    reader: Arc<IntervalReader>,
+    interval: Duration,
+    tx: Arc<watch::Sender<CongestionSignals>>,
+) {
+    let mut ticker = Rt::interval(interval, MissedTicks::Delay);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        // Every stream dropped: stop resetting, whatever else reads the
+        // collector gets the data. A new stream picks up from here
+        if tx.receiver_count() == 0 {
+            continue;
+        }
+        let (signals, _) = reader.read_and_reset_per_cpu();
+        tx.send_replace(signals);
+    }
+}
+
+/// Unlike `run`, reads with no stream left too: nothing else takes the fast
+/// counters, and a stream that comes back should start on a fresh window
+/// rather than on everything since the last one went away
+async fn run_fast(
+    reader: Arc<IntervalReader>,
+    interval: Duration,
+    tx: Arc<watch::Sender<FastSignals>>,
+) {
+    let mut ticker = Rt::interval(interval, MissedTicks::Delay);
+    ticker.tick().await;
+    // Starts the first window now instead of at load
+    reader.read_fast();
+    loop {
+        ticker.tick().await;
+        let signals = reader.read_fast();
+        if tx.receiver_count() > 0 {
+            tx.send_replace(signals);
+        }
+    }
+}
+
+/// Sleeps to each boundary by the clock's wall time, so a clock that's slewed by
+/// NTP is followed, and treats a wakeup more than half an interval off its boundary
+/// as a clock step. The interval before the first boundary, and the one
+/// spanning a step, cover unknown wall time and are read but not published
+async fn run_aligned(
+    reader: Arc<IntervalReader>,
+    interval: Duration,
+    tx: Arc<watch::Sender<CongestionSignals>>,
+) {
+    let clock = reader.clock.clone();
+    let step = interval.as_nanos().max(1);
+    // Boundary the interval being collected started on, None when that isn't known
+    let mut start: Option<u128> = None;
+    loop {
+        let now = unix_ns(&*clock);
+        let boundary = (now / step + 1) * step;
+        Rt::sleep(Duration::from_nanos((boundary - now) as u64)).await;
+
+        let woke = unix_ns(&*clock);
+        if woke.abs_diff(boundary) > step / 2 {
+            log::warn!(
+                "system clock stepped by ~{:?}, realigning snapshots",
+                Duration::from_nanos(woke.abs_diff(boundary) as u64)
+            );
+            start = None;
+            continue;
+        }
+        if tx.receiver_count() == 0 {
+            start = None;
+            continue;
+        }
+
+        let (mut signals, _) = reader.read_and_reset_per_cpu();
+        match start.replace(boundary) {
+            Some(start) => {
+                signals.aligned_start = Some(system_time(start));
+                signals.aligned_end = Some(system_time(boundary));
+                tx.send_replace(signals);
+            }
+            None => log::debug!("partial interval before {} dropped", boundary),
+        }
+    }
+}
+
+fn unix_ns(clock: &dyn Clock) -> u128 {
+    clock
+        .system_time()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+}
+
+fn system_time(unix_ns: u128) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(unix_ns as u64)
+}
+
+impl Drop for Publisher {
+    fn drop(&mut self) {
+        // Takes the task's sender with it, which ends every stream
+        self.task.abort();
+    }
+}
+
+impl Drop for FastPublisher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Snapshots from `rx` until the publisher goes away. None gives an empty stream
+pub(crate) fn stream<T: Clone + Send + Sync + 'static>(
+    rx: Option<watch::Receiver<T>>,
+) -> impl Stream<Item = T> + Send + 'static {
+    futures_util::stream::unfold(rx, |rx| async move {
+        let mut rx = rx?;
+        rx.changed().await.ok()?;
+        let signals = rx.borrow_and_update().clone();
+        Some((signals, Some(rx)))
+    })
+}
+
+/// Everything `rx` receives until its sender goes away, skipping what it lagged
+/// past
+pub(crate) fn broadcast_stream<T: Clone + Send + 'static>(
+    rx: broadcast::Receiver<T>,
+) -> impl Stream<Item = T> + Send + 'static {
+    futures_util::stream::unfold(rx, |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(item) => return Some((item, rx)),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, Replay};
+    use crate::supervisor::RestartPolicies;
+    use crate::SignalSmoother;
+    use futures_util::StreamExt;
+    use std::future::Future;
+    use tokio::time::Instant;
+
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    fn paused<F: Future>(test: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap()
+            .block_on(test)
+    }
+
+    fn publisher(replay: &Replay, supervisor: &Supervisor) -> Publisher {
+        Publisher::start(replay.interval.clone(), INTERVAL, false, supervisor)
+    }
+
+    #[test]
+    fn snapshots_tick_on_the_interval() {
+        paused(async {
+            let replay = Replay::new(crate::CollectorConfig::default(), 1);
+            let supervisor = Supervisor::new(RestartPolicies::default());
+            let publisher = publisher(&replay, &supervisor);
+            let mut snapshots = Box::pin(stream(Some(publisher.subscribe())));
+
+            let start = Instant::now();
+            for tick in 1..=3 {
+                snapshots.next().await.unwrap();
+                assert_eq!(start.elapsed(), INTERVAL * tick);
+            }
+        });
+    }
+
+    #[test]
+    fn streams_share_one_read_per_tick() {
+        paused(async {
+            let replay = Replay::new(crate::CollectorConfig::default(), 1);
+            let supervisor = Supervisor::new(RestartPolicies::default());
+            let publisher = publisher(&replay, &supervisor);
+            let mut first = Box::pin(stream(Some(publisher.subscribe())));
+            let mut second = Box::pin(stream(Some(publisher.subscribe())));
+
+            replay.feed(&[fixtures::udp_send(1, 0, 7, 1200)]);
+            // Two streams, but the interval isn't split or reset twice
+            assert_eq!(first.next().await.unwrap().send_bytes, 1200);
+            assert_eq!(second.next().await.unwrap().send_bytes, 1200);
+            assert_eq!(first.next().await.unwrap().send_bytes, 0);
+            assert_eq!(second.next().await.unwrap().send_bytes, 0);
+        });
+    }
+
+    #[test]
+    fn dropping_every_stream_stops_the_reads() {
+        paused(async {
+            let replay = Replay::new(crate::CollectorConfig::default(), 1);
+            let supervisor = Supervisor::new(RestartPolicies::default());
+            let publisher = publisher(&replay, &supervisor);
+            let mut snapshots = Box::pin(stream(Some(publisher.subscribe())));
+            snapshots.next().await.unwrap();
+            drop(snapshots);
+
+            replay.feed(&[fixtures::udp_send(1, 0, 7, 1200)]);
+            tokio::time::sleep(INTERVAL * 5).await;
+            // Left for whoever reads the collector next
+            assert_eq!(replay.read(INTERVAL).send_bytes, 1200);
+
+            // A new stream picks up from there
+            let mut snapshots = Box::pin(stream(Some(publisher.subscribe())));
+            replay.feed(&[fixtures::udp_send(2, 0, 7, 800)]);
+            assert_eq!(snapshots.next().await.unwrap().send_bytes, 800);
+        });
+    }
+
+    #[test]
+    fn dropping_the_publisher_ends_its_streams() {
+        paused(async {
+            let replay = Replay::new(crate::CollectorConfig::default(), 1);
+            let supervisor = Supervisor::new(RestartPolicies::default());
+            let publisher = publisher(&replay, &supervisor);
+            let mut snapshots = Box::pin(stream(Some(publisher.subscribe())));
+            snapshots.next().await.unwrap();
+
+            // What stop_collection() does
+            drop(publisher);
+            assert!(snapshots.next().await.is_none());
+            // And a stream asked for while stopped is empty from the start
+            assert!(Box::pin(stream::<CongestionSignals>(None))
+                .next()
+                .await
+                .is_none());
+        });
+    }
+
+    #[test]
+    fn smoothed_snapshots_fold_each_tick() {
+        paused(async {
+            let replay = Replay::new(crate::CollectorConfig::default(), 1);
+            let supervisor = Supervisor::new(RestartPolicies::default());
+            let publisher = publisher(&replay, &supervisor);
+            let smoother = Arc::new(std::sync::Mutex::new(
+                SignalSmoother::new(0.5).with_clock(Arc::new(replay.clock.clone())),
+            ));
+            let folding = smoother.clone();
+            let mut smoothed = Box::pin(
+                stream(Some(publisher.subscribe()))
+                    .map(move |signals| folding.lock().unwrap().update(&signals)),
+            );
+
+            // The reads time their intervals by the replay's clock
+            replay.feed(&[fixtures::qdisc_drop(1, 0, 10, 0)]);
+            replay.clock.advance(INTERVAL);
+            let first = smoothed.next().await.unwrap();
+            assert_eq!(first.latest.drops, 10);
+            replay.clock.advance(INTERVAL);
+            let second = smoothed.next().await.unwrap();
+            assert_eq!(second.latest.drops, 0);
+            assert!(second.drop_rate > 0.0 && second.drop_rate < first.drop_rate);
+            // And how long the smoothed view has gone without a tick, on the same clock
+            let since_update = || smoother.lock().unwrap().since_update();
+            assert_eq!(since_update(), Some(Duration::ZERO));
+            replay.clock.advance(INTERVAL * 3);
+            assert_eq!(since_update(), Some(INTERVAL * 3));
+        });
+    }
+
+    #[test]
+    fn a_stalled_publisher_reads_stale_until_its_next_tick() {
+        paused(async {
+            let replay = Replay::new(crate::CollectorConfig::default(), 1);
+            let supervisor = Supervisor::new(RestartPolicies::default());
+            let publisher = publisher(&replay, &supervisor);
+            let mut snapshots = Box::pin(stream(Some(publisher.subscribe())));
+            snapshots.next().await.unwrap();
+            let max_age = Duration::from_secs(1);
+            assert!(matches!(
+                publisher.latest(max_age),
+                LatestSnapshot::Fresh(_)
+            ));
+
+            // The runtime is hogged for 5 s: the reader's clock moves on, the
+            // publisher's tick doesn't get to run
+            replay.clock.advance(Duration::from_secs(5));
+            let stale = Duration::from_secs(5);
+            assert!(matches!(
+                publisher.latest(max_age),
+                LatestSnapshot::Stale { age } if age == stale
+            ));
+
+            // Fresh again once the runtime comes back to the publisher
+            snapshots.next().await.unwrap();
+            assert!(matches!(
+                publisher.latest(max_age),
+                LatestSnapshot::Fresh(_)
+            ));
+        });
+    }
+
+    /// The runtime's paused time, as wall time from `base` plus any steps
+    #[derive(Debug)]
+    struct PausedWallClock {
+        base: SystemTime,
+        start: Instant,
+        stepped_back: std::sync::Mutex<Duration>,
+    }
+
+    impl PausedWallClock {
+        fn new(base: SystemTime) -> Self {
+            Self {
+                base,
+                start: Instant::now(),
+                stepped_back: std::sync::Mutex::new(Duration::ZERO),
+            }
+        }
+
+        fn step_back(&self, by: Duration) {
+            *self.stepped_back.lock().unwrap() += by;
+        }
+    }
+
+    impl Clock for PausedWallClock {
+        fn now(&self) -> std::time::Instant {
+            Instant::now().into_std()
+        }
+
+        fn sleep(&self, _: Duration) {
+            unreachable!("the publisher sleeps on the runtime")
+        }
+
+        fn system_time(&self) -> SystemTime {
+            self.base + self.start.elapsed() - *self.stepped_back.lock().unwrap()
+        }
+    }
+
+    fn aligned(clock: Arc<PausedWallClock>, supervisor: &Supervisor) -> Publisher {
+        let config = crate::CollectorConfig::default().with_clock(clock);
+        let signals = Arc::new(crate::collector::AtomicSignals::new(
+            crate::CpuSlots::new(0..1),
+            &config,
+        ));
+        let reader = Arc::new(IntervalReader::detached(signals, &config));
+        Publisher::start(reader, Duration::from_secs(1), true, supervisor)
+    }
+
+    fn unix_secs(time: Option<SystemTime>) -> f64 {
+        time.unwrap()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64()
+    }
+
+    #[test]
+    fn aligned_snapshots_fall_on_wall_clock_seconds() {
+        paused(async {
+            // A quarter past a second
+            let base = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+            let clock = Arc::new(PausedWallClock::new(base));
+            let supervisor = Supervisor::new(RestartPolicies::default());
+            let publisher = aligned(clock.clone(), &supervisor);
+            let mut snapshots = Box::pin(stream(Some(publisher.subscribe())));
+
+            let start = Instant::now();
+            // The partial second up to :01 isn't published
+            let first = snapshots.next().await.unwrap();
+            assert_eq!(start.elapsed(), Duration::from_millis(1_750));
+            assert_eq!(unix_secs(first.aligned_start), 1_700_000_001.0);
+            assert_eq!(unix_secs(first.aligned_end), 1_700_000_002.0);
+            let second = snapshots.next().await.unwrap();
+            assert_eq!(first.aligned_end, second.aligned_start);
+            assert_eq!(unix_secs(second.aligned_end), 1_700_000_003.0);
+        });
+    }
+
+    #[test]
+    fn a_backward_clock_step_realigns() {
+        paused(async {
+            let base = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+            let clock = Arc::new(PausedWallClock::new(base));
+            let supervisor = Supervisor::new(RestartPolicies::default());
+            let publisher = aligned(clock.clone(), &supervisor);
+            let mut snapshots = Box::pin(stream(Some(publisher.subscribe())));
+            let before = snapshots.next().await.unwrap();
+            assert_eq!(unix_secs(before.aligned_end), 1_700_000_002.0);
+
+            let stepper = clock.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                stepper.step_back(Duration::from_secs(10));
+            });
+            let start = Instant::now();
+            let after = snapshots.next().await.unwrap();
+            // The wakeup 10 s off, then the partial interval after it, aren't
+            // published: no snapshot spans the step
+            assert_eq!(start.elapsed(), Duration::from_secs(3));
+            assert_eq!(unix_secs(after.aligned_start), 1_699_999_994.0);
+            assert_eq!(unix_secs(after.aligned_end), 1_699_999_995.0);
+        });
+    }
+}
@@ -0,0 +1,228 @@
+//How long a signal takes to move after something changed at a known moment:
+//an impairment applied, a rate cut, a config reload. A series holds one value
+//per interval read, stamped with the read's Instant (CongestionSignals::
+//produced_at), so the change has to be stamped on the same monotonic clock.
+//The baseline is the mean before the change, the onset the first read after
+//it that clears the baseline by a factor and an absolute rise, for a few reads
+//in a row so one noisy interval isn't taken for a reaction. A read covers the
+//interval before it, so a latency is resolved to one interval and errs late.
+
+use std::time::{Duration, Instant};
+
+/// When a value counts as a reaction, see [`SignalSeries::reaction`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OnsetThreshold {
+    /// The value has to reach the baseline times this
+    pub factor: f64,
+    /// and the baseline plus this, in the series' unit. Keeps a zero or near
+    /// zero baseline from turning any blip into a reaction
+    pub min_rise: f64,
+    /// Reads in a row that have to clear both, at least 1
+    pub sustain: usize,
+    /// Highest level asked for whatever the baseline, for bounded signals: a
+    /// 0.0-1.0 pressure already at 0.6 can't double
+    pub ceiling: Option<f64>,
+}
+
+impl Default for OnsetThreshold {
+    fn default() -> Self {
+        Self {
+            factor: 2.0,
+            min_rise: 0.0,
+            sustain: 2,
+            ceiling: None,
+        }
+    }
+}
+
+/// A signal's reaction to a change, see [`SignalSeries::reaction`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reaction {
+    /// From the change to the first read of the onset
+    pub latency: Duration,
+    /// Mean of the reads before the change
+    pub baseline: f64,
+    /// The first read of the onset
+    pub value: f64,
+}
+
+/// One signal over time, a value per read, oldest first
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SignalSeries {
+    samples: Vec<(Instant, f64)>,
+}
+
+impl SignalSeries {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a read. Reads out of order are kept where they land; the
+    /// searches go by position
+    pub fn push(&mut self, at: Instant, value: f64) {
+        self.samples.push((at, value));
+    }
+
+    pub fn samples(&self) -> &[(Instant, f64)] {
+        &self.samples
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Mean of the reads before `change`, None without any
+    pub fn baseline(&self, change: Instant) -> Option<f64> {
+        let before: Vec<f64> = self
+            .samples
+            .iter()
+            .filter(|(at, _)| *at < change)
+            .map(|(_, value)| *value)
+            .collect();
+        (!before.is_empty()).then(|| before.iter().sum::<f64>() / before.len() as f64)
+    }
+
+    /// Highest read from `change` on, None without any
+    pub fn peak_after(&self, change: Instant) -> Option<f64> {
+        self.samples
+            .iter()
+            .filter(|(at, _)| *at >= change)
+            .map(|(_, value)| *value)
+            .reduce(f64::max)
+    }
+
+    /// The first run of `threshold.sustain` reads from `change` on that clear
+    /// the baseline by `threshold`. None without a baseline or without such a
+    /// run before the series ends
+    pub fn reaction(&self, change: Instant, threshold: &OnsetThreshold) -> Option<Reaction> {
+        let baseline = self.baseline(change)?;
+        let level = (baseline * threshold.factor).max(baseline + threshold.min_rise);
+        let level = threshold
+            .ceiling
+            .map_or(level, |ceiling| level.min(ceiling));
+        let after: Vec<(Instant, f64)> = self
+            .samples
+            .iter()
+            .copied()
+            .filter(|(at, _)| *at >= change)
+            .collect();
+        after
+            .windows(threshold.sustain.max(1))
+            .find(|run| run.iter().all(|(_, value)| *value >= level))
+            .map(|run| {
+                let (at, value) = run[0];
+                Reaction {
+                    latency: at.duration_since(change),
+                    baseline,
+                    value,
+                }
+            })
+    }
+
+    /// The `quantile` (0.0-1.0, nearest rank) of each read and the up to
+    /// `window - 1` before it: a p95 over the last few intervals rises on the
+    /// first one that's high, where a mean would dilute it
+    pub fn rolling_quantile(&self, window: usize, quantile: f64) -> SignalSeries {
+        let window = window.max(1);
+        let samples = (0..self.samples.len())
+            .map(|i| {
+                let mut recent: Vec<f64> = self.samples[(i + 1).saturating_sub(window)..=i]
+                    .iter()
+                    .map(|(_, value)| *value)
+                    .collect();
+                recent.sort_by(f64::total_cmp);
+                let rank = ((recent.len() - 1) as f64 * quantile.clamp(0.0, 1.0)).round();
+                (self.samples[i].0, recent[rank as usize])
+            })
+            .collect();
+        SignalSeries { samples }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn threshold() -> OnsetThreshold {
+        OnsetThreshold {
+            min_rise: 5.0,
+            ..Default::default()
+        }
+    }
+
+    fn at(t0: Instant, ms: u64) -> Instant {
+        t0 + Duration::from_millis(ms)
+    }
+
+    /// Reads at 100..1000 ms; the change lands at 450, between the 4th and 5th
+    fn series(t0: Instant, values: &[f64]) -> SignalSeries {
+        let mut series = SignalSeries::new();
+        for (i, value) in values.iter().enumerate() {
+            series.push(at(t0, 100 * (i as u64 + 1)), *value);
+        }
+        series
+    }
+
+    fn latency_ms(
+        series: &SignalSeries,
+        change: Instant,
+        threshold: &OnsetThreshold,
+    ) -> Option<u128> {
+        series
+            .reaction(change, threshold)
+            .map(|reaction| reaction.latency.as_millis())
+    }
+
+    #[test]
+    fn a_step_is_found_at_its_first_read() {
+        let t0 = Instant::now();
+        let step = series(
+            t0,
+            &[10.0, 12.0, 8.0, 10.0, 11.0, 30.0, 31.0, 29.0, 30.0, 30.0],
+        );
+        let reaction = step.reaction(at(t0, 450), &threshold()).unwrap();
+        assert_eq!(reaction.latency, Duration::from_millis(150));
+        assert_eq!(reaction.baseline, 10.0);
+        assert_eq!(reaction.value, 30.0);
+        assert_eq!(step.peak_after(at(t0, 450)), Some(31.0));
+        // No reads before the change, no baseline
+        assert!(step.reaction(at(t0, 50), &threshold()).is_none());
+    }
+
+    #[test]
+    fn a_one_read_blip_is_not_an_onset() {
+        let t0 = Instant::now();
+        let blip = series(
+            t0,
+            &[10.0, 10.0, 10.0, 10.0, 40.0, 10.0, 10.0, 25.0, 26.0, 10.0],
+        );
+        assert_eq!(latency_ms(&blip, at(t0, 450), &threshold()), Some(350));
+    }
+
+    #[test]
+    fn a_zero_baseline_needs_the_absolute_rise() {
+        let t0 = Instant::now();
+        let zero = series(t0, &[0.0, 0.0, 0.0, 0.0, 3.0, 3.0, 6.0, 6.0, 6.0, 6.0]);
+        assert_eq!(latency_ms(&zero, at(t0, 450), &threshold()), Some(250));
+    }
+
+    #[test]
+    fn a_ceiling_lets_a_high_pressure_react() {
+        let t0 = Instant::now();
+        let high = series(t0, &[0.6, 0.6, 0.6, 0.6, 0.7, 0.95, 0.97, 0.96, 0.95, 0.95]);
+        let capped = OnsetThreshold {
+            ceiling: Some(0.9),
+            ..threshold()
+        };
+        assert_eq!(latency_ms(&high, at(t0, 450), &capped), Some(150));
+        assert_eq!(latency_ms(&high, at(t0, 450), &threshold()), None);
+    }
+
+    #[test]
+    fn the_rolling_p95_follows_the_windows_highest_value() {
+        let t0 = Instant::now();
+        let p95 = series(t0, &[0.1, 0.9, 0.1, 0.1, 0.1, 0.1, 0.1]).rolling_quantile(3, 0.95);
+        let values: Vec<f64> = p95.samples().iter().map(|(_, v)| *v).collect();
+        assert_eq!(values, [0.1, 0.9, 0.9, 0.9, 0.1, 0.1, 0.1]);
+    }
+}
@@ -0,0 +1,1037 @@
+//Per-CPU perf buffer readers. Each one decodes a batch of samples, adds them up
+//in a plain EventBatch, folds that into the atomics once per batch and hands
+//the events on to the pipeline. Supervised tasks waiting on the buffer's fd
+//for `async` and `smol`, one poll(2) thread per CPU for `blocking`. Either wakes as
+//`CollectorConfig::reader_wakeup` says and drains the buffer, see ReaderWakeup.
+//
+//Read errors (seen after suspend/resume and VM migrations) are retried with
+//backoff, up to `CollectorConfig::reader_max_retries` in a row before the CPU's
+//reader gives up; `health()` reports both. An async reader that gave up or
+//panicked is reopened on its CPU as `RestartPolicies::readers` allows.
+
+use crate::collector::{implausible_softirq, AtomicSignals, EventBatch};
+use crate::perf_ring::{PerfRing, SampleBatch, PER_EVENT_PAGES, WATERMARK_PAGES};
+use crate::pipeline::{self, Pipeline, PipelineCounters, Queue};
+#[cfg(feature = "async-runtime")]
+use crate::runtime::{self, AbortHandle, MissedTicks, Readable, Rt, Runtime, TaskHandle, Ticker};
+#[cfg(feature = "async-runtime")]
+use crate::supervisor::{Component, Supervisor};
+use crate::{
+    schema, traced_only, CollectorConfig, CongestionEvent, ReaderWakeup, WakeupWatermark,
+    EVENT_SOCKET_STATE,
+};
+use aya::maps::perf::Events;
+use aya::maps::{Map, MapData};
+use aya::util::online_cpus;
+use aya::Ebpf;
+use std::fmt::Display;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Room per sample in a reader's batch, only grown for samples past the event
+// size (the perf header's padding fits)
+const SAMPLE_CAPACITY: usize = size_of::<CongestionEvent>() + 8;
+// Between tries after a read error, doubling per consecutive error
+const RETRY_BACKOFF_START: Duration = Duration::from_millis(10);
+const RETRY_BACKOFF_MAX: Duration = Duration::from_secs(1);
+// Kernel timestamps moving this much further than the time spent reading them,
+// either way, are a clock jump rather than scheduling noise
+const CLOCK_JUMP_NS: i128 = 5_000_000_000;
+// How long a blocking reader sleeps in poll() before checking for stop
+#[cfg(feature = "blocking")]
+const POLL_TIMEOUT_MS: i32 = 100;
+// Shortest period a coalescing reader waits for, below which it's a busy loop
+const MIN_WAKEUP_PERIOD: Duration = Duration::from_millis(1);
+
+pub(crate) struct Readers {
+    // Stopped when dropped
+    _kind: ReaderKind,
+    // Shared by the set's readers
+    window: Arc<ReadWindow>,
+}
+
+enum ReaderKind {
+    #[cfg(feature = "async-runtime")]
+    Tasks(Vec<AbortHandle>),
+    #[cfg(feature = "blocking")]
+    Threads {
+        stop: Arc<std::sync::atomic::AtomicBool>,
+        threads: Vec<std::thread::JoinHandle<()>>,
+        // Holds the map whose entries point at our rings
+        _events: PerfMap,
+    },
+}
+
+impl Readers {
+    /// Take the EVENTS map and start one reader per online CPU, of the kind
+    /// matching the pipeline's queue, counting what `window` admits
+    pub(crate) fn spawn(
+        ebpf: &mut Ebpf,
+        signals: &Arc<AtomicSignals>,
+        pipeline: &Pipeline,
+        buffer_pages: usize,
+        window: ReadWindow,
+    ) -> anyhow::Result<Self> {
+        let map = ebpf
+            .take_map("EVENTS")
+            .ok_or_else(|| anyhow::anyhow!("map EVENTS not in object"))?;
+        let map = PerfMap::new(map, pipeline.reader_wakeup, buffer_pages)?;
+
+        // fixed online_cpus() should return Vec<32>
+        let cpus =
+            online_cpus().map_err(|e| anyhow::anyhow!("Failed to get online CPUs: {:?}", e))?;
+
+        log::info!("Starting event collection on {} CPUs", cpus.len());
+        // Fresh readers on every CPU, whichever gave up before
+        pipeline.counters.failed_readers.lock().unwrap().clear();
+        pipeline.counters.reader_wakeups.store(0, Ordering::Relaxed);
+        *pipeline.counters.readers_started.lock().unwrap() = Some(Instant::now());
+
+        let window = Arc::new(window);
+        let kind = match &pipeline.queue {
+            #[cfg(feature = "async-runtime")]
+            Queue::Async { .. } => {
+                let supervisor = pipeline
+                    .supervisor()
+                    .expect("async pipelines are supervised")
+                    .clone();
+                Self::spawn_tasks(map, cpus, signals, pipeline, &window, &supervisor)?
+            }
+            #[cfg(feature = "blocking")]
+            Queue::Blocking { .. } => Self::spawn_threads(map, cpus, signals, pipeline, &window)?,
+        };
+        Ok(Self {
+            _kind: kind,
+            window,
+        })
+    }
+
+    pub(crate) fn window(&self) -> &ReadWindow {
+        &self.window
+    }
+
+    #[cfg(feature = "async-runtime")]
+    fn spawn_tasks(
+        map: PerfMap,
+        cpus: Vec<u32>,
+        signals: &Arc<AtomicSignals>,
+        pipeline: &Pipeline,
+        window: &Arc<ReadWindow>,
+        supervisor: &Supervisor,
+    ) -> anyhow::Result<ReaderKind> {
+        // Restarts open a fresh buffer on the same CPU
+        let map = Arc::new(std::sync::Mutex::new(map));
+
+        let mut readers = Vec::with_capacity(cpus.len());
+        for cpu_id in cpus {
+            // The first buffer is opened here so a failure fails the spawn
+            let mut first = Some(map.lock().unwrap().open(cpu_id)?);
+            let map = map.clone();
+            let signals = signals.clone();
+            let queue = pipeline.queue.clone();
+            let counters = pipeline.counters.clone();
+            let max_retries = pipeline.reader_max_retries;
+            let batch_size = pipeline.reader_batch_size;
+            let wakeup = pipeline.reader_wakeup;
+            let window = window.clone();
+
+            let start = move || {
+                let buf = first.take().map_or_else(
+                    || {
+                        counters.failed_readers.lock().unwrap().remove(&cpu_id);
+                        map.lock().unwrap().open(cpu_id)
+                    },
+                    Ok,
+                );
+                let signals = signals.clone();
+                let queue = queue.clone();
+                let counters = counters.clone();
+                let window = window.clone();
+                async move {
+                    let mut wait = AsyncWait::new(buf?, wakeup)?;
+                    let mut state = ReaderState::new(cpu_id, max_retries, window);
+                    let mut samples = SampleBatch::new(batch_size, SAMPLE_CAPACITY);
+                    let mut batch = EventBatch::new(&signals);
+
+                    loop {
+                        let buf = wait.next().await?;
+                        counters.reader_wakeups.fetch_add(1, Ordering::Relaxed);
+                        let next = drain_or_back_off(
+                            buf,
+                            &mut state,
+                            &mut samples,
+                            &mut batch,
+                            &signals,
+                            &queue,
+                            &counters,
+                        );
+                        match next {
+                            Next::Read => {}
+                            Next::Retry(backoff) => Rt::sleep(backoff).await,
+                            // The last error was logged with the count
+                            Next::GiveUp => anyhow::bail!("gave up on perf reads"),
+                        }
+                    }
+                }
+            };
+            readers.push(supervisor.spawn(Component::Reader { cpu: cpu_id }, start));
+        }
+
+        Ok(ReaderKind::Tasks(readers))
+    }
+
+    #[cfg(feature = "blocking")]
+    fn spawn_threads(
+        mut map: PerfMap,
+        cpus: Vec<u32>,
+        signals: &Arc<AtomicSignals>,
+        pipeline: &Pipeline,
+        window: &Arc<ReadWindow>,
+    ) -> anyhow::Result<ReaderKind> {
+        use std::os::fd::AsRawFd;
+        use std::sync::atomic::AtomicBool;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let wakeup = pipeline.reader_wakeup;
+
+        let mut threads = Vec::with_capacity(cpus.len());
+        for cpu_id in cpus {
+            let mut buf = map.open(cpu_id)?;
+            let signals = signals.clone();
+            let queue = pipeline.queue.clone();
+            let counters = pipeline.counters.clone();
+            let stop = stop.clone();
+            let mut state = ReaderState::new(cpu_id, pipeline.reader_max_retries, window.clone());
+            let batch_size = pipeline.reader_batch_size;
+
+            let thread = std::thread::Builder::new()
+                .name(format!("congestion-cpu{}", cpu_id))
+                .spawn(move || {
+                    let mut samples = SampleBatch::new(batch_size, SAMPLE_CAPACITY);
+                    let mut batch = EventBatch::new(&signals);
+                    let mut pollfd = libc::pollfd {
+                        fd: buf.as_raw_fd(),
+                        events: libc::POLLIN,
+                        revents: 0,
+                    };
+
+                    while !stop.load(Ordering::Relaxed) {
+                        match wakeup {
+                            ReaderWakeup::Timer(period) => {
+                                std::thread::sleep(period.max(MIN_WAKEUP_PERIOD))
+                            }
+                            _ => {
+                                let timeout = poll_timeout_ms(wakeup);
+                                let ready = unsafe { libc::poll(&mut pollfd, 1, timeout) };
+                                if ready < 0 {
+                                    let e = std::io::Error::last_os_error();
+                                    if e.kind() != std::io::ErrorKind::Interrupted {
+                                        match state.failed(&e, &counters) {
+                                            Some(backoff) => std::thread::sleep(backoff),
+                                            None => return,
+                                        }
+                                        continue;
+                                    }
+                                }
+                            }
+                        }
+                        counters.reader_wakeups.fetch_add(1, Ordering::Relaxed);
+
+                        let next = drain_or_back_off(
+                            &mut buf,
+                            &mut state,
+                            &mut samples,
+                            &mut batch,
+                            &signals,
+                            &queue,
+                            &counters,
+                        );
+                        match next {
+                            Next::Read => {}
+                            Next::Retry(backoff) => std::thread::sleep(backoff),
+                            Next::GiveUp => return,
+                        }
+                    }
+                })?;
+            threads.push(thread);
+        }
+
+        Ok(ReaderKind::Threads {
+            stop,
+            threads,
+            _events: map,
+        })
+    }
+}
+
+impl Drop for ReaderKind {
+    fn drop(&mut self) {
+        match self {
+            #[cfg(feature = "async-runtime")]
+            Self::Tasks(tasks) => {
+                for task in tasks.drain(..) {
+                    task.abort();
+                }
+            }
+            #[cfg(feature = "blocking")]
+            Self::Threads { stop, threads, .. } => {
+                stop.store(true, Ordering::Relaxed);
+                for thread in threads.drain(..) {
+                    let _ = thread.join();
+                }
+            }
+        }
+    }
+}
+
+/// The kernel timestamps a set of readers counts. Both objects' programs see
+/// the same calls while a reload has them attached together, so the old set
+/// is closed at a cutover and the new set opened there: each call is counted
+/// by exactly one of them
+pub(crate) struct ReadWindow {
+    from_ns: AtomicU64,
+    until_ns: AtomicU64,
+}
+
+impl ReadWindow {
+    /// Everything, for readers started with collection
+    pub(crate) fn open() -> Self {
+        Self {
+            from_ns: AtomicU64::new(0),
+            until_ns: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    /// Nothing until `Readers::open_at`, for readers replacing others
+    pub(crate) fn pending() -> Self {
+        Self {
+            from_ns: AtomicU64::new(u64::MAX),
+            until_ns: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    /// Count events stamped at or after `cutover_ns`
+    pub(crate) fn open_at(&self, cutover_ns: u64) {
+        self.from_ns.store(cutover_ns, Ordering::Relaxed);
+    }
+
+    /// Stop counting events stamped at or after `cutover_ns`. The readers keep
+    /// draining their buffers for what came before
+    pub(crate) fn close_at(&self, cutover_ns: u64) {
+        self.until_ns.store(cutover_ns, Ordering::Relaxed);
+    }
+
+    pub(crate) fn admits(&self, timestamp_ns: u64) -> bool {
+        timestamp_ns >= self.from_ns.load(Ordering::Relaxed)
+            && timestamp_ns < self.until_ns.load(Ordering::Relaxed)
+    }
+}
+
+/// Data pages per CPU buffer `config` asks for, before any cut to fit
+/// RLIMIT_MEMLOCK. Rounded down to a power of two, which perf needs
+pub(crate) fn wanted_buffer_pages(config: &CollectorConfig) -> usize {
+    let pages = config
+        .perf_buffer_pages
+        .unwrap_or(match config.reader_wakeup {
+            ReaderWakeup::Watermark { .. } => WATERMARK_PAGES,
+            _ => PER_EVENT_PAGES,
+        })
+        .max(1);
+    1 << pages.ilog2()
+}
+
+/// Where each CPU's buffer is opened, again for a restarted reader
+pub(crate) struct PerfMap {
+    map: MapData,
+    watermark: WakeupWatermark,
+    pages: usize,
+}
+
+impl PerfMap {
+    fn new(map: Map, wakeup: ReaderWakeup, pages: usize) -> anyhow::Result<Self> {
+        let Map::PerfEventArray(map) = map else {
+            anyhow::bail!("map EVENTS is not a perf event array");
+        };
+        let watermark = match wakeup {
+            ReaderWakeup::Watermark { watermark, .. } => watermark,
+            // What aya opens: the kernel signals every sample, and a timer
+            // reader just doesn't wait for it
+            _ => WakeupWatermark::Events(1),
+        };
+        Ok(Self {
+            map,
+            watermark,
+            pages,
+        })
+    }
+
+    fn open(&mut self, cpu_id: u32) -> anyhow::Result<PerfRing> {
+        Ok(PerfRing::open(
+            &self.map,
+            cpu_id,
+            self.watermark,
+            self.pages,
+        )?)
+    }
+}
+
+/// An async reader's wait for something to read
+#[cfg(feature = "async-runtime")]
+enum AsyncWait {
+    /// For the kernel's signal, or `timeout` without one
+    Signal {
+        fd: <Rt as Runtime>::Readable<PerfRing>,
+        timeout: Option<Duration>,
+    },
+    /// For the next tick. The fd isn't registered with the runtime, so the
+    /// kernel's signals wake nothing
+    Timer {
+        buf: PerfRing,
+        tick: <Rt as Runtime>::Ticker,
+    },
+}
+
+#[cfg(feature = "async-runtime")]
+impl AsyncWait {
+    fn new(buf: PerfRing, wakeup: ReaderWakeup) -> std::io::Result<Self> {
+        Ok(match wakeup {
+            ReaderWakeup::PerEvent => Self::Signal {
+                fd: Rt::readable(buf)?,
+                timeout: None,
+            },
+            ReaderWakeup::Watermark { max_delay, .. } => Self::Signal {
+                fd: Rt::readable(buf)?,
+                timeout: Some(max_delay.max(MIN_WAKEUP_PERIOD)),
+            },
+            ReaderWakeup::Timer(period) => Self::Timer {
+                buf,
+                tick: Rt::interval(period.max(MIN_WAKEUP_PERIOD), MissedTicks::Delay),
+            },
+        })
+    }
+
+    async fn next(&mut self) -> std::io::Result<&mut PerfRing> {
+        match self {
+            Self::Signal { fd, timeout } => {
+                // Used up before reading: a sample landing meanwhile signals again
+                match timeout {
+                    Some(timeout) => {
+                        if let Some(ready) = runtime::timeout(*timeout, fd.readable()).await {
+                            ready?;
+                        }
+                    }
+                    None => fd.readable().await?,
+                }
+                Ok(fd.get_mut())
+            }
+            Self::Timer { buf, tick } => {
+                tick.tick().await;
+                Ok(buf)
+            }
+        }
+    }
+}
+
+/// How long a blocking reader waits in poll() for the kernel's signal
+#[cfg(feature = "blocking")]
+fn poll_timeout_ms(wakeup: ReaderWakeup) -> i32 {
+    match wakeup {
+        ReaderWakeup::Watermark { max_delay, .. } => {
+            let max_delay = max_delay.max(MIN_WAKEUP_PERIOD).as_millis();
+            max_delay.min(POLL_TIMEOUT_MS as u128) as i32
+        }
+        _ => POLL_TIMEOUT_MS,
+    }
+}
+
+/// What a reader drains: a CPU's perf ring, or one the tests make fail
+trait SampleSource {
+    /// Samples waiting
+    fn readable(&self) -> bool;
+    fn read_batch(&mut self, samples: &mut SampleBatch) -> std::io::Result<Events>;
+}
+
+impl SampleSource for PerfRing {
+    fn readable(&self) -> bool {
+        PerfRing::readable(self)
+    }
+
+    fn read_batch(&mut self, samples: &mut SampleBatch) -> std::io::Result<Events> {
+        PerfRing::read_batch(self, samples)
+    }
+}
+
+/// What a reader does after a wakeup's drain
+#[derive(Debug, PartialEq)]
+enum Next {
+    Read,
+    /// Wait this long after a read error, then read again
+    Retry(Duration),
+    /// Past `max_retries` errors in a row
+    GiveUp,
+}
+
+/// Read `buf` until it's empty, or until it fails
+fn drain_or_back_off(
+    buf: &mut impl SampleSource,
+    state: &mut ReaderState,
+    samples: &mut SampleBatch,
+    batch: &mut EventBatch,
+    signals: &AtomicSignals,
+    queue: &Queue,
+    counters: &PipelineCounters,
+) -> Next {
+    while buf.readable() {
+        let events = match buf.read_batch(samples) {
+            Ok(events) => events,
+            Err(e) => {
+                return match state.failed(&e, counters) {
+                    Some(backoff) => Next::Retry(backoff),
+                    None => Next::GiveUp,
+                }
+            }
+        };
+        state.succeeded(counters);
+        handle_samples(state, samples, events, batch, signals, queue, counters);
+    }
+    Next::Read
+}
+
+/// What one CPU's reader keeps between reads: its error streak, the kernel
+/// timestamp and read time of its last event, and its set's window. A CPU's
+/// events are in timestamp order, and the kernel clock can't advance much more
+/// between two of them than userspace waited between reading them
+struct ReaderState {
+    cpu_id: u32,
+    max_retries: u32,
+    errors: u32,
+    last_event: Option<(u64, Instant)>,
+    window: Arc<ReadWindow>,
+}
+
+impl ReaderState {
+    fn new(cpu_id: u32, max_retries: u32, window: Arc<ReadWindow>) -> Self {
+        Self {
+            cpu_id,
+            max_retries,
+            errors: 0,
+            last_event: None,
+            window,
+        }
+    }
+
+    /// How long to wait before reading again, None once the reader should give up
+    fn failed(&mut self, e: &impl Display, counters: &PipelineCounters) -> Option<Duration> {
+        self.errors += 1;
+        if self.errors > self.max_retries {
+            log::error!(
+                "perf reader on CPU {} giving up after {} errors in a row: {}",
+                self.cpu_id,
+                self.errors,
+                e
+            );
+            counters.failed_readers.lock().unwrap().insert(self.cpu_id);
+            return None;
+        }
+        let backoff = RETRY_BACKOFF_START
+            .saturating_mul(1 << (self.errors - 1).min(16))
+            .min(RETRY_BACKOFF_MAX);
+        log::warn!(
+            "Error reading events from CPU {} ({}/{}), retrying in {:?}: {}",
+            self.cpu_id,
+            self.errors,
+            self.max_retries,
+            backoff,
+            e
+        );
+        Some(backoff)
+    }
+
+    fn succeeded(&mut self, counters: &PipelineCounters) {
+        if self.errors == 0 {
+            return;
+        }
+        log::info!(
+            "perf reader on CPU {} recovered after {} errors",
+            self.cpu_id,
+            self.errors
+        );
+        self.errors = 0;
+        *counters
+            .reader_restarts
+            .lock()
+            .unwrap()
+            .entry(self.cpu_id)
+            .or_default() += 1;
+    }
+
+    /// The jump in ns when this event's timestamp is implausible next to the last one
+    fn clock_jump(&mut self, timestamp_ns: u64) -> Option<i128> {
+        let now = Instant::now();
+        let (last_ns, last_at) = self.last_event.replace((timestamp_ns, now))?;
+        let kernel = timestamp_ns as i128 - last_ns as i128;
+        let waited = now.duration_since(last_at).as_nanos() as i128;
+        (kernel < -CLOCK_JUMP_NS || kernel > waited + CLOCK_JUMP_NS).then_some(kernel)
+    }
+}
+
+/// Decode one batch of samples from a CPU's perf buffer
+fn handle_samples(
+    state: &mut ReaderState,
+    samples: &SampleBatch,
+    events: Events,
+    batch: &mut EventBatch,
+    signals: &AtomicSignals,
+    queue: &Queue,
+    counters: &PipelineCounters,
+) {
+    let cpu_id = state.cpu_id;
+    counters
+        .events_read
+        .fetch_add(events.read as u64, Ordering::Relaxed);
+    if events.lost > 0 {
+        counters
+            .perf_lost
+            .fetch_add(events.lost as u64, Ordering::Relaxed);
+        log::warn!("Lost {} perf events on CPU {}", events.lost, cpu_id);
+    }
+
+    for buf in samples.iter() {
+        if buf.len() < size_of::<CongestionEvent>() {
+            log::warn!(
+                "Short perf sample on CPU {}: {} bytes (expected at least {})",
+                cpu_id,
+                buf.len(),
+                size_of::<CongestionEvent>()
+            );
+            continue;
+        }
+
+        let event = unsafe {
+            // Perf buffers don't guarantee alignment; copy unaligned.
+            std::ptr::read_unaligned(buf.as_ptr() as *const CongestionEvent)
+        };
+        // The other set of readers around a reload has it
+        if !state.window.admits(event.timestamp_ns) {
+            continue;
+        }
+
+        if !schema::is_known_event(event.event_type) {
+            if signals.extension_types.contains(event.event_type) {
+                // For its handler on the processing side, nothing else here
+                // reads an extension's payload
+                counters.extension_events.fetch_add(1, Ordering::Relaxed);
+                batch.add(&event);
+                pipeline::enqueue(queue, counters, event);
+            } else {
+                // A newer, forward-compatible object, or an extension type
+                // nobody registered for
+                counters.unknown_events.fetch_add(1, Ordering::Relaxed);
+            }
+            continue;
+        }
+        // Before anything counts it
+        #[cfg(feature = "async-runtime")]
+        if signals
+            .heartbeat
+            .as_ref()
+            .is_some_and(|heartbeat| heartbeat.observe(&event))
+        {
+            continue;
+        }
+
+        if let Some(jump_ns) = state.clock_jump(event.timestamp_ns) {
+            log::warn!(
+                "kernel clock on CPU {} jumped by {} ms, resetting timestamp state",
+                cpu_id,
+                jump_ns / 1_000_000
+            );
+            counters.clock_jumps.fetch_add(1, Ordering::Relaxed);
+            // What's batched so far goes in under the old clock
+            signals.fold(batch);
+            signals.reset_clock_state(event.timestamp_ns);
+        }
+
+        if aggregate(&event, batch, signals) {
+            pipeline::enqueue(queue, counters, event);
+        }
+    }
+    signals.fold(batch);
+}
+
+/// The readers' share of a decoded event of a known type: counted into
+/// `batch`, and true when it goes on to the processing side
+pub(crate) fn aggregate(
+    event: &CongestionEvent,
+    batch: &mut EventBatch,
+    signals: &AtomicSignals,
+) -> bool {
+    if event.event_type == EVENT_SOCKET_STATE
+        && signals.socket_state_disabled.load(Ordering::Relaxed)
+    {
+        return false;
+    }
+    batch.count_sampled(event);
+    if let Some(filter) = &signals.netns_filter {
+        if !filter.allows(event) {
+            return false;
+        }
+    }
+
+    let compared_only = batch.add_compared(event);
+    // A traced socket's events reach the trace and subscribers even when
+    // no sampler took them, but only sampled ones count
+    if traced_only(event) {
+        return true;
+    }
+    if compared_only {
+        return false;
+    }
+    // Cheap aggregation inline, the rest on the processing side
+    batch.add(event);
+    !implausible_softirq(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, Replay};
+
+    #[test]
+    fn a_cutover_splits_timestamps_between_the_windows() {
+        let old = ReadWindow::open();
+        let new = ReadWindow::pending();
+        assert!(old.admits(0) && old.admits(u64::MAX - 1));
+        assert!(!new.admits(0) && !new.admits(u64::MAX - 1));
+
+        new.open_at(1_000);
+        old.close_at(1_000);
+        for timestamp_ns in [0, 999, 1_000, 1_001, u64::MAX - 1] {
+            assert!(old.admits(timestamp_ns) != new.admits(timestamp_ns));
+        }
+        assert!(old.admits(999) && new.admits(1_000));
+    }
+
+    fn samples(events: &[CongestionEvent]) -> SampleBatch {
+        let mut samples = SampleBatch::new(events.len(), SAMPLE_CAPACITY);
+        for event in events {
+            // SAFETY: reading the event's own bytes
+            let bytes = unsafe {
+                std::slice::from_raw_parts(
+                    event as *const CongestionEvent as *const u8,
+                    size_of::<CongestionEvent>(),
+                )
+            };
+            samples.push(bytes);
+        }
+        samples
+    }
+
+    #[test]
+    fn unknown_event_types_are_counted_and_skipped() {
+        let replay = Replay::new(CollectorConfig::default(), 1);
+        let (queue, _rx) = fixtures::stalled_queues(16).remove(0);
+        let counters = PipelineCounters::default();
+        let mut newer = fixtures::udp_send(2, 0, 7, 1_000);
+        // A type from a newer, forward-compatible object
+        newer.event_type = schema::MAX_EVENT_TYPES as u32 - 1;
+        let samples = samples(&[
+            fixtures::udp_send(1, 0, 7, 1_000),
+            newer,
+            fixtures::udp_send(3, 0, 7, 1_000),
+        ]);
+
+        let mut state = ReaderState::new(0, 3, Arc::new(ReadWindow::open()));
+        let mut batch = EventBatch::new(&replay.signals);
+        let events = Events { read: 3, lost: 0 };
+        handle_samples(
+            &mut state,
+            &samples,
+            events,
+            &mut batch,
+            &replay.signals,
+            &queue,
+            &counters,
+        );
+
+        assert_eq!(counters.events_read.load(Ordering::Relaxed), 3);
+        assert_eq!(counters.unknown_events.load(Ordering::Relaxed), 1);
+        let signals = replay.read(Duration::from_secs(1));
+        assert_eq!(signals.send_bytes, 2_000);
+        assert_eq!(signals.observed.events_received, 2);
+    }
+
+    fn comparable(
+        (mut signals, per_cpu): (crate::CongestionSignals, crate::PerCpuSignals),
+    ) -> (String, String) {
+        signals.produced_at = None;
+        (format!("{signals:?}"), format!("{per_cpu:?}"))
+    }
+
+    #[test]
+    fn the_batch_walk_reads_what_per_event_folds_do() {
+        const CPUS: u32 = 4;
+        let workload = fixtures::mixed_workload(20_000, CPUS);
+
+        // Per CPU, one batch buffer walked and folded once, the way the
+        // readers take a wakeup's samples
+        let walked = Replay::new(CollectorConfig::default(), CPUS);
+        let (queue, rx) = fixtures::stalled_queues(workload.len()).remove(0);
+        let counters = PipelineCounters::default();
+        for cpu_id in 0..CPUS {
+            let events: Vec<_> = workload
+                .iter()
+                .filter(|event| event.cpu_id == cpu_id)
+                .copied()
+                .collect();
+            let mut state = ReaderState::new(cpu_id, CPUS, Arc::new(ReadWindow::open()));
+            let mut batch = EventBatch::new(&walked.signals);
+            handle_samples(
+                &mut state,
+                &samples(&events),
+                Events {
+                    read: events.len(),
+                    lost: 0,
+                },
+                &mut batch,
+                &walked.signals,
+                &queue,
+                &counters,
+            );
+        }
+        // Back in timestamp order across the CPUs, as the reorderer has them
+        let mut queued = fixtures::queued(&rx);
+        queued.sort_by_key(|event| event.timestamp_ns);
+        for event in &queued {
+            pipeline::process_slow(&walked.signals, event);
+        }
+
+        // Every event folded on its own
+        let folded = Replay::new(CollectorConfig::default(), CPUS);
+        for event in &workload {
+            folded.feed([event]);
+        }
+
+        assert_eq!(
+            counters.events_read.load(Ordering::Relaxed),
+            workload.len() as u64
+        );
+        let elapsed = Duration::from_secs(1);
+        let walked = comparable(walked.read_per_cpu(elapsed));
+        let folded = comparable(folded.read_per_cpu(elapsed));
+        assert_eq!(walked.0, folded.0);
+        assert_eq!(walked.1, folded.1);
+    }
+
+    /// A ring whose next `failures` reads fail, then holding `events`
+    struct FailingSource {
+        failures: u32,
+        events: Vec<CongestionEvent>,
+    }
+
+    impl SampleSource for FailingSource {
+        fn readable(&self) -> bool {
+            self.failures > 0 || !self.events.is_empty()
+        }
+
+        fn read_batch(&mut self, batch: &mut SampleBatch) -> std::io::Result<Events> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(std::io::Error::other("perf buffer unmapped"));
+            }
+            let events = std::mem::take(&mut self.events);
+            *batch = samples(&events);
+            Ok(Events {
+                read: events.len(),
+                lost: 0,
+            })
+        }
+    }
+
+    /// Wakeups of a reader with `max_retries` on `source` until it reads on
+    /// or gives up, and what each one said
+    fn wake_until_settled(
+        replay: &Replay,
+        source: &mut FailingSource,
+        max_retries: u32,
+        counters: &PipelineCounters,
+    ) -> Vec<Next> {
+        let (queue, _rx) = fixtures::stalled_queues(16).remove(0);
+        let mut state = ReaderState::new(0, max_retries, Arc::new(ReadWindow::open()));
+        let mut samples = SampleBatch::new(16, SAMPLE_CAPACITY);
+        let mut batch = EventBatch::new(&replay.signals);
+        let mut wakeups = Vec::new();
+        loop {
+            let next = drain_or_back_off(
+                source,
+                &mut state,
+                &mut samples,
+                &mut batch,
+                &replay.signals,
+                &queue,
+                counters,
+            );
+            let settled = !matches!(next, Next::Retry(_));
+            wakeups.push(next);
+            if settled {
+                return wakeups;
+            }
+        }
+    }
+
+    #[test]
+    fn read_errors_back_off_until_the_ring_recovers() {
+        let replay = Replay::new(CollectorConfig::default(), 1);
+        let counters = PipelineCounters::default();
+        let mut source = FailingSource {
+            failures: 3,
+            events: vec![
+                fixtures::udp_send(1, 0, 7, 1_000),
+                fixtures::udp_send(2, 0, 7, 1_000),
+            ],
+        };
+
+        let wakeups = wake_until_settled(&replay, &mut source, 3, &counters);
+        let ms = Duration::from_millis;
+        assert_eq!(
+            wakeups,
+            [
+                Next::Retry(ms(10)),
+                Next::Retry(ms(20)),
+                Next::Retry(ms(40)),
+                Next::Read
+            ]
+        );
+        assert_eq!(counters.reader_restarts.lock().unwrap()[&0], 1);
+        assert!(counters.failed_readers.lock().unwrap().is_empty());
+        assert_eq!(replay.read(Duration::from_secs(1)).send_bytes, 2_000);
+    }
+
+    #[test]
+    fn a_reader_gives_up_past_its_retries() {
+        let replay = Replay::new(CollectorConfig::default(), 1);
+        let counters = PipelineCounters::default();
+        let mut source = FailingSource {
+            failures: u32::MAX,
+            events: Vec::new(),
+        };
+
+        let wakeups = wake_until_settled(&replay, &mut source, 2, &counters);
+        assert_eq!(wakeups.len(), 3);
+        assert_eq!(wakeups.last(), Some(&Next::GiveUp));
+        assert!(counters.failed_readers.lock().unwrap().contains(&0));
+        assert!(counters.reader_restarts.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_clock_jump_resets_the_timestamp_state_and_keeps_the_counts() {
+        let replay = Replay::new(CollectorConfig::default(), 1);
+        let (queue, _rx) = fixtures::stalled_queues(16).remove(0);
+        let counters = PipelineCounters::default();
+        let mut state = ReaderState::new(0, 3, Arc::new(ReadWindow::open()));
+        let mut batch = EventBatch::new(&replay.signals);
+        let second = 1_000_000_000;
+        // Forward a minute between two reads microseconds apart, then back
+        let events = [
+            fixtures::udp_send(10 * second, 0, 7, 1_000),
+            fixtures::udp_send(70 * second, 0, 7, 1_000),
+            fixtures::udp_send(70 * second + 1, 0, 7, 1_000),
+            fixtures::udp_send(second, 0, 7, 1_000),
+        ];
+        handle_samples(
+            &mut state,
+            &samples(&events),
+            Events { read: 4, lost: 0 },
+            &mut batch,
+            &replay.signals,
+            &queue,
+            &counters,
+        );
+
+        assert_eq!(counters.clock_jumps.load(Ordering::Relaxed), 2);
+        assert_eq!(replay.interval.last_seen()[&crate::EVENT_UDP_SEND], second);
+        assert_eq!(replay.read(Duration::from_secs(1)).send_bytes, 4_000);
+    }
+
+    /// A ring holding `events`, read `batch_size` samples at a time into the
+    /// reader's buffer
+    struct ReplayedRing<'a> {
+        events: &'a [CongestionEvent],
+        batch_size: usize,
+    }
+
+    impl SampleSource for ReplayedRing<'_> {
+        fn readable(&self) -> bool {
+            !self.events.is_empty()
+        }
+
+        fn read_batch(&mut self, batch: &mut SampleBatch) -> std::io::Result<Events> {
+            let (read, rest) = self.events.split_at(self.batch_size.min(self.events.len()));
+            self.events = rest;
+            batch.clear();
+            for event in read {
+                // SAFETY: reading the event's own bytes
+                batch.push(unsafe {
+                    std::slice::from_raw_parts(
+                        event as *const CongestionEvent as *const u8,
+                        size_of::<CongestionEvent>(),
+                    )
+                });
+            }
+            Ok(Events {
+                read: read.len(),
+                lost: 0,
+            })
+        }
+    }
+
+    #[test]
+    fn a_warmed_up_reader_allocates_nothing_over_a_million_events() {
+        const WARM_UP: usize = 50_000;
+        const EVENTS: usize = 1_000_000;
+        let config = CollectorConfig::default();
+        let workload = fixtures::mixed_workload(WARM_UP + EVENTS, 1);
+        let (warm_up, steady) = workload.split_at(WARM_UP);
+
+        for (queue, rx) in fixtures::stalled_queues(4 * config.reader_batch_size) {
+            let replay = Replay::new(config.clone(), 1);
+            let counters = PipelineCounters::default();
+            let mut state = ReaderState::new(0, 3, Arc::new(ReadWindow::open()));
+            let mut samples = SampleBatch::new(config.reader_batch_size, SAMPLE_CAPACITY);
+            let mut batch = EventBatch::new(&replay.signals);
+            let mut read = |events: &[CongestionEvent]| {
+                for wakeup in events.chunks(4 * config.reader_batch_size) {
+                    let mut ring = ReplayedRing {
+                        events: wakeup,
+                        batch_size: config.reader_batch_size,
+                    };
+                    let next = drain_or_back_off(
+                        &mut ring,
+                        &mut state,
+                        &mut samples,
+                        &mut batch,
+                        &replay.signals,
+                        &queue,
+                        &counters,
+                    );
+                    assert_eq!(next, Next::Read);
+                    // The pipeline's side, through the slow path
+                    for event in rx.try_iter() {
+                        pipeline::process_slow(&replay.signals, &event);
+                    }
+                }
+            };
+
+            read(warm_up);
+            let ((), allocations) = fixtures::allocations(|| read(steady));
+            assert_eq!(allocations, 0);
+            assert_eq!(
+                counters.events_read.load(Ordering::Relaxed),
+                workload.len() as u64
+            );
+            assert_eq!(counters.queue_dropped.load(Ordering::Relaxed), 0);
+        }
+    }
+}
@@ -0,0 +1,389 @@
+//Event-level recordings: the raw CongestionEvents exactly as the readers decoded
+//them, so a capture can be inspected or converted offline.
+//
+//Layout: 8-byte magic, u32 schema version, u32 record size, then records back to
+//back, each the repr(C) bytes of one CongestionEvent. Native endian, same as
+//the perf buffers they came from.
+//
+//Records are written field by field from the variant the event type names, with
+//padding and the rest of the union zeroed: an event built from one variant's
+//struct leaves the other bytes uninitialized, so its memory can't be copied as
+//is. Extension types carry `EventData::raw` whole, an unknown type only the
+//header.
+
+use crate::extensions::{ExtensionEvent, EXTENSION_EVENT_TYPES};
+use crate::{
+    CongestionEvent, NapiPollData, QdiscData, RcvSocketData, RxSqueezeData, SendMsgData,
+    SocketData, SocketLifecycleData, SoftirqData, TcpStateData, EVENT_NAPI_POLL,
+    EVENT_NET_DEV_QUEUE, EVENT_QDISC_DROP, EVENT_RX_TIME_SQUEEZE, EVENT_SOCKET_LIFECYCLE,
+    EVENT_SOCKET_RCV_STATE, EVENT_SOCKET_STATE, EVENT_SOFTIRQ_ENTER, EVENT_SOFTIRQ_EXIT,
+    EVENT_TCP_CWR, EVENT_TCP_RECOVERY, EVENT_TCP_RTO, EVENT_TCP_SEND, EVENT_TCP_STATE,
+    EVENT_UDP_RCV_CE, EVENT_UDP_RCV_DROP, EVENT_UDP_SEND, SCHEMA_VERSION,
+};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem::{offset_of, size_of};
+use std::path::Path;
+
+const RECORDING_MAGIC: [u8; 8] = *b"CSIGREC\0";
+const HEADER_LEN: usize = 16;
+const RECORD_SIZE: usize = size_of::<CongestionEvent>();
+const DATA: usize = offset_of!(CongestionEvent, data);
+
+// Each listed field of `$value`, a `$ty` at `$at` in the record
+macro_rules! put_fields {
+    ($record:ident, $at:expr, $ty:ty, $value:expr, $($field:ident),+) => {{
+        let value = $value;
+        $(put(&mut $record, $at + offset_of!($ty, $field), &value.$field.to_ne_bytes());)+
+    }};
+}
+
+fn put(record: &mut [u8; RECORD_SIZE], at: usize, bytes: &[u8]) {
+    record[at..at + bytes.len()].copy_from_slice(bytes);
+}
+
+/// The record of `event`, see the module docs
+pub(crate) fn encode(event: &CongestionEvent) -> [u8; RECORD_SIZE] {
+    let mut record = [0u8; RECORD_SIZE];
+    put_fields!(
+        record,
+        0,
+        CongestionEvent,
+        event,
+        timestamp_ns,
+        event_type,
+        cpu_id
+    );
+    // SAFETY: each arm reads the variant its event type carries
+    unsafe {
+        match event.event_type {
+            EVENT_UDP_SEND | EVENT_TCP_SEND => put_fields!(
+                record,
+                DATA,
+                SendMsgData,
+                event.data.sendmsg,
+                bytes,
+                is_tcp,
+                loopback,
+                socket_id,
+                netns,
+                dscp,
+                priority,
+                samplers
+            ),
+            EVENT_QDISC_DROP | EVENT_NET_DEV_QUEUE => put_fields!(
+                record,
+                DATA,
+                QdiscData,
+                event.data.qdisc,
+                dropped,
+                backlog_bytes,
+                backlog_packets,
+                reason
+            ),
+            EVENT_SOCKET_STATE => put_fields!(
+                record,
+                DATA,
+                SocketData,
+                event.data.socket,
+                wmem_queued,
+                sndbuf,
+                socket_id,
+                protocol,
+                netns,
+                dscp,
+                priority,
+                pacing_rate,
+                max_pacing_rate,
+                samplers
+            ),
+            EVENT_SOFTIRQ_ENTER | EVENT_SOFTIRQ_EXIT => put_fields!(
+                record,
+                DATA,
+                SoftirqData,
+                event.data.softirq,
+                vec_nr,
+                duration_ns
+            ),
+            EVENT_UDP_RCV_DROP | EVENT_SOCKET_RCV_STATE | EVENT_UDP_RCV_CE => put_fields!(
+                record,
+                DATA,
+                RcvSocketData,
+                event.data.rcv,
+                rmem_alloc,
+                rcvbuf,
+                socket_id,
+                samplers
+            ),
+            EVENT_TCP_STATE | EVENT_TCP_CWR | EVENT_TCP_RTO | EVENT_TCP_RECOVERY => put_fields!(
+                record,
+                DATA,
+                TcpStateData,
+                event.data.tcp,
+                snd_cwnd,
+                snd_ssthresh,
+                pacing_rate,
+                socket_id,
+                samplers
+            ),
+            EVENT_SOCKET_LIFECYCLE => put_fields!(
+                record,
+                DATA,
+                SocketLifecycleData,
+                event.data.lifecycle,
+                socket_id,
+                oldstate,
+                newstate
+            ),
+            EVENT_RX_TIME_SQUEEZE => put_fields!(
+                record,
+                DATA,
+                RxSqueezeData,
+                event.data.rx_squeeze,
+                elapsed_ns,
+                packets,
+                budget_exhausted
+            ),
+            EVENT_NAPI_POLL => {
+                let napi = event.data.napi;
+                put_fields!(record, DATA, NapiPollData, napi, work, budget);
+                put(
+                    &mut record,
+                    DATA + offset_of!(NapiPollData, dev_name),
+                    &napi.dev_name,
+                );
+            }
+            t if EXTENSION_EVENT_TYPES.contains(&t) => put(&mut record, DATA, &event.data.raw),
+            _ => {}
+        }
+    }
+    record
+}
+
+/// The event a record holds, the inverse of `encode` for every byte it wrote
+pub(crate) fn decode(record: &[u8; RECORD_SIZE]) -> CongestionEvent {
+    // SAFETY: every byte of the record is initialized, and any bytes are a
+    // valid CongestionEvent
+    unsafe { std::ptr::read_unaligned(record.as_ptr() as *const CongestionEvent) }
+}
+
+pub struct RecordingWriter<W: Write> {
+    inner: W,
+}
+
+impl RecordingWriter<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> RecordingWriter<W> {
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        let mut header = [0u8; HEADER_LEN];
+        header[..8].copy_from_slice(&RECORDING_MAGIC);
+        header[8..12].copy_from_slice(&SCHEMA_VERSION.to_ne_bytes());
+        header[12..].copy_from_slice(&(size_of::<CongestionEvent>() as u32).to_ne_bytes());
+        inner.write_all(&header)?;
+        Ok(Self { inner })
+    }
+
+    pub fn write_event(&mut self, event: &CongestionEvent) -> io::Result<()> {
+        self.inner.write_all(&encode(event))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Iterates the events of a recording. Fails up front (InvalidData) on a file
+/// that isn't a recording or was written with a different schema
+pub struct RecordingReader<R: Read> {
+    inner: R,
+}
+
+impl RecordingReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> RecordingReader<R> {
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut header = [0u8; HEADER_LEN];
+        inner.read_exact(&mut header)?;
+        if header[..8] != RECORDING_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a congestion signals recording",
+            ));
+        }
+
+        let version = u32::from_ne_bytes(header[8..12].try_into().unwrap());
+        let record_size = u32::from_ne_bytes(header[12..].try_into().unwrap());
+        if version != SCHEMA_VERSION || record_size as usize != size_of::<CongestionEvent>() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "recording schema {} ({}-byte records), expected {} ({}-byte records)",
+                    version,
+                    record_size,
+                    SCHEMA_VERSION,
+                    size_of::<CongestionEvent>()
+                ),
+            ));
+        }
+
+        Ok(Self { inner })
+    }
+}
+
+impl<R: Read> RecordingReader<R> {
+    /// The recording's events as extension handlers take them, see
+    /// `ExtensionRegistry::record`
+    pub fn extension_events(self) -> impl Iterator<Item = io::Result<ExtensionEvent>> {
+        self.map(|event| event.map(|event| ExtensionEvent::new(&event)))
+    }
+}
+
+impl<R: Read> Iterator for RecordingReader<R> {
+    type Item = io::Result<CongestionEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buf = [0u8; RECORD_SIZE];
+        // Clean EOF between records ends the iteration, a partial record is an error
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.inner.read(&mut buf[filled..]) {
+                Ok(0) if filled == 0 => return None,
+                Ok(0) => return Some(Err(io::ErrorKind::UnexpectedEof.into())),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        Some(Ok(decode(&buf)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extensions::extension_event;
+    use crate::fixtures;
+    use crate::{SoftirqData, EXTENSION_PAYLOAD_SIZE, TCP_ESTABLISHED, TCP_SYN_SENT};
+
+    fn every_variant() -> Vec<CongestionEvent> {
+        vec![
+            fixtures::udp_send(1, 0, 7, 1200),
+            fixtures::tcp_send(2, 1, 8, 64_000),
+            fixtures::qdisc_drop(3, 2, 5, 40_000),
+            fixtures::socket_state(4, 3, 7, 1_000, 212_992),
+            fixtures::softirq_enter(5, 0, 3),
+            fixtures::softirq_exit(6, 0, 3, 150_000),
+            fixtures::rcv_state(7, 1, 9, 4_096, 212_992),
+            fixtures::tcp_state(8, 2, 8, 10, 7),
+            fixtures::lifecycle(9, 8, TCP_SYN_SENT, TCP_ESTABLISHED),
+            fixtures::rx_squeeze(10, 3, 2_000_000, 300),
+            fixtures::napi_poll(11, 0, "eth0", 64),
+            extension_event(EXTENSION_EVENT_TYPES.start, 12, 1, &[0xab; 60]),
+        ]
+    }
+
+    fn recording(events: &[CongestionEvent]) -> Vec<u8> {
+        let mut writer = RecordingWriter::new(Vec::new()).unwrap();
+        for event in events {
+            writer.write_event(event).unwrap();
+        }
+        writer.inner
+    }
+
+    #[test]
+    fn every_variant_round_trips() {
+        let events = every_variant();
+        let bytes = recording(&events);
+        assert_eq!(bytes.len(), HEADER_LEN + events.len() * RECORD_SIZE);
+
+        let read: Vec<_> = RecordingReader::new(bytes.as_slice())
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(read.len(), events.len());
+        for (written, read) in events.iter().zip(&read) {
+            assert_eq!(encode(written), encode(read));
+            assert_eq!(written.timestamp_ns, read.timestamp_ns);
+            assert_eq!(written.event_type, read.event_type);
+        }
+        unsafe {
+            assert_eq!(read[0].data.sendmsg.bytes, 1200);
+            assert_eq!(read[3].data.socket.sndbuf, 212_992);
+            assert_eq!(read[5].data.softirq.duration_ns, 150_000);
+            assert_eq!(read[8].data.lifecycle.newstate, TCP_ESTABLISHED);
+            assert_eq!(read[10].data.napi.device(), "eth0");
+            assert_eq!(read[11].data.raw, [0xab; EXTENSION_PAYLOAD_SIZE]);
+        }
+    }
+
+    #[test]
+    fn padding_and_unused_union_bytes_are_zero() {
+        let event = fixtures::softirq_exit(1, 0, 3, u64::MAX);
+        let record = encode(&event);
+        // vec_nr is followed by four bytes of padding before duration_ns
+        let padding = DATA + offset_of!(SoftirqData, vec_nr) + 4;
+        assert_eq!(record[padding..padding + 4], [0; 4]);
+        let end = DATA + size_of::<SoftirqData>();
+        assert!(record[end..].iter().all(|&b| b == 0));
+        assert_eq!(record[DATA + 8..end], [0xff; 8]);
+    }
+
+    #[test]
+    fn unknown_types_keep_only_the_header() {
+        let mut event = fixtures::udp_send(5, 2, 7, 1200);
+        event.event_type = 31;
+        let record = encode(&event);
+        assert!(record[DATA..].iter().all(|&b| b == 0));
+        let read = decode(&record);
+        assert_eq!(
+            (read.timestamp_ns, read.event_type, read.cpu_id),
+            (5, 31, 2)
+        );
+    }
+
+    #[test]
+    fn foreign_files_are_rejected() {
+        let err = RecordingReader::new(&b"not a recording at all"[..])
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut bytes = recording(&[]);
+        bytes[8..12].copy_from_slice(&(SCHEMA_VERSION + 1).to_ne_bytes());
+        let err = RecordingReader::new(bytes.as_slice()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("expected"));
+    }
+
+    #[test]
+    fn partial_record_is_an_error() {
+        let mut bytes = recording(&every_variant()[..2]);
+        bytes.truncate(bytes.len() - 3);
+        let mut reader = RecordingReader::new(bytes.as_slice()).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn extension_events_carry_the_payload() {
+        let bytes = recording(&every_variant());
+        let events: Vec<_> = RecordingReader::new(bytes.as_slice())
+            .unwrap()
+            .extension_events()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        let extension = events.last().unwrap();
+        assert_eq!(extension.event_type, EXTENSION_EVENT_TYPES.start);
+        assert_eq!(extension.u64_at(0), Some(u64::from_ne_bytes([0xab; 8])));
+        assert_eq!(events[0].u64_at(0), Some(1200));
+    }
+}
@@ -0,0 +1,153 @@
+//Redaction at the export boundary. Socket ids fall back to kernel struct sock
+//addresses when the cookie can't be read, and addresses of peers don't belong in
+//files that leave the host either, so exporters run identifiers through a
+//Redaction before writing them. Everything in-process (read_per_socket(),
+//subscribers, recordings) keeps full fidelity.
+
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::OnceLock;
+
+const BOOT_ID: &str = "/proc/sys/kernel/random/boot_id";
+
+/// What happens to socket ids on export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketIdRedaction {
+    Keep,
+    /// Keyed hash salted with the boot id (`/proc/sys/kernel/random/boot_id`):
+    /// exports from restarts within a boot join by socket, ids from two boots
+    /// don't, and none can be reversed off the host. Salted once per process
+    /// where the boot id can't be read
+    Hash,
+    /// Left out, no per-flow detail at all
+    Omit,
+}
+
+/// What happens to IP addresses on export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressRedaction {
+    Keep,
+    /// Cut to the /24 (IPv4) or /48 (IPv6) prefix
+    Prefix,
+    Omit,
+}
+
+/// Applied by exporters, see `ParquetSink::with_redaction`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Redaction {
+    pub socket_ids: SocketIdRedaction,
+    pub addresses: AddressRedaction,
+}
+
+impl Redaction {
+    /// Export as collected
+    pub fn none() -> Self {
+        Self {
+            socket_ids: SocketIdRedaction::Keep,
+            addresses: AddressRedaction::Keep,
+        }
+    }
+
+    /// Nothing per flow: no socket ids, no addresses
+    pub fn all() -> Self {
+        Self {
+            socket_ids: SocketIdRedaction::Omit,
+            addresses: AddressRedaction::Omit,
+        }
+    }
+
+    /// Socket ids hashed, addresses cut to their prefix: what the daemon
+    /// binaries export unless told otherwise
+    pub fn hashed() -> Self {
+        Self {
+            socket_ids: SocketIdRedaction::Hash,
+            addresses: AddressRedaction::Prefix,
+        }
+    }
+
+    pub fn socket_id(&self, socket_id: u64) -> Option<u64> {
+        match self.socket_ids {
+            SocketIdRedaction::Keep => Some(socket_id),
+            SocketIdRedaction::Hash => Some(salted(*salt(), socket_id)),
+            SocketIdRedaction::Omit => None,
+        }
+    }
+
+    pub fn address(&self, address: IpAddr) -> Option<IpAddr> {
+        match self.addresses {
+            AddressRedaction::Keep => Some(address),
+            AddressRedaction::Prefix => Some(match address {
+                IpAddr::V4(v4) => IpAddr::V4(Ipv4Addr::from(v4.to_bits() & !0xff)),
+                IpAddr::V6(v6) => IpAddr::V6(Ipv6Addr::from(v6.to_bits() & !(u128::MAX >> 48))),
+            }),
+            AddressRedaction::Omit => None,
+        }
+    }
+}
+
+fn salt() -> &'static u64 {
+    static SALT: OnceLock<u64> = OnceLock::new();
+    SALT.get_or_init(|| match std::fs::read_to_string(BOOT_ID) {
+        Ok(boot_id) => boot_salt(&boot_id),
+        Err(_) => RandomState::new().hash_one(0u64),
+    })
+}
+
+fn boot_salt(boot_id: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    boot_id.trim().hash(&mut hasher);
+    hasher.finish()
+}
+
+fn salted(salt: u64, socket_id: u64) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (salt, socket_id).hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOCKET: u64 = 0xffff_8881_0a2b_3c40;
+
+    #[test]
+    fn socket_ids_keep_hash_or_go() {
+        assert_eq!(Redaction::none().socket_id(SOCKET), Some(SOCKET));
+        assert_eq!(Redaction::all().socket_id(SOCKET), None);
+
+        let hashed = Redaction::hashed();
+        let id = hashed.socket_id(SOCKET).unwrap();
+        assert_ne!(id, SOCKET);
+        // Rows of one export still group by socket
+        assert_eq!(hashed.socket_id(SOCKET), Some(id));
+        assert_ne!(hashed.socket_id(SOCKET + 1), Some(id));
+    }
+
+    #[test]
+    fn the_salt_follows_the_boot() {
+        let boot = "6f1d4c2e-8a3b-4f7e-9c15-2d8e0b7a4f91\n";
+        let salt = boot_salt(boot);
+        // Read again after a restart
+        assert_eq!(boot_salt(boot.trim()), salt);
+        let next_boot = boot_salt("0b9e27d4-51c3-4a8f-b6e2-7f3d1c9a5e08\n");
+        assert_ne!(next_boot, salt);
+        assert_eq!(salted(salt, SOCKET), salted(boot_salt(boot), SOCKET));
+        assert_ne!(salted(next_boot, SOCKET), salted(salt, SOCKET));
+    }
+
+    #[test]
+    fn addresses_cut_to_their_prefix() {
+        let prefix = Redaction {
+            socket_ids: SocketIdRedaction::Keep,
+            addresses: AddressRedaction::Prefix,
+        };
+        let v4: IpAddr = "192.0.2.77".parse().unwrap();
+        let v6: IpAddr = "2001:db8:1234:5678::9".parse().unwrap();
+        assert_eq!(prefix.address(v4), Some("192.0.2.0".parse().unwrap()));
+        assert_eq!(prefix.address(v6), Some("2001:db8:1234::".parse().unwrap()));
+        assert_eq!(Redaction::none().address(v4), Some(v4));
+        assert_eq!(Redaction::all().address(v6), None);
+    }
+}
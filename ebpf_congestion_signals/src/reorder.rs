@@ -0,0 +1,343 @@
+//Timestamp ordering across the per-CPU perf buffers. Each CPU's events are in
+//order, but the readers hand them over interleaved and a CPU whose buffer is
+//read later can be tens of microseconds behind the others. This holds events in
+//a bounded min-heap on timestamp_ns and releases them once the newest
+//timestamp seen is a watermark past theirs, or the watermark has passed in wall
+//time with nothing newer arriving.
+//
+//An event older than one already released is late: it is counted and released
+//straight away, out of order, rather than held.
+
+use crate::clock::{system_clock, Clock};
+use crate::CongestionEvent;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// A timestamp this far behind what was released is the kernel clock jumping
+// back (see ReaderStats::clock_jumps), not a late event
+const CLOCK_JUMP_NS: u64 = 5_000_000_000;
+
+/// How long events are held to put them in order, see
+/// `CollectorConfig::with_event_reordering`
+#[derive(Debug, Clone, Copy)]
+pub struct EventReordering {
+    /// Added to every event's processing latency. Events further behind than
+    /// this across CPUs arrive late
+    pub watermark: Duration,
+    /// Events held at most; past it the oldest is released early
+    pub max_buffered: usize,
+}
+
+impl Default for EventReordering {
+    fn default() -> Self {
+        Self {
+            watermark: Duration::from_millis(5),
+            max_buffered: 8192,
+        }
+    }
+}
+
+/// What the reordering stage did so far, see `ReaderStats::reordering`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReorderStats {
+    /// Events held right now
+    pub held: usize,
+    /// Events released in order
+    pub released: u64,
+    /// Events that arrived after a later one had been released, released out
+    /// of order
+    pub late_events: u64,
+    /// Released early because `max_buffered` were held
+    pub overflowed: u64,
+    /// Time from arrival to release, over the in-order events
+    pub mean_delay: Duration,
+    pub max_delay: Duration,
+}
+
+struct Held {
+    event: CongestionEvent,
+    // Arrival order, so equal timestamps keep it
+    seq: u64,
+    at: Instant,
+}
+
+impl Held {
+    fn key(&self) -> (u64, u64) {
+        (self.event.timestamp_ns, self.seq)
+    }
+}
+
+impl PartialEq for Held {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Held {}
+
+impl PartialOrd for Held {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Held {
+    // Reversed, BinaryHeap pops the largest
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.key().cmp(&self.key())
+    }
+}
+
+/// The reordering stage on its own, e.g. for events merged from several
+/// recordings. Events go in with `push` and come out through the `release`
+/// callback, in timestamp order apart from late ones
+pub struct EventReorderer {
+    heap: BinaryHeap<Held>,
+    watermark_ns: u64,
+    max_buffered: usize,
+    seq: u64,
+    // Newest timestamp pushed and when it arrived
+    newest: Option<(u64, Instant)>,
+    // Newest timestamp released in order
+    released_ns: u64,
+    released: u64,
+    late_events: u64,
+    overflowed: u64,
+    delay_total: Duration,
+    max_delay: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl EventReorderer {
+    pub fn new(config: EventReordering) -> Self {
+        let max_buffered = config.max_buffered.max(1);
+        Self {
+            heap: BinaryHeap::with_capacity(max_buffered.min(8192)),
+            watermark_ns: config.watermark.as_nanos() as u64,
+            max_buffered,
+            seq: 0,
+            newest: None,
+            released_ns: 0,
+            released: 0,
+            late_events: 0,
+            overflowed: 0,
+            delay_total: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            clock: system_clock(),
+        }
+    }
+
+    /// Measure arrivals and delays on `clock` rather than the system's.
+    /// `release_ready` is then given its `now()`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Hold `event`, then release whatever is past the watermark
+    pub fn push(&mut self, event: CongestionEvent, mut release: impl FnMut(CongestionEvent)) {
+        let now = self.clock.now();
+        let ts = event.timestamp_ns;
+        if ts < self.released_ns {
+            if self.released_ns - ts <= CLOCK_JUMP_NS {
+                self.late_events += 1;
+                release(event);
+                return;
+            }
+            // Whatever is held is on the old clock and goes first
+            self.flush(&mut release);
+            self.released_ns = 0;
+            self.newest = None;
+        }
+
+        if self.newest.is_none_or(|(newest_ns, _)| ts > newest_ns) {
+            self.newest = Some((ts, now));
+        }
+        self.heap.push(Held {
+            event,
+            seq: self.seq,
+            at: now,
+        });
+        self.seq += 1;
+
+        while self.heap.len() > self.max_buffered {
+            self.overflowed += 1;
+            self.release_oldest(now, &mut release);
+        }
+        self.release_ready(now, release);
+    }
+
+    /// Release held events whose timestamp is a watermark behind the newest
+    /// one, counting the wall time since that arrived as having passed on the
+    /// kernel clock too. Call it periodically when events may stop arriving
+    pub fn release_ready(&mut self, now: Instant, mut release: impl FnMut(CongestionEvent)) {
+        let Some((newest_ns, newest_at)) = self.newest else {
+            return;
+        };
+        let frontier = newest_ns + now.saturating_duration_since(newest_at).as_nanos() as u64;
+        while self
+            .heap
+            .peek()
+            .is_some_and(|held| held.event.timestamp_ns + self.watermark_ns <= frontier)
+        {
+            self.release_oldest(now, &mut release);
+        }
+    }
+
+    /// Release everything held, e.g. on shutdown
+    pub fn flush(&mut self, mut release: impl FnMut(CongestionEvent)) {
+        let now = self.clock.now();
+        while !self.heap.is_empty() {
+            self.release_oldest(now, &mut release);
+        }
+    }
+
+    fn release_oldest(&mut self, now: Instant, release: &mut impl FnMut(CongestionEvent)) {
+        let Some(held) = self.heap.pop() else {
+            return;
+        };
+        let delay = now.saturating_duration_since(held.at);
+        self.delay_total += delay;
+        self.max_delay = self.max_delay.max(delay);
+        self.released += 1;
+        self.released_ns = self.released_ns.max(held.event.timestamp_ns);
+        release(held.event);
+    }
+
+    pub fn held(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn stats(&self) -> ReorderStats {
+        ReorderStats {
+            held: self.heap.len(),
+            released: self.released,
+            late_events: self.late_events,
+            overflowed: self.overflowed,
+            mean_delay: match self.released {
+                0 => Duration::ZERO,
+                released => self.delay_total.div_f64(released as f64),
+            },
+            max_delay: self.max_delay,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::fixtures;
+
+    // 4 CPUs' events 10 µs apart, arriving up to JITTER_NS behind their turn,
+    // well inside the watermark
+    const EVENTS: u64 = 1000;
+    const JITTER_NS: u64 = 40_000;
+    const WATERMARK: Duration = Duration::from_millis(1);
+    const START_NS: u64 = 1_000_000_000;
+
+    /// Stood still: only timestamps release anything
+    fn reorderer(max_buffered: usize) -> EventReorderer {
+        reorderer_on(&ManualClock::new(), max_buffered)
+    }
+
+    fn reorderer_on(clock: &ManualClock, max_buffered: usize) -> EventReorderer {
+        EventReorderer::new(EventReordering {
+            watermark: WATERMARK,
+            max_buffered,
+        })
+        .with_clock(Arc::new(clock.clone()))
+    }
+
+    fn event(timestamp_ns: u64, cpu_id: u32) -> CongestionEvent {
+        fixtures::softirq_exit(timestamp_ns, cpu_id, 3, 0)
+    }
+
+    #[test]
+    fn a_shuffled_stream_comes_out_in_order() {
+        let mut reorderer = reorderer(4096);
+        let mut arrivals: Vec<(u64, u64)> = (0..EVENTS)
+            .map(|i| {
+                // Deterministic jitter from a multiplicative hash of the index
+                let jitter = i.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32;
+                (START_NS + i * 10_000, jitter % JITTER_NS)
+            })
+            .collect();
+        arrivals.sort_by_key(|&(ts, jitter)| ts + jitter);
+        assert!(arrivals.windows(2).any(|w| w[1].0 < w[0].0));
+
+        let mut released = Vec::new();
+        for (i, &(ts, _)) in arrivals.iter().enumerate() {
+            reorderer.push(event(ts, i as u32 % 4), |e| released.push(e.timestamp_ns));
+        }
+        reorderer.flush(|e| released.push(e.timestamp_ns));
+        assert_eq!(released.len(), EVENTS as usize);
+        assert!(released.windows(2).all(|w| w[0] <= w[1]));
+        let stats = reorderer.stats();
+        assert_eq!(stats.released, EVENTS);
+        assert_eq!((stats.held, stats.late_events), (0, 0));
+    }
+
+    #[test]
+    fn an_event_behind_the_released_ones_comes_out_at_once() {
+        let mut reorderer = reorderer(4096);
+        let mut released = Vec::new();
+        for ts in [START_NS, START_NS + 2 * WATERMARK.as_nanos() as u64] {
+            reorderer.push(event(ts, 0), |e| released.push(e.timestamp_ns));
+        }
+        assert_eq!(released, [START_NS]);
+        // Behind the one released, ahead of the one held
+        reorderer.push(event(START_NS - 20_000, 1), |e| {
+            released.push(e.timestamp_ns)
+        });
+        assert_eq!(released, [START_NS, START_NS - 20_000]);
+        let stats = reorderer.stats();
+        assert_eq!((stats.released, stats.late_events, stats.held), (1, 1, 1));
+    }
+
+    #[test]
+    fn past_max_buffered_the_oldest_is_pushed_out() {
+        let mut reorderer = reorderer(8);
+        let mut early = Vec::new();
+        for i in 0..20 {
+            reorderer.push(event(START_NS + i, 0), |e| early.push(e.timestamp_ns));
+        }
+        assert_eq!(early, (START_NS..START_NS + 12).collect::<Vec<_>>());
+        let stats = reorderer.stats();
+        assert_eq!(
+            (stats.held, stats.overflowed, stats.late_events),
+            (8, 12, 0)
+        );
+    }
+
+    #[test]
+    fn equal_timestamps_keep_their_arrival_order() {
+        let mut reorderer = reorderer(64);
+        let mut released = Vec::new();
+        for cpu in [3, 1, 2] {
+            reorderer.push(event(START_NS, cpu), |e| released.push(e.cpu_id));
+        }
+        reorderer.flush(|e| released.push(e.cpu_id));
+        assert_eq!(released, [3, 1, 2]);
+    }
+
+    #[test]
+    fn held_events_wait_out_the_watermark_on_the_clock() {
+        let clock = ManualClock::new();
+        let mut reorderer = reorderer_on(&clock, 64);
+        let mut released = Vec::new();
+        for ts in [START_NS + 2_000, START_NS, START_NS + 1_000] {
+            reorderer.push(event(ts, 0), |e| released.push(e.timestamp_ns));
+        }
+        // Nothing moves until the clock does
+        reorderer.release_ready(clock.now(), |e| released.push(e.timestamp_ns));
+        assert!(released.is_empty());
+
+        clock.advance(WATERMARK);
+        reorderer.release_ready(clock.now(), |e| released.push(e.timestamp_ns));
+        assert_eq!(released, [START_NS, START_NS + 1_000, START_NS + 2_000]);
+        assert_eq!(reorderer.stats().max_delay, WATERMARK);
+    }
+}
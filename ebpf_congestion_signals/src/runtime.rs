@@ -0,0 +1,307 @@
+//Everything the async build needs from an executor: spawning and aborting
+//tasks, sleeps and intervals, readiness on a perf buffer's fd, a thread for
+//blocking work. Tokio with `async`, smol with `smol` (async-std runs on the
+//same reactor); `Rt` is whichever was built, tokio if both were. Channels
+//aren't part of it: tokio::sync's work under any executor, so the queues,
+//broadcasts and watches stay what they were.
+//
+//smol's tasks cancel when dropped rather than when aborted, so its handle keeps
+//the task behind a shared slot that `abort()` empties.
+
+use std::future::Future;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::time::Duration;
+
+/// What an interval does after falling behind, as tokio's MissedTickBehavior
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MissedTicks {
+    /// Tick back to back until caught up, what statsd flushes on
+    #[cfg(feature = "statsd")]
+    Burst,
+    /// Tick now, then a whole period from now
+    Delay,
+    /// Tick now, then on the next multiple of the period
+    Skip,
+}
+
+pub(crate) trait Runtime {
+    type Handle: TaskHandle;
+    type Ticker: Ticker;
+    type Readable<T: AsRawFd + Send + 'static>: Readable<T>;
+
+    fn spawn<F>(future: F) -> Self::Handle
+    where
+        F: Future<Output = ()> + Send + 'static;
+
+    /// Run `work` off the executor's threads
+    fn spawn_blocking<T, F>(work: F) -> impl Future<Output = anyhow::Result<T>> + Send
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static;
+
+    fn sleep(duration: Duration) -> impl Future<Output = ()> + Send;
+
+    /// Ticks every `period`, the first one at once
+    fn interval(period: Duration, missed: MissedTicks) -> Self::Ticker;
+
+    /// Register `io`'s fd for read readiness. Must be called on the runtime
+    fn readable<T: AsRawFd + Send + 'static>(io: T) -> io::Result<Self::Readable<T>>;
+}
+
+/// Cancels a spawned task; clones cancel the same one
+pub(crate) trait TaskHandle: Clone + Send + Sync + 'static {
+    fn abort(&self);
+    fn is_finished(&self) -> bool;
+}
+
+pub(crate) trait Ticker: Send {
+    fn tick(&mut self) -> impl Future<Output = ()> + Send;
+}
+
+pub(crate) trait Readable<T>: Send {
+    /// Until the fd signals readable. The readiness is used up on return, so
+    /// read until empty before waiting again
+    fn readable(&mut self) -> impl Future<Output = io::Result<()>> + Send;
+    fn get_mut(&mut self) -> &mut T;
+}
+
+#[cfg(feature = "async")]
+pub(crate) type Rt = tokio_rt::Tokio;
+#[cfg(all(feature = "smol", not(feature = "async")))]
+pub(crate) type Rt = smol_rt::Smol;
+
+pub(crate) type AbortHandle = <Rt as Runtime>::Handle;
+
+/// `future`'s output, or None if `duration` passes first
+pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    use futures_util::future::{select, Either};
+    let sleep = Rt::sleep(duration);
+    match select(std::pin::pin!(future), std::pin::pin!(sleep)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
+
+#[cfg(feature = "async")]
+mod tokio_rt {
+    use super::{MissedTicks, Readable, Runtime, TaskHandle, Ticker};
+    use std::future::Future;
+    use std::io;
+    use std::os::fd::AsRawFd;
+    use std::time::Duration;
+    use tokio::io::unix::AsyncFd;
+    use tokio::io::Interest;
+    use tokio::time::{Interval, MissedTickBehavior};
+
+    pub(crate) struct Tokio;
+
+    impl Runtime for Tokio {
+        type Handle = TokioHandle;
+        type Ticker = TokioTicker;
+        type Readable<T: AsRawFd + Send + 'static> = TokioReadable<T>;
+
+        fn spawn<F>(future: F) -> Self::Handle
+        where
+            F: Future<Output = ()> + Send + 'static,
+        {
+            TokioHandle(tokio::spawn(future).abort_handle())
+        }
+
+        fn spawn_blocking<T, F>(work: F) -> impl Future<Output = anyhow::Result<T>> + Send
+        where
+            T: Send + 'static,
+            F: FnOnce() -> T + Send + 'static,
+        {
+            let task = tokio::task::spawn_blocking(work);
+            async move { Ok(task.await?) }
+        }
+
+        fn sleep(duration: Duration) -> impl Future<Output = ()> + Send {
+            tokio::time::sleep(duration)
+        }
+
+        fn interval(period: Duration, missed: MissedTicks) -> Self::Ticker {
+            let mut interval = tokio::time::interval(period);
+            interval.set_missed_tick_behavior(match missed {
+                #[cfg(feature = "statsd")]
+                MissedTicks::Burst => MissedTickBehavior::Burst,
+                MissedTicks::Delay => MissedTickBehavior::Delay,
+                MissedTicks::Skip => MissedTickBehavior::Skip,
+            });
+            TokioTicker(interval)
+        }
+
+        fn readable<T: AsRawFd + Send + 'static>(io: T) -> io::Result<Self::Readable<T>> {
+            AsyncFd::with_interest(io, Interest::READABLE).map(TokioReadable)
+        }
+    }
+
+    #[derive(Clone)]
+    pub(crate) struct TokioHandle(tokio::task::AbortHandle);
+
+    impl TaskHandle for TokioHandle {
+        fn abort(&self) {
+            self.0.abort()
+        }
+
+        fn is_finished(&self) -> bool {
+            self.0.is_finished()
+        }
+    }
+
+    pub(crate) struct TokioTicker(Interval);
+
+    impl Ticker for TokioTicker {
+        async fn tick(&mut self) {
+            self.0.tick().await;
+        }
+    }
+
+    pub(crate) struct TokioReadable<T: AsRawFd>(AsyncFd<T>);
+
+    impl<T: AsRawFd + Send + 'static> Readable<T> for TokioReadable<T> {
+        async fn readable(&mut self) -> io::Result<()> {
+            self.0.readable_mut().await?.clear_ready();
+            Ok(())
+        }
+
+        fn get_mut(&mut self) -> &mut T {
+            self.0.get_mut()
+        }
+    }
+}
+
+#[cfg(all(feature = "smol", not(feature = "async")))]
+mod smol_rt {
+    use super::{MissedTicks, Readable, Runtime, TaskHandle, Ticker};
+    use std::future::Future;
+    use std::io;
+    use std::os::fd::{AsFd, AsRawFd, BorrowedFd, RawFd};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    // A tick this late counts as missed, as in tokio
+    const MISSED_AFTER: Duration = Duration::from_millis(5);
+
+    pub(crate) struct Smol;
+
+    impl Runtime for Smol {
+        type Handle = SmolHandle;
+        type Ticker = SmolTicker;
+        type Readable<T: AsRawFd + Send + 'static> = SmolReadable<T>;
+
+        fn spawn<F>(future: F) -> Self::Handle
+        where
+            F: Future<Output = ()> + Send + 'static,
+        {
+            SmolHandle(Arc::new(Mutex::new(Some(smol::spawn(future)))))
+        }
+
+        fn spawn_blocking<T, F>(work: F) -> impl Future<Output = anyhow::Result<T>> + Send
+        where
+            T: Send + 'static,
+            F: FnOnce() -> T + Send + 'static,
+        {
+            let task = smol::unblock(work);
+            async move { Ok(task.await) }
+        }
+
+        fn sleep(duration: Duration) -> impl Future<Output = ()> + Send {
+            let timer = smol::Timer::after(duration);
+            async move {
+                timer.await;
+            }
+        }
+
+        fn interval(period: Duration, missed: MissedTicks) -> Self::Ticker {
+            SmolTicker {
+                next: Instant::now(),
+                period,
+                missed,
+            }
+        }
+
+        fn readable<T: AsRawFd + Send + 'static>(io: T) -> io::Result<Self::Readable<T>> {
+            Ok(SmolReadable {
+                fd: smol::Async::new(Fd(io.as_raw_fd()))?,
+                io,
+            })
+        }
+    }
+
+    /// Dropping the last clone cancels the task, like `abort()`
+    #[derive(Clone)]
+    pub(crate) struct SmolHandle(Arc<Mutex<Option<smol::Task<()>>>>);
+
+    impl TaskHandle for SmolHandle {
+        fn abort(&self) {
+            // Dropping a smol task cancels it
+            drop(self.0.lock().unwrap().take());
+        }
+
+        fn is_finished(&self) -> bool {
+            self.0
+                .lock()
+                .unwrap()
+                .as_ref()
+                .is_none_or(smol::Task::is_finished)
+        }
+    }
+
+    pub(crate) struct SmolTicker {
+        next: Instant,
+        period: Duration,
+        missed: MissedTicks,
+    }
+
+    impl Ticker for SmolTicker {
+        async fn tick(&mut self) {
+            let deadline = self.next;
+            smol::Timer::at(deadline).await;
+            let now = Instant::now();
+            self.next = if now <= deadline + MISSED_AFTER {
+                deadline + self.period
+            } else {
+                match self.missed {
+                    #[cfg(feature = "statsd")]
+                    MissedTicks::Burst => deadline + self.period,
+                    MissedTicks::Delay => now + self.period,
+                    MissedTicks::Skip => {
+                        let period = self.period.as_nanos().max(1);
+                        let behind = (now - deadline).as_nanos() / period + 1;
+                        deadline + Duration::from_nanos((behind * period) as u64)
+                    }
+                }
+            };
+        }
+    }
+
+    // The fd of an `io` that outlives it, registered in its place: smol's
+    // Async would hand out `io` only through an unsafe get_mut
+    struct Fd(RawFd);
+
+    impl AsFd for Fd {
+        fn as_fd(&self) -> BorrowedFd<'_> {
+            // SAFETY: an Fd lives in a SmolReadable next to the `io` owning
+            // the fd, and is dropped (deregistered) before it
+            unsafe { BorrowedFd::borrow_raw(self.0) }
+        }
+    }
+
+    pub(crate) struct SmolReadable<T> {
+        // Declared first so it's dropped first, see Fd
+        fd: smol::Async<Fd>,
+        io: T,
+    }
+
+    impl<T: AsRawFd + Send + 'static> Readable<T> for SmolReadable<T> {
+        async fn readable(&mut self) -> io::Result<()> {
+            self.fd.readable().await
+        }
+
+        fn get_mut(&mut self) -> &mut T {
+            &mut self.io
+        }
+    }
+}
@@ -0,0 +1,452 @@
+//Two send samplers side by side, to see whether a cheaper ratio tracks the
+//1-in-SEND_SAMPLE_RATIO one before switching. With
+//`CollectorConfig::with_sampler_comparison` the kernel keeps a second per-CPU
+//counter next to the primary one and tags each send event with whichever
+//admitted it. Sends only the second sampler took are counted by the readers
+//and go no further, so every other signal stays the primary sampler's.
+//
+//Each interval read scales both samplers' bytes up to an estimate of what was
+//sent; `SamplerComparison` collects the relative error between them over a
+//run and says whether the candidate stayed within a bound.
+//
+//Per-socket sampling replaces the 1-in-100 counter with a hash of the socket
+//id: a socket is either in the sample and has every sampled event sent, or
+//has none. Its per-socket series then has no gaps, and totals are the sample's
+//over the share of sockets in it.
+
+use crate::{CongestionSignals, SEND_SAMPLE_RATIO};
+#[cfg(feature = "collector-core")]
+use crate::{EstimatedTotals, ProtocolCounts, SampleScale};
+use std::fmt;
+
+/// One interval's send bytes as estimated by each sampler, see
+/// `CongestionSignals::sampler_comparison`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SamplerEstimates {
+    /// 1-in-N of the candidate sampler
+    pub candidate_ratio: u64,
+    /// Sampled send bytes times SEND_SAMPLE_RATIO
+    pub primary_send_bytes: u64,
+    /// The candidate's sampled send bytes times `candidate_ratio`
+    pub candidate_send_bytes: u64,
+    /// Sends the candidate admitted
+    pub candidate_samples: u64,
+}
+
+impl SamplerEstimates {
+    /// The candidate's estimate off the primary one, relative to it. None
+    /// without primary bytes to compare against
+    pub fn relative_error(&self) -> Option<f64> {
+        (self.primary_send_bytes > 0).then(|| {
+            (self.candidate_send_bytes as f64 - self.primary_send_bytes as f64).abs()
+                / self.primary_send_bytes as f64
+        })
+    }
+
+    /// From the interval's sampled bytes of each sampler
+    #[cfg(feature = "collector-core")]
+    pub(crate) fn from_sampled(primary: u64, candidate: u64, samples: u64, ratio: u64) -> Self {
+        Self {
+            candidate_ratio: ratio,
+            primary_send_bytes: primary * SEND_SAMPLE_RATIO,
+            candidate_send_bytes: candidate * ratio,
+            candidate_samples: samples,
+        }
+    }
+}
+
+/// What a comparison run must show, see `SamplerComparison`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ComparisonBound {
+    /// Largest relative error tolerated at `percentile`
+    pub max_error: f64,
+    /// Of the compared intervals' errors, 0.0-1.0
+    pub percentile: f64,
+    /// Intervals whose primary estimate is below this are too thin to say
+    /// anything and are skipped
+    pub min_send_bytes: u64,
+}
+
+impl Default for ComparisonBound {
+    fn default() -> Self {
+        Self {
+            max_error: 0.05,
+            percentile: 0.95,
+            min_send_bytes: 2_000_000,
+        }
+    }
+}
+
+/// Relative errors between the two samplers over a run of interval reads
+#[derive(Debug, Clone, Default)]
+pub struct SamplerComparison {
+    bound: ComparisonBound,
+    candidate_ratio: Option<u64>,
+    errors: Vec<f64>,
+    skipped: u64,
+    primary_total: u64,
+    candidate_total: u64,
+}
+
+impl SamplerComparison {
+    pub fn new(bound: ComparisonBound) -> Self {
+        Self {
+            bound,
+            ..Default::default()
+        }
+    }
+
+    /// Add one interval read. Intervals without estimates (comparison off)
+    /// are ignored, thin ones counted as skipped
+    pub fn observe(&mut self, signals: &CongestionSignals) {
+        let Some(estimates) = &signals.sampler_comparison else {
+            return;
+        };
+        self.candidate_ratio = Some(estimates.candidate_ratio);
+        self.primary_total += estimates.primary_send_bytes;
+        self.candidate_total += estimates.candidate_send_bytes;
+        match estimates.relative_error() {
+            Some(error) if estimates.primary_send_bytes >= self.bound.min_send_bytes => {
+                self.errors.push(error)
+            }
+            _ => self.skipped += 1,
+        }
+    }
+
+    pub fn report(&self) -> SamplerComparisonReport {
+        let mut errors = self.errors.clone();
+        errors.sort_unstable_by(f64::total_cmp);
+        let at_percentile = (!errors.is_empty()).then(|| {
+            let rank = (self.bound.percentile * errors.len() as f64).ceil() as usize;
+            errors[rank.clamp(1, errors.len()) - 1]
+        });
+        let run_error = (self.primary_total > 0).then(|| {
+            (self.candidate_total as f64 - self.primary_total as f64).abs()
+                / self.primary_total as f64
+        });
+        SamplerComparisonReport {
+            bound: self.bound,
+            candidate_ratio: self.candidate_ratio,
+            compared: errors.len(),
+            skipped: self.skipped,
+            mean_error: (!errors.is_empty())
+                .then(|| errors.iter().sum::<f64>() / errors.len() as f64),
+            percentile_error: at_percentile,
+            max_error: errors.last().copied(),
+            run_error,
+            within_bound: at_percentile.is_some_and(|error| error <= self.bound.max_error),
+        }
+    }
+}
+
+/// The outcome of a comparison run. Its `Display` is the recommendation
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplerComparisonReport {
+    pub bound: ComparisonBound,
+    /// None when no interval carried estimates
+    pub candidate_ratio: Option<u64>,
+    /// Intervals compared, and ones skipped as too thin
+    pub compared: usize,
+    pub skipped: u64,
+    pub mean_error: Option<f64>,
+    /// At `ComparisonBound::percentile`
+    pub percentile_error: Option<f64>,
+    pub max_error: Option<f64>,
+    /// Of the whole run's totals, where per-interval noise averages out
+    pub run_error: Option<f64>,
+    /// The error at the percentile was within `ComparisonBound::max_error`.
+    /// False when nothing could be compared
+    pub within_bound: bool,
+}
+
+impl fmt::Display for SamplerComparisonReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (Some(ratio), Some(error)) = (self.candidate_ratio, self.percentile_error) else {
+            return write!(
+                f,
+                "nothing to compare: no interval reached {} send bytes ({} skipped)",
+                self.bound.min_send_bytes, self.skipped
+            );
+        };
+        let percentile = self.bound.percentile * 100.0;
+        if self.within_bound {
+            write!(
+                f,
+                "1-in-{} stayed within {:.1}% of 1-in-{} at p{:.0} ({:.1}% over {} intervals): \
+                 safe to lower the sampling rate",
+                ratio,
+                self.bound.max_error * 100.0,
+                SEND_SAMPLE_RATIO,
+                percentile,
+                error * 100.0,
+                self.compared
+            )
+        } else {
+            write!(
+                f,
+                "1-in-{} was {:.1}% off 1-in-{} at p{:.0}, past the {:.1}% bound, over {} \
+                 intervals: keep 1-in-{}",
+                ratio,
+                error * 100.0,
+                SEND_SAMPLE_RATIO,
+                percentile,
+                self.bound.max_error * 100.0,
+                self.compared,
+                SEND_SAMPLE_RATIO
+            )
+        }
+    }
+}
+
+// Hash values are the top 32 bits of the mixed socket id
+const SOCKET_HASH_SPAN: u64 = 1 << 32;
+
+/// Which sockets are in the per-socket sample, see
+/// `CollectorConfig::with_socket_sampling`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketSampling {
+    // Sockets hashing below this are in, 1..=SOCKET_HASH_SPAN
+    threshold: u64,
+}
+
+impl SocketSampling {
+    /// About `fraction` (0.0-1.0) of sockets, at least one hash value's worth
+    pub fn new(fraction: f64) -> Self {
+        let threshold = (fraction * SOCKET_HASH_SPAN as f64).round();
+        Self {
+            threshold: (threshold as u64).clamp(1, SOCKET_HASH_SPAN),
+        }
+    }
+
+    /// The share of hash values in the sample, `new`'s fraction rounded
+    pub fn fraction(&self) -> f64 {
+        self.threshold as f64 / SOCKET_HASH_SPAN as f64
+    }
+
+    /// What the kernel compares hashes against, SOCKET_SAMPLE_THRESHOLD
+    pub fn threshold(&self) -> u64 {
+        self.threshold
+    }
+
+    /// Whether the kernel sends this socket's events
+    pub fn admits(&self, socket_id: u64) -> bool {
+        Self::hash(socket_id) < self.threshold
+    }
+
+    /// The socket id through murmur3's finalizer, top 32 bits, as the kernel
+    /// computes it: cookies are handed out in sequence and would otherwise
+    /// land in the sample in runs
+    pub fn hash(socket_id: u64) -> u64 {
+        let mut h = socket_id;
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        h ^= h >> 33;
+        h >> 32
+    }
+
+    /// Re-estimate a per-cgroup, namespace or class breakdown, built at the
+    /// 1-in-100 ratio, at this sample's
+    #[cfg(feature = "collector-core")]
+    pub(crate) fn rescale(&self, signals: &mut CongestionSignals) {
+        let built_at = signals.estimated.scale.send_factor;
+        let scale = SampleScale::per_socket(None, signals.estimated.scale.sends_sampled, *self);
+        signals.estimated =
+            EstimatedTotals::from_samples(signals.send_bytes, signals.external_send_bytes, scale);
+        // The messages it was built with, over the factor they were scaled by
+        let sampled = |estimate: u64| (estimate as f64 / built_at).round() as u64;
+        let msgs = signals.est_send_msgs;
+        signals.est_send_msgs = ProtocolCounts {
+            udp: sampled(msgs.udp),
+            tcp: sampled(msgs.tcp),
+        }
+        .scaled(&scale);
+        signals.est_wire_packets = signals.est_send_msgs;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Option<f64>, b: f64) -> bool {
+        a.is_some_and(|a| (a - b).abs() < 1e-9)
+    }
+
+    fn compared(primary: u64, candidate: u64) -> CongestionSignals {
+        CongestionSignals::builder()
+            .sampler_comparison(SamplerEstimates {
+                candidate_ratio: 1000,
+                primary_send_bytes: primary,
+                candidate_send_bytes: candidate,
+                candidate_samples: candidate / 1_200_000,
+            })
+            .build()
+    }
+
+    /// Nine intervals 2% apart and one 20% apart, one too thin to compare and
+    /// one without estimates
+    fn run(percentile: f64) -> SamplerComparisonReport {
+        let mut comparison = SamplerComparison::new(ComparisonBound {
+            percentile,
+            ..Default::default()
+        });
+        for _ in 0..9 {
+            comparison.observe(&compared(100_000_000, 102_000_000));
+        }
+        comparison.observe(&compared(100_000_000, 80_000_000));
+        comparison.observe(&compared(1_000_000, 3_000_000));
+        comparison.observe(&CongestionSignals::default());
+        comparison.report()
+    }
+
+    #[test]
+    fn the_bound_is_judged_at_its_percentile() {
+        let p90 = run(0.90);
+        assert!(p90.within_bound);
+        assert_eq!((p90.compared, p90.skipped), (10, 1));
+        assert!(close(p90.percentile_error, 0.02));
+        assert!(close(p90.max_error, 0.20));
+        assert_eq!(p90.candidate_ratio, Some(1000));
+        assert!(p90.to_string().contains("safe to lower"), "{}", p90);
+
+        let p95 = run(0.95);
+        assert!(!p95.within_bound);
+        assert!(close(p95.percentile_error, 0.20));
+        assert!(p95.to_string().contains("keep 1-in-100"), "{}", p95);
+    }
+
+    #[test]
+    fn nothing_compared_is_not_within_the_bound() {
+        let empty = SamplerComparison::new(ComparisonBound::default()).report();
+        assert!(!empty.within_bound);
+        assert_eq!(empty.candidate_ratio, None);
+        assert!(empty.to_string().starts_with("nothing to compare"));
+    }
+
+    #[test]
+    fn the_run_error_is_of_the_totals() {
+        let mut comparison = SamplerComparison::new(ComparisonBound::default());
+        // 10% over, then 10% under: off per interval, even over the run
+        comparison.observe(&compared(100_000_000, 110_000_000));
+        comparison.observe(&compared(100_000_000, 90_000_000));
+        let report = comparison.report();
+        assert!(close(report.mean_error, 0.10));
+        assert!(close(report.run_error, 0.0));
+    }
+
+    #[cfg(feature = "collector-core")]
+    #[test]
+    fn sends_only_the_candidate_took_go_no_further() {
+        use crate::fixtures::{self, Replay};
+        use crate::{SAMPLER_COMPARE, SAMPLER_PRIMARY};
+
+        let config = crate::CollectorConfig::default().with_sampler_comparison(1000);
+        let replay = Replay::new(config, 1);
+        let tagged = |samplers| {
+            let mut event = fixtures::udp_send(1, 0, 7, 1200);
+            event.data.sendmsg.samplers = samplers;
+            event
+        };
+        replay.feed(&[
+            tagged(SAMPLER_PRIMARY),
+            tagged(SAMPLER_PRIMARY),
+            tagged(SAMPLER_PRIMARY | SAMPLER_COMPARE),
+            tagged(SAMPLER_COMPARE),
+        ]);
+        let signals = replay.read(std::time::Duration::from_secs(1));
+        assert_eq!(signals.send_bytes, 3600);
+        let estimates = signals.sampler_comparison.unwrap();
+        assert_eq!(estimates.primary_send_bytes, 3600 * SEND_SAMPLE_RATIO);
+        assert_eq!(estimates.candidate_send_bytes, 2400 * 1000);
+        assert_eq!(estimates.candidate_samples, 2);
+    }
+
+    #[cfg(feature = "collector-core")]
+    const SAMPLED_SOCKETS: u64 = 4096;
+    #[cfg(feature = "collector-core")]
+    const SOCKET_FRACTION: f64 = 1.0 / 16.0;
+    #[cfg(feature = "collector-core")]
+    const SOCKET_SAMPLE_TOLERANCE: f64 = 0.15;
+
+    // Thousands of sockets of uneven sizes
+    #[cfg(feature = "collector-core")]
+    fn sends_of(socket_id: u64) -> u64 {
+        1 + socket_id % 7
+    }
+
+    #[cfg(feature = "collector-core")]
+    fn bytes_of(socket_id: u64) -> u64 {
+        200 + (socket_id % 13) * 100
+    }
+
+    /// Every send of a socket in the hash sample, none of the rest: the
+    /// estimate is scaled by the configured 16 and lands near the truth
+    #[cfg(feature = "collector-core")]
+    #[test]
+    fn the_per_socket_estimate_lands_near_the_truth() {
+        let sampling = SocketSampling::new(SOCKET_FRACTION);
+        let (mut sent, mut sends_seen) = (0, 0);
+        let (mut sampled_bytes, mut sends_sampled) = (0, 0);
+        let mut admitted = 0;
+        for socket_id in 1..=SAMPLED_SOCKETS {
+            sent += sends_of(socket_id) * bytes_of(socket_id);
+            sends_seen += sends_of(socket_id);
+            if sampling.admits(socket_id) {
+                admitted += 1;
+                sampled_bytes += sends_of(socket_id) * bytes_of(socket_id);
+                sends_sampled += sends_of(socket_id);
+            }
+        }
+        let share = admitted as f64 / SAMPLED_SOCKETS as f64;
+        assert!((share - SOCKET_FRACTION).abs() / SOCKET_FRACTION <= SOCKET_SAMPLE_TOLERANCE);
+
+        let scale = SampleScale::per_socket(Some(sends_seen), sends_sampled, sampling);
+        assert_eq!(scale.send_factor, 16.0);
+        assert_eq!(scale.configured_factor(), 16.0);
+        let estimated = EstimatedTotals::from_samples(sampled_bytes, sampled_bytes, scale);
+        let error = (estimated.send_bytes as f64 - sent as f64).abs() / sent as f64;
+        assert!(
+            error <= SOCKET_SAMPLE_TOLERANCE,
+            "{} against {}",
+            estimated.send_bytes,
+            sent
+        );
+    }
+
+    /// Replaying each interval's events gives exactly the sampled sockets,
+    /// each with every byte it sent
+    #[cfg(feature = "collector-core")]
+    #[test]
+    fn a_replay_returns_exactly_the_sampled_sockets() {
+        use crate::fixtures::udp_send;
+        use crate::{replay_per_socket, CollectorConfig, SAMPLER_PRIMARY, SAMPLER_SOCKET};
+        let sampling = SocketSampling::new(SOCKET_FRACTION);
+        let config = CollectorConfig::default().with_socket_sampling(SOCKET_FRACTION);
+        let admitted: Vec<u64> = (1..=SAMPLED_SOCKETS)
+            .filter(|&socket_id| sampling.admits(socket_id))
+            .collect();
+        for window in 0..4 {
+            let mut events = Vec::new();
+            for &socket_id in &admitted {
+                for send in 0..sends_of(socket_id) {
+                    let timestamp_ns = window * 1_000_000_000 + send;
+                    let mut event = udp_send(timestamp_ns, 0, socket_id, bytes_of(socket_id));
+                    event.data.sendmsg.samplers = SAMPLER_PRIMARY | SAMPLER_SOCKET;
+                    events.push(event);
+                }
+            }
+            let sockets = replay_per_socket(&events, &config);
+            assert_eq!(sockets.len(), admitted.len());
+            for socket_id in &admitted {
+                let socket = &sockets[socket_id];
+                assert!(socket.fully_sampled);
+                assert_eq!(
+                    socket.send_bytes,
+                    sends_of(*socket_id) * bytes_of(*socket_id)
+                );
+            }
+        }
+    }
+}
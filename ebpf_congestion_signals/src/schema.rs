@@ -0,0 +1,416 @@
+//Schema handshake with the eBPF object. The object carries a SchemaDescriptor
+//(CONGESTION_SCHEMA) describing the event layout it was built with; we read it
+//straight from the ELF before loading and compare it with our own mirror.
+
+use crate::{
+    CollectorError, CongestionEvent, NapiPollData, QdiscData, RcvSocketData, SendMsgData, SocketData,
+    RxSqueezeData, SocketLifecycleData, SoftirqData, TcpStateData, EVENT_NAPI_POLL, EVENT_NET_DEV_QUEUE, EVENT_QDISC_DROP, EVENT_RX_TIME_SQUEEZE,
+    EVENT_SOCKET_LIFECYCLE, EVENT_SOCKET_RCV_STATE, EVENT_SOCKET_STATE, EVENT_SOFTIRQ_ENTER, EVENT_SOFTIRQ_EXIT, EVENT_TCP_CWR, EVENT_TCP_RECOVERY, EVENT_TCP_RTO, EVENT_TCP_SEND,
+    EVENT_TCP_STATE, EVENT_TYPE_SLOTS, EVENT_UDP_RCV_CE, EVENT_UDP_RCV_DROP, EVENT_UDP_SEND,
+    SCHEMA_MAGIC, SCHEMA_SYMBOL, SCHEMA_VERSION,
+};
+use std::mem::size_of;
+
+// LAYOUT_HASH of the kernel-side types.rs our mirror follows, from build.rs
+include!(concat!(env!("OUT_DIR"), "/layout_hash.rs"));
+
+/// Must match MAX_EVENT_TYPES in the kernel-side types.rs
+pub const MAX_EVENT_TYPES: usize = 32;
+
+// Size of the pre-descriptor marker: [magic, version]
+const LEGACY_MARKER_LEN: usize = 8;
+
+/// Mirror of the kernel-side descriptor, see types.rs
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchemaDescriptor {
+    pub magic: u32,
+    pub version: u32,
+    pub layout_hash: u64,
+    pub record_size: u32,
+    pub event_type_count: u32,
+    pub payload_sizes: [u32; MAX_EVENT_TYPES],
+}
+
+fn payload_sizes() -> [u32; MAX_EVENT_TYPES] {
+    let mut sizes = [0u32; MAX_EVENT_TYPES];
+    sizes[EVENT_UDP_SEND as usize] = size_of::<SendMsgData>() as u32;
+    sizes[EVENT_TCP_SEND as usize] = size_of::<SendMsgData>() as u32;
+    sizes[EVENT_QDISC_DROP as usize] = size_of::<QdiscData>() as u32;
+    sizes[EVENT_SOCKET_STATE as usize] = size_of::<SocketData>() as u32;
+    sizes[EVENT_SOFTIRQ_ENTER as usize] = size_of::<SoftirqData>() as u32;
+    sizes[EVENT_SOFTIRQ_EXIT as usize] = size_of::<SoftirqData>() as u32;
+    sizes[EVENT_NET_DEV_QUEUE as usize] = size_of::<QdiscData>() as u32;
+    sizes[EVENT_UDP_RCV_DROP as usize] = size_of::<RcvSocketData>() as u32;
+    sizes[EVENT_SOCKET_RCV_STATE as usize] = size_of::<RcvSocketData>() as u32;
+    sizes[EVENT_TCP_STATE as usize] = size_of::<TcpStateData>() as u32;
+    sizes[EVENT_TCP_CWR as usize] = size_of::<TcpStateData>() as u32;
+    sizes[EVENT_UDP_RCV_CE as usize] = size_of::<RcvSocketData>() as u32;
+    sizes[EVENT_SOCKET_LIFECYCLE as usize] = size_of::<SocketLifecycleData>() as u32;
+    sizes[EVENT_RX_TIME_SQUEEZE as usize] = size_of::<RxSqueezeData>() as u32;
+    sizes[EVENT_TCP_RTO as usize] = size_of::<TcpStateData>() as u32;
+    sizes[EVENT_TCP_RECOVERY as usize] = size_of::<TcpStateData>() as u32;
+    sizes[EVENT_NAPI_POLL as usize] = size_of::<NapiPollData>() as u32;
+    sizes
+}
+
+/// Whether userspace knows how to decode this event type
+#[cfg(feature = "collector-core")]
+pub(crate) fn is_known_event(event_type: u32) -> bool {
+    payload_sizes()
+        .get(event_type as usize)
+        .is_some_and(|&size| size != 0)
+}
+
+impl SchemaDescriptor {
+    /// The descriptor this build of the collector expects
+    pub fn current() -> Self {
+        Self {
+            magic: SCHEMA_MAGIC,
+            version: SCHEMA_VERSION,
+            layout_hash: LAYOUT_HASH,
+            record_size: size_of::<CongestionEvent>() as u32,
+            event_type_count: EVENT_TYPE_SLOTS as u32,
+            payload_sizes: payload_sizes(),
+        }
+    }
+
+    /// Read CONGESTION_SCHEMA straight from the ELF, before anything gets loaded
+    /// into the kernel
+    pub fn from_object(bytecode: &[u8]) -> Result<Self, CollectorError> {
+        use object::{Object, ObjectSection, ObjectSymbol};
+
+        let obj = object::File::parse(bytecode).map_err(|_| CollectorError::MissingSchema)?;
+        let sym = obj
+            .symbols()
+            .find(|s| s.name() == Ok(SCHEMA_SYMBOL))
+            .ok_or(CollectorError::MissingSchema)?;
+        let section = sym
+            .section_index()
+            .and_then(|idx| obj.section_by_index(idx).ok())
+            .ok_or(CollectorError::MissingSchema)?;
+        let data = section.data().map_err(|_| CollectorError::MissingSchema)?;
+        let offset = (sym.address() - section.address()) as usize;
+
+        let word = |i: usize| -> Option<u32> {
+            let bytes = data.get(offset + i * 4..offset + i * 4 + 4)?;
+            Some(u32::from_le_bytes(bytes.try_into().ok()?))
+        };
+
+        let (magic, version) = match (word(0), word(1)) {
+            (Some(magic), Some(version)) => (magic, version),
+            _ => return Err(CollectorError::MissingSchema),
+        };
+        if magic != SCHEMA_MAGIC {
+            return Err(CollectorError::MissingSchema);
+        }
+
+        // Objects from before the descriptor only carry [magic, version], ones
+        // from before v13 a 16-slot payload table
+        if (sym.size() as usize) <= LEGACY_MARKER_LEN
+            || (sym.size() as usize) < size_of::<SchemaDescriptor>()
+        {
+            return Err(CollectorError::SchemaMismatch {
+                expected: SCHEMA_VERSION,
+                found: version,
+            });
+        }
+
+        let bytes = data
+            .get(offset..offset + size_of::<SchemaDescriptor>())
+            .ok_or(CollectorError::MissingSchema)?;
+        // SAFETY: length checked above, all fields are plain integers
+        Ok(unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const SchemaDescriptor) })
+    }
+
+    /// Decide whether events described by `self` (the object's descriptor) can
+    /// be decoded by this build.
+    ///
+    /// Same version: the layout hash must match too. Newer version: refused
+    /// unless `forward_compatible`, and even then every event type we know must
+    /// still have the payload size we expect. Older versions are always refused.
+    pub fn check_compatibility(&self, forward_compatible: bool) -> Result<(), CollectorError> {
+        let ours = Self::current();
+        if self.magic != ours.magic {
+            return Err(CollectorError::MissingSchema);
+        }
+
+        let incompatible = |reason: String| CollectorError::IncompatibleLayout {
+            version: self.version,
+            reason,
+        };
+
+        if self.version < ours.version || (self.version > ours.version && !forward_compatible) {
+            return Err(CollectorError::SchemaMismatch {
+                expected: ours.version,
+                found: self.version,
+            });
+        }
+
+        if self.record_size != ours.record_size {
+            return Err(incompatible(format!(
+                "record size {} bytes, expected {}",
+                self.record_size, ours.record_size
+            )));
+        }
+
+        for (event_type, (&theirs, &expected)) in self
+            .payload_sizes
+            .iter()
+            .zip(&ours.payload_sizes)
+            .enumerate()
+        {
+            // 0 on their side: type retired, we'll just never see it
+            if expected != 0 && theirs != 0 && theirs != expected {
+                return Err(incompatible(format!(
+                    "event type {} payload is {} bytes, expected {}",
+                    event_type, theirs, expected
+                )));
+            }
+        }
+
+        if self.version == ours.version && self.layout_hash != ours.layout_hash {
+            return Err(incompatible(format!(
+                "layout hash {:#x}, expected {:#x} (types changed without a SCHEMA_VERSION bump)",
+                self.layout_hash, ours.layout_hash
+            )));
+        }
+
+        if self.version > ours.version {
+            log::warn!(
+                "eBPF object schema {} is newer than ours ({}), unknown event types will be skipped",
+                self.version,
+                ours.version
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes_of(descriptor: &SchemaDescriptor) -> Vec<u8> {
+        // SAFETY: plain integers with no padding, see the repr(C) layout
+        unsafe {
+            std::slice::from_raw_parts(
+                descriptor as *const SchemaDescriptor as *const u8,
+                size_of::<SchemaDescriptor>(),
+            )
+        }
+        .to_vec()
+    }
+
+    /// A relocatable BPF ELF with `data` as its .data section and, when
+    /// `symbol` says so, a global of that name over all of it
+    fn object(data: &[u8], symbol: Option<&str>) -> Vec<u8> {
+        const EHDR: usize = 64;
+        const SHDR: usize = 64;
+        const SYM: usize = 24;
+        let mut strtab = vec![0u8];
+        let mut symtab = vec![0u8; SYM];
+        if let Some(name) = symbol {
+            let name_offset = strtab.len() as u32;
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+            symtab.extend_from_slice(&name_offset.to_le_bytes());
+            // STB_GLOBAL, STT_OBJECT
+            symtab.push(0x11);
+            symtab.push(0);
+            // In .data
+            symtab.extend_from_slice(&1u16.to_le_bytes());
+            symtab.extend_from_slice(&0u64.to_le_bytes());
+            symtab.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        }
+        let shstrtab = b"\0.data\0.symtab\0.strtab\0.shstrtab\0";
+
+        let mut elf = vec![0u8; EHDR];
+        let mut sections = vec![[0u8; SHDR]];
+        // name, type, flags, offset, size, link, info, entsize
+        let mut section = |elf: &mut Vec<u8>, fields: (u32, u32, u64, &[u8], u32, u32, u64)| {
+            let (name, kind, flags, contents, link, info, entsize) = fields;
+            let offset = elf.len() as u64;
+            elf.extend_from_slice(contents);
+            let mut header = [0u8; SHDR];
+            header[0..4].copy_from_slice(&name.to_le_bytes());
+            header[4..8].copy_from_slice(&kind.to_le_bytes());
+            header[8..16].copy_from_slice(&flags.to_le_bytes());
+            header[24..32].copy_from_slice(&offset.to_le_bytes());
+            header[32..40].copy_from_slice(&(contents.len() as u64).to_le_bytes());
+            header[40..44].copy_from_slice(&link.to_le_bytes());
+            header[44..48].copy_from_slice(&info.to_le_bytes());
+            header[48..56].copy_from_slice(&1u64.to_le_bytes());
+            header[56..64].copy_from_slice(&entsize.to_le_bytes());
+            sections.push(header);
+        };
+        // SHT_PROGBITS, SHF_WRITE | SHF_ALLOC
+        section(&mut elf, (1, 1, 3, data, 0, 0, 0));
+        // SHT_SYMTAB, linked to .strtab, every symbol past the null one global
+        section(&mut elf, (7, 2, 0, &symtab, 3, 1, SYM as u64));
+        // SHT_STRTAB
+        section(&mut elf, (15, 3, 0, &strtab, 0, 0, 0));
+        section(&mut elf, (23, 3, 0, shstrtab, 0, 0, 0));
+
+        while !elf.len().is_multiple_of(8) {
+            elf.push(0);
+        }
+        let shoff = elf.len() as u64;
+        for header in &sections {
+            elf.extend_from_slice(header);
+        }
+        elf[0..4].copy_from_slice(b"\x7fELF");
+        // 64-bit, little-endian, version 1
+        elf[4..7].copy_from_slice(&[2, 1, 1]);
+        // ET_REL, EM_BPF, version 1
+        elf[16..18].copy_from_slice(&1u16.to_le_bytes());
+        elf[18..20].copy_from_slice(&247u16.to_le_bytes());
+        elf[20..24].copy_from_slice(&1u32.to_le_bytes());
+        elf[40..48].copy_from_slice(&shoff.to_le_bytes());
+        elf[52..54].copy_from_slice(&(EHDR as u16).to_le_bytes());
+        elf[58..60].copy_from_slice(&(SHDR as u16).to_le_bytes());
+        elf[60..62].copy_from_slice(&(sections.len() as u16).to_le_bytes());
+        elf[62..64].copy_from_slice(&4u16.to_le_bytes());
+        elf
+    }
+
+    fn newer() -> SchemaDescriptor {
+        SchemaDescriptor {
+            version: SCHEMA_VERSION + 1,
+            layout_hash: !LAYOUT_HASH,
+            ..SchemaDescriptor::current()
+        }
+    }
+
+    #[test]
+    fn the_descriptor_is_read_from_the_object() {
+        let ours = SchemaDescriptor::current();
+        let found = SchemaDescriptor::from_object(&object(&bytes_of(&ours), Some(SCHEMA_SYMBOL)));
+        assert_eq!(found, Ok(ours));
+        assert_eq!(ours.check_compatibility(false), Ok(()));
+    }
+
+    #[test]
+    fn objects_without_a_descriptor_are_not_ours() {
+        let ours = bytes_of(&SchemaDescriptor::current());
+        for bytecode in [
+            object(&ours, None),
+            object(&ours, Some("SOMETHING_ELSE")),
+            object(&[0u8; size_of::<SchemaDescriptor>()], Some(SCHEMA_SYMBOL)),
+            b"not an ELF".to_vec(),
+        ] {
+            assert_eq!(
+                SchemaDescriptor::from_object(&bytecode),
+                Err(CollectorError::MissingSchema)
+            );
+        }
+    }
+
+    #[test]
+    fn a_legacy_marker_names_its_version() {
+        let mut marker = SCHEMA_MAGIC.to_le_bytes().to_vec();
+        marker.extend_from_slice(&3u32.to_le_bytes());
+        assert_eq!(
+            SchemaDescriptor::from_object(&object(&marker, Some(SCHEMA_SYMBOL))),
+            Err(CollectorError::SchemaMismatch {
+                expected: SCHEMA_VERSION,
+                found: 3
+            })
+        );
+    }
+
+    #[test]
+    fn a_newer_object_needs_forward_compatibility() {
+        let theirs = newer();
+        let bytecode = object(&bytes_of(&theirs), Some(SCHEMA_SYMBOL));
+        let theirs = SchemaDescriptor::from_object(&bytecode).unwrap();
+
+        let err = theirs.check_compatibility(false).unwrap_err();
+        assert_eq!(
+            err,
+            CollectorError::SchemaMismatch {
+                expected: SCHEMA_VERSION,
+                found: SCHEMA_VERSION + 1
+            }
+        );
+        assert!(err.to_string().contains(&SCHEMA_VERSION.to_string()));
+        // The layout hash only binds the same version
+        assert_eq!(theirs.check_compatibility(true), Ok(()));
+    }
+
+    #[test]
+    fn a_newer_object_may_add_and_retire_event_types() {
+        let mut theirs = newer();
+        let unused = theirs
+            .payload_sizes
+            .iter()
+            .position(|&size| size == 0)
+            .unwrap();
+        theirs.payload_sizes[unused] = 48;
+        theirs.payload_sizes[EVENT_NAPI_POLL as usize] = 0;
+        assert_eq!(theirs.check_compatibility(true), Ok(()));
+    }
+
+    #[test]
+    fn an_older_object_is_always_refused() {
+        let theirs = SchemaDescriptor {
+            version: SCHEMA_VERSION - 1,
+            ..SchemaDescriptor::current()
+        };
+        for forward_compatible in [false, true] {
+            assert_eq!(
+                theirs.check_compatibility(forward_compatible),
+                Err(CollectorError::SchemaMismatch {
+                    expected: SCHEMA_VERSION,
+                    found: SCHEMA_VERSION - 1
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn layout_changes_are_incompatible_even_forward() {
+        let mut resized = newer();
+        resized.payload_sizes[EVENT_UDP_SEND as usize] += 8;
+        let longer = SchemaDescriptor {
+            record_size: SchemaDescriptor::current().record_size + 8,
+            ..newer()
+        };
+        let rehashed = SchemaDescriptor {
+            layout_hash: !LAYOUT_HASH,
+            ..SchemaDescriptor::current()
+        };
+        for theirs in [resized, longer] {
+            assert!(matches!(
+                theirs.check_compatibility(true),
+                Err(CollectorError::IncompatibleLayout { version, .. }) if version == SCHEMA_VERSION + 1
+            ));
+        }
+        assert!(matches!(
+            rehashed.check_compatibility(true),
+            Err(CollectorError::IncompatibleLayout { reason, .. }) if reason.contains("layout hash")
+        ));
+    }
+
+    #[test]
+    fn a_foreign_magic_is_not_ours() {
+        let theirs = SchemaDescriptor {
+            magic: !SCHEMA_MAGIC,
+            ..SchemaDescriptor::current()
+        };
+        assert_eq!(
+            theirs.check_compatibility(true),
+            Err(CollectorError::MissingSchema)
+        );
+    }
+
+    #[cfg(feature = "collector-core")]
+    #[test]
+    fn only_described_event_types_are_known() {
+        assert!(is_known_event(EVENT_UDP_SEND));
+        assert!(is_known_event(EVENT_NAPI_POLL));
+        assert!(!is_known_event(MAX_EVENT_TYPES as u32 - 1));
+        assert!(!is_known_event(u32::MAX));
+    }
+}
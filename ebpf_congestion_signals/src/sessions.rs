@@ -0,0 +1,670 @@
+//Labelled measurement sessions, for experiments that flip something for a while
+//("GSO batch size 45 for 10 minutes") and want the signals of just that window.
+//Every interval read is folded into each open session as it happens, so a
+//session can run far longer than the history ring holds and any number of them
+//can overlap or nest. Sessions cover whole intervals: the first read after
+//begin_session() through the last one before end(). Nothing reads on its own,
+//so a session only sees intervals someone read (read_and_reset() or a snapshot
+//stream).
+
+use crate::collector::IntervalReader;
+use crate::extensions::merge_snapshot;
+use crate::json::{json_string, unix_ms};
+use crate::{
+    CongestionSignals, CongestionState, DropInterarrival, InterfaceEgress, InterfaceTxq,
+    Limitation, LimitationThresholds, SampleScale, SamplerEstimates, SendSizes, SeverityClassifier,
+    SocketSampling, SoftirqAttribution, SoftirqCoupling, EXPORT_SCHEMA_VERSION,
+};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+// Reports of sessions whose handle was dropped, kept for take_session_reports()
+const ENDED_REPORTS: usize = 64;
+
+/// An open session, see `CongestionCollector::begin_session`. `end()` returns
+/// its report; dropping it instead keeps the report for
+/// `CongestionCollector::take_session_reports`
+pub struct SessionHandle {
+    reader: Arc<IntervalReader>,
+    id: u64,
+    label: String,
+    ended: bool,
+}
+
+impl SessionHandle {
+    pub(crate) fn begin(reader: Arc<IntervalReader>, label: &str) -> Self {
+        let at = reader.clock.system_time();
+        let id = reader.sessions().lock().unwrap().begin(label, at);
+        Self {
+            reader,
+            id,
+            label: label.to_string(),
+            ended: false,
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Close the session and report on it
+    pub fn end(mut self) -> SessionReport {
+        self.ended = true;
+        self.close()
+    }
+
+    fn close(&self) -> SessionReport {
+        let limitation = self.reader.limitation_thresholds();
+        let at = self.reader.clock.system_time();
+        self.reader
+            .sessions()
+            .lock()
+            .unwrap()
+            .end(self.id, limitation, at)
+            .unwrap_or_else(|| SessionReport::empty(&self.label, at))
+    }
+}
+
+impl Drop for SessionHandle {
+    fn drop(&mut self) {
+        if self.ended {
+            return;
+        }
+        let report = self.close();
+        log::info!("session ended: {}", report.to_json());
+        self.reader.sessions().lock().unwrap().keep(report);
+    }
+}
+
+/// A session's intervals merged into one
+#[derive(Debug, Clone)]
+pub struct SessionReport {
+    pub label: String,
+    /// When `begin_session` and `end()` (or the drop) were called
+    pub started_at: SystemTime,
+    pub ended_at: SystemTime,
+    /// Interval reads merged into `signals`
+    pub intervals: u64,
+    /// Counters summed over the session's intervals, pressures and fractions
+    /// averaged over them by time, `active_sockets`, `active_flows` and
+    /// `tcp_sockets_below_ssthresh` the most of any interval. `sessions` is
+    /// just this label
+    pub signals: CongestionSignals,
+    /// Changes of the `CongestionState` a default `SeverityClassifier` run over
+    /// the session's intervals went through, with the interval they came on.
+    /// The state starts at Green
+    pub transitions: Vec<StateTransition>,
+}
+
+/// See `SessionReport::transitions`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateTransition {
+    /// Wall-clock time of the interval read that caused it
+    pub at: SystemTime,
+    pub from: CongestionState,
+    pub to: CongestionState,
+}
+
+impl SessionReport {
+    fn empty(label: &str, at: SystemTime) -> Self {
+        Self {
+            label: label.to_string(),
+            started_at: at,
+            ended_at: at,
+            intervals: 0,
+            signals: CongestionSignals::default(),
+            transitions: Vec::new(),
+        }
+    }
+
+    /// The report as one JSON object, no trailing newline
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        let _ = write!(
+            out,
+            "{{\"schema_version\":{},\"label\":{},\"started_at_ms\":{},\"ended_at_ms\":{},\"intervals\":{},\
+             \"transitions\":[",
+            EXPORT_SCHEMA_VERSION,
+            json_string(&self.label),
+            unix_ms(self.started_at),
+            unix_ms(self.ended_at),
+            self.intervals,
+        );
+        for (i, t) in self.transitions.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let reasons: Vec<String> =
+                t.to.reasons()
+                    .iter()
+                    .map(|r| format!("\"{}\"", r.label()))
+                    .collect();
+            let _ = write!(
+                out,
+                "{{\"at_ms\":{},\"from\":{},\"to\":{},\"reasons\":[{}]}}",
+                unix_ms(t.at),
+                t.from.level(),
+                t.to.level(),
+                reasons.join(","),
+            );
+        }
+        let _ = write!(out, "],\"signals\":{}}}", self.signals.to_json());
+        out
+    }
+}
+
+/// Every open session, in `IntervalReader`
+#[derive(Default)]
+pub(crate) struct Sessions {
+    next_id: u64,
+    open: HashMap<u64, OpenSession>,
+    ended: VecDeque<SessionReport>,
+}
+
+impl Sessions {
+    fn begin(&mut self, label: &str, at: SystemTime) -> u64 {
+        self.next_id += 1;
+        self.open.insert(self.next_id, OpenSession::new(label, at));
+        self.next_id
+    }
+
+    fn end(
+        &mut self,
+        id: u64,
+        limitation: &LimitationThresholds,
+        at: SystemTime,
+    ) -> Option<SessionReport> {
+        self.open
+            .remove(&id)
+            .map(|session| session.report(limitation, at))
+    }
+
+    fn keep(&mut self, report: SessionReport) {
+        if self.ended.len() == ENDED_REPORTS {
+            self.ended.pop_front();
+        }
+        self.ended.push_back(report);
+    }
+
+    pub(crate) fn take_ended(&mut self) -> Vec<SessionReport> {
+        self.ended.drain(..).collect()
+    }
+
+    /// Fold one interval read at `at` into every open session, returning their
+    /// labels in the order they were begun
+    pub(crate) fn fold(&mut self, signals: &CongestionSignals, at: SystemTime) -> Vec<String> {
+        if self.open.is_empty() {
+            return Vec::new();
+        }
+        let mut open: Vec<(&u64, &mut OpenSession)> = self.open.iter_mut().collect();
+        open.sort_unstable_by_key(|(id, _)| **id);
+        open.into_iter()
+            .map(|(_, session)| {
+                session.fold(signals, at);
+                session.label.clone()
+            })
+            .collect()
+    }
+}
+
+struct OpenSession {
+    label: String,
+    started_at: SystemTime,
+    intervals: u64,
+    merged: Merged,
+    classifier: SeverityClassifier,
+    transitions: Vec<StateTransition>,
+}
+
+impl OpenSession {
+    fn new(label: &str, at: SystemTime) -> Self {
+        Self {
+            label: label.to_string(),
+            started_at: at,
+            intervals: 0,
+            merged: Merged::default(),
+            classifier: SeverityClassifier::default(),
+            transitions: Vec::new(),
+        }
+    }
+
+    /// `at` unless the interval was aligned
+    fn fold(&mut self, signals: &CongestionSignals, at: SystemTime) {
+        self.intervals += 1;
+        self.merged.add(signals);
+        let from = self.classifier.state().clone();
+        let to = self.classifier.update(signals);
+        if to != from {
+            self.transitions.push(StateTransition {
+                at: signals.aligned_end.unwrap_or(at),
+                from,
+                to,
+            });
+        }
+    }
+
+    fn report(self, limitation: &LimitationThresholds, at: SystemTime) -> SessionReport {
+        let mut signals = self.merged.finish(limitation);
+        signals.sessions = vec![self.label.clone()];
+        SessionReport {
+            label: self.label,
+            started_at: self.started_at,
+            ended_at: at,
+            intervals: self.intervals,
+            signals,
+            transitions: self.transitions,
+        }
+    }
+}
+
+/// Time-weighted mean of a value over the intervals that had one
+#[derive(Default)]
+struct Mean {
+    sum: f64,
+    weight: f64,
+}
+
+impl Mean {
+    fn add(&mut self, value: Option<f64>, weight: f64) {
+        if let Some(value) = value.filter(|v| v.is_finite()) {
+            self.sum += value * weight;
+            self.weight += weight;
+        }
+    }
+
+    fn get(&self) -> Option<f64> {
+        (self.weight > 0.0).then(|| self.sum / self.weight)
+    }
+}
+
+/// Running merge of intervals: the sums go straight into `signals`, the
+/// averages are kept as weighted sums until `finish`
+#[derive(Default)]
+struct Merged {
+    signals: CongestionSignals,
+    wmem: Mean,
+    udp_wmem: Mean,
+    tcp_wmem: Mean,
+    rmem: Mean,
+    softirq_fraction: Mean,
+    tcp_cwnd: Mean,
+    tcp_ssthresh: Mean,
+    tcp_pacing_rate: Mean,
+    socket_pacing_rate: Mean,
+    // Weighted by sampled send bytes
+    kernel_paced_share: Mean,
+    // Weighted by drops, it's a share of them
+    burst_drop_correlation: Mean,
+}
+
+impl Merged {
+    fn add(&mut self, next: &CongestionSignals) {
+        let m = &mut self.signals;
+        let weight = next.interval_ns as f64;
+        if m.interval_ns == 0 {
+            m.aligned_start = next.aligned_start;
+        }
+        m.aligned_end = next.aligned_end;
+        m.produced_at = next.produced_at;
+        m.interval_ns += next.interval_ns;
+
+        m.observed.events_received += next.observed.events_received;
+        m.observed.drops += next.observed.drops;
+        m.observed.udp_rcv_drops += next.observed.udp_rcv_drops;
+        m.observed.softirq_exits += next.observed.softirq_exits;
+        // Each interval's estimate at its own factor; the scale is the whole
+        // session's coverage
+        m.estimated.send_bytes += next.estimated.send_bytes;
+        m.estimated.external_send_bytes += next.estimated.external_send_bytes;
+        m.estimated.send_packets += next.estimated.send_packets;
+        let mut sends_seen = m.estimated.scale.sends_seen;
+        add_opt(&mut sends_seen, next.estimated.scale.sends_seen);
+        let sends_sampled = m.estimated.scale.sends_sampled + next.estimated.scale.sends_sampled;
+        m.estimated.scale = match next.estimated.scale.socket_fraction {
+            Some(fraction) => {
+                SampleScale::per_socket(sends_seen, sends_sampled, SocketSampling::new(fraction))
+            }
+            None => SampleScale::from_coverage(sends_seen, sends_sampled),
+        };
+        m.send_bytes += next.send_bytes;
+        add_opt(&mut m.loopback_send_bytes, next.loopback_send_bytes);
+        m.external_send_bytes += next.external_send_bytes;
+        if let Some(next) = &next.sampler_comparison {
+            let total = m.sampler_comparison.get_or_insert(SamplerEstimates {
+                candidate_ratio: next.candidate_ratio,
+                ..Default::default()
+            });
+            total.primary_send_bytes += next.primary_send_bytes;
+            total.candidate_send_bytes += next.candidate_send_bytes;
+            total.candidate_samples += next.candidate_samples;
+        }
+        if let Some(next) = &next.softirq_coupling {
+            let total = m.softirq_coupling.get_or_insert(SoftirqCoupling::default());
+            // Weighted by delayed sends, it's an average over them
+            let delayed = total.sends_delayed_by_softirq + next.sends_delayed_by_softirq;
+            let delay_ns = total.avg_softirq_delay_ns * total.sends_delayed_by_softirq
+                + next.avg_softirq_delay_ns * next.sends_delayed_by_softirq;
+            total.avg_softirq_delay_ns = delay_ns.checked_div(delayed).unwrap_or(0);
+            total.sends_checked += next.sends_checked;
+            total.sends_delayed_by_softirq = delayed;
+            total.sends_in_softirq += next.sends_in_softirq;
+        }
+        if let Some(next) = &next.softirq_attribution {
+            let total = m
+                .softirq_attribution
+                .get_or_insert(SoftirqAttribution::default());
+            // Weighted by NET_TX time, the confidence is a share of it
+            let before_ns = total.self_induced_softirq_ns + total.external_softirq_ns;
+            let next_ns = next.self_induced_softirq_ns + next.external_softirq_ns;
+            if before_ns + next_ns > 0 {
+                total.confidence = (total.confidence * before_ns as f64
+                    + next.confidence * next_ns as f64)
+                    / (before_ns + next_ns) as f64;
+            }
+            total.self_induced_softirq_ns += next.self_induced_softirq_ns;
+            total.external_softirq_ns += next.external_softirq_ns;
+            total.rounds += next.rounds;
+            total.covered_rounds += next.covered_rounds;
+        }
+        if let Some(next) = &next.drop_interarrival {
+            m.drop_interarrival
+                .get_or_insert(DropInterarrival::default())
+                .add(next);
+        }
+        add_sizes(&mut m.send_size_stats.udp, &next.send_size_stats.udp);
+        add_sizes(&mut m.send_size_stats.tcp, &next.send_size_stats.tcp);
+        m.est_send_msgs.add(&next.est_send_msgs);
+        m.est_wire_packets.add(&next.est_wire_packets);
+        if let Some(next) = &next.udp_gso {
+            m.udp_gso.get_or_insert_with(Default::default).add(next);
+        }
+        m.drops += next.drops;
+        m.softirq_ns += next.softirq_ns;
+        m.event_count += next.event_count;
+        // Distinct per interval, they don't add up
+        m.active_sockets = m.active_sockets.max(next.active_sockets);
+        m.active_flows = m.active_flows.max(next.active_flows);
+        m.new_connections_per_interval += next.new_connections_per_interval;
+        m.closed_connections_per_interval += next.closed_connections_per_interval;
+        let (total, next_churn) = (&mut m.connections, &next.connections);
+        add_opt(&mut total.tcp_active_established, next_churn.tcp_active_established);
+        add_opt(&mut total.tcp_passive_established, next_churn.tcp_passive_established);
+        add_opt(&mut total.tcp_accepts, next_churn.tcp_accepts);
+        add_opt(&mut total.tcp_closed, next_churn.tcp_closed);
+        total.udp_first_seen += next_churn.udp_first_seen;
+        m.queue_depth_packets += next.queue_depth_packets;
+        m.queue_depth_bytes += next.queue_depth_bytes;
+        m.udp_rcv_drops += next.udp_rcv_drops;
+        m.tcp_sockets_below_ssthresh = match (
+            m.tcp_sockets_below_ssthresh,
+            next.tcp_sockets_below_ssthresh,
+        ) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+        add_opt(&mut m.ce_marks, next.ce_marks);
+        add_opt(&mut m.ce_triggered_cwr, next.ce_triggered_cwr);
+        add_opt(&mut m.rto_events, next.rto_events);
+        add_opt(&mut m.loss_recovery_episodes, next.loss_recovery_episodes);
+        add_opt(&mut m.rx_time_squeeze, next.rx_time_squeeze);
+        m.implausible_socket_samples += next.implausible_socket_samples;
+        add_opt(&mut m.tsq_throttles, next.tsq_throttles);
+        // A level, not a count: the session's peak
+        m.net_memory_pressure = match (m.net_memory_pressure, next.net_memory_pressure) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+        add_opt(&mut m.memory_pressure_events, next.memory_pressure_events);
+        m.softirq_discarded += next.softirq_discarded;
+        m.degradation_level = m.degradation_level.max(next.degradation_level);
+        for signal in &next.missing_signals {
+            if !m.missing_signals.contains(signal) {
+                m.missing_signals.push(*signal);
+            }
+        }
+        for egress in &next.egress {
+            match m
+                .egress
+                .iter_mut()
+                .find(|e| e.interface == egress.interface)
+            {
+                Some(total) => {
+                    total.egress_bytes_exact += egress.egress_bytes_exact;
+                    total.egress_packets_exact += egress.egress_packets_exact;
+                }
+                None => m.egress.push(InterfaceEgress {
+                    interface: egress.interface.clone(),
+                    egress_bytes_exact: egress.egress_bytes_exact,
+                    egress_packets_exact: egress.egress_packets_exact,
+                }),
+            }
+        }
+        add_opt(&mut m.txq_stalls, next.txq_stalls);
+        add_opt(&mut m.txq_stalled_ns, next.txq_stalled_ns);
+        for txq in &next.txq_by_interface {
+            match m
+                .txq_by_interface
+                .iter_mut()
+                .find(|t| (t.ifindex, t.netns) == (txq.ifindex, txq.netns))
+            {
+                Some(total) => {
+                    total.txq_stalls += txq.txq_stalls;
+                    total.txq_stalled_ns += txq.txq_stalled_ns;
+                    total.xmit_busy += txq.xmit_busy;
+                }
+                None => m.txq_by_interface.push(InterfaceTxq {
+                    interface: txq.interface.clone(),
+                    ..*txq
+                }),
+            }
+        }
+        m.captures.extend(next.captures.iter().cloned());
+        for (name, snapshot) in &next.extensions {
+            match m.extensions.get_mut(name) {
+                Some(total) => merge_snapshot(total, snapshot),
+                None => {
+                    m.extensions.insert(name.clone(), snapshot.clone());
+                }
+            }
+        }
+
+        self.wmem.add(Some(next.avg_wmem_pressure), weight);
+        self.udp_wmem.add(next.udp_wmem_pressure, weight);
+        self.tcp_wmem.add(next.tcp_wmem_pressure, weight);
+        self.rmem.add(Some(next.avg_rmem_pressure), weight);
+        self.softirq_fraction
+            .add(Some(next.softirq_cpu_fraction), weight);
+        self.tcp_cwnd.add(next.tcp_avg_cwnd, weight);
+        self.tcp_ssthresh.add(next.tcp_avg_ssthresh, weight);
+        self.tcp_pacing_rate.add(next.tcp_avg_pacing_rate, weight);
+        self.socket_pacing_rate
+            .add(next.avg_socket_pacing_rate, weight);
+        self.kernel_paced_share
+            .add(next.kernel_paced_send_share, next.send_bytes as f64);
+        self.burst_drop_correlation
+            .add(next.burst_drop_correlation, next.drops as f64);
+    }
+
+    fn finish(self, limitation: &LimitationThresholds) -> CongestionSignals {
+        let mut signals = self.signals;
+        signals.avg_wmem_pressure = self.wmem.get().unwrap_or(0.0);
+        signals.udp_wmem_pressure = self.udp_wmem.get();
+        signals.tcp_wmem_pressure = self.tcp_wmem.get();
+        signals.avg_rmem_pressure = self.rmem.get().unwrap_or(0.0);
+        signals.softirq_cpu_fraction = self.softirq_fraction.get().unwrap_or(0.0);
+        // Weighted means of the floats, not the intervals' integer sums
+        signals.fixed = None;
+        signals.tcp_avg_cwnd = self.tcp_cwnd.get();
+        signals.tcp_avg_ssthresh = self.tcp_ssthresh.get();
+        signals.tcp_avg_pacing_rate = self.tcp_pacing_rate.get();
+        signals.avg_socket_pacing_rate = self.socket_pacing_rate.get();
+        signals.kernel_paced_send_share = self.kernel_paced_share.get();
+        signals.kernel_paced = limitation
+            .kernel_paced
+            .interval_paced(signals.kernel_paced_send_share);
+        signals.burst_drop_correlation = self.burst_drop_correlation.get();
+
+        let egress_bytes: u64 = signals.egress.iter().map(|e| e.egress_bytes_exact).sum();
+        signals.egress_estimate_error = (egress_bytes > 0)
+            .then(|| signals.estimated.external_send_bytes as f64 / egress_bytes as f64 - 1.0);
+        signals.limitation = Limitation::classify(&signals, limitation);
+        signals
+    }
+}
+
+fn add_opt(total: &mut Option<u64>, value: Option<u64>) {
+    if let Some(value) = value {
+        *total = Some(total.unwrap_or(0) + value);
+    }
+}
+
+fn add_sizes(total: &mut Option<SendSizes>, sizes: &Option<SendSizes>) {
+    let Some(sizes) = sizes else {
+        return;
+    };
+    let Some(total) = total else {
+        *total = Some(sizes.clone());
+        return;
+    };
+    let samples = total.samples + sizes.samples;
+    if samples > 0 {
+        total.avg_bytes = (total.avg_bytes * total.samples as f64
+            + sizes.avg_bytes * sizes.samples as f64)
+            / samples as f64;
+    }
+    total.samples = samples;
+    total.min_bytes = total.min_bytes.min(sizes.min_bytes);
+    total.max_bytes = total.max_bytes.max(sizes.max_bytes);
+    for (bucket, n) in total.histogram.iter_mut().zip(sizes.histogram) {
+        *bucket += n;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, Replay};
+    use std::time::Duration;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn replay() -> Replay {
+        Replay::new(crate::CollectorConfig::default(), 1)
+    }
+
+    fn begin(replay: &Replay, label: &str) -> SessionHandle {
+        SessionHandle::begin(replay.interval.clone(), label)
+    }
+
+    #[test]
+    fn overlapping_sessions_merge_just_the_reads_they_were_open_for() {
+        let replay = replay();
+        replay.feed(&[fixtures::udp_send(1, 0, 7, 9000)]);
+        // Read before either began
+        replay.read(SECOND);
+
+        let outer = begin(&replay, "outer");
+        replay.feed(&[fixtures::udp_send(2, 0, 7, 1200)]);
+        let first = replay.read(SECOND);
+        let inner = begin(&replay, "inner");
+        replay.feed(&[fixtures::udp_send(3, 0, 7, 800)]);
+        let second = replay.read(SECOND * 2);
+        let inner = inner.end();
+        replay.feed(&[fixtures::udp_send(4, 0, 7, 500)]);
+        let third = replay.read(SECOND);
+        let outer = outer.end();
+
+        assert_eq!(first.sessions, ["outer"]);
+        assert_eq!(second.sessions, ["outer", "inner"]);
+        assert_eq!(third.sessions, ["outer"]);
+
+        assert_eq!(outer.intervals, 3);
+        assert_eq!(outer.signals.interval_ns, 4_000_000_000);
+        assert_eq!(outer.signals.send_bytes, 2500);
+        assert_eq!(outer.signals.sessions, ["outer"]);
+        assert_eq!(inner.intervals, 1);
+        assert_eq!(inner.signals.interval_ns, 2_000_000_000);
+        assert_eq!(inner.signals.send_bytes, 800);
+        assert_eq!(inner.signals.sessions, ["inner"]);
+        // On the replay's clock, from begin to end
+        let span = |report: &SessionReport| report.ended_at.duration_since(report.started_at);
+        assert_eq!(span(&outer).unwrap(), SECOND * 4);
+        assert_eq!(span(&inner).unwrap(), SECOND * 2);
+        let after_outer = inner.started_at.duration_since(outer.started_at);
+        assert_eq!(after_outer.unwrap(), SECOND);
+    }
+
+    #[test]
+    fn pressures_average_over_the_session_by_time() {
+        let replay = replay();
+        let session = begin(&replay, "mean");
+        // Half full for three seconds, then empty for one
+        replay.feed(&[fixtures::socket_state(1, 0, 7, 100_000, 200_000)]);
+        let busy = replay.read(SECOND * 3);
+        replay.feed(&[fixtures::socket_state(2, 0, 7, 0, 200_000)]);
+        let quiet = replay.read(SECOND);
+        let report = session.end();
+
+        let expected = (busy.avg_wmem_pressure * 3.0 + quiet.avg_wmem_pressure) / 4.0;
+        assert!(busy.avg_wmem_pressure > quiet.avg_wmem_pressure);
+        assert!((report.signals.avg_wmem_pressure - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn a_dropped_session_keeps_its_report() {
+        let replay = replay();
+        let outer = begin(&replay, "outer");
+        let nested = begin(&replay, "nested");
+        replay.feed(&[fixtures::udp_send(1, 0, 7, 1200)]);
+        replay.read(SECOND);
+        drop(nested);
+        replay.read(SECOND);
+
+        let ended = replay.interval.sessions().lock().unwrap().take_ended();
+        assert_eq!(ended.len(), 1);
+        assert_eq!(ended[0].label, "nested");
+        assert_eq!(ended[0].intervals, 1);
+        assert_eq!(ended[0].signals.send_bytes, 1200);
+        assert_eq!(outer.end().intervals, 2);
+        // Taken once
+        assert!(replay
+            .interval
+            .sessions()
+            .lock()
+            .unwrap()
+            .take_ended()
+            .is_empty());
+    }
+
+    #[test]
+    fn a_session_with_no_reads_reports_empty() {
+        let replay = replay();
+        let report = begin(&replay, "idle").end();
+        assert_eq!(report.intervals, 0);
+        assert_eq!(report.signals.send_bytes, 0);
+        assert!(report.transitions.is_empty());
+        // And the next read isn't labelled with it
+        assert!(replay.read(SECOND).sessions.is_empty());
+    }
+
+    #[test]
+    fn transitions_are_the_sessions_own() {
+        let replay = replay();
+        let early = begin(&replay, "early");
+        replay.feed(&[fixtures::qdisc_drop(1, 0, 100, 0)]);
+        replay.read(SECOND);
+        // Begun with the drops already read: Red to early, nothing to late
+        let late = begin(&replay, "late");
+        replay.read(SECOND);
+
+        let early = early.end();
+        let late = late.end();
+        assert_eq!(early.transitions.len(), 1);
+        assert_eq!(early.transitions[0].from, CongestionState::Green);
+        assert!(matches!(early.transitions[0].to, CongestionState::Red(_)));
+        assert!(late.transitions.is_empty());
+        assert!(early.to_json().contains("\"transitions\":[{\"at_ms\":"));
+    }
+}
@@ -0,0 +1,485 @@
+//A tri-state to page on, for when a score is one number too many. Each interval
+//is judged against a few thresholds: Red for drops or send buffers that filled
+//up, Yellow for send buffer pressure that stays high, TX queues that stay
+//stopped long enough for what's queued behind them to wait, or a queue building
+//with nothing dropped (bufferbloat.rs). A raised state drops back only after
+//`demote_after` calmer intervals in a row, so a state that flips every interval
+//doesn't page twice. Fed fast snapshots too (`observe_fast`), a raised state
+//also says whether it was a microburst, sustained or periodic (microburst.rs).
+//Told how a governor's cuts work out (`observe_effectiveness`), it adds that
+//the congestion is non-responsive while cutting doesn't relieve it.
+
+use crate::{
+    BufferbloatConfig, BufferbloatDetector, BurstClass, BurstClassifier, BurstConfig,
+    CongestionSignals, Effectiveness, FastSignals,
+};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// How congested the host is, with what made it so. See [`SeverityClassifier`]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum CongestionState {
+    #[default]
+    Green,
+    Yellow(Vec<Reason>),
+    Red(Vec<Reason>),
+}
+
+impl CongestionState {
+    /// 0 for Green, 1 for Yellow, 2 for Red
+    pub fn level(&self) -> u8 {
+        match self {
+            Self::Green => 0,
+            Self::Yellow(_) => 1,
+            Self::Red(_) => 2,
+        }
+    }
+
+    /// Empty for Green
+    pub fn reasons(&self) -> &[Reason] {
+        match self {
+            Self::Green => &[],
+            Self::Yellow(reasons) | Self::Red(reasons) => reasons,
+        }
+    }
+
+    fn at(level: u8, reasons: Vec<Reason>) -> Self {
+        match level {
+            0 => Self::Green,
+            1 => Self::Yellow(reasons),
+            _ => Self::Red(reasons),
+        }
+    }
+}
+
+/// One threshold a [`CongestionState`] crossed, with the value that crossed it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+    /// 95th percentile of `avg_wmem_pressure` over the last `window` intervals
+    WmemPressure {
+        p95: f64,
+    },
+    /// How long TX queues stayed stopped on average, which is what packets in
+    /// the qdisc behind them wait out
+    QueueDelay {
+        ms: f64,
+    },
+    DropRate {
+        per_sec: f64,
+    },
+    /// Sampled send buffers averaged this full: sends block or fail with EAGAIN
+    SndbufStall {
+        pressure: f64,
+    },
+    /// The qdisc backlog grew by `growth` over the bufferbloat window to
+    /// `backlog_bytes` with no drops, or TX queues stayed stopped throughout,
+    /// see [`BufferbloatDetector`]. Latency climbs without any loss to show it
+    Bufferbloat {
+        backlog_bytes: u64,
+        growth: f64,
+    },
+    /// What kind of episode the interval's fast snapshots showed, next to the
+    /// reasons the state is named for; see [`BurstClassifier`]. Only with
+    /// `SeverityClassifier::observe_fast`
+    Burst {
+        class: BurstClass,
+        pressured_fraction: f64,
+    },
+    /// Cutting our rate hasn't relieved it: only `improved_fraction` of the
+    /// governor's latest cuts did, so it's coming from elsewhere. Next to the
+    /// reasons the state is named for, only with
+    /// `SeverityClassifier::observe_effectiveness`
+    NonResponsive {
+        improved_fraction: f64,
+    },
+}
+
+impl Reason {
+    /// Short name, e.g. for a metric tag
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::WmemPressure { .. } => "wmem_pressure",
+            Self::QueueDelay { .. } => "queue_delay",
+            Self::DropRate { .. } => "drop_rate",
+            Self::SndbufStall { .. } => "sndbuf_stall",
+            Self::Bufferbloat { .. } => "bufferbloat",
+            Self::Burst { class, .. } => class.label(),
+            Self::NonResponsive { .. } => "non_responsive",
+        }
+    }
+
+    fn level(&self) -> u8 {
+        match self {
+            Self::WmemPressure { .. }
+            | Self::QueueDelay { .. }
+            | Self::Bufferbloat { .. }
+            | Self::Burst { .. }
+            | Self::NonResponsive { .. } => 1,
+            Self::DropRate { .. } | Self::SndbufStall { .. } => 2,
+        }
+    }
+}
+
+/// When intervals count as Yellow or Red
+#[derive(Debug, Clone)]
+pub struct SeverityThresholds {
+    /// Yellow above this p95 of send buffer pressure over `window` intervals
+    pub yellow_wmem_p95: f64,
+    /// Yellow above this average TX queue stop, needs the txq probes
+    pub yellow_queue_delay: Duration,
+    /// Red at or above this many drops per second
+    pub red_drops_per_sec: f64,
+    /// Red at or above this average send buffer pressure in one interval
+    pub red_sndbuf_stall: f64,
+    /// Intervals the wmem percentile is taken over; it isn't judged before
+    /// that many have been seen
+    pub window: usize,
+    /// Calmer intervals in a row before a raised state drops back
+    pub demote_after: u32,
+    /// When a growing queue without drops is Yellow for bufferbloat
+    pub bufferbloat: BufferbloatConfig,
+    /// How fast snapshots classify an episode, see [`Reason::Burst`]
+    pub burst: BurstConfig,
+}
+
+impl Default for SeverityThresholds {
+    fn default() -> Self {
+        Self {
+            yellow_wmem_p95: 0.6,
+            yellow_queue_delay: Duration::from_millis(5),
+            red_drops_per_sec: 50.0,
+            red_sndbuf_stall: 0.95,
+            // A second at 200 ms intervals
+            window: 5,
+            demote_after: 3,
+            bufferbloat: BufferbloatConfig::default(),
+            burst: BurstConfig::default(),
+        }
+    }
+}
+
+/// Turns successive intervals into a [`CongestionState`]. Raising is immediate;
+/// a raised state is held with the reasons that raised it until `demote_after`
+/// intervals in a row would have been lower, then drops to what they were
+#[derive(Debug, Clone)]
+pub struct SeverityClassifier {
+    thresholds: SeverityThresholds,
+    wmem: VecDeque<f64>,
+    bufferbloat: BufferbloatDetector,
+    burst: BurstClassifier,
+    // improved_fraction while the governor found it non-responsive
+    non_responsive: Option<f64>,
+    state: CongestionState,
+    calmer: u32,
+}
+
+impl SeverityClassifier {
+    pub fn new(thresholds: SeverityThresholds) -> Self {
+        Self {
+            wmem: VecDeque::with_capacity(thresholds.window),
+            bufferbloat: BufferbloatDetector::new(thresholds.bufferbloat),
+            burst: BurstClassifier::new(thresholds.burst),
+            non_responsive: None,
+            thresholds,
+            state: CongestionState::Green,
+            calmer: 0,
+        }
+    }
+
+    pub fn state(&self) -> &CongestionState {
+        &self.state
+    }
+
+    /// Fold in a fast snapshot taken during the interval the next `update` is
+    /// for, one bucket of it
+    pub fn observe_fast(&mut self, fast: &FastSignals) {
+        self.burst.observe(fast);
+    }
+
+    /// How the governor's cuts have worked out, `Governor::effectiveness` after
+    /// each update: while it's non-responsive a raised state says so
+    pub fn observe_effectiveness(&mut self, effectiveness: &Effectiveness) {
+        self.non_responsive = effectiveness
+            .non_responsive
+            .then(|| effectiveness.improved_fraction.unwrap_or(0.0));
+    }
+
+    /// Fold in one interval and return the state after it
+    pub fn update(&mut self, signals: &CongestionSignals) -> CongestionState {
+        let reasons = self.reasons(signals);
+        let level = reasons.iter().map(Reason::level).max().unwrap_or(0);
+        // Only what the state is named for, and what kind of episode it was
+        let mut reasons: Vec<Reason> = reasons.into_iter().filter(|r| r.level() == level).collect();
+        if let Some(episode) = self.burst.classify().filter(|_| level > 0) {
+            reasons.push(Reason::Burst {
+                class: episode.class,
+                pressured_fraction: episode.pressured_fraction,
+            });
+        }
+        if let Some(improved_fraction) = self.non_responsive.filter(|_| level > 0) {
+            reasons.push(Reason::NonResponsive { improved_fraction });
+        }
+
+        let current = self.state.level();
+        if level >= current {
+            self.calmer = 0;
+            self.state = CongestionState::at(level, reasons);
+        } else {
+            self.calmer += 1;
+            if self.calmer >= self.thresholds.demote_after {
+                self.calmer = 0;
+                self.state = CongestionState::at(level, reasons);
+            }
+        }
+        self.state.clone()
+    }
+
+    fn reasons(&mut self, signals: &CongestionSignals) -> Vec<Reason> {
+        let thresholds = &self.thresholds;
+        let mut reasons = Vec::new();
+        let secs = signals.interval_ns as f64 / 1e9;
+
+        if self.wmem.len() == thresholds.window.max(1) {
+            self.wmem.pop_front();
+        }
+        self.wmem.push_back(signals.avg_wmem_pressure);
+        if self.wmem.len() >= thresholds.window {
+            let mut sorted: Vec<f64> = self.wmem.iter().copied().collect();
+            sorted.sort_by(f64::total_cmp);
+            let p95 = sorted[((sorted.len() - 1) as f64 * 0.95).round() as usize];
+            if p95 > thresholds.yellow_wmem_p95 {
+                reasons.push(Reason::WmemPressure { p95 });
+            }
+        }
+
+        let stall_ns = signals
+            .txq_stalls
+            .zip(signals.txq_stalled_ns)
+            .and_then(|(stalls, stalled_ns)| stalled_ns.checked_div(stalls));
+        if let Some(stall_ns) = stall_ns {
+            let delay = Duration::from_nanos(stall_ns);
+            if delay > thresholds.yellow_queue_delay {
+                reasons.push(Reason::QueueDelay {
+                    ms: delay.as_secs_f64() * 1e3,
+                });
+            }
+        }
+
+        if secs > 0.0 {
+            let drops_per_sec = signals.drops as f64 / secs;
+            if drops_per_sec >= thresholds.red_drops_per_sec {
+                reasons.push(Reason::DropRate {
+                    per_sec: drops_per_sec,
+                });
+            }
+        }
+
+        if let Some(bloat) = self.bufferbloat.update(signals) {
+            reasons.push(Reason::Bufferbloat {
+                backlog_bytes: bloat.backlog_bytes,
+                growth: bloat.growth,
+            });
+        }
+
+        if signals.avg_wmem_pressure >= thresholds.red_sndbuf_stall {
+            reasons.push(Reason::SndbufStall {
+                pressure: signals.avg_wmem_pressure,
+            });
+        }
+        reasons
+    }
+}
+
+impl Default for SeverityClassifier {
+    fn default() -> Self {
+        Self::new(SeverityThresholds::default())
+    }
+}
+
+/// A snapshot with the state it put the classifier in, see
+/// `CongestionCollector::severity_snapshots`
+#[derive(Debug, Clone, Default)]
+pub struct SeveritySnapshot {
+    pub signals: CongestionSignals,
+    pub state: CongestionState,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 200 ms interval
+    fn interval() -> crate::CongestionSignalsBuilder {
+        CongestionSignals::builder().interval_ns(200_000_000)
+    }
+
+    fn calm() -> CongestionSignals {
+        interval().avg_wmem_pressure(0.1).build()
+    }
+
+    fn pressured() -> CongestionSignals {
+        interval().avg_wmem_pressure(0.7).build()
+    }
+
+    fn dropping() -> CongestionSignals {
+        interval().avg_wmem_pressure(0.1).drops(20).build()
+    }
+
+    fn labels(state: &CongestionState) -> Vec<&'static str> {
+        state.reasons().iter().map(Reason::label).collect()
+    }
+
+    #[test]
+    fn sustained_wmem_pressure_turns_yellow_once_the_window_fills() {
+        let mut classifier = SeverityClassifier::default();
+        for _ in 0..4 {
+            assert_eq!(classifier.update(&pressured()), CongestionState::Green);
+        }
+        let state = classifier.update(&pressured());
+        assert_eq!(
+            state,
+            CongestionState::Yellow(vec![Reason::WmemPressure { p95: 0.7 }])
+        );
+    }
+
+    #[test]
+    fn long_tx_queue_stops_are_yellow() {
+        let mut classifier = SeverityClassifier::default();
+        let stopped = interval().txq_stalls(4).txq_stalled_ns(40_000_000).build();
+        let state = classifier.update(&stopped);
+        assert_eq!(
+            state,
+            CongestionState::Yellow(vec![Reason::QueueDelay { ms: 10.0 }])
+        );
+
+        let brief = interval().txq_stalls(4).txq_stalled_ns(4_000_000).build();
+        let mut classifier = SeverityClassifier::default();
+        assert_eq!(classifier.update(&brief), CongestionState::Green);
+    }
+
+    #[test]
+    fn drops_and_full_send_buffers_go_straight_to_red() {
+        let mut classifier = SeverityClassifier::default();
+        assert_eq!(
+            classifier.update(&dropping()),
+            CongestionState::Red(vec![Reason::DropRate { per_sec: 100.0 }])
+        );
+
+        let mut classifier = SeverityClassifier::default();
+        let full = interval().avg_wmem_pressure(0.97).build();
+        assert_eq!(
+            classifier.update(&full),
+            CongestionState::Red(vec![Reason::SndbufStall { pressure: 0.97 }])
+        );
+    }
+
+    #[test]
+    fn red_holds_its_reasons_then_steps_down_through_yellow() {
+        let mut classifier = SeverityClassifier::default();
+        for _ in 0..5 {
+            classifier.update(&pressured());
+        }
+        let red = classifier.update(&dropping());
+        // Only the reasons the state is named for
+        assert_eq!(labels(&red), ["drop_rate"]);
+
+        // Yellow intervals: held at Red for demote_after - 1 of them
+        for _ in 0..2 {
+            assert_eq!(classifier.update(&pressured()), red);
+        }
+        let yellow = classifier.update(&pressured());
+        assert_eq!(labels(&yellow), ["wmem_pressure"]);
+
+        // A raise in between restarts the count
+        classifier.update(&calm());
+        assert_eq!(classifier.update(&dropping()).level(), 2);
+        for _ in 0..2 {
+            assert_eq!(classifier.update(&calm()).level(), 2);
+        }
+        // Straight to what the calmer intervals were, Green
+        assert_eq!(classifier.update(&calm()), CongestionState::Green);
+    }
+
+    #[test]
+    fn a_non_responsive_governor_is_named_among_the_reasons() {
+        let mut classifier = SeverityClassifier::default();
+        classifier.observe_effectiveness(&Effectiveness {
+            non_responsive: true,
+            improved_fraction: Some(0.1),
+            ..Effectiveness::default()
+        });
+        assert_eq!(classifier.update(&calm()), CongestionState::Green);
+        assert_eq!(
+            labels(&classifier.update(&dropping())),
+            ["drop_rate", "non_responsive"]
+        );
+    }
+
+    fn bufferbloat_state(trace: &[CongestionSignals]) -> CongestionState {
+        let mut classifier = SeverityClassifier::new(SeverityThresholds {
+            bufferbloat: crate::BufferbloatConfig {
+                link_rate: Some(12_500_000),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let mut state = CongestionState::Green;
+        for signals in trace {
+            state = classifier.update(signals);
+        }
+        state
+    }
+
+    #[test]
+    fn bufferbloat_is_named_and_loss_is_not() {
+        let rising = |drops| -> Vec<CongestionSignals> {
+            (0..8)
+                .map(|i| crate::fixtures::backlogged(100_000 + i * 80_000, drops, 11_500_000, None))
+                .collect()
+        };
+        let bloated = bufferbloat_state(&rising(0));
+        assert_eq!(bloated.level(), 1);
+        assert!(
+            labels(&bloated).contains(&"bufferbloat"),
+            "{:?}",
+            labels(&bloated)
+        );
+        let lossy = bufferbloat_state(&rising(400));
+        assert_eq!(lossy.level(), 2);
+        assert!(
+            !labels(&lossy).contains(&"bufferbloat"),
+            "{:?}",
+            labels(&lossy)
+        );
+        let flat: Vec<_> = (0..8)
+            .map(|_| crate::fixtures::backlogged(400_000, 0, 11_500_000, None))
+            .collect();
+        assert_eq!(bufferbloat_state(&flat), CongestionState::Green);
+    }
+
+    fn burst_state(buckets: &[crate::FastSignals]) -> CongestionState {
+        let mut classifier = SeverityClassifier::default();
+        for fast in buckets {
+            classifier.observe_fast(fast);
+        }
+        classifier.update(&crate::fixtures::bucket_drops(buckets))
+    }
+
+    fn burst_class(state: &CongestionState) -> Option<BurstClass> {
+        state.reasons().iter().find_map(|reason| match reason {
+            Reason::Burst { class, .. } => Some(*class),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn a_microburst_is_still_red_and_named_so() {
+        use crate::fixtures::burst_buckets;
+        let micro = burst_state(&burst_buckets(|i| if i == 25 { 400 } else { 0 }));
+        assert_eq!(micro.level(), 2);
+        assert_eq!(burst_class(&micro), Some(BurstClass::Microburst));
+        let sustained = burst_state(&burst_buckets(|i| if i < 40 { 10 } else { 0 }));
+        assert_eq!(sustained.level(), 2);
+        assert_eq!(burst_class(&sustained), Some(BurstClass::Sustained));
+    }
+}
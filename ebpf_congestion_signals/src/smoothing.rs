@@ -0,0 +1,149 @@
+//Exponentially weighted view of successive intervals. Single intervals are
+//noisy at 100-200 ms (a drop burst, one busy softirq), so rate control usually
+//wants the trend; this keeps the rates and pressures averaged across intervals
+//next to the latest raw interval.
+//
+//The averaging itself goes by interval, the rates by `interval_ns`; only how
+//long ago the last update came is timed, on the smoother's `Clock`.
+
+use crate::clock::{system_clock, Clock};
+use crate::{permille_from_f64, CongestionSignals};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Fed one interval at a time by [`SignalSmoother::update`]
+#[derive(Debug, Clone, Default)]
+pub struct SmoothedSignals {
+    /// The interval this update was made from, unsmoothed
+    pub latest: CongestionSignals,
+    /// Sampled send bytes per second, same units as `external_send_bytes`
+    pub send_rate: f64,
+    pub drop_rate: f64,
+    pub wmem_pressure: f64,
+    pub rmem_pressure: f64,
+    pub softirq_cpu_fraction: f64,
+}
+
+impl SmoothedSignals {
+    /// The smoothed pressures and fraction in per-mille, rounded half up. The
+    /// EWMA itself is f64: its weight is
+    pub fn wmem_pressure_permille(&self) -> u32 {
+        permille_from_f64(self.wmem_pressure)
+    }
+
+    pub fn rmem_pressure_permille(&self) -> u32 {
+        permille_from_f64(self.rmem_pressure)
+    }
+
+    pub fn softirq_cpu_fraction_permille(&self) -> u32 {
+        permille_from_f64(self.softirq_cpu_fraction)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SignalSmoother {
+    alpha: f64,
+    current: Option<SmoothedSignals>,
+    clock: Arc<dyn Clock>,
+    updated_at: Option<Instant>,
+}
+
+impl SignalSmoother {
+    /// `alpha` (0.0-1.0] is the weight of each new interval, 1.0 disables smoothing
+    pub fn new(alpha: f64) -> Self {
+        Self {
+            alpha: alpha.clamp(f64::EPSILON, 1.0),
+            current: None,
+            clock: system_clock(),
+            updated_at: None,
+        }
+    }
+
+    /// Time `since_update` on `clock` rather than the system's
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Fold in one interval. The first one is taken as-is
+    pub fn update(&mut self, signals: &CongestionSignals) -> SmoothedSignals {
+        let secs = signals.interval_ns as f64 / 1e9;
+        let per_sec = |count: u64| {
+            if secs > 0.0 {
+                count as f64 / secs
+            } else {
+                0.0
+            }
+        };
+        let sample = SmoothedSignals {
+            latest: signals.clone(),
+            send_rate: per_sec(signals.external_send_bytes),
+            drop_rate: per_sec(signals.drops),
+            wmem_pressure: signals.avg_wmem_pressure,
+            rmem_pressure: signals.avg_rmem_pressure,
+            softirq_cpu_fraction: signals.softirq_cpu_fraction,
+        };
+
+        let smoothed = match &self.current {
+            None => sample,
+            Some(previous) => {
+                let ewma = |old: f64, new: f64| old + self.alpha * (new - old);
+                SmoothedSignals {
+                    send_rate: ewma(previous.send_rate, sample.send_rate),
+                    drop_rate: ewma(previous.drop_rate, sample.drop_rate),
+                    wmem_pressure: ewma(previous.wmem_pressure, sample.wmem_pressure),
+                    rmem_pressure: ewma(previous.rmem_pressure, sample.rmem_pressure),
+                    softirq_cpu_fraction: ewma(
+                        previous.softirq_cpu_fraction,
+                        sample.softirq_cpu_fraction,
+                    ),
+                    latest: sample.latest,
+                }
+            }
+        };
+        self.current = Some(smoothed.clone());
+        self.updated_at = Some(self.clock.now());
+        smoothed
+    }
+
+    /// The latest smoothed value, None before the first update
+    pub fn current(&self) -> Option<&SmoothedSignals> {
+        self.current.as_ref()
+    }
+
+    /// How long ago the last update came, None before the first. A caller
+    /// holding `current()` while its stream stalls can tell it's gone stale
+    pub fn since_update(&self) -> Option<Duration> {
+        self.updated_at
+            .map(|at| self.clock.now().saturating_duration_since(at))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CongestionSignalsBuilder;
+
+    #[test]
+    fn smoothed_pressures_round_half_up_to_per_mille() {
+        let first = CongestionSignals::builder()
+            .interval_ns(200_000_000)
+            .avg_wmem_pressure(0.4375)
+            .softirq_cpu_fraction(0.25)
+            .build();
+        let mut smoother = SignalSmoother::new(0.3);
+        smoother.update(&first);
+        let smoothed = smoother.update(
+            &CongestionSignalsBuilder::from_signals(first)
+                .avg_wmem_pressure(0.1)
+                .build(),
+        );
+        // 0.4375 + 0.3 * (0.1 - 0.4375)
+        assert_eq!(smoothed.wmem_pressure_permille(), 336);
+        assert_eq!(
+            smoothed.wmem_pressure_permille(),
+            permille_from_f64(smoothed.wmem_pressure)
+        );
+        assert_eq!(smoothed.softirq_cpu_fraction_permille(), 250);
+    }
+}
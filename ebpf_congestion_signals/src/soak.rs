@@ -0,0 +1,468 @@
+//Bookkeeping for soak runs: days of collection before a rollout, looking for
+//what only shows over time. `validate --soak <hours>` takes a `SoakSample`
+//every hour (RSS and open fds from /proc, the memory report's total and table
+//sizes, the cumulative counters, the SNMP cross-check's error since start)
+//and feeds it to a `SoakTracker`, whose report judges the run by `SoakBounds`:
+//
+//- RSS, the memory estimate and every table grow per hour by the least-squares
+//  slope over the samples past `warmup`, so one busy hour doesn't fail a run
+//  and a slow leak can't hide behind one quiet one
+//- open fds, the last sample's over the first past warmup
+//- cumulative counters never go down; one that did is named with when
+//- the cross-check's error spreads (max - min) past warmup no more than
+//  `max_accuracy_drift`: a sampler whose estimate wanders off over a day
+//
+//The report's JSON is written as a checkpoint after every sample, so a run
+//that crashes still leaves its evidence. The soak itself is a nightly job; the
+//tests check the bookkeeping on scripted samples.
+
+use crate::json::{json_f64, json_opt, json_opt_f64, json_string};
+use crate::EXPORT_SCHEMA_VERSION;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::time::Duration;
+
+const HOUR_SECS: f64 = 3600.0;
+
+/// This process's resident memory and open file descriptors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessUsage {
+    pub rss_bytes: u64,
+    pub open_fds: u64,
+}
+
+impl ProcessUsage {
+    /// From /proc/self/status and /proc/self/fd
+    pub fn read() -> std::io::Result<Self> {
+        let status = std::fs::read_to_string("/proc/self/status")?;
+        let rss_bytes = Self::parse_rss(&status).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "no VmRSS in /proc/self/status",
+            )
+        })?;
+        // Less the one the directory is read through
+        let open_fds = std::fs::read_dir("/proc/self/fd")?.count() as u64;
+        Ok(Self {
+            rss_bytes,
+            open_fds: open_fds.saturating_sub(1),
+        })
+    }
+
+    /// VmRSS, which the kernel writes in kB
+    pub fn parse_rss(status: &str) -> Option<u64> {
+        let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+        let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+        Some(kb * 1024)
+    }
+}
+
+/// What a soak may show and still pass
+#[derive(Debug, Clone)]
+pub struct SoakBounds {
+    /// Samples this early are left out of the judgement: tables fill and the
+    /// allocator settles in the first hour
+    pub warmup: Duration,
+    pub max_rss_growth_per_hour: u64,
+    /// Of `MemoryReport::total_bytes`
+    pub max_memory_growth_per_hour: u64,
+    /// Entries per hour, any one table
+    pub max_table_growth_per_hour: u64,
+    pub max_fd_growth: u64,
+    /// Spread of the cross-check's relative error, 0.05 for 5 points
+    pub max_accuracy_drift: f64,
+}
+
+impl Default for SoakBounds {
+    fn default() -> Self {
+        Self {
+            warmup: Duration::from_secs(3600),
+            max_rss_growth_per_hour: 4 << 20,
+            max_memory_growth_per_hour: 1 << 20,
+            max_table_growth_per_hour: 256,
+            max_fd_growth: 4,
+            max_accuracy_drift: 0.05,
+        }
+    }
+}
+
+/// One hourly sample of a soak
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SoakSample {
+    /// Since the soak started
+    pub elapsed: Duration,
+    pub rss_bytes: u64,
+    pub open_fds: u64,
+    /// `MemoryReport::total_bytes`
+    pub memory_bytes: u64,
+    /// Entries of each userspace structure, `MemoryReport::structures`
+    pub tables: BTreeMap<String, u64>,
+    /// Counters that only go up, e.g. `CumulativeTotals`' by field name
+    pub counters: BTreeMap<String, u64>,
+    /// Worst relative error of the SNMP cross-check since start, None
+    /// without one
+    pub accuracy_error: Option<f64>,
+}
+
+/// A cumulative counter that went down between two samples
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CounterRegression {
+    pub counter: String,
+    /// The later sample's `elapsed`
+    pub at: Duration,
+    pub from: u64,
+    pub to: u64,
+}
+
+/// Collects a soak's samples, see the module docs
+#[derive(Debug, Clone)]
+pub struct SoakTracker {
+    bounds: SoakBounds,
+    samples: Vec<SoakSample>,
+    regressions: Vec<CounterRegression>,
+}
+
+impl SoakTracker {
+    pub fn new(bounds: SoakBounds) -> Self {
+        Self {
+            bounds,
+            samples: Vec::new(),
+            regressions: Vec::new(),
+        }
+    }
+
+    pub fn bounds(&self) -> &SoakBounds {
+        &self.bounds
+    }
+
+    /// Add the next sample. Returns the counters it finds gone down
+    pub fn record(&mut self, sample: SoakSample) -> Vec<CounterRegression> {
+        let mut found = Vec::new();
+        if let Some(previous) = self.samples.last() {
+            for (counter, &to) in &sample.counters {
+                match previous.counters.get(counter) {
+                    Some(&from) if to < from => found.push(CounterRegression {
+                        counter: counter.clone(),
+                        at: sample.elapsed,
+                        from,
+                        to,
+                    }),
+                    _ => {}
+                }
+            }
+        }
+        self.regressions.extend(found.iter().cloned());
+        self.samples.push(sample);
+        found
+    }
+
+    pub fn samples(&self) -> &[SoakSample] {
+        &self.samples
+    }
+
+    /// The run so far judged by the bounds
+    pub fn report(&self) -> SoakReport {
+        let bounds = &self.bounds;
+        let settled: Vec<&SoakSample> = self
+            .samples
+            .iter()
+            .filter(|sample| sample.elapsed >= bounds.warmup)
+            .collect();
+        let slope = |value: &dyn Fn(&SoakSample) -> Option<u64>| {
+            let points: Vec<(f64, f64)> = settled
+                .iter()
+                .filter_map(|sample| {
+                    let hours = sample.elapsed.as_secs_f64() / HOUR_SECS;
+                    value(sample).map(|value| (hours, value as f64))
+                })
+                .collect();
+            growth_per_hour(&points)
+        };
+        let rss_growth_per_hour = slope(&|sample| Some(sample.rss_bytes));
+        let memory_growth_per_hour = slope(&|sample| Some(sample.memory_bytes));
+        let mut table_growth_per_hour = BTreeMap::new();
+        for name in settled.iter().flat_map(|sample| sample.tables.keys()) {
+            if table_growth_per_hour.contains_key(name) {
+                continue;
+            }
+            if let Some(growth) = slope(&|sample| sample.tables.get(name).copied()) {
+                table_growth_per_hour.insert(name.clone(), growth);
+            }
+        }
+        let fd_growth = match (settled.first(), settled.last()) {
+            (Some(first), Some(last)) if settled.len() > 1 => {
+                Some(last.open_fds as i64 - first.open_fds as i64)
+            }
+            _ => None,
+        };
+        let errors: Vec<f64> = settled
+            .iter()
+            .filter_map(|sample| sample.accuracy_error)
+            .collect();
+        let accuracy_drift = (errors.len() > 1).then(|| {
+            let max = errors.iter().cloned().fold(f64::MIN, f64::max);
+            let min = errors.iter().cloned().fold(f64::MAX, f64::min);
+            max - min
+        });
+
+        let mut failures = Vec::new();
+        if settled.len() < 2 {
+            failures.push(format!(
+                "{} samples past the {:?} warmup, at least 2 needed",
+                settled.len(),
+                bounds.warmup
+            ));
+        }
+        let mut over = |what: &str, growth: Option<f64>, bound: u64, unit: &str| {
+            if let Some(growth) = growth.filter(|&growth| growth > bound as f64) {
+                failures.push(format!(
+                    "{} grew {:.0} {}/h, over {}",
+                    what, growth, unit, bound
+                ));
+            }
+        };
+        over(
+            "rss",
+            rss_growth_per_hour,
+            bounds.max_rss_growth_per_hour,
+            "bytes",
+        );
+        over(
+            "memory estimate",
+            memory_growth_per_hour,
+            bounds.max_memory_growth_per_hour,
+            "bytes",
+        );
+        for (name, &growth) in &table_growth_per_hour {
+            over(
+                name,
+                Some(growth),
+                bounds.max_table_growth_per_hour,
+                "entries",
+            );
+        }
+        if let Some(growth) = fd_growth.filter(|&growth| growth > bounds.max_fd_growth as i64) {
+            failures.push(format!(
+                "open fds grew by {}, over {}",
+                growth, bounds.max_fd_growth
+            ));
+        }
+        for regression in &self.regressions {
+            failures.push(format!(
+                "{} went down from {} to {} at {:?}",
+                regression.counter, regression.from, regression.to, regression.at
+            ));
+        }
+        if let Some(drift) = accuracy_drift.filter(|&drift| drift > bounds.max_accuracy_drift) {
+            failures.push(format!(
+                "cross-check error drifted by {:.3}, over {}",
+                drift, bounds.max_accuracy_drift
+            ));
+        }
+
+        SoakReport {
+            elapsed: self.samples.last().map_or(Duration::ZERO, |s| s.elapsed),
+            samples: self.samples.clone(),
+            rss_growth_per_hour,
+            memory_growth_per_hour,
+            table_growth_per_hour,
+            fd_growth,
+            counter_regressions: self.regressions.clone(),
+            accuracy_drift,
+            failures,
+        }
+    }
+}
+
+/// Least-squares slope of (hours, value), None for fewer than two distinct
+/// times
+fn growth_per_hour(points: &[(f64, f64)]) -> Option<f64> {
+    if points.len() < 2 {
+        return None;
+    }
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let sxx: f64 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+    let sxy: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+    (sxx > 0.0).then(|| sxy / sxx)
+}
+
+/// What `SoakTracker::report` judged, and the samples it judged from
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoakReport {
+    /// Of the last sample
+    pub elapsed: Duration,
+    pub samples: Vec<SoakSample>,
+    /// Bytes per hour past warmup, None with fewer than two samples past it
+    pub rss_growth_per_hour: Option<f64>,
+    pub memory_growth_per_hour: Option<f64>,
+    /// Entries per hour, by table
+    pub table_growth_per_hour: BTreeMap<String, f64>,
+    /// Open fds at the last sample less the first past warmup
+    pub fd_growth: Option<i64>,
+    pub counter_regressions: Vec<CounterRegression>,
+    /// Spread of the cross-check's error past warmup
+    pub accuracy_drift: Option<f64>,
+    /// What went past the bounds, empty when the soak passed
+    pub failures: Vec<String>,
+}
+
+impl SoakReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// The report as one JSON object, no trailing newline
+    pub fn to_json(&self) -> String {
+        let counts = |counts: &BTreeMap<String, u64>| {
+            let counts: Vec<String> = counts
+                .iter()
+                .map(|(name, value)| {
+                    format!("{{\"name\":{},\"value\":{}}}", json_string(name), value)
+                })
+                .collect();
+            counts.join(",")
+        };
+        let mut out = String::new();
+        let _ = write!(
+            out,
+            "{{\"schema_version\":{},\"elapsed_ms\":{},\"passed\":{},\"rss_growth_per_hour\":{},\
+             \"memory_growth_per_hour\":{},\"fd_growth\":{},\"accuracy_drift\":{},\
+             \"table_growth\":[",
+            EXPORT_SCHEMA_VERSION,
+            self.elapsed.as_millis(),
+            self.passed(),
+            json_opt_f64(self.rss_growth_per_hour),
+            json_opt_f64(self.memory_growth_per_hour),
+            json_opt(self.fd_growth),
+            json_opt_f64(self.accuracy_drift),
+        );
+        for (i, (name, growth)) in self.table_growth_per_hour.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(
+                out,
+                "{{\"name\":{},\"growth_per_hour\":{}}}",
+                json_string(name),
+                json_f64(*growth)
+            );
+        }
+        out.push_str("],\"counter_regressions\":[");
+        for (i, regression) in self.counter_regressions.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(
+                out,
+                "{{\"counter\":{},\"at_ms\":{},\"from\":{},\"to\":{}}}",
+                json_string(&regression.counter),
+                regression.at.as_millis(),
+                regression.from,
+                regression.to
+            );
+        }
+        let failures: Vec<String> = self.failures.iter().map(|f| json_string(f)).collect();
+        let _ = write!(out, "],\"failures\":[{}],\"samples\":[", failures.join(","));
+        for (i, sample) in self.samples.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(
+                out,
+                "{{\"elapsed_ms\":{},\"rss_bytes\":{},\"open_fds\":{},\"memory_bytes\":{},\
+                 \"tables\":[{}],\"counters\":[{}],\"accuracy_error\":{}}}",
+                sample.elapsed.as_millis(),
+                sample.rss_bytes,
+                sample.open_fds,
+                sample.memory_bytes,
+                counts(&sample.tables),
+                counts(&sample.counters),
+                json_opt_f64(sample.accuracy_error)
+            );
+        }
+        out.push_str("]}");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1 << 20;
+
+    /// Six hours; the first fills the socket table and the heap
+    fn steady(hour: u64) -> SoakSample {
+        SoakSample {
+            elapsed: Duration::from_secs(hour * 3600),
+            rss_bytes: if hour == 0 {
+                20 * MB
+            } else {
+                48 * MB + (hour % 2) * MB
+            },
+            open_fds: 40,
+            memory_bytes: if hour == 0 { 2 * MB } else { 6 * MB },
+            tables: [("sockets".to_string(), if hour == 0 { 100 } else { 4000 })].into(),
+            counters: [("drops".to_string(), hour * 1000)].into(),
+            accuracy_error: Some(0.02 + (hour % 2) as f64 * 0.01),
+        }
+    }
+
+    fn run(sample: impl Fn(u64) -> SoakSample) -> SoakReport {
+        let mut tracker = SoakTracker::new(SoakBounds::default());
+        for hour in 0..=6 {
+            tracker.record(sample(hour));
+        }
+        tracker.report()
+    }
+
+    #[test]
+    fn a_steady_run_passes_with_its_warmup_left_out() {
+        let report = run(steady);
+        assert!(report.passed(), "{:?}", report.failures);
+        assert!(report.rss_growth_per_hour.unwrap().abs() < MB as f64);
+        assert_eq!(report.table_growth_per_hour.get("sockets"), Some(&0.0));
+    }
+
+    #[test]
+    fn leaks_and_drift_fail_the_run_by_name() {
+        let report = run(|hour| {
+            let mut sample = steady(hour);
+            sample.rss_bytes += hour * 8 * MB;
+            sample.tables.insert("flows".to_string(), hour * 1000);
+            sample.open_fds += hour * 3;
+            if hour == 4 {
+                sample.counters.insert("drops".to_string(), 10);
+            }
+            sample.accuracy_error = Some(0.02 * hour as f64);
+            sample
+        });
+        let failed_on = |what: &str| report.failures.iter().any(|f| f.starts_with(what));
+        assert!(!report.passed());
+        assert!(failed_on("rss grew"));
+        assert!(failed_on("flows grew"));
+        assert!(!failed_on("sockets"));
+        assert!(failed_on("open fds grew by 15"));
+        assert!(failed_on("drops went down"));
+        assert!(failed_on("cross-check error drifted"));
+        let regression = &report.counter_regressions[0];
+        assert_eq!((regression.from, regression.to), (3000, 10));
+    }
+
+    #[test]
+    fn one_sample_is_too_short_to_pass() {
+        let mut tracker = SoakTracker::new(SoakBounds::default());
+        tracker.record(steady(0));
+        assert!(!tracker.report().passed());
+    }
+
+    #[test]
+    fn rss_parses_from_proc_status() {
+        let status = "Name:\tvalidate\nVmRSS:\t    2048 kB\nVmData:\t 1 kB\n";
+        assert_eq!(ProcessUsage::parse_rss(status), Some(2 * MB));
+        let usage = ProcessUsage::read().unwrap();
+        assert!(usage.rss_bytes > 0 && usage.open_fds > 0);
+    }
+}
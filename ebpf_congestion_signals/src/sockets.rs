@@ -0,0 +1,916 @@
+//Per-socket view, fed by the processing task. Entries are keyed by
+//socket_id (the socket cookie, never reused while the host is up) and each
+//covers exactly one socket lifetime: TCP entries end on the lifecycle close
+//event, UDP sockets have no such event and are retired once idle for
+//`CollectorConfig::socket_idle_ttl`.
+//
+//The kernel only assigns a cookie the first time something asks for it, and
+//kprobes can't ask. Until then the probes fall back to the socket address, which
+//can alias; `socket_cookie()` on your own sockets right after creating them
+//makes their ids stable from the first event and lets you find them here.
+//
+//Send buffer samples carry the socket's pacing rate. Against the send rate of
+//the last second of sampled sends it marks sockets the kernel paces
+//(KernelPacedThresholds), and the share of sends from them makes an interval
+//kernel-paced.
+//
+//The same events count connection churn: TCP handshakes completing and closing
+//from the lifecycle tracepoint, UDP sockets by their first event in the table.
+//
+//Under per-socket sampling (`CollectorConfig::with_socket_sampling`) the events
+//of sockets in the sample say so, and only those sockets (and registered ones)
+//are reported: the others' entries hold nothing but unsampled events.
+//
+//The table is split into up to 16 shards by socket id hash, each behind its own
+//lock, and each shard's map is copy-on-write: a snapshot locks every shard at
+//once just long enough to take a reference to its current generation, then
+//copies them with no lock held. Writers only pay when they touch a shard whose
+//generation a reader still holds, cloning it once and going on with the copy.
+//So `read_per_socket()` is one point in time across the table, and never holds
+//up the processing task for longer than those reference copies. Retiring what
+//a snapshot reported goes back under each shard's lock and skips any entry
+//written since. Eviction works on a full shard alone, never scanning the whole
+//table.
+
+use crate::memory::{hash_table_bytes, ShardMemory, StructureMemory};
+use crate::probe::getsockopt;
+use crate::{
+    event_samplers, event_socket, traced_only, CollectorConfig, ConcentrationConfig,
+    CongestionEvent, ConnectionChurn, IntervalActivity, KernelPacedThresholds, SendConcentration,
+    SocketData, SocketSampling, EVENT_SOCKET_LIFECYCLE, EVENT_SOCKET_RCV_STATE, EVENT_SOCKET_STATE,
+    EVENT_TCP_CWR, EVENT_TCP_RECOVERY, EVENT_TCP_RTO, EVENT_TCP_SEND, EVENT_TCP_STATE,
+    EVENT_UDP_RCV_CE, EVENT_UDP_RCV_DROP, EVENT_UDP_SEND, PACING_UNLIMITED, SAMPLER_SOCKET,
+    SEND_SAMPLE_RATIO, TCP_CLOSE, TCP_ESTABLISHED, TCP_SYN_RECV, TCP_SYN_SENT,
+};
+use std::collections::HashMap;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::time::Duration;
+
+// Sampled sends a socket's send rate is estimated over, at least. At 1-in-100
+// sampling a 1 MB/s QUIC flow has ~8 samples a second
+const SEND_RATE_WINDOW_NS: u64 = 1_000_000_000;
+
+// Shards of the table: at most 16, fewer for small tables so each shard's
+// eviction batch stays meaningful
+const MAX_SHARDS: usize = 16;
+const MIN_SHARD_SOCKETS: usize = 256;
+
+/// SO_COOKIE of a socket, the socket_id its events carry. Assigns the cookie if
+/// the kernel hadn't yet
+pub fn socket_cookie(socket: &impl AsRawFd) -> io::Result<u64> {
+    getsockopt(socket.as_raw_fd(), libc::SO_COOKIE)
+}
+
+/// Cumulative signals for one socket lifetime, see
+/// `CongestionCollector::read_per_socket()`
+#[derive(Debug, Clone, Default)]
+pub struct SocketSignals {
+    /// Kernel monotonic time (bpf_ktime_get_ns) of the first and latest event
+    pub first_seen_ns: u64,
+    pub last_seen_ns: u64,
+    pub is_tcp: bool,
+    pub send_bytes: u64,
+    /// The part of `send_bytes` sent to loopback addresses, 0 while the
+    /// collector excludes them
+    pub loopback_send_bytes: u64,
+    /// Datagrams dropped on a full rcvbuf
+    pub udp_rcv_drops: u64,
+    /// CE-marked receives, sampled like the aggregate `ce_marks`
+    pub ce_marks: u64,
+    /// rcvbuf occupancy (0.0-1.0) on the latest receive sample
+    pub rmem_pressure: Option<f64>,
+    /// sndbuf occupancy (0.0-1.0) on the latest send-side sample: sk_wmem_alloc
+    /// for UDP, sk_wmem_queued for TCP
+    pub wmem_pressure: Option<f64>,
+    /// cwnd and ssthresh on the latest TCP state sample
+    pub tcp_cwnd: Option<u32>,
+    pub tcp_ssthresh: Option<u32>,
+    pub tcp_cwr: u64,
+    /// `SocketData::pacing_limit` on the latest send buffer sample, bytes/sec
+    pub pacing_rate: Option<u64>,
+    /// sk_max_pacing_rate on it, when SO_MAX_PACING_RATE set one
+    pub max_pacing_rate: Option<u64>,
+    /// Sampled send bytes, scaled up by the sampling ratio, over the last
+    /// second or so before a send buffer sample, bytes/sec
+    pub send_rate: Option<f64>,
+    /// The socket is in the per-socket sample: every send and occupancy sample
+    /// of it reached the table, so `send_bytes` and `send_rate` are its own
+    /// rather than 1 in 100. Never set under 1-in-100 sampling
+    pub fully_sampled: bool,
+    /// On the latest send buffer sample, sending at its pacing rate with
+    /// sndbuf to spare: the kernel is already governing this socket
+    pub kernel_paced: bool,
+    /// Retransmission timeouts and fast recovery entries, see the aggregate
+    /// `rto_events` and `loss_recovery_episodes`
+    pub rto_events: u64,
+    pub loss_recovery_episodes: u64,
+    /// The TCP close was seen; the entry is dropped after the read that reports it
+    pub closed: bool,
+    /// What the socket did between the two latest interval reads, see
+    /// `CongestionCollector::top_talkers`
+    pub last_interval: IntervalActivity,
+    // Sampled send bytes since the start of the current send_rate window
+    rate_window_start_ns: u64,
+    rate_window_bytes: u64,
+    // Since the last interval read: sampled send bytes, the highest sndbuf
+    // occupancy sampled, RTOs and recoveries
+    interval_send_bytes: u64,
+    interval_wmem_peak: Option<f64>,
+    interval_rto_events: u64,
+    interval_recoveries: u64,
+    // The shard's write count at the latest event, to tell whether the entry
+    // changed since a snapshot reported it
+    updated_at: u64,
+}
+
+impl SocketSignals {
+    fn new(timestamp_ns: u64) -> Self {
+        Self {
+            first_seen_ns: timestamp_ns,
+            last_seen_ns: timestamp_ns,
+            rate_window_start_ns: timestamp_ns,
+            ..Default::default()
+        }
+    }
+
+    /// Close the send rate window if it's long enough, then recheck the
+    /// kernel-paced hint against the sample's pacing rate
+    fn pacing_sample(
+        &mut self,
+        timestamp_ns: u64,
+        socket: &SocketData,
+        thresholds: &KernelPacedThresholds,
+    ) {
+        let span = timestamp_ns.saturating_sub(self.rate_window_start_ns);
+        if span >= SEND_RATE_WINDOW_NS {
+            let ratio = if self.fully_sampled {
+                1
+            } else {
+                SEND_SAMPLE_RATIO
+            };
+            let bytes = self.rate_window_bytes * ratio;
+            self.send_rate = Some(bytes as f64 * 1e9 / span as f64);
+            self.rate_window_start_ns = timestamp_ns;
+            self.rate_window_bytes = 0;
+        }
+        self.pacing_rate = socket.pacing_limit();
+        self.max_pacing_rate =
+            Some(socket.max_pacing_rate).filter(|&rate| rate != 0 && rate != PACING_UNLIMITED);
+        self.kernel_paced = match (self.send_rate, self.pacing_rate) {
+            (Some(send_rate), Some(limit)) => {
+                thresholds.socket_paced(send_rate, limit, socket.sndbuf)
+            }
+            _ => false,
+        };
+    }
+}
+
+/// Per-socket signals of a run of events, e.g. a recording's, as
+/// `CongestionCollector::read_per_socket` would have them after the last one
+/// with nothing retired. Under `config.socket_sampling` only sockets in the
+/// sample are returned
+pub fn replay_per_socket<'a>(
+    events: impl IntoIterator<Item = &'a CongestionEvent>,
+    config: &CollectorConfig,
+) -> HashMap<u64, SocketSignals> {
+    let table = SocketTable::from_config(config);
+    for event in events {
+        table.record(event);
+    }
+    let mut sockets = table.peek();
+    if config.socket_sampling.is_some() {
+        sockets.retain(|_, socket| socket.fully_sampled);
+    }
+    sockets
+}
+
+/// The per-socket store behind `CongestionCollector::read_per_socket`, public
+/// for replays and load tests. Safe to record into and snapshot from different
+/// threads at once, see the module docs
+pub struct SocketTable {
+    shards: Box<[ShardSlot]>,
+    // Entries a shard holds before it evicts
+    shard_cap: usize,
+    max_sockets: usize,
+    kernel_paced: KernelPacedThresholds,
+    concentration: ConcentrationConfig,
+}
+
+struct ShardSlot {
+    shard: Mutex<Shard>,
+    // Locks of this shard that had to wait for another holder
+    contended: AtomicU64,
+}
+
+// The current generation of a shard's entries, shared with the snapshots that
+// took it
+type Generation = Arc<HashMap<u64, SocketSignals>>;
+
+#[derive(Default)]
+struct Shard {
+    sockets: Generation,
+    // Events folded in, for `SocketSignals::updated_at`
+    writes: u64,
+    evictions: u64,
+    eviction_passes: u64,
+    // Scratch for evict(), kept so evicting doesn't allocate
+    last_seen: Vec<u64>,
+    // Sampled send bytes since the last interval read, and the part of them
+    // from kernel-paced sockets
+    interval_send_bytes: u64,
+    interval_paced_bytes: u64,
+    // Lifecycle events and new UDP entries since the last interval read
+    churn: ChurnCounts,
+}
+
+#[derive(Default)]
+struct ChurnCounts {
+    tcp_active_established: u64,
+    tcp_passive_established: u64,
+    tcp_closed: u64,
+    udp_first_seen: u64,
+}
+
+impl Default for SocketTable {
+    fn default() -> Self {
+        Self::new(
+            0,
+            0,
+            KernelPacedThresholds::default(),
+            ConcentrationConfig::default(),
+        )
+    }
+}
+
+impl SocketTable {
+    /// Allocated for `capacity` sockets (at most `max_sockets`), grown from there
+    pub(crate) fn new(
+        max_sockets: usize,
+        capacity: usize,
+        kernel_paced: KernelPacedThresholds,
+        concentration: ConcentrationConfig,
+    ) -> Self {
+        let shards = (max_sockets / MIN_SHARD_SOCKETS)
+            .clamp(1, MAX_SHARDS)
+            .next_power_of_two();
+        let shard_capacity = capacity.min(max_sockets).div_ceil(shards);
+        Self {
+            shards: (0..shards)
+                .map(|_| ShardSlot {
+                    shard: Mutex::new(Shard {
+                        sockets: Arc::new(HashMap::with_capacity(shard_capacity)),
+                        ..Default::default()
+                    }),
+                    contended: AtomicU64::new(0),
+                })
+                .collect(),
+            shard_cap: max_sockets.div_ceil(shards),
+            max_sockets,
+            kernel_paced,
+            concentration,
+        }
+    }
+
+    /// Sized and configured as a collector with `config` would have it
+    pub fn from_config(config: &CollectorConfig) -> Self {
+        Self::new(
+            config.max_tracked_sockets,
+            config.socket_table_capacity,
+            config.limitation.kernel_paced,
+            config.concentration,
+        )
+    }
+
+    fn shard(&self, socket_id: u64) -> MutexGuard<'_, Shard> {
+        // The low bits of the hash: per-socket sampling admits by the high ones
+        let index = SocketSampling::hash(socket_id) as usize & (self.shards.len() - 1);
+        self.lock(&self.shards[index])
+    }
+
+    fn lock<'a>(&self, slot: &'a ShardSlot) -> MutexGuard<'a, Shard> {
+        match slot.shard.try_lock() {
+            Ok(shard) => shard,
+            Err(TryLockError::WouldBlock) => {
+                slot.contended.fetch_add(1, Ordering::Relaxed);
+                slot.shard.lock().unwrap()
+            }
+            Err(TryLockError::Poisoned(e)) => panic!("socket table shard poisoned: {e}"),
+        }
+    }
+
+    /// Each shard in turn, locked only while `f` runs on it
+    fn each_shard(&self, mut f: impl FnMut(&mut Shard)) {
+        for slot in self.shards.iter() {
+            f(&mut self.lock(slot));
+        }
+    }
+
+    /// Fold one event into its socket's entry. Events of other types, and
+    /// those only a trace admitted, are ignored
+    pub fn record(&self, event: &CongestionEvent) {
+        let ts = event.timestamp_ns;
+        let Some((socket_id, is_tcp)) = event_socket(event) else {
+            return;
+        };
+        if traced_only(event) {
+            return;
+        }
+        let mut shard = self.shard(socket_id);
+        let shard = &mut *shard;
+        if shard.sockets.len() >= self.shard_cap && !shard.sockets.contains_key(&socket_id) {
+            shard.evict();
+        }
+        shard.writes += 1;
+        let sockets = Arc::make_mut(&mut shard.sockets);
+
+        if event.event_type == EVENT_SOCKET_LIFECYCLE {
+            let lifecycle = unsafe { event.data.lifecycle };
+            let churn = &mut shard.churn;
+            match (lifecycle.oldstate, lifecycle.newstate) {
+                (_, TCP_SYN_SENT | TCP_SYN_RECV) => {
+                    // A new lifetime starts here, whatever was under this id before is over
+                    sockets.insert(socket_id, SocketSignals::new(ts));
+                }
+                (TCP_SYN_SENT, TCP_ESTABLISHED) => churn.tcp_active_established += 1,
+                (TCP_SYN_RECV, TCP_ESTABLISHED) => churn.tcp_passive_established += 1,
+                (_, TCP_CLOSE) => churn.tcp_closed += 1,
+                _ => {}
+            }
+        }
+
+        let churn = &mut shard.churn;
+        let entry = sockets.entry(socket_id).or_insert_with(|| {
+            if !is_tcp {
+                churn.udp_first_seen += 1;
+            }
+            SocketSignals::new(ts)
+        });
+        if entry.closed {
+            // Only an address-based id can come back after close
+            *entry = SocketSignals::new(ts);
+        }
+        entry.last_seen_ns = entry.last_seen_ns.max(ts);
+        entry.updated_at = shard.writes;
+        entry.is_tcp |= is_tcp;
+        entry.fully_sampled |= event_samplers(event) & SAMPLER_SOCKET != 0;
+
+        match event.event_type {
+            EVENT_UDP_SEND | EVENT_TCP_SEND => {
+                let sendmsg = unsafe { event.data.sendmsg };
+                entry.send_bytes += sendmsg.bytes;
+                if sendmsg.loopback != 0 {
+                    entry.loopback_send_bytes += sendmsg.bytes;
+                }
+                entry.rate_window_bytes += sendmsg.bytes;
+                entry.interval_send_bytes += sendmsg.bytes;
+                shard.interval_send_bytes += sendmsg.bytes;
+                if entry.kernel_paced {
+                    shard.interval_paced_bytes += sendmsg.bytes;
+                }
+            }
+            EVENT_UDP_RCV_DROP => entry.udp_rcv_drops += 1,
+            EVENT_UDP_RCV_CE => entry.ce_marks += 1,
+            EVENT_SOCKET_RCV_STATE => {
+                let rcv = unsafe { event.data.rcv };
+                if rcv.rcvbuf > 0 {
+                    entry.rmem_pressure = Some(rcv.rmem_alloc as f64 / rcv.rcvbuf as f64);
+                }
+            }
+            EVENT_SOCKET_STATE => {
+                let socket = unsafe { event.data.socket };
+                if socket.sndbuf > 0 {
+                    let pressure = socket.wmem_queued as f64 / socket.sndbuf as f64;
+                    entry.wmem_pressure = Some(pressure);
+                    let peak = entry.interval_wmem_peak.unwrap_or(0.0).max(pressure);
+                    entry.interval_wmem_peak = Some(peak);
+                }
+                entry.pacing_sample(ts, &socket, &self.kernel_paced);
+            }
+            EVENT_TCP_STATE => {
+                let tcp = unsafe { event.data.tcp };
+                entry.tcp_cwnd = Some(tcp.snd_cwnd);
+                entry.tcp_ssthresh = Some(tcp.snd_ssthresh);
+            }
+            EVENT_TCP_CWR => entry.tcp_cwr += 1,
+            EVENT_TCP_RTO => {
+                entry.rto_events += 1;
+                entry.interval_rto_events += 1;
+            }
+            EVENT_TCP_RECOVERY => {
+                entry.loss_recovery_episodes += 1;
+                entry.interval_recoveries += 1;
+            }
+            EVENT_SOCKET_LIFECYCLE => {
+                entry.closed = unsafe { event.data.lifecycle.newstate } == TCP_CLOSE;
+            }
+            _ => {}
+        }
+    }
+
+    /// Entries, evictions and lock contention of each shard
+    pub fn shards(&self) -> Vec<ShardMemory> {
+        let mut shards = Vec::with_capacity(self.shards.len());
+        for slot in self.shards.iter() {
+            let shard = slot.shard.lock().unwrap();
+            shards.push(ShardMemory {
+                entries: shard.sockets.len(),
+                cap: self.shard_cap,
+                estimated_bytes: hash_table_bytes::<u64, SocketSignals>(shard.sockets.capacity()),
+                evictions: shard.evictions,
+                eviction_passes: shard.eviction_passes,
+                contended: slot.contended.load(Ordering::Relaxed),
+            });
+        }
+        shards
+    }
+
+    pub(crate) fn memory(&self) -> StructureMemory {
+        let shards = self.shards();
+        StructureMemory {
+            name: "per-socket table",
+            entries: shards.iter().map(|s| s.entries).sum(),
+            cap: self.max_sockets,
+            estimated_bytes: shards.iter().map(|s| s.estimated_bytes).sum(),
+            evictions: shards.iter().map(|s| s.evictions).sum(),
+        }
+    }
+
+    /// Treat every socket as last seen at `now_ns`, after the clock jumped
+    pub(crate) fn rebase(&self, now_ns: u64) {
+        self.each_shard(|shard| {
+            for socket in Arc::make_mut(&mut shard.sockets).values_mut() {
+                socket.first_seen_ns = socket.first_seen_ns.min(now_ns);
+                socket.last_seen_ns = now_ns;
+                socket.rate_window_start_ns = now_ns;
+                socket.rate_window_bytes = 0;
+            }
+        });
+    }
+
+    /// Share of the sampled send bytes since the previous call that came from
+    /// kernel-paced sockets, None without sends
+    pub(crate) fn take_paced_share(&self) -> Option<f64> {
+        let (mut sent, mut paced) = (0, 0);
+        self.each_shard(|shard| {
+            sent += std::mem::take(&mut shard.interval_send_bytes);
+            paced += std::mem::take(&mut shard.interval_paced_bytes);
+        });
+        (sent > 0).then(|| paced as f64 / sent as f64)
+    }
+
+    /// Connections opened and closed since the previous call, the TCP counts
+    /// only when the lifecycle tracepoint is `attached`
+    pub(crate) fn take_churn(&self, lifecycle: bool, tcp_accepts: Option<u64>) -> ConnectionChurn {
+        let mut churn = ChurnCounts::default();
+        self.each_shard(|shard| {
+            let shard = std::mem::take(&mut shard.churn);
+            churn.tcp_active_established += shard.tcp_active_established;
+            churn.tcp_passive_established += shard.tcp_passive_established;
+            churn.tcp_closed += shard.tcp_closed;
+            churn.udp_first_seen += shard.udp_first_seen;
+        });
+        ConnectionChurn {
+            tcp_active_established: lifecycle.then_some(churn.tcp_active_established),
+            tcp_passive_established: lifecycle.then_some(churn.tcp_passive_established),
+            tcp_accepts,
+            tcp_closed: lifecycle.then_some(churn.tcp_closed),
+            udp_first_seen: churn.udp_first_seen,
+        }
+    }
+
+    /// Spread of the sampled send bytes since the previous call over the
+    /// sockets that sent them. Sockets evicted or retired meanwhile are missing.
+    /// Closes every socket's `last_interval` too
+    pub(crate) fn take_concentration(&self) -> Option<SendConcentration> {
+        let mut per_socket = Vec::new();
+        self.each_shard(|shard| {
+            let sockets = Arc::make_mut(&mut shard.sockets);
+            per_socket.extend(sockets.iter_mut().map(|(&socket_id, socket)| {
+                let sampled = std::mem::take(&mut socket.interval_send_bytes);
+                let ratio = if socket.fully_sampled {
+                    1
+                } else {
+                    SEND_SAMPLE_RATIO
+                };
+                socket.last_interval = IntervalActivity {
+                    send_bytes: sampled * ratio,
+                    wmem_pressure: socket.interval_wmem_peak.take(),
+                    stalls: std::mem::take(&mut socket.interval_rto_events),
+                    retransmits: std::mem::take(&mut socket.interval_recoveries),
+                };
+                (socket_id, sampled)
+            }));
+        });
+        SendConcentration::of(per_socket, &self.concentration)
+    }
+
+    /// Each socket's `last_interval`, as of the latest `take_concentration`
+    pub(crate) fn last_intervals(&self) -> Vec<(u64, IntervalActivity)> {
+        let mut last = Vec::new();
+        self.each_shard(|shard| {
+            last.extend(
+                shard
+                    .sockets
+                    .iter()
+                    .map(|(&socket_id, socket)| (socket_id, socket.last_interval)),
+            );
+        });
+        last
+    }
+
+    /// One socket's entry as it stands
+    pub(crate) fn get(&self, socket_id: u64) -> Option<SocketSignals> {
+        self.shard(socket_id).sockets.get(&socket_id).cloned()
+    }
+
+    /// The table as it stands, retiring nothing
+    pub fn peek(&self) -> HashMap<u64, SocketSignals> {
+        let generations = self.generations();
+        let mut copy = HashMap::with_capacity(generations.iter().map(|g| g.len()).sum());
+        for generation in generations {
+            copy.extend(generation.iter().map(|(&id, socket)| (id, socket.clone())));
+        }
+        copy
+    }
+
+    /// Copy of every live entry. Closed sockets are reported once more and then
+    /// dropped; UDP (and TCP seen only mid-life) entries go after `idle_ttl`
+    /// without events
+    pub fn snapshot(&self, now_ns: u64, idle_ttl: Duration) -> HashMap<u64, SocketSignals> {
+        let ttl_ns = u64::try_from(idle_ttl.as_nanos()).unwrap_or(u64::MAX);
+        let generations = self.generations();
+        let mut copy = HashMap::with_capacity(generations.iter().map(|g| g.len()).sum());
+        for (slot, generation) in self.shards.iter().zip(generations) {
+            let retiring: Vec<(u64, u64)> = generation
+                .iter()
+                .filter(|(_, socket)| {
+                    socket.closed || now_ns.saturating_sub(socket.last_seen_ns) > ttl_ns
+                })
+                .map(|(&id, socket)| (id, socket.updated_at))
+                .collect();
+            copy.extend(generation.iter().map(|(&id, socket)| (id, socket.clone())));
+            // Let go of it first, or retiring would have to fork the shard
+            drop(generation);
+            self.retire(slot, &retiring);
+        }
+        copy
+    }
+
+    /// Every shard's current generation, taken with all the shards locked so
+    /// they're of the same instant. Writers lock one shard at a time, in no
+    /// order that could deadlock this
+    fn generations(&self) -> Vec<Generation> {
+        let shards: Vec<MutexGuard<'_, Shard>> =
+            self.shards.iter().map(|slot| self.lock(slot)).collect();
+        shards
+            .iter()
+            .map(|shard| Arc::clone(&shard.sockets))
+            .collect()
+    }
+
+    /// Drop the entries a snapshot reported for the last time, by id and
+    /// `updated_at`, unless an event came in for them since
+    fn retire(&self, slot: &ShardSlot, retiring: &[(u64, u64)]) {
+        if retiring.is_empty() {
+            return;
+        }
+        let mut shard = self.lock(slot);
+        let sockets = Arc::make_mut(&mut shard.sockets);
+        for (socket_id, updated_at) in retiring {
+            if sockets
+                .get(socket_id)
+                .is_some_and(|socket| socket.updated_at == *updated_at)
+            {
+                sockets.remove(socket_id);
+            }
+        }
+    }
+}
+
+impl Shard {
+    /// Drop the least recently seen eighth of this shard. In batches, so a
+    /// full shard doesn't scan for the oldest entry on every new socket, and
+    /// one shard at a time, so no eviction scans the whole table
+    fn evict(&mut self) {
+        let last_seen = &mut self.last_seen;
+        last_seen.clear();
+        last_seen.extend(self.sockets.values().map(|s| s.last_seen_ns));
+        if last_seen.is_empty() {
+            return;
+        }
+        let oldest = (last_seen.len() / 8).max(1);
+        let cutoff = *last_seen.select_nth_unstable(oldest - 1).1;
+        let before = self.sockets.len();
+        Arc::make_mut(&mut self.sockets).retain(|_, socket| socket.last_seen_ns > cutoff);
+        let evicted = before - self.sockets.len();
+        self.evictions += evicted as u64;
+        self.eviction_passes += 1;
+        log::debug!("socket shard full, evicted {} least recently seen", evicted);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    const SOCKET: u64 = 0xffff_8880_1234_5000;
+
+    fn table(events: &[CongestionEvent]) -> SocketTable {
+        let table = SocketTable::from_config(&CollectorConfig::default());
+        for event in events {
+            table.record(event);
+        }
+        table
+    }
+
+    #[test]
+    fn a_reused_id_starts_a_new_lifetime() {
+        let table = table(&[
+            fixtures::lifecycle(1_000, SOCKET, TCP_CLOSE, TCP_SYN_SENT),
+            fixtures::tcp_send(2_000, 0, SOCKET, 5_000),
+            fixtures::tcp_state(3_000, 0, SOCKET, 10, 20),
+            fixtures::lifecycle(5_000, SOCKET, TCP_ESTABLISHED, TCP_CLOSE),
+            // The address comes back for a new connection
+            fixtures::lifecycle(6_000, SOCKET, TCP_CLOSE, TCP_SYN_SENT),
+            fixtures::tcp_send(7_000, 0, SOCKET, 300),
+        ]);
+
+        let socket = table.get(SOCKET).unwrap();
+        assert_eq!(socket.first_seen_ns, 6_000);
+        assert_eq!(socket.last_seen_ns, 7_000);
+        assert_eq!(socket.send_bytes, 300);
+        assert_eq!(socket.tcp_cwnd, None);
+        assert!(!socket.closed);
+    }
+
+    #[test]
+    fn any_event_after_a_close_starts_a_new_lifetime() {
+        // An address-based id seen again without the open, e.g. for UDP
+        let table = table(&[
+            fixtures::tcp_send(1_000, 0, SOCKET, 5_000),
+            fixtures::lifecycle(2_000, SOCKET, TCP_ESTABLISHED, TCP_CLOSE),
+            fixtures::udp_send(3_000, 0, SOCKET, 100),
+        ]);
+
+        let socket = table.get(SOCKET).unwrap();
+        assert_eq!(socket.first_seen_ns, 3_000);
+        assert_eq!(socket.send_bytes, 100);
+    }
+
+    #[test]
+    fn a_closed_socket_is_reported_once_more() {
+        let table = table(&[
+            fixtures::tcp_send(1_000, 0, SOCKET, 5_000),
+            fixtures::lifecycle(2_000, SOCKET, TCP_ESTABLISHED, TCP_CLOSE),
+        ]);
+
+        let first = table.snapshot(2_000, Duration::MAX);
+        assert!(first[&SOCKET].closed);
+        assert_eq!(first[&SOCKET].send_bytes, 5_000);
+        assert!(table.snapshot(2_000, Duration::MAX).is_empty());
+    }
+
+    #[test]
+    fn idle_sockets_retire_after_the_ttl() {
+        let table = table(&[
+            fixtures::udp_send(1_000_000_000, 0, 1, 100),
+            fixtures::udp_send(2_000_000_000, 0, 2, 100),
+        ]);
+        let ttl = Duration::from_secs(1);
+
+        let sockets = table.snapshot(2_000_000_000, ttl);
+        assert_eq!(sockets.len(), 2);
+        // The read that retires a socket still returns it
+        let sockets = table.snapshot(2_500_000_000, ttl);
+        assert_eq!(sockets.len(), 2);
+        let sockets = table.snapshot(2_500_000_000, ttl);
+        assert_eq!(sockets.keys().collect::<Vec<_>>(), vec![&2]);
+    }
+
+    #[test]
+    fn replayed_lifetimes_are_not_merged() {
+        let events = [
+            fixtures::lifecycle(1_000, SOCKET, TCP_CLOSE, TCP_SYN_SENT),
+            fixtures::tcp_send(2_000, 0, SOCKET, 5_000),
+            fixtures::lifecycle(3_000, SOCKET, TCP_ESTABLISHED, TCP_CLOSE),
+            fixtures::lifecycle(4_000, SOCKET, TCP_CLOSE, TCP_SYN_RECV),
+            fixtures::tcp_send(5_000, 0, SOCKET, 700),
+        ];
+        let sockets = replay_per_socket(&events, &CollectorConfig::default());
+        assert_eq!(sockets[&SOCKET].send_bytes, 700);
+        assert_eq!(sockets[&SOCKET].first_seen_ns, 4_000);
+    }
+
+    fn capped(max_tracked_sockets: usize) -> SocketTable {
+        SocketTable::from_config(&CollectorConfig {
+            max_tracked_sockets,
+            ..CollectorConfig::default()
+        })
+    }
+
+    #[test]
+    fn a_full_table_evicts_its_least_recently_seen_eighth() {
+        let table = capped(MIN_SHARD_SOCKETS);
+        for socket_id in 1..=256 {
+            table.record(&fixtures::udp_send(socket_id, 0, socket_id, 1_200));
+        }
+        let memory = table.memory();
+        assert_eq!(
+            (memory.entries, memory.cap, memory.evictions),
+            (256, 256, 0)
+        );
+
+        table.record(&fixtures::udp_send(1_000, 0, 1_000, 1_200));
+        let memory = table.memory();
+        assert_eq!((memory.entries, memory.evictions), (256 - 32 + 1, 32));
+        assert!(table.get(32).is_none());
+        assert!(table.get(33).is_some() && table.get(1_000).is_some());
+        // Known sockets go on being counted without evicting anything
+        table.record(&fixtures::udp_send(1_001, 0, 33, 1_200));
+        assert_eq!(table.memory().evictions, 32);
+        assert_eq!(table.get(33).unwrap().send_bytes, 2_400);
+    }
+
+    #[test]
+    fn filling_the_table_stays_under_its_cap() {
+        let table = capped(4_096);
+        let sockets = 100_000;
+        for socket_id in 1..=sockets {
+            table.record(&fixtures::udp_send(socket_id, 0, socket_id, 1_200));
+            if socket_id % 10_000 == 0 {
+                assert!(table.memory().entries <= 4_096);
+            }
+        }
+        let memory = table.memory();
+        assert_eq!(memory.evictions, sockets - memory.entries as u64);
+        let shards = table.shards();
+        assert_eq!(shards.len(), MAX_SHARDS);
+        assert!(shards.iter().all(|shard| shard.entries <= shard.cap));
+        assert_eq!(
+            shards.iter().map(|shard| shard.evictions).sum::<u64>(),
+            memory.evictions
+        );
+        // The newest socket is there, the first long gone
+        assert!(table.get(sockets).is_some());
+        assert!(table.get(1).is_none());
+    }
+
+    #[test]
+    fn loss_episodes_are_counted_per_socket() {
+        let table = table(&[
+            fixtures::tcp_recovery(1_000, 0, SOCKET, 40),
+            fixtures::tcp_recovery(2_000, 0, SOCKET, 20),
+            fixtures::tcp_rto(3_000, 1, SOCKET, 10),
+            fixtures::tcp_recovery(4_000, 0, SOCKET + 1, 40),
+        ]);
+
+        let sockets = table.snapshot(4_000, Duration::MAX);
+        assert_eq!(sockets[&SOCKET].loss_recovery_episodes, 2);
+        assert_eq!(sockets[&SOCKET].rto_events, 1);
+        assert_eq!(sockets[&(SOCKET + 1)].loss_recovery_episodes, 1);
+        assert_eq!(sockets[&(SOCKET + 1)].rto_events, 0);
+    }
+
+    #[test]
+    fn a_snapshot_leaves_entries_written_since_it() {
+        let table = table(&[
+            fixtures::tcp_send(1_000, 0, SOCKET, 5_000),
+            fixtures::lifecycle(2_000, SOCKET, TCP_ESTABLISHED, TCP_CLOSE),
+        ]);
+        let index = SocketSampling::hash(SOCKET) as usize & (table.shards.len() - 1);
+        let reported = table.generations().swap_remove(index);
+        let retiring = [(SOCKET, reported[&SOCKET].updated_at)];
+        drop(reported);
+        // The address comes back before the read retires the closed lifetime
+        table.record(&fixtures::udp_send(3_000, 0, SOCKET, 100));
+        table.retire(&table.shards[index], &retiring);
+        assert_eq!(table.get(SOCKET).unwrap().send_bytes, 100);
+    }
+
+    #[test]
+    fn a_held_snapshot_is_not_written_through() {
+        let table = table(&[fixtures::udp_send(1_000, 0, SOCKET, 100)]);
+        let generations = table.generations();
+        table.record(&fixtures::udp_send(2_000, 0, SOCKET, 100));
+        table.record(&fixtures::udp_send(2_000, 0, SOCKET + 1, 100));
+        let held: usize = generations.iter().map(|generation| generation.len()).sum();
+        assert_eq!(held, 1);
+        let socket = generations.iter().find_map(|g| g.get(&SOCKET)).unwrap();
+        assert_eq!(socket.send_bytes, 100);
+        assert_eq!(table.get(SOCKET).unwrap().send_bytes, 200);
+    }
+
+    // At most CHURN_LIVE open at once
+    const CHURN_LIFECYCLES: u64 = 100_000;
+    const CHURN_LIVE: u64 = 1_000;
+    const CHURN_SENDS: u64 = 3;
+    // One lifecycle per step of the replay's clock, a snapshot every
+    // CHURN_READ_EVERY of it
+    const CHURN_STEP: Duration = Duration::from_micros(1);
+    const CHURN_READ_EVERY: Duration = Duration::from_millis(10);
+    // CPU time a snapshot may take. The replay closes sockets far faster than
+    // real churn, ten thousand between reads
+    const CHURN_MAX_READ: Duration = Duration::from_millis(50);
+
+    /// CPU time of the calling thread so far
+    fn thread_cpu_time() -> Duration {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut ts) };
+        Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+    }
+
+    /// 100k TCP lifetimes recorded while another thread snapshots every 10 ms
+    /// of the recording's ManualClock, as `read_per_socket` does: every
+    /// lifetime is reported closed exactly once with all its bytes, none is
+    /// left behind, and no snapshot takes more than CHURN_MAX_READ. CPU time
+    /// rather than wall, which other tests running alongside would stretch
+    #[test]
+    fn snapshots_under_churn_lose_nothing() {
+        use crate::Clock;
+        use std::collections::HashSet;
+        use std::sync::atomic::AtomicBool;
+        let bytes_of = |socket_id: u64| 1200 + (socket_id % 7) * 100;
+        let table = SocketTable::from_config(&CollectorConfig::default());
+        let done = AtomicBool::new(false);
+        let clock = crate::ManualClock::new();
+        let (sent, (reads, closed, duplicates, reported)) = std::thread::scope(|scope| {
+            let reader = scope.spawn(|| {
+                let mut next = clock.now();
+                let mut reads = Vec::new();
+                let mut closed = HashSet::new();
+                let (mut duplicates, mut reported) = (0, 0);
+                loop {
+                    let finished = done.load(Ordering::Acquire);
+                    let cpu = thread_cpu_time();
+                    let snapshot = table.snapshot(0, Duration::MAX);
+                    reads.push(thread_cpu_time() - cpu);
+                    for (socket_id, socket) in snapshot.into_iter().filter(|(_, s)| s.closed) {
+                        if !closed.insert(socket_id) {
+                            duplicates += 1;
+                        }
+                        reported += socket.send_bytes;
+                    }
+                    if finished {
+                        break;
+                    }
+                    next += CHURN_READ_EVERY;
+                    while clock.now() < next && !done.load(Ordering::Acquire) {
+                        std::thread::yield_now();
+                    }
+                }
+                (reads, closed, duplicates, reported)
+            });
+
+            let (mut sent, mut ts) = (0, 0);
+            for i in 0..CHURN_LIFECYCLES + CHURN_LIVE {
+                ts += CHURN_STEP.as_nanos() as u64;
+                clock.advance(CHURN_STEP);
+                if i < CHURN_LIFECYCLES {
+                    let socket_id = i + 1;
+                    table.record(&fixtures::lifecycle(ts, socket_id, TCP_CLOSE, TCP_SYN_SENT));
+                    table.record(&fixtures::lifecycle(
+                        ts,
+                        socket_id,
+                        TCP_SYN_SENT,
+                        TCP_ESTABLISHED,
+                    ));
+                    for _ in 0..CHURN_SENDS {
+                        table.record(&fixtures::tcp_send(ts, 0, socket_id, bytes_of(socket_id)));
+                        sent += bytes_of(socket_id);
+                    }
+                }
+                if i >= CHURN_LIVE {
+                    let closing = i - CHURN_LIVE + 1;
+                    table.record(&fixtures::lifecycle(
+                        ts,
+                        closing,
+                        TCP_ESTABLISHED,
+                        TCP_CLOSE,
+                    ));
+                }
+            }
+            done.store(true, Ordering::Release);
+            (sent, reader.join().unwrap())
+        });
+
+        assert_eq!(closed.len() as u64, CHURN_LIFECYCLES);
+        assert_eq!(duplicates, 0);
+        assert_eq!(reported, sent);
+        assert!(table.peek().is_empty());
+        assert!(table.shards().iter().all(|shard| shard.evictions == 0));
+        let slowest = reads.iter().max().unwrap();
+        assert!(
+            *slowest <= CHURN_MAX_READ,
+            "{} snapshots, slowest {:?}",
+            reads.len(),
+            slowest
+        );
+    }
+}
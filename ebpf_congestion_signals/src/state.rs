@@ -0,0 +1,491 @@
+//Cumulative counters carried over a restart of the process. The kernel's own
+//counters (SNMP, qdisc stats) keep counting while we're gone, but everything
+//the collector sums up since load starts again from zero with the new object.
+//With a state file the totals are saved periodically and on drop, and the next
+//load adds them back as offsets, so cumulative readers see one monotonic count.
+//
+//A reboot resets everything kernel-side too, so a file from another boot, by
+//the kernel's random boot_id, is discarded; so is one older than
+//`max_age`, whose gap would hide everything that happened in between. The file
+//is line-based text ending in an FNV-1a checksum of the lines above it;
+//anything that doesn't check out is discarded with a warning, never half-read.
+
+use crate::json::unix_ms;
+use crate::CongestionSignals;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Layout of the state file, bumped when a line changes meaning
+pub const STATE_FILE_VERSION: u32 = 1;
+
+const HEADER: &str = "ebpf-congestion-signals state";
+const BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+
+/// Where and how often cumulative totals are saved, see
+/// `CollectorConfig::with_state_file`
+#[derive(Debug, Clone)]
+pub struct StateFileConfig {
+    pub path: PathBuf,
+    /// Saved at the first interval read this long after the previous save, and
+    /// on drop. What a crash loses at most
+    pub save_every: Duration,
+    /// A file saved longer ago than this is discarded at load
+    pub max_age: Duration,
+}
+
+impl StateFileConfig {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            save_every: Duration::from_secs(60),
+            max_age: Duration::from_secs(300),
+        }
+    }
+}
+
+/// Egress and TX queue stalls on one interface, summed over interval reads
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InterfaceTotals {
+    pub egress_bytes: u64,
+    pub egress_packets: u64,
+    pub txq_stalls: u64,
+}
+
+/// One cgroup's totals, as counted in the kernel for `read_per_cgroup`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CgroupTotals {
+    /// Sampled, like `CongestionSignals::send_bytes`
+    pub send_bytes: u64,
+    pub drops: u64,
+}
+
+/// Counters since the first load whose totals were carried over, see
+/// `CongestionCollector::cumulative`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CumulativeTotals {
+    /// Interval reads summed in
+    pub intervals: u64,
+    /// Sampled, like `CongestionSignals::send_bytes`
+    pub send_bytes: u64,
+    pub external_send_bytes: u64,
+    pub drops: u64,
+    pub udp_rcv_drops: u64,
+    pub event_count: u64,
+    pub softirq_ns: u64,
+    /// By interface name
+    pub interfaces: BTreeMap<String, InterfaceTotals>,
+    /// By cgroup id, with cgroup aggregation on
+    pub cgroups: BTreeMap<u64, CgroupTotals>,
+    /// kfree_skb drops by raw enum skb_drop_reason value, which only means
+    /// the same thing on the same kernel; a boot id match guarantees that
+    pub drops_by_reason: BTreeMap<u32, u64>,
+}
+
+impl CumulativeTotals {
+    /// Sum in one interval read
+    pub fn add_interval(&mut self, signals: &CongestionSignals) {
+        self.intervals += 1;
+        self.send_bytes += signals.send_bytes;
+        self.external_send_bytes += signals.external_send_bytes;
+        self.drops += signals.drops;
+        self.udp_rcv_drops += signals.udp_rcv_drops;
+        self.event_count += signals.event_count;
+        self.softirq_ns += signals.softirq_ns;
+        for egress in &signals.egress {
+            let totals = self.interfaces.entry(egress.interface.clone()).or_default();
+            totals.egress_bytes += egress.egress_bytes_exact;
+            totals.egress_packets += egress.egress_packets_exact;
+        }
+        for txq in &signals.txq_by_interface {
+            self.interfaces
+                .entry(txq.interface.clone())
+                .or_default()
+                .txq_stalls += txq.txq_stalls;
+        }
+    }
+}
+
+/// Why a state file was discarded
+#[derive(Debug)]
+pub enum StateError {
+    Io(io::Error),
+    /// Written by a build with another `STATE_FILE_VERSION`
+    Version(u32),
+    /// The checksum line is missing or doesn't match the content
+    Checksum,
+    /// A line that doesn't parse, 1-based
+    Malformed(usize),
+    /// Saved in another boot, whose kernel counters are gone
+    BootId {
+        saved: String,
+        current: String,
+    },
+    /// Saved this long ago, past `StateFileConfig::max_age`
+    Stale(Duration),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::Io(e) => write!(f, "state file unreadable: {}", e),
+            StateError::Version(version) => write!(
+                f,
+                "state file version {} does not match {}",
+                version, STATE_FILE_VERSION
+            ),
+            StateError::Checksum => write!(f, "state file checksum mismatch"),
+            StateError::Malformed(line) => write!(f, "state file line {} malformed", line),
+            StateError::BootId { saved, current } => write!(
+                f,
+                "state file is from boot {}, this is boot {}",
+                saved, current
+            ),
+            StateError::Stale(age) => write!(f, "state file is {:?} old", age),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl From<io::Error> for StateError {
+    fn from(e: io::Error) -> Self {
+        StateError::Io(e)
+    }
+}
+
+/// What goes in the state file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PersistedState {
+    pub boot_id: String,
+    pub saved_at: SystemTime,
+    pub totals: CumulativeTotals,
+}
+
+impl PersistedState {
+    /// `totals` as of now, in this boot
+    pub fn capture(totals: CumulativeTotals) -> io::Result<Self> {
+        Ok(Self {
+            boot_id: boot_id()?,
+            saved_at: SystemTime::now(),
+            totals,
+        })
+    }
+
+    pub fn encode(&self) -> String {
+        let totals = &self.totals;
+        let mut text = format!("{} {}\n", HEADER, STATE_FILE_VERSION);
+        let mut line = |key: &str, value: String| {
+            text.push_str(key);
+            text.push(' ');
+            text.push_str(&value);
+            text.push('\n');
+        };
+        line("boot_id", self.boot_id.clone());
+        line("saved_at_ms", unix_ms(self.saved_at).to_string());
+        line("intervals", totals.intervals.to_string());
+        line("send_bytes", totals.send_bytes.to_string());
+        line(
+            "external_send_bytes",
+            totals.external_send_bytes.to_string(),
+        );
+        line("drops", totals.drops.to_string());
+        line("udp_rcv_drops", totals.udp_rcv_drops.to_string());
+        line("event_count", totals.event_count.to_string());
+        line("softirq_ns", totals.softirq_ns.to_string());
+        for (name, t) in &totals.interfaces {
+            line(
+                "interface",
+                format!(
+                    "{} {} {} {}",
+                    name, t.egress_bytes, t.egress_packets, t.txq_stalls
+                ),
+            );
+        }
+        for (id, t) in &totals.cgroups {
+            line("cgroup", format!("{} {} {}", id, t.send_bytes, t.drops));
+        }
+        for (reason, count) in &totals.drops_by_reason {
+            line("drop_reason", format!("{} {}", reason, count));
+        }
+        let checksum = fnv1a(text.as_bytes());
+        text.push_str(&format!("checksum {:016x}\n", checksum));
+        text
+    }
+
+    /// Parse a file written by `encode`, checking version and checksum; not
+    /// yet whether it's from this boot or fresh, see `check`
+    pub fn decode(text: &str) -> Result<Self, StateError> {
+        let body_len = text
+            .trim_end_matches('\n')
+            .rfind('\n')
+            .map_or(0, |newline| newline + 1);
+        let (body, checksum_line) = text.split_at(body_len);
+        let checksum = checksum_line
+            .trim_end()
+            .strip_prefix("checksum ")
+            .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+            .ok_or(StateError::Checksum)?;
+
+        let mut lines = body.lines().enumerate().map(|(i, line)| (i + 1, line));
+        let version = lines
+            .next()
+            .and_then(|(_, header)| header.strip_prefix(HEADER))
+            .and_then(|version| version.trim().parse::<u32>().ok())
+            .ok_or(StateError::Malformed(1))?;
+        if version != STATE_FILE_VERSION {
+            return Err(StateError::Version(version));
+        }
+        if fnv1a(body.as_bytes()) != checksum {
+            return Err(StateError::Checksum);
+        }
+
+        let mut boot_id = None;
+        let mut saved_at = None;
+        let mut totals = CumulativeTotals::default();
+        for (number, line) in lines {
+            let malformed = || StateError::Malformed(number);
+            let fields: Vec<&str> = line.split(' ').collect();
+            let text = |i: usize| fields.get(i).copied().ok_or_else(malformed);
+            let value = |i: usize| text(i)?.parse::<u64>().map_err(|_| malformed());
+            match fields[0] {
+                "boot_id" => boot_id = Some(text(1)?.to_string()),
+                "saved_at_ms" => saved_at = Some(UNIX_EPOCH + Duration::from_millis(value(1)?)),
+                "intervals" => totals.intervals = value(1)?,
+                "send_bytes" => totals.send_bytes = value(1)?,
+                "external_send_bytes" => totals.external_send_bytes = value(1)?,
+                "drops" => totals.drops = value(1)?,
+                "udp_rcv_drops" => totals.udp_rcv_drops = value(1)?,
+                "event_count" => totals.event_count = value(1)?,
+                "softirq_ns" => totals.softirq_ns = value(1)?,
+                "interface" => {
+                    let interface = InterfaceTotals {
+                        egress_bytes: value(2)?,
+                        egress_packets: value(3)?,
+                        txq_stalls: value(4)?,
+                    };
+                    totals.interfaces.insert(text(1)?.to_string(), interface);
+                }
+                "cgroup" => {
+                    let cgroup = CgroupTotals {
+                        send_bytes: value(2)?,
+                        drops: value(3)?,
+                    };
+                    totals.cgroups.insert(value(1)?, cgroup);
+                }
+                "drop_reason" => {
+                    let reason = u32::try_from(value(1)?).map_err(|_| malformed())?;
+                    totals.drops_by_reason.insert(reason, value(2)?);
+                }
+                _ => return Err(malformed()),
+            }
+        }
+        Ok(Self {
+            boot_id: boot_id.ok_or(StateError::Malformed(2))?,
+            saved_at: saved_at.ok_or(StateError::Malformed(3))?,
+            totals,
+        })
+    }
+
+    /// Whether these totals still add up with this boot's counters: saved in
+    /// `boot_id`, no longer than `max_age` before `now`
+    pub fn check(
+        &self,
+        boot_id: &str,
+        now: SystemTime,
+        max_age: Duration,
+    ) -> Result<(), StateError> {
+        if self.boot_id != boot_id {
+            return Err(StateError::BootId {
+                saved: self.boot_id.clone(),
+                current: boot_id.to_string(),
+            });
+        }
+        // A clock stepped back makes the file look new, which is the lesser evil
+        let age = now.duration_since(self.saved_at).unwrap_or_default();
+        if age > max_age {
+            return Err(StateError::Stale(age));
+        }
+        Ok(())
+    }
+
+    /// Write to `path` through a temporary file renamed over it, so a crash
+    /// mid-write leaves the previous file
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, self.encode())?;
+        std::fs::rename(&tmp, path)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, StateError> {
+        Self::decode(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// This boot's id, a UUID that changes at every boot
+pub fn boot_id() -> io::Result<String> {
+    Ok(std::fs::read_to_string(BOOT_ID_PATH)?.trim().to_string())
+}
+
+/// Totals to start from per `config`: the file's if it's from this boot and
+/// fresh, else nothing, with a warning unless there was no file
+#[cfg(feature = "collector-core")]
+pub(crate) fn restore(config: &StateFileConfig) -> Option<CumulativeTotals> {
+    let restored = PersistedState::load(&config.path).and_then(|state| {
+        state.check(&boot_id()?, SystemTime::now(), config.max_age)?;
+        Ok(state.totals)
+    });
+    match restored {
+        Ok(totals) => {
+            log::info!(
+                "Restored cumulative totals of {} intervals from {}",
+                totals.intervals,
+                config.path.display()
+            );
+            Some(totals)
+        }
+        Err(StateError::Io(e)) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => {
+            log::warn!("Discarding {}: {}", config.path.display(), e);
+            None
+        }
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_AGE: Duration = Duration::from_secs(300);
+
+    fn saved(boot_id: &str, ago: Duration) -> PersistedState {
+        let mut totals = CumulativeTotals {
+            intervals: 12,
+            send_bytes: 48_000,
+            drops: 3,
+            ..Default::default()
+        };
+        totals.interfaces.insert(
+            "eth0".to_string(),
+            InterfaceTotals {
+                egress_bytes: 4_800_000,
+                egress_packets: 4_000,
+                txq_stalls: 1,
+            },
+        );
+        totals.cgroups.insert(
+            4242,
+            CgroupTotals {
+                send_bytes: 9_600,
+                drops: 1,
+            },
+        );
+        totals.drops_by_reason.insert(2, 3);
+        // The file keeps milliseconds
+        let saved_at = (SystemTime::now() - ago)
+            .duration_since(UNIX_EPOCH)
+            .unwrap();
+        PersistedState {
+            boot_id: boot_id.to_string(),
+            saved_at: UNIX_EPOCH + Duration::from_millis(saved_at.as_millis() as u64),
+            totals,
+        }
+    }
+
+    #[test]
+    fn a_state_file_round_trips() {
+        let state = saved("boot-a", Duration::from_secs(30));
+        let decoded = PersistedState::decode(&state.encode()).unwrap();
+        assert_eq!(decoded, state);
+        assert!(decoded.check("boot-a", SystemTime::now(), MAX_AGE).is_ok());
+    }
+
+    #[test]
+    fn a_changed_or_cut_short_file_is_refused() {
+        let text = saved("boot-a", Duration::ZERO).encode();
+        let corrupted = text.replace("send_bytes 48000", "send_bytes 48001");
+        assert!(matches!(
+            PersistedState::decode(&corrupted),
+            Err(StateError::Checksum)
+        ));
+        let cut = &text[..text.len() / 2];
+        assert!(PersistedState::decode(cut).is_err());
+    }
+
+    #[test]
+    fn a_file_from_another_boot_or_too_long_ago_is_refused() {
+        let state = saved("boot-a", Duration::ZERO);
+        assert!(matches!(
+            state.check("boot-b", SystemTime::now(), MAX_AGE),
+            Err(StateError::BootId { .. })
+        ));
+        assert!(matches!(
+            state.check("boot-a", SystemTime::now() + MAX_AGE * 2, MAX_AGE),
+            Err(StateError::Stale(_))
+        ));
+        // A clock stepped back is taken as fresh
+        assert!(state
+            .check("boot-a", SystemTime::now() - MAX_AGE, MAX_AGE)
+            .is_ok());
+    }
+
+    #[test]
+    fn save_replaces_the_file_whole() {
+        let dir = crate::fixtures::scratch_dir("state-save");
+        let path = dir.join("state");
+        saved("boot-a", Duration::ZERO).save(&path).unwrap();
+        let state = saved("boot-b", Duration::ZERO);
+        state.save(&path).unwrap();
+        assert_eq!(PersistedState::load(&path).unwrap(), state);
+        // No temporary file left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[cfg(feature = "collector-core")]
+    #[test]
+    fn only_a_fresh_file_from_this_boot_is_restored() {
+        let Ok(boot) = boot_id() else {
+            // No /proc/sys/kernel/random here
+            return;
+        };
+        let dir = crate::fixtures::scratch_dir("state-restore");
+        let config = StateFileConfig::new(dir.join("state"));
+        // No file yet
+        assert_eq!(restore(&config), None);
+        saved(&boot, Duration::from_secs(10))
+            .save(&config.path)
+            .unwrap();
+        assert_eq!(restore(&config).unwrap().send_bytes, 48_000);
+        saved(&boot, MAX_AGE * 2).save(&config.path).unwrap();
+        assert_eq!(restore(&config), None);
+        saved("another boot", Duration::ZERO)
+            .save(&config.path)
+            .unwrap();
+        assert_eq!(restore(&config), None);
+    }
+
+    #[test]
+    fn intervals_sum_into_the_totals() {
+        let mut totals = CumulativeTotals::default();
+        let signals = CongestionSignals::builder()
+            .send_bytes(1200)
+            .external_send_bytes(1000)
+            .drops(2)
+            .build();
+        totals.add_interval(&signals);
+        totals.add_interval(&signals);
+        assert_eq!(totals.intervals, 2);
+        assert_eq!(totals.send_bytes, 2400);
+        assert_eq!(totals.external_send_bytes, 2000);
+        assert_eq!(totals.drops, 4);
+    }
+}
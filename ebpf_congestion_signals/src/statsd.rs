@@ -0,0 +1,613 @@
+//Push-based export for sites that only run a local statsd/dogstatsd agent.
+//Each flush turns one interval's signals into gauges/counters, packed into as
+//few UDP datagrams as fit the configured size. Sending is fire-and-forget:
+//socket errors are counted, never returned.
+
+#[cfg(feature = "async-runtime")]
+use crate::runtime::{MissedTicks, Rt, Runtime, Ticker};
+#[cfg(feature = "collector-core")]
+use crate::HeartbeatStatus;
+use crate::{CongestionSignals, CongestionState, JsonValue, ReaderStats};
+use std::collections::HashMap;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+// Fits a 1500 MTU with IPv6 + UDP headers
+const DEFAULT_MAX_DATAGRAM: usize = 1432;
+
+pub struct StatsdExporter {
+    socket: UdpSocket,
+    addr: SocketAddr,
+    prefix: String,
+    interval: Duration,
+    // Preformatted "|#k:v,k:v" suffix, empty without tags
+    tags: String,
+    max_datagram: usize,
+    // Cgroups per flush_per_cgroup, None until with_cgroup_series
+    cgroup_series: Option<usize>,
+    // Cumulative ReaderStats at the previous flush, to emit deltas as counters
+    last_perf_lost: u64,
+    last_queue_dropped: u64,
+    send_errors: u64,
+}
+
+impl StatsdExporter {
+    /// `tags` are constant dogstatsd tags added to every metric, e.g. `("site", "ams1")`
+    pub fn new(
+        addr: SocketAddr,
+        prefix: &str,
+        interval: Duration,
+        tags: &[(&str, &str)],
+    ) -> std::io::Result<Self> {
+        let bind: SocketAddr = if addr.is_ipv4() {
+            "0.0.0.0:0".parse().unwrap()
+        } else {
+            "[::]:0".parse().unwrap()
+        };
+        let socket = UdpSocket::bind(bind)?;
+        socket.set_nonblocking(true)?;
+
+        let tags = if tags.is_empty() {
+            String::new()
+        } else {
+            let joined: Vec<String> = tags.iter().map(|(k, v)| format!("{}:{}", k, v)).collect();
+            format!("|#{}", joined.join(","))
+        };
+
+        Ok(Self {
+            socket,
+            addr,
+            prefix: prefix.trim_end_matches('.').to_string(),
+            interval,
+            tags,
+            max_datagram: DEFAULT_MAX_DATAGRAM,
+            cgroup_series: None,
+            last_perf_lost: 0,
+            last_queue_dropped: 0,
+            send_errors: 0,
+        })
+    }
+
+    /// Largest datagram payload to send. A single metric longer than this still
+    /// goes out on its own
+    pub fn with_max_datagram(mut self, bytes: usize) -> Self {
+        self.max_datagram = bytes;
+        self
+    }
+
+    /// Let `flush_per_cgroup` emit series tagged `cgroup:<path>`, for at most
+    /// `max_cgroups` cgroups per flush, the most sending first. Every cgroup is
+    /// a new set of series in the agent and its backend, so keep this to what
+    /// the backend's cardinality budget takes; a rollup to pods or containers
+    /// helps
+    pub fn with_cgroup_series(mut self, max_cgroups: usize) -> Self {
+        self.cgroup_series = Some(max_cgroups);
+        self
+    }
+
+    /// Datagrams that failed to send since creation
+    pub fn send_errors(&self) -> u64 {
+        self.send_errors
+    }
+
+    /// Format one interval's metrics into datagram payloads
+    pub fn encode(&mut self, signals: &CongestionSignals, stats: &ReaderStats) -> Vec<Vec<u8>> {
+        let secs = if signals.interval_ns > 0 {
+            signals.interval_ns as f64 / 1e9
+        } else {
+            self.interval.as_secs_f64().max(f64::EPSILON)
+        };
+        let lost = stats.perf_lost.saturating_sub(self.last_perf_lost)
+            + stats.queue_dropped.saturating_sub(self.last_queue_dropped);
+        self.last_perf_lost = stats.perf_lost;
+        self.last_queue_dropped = stats.queue_dropped;
+
+        let mut metrics = vec![
+            ("send_bytes_per_sec", signals.send_bytes as f64 / secs, "g"),
+            ("drops_per_sec", signals.drops as f64 / secs, "g"),
+            (
+                "udp_rcv_drops_per_sec",
+                signals.udp_rcv_drops as f64 / secs,
+                "g",
+            ),
+            ("wmem_pressure", signals.avg_wmem_pressure, "g"),
+            ("rmem_pressure", signals.avg_rmem_pressure, "g"),
+            ("softirq_fraction", signals.softirq_cpu_fraction, "g"),
+            ("send_bytes", signals.send_bytes as f64, "c"),
+            ("drops", signals.drops as f64, "c"),
+            ("lost_events", lost as f64, "c"),
+        ];
+        let estimated = &signals.estimated;
+        metrics.push((
+            "estimated_send_bytes_per_sec",
+            estimated.send_bytes as f64 / secs,
+            "g",
+        ));
+        metrics.push((
+            "estimated_send_packets_per_sec",
+            estimated.send_packets as f64 / secs,
+            "g",
+        ));
+        for (name, per_interval) in [
+            ("est_udp_send_msgs_per_sec", signals.est_send_msgs.udp),
+            ("est_tcp_send_msgs_per_sec", signals.est_send_msgs.tcp),
+            ("est_udp_wire_packets_per_sec", signals.est_wire_packets.udp),
+            ("est_tcp_wire_packets_per_sec", signals.est_wire_packets.tcp),
+        ] {
+            metrics.push((name, per_interval as f64 / secs, "g"));
+        }
+        metrics.push(("send_scale_factor", estimated.scale.send_factor, "g"));
+        metrics.push(("estimated_send_bytes", estimated.send_bytes as f64, "c"));
+        metrics.push((
+            "events_received",
+            signals.observed.events_received as f64,
+            "c",
+        ));
+        metrics.push(("softirq_exits", signals.observed.softirq_exits as f64, "c"));
+        metrics.push(("active_sockets", signals.active_sockets as f64, "g"));
+        if let Some(flows) = signals.active_flows {
+            metrics.push(("active_flows", flows as f64, "g"));
+        }
+        metrics.push((
+            "new_connections_per_sec",
+            signals.new_connections_per_interval as f64 / secs,
+            "g",
+        ));
+        metrics.push((
+            "closed_connections_per_sec",
+            signals.closed_connections_per_interval as f64 / secs,
+            "g",
+        ));
+        if let Some(accepts) = signals.connections.tcp_accepts {
+            metrics.push(("tcp_accepts", accepts as f64, "c"));
+        }
+        if let Some(concentration) = &signals.send_concentration {
+            metrics.push(("send_top1_share", concentration.top1_share, "g"));
+            metrics.push(("send_gini", concentration.gini, "g"));
+        }
+        if let Some(loopback) = signals.loopback_send_bytes {
+            metrics.push(("loopback_send_bytes", loopback as f64, "c"));
+            metrics.push((
+                "external_send_bytes",
+                signals.external_send_bytes as f64,
+                "c",
+            ));
+        }
+        if let Some(pressure) = signals.udp_wmem_pressure {
+            metrics.push(("udp_wmem_pressure", pressure, "g"));
+        }
+        if let Some(pressure) = signals.tcp_wmem_pressure {
+            metrics.push(("tcp_wmem_pressure", pressure, "g"));
+        }
+        metrics.push(("degradation_level", signals.degradation_level as f64, "g"));
+        if let Some(pressure) = signals.net_memory_pressure {
+            metrics.push(("net_memory_pressure", pressure, "g"));
+        }
+        if let Some(events) = signals.memory_pressure_events {
+            metrics.push(("memory_pressure_events", events as f64, "c"));
+        }
+        if let Some(throttles) = signals.tsq_throttles {
+            metrics.push(("tsq_throttles_per_sec", throttles as f64 / secs, "g"));
+            metrics.push(("tsq_throttles", throttles as f64, "c"));
+        }
+        if let Some(rtos) = signals.rto_events {
+            metrics.push(("tcp_rto", rtos as f64, "c"));
+        }
+        if let Some(episodes) = signals.loss_recovery_episodes {
+            metrics.push(("tcp_loss_recovery", episodes as f64, "c"));
+        }
+        if let Some(stalls) = signals.txq_stalls {
+            metrics.push(("txq_stalls", stalls as f64, "c"));
+        }
+        if let Some(stalled_ns) = signals.txq_stalled_ns {
+            metrics.push(("txq_stalled_ms", stalled_ns as f64 / 1e6, "c"));
+        }
+
+        let mut lines: Vec<String> = metrics
+            .into_iter()
+            .map(|(name, value, kind)| {
+                format!("{}.{}:{}|{}{}", self.prefix, name, value, kind, self.tags)
+            })
+            .collect();
+        // Each number in an extension's snapshot, as a gauge named by its path
+        let mut extensions: Vec<(&String, &JsonValue)> = signals.extensions.iter().collect();
+        extensions.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, snapshot) in extensions {
+            let name = format!("{}.ext.{}", self.prefix, escape(name));
+            self.extension_lines(&name, snapshot, &mut lines);
+        }
+        self.pack(lines)
+    }
+
+    fn extension_lines(&self, name: &str, value: &JsonValue, lines: &mut Vec<String>) {
+        match value {
+            JsonValue::Number { value, .. } if value.is_finite() => {
+                lines.push(format!("{}:{}|g{}", name, value, self.tags));
+            }
+            JsonValue::Object(fields) => {
+                for (key, value) in fields {
+                    self.extension_lines(&format!("{}.{}", name, escape(key)), value, lines);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Format per-cgroup metrics, from `read_per_cgroup_rolled_up()`, into
+    /// datagram payloads. Nothing without `with_cgroup_series`
+    pub fn encode_per_cgroup(
+        &self,
+        per_cgroup: &HashMap<String, CongestionSignals>,
+    ) -> Vec<Vec<u8>> {
+        let Some(max_cgroups) = self.cgroup_series else {
+            return Vec::new();
+        };
+        let mut cgroups: Vec<(&String, &CongestionSignals)> = per_cgroup.iter().collect();
+        cgroups.sort_by(|a, b| b.1.send_bytes.cmp(&a.1.send_bytes).then(a.0.cmp(b.0)));
+
+        let mut lines = Vec::new();
+        for (path, signals) in cgroups.into_iter().take(max_cgroups) {
+            let secs = if signals.interval_ns > 0 {
+                signals.interval_ns as f64 / 1e9
+            } else {
+                self.interval.as_secs_f64().max(f64::EPSILON)
+            };
+            let path = escape(path);
+            let tags = if self.tags.is_empty() {
+                format!("|#cgroup:{}", path)
+            } else {
+                format!("{},cgroup:{}", self.tags, path)
+            };
+            let metrics = [
+                ("send_bytes_per_sec", signals.send_bytes as f64 / secs, "g"),
+                ("drops_per_sec", signals.drops as f64 / secs, "g"),
+                ("wmem_pressure", signals.avg_wmem_pressure, "g"),
+                ("send_bytes", signals.send_bytes as f64, "c"),
+                ("drops", signals.drops as f64, "c"),
+            ];
+            for (name, value, kind) in metrics {
+                lines.push(format!(
+                    "{}.cgroup.{}:{}|{}{}",
+                    self.prefix, name, value, kind, tags
+                ));
+            }
+        }
+        self.pack(lines)
+    }
+
+    /// Format a `CongestionState` as the `congestion_state` gauge, 0 for Green,
+    /// 1 for Yellow, 2 for Red, with a `reason:<label>` tag per reason
+    pub fn encode_state(&self, state: &CongestionState) -> Vec<Vec<u8>> {
+        let reasons: Vec<String> = state
+            .reasons()
+            .iter()
+            .map(|reason| format!("reason:{}", reason.label()))
+            .collect();
+        let tags = match (self.tags.is_empty(), reasons.is_empty()) {
+            (_, true) => self.tags.clone(),
+            (true, false) => format!("|#{}", reasons.join(",")),
+            (false, false) => format!("{},{}", self.tags, reasons.join(",")),
+        };
+        self.pack(vec![format!(
+            "{}.congestion_state:{}|g{}",
+            self.prefix,
+            state.level(),
+            tags
+        )])
+    }
+
+    /// Format a `HeartbeatStatus` as the `pipeline_alive` gauge, 1 or 0, and
+    /// `heartbeat_round_trip_us` once a beat came through
+    #[cfg(feature = "collector-core")]
+    pub fn encode_heartbeat(&self, status: &HeartbeatStatus) -> Vec<Vec<u8>> {
+        let mut lines = vec![format!(
+            "{}.pipeline_alive:{}|g{}",
+            self.prefix, status.pipeline_alive as u8, self.tags
+        )];
+        if let Some(round_trip) = status.last_round_trip {
+            lines.push(format!(
+                "{}.heartbeat_round_trip_us:{}|g{}",
+                self.prefix,
+                round_trip.as_micros(),
+                self.tags
+            ));
+        }
+        self.pack(lines)
+    }
+
+    /// Encode and send one interval
+    pub fn flush(&mut self, signals: &CongestionSignals, stats: &ReaderStats) {
+        let datagrams = self.encode(signals, stats);
+        self.send(datagrams);
+    }
+
+    /// Encode and send one interval's per-cgroup signals, see `with_cgroup_series`
+    pub fn flush_per_cgroup(&mut self, per_cgroup: &HashMap<String, CongestionSignals>) {
+        let datagrams = self.encode_per_cgroup(per_cgroup);
+        self.send(datagrams);
+    }
+
+    /// Encode and send a `CongestionState`, see `encode_state`
+    pub fn flush_state(&mut self, state: &CongestionState) {
+        let datagrams = self.encode_state(state);
+        self.send(datagrams);
+    }
+
+    /// Encode and send a `HeartbeatStatus`, e.g. `collector.health().heartbeat`
+    #[cfg(feature = "collector-core")]
+    pub fn flush_heartbeat(&mut self, status: &HeartbeatStatus) {
+        let datagrams = self.encode_heartbeat(status);
+        self.send(datagrams);
+    }
+
+    fn pack(&self, lines: Vec<String>) -> Vec<Vec<u8>> {
+        let mut datagrams = Vec::new();
+        let mut current = Vec::new();
+        for line in lines {
+            if !current.is_empty() && current.len() + 1 + line.len() > self.max_datagram {
+                datagrams.push(std::mem::take(&mut current));
+            }
+            if !current.is_empty() {
+                current.push(b'\n');
+            }
+            current.extend_from_slice(line.as_bytes());
+        }
+        if !current.is_empty() {
+            datagrams.push(current);
+        }
+        datagrams
+    }
+
+    fn send(&mut self, datagrams: Vec<Vec<u8>>) {
+        for datagram in datagrams {
+            if let Err(e) = self.socket.send_to(&datagram, self.addr) {
+                self.send_errors += 1;
+                let errors = self.send_errors;
+                // First failure and then every 100th, the agent may simply be down
+                if errors % 100 == 1 {
+                    log::warn!(
+                        "statsd send to {} failed ({} so far): {}",
+                        self.addr,
+                        errors,
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    /// Flush every `interval`, pulling each interval's data from `source`
+    /// (typically `|| (collector.read_and_reset(), collector.reader_stats())`)
+    #[cfg(feature = "async-runtime")]
+    pub async fn run<F>(mut self, mut source: F)
+    where
+        F: FnMut() -> (CongestionSignals, ReaderStats),
+    {
+        let mut ticker = Rt::interval(self.interval, MissedTicks::Burst);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let (signals, stats) = source();
+            self.flush(&signals, &stats);
+        }
+    }
+}
+
+// dogstatsd reserves these in names and tags
+fn escape(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if matches!(c, ',' | '|' | ':' | '#' | '@') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Reason;
+
+    fn agent() -> (UdpSocket, SocketAddr) {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        agent
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let addr = agent.local_addr().unwrap();
+        (agent, addr)
+    }
+
+    fn received(agent: &UdpSocket) -> String {
+        let mut buf = [0u8; 65536];
+        let len = agent.recv(&mut buf).unwrap();
+        String::from_utf8(buf[..len].to_vec()).unwrap()
+    }
+
+    fn one_second() -> CongestionSignals {
+        CongestionSignals::builder()
+            .interval_ns(1_000_000_000)
+            .send_bytes(3_000)
+            .drops(4)
+            .avg_wmem_pressure(0.5)
+            .softirq_cpu_fraction(0.25)
+            .build()
+    }
+
+    #[test]
+    fn a_flush_arrives_as_tagged_dogstatsd_lines() {
+        let (agent, addr) = agent();
+        let mut exporter = StatsdExporter::new(
+            addr,
+            "congestion.",
+            Duration::from_secs(1),
+            &[("site", "ams1"), ("host", "edge-3")],
+        )
+        .unwrap()
+        .with_max_datagram(65_000);
+        exporter.flush(&one_second(), &ReaderStats::default());
+
+        let datagram = received(&agent);
+        let lines: Vec<&str> = datagram.lines().collect();
+        for line in [
+            "congestion.send_bytes_per_sec:3000|g|#site:ams1,host:edge-3",
+            "congestion.drops_per_sec:4|g|#site:ams1,host:edge-3",
+            "congestion.wmem_pressure:0.5|g|#site:ams1,host:edge-3",
+            "congestion.softirq_fraction:0.25|g|#site:ams1,host:edge-3",
+            "congestion.send_bytes:3000|c|#site:ams1,host:edge-3",
+            "congestion.drops:4|c|#site:ams1,host:edge-3",
+            "congestion.lost_events:0|c|#site:ams1,host:edge-3",
+        ] {
+            assert!(lines.contains(&line), "{} missing from {:?}", line, lines);
+        }
+        assert!(lines
+            .iter()
+            .all(|line| line.ends_with("|#site:ams1,host:edge-3")));
+        assert_eq!(exporter.send_errors(), 0);
+    }
+
+    #[test]
+    fn lost_events_count_the_change_since_the_last_flush() {
+        let (agent, addr) = agent();
+        let mut exporter = StatsdExporter::new(addr, "c", Duration::from_secs(1), &[])
+            .unwrap()
+            .with_max_datagram(65_000);
+        let mut stats = ReaderStats {
+            perf_lost: 10,
+            queue_dropped: 5,
+            ..Default::default()
+        };
+        exporter.flush(&one_second(), &stats);
+        stats.perf_lost = 12;
+        exporter.flush(&one_second(), &stats);
+
+        assert!(received(&agent)
+            .lines()
+            .any(|line| line == "c.lost_events:15|c"));
+        assert!(received(&agent)
+            .lines()
+            .any(|line| line == "c.lost_events:2|c"));
+    }
+
+    #[test]
+    fn metrics_pack_into_datagrams_under_the_limit() {
+        let (agent, addr) = agent();
+        let mut exporter = StatsdExporter::new(addr, "c", Duration::from_secs(1), &[])
+            .unwrap()
+            .with_max_datagram(120);
+        let signals = one_second();
+        let expected = exporter.encode(&signals, &ReaderStats::default());
+        assert!(expected.len() > 1);
+        let lines: usize = expected
+            .iter()
+            .map(|d| d.split(|&b| b == b'\n').count())
+            .sum();
+
+        exporter.flush(&signals, &ReaderStats::default());
+        let mut received_lines = 0;
+        for _ in 0..expected.len() {
+            let datagram = received(&agent);
+            assert!(datagram.len() <= 120, "{} bytes", datagram.len());
+            assert!(!datagram.ends_with('\n'));
+            received_lines += datagram.lines().count();
+        }
+        assert_eq!(received_lines, lines);
+    }
+
+    #[test]
+    fn a_line_longer_than_the_limit_goes_out_alone() {
+        let (_, addr) = agent();
+        let exporter = StatsdExporter::new(addr, "c", Duration::from_secs(1), &[])
+            .unwrap()
+            .with_max_datagram(8);
+        let datagrams = exporter.pack(vec!["a:1|g".into(), "long.name:1|g".into(), "b:1|g".into()]);
+        assert_eq!(
+            datagrams,
+            vec![
+                b"a:1|g".to_vec(),
+                b"long.name:1|g".to_vec(),
+                b"b:1|g".to_vec()
+            ]
+        );
+    }
+
+    #[test]
+    fn send_failures_are_counted_not_returned() {
+        // Port 0 isn't a destination, every send fails
+        let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let mut exporter = StatsdExporter::new(addr, "c", Duration::from_secs(1), &[]).unwrap();
+        let datagrams = exporter
+            .encode(&one_second(), &ReaderStats::default())
+            .len() as u64;
+        exporter.flush(&one_second(), &ReaderStats::default());
+        exporter.flush(&one_second(), &ReaderStats::default());
+        assert_eq!(exporter.send_errors(), 2 * datagrams);
+    }
+
+    #[test]
+    fn state_reasons_become_tags() {
+        let (agent, addr) = agent();
+        let mut exporter =
+            StatsdExporter::new(addr, "c", Duration::from_secs(1), &[("site", "ams1")]).unwrap();
+        exporter.flush_state(&CongestionState::Green);
+        exporter.flush_state(&CongestionState::Red(vec![Reason::WmemPressure {
+            p95: 0.9,
+        }]));
+
+        assert_eq!(received(&agent), "c.congestion_state:0|g|#site:ams1");
+        assert_eq!(
+            received(&agent),
+            "c.congestion_state:2|g|#site:ams1,reason:wmem_pressure"
+        );
+    }
+
+    #[test]
+    fn reserved_characters_are_escaped_in_cgroup_tags() {
+        let (agent, addr) = agent();
+        let mut exporter = StatsdExporter::new(addr, "c", Duration::from_secs(1), &[])
+            .unwrap()
+            .with_max_datagram(65_000)
+            .with_cgroup_series(1);
+        let per_cgroup = HashMap::from([
+            ("/a|b:c".to_string(), one_second()),
+            ("/idle".to_string(), CongestionSignals::idle()),
+        ]);
+        exporter.flush_per_cgroup(&per_cgroup);
+
+        let datagram = received(&agent);
+        assert!(datagram
+            .lines()
+            .all(|line| line.starts_with("c.cgroup.") && line.ends_with("|#cgroup:/a_b_c")));
+        assert_eq!(datagram.lines().count(), 5);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn run_flushes_the_source_every_interval() {
+        let (agent, addr) = agent();
+        let exporter = StatsdExporter::new(addr, "c", Duration::from_millis(10), &[])
+            .unwrap()
+            .with_max_datagram(65_000);
+        let mut pulls = 0u64;
+        let source = move || {
+            pulls += 1;
+            let signals = CongestionSignals::builder()
+                .interval_ns(10_000_000)
+                .drops(pulls)
+                .build();
+            (signals, ReaderStats::default())
+        };
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let _ = tokio::time::timeout(Duration::from_millis(100), exporter.run(source)).await;
+        });
+
+        assert!(received(&agent).lines().any(|line| line == "c.drops:1|c"));
+        assert!(received(&agent).lines().any(|line| line == "c.drops:2|c"));
+    }
+}
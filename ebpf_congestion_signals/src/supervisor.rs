@@ -0,0 +1,391 @@
+//Every background task of the async build (per-CPU readers, the processing
+//task, the snapshot publisher, the heartbeat) runs under one supervisor. A task
+//that panics or returns an error is reported on a single broadcast channel,
+//`failures()`, and started again from its factory as its component's
+//RestartPolicy allows; the heartbeat reports a dead event path on the same
+//channel, and `verify_attachments()` other programs coming or going where ours
+//attach. The supervisor owned by the collector holds every task's handle, so
+//stopping it cancels all of them at once.
+
+use crate::runtime::{AbortHandle, Rt, Runtime, TaskHandle};
+use futures_util::FutureExt;
+use std::fmt;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+// Failures kept for a slow `failures()` receiver before it lags
+const FAILURE_CAPACITY: usize = 64;
+// Pause before a restart, so a task failing on start doesn't spin
+const RESTART_DELAY: Duration = Duration::from_millis(100);
+
+/// Which background task failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Component {
+    /// A CPU's perf buffer reader
+    Reader { cpu: u32 },
+    /// The processing task behind per-socket state and `subscribe_events()`
+    Pipeline,
+    /// The task behind `snapshots()`
+    Publisher,
+    /// The task behind `fast_snapshots()`, under the publisher's restart policy
+    FastPublisher,
+    /// `CollectorConfig::with_heartbeat`'s beats not coming through the
+    /// readers, `max_missed` in a row. Nothing is restarted for that; its task
+    /// is under the pipeline's restart policy
+    Heartbeat,
+    /// A warning, not a task: `verify_attachments()` found a different number
+    /// of other programs attached where ours are than the scan before
+    CoAttachment,
+}
+
+impl fmt::Display for Component {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Component::Reader { cpu } => write!(f, "reader on CPU {}", cpu),
+            Component::Pipeline => write!(f, "processing task"),
+            Component::Publisher => write!(f, "snapshot publisher"),
+            Component::FastPublisher => write!(f, "fast snapshot publisher"),
+            Component::Heartbeat => write!(f, "heartbeat"),
+            Component::CoAttachment => write!(f, "co-attached programs"),
+        }
+    }
+}
+
+/// One panic or error of a background task, from `CongestionCollector::failures()`
+#[derive(Debug, Clone)]
+pub struct ComponentFailure {
+    pub component: Component,
+    /// The error, or the panic message
+    pub error: String,
+    pub panicked: bool,
+    /// Started again; false once its `RestartPolicy` is used up
+    pub restarted: bool,
+}
+
+/// What happens to a component's task after it fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// Report the failure and leave it stopped
+    Never,
+    /// Restart it up to this many times per task, then leave it stopped
+    UpTo(u32),
+}
+
+impl RestartPolicy {
+    fn allows(self, restarts: u32) -> bool {
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::UpTo(max) => restarts < max,
+        }
+    }
+}
+
+/// Per component, part of `CollectorConfig`. A reader that gives up after
+/// `reader_max_retries` read errors counts as failed, and one left stopped
+/// is what `verify_attachments()` reloads for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestartPolicies {
+    pub readers: RestartPolicy,
+    pub pipeline: RestartPolicy,
+    pub publisher: RestartPolicy,
+}
+
+impl Default for RestartPolicies {
+    fn default() -> Self {
+        Self {
+            readers: RestartPolicy::UpTo(3),
+            pipeline: RestartPolicy::UpTo(3),
+            publisher: RestartPolicy::UpTo(3),
+        }
+    }
+}
+
+impl RestartPolicies {
+    pub(crate) fn for_component(&self, component: Component) -> RestartPolicy {
+        match component {
+            Component::Reader { .. } => self.readers,
+            Component::Pipeline | Component::Heartbeat | Component::CoAttachment => self.pipeline,
+            Component::Publisher | Component::FastPublisher => self.publisher,
+        }
+    }
+}
+
+pub(crate) struct Supervisor {
+    tasks: Mutex<Vec<AbortHandle>>,
+    reporter: FailureReporter,
+    policies: RestartPolicies,
+}
+
+/// Counts and sends failures, those of tasks ending and the heartbeat's. Holds
+/// no reference to the supervisor, so tasks can keep one
+#[derive(Clone)]
+pub(crate) struct FailureReporter {
+    failures: broadcast::Sender<ComponentFailure>,
+    failure_count: Arc<AtomicU64>,
+}
+
+impl FailureReporter {
+    /// Log and send a failure that isn't a task's, nothing restarts for it
+    pub(crate) fn report(&self, failure: ComponentFailure) {
+        log::error!("{}: {}", failure.component, failure.error);
+        self.send(failure);
+    }
+
+    /// `report` for what's worth knowing but isn't broken, logged as a warning
+    pub(crate) fn warn(&self, failure: ComponentFailure) {
+        log::warn!("{}: {}", failure.component, failure.error);
+        self.send(failure);
+    }
+
+    fn send(&self, failure: ComponentFailure) {
+        self.failure_count.fetch_add(1, Ordering::Relaxed);
+        // Nobody listening is fine
+        let _ = self.failures.send(failure);
+    }
+}
+
+impl Supervisor {
+    pub(crate) fn new(policies: RestartPolicies) -> Self {
+        Self {
+            tasks: Mutex::new(Vec::new()),
+            reporter: FailureReporter {
+                failures: broadcast::channel(FAILURE_CAPACITY).0,
+                failure_count: Arc::new(AtomicU64::new(0)),
+            },
+            policies,
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<ComponentFailure> {
+        self.reporter.failures.subscribe()
+    }
+
+    /// Failures since load, restarted or not
+    pub(crate) fn failure_count(&self) -> u64 {
+        self.reporter.failure_count.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn reporter(&self) -> FailureReporter {
+        self.reporter.clone()
+    }
+
+    /// Run `start()`'s future until it returns Ok, restarting it from `start`
+    /// after each failure as the component's policy allows. Needs the runtime
+    /// `Rt` is for. Aborting the handle cancels the task and its restarts
+    pub(crate) fn spawn<F, Fut>(&self, component: Component, mut start: F) -> AbortHandle
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let policy = self.policies.for_component(component);
+        let reporter = self.reporter.clone();
+
+        let mut tasks = self.tasks.lock().unwrap();
+        // Tasks that ended (stopped readers, finished restarts) until now
+        tasks.retain(|task| !task.is_finished());
+        let task = Rt::spawn(async move {
+            let mut restarts = 0;
+            loop {
+                let (error, panicked) = match AssertUnwindSafe(start()).catch_unwind().await {
+                    Ok(Ok(())) => return,
+                    Ok(Err(e)) => (format!("{:#}", e), false),
+                    Err(panic) => (panic_message(panic), true),
+                };
+                let restarted = policy.allows(restarts);
+                log::error!(
+                    "{} {}: {}{}",
+                    component,
+                    if panicked { "panicked" } else { "failed" },
+                    error,
+                    if restarted { ", restarting" } else { "" }
+                );
+                reporter.send(ComponentFailure {
+                    component,
+                    error,
+                    panicked,
+                    restarted,
+                });
+                if !restarted {
+                    return;
+                }
+                restarts += 1;
+                Rt::sleep(RESTART_DELAY).await;
+            }
+        });
+        tasks.push(task.clone());
+        task
+    }
+
+    /// Cancel every task. Called on stop and drop, when nothing is left to
+    /// restart them for
+    pub(crate) fn shutdown(&self) {
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "non-string panic payload".to_string(),
+        },
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+    use super::*;
+
+    fn paused<F: Future>(test: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap()
+            .block_on(test)
+    }
+
+    fn policies(pipeline: RestartPolicy) -> RestartPolicies {
+        RestartPolicies {
+            pipeline,
+            readers: RestartPolicy::Never,
+            ..RestartPolicies::default()
+        }
+    }
+
+    /// A component that fails its first `failures` runs, every start counted
+    /// in `starts`
+    fn flaky(
+        failures: u64,
+        panics: bool,
+        starts: &Arc<AtomicU64>,
+    ) -> impl FnMut() -> std::pin::Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>
+           + Send
+           + 'static {
+        let starts = starts.clone();
+        move || {
+            let start = starts.fetch_add(1, Ordering::Relaxed);
+            Box::pin(async move {
+                if start >= failures {
+                    return Ok(());
+                }
+                if panics {
+                    panic!("mock component panicked on run {start}");
+                }
+                anyhow::bail!("mock component failed")
+            })
+        }
+    }
+
+    async fn failures(rx: &mut broadcast::Receiver<ComponentFailure>) -> Vec<ComponentFailure> {
+        let mut failures = Vec::new();
+        while let Ok(Ok(failure)) = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await {
+            failures.push(failure);
+        }
+        failures
+    }
+
+    #[test]
+    fn failures_restart_until_the_policy_is_used_up() {
+        paused(async {
+            let supervisor = Supervisor::new(policies(RestartPolicy::UpTo(2)));
+            let mut rx = supervisor.subscribe();
+            let starts = Arc::new(AtomicU64::new(0));
+            let task = supervisor.spawn(Component::Pipeline, flaky(u64::MAX, false, &starts));
+
+            let failures = failures(&mut rx).await;
+            let restarted: Vec<bool> = failures.iter().map(|f| f.restarted).collect();
+            assert_eq!(restarted, [true, true, false]);
+            assert!(failures.iter().all(|f| f.component == Component::Pipeline
+                && f.error == "mock component failed"
+                && !f.panicked));
+            assert_eq!(starts.load(Ordering::Relaxed), 3);
+            assert_eq!(supervisor.failure_count(), 3);
+            assert!(task.is_finished());
+        });
+    }
+
+    #[test]
+    fn a_panic_is_reported_and_the_component_recovers() {
+        paused(async {
+            let supervisor = Supervisor::new(policies(RestartPolicy::UpTo(3)));
+            let mut rx = supervisor.subscribe();
+            let starts = Arc::new(AtomicU64::new(0));
+            let task = supervisor.spawn(Component::Pipeline, flaky(1, true, &starts));
+
+            let failures = failures(&mut rx).await;
+            assert_eq!(failures.len(), 1);
+            assert!(failures[0].panicked && failures[0].restarted);
+            assert_eq!(failures[0].error, "mock component panicked on run 0");
+            // Started again, and ran to completion
+            assert_eq!(starts.load(Ordering::Relaxed), 2);
+            assert!(task.is_finished());
+        });
+    }
+
+    #[test]
+    fn components_go_by_their_own_policy() {
+        paused(async {
+            let supervisor = Supervisor::new(policies(RestartPolicy::UpTo(3)));
+            let mut rx = supervisor.subscribe();
+            let starts = Arc::new(AtomicU64::new(0));
+            supervisor.spawn(Component::Reader { cpu: 2 }, flaky(1, false, &starts));
+
+            let failures = failures(&mut rx).await;
+            assert_eq!(failures.len(), 1);
+            assert_eq!(failures[0].component, Component::Reader { cpu: 2 });
+            assert!(!failures[0].restarted);
+            assert_eq!(starts.load(Ordering::Relaxed), 1);
+        });
+    }
+
+    #[test]
+    fn shutdown_cancels_every_task() {
+        paused(async {
+            let supervisor = Supervisor::new(RestartPolicies::default());
+            let tasks: Vec<AbortHandle> = (0..4)
+                .map(|cpu| {
+                    supervisor.spawn(Component::Reader { cpu }, || {
+                        futures_util::future::pending::<anyhow::Result<()>>()
+                    })
+                })
+                .collect();
+            tokio::task::yield_now().await;
+            assert!(tasks.iter().all(|task| !task.is_finished()));
+
+            supervisor.shutdown();
+            tokio::task::yield_now().await;
+            assert!(tasks.iter().all(TaskHandle::is_finished));
+            assert_eq!(supervisor.failure_count(), 0);
+        });
+    }
+
+    #[test]
+    fn reported_failures_count_without_a_task() {
+        let supervisor = Supervisor::new(RestartPolicies::default());
+        let mut rx = supervisor.subscribe();
+        supervisor.reporter().report(ComponentFailure {
+            component: Component::Heartbeat,
+            error: "3 beats missed".to_string(),
+            panicked: false,
+            restarted: false,
+        });
+        assert_eq!(supervisor.failure_count(), 1);
+        assert_eq!(rx.try_recv().unwrap().component, Component::Heartbeat);
+    }
+}
@@ -0,0 +1,551 @@
+//Running under systemd (feature `systemd`): what a service embedding the
+//collector needs to be a `Type=notify` unit with a watchdog, without libsystemd.
+//The notify protocol is one datagram of `KEY=value` lines to $NOTIFY_SOCKET;
+//socket activation hands over listening fds from 3 up, counted in $LISTEN_FDS.
+//
+//ServiceLifecycle is the readiness and watchdog logic on its own, fed what the
+//service saw and returning what to tell systemd, so it can be driven against a
+//mock socket. READY=1 only goes out once the probes are attached and the
+//self-test passed. WATCHDOG=1 only while snapshots keep coming: a collector
+//whose publisher wedged stops pinging, and systemd restarts it after
+//WatchdogSec. There's no daemon in this crate; examples/systemd_service.rs is
+//one, with unit files next to it.
+
+use std::env;
+use std::fmt;
+use std::io;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixListener};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// sd_listen_fds(3): the first passed fd
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Exit status of a service whose probes didn't attach, EX_UNAVAILABLE
+pub const EXIT_PROBES_UNAVAILABLE: i32 = 69;
+/// Exit status of a self-test that failed, EX_SOFTWARE
+pub const EXIT_SELF_TEST_FAILED: i32 = 70;
+/// Exit status of a service refused the privileges to load probes, EX_NOPERM
+pub const EXIT_NO_PERMISSION: i32 = 77;
+
+/// One line of a notify message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServiceNotification {
+    Ready,
+    Watchdog,
+    Stopping,
+    /// Free text `systemctl status` shows
+    Status(String),
+    /// An errno to go with a failure status
+    Errno(i32),
+}
+
+impl fmt::Display for ServiceNotification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ready => write!(f, "READY=1"),
+            Self::Watchdog => write!(f, "WATCHDOG=1"),
+            Self::Stopping => write!(f, "STOPPING=1"),
+            // A newline would start another assignment
+            Self::Status(status) => write!(f, "STATUS={}", status.replace('\n', " ")),
+            Self::Errno(errno) => write!(f, "ERRNO={}", errno),
+        }
+    }
+}
+
+/// Sends to systemd's notify socket, see `sd_notify(3)`
+#[derive(Debug)]
+pub struct SystemdNotifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+}
+
+impl SystemdNotifier {
+    /// The socket in $NOTIFY_SOCKET, a path or an `@` abstract name. None when
+    /// it's unset: not started by systemd, or not as `Type=notify`
+    pub fn from_env() -> io::Result<Option<Self>> {
+        let Some(socket) = env::var_os("NOTIFY_SOCKET") else {
+            return Ok(None);
+        };
+        let socket = socket.to_string_lossy();
+        let addr = match socket.strip_prefix('@') {
+            Some(name) => abstract_addr(name)?,
+            None => SocketAddr::from_pathname(&*socket)?,
+        };
+        Self::to_addr(addr).map(Some)
+    }
+
+    /// Sends to the datagram socket bound at `path`, for a notify socket of
+    /// your own
+    pub fn connect(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::to_addr(SocketAddr::from_pathname(path)?)
+    }
+
+    fn to_addr(addr: SocketAddr) -> io::Result<Self> {
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            addr,
+        })
+    }
+
+    /// All of `notifications` in one datagram; nothing for none
+    pub fn notify(&self, notifications: &[ServiceNotification]) -> io::Result<()> {
+        if notifications.is_empty() {
+            return Ok(());
+        }
+        let message: Vec<String> = notifications.iter().map(ToString::to_string).collect();
+        self.socket
+            .send_to_addr(message.join("\n").as_bytes(), &self.addr)
+            .map(|_| ())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_addr(name: &str) -> io::Result<SocketAddr> {
+    use std::os::linux::net::SocketAddrExt;
+    SocketAddr::from_abstract_name(name)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn abstract_addr(_name: &str) -> io::Result<SocketAddr> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "abstract notify sockets are Linux-only",
+    ))
+}
+
+/// The fds socket activation passed this process, named by
+/// $LISTEN_FDNAMES (`FileDescriptorName=`, "unknown" without). Empty when
+/// not socket-activated. Clears the variables, so child processes don't take
+/// them as theirs, and marks the fds close-on-exec.
+///
+/// Takes ownership of the fds: call it once, before anything else opens one
+pub fn listen_fds() -> Vec<(String, OwnedFd)> {
+    let pid_matches = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<RawFd>().ok())
+        .filter(|_| pid_matches)
+        .unwrap_or(0);
+    let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+    // Before any thread that could read the environment, as at startup;
+    // sd_listen_fds unsets them the same way
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(var);
+    }
+    let mut names = names.split(':');
+    (LISTEN_FDS_START..LISTEN_FDS_START + count.max(0))
+        .map(|fd| {
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+            let name = names.next().filter(|name| !name.is_empty());
+            // SAFETY: systemd passed these to this process, and nothing else
+            // owns them once the variables are gone
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            (name.unwrap_or("unknown").to_string(), fd)
+        })
+        .collect()
+}
+
+/// The listening Unix socket socket activation passed as `name` (the first
+/// one, with None), taken out of `passed` from `listen_fds()`, or one bound at
+/// `fallback` when there's none. The other fds stay in `passed` for the
+/// caller. A stale socket file at `fallback` is replaced
+pub fn control_listener(
+    passed: &mut Vec<(String, OwnedFd)>,
+    name: Option<&str>,
+    fallback: &Path,
+) -> io::Result<UnixListener> {
+    let matched = passed
+        .iter()
+        .position(|(fd_name, _)| name.is_none_or(|name| name == fd_name));
+    if let Some(index) = matched {
+        return Ok(UnixListener::from(passed.remove(index).1));
+    }
+    match std::fs::remove_file(fallback) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    UnixListener::bind(fallback)
+}
+
+/// `WatchdogSec=` of the unit, from $WATCHDOG_USEC when $WATCHDOG_PID is
+/// this process or unset. None without a watchdog
+pub fn watchdog_interval() -> Option<Duration> {
+    let for_us = env::var("WATCHDOG_PID").map_or(true, |pid| {
+        pid.parse::<u32>()
+            .is_ok_and(|pid| pid == std::process::id())
+    });
+    env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| usec.parse::<u64>().ok())
+        .filter(|&usec| usec > 0 && for_us)
+        .map(Duration::from_micros)
+}
+
+/// Where a service is in its life, see [`ServiceLifecycle`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServiceState {
+    /// Loading the eBPF object and attaching probes
+    Starting,
+    /// Probes attached, the self-test running
+    Attached,
+    /// READY=1 sent; watchdog pings while snapshots are fresh
+    Ready,
+    Stopping,
+    /// Probes didn't attach or the self-test failed; exit with `exit_code`
+    Failed {
+        exit_code: i32,
+    },
+}
+
+/// The readiness and watchdog state machine, see the module docs. Each method
+/// takes what the service just saw and returns what to send, for
+/// [`SystemdNotifier::notify`]
+#[derive(Debug)]
+pub struct ServiceLifecycle {
+    state: ServiceState,
+    watchdog: Option<Duration>,
+    last_ping: Option<Instant>,
+    // The latest tick's snapshots were fresh, so a change gets a STATUS
+    fresh: bool,
+}
+
+impl ServiceLifecycle {
+    /// `watchdog` is `WatchdogSec=`, see [`watchdog_interval`]
+    pub fn new(watchdog: Option<Duration>) -> Self {
+        Self {
+            state: ServiceState::Starting,
+            watchdog,
+            last_ping: None,
+            fresh: true,
+        }
+    }
+
+    pub fn state(&self) -> &ServiceState {
+        &self.state
+    }
+
+    /// 0 unless the service failed, see [`ServiceState::Failed`]
+    pub fn exit_code(&self) -> i32 {
+        match self.state {
+            ServiceState::Failed { exit_code } => exit_code,
+            _ => 0,
+        }
+    }
+
+    /// The collector loaded and its probes attached
+    pub fn probes_attached(&mut self) -> Vec<ServiceNotification> {
+        if self.state != ServiceState::Starting {
+            return Vec::new();
+        }
+        self.state = ServiceState::Attached;
+        vec![ServiceNotification::Status(
+            "probes attached, self-testing".to_string(),
+        )]
+    }
+
+    /// Loading the collector failed. A permission error (no CAP_BPF, say)
+    /// exits with EXIT_NO_PERMISSION, anything else EXIT_PROBES_UNAVAILABLE
+    pub fn attach_failed(
+        &mut self,
+        error: &(dyn std::error::Error + 'static),
+    ) -> Vec<ServiceNotification> {
+        let io_error = std::iter::successors(Some(error), |e| e.source())
+            .find_map(|e| e.downcast_ref::<io::Error>());
+        let denied = io_error.is_some_and(|e| e.kind() == io::ErrorKind::PermissionDenied);
+        let exit_code = if denied {
+            EXIT_NO_PERMISSION
+        } else {
+            EXIT_PROBES_UNAVAILABLE
+        };
+        self.state = ServiceState::Failed { exit_code };
+        let mut notifications = vec![ServiceNotification::Status(format!(
+            "probes failed to attach: {}",
+            error
+        ))];
+        notifications.extend(
+            io_error
+                .and_then(io::Error::raw_os_error)
+                .map(ServiceNotification::Errno),
+        );
+        notifications
+    }
+
+    /// The self-test's result, Err with why it failed. READY=1 when it passed
+    /// with the probes attached
+    pub fn self_test(
+        &mut self,
+        result: Result<(), String>,
+        now: Instant,
+    ) -> Vec<ServiceNotification> {
+        if self.state != ServiceState::Attached {
+            return Vec::new();
+        }
+        match result {
+            Ok(()) => {
+                self.state = ServiceState::Ready;
+                self.last_ping = Some(now);
+                vec![
+                    ServiceNotification::Ready,
+                    ServiceNotification::Status("collecting".to_string()),
+                ]
+            }
+            Err(why) => {
+                self.state = ServiceState::Failed {
+                    exit_code: EXIT_SELF_TEST_FAILED,
+                };
+                vec![ServiceNotification::Status(format!(
+                    "self-test failed: {}",
+                    why
+                ))]
+            }
+        }
+    }
+
+    /// Call at least twice per watchdog interval with the age of the latest
+    /// snapshot (None for none yet). Pings every half interval while that's
+    /// under the interval; a stale publisher gets a STATUS instead and no
+    /// ping, until it recovers
+    pub fn tick(
+        &mut self,
+        snapshot_age: Option<Duration>,
+        now: Instant,
+    ) -> Vec<ServiceNotification> {
+        let (ServiceState::Ready, Some(watchdog)) = (&self.state, self.watchdog) else {
+            return Vec::new();
+        };
+        let fresh = snapshot_age.is_some_and(|age| age < watchdog);
+        let mut notifications = Vec::new();
+        if fresh != self.fresh {
+            self.fresh = fresh;
+            notifications.push(ServiceNotification::Status(if fresh {
+                "collecting".to_string()
+            } else {
+                match snapshot_age {
+                    Some(age) => format!("no fresh snapshot for {:.1}s", age.as_secs_f64()),
+                    None => "no snapshot published".to_string(),
+                }
+            }));
+        }
+        let due = self
+            .last_ping
+            .is_none_or(|last| now.saturating_duration_since(last) >= watchdog / 2);
+        if fresh && due {
+            self.last_ping = Some(now);
+            notifications.push(ServiceNotification::Watchdog);
+        }
+        notifications
+    }
+
+    /// SIGTERM or another reason to stop: STOPPING=1, then detach the probes
+    pub fn stopping(&mut self) -> Vec<ServiceNotification> {
+        if matches!(
+            self.state,
+            ServiceState::Stopping | ServiceState::Failed { .. }
+        ) {
+            return Vec::new();
+        }
+        self.state = ServiceState::Stopping;
+        vec![
+            ServiceNotification::Stopping,
+            ServiceNotification::Status("stopping, detaching probes".to_string()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends what a step wanted sent to a mock NOTIFY_SOCKET and reads it back
+    struct MockSocket {
+        path: std::path::PathBuf,
+        mock: UnixDatagram,
+        notifier: SystemdNotifier,
+    }
+
+    impl MockSocket {
+        fn new(test: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "congestion-signals-notify-{}-{}",
+                test,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_file(&path);
+            let mock = UnixDatagram::bind(&path).unwrap();
+            mock.set_nonblocking(true).unwrap();
+            let notifier = SystemdNotifier::connect(&path).unwrap();
+            Self {
+                path,
+                mock,
+                notifier,
+            }
+        }
+
+        fn step(&self, notifications: Vec<ServiceNotification>) -> Vec<String> {
+            self.notifier.notify(&notifications).unwrap();
+            let mut received = Vec::new();
+            let mut buf = [0u8; 4096];
+            while let Ok(n) = self.mock.recv(&mut buf) {
+                let datagram = String::from_utf8_lossy(&buf[..n]);
+                received.extend(datagram.lines().map(str::to_string));
+            }
+            received
+        }
+    }
+
+    impl Drop for MockSocket {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    fn has(lines: &[String], what: &str) -> bool {
+        lines.iter().any(|line| line == what)
+    }
+
+    fn has_status(lines: &[String]) -> bool {
+        lines.iter().any(|line| line.starts_with("STATUS="))
+    }
+
+    const WATCHDOG: Duration = Duration::from_secs(10);
+    const FRESH: Option<Duration> = Some(Duration::from_secs(1));
+
+    #[test]
+    fn ready_only_after_the_probes_attach_and_test_clean() {
+        let mock = MockSocket::new("ready");
+        let start = Instant::now();
+        let mut lifecycle = ServiceLifecycle::new(Some(WATCHDOG));
+        assert!(mock.step(lifecycle.tick(FRESH, start)).is_empty());
+        let attached = mock.step(lifecycle.probes_attached());
+        assert!(!has(&attached, "READY=1"));
+        assert!(has_status(&attached));
+        let ready = mock.step(lifecycle.self_test(Ok(()), start + Duration::from_secs(1)));
+        assert!(has(&ready, "READY=1"), "{:?}", ready);
+        assert!(has(&mock.step(lifecycle.stopping()), "STOPPING=1"));
+        assert_eq!(lifecycle.exit_code(), 0);
+    }
+
+    #[test]
+    fn the_watchdog_pings_only_while_snapshots_are_fresh() {
+        let mock = MockSocket::new("watchdog");
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut lifecycle = ServiceLifecycle::new(Some(WATCHDOG));
+        mock.step(lifecycle.probes_attached());
+        mock.step(lifecycle.self_test(Ok(()), at(1)));
+
+        // Half the interval after READY=1 is the first ping, then every 5 s
+        let pings: Vec<u64> = (2..=20)
+            .filter(|&secs| has(&mock.step(lifecycle.tick(FRESH, at(secs))), "WATCHDOG=1"))
+            .collect();
+        assert_eq!(pings, [6, 11, 16]);
+
+        let stale = mock.step(lifecycle.tick(Some(Duration::from_secs(12)), at(21)));
+        assert!(!has(&stale, "WATCHDOG=1"));
+        assert!(
+            has(&stale, "STATUS=no fresh snapshot for 12.0s"),
+            "{:?}",
+            stale
+        );
+        for secs in 22..=40 {
+            let lines = mock.step(lifecycle.tick(Some(Duration::from_secs(secs - 9)), at(secs)));
+            assert!(
+                !has(&lines, "WATCHDOG=1"),
+                "pinged at {}s while stale",
+                secs
+            );
+        }
+        let recovered = mock.step(lifecycle.tick(FRESH, at(41)));
+        assert!(has(&recovered, "WATCHDOG=1"));
+        assert!(has(&recovered, "STATUS=collecting"));
+    }
+
+    #[test]
+    fn a_denied_attach_exits_noperm_with_its_errno() {
+        let mock = MockSocket::new("denied");
+        let mut lifecycle = ServiceLifecycle::new(Some(WATCHDOG));
+        let error = anyhow::Error::new(io::Error::from_raw_os_error(libc::EPERM))
+            .context("loading the eBPF object");
+        let lines = mock.step(lifecycle.attach_failed(error.as_ref()));
+        assert_eq!(lifecycle.exit_code(), EXIT_NO_PERMISSION);
+        assert!(has(&lines, "ERRNO=1"));
+        assert!(has_status(&lines));
+        assert!(!has(&lines, "READY=1"));
+    }
+
+    #[test]
+    fn a_failed_self_test_never_gets_ready_or_pings() {
+        let mock = MockSocket::new("self-test");
+        let start = Instant::now();
+        let mut lifecycle = ServiceLifecycle::new(Some(WATCHDOG));
+        let mut lines = mock.step(lifecycle.probes_attached());
+        let why = "calibration mismatch";
+        lines.extend(
+            mock.step(lifecycle.self_test(Err(why.to_string()), start + Duration::from_secs(1))),
+        );
+        lines.extend(mock.step(lifecycle.tick(FRESH, start + Duration::from_secs(10))));
+        assert_eq!(lifecycle.exit_code(), EXIT_SELF_TEST_FAILED);
+        assert!(!has(&lines, "READY=1"));
+        assert!(!has(&lines, "WATCHDOG=1"));
+        assert!(has(&lines, &format!("STATUS=self-test failed: {}", why)));
+    }
+
+    fn listening(test: &str) -> (std::path::PathBuf, OwnedFd) {
+        let path = std::env::temp_dir().join(format!(
+            "congestion-signals-listen-{}-{}",
+            test,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        (path, OwnedFd::from(listener))
+    }
+
+    #[test]
+    fn the_control_listener_leaves_the_other_passed_fds() {
+        let (control_path, control) = listening("control");
+        let (metrics_path, metrics) = listening("metrics");
+        let mut passed = vec![
+            ("metrics".to_string(), metrics),
+            ("control".to_string(), control),
+        ];
+        let fallback = std::env::temp_dir().join("congestion-signals-listen-unused");
+        let listener = control_listener(&mut passed, Some("control"), &fallback).unwrap();
+        assert_eq!(
+            listener.local_addr().unwrap().as_pathname(),
+            Some(control_path.as_path())
+        );
+        let names: Vec<&str> = passed.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["metrics"]);
+        assert!(!fallback.exists());
+        for path in [control_path, metrics_path] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn without_a_match_the_control_listener_binds_the_fallback() {
+        let (metrics_path, metrics) = listening("unmatched");
+        let mut passed = vec![("metrics".to_string(), metrics)];
+        let fallback = std::env::temp_dir().join(format!(
+            "congestion-signals-listen-fallback-{}",
+            std::process::id()
+        ));
+        // A stale file from an earlier run is replaced
+        std::fs::write(&fallback, b"").unwrap();
+        let listener = control_listener(&mut passed, Some("control"), &fallback).unwrap();
+        assert_eq!(
+            listener.local_addr().unwrap().as_pathname(),
+            Some(fallback.as_path())
+        );
+        assert_eq!(passed.len(), 1);
+        for path in [fallback, metrics_path] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
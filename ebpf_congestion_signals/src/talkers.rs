@@ -0,0 +1,232 @@
+//Which sockets the latest interval's pressure came from, the first question of
+//an incident. Every interval read closes each socket's counts in the per-socket
+//table into `SocketSignals::last_interval`; the ranking goes over those with a
+//min-heap of the N best so far, O(sockets × log N) instead of a sort of the
+//whole table, totalling the criterion over every socket on the way for shares.
+//
+//Peer addresses come from getpeername on registered sockets, the only ones
+//whose fd the collector holds, and only when they're connected.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::net::SocketAddr;
+
+/// What `CongestionCollector::top_talkers()` ranks by, see
+/// `CollectorConfig::talker_ranking`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TalkerRanking {
+    /// `IntervalActivity::send_bytes`
+    #[default]
+    SendBytes,
+    /// `IntervalActivity::wmem_pressure`
+    WmemPressure,
+    /// `IntervalActivity::stalls`
+    Stalls,
+    /// `IntervalActivity::retransmits`
+    Retransmits,
+}
+
+/// One socket's part of an interval, see `SocketSignals::last_interval`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct IntervalActivity {
+    /// Send bytes, scaled up from the samples like `EstimatedTotals`
+    pub send_bytes: u64,
+    /// Highest sndbuf occupancy (0.0-1.0) sampled, None without a sample
+    pub wmem_pressure: Option<f64>,
+    /// Retransmission timeouts: the connection sat until the timer fired
+    pub stalls: u64,
+    /// Fast recovery entries, losses repaired without waiting for a timeout
+    pub retransmits: u64,
+}
+
+impl IntervalActivity {
+    fn metric(&self, ranking: TalkerRanking) -> f64 {
+        match ranking {
+            TalkerRanking::SendBytes => self.send_bytes as f64,
+            TalkerRanking::WmemPressure => self.wmem_pressure.unwrap_or(0.0),
+            TalkerRanking::Stalls => self.stalls as f64,
+            TalkerRanking::Retransmits => self.retransmits as f64,
+        }
+    }
+}
+
+/// One of the heaviest sockets of an interval, see [`rank_talkers`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TopTalker {
+    /// The socket cookie, as from `socket_cookie()`
+    pub socket_id: u64,
+    /// The peer of a registered, connected socket
+    pub peer: Option<SocketAddr>,
+    pub activity: IntervalActivity,
+    /// Of the ranked metric's total over every socket. None when ranking by
+    /// `WmemPressure`, a level rather than something sockets add up to
+    pub share: Option<f64>,
+}
+
+// Heap entry: the higher metric is greater, the lower id wins a tie
+struct Ranked {
+    metric: f64,
+    socket_id: u64,
+    activity: IntervalActivity,
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.metric
+            .total_cmp(&other.metric)
+            .then(other.socket_id.cmp(&self.socket_id))
+    }
+}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked {}
+
+/// The `n` sockets highest on `ranking`, highest first, the lower socket id
+/// first among equals. Sockets at zero are left out; `peer` is left None
+pub fn rank_talkers(
+    per_socket: impl IntoIterator<Item = (u64, IntervalActivity)>,
+    ranking: TalkerRanking,
+    n: usize,
+) -> Vec<TopTalker> {
+    let mut heap: BinaryHeap<Reverse<Ranked>> = BinaryHeap::with_capacity(n + 1);
+    let mut total = 0.0;
+    for (socket_id, activity) in per_socket {
+        let metric = activity.metric(ranking);
+        if metric <= 0.0 {
+            continue;
+        }
+        total += metric;
+        if n == 0 {
+            continue;
+        }
+        let ranked = Ranked {
+            metric,
+            socket_id,
+            activity,
+        };
+        if heap.len() < n {
+            heap.push(Reverse(ranked));
+        } else if heap.peek().is_some_and(|Reverse(lowest)| ranked > *lowest) {
+            heap.pop();
+            heap.push(Reverse(ranked));
+        }
+    }
+    let additive = ranking != TalkerRanking::WmemPressure;
+    // Ascending on Reverse is highest first
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(ranked)| TopTalker {
+            socket_id: ranked.socket_id,
+            peer: None,
+            activity: ranked.activity,
+            share: additive.then(|| ranked.metric / total),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    fn ids(talkers: &[TopTalker]) -> Vec<u64> {
+        talkers.iter().map(|talker| talker.socket_id).collect()
+    }
+
+    #[test]
+    fn the_heaviest_come_first_with_their_share() {
+        // 1..=200 KB: the top one's share is 200 of 20100
+        let table: Vec<_> = (1..=200)
+            .map(|id| {
+                let activity = IntervalActivity {
+                    send_bytes: id * 1000,
+                    ..Default::default()
+                };
+                (id, activity)
+            })
+            .collect();
+        let by_send = rank_talkers(table, TalkerRanking::SendBytes, 3);
+        assert_eq!(ids(&by_send), [200, 199, 198]);
+        assert!(close(by_send[0].share.unwrap(), 200.0 / 20100.0));
+    }
+
+    #[test]
+    fn equal_metrics_go_by_socket_id() {
+        // 500 and 501 tie; 503 never stalled
+        let stalling = |stalls| IntervalActivity {
+            stalls,
+            ..Default::default()
+        };
+        let stalls = [
+            (501, stalling(7)),
+            (503, stalling(0)),
+            (500, stalling(7)),
+            (502, stalling(9)),
+        ];
+        let by_stalls = rank_talkers(stalls, TalkerRanking::Stalls, 5);
+        assert_eq!(ids(&by_stalls), [502, 500, 501]);
+        let shares: Option<Vec<f64>> = by_stalls.iter().map(|talker| talker.share).collect();
+        assert!(close(shares.unwrap().iter().sum(), 1.0));
+        assert!(rank_talkers(stalls, TalkerRanking::Stalls, 0).is_empty());
+    }
+
+    #[test]
+    fn wmem_pressure_has_no_share() {
+        let pressured = |wmem_pressure| IntervalActivity {
+            wmem_pressure,
+            ..Default::default()
+        };
+        let by_wmem = rank_talkers(
+            [
+                (1, pressured(Some(0.4))),
+                (2, pressured(None)),
+                (3, pressured(Some(0.9))),
+            ],
+            TalkerRanking::WmemPressure,
+            5,
+        );
+        assert_eq!(ids(&by_wmem), [3, 1]);
+        assert!(by_wmem.iter().all(|talker| talker.share.is_none()));
+    }
+
+    #[test]
+    fn the_bounded_heap_agrees_with_a_sort() {
+        // Ten of 10k pseudo-random retransmit counts, many equal
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let table: Vec<(u64, IntervalActivity)> = (0..10_000)
+            .map(|id| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                let activity = IntervalActivity {
+                    retransmits: (state >> 33) % 500,
+                    ..Default::default()
+                };
+                (id, activity)
+            })
+            .collect();
+        let mut sorted: Vec<(u64, u64)> = table
+            .iter()
+            .filter(|(_, activity)| activity.retransmits > 0)
+            .map(|&(id, activity)| (id, activity.retransmits))
+            .collect();
+        sorted.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let sorted: Vec<u64> = sorted.iter().take(10).map(|&(id, _)| id).collect();
+        assert_eq!(
+            ids(&rank_talkers(table, TalkerRanking::Retransmits, 10)),
+            sorted
+        );
+    }
+}
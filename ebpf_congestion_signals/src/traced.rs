@@ -0,0 +1,187 @@
+//Full-rate events for a few sockets, for when sampling hides what one
+//connection does. `CongestionCollector::trace_socket` puts the socket's cookie
+//in TRACED_SOCKETS with the kernel time its trace ends; the send and occupancy
+//probes look every socket up there before their sampling check, and send the
+//traced ones' events with SAMPLER_TRACE whether a sampler took them or not.
+//The kernel stops at the end time on its own; the entry is removed by the
+//next interval read after it. Events only the trace admitted go to the
+//triggered capture and subscribers and are left out of every signal, so the
+//totals read the same whether a socket is traced or not.
+//
+//The limit is what keeps it cheap: a traced socket's every send goes through
+//the perf buffer and the processing side, at its full send rate.
+
+use crate::MAX_TRACED_SOCKETS;
+use aya::maps::{HashMap as BpfHashMap, MapData};
+use aya::Ebpf;
+use std::collections::HashMap;
+
+/// A socket traced with `CongestionCollector::trace_socket`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketTrace {
+    pub socket_id: u64,
+    /// Kernel monotonic time the trace was started, and when it ends
+    pub started_ns: u64,
+    pub until_ns: u64,
+    /// Its events are written to a capture, with
+    /// `CollectorConfig::with_triggered_capture`
+    pub recorded: bool,
+}
+
+pub(crate) struct SocketTracer {
+    // None if the map was unusable
+    map: Option<BpfHashMap<MapData, u64, u64>>,
+    traces: HashMap<u64, SocketTrace>,
+    limit: usize,
+}
+
+impl SocketTracer {
+    pub(crate) fn take(ebpf: &mut Ebpf, limit: usize) -> Self {
+        let map = match ebpf.take_map("TRACED_SOCKETS").map(BpfHashMap::try_from) {
+            Some(Ok(map)) => Some(map),
+            _ => {
+                log::warn!("TRACED_SOCKETS map unusable, socket tracing disabled");
+                None
+            }
+        };
+        Self {
+            map,
+            traces: HashMap::new(),
+            limit: limit.min(MAX_TRACED_SOCKETS as usize),
+        }
+    }
+
+    /// Without a map, as if it were unusable
+    #[cfg(test)]
+    pub(crate) fn detached(limit: usize) -> Self {
+        Self {
+            map: None,
+            traces: HashMap::new(),
+            limit: limit.min(MAX_TRACED_SOCKETS as usize),
+        }
+    }
+
+    /// `next`, on a reloaded object's map, with the traces carried over, to
+    /// put in place once the rest of the reload has worked. Leaves this one as
+    /// it was
+    pub(crate) fn carried_over(&self, mut next: SocketTracer) -> anyhow::Result<Self> {
+        for trace in self.traces.values() {
+            next.insert(trace.socket_id, trace.until_ns)?;
+        }
+        next.traces = self.traces.clone();
+        Ok(next)
+    }
+
+    /// Trace `trace.socket_id` until `trace.until_ns`. A socket already
+    /// traced keeps its start and gets the new end
+    pub(crate) fn start(&mut self, mut trace: SocketTrace) -> anyhow::Result<SocketTrace> {
+        if let Some(current) = self.traces.get(&trace.socket_id) {
+            trace.started_ns = current.started_ns;
+        } else if self.traces.len() >= self.limit {
+            anyhow::bail!("{} sockets traced already", self.traces.len());
+        }
+        self.insert(trace.socket_id, trace.until_ns)?;
+        self.traces.insert(trace.socket_id, trace);
+        Ok(trace)
+    }
+
+    fn insert(&mut self, socket_id: u64, until_ns: u64) -> anyhow::Result<()> {
+        let map = self
+            .map
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("TRACED_SOCKETS map unavailable"))?;
+        map.insert(socket_id, until_ns, 0)?;
+        Ok(())
+    }
+
+    /// End `socket_id`'s trace now, None if it wasn't traced
+    pub(crate) fn stop(&mut self, socket_id: u64) -> Option<SocketTrace> {
+        let trace = self.traces.remove(&socket_id)?;
+        if let Some(map) = self.map.as_mut() {
+            let _ = map.remove(&socket_id);
+        }
+        Some(trace)
+    }
+
+    /// Remove the traces `now_ns` is past, once per interval read. The kernel
+    /// stopped sending them already
+    pub(crate) fn expire(&mut self, now_ns: u64) {
+        let ended: Vec<u64> = self
+            .traces
+            .values()
+            .filter(|trace| now_ns > trace.until_ns)
+            .map(|trace| trace.socket_id)
+            .collect();
+        for socket_id in ended {
+            self.stop(socket_id);
+        }
+    }
+
+    /// Every trace, e.g. when collection stops
+    pub(crate) fn stop_all(&mut self) {
+        let traced: Vec<u64> = self.traces.keys().copied().collect();
+        for socket_id in traced {
+            self.stop(socket_id);
+        }
+    }
+
+    /// The traces running, by socket id
+    pub(crate) fn traces(&self) -> Vec<SocketTrace> {
+        let mut traces: Vec<SocketTrace> = self.traces.values().copied().collect();
+        traces.sort_by_key(|trace| trace.socket_id);
+        traces
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::traced_run;
+    use crate::{replay_per_socket, replay_send_packets, traced_only, CollectorConfig};
+    use crate::{CongestionEvent, SampleScale};
+
+    const TRACED: u64 = 41;
+    const OTHER: u64 = 42;
+
+    /// Replayed with and without the events only the trace admitted, the
+    /// per-socket table and packet estimates read the same
+    #[test]
+    fn a_trace_leaves_the_signals_as_they_were() {
+        let events = traced_run(TRACED, OTHER);
+        let sampled: Vec<CongestionEvent> =
+            events.iter().filter(|e| !traced_only(e)).copied().collect();
+        assert!(sampled.len() < events.len());
+
+        let config = CollectorConfig::default();
+        let all = replay_per_socket(&events, &config);
+        let without = replay_per_socket(&sampled, &config);
+        for id in [TRACED, OTHER] {
+            let (a, b) = (&all[&id], &without[&id]);
+            assert_eq!(a.send_bytes, b.send_bytes);
+            assert_eq!(a.last_seen_ns, b.last_seen_ns);
+            assert_eq!(a.wmem_pressure, b.wmem_pressure);
+        }
+        assert_eq!(all[&TRACED].send_bytes, 30 * 1200);
+        assert_eq!(all[&TRACED].wmem_pressure, Some(0.1));
+
+        let scale = SampleScale::from_coverage(Some(6_000), 60);
+        let packets = replay_send_packets(&events, &scale, None);
+        assert_eq!(packets, replay_send_packets(&sampled, &scale, None));
+        assert_eq!(packets.0.udp, 6_000);
+    }
+
+    #[test]
+    fn a_failed_carry_over_leaves_the_traces_where_they_were() {
+        let mut tracer = SocketTracer::detached(4);
+        let trace = SocketTrace {
+            socket_id: TRACED,
+            started_ns: 0,
+            until_ns: 1_000,
+            recorded: false,
+        };
+        tracer.traces.insert(TRACED, trace);
+        // Its map can't take the trace
+        assert!(tracer.carried_over(SocketTracer::detached(4)).is_err());
+        assert_eq!(tracer.traces(), [trace]);
+    }
+}
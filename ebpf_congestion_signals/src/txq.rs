@@ -0,0 +1,224 @@
+//TX queue stalls from netif_tx_stop_queue / netif_tx_wake_queue and busy
+//returns out of net_dev_xmit: the NIC (or its driver) not keeping up, as
+//opposed to the qdisc dropping. Counted per CPU and (ifindex, netns) in
+//TXQ_STALLS, since indexes repeat across namespaces, never reset, so every read diffs against the previous one like egress does.
+//
+//Stopped time is only added at the wake, so a queue stopped across a read
+//counts in the interval it's woken in.
+//
+//Fast snapshots read the same map on a cursor of their own, summed over every
+//interface, so neither read moves the other's starting point.
+
+use crate::netns::NetnsResolver;
+use crate::{InterfaceTxq, TxqKey, TxqStall};
+use aya::maps::{MapData, PerCpuHashMap};
+use aya::Ebpf;
+use std::collections::HashMap;
+
+pub(crate) struct TxqStalls {
+    // None if the map was unusable
+    counters: Option<PerCpuHashMap<MapData, TxqKey, TxqStall>>,
+    // (ifindex, netns) -> totals at the previous read
+    last_totals: HashMap<TxqKey, TxqStall>,
+    // Summed over every interface at the previous read_fast()
+    last_fast: TxqStall,
+}
+
+impl TxqStalls {
+    pub(crate) fn take(ebpf: &mut Ebpf) -> Self {
+        let counters = match ebpf.take_map("TXQ_STALLS").map(PerCpuHashMap::try_from) {
+            Some(Ok(map)) => Some(map),
+            _ => {
+                log::warn!("TXQ_STALLS map unusable, TX queue stalls read nothing");
+                None
+            }
+        };
+        Self {
+            counters,
+            last_totals: HashMap::new(),
+            last_fast: TxqStall::default(),
+        }
+    }
+
+    /// Without a map, as if it were unusable
+    #[cfg(test)]
+    pub(crate) fn detached() -> Self {
+        Self {
+            counters: None,
+            last_totals: HashMap::new(),
+            last_fast: TxqStall::default(),
+        }
+    }
+
+    /// Stalls per interface since the previous call, interfaces without any left
+    /// out. Names are looked up in the interface's own namespace
+    pub(crate) fn read_interval(&mut self, resolver: &mut NetnsResolver) -> Vec<InterfaceTxq> {
+        let Some(map) = &self.counters else {
+            return Vec::new();
+        };
+        let totals: Vec<(TxqKey, TxqStall)> = map
+            .iter()
+            .filter_map(Result::ok)
+            .map(|(key, per_cpu)| (key, per_cpu.iter().fold(TxqStall::default(), add)))
+            .collect();
+        self.interval_since_last(totals, resolver)
+    }
+
+    /// `read_interval` from each interface's totals over the CPUs
+    fn interval_since_last(
+        &mut self,
+        totals: impl IntoIterator<Item = (TxqKey, TxqStall)>,
+        resolver: &mut NetnsResolver,
+    ) -> Vec<InterfaceTxq> {
+        let mut interval = Vec::new();
+        for (key, total) in totals {
+            let last = self
+                .last_totals
+                .insert(key, total)
+                .unwrap_or_default();
+            let txq = InterfaceTxq {
+                interface: resolver.interface_name(key.netns, key.ifindex),
+                ifindex: key.ifindex,
+                netns: key.netns,
+                txq_stalls: total.stops.saturating_sub(last.stops),
+                txq_stalled_ns: total.stopped_ns.saturating_sub(last.stopped_ns),
+                xmit_busy: total.xmit_busy.saturating_sub(last.xmit_busy),
+            };
+            if txq.txq_stalls > 0 || txq.txq_stalled_ns > 0 || txq.xmit_busy > 0 {
+                interval.push(txq);
+            }
+        }
+        interval.sort_by_key(|txq| (txq.netns, txq.ifindex));
+        interval
+    }
+
+    /// Stalls over every interface since the previous call, independent of
+    /// `read_interval`
+    pub(crate) fn read_fast(&mut self) -> TxqStall {
+        let Some(map) = &self.counters else {
+            return TxqStall::default();
+        };
+        let mut total = TxqStall::default();
+        for (_, per_cpu) in map.iter().filter_map(Result::ok) {
+            total = per_cpu.iter().fold(total, add);
+        }
+        self.fast_since_last(total)
+    }
+
+    fn fast_since_last(&mut self, total: TxqStall) -> TxqStall {
+        let last = std::mem::replace(&mut self.last_fast, total);
+        TxqStall {
+            stops: total.stops.saturating_sub(last.stops),
+            stopped_ns: total.stopped_ns.saturating_sub(last.stopped_ns),
+            xmit_busy: total.xmit_busy.saturating_sub(last.xmit_busy),
+        }
+    }
+}
+
+fn add(t: TxqStall, c: &TxqStall) -> TxqStall {
+    TxqStall {
+        stops: t.stops + c.stops,
+        stopped_ns: t.stopped_ns + c.stopped_ns,
+        xmit_busy: t.xmit_busy + c.xmit_busy,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LO: TxqKey = TxqKey {
+        ifindex: 1,
+        netns: 0,
+    };
+    // Same index in another namespace, a different interface
+    const OTHER_NS: TxqKey = TxqKey {
+        ifindex: 1,
+        netns: 4_026_532_999,
+    };
+
+    fn stall(stops: u64, stopped_ns: u64, xmit_busy: u64) -> TxqStall {
+        TxqStall {
+            stops,
+            stopped_ns,
+            xmit_busy,
+        }
+    }
+
+    #[test]
+    fn per_cpu_values_sum_per_interface() {
+        let per_cpu = [stall(2, 1_000, 0), stall(3, 500, 1), stall(0, 0, 0)];
+        let total = per_cpu.iter().fold(TxqStall::default(), add);
+        assert_eq!(
+            (total.stops, total.stopped_ns, total.xmit_busy),
+            (5, 1_500, 1)
+        );
+    }
+
+    #[test]
+    fn each_read_takes_the_growth_since_the_last() {
+        let mut stalls = TxqStalls::detached();
+        let mut resolver = NetnsResolver::new();
+        let first = stalls.interval_since_last([(LO, stall(4, 2_000, 1))], &mut resolver);
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].interface, "lo");
+        assert_eq!(
+            (
+                first[0].txq_stalls,
+                first[0].txq_stalled_ns,
+                first[0].xmit_busy
+            ),
+            (4, 2_000, 1)
+        );
+
+        let second = stalls.interval_since_last(
+            [(LO, stall(6, 2_500, 1)), (OTHER_NS, stall(1, 0, 0))],
+            &mut resolver,
+        );
+        assert_eq!(second.len(), 2);
+        assert_eq!((second[0].txq_stalls, second[0].txq_stalled_ns), (2, 500));
+        assert_eq!(second[0].xmit_busy, 0);
+        assert_eq!((second[1].netns, second[1].txq_stalls), (OTHER_NS.netns, 1));
+
+        // Nothing new anywhere: nothing reported
+        let third = stalls.interval_since_last(
+            [(LO, stall(6, 2_500, 1)), (OTHER_NS, stall(1, 0, 0))],
+            &mut resolver,
+        );
+        assert!(third.is_empty());
+    }
+
+    #[test]
+    fn a_queue_stopped_across_a_read_counts_at_its_wake() {
+        let mut stalls = TxqStalls::detached();
+        let mut resolver = NetnsResolver::new();
+        // Stopped, not woken yet: the stop counts, its time doesn't
+        let stopped = stalls.interval_since_last([(LO, stall(1, 0, 0))], &mut resolver);
+        assert_eq!((stopped[0].txq_stalls, stopped[0].txq_stalled_ns), (1, 0));
+        let woken = stalls.interval_since_last([(LO, stall(1, 7_000_000, 0))], &mut resolver);
+        assert_eq!(
+            (woken[0].txq_stalls, woken[0].txq_stalled_ns),
+            (0, 7_000_000)
+        );
+    }
+
+    #[test]
+    fn fast_reads_keep_their_own_cursor() {
+        let mut stalls = TxqStalls::detached();
+        let mut resolver = NetnsResolver::new();
+        let fast = stalls.fast_since_last(stall(3, 900, 0));
+        assert_eq!((fast.stops, fast.stopped_ns), (3, 900));
+        let interval = stalls.interval_since_last([(LO, stall(3, 900, 0))], &mut resolver);
+        assert_eq!(interval[0].txq_stalls, 3);
+        // The interval read didn't move the fast one's start
+        let fast = stalls.fast_since_last(stall(5, 1_000, 2));
+        assert_eq!((fast.stops, fast.stopped_ns, fast.xmit_busy), (2, 100, 2));
+    }
+
+    #[test]
+    fn no_map_reads_nothing() {
+        let mut stalls = TxqStalls::detached();
+        assert!(stalls.read_interval(&mut NetnsResolver::new()).is_empty());
+        assert_eq!(stalls.read_fast().stops, 0);
+    }
+}
@@ -0,0 +1,227 @@
+//Event timestamps as wall-clock time. bpf_ktime_get_ns is the host's
+//CLOCK_MONOTONIC whatever namespace the consumer runs in. Inside a time
+//namespace (`unshare --time`, a container runtime's, a CRIU restore) our own
+//CLOCK_MONOTONIC and CLOCK_BOOTTIME read the host's plus the offsets in the
+//namespace's timens_offsets, while CLOCK_REALTIME isn't namespaced at all. So a
+//WallClock anchors one realtime reading to the host monotonic time it was taken
+//at, our monotonic reading less the namespace's offset, and converts from
+//there. Re-anchoring every WALL_CLOCK_REFRESH picks up NTP steps and a changed
+//offset file; a realtime that moved off the old anchor's prediction by more
+//than WALL_CLOCK_TOLERANCE is logged as a step.
+
+use std::io;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const TIMENS_OFFSETS: &str = "/proc/self/timens_offsets";
+
+/// How long an anchor is used before the clocks and offsets are read again
+pub const WALL_CLOCK_REFRESH: Duration = Duration::from_secs(10);
+/// A converted timestamp further than this from the wall clock is a warning:
+/// at the startup self-check, and as a clock step on re-anchoring
+pub const WALL_CLOCK_TOLERANCE: Duration = Duration::from_secs(1);
+
+/// This process's time namespace offsets, zero on the host's or on kernels
+/// before 5.6 without time namespaces
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeNamespaceOffsets {
+    /// Added to the host's CLOCK_MONOTONIC, the clock events are stamped with
+    pub monotonic_ns: i64,
+    /// Added to the host's CLOCK_BOOTTIME. No event carries boot time; it's
+    /// here for the record
+    pub boottime_ns: i64,
+}
+
+impl TimeNamespaceOffsets {
+    /// The contents of /proc/self/timens_offsets: a clock, seconds and
+    /// nanoseconds per line. The kernel writes clocks by name; ids (1 for
+    /// CLOCK_MONOTONIC, 7 for CLOCK_BOOTTIME) are taken too
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let mut offsets = Self::default();
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [clock, secs, nanos] = fields[..] else {
+                return Err(format!("timens_offsets line {:?}", line));
+            };
+            let number = |field: &str| {
+                field
+                    .parse::<i64>()
+                    .map_err(|e| format!("timens_offsets {:?}: {}", field, e))
+            };
+            let (secs, nanos) = (number(secs)?, number(nanos)?);
+            let offset = secs
+                .checked_mul(1_000_000_000)
+                .and_then(|ns| ns.checked_add(nanos))
+                .ok_or_else(|| format!("timens_offsets line {:?} out of range", line))?;
+            match clock {
+                "monotonic" | "1" => offsets.monotonic_ns = offset,
+                "boottime" | "7" => offsets.boottime_ns = offset,
+                _ => return Err(format!("timens_offsets clock {:?}", clock)),
+            }
+        }
+        Ok(offsets)
+    }
+
+    /// This process's, zero where the file doesn't exist
+    pub fn read() -> io::Result<Self> {
+        match std::fs::read_to_string(TIMENS_OFFSETS) {
+            Ok(contents) => {
+                Self::parse(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn is_host(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Converts event timestamps to wall-clock time from one anchor, see
+/// `CongestionCollector::to_wallclock`
+#[derive(Debug, Clone, Copy)]
+pub struct WallClock {
+    realtime_ns: i64,
+    // The host's CLOCK_MONOTONIC at the realtime reading
+    host_monotonic_ns: i64,
+    offsets: TimeNamespaceOffsets,
+    anchored_at: Instant,
+}
+
+impl WallClock {
+    /// Anchored now, with this process's offsets. An unreadable offset file is
+    /// logged and taken as the host's
+    pub fn now() -> Option<Self> {
+        let offsets = TimeNamespaceOffsets::read().unwrap_or_else(|e| {
+            log::warn!(
+                "{}: {}, converting timestamps as on the host",
+                TIMENS_OFFSETS,
+                e
+            );
+            TimeNamespaceOffsets::default()
+        });
+        let realtime = SystemTime::now();
+        let monotonic_ns = crate::health::monotonic_now_ns()?;
+        Some(Self::from_readings(realtime, monotonic_ns, offsets))
+    }
+
+    /// From a realtime reading and the CLOCK_MONOTONIC taken with it inside the
+    /// namespace `offsets` are of
+    pub fn from_readings(
+        realtime: SystemTime,
+        monotonic_ns: u64,
+        offsets: TimeNamespaceOffsets,
+    ) -> Self {
+        let realtime_ns = match realtime.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_nanos() as i64,
+            Err(before) => -(before.duration().as_nanos() as i64),
+        };
+        Self {
+            realtime_ns,
+            host_monotonic_ns: monotonic_ns as i64 - offsets.monotonic_ns,
+            offsets,
+            anchored_at: Instant::now(),
+        }
+    }
+
+    pub fn offsets(&self) -> TimeNamespaceOffsets {
+        self.offsets
+    }
+
+    /// Past WALL_CLOCK_REFRESH since it was anchored
+    pub fn is_due(&self) -> bool {
+        self.anchored_at.elapsed() >= WALL_CLOCK_REFRESH
+    }
+
+    /// An event's `timestamp_ns` as wall-clock time
+    pub fn to_wallclock(&self, timestamp_ns: u64) -> SystemTime {
+        let ns = self.realtime_ns + (timestamp_ns as i64 - self.host_monotonic_ns);
+        if ns >= 0 {
+            UNIX_EPOCH + Duration::from_nanos(ns as u64)
+        } else {
+            UNIX_EPOCH - Duration::from_nanos(ns.unsigned_abs())
+        }
+    }
+
+    /// How far an event stamped `timestamp_ns` converts from `seen_at`, when it
+    /// was known to have happened
+    pub fn skew(&self, timestamp_ns: u64, seen_at: SystemTime) -> Duration {
+        let converted = self.to_wallclock(timestamp_ns);
+        converted
+            .duration_since(seen_at)
+            .or_else(|_| seen_at.duration_since(converted))
+            .unwrap_or_default()
+    }
+
+    /// How far realtime moved against the host's monotonic clock between
+    /// `earlier` and this anchor, positive for a step forward
+    pub fn step_from(&self, earlier: &WallClock) -> i64 {
+        let predicted = earlier.realtime_ns + (self.host_monotonic_ns - earlier.host_monotonic_ns);
+        self.realtime_ns - predicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WALL_CLOCK_TOLERANCE;
+
+    const HOUR_NS: i64 = 3_600_000_000_000;
+
+    #[test]
+    fn offsets_parse_by_name_or_clock_id() {
+        let named = TimeNamespaceOffsets::parse("monotonic 3600 0\nboottime 7200 500\n").unwrap();
+        assert_eq!(named.monotonic_ns, HOUR_NS);
+        assert_eq!(named.boottime_ns, 2 * HOUR_NS + 500);
+        let by_id = TimeNamespaceOffsets::parse("1 -5 0\n7 0 0\n").unwrap();
+        assert_eq!(by_id.monotonic_ns, -5_000_000_000);
+        assert!(TimeNamespaceOffsets::parse("monotonic 0 0\nboottime 0 0\n")
+            .unwrap()
+            .is_host());
+    }
+
+    #[test]
+    fn malformed_or_unknown_offsets_are_refused() {
+        assert!(TimeNamespaceOffsets::parse("monotonic 3600\n").is_err());
+        assert!(TimeNamespaceOffsets::parse("realtime 1 0\n").is_err());
+    }
+
+    /// An hour's monotonic offset, as a container restored elsewhere might
+    /// have, comes off our own monotonic reading: a host-clock event converts
+    /// to 1 s after the anchor, not an hour before
+    #[test]
+    fn the_namespace_offset_comes_off_our_reading() {
+        let offsets = TimeNamespaceOffsets::parse("monotonic 3600 0\n").unwrap();
+        // The host's monotonic clock is 100 s at the anchor, ours reads 3700 s
+        let anchor = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let ours_ns = (100 * 1_000_000_000 + HOUR_NS) as u64;
+        let corrected = WallClock::from_readings(anchor, ours_ns, offsets);
+        let uncorrected =
+            WallClock::from_readings(anchor, ours_ns, TimeNamespaceOffsets::default());
+        let event_ns = 101 * 1_000_000_000;
+        assert_eq!(
+            corrected.to_wallclock(event_ns),
+            anchor + Duration::from_secs(1)
+        );
+        assert_eq!(
+            uncorrected.to_wallclock(event_ns),
+            anchor - Duration::from_secs(3_599)
+        );
+
+        let seen_at = anchor + Duration::from_millis(1_002);
+        assert_eq!(corrected.skew(event_ns, seen_at), Duration::from_millis(2));
+        assert!(uncorrected.skew(event_ns, seen_at) > WALL_CLOCK_TOLERANCE);
+    }
+
+    #[test]
+    fn a_realtime_step_shows_between_anchors() {
+        let anchor = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let offsets = TimeNamespaceOffsets::default();
+        let first = WallClock::from_readings(anchor, 100_000_000_000, offsets);
+        // 10 s on, with realtime stepped 2 s forward
+        let stepped =
+            WallClock::from_readings(anchor + Duration::from_secs(12), 110_000_000_000, offsets);
+        assert_eq!(stepped.step_from(&first), 2_000_000_000);
+        assert_eq!(first.step_from(&first), 0);
+    }
+}
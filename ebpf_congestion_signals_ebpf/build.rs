@@ -0,0 +1,32 @@
+//Hash the shared types so the object can carry a fingerprint of the layout it
+//was built against. ebpf_congestion_signals/build.rs hashes the same file the
+//same way; keep the two in sync.
+
+use std::{env, fs, path::Path};
+
+fn layout_hash(src: &str) -> u64 {
+    // FNV-1a over the code with comments and whitespace removed, so reformatting
+    // or rewording a comment doesn't count as a layout change
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for line in src.lines() {
+        let code = line.split("//").next().unwrap_or("");
+        for b in code.bytes().filter(|b| !b.is_ascii_whitespace()) {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+fn main() {
+    let types = "src/types.rs";
+    println!("cargo:rerun-if-changed={}", types);
+
+    let src = fs::read_to_string(types).expect("read src/types.rs");
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("layout_hash.rs");
+    fs::write(
+        out,
+        format!("pub const LAYOUT_HASH: u64 = {:#x};\n", layout_hash(&src)),
+    )
+    .unwrap();
+}
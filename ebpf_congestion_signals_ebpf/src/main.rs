@@ -4,247 +4,1855 @@
 mod types;
 
 use aya_ebpf::{
-    bindings::BPF_F_CURRENT_CPU,
-    helpers::{bpf_get_smp_processor_id, bpf_ktime_get_ns},
-    macros::{kprobe, map, tracepoint},
-    maps::{PerCpuArray, PerfEventArray},
-    programs::{ProbeContext, TracePointContext},
+    bindings::{BPF_F_CURRENT_CPU, BPF_NOEXIST, TC_ACT_PIPE},
+    helpers::{
+        bpf_get_current_cgroup_id, bpf_get_smp_processor_id, bpf_ktime_get_ns,
+        bpf_probe_read_kernel, bpf_probe_read_kernel_str_bytes,
+    },
+    macros::{classifier, kprobe, kretprobe, map, tracepoint},
+    maps::{Array, HashMap, PerCpuArray, PerCpuHashMap, PerfEventArray},
+    programs::{ProbeContext, RetProbeContext, TcContext, TracePointContext},
+    EbpfContext,
 };
 
 use types::*;
 
+// LAYOUT_HASH, generated by build.rs from types.rs
+include!(concat!(env!("OUT_DIR"), "/layout_hash.rs"));
+
+/// Read by userspace from the object file before loading, see types.rs
+#[no_mangle]
+static CONGESTION_SCHEMA: SchemaDescriptor = SchemaDescriptor {
+    magic: SCHEMA_MAGIC,
+    version: SCHEMA_VERSION,
+    layout_hash: LAYOUT_HASH,
+    record_size: core::mem::size_of::<CongestionEvent>() as u32,
+    event_type_count: EVENT_TYPE_COUNT,
+    payload_sizes: payload_sizes(),
+};
+
+/// Filled in by userspace via EbpfLoader::set_global, see types.rs
+#[no_mangle]
+static KERNEL_OFFSETS: KernelOffsets = KernelOffsets {
+    tcp_snd_cwnd: OFFSET_UNKNOWN,
+    tcp_snd_ssthresh: OFFSET_UNKNOWN,
+    sk_pacing_rate: OFFSET_UNKNOWN,
+    skb_head: OFFSET_UNKNOWN,
+    skb_network_header: OFFSET_UNKNOWN,
+    sk_cookie: OFFSET_UNKNOWN,
+    kfree_skb_reason: OFFSET_UNKNOWN,
+    sk_wmem_alloc: OFFSET_UNKNOWN,
+    sk_wmem_queued: OFFSET_UNKNOWN,
+    sk_sndbuf: OFFSET_UNKNOWN,
+    skb_sk: OFFSET_UNKNOWN,
+    skb_mac_header: OFFSET_UNKNOWN,
+    skb_transport_header: OFFSET_UNKNOWN,
+    skb_dev: OFFSET_UNKNOWN,
+    netdev_queue_dev: OFFSET_UNKNOWN,
+    net_device_ifindex: OFFSET_UNKNOWN,
+    sk_family: OFFSET_UNKNOWN,
+    sk_daddr: OFFSET_UNKNOWN,
+    sk_v6_daddr: OFFSET_UNKNOWN,
+    sk_net: OFFSET_UNKNOWN,
+    net_ns_inum: OFFSET_UNKNOWN,
+    net_device_nd_net: OFFSET_UNKNOWN,
+    inet_tos: OFFSET_UNKNOWN,
+    sk_priority: OFFSET_UNKNOWN,
+    sk_max_pacing_rate: OFFSET_UNKNOWN,
+    skb_len: OFFSET_UNKNOWN,
+    inet_cork_gso_size: OFFSET_UNKNOWN,
+    sk_rmem_alloc: OFFSET_UNKNOWN,
+    sk_rcvbuf: OFFSET_UNKNOWN,
+    sk_dport: OFFSET_UNKNOWN,
+};
+
+/// Non-zero to leave loopback sends out of the sampled events, see
+/// CollectorConfig::exclude_loopback. Set like KERNEL_OFFSETS
+#[no_mangle]
+static EXCLUDE_LOOPBACK: u32 = 0;
+
+/// Non-zero to put a flow hash on sampled UDP sends, see
+/// CollectorConfig::track_flows. Set like KERNEL_OFFSETS
+#[no_mangle]
+static TRACK_FLOWS: u32 = 0;
+
+/// Non-zero to add sends, drops and send buffer samples up per cgroup in
+/// CGROUP_SIGNALS, see CollectorConfig::cgroup_aggregation. Set like KERNEL_OFFSETS
+#[no_mangle]
+static AGGREGATE_CGROUPS: u32 = 0;
+
+/// Longest a run of kfree_skb drops is held back for coalescing, ns. 0 sends
+/// every drop on its own; see CollectorConfig::drop_coalescing
+#[no_mangle]
+static DROP_COALESCE_NS: u64 = 0;
+
+/// Most drops coalesced into one event
+#[no_mangle]
+static DROP_COALESCE_MAX: u32 = 0;
+
+/// 1-in-N ratio of the second send sampler, 0 when it's off; see
+/// CollectorConfig::with_sampler_comparison
+#[no_mangle]
+static COMPARE_SAMPLE_RATIO: u64 = 0;
+
+/// Per-socket sampling: sockets whose socket_sample_hash is below this, out of
+/// 1 << 32, have every send and occupancy sample sent, the others none. 0
+/// samples 1 in 100 events instead; see CollectorConfig::with_socket_sampling
+#[no_mangle]
+static SOCKET_SAMPLE_THRESHOLD: u64 = 0;
+
+/// NET_RX rounds at least this long, ns, have their NAPI polls sent ahead of
+/// the exit. 0 keeps none; see CollectorConfig::with_softirq_breakdown
+#[no_mangle]
+static NAPI_SAMPLE_NS: u64 = 0;
+
+/// Cookie of the collector's heartbeat socket, 0 without one. Its sends all go
+/// out, as trace-only samples nothing counts; see CollectorConfig::with_heartbeat
+#[no_mangle]
+static HEARTBEAT_SOCKET: u64 = 0;
+
 // Maps
 #[map]
 static EVENTS: PerfEventArray<CongestionEvent> = PerfEventArray::new(0);
 
-#[map]
-static SOFTIRQ_START: PerCpuArray<u64> = PerCpuArray::with_max_entries(10, 0);
+/// Entry timestamp per softirq vector. Being per-CPU, each slot is keyed by
+/// (cpu, vec); 0 when no entry is outstanding
+#[map]
+static SOFTIRQ_START: PerCpuArray<u64> = PerCpuArray::with_max_entries(10, 0);
+
+/// softirq exits dropped without a duration: no matching entry (probe attached
+/// mid-softirq, a lost entry) or an elapsed time past SOFTIRQ_MAX_DURATION_NS.
+/// Never reset, userspace diffs successive reads
+#[map]
+static SOFTIRQ_DISCARDED: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+/// net.core.netdev_budget{,_usecs}, kept current by userspace since they're
+/// often tuned at runtime. All zero until written, napi_poll stays quiet then
+#[map]
+static RX_BUDGET: Array<RxBudget> = Array::with_max_entries(1, 0);
+
+/// Sampler shift and event mask of the overhead budget, see DEGRADE_* in
+/// types.rs
+#[map]
+static DEGRADATION: Array<u32> = Array::with_max_entries(DEGRADE_SLOTS, 0);
+
+/// The NET_RX softirq running on this CPU, if any, see napi_poll
+#[map]
+static NET_RX_ROUND: PerCpuArray<RxRound> = PerCpuArray::with_max_entries(1, 0);
+
+/// The NET_RX round's first NAPI_POLLS_PER_ROUND polls on this CPU, ready to
+/// send should it turn out long, see send_napi_polls
+#[map]
+static NAPI_POLLS: PerCpuArray<CongestionEvent> =
+    PerCpuArray::with_max_entries(NAPI_POLLS_PER_ROUND, 0);
+
+/// The run of drops being coalesced on this CPU, see try_skb_kfree
+#[map]
+static PENDING_DROPS: PerCpuArray<PendingDrops> = PerCpuArray::with_max_entries(1, 0);
+
+/// Per-CPU sampling state for send operations, one slot per sampler: the
+/// primary one, and the comparison one when COMPARE_SAMPLE_RATIO is set
+/// Note: Could be made per-socket by hashing socket pointer, but per-CPU is simpler
+#[map]
+static SEND_SAMPLE_STATE: PerCpuArray<u64> = PerCpuArray::with_max_entries(2, 0);
+
+/// Per-CPU sampling state for the rmem occupancy reads on the UDP receive path
+#[map]
+static RCV_SAMPLE_STATE: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+/// Socket handed to __udp_enqueue_schedule_skb, stashed on entry so the
+/// return probe knows which socket a failed enqueue belonged to.
+/// Softirq context doesn't nest per CPU here, so one slot is enough.
+#[map]
+static UDP_RCV_SK: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+/// Per-CPU sampling state for TCP congestion-control state reads
+#[map]
+static TCP_SAMPLE_STATE: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+/// Sampled socket buffer reads thrown away as implausible, see
+/// plausible_rcv_state. Never reset, userspace diffs successive reads
+#[map]
+static IMPLAUSIBLE_SOCKET_SAMPLES: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+/// tcp_tsq_handler calls: TCP Small Queues releasing a flow it had throttled.
+/// Never reset, userspace diffs successive reads
+#[map]
+static TSQ_THROTTLES: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+/// inet_csk_accept calls that returned a socket: connections a listener handed
+/// to the application. Never reset, userspace diffs successive reads
+#[map]
+static TCP_ACCEPTS: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+/// UDP skbs and the wire packets they leave as, see GsoCounters. Never reset,
+/// userspace diffs successive reads
+#[map]
+static UDP_GSO: PerCpuArray<GsoCounters> = PerCpuArray::with_max_entries(1, 0);
+
+/// Sockets userspace registered for the buffered-bytes gauge, keyed by socket
+/// cookie. Entries are created and removed by userspace only
+#[map]
+static REGISTERED_SOCKETS: PerCpuHashMap<u64, SocketBytes> =
+    PerCpuHashMap::with_max_entries(MAX_REGISTERED_SOCKETS, 0);
+
+/// Socket cookie -> kernel time its trace ends, see trace_sampler. Entries are
+/// created by userspace, and removed by it once they've passed
+#[map]
+static TRACED_SOCKETS: HashMap<u64, u64> = HashMap::with_max_entries(MAX_TRACED_SOCKETS, 0);
+
+/// TX queue stops and busy returns per interface and namespace. Never reset,
+/// userspace diffs successive reads
+#[map]
+static TXQ_STALLS: PerCpuHashMap<TxqKey, TxqStall> =
+    PerCpuHashMap::with_max_entries(MAX_TXQ_INTERFACES, 0);
+
+/// struct netdev_queue address -> when it was stopped, for the queues
+/// currently stopped. Shared across CPUs: stop and wake rarely run on the same one
+#[map]
+static TXQ_STOPPED_AT: HashMap<u64, u64> = HashMap::with_max_entries(MAX_TX_QUEUES, 0);
+
+/// ifindex -> bytes/packets seen by the tc egress program. Never reset,
+/// userspace diffs successive reads
+#[map]
+static EGRESS_COUNTERS: PerCpuHashMap<u32, EgressCount> =
+    PerCpuHashMap::with_max_entries(MAX_EGRESS_INTERFACES, 0);
+
+/// cgroup id -> CgroupCounters, only written with AGGREGATE_CGROUPS set. Never
+/// reset; userspace diffs successive reads and removes cgroups gone stale.
+/// Resized by userspace, a full map leaves new cgroups out
+#[map]
+static CGROUP_SIGNALS: PerCpuHashMap<u64, CgroupCounters> =
+    PerCpuHashMap::with_max_entries(MAX_CGROUPS, 0);
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RxRound {
+    start_ns: u64,
+    packets: u32,
+    state: u32,
+    // Kept in NAPI_POLLS, at most NAPI_POLLS_PER_ROUND
+    polls: u32,
+}
+
+/// Drops with the same reason on the same device, not yet sent
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PendingDrops {
+    count: u32,
+    reason: u32,
+    device: TxqKey,
+    first_ns: u64,
+    last_ns: u64,
+}
+
+const RX_ROUND_IDLE: u32 = 0;
+const RX_ROUND_RUNNING: u32 = 1;
+// Squeeze already reported, net_rx_action breaks out right after
+const RX_ROUND_SQUEEZED: u32 = 2;
+
+const NET_TX_SOFTIRQ: u32 = 2;
+const NET_RX_SOFTIRQ: u32 = 3;
+
+// Polls of one round kept for sending; a round that polls more leaves the rest
+// unattributed
+const NAPI_POLLS_PER_ROUND: u32 = 8;
+
+const NETDEV_TX_OK: i32 = 0;
+const NETDEV_TX_BUSY: i32 = 0x10;
+const UDP_HEADER_LEN: u32 = 8;
+
+// Longest softirq duration taken at face value. net_rx_action gives up after
+// netdev_budget_usecs and __do_softirq restarts for 2 ms at most, so anything
+// near this is a mispaired entry/exit, not a real softirq. Userspace applies the
+// same bound to what it decodes
+const SOFTIRQ_MAX_DURATION_NS: u64 = 100_000_000;
+
+// What a real socket buffer can look like. With a wrong layout the buffer reads
+// land on unrelated fields and come back as 7 bytes or 3 GB, so samples outside
+// these bounds are counted instead of sent. Occupancy overshoots the limit a
+// little (truesize accounting), never by 4x
+const SK_BUF_MIN: u32 = 4 * 1024;
+const SK_BUF_MAX: u32 = 1024 * 1024 * 1024;
+const SK_BUF_MAX_OVERSHOOT: u64 = 4;
+
+// Helper Functions
+#[inline(always)]
+fn should_sample(state: &PerCpuArray<u64>, ratio: u64) -> bool {
+    should_sample_slot(state, 0, ratio)
+}
+
+#[inline(always)]
+fn should_sample_slot(state: &PerCpuArray<u64>, slot: u32, ratio: u64) -> bool {
+    unsafe {
+        if let Some(counter) = state.get_ptr_mut(slot) {
+            let count = counter.read();
+            counter.write(count.wrapping_add(1));
+            return count % ratio == 0;
+        }
+    }
+    false
+}
+
+/// SendMsgData::samplers for a send from socket `id`, 0 when no sampler
+/// admits it. The comparison sampler counts on its own slot, so it neither
+/// takes nor gives samples to the primary one
+#[inline(always)]
+fn sample_send(id: u64) -> u32 {
+    // Sample every 100th send to reduce overhead
+    // Adjust this ratio based on observed CPU overhead
+    let socket = socket_sampler(id);
+    // The counter runs in both modes, it's what userspace scales coverage by
+    let counted = should_sample_slot(&SEND_SAMPLE_STATE, 0, 100);
+    let mut samplers = socket | trace_sampler(id);
+    if socket != 0 || (counted && !per_socket_sampling()) {
+        samplers |= SAMPLER_PRIMARY;
+    }
+    let compare_ratio = unsafe { core::ptr::read_volatile(&COMPARE_SAMPLE_RATIO) };
+    if compare_ratio > 0 && should_sample_slot(&SEND_SAMPLE_STATE, 1, compare_ratio) {
+        samplers |= SAMPLER_COMPARE;
+    }
+    samplers
+}
+
+/// Samplers of an occupancy sample (receive buffer, TCP state) of socket
+/// `id` counted on `state`, 0 when it isn't taken. Receive enqueues are as
+/// frequent as sends, same ratio. Per socket, a sampled socket's are all taken
+/// unless the overhead budget thins them
+#[inline(always)]
+fn sample_occupancy(state: &PerCpuArray<u64>, id: u64) -> u32 {
+    // A traced socket's are all taken, the overhead budget doesn't thin them
+    let traced = trace_sampler(id);
+    if !per_socket_sampling() {
+        return if should_sample(state, 100 << degrade_shift()) {
+            traced | SAMPLER_PRIMARY
+        } else {
+            traced
+        };
+    }
+    let socket = socket_sampler(id);
+    let shift = degrade_shift();
+    if socket == 0 || (shift > 0 && !should_sample(state, 1 << shift)) {
+        return traced;
+    }
+    traced | socket | SAMPLER_PRIMARY
+}
+
+#[inline(always)]
+fn per_socket_sampling() -> bool {
+    unsafe { core::ptr::read_volatile(&SOCKET_SAMPLE_THRESHOLD) != 0 }
+}
+
+/// SAMPLER_TRACE while socket `id`'s trace hasn't ended, 0 otherwise. One
+/// lookup in a map that's empty unless userspace traces a socket
+#[inline(always)]
+fn trace_sampler(id: u64) -> u32 {
+    match unsafe { TRACED_SOCKETS.get(&id) } {
+        Some(&until) if unsafe { bpf_ktime_get_ns() } <= until => SAMPLER_TRACE,
+        _ => 0,
+    }
+}
+
+/// SAMPLER_SOCKET when socket `id` is in the per-socket sample, 0 otherwise
+/// and without per-socket sampling
+#[inline(always)]
+fn socket_sampler(id: u64) -> u32 {
+    let threshold = unsafe { core::ptr::read_volatile(&SOCKET_SAMPLE_THRESHOLD) };
+    if threshold != 0 && socket_sample_hash(id) < threshold {
+        SAMPLER_SOCKET
+    } else {
+        0
+    }
+}
+
+/// The socket id mixed (murmur3's finalizer) so sequential cookies spread
+/// evenly, top 32 bits. Userspace's SocketSampling::hash must agree
+#[inline(always)]
+fn socket_sample_hash(id: u64) -> u64 {
+    let mut h = id;
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^= h >> 33;
+    h >> 32
+}
+
+/// The overhead budget's extra shift on the occupancy samplers, bounded so a
+/// bad write can't silence them
+#[inline(always)]
+fn degrade_shift() -> u64 {
+    DEGRADATION
+        .get(DEGRADE_SAMPLE_SHIFT)
+        .map_or(0, |shift| (*shift).min(DEGRADE_MAX_SHIFT) as u64)
+}
+
+/// Whether the overhead budget stopped this event type from being sent
+#[inline(always)]
+fn event_masked(event_type: u32) -> bool {
+    DEGRADATION
+        .get(DEGRADE_EVENT_MASK)
+        .is_some_and(|mask| *mask & DEGRADE_MASKABLE & (1 << event_type) != 0)
+}
+
+/// Globals must be read volatile, otherwise the compiler folds in the
+/// OFFSET_UNKNOWN defaults and userspace's values are never seen
+#[inline(always)]
+fn kernel_offsets() -> KernelOffsets {
+    unsafe { core::ptr::read_volatile(&KERNEL_OFFSETS) }
+}
+
+const AF_INET: u16 = 2;
+const AF_INET6: u16 = 10;
+// sockaddr_in.sin_addr and sockaddr_in6.sin6_addr, fixed by the UAPI
+const SIN_ADDR_OFFSET: usize = 4;
+const SIN6_ADDR_OFFSET: usize = 8;
+// ::1, and the ::ffff:127.0.0.0/104 prefix, as the two halves of an in6_addr
+const IN6_LOOPBACK_LOW: u64 = u64::from_ne_bytes([0, 0, 0, 0, 0, 0, 0, 1]);
+const IN6_V4_MAPPED_LOW: u64 = u64::from_ne_bytes([0, 0, 0xff, 0xff, 127, 0, 0, 0]);
+const IN6_V4_MAPPED_MASK: u64 = u64::from_ne_bytes([0xff, 0xff, 0xff, 0xff, 0xff, 0, 0, 0]);
+
+#[inline(always)]
+fn is_loopback_v4(addr: *const u8) -> bool {
+    unsafe { bpf_probe_read_kernel(addr as *const [u8; 4]) }.is_ok_and(|a| a[0] == 127)
+}
+
+#[inline(always)]
+fn is_loopback_v6(addr: *const u8) -> bool {
+    match unsafe { bpf_probe_read_kernel(addr as *const [u64; 2]) } {
+        Ok([high, low]) => {
+            high == 0 && (low == IN6_LOOPBACK_LOW || low & IN6_V4_MAPPED_MASK == IN6_V4_MAPPED_LOW)
+        }
+        Err(_) => false,
+    }
+}
+
+/// Whether a UDP send goes to a loopback address: sendto's address when the
+/// msghdr carries one, else the connected socket's peer. Anything unreadable
+/// counts as real egress. Sends to the host's own non-loopback addresses also
+/// stay on lo, those aren't caught
+#[inline(always)]
+fn is_loopback_send(sk: *const u8, msg: *const u8) -> bool {
+    // msg_name is msghdr's first member. The syscalls copy the address into
+    // the kernel before udp_sendmsg, so it's a kernel pointer
+    let name =
+        unsafe { bpf_probe_read_kernel(msg as *const *const u8) }.unwrap_or(core::ptr::null());
+    if !name.is_null() {
+        return match unsafe { bpf_probe_read_kernel(name as *const u16) } {
+            Ok(AF_INET) => is_loopback_v4(unsafe { name.add(SIN_ADDR_OFFSET) }),
+            Ok(AF_INET6) => is_loopback_v6(unsafe { name.add(SIN6_ADDR_OFFSET) }),
+            _ => false,
+        };
+    }
+
+    let offsets = kernel_offsets();
+    if offsets.sk_family == OFFSET_UNKNOWN {
+        return false;
+    }
+    // skc_daddr of an IPv6 socket holds a placeholder, so go by the family
+    match unsafe { bpf_probe_read_kernel(sk.add(offsets.sk_family as usize) as *const u16) } {
+        Ok(AF_INET) if offsets.sk_daddr != OFFSET_UNKNOWN => {
+            is_loopback_v4(unsafe { sk.add(offsets.sk_daddr as usize) })
+        }
+        Ok(AF_INET6) if offsets.sk_v6_daddr != OFFSET_UNKNOWN => {
+            is_loopback_v6(unsafe { sk.add(offsets.sk_v6_daddr as usize) })
+        }
+        _ => false,
+    }
+}
+
+/// Where a UDP send goes, as one value: port in the top 16 bits over the
+/// address folded to 48. sendto's address when the msghdr carries one, else
+/// the connected socket's peer. 0 with TRACK_FLOWS off or nothing readable;
+/// userspace pairs it with the socket id
+#[inline(always)]
+fn flow_hash(sk: *const u8, msg: *const u8) -> u64 {
+    if unsafe { core::ptr::read_volatile(&TRACK_FLOWS) } == 0 {
+        return 0;
+    }
+
+    let name =
+        unsafe { bpf_probe_read_kernel(msg as *const *const u8) }.unwrap_or(core::ptr::null());
+    if !name.is_null() {
+        // sin_port and sin6_port both follow the family
+        return match unsafe { bpf_probe_read_kernel(name as *const [u16; 2]) } {
+            Ok([AF_INET, port]) => {
+                fold_address(AF_INET, port, unsafe { name.add(SIN_ADDR_OFFSET) })
+            }
+            Ok([AF_INET6, port]) => {
+                fold_address(AF_INET6, port, unsafe { name.add(SIN6_ADDR_OFFSET) })
+            }
+            _ => 0,
+        };
+    }
+
+    let offsets = kernel_offsets();
+    if offsets.sk_family == OFFSET_UNKNOWN || offsets.sk_dport == OFFSET_UNKNOWN {
+        return 0;
+    }
+    let family = unsafe { bpf_probe_read_kernel(sk.add(offsets.sk_family as usize) as *const u16) };
+    let port = unsafe { bpf_probe_read_kernel(sk.add(offsets.sk_dport as usize) as *const u16) };
+    let (Ok(family), Ok(port)) = (family, port) else {
+        return 0;
+    };
+    let address = match family {
+        AF_INET => offsets.sk_daddr,
+        AF_INET6 => offsets.sk_v6_daddr,
+        _ => OFFSET_UNKNOWN,
+    };
+    if address == OFFSET_UNKNOWN {
+        return 0;
+    }
+    fold_address(family, port, unsafe { sk.add(address as usize) })
+}
+
+/// flow_hash's value for `port` and the address of `family` at `addr`
+#[inline(always)]
+fn fold_address(family: u16, port: u16, addr: *const u8) -> u64 {
+    let read = match family {
+        AF_INET => unsafe { bpf_probe_read_kernel(addr as *const u32) }.map(|v4| [v4 as u64, 0]),
+        _ => unsafe { bpf_probe_read_kernel(addr as *const [u64; 2]) },
+    };
+    let Ok([high, low]) = read else {
+        return 0;
+    };
+    let address = high ^ low.rotate_left(29);
+    (((port as u64) << 48) | ((address ^ (address >> 48)) & 0xffff_ffff_ffff)).max(1)
+}
+
+const ECN_CE: u8 = 0b11;
+
+/// ECN bits of the packet's IP header, None when the offsets are unknown or
+/// it's neither IPv4 nor IPv6
+#[inline(always)]
+fn read_skb_ecn(skb: *const u8) -> Option<u8> {
+    let offsets = kernel_offsets();
+    if offsets.skb_head == OFFSET_UNKNOWN || offsets.skb_network_header == OFFSET_UNKNOWN {
+        return None;
+    }
+
+    unsafe {
+        let head =
+            bpf_probe_read_kernel(skb.add(offsets.skb_head as usize) as *const *const u8).ok()?;
+        let network_header =
+            bpf_probe_read_kernel(skb.add(offsets.skb_network_header as usize) as *const u16)
+                .ok()?;
+        // IPv4: version/IHL then TOS. IPv6: version + traffic class split across both bytes
+        let ip = bpf_probe_read_kernel(head.add(network_header as usize) as *const [u8; 2]).ok()?;
+        match ip[0] >> 4 {
+            4 => Some(ip[1] & 0x3),
+            6 => Some((ip[1] >> 4) & 0x3),
+            _ => None,
+        }
+    }
+}
+
+/// Socket cookie when the kernel has assigned one (it does so lazily, e.g. on
+/// getsockopt(SO_COOKIE)), else the sk pointer. Cookies are never reused, so
+/// only pointer ids can alias after a socket is freed; the lifecycle events
+/// below let userspace tell those lifetimes apart.
+/// bpf_get_socket_cookie() itself isn't callable from kprobes/tracepoints.
+#[inline(always)]
+fn socket_id(sk: *const u8) -> u64 {
+    let offset = kernel_offsets().sk_cookie;
+    if offset != OFFSET_UNKNOWN {
+        if let Ok(cookie) = unsafe { bpf_probe_read_kernel(sk.add(offset as usize) as *const u64) } {
+            if cookie != 0 {
+                return cookie;
+            }
+        }
+    }
+    sk as u64
+}
+
+/// ns.inum of the struct net at `holder + offset` (a possible_net_t), 0 when
+/// it can't be read
+#[inline(always)]
+fn netns_at(holder: *const u8, offset: u32) -> u32 {
+    let inum_offset = kernel_offsets().net_ns_inum;
+    if holder.is_null() || offset == OFFSET_UNKNOWN || inum_offset == OFFSET_UNKNOWN {
+        return 0;
+    }
+    unsafe {
+        let Ok(net) = bpf_probe_read_kernel(holder.add(offset as usize) as *const *const u8) else {
+            return 0;
+        };
+        if net.is_null() {
+            return 0;
+        }
+        bpf_probe_read_kernel(net.add(inum_offset as usize) as *const u32).unwrap_or(0)
+    }
+}
+
+/// Network namespace of a socket, see SendMsgData::netns
+#[inline(always)]
+fn sock_netns(sk: *const u8) -> u32 {
+    netns_at(sk, kernel_offsets().sk_net)
+}
+
+/// DSCP and sk_priority of a socket, TRAFFIC_CLASS_UNKNOWN for either one
+/// whose offset isn't known
+#[inline(always)]
+fn sock_class(sk: *const u8) -> (u32, u32) {
+    let offsets = kernel_offsets();
+    let dscp = if offsets.inet_tos != OFFSET_UNKNOWN {
+        unsafe { bpf_probe_read_kernel(sk.add(offsets.inet_tos as usize)) }
+            .map_or(TRAFFIC_CLASS_UNKNOWN, |tos: u8| (tos >> 2) as u32)
+    } else {
+        TRAFFIC_CLASS_UNKNOWN
+    };
+    let priority = if offsets.sk_priority != OFFSET_UNKNOWN {
+        unsafe { bpf_probe_read_kernel(sk.add(offsets.sk_priority as usize) as *const u32) }
+            .unwrap_or(TRAFFIC_CLASS_UNKNOWN)
+    } else {
+        TRAFFIC_CLASS_UNKNOWN
+    };
+    (dscp, priority)
+}
+
+/// sk_pacing_rate and sk_max_pacing_rate of a socket, 0 for either one whose
+/// offset isn't known
+#[inline(always)]
+fn sock_pacing(sk: *const u8) -> (u64, u64) {
+    let offsets = kernel_offsets();
+    let read = |offset: u32| {
+        if offset == OFFSET_UNKNOWN {
+            return 0;
+        }
+        unsafe { bpf_probe_read_kernel(sk.add(offset as usize) as *const u64) }.unwrap_or(0)
+    };
+    (read(offsets.sk_pacing_rate), read(offsets.sk_max_pacing_rate))
+}
+
+/// sk_rmem_alloc and sk_rcvbuf of a socket, both 0 when either offset isn't
+/// known or a read fails
+#[inline(always)]
+fn read_rcv_state(sk: *const u8, id: u64, samplers: u32) -> RcvSocketData {
+    let offsets = kernel_offsets();
+    let (rmem_alloc, rcvbuf) =
+        if offsets.sk_rmem_alloc == OFFSET_UNKNOWN || offsets.sk_rcvbuf == OFFSET_UNKNOWN {
+            (0, 0)
+        } else {
+            let read = |offset: u32| unsafe {
+                bpf_probe_read_kernel(sk.add(offset as usize) as *const u32).ok()
+            };
+            match (read(offsets.sk_rmem_alloc), read(offsets.sk_rcvbuf)) {
+                (Some(rmem_alloc), Some(rcvbuf)) => (rmem_alloc, rcvbuf),
+                _ => (0, 0),
+            }
+        };
+
+    RcvSocketData {
+        rmem_alloc,
+        rcvbuf,
+        socket_id: id,
+        samplers,
+    }
+}
+
+/// False for reads that can't come from a correctly laid out struct sock, which
+/// are counted in IMPLAUSIBLE_SOCKET_SAMPLES
+#[inline(always)]
+fn plausible_buffer(occupancy: u32, limit: u32) -> bool {
+    let plausible = (SK_BUF_MIN..=SK_BUF_MAX).contains(&limit)
+        && occupancy as u64 <= limit as u64 * SK_BUF_MAX_OVERSHOOT;
+    if !plausible {
+        if let Some(count) = IMPLAUSIBLE_SOCKET_SAMPLES.get_ptr_mut(0) {
+            unsafe { *count += 1 };
+        }
+    }
+    plausible
+}
+
+#[inline(always)]
+fn plausible_rcv_state(rcv: &RcvSocketData) -> bool {
+    plausible_buffer(rcv.rmem_alloc, rcv.rcvbuf)
+}
+
+/// Send buffer occupancy at `occupancy_offset` against sk_sndbuf, None when
+/// either offset is unknown, a read fails or the pair looks implausible
+#[inline(always)]
+fn read_send_state(
+    sk: *const u8,
+    occupancy_offset: u32,
+    protocol: u32,
+    samplers: u32,
+) -> Option<SocketData> {
+    let sndbuf_offset = kernel_offsets().sk_sndbuf;
+    if occupancy_offset == OFFSET_UNKNOWN || sndbuf_offset == OFFSET_UNKNOWN {
+        return None;
+    }
+    let (wmem_queued, sndbuf) = unsafe {
+        (
+            bpf_probe_read_kernel(sk.add(occupancy_offset as usize) as *const u32).ok()?,
+            bpf_probe_read_kernel(sk.add(sndbuf_offset as usize) as *const u32).ok()?,
+        )
+    };
+    if !plausible_buffer(wmem_queued, sndbuf) {
+        return None;
+    }
+    let (dscp, priority) = sock_class(sk);
+    let (pacing_rate, max_pacing_rate) = sock_pacing(sk);
+    Some(SocketData {
+        wmem_queued,
+        sndbuf,
+        socket_id: socket_id(sk),
+        protocol,
+        netns: sock_netns(sk),
+        dscp,
+        priority,
+        pacing_rate,
+        max_pacing_rate,
+        samplers,
+    })
+}
+
+#[inline(always)]
+fn output_send_state<C: EbpfContext>(ctx: &C, socket: SocketData) {
+    let event = CongestionEvent {
+        timestamp_ns: unsafe { bpf_ktime_get_ns() },
+        event_type: EVENT_SOCKET_STATE,
+        cpu_id: unsafe { bpf_get_smp_processor_id() },
+        data: EventData { socket },
+    };
+    EVENTS.output(ctx, &event, (BPF_F_CURRENT_CPU as u64).try_into().unwrap());
+
+    if socket.sndbuf > 0 {
+        let pressure = (socket.wmem_queued as u64 * 1000) / socket.sndbuf as u64;
+        add_to_cgroup(|cgroup| match socket.protocol {
+            IPPROTO_UDP => {
+                cgroup.udp_wmem_total += pressure;
+                cgroup.udp_wmem_samples += 1;
+            }
+            IPPROTO_TCP => {
+                cgroup.tcp_wmem_total += pressure;
+                cgroup.tcp_wmem_samples += 1;
+            }
+            _ => {}
+        });
+    }
+}
+
+/// A NET_TX or NET_RX softirq is running on this CPU: whatever task it
+/// interrupted has nothing to do with the packet
+#[inline(always)]
+fn in_net_softirq() -> bool {
+    let running = |vec| SOFTIRQ_START.get(vec).is_some_and(|&start| start != 0);
+    running(NET_TX_SOFTIRQ) || running(NET_RX_SOFTIRQ)
+}
+
+/// Count something against the current task's cgroup, with AGGREGATE_CGROUPS
+/// set and outside network softirqs. One helper call and one map update
+#[inline(always)]
+fn add_to_cgroup(update: impl FnOnce(&mut CgroupCounters)) {
+    if unsafe { core::ptr::read_volatile(&AGGREGATE_CGROUPS) } == 0 || in_net_softirq() {
+        return;
+    }
+    let cgroup_id = unsafe { bpf_get_current_cgroup_id() };
+    let now = unsafe { bpf_ktime_get_ns() };
+    match CGROUP_SIGNALS.get_ptr_mut(&cgroup_id) {
+        // Per-CPU slot, nothing else writes it while we run
+        Some(counters) => {
+            let counters = unsafe { &mut *counters };
+            update(counters);
+            counters.last_seen_ns = now;
+        }
+        None => {
+            let mut counters = CgroupCounters {
+                send_bytes: 0,
+                loopback_send_bytes: 0,
+                sends: 0,
+                drops: 0,
+                udp_wmem_total: 0,
+                udp_wmem_samples: 0,
+                tcp_wmem_total: 0,
+                tcp_wmem_samples: 0,
+                last_seen_ns: now,
+            };
+            update(&mut counters);
+            // Fails once the map is full
+            let _ = CGROUP_SIGNALS.insert(&cgroup_id, &counters, 0);
+        }
+    }
+}
+
+// QUIC-Relevant Probes
+/// Probe UDP sends - CRITICAL for QUIC (which runs over UDP)
+#[kprobe]
+pub fn udp_sendmsg(ctx: ProbeContext) -> u32 {
+    match try_udp_sendmsg(ctx) {
+        Ok(()) => 0,
+        Err(_) => 1,
+    }
+}
+
+fn try_udp_sendmsg(ctx: ProbeContext) -> Result<(), i64> {
+    let sk: *const core::ffi::c_void = unsafe { ctx.arg(0).ok_or(1i64)? };
+    let msg: *const u8 = ctx.arg(1).ok_or(1i64)?;
+    let len: usize = unsafe { ctx.arg(2).ok_or(1i64)? };
+
+    // Registered sockets see every send, not 1 in 100
+    let id = socket_id(sk as *const u8);
+    // The heartbeat's loopback sends go ahead of the exclusion and sampling
+    let heartbeat = unsafe { core::ptr::read_volatile(&HEARTBEAT_SOCKET) };
+    if heartbeat != 0 && id == heartbeat {
+        let event = CongestionEvent {
+            timestamp_ns: unsafe { bpf_ktime_get_ns() },
+            event_type: EVENT_UDP_SEND,
+            cpu_id: unsafe { bpf_get_smp_processor_id() },
+            data: EventData {
+                sendmsg: SendMsgData {
+                    bytes: len as u64,
+                    is_tcp: 0,
+                    loopback: 1,
+                    socket_id: id,
+                    netns: 0,
+                    dscp: 0,
+                    priority: 0,
+                    samplers: SAMPLER_TRACE,
+                    flow_hash: 0,
+                },
+            },
+        };
+        EVENTS.output(&ctx, &event, (BPF_F_CURRENT_CPU as u64).try_into().unwrap());
+        return Ok(());
+    }
+    if let Some(bytes) = REGISTERED_SOCKETS.get_ptr_mut(&id) {
+        let wmem_offset = kernel_offsets().sk_wmem_alloc;
+        let wmem = if wmem_offset != OFFSET_UNKNOWN {
+            unsafe {
+                bpf_probe_read_kernel(sk.add(wmem_offset as usize) as *const u32)
+                    .unwrap_or(WMEM_UNREAD)
+            }
+        } else {
+            WMEM_UNREAD
+        };
+        // Per-CPU slot. The reading goes in before the bytes, so userspace never
+        // sees bytes newer than the wmem reading bounding them
+        unsafe {
+            (*bytes).last_send_ns = bpf_ktime_get_ns();
+            (*bytes).last_send_wmem = wmem;
+            (*bytes).last_send_len = len as u32;
+            (*bytes).sent += len as u64;
+        }
+    }
+
+    // Loopback sends never reach a NIC. Excluded ones go before sampling so
+    // they don't take sample slots from real egress
+    let exclude_loopback = unsafe { core::ptr::read_volatile(&EXCLUDE_LOOPBACK) } != 0;
+    if exclude_loopback && is_loopback_send(sk as *const u8, msg) {
+        return Ok(());
+    }
+
+    let samplers = sample_send(id);
+    if samplers == 0 {
+        return Ok(());
+    }
+    // Excluded loopback sends returned above
+    let loopback = !exclude_loopback && is_loopback_send(sk as *const u8, msg);
+    let (dscp, priority) = sock_class(sk as *const u8);
+
+    let event = CongestionEvent {
+        timestamp_ns: unsafe { bpf_ktime_get_ns() },
+        event_type: EVENT_UDP_SEND,
+        cpu_id: unsafe { bpf_get_smp_processor_id() },
+        data: EventData {
+            sendmsg: SendMsgData {
+                bytes: len as u64,
+                is_tcp: 0,
+                loopback: loopback as u32,
+                socket_id: id,
+                netns: sock_netns(sk as *const u8),
+                dscp,
+                priority,
+                samplers,
+                flow_hash: flow_hash(sk as *const u8, msg),
+            },
+        },
+    };
+
+    unsafe {
+        EVENTS.output(&ctx, &event, (BPF_F_CURRENT_CPU as u64).try_into().unwrap());
+    }
+    // Everything past the event is the primary sampler's, and a trace's
+    if samplers & (SAMPLER_PRIMARY | SAMPLER_TRACE) == 0 {
+        return Ok(());
+    }
+    if samplers & SAMPLER_PRIMARY != 0 {
+        add_to_cgroup(|cgroup| {
+            cgroup.send_bytes += len as u64;
+            cgroup.sends += 1;
+            if loopback {
+                cgroup.loopback_send_bytes += len as u64;
+            }
+        });
+    }
+
+    // The same sampled sends carry the socket's wmem. UDP has no send queue of
+    // its own, sk_wmem_alloc is what sits in the qdisc/NIC until TX completion
+    let offsets = kernel_offsets();
+    if let Some(socket) =
+        read_send_state(sk as *const u8, offsets.sk_wmem_alloc, IPPROTO_UDP, samplers)
+    {
+        output_send_state(&ctx, socket);
+    }
+
+    Ok(())
+}
+
+/// Tracepoint for packet drops - detects network congestion
+#[tracepoint]
+pub fn skb_kfree(ctx: TracePointContext) -> u32 {
+    match try_skb_kfree(ctx) {
+        Ok(()) => 0,
+        Err(_) => 1,
+    }
+}
+
+fn try_skb_kfree(ctx: TracePointContext) -> Result<(), i64> {
+    // The record gained fields over time (rx_sk in 6.12), so the reason's offset
+    // comes from BTF like the struct offsets
+    let offsets = kernel_offsets();
+    let reason = if offsets.kfree_skb_reason != OFFSET_UNKNOWN {
+        unsafe {
+            ctx.read_at::<u32>(offsets.kfree_skb_reason as usize)
+                .unwrap_or(0)
+        }
+    } else {
+        0
+    };
+    let now = unsafe { bpf_ktime_get_ns() };
+    // Drops in the sender's own syscall (a full qdisc at enqueue) are its
+    // cgroup's; ones from softirq aren't attributable
+    add_to_cgroup(|cgroup| cgroup.drops += 1);
+
+    // A microburst drops thousands of identical skbs within a millisecond;
+    // one event per run of them with the same reason and device, flushed when
+    // that changes, at DROP_COALESCE_MAX, or DROP_COALESCE_NS after the first
+    let window = unsafe { core::ptr::read_volatile(&DROP_COALESCE_NS) };
+    let Some(pending) = PENDING_DROPS.get_ptr_mut(0).filter(|_| window > 0) else {
+        output_drops(&ctx, 1, reason, now, now);
+        return Ok(());
+    };
+    let pending = unsafe { &mut *pending };
+    let skb = unsafe { ctx.read_at::<*const u8>(8).unwrap_or(core::ptr::null()) };
+    let device = if skb.is_null() {
+        None
+    } else {
+        skb_txq_key(skb, &offsets)
+    }
+    .unwrap_or(TxqKey {
+        ifindex: 0,
+        netns: 0,
+    });
+    if pending.count > 0
+        && (pending.reason != reason
+            || pending.device.ifindex != device.ifindex
+            || pending.device.netns != device.netns
+            || now.saturating_sub(pending.first_ns) > window)
+    {
+        flush_drops(&ctx, pending);
+    }
+    if pending.count == 0 {
+        pending.reason = reason;
+        pending.device = device;
+        pending.first_ns = now;
+    }
+    pending.count += 1;
+    pending.last_ns = now;
+    if pending.count >= unsafe { core::ptr::read_volatile(&DROP_COALESCE_MAX) } {
+        flush_drops(&ctx, pending);
+    }
+
+    Ok(())
+}
+
+/// Send this CPU's pending drops if the run is older than DROP_COALESCE_NS,
+/// from probes that fire often so the last run of a burst isn't held back
+#[inline(always)]
+fn flush_stale_drops<C: EbpfContext>(ctx: &C) {
+    let window = unsafe { core::ptr::read_volatile(&DROP_COALESCE_NS) };
+    if window == 0 {
+        return;
+    }
+    let Some(pending) = PENDING_DROPS.get_ptr_mut(0) else {
+        return;
+    };
+    let pending = unsafe { &mut *pending };
+    let age = unsafe { bpf_ktime_get_ns() }.saturating_sub(pending.first_ns);
+    if pending.count > 0 && age > window {
+        flush_drops(ctx, pending);
+    }
+}
+
+#[inline(always)]
+fn flush_drops<C: EbpfContext>(ctx: &C, pending: &mut PendingDrops) {
+    output_drops(
+        ctx,
+        pending.count,
+        pending.reason,
+        pending.first_ns,
+        pending.last_ns,
+    );
+    pending.count = 0;
+}
+
+/// One EVENT_QDISC_DROP for `count` drops, stamped with the last of them
+#[inline(always)]
+fn output_drops<C: EbpfContext>(ctx: &C, count: u32, reason: u32, first_ns: u64, last_ns: u64) {
+    let span_ns = last_ns.saturating_sub(first_ns);
+    let event = CongestionEvent {
+        timestamp_ns: last_ns,
+        event_type: EVENT_QDISC_DROP,
+        cpu_id: unsafe { bpf_get_smp_processor_id() },
+        data: EventData {
+            qdisc: QdiscData {
+                dropped: count,
+                backlog_bytes: span_ns.min(u32::MAX as u64) as u32,
+                backlog_packets: 0,
+                reason,
+            },
+        },
+    };
+
+    unsafe {
+        EVENTS.output(ctx, &event, (BPF_F_CURRENT_CPU as u64).try_into().unwrap());
+    }
+}
+
+/// Tracepoint for qdisc queue events - leading indicator of congestion
+/// This replaces the complex tcp_write_xmit approach for qdisc visibility
+#[tracepoint]
+pub fn net_dev_queue(ctx: TracePointContext) -> u32 {
+    match try_net_dev_queue(ctx) {
+        Ok(()) => 0,
+        Err(_) => 1,
+    }
+}
+
+fn try_net_dev_queue(ctx: TracePointContext) -> Result<(), i64> {
+    if event_masked(EVENT_NET_DEV_QUEUE) {
+        return Ok(());
+    }
+
+    // Tracepoint format (from /sys/kernel/debug/tracing/events/net/net_dev_queue/format):
+    // field:void * skbaddr;
+    // field:unsigned int len;
+    // field:__data_loc char[] name;
+    
+    // Read packet length (offset may vary - typically at offset 16 or 24)
+    let len = unsafe { ctx.read_at::<u32>(16).unwrap_or(0) };
+    
+    // We can sample this too if it generates too many events
+    // For now, capture all queue events since they're already relatively infrequent
+    
+    let event = CongestionEvent {
+        timestamp_ns: unsafe { bpf_ktime_get_ns() },
+        event_type: EVENT_NET_DEV_QUEUE,
+        cpu_id: unsafe { bpf_get_smp_processor_id() },
+        data: EventData {
+            qdisc: QdiscData {
+                dropped: 0,
+                backlog_bytes: len,
+                backlog_packets: 1,
+                reason: 0,
+            },
+        },
+    };
+
+    unsafe {
+        EVENTS.output(&ctx, &event, (BPF_F_CURRENT_CPU as u64).try_into().unwrap());
+    }
+
+    Ok(())
+}
+
+/// Tracepoint on net_dev_xmit - the driver took an skb (or refused it). Only
+/// looked at for registered sockets: whatever of theirs got this far has left
+/// the socket and the qdisc
+#[tracepoint]
+pub fn net_dev_xmit(ctx: TracePointContext) -> u32 {
+    match try_net_dev_xmit(ctx) {
+        Ok(()) => 0,
+        Err(_) => 1,
+    }
+}
+
+fn try_net_dev_xmit(ctx: TracePointContext) -> Result<(), i64> {
+    // field:void * skbaddr; offset:8
+    // field:unsigned int len; offset:16
+    // field:int rc; offset:20
+    let rc = unsafe { ctx.read_at::<i32>(20)? };
+    let offsets = kernel_offsets();
+    let skb = unsafe { ctx.read_at::<*const u8>(8)? };
+    if rc == NETDEV_TX_BUSY {
+        // The driver should have stopped the queue before it got this full
+        if let Some(key) = skb_txq_key(skb, &offsets) {
+            add_txq_stall(key, |stall| stall.xmit_busy += 1);
+        }
+        return Ok(());
+    }
+    if rc != NETDEV_TX_OK || offsets.skb_sk == OFFSET_UNKNOWN {
+        return Ok(());
+    }
+    let sk = unsafe { bpf_probe_read_kernel(skb.add(offsets.skb_sk as usize) as *const *const u8)? };
+    if sk.is_null() {
+        return Ok(());
+    }
+    let Some(bytes) = REGISTERED_SOCKETS.get_ptr_mut(&socket_id(sk)) else {
+        return Ok(());
+    };
+
+    let len = unsafe { ctx.read_at::<u32>(16)? };
+    let payload = len.saturating_sub(skb_udp_headers(skb, &offsets).unwrap_or(0));
+    unsafe { (*bytes).transmitted += payload as u64 };
+
+    Ok(())
+}
+
+#[inline(always)]
+fn skb_txq_key(skb: *const u8, offsets: &KernelOffsets) -> Option<TxqKey> {
+    if offsets.skb_dev == OFFSET_UNKNOWN {
+        return None;
+    }
+    let dev =
+        unsafe { bpf_probe_read_kernel(skb.add(offsets.skb_dev as usize) as *const *const u8) }
+            .ok()?;
+    net_device_txq_key(dev, offsets)
+}
+
+/// ifindex and namespace of a struct net_device
+#[inline(always)]
+fn net_device_txq_key(dev: *const u8, offsets: &KernelOffsets) -> Option<TxqKey> {
+    if dev.is_null() || offsets.net_device_ifindex == OFFSET_UNKNOWN {
+        return None;
+    }
+    let ifindex = unsafe {
+        bpf_probe_read_kernel(dev.add(offsets.net_device_ifindex as usize) as *const u32)
+    }
+    .ok()?;
+    Some(TxqKey {
+        ifindex,
+        netns: netns_at(dev, offsets.net_device_nd_net),
+    })
+}
+
+#[inline(always)]
+fn add_txq_stall(key: TxqKey, update: impl FnOnce(&mut TxqStall)) {
+    match TXQ_STALLS.get_ptr_mut(&key) {
+        // Per-CPU slot, nothing else writes it while we run
+        Some(stall) => update(unsafe { &mut *stall }),
+        None => {
+            let mut stall = TxqStall {
+                stops: 0,
+                stopped_ns: 0,
+                xmit_busy: 0,
+            };
+            update(&mut stall);
+            let _ = TXQ_STALLS.insert(&key, &stall, 0);
+        }
+    }
+}
+
+/// Device of a struct netdev_queue, as a TXQ_STALLS key
+#[inline(always)]
+fn txq_key(queue: *const u8) -> Option<TxqKey> {
+    let offsets = kernel_offsets();
+    if offsets.netdev_queue_dev == OFFSET_UNKNOWN {
+        return None;
+    }
+    let dev = unsafe {
+        bpf_probe_read_kernel(queue.add(offsets.netdev_queue_dev as usize) as *const *const u8)
+    }
+    .ok()?;
+    net_device_txq_key(dev, &offsets)
+}
+
+/// Kprobe on netif_tx_stop_queue - the driver ran out of TX descriptors (or its
+/// own limit) and stopped the queue; the qdisc backs up behind it until the
+/// wake. Older kernels inline it into the drivers and this can't attach.
+/// BQL limits set a different bit without calling it and aren't seen
+#[kprobe]
+pub fn netif_tx_stop_queue(ctx: ProbeContext) -> u32 {
+    let Some(queue) = ctx.arg::<*const u8>(0) else {
+        return 0;
+    };
+    let now = unsafe { bpf_ktime_get_ns() };
+    // Only a running queue starts a stall; stopping a stopped one changes nothing
+    if TXQ_STOPPED_AT.insert(&(queue as u64), &now, BPF_NOEXIST as u64).is_ok() {
+        if let Some(key) = txq_key(queue) {
+            add_txq_stall(key, |stall| stall.stops += 1);
+        }
+    }
+    0
+}
+
+/// Kprobe on netif_tx_wake_queue - the driver freed descriptors and restarts
+/// the queue. Drivers wake queues that never stopped too, those aren't in
+/// TXQ_STOPPED_AT
+#[kprobe]
+pub fn netif_tx_wake_queue(ctx: ProbeContext) -> u32 {
+    let Some(queue) = ctx.arg::<*const u8>(0) else {
+        return 0;
+    };
+    let key = queue as u64;
+    let Some(&stopped_at) = (unsafe { TXQ_STOPPED_AT.get(&key) }) else {
+        return 0;
+    };
+    let _ = TXQ_STOPPED_AT.remove(&key);
+    let stopped_ns = unsafe { bpf_ktime_get_ns() }.saturating_sub(stopped_at);
+    if let Some(key) = txq_key(queue) {
+        add_txq_stall(key, |stall| stall.stopped_ns += stopped_ns);
+    }
+    0
+}
+
+/// Link, IP and UDP header bytes in front of the payload. A GSO skb carries
+/// them once, like sendmsg's length doesn't carry them at all
+#[inline(always)]
+fn skb_udp_headers(skb: *const u8, offsets: &KernelOffsets) -> Option<u32> {
+    if offsets.skb_mac_header == OFFSET_UNKNOWN
+        || offsets.skb_network_header == OFFSET_UNKNOWN
+        || offsets.skb_transport_header == OFFSET_UNKNOWN
+    {
+        return None;
+    }
+    let read = |offset: u32| unsafe {
+        bpf_probe_read_kernel(skb.add(offset as usize) as *const u16).ok()
+    };
+    let transport = read(offsets.skb_transport_header)?;
+    // No link header set (e.g. a tun device): count from the IP header
+    let start = match read(offsets.skb_mac_header)? {
+        u16::MAX => read(offsets.skb_network_header)?,
+        mac => mac,
+    };
+    Some(transport.checked_sub(start)? as u32 + UDP_HEADER_LEN)
+}
+
+/// Kprobes on udp_send_skb and udp_v6_send_skb - every UDP skb on its way to
+/// IP, with the inet_cork that carries its gso_size whether UDP_SEGMENT was set
+/// on the socket or per sendmsg. Counted, not emitted: userspace turns the
+/// segments per skb into wire packets per sampled send
+#[kprobe]
+pub fn udp_send_skb(ctx: ProbeContext) -> u32 {
+    match try_udp_send_skb(ctx) {
+        Ok(()) => 0,
+        Err(_) => 1,
+    }
+}
+
+#[kprobe]
+pub fn udp_v6_send_skb(ctx: ProbeContext) -> u32 {
+    match try_udp_send_skb(ctx) {
+        Ok(()) => 0,
+        Err(_) => 1,
+    }
+}
+
+// Both take (skb, flowi, cork)
+fn try_udp_send_skb(ctx: ProbeContext) -> Result<(), i64> {
+    let offsets = kernel_offsets();
+    if offsets.skb_len == OFFSET_UNKNOWN || offsets.inet_cork_gso_size == OFFSET_UNKNOWN {
+        return Ok(());
+    }
+    let skb: *const u8 = ctx.arg(0).ok_or(1i64)?;
+    let cork: *const u8 = ctx.arg(2).ok_or(1i64)?;
+    let (len, gso_size) = unsafe {
+        (
+            bpf_probe_read_kernel(skb.add(offsets.skb_len as usize) as *const u32)?,
+            bpf_probe_read_kernel(cork.add(offsets.inet_cork_gso_size as usize) as *const u16)?,
+        )
+    };
+    // skb->data is at the IP header here, so len is IP + UDP + payload
+    let payload = len.saturating_sub(skb_udp_headers(skb, &offsets).ok_or(1i64)?);
+    let gso_size = gso_size as u32;
+    // udp_send_skb segments only a payload over gso_size
+    let segments = if gso_size > 0 && payload > gso_size {
+        payload.div_ceil(gso_size) as u64
+    } else {
+        1
+    };
+    if let Some(counters) = UDP_GSO.get_ptr_mut(0) {
+        unsafe {
+            (*counters).skbs += 1;
+            (*counters).gso_skbs += (gso_size > 0) as u64;
+            (*counters).segments += segments;
+        }
+    }
+    Ok(())
+}
+
+/// Tracepoint for softirq entry - track when network interrupts start
+#[tracepoint]
+pub fn softirq_entry(ctx: TracePointContext) -> u32 {
+    match try_softirq_entry(ctx) {
+        Ok(()) => 0,
+        Err(_) => 1,
+    }
+}
+
+fn try_softirq_entry(ctx: TracePointContext) -> Result<(), i64> {
+    // `vec` is typically at offset 8 after tracepoint common fields.
+    // Keep a fallback at 16 for kernel/layout variance.
+    let vec = unsafe {
+        ctx.read_at::<u32>(8)
+            .or_else(|_| ctx.read_at::<u32>(16))
+            .unwrap_or(u32::MAX)
+    };
+    
+    // Only track NET_TX_SOFTIRQ (2) and NET_RX_SOFTIRQ (3)
+    if vec != 2 && vec != 3 {
+        return Ok(());
+    }
+
+    let timestamp = unsafe { bpf_ktime_get_ns() };
+    
+    unsafe {
+        if let Some(start_ptr) = SOFTIRQ_START.get_ptr_mut(vec) {
+            start_ptr.write(timestamp);
+        }
+        if vec == NET_RX_SOFTIRQ {
+            if let Some(round) = NET_RX_ROUND.get_ptr_mut(0) {
+                round.write(RxRound {
+                    start_ns: timestamp,
+                    packets: 0,
+                    state: RX_ROUND_RUNNING,
+                    polls: 0,
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Tracepoint for softirq exit - calculate duration and send event
+#[tracepoint]
+pub fn softirq_exit(ctx: TracePointContext) -> u32 {
+    match try_softirq_exit(ctx) {
+        Ok(()) => 0,
+        Err(_) => 1,
+    }
+}
+
+fn try_softirq_exit(ctx: TracePointContext) -> Result<(), i64> {
+    // Every vector, the timer one runs on any CPU that isn't idle
+    flush_stale_drops(&ctx);
+
+    // `vec` is typically at offset 8 after tracepoint common fields.
+    // Keep a fallback at 16 for kernel/layout variance.
+    let vec = unsafe {
+        ctx.read_at::<u32>(8)
+            .or_else(|_| ctx.read_at::<u32>(16))
+            .unwrap_or(u32::MAX)
+    };
+    
+    if vec != 2 && vec != 3 {
+        return Ok(());
+    }
+
+    let cpu = unsafe { bpf_get_smp_processor_id() };
+    let exit_time = unsafe { bpf_ktime_get_ns() };
 
-/// Per-CPU sampling state for send operations
-/// Note: Could be made per-socket by hashing socket pointer, but per-CPU is simpler
-#[map]
-static SEND_SAMPLE_STATE: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+    if vec == NET_RX_SOFTIRQ {
+        // napi_poll outside the softirq (busy polling, threaded NAPI) isn't ours
+        if let Some(round) = NET_RX_ROUND.get_ptr_mut(0) {
+            unsafe { (*round).state = RX_ROUND_IDLE };
+        }
+    }
+    
+    let Some(start_ptr) = SOFTIRQ_START.get_ptr_mut(vec) else {
+        return Ok(());
+    };
+    // Cleared on every exit, so an exit whose entry we missed can't pair with
+    // a start left over from an earlier round
+    let start_time = unsafe { start_ptr.replace(0) };
+    let duration = match exit_time.checked_sub(start_time) {
+        Some(duration) if start_time > 0 && duration <= SOFTIRQ_MAX_DURATION_NS => duration,
+        _ => {
+            if let Some(count) = SOFTIRQ_DISCARDED.get_ptr_mut(0) {
+                unsafe { *count += 1 };
+            }
+            return Ok(());
+        }
+    };
 
+    // Rounds and discards above are still tracked, only the event goes. The
+    // polls go with it, there'd be no round to put them in
+    if event_masked(EVENT_SOFTIRQ_EXIT) {
+        return Ok(());
+    }
+    if vec == NET_RX_SOFTIRQ {
+        send_napi_polls(&ctx, duration);
+    }
+
+    let event = CongestionEvent {
+        timestamp_ns: exit_time,
+        event_type: EVENT_SOFTIRQ_EXIT,
+        cpu_id: cpu,
+        data: EventData {
+            softirq: SoftirqData {
+                vec_nr: vec,
+                duration_ns: duration,
+            },
+        },
+    };
 
-// Helper Functions
-#[inline(always)]
-fn should_sample_send() -> bool {
-    // Sample every 100th send to reduce overhead
-    // Adjust this ratio based on observed CPU overhead
     unsafe {
-        if let Some(counter) = SEND_SAMPLE_STATE.get_ptr_mut(0) {
-            let count = counter.read();
-            counter.write(count.wrapping_add(1));
-            return count % 100 == 0;
-        }
+        EVENTS.output(&ctx, &event, (BPF_F_CURRENT_CPU as u64).try_into().unwrap());
     }
-    false
+
+    Ok(())
 }
 
-// QUIC-Relevant Probes
-/// Probe UDP sends - CRITICAL for QUIC (which runs over UDP)
+/// Probe UDP receive enqueue - samples rmem occupancy and remembers the socket
+/// for the return probe below
 #[kprobe]
-pub fn udp_sendmsg(ctx: ProbeContext) -> u32 {
-    match try_udp_sendmsg(ctx) {
+pub fn udp_enqueue_schedule_skb(ctx: ProbeContext) -> u32 {
+    match try_udp_enqueue_schedule_skb(ctx) {
         Ok(()) => 0,
         Err(_) => 1,
     }
 }
 
-fn try_udp_sendmsg(ctx: ProbeContext) -> Result<(), i64> {
-    if !should_sample_send() {
-        return Ok(());
+fn try_udp_enqueue_schedule_skb(ctx: ProbeContext) -> Result<(), i64> {
+    let sk: *const u8 = ctx.arg(0).ok_or(1i64)?;
+
+    unsafe {
+        if let Some(slot) = UDP_RCV_SK.get_ptr_mut(0) {
+            slot.write(sk as u64);
+        }
     }
 
-    let sk: *const core::ffi::c_void = unsafe { ctx.arg(0).ok_or(1i64)? };
-    let len: usize = unsafe { ctx.arg(2).ok_or(1i64)? };
+    let id = socket_id(sk);
+    let samplers = sample_occupancy(&RCV_SAMPLE_STATE, id);
+    if samplers == 0 {
+        return Ok(());
+    }
 
+    // Without the offsets the sample carries no occupancy and isn't sent, the
+    // CE check below still runs
+    let rcv = read_rcv_state(sk, id, samplers);
     let event = CongestionEvent {
         timestamp_ns: unsafe { bpf_ktime_get_ns() },
-        event_type: EVENT_UDP_SEND,
+        event_type: EVENT_SOCKET_RCV_STATE,
         cpu_id: unsafe { bpf_get_smp_processor_id() },
-        data: EventData {
-            sendmsg: SendMsgData {
-                bytes: len as u64,
-                is_tcp: 0,
-                socket_id: sk as u64,
-            },
-        },
+        data: EventData { rcv },
     };
 
-    unsafe {
+    if rcv.rcvbuf != 0 && plausible_rcv_state(&rcv) && !event_masked(EVENT_SOCKET_RCV_STATE) {
+        EVENTS.output(&ctx, &event, (BPF_F_CURRENT_CPU as u64).try_into().unwrap());
+    }
+
+    // CE marks ride on the same sample; the IP header read doesn't depend on
+    // the sock offsets, so they're kept either way
+    let skb: *const u8 = ctx.arg(1).ok_or(1i64)?;
+    if read_skb_ecn(skb) == Some(ECN_CE) {
+        let event = CongestionEvent {
+            event_type: EVENT_UDP_RCV_CE,
+            ..event
+        };
         EVENTS.output(&ctx, &event, (BPF_F_CURRENT_CPU as u64).try_into().unwrap());
     }
 
     Ok(())
 }
 
-/// Tracepoint for packet drops - detects network congestion
-#[tracepoint]
-pub fn skb_kfree(ctx: TracePointContext) -> u32 {
-    match try_skb_kfree(ctx) {
+/// Return probe for UDP receive enqueue - a negative return means the datagram
+/// was dropped because the receiver's rcvbuf is full (RcvbufErrors)
+#[kretprobe]
+pub fn udp_enqueue_schedule_skb_ret(ctx: RetProbeContext) -> u32 {
+    match try_udp_enqueue_schedule_skb_ret(ctx) {
         Ok(()) => 0,
         Err(_) => 1,
     }
 }
 
-fn try_skb_kfree(ctx: TracePointContext) -> Result<(), i64> {
-    // TODO: Could read drop reason from args->reason to distinguish qdisc drops
-    // from other types of drops (e.g., invalid packets, routing failures)
-    
+fn try_udp_enqueue_schedule_skb_ret(ctx: RetProbeContext) -> Result<(), i64> {
+    let rc: i32 = ctx.ret().ok_or(1i64)?;
+    if rc >= 0 {
+        return Ok(());
+    }
+
+    let sk = unsafe {
+        match UDP_RCV_SK.get_ptr(0) {
+            Some(slot) => slot.read() as *const u8,
+            None => return Ok(()),
+        }
+    };
+    if sk.is_null() {
+        return Ok(());
+    }
+
+    // Drops are rare and the whole point of this probe, so never sampled
+    let id = socket_id(sk);
     let event = CongestionEvent {
         timestamp_ns: unsafe { bpf_ktime_get_ns() },
-        event_type: EVENT_QDISC_DROP,
+        event_type: EVENT_UDP_RCV_DROP,
         cpu_id: unsafe { bpf_get_smp_processor_id() },
         data: EventData {
-            qdisc: QdiscData {
-                dropped: 1,
-                backlog_bytes: 0,
-                backlog_packets: 0,
+            rcv: read_rcv_state(sk, id, socket_sampler(id)),
+        },
+    };
+
+    EVENTS.output(&ctx, &event, (BPF_F_CURRENT_CPU as u64).try_into().unwrap());
+
+    Ok(())
+}
+/// Probe TCP send path - samples cwnd/ssthresh/pacing rate of coexisting TCP
+/// flows so the governor knows what the kernel's own CC thinks of the path
+#[kprobe]
+pub fn tcp_rate_check_app_limited(ctx: ProbeContext) -> u32 {
+    match try_tcp_rate_check_app_limited(ctx) {
+        Ok(()) => 0,
+        Err(_) => 1,
+    }
+}
+
+fn try_tcp_rate_check_app_limited(ctx: ProbeContext) -> Result<(), i64> {
+    let offsets = kernel_offsets();
+    if offsets.tcp_snd_cwnd == OFFSET_UNKNOWN
+        || offsets.tcp_snd_ssthresh == OFFSET_UNKNOWN
+        || offsets.sk_pacing_rate == OFFSET_UNKNOWN
+    {
+        return Ok(());
+    }
+
+    if event_masked(EVENT_TCP_STATE) {
+        return Ok(());
+    }
+
+    let sk: *const u8 = ctx.arg(0).ok_or(1i64)?;
+    let id = socket_id(sk);
+    let samplers = sample_occupancy(&TCP_SAMPLE_STATE, id);
+    if samplers == 0 {
+        return Ok(());
+    }
+    let (snd_cwnd, snd_ssthresh, pacing_rate) = unsafe {
+        (
+            bpf_probe_read_kernel(sk.add(offsets.tcp_snd_cwnd as usize) as *const u32)?,
+            bpf_probe_read_kernel(sk.add(offsets.tcp_snd_ssthresh as usize) as *const u32)?,
+            bpf_probe_read_kernel(sk.add(offsets.sk_pacing_rate as usize) as *const u64)?,
+        )
+    };
+
+    let event = CongestionEvent {
+        timestamp_ns: unsafe { bpf_ktime_get_ns() },
+        event_type: EVENT_TCP_STATE,
+        cpu_id: unsafe { bpf_get_smp_processor_id() },
+        data: EventData {
+            tcp: TcpStateData {
+                snd_cwnd,
+                snd_ssthresh,
+                pacing_rate,
+                socket_id: id,
+                samplers,
             },
         },
     };
 
-    unsafe {
-        EVENTS.output(&ctx, &event, (BPF_F_CURRENT_CPU as u64).try_into().unwrap());
+    EVENTS.output(&ctx, &event, (BPF_F_CURRENT_CPU as u64).try_into().unwrap());
+
+    if let Some(socket) = read_send_state(sk, offsets.sk_wmem_queued, IPPROTO_TCP, samplers) {
+        output_send_state(&ctx, socket);
     }
 
     Ok(())
 }
 
-/// Tracepoint for qdisc queue events - leading indicator of congestion
-/// This replaces the complex tcp_write_xmit approach for qdisc visibility
-#[tracepoint]
-pub fn net_dev_queue(ctx: TracePointContext) -> u32 {
-    match try_net_dev_queue(ctx) {
+/// Kprobe on tcp_tsq_handler - runs once for every socket TSQ throttled (too
+/// many of its bytes queued below the socket) when the qdisc/NIC has drained
+/// enough to let it send again, from the TSQ tasklet or tcp_release_cb.
+/// Egress saturation on this host without any loss. Counted, not emitted
+#[kprobe]
+pub fn tcp_tsq_handler(_ctx: ProbeContext) -> u32 {
+    if let Some(count) = TSQ_THROTTLES.get_ptr_mut(0) {
+        unsafe { *count += 1 };
+    }
+    0
+}
+
+/// Return probe for inet_csk_accept - a non-null return is a connection the
+/// listener handed to accept(). Counted, not emitted
+#[kretprobe]
+pub fn inet_csk_accept_ret(ctx: RetProbeContext) -> u32 {
+    let sk: u64 = match ctx.ret() {
+        Some(sk) => sk,
+        None => return 1,
+    };
+    if sk == 0 {
+        return 0;
+    }
+    if let Some(count) = TCP_ACCEPTS.get_ptr_mut(0) {
+        unsafe { *count += 1 };
+    }
+    0
+}
+
+/// Kprobe on tcp_enter_cwr - a coexisting TCP flow reducing its window because
+/// the peer echoed CE (or the local qdisc returned NET_XMIT_CN). Not sampled:
+/// happens at most once per RTT per flow
+#[kprobe]
+pub fn tcp_enter_cwr(ctx: ProbeContext) -> u32 {
+    match try_tcp_window_event(ctx, EVENT_TCP_CWR) {
         Ok(()) => 0,
         Err(_) => 1,
     }
 }
 
-fn try_net_dev_queue(ctx: TracePointContext) -> Result<(), i64> {
-    // Tracepoint format (from /sys/kernel/debug/tracing/events/net/net_dev_queue/format):
-    // field:void * skbaddr;
-    // field:unsigned int len;
-    // field:__data_loc char[] name;
-    
-    // Read packet length (offset may vary - typically at offset 16 or 24)
-    let len = unsafe { ctx.read_at::<u32>(16).unwrap_or(0) };
-    
-    // We can sample this too if it generates too many events
-    // For now, capture all queue events since they're already relatively infrequent
-    
+/// Kprobe on tcp_retransmit_timer - the retransmission timeout fired: nothing
+/// was acked for a whole RTO and the flow drops back to one segment. Runs it
+/// again on every backoff, so a stalled flow shows up as several
+#[kprobe]
+pub fn tcp_retransmit_timer(ctx: ProbeContext) -> u32 {
+    match try_tcp_window_event(ctx, EVENT_TCP_RTO) {
+        Ok(()) => 0,
+        Err(_) => 1,
+    }
+}
+
+/// Kprobe on tcp_enter_recovery - fast recovery after duplicate acks/SACKs, the
+/// mild loss response: the window is roughly halved and the flow keeps sending.
+/// Static in tcp_input.c, so only attachable where it wasn't inlined
+#[kprobe]
+pub fn tcp_enter_recovery(ctx: ProbeContext) -> u32 {
+    match try_tcp_window_event(ctx, EVENT_TCP_RECOVERY) {
+        Ok(()) => 0,
+        Err(_) => 1,
+    }
+}
+
+// Window reductions, all fired with the sock as the first argument
+fn try_tcp_window_event(ctx: ProbeContext, event_type: u32) -> Result<(), i64> {
+    let sk: *const u8 = ctx.arg(0).ok_or(1i64)?;
+    let offsets = kernel_offsets();
+    let id = socket_id(sk);
+
+    // cwnd/ssthresh before the cut when we know where they are, zeros otherwise
+    let read_u32 = |offset: u32| {
+        if offset == OFFSET_UNKNOWN {
+            0
+        } else {
+            unsafe { bpf_probe_read_kernel(sk.add(offset as usize) as *const u32).unwrap_or(0) }
+        }
+    };
+
     let event = CongestionEvent {
         timestamp_ns: unsafe { bpf_ktime_get_ns() },
-        event_type: EVENT_NET_DEV_QUEUE,
+        event_type,
         cpu_id: unsafe { bpf_get_smp_processor_id() },
         data: EventData {
-            qdisc: QdiscData {
-                dropped: 0,
-                backlog_bytes: len,
-                backlog_packets: 1,
+            tcp: TcpStateData {
+                snd_cwnd: read_u32(offsets.tcp_snd_cwnd),
+                snd_ssthresh: read_u32(offsets.tcp_snd_ssthresh),
+                pacing_rate: 0,
+                socket_id: id,
+                samplers: socket_sampler(id),
             },
         },
     };
 
-    unsafe {
-        EVENTS.output(&ctx, &event, (BPF_F_CURRENT_CPU as u64).try_into().unwrap());
-    }
+    EVENTS.output(&ctx, &event, (BPF_F_CURRENT_CPU as u64).try_into().unwrap());
 
     Ok(())
 }
 
-/// Tracepoint for softirq entry - track when network interrupts start
+/// Tracepoint sock:inet_sock_set_state - TCP sockets opening and closing
 #[tracepoint]
-pub fn softirq_entry(ctx: TracePointContext) -> u32 {
-    match try_softirq_entry(ctx) {
+pub fn inet_sock_set_state(ctx: TracePointContext) -> u32 {
+    match try_inet_sock_set_state(ctx) {
         Ok(()) => 0,
         Err(_) => 1,
     }
 }
 
-fn try_softirq_entry(ctx: TracePointContext) -> Result<(), i64> {
-    // `vec` is typically at offset 8 after tracepoint common fields.
-    // Keep a fallback at 16 for kernel/layout variance.
-    let vec = unsafe {
-        ctx.read_at::<u32>(8)
-            .or_else(|_| ctx.read_at::<u32>(16))
-            .unwrap_or(u32::MAX)
+fn try_inet_sock_set_state(ctx: TracePointContext) -> Result<(), i64> {
+    // Format: common fields, skaddr (8), oldstate (16), newstate (20), sport,
+    // dport, family (28), protocol (30), ...
+    let (sk, oldstate, newstate, protocol) = unsafe {
+        (
+            ctx.read_at::<u64>(8)? as *const u8,
+            ctx.read_at::<u32>(16)?,
+            ctx.read_at::<u32>(20)?,
+            ctx.read_at::<u16>(30)?,
+        )
     };
-    
-    // Only track NET_TX_SOFTIRQ (2) and NET_RX_SOFTIRQ (3)
-    if vec != 2 && vec != 3 {
+
+    const IPPROTO_TCP: u16 = 6;
+    if protocol != IPPROTO_TCP {
         return Ok(());
     }
-
-    let timestamp = unsafe { bpf_ktime_get_ns() };
-    
-    unsafe {
-        if let Some(start_ptr) = SOFTIRQ_START.get_ptr_mut(vec) {
-            start_ptr.write(timestamp);
-        }
+    // Only lifetime boundaries and the handshake completing, not every
+    // intermediate state
+    let established =
+        newstate == TCP_ESTABLISHED && (oldstate == TCP_SYN_SENT || oldstate == TCP_SYN_RECV);
+    if !established
+        && newstate != TCP_CLOSE
+        && newstate != TCP_SYN_SENT
+        && newstate != TCP_SYN_RECV
+    {
+        return Ok(());
     }
 
+    let event = CongestionEvent {
+        timestamp_ns: unsafe { bpf_ktime_get_ns() },
+        event_type: EVENT_SOCKET_LIFECYCLE,
+        cpu_id: unsafe { bpf_get_smp_processor_id() },
+        data: EventData {
+            lifecycle: SocketLifecycleData {
+                socket_id: socket_id(sk),
+                oldstate,
+                newstate,
+            },
+        },
+    };
+
+    EVENTS.output(&ctx, &event, (BPF_F_CURRENT_CPU as u64).try_into().unwrap());
+
     Ok(())
 }
 
-/// Tracepoint for softirq exit - calculate duration and send event
+/// Tracepoint napi:napi_poll - fires after every NAPI poll. Inside net_rx_action
+/// this replays its budget check: once the round's packets reach netdev_budget, or
+/// netdev_budget_usecs have passed, the kernel bumps softnet_data.time_squeeze and
+/// defers the rest to the next softirq, which is what we report
 #[tracepoint]
-pub fn softirq_exit(ctx: TracePointContext) -> u32 {
-    match try_softirq_exit(ctx) {
+pub fn napi_poll(ctx: TracePointContext) -> u32 {
+    match try_napi_poll(ctx) {
         Ok(()) => 0,
         Err(_) => 1,
     }
 }
 
-fn try_softirq_exit(ctx: TracePointContext) -> Result<(), i64> {
-    // `vec` is typically at offset 8 after tracepoint common fields.
-    // Keep a fallback at 16 for kernel/layout variance.
-    let vec = unsafe {
-        ctx.read_at::<u32>(8)
-            .or_else(|_| ctx.read_at::<u32>(16))
-            .unwrap_or(u32::MAX)
+fn try_napi_poll(ctx: TracePointContext) -> Result<(), i64> {
+    let Some(round) = NET_RX_ROUND.get_ptr_mut(0) else {
+        return Ok(());
     };
-    
-    if vec != 2 && vec != 3 {
+    if unsafe { (*round).state } != RX_ROUND_RUNNING {
         return Ok(());
     }
 
-    let cpu = unsafe { bpf_get_smp_processor_id() };
-    let exit_time = unsafe { bpf_ktime_get_ns() };
-    
-    let duration = unsafe {
-        if let Some(start_ptr) = SOFTIRQ_START.get_ptr(vec) {
-            let start_time = start_ptr.read();
-            if start_time > 0 {
-                exit_time - start_time
-            } else {
-                return Ok(());
-            }
-        } else {
-            return Ok(());
-        }
+    // Format: common fields, napi (8), dev_name (16, __data_loc), work (20), budget (24)
+    let work = unsafe { ctx.read_at::<i32>(20)? };
+    let now = unsafe { bpf_ktime_get_ns() };
+    keep_napi_poll(&ctx, round, work, now);
+
+    let budget = match RX_BUDGET.get(0) {
+        Some(budget) if budget.packets > 0 => *budget,
+        _ => return Ok(()),
+    };
+
+    let (packets, elapsed_ns) = unsafe {
+        (*round).packets += work.max(0) as u32;
+        ((*round).packets, now - (*round).start_ns)
     };
+    // The kernel's limit is in jiffies, so it's only this exact at HZ=1000
+    let budget_exhausted = packets >= budget.packets;
+    if !budget_exhausted && elapsed_ns < budget.usecs as u64 * 1000 {
+        return Ok(());
+    }
+    unsafe { (*round).state = RX_ROUND_SQUEEZED };
 
     let event = CongestionEvent {
-        timestamp_ns: exit_time,
-        event_type: EVENT_SOFTIRQ_EXIT,
-        cpu_id: cpu,
+        timestamp_ns: now,
+        event_type: EVENT_RX_TIME_SQUEEZE,
+        cpu_id: unsafe { bpf_get_smp_processor_id() },
         data: EventData {
-            softirq: SoftirqData {
-                vec_nr: vec,
-                duration_ns: duration,
+            rx_squeeze: RxSqueezeData {
+                elapsed_ns,
+                packets,
+                budget_exhausted: budget_exhausted as u32,
             },
         },
     };
 
+    EVENTS.output(&ctx, &event, (BPF_F_CURRENT_CPU as u64).try_into().unwrap());
+
+    Ok(())
+}
+
+/// Keep a poll in NAPI_POLLS with NAPI_SAMPLE_NS set, the round's first
+/// NAPI_POLLS_PER_ROUND only
+fn keep_napi_poll(ctx: &TracePointContext, round: *mut RxRound, work: i32, now: u64) {
+    if unsafe { core::ptr::read_volatile(&NAPI_SAMPLE_NS) } == 0 {
+        return;
+    }
+    let index = unsafe { (*round).polls };
+    if index >= NAPI_POLLS_PER_ROUND {
+        return;
+    }
+    let Some(slot) = NAPI_POLLS.get_ptr_mut(index) else {
+        return;
+    };
+    let (Ok(name_loc), Ok(budget)) = (unsafe { ctx.read_at::<u32>(16) }, unsafe {
+        ctx.read_at::<i32>(24)
+    }) else {
+        return;
+    };
+    let mut poll = NapiPollData {
+        work: work.max(0) as u32,
+        budget: budget.max(0) as u32,
+        dev_name: [0; 16],
+    };
+    // __data_loc: offset from the record start in the low 16 bits
+    let name = unsafe { (ctx.as_ptr() as *const u8).add((name_loc & 0xFFFF) as usize) };
+    // A failed read leaves the name empty, the poll still counts
+    let _ = unsafe { bpf_probe_read_kernel_str_bytes(name, &mut poll.dev_name) };
     unsafe {
-        EVENTS.output(&ctx, &event, (BPF_F_CURRENT_CPU as u64).try_into().unwrap());
+        slot.write(CongestionEvent {
+            timestamp_ns: now,
+            event_type: EVENT_NAPI_POLL,
+            cpu_id: bpf_get_smp_processor_id(),
+            data: EventData { napi: poll },
+        });
+        (*round).polls = index + 1;
     }
+}
 
-    Ok(())
+/// Send the polls keep_napi_poll kept for this round if it ran NAPI_SAMPLE_NS
+/// or longer, ahead of its exit. Userspace puts them in the round by CPU and
+/// time, see napi.rs
+fn send_napi_polls(ctx: &TracePointContext, duration: u64) {
+    let Some(round) = NET_RX_ROUND.get_ptr_mut(0) else {
+        return;
+    };
+    let polls = unsafe { core::mem::replace(&mut (*round).polls, 0) };
+    let threshold = unsafe { core::ptr::read_volatile(&NAPI_SAMPLE_NS) };
+    if threshold == 0 || duration < threshold {
+        return;
+    }
+    for index in 0..NAPI_POLLS_PER_ROUND {
+        if index >= polls {
+            break;
+        }
+        if let Some(poll) = NAPI_POLLS.get(index) {
+            EVENTS.output(ctx, poll, (BPF_F_CURRENT_CPU as u64).try_into().unwrap());
+        }
+    }
 }
 
+/// tc egress (clsact or TCX) - exact per-interface totals, every packet, no sampling.
+/// Only counts; TC_ACT_PIPE hands the packet on to whatever else is attached
+#[classifier]
+pub fn tc_egress(ctx: TcContext) -> i32 {
+    let (ifindex, gso_segs) = unsafe { ((*ctx.skb.skb).ifindex, (*ctx.skb.skb).gso_segs) };
+    let bytes = ctx.len() as u64;
+    // A GSO skb leaves as gso_segs packets; its len counts the headers once
+    let packets = if gso_segs > 1 { gso_segs as u64 } else { 1 };
+
+    match EGRESS_COUNTERS.get_ptr_mut(&ifindex) {
+        // Per-CPU slot, nothing else writes it while we run
+        Some(count) => unsafe {
+            (*count).bytes += bytes;
+            (*count).packets += packets;
+        },
+        None => {
+            let _ = EGRESS_COUNTERS.insert(&ifindex, &EgressCount { bytes, packets }, 0);
+        }
+    }
+
+    TC_ACT_PIPE as i32
+}
 
 // tcp_sendmsg - REMOVED: QUIC uses UDP, not TCP
 // tcp_write_xmit - REMOVED: TCP-specific socket buffer tracking, not useful for QUIC
@@ -18,6 +18,11 @@ pub union EventData {
     pub qdisc: QdiscData,
     pub socket: SocketData,
     pub softirq: SoftirqData,
+    pub rcv: RcvSocketData,
+    pub tcp: TcpStateData,
+    pub lifecycle: SocketLifecycleData,
+    pub rx_squeeze: RxSqueezeData,
+    pub napi: NapiPollData,
 }
 
 #[repr(C)]
@@ -25,23 +30,61 @@ pub union EventData {
 pub struct SendMsgData {
     pub bytes: u64,
     pub is_tcp: u32,
+    /// 1 when the destination is a loopback address
+    pub loopback: u32,
     pub socket_id: u64,
+    /// Inode of the socket's network namespace, sock_net(sk)->ns.inum. 0 when
+    /// the offsets are unknown
+    pub netns: u32,
+    /// DSCP of the socket, inet_sock.tos >> 2, and sock.sk_priority, the
+    /// traffic class userspace groups sends by. TRAFFIC_CLASS_UNKNOWN when the
+    /// offsets are unknown
+    pub dscp: u32,
+    pub priority: u32,
+    /// Which send samplers admitted this send, SAMPLER_PRIMARY and/or
+    /// SAMPLER_COMPARE, plus SAMPLER_SOCKET from a socket in the per-socket
+    /// sample
+    pub samplers: u32,
+    /// Destination address and port packed into one value, sendto's address
+    /// or the connected peer. 0 when flow tracking is off or it's unreadable
+    pub flow_hash: u64,
 }
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct QdiscData {
+    /// Drops this event stands for: consecutive kfree_skb drops on a CPU with
+    /// the same reason and device are coalesced into one event
     pub dropped: u32,
+    /// For EVENT_QDISC_DROP, where there's no backlog to report: ns from the
+    /// first coalesced drop to the last, saturating
     pub backlog_bytes: u32,
     pub backlog_packets: u32,
+    /// kfree_skb's enum skb_drop_reason, numbering is per kernel. 0 when unread
+    pub reason: u32,
 }
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct SocketData {
+    /// Send-side occupancy: sk_wmem_alloc for UDP (bytes below the socket not
+    /// yet freed by TX completion), sk_wmem_queued for TCP
     pub wmem_queued: u32,
     pub sndbuf: u32,
     pub socket_id: u64,
+    /// IPPROTO_UDP or IPPROTO_TCP
+    pub protocol: u32,
+    /// As in SendMsgData
+    pub netns: u32,
+    pub dscp: u32,
+    pub priority: u32,
+    /// sock.sk_pacing_rate and sk_max_pacing_rate, bytes/sec. ~0UL until the
+    /// stack or SO_MAX_PACING_RATE sets one, 0 when the offsets aren't in
+    /// kernel BTF
+    pub pacing_rate: u64,
+    pub max_pacing_rate: u64,
+    /// As in SendMsgData, those of the send that carried it
+    pub samplers: u32,
 }
 
 #[repr(C)]
@@ -51,13 +94,333 @@ pub struct SoftirqData {
     pub duration_ns: u64,
 }
 
+/// Receive-side socket state, used by both the sampled rmem probe and
+/// the UDP receive drop event (occupancy at the moment of the drop)
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct RcvSocketData {
+    pub rmem_alloc: u32,
+    pub rcvbuf: u32,
+    pub socket_id: u64,
+    /// SAMPLER_PRIMARY on receive samples, SAMPLER_SOCKET from a socket in the
+    /// per-socket sample; drops aren't sampled and carry only the latter
+    pub samplers: u32,
+}
+
+/// TCP socket opened (-> SYN_SENT / SYN_RECV) or closed (-> CLOSE), from
+/// sock:inet_sock_set_state. Lets userspace retire per-socket state exactly
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SocketLifecycleData {
+    pub socket_id: u64,
+    pub oldstate: u32,
+    pub newstate: u32,
+}
+
+/// net_rx_action stopped with work left, what softnet_stat counts as time_squeeze
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct RxSqueezeData {
+    /// Since the NET_RX softirq started
+    pub elapsed_ns: u64,
+    /// Packets polled in this round
+    pub packets: u32,
+    /// 1 when the packet budget ran out, 0 when the time limit did
+    pub budget_exhausted: u32,
+}
+
+/// One NAPI poll of a long NET_RX softirq, from napi:napi_poll. Sent with the
+/// round's softirq_exit, timestamped at the poll's end
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct NapiPollData {
+    /// Packets the poll processed, and the most it could (the NAPI weight)
+    pub work: u32,
+    pub budget: u32,
+    /// The polled net_device's name, NUL-padded
+    pub dev_name: [u8; 16],
+}
+
+/// TCP congestion-control state of a coexisting flow, sampled on the send path
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct TcpStateData {
+    pub snd_cwnd: u32,
+    pub snd_ssthresh: u32,
+    /// sk_pacing_rate, bytes/sec
+    pub pacing_rate: u64,
+    pub socket_id: u64,
+    /// As in RcvSocketData: SAMPLER_PRIMARY on the sampled EVENT_TCP_STATE only
+    pub samplers: u32,
+}
+
+/// Struct field offsets resolved by userspace from kernel BTF and written into
+/// the KERNEL_OFFSETS global before load (aya-ebpf has no Rust-side CO-RE).
+/// OFFSET_UNKNOWN means the field couldn't be resolved; probes needing it stay quiet.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct KernelOffsets {
+    pub tcp_snd_cwnd: u32,
+    pub tcp_snd_ssthresh: u32,
+    pub sk_pacing_rate: u32,
+    /// sk_buff.head and sk_buff.network_header, to find the IP header for ECN bits
+    pub skb_head: u32,
+    pub skb_network_header: u32,
+    /// sock.__sk_common.skc_cookie, the same value bpf_get_socket_cookie() returns
+    pub sk_cookie: u32,
+    /// reason in the kfree_skb tracepoint record (trace_event_raw_kfree_skb)
+    pub kfree_skb_reason: u32,
+    /// sock.sk_wmem_alloc (refcount_t, the counter is its first member),
+    /// sock.sk_wmem_queued and sock.sk_sndbuf for send-side buffer pressure
+    pub sk_wmem_alloc: u32,
+    pub sk_wmem_queued: u32,
+    pub sk_sndbuf: u32,
+    /// sk_buff.sk, mac_header and transport_header, to attribute transmitted
+    /// skbs to registered sockets and take their headers off
+    pub skb_sk: u32,
+    pub skb_mac_header: u32,
+    pub skb_transport_header: u32,
+    /// sk_buff.dev, netdev_queue.dev and net_device.ifindex, to put TX queue
+    /// stalls on an interface
+    pub skb_dev: u32,
+    pub netdev_queue_dev: u32,
+    pub net_device_ifindex: u32,
+    /// sock.__sk_common.skc_family, skc_daddr and skc_v6_daddr, the peer of a
+    /// connected socket, to tell loopback sends from real egress
+    pub sk_family: u32,
+    pub sk_daddr: u32,
+    pub sk_v6_daddr: u32,
+    /// sock.__sk_common.skc_net.net, net.ns.inum and net_device.nd_net.net, the
+    /// network namespace of a socket or device
+    pub sk_net: u32,
+    pub net_ns_inum: u32,
+    pub net_device_nd_net: u32,
+    /// inet_sock.tos (inet_sock starts with its sock) and sock.sk_priority,
+    /// the socket's traffic class
+    pub inet_tos: u32,
+    pub sk_priority: u32,
+    /// sock.sk_max_pacing_rate, next to sk_pacing_rate above
+    pub sk_max_pacing_rate: u32,
+    /// sk_buff.len and inet_cork.gso_size, the wire packets a UDP skb leaves as
+    pub skb_len: u32,
+    pub inet_cork_gso_size: u32,
+    /// sock.sk_backlog.rmem_alloc (atomic_t, sk_rmem_alloc in the kernel) and
+    /// sock.sk_rcvbuf for receive-side buffer pressure
+    pub sk_rmem_alloc: u32,
+    pub sk_rcvbuf: u32,
+    /// sock.__sk_common.skc_dport, the connected peer's port for flow hashes
+    pub sk_dport: u32,
+}
+
+pub const OFFSET_UNKNOWN: u32 = u32::MAX;
+pub const TRAFFIC_CLASS_UNKNOWN: u32 = u32::MAX;
+
+/// SendMsgData::samplers bits: the 1-in-100 sampler every signal is built
+/// from, and the one run next to it with COMPARE_SAMPLE_RATIO
+pub const SAMPLER_PRIMARY: u32 = 1;
+pub const SAMPLER_COMPARE: u32 = 2;
+/// The event's socket hashes into the per-socket sample (SOCKET_SAMPLE_THRESHOLD):
+/// every sampled event of that socket is sent, not 1 in 100
+pub const SAMPLER_SOCKET: u32 = 4;
+/// The event's socket is in TRACED_SOCKETS: sent whether or not a sampler
+/// admitted it. Without SAMPLER_PRIMARY it's for the trace only
+pub const SAMPLER_TRACE: u32 = 8;
+
+/// net.core.netdev_budget and netdev_budget_usecs, the value of the RX_BUDGET
+/// map, so the napi_poll probe knows when net_rx_action gives up
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RxBudget {
+    pub packets: u32,
+    pub usecs: u32,
+}
+
+/// Slots of the DEGRADATION map, written by userspace's overhead budget. All
+/// zero until written: every sampler at its ratio, every event sent.
+/// The occupancy samplers (receive buffer, TCP state) take 1 in 100 << this
+pub const DEGRADE_SAMPLE_SHIFT: u32 = 0;
+/// Bits of 1 << event type not to send, honored for DEGRADE_MASKABLE only
+pub const DEGRADE_EVENT_MASK: u32 = 1;
+pub const DEGRADE_SLOTS: u32 = 2;
+pub const DEGRADE_MAX_SHIFT: u32 = 7;
+pub const DEGRADE_MASKABLE: u32 = 1 << EVENT_NET_DEV_QUEUE
+    | 1 << EVENT_SOFTIRQ_EXIT
+    | 1 << EVENT_SOCKET_RCV_STATE
+    | 1 << EVENT_TCP_STATE;
+
+/// Value of EGRESS_COUNTERS, per CPU and interface
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct EgressCount {
+    pub bytes: u64,
+    pub packets: u64,
+}
+
+pub const MAX_EGRESS_INTERFACES: u32 = 64;
+
+/// Value of REGISTERED_SOCKETS, per CPU and socket cookie: what udp_sendmsg and
+/// net_dev_xmit saw of one socket the application registered
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SocketBytes {
+    /// Payload handed to udp_sendmsg
+    pub sent: u64,
+    /// Payload of the socket's skbs the driver accepted, headers taken off
+    pub transmitted: u64,
+    /// The latest sendmsg on this CPU: when, sk_wmem_alloc just before its
+    /// datagram was allocated (WMEM_UNREAD without the offset) and its length
+    pub last_send_ns: u64,
+    pub last_send_wmem: u32,
+    pub last_send_len: u32,
+}
+
+pub const WMEM_UNREAD: u32 = u32::MAX;
+
+/// Key of TXQ_STALLS: ifindex values repeat across network namespaces
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct TxqKey {
+    pub ifindex: u32,
+    /// net.ns.inum of the device's namespace, 0 when the offsets are unknown
+    pub netns: u32,
+}
+
+/// Value of TXQ_STALLS, per CPU and interface
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct TxqStall {
+    /// netif_tx_stop_queue on a running queue
+    pub stops: u64,
+    /// Stop to netif_tx_wake_queue, added on the wake
+    pub stopped_ns: u64,
+    /// Driver returned NETDEV_TX_BUSY at net_dev_xmit
+    pub xmit_busy: u64,
+}
+
+/// Value of UDP_GSO, per CPU
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct GsoCounters {
+    /// UDP skbs udp_send_skb/udp_v6_send_skb handed to IP
+    pub skbs: u64,
+    /// The ones sent with a gso_size, UDP_SEGMENT on the socket or in a cmsg
+    pub gso_skbs: u64,
+    /// Wire packets the skbs leave as: an skb over its gso_size is split into
+    /// payload / gso_size segments, rounded up
+    pub segments: u64,
+}
+
+pub const MAX_TXQ_INTERFACES: u32 = 64;
+pub const MAX_TX_QUEUES: u32 = 4096;
+pub const MAX_REGISTERED_SOCKETS: u32 = 1024;
+pub const MAX_TRACED_SOCKETS: u32 = 16;
+
+/// Value of CGROUP_SIGNALS, per CPU and cgroup id: what ran in the cgroup's
+/// tasks, outside network softirqs. Sends and socket state are the sampled ones
+/// the events carry, wmem pressures are permille sums like userspace adds them
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CgroupCounters {
+    pub send_bytes: u64,
+    pub loopback_send_bytes: u64,
+    pub sends: u64,
+    pub drops: u64,
+    pub udp_wmem_total: u64,
+    pub udp_wmem_samples: u64,
+    pub tcp_wmem_total: u64,
+    pub tcp_wmem_samples: u64,
+    /// bpf_ktime_get_ns of the latest update on this CPU
+    pub last_seen_ns: u64,
+}
+
+/// Default CGROUP_SIGNALS size, userspace resizes it before load
+pub const MAX_CGROUPS: u32 = 1024;
+
+// Layout descriptor embedded in the object as CONGESTION_SCHEMA so userspace can
+// refuse to decode events from an object built against different structs.
+// Bump SCHEMA_VERSION whenever CongestionEvent or any payload changes layout;
+// the layout hash catches the times someone forgets.
+pub const SCHEMA_MAGIC: u32 = 0x4353_4947; // "CSIG"
+pub const SCHEMA_VERSION: u32 = 26;
+
+/// Slots in SchemaDescriptor::payload_sizes, indexed by event type
+pub const MAX_EVENT_TYPES: usize = 32;
+
+#[repr(C)]
+pub struct SchemaDescriptor {
+    pub magic: u32,
+    pub version: u32,
+    /// FNV-1a of this file minus comments and whitespace, computed by build.rs
+    pub layout_hash: u64,
+    /// size_of::<CongestionEvent>()
+    pub record_size: u32,
+    /// Highest event type in use + 1
+    pub event_type_count: u32,
+    /// Payload size per event type, 0 for unused discriminators
+    pub payload_sizes: [u32; MAX_EVENT_TYPES],
+}
+
+pub const fn payload_sizes() -> [u32; MAX_EVENT_TYPES] {
+    use core::mem::size_of;
+    let mut sizes = [0u32; MAX_EVENT_TYPES];
+    sizes[EVENT_UDP_SEND as usize] = size_of::<SendMsgData>() as u32;
+    sizes[EVENT_TCP_SEND as usize] = size_of::<SendMsgData>() as u32;
+    sizes[EVENT_QDISC_DROP as usize] = size_of::<QdiscData>() as u32;
+    sizes[EVENT_SOCKET_STATE as usize] = size_of::<SocketData>() as u32;
+    sizes[EVENT_SOFTIRQ_ENTER as usize] = size_of::<SoftirqData>() as u32;
+    sizes[EVENT_SOFTIRQ_EXIT as usize] = size_of::<SoftirqData>() as u32;
+    sizes[EVENT_NET_DEV_QUEUE as usize] = size_of::<QdiscData>() as u32;
+    sizes[EVENT_UDP_RCV_DROP as usize] = size_of::<RcvSocketData>() as u32;
+    sizes[EVENT_SOCKET_RCV_STATE as usize] = size_of::<RcvSocketData>() as u32;
+    sizes[EVENT_TCP_STATE as usize] = size_of::<TcpStateData>() as u32;
+    sizes[EVENT_TCP_CWR as usize] = size_of::<TcpStateData>() as u32;
+    sizes[EVENT_UDP_RCV_CE as usize] = size_of::<RcvSocketData>() as u32;
+    sizes[EVENT_SOCKET_LIFECYCLE as usize] = size_of::<SocketLifecycleData>() as u32;
+    sizes[EVENT_RX_TIME_SQUEEZE as usize] = size_of::<RxSqueezeData>() as u32;
+    sizes[EVENT_TCP_RTO as usize] = size_of::<TcpStateData>() as u32;
+    sizes[EVENT_TCP_RECOVERY as usize] = size_of::<TcpStateData>() as u32;
+    sizes[EVENT_NAPI_POLL as usize] = size_of::<NapiPollData>() as u32;
+    sizes
+}
+
+// TCP states (include/net/tcp_states.h) the lifecycle probe cares about
+pub const TCP_ESTABLISHED: u32 = 1;
+pub const TCP_SYN_SENT: u32 = 2;
+pub const TCP_SYN_RECV: u32 = 3;
+pub const TCP_CLOSE: u32 = 7;
+
+// SocketData::protocol
+pub const IPPROTO_TCP: u32 = 6;
+pub const IPPROTO_UDP: u32 = 17;
+
 // Event type discriminators. I plan to eliminate these in favor of separate maps
 // per event type, but for now they help keep things simple. 
 
 pub const EVENT_UDP_SEND: u32 = 1;
 pub const EVENT_TCP_SEND: u32 = 2;   //deprecated: only keeping for compatibility here
 pub const EVENT_QDISC_DROP: u32 = 3;
-pub const EVENT_SOCKET_STATE: u32 = 4;  //deprecated: decided to removw tcp_write_xmit as it is useless for our case
+pub const EVENT_SOCKET_STATE: u32 = 4;
 pub const EVENT_SOFTIRQ_ENTER: u32 = 5;
 pub const EVENT_SOFTIRQ_EXIT: u32 = 6;
-pub const EVENT_NET_DEV_QUEUE: u32 = 7; 
\ No newline at end of file
+pub const EVENT_NET_DEV_QUEUE: u32 = 7;
+pub const EVENT_UDP_RCV_DROP: u32 = 8;
+pub const EVENT_SOCKET_RCV_STATE: u32 = 9;
+pub const EVENT_TCP_STATE: u32 = 10; 
+
+/// tcp_enter_cwr: window reduced on ECE feedback (or local NET_XMIT_CN), TcpStateData before the cut
+pub const EVENT_TCP_CWR: u32 = 11;
+/// Sampled UDP receive carrying CE in the IP header, RcvSocketData
+pub const EVENT_UDP_RCV_CE: u32 = 12;
+
+pub const EVENT_SOCKET_LIFECYCLE: u32 = 13;
+/// RxSqueezeData, at most once per NET_RX softirq per CPU
+pub const EVENT_RX_TIME_SQUEEZE: u32 = 14;
+
+/// tcp_retransmit_timer: an RTO fired, TcpStateData before the collapse
+pub const EVENT_TCP_RTO: u32 = 15;
+/// tcp_enter_recovery: fast recovery entered, TcpStateData before the cut
+pub const EVENT_TCP_RECOVERY: u32 = 16;
+/// NapiPollData, only for NET_RX rounds of NAPI_SAMPLE_NS or longer
+pub const EVENT_NAPI_POLL: u32 = 17;
+
+pub const EVENT_TYPE_COUNT: u32 = EVENT_NAPI_POLL + 1;
@@ -0,0 +1,277 @@
+//Repo chores that need more than one cargo invocation. Run as `cargo xtask <task>`.
+//
+//  features   cargo clippy -D warnings every supported feature combination of the
+//             userspace crate, tests included, then make sure the sensor build's
+//             dependency tree stays small
+//  runtimes   run examples/runtime_parity on tokio and on smol, and compare
+//  schema     regenerate the crate's schema/export.schema.json from its model
+//  unit-time  run the userspace crate's unit tests, once built, within
+//             UNIT_TEST_BUDGET
+
+use std::process::{exit, Command};
+use std::time::{Duration, Instant};
+
+const CRATE: &str = "ebpf_congestion_signals";
+
+// --no-default-features plus these. `collector-core` on its own is deliberately
+// missing: it has no reader and refuses to compile, and so does `async-runtime`,
+// what `async` and `smol` share. So is `quiche-example`, quiche builds
+// BoringSSL from source and that needs cmake
+const COMBINATIONS: &[&str] = &[
+    "",
+    "collector-core,blocking",
+    "collector-core,async",
+    "async,blocking",
+    "smol",
+    "smol,governor,exporters",
+    "governor",
+    "exporters",
+    "statsd",
+    "parquet",
+    "blocking,governor,exporters",
+    "async,governor,exporters",
+    "daemon",
+    "daemon,blocking,governor,exporters",
+    "grpc",
+    "daemon,grpc",
+    "pacing-adapter",
+    "systemd",
+    "daemon,systemd",
+    "schema",
+    "no-float",
+];
+
+// The sidecar sensor build and crates that must not show up in its tree
+const SENSOR: &str = "collector-core,blocking";
+// The example's features per runtime, see `runtimes`
+const RUNTIMES: &[(&str, &str)] = &[("tokio", "async,statsd"), ("smol", "smol,statsd")];
+
+// Time-based components run on a ManualClock or tokio's paused timer in the
+// unit tests, so the suite is bounded by CPU alone, 3-5 s in debug and most
+// of it the million-event allocation check. A test that sleeps or waits out
+// real time pushes it past this
+const UNIT_TEST_BUDGET: Duration = Duration::from_secs(20);
+// On top of the defaults, for the blocking pipeline's tests
+const UNIT_TEST_FEATURES: &str = "blocking";
+
+const SENSOR_FORBIDDEN: &[&str] = &[
+    "tokio",
+    "async-io",
+    "env_logger",
+    "arrow-array",
+    "arrow-schema",
+    "parquet",
+    "tonic",
+    "prost",
+    "serde_json",
+    "schemars",
+];
+
+fn main() {
+    let task = std::env::args().nth(1);
+    let ok = match task.as_deref() {
+        Some("features") => check_features() && check_sensor_tree(),
+        Some("runtimes") => check_runtimes(),
+        Some("schema") => write_schema(),
+        Some("unit-time") => check_unit_time(),
+        _ => {
+            eprintln!("usage: cargo xtask features|runtimes|schema|unit-time");
+            false
+        }
+    };
+    if !ok {
+        exit(1);
+    }
+}
+
+fn cargo() -> Command {
+    Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".into()))
+}
+
+fn clippy() -> Command {
+    let mut clippy = cargo();
+    clippy.args(["clippy", "--quiet", "--package", CRATE, "--all-targets"]);
+    clippy
+}
+
+fn check_features() -> bool {
+    let mut failed = Vec::new();
+    for features in COMBINATIONS {
+        println!(
+            "== {} --no-default-features --features [{}]",
+            CRATE, features
+        );
+        let status = clippy()
+            .args(["--no-default-features", "--features", features])
+            .args(["--", "-D", "warnings"])
+            .status();
+        if !matches!(status, Ok(s) if s.success()) {
+            failed.push(*features);
+        }
+    }
+    println!("== default features");
+    if !matches!(clippy().args(["--", "-D", "warnings"]).status(), Ok(s) if s.success()) {
+        failed.push("<default>");
+    }
+
+    if failed.is_empty() {
+        println!(
+            "all {} feature combinations pass clippy",
+            COMBINATIONS.len() + 1
+        );
+        true
+    } else {
+        eprintln!("failed: {}", failed.join(" | "));
+        false
+    }
+}
+
+fn check_sensor_tree() -> bool {
+    let output = cargo()
+        .args([
+            "tree",
+            "--package",
+            CRATE,
+            "--edges",
+            "normal",
+            "--prefix",
+            "none",
+        ])
+        .args([
+            "--format",
+            "{p}",
+            "--no-default-features",
+            "--features",
+            SENSOR,
+        ])
+        .output();
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        _ => {
+            eprintln!("cargo tree failed");
+            return false;
+        }
+    };
+
+    // "name v1.2.3 (...)" per line
+    let tree = String::from_utf8_lossy(&output.stdout);
+    let leaked: Vec<&str> = tree
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|name| SENSOR_FORBIDDEN.contains(name))
+        .collect();
+
+    if leaked.is_empty() {
+        println!(
+            "sensor build [{}] has none of {}",
+            SENSOR,
+            SENSOR_FORBIDDEN.join(", ")
+        );
+        true
+    } else {
+        eprintln!("sensor build [{}] pulls in {}", SENSOR, leaked.join(", "));
+        false
+    }
+}
+
+fn check_runtimes() -> bool {
+    let mut outputs = Vec::new();
+    for (runtime, features) in RUNTIMES {
+        println!("== runtime_parity on {} [{}]", runtime, features);
+        let output = cargo()
+            .args(["run", "--quiet", "--package", CRATE])
+            .args(["--example", "runtime_parity"])
+            .args(["--no-default-features", "--features", features])
+            .output();
+        match output {
+            Ok(output) if output.status.success() => {
+                eprint!("{}", String::from_utf8_lossy(&output.stderr));
+                outputs.push((*runtime, output.stdout));
+            }
+            Ok(output) => {
+                eprint!("{}", String::from_utf8_lossy(&output.stderr));
+                eprintln!("runtime_parity failed on {}", runtime);
+                return false;
+            }
+            Err(e) => {
+                eprintln!("cargo run failed: {}", e);
+                return false;
+            }
+        }
+    }
+
+    let (first, expected) = &outputs[0];
+    let differing: Vec<&str> = outputs[1..]
+        .iter()
+        .filter(|(_, stdout)| stdout != expected)
+        .map(|(runtime, _)| *runtime)
+        .collect();
+    if differing.is_empty() {
+        let lines = String::from_utf8_lossy(expected).lines().count();
+        println!("{} runtimes agree on {} lines", outputs.len(), lines);
+        true
+    } else {
+        eprintln!("{} differs from {}", differing.join(", "), first);
+        false
+    }
+}
+
+fn write_schema() -> bool {
+    let output = cargo()
+        .args(["run", "--quiet", "--package", CRATE, "--features", "schema"])
+        .args(["--bin", "validate", "--", "--export-schema"])
+        .output();
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            eprint!("{}", String::from_utf8_lossy(&output.stderr));
+            eprintln!("validate --export-schema failed");
+            return false;
+        }
+        Err(e) => {
+            eprintln!("cargo run failed: {}", e);
+            return false;
+        }
+    };
+    let path = format!("{}/schema/export.schema.json", CRATE);
+    match std::fs::write(&path, &output.stdout) {
+        Ok(()) => {
+            println!("wrote {}", path);
+            true
+        }
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            false
+        }
+    }
+}
+
+fn check_unit_time() -> bool {
+    let unit_tests = || {
+        let mut test = cargo();
+        test.args(["test", "--quiet", "--package", CRATE, "--lib"])
+            .args(["--features", UNIT_TEST_FEATURES]);
+        test
+    };
+    // Compiling isn't what's timed
+    if !matches!(unit_tests().arg("--no-run").status(), Ok(s) if s.success()) {
+        eprintln!("building the unit tests failed");
+        return false;
+    }
+    let start = Instant::now();
+    let passed = matches!(unit_tests().status(), Ok(s) if s.success());
+    let elapsed = start.elapsed();
+    if !passed {
+        eprintln!("unit tests failed");
+        false
+    } else if elapsed > UNIT_TEST_BUDGET {
+        eprintln!(
+            "unit tests took {:?}, over the {:?} budget: something waits on real time",
+            elapsed, UNIT_TEST_BUDGET
+        );
+        false
+    } else {
+        println!("unit tests took {:?} of {:?}", elapsed, UNIT_TEST_BUDGET);
+        true
+    }
+}